@@ -57,8 +57,11 @@ pub enum EthereumContractCallError {
     EncodingError(ethabi::Error),
     #[error("call error: {0}")]
     Web3Error(web3::Error),
+    /// The reason the node gave for the revert, and the data that the
+    /// contract reverted with, which is empty if the node did not return
+    /// it
     #[error("call reverted: {0}")]
-    Revert(String),
+    Revert(String, Vec<u8>),
     #[error("ethereum node took too long to perform call")]
     Timeout,
 }
@@ -200,6 +203,27 @@ impl bc::TriggerFilter<Chain> for TriggerFilter {
     }
 }
 
+/// How the `eth_getLogs` calls for a block range are split up for a
+/// provider. Providers limit the number of addresses in one call, so
+/// filters with many contracts, like those of deployments with many dynamic
+/// data sources, are split into chunks that are requested in parallel
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct GetLogsLimits {
+    /// The most contract addresses in one call
+    pub max_contracts: usize,
+    /// How many calls for the same block range can run at the same time
+    pub parallelism: usize,
+}
+
+impl Default for GetLogsLimits {
+    fn default() -> Self {
+        GetLogsLimits {
+            max_contracts: ENV_VARS.get_logs_max_contracts,
+            parallelism: ENV_VARS.block_ingestor_max_concurrent_json_rpc_calls,
+        }
+    }
+}
+
 #[derive(Clone, Debug, Default)]
 pub(crate) struct EthereumLogFilter {
     /// Log filters can be represented as a bipartite graph between contracts and events. An edge
@@ -215,7 +239,7 @@ pub(crate) struct EthereumLogFilter {
 
 impl Into<Vec<LogFilter>> for EthereumLogFilter {
     fn into(self) -> Vec<LogFilter> {
-        self.eth_get_logs_filters()
+        self.eth_get_logs_filters(ENV_VARS.get_logs_max_contracts)
             .map(
                 |EthGetLogsFilter {
                      contracts,
@@ -249,8 +273,7 @@ impl EthereumLogFilter {
                 let contract = LogFilterNode::Contract(log.address);
                 let event = LogFilterNode::Event(*sig);
                 self.contracts_and_events_graph
-                    .all_edges()
-                    .any(|(s, t, _)| (s == contract && t == event) || (t == contract && s == event))
+                    .contains_edge(contract, event)
                     || self.wildcard_events.contains_key(sig)
             }
         }
@@ -339,8 +362,14 @@ impl EthereumLogFilter {
 
     /// Filters for `eth_getLogs` calls. The filters will not return false positives. This attempts
     /// to balance between having granular filters but too many calls and having few calls but too
-    /// broad filters causing the Ethereum endpoint to timeout.
-    pub fn eth_get_logs_filters(self) -> impl Iterator<Item = EthGetLogsFilter> {
+    /// broad filters causing the Ethereum endpoint to timeout. No filter has more than
+    /// `max_contracts` contracts, since providers limit how many addresses one call can have.
+    pub fn eth_get_logs_filters(
+        self,
+        max_contracts: usize,
+    ) -> impl Iterator<Item = EthGetLogsFilter> {
+        let max_contracts = max_contracts.max(1);
+
         // Start with the wildcard event filters.
         let mut filters = self
             .wildcard_events
@@ -381,7 +410,7 @@ impl EthereumLogFilter {
             for neighbor in g.neighbors(max_vertex) {
                 match neighbor {
                     LogFilterNode::Contract(address) => {
-                        if filter.contracts.len() == ENV_VARS.get_logs_max_contracts {
+                        if filter.contracts.len() == max_contracts {
                             // The batch size was reached, register the filter and start a new one.
                             let event = filter.event_signatures[0];
                             push_filter(filter);
@@ -939,6 +968,17 @@ pub trait EthereumAdapter: Send + Sync + 'static {
         call: EthereumContractCall,
         cache: Arc<dyn EthereumCallCache>,
     ) -> Box<dyn Future<Item = Vec<Token>, Error = EthereumContractCallError> + Send>;
+
+    /// Find the number of the block in which the contract at `address` was
+    /// created by searching with `eth_getCode` up to block `latest`. Returns
+    /// `None` if there is no code at `address` in block `latest`. This only
+    /// works with nodes that can return the state of old blocks
+    async fn contract_creation_block(
+        &self,
+        logger: &Logger,
+        address: Address,
+        latest: BlockNumber,
+    ) -> Result<Option<BlockNumber>, Error>;
 }
 
 #[cfg(test)]
@@ -1365,7 +1405,7 @@ fn complete_log_filter() {
                 contracts_and_events_graph,
                 wildcard_events: HashMap::new(),
             }
-            .eth_get_logs_filters()
+            .eth_get_logs_filters(ENV_VARS.get_logs_max_contracts)
             .collect();
 
             // Assert that a contract or event is filtered on iff it was present in the graph.
@@ -1396,6 +1436,33 @@ fn complete_log_filter() {
     }
 }
 
+#[test]
+fn log_filter_chunks_contracts() {
+    let event = H256::from_low_u64_le(1);
+    let mut contracts_and_events_graph = GraphMap::new();
+    for i in 0..10 {
+        contracts_and_events_graph.add_edge(
+            LogFilterNode::Contract(Address::from_low_u64_le(i)),
+            LogFilterNode::Event(event),
+            false,
+        );
+    }
+    let filter = EthereumLogFilter {
+        contracts_and_events_graph,
+        wildcard_events: HashMap::new(),
+    };
+
+    let sizes: Vec<_> = filter
+        .clone()
+        .eth_get_logs_filters(3)
+        .map(|filter| filter.contracts.len())
+        .collect();
+    assert_eq!(vec![3, 3, 3, 1], sizes);
+
+    // A limit of zero is treated as one address per call
+    assert_eq!(10, filter.eth_get_logs_filters(0).count());
+}
+
 #[test]
 fn log_filter_require_transacion_receipt_method() {
     // test data
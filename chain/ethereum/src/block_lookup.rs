@@ -0,0 +1,240 @@
+//! Looking up the hash, number, timestamp and parent of a block by its hash
+//! or number, for mappings and the index node API. Lookups are served from
+//! the block cache in the chain store if possible, then from the chain
+//! store's block time index, which keeps blocks that were purged from the
+//! cache, and fall back to a provider, if there is one, for blocks that the
+//! chain store knows nothing about
+use std::sync::Arc;
+
+use anyhow::{anyhow, Error};
+use graph::blockchain::BlockPtr;
+use graph::components::store::{ChainStore, IndexedBlock};
+use graph::prelude::serde_json;
+use graph::prelude::web3::types::{H256, U256, U64};
+use graph::prelude::{
+    BlockNumber, CheapClone, Deserialize, Future01CompatExt, LightEthereumBlock, Logger,
+};
+
+use crate::adapter::EthereumAdapter as _;
+use crate::EthereumAdapter;
+
+/// The parts of a block that mappings and the index node API can look up
+#[derive(Clone, Debug, PartialEq)]
+pub struct BlockInfo {
+    pub hash: H256,
+    pub number: BlockNumber,
+    pub timestamp: U256,
+    pub parent_hash: H256,
+}
+
+impl From<IndexedBlock> for BlockInfo {
+    fn from(block: IndexedBlock) -> Self {
+        BlockInfo {
+            hash: block.hash,
+            number: block.number,
+            timestamp: U256::from(block.timestamp),
+            parent_hash: block.parent_hash,
+        }
+    }
+}
+
+impl BlockInfo {
+    fn from_block(block: &LightEthereumBlock) -> Result<Self, Error> {
+        let hash = block
+            .hash
+            .ok_or_else(|| anyhow!("block is missing its hash"))?;
+        let number = block
+            .number
+            .ok_or_else(|| anyhow!("block {:x} is missing its number", hash))?;
+        Ok(BlockInfo {
+            hash,
+            number: BlockNumber::try_from(number.as_u64())?,
+            timestamp: block.timestamp,
+            parent_hash: block.parent_hash,
+        })
+    }
+
+    /// Parse the JSON the chain store keeps for a block. Depending on how
+    /// the block got into the cache, the block is either the whole JSON
+    /// object or its `block` field
+    fn from_json(mut json: serde_json::Value) -> Result<Self, Error> {
+        #[derive(Deserialize)]
+        #[serde(rename_all = "camelCase")]
+        struct Header {
+            hash: H256,
+            number: U64,
+            timestamp: U256,
+            parent_hash: H256,
+        }
+
+        if let Some(block) = json.get_mut("block") {
+            json = block.take();
+        }
+        let header: Header = serde_json::from_value(json)?;
+        Ok(BlockInfo {
+            hash: header.hash,
+            number: BlockNumber::try_from(header.number.as_u64())?,
+            timestamp: header.timestamp,
+            parent_hash: header.parent_hash,
+        })
+    }
+}
+
+/// The block with `hash`, from the block cache or the block time index or,
+/// if the chain store does not know it, from `adapter`
+pub async fn block_by_hash(
+    logger: &Logger,
+    chain_store: &dyn ChainStore,
+    adapter: Option<&EthereumAdapter>,
+    hash: H256,
+) -> Result<Option<BlockInfo>, Error> {
+    if let Some(json) = chain_store.blocks(&[hash])?.into_iter().next() {
+        return BlockInfo::from_json(json).map(Some);
+    }
+    if let Some(block) = chain_store.indexed_block(&hash)? {
+        return Ok(Some(block.into()));
+    }
+    let adapter = match adapter {
+        Some(adapter) => adapter,
+        None => return Ok(None),
+    };
+    match adapter.block_by_hash(logger, hash).compat().await? {
+        Some(block) => BlockInfo::from_block(&block).map(Some),
+        None => Ok(None),
+    }
+}
+
+/// The block with `number` on the main chain. The block cache and the
+/// block time index only answer if they have exactly one block with that
+/// number; otherwise we ask `adapter`
+pub async fn block_by_number(
+    logger: &Logger,
+    chain_store: &dyn ChainStore,
+    adapter: Option<&EthereumAdapter>,
+    number: BlockNumber,
+) -> Result<Option<BlockInfo>, Error> {
+    let hashes = chain_store.block_hashes_by_block_number(number)?;
+    match hashes.as_slice() {
+        [hash] => {
+            if let Some(json) = chain_store.blocks(&[*hash])?.into_iter().next() {
+                return BlockInfo::from_json(json).map(Some);
+            }
+        }
+        [] => {
+            let mut blocks = chain_store.indexed_blocks_by_number(number)?;
+            if blocks.len() == 1 {
+                return Ok(blocks.pop().map(BlockInfo::from));
+            }
+        }
+        _ => {}
+    }
+    let adapter = match adapter {
+        Some(adapter) => adapter,
+        None => return Ok(None),
+    };
+    match adapter.block_by_number(logger, number).compat().await? {
+        Some(block) => BlockInfo::from_block(&block).map(Some),
+        None => Ok(None),
+    }
+}
+
+/// The block with `hash` if it is on the chain that ends in `head`, and
+/// `None` for blocks that come after `head` or are on a different fork,
+/// so that mappings can not see blocks that their block does not build on
+pub async fn ancestor_by_hash(
+    logger: &Logger,
+    chain_store: Arc<dyn ChainStore>,
+    adapter: Option<&EthereumAdapter>,
+    head: &BlockPtr,
+    hash: H256,
+    reorg_threshold: BlockNumber,
+) -> Result<Option<BlockInfo>, Error> {
+    let block = match block_by_hash(logger, chain_store.as_ref(), adapter, hash).await? {
+        Some(block) => block,
+        None => return Ok(None),
+    };
+    let ancestor = ancestor_by_number(
+        logger,
+        chain_store,
+        adapter,
+        head,
+        block.number,
+        reorg_threshold,
+    )
+    .await?;
+    Ok(ancestor.filter(|ancestor| ancestor.hash == block.hash))
+}
+
+/// The block with `number` on the chain that ends in `head`, or `None` if
+/// `number` comes after `head`. Blocks within `reorg_threshold` of `head`
+/// are found by following parent hashes, so that the answer does not
+/// depend on which fork a provider is on; older blocks are final and are
+/// looked up on the main chain
+pub async fn ancestor_by_number(
+    logger: &Logger,
+    chain_store: Arc<dyn ChainStore>,
+    adapter: Option<&EthereumAdapter>,
+    head: &BlockPtr,
+    number: BlockNumber,
+    reorg_threshold: BlockNumber,
+) -> Result<Option<BlockInfo>, Error> {
+    if number > head.number || number < 0 {
+        return Ok(None);
+    }
+    let offset = head.number - number;
+    if offset <= reorg_threshold {
+        if let Some(json) = chain_store
+            .cheap_clone()
+            .ancestor_block(head.clone(), offset)
+            .await?
+        {
+            return BlockInfo::from_json(json).map(Some);
+        }
+        // The block cache does not have the whole chain from `head`; walk
+        // it block by block, asking `adapter` for the missing ones
+        let mut hash = head.hash_as_h256();
+        loop {
+            let block = match block_by_hash(logger, chain_store.as_ref(), adapter, hash).await? {
+                Some(block) => block,
+                None => return Ok(None),
+            };
+            if block.number <= number {
+                return Ok(Some(block).filter(|block| block.number == number));
+            }
+            hash = block.parent_hash;
+        }
+    }
+    block_by_number(logger, chain_store.as_ref(), adapter, number).await
+}
+
+#[cfg(test)]
+mod tests {
+    use graph::prelude::serde_json::{self, json};
+    use graph::prelude::web3::types::{H256, U256, U64};
+    use graph::prelude::LightEthereumBlock;
+
+    use super::BlockInfo;
+
+    #[test]
+    fn block_info_from_cached_json() {
+        let block = LightEthereumBlock {
+            hash: Some(H256::from_low_u64_be(2)),
+            number: Some(U64::from(2)),
+            parent_hash: H256::from_low_u64_be(1),
+            timestamp: U256::from(1_600_000_000),
+            ..Default::default()
+        };
+        let expected = BlockInfo {
+            hash: H256::from_low_u64_be(2),
+            number: 2,
+            timestamp: U256::from(1_600_000_000),
+            parent_hash: H256::from_low_u64_be(1),
+        };
+
+        let plain = serde_json::to_value(&block).unwrap();
+        assert_eq!(expected, BlockInfo::from_json(plain.clone()).unwrap());
+        let nested = json!({ "block": plain, "transaction_receipts": [] });
+        assert_eq!(expected, BlockInfo::from_json(nested).unwrap());
+        assert_eq!(expected, BlockInfo::from_block(&block).unwrap());
+    }
+}
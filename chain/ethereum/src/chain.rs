@@ -1,7 +1,9 @@
 use anyhow::{Context, Error};
 use graph::blockchain::BlockchainKind;
+use graph::data::subgraph::status::ProviderInfo;
 use graph::data::subgraph::UnifiedMappingApiVersion;
 use graph::firehose::{FirehoseEndpoint, FirehoseEndpoints, ForkStep};
+use graph::prelude::web3::types::{H256, U256};
 use graph::prelude::{EthereumBlock, EthereumCallCache, LightEthereumBlock, LightEthereumBlockExt};
 use graph::slog::debug;
 use graph::{
@@ -26,9 +28,12 @@ use prost::Message;
 use std::collections::HashSet;
 use std::iter::FromIterator;
 use std::sync::Arc;
+use std::time::Duration;
 
+use crate::block_lookup::{self, BlockInfo};
 use crate::data_source::DataSourceTemplate;
 use crate::data_source::UnresolvedDataSourceTemplate;
+use crate::log_scan::LogSourceChooser;
 use crate::RuntimeAdapter;
 use crate::{
     adapter::EthereumAdapter as _,
@@ -41,7 +46,7 @@ use crate::{
     SubgraphEthRpcMetrics, TriggerFilter, ENV_VARS,
 };
 use crate::{network::EthereumNetworkAdapters, EthereumAdapter};
-use graph::blockchain::block_stream::{BlockStream, FirehoseCursor};
+use graph::blockchain::block_stream::{BlockStream, BlockStreamBuilder, FirehoseCursor};
 
 /// Celo Mainnet: 42220, Testnet Alfajores: 44787, Testnet Baklava: 62320
 const CELO_CHAIN_IDS: [u64; 3] = [42220, 44787, 62320];
@@ -57,6 +62,7 @@ pub struct Chain {
     call_cache: Arc<dyn EthereumCallCache>,
     chain_head_update_listener: Arc<dyn ChainHeadUpdateListener>,
     reorg_threshold: BlockNumber,
+    block_stream_builder: Option<Arc<dyn BlockStreamBuilder<Chain>>>,
     pub is_ingestible: bool,
 }
 
@@ -92,10 +98,21 @@ impl Chain {
             call_cache,
             chain_head_update_listener,
             reorg_threshold,
+            block_stream_builder: None,
             is_ingestible,
         }
     }
 
+    /// Build the block streams for subgraphs with `builder` instead of
+    /// polling the providers or connecting to Firehose
+    pub fn with_block_stream_builder(
+        mut self,
+        builder: Arc<dyn BlockStreamBuilder<Chain>>,
+    ) -> Self {
+        self.block_stream_builder = Some(builder);
+        self
+    }
+
     /// Returns a handler to this chain's [`EthereumCallCache`].
     pub fn call_cache(&self) -> Arc<dyn EthereumCallCache> {
         self.call_cache.clone()
@@ -104,6 +121,38 @@ impl Chain {
     pub fn cheapest_adapter(&self) -> Arc<EthereumAdapter> {
         self.eth_adapters.cheapest().unwrap().clone()
     }
+
+    /// How the providers of the chain are doing, for the status API
+    pub async fn provider_statuses(&self, timeout: Duration) -> Vec<ProviderInfo> {
+        self.eth_adapters.probe(timeout).await
+    }
+
+    /// Look up the block with `hash` for the status API
+    pub async fn block_by_hash(
+        &self,
+        logger: &Logger,
+        hash: H256,
+    ) -> Result<Option<BlockInfo>, Error> {
+        let adapter = self.eth_adapters.cheapest();
+        block_lookup::block_by_hash(logger, self.chain_store.as_ref(), adapter.as_deref(), hash)
+            .await
+    }
+
+    /// Look up the block with `number` on the main chain for the status API
+    pub async fn block_by_number(
+        &self,
+        logger: &Logger,
+        number: BlockNumber,
+    ) -> Result<Option<BlockInfo>, Error> {
+        let adapter = self.eth_adapters.cheapest();
+        block_lookup::block_by_number(
+            logger,
+            self.chain_store.as_ref(),
+            adapter.as_deref(),
+            number,
+        )
+        .await
+    }
 }
 
 #[async_trait]
@@ -165,6 +214,7 @@ impl Blockchain for Chain {
             eth_adapter,
             chain_store: self.chain_store.cheap_clone(),
             unified_api_version,
+            log_source: LogSourceChooser::new(),
         };
         Ok(Arc::new(adapter))
     }
@@ -177,6 +227,7 @@ impl Blockchain for Chain {
         subgraph_current_block: Option<BlockPtr>,
         filter: Arc<Self::TriggerFilter>,
         unified_api_version: UnifiedMappingApiVersion,
+        indexing_delay: BlockNumber,
     ) -> Result<Box<dyn BlockStream<Self>>, Error> {
         let requirements = filter.node_capabilities();
         let adapter = self
@@ -186,16 +237,28 @@ impl Blockchain for Chain {
                 self.name, requirements
             ));
 
-        let firehose_endpoint = match self.firehose_endpoints.random() {
-            Some(e) => e.clone(),
-            None => return Err(anyhow::format_err!("no firehose endpoint available",)),
-        };
-
         let logger = self
             .logger_factory
             .subgraph_logger(&deployment)
             .new(o!("component" => "FirehoseBlockStream"));
 
+        if let Some(builder) = &self.block_stream_builder {
+            return builder
+                .build(
+                    logger,
+                    adapter,
+                    filter,
+                    start_blocks,
+                    subgraph_current_block,
+                )
+                .await;
+        }
+
+        let firehose_endpoint = match self.firehose_endpoints.random() {
+            Some(e) => e.clone(),
+            None => return Err(anyhow::format_err!("no firehose endpoint available",)),
+        };
+
         let firehose_mapper = Arc::new(FirehoseMapper {
             endpoint: firehose_endpoint.cheap_clone(),
         });
@@ -234,6 +297,19 @@ impl Blockchain for Chain {
             .logger_factory
             .subgraph_logger(&deployment)
             .new(o!("component" => "BlockStream"));
+
+        if let Some(builder) = &self.block_stream_builder {
+            return builder
+                .build(
+                    logger,
+                    adapter,
+                    filter,
+                    start_blocks,
+                    subgraph_current_block,
+                )
+                .await;
+        }
+
         let chain_store = self.chain_store().clone();
         let chain_head_update_stream = self
             .chain_head_update_listener
@@ -258,6 +334,7 @@ impl Blockchain for Chain {
             filter,
             start_blocks,
             reorg_threshold,
+            indexing_delay,
             logger,
             ENV_VARS.max_block_range_size,
             ENV_VARS.target_triggers_per_block_range,
@@ -286,10 +363,58 @@ impl Blockchain for Chain {
             .await
     }
 
+    async fn detect_start_block(
+        &self,
+        logger: &Logger,
+        data_source: &Self::DataSource,
+    ) -> Result<Option<BlockNumber>, Error> {
+        let address = match data_source.source.address {
+            Some(address) => address,
+            None => return Ok(None),
+        };
+        // Block handlers without a filter are called for every block, also
+        // for the ones before the contract was created
+        if data_source
+            .mapping
+            .block_handlers
+            .iter()
+            .any(|handler| handler.filter.is_none())
+        {
+            return Ok(None);
+        }
+
+        if let Some(number) = self
+            .chain_store
+            .contract_creation_block(address.as_bytes())?
+        {
+            return Ok(Some(number));
+        }
+        let head = match self.chain_store.cheap_clone().chain_head_ptr().await? {
+            Some(head) => head,
+            None => return Ok(None),
+        };
+        let eth_adapter =
+            self.eth_adapters
+                .cheapest_with(&crate::capabilities::NodeCapabilities {
+                    archive: true,
+                    traces: false,
+                })?;
+        let number = eth_adapter
+            .contract_creation_block(logger, address, head.number)
+            .await?;
+        if let Some(number) = number {
+            self.chain_store
+                .set_contract_creation_block(address.as_bytes(), number)?;
+        }
+        Ok(number)
+    }
+
     fn runtime_adapter(&self) -> Arc<Self::RuntimeAdapter> {
         Arc::new(RuntimeAdapter {
             eth_adapters: self.eth_adapters.cheap_clone(),
             call_cache: self.call_cache.cheap_clone(),
+            chain_store: self.chain_store.cheap_clone(),
+            reorg_threshold: self.reorg_threshold,
         })
     }
 
@@ -343,6 +468,14 @@ impl Block for BlockFinality {
         }
     }
 
+    fn timestamp(&self) -> Option<u64> {
+        let timestamp = match self {
+            BlockFinality::Final(block) => block.timestamp,
+            BlockFinality::NonFinal(block) => block.ethereum_block.block.timestamp,
+        };
+        (timestamp <= U256::from(u64::MAX)).then(|| timestamp.as_u64())
+    }
+
     fn data(&self) -> Result<json::Value, json::Error> {
         // The serialization here very delicately depends on how the
         // `ChainStore`'s `blocks` and `ancestor_block` return the data we
@@ -384,6 +517,7 @@ pub struct TriggersAdapter {
     chain_store: Arc<dyn ChainStore>,
     eth_adapter: Arc<EthereumAdapter>,
     unified_api_version: UnifiedMappingApiVersion,
+    log_source: LogSourceChooser,
 }
 
 #[async_trait]
@@ -403,6 +537,7 @@ impl TriggersAdapterTrait<Chain> for TriggersAdapter {
             to,
             filter,
             self.unified_api_version.clone(),
+            &self.log_source,
         )
         .await
     }
@@ -434,6 +569,7 @@ impl TriggersAdapterTrait<Chain> for TriggersAdapter {
                     block_number,
                     filter,
                     self.unified_api_version.clone(),
+                    &self.log_source,
                 )
                 .await?;
                 assert!(blocks.len() == 1);
@@ -317,6 +317,13 @@ impl BlockHeader {
             ))),
         }
     }
+
+    /// The timestamp of the block in seconds since the epoch
+    pub fn timestamp(&self) -> Option<u64> {
+        self.timestamp
+            .as_ref()
+            .and_then(|timestamp| u64::try_from(timestamp.seconds).ok())
+    }
 }
 
 impl<'a> From<&'a BlockHeader> for BlockPtr {
@@ -357,6 +364,10 @@ impl BlockchainBlock for Block {
     fn parent_ptr(&self) -> Option<BlockPtr> {
         self.parent_ptr()
     }
+
+    fn timestamp(&self) -> Option<u64> {
+        self.header().timestamp()
+    }
 }
 
 impl HeaderOnlyBlock {
@@ -383,4 +394,8 @@ impl BlockchainBlock for HeaderOnlyBlock {
     fn parent_ptr(&self) -> Option<BlockPtr> {
         self.header().parent_ptr()
     }
+
+    fn timestamp(&self) -> Option<u64> {
+        self.header().timestamp()
+    }
 }
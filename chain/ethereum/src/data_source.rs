@@ -25,7 +25,7 @@ use graph::{
     },
 };
 
-use graph::data::subgraph::{calls_host_fn, DataSourceContext, Source};
+use graph::data::subgraph::{calls_host_fn, version_gate, DataSourceContext, Source};
 
 use crate::chain::Chain;
 use crate::trigger::{EthereumBlockTriggerType, EthereumTrigger, MappingTrigger};
@@ -126,6 +126,7 @@ impl blockchain::DataSource<Chain> for DataSource {
                 .as_ref()
                 .map(|ctx| serde_json::to_string(&ctx).unwrap()),
             creation_block: self.creation_block,
+            removed_block: None,
         }
     }
 
@@ -138,6 +139,7 @@ impl blockchain::DataSource<Chain> for DataSource {
             source,
             context,
             creation_block,
+            removed_block: _,
         } = stored;
         let template = templates
             .get(name.as_str())
@@ -198,17 +200,17 @@ impl blockchain::DataSource<Chain> for DataSource {
             errors.push(anyhow!("data source has duplicated block handlers"));
         }
 
-        // Validate that event handlers don't require receipts for API versions lower than 0.0.7
-        let api_version = self.api_version();
-        if api_version < semver::Version::new(0, 0, 7) {
-            for event_handler in &self.mapping.event_handlers {
-                if event_handler.receipt {
-                    errors.push(anyhow!(
-                        "data source has event handlers that require transaction receipts, but this \
-                         is only supported for apiVersion >= 0.0.7"
-                    ));
-                    break;
-                }
+        errors.extend(self.mapping.validate_handler_signatures(&self.contract_abi));
+        errors.extend(self.mapping.validate_handler_entities());
+
+        if self
+            .mapping
+            .event_handlers
+            .iter()
+            .any(|handler| handler.receipt)
+        {
+            if let Err(e) = version_gate::EVENT_HANDLER_RECEIPTS.check(&self.api_version()) {
+                errors.push(e.into());
             }
         }
 
@@ -222,6 +224,14 @@ impl blockchain::DataSource<Chain> for DataSource {
     fn runtime(&self) -> &[u8] {
         self.mapping.runtime.as_ref()
     }
+
+    fn entities(&self) -> &[String] {
+        &self.mapping.entities
+    }
+
+    fn handler_entities(&self) -> Vec<(&str, &[String])> {
+        self.mapping.handler_entities()
+    }
 }
 
 impl DataSource {
@@ -308,134 +318,6 @@ impl DataSource {
         }
     }
 
-    /// Returns the contract event with the given signature, if it exists. A an event from the ABI
-    /// will be matched if:
-    /// 1. An event signature is equal to `signature`.
-    /// 2. There are no equal matches, but there is exactly one event that equals `signature` if all
-    ///    `indexed` modifiers are removed from the parameters.
-    fn contract_event_with_signature(&self, signature: &str) -> Option<&Event> {
-        // Returns an `Event(uint256,address)` signature for an event, without `indexed` hints.
-        fn ambiguous_event_signature(event: &Event) -> String {
-            format!(
-                "{}({})",
-                event.name,
-                event
-                    .inputs
-                    .iter()
-                    .map(|input| format!("{}", event_param_type_signature(&input.kind)))
-                    .collect::<Vec<_>>()
-                    .join(",")
-            )
-        }
-
-        // Returns an `Event(indexed uint256,address)` type signature for an event.
-        fn event_signature(event: &Event) -> String {
-            format!(
-                "{}({})",
-                event.name,
-                event
-                    .inputs
-                    .iter()
-                    .map(|input| format!(
-                        "{}{}",
-                        if input.indexed { "indexed " } else { "" },
-                        event_param_type_signature(&input.kind)
-                    ))
-                    .collect::<Vec<_>>()
-                    .join(",")
-            )
-        }
-
-        // Returns the signature of an event parameter type (e.g. `uint256`).
-        fn event_param_type_signature(kind: &ParamType) -> String {
-            use ParamType::*;
-
-            match kind {
-                Address => "address".into(),
-                Bytes => "bytes".into(),
-                Int(size) => format!("int{}", size),
-                Uint(size) => format!("uint{}", size),
-                Bool => "bool".into(),
-                String => "string".into(),
-                Array(inner) => format!("{}[]", event_param_type_signature(&*inner)),
-                FixedBytes(size) => format!("bytes{}", size),
-                FixedArray(inner, size) => {
-                    format!("{}[{}]", event_param_type_signature(&*inner), size)
-                }
-                Tuple(components) => format!(
-                    "({})",
-                    components
-                        .iter()
-                        .map(|component| event_param_type_signature(&component))
-                        .collect::<Vec<_>>()
-                        .join(",")
-                ),
-            }
-        }
-
-        self.contract_abi
-            .contract
-            .events()
-            .find(|event| event_signature(event) == signature)
-            .or_else(|| {
-                // Fallback for subgraphs that don't use `indexed` in event signatures yet:
-                //
-                // If there is only one event variant with this name and if its signature
-                // without `indexed` matches the event signature from the manifest, we
-                // can safely assume that the event is a match, we don't need to force
-                // the subgraph to add `indexed`.
-
-                // Extract the event name; if there is no '(' in the signature,
-                // `event_name` will be empty and not match any events, so that's ok
-                let parens = signature.find('(').unwrap_or(0);
-                let event_name = &signature[0..parens];
-
-                let matching_events = self
-                    .contract_abi
-                    .contract
-                    .events()
-                    .filter(|event| event.name == event_name)
-                    .collect::<Vec<_>>();
-
-                // Only match the event signature without `indexed` if there is
-                // only a single event variant
-                if matching_events.len() == 1
-                    && ambiguous_event_signature(matching_events[0]) == signature
-                {
-                    Some(matching_events[0])
-                } else {
-                    // More than one event variant or the signature
-                    // still doesn't match, even if we ignore `indexed` hints
-                    None
-                }
-            })
-    }
-
-    fn contract_function_with_signature(&self, target_signature: &str) -> Option<&Function> {
-        self.contract_abi
-            .contract
-            .functions()
-            .filter(|function| match function.state_mutability {
-                StateMutability::Payable | StateMutability::NonPayable => true,
-                StateMutability::Pure | StateMutability::View => false,
-            })
-            .find(|function| {
-                // Construct the argument function signature:
-                // `address,uint256,bool`
-                let mut arguments = function
-                    .inputs
-                    .iter()
-                    .map(|input| format!("{}", input.kind))
-                    .collect::<Vec<String>>()
-                    .join(",");
-                // `address,uint256,bool)
-                arguments.push_str(")");
-                // `operation(address,uint256,bool)`
-                let actual_signature = vec![function.name.clone(), arguments].join("(");
-                target_signature == actual_signature
-            })
-    }
-
     fn matches_trigger_address(&self, trigger: &EthereumTrigger) -> bool {
         let ds_address = match self.source.address {
             Some(addr) => addr,
@@ -495,7 +377,8 @@ impl DataSource {
                     .map(|event_handler| {
                         // Identify the event ABI in the contract
                         let event_abi = self
-                            .contract_event_with_signature(event_handler.event.as_str())
+                            .contract_abi
+                            .event_with_signature(event_handler.event.as_str())
                             .with_context(|| {
                                 anyhow!(
                                     "Event with the signature \"{}\" not found in \
@@ -601,7 +484,8 @@ impl DataSource {
 
                 // Identify the function ABI in the contract
                 let function_abi = self
-                    .contract_function_with_signature(handler.function.as_str())
+                    .contract_abi
+                    .function_with_signature(handler.function.as_str())
                     .with_context(|| {
                         anyhow!(
                             "Function with the signature \"{}\" not found in \
@@ -833,6 +717,15 @@ impl blockchain::DataSourceTemplate<Chain> for DataSourceTemplate {
         &self.name
     }
 
+    fn validate(&self) -> Vec<Error> {
+        let mut errors = match self.mapping.find_abi(&self.source.abi) {
+            Ok(abi) => self.mapping.validate_handler_signatures(&abi),
+            Err(e) => vec![e],
+        };
+        errors.extend(self.mapping.validate_handler_entities());
+        errors
+    }
+
     fn api_version(&self) -> semver::Version {
         self.mapping.api_version.clone()
     }
@@ -874,6 +767,131 @@ pub struct Mapping {
 }
 
 impl Mapping {
+    /// The handlers that declare the entity types they may access, with
+    /// those entity types
+    fn handler_entities(&self) -> Vec<(&str, &[String])> {
+        let event_handlers = self
+            .event_handlers
+            .iter()
+            .map(|handler| (&handler.handler, &handler.entities));
+        let call_handlers = self
+            .call_handlers
+            .iter()
+            .map(|handler| (&handler.handler, &handler.entities));
+        let block_handlers = self
+            .block_handlers
+            .iter()
+            .map(|handler| (&handler.handler, &handler.entities));
+        event_handlers
+            .chain(call_handlers)
+            .chain(block_handlers)
+            .filter_map(|(handler, entities)| {
+                entities
+                    .as_ref()
+                    .map(|entities| (handler.as_str(), entities.as_slice()))
+            })
+            .collect()
+    }
+
+    /// Check that handlers only declare entity types that the mapping
+    /// declares
+    fn validate_handler_entities(&self) -> Vec<Error> {
+        let mut errors = vec![];
+        for (handler, entities) in self.handler_entities() {
+            for entity in entities {
+                if !self.entities.contains(entity) {
+                    errors.push(anyhow!(
+                        "handler `{}` declares the entity type `{}`, which is not listed \
+                         in the `entities` of its mapping",
+                        handler,
+                        entity
+                    ));
+                }
+            }
+        }
+        errors
+    }
+
+    /// Check that every event handler is for an event in `abi` and every
+    /// call handler for a function in it that can be called in a
+    /// transaction. Handlers for which that is not the case would never
+    /// be triggered
+    fn validate_handler_signatures(&self, abi: &MappingABI) -> Vec<Error> {
+        let mut errors = vec![];
+
+        for handler in &self.event_handlers {
+            if abi.event_with_signature(&handler.event).is_some() {
+                continue;
+            }
+            let name = signature_name(&handler.event);
+            let events = abi
+                .contract
+                .events()
+                .filter(|event| event.name == name)
+                .collect::<Vec<_>>();
+            errors.push(if events.is_empty() {
+                anyhow!(
+                    "event handler `{}` is for the event `{}`, but ABI `{}` has no event named `{}`",
+                    handler.handler,
+                    handler.event,
+                    abi.name,
+                    name
+                )
+            } else {
+                anyhow!(
+                    "event handler `{}` is for the event `{}`, which does not match {} in ABI `{}`; \
+                     the parameter types and which parameters are `indexed` must be the same",
+                    handler.handler,
+                    handler.event,
+                    quoted(events.into_iter().map(event_signature)),
+                    abi.name
+                )
+            });
+        }
+
+        for handler in &self.call_handlers {
+            if abi.function_with_signature(&handler.function).is_some() {
+                continue;
+            }
+            let name = signature_name(&handler.function);
+            let functions = abi
+                .contract
+                .functions()
+                .filter(|function| function.name == name)
+                .collect::<Vec<_>>();
+            errors.push(if functions.is_empty() {
+                anyhow!(
+                    "call handler `{}` is for the function `{}`, but ABI `{}` has no function named `{}`",
+                    handler.handler,
+                    handler.function,
+                    abi.name,
+                    name
+                )
+            } else if functions
+                .iter()
+                .any(|function| function_signature(function) == handler.function)
+            {
+                anyhow!(
+                    "call handler `{}` is for the function `{}`, but that is a `view` or `pure` \
+                     function in ABI `{}`, and calls to those are never handled",
+                    handler.handler,
+                    handler.function,
+                    abi.name
+                )
+            } else {
+                anyhow!(
+                    "call handler `{}` is for the function `{}`, which does not match {} in ABI `{}`",
+                    handler.handler,
+                    handler.function,
+                    quoted(functions.into_iter().map(function_signature)),
+                    abi.name
+                )
+            });
+        }
+
+        errors
+    }
+
     pub fn requires_archive(&self) -> anyhow::Result<bool> {
         calls_host_fn(&self.runtime, "ethereum.call")
     }
@@ -964,6 +982,145 @@ pub struct MappingABI {
     pub contract: Contract,
 }
 
+impl MappingABI {
+    /// Returns the contract event with the given signature, if it exists. A an event from the ABI
+    /// will be matched if:
+    /// 1. An event signature is equal to `signature`.
+    /// 2. There are no equal matches, but there is exactly one event that equals `signature` if all
+    ///    `indexed` modifiers are removed from the parameters.
+    pub fn event_with_signature(&self, signature: &str) -> Option<&Event> {
+        self.contract
+            .events()
+            .find(|event| event_signature(event) == signature)
+            .or_else(|| {
+                // Fallback for subgraphs that don't use `indexed` in event signatures yet:
+                //
+                // If there is only one event variant with this name and if its signature
+                // without `indexed` matches the event signature from the manifest, we
+                // can safely assume that the event is a match, we don't need to force
+                // the subgraph to add `indexed`.
+
+                // Extract the event name; if there is no '(' in the signature,
+                // `event_name` will be empty and not match any events, so that's ok
+                let parens = signature.find('(').unwrap_or(0);
+                let event_name = &signature[0..parens];
+
+                let matching_events = self
+                    .contract
+                    .events()
+                    .filter(|event| event.name == event_name)
+                    .collect::<Vec<_>>();
+
+                // Only match the event signature without `indexed` if there is
+                // only a single event variant
+                if matching_events.len() == 1
+                    && ambiguous_event_signature(matching_events[0]) == signature
+                {
+                    Some(matching_events[0])
+                } else {
+                    // More than one event variant or the signature
+                    // still doesn't match, even if we ignore `indexed` hints
+                    None
+                }
+            })
+    }
+
+    pub fn function_with_signature(&self, target_signature: &str) -> Option<&Function> {
+        self.contract
+            .functions()
+            .filter(|function| match function.state_mutability {
+                StateMutability::Payable | StateMutability::NonPayable => true,
+                StateMutability::Pure | StateMutability::View => false,
+            })
+            .find(|function| target_signature == function_signature(function))
+    }
+}
+
+// Returns an `Event(uint256,address)` signature for an event, without `indexed` hints.
+fn ambiguous_event_signature(event: &Event) -> String {
+    format!(
+        "{}({})",
+        event.name,
+        event
+            .inputs
+            .iter()
+            .map(|input| format!("{}", event_param_type_signature(&input.kind)))
+            .collect::<Vec<_>>()
+            .join(",")
+    )
+}
+
+// Returns an `Event(indexed uint256,address)` type signature for an event.
+fn event_signature(event: &Event) -> String {
+    format!(
+        "{}({})",
+        event.name,
+        event
+            .inputs
+            .iter()
+            .map(|input| format!(
+                "{}{}",
+                if input.indexed { "indexed " } else { "" },
+                event_param_type_signature(&input.kind)
+            ))
+            .collect::<Vec<_>>()
+            .join(",")
+    )
+}
+
+// Returns the signature of an event parameter type (e.g. `uint256`).
+fn event_param_type_signature(kind: &ParamType) -> String {
+    use ParamType::*;
+
+    match kind {
+        Address => "address".into(),
+        Bytes => "bytes".into(),
+        Int(size) => format!("int{}", size),
+        Uint(size) => format!("uint{}", size),
+        Bool => "bool".into(),
+        String => "string".into(),
+        Array(inner) => format!("{}[]", event_param_type_signature(&*inner)),
+        FixedBytes(size) => format!("bytes{}", size),
+        FixedArray(inner, size) => {
+            format!("{}[{}]", event_param_type_signature(&*inner), size)
+        }
+        Tuple(components) => format!(
+            "({})",
+            components
+                .iter()
+                .map(|component| event_param_type_signature(&component))
+                .collect::<Vec<_>>()
+                .join(",")
+        ),
+    }
+}
+
+// Returns an `operation(address,uint256,bool)` signature for a function.
+fn function_signature(function: &Function) -> String {
+    format!(
+        "{}({})",
+        function.name,
+        function
+            .inputs
+            .iter()
+            .map(|input| format!("{}", input.kind))
+            .collect::<Vec<_>>()
+            .join(",")
+    )
+}
+
+// Returns the part of a handler's `event` or `function` before the parameters.
+fn signature_name(signature: &str) -> &str {
+    &signature[..signature.find('(').unwrap_or(signature.len())]
+}
+
+fn quoted(signatures: impl Iterator<Item = String>) -> String {
+    signatures
+        .map(|signature| format!("`{}`", signature))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
 impl UnresolvedMappingABI {
     pub async fn resolve(
         self,
@@ -990,6 +1147,11 @@ impl UnresolvedMappingABI {
 pub struct MappingBlockHandler {
     pub handler: String,
     pub filter: Option<BlockHandlerFilter>,
+    /// The entity types that the handler may access, which must be a
+    /// subset of the `entities` of the mapping. Handlers that do not
+    /// declare any are not restricted
+    #[serde(default)]
+    pub entities: Option<Vec<String>>,
 }
 
 #[derive(Clone, Debug, Hash, Eq, PartialEq, Deserialize)]
@@ -1004,6 +1166,8 @@ pub enum BlockHandlerFilter {
 pub struct MappingCallHandler {
     pub function: String,
     pub handler: String,
+    #[serde(default)]
+    pub entities: Option<Vec<String>>,
 }
 
 #[derive(Clone, Debug, Hash, Eq, PartialEq, Deserialize)]
@@ -1013,6 +1177,8 @@ pub struct MappingEventHandler {
     pub handler: String,
     #[serde(default)]
     pub receipt: bool,
+    #[serde(default)]
+    pub entities: Option<Vec<String>>,
 }
 
 impl MappingEventHandler {
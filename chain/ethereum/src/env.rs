@@ -26,9 +26,6 @@ pub struct EnvVars {
     /// default value is 2000.
     pub get_logs_max_contracts: usize,
 
-    /// Set by the environment variable `ETHEREUM_REORG_THRESHOLD`. The default
-    /// value is 250 blocks.
-    pub reorg_threshold: BlockNumber,
     /// Set by the environment variable `ETHEREUM_TRACE_STREAM_STEP_SIZE`. The
     /// default value is 50 blocks.
     pub trace_stream_step_size: BlockNumber,
@@ -83,6 +80,17 @@ pub struct EnvVars {
     /// `GRAPH_ETHEREUM_TARGET_TRIGGERS_PER_BLOCK_RANGE`. The default value is
     /// 100.
     pub target_triggers_per_block_range: u64,
+    /// Whether logs for a block range may come from the receipts of blocks
+    /// in the block cache instead of `eth_getLogs` when that is cheaper.
+    ///
+    /// Set by the flag `GRAPH_ETHEREUM_SCAN_CACHED_BLOCKS`. Off by default.
+    pub scan_cached_blocks: bool,
+    /// How many cached blocks reading one `eth_getLogs` call is worth when
+    /// choosing between the two.
+    ///
+    /// Set by the environment variable `GRAPH_ETHEREUM_GET_LOGS_CALL_COST`.
+    /// The default value is 50.
+    pub get_logs_call_cost: f64,
 }
 
 // This does not print any values avoid accidentally leaking any sensitive env vars
@@ -109,7 +117,6 @@ impl From<Inner> for EnvVars {
                 .filter(|s| !s.is_empty())
                 .map(str::to_string)
                 .collect(),
-            reorg_threshold: x.reorg_threshold,
             trace_stream_step_size: x.trace_stream_step_size,
             max_event_only_range: x.max_event_only_range,
             block_batch_size: x.block_batch_size,
@@ -124,6 +131,8 @@ impl From<Inner> for EnvVars {
                 .unwrap_or(cfg!(target_os = "macos")),
             cleanup_blocks: x.cleanup_blocks.0,
             target_triggers_per_block_range: x.target_triggers_per_block_range,
+            scan_cached_blocks: x.scan_cached_blocks.0,
+            get_logs_call_cost: x.get_logs_call_cost,
         }
     }
 }
@@ -144,8 +153,6 @@ struct Inner {
     get_logs_max_contracts: usize,
 
     // JSON-RPC specific.
-    #[envconfig(from = "ETHEREUM_REORG_THRESHOLD", default = "250")]
-    reorg_threshold: BlockNumber,
     #[envconfig(from = "ETHEREUM_TRACE_STREAM_STEP_SIZE", default = "50")]
     trace_stream_step_size: BlockNumber,
     #[envconfig(from = "GRAPH_ETHEREUM_MAX_EVENT_ONLY_RANGE", default = "500")]
@@ -172,4 +179,8 @@ struct Inner {
         default = "100"
     )]
     target_triggers_per_block_range: u64,
+    #[envconfig(from = "GRAPH_ETHEREUM_SCAN_CACHED_BLOCKS", default = "false")]
+    scan_cached_blocks: EnvVarBoolean,
+    #[envconfig(from = "GRAPH_ETHEREUM_GET_LOGS_CALL_COST", default = "50")]
+    get_logs_call_cost: f64,
 }
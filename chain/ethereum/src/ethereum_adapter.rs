@@ -4,12 +4,13 @@ use futures03::{future::BoxFuture, stream::FuturesUnordered};
 use graph::blockchain::BlockHash;
 use graph::blockchain::ChainIdentifier;
 use graph::components::transaction_receipt::LightTransactionReceipt;
+use graph::data::subgraph::status::{ProviderInfo, ProviderState};
 use graph::data::subgraph::UnifiedMappingApiVersion;
 use graph::data::subgraph::API_VERSION_0_0_5;
 use graph::data::subgraph::API_VERSION_0_0_7;
 use graph::prelude::ethabi::ParamType;
 use graph::prelude::ethabi::Token;
-use graph::prelude::tokio::try_join;
+use graph::prelude::tokio::{self, try_join};
 use graph::{
     blockchain::{block_stream::BlockWithTriggers, BlockPtr, IngestorError},
     prelude::{
@@ -24,31 +25,37 @@ use graph::{
                 FilterBuilder, Log, Transaction, TransactionReceipt, H256,
             },
         },
-        BlockNumber, ChainStore, CheapClone, DynTryFuture, Error, EthereumCallCache, Logger,
-        TimeoutError, TryFutureExt,
+        BlockNumber, ChainStore, CheapClone, Deserialize, DynTryFuture, Error, EthereumCallCache,
+        Logger, TimeoutError, TryFutureExt,
     },
 };
 use graph::{
     components::ethereum::*,
     prelude::web3::api::Web3,
     prelude::web3::transports::Batch,
-    prelude::web3::types::{Trace, TraceFilter, TraceFilterBuilder, H160},
+    prelude::web3::types::{
+        Action, ActionType, Call, CallResult, CallType, Res, Trace, TraceFilter,
+        TraceFilterBuilder, H160, U256,
+    },
+    prelude::web3::Transport as _,
 };
 use itertools::Itertools;
 use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::convert::TryFrom;
 use std::iter::FromIterator;
 use std::pin::Pin;
-use std::sync::Arc;
-use std::time::Instant;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
 use crate::adapter::ProviderStatus;
 use crate::chain::BlockFinality;
+use crate::log_scan::{cached_blocks, LogSource, LogSourceChooser};
 use crate::{
     adapter::{
         EthGetLogsFilter, EthereumAdapter as EthereumAdapterTrait, EthereumBlockFilter,
         EthereumCallFilter, EthereumContractCall, EthereumContractCallError, EthereumLogFilter,
-        ProviderEthRpcMetrics, SubgraphEthRpcMetrics,
+        GetLogsLimits, ProviderEthRpcMetrics, SubgraphEthRpcMetrics,
     },
     transport::Transport,
     trigger::{EthereumBlockTriggerType, EthereumTrigger},
@@ -64,6 +71,13 @@ pub struct EthereumAdapter {
     web3: Arc<Web3<Transport>>,
     metrics: Arc<ProviderEthRpcMetrics>,
     supports_eip_1898: bool,
+    /// Whether the provider can return all receipts of a block in one
+    /// call. Shared between clones so that it is only determined once
+    block_receipts: Arc<Mutex<BlockReceiptsSupport>>,
+    /// Set once we found out that the provider does not support
+    /// `trace_filter`, after which traces come from `debug_traceBlockByHash`
+    debug_traces: Arc<AtomicBool>,
+    get_logs_limits: GetLogsLimits,
 }
 
 /// Gas limit for `eth_call`. The value of 50_000_000 is a protocol-wide parameter so this
@@ -85,11 +99,36 @@ impl CheapClone for EthereumAdapter {
             web3: self.web3.cheap_clone(),
             metrics: self.metrics.cheap_clone(),
             supports_eip_1898: self.supports_eip_1898,
+            block_receipts: self.block_receipts.cheap_clone(),
+            debug_traces: self.debug_traces.cheap_clone(),
+            get_logs_limits: self.get_logs_limits,
         }
     }
 }
 
 impl EthereumAdapter {
+    /// Ask the provider for its latest block number for the status API,
+    /// waiting at most `timeout` for the answer
+    pub async fn probe(&self, timeout: Duration) -> ProviderInfo {
+        let start = Instant::now();
+        let answer = tokio::time::timeout(timeout, self.web3.eth().block_number()).await;
+        let (status, latest_block, latency) = match answer {
+            Ok(Ok(number)) => (
+                ProviderState::Ok,
+                Some(number.as_u64() as BlockNumber),
+                Some(start.elapsed()),
+            ),
+            Ok(Err(_)) => (ProviderState::Failed, None, Some(start.elapsed())),
+            Err(_) => (ProviderState::Timeout, None, None),
+        };
+        ProviderInfo {
+            name: self.provider.clone(),
+            status,
+            latest_block,
+            latency,
+        }
+    }
+
     pub async fn new(
         logger: Logger,
         provider: String,
@@ -97,6 +136,7 @@ impl EthereumAdapter {
         transport: Transport,
         provider_metrics: Arc<ProviderEthRpcMetrics>,
         supports_eip_1898: bool,
+        get_logs_limits: GetLogsLimits,
     ) -> Self {
         // Unwrap: The transport was constructed with this url, so it is valid and has a host.
         let hostname = graph::url::Url::parse(url)
@@ -123,9 +163,88 @@ impl EthereumAdapter {
             web3,
             metrics: provider_metrics,
             supports_eip_1898: supports_eip_1898 && !is_ganache,
+            block_receipts: Arc::new(Mutex::new(BlockReceiptsSupport::Unknown)),
+            debug_traces: Arc::new(AtomicBool::new(false)),
+            get_logs_limits,
         }
     }
 
+    /// All receipts of the block `block_hash`, ordered by transaction
+    /// index, if the provider can return them in one call, and `None` if
+    /// it can not. Which method the provider supports for that is
+    /// determined the first time this is called
+    async fn block_receipts(
+        &self,
+        logger: &Logger,
+        block_hash: H256,
+    ) -> Result<Option<Vec<Arc<TransactionReceipt>>>, IngestorError> {
+        let support = *self.block_receipts.lock().unwrap();
+        let methods = match support {
+            BlockReceiptsSupport::Unsupported => return Ok(None),
+            BlockReceiptsSupport::Supported(method) => vec![method],
+            BlockReceiptsSupport::Unknown => BlockReceiptsMethod::ALL.to_vec(),
+        };
+
+        for method in methods {
+            let receipts = fetch_block_receipts_with_retry(
+                self.web3.cheap_clone(),
+                method,
+                block_hash,
+                logger.cheap_clone(),
+            )
+            .await?;
+            if let Some(receipts) = receipts {
+                if support == BlockReceiptsSupport::Unknown {
+                    info!(logger, "Fetching the receipts of blocks with `{}`", method.name();
+                          "provider" => &self.provider);
+                }
+                *self.block_receipts.lock().unwrap() = BlockReceiptsSupport::Supported(method);
+                return Ok(Some(receipts));
+            }
+        }
+
+        info!(logger, "Provider can not return all receipts of a block at once, fetching them one by one";
+              "provider" => &self.provider);
+        *self.block_receipts.lock().unwrap() = BlockReceiptsSupport::Unsupported;
+        Ok(None)
+    }
+
+    /// Whether there is code at `address` in block `number`
+    async fn has_code(
+        &self,
+        logger: &Logger,
+        address: Address,
+        number: BlockNumber,
+    ) -> Result<bool, Error> {
+        let web3 = self.web3.clone();
+        retry(
+            format!("eth_getCode RPC call for block #{}", number),
+            logger,
+        )
+        .limit(ENV_VARS.request_retries)
+        .timeout_secs(ENV_VARS.json_rpc_timeout.as_secs())
+        .run(move || {
+            let web3 = web3.cheap_clone();
+            async move {
+                web3.eth()
+                    .code(address, Some(number.into()))
+                    .await
+                    .map(|code| !code.0.is_empty())
+                    .map_err(Error::from)
+            }
+        })
+        .await
+        .map_err(move |e| {
+            e.into_inner().unwrap_or_else(move || {
+                anyhow!(
+                    "Ethereum node took too long to return the code of {:?} at block #{}",
+                    address,
+                    number
+                )
+            })
+        })
+    }
+
     async fn traces(
         self,
         logger: Logger,
@@ -134,10 +253,59 @@ impl EthereumAdapter {
         to: BlockNumber,
         addresses: Vec<H160>,
     ) -> Result<Vec<Trace>, Error> {
+        if self.debug_traces.load(Ordering::SeqCst) {
+            return self
+                .debug_traces(&logger, subgraph_metrics, from, to, addresses)
+                .await;
+        }
+
+        match self
+            .cheap_clone()
+            .trace_filter(
+                logger.cheap_clone(),
+                subgraph_metrics.cheap_clone(),
+                from,
+                to,
+                addresses.clone(),
+            )
+            .await
+        {
+            Ok(traces) => Ok(traces),
+            Err(TimeoutError::Inner(e)) if is_unsupported_method(&e) => {
+                if !self.debug_traces.swap(true, Ordering::SeqCst) {
+                    info!(logger, "Provider does not support `trace_filter`, getting traces with `debug_traceBlockByHash`";
+                          "provider" => &self.provider);
+                }
+                self.debug_traces(&logger, subgraph_metrics, from, to, addresses)
+                    .await
+            }
+            Err(e) => Err(e.into_inner().map(Error::from).unwrap_or_else(move || {
+                anyhow::anyhow!(
+                    "Ethereum node took too long to respond to trace_filter \
+                     (from block {}, to block {})",
+                    from,
+                    to
+                )
+            })),
+        }
+    }
+
+    async fn trace_filter(
+        self,
+        logger: Logger,
+        subgraph_metrics: Arc<SubgraphEthRpcMetrics>,
+        from: BlockNumber,
+        to: BlockNumber,
+        addresses: Vec<H160>,
+    ) -> Result<Vec<Trace>, TimeoutError<web3::Error>> {
         let eth = self.clone();
         let retry_log_message =
             format!("trace_filter RPC call for block range: [{}..{}]", from, to);
         retry(retry_log_message, &logger)
+            .when(|result: &Result<Vec<Trace>, web3::Error>| match result {
+                Ok(_) => false,
+                Err(e) => !is_unsupported_method(e),
+            })
             .limit(ENV_VARS.request_retries)
             .timeout_secs(ENV_VARS.json_rpc_timeout.as_secs())
             .run(move || {
@@ -187,8 +355,7 @@ impl EthereumAdapter {
                                 }
                             }
                             traces
-                        })
-                        .map_err(Error::from);
+                        });
 
                     let elapsed = start.elapsed().as_secs_f64();
                     provider_metrics.observe_request(elapsed, "trace_filter", &provider);
@@ -204,17 +371,123 @@ impl EthereumAdapter {
                     result
                 }
             })
+            .await
+    }
+
+    /// The same traces that `trace_filter` returns for the calls to
+    /// `addresses` in blocks `from` to `to`, assembled from what Geth's
+    /// `callTracer` reports for each block
+    async fn debug_traces(
+        &self,
+        logger: &Logger,
+        subgraph_metrics: Arc<SubgraphEthRpcMetrics>,
+        from: BlockNumber,
+        to: BlockNumber,
+        addresses: Vec<H160>,
+    ) -> Result<Vec<Trace>, Error> {
+        let addresses: Arc<HashSet<H160>> = Arc::new(addresses.into_iter().collect());
+        let traces: Vec<Vec<Trace>> = futures03::stream::iter(from..=to)
+            .map(|number| {
+                self.debug_traces_in_block(
+                    logger.cheap_clone(),
+                    subgraph_metrics.cheap_clone(),
+                    number,
+                    addresses.cheap_clone(),
+                )
+            })
+            .buffered(ENV_VARS.block_batch_size)
+            .try_collect()
+            .await?;
+        Ok(traces.into_iter().flatten().collect())
+    }
+
+    async fn debug_traces_in_block(
+        &self,
+        logger: Logger,
+        subgraph_metrics: Arc<SubgraphEthRpcMetrics>,
+        number: BlockNumber,
+        addresses: Arc<HashSet<H160>>,
+    ) -> Result<Vec<Trace>, Error> {
+        let web3 = self.web3.cheap_clone();
+        let provider_metrics = self.metrics.cheap_clone();
+        let provider = self.provider.clone();
+        let retry_log_message = format!("debug_traceBlockByHash RPC call for block {}", number);
+        retry(retry_log_message, &logger)
+            .limit(ENV_VARS.request_retries)
+            .timeout_secs(ENV_VARS.json_rpc_timeout.as_secs())
+            .run(move || {
+                let web3 = web3.cheap_clone();
+                let addresses = addresses.cheap_clone();
+                let subgraph_metrics = subgraph_metrics.cheap_clone();
+                let provider_metrics = provider_metrics.cheap_clone();
+                let provider = provider.clone();
+                async move {
+                    let start = Instant::now();
+                    let block = web3
+                        .eth()
+                        .block(BlockId::Number(Web3BlockNumber::Number(
+                            (number as u64).into(),
+                        )))
+                        .await?
+                        .ok_or_else(|| anyhow!("Ethereum node could not find block {}", number))?;
+                    let block_hash = block
+                        .hash
+                        .ok_or_else(|| anyhow!("block {} has no hash", number))?;
+                    let result = web3
+                        .transport()
+                        .execute(
+                            "debug_traceBlockByHash",
+                            vec![
+                                json::Value::String(format!("{:#x}", block_hash)),
+                                json::json!({ "tracer": "callTracer" }),
+                            ],
+                        )
+                        .await;
+
+                    let elapsed = start.elapsed().as_secs_f64();
+                    provider_metrics.observe_request(elapsed, "debug_traceBlockByHash", &provider);
+                    subgraph_metrics.observe_request(elapsed, "debug_traceBlockByHash", &provider);
+                    if result.is_err() {
+                        provider_metrics.add_error("debug_traceBlockByHash", &provider);
+                        subgraph_metrics.add_error("debug_traceBlockByHash", &provider);
+                    }
+
+                    let frames: Vec<DebugTraceResult> = json::from_value(result?)?;
+                    if frames.len() != block.transactions.len() {
+                        return Err(anyhow!(
+                            "debug_traceBlockByHash returned {} traces for the {} transactions of block {}",
+                            frames.len(),
+                            block.transactions.len(),
+                            number
+                        ));
+                    }
+                    let mut traces = Vec::new();
+                    for (position, (frame, transaction_hash)) in
+                        frames.into_iter().zip(block.transactions).enumerate()
+                    {
+                        let transaction = CallFrameTransaction {
+                            block_number: number as u64,
+                            block_hash,
+                            transaction_hash,
+                            transaction_position: position,
+                        };
+                        frame
+                            .result
+                            .into_traces(&transaction, vec![], &addresses, &mut traces);
+                    }
+                    Ok(traces)
+                }
+            })
+            .await
             .map_err(move |e| {
                 e.into_inner().unwrap_or_else(move || {
-                    anyhow::anyhow!(
-                        "Ethereum node took too long to respond to trace_filter \
-                         (from block {}, to block {})",
-                        from,
-                        to
+                    anyhow!(
+                        "Ethereum node took too long to respond to debug_traceBlockByHash \
+                         (block {})",
+                        number
                     )
                 })
             })
-            .await
     }
 
     async fn logs_with_sigs(
@@ -431,7 +704,7 @@ impl EthereumAdapter {
         let retry_log_message = format!("eth_call RPC call for block {}", block_ptr);
         retry(retry_log_message, &logger)
             .when(|result| match result {
-                Ok(_) | Err(EthereumContractCallError::Revert(_)) => false,
+                Ok(_) | Err(EthereumContractCallError::Revert(..)) => false,
                 Err(_) => true,
             })
             .limit(ENV_VARS.request_retries)
@@ -520,7 +793,15 @@ impl EthereumAdapter {
                             if geth_execution_errors
                                 .any(|e| rpc_error.message.to_lowercase().contains(e)) =>
                         {
-                            Err(EthereumContractCallError::Revert(rpc_error.message))
+                            // Geth includes the data the contract reverted
+                            // with as a hex string
+                            let data = rpc_error
+                                .data
+                                .as_ref()
+                                .and_then(|data| data.as_str())
+                                .and_then(|data| hex::decode(data.trim_start_matches("0x")).ok())
+                                .unwrap_or_default();
+                            Err(EthereumContractCallError::Revert(rpc_error.message, data))
                         }
 
                         // Check for Parity revert.
@@ -536,18 +817,20 @@ impl EthereumAdapter {
                                         || data == PARITY_BAD_INSTRUCTION_FD
                                         || data == PARITY_OUT_OF_GAS =>
                                 {
+                                    let payload = match data.starts_with(PARITY_REVERT_PREFIX) {
+                                        true => hex::decode(
+                                            data.trim_start_matches(PARITY_REVERT_PREFIX),
+                                        )
+                                        .unwrap_or_default(),
+                                        false => vec![],
+                                    };
                                     let reason = if data == PARITY_BAD_INSTRUCTION_FE {
                                         PARITY_BAD_INSTRUCTION_FE.to_owned()
                                     } else {
-                                        let payload = data.trim_start_matches(PARITY_REVERT_PREFIX);
-                                        hex::decode(payload)
-                                            .ok()
-                                            .and_then(|payload| {
-                                                as_solidity_revert_with_reason(&payload)
-                                            })
+                                        as_solidity_revert_with_reason(&payload)
                                             .unwrap_or("no reason".to_owned())
                                     };
-                                    Err(EthereumContractCallError::Revert(reason))
+                                    Err(EthereumContractCallError::Revert(reason, payload))
                                 }
 
                                 // The VM execution error was not identified as a revert.
@@ -669,19 +952,23 @@ impl EthereumAdapter {
     ) -> DynTryFuture<'static, Vec<Log>, Error> {
         let eth: Self = self.cheap_clone();
         let logger = logger.clone();
+        let limits = self.get_logs_limits;
 
-        futures03::stream::iter(log_filter.eth_get_logs_filters().map(move |filter| {
-            eth.cheap_clone().log_stream(
-                logger.cheap_clone(),
-                subgraph_metrics.cheap_clone(),
-                from,
-                to,
-                filter,
-            )
-        }))
+        futures03::stream::iter(log_filter.eth_get_logs_filters(limits.max_contracts).map(
+            move |filter| {
+                eth.cheap_clone().log_stream(
+                    logger.cheap_clone(),
+                    subgraph_metrics.cheap_clone(),
+                    from,
+                    to,
+                    filter,
+                )
+            },
+        ))
         // Real limits on the number of parallel requests are imposed within the adapter.
-        .buffered(ENV_VARS.block_ingestor_max_concurrent_json_rpc_calls)
+        .buffered(limits.parallelism.max(1))
         .try_concat()
+        .map_ok(merge_logs)
         .boxed()
     }
 
@@ -1064,35 +1351,30 @@ impl EthereumAdapterTrait for EthereumAdapter {
             })));
         }
         let hashes: Vec<_> = block.transactions.iter().map(|txn| txn.hash).collect();
-        let receipts_future = if ENV_VARS.fetch_receipts_in_batches {
-            // Deprecated batching retrieval of transaction receipts.
-            fetch_transaction_receipts_in_batch_with_retry(web3, hashes, block_hash, logger).boxed()
-        } else {
-            let hash_stream = graph::tokio_stream::iter(hashes);
-            let receipt_stream = graph::tokio_stream::StreamExt::map(hash_stream, move |tx_hash| {
-                fetch_transaction_receipt_with_retry(
-                    web3.cheap_clone(),
-                    tx_hash,
-                    block_hash,
-                    logger.cheap_clone(),
-                )
-            })
-            .buffered(ENV_VARS.block_ingestor_max_concurrent_json_rpc_calls);
-            graph::tokio_stream::StreamExt::collect::<
-                Result<Vec<Arc<TransactionReceipt>>, IngestorError>,
-            >(receipt_stream)
-            .boxed()
-        };
-
-        let block_future =
-            futures03::TryFutureExt::map_ok(receipts_future, move |transaction_receipts| {
-                EthereumBlock {
-                    block: Arc::new(block),
-                    transaction_receipts,
+        let adapter = self.cheap_clone();
+        Box::pin(async move {
+            let transaction_receipts = match adapter.block_receipts(&logger, block_hash).await? {
+                Some(receipts) => {
+                    if !receipts
+                        .iter()
+                        .map(|receipt| &receipt.transaction_hash)
+                        .eq(hashes.iter())
+                    {
+                        return Err(anyhow!(
+                            "the receipts for block {:?} do not match its transactions",
+                            block_hash
+                        )
+                        .into());
+                    }
+                    receipts
                 }
-            });
-
-        Box::pin(block_future)
+                None => fetch_transaction_receipts(web3, hashes, block_hash, logger).await?,
+            };
+            Ok(EthereumBlock {
+                block: Arc::new(block),
+                transaction_receipts,
+            })
+        })
     }
 
     fn block_pointer_from_number(
@@ -1227,12 +1509,18 @@ impl EthereumAdapterTrait for EthereumAdapter {
                     // We got a `0x` response. For old Geth, this can mean a revert. It can also be
                     // that the contract actually returned an empty response. A view call is meant
                     // to return something, so we treat empty responses the same as reverts.
-                    Err(EthereumContractCallError::Revert("empty response".into()))
+                    Err(EthereumContractCallError::Revert(
+                        "empty response".into(),
+                        vec![],
+                    ))
                 } else {
                     // Decode failures are reverts. The reasoning is that if Solidity fails to
                     // decode an argument, that's a revert, so the same goes for the output.
                     call.function.decode_output(&output).map_err(|e| {
-                        EthereumContractCallError::Revert(format!("failed to decode output: {}", e))
+                        EthereumContractCallError::Revert(
+                            format!("failed to decode output: {}", e),
+                            vec![],
+                        )
                     })
                 }
             }),
@@ -1287,6 +1575,30 @@ impl EthereumAdapterTrait for EthereumAdapter {
                 .flatten_stream(),
         )
     }
+
+    async fn contract_creation_block(
+        &self,
+        logger: &Logger,
+        address: Address,
+        latest: BlockNumber,
+    ) -> Result<Option<BlockNumber>, Error> {
+        if !self.has_code(logger, address, latest).await? {
+            return Ok(None);
+        }
+
+        // Contracts have code from the block in which they are created on,
+        // which makes it possible to find that block by bisection
+        let (mut low, mut high) = (0, latest);
+        while low < high {
+            let mid = low + (high - low) / 2;
+            if self.has_code(logger, address, mid).await? {
+                high = mid;
+            } else {
+                low = mid + 1;
+            }
+        }
+        Ok(Some(low))
+    }
 }
 
 /// Returns blocks with triggers, corresponding to the specified range and filters.
@@ -1311,6 +1623,7 @@ pub(crate) async fn blocks_with_triggers(
     to: BlockNumber,
     filter: &TriggerFilter,
     unified_api_version: UnifiedMappingApiVersion,
+    log_source: &LogSourceChooser,
 ) -> Result<Vec<BlockWithTriggers<crate::Chain>>, Error> {
     // Each trigger filter needs to be queried for the same block range
     // and the blocks yielded need to be deduped. If any error occurs
@@ -1322,19 +1635,76 @@ pub(crate) async fn blocks_with_triggers(
     let trigger_futs: FuturesUnordered<BoxFuture<Result<Vec<EthereumTrigger>, anyhow::Error>>> =
         FuturesUnordered::new();
 
-    // Scan for Logs
+    let to_hash_fut = || {
+        adapter
+            .block_hash_by_block_number(&logger, to)
+            .and_then(|hash| match hash {
+                Some(hash) => Ok(hash),
+                None => {
+                    warn!(logger,
+                          "Ethereum endpoint is behind";
+                          "url" => eth.url_hostname()
+                    );
+                    bail!("Block {} not found in the chain", to)
+                }
+            })
+            .compat()
+    };
+    let mut scanned_to_hash = None;
+
+    // Scan for Logs, either with `eth_getLogs` or in the receipts of
+    // cached blocks, whichever is expected to be cheaper
     if !filter.log.is_empty() {
-        let logs_future = get_logs_and_transactions(
-            eth.clone(),
-            &logger,
-            subgraph_metrics.clone(),
-            from,
-            to,
-            filter.log.clone(),
-            &unified_api_version,
-        )
-        .boxed();
-        trigger_futs.push(logs_future)
+        let blocks = to - from + 1;
+        let step = ENV_VARS.max_event_only_range.max(1);
+        let calls = filter
+            .log
+            .clone()
+            .eth_get_logs_filters(adapter.get_logs_limits.max_contracts)
+            .count()
+            * ((blocks + step - 1) / step) as usize;
+        let cached = match log_source.choose(blocks, calls) {
+            LogSource::BlockCache => {
+                // The cached blocks have to form the chain that the
+                // provider has at `to`; the cache can also hold blocks
+                // that were reorged away
+                let to_hash = to_hash_fut()
+                    .await
+                    .with_context(|| format!("Failed to infer hash for block {}", to))?;
+                scanned_to_hash = Some(to_hash);
+                let chain_store = chain_store.cheap_clone();
+                let cached = graph::spawn_blocking_allow_panic(move || {
+                    cached_blocks(chain_store.as_ref(), from, to, to_hash)
+                })
+                .await??;
+                log_source.record_scan(cached.is_some());
+                cached
+            }
+            LogSource::GetLogs => None,
+        };
+        match cached {
+            Some(cached) => {
+                debug!(logger, "Found logs in cached blocks"; "from" => from, "to" => to);
+                let triggers = cached
+                    .iter()
+                    .flat_map(|block| parse_log_triggers(&filter.log, block))
+                    .collect();
+                trigger_futs.push(futures03::future::ready(Ok(triggers)).boxed())
+            }
+            None => {
+                let logs_future = get_logs_and_transactions(
+                    eth.clone(),
+                    &logger,
+                    subgraph_metrics.clone(),
+                    from,
+                    to,
+                    filter.log.clone(),
+                    &unified_api_version,
+                )
+                .boxed();
+                trigger_futs.push(logs_future)
+            }
+        }
     }
     // Scan for Calls
     if !filter.call.is_empty() {
@@ -1378,20 +1748,11 @@ pub(crate) async fn blocks_with_triggers(
         trigger_futs.push(block_future)
     }
 
-    // Get hash for "to" block
-    let to_hash_fut = adapter
-        .block_hash_by_block_number(&logger, to)
-        .and_then(|hash| match hash {
-            Some(hash) => Ok(hash),
-            None => {
-                warn!(logger,
-                      "Ethereum endpoint is behind";
-                      "url" => eth.url_hostname()
-                );
-                bail!("Block {} not found in the chain", to)
-            }
-        })
-        .compat();
+    // Get hash for "to" block, unless we already did for the scan
+    let to_hash_fut = match scanned_to_hash {
+        Some(hash) => futures03::future::ok(hash).boxed(),
+        None => to_hash_fut().boxed(),
+    };
 
     // Join on triggers and block hash resolution
     let (triggers, to_hash) = futures03::join!(trigger_futs.try_concat(), to_hash_fut);
@@ -1401,6 +1762,16 @@ pub(crate) async fn blocks_with_triggers(
         triggers.with_context(|| format!("Failed to obtain triggers for block {}", to))?;
     let to_hash = to_hash.with_context(|| format!("Failed to infer hash for block {}", to))?;
 
+    if !filter.log.is_empty() {
+        let with_logs = triggers
+            .iter()
+            .filter(|trigger| matches!(trigger, EthereumTrigger::Log(..)))
+            .map(EthereumTrigger::block_number)
+            .collect::<HashSet<_>>()
+            .len();
+        log_source.record_density(to - from + 1, with_logs);
+    }
+
     let mut block_hashes: HashSet<H256> =
         triggers.iter().map(EthereumTrigger::block_hash).collect();
     let mut triggers_by_block: HashMap<BlockNumber, Vec<EthereumTrigger>> =
@@ -1737,6 +2108,249 @@ async fn filter_call_triggers_from_unsuccessful_transactions(
     Ok(block)
 }
 
+/// Fetches the receipts for `hashes` one transaction at a time, for
+/// providers that can not return all receipts of a block at once
+async fn fetch_transaction_receipts(
+    web3: Arc<Web3<Transport>>,
+    hashes: Vec<H256>,
+    block_hash: H256,
+    logger: Logger,
+) -> Result<Vec<Arc<TransactionReceipt>>, IngestorError> {
+    if ENV_VARS.fetch_receipts_in_batches {
+        // Deprecated batching retrieval of transaction receipts.
+        return fetch_transaction_receipts_in_batch_with_retry(web3, hashes, block_hash, logger)
+            .await;
+    }
+
+    let hash_stream = graph::tokio_stream::iter(hashes);
+    let receipt_stream = graph::tokio_stream::StreamExt::map(hash_stream, move |tx_hash| {
+        fetch_transaction_receipt_with_retry(
+            web3.cheap_clone(),
+            tx_hash,
+            block_hash,
+            logger.cheap_clone(),
+        )
+    })
+    .buffered(ENV_VARS.block_ingestor_max_concurrent_json_rpc_calls);
+    graph::tokio_stream::StreamExt::collect::<Result<Vec<Arc<TransactionReceipt>>, IngestorError>>(
+        receipt_stream,
+    )
+    .await
+}
+
+/// The JSON-RPC methods with which providers return all receipts of a
+/// block in one call
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum BlockReceiptsMethod {
+    /// Supported by Erigon, Nethermind and recent versions of Geth
+    Eth,
+    /// Alchemy's equivalent of `eth_getBlockReceipts`
+    Alchemy,
+}
+
+impl BlockReceiptsMethod {
+    /// The methods in the order in which we try them
+    const ALL: [BlockReceiptsMethod; 2] = [BlockReceiptsMethod::Eth, BlockReceiptsMethod::Alchemy];
+
+    fn name(&self) -> &'static str {
+        match self {
+            BlockReceiptsMethod::Eth => "eth_getBlockReceipts",
+            BlockReceiptsMethod::Alchemy => "alchemy_getTransactionReceipts",
+        }
+    }
+
+    fn params(&self, block_hash: H256) -> Vec<json::Value> {
+        let block_hash = format!("{:#x}", block_hash);
+        match self {
+            BlockReceiptsMethod::Eth => vec![json::Value::String(block_hash)],
+            BlockReceiptsMethod::Alchemy => vec![json::json!({ "blockHash": block_hash })],
+        }
+    }
+
+    /// The receipts in the response to a call of this method; `None` if
+    /// the provider does not know the block
+    fn receipts(&self, response: json::Value) -> Result<Option<Vec<TransactionReceipt>>, Error> {
+        let receipts = match self {
+            BlockReceiptsMethod::Eth => response,
+            BlockReceiptsMethod::Alchemy => response
+                .get("receipts")
+                .cloned()
+                .unwrap_or(json::Value::Null),
+        };
+        json::from_value(receipts).map_err(Error::from)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum BlockReceiptsSupport {
+    Unknown,
+    Supported(BlockReceiptsMethod),
+    Unsupported,
+}
+
+/// Whether the provider rejected a call because it does not implement
+/// the method. Some providers use a generic error code for that, but
+/// then say "method not found"
+fn is_unsupported_method(error: &web3::Error) -> bool {
+    const METHOD_NOT_FOUND: i64 = -32601;
+
+    match error {
+        web3::Error::Rpc(rpc_error) => {
+            rpc_error.code.code() == METHOD_NOT_FOUND
+                || rpc_error
+                    .message
+                    .to_lowercase()
+                    .contains("method not found")
+        }
+        _ => false,
+    }
+}
+
+/// Fetches all receipts of the block `block_hash` with one call to
+/// `method`, ordered by transaction index. Returns `None` if the provider
+/// does not support `method`
+async fn fetch_block_receipts_with_retry(
+    web3: Arc<Web3<Transport>>,
+    method: BlockReceiptsMethod,
+    block_hash: H256,
+    logger: Logger,
+) -> Result<Option<Vec<Arc<TransactionReceipt>>>, IngestorError> {
+    let retry_log_message = format!("{} RPC call for block {:?}", method.name(), block_hash);
+    let response = retry(retry_log_message, &logger)
+        .when(|result: &Result<json::Value, web3::Error>| match result {
+            Ok(_) => false,
+            Err(e) => !is_unsupported_method(e),
+        })
+        .limit(ENV_VARS.request_retries)
+        .timeout_secs(ENV_VARS.json_rpc_timeout.as_secs())
+        .run(move || {
+            web3.transport()
+                .execute(method.name(), method.params(block_hash))
+                .boxed()
+        })
+        .await;
+    let response = match response {
+        Ok(response) => response,
+        Err(TimeoutError::Inner(e)) if is_unsupported_method(&e) => return Ok(None),
+        Err(_) => return Err(anyhow!(block_hash).into()),
+    };
+
+    let mut receipts = method
+        .receipts(response)
+        .with_context(|| format!("invalid response to {}", method.name()))?
+        .ok_or(IngestorError::BlockUnavailable(block_hash))?;
+    // Receipts from a different block mean that the block is no longer on
+    // the provider's main chain
+    if receipts
+        .iter()
+        .any(|receipt| receipt.block_hash != Some(block_hash))
+    {
+        return Err(IngestorError::BlockUnavailable(block_hash));
+    }
+    receipts.sort_by_key(|receipt| receipt.transaction_index);
+    Ok(Some(receipts.into_iter().map(Arc::new).collect()))
+}
+
+/// One entry in the response to `debug_traceBlockByHash`
+#[derive(Deserialize)]
+struct DebugTraceResult {
+    result: CallFrame,
+}
+
+/// A call as reported by Geth's `callTracer`, together with all the calls
+/// it made
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct CallFrame {
+    #[serde(rename = "type")]
+    kind: String,
+    from: Address,
+    #[serde(default)]
+    to: Option<Address>,
+    #[serde(default)]
+    value: Option<U256>,
+    #[serde(default)]
+    gas: U256,
+    #[serde(default)]
+    gas_used: U256,
+    #[serde(default)]
+    input: Bytes,
+    #[serde(default)]
+    output: Option<Bytes>,
+    #[serde(default)]
+    error: Option<String>,
+    #[serde(default)]
+    calls: Vec<CallFrame>,
+}
+
+/// Where the calls in a `CallFrame` happened
+struct CallFrameTransaction {
+    block_number: u64,
+    block_hash: H256,
+    transaction_hash: H256,
+    transaction_position: usize,
+}
+
+impl CallFrame {
+    /// Add the traces for this call and the calls it made to `traces` in
+    /// the order in which `trace_filter` would return them. Like
+    /// `trace_filter`, only calls to one of `addresses` are included, unless
+    /// `addresses` is empty. Contract creations and self destructs are left
+    /// out since no handler can be triggered by them
+    fn into_traces(
+        self,
+        transaction: &CallFrameTransaction,
+        trace_address: Vec<usize>,
+        addresses: &HashSet<H160>,
+        traces: &mut Vec<Trace>,
+    ) {
+        let call_type = match self.kind.as_str() {
+            "CALL" => Some(CallType::Call),
+            "CALLCODE" => Some(CallType::CallCode),
+            "DELEGATECALL" => Some(CallType::DelegateCall),
+            "STATICCALL" => Some(CallType::StaticCall),
+            _ => None,
+        };
+        let to = self.to.unwrap_or_default();
+        if let Some(call_type) = call_type {
+            if addresses.is_empty() || addresses.contains(&to) {
+                let result = match self.error {
+                    Some(_) => None,
+                    None => Some(Res::Call(CallResult {
+                        gas_used: self.gas_used,
+                        output: self.output.unwrap_or_default(),
+                    })),
+                };
+                traces.push(Trace {
+                    action: Action::Call(Call {
+                        from: self.from,
+                        to,
+                        value: self.value.unwrap_or_default(),
+                        gas: self.gas,
+                        input: self.input,
+                        call_type,
+                    }),
+                    result,
+                    trace_address: trace_address.clone(),
+                    subtraces: self.calls.len(),
+                    transaction_position: Some(transaction.transaction_position),
+                    transaction_hash: Some(transaction.transaction_hash),
+                    block_number: transaction.block_number,
+                    block_hash: transaction.block_hash,
+                    action_type: ActionType::Call,
+                    error: self.error,
+                });
+            }
+        }
+
+        for (index, call) in self.calls.into_iter().enumerate() {
+            let mut trace_address = trace_address.clone();
+            trace_address.push(index);
+            call.into_traces(transaction, trace_address, addresses, traces);
+        }
+    }
+}
+
 /// Deprecated. Wraps the [`fetch_transaction_receipts_in_batch`] in a retry loop.
 async fn fetch_transaction_receipts_in_batch_with_retry(
     web3: Arc<Web3<Transport>>,
@@ -1865,6 +2479,18 @@ fn resolve_transaction_receipt(
     }
 }
 
+/// Merge the logs from the `eth_getLogs` calls for one block range. A log
+/// matches more than one call if a wildcard event filter and a contract
+/// filter both cover it; keep only one copy
+fn merge_logs(mut logs: Vec<Log>) -> Vec<Log> {
+    let mut seen = HashSet::new();
+    logs.retain(|log| match (log.block_hash, log.log_index) {
+        (Some(block_hash), Some(log_index)) => seen.insert((block_hash, log_index)),
+        _ => true,
+    });
+    logs
+}
+
 /// Retrieves logs and the associated transaction receipts, if required by the [`EthereumLogFilter`].
 async fn get_logs_and_transactions(
     adapter: Arc<EthereumAdapter>,
@@ -1949,23 +2575,40 @@ async fn get_transaction_receipts_for_transaction_hashes(
     // later use this to check if we have collected the receipts from all required transactions.
     let mut unique_transaction_hashes: HashSet<&H256> = HashSet::new();
 
-    // Request transaction receipts concurrently
+    // Request transaction receipts concurrently, a block at a time if the
+    // provider supports that
     let receipt_futures = FuturesUnordered::new();
 
-    let web3 = Arc::clone(&adapter.web3);
     for (block_hash, transaction_hashes) in transaction_hashes_by_block {
-        for transaction_hash in transaction_hashes {
-            unique_transaction_hashes.insert(transaction_hash);
-            let receipt_future = fetch_transaction_receipt_with_retry(
-                web3.cheap_clone(),
-                *transaction_hash,
-                *block_hash,
-                logger.cheap_clone(),
-            );
-            receipt_futures.push(receipt_future)
-        }
+        unique_transaction_hashes.extend(transaction_hashes);
+        let adapter = adapter.cheap_clone();
+        let logger = logger.cheap_clone();
+        let block_hash = *block_hash;
+        let transaction_hashes = transaction_hashes.clone();
+        receipt_futures.push(async move {
+            match adapter.block_receipts(&logger, block_hash).await? {
+                Some(receipts) => Ok(receipts
+                    .into_iter()
+                    .filter(|receipt| transaction_hashes.contains(&receipt.transaction_hash))
+                    .collect()),
+                None => {
+                    futures03::future::try_join_all(transaction_hashes.into_iter().map(
+                        |transaction_hash| {
+                            fetch_transaction_receipt_with_retry(
+                                adapter.web3.cheap_clone(),
+                                transaction_hash,
+                                block_hash,
+                                logger.cheap_clone(),
+                            )
+                        },
+                    ))
+                    .await
+                }
+            }
+        });
     }
-    let receipts: Vec<_> = receipt_futures.try_collect().await?;
+    let receipts: Vec<Vec<Arc<TransactionReceipt>>> = receipt_futures.try_collect().await?;
+    let receipts = receipts.into_iter().flatten();
 
     // Build a map between transaction hashes and their receipts
     for receipt in receipts.into_iter() {
@@ -1987,3 +2630,157 @@ async fn get_transaction_receipts_for_transaction_hashes(
 
     Ok(receipts_by_hash)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use graph::prelude::web3::types::U64;
+    use graph::prelude::EthereumCall;
+
+    #[test]
+    fn merged_logs_are_unique() {
+        let log = |block: u64, index: u64| Log {
+            address: H160::zero(),
+            topics: vec![],
+            data: Bytes::default(),
+            block_hash: Some(H256::from_low_u64_be(block)),
+            block_number: Some(U64::from(block)),
+            transaction_hash: Some(H256::zero()),
+            transaction_index: Some(0.into()),
+            log_index: Some(index.into()),
+            transaction_log_index: Some(index.into()),
+            log_type: None,
+            removed: Some(false),
+        };
+        let pending = Log {
+            block_hash: None,
+            log_index: None,
+            ..log(0, 0)
+        };
+
+        let merged = merge_logs(vec![
+            log(1, 0),
+            log(1, 1),
+            pending.clone(),
+            log(1, 0),
+            log(2, 0),
+            pending,
+        ]);
+        let keys: Vec<_> = merged
+            .iter()
+            .map(|log| (log.block_hash, log.log_index))
+            .collect();
+        assert_eq!(
+            vec![
+                (Some(H256::from_low_u64_be(1)), Some(0.into())),
+                (Some(H256::from_low_u64_be(1)), Some(1.into())),
+                (None, None),
+                (Some(H256::from_low_u64_be(2)), Some(0.into())),
+                (None, None),
+            ],
+            keys
+        );
+    }
+
+    #[test]
+    fn call_frames_become_traces() {
+        let frame: CallFrame = json::from_value(json::json!({
+            "type": "CALL",
+            "from": "0x0000000000000000000000000000000000000001",
+            "to": "0x0000000000000000000000000000000000000002",
+            "value": "0x10",
+            "gas": "0x5208",
+            "gasUsed": "0x100",
+            "input": "0x12345678",
+            "output": "0x01",
+            "calls": [
+                {
+                    "type": "STATICCALL",
+                    "from": "0x0000000000000000000000000000000000000002",
+                    "to": "0x0000000000000000000000000000000000000003",
+                    "gas": "0x100",
+                    "gasUsed": "0x10",
+                    "input": "0xabcdef01",
+                    "error": "execution reverted"
+                },
+                {
+                    "type": "CREATE",
+                    "from": "0x0000000000000000000000000000000000000002",
+                    "to": "0x0000000000000000000000000000000000000004",
+                    "gas": "0x100",
+                    "gasUsed": "0x10",
+                    "input": "0x",
+                    "calls": [
+                        {
+                            "type": "DELEGATECALL",
+                            "from": "0x0000000000000000000000000000000000000004",
+                            "to": "0x0000000000000000000000000000000000000002",
+                            "gas": "0x10",
+                            "gasUsed": "0x1",
+                            "input": "0x87654321",
+                            "output": "0x"
+                        }
+                    ]
+                }
+            ]
+        }))
+        .unwrap();
+        let transaction = CallFrameTransaction {
+            block_number: 7,
+            block_hash: H256::from_low_u64_be(7),
+            transaction_hash: H256::from_low_u64_be(1),
+            transaction_position: 3,
+        };
+
+        let mut traces = Vec::new();
+        frame.into_traces(&transaction, vec![], &HashSet::new(), &mut traces);
+        let addresses: Vec<_> = traces
+            .iter()
+            .map(|trace| trace.trace_address.clone())
+            .collect();
+        assert_eq!(vec![vec![], vec![0], vec![1, 0]], addresses);
+        assert_eq!(Some(3), traces[0].transaction_position);
+        assert_eq!(2, traces[0].subtraces);
+
+        let calls: Vec<_> = traces
+            .iter()
+            .filter_map(EthereumCall::try_from_trace)
+            .collect();
+        // The reverted static call does not trigger handlers
+        assert_eq!(2, calls.len());
+        assert_eq!(U256::from(0x100), calls[0].gas_used);
+        assert_eq!(Address::from_low_u64_be(2), calls[1].to);
+    }
+
+    #[test]
+    fn unsupported_methods() {
+        use jsonrpc_core::{Error as RpcError, ErrorCode};
+
+        let rpc = |code: i64, message: &str| {
+            web3::Error::Rpc(RpcError {
+                code: ErrorCode::from(code),
+                message: message.to_string(),
+                data: None,
+            })
+        };
+
+        assert!(is_unsupported_method(&rpc(
+            -32601,
+            "the method eth_getBlockReceipts does not exist/is not available"
+        )));
+        assert!(is_unsupported_method(&rpc(-32000, "Method not found")));
+
+        // Errors that only sound similar are not about the method
+        assert!(!is_unsupported_method(&rpc(
+            -32000,
+            "execution reverted: token does not exist"
+        )));
+        assert!(!is_unsupported_method(&rpc(
+            -32000,
+            "transaction type not supported"
+        )));
+        assert!(!is_unsupported_method(&web3::Error::Decoder(
+            "method not found".to_string()
+        )));
+    }
+}
@@ -1,10 +1,12 @@
 mod adapter;
+pub mod block_lookup;
 mod capabilities;
 pub mod codec;
 mod data_source;
 mod env;
 mod ethereum_adapter;
 mod ingestor;
+pub mod log_scan;
 pub mod runtime;
 mod transport;
 
@@ -25,7 +27,8 @@ mod trigger;
 
 pub use crate::adapter::{
     EthereumAdapter as EthereumAdapterTrait, EthereumContractCall, EthereumContractCallError,
-    MockEthereumAdapter, ProviderEthRpcMetrics, SubgraphEthRpcMetrics, TriggerFilter,
+    GetLogsLimits, MockEthereumAdapter, ProviderEthRpcMetrics, SubgraphEthRpcMetrics,
+    TriggerFilter,
 };
 pub use crate::chain::Chain;
 pub use crate::network::EthereumNetworks;
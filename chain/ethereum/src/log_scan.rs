@@ -0,0 +1,216 @@
+//! Choosing, for each block range, whether the logs for a deployment come
+//! from `eth_getLogs` or from the receipts of the blocks in the block
+//! cache. Reading a cached block is much cheaper than an `eth_getLogs`
+//! call, but a scan reads every block in the range, while `eth_getLogs`
+//! only leads to loading the blocks that have triggers. Scanning pays off
+//! when most blocks have triggers anyway or when the log filter needs many
+//! calls, but only if the whole range is cached. Both the share of blocks
+//! with triggers and how often the cache had the whole range are estimated
+//! from the ranges the deployment processed recently
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use anyhow::Error;
+use graph::components::store::ChainStore;
+use graph::prelude::{serde_json, web3::types::H256, BlockNumber, EthereumBlock};
+
+use crate::ENV_VARS;
+
+/// How much the latest range counts for the estimates
+const WEIGHT: f64 = 0.2;
+
+/// While the cache misses too often for scanning to be worth it, scan
+/// anyway after this many ranges to notice when the cache fills up
+const PROBE_INTERVAL: u32 = 16;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum LogSource {
+    GetLogs,
+    BlockCache,
+}
+
+struct Estimates {
+    /// The share of blocks that had log triggers
+    density: f64,
+    /// The share of scans for which the whole range was cached
+    hit_rate: f64,
+    /// How many ranges used `eth_getLogs` only because of cache misses
+    since_scan: u32,
+}
+
+pub(crate) struct LogSourceChooser {
+    enabled: bool,
+    /// The cost of one `eth_getLogs` call, in cached blocks read
+    call_cost: f64,
+    estimates: Mutex<Estimates>,
+}
+
+impl LogSourceChooser {
+    pub fn new() -> Self {
+        Self::with_settings(ENV_VARS.scan_cached_blocks, ENV_VARS.get_logs_call_cost)
+    }
+
+    fn with_settings(enabled: bool, call_cost: f64) -> Self {
+        LogSourceChooser {
+            enabled,
+            call_cost,
+            estimates: Mutex::new(Estimates {
+                density: 0.0,
+                hit_rate: 0.5,
+                since_scan: 0,
+            }),
+        }
+    }
+
+    /// Where the logs for a range of `blocks` blocks should come from if
+    /// getting them with `eth_getLogs` takes `calls` calls
+    pub fn choose(&self, blocks: BlockNumber, calls: usize) -> LogSource {
+        if !self.enabled || calls == 0 {
+            return LogSource::GetLogs;
+        }
+
+        let mut estimates = self.estimates.lock().unwrap();
+        let blocks = blocks as f64;
+        let get_logs = calls as f64 * self.call_cost + estimates.density * blocks;
+        // A scan that misses the cache is followed by `eth_getLogs`
+        let scan = blocks + (1.0 - estimates.hit_rate) * get_logs;
+        if scan < get_logs {
+            return LogSource::BlockCache;
+        }
+        if blocks < get_logs {
+            estimates.since_scan += 1;
+            if estimates.since_scan >= PROBE_INTERVAL {
+                return LogSource::BlockCache;
+            }
+        }
+        LogSource::GetLogs
+    }
+
+    /// Record whether a scan found the whole range in the cache
+    pub fn record_scan(&self, hit: bool) {
+        let mut estimates = self.estimates.lock().unwrap();
+        estimates.since_scan = 0;
+        estimates.hit_rate = mix(estimates.hit_rate, if hit { 1.0 } else { 0.0 });
+    }
+
+    /// Record that `with_triggers` of the `blocks` blocks in a range had
+    /// log triggers
+    pub fn record_density(&self, blocks: BlockNumber, with_triggers: usize) {
+        if blocks <= 0 {
+            return;
+        }
+        let mut estimates = self.estimates.lock().unwrap();
+        let density = (with_triggers as f64 / blocks as f64).min(1.0);
+        estimates.density = mix(estimates.density, density);
+    }
+}
+
+fn mix(estimate: f64, latest: f64) -> f64 {
+    (1.0 - WEIGHT) * estimate + WEIGHT * latest
+}
+
+/// The blocks `from..=to` with their receipts from the block cache that
+/// form the chain ending in the block with `to_hash`. Return `None` unless
+/// the cache has all of these blocks, and all of them with receipts.
+///
+/// This makes blocking calls to the database and should be run with
+/// `spawn_blocking`
+pub fn cached_blocks(
+    chain_store: &dyn ChainStore,
+    from: BlockNumber,
+    to: BlockNumber,
+    to_hash: H256,
+) -> Result<Option<Vec<EthereumBlock>>, Error> {
+    // The cache can have several blocks for a number when there were
+    // reorgs; we only know which one is on our chain by following parent
+    // hashes
+    let mut hashes = Vec::new();
+    for number in from..=to {
+        let candidates = chain_store.block_hashes_by_block_number(number)?;
+        if candidates.is_empty() {
+            return Ok(None);
+        }
+        hashes.extend(candidates);
+    }
+
+    let mut cached: HashMap<H256, serde_json::Value> = HashMap::new();
+    for json in chain_store.blocks(&hashes)? {
+        let hash = json
+            .get("block")
+            .and_then(|block| block.get("hash"))
+            .cloned()
+            .map(serde_json::from_value::<H256>)
+            .transpose()?;
+        if let Some(hash) = hash {
+            cached.insert(hash, json);
+        }
+    }
+
+    let mut blocks = Vec::with_capacity((to - from + 1) as usize);
+    let mut hash = to_hash;
+    for number in (from..=to).rev() {
+        let json = match cached.remove(&hash) {
+            Some(json) => json,
+            None => return Ok(None),
+        };
+        // Blocks that were cached without their receipts are stored as
+        // plain blocks
+        if json.get("transaction_receipts").is_none() {
+            return Ok(None);
+        }
+        let block: EthereumBlock = serde_json::from_value(json)?;
+        if block.block.number.map(|n| n.as_u64()) != Some(number as u64)
+            || block.transaction_receipts.len() != block.block.transactions.len()
+        {
+            return Ok(None);
+        }
+        hash = block.block.parent_hash;
+        blocks.push(block);
+    }
+    blocks.reverse();
+    Ok(Some(blocks))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scans_when_cheaper() {
+        let disabled = LogSourceChooser::with_settings(false, 50.0);
+        assert_eq!(LogSource::GetLogs, disabled.choose(100, 1000));
+
+        let chooser = LogSourceChooser::with_settings(true, 50.0);
+        // One call for a long range is cheaper than reading every block
+        assert_eq!(LogSource::GetLogs, chooser.choose(500, 1));
+        // Many calls are not
+        assert_eq!(LogSource::BlockCache, chooser.choose(500, 100));
+
+        // When most blocks have triggers, a few calls are enough to make
+        // scanning worth it
+        for _ in 0..20 {
+            chooser.record_density(500, 450);
+            chooser.record_scan(true);
+        }
+        assert_eq!(LogSource::BlockCache, chooser.choose(500, 5));
+    }
+
+    #[test]
+    fn probes_after_misses() {
+        let chooser = LogSourceChooser::with_settings(true, 50.0);
+        for _ in 0..20 {
+            chooser.record_scan(false);
+        }
+        let sources: Vec<_> = (0..PROBE_INTERVAL)
+            .map(|_| chooser.choose(500, 100))
+            .collect();
+        assert!(sources[..PROBE_INTERVAL as usize - 1]
+            .iter()
+            .all(|source| *source == LogSource::GetLogs));
+        assert_eq!(Some(&LogSource::BlockCache), sources.last());
+
+        // A probe that misses starts the wait for the next one over
+        chooser.record_scan(false);
+        assert_eq!(LogSource::GetLogs, chooser.choose(500, 100));
+    }
+}
@@ -1,8 +1,11 @@
 use anyhow::{anyhow, Context};
 use graph::cheap_clone::CheapClone;
+use graph::data::subgraph::status::ProviderInfo;
+use graph::prelude::futures03::future::join_all;
 use graph::prelude::rand::{self, seq::IteratorRandom};
 use std::collections::HashMap;
-use std::sync::Arc;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
 
 pub use graph::impl_slog_value;
 use graph::prelude::Error;
@@ -17,24 +20,50 @@ pub struct EthereumNetworkAdapter {
     adapter: Arc<EthereumAdapter>,
 }
 
-#[derive(Clone)]
+/// The adapters for one network. Clones share the same list of adapters
+/// so that replacing the adapters, e.g., when the configuration is
+/// reloaded, is visible to everybody holding on to a clone
+#[derive(Clone, Default)]
 pub struct EthereumNetworkAdapters {
-    pub adapters: Vec<EthereumNetworkAdapter>,
+    adapters: Arc<RwLock<Vec<EthereumNetworkAdapter>>>,
 }
 
 impl EthereumNetworkAdapters {
+    /// A snapshot of the adapters, sorted by their capabilities
+    pub fn adapters(&self) -> Vec<EthereumNetworkAdapter> {
+        self.adapters.read().unwrap().clone()
+    }
+
+    pub fn len(&self) -> usize {
+        self.adapters.read().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The labels of all providers
+    pub fn providers(&self) -> Vec<String> {
+        self.adapters
+            .read()
+            .unwrap()
+            .iter()
+            .map(|adapter| adapter.adapter.provider().to_string())
+            .collect()
+    }
+
     pub fn cheapest_with(
         &self,
         required_capabilities: &NodeCapabilities,
     ) -> Result<Arc<EthereumAdapter>, Error> {
-        let cheapest_sufficient_capability = self
-            .adapters
+        let adapters = self.adapters.read().unwrap();
+        let cheapest_sufficient_capability = adapters
             .iter()
             .find(|adapter| &adapter.capabilities >= required_capabilities)
             .map(|adapter| &adapter.capabilities);
 
         // Select randomly from the cheapest adapters that have sufficent capabilities.
-        self.adapters
+        adapters
             .iter()
             .filter(|adapter| Some(&adapter.capabilities) == cheapest_sufficient_capability)
             .choose(&mut rand::thread_rng())
@@ -47,19 +76,54 @@ impl EthereumNetworkAdapters {
             })
     }
 
+    /// Ask all providers for their latest block at the same time
+    pub async fn probe(&self, timeout: Duration) -> Vec<ProviderInfo> {
+        let adapters: Vec<_> = self
+            .adapters
+            .read()
+            .unwrap()
+            .iter()
+            .map(|adapter| adapter.adapter.cheap_clone())
+            .collect();
+        join_all(adapters.iter().map(|adapter| adapter.probe(timeout))).await
+    }
+
     pub fn cheapest(&self) -> Option<Arc<EthereumAdapter>> {
         // EthereumAdapters are sorted by their NodeCapabilities when the EthereumNetworks
         // struct is instantiated so they do not need to be sorted here
         self.adapters
+            .read()
+            .unwrap()
             .iter()
             .next()
             .map(|ethereum_network_adapter| ethereum_network_adapter.adapter.clone())
     }
 
-    pub fn remove(&mut self, provider: &str) {
+    fn push(&self, adapter: EthereumNetworkAdapter) {
+        self.adapters.write().unwrap().push(adapter);
+    }
+
+    fn sort(&self) {
+        self.adapters
+            .write()
+            .unwrap()
+            .sort_by_key(|adapter| adapter.capabilities)
+    }
+
+    pub fn remove(&self, provider: &str) {
         self.adapters
+            .write()
+            .unwrap()
             .retain(|adapter| adapter.adapter.provider() != provider);
     }
+
+    /// Replace the adapters with the ones from `other`. Queries that are
+    /// already running against one of the old adapters continue to use it
+    pub fn replace(&self, other: &EthereumNetworkAdapters) {
+        let mut adapters = other.adapters();
+        adapters.sort_by_key(|adapter| adapter.capabilities);
+        *self.adapters.write().unwrap() = adapters;
+    }
 }
 
 #[derive(Clone)]
@@ -83,15 +147,15 @@ impl EthereumNetworks {
         let network_adapters = self
             .networks
             .entry(name)
-            .or_insert(EthereumNetworkAdapters { adapters: vec![] });
-        network_adapters.adapters.push(EthereumNetworkAdapter {
+            .or_insert_with(EthereumNetworkAdapters::default);
+        network_adapters.push(EthereumNetworkAdapter {
             capabilities,
             adapter: adapter.clone(),
         });
     }
 
     pub fn remove(&mut self, name: &str, provider: &str) {
-        if let Some(adapters) = self.networks.get_mut(name) {
+        if let Some(adapters) = self.networks.get(name) {
             adapters.remove(provider);
         }
     }
@@ -105,8 +169,8 @@ impl EthereumNetworks {
             .iter()
             .flat_map(|(network_name, network_adapters)| {
                 network_adapters
-                    .adapters
-                    .iter()
+                    .adapters()
+                    .into_iter()
                     .map(move |network_adapter| {
                         (
                             network_name.clone(),
@@ -119,10 +183,8 @@ impl EthereumNetworks {
     }
 
     pub fn sort(&mut self) {
-        for adapters in self.networks.values_mut() {
-            adapters
-                .adapters
-                .sort_by_key(|adapter| adapter.capabilities)
+        for adapters in self.networks.values() {
+            adapters.sort()
         }
     }
 
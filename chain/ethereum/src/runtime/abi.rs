@@ -1,4 +1,5 @@
-use super::runtime_adapter::UnresolvedContractCall;
+use super::runtime_adapter::{ContractRevert, UnresolvedContractCall};
+use crate::block_lookup::BlockInfo;
 use crate::trigger::{
     EthereumBlockData, EthereumCallData, EthereumEventData, EthereumTransactionData,
 };
@@ -15,8 +16,8 @@ use graph::{
 };
 use graph_runtime_derive::AscType;
 use graph_runtime_wasm::asc_abi::class::{
-    Array, AscAddress, AscBigInt, AscEnum, AscH160, AscString, AscWrapped, EthereumValueKind,
-    Uint8Array,
+    Array, AscAddress, AscBigInt, AscEnum, AscEnumArray, AscH160, AscString, AscWrapped,
+    EthereumValueKind, Uint8Array,
 };
 use semver::Version;
 
@@ -781,3 +782,65 @@ impl ToAscObj<AscLogParam> for ethabi::LogParam {
         })
     }
 }
+
+/// `value` is null exactly if the call `reverted`. Why it reverted is left
+/// out since what Ethereum nodes report about reverts differs between
+/// clients, and mappings must not depend on the client that indexers use
+#[repr(C)]
+#[derive(AscType)]
+pub(crate) struct AscEthereumCallResult {
+    pub value: AscEnumArray<EthereumValueKind>,
+    pub reverted: bool,
+}
+
+impl AscIndexId for AscEthereumCallResult {
+    const INDEX_ASC_TYPE_ID: IndexForAscTypeId = IndexForAscTypeId::EthereumCallResult;
+}
+
+impl ToAscObj<AscEthereumCallResult> for Result<Vec<ethabi::Token>, ContractRevert> {
+    fn to_asc_obj<H: AscHeap + ?Sized>(
+        &self,
+        heap: &mut H,
+        gas: &GasCounter,
+    ) -> Result<AscEthereumCallResult, DeterministicHostError> {
+        Ok(match self {
+            Ok(tokens) => AscEthereumCallResult {
+                value: asc_new(heap, tokens.as_slice(), gas)?,
+                reverted: false,
+            },
+            Err(_) => AscEthereumCallResult {
+                value: AscPtr::null(),
+                reverted: true,
+            },
+        })
+    }
+}
+
+/// What `ethereum.getBlockByHash` and `ethereum.getBlockByNumber` return
+#[repr(C)]
+#[derive(AscType)]
+pub(crate) struct AscEthereumBlockInfo {
+    pub hash: AscPtr<AscH256>,
+    pub number: AscPtr<AscBigInt>,
+    pub timestamp: AscPtr<AscBigInt>,
+    pub parent_hash: AscPtr<AscH256>,
+}
+
+impl AscIndexId for AscEthereumBlockInfo {
+    const INDEX_ASC_TYPE_ID: IndexForAscTypeId = IndexForAscTypeId::EthereumBlockInfo;
+}
+
+impl ToAscObj<AscEthereumBlockInfo> for BlockInfo {
+    fn to_asc_obj<H: AscHeap + ?Sized>(
+        &self,
+        heap: &mut H,
+        gas: &GasCounter,
+    ) -> Result<AscEthereumBlockInfo, DeterministicHostError> {
+        Ok(AscEthereumBlockInfo {
+            hash: asc_new(heap, &self.hash, gas)?,
+            number: asc_new(heap, &BigInt::from(self.number), gas)?,
+            timestamp: asc_new(heap, &BigInt::from_unsigned_u256(&self.timestamp), gas)?,
+            parent_hash: asc_new(heap, &self.parent_hash, gas)?,
+        })
+    }
+}
@@ -1,28 +1,35 @@
 use std::{sync::Arc, time::Instant};
 
+use crate::block_lookup::{self, BlockInfo};
 use crate::data_source::MappingABI;
 use crate::{
     capabilities::NodeCapabilities, network::EthereumNetworkAdapters, Chain, DataSource,
     EthereumAdapter, EthereumAdapterTrait, EthereumContractCall, EthereumContractCallError,
 };
-use anyhow::{Context, Error};
+use anyhow::{anyhow, Context, Error};
 use blockchain::HostFn;
 use graph::runtime::gas::Gas;
 use graph::runtime::{AscIndexId, IndexForAscTypeId};
 use graph::{
     blockchain::{self, BlockPtr, HostFnCtx},
     cheap_clone::CheapClone,
+    components::store::ChainStore,
     prelude::{
         ethabi::{self, Address, Token},
-        EthereumCallCache, Future01CompatExt,
+        web3::types::H256,
+        BlockNumber, EthereumCallCache, Future01CompatExt,
     },
     runtime::{asc_get, asc_new, AscPtr, HostExportError},
     semver::Version,
     slog::{info, trace, Logger},
 };
 use graph_runtime_wasm::asc_abi::class::{AscEnumArray, EthereumValueKind};
+use itertools::Itertools;
 
-use super::abi::{AscUnresolvedContractCall, AscUnresolvedContractCall_0_0_4};
+use super::abi::{
+    AscEthereumBlockInfo, AscEthereumCallResult, AscUnresolvedContractCall,
+    AscUnresolvedContractCall_0_0_4,
+};
 
 // When making an ethereum call, the maximum ethereum gas is ETH_CALL_GAS which is 50 million. One
 // unit of Ethereum gas is at least 100ns according to these benchmarks [1], so 1000 of our gas. In
@@ -34,9 +41,16 @@ use super::abi::{AscUnresolvedContractCall, AscUnresolvedContractCall_0_0_4};
 // [1] - https://www.sciencedirect.com/science/article/abs/pii/S0166531620300900
 pub const ETHEREUM_CALL: Gas = Gas::new(5_000_000_000);
 
+// Block lookups are usually answered from the block cache, and only go to
+// the Ethereum node for blocks that are not cached; this allows 10000
+// lookups per handler.
+pub const ETHEREUM_BLOCK_LOOKUP: Gas = Gas::new(1_000_000_000);
+
 pub struct RuntimeAdapter {
     pub(crate) eth_adapters: Arc<EthereumNetworkAdapters>,
     pub(crate) call_cache: Arc<dyn EthereumCallCache>,
+    pub(crate) chain_store: Arc<dyn ChainStore>,
+    pub(crate) reorg_threshold: BlockNumber,
 }
 
 impl blockchain::RuntimeAdapter<Chain> for RuntimeAdapter {
@@ -51,15 +65,71 @@ impl blockchain::RuntimeAdapter<Chain> for RuntimeAdapter {
             })?
             .cheap_clone();
 
-        let ethereum_call = HostFn {
-            name: "ethereum.call",
+        let ethereum_call = {
+            let eth_adapter = eth_adapter.cheap_clone();
+            let call_cache = call_cache.cheap_clone();
+            let abis = abis.clone();
+            HostFn {
+                name: "ethereum.call",
+                func: Arc::new(move |ctx, wasm_ptr| {
+                    ethereum_call(&eth_adapter, call_cache.cheap_clone(), ctx, wasm_ptr, &abis)
+                        .map(|ptr| ptr.wasm_ptr())
+                }),
+            }
+        };
+
+        let ethereum_get_block_by_hash = {
+            let eth_adapter = eth_adapter.cheap_clone();
+            let chain_store = self.chain_store.cheap_clone();
+            let reorg_threshold = self.reorg_threshold;
+            HostFn {
+                name: "ethereum.getBlockByHash",
+                func: Arc::new(move |ctx, wasm_ptr| {
+                    ethereum_get_block_by_hash(
+                        &eth_adapter,
+                        chain_store.cheap_clone(),
+                        reorg_threshold,
+                        ctx,
+                        wasm_ptr,
+                    )
+                    .map(|ptr| ptr.wasm_ptr())
+                }),
+            }
+        };
+
+        let ethereum_get_block_by_number = {
+            let eth_adapter = eth_adapter.cheap_clone();
+            let chain_store = self.chain_store.cheap_clone();
+            let reorg_threshold = self.reorg_threshold;
+            HostFn {
+                name: "ethereum.getBlockByNumber",
+                func: Arc::new(move |ctx, number| {
+                    ethereum_get_block_by_number(
+                        &eth_adapter,
+                        chain_store.cheap_clone(),
+                        reorg_threshold,
+                        ctx,
+                        number,
+                    )
+                    .map(|ptr| ptr.wasm_ptr())
+                }),
+            }
+        };
+
+        let ethereum_try_call = HostFn {
+            name: "ethereum.tryCall",
             func: Arc::new(move |ctx, wasm_ptr| {
-                ethereum_call(&eth_adapter, call_cache.cheap_clone(), ctx, wasm_ptr, &abis)
+                ethereum_try_call(&eth_adapter, call_cache.cheap_clone(), ctx, wasm_ptr, &abis)
                     .map(|ptr| ptr.wasm_ptr())
             }),
         };
 
-        Ok(vec![ethereum_call])
+        Ok(vec![
+            ethereum_call,
+            ethereum_try_call,
+            ethereum_get_block_by_hash,
+            ethereum_get_block_by_number,
+        ])
     }
 }
 
@@ -73,15 +143,7 @@ fn ethereum_call(
 ) -> Result<AscEnumArray<EthereumValueKind>, HostExportError> {
     ctx.gas.consume_host_fn(ETHEREUM_CALL)?;
 
-    // For apiVersion >= 0.0.4 the call passed from the mapping includes the
-    // function signature; subgraphs using an apiVersion < 0.0.4 don't pass
-    // the signature along with the call.
-    let call: UnresolvedContractCall = if ctx.heap.api_version() >= Version::new(0, 0, 4) {
-        asc_get::<_, AscUnresolvedContractCall_0_0_4, _>(ctx.heap, wasm_ptr.into(), &ctx.gas)?
-    } else {
-        asc_get::<_, AscUnresolvedContractCall, _>(ctx.heap, wasm_ptr.into(), &ctx.gas)?
-    };
-
+    let call = unresolved_call(&ctx, wasm_ptr)?;
     let result = eth_call(
         eth_adapter,
         call_cache,
@@ -91,12 +153,187 @@ fn ethereum_call(
         abis,
     )?;
     match result {
-        Some(tokens) => Ok(asc_new(ctx.heap, tokens.as_slice(), &ctx.gas)?),
-        None => Ok(AscPtr::null()),
+        Ok(tokens) => Ok(asc_new(ctx.heap, tokens.as_slice(), &ctx.gas)?),
+        Err(_) => Ok(AscPtr::null()),
     }
 }
 
-/// Returns `Ok(None)` if the call was reverted.
+/// Like `ethereum.call`, but tells the mapping whether the call reverted
+/// instead of returning `null` for reverts. Why the call reverted is only
+/// logged, since Ethereum clients differ in what they report about reverts
+///
+/// function ethereum.tryCall(call: SmartContractCall): EthereumCallResult
+fn ethereum_try_call(
+    eth_adapter: &EthereumAdapter,
+    call_cache: Arc<dyn EthereumCallCache>,
+    ctx: HostFnCtx<'_>,
+    wasm_ptr: u32,
+    abis: &[Arc<MappingABI>],
+) -> Result<AscPtr<AscEthereumCallResult>, HostExportError> {
+    ctx.gas.consume_host_fn(ETHEREUM_CALL)?;
+
+    let call = unresolved_call(&ctx, wasm_ptr)?;
+    let result = eth_call(
+        eth_adapter,
+        call_cache,
+        &ctx.logger,
+        &ctx.block_ptr,
+        call,
+        abis,
+    )?;
+    Ok(asc_new(ctx.heap, &result, &ctx.gas)?)
+}
+
+/// Looks up a block in the block cache or, if it is not cached, with the
+/// Ethereum node. Only ancestors of the block that is being processed are
+/// found; later blocks and blocks on other forks are treated as unknown,
+/// so that the result does not depend on how far the chain has progressed
+/// or which fork the Ethereum node is on when the handler runs
+///
+/// function ethereum.getBlockByHash(hash: Bytes): BlockInfo | null
+fn ethereum_get_block_by_hash(
+    eth_adapter: &EthereumAdapter,
+    chain_store: Arc<dyn ChainStore>,
+    reorg_threshold: BlockNumber,
+    ctx: HostFnCtx<'_>,
+    wasm_ptr: u32,
+) -> Result<AscPtr<AscEthereumBlockInfo>, HostExportError> {
+    ctx.gas.consume_host_fn(ETHEREUM_BLOCK_LOOKUP)?;
+
+    let hash: Vec<u8> = asc_get(&*ctx.heap, wasm_ptr.into(), &ctx.gas)?;
+    if hash.len() != 32 {
+        return Err(HostExportError::Deterministic(anyhow!(
+            "ethereum.getBlockByHash: a block hash has 32 bytes, but got {} bytes",
+            hash.len()
+        )));
+    }
+    let block = graph::block_on(block_lookup::ancestor_by_hash(
+        &ctx.logger,
+        chain_store,
+        Some(eth_adapter),
+        &ctx.block_ptr,
+        H256::from_slice(&hash),
+        reorg_threshold,
+    ))
+    .map_err(|e| HostExportError::Unknown(anyhow!("ethereum.getBlockByHash failed: {:#}", e)))?;
+    block_info(ctx, block)
+}
+
+/// Looks up the block with a number on the chain that leads to the block
+/// that is being processed, and treats later blocks as unknown
+///
+/// function ethereum.getBlockByNumber(number: u32): BlockInfo | null
+fn ethereum_get_block_by_number(
+    eth_adapter: &EthereumAdapter,
+    chain_store: Arc<dyn ChainStore>,
+    reorg_threshold: BlockNumber,
+    ctx: HostFnCtx<'_>,
+    number: u32,
+) -> Result<AscPtr<AscEthereumBlockInfo>, HostExportError> {
+    ctx.gas.consume_host_fn(ETHEREUM_BLOCK_LOOKUP)?;
+
+    let number = match BlockNumber::try_from(number) {
+        Ok(number) => number,
+        Err(_) => return Ok(AscPtr::null()),
+    };
+    let block = graph::block_on(block_lookup::ancestor_by_number(
+        &ctx.logger,
+        chain_store,
+        Some(eth_adapter),
+        &ctx.block_ptr,
+        number,
+        reorg_threshold,
+    ))
+    .map_err(|e| HostExportError::Unknown(anyhow!("ethereum.getBlockByNumber failed: {:#}", e)))?;
+    block_info(ctx, block)
+}
+
+fn block_info(
+    ctx: HostFnCtx<'_>,
+    block: Option<BlockInfo>,
+) -> Result<AscPtr<AscEthereumBlockInfo>, HostExportError> {
+    match block {
+        Some(block) if block.number <= ctx.block_ptr.number => {
+            Ok(asc_new(ctx.heap, &block, &ctx.gas)?)
+        }
+        _ => Ok(AscPtr::null()),
+    }
+}
+
+fn unresolved_call(
+    ctx: &HostFnCtx<'_>,
+    wasm_ptr: u32,
+) -> Result<UnresolvedContractCall, HostExportError> {
+    // For apiVersion >= 0.0.4 the call passed from the mapping includes the
+    // function signature; subgraphs using an apiVersion < 0.0.4 don't pass
+    // the signature along with the call.
+    let call = if ctx.heap.api_version() >= Version::new(0, 0, 4) {
+        asc_get::<_, AscUnresolvedContractCall_0_0_4, _>(&*ctx.heap, wasm_ptr.into(), &ctx.gas)?
+    } else {
+        asc_get::<_, AscUnresolvedContractCall, _>(&*ctx.heap, wasm_ptr.into(), &ctx.gas)?
+    };
+    Ok(call)
+}
+
+/// Why a contract call reverted, as far as the Ethereum node told us. This
+/// depends on the node's client and is therefore only used for logging
+#[derive(Clone, Debug)]
+pub struct ContractRevert {
+    /// The reason the Ethereum node gave
+    pub reason: String,
+    /// The data the contract reverted with; empty if the node did not
+    /// return it
+    pub data: Vec<u8>,
+    /// The name and arguments of the custom error in `data`, if the
+    /// contract's ABI declares it
+    pub error: Option<(String, Vec<ethabi::LogParam>)>,
+}
+
+impl ContractRevert {
+    fn new(reason: String, data: Vec<u8>, contract: &ethabi::Contract) -> Self {
+        let error = decode_custom_error(contract, &data);
+        ContractRevert {
+            reason,
+            data,
+            error,
+        }
+    }
+}
+
+/// Find the custom error from `contract` that `data` starts with and
+/// decode its arguments
+fn decode_custom_error(
+    contract: &ethabi::Contract,
+    data: &[u8],
+) -> Option<(String, Vec<ethabi::LogParam>)> {
+    if data.len() < 4 {
+        return None;
+    }
+
+    contract.errors.values().flatten().find_map(|error| {
+        let types: Vec<_> = error
+            .inputs
+            .iter()
+            .map(|input| input.kind.clone())
+            .collect();
+        if ethabi::short_signature(&error.name, &types)[..] != data[..4] {
+            return None;
+        }
+        let tokens = ethabi::decode(&types, &data[4..]).ok()?;
+        let params = error
+            .inputs
+            .iter()
+            .zip(tokens)
+            .map(|(input, value)| ethabi::LogParam {
+                name: input.name.clone(),
+                value,
+            })
+            .collect();
+        Some((error.name.clone(), params))
+    })
+}
+
+/// Returns `Ok(Err(revert))` if the call was reverted.
 fn eth_call(
     eth_adapter: &EthereumAdapter,
     call_cache: Arc<dyn EthereumCallCache>,
@@ -104,7 +341,7 @@ fn eth_call(
     block_ptr: &BlockPtr,
     unresolved_call: UnresolvedContractCall,
     abis: &[Arc<MappingABI>],
-) -> Result<Option<Vec<Token>>, HostExportError> {
+) -> Result<Result<Vec<Token>, ContractRevert>, HostExportError> {
     let start_time = Instant::now();
 
     // Obtain the path to the contract ABI
@@ -171,10 +408,27 @@ fn eth_call(
     let result = match graph::block_on(
             eth_adapter.contract_call(&logger1, call, call_cache).compat()
         ) {
-            Ok(tokens) => Ok(Some(tokens)),
-            Err(EthereumContractCallError::Revert(reason)) => {
-                info!(logger, "Contract call reverted"; "reason" => reason);
-                Ok(None)
+            Ok(tokens) => Ok(Ok(tokens)),
+            Err(EthereumContractCallError::Revert(reason, data)) => {
+                let revert = ContractRevert::new(reason, data, &contract);
+                let error = revert
+                    .error
+                    .as_ref()
+                    .map(|(name, params)| {
+                        format!(
+                            "{}({})",
+                            name,
+                            params
+                                .iter()
+                                .map(|param| format!("{}: {}", param.name, param.value))
+                                .join(", ")
+                        )
+                    })
+                    .unwrap_or_default();
+                info!(logger, "Contract call reverted";
+                      "reason" => &revert.reason,
+                      "error" => error);
+                Ok(Err(revert))
             }
 
             // Any error reported by the Ethereum node could be due to the block no longer being on
@@ -224,3 +478,84 @@ pub struct UnresolvedContractCall {
 impl AscIndexId for AscUnresolvedContractCall {
     const INDEX_ASC_TYPE_ID: IndexForAscTypeId = IndexForAscTypeId::SmartContractCall;
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use graph::prelude::ethabi::{ParamType, Uint};
+
+    #[test]
+    fn decodes_custom_errors() {
+        let contract = ethabi::Contract::load(
+            r#"[{
+                "type": "error",
+                "name": "Insufficient",
+                "inputs": [
+                    { "name": "needed", "type": "uint256" },
+                    {
+                        "name": "orders",
+                        "type": "tuple[]",
+                        "components": [
+                            { "name": "id", "type": "uint256" },
+                            { "name": "path", "type": "address[2]" },
+                            {
+                                "name": "fee",
+                                "type": "tuple",
+                                "components": [
+                                    { "name": "amount", "type": "uint256" },
+                                    { "name": "token", "type": "address" }
+                                ]
+                            }
+                        ]
+                    }
+                ]
+            }]"#
+            .as_bytes(),
+        )
+        .unwrap();
+
+        let order = |id: u64| {
+            Token::Tuple(vec![
+                Token::Uint(Uint::from(id)),
+                Token::FixedArray(vec![
+                    Token::Address(Address::from_low_u64_be(id)),
+                    Token::Address(Address::from_low_u64_be(id + 1)),
+                ]),
+                Token::Tuple(vec![
+                    Token::Uint(Uint::from(id * 10)),
+                    Token::Address(Address::from_low_u64_be(id + 2)),
+                ]),
+            ])
+        };
+        let args = vec![
+            Token::Uint(Uint::from(100)),
+            Token::Array(vec![order(1), order(2)]),
+        ];
+        let types = vec![
+            ParamType::Uint(256),
+            ParamType::Array(Box::new(ParamType::Tuple(vec![
+                ParamType::Uint(256),
+                ParamType::FixedArray(Box::new(ParamType::Address), 2),
+                ParamType::Tuple(vec![ParamType::Uint(256), ParamType::Address]),
+            ]))),
+        ];
+        let mut data = ethabi::short_signature("Insufficient", &types).to_vec();
+        data.extend(ethabi::encode(&args));
+
+        let (name, params) = decode_custom_error(&contract, &data).unwrap();
+        assert_eq!("Insufficient", name);
+        assert_eq!(
+            vec!["needed", "orders"],
+            params.iter().map(|p| p.name.as_str()).collect::<Vec<_>>()
+        );
+        assert_eq!(
+            args,
+            params.into_iter().map(|p| p.value).collect::<Vec<_>>()
+        );
+
+        // Errors that are not in the ABI, and data that is too short to
+        // contain an error, are not decoded
+        assert!(decode_custom_error(&contract, &[0xde, 0xad, 0xbe, 0xef]).is_none());
+        assert!(decode_custom_error(&contract, &data[..3]).is_none());
+    }
+}
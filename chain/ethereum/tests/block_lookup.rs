@@ -0,0 +1,145 @@
+//! Test that mappings only see blocks that the block they process builds on
+
+use std::sync::Arc;
+
+use graph::cheap_clone::CheapClone;
+use graph::components::store::{BlockStore as _, ChainStore};
+use graph::prelude::web3::types::U256;
+use graph::prelude::BlockNumber;
+use graph_chain_ethereum::block_lookup::{ancestor_by_hash, ancestor_by_number};
+use test_store::block_store::{
+    self, FakeBlock, BLOCK_FIVE, BLOCK_FOUR, BLOCK_ONE, BLOCK_ONE_SIBLING, BLOCK_THREE, BLOCK_TWO,
+    GENESIS_BLOCK,
+};
+use test_store::*;
+
+async fn lookup(
+    chain_store: &Arc<dyn ChainStore>,
+    head: &FakeBlock,
+    block: &FakeBlock,
+    reorg_threshold: BlockNumber,
+) -> Option<BlockNumber> {
+    ancestor_by_hash(
+        &LOGGER,
+        chain_store.clone(),
+        None,
+        &head.block_ptr(),
+        block.block_hash(),
+        reorg_threshold,
+    )
+    .await
+    .unwrap()
+    .map(|block| block.number)
+}
+
+#[test]
+fn block_by_hash_only_finds_ancestors() {
+    run_test_sequentially(|store| async move {
+        let chain = vec![
+            &*GENESIS_BLOCK,
+            &*BLOCK_ONE,
+            &*BLOCK_ONE_SIBLING,
+            &*BLOCK_TWO,
+            &*BLOCK_THREE,
+            &*BLOCK_FOUR,
+        ];
+        block_store::set_chain(chain, NETWORK_NAME);
+        let chain_store: Arc<dyn ChainStore> =
+            store.block_store().chain_store(NETWORK_NAME).unwrap();
+
+        // Within the reorg threshold, we follow parent hashes
+        assert_eq!(
+            Some(1),
+            lookup(&chain_store, &BLOCK_THREE, &BLOCK_ONE, 10).await
+        );
+        assert_eq!(
+            Some(3),
+            lookup(&chain_store, &BLOCK_THREE, &BLOCK_THREE, 10).await
+        );
+        assert_eq!(
+            None,
+            lookup(&chain_store, &BLOCK_THREE, &BLOCK_ONE_SIBLING, 10).await
+        );
+
+        // Later blocks and blocks we know nothing about are not visible
+        assert_eq!(
+            None,
+            lookup(&chain_store, &BLOCK_THREE, &BLOCK_FOUR, 10).await
+        );
+        assert_eq!(
+            None,
+            lookup(&chain_store, &BLOCK_FOUR, &BLOCK_FIVE, 10).await
+        );
+
+        // Beyond the reorg threshold, blocks have to be on the main chain;
+        // with two blocks at number 1 and no provider, we can't tell which
+        // one that is
+        assert_eq!(
+            Some(0),
+            lookup(&chain_store, &BLOCK_FOUR, &GENESIS_BLOCK, 1).await
+        );
+        assert_eq!(None, lookup(&chain_store, &BLOCK_FOUR, &BLOCK_ONE, 1).await);
+
+        let sibling = ancestor_by_number(
+            &LOGGER,
+            chain_store.clone(),
+            None,
+            &BLOCK_ONE_SIBLING.block_ptr(),
+            1,
+            10,
+        )
+        .await
+        .unwrap()
+        .unwrap();
+        assert_eq!(BLOCK_ONE_SIBLING.block_hash(), sibling.hash);
+    })
+}
+
+#[test]
+fn purged_blocks_come_from_the_time_index() {
+    run_test_sequentially(|store| async move {
+        remove_subgraphs();
+        let chain = vec![
+            &*GENESIS_BLOCK,
+            &*BLOCK_ONE,
+            &*BLOCK_TWO,
+            &*BLOCK_THREE,
+            &*BLOCK_FOUR,
+        ];
+        block_store::set_chain(chain, NETWORK_NAME);
+        let chain_store: Arc<dyn ChainStore> =
+            store.block_store().chain_store(NETWORK_NAME).unwrap();
+
+        // Remove blocks 1 and 2 from the block cache; without a provider,
+        // they can only be found in the block time index
+        chain_store
+            .cheap_clone()
+            .attempt_chain_head_update(1)
+            .await
+            .unwrap();
+        assert_eq!(Some((3, 2)), chain_store.cleanup_cached_blocks(1).unwrap());
+        assert!(chain_store
+            .blocks(&[BLOCK_ONE.block_hash()])
+            .unwrap()
+            .is_empty());
+
+        assert_eq!(
+            Some(1),
+            lookup(&chain_store, &BLOCK_FOUR, &BLOCK_ONE, 1).await
+        );
+        let block = ancestor_by_number(
+            &LOGGER,
+            chain_store.clone(),
+            None,
+            &BLOCK_FOUR.block_ptr(),
+            2,
+            1,
+        )
+        .await
+        .unwrap()
+        .unwrap();
+        assert_eq!(BLOCK_TWO.block_hash(), block.hash);
+        assert_eq!(BLOCK_ONE.block_hash(), block.parent_hash);
+        assert_eq!(U256::from(BLOCK_TWO.block_timestamp()), block.timestamp);
+    })
+}
@@ -0,0 +1,67 @@
+//! Test that scanning the block cache for logs only uses blocks on the
+//! chain that ends in the block the provider has
+
+use std::sync::Arc;
+
+use graph::components::store::{BlockStore as _, ChainStore};
+use graph::prelude::BlockNumber;
+use graph_chain_ethereum::log_scan::cached_blocks;
+use test_store::block_store::{
+    self, FakeBlock, BLOCK_FOUR, BLOCK_ONE, BLOCK_ONE_SIBLING, BLOCK_THREE, BLOCK_TWO,
+    GENESIS_BLOCK,
+};
+use test_store::*;
+
+fn scan(
+    chain_store: &Arc<dyn ChainStore>,
+    from: BlockNumber,
+    to: &FakeBlock,
+) -> Option<Vec<BlockNumber>> {
+    cached_blocks(chain_store.as_ref(), from, to.number, to.block_hash())
+        .unwrap()
+        .map(|blocks| {
+            blocks
+                .iter()
+                .map(|block| block.block.number.unwrap().as_u64() as BlockNumber)
+                .collect()
+        })
+}
+
+#[test]
+fn scan_follows_parent_hashes() {
+    run_test_sequentially(|store| async move {
+        let chain = vec![
+            &*GENESIS_BLOCK,
+            &*BLOCK_ONE,
+            &*BLOCK_ONE_SIBLING,
+            &*BLOCK_TWO,
+            &*BLOCK_THREE,
+        ];
+        block_store::set_chain(chain, NETWORK_NAME);
+        let chain_store: Arc<dyn ChainStore> =
+            store.block_store().chain_store(NETWORK_NAME).unwrap();
+
+        // The sibling of block one is not on the chain of block three
+        assert_eq!(Some(vec![1, 2, 3]), scan(&chain_store, 1, &BLOCK_THREE));
+        let blocks = cached_blocks(chain_store.as_ref(), 1, 1, BLOCK_ONE_SIBLING.block_hash())
+            .unwrap()
+            .unwrap();
+        assert_eq!(Some(BLOCK_ONE_SIBLING.block_hash()), blocks[0].block.hash);
+
+        // A range that ends in a block that is not cached can't be scanned
+        assert_eq!(None, scan(&chain_store, 2, &BLOCK_FOUR));
+    })
+}
+
+#[test]
+fn scan_needs_whole_chain() {
+    run_test_sequentially(|store| async move {
+        let chain = vec![&*GENESIS_BLOCK, &*BLOCK_ONE, &*BLOCK_THREE];
+        block_store::set_chain(chain, NETWORK_NAME);
+        let chain_store: Arc<dyn ChainStore> =
+            store.block_store().chain_store(NETWORK_NAME).unwrap();
+
+        assert_eq!(None, scan(&chain_store, 1, &BLOCK_THREE));
+        assert_eq!(Some(vec![0, 1]), scan(&chain_store, 0, &BLOCK_ONE));
+    })
+}
@@ -8,12 +8,12 @@ use graph::prelude::{
     SubgraphManifestValidationError, UnvalidatedSubgraphManifest,
 };
 use graph::{
-    blockchain::NodeCapabilities as _,
+    blockchain::{DataSource as _, NodeCapabilities as _},
     components::{
         link_resolver::{JsonValueStream, LinkResolver as LinkResolverTrait},
         store::EntityType,
     },
-    data::subgraph::SubgraphFeature,
+    data::subgraph::{schema::SubgraphManifestEntity, SubgraphFeature},
 };
 
 use graph_chain_ethereum::{Chain, NodeCapabilities};
@@ -23,6 +23,16 @@ const GQL_SCHEMA: &str = "type Thing @entity { id: ID! }";
 const GQL_SCHEMA_FULLTEXT: &str = include_str!("full-text.graphql");
 const MAPPING_WITH_IPFS_FUNC_WASM: &[u8] = include_bytes!("ipfs-on-ethereum-contracts.wasm");
 const ABI: &str = "[{\"type\":\"function\", \"inputs\": [{\"name\": \"i\",\"type\": \"uint256\"}],\"name\":\"get\",\"outputs\": [{\"type\": \"address\",\"name\": \"o\"}]}]";
+const TOKEN_ABI: &str = r#"[
+  {"type": "event", "name": "Transfer", "anonymous": false, "inputs": [
+    {"name": "from", "type": "address", "indexed": true},
+    {"name": "to", "type": "address", "indexed": false}]},
+  {"type": "function", "name": "set", "stateMutability": "nonpayable",
+   "inputs": [{"name": "v", "type": "uint256"}], "outputs": []},
+  {"type": "function", "name": "balanceOf", "stateMutability": "view",
+   "inputs": [{"name": "a", "type": "address"}],
+   "outputs": [{"name": "b", "type": "uint256"}]}
+]"#;
 
 #[derive(Default, Debug, Clone)]
 struct TextResolver {
@@ -71,6 +81,7 @@ async fn resolve_manifest(text: &str) -> SubgraphManifest<graph_chain_ethereum::
     resolver.add(id.as_str(), &text);
     resolver.add("/ipfs/Qmschema", &GQL_SCHEMA);
     resolver.add("/ipfs/Qmabi", &ABI);
+    resolver.add("/ipfs/Qmtoken", &TOKEN_ABI);
     resolver.add("/ipfs/Qmmapping", &MAPPING_WITH_IPFS_FUNC_WASM);
 
     let resolver: Arc<dyn LinkResolverTrait> = Arc::new(resolver);
@@ -136,6 +147,69 @@ specVersion: 0.0.2
     assert_eq!(12345, graft.block);
 }
 
+#[tokio::test]
+async fn indexing_delay_manifest() {
+    const YAML: &str = "
+dataSources: []
+schema:
+  file:
+    /: /ipfs/Qmschema
+indexingDelay: 100
+specVersion: 0.0.2
+";
+
+    let manifest = resolve_manifest(YAML).await;
+    assert_eq!(Some(100), manifest.indexing_delay);
+
+    let mut resolver = TextResolver::default();
+    let id = DeploymentHash::new("Qmmanifest").unwrap();
+    let text = YAML.replace("indexingDelay: 100", "indexingDelay: -1");
+    resolver.add(id.as_str(), &text);
+    resolver.add("/ipfs/Qmschema", &GQL_SCHEMA);
+    let resolver: Arc<dyn LinkResolverTrait> = Arc::new(resolver);
+    let raw = serde_yaml::from_str(&text).unwrap();
+    let res = SubgraphManifest::<Chain>::resolve_from_raw(
+        id,
+        raw,
+        &resolver,
+        &LOGGER,
+        SPEC_VERSION_0_0_4.clone(),
+    )
+    .await;
+    assert!(res.is_err());
+}
+
+#[test]
+fn indexing_delay_requires_spec_version() {
+    const YAML: &str = "
+dataSources: []
+schema:
+  file:
+    /: /ipfs/Qmschema
+indexingDelay: 100
+specVersion: 0.0.4
+";
+
+    test_store::run_test_sequentially(|store| async move {
+        let store = store.subgraph_store();
+
+        let msg = resolve_unvalidated(YAML)
+            .await
+            .validate(store, true)
+            .await
+            .expect_err("Validation must fail")
+            .into_iter()
+            .find(|e| matches!(e, SubgraphManifestValidationError::VersionGate(_)))
+            .expect("There must be a VersionGate error")
+            .to_string();
+        assert_eq!(
+            "`indexingDelay` requires specVersion 0.0.5 or later, \
+            but the manifest uses specVersion 0.0.4",
+            msg
+        );
+    })
+}
+
 #[test]
 fn graft_invalid_manifest() {
     const YAML: &str = "
@@ -239,6 +313,123 @@ specVersion: 0.0.2
     assert_eq!(true, required_capabilities.traces);
 }
 
+#[tokio::test]
+async fn handler_signatures_must_match_abi() {
+    const YAML: &str = "
+dataSources:
+  - kind: ethereum/contract
+    name: Token
+    network: mainnet
+    source:
+      address: \"0x2E645469f354BB4F5c8a05B3b30A929361cf77eC\"
+      abi: Token
+    mapping:
+      kind: ethereum/events
+      apiVersion: 0.0.4
+      language: wasm/assemblyscript
+      entities:
+        - Thing
+      file:
+        /: /ipfs/Qmmapping
+      abis:
+        - name: Token
+          file:
+            /: /ipfs/Qmtoken
+      eventHandlers:
+        - event: Transfer(indexed address,address)
+          handler: handleTransfer
+        - event: Transfer(address,indexed address)
+          handler: handleSwappedTransfer
+        - event: Approval(address)
+          handler: handleApproval
+      callHandlers:
+        - function: set(uint256)
+          handler: handleSet
+        - function: balanceOf(address)
+          handler: handleBalanceOf
+        - function: set(address)
+          handler: handleSetAddress
+schema:
+  file:
+    /: /ipfs/Qmschema
+specVersion: 0.0.2
+";
+
+    let manifest = resolve_manifest(YAML).await;
+    let errors = manifest.data_sources[0]
+        .validate()
+        .into_iter()
+        .map(|e| e.to_string())
+        .collect::<Vec<_>>();
+
+    assert_eq!(
+        vec![
+            "event handler `handleSwappedTransfer` is for the event \
+             `Transfer(address,indexed address)`, which does not match \
+             `Transfer(indexed address,address)` in ABI `Token`; the parameter types \
+             and which parameters are `indexed` must be the same",
+            "event handler `handleApproval` is for the event `Approval(address)`, \
+             but ABI `Token` has no event named `Approval`",
+            "call handler `handleBalanceOf` is for the function `balanceOf(address)`, \
+             but that is a `view` or `pure` function in ABI `Token`, and calls to \
+             those are never handled",
+            "call handler `handleSetAddress` is for the function `set(address)`, \
+             which does not match `set(uint256)` in ABI `Token`",
+        ],
+        errors
+    );
+}
+
+#[tokio::test]
+async fn handler_entities_must_be_declared_by_mapping() {
+    const YAML: &str = "
+dataSources:
+  - kind: ethereum/contract
+    name: Token
+    network: mainnet
+    source:
+      address: \"0x2E645469f354BB4F5c8a05B3b30A929361cf77eC\"
+      abi: Token
+    mapping:
+      kind: ethereum/events
+      apiVersion: 0.0.4
+      language: wasm/assemblyscript
+      entities:
+        - Thing
+      file:
+        /: /ipfs/Qmmapping
+      abis:
+        - name: Token
+          file:
+            /: /ipfs/Qmtoken
+      eventHandlers:
+        - event: Transfer(indexed address,address)
+          handler: handleTransfer
+          entities:
+            - Thing
+            - Other
+schema:
+  file:
+    /: /ipfs/Qmschema
+specVersion: 0.0.2
+";
+
+    let manifest = resolve_manifest(YAML).await;
+    let errors = manifest.data_sources[0]
+        .validate()
+        .into_iter()
+        .map(|e| e.to_string())
+        .collect::<Vec<_>>();
+
+    assert_eq!(
+        vec![
+            "handler `handleTransfer` declares the entity type `Other`, which is not \
+             listed in the `entities` of its mapping"
+        ],
+        errors
+    );
+}
+
 #[test]
 fn undeclared_grafting_feature_causes_feature_validation_error() {
     const YAML: &str = "
@@ -340,6 +531,28 @@ schema:
     });
 }
 
+#[tokio::test]
+async fn detected_features_are_stored() {
+    // Manifests before spec version 0.0.4 can not declare features, but we
+    // still record the ones they use
+    const YAML: &str = "
+dataSources: []
+schema:
+  file:
+    /: /ipfs/Qmschema
+graft:
+  base: Qmbase
+  block: 12345
+specVersion: 0.0.2
+";
+
+    let manifest = resolve_manifest(YAML).await;
+    assert!(manifest.features.is_empty());
+
+    let entity = SubgraphManifestEntity::from(&manifest);
+    assert_eq!(vec!["grafting".to_string()], entity.features);
+}
+
 #[test]
 fn declared_full_text_search_feature_causes_no_feature_validation_errors() {
     const YAML: &str = "
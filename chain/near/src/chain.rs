@@ -147,6 +147,7 @@ impl Blockchain for Chain {
         _subgraph_current_block: Option<BlockPtr>,
         _filter: Arc<Self::TriggerFilter>,
         _unified_api_version: UnifiedMappingApiVersion,
+        _indexing_delay: BlockNumber,
     ) -> Result<Box<dyn BlockStream<Self>>, Error> {
         panic!("NEAR does not support polling block stream")
     }
@@ -171,6 +172,14 @@ impl Blockchain for Chain {
             .await
     }
 
+    async fn detect_start_block(
+        &self,
+        _logger: &Logger,
+        _data_source: &Self::DataSource,
+    ) -> Result<Option<BlockNumber>, Error> {
+        Ok(None)
+    }
+
     fn runtime_adapter(&self) -> Arc<Self::RuntimeAdapter> {
         Arc::new(RuntimeAdapter {})
     }
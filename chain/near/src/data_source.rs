@@ -171,6 +171,10 @@ impl blockchain::DataSource<Chain> for DataSource {
     fn runtime(&self) -> &[u8] {
         self.mapping.runtime.as_ref()
     }
+
+    fn entities(&self) -> &[String] {
+        &self.mapping.entities
+    }
 }
 
 impl DataSource {
@@ -319,6 +323,10 @@ impl blockchain::DataSourceTemplate<Chain> for DataSourceTemplate {
         &self.name
     }
 
+    fn validate(&self) -> Vec<Error> {
+        vec![]
+    }
+
     fn api_version(&self) -> semver::Version {
         self.mapping.api_version.clone()
     }
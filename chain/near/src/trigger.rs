@@ -494,5 +494,14 @@ mod tests {
             // Not totally clear what is the purpose of this method, why not a default implementation here?
             Ok(type_id_index as u32)
         }
+
+        fn managed_new(
+            &mut self,
+            _type_id_index: graph::runtime::IndexForAscTypeId,
+            content: &[u8],
+            gas: &GasCounter,
+        ) -> Result<u32, DeterministicHostError> {
+            self.raw_new(content, gas)
+        }
     }
 }
@@ -143,6 +143,7 @@ impl Blockchain for Chain {
         _subgraph_start_block: Option<BlockPtr>,
         _filter: Arc<Self::TriggerFilter>,
         _unified_api_version: UnifiedMappingApiVersion,
+        _indexing_delay: BlockNumber,
     ) -> Result<Box<dyn BlockStream<Self>>, Error> {
         panic!("Tendermint does not support polling block stream")
     }
@@ -167,6 +168,14 @@ impl Blockchain for Chain {
             .map_err(Into::into)
     }
 
+    async fn detect_start_block(
+        &self,
+        _logger: &Logger,
+        _data_source: &Self::DataSource,
+    ) -> Result<Option<BlockNumber>, Error> {
+        Ok(None)
+    }
+
     fn runtime_adapter(&self) -> Arc<Self::RuntimeAdapter> {
         Arc::new(RuntimeAdapter {})
     }
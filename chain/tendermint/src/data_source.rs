@@ -189,6 +189,10 @@ impl blockchain::DataSource<Chain> for DataSource {
     fn runtime(&self) -> &[u8] {
         self.mapping.runtime.as_ref()
     }
+
+    fn entities(&self) -> &[String] {
+        &self.mapping.entities
+    }
 }
 
 impl DataSource {
@@ -308,6 +312,10 @@ impl blockchain::DataSourceTemplate<Chain> for DataSourceTemplate {
         unimplemented!("{}", TEMPLATE_ERROR);
     }
 
+    fn validate(&self) -> Vec<Error> {
+        unimplemented!("{}", TEMPLATE_ERROR);
+    }
+
     fn api_version(&self) -> semver::Version {
         unimplemented!("{}", TEMPLATE_ERROR);
     }
@@ -0,0 +1,203 @@
+//! Publish the changes to the entities of all deployments to Kafka. Changes
+//! are taken from the outbox that the store fills in the same transaction
+//! in which it commits them, and are only removed from it once Kafka has
+//! acknowledged them
+use std::time::Duration;
+
+use graph::components::store::OutboxEntry;
+use graph::prelude::futures03::future::join_all;
+use graph::prelude::*;
+use rdkafka::config::ClientConfig;
+use rdkafka::message::OwnedHeaders;
+use rdkafka::producer::{FutureProducer, FutureRecord};
+
+/// How many changes we publish at once
+const BATCH_SIZE: usize = 1_000;
+
+/// How long we wait before looking for new changes when the outbox is empty
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// How long we wait before trying again when publishing fails
+const ERROR_BACKOFF: Duration = Duration::from_secs(10);
+
+/// How long Kafka has to acknowledge a message. The store only keeps the
+/// changes we publish claimed for a few minutes, and this needs to be
+/// much shorter than that
+const SEND_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The message for one change, before it is handed to the producer
+#[derive(Debug, PartialEq)]
+struct Message {
+    topic: String,
+    key: String,
+    payload: String,
+    headers: Vec<(&'static str, String)>,
+}
+
+impl Message {
+    /// The message for `entry`; `topic` is the topic template
+    fn new(topic: &str, entry: &OutboxEntry) -> Result<Self, Error> {
+        Ok(Message {
+            topic: topic.replace("{deployment}", entry.deployment.as_str()),
+            key: entry.entity_id.clone(),
+            payload: serde_json::to_string(entry)?,
+            headers: vec![
+                ("deployment", entry.deployment.to_string()),
+                ("block_number", entry.block.to_string()),
+                ("entity_type", entry.entity_type.clone()),
+                ("operation", entry.operation.clone()),
+            ],
+        })
+    }
+}
+
+pub struct KafkaPublisher<S> {
+    logger: Logger,
+    store: Arc<S>,
+    producer: FutureProducer,
+    topic: String,
+}
+
+impl<S: SubgraphStore> KafkaPublisher<S> {
+    /// Create a publisher that sends to the brokers `brokers`, a
+    /// comma-separated list of `host:port`; `topic` is the name of the
+    /// topic for each deployment, with `{deployment}` standing in for the
+    /// deployment hash
+    pub fn new(
+        logger: &Logger,
+        store: Arc<S>,
+        brokers: &str,
+        topic: String,
+    ) -> Result<Self, Error> {
+        let producer = ClientConfig::new()
+            .set("bootstrap.servers", brokers)
+            .set("acks", "all")
+            .set("enable.idempotence", "true")
+            .create()?;
+        Ok(KafkaPublisher {
+            logger: logger.new(o!("component" => "KafkaPublisher")),
+            store,
+            producer,
+            topic,
+        })
+    }
+
+    pub async fn run(self) {
+        info!(self.logger, "Publishing entity changes to Kafka"; "topic" => &self.topic);
+        let this = Arc::new(self);
+        loop {
+            let publisher = this.cheap_clone();
+            let delivered = graph::spawn_blocking_allow_panic(move || {
+                publisher
+                    .store
+                    .deliver_entity_changes(BATCH_SIZE, &mut |entries: &[OutboxEntry]| {
+                        graph::block_on(publisher.publish(entries))
+                    })
+            })
+            .await
+            .unwrap(); // Propagate panics
+
+            match delivered {
+                Ok(count) if count > 0 => {
+                    debug!(this.logger, "Published entity changes"; "count" => count)
+                }
+                Ok(_) => tokio::time::sleep(POLL_INTERVAL).await,
+                Err(e) => {
+                    warn!(this.logger, "Failed to publish entity changes";
+                          "error" => e.to_string(),
+                          "retry_delay_s" => ERROR_BACKOFF.as_secs());
+                    tokio::time::sleep(ERROR_BACKOFF).await
+                }
+            }
+        }
+    }
+
+    /// Send one message for each of `entries` and wait until Kafka has
+    /// acknowledged all of them
+    async fn publish(&self, entries: &[OutboxEntry]) -> Result<(), Error> {
+        let messages = entries
+            .iter()
+            .map(|entry| Message::new(&self.topic, entry))
+            .collect::<Result<Vec<_>, Error>>()?;
+
+        let sends = messages.iter().map(|message| {
+            let headers = message
+                .headers
+                .iter()
+                .fold(OwnedHeaders::new(), |headers, (name, value)| {
+                    headers.add(*name, value.as_str())
+                });
+            let record = FutureRecord::to(&message.topic)
+                .key(message.key.as_str())
+                .payload(message.payload.as_str())
+                .headers(headers);
+            self.producer.send(record, SEND_TIMEOUT)
+        });
+
+        for result in join_all(sends).await {
+            if let Err((e, _)) = result {
+                return Err(e.into());
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(operation: &str, data: Option<serde_json::Value>) -> OutboxEntry {
+        OutboxEntry {
+            id: 17,
+            shard: "primary".to_string(),
+            deployment: DeploymentHash::new("QmKafkaTest").unwrap(),
+            block: 42,
+            entity_type: "Token".to_string(),
+            entity_id: "0xabc".to_string(),
+            operation: operation.to_string(),
+            data,
+        }
+    }
+
+    #[test]
+    fn set_message() {
+        let data = serde_json::json!({ "id": "0xabc", "supply": "12" });
+        let message = Message::new("changes-{deployment}", &entry("set", Some(data))).unwrap();
+
+        assert_eq!("changes-QmKafkaTest", message.topic);
+        assert_eq!("0xabc", message.key);
+        assert_eq!(
+            vec![
+                ("deployment", "QmKafkaTest".to_string()),
+                ("block_number", "42".to_string()),
+                ("entity_type", "Token".to_string()),
+                ("operation", "set".to_string()),
+            ],
+            message.headers
+        );
+        // The position in the outbox and the shard are internal to the store
+        let payload: serde_json::Value = serde_json::from_str(&message.payload).unwrap();
+        assert_eq!(
+            serde_json::json!({
+                "deployment": "QmKafkaTest",
+                "block": 42,
+                "entity_type": "Token",
+                "entity_id": "0xabc",
+                "operation": "set",
+                "data": { "id": "0xabc", "supply": "12" }
+            }),
+            payload
+        );
+    }
+
+    #[test]
+    fn remove_message() {
+        let message = Message::new("entity-changes", &entry("remove", None)).unwrap();
+
+        assert_eq!("entity-changes", message.topic);
+        let payload: serde_json::Value = serde_json::from_str(&message.payload).unwrap();
+        assert_eq!(serde_json::Value::Null, payload["data"]);
+        assert_eq!("remove", payload["operation"]);
+    }
+}
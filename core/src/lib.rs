@@ -1,7 +1,11 @@
+#[cfg(feature = "kafka")]
+pub mod kafka;
 mod link_resolver;
 mod metrics;
 mod subgraph;
+#[cfg(feature = "test-harness")]
+pub mod test_harness;
 
-pub use crate::link_resolver::LinkResolver;
+pub use crate::link_resolver::{LinkResolver, StoredLinkResolver};
 pub use crate::metrics::MetricsRegistry;
 pub use crate::subgraph::{SubgraphAssignmentProvider, SubgraphInstanceManager, SubgraphRegistrar};
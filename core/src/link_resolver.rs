@@ -12,6 +12,7 @@ use lru_time_cache::LruCache;
 use serde_json::Value;
 
 use graph::{
+    components::store::FileRegistry,
     ipfs_client::{IpfsClient, ObjectStatResponse},
     prelude::{LinkResolver as LinkResolverTrait, *},
 };
@@ -296,6 +297,80 @@ impl LinkResolverTrait for LinkResolver {
     }
 }
 
+/// Resolves the files that make up deployments through a `FileRegistry`
+/// and only falls back to `inner` for files that are not in it yet, which
+/// are then added to it. That way, deployments whose files were resolved
+/// once can be started even when IPFS is not available. Only links to IPFS
+/// are kept in the registry since only they are content-addressed
+#[derive(Clone)]
+pub struct StoredLinkResolver {
+    inner: Arc<dyn LinkResolverTrait>,
+    registry: Arc<dyn FileRegistry>,
+}
+
+impl StoredLinkResolver {
+    pub fn new(inner: Arc<dyn LinkResolverTrait>, registry: Arc<dyn FileRegistry>) -> Self {
+        Self { inner, registry }
+    }
+}
+
+impl Debug for StoredLinkResolver {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("StoredLinkResolver")
+            .field("inner", &self.inner)
+            .finish()
+    }
+}
+
+#[async_trait]
+impl LinkResolverTrait for StoredLinkResolver {
+    fn with_timeout(&self, timeout: Duration) -> Box<dyn LinkResolverTrait> {
+        Box::new(Self::new(
+            self.inner.with_timeout(timeout).into(),
+            self.registry.cheap_clone(),
+        ))
+    }
+
+    fn with_retries(&self) -> Box<dyn LinkResolverTrait> {
+        Box::new(Self::new(
+            self.inner.with_retries().into(),
+            self.registry.cheap_clone(),
+        ))
+    }
+
+    async fn cat(&self, logger: &Logger, link: &Link) -> Result<Vec<u8>, Error> {
+        if link.link.contains("://") {
+            return self.inner.cat(logger, link).await;
+        }
+        let path = link.link.trim_start_matches("/ipfs/");
+
+        match self.registry.get_file(path) {
+            Ok(Some(data)) => {
+                trace!(logger, "Deployment file found in the store"; "hash" => path);
+                return Ok(data);
+            }
+            Ok(None) => {}
+            Err(e) => {
+                warn!(logger, "Failed to read deployment file from the store, fetching it again";
+                              "hash" => path,
+                              "error" => e.to_string());
+            }
+        }
+
+        let data = self.inner.cat(logger, link).await?;
+        if let Err(e) = self.registry.put_file(path, &data) {
+            warn!(logger, "Failed to add deployment file to the store";
+                          "hash" => path,
+                          "error" => e.to_string());
+        }
+        Ok(data)
+    }
+
+    async fn json_stream(&self, logger: &Logger, link: &Link) -> Result<JsonValueStream, Error> {
+        self.inner.json_stream(logger, link).await
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -376,4 +451,73 @@ mod tests {
             values.unwrap()
         );
     }
+
+    /// Serves the same bytes for every link, until it is told that IPFS is
+    /// down
+    #[derive(Debug, Default)]
+    struct FlakyResolver {
+        down: std::sync::atomic::AtomicBool,
+    }
+
+    #[async_trait]
+    impl LinkResolverTrait for FlakyResolver {
+        fn with_timeout(&self, _timeout: Duration) -> Box<dyn LinkResolverTrait> {
+            unimplemented!()
+        }
+
+        fn with_retries(&self) -> Box<dyn LinkResolverTrait> {
+            unimplemented!()
+        }
+
+        async fn cat(&self, _logger: &Logger, link: &Link) -> Result<Vec<u8>, Error> {
+            if self.down.load(std::sync::atomic::Ordering::SeqCst) {
+                return Err(anyhow!("IPFS is down"));
+            }
+            Ok(link.link.as_bytes().to_vec())
+        }
+
+        async fn json_stream(
+            &self,
+            _logger: &Logger,
+            _link: &Link,
+        ) -> Result<JsonValueStream, Error> {
+            unimplemented!()
+        }
+    }
+
+    #[derive(Default)]
+    struct MemoryRegistry(Mutex<std::collections::HashMap<String, Vec<u8>>>);
+
+    impl FileRegistry for MemoryRegistry {
+        fn get_file(&self, link: &str) -> Result<Option<Vec<u8>>, StoreError> {
+            Ok(self.0.lock().unwrap().get(link).cloned())
+        }
+
+        fn put_file(&self, link: &str, content: &[u8]) -> Result<(), StoreError> {
+            self.0
+                .lock()
+                .unwrap()
+                .insert(link.to_string(), content.to_vec());
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn stored_files_survive_ipfs_outage() {
+        let inner = Arc::new(FlakyResolver::default());
+        let registry = Arc::new(MemoryRegistry::default());
+        let resolver = StoredLinkResolver::new(inner.clone(), registry.clone());
+        let logger = Logger::root(slog::Discard, o!());
+
+        let link = Link::from("/ipfs/QmManifest".to_string());
+        let data = resolver.cat(&logger, &link).await.unwrap();
+        assert_eq!(Some(data.clone()), registry.get_file("QmManifest").unwrap());
+
+        inner.down.store(true, std::sync::atomic::Ordering::SeqCst);
+        assert_eq!(data, resolver.cat(&logger, &link).await.unwrap());
+        assert!(resolver
+            .cat(&logger, &Link::from("QmOther".to_string()))
+            .await
+            .is_err());
+    }
 }
@@ -1,3 +1,4 @@
+use crate::subgraph::shutdown::ShutdownListener;
 use crate::subgraph::SubgraphInstance;
 use graph::{
     blockchain::Blockchain,
@@ -14,4 +15,5 @@ pub struct IndexingContext<T: RuntimeHostBuilder<C>, C: Blockchain> {
     pub instance: SubgraphInstance<C, T>,
     pub instances: SharedInstanceKeepAliveMap,
     pub filter: C::TriggerFilter,
+    pub shutdown: ShutdownListener,
 }
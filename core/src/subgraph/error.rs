@@ -11,13 +11,52 @@ pub enum BlockProcessingError {
     #[error("{0}")]
     Deterministic(SubgraphError),
 
+    // The deployment used more of the node's resources than its quota allows. Processing the
+    // block again would use as much, so the deployment is stopped.
+    #[error("{0:#}")]
+    QuotaExceeded(Error),
+
     #[error("subgraph stopped while processing triggers")]
     Canceled,
 }
 
+/// How the runner deals with an error from processing a block
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ErrorKind {
+    /// Processing the block again fails the same way, e.g. because a
+    /// handler trapped or ran out of gas. The deployment is failed and
+    /// stops
+    Deterministic,
+    /// The error depends on circumstances outside of the block, e.g. a
+    /// handler or IPFS timeout or a failing provider. The deployment is
+    /// marked as failed and the block is retried with backoff; the failure
+    /// is cleared once the deployment makes progress again
+    NonDeterministic,
+    /// The deployment used more resources than its quota allows. The
+    /// deployment is failed and stops until it is restarted
+    QuotaExceeded,
+    /// Another node took over writing the deployment. The deployment stops
+    /// without being failed
+    ConcurrentWriter,
+    /// The deployment was stopped while processing the block
+    Canceled,
+}
+
 impl BlockProcessingError {
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            BlockProcessingError::Deterministic(_) => ErrorKind::Deterministic,
+            BlockProcessingError::QuotaExceeded(_) => ErrorKind::QuotaExceeded,
+            BlockProcessingError::Canceled => ErrorKind::Canceled,
+            BlockProcessingError::Unknown(e) => match e.downcast_ref::<StoreError>() {
+                Some(StoreError::ConcurrentWriter(..)) => ErrorKind::ConcurrentWriter,
+                _ => ErrorKind::NonDeterministic,
+            },
+        }
+    }
+
     pub fn is_deterministic(&self) -> bool {
-        matches!(self, BlockProcessingError::Deterministic(_))
+        self.kind() == ErrorKind::Deterministic
     }
 }
 
@@ -32,3 +71,41 @@ impl From<StoreError> for BlockProcessingError {
         BlockProcessingError::Unknown(e.into())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use graph::data::subgraph::schema::SubgraphError;
+    use graph::prelude::{anyhow, DeploymentHash, StoreError};
+
+    use super::{BlockProcessingError, ErrorKind};
+
+    #[test]
+    fn error_kinds() {
+        let id = DeploymentHash::new("errorKinds").unwrap();
+        let deterministic = BlockProcessingError::Deterministic(SubgraphError {
+            subgraph_id: id.clone(),
+            message: "unreachable code reached".to_owned(),
+            block_ptr: None,
+            handler: Some("handleTransfer".to_owned()),
+            deterministic: true,
+        });
+        assert_eq!(ErrorKind::Deterministic, deterministic.kind());
+        assert!(deterministic.is_deterministic());
+
+        let timeout = BlockProcessingError::Unknown(
+            anyhow!("interrupt").context("Handler 'handleTransfer' hit the timeout of '5' seconds"),
+        );
+        assert_eq!(ErrorKind::NonDeterministic, timeout.kind());
+        assert!(!timeout.is_deterministic());
+
+        let store = BlockProcessingError::from(StoreError::DatabaseUnavailable);
+        assert_eq!(ErrorKind::NonDeterministic, store.kind());
+
+        let writer = BlockProcessingError::from(StoreError::ConcurrentWriter(id, None));
+        assert_eq!(ErrorKind::ConcurrentWriter, writer.kind());
+
+        let quota = BlockProcessingError::QuotaExceeded(anyhow!("memory quota exceeded"));
+        assert_eq!(ErrorKind::QuotaExceeded, quota.kind());
+        assert_eq!(ErrorKind::Canceled, BlockProcessingError::Canceled.kind());
+    }
+}
@@ -13,6 +13,8 @@ pub struct IndexingInputs<C: Blockchain> {
     pub features: BTreeSet<SubgraphFeature>,
     pub start_blocks: Vec<BlockNumber>,
     pub stop_block: Option<BlockNumber>,
+    /// How many blocks behind the chain head the subgraph stays
+    pub indexing_delay: BlockNumber,
     pub store: Arc<dyn WritableStore>,
     pub debug_fork: Option<Arc<dyn SubgraphFork>>,
     pub triggers_adapter: Arc<C::TriggersAdapter>,
@@ -1,16 +1,20 @@
+use atomic_refcell::AtomicRefCell;
 use futures01::sync::mpsc::Sender;
 
-use std::collections::HashMap;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::time::Instant;
 
+use graph::prelude::futures03::future::join_all;
 use graph::{blockchain::DataSource, prelude::*};
 use graph::{
-    blockchain::{Block, Blockchain},
+    blockchain::{Block, Blockchain, TriggerData},
     components::{
-        store::SubgraphFork,
-        subgraph::{MappingError, SharedProofOfIndexing},
+        store::{SubgraphFork, WritableStore},
+        subgraph::{MappingError, ProofOfIndexing, RemovedDataSource, SharedProofOfIndexing},
     },
+    data::subgraph::schema::SubgraphError,
     prelude::ENV_VARS,
+    util::lfu_cache::LfuCache,
 };
 
 use super::metrics::SubgraphInstanceMetrics;
@@ -27,6 +31,17 @@ pub struct SubgraphInstance<C: Blockchain, T: RuntimeHostBuilder<C>> {
     /// stream events are processed by the mappings in this same order.
     hosts: Vec<Arc<T::Host>>,
 
+    /// Hosts of dynamic data sources that were removed, in the order in
+    /// which they were removed, so that reverting the removal can put them
+    /// back where they were in `hosts`. Hosts are forgotten once their
+    /// removal is final
+    removed_hosts: Vec<RemovedHost<T::Host>>,
+
+    /// The dynamic data sources that expire after a block, by that block.
+    /// Entries for data sources that were removed in the meantime do
+    /// nothing when they expire
+    expiring: BTreeMap<BlockNumber, Vec<RemovedDataSource>>,
+
     /// Maps the hash of a module to a channel to the thread in which the module is instantiated.
     module_cache: HashMap<[u8; 32], Sender<T::Req>>,
 }
@@ -50,6 +65,8 @@ where
             subgraph_id,
             network,
             hosts: Vec::new(),
+            removed_hosts: Vec::new(),
+            expiring: BTreeMap::new(),
             module_cache: HashMap::new(),
         };
 
@@ -57,6 +74,7 @@ where
         // we use the same order here as in the subgraph manifest to make the
         // event processing behavior predictable
         for ds in manifest.data_sources {
+            this.track_expiry(&ds);
             let host = this.new_host(
                 logger.cheap_clone(),
                 ds,
@@ -185,6 +203,179 @@ where
         Ok(state)
     }
 
+    /// Process `triggers` with the same outcome as calling `process_trigger`
+    /// for each of them in order, but run the handlers of hosts whose
+    /// entities do not overlap concurrently. The entity changes of the
+    /// lanes are merged, and created data sources, deterministic errors and
+    /// proof of indexing events are applied in the order in which the
+    /// handlers would have run. Handlers may only access the entities that
+    /// their mapping declares, otherwise lanes could see each other's
+    /// changes
+    pub(crate) async fn process_triggers_in_parallel(
+        &self,
+        logger: &Logger,
+        block: &Arc<C::Block>,
+        triggers: Vec<C::TriggerData>,
+        store: Arc<dyn WritableStore>,
+        entity_lfu_cache: LfuCache<EntityKey, Option<Entity>>,
+        proof_of_indexing: &SharedProofOfIndexing,
+        causality_region: &str,
+        debug_fork: &Option<Arc<dyn SubgraphFork>>,
+        subgraph_metrics: &Arc<SubgraphInstanceMetrics>,
+    ) -> Result<BlockState<C>, MappingError> {
+        let (host_lanes, lane_entities) = lanes(self.hosts.iter().map(|host| host.entities()));
+
+        // Processing the triggers one after the other stops at the first
+        // trigger that can not be decoded, and only the handlers before it
+        // would have run
+        let mut lane_handlers: Vec<Vec<_>> = lane_entities.iter().map(|_| Vec::new()).collect();
+        let mut decode_error = None;
+        'triggers: for (trigger_index, trigger) in triggers.iter().enumerate() {
+            for (host_index, host) in self.hosts.iter().enumerate() {
+                match host.match_and_decode(trigger, block, logger) {
+                    Ok(Some(mapping_trigger)) => lane_handlers[host_lanes[host_index]].push((
+                        trigger_index,
+                        host_index,
+                        mapping_trigger,
+                    )),
+                    Ok(None) => {}
+                    Err(e) => {
+                        decode_error = Some(trigger_error(e.into(), trigger));
+                        break 'triggers;
+                    }
+                }
+            }
+        }
+
+        // Each lane only needs the cached entities it can access
+        let mut entity_lfu_cache = entity_lfu_cache;
+        let mut lane_caches = Vec::with_capacity(lane_entities.len());
+        for entities in &lane_entities {
+            let (lane_cache, rest) =
+                entity_lfu_cache.partition(|key| entities.contains(key.entity_type.as_str()));
+            lane_caches.push(lane_cache);
+            entity_lfu_cache = rest;
+        }
+
+        let hosts = &self.hosts;
+        let triggers = &triggers;
+        let block_number = block.number();
+        let lane_futures =
+            lane_handlers
+                .into_iter()
+                .zip(lane_caches)
+                .map(|(handlers, lane_cache)| {
+                    let mut state = BlockState::new(store.cheap_clone(), lane_cache)
+                        .with_declared_entities_only(true);
+                    async move {
+                        let mut handled = Vec::with_capacity(handlers.len());
+                        for (trigger_index, host_index, mapping_trigger) in handlers {
+                            let recording = proof_of_indexing.as_ref().map(|_| {
+                                Arc::new(AtomicRefCell::new(ProofOfIndexing::recording(
+                                    block_number,
+                                )))
+                            });
+
+                            let start = Instant::now();
+                            state = hosts[host_index]
+                                .process_mapping_trigger(
+                                    logger,
+                                    block.ptr(),
+                                    mapping_trigger,
+                                    state,
+                                    recording.cheap_clone(),
+                                    debug_fork,
+                                )
+                                .await
+                                .map_err(|e| (trigger_index, host_index, e))?;
+                            let elapsed = start.elapsed().as_secs_f64();
+                            subgraph_metrics.observe_trigger_processing_duration(elapsed);
+
+                            handled.push(HandledTrigger {
+                                trigger_index,
+                                host_index,
+                                proof_of_indexing: recording
+                                    .map(|recording| std::mem::take(&mut *recording.borrow_mut())),
+                                created_data_sources: state.drain_created_data_sources(),
+                                removed_data_sources: state.drain_removed_data_sources(),
+                                deterministic_errors: std::mem::take(
+                                    &mut state.deterministic_errors,
+                                ),
+                                warnings: state.drain_warnings(),
+                            });
+                        }
+                        Ok::<_, (usize, usize, MappingError)>((state, handled))
+                    }
+                });
+
+        // Every lane stops at its first failing handler, and the lanes do
+        // not depend on each other. The failure of the earliest handler is
+        // therefore the one that processing the triggers one after the
+        // other would have run into, no matter which lane finished first
+        let mut lane_results = Vec::with_capacity(lane_entities.len());
+        let mut failures = Vec::new();
+        for result in join_all(lane_futures).await {
+            match result {
+                Ok(lane_result) => lane_results.push(lane_result),
+                Err(failure) => failures.push(failure),
+            }
+        }
+        let first_failure = failures
+            .into_iter()
+            .min_by_key(|(trigger_index, host_index, _)| (*trigger_index, *host_index));
+        if let Some((trigger_index, _, e)) = first_failure {
+            return Err(trigger_error(e, &triggers[trigger_index]));
+        }
+        if let Some(e) = decode_error {
+            return Err(e);
+        }
+
+        let mut state = BlockState::new(store, entity_lfu_cache).with_declared_entities_only(true);
+        let mut handled = Vec::new();
+        for (lane_state, lane_handled) in lane_results {
+            state.extend(lane_state);
+            handled.extend(lane_handled);
+        }
+        handled.sort_by_key(|handled| (handled.trigger_index, handled.host_index));
+
+        // Apply what the handlers did in the same order and with the same
+        // proof of indexing bookkeeping as `process_trigger_in_runtime_hosts`
+        let mut handled = handled.into_iter().peekable();
+        for trigger_index in 0..triggers.len() {
+            let error_count = state.deterministic_errors.len();
+
+            if let Some(proof_of_indexing) = proof_of_indexing {
+                proof_of_indexing
+                    .borrow_mut()
+                    .start_handler(causality_region);
+            }
+
+            while let Some(done) = handled.next_if(|done| done.trigger_index == trigger_index) {
+                if let (Some(proof_of_indexing), Some(recording)) =
+                    (proof_of_indexing, done.proof_of_indexing)
+                {
+                    proof_of_indexing.borrow_mut().replay(logger, recording);
+                }
+                state.append_created_data_sources(done.created_data_sources);
+                state.append_removed_data_sources(done.removed_data_sources);
+                state.deterministic_errors.extend(done.deterministic_errors);
+                state.append_warnings(done.warnings);
+            }
+
+            if let Some(proof_of_indexing) = proof_of_indexing {
+                if state.deterministic_errors.len() != error_count {
+                    assert!(state.deterministic_errors.len() == error_count + 1);
+
+                    proof_of_indexing
+                        .borrow_mut()
+                        .write_deterministic_error(&logger, causality_region);
+                }
+            }
+        }
+
+        Ok(state)
+    }
+
     pub(crate) fn add_dynamic_data_source(
         &mut self,
         logger: &Logger,
@@ -214,25 +405,679 @@ where
         Ok(if self.hosts.contains(&host) {
             None
         } else {
+            self.track_expiry(host.data_source());
             self.hosts.push(host.clone());
             Some(host)
         })
     }
 
-    pub(crate) fn revert_data_sources(&mut self, reverted_block: BlockNumber) {
-        // `hosts` is ordered by the creation block.
-        // See also 8f1bca33-d3b7-4035-affc-fd6161a12448.
-        while self
-            .hosts
-            .last()
-            .filter(|h| h.creation_block_number() >= Some(reverted_block))
-            .is_some()
-        {
-            self.hosts.pop();
+    fn track_expiry(&mut self, data_source: &C::DataSource) {
+        let expiry = RemovedDataSource::expiry::<C>(data_source);
+        if let (Some(block), Some(removed)) = (expiry, RemovedDataSource::of::<C>(data_source)) {
+            self.expiring.entry(block).or_default().push(removed);
         }
     }
 
+    /// The data sources that expire after `block` or earlier and have not
+    /// been returned from this method before
+    pub(crate) fn expired_data_sources(&mut self, block: BlockNumber) -> Vec<RemovedDataSource> {
+        let later = self.expiring.split_off(&(block + 1));
+        std::mem::replace(&mut self.expiring, later)
+            .into_values()
+            .flatten()
+            .collect()
+    }
+
+    /// Remove the hosts for the `removed` data sources in `block` and
+    /// return their data sources. Data sources that have already been
+    /// removed are ignored
+    pub(crate) fn remove_data_sources(
+        &mut self,
+        removed: &[RemovedDataSource],
+        block: BlockNumber,
+    ) -> Vec<C::DataSource> {
+        // Removals that can not be reverted anymore do not have to be
+        // remembered
+        let final_block = block - ENV_VARS.reorg_threshold;
+        self.removed_hosts.retain(|host| host.block > final_block);
+
+        removed
+            .iter()
+            .filter_map(|removed| {
+                remove_host(&mut self.hosts, &mut self.removed_hosts, block, |host| {
+                    removed.matches::<C>(host.data_source())
+                })
+            })
+            .map(|host| host.data_source().clone())
+            .collect()
+    }
+
+    /// Undo the creation and removal of data sources in `reverted_block`
+    /// and return the data sources whose removal was undone
+    pub(crate) fn revert_data_sources(
+        &mut self,
+        reverted_block: BlockNumber,
+    ) -> Vec<C::DataSource> {
+        revert_hosts(
+            &mut self.hosts,
+            &mut self.removed_hosts,
+            reverted_block,
+            |host| host.creation_block_number(),
+        )
+        .into_iter()
+        .map(|host| {
+            self.track_expiry(host.data_source());
+            host.data_source().clone()
+        })
+        .collect()
+    }
+
+    /// The data sources of all hosts
+    pub(crate) fn data_sources(&self) -> impl Iterator<Item = &C::DataSource> + Clone {
+        self.hosts.iter().map(|host| host.data_source())
+    }
+
     pub(crate) fn network(&self) -> &str {
         &self.network
     }
 }
+
+/// A host whose data source was removed in `block` from position `index`
+/// in `SubgraphInstance.hosts`
+struct RemovedHost<H> {
+    block: BlockNumber,
+    index: usize,
+    host: Arc<H>,
+}
+
+/// Move the first host in `hosts` that `matches` to `removed_hosts`
+fn remove_host<H>(
+    hosts: &mut Vec<Arc<H>>,
+    removed_hosts: &mut Vec<RemovedHost<H>>,
+    block: BlockNumber,
+    matches: impl Fn(&H) -> bool,
+) -> Option<Arc<H>> {
+    let index = hosts.iter().position(|host| matches(host))?;
+    let host = hosts.remove(index);
+    removed_hosts.push(RemovedHost {
+        block,
+        index,
+        host: host.cheap_clone(),
+    });
+    Some(host)
+}
+
+/// Put the hosts that were removed in `reverted_block` or later back into
+/// `hosts`, drop the ones that were created in it, and return the restored
+/// hosts that existed before `reverted_block`
+fn revert_hosts<H>(
+    hosts: &mut Vec<Arc<H>>,
+    removed_hosts: &mut Vec<RemovedHost<H>>,
+    reverted_block: BlockNumber,
+    creation_block: impl Fn(&H) -> Option<BlockNumber>,
+) -> Vec<Arc<H>> {
+    // Put removed hosts back in the reverse order of their removal so
+    // that each of them goes back to where it was. Data sources are
+    // removed after the ones created in the same block were added, and
+    // removals therefore have to be undone first
+    let mut restored = Vec::new();
+    while removed_hosts
+        .last()
+        .filter(|removed| removed.block >= reverted_block)
+        .is_some()
+    {
+        let RemovedHost { index, host, .. } = removed_hosts.pop().unwrap();
+        hosts.insert(index, host.cheap_clone());
+        restored.push(host);
+    }
+
+    // `hosts` is ordered by the creation block.
+    // See also 8f1bca33-d3b7-4035-affc-fd6161a12448.
+    while hosts
+        .last()
+        .filter(|h| creation_block(h) >= Some(reverted_block))
+        .is_some()
+    {
+        hosts.pop();
+    }
+
+    restored
+        .into_iter()
+        .filter(|host| creation_block(host) < Some(reverted_block))
+        .collect()
+}
+
+/// What running the handler of one host for one trigger in a lane produced
+/// that has to be applied in trigger order
+struct HandledTrigger<C: Blockchain> {
+    trigger_index: usize,
+    host_index: usize,
+    proof_of_indexing: Option<ProofOfIndexing>,
+    created_data_sources: Vec<DataSourceTemplateInfo<C>>,
+    removed_data_sources: Vec<RemovedDataSource>,
+    deterministic_errors: Vec<SubgraphError>,
+    warnings: Vec<SubgraphError>,
+}
+
+pub(crate) fn trigger_error(mut e: MappingError, trigger: &impl TriggerData) -> MappingError {
+    let error_context = trigger.error_context();
+    if !error_context.is_empty() {
+        e = e.context(error_context);
+    }
+    e.context("failed to process trigger".to_string())
+}
+
+/// Assign hosts to lanes so that hosts whose entities overlap, directly or
+/// through other hosts, are in the same lane. Returns the lane of each host
+/// and the entities of each lane. Lanes are numbered in the order of their
+/// first host; some lanes may end up without hosts when later hosts join
+/// lanes together
+fn lanes<'a>(
+    host_entities: impl Iterator<Item = &'a [String]>,
+) -> (Vec<usize>, Vec<BTreeSet<&'a str>>) {
+    let mut host_lanes: Vec<usize> = Vec::new();
+    let mut lane_entities: Vec<BTreeSet<&str>> = Vec::new();
+    for entities in host_entities {
+        let entities: BTreeSet<&str> = entities.iter().map(String::as_str).collect();
+        let overlapping: Vec<usize> = lane_entities
+            .iter()
+            .enumerate()
+            .filter(|(_, lane)| !lane.is_disjoint(&entities))
+            .map(|(lane, _)| lane)
+            .collect();
+
+        let lane = match overlapping.first() {
+            Some(lane) => *lane,
+            None => {
+                lane_entities.push(BTreeSet::new());
+                lane_entities.len() - 1
+            }
+        };
+        for other in overlapping.iter().skip(1) {
+            let other_entities = std::mem::take(&mut lane_entities[*other]);
+            lane_entities[lane].extend(other_entities);
+            for host_lane in host_lanes
+                .iter_mut()
+                .filter(|host_lane| **host_lane == *other)
+            {
+                *host_lane = lane;
+            }
+        }
+        lane_entities[lane].extend(entities);
+        host_lanes.push(lane);
+    }
+    (host_lanes, lane_entities)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{BTreeMap, HashMap};
+    use std::sync::Arc;
+
+    use async_trait::async_trait;
+    use atomic_refcell::AtomicRefCell;
+    use futures01::sync::mpsc::Sender;
+    use graph::blockchain::mock::{
+        MockBlock, MockBlockchain, MockDataSource, MockDataSourceTemplate, MockMappingTrigger,
+        MockTriggerData,
+    };
+    use graph::blockchain::{BlockPtr, TriggerWithHandler};
+    use graph::components::store::{
+        EntityType, StoredDynamicDataSource, SubgraphFork, WritableStore,
+    };
+    use graph::components::subgraph::{
+        MappingError, ProofOfIndexing, ProofOfIndexingEvent, SharedProofOfIndexing,
+    };
+    use graph::data::subgraph::schema::{SubgraphError, SubgraphHealth};
+    use graph::prelude::{
+        anyhow, tokio, BlockNumber, BlockState, DeploymentHash, Entity, EntityKey,
+        EntityModification, Error, HostMetrics, Logger, RuntimeHost, RuntimeHostBuilder, Schema,
+        StopwatchMetrics, StoreError, UnfailOutcome, Value,
+    };
+    use graph::util::lfu_cache::LfuCache;
+    use graph_mock::MockMetricsRegistry;
+    use lazy_static::lazy_static;
+
+    use super::{lanes, remove_host, revert_hosts, trigger_error, SubgraphInstance};
+    use crate::subgraph::metrics::SubgraphInstanceMetrics;
+
+    #[test]
+    fn hosts_with_overlapping_entities_share_a_lane() {
+        let hosts: Vec<Vec<String>> = [
+            vec!["Token"],
+            vec!["Pair"],
+            vec!["Swap", "User"],
+            vec![],
+            vec!["User", "Pair"],
+            vec!["Token"],
+        ]
+        .iter()
+        .map(|entities| entities.iter().map(|entity| entity.to_string()).collect())
+        .collect();
+
+        let (host_lanes, lane_entities) = lanes(hosts.iter().map(|entities| entities.as_slice()));
+        assert_eq!(vec![0, 1, 1, 3, 1, 0], host_lanes);
+        assert_eq!(
+            vec!["Pair", "Swap", "User"],
+            lane_entities[1].iter().cloned().collect::<Vec<_>>()
+        );
+        assert!(lane_entities[2].is_empty());
+    }
+
+    type Host = (&'static str, Option<BlockNumber>);
+
+    fn names(hosts: &[Arc<Host>]) -> Vec<&'static str> {
+        hosts.iter().map(|host| host.0).collect()
+    }
+
+    /// The runner removes expired and removed data sources while it
+    /// processes a block; when processing or writing the block fails, it
+    /// reverts them so that the block is retried with the same hosts
+    #[test]
+    fn failed_block_restores_hosts() {
+        let mut hosts: Vec<Arc<Host>> = vec![
+            Arc::new(("static", None)),
+            Arc::new(("pair-a", Some(3))),
+            Arc::new(("pair-b", Some(4))),
+            Arc::new(("pair-c", Some(6))),
+        ];
+        let mut removed = Vec::new();
+        let creation_block = |host: &Host| host.1;
+
+        // Processing block 10 expires `pair-b`, creates `pair-d` and
+        // removes `pair-a`, and then fails
+        assert!(remove_host(&mut hosts, &mut removed, 10, |host| host.0 == "pair-b").is_some());
+        hosts.push(Arc::new(("pair-d", Some(10))));
+        assert!(remove_host(&mut hosts, &mut removed, 10, |host| host.0 == "pair-a").is_some());
+        assert!(remove_host(&mut hosts, &mut removed, 10, |host| host.0 == "gone").is_none());
+        assert_eq!(vec!["static", "pair-c", "pair-d"], names(&hosts));
+
+        let restored = revert_hosts(&mut hosts, &mut removed, 10, creation_block);
+        assert_eq!(vec!["pair-a", "pair-b"], names(&restored));
+        assert_eq!(vec!["static", "pair-a", "pair-b", "pair-c"], names(&hosts));
+        assert!(removed.is_empty());
+
+        // The retry succeeds, and reverting a later block leaves the
+        // removal alone
+        assert!(remove_host(&mut hosts, &mut removed, 10, |host| host.0 == "pair-b").is_some());
+        assert!(revert_hosts(&mut hosts, &mut removed, 11, creation_block).is_empty());
+        assert_eq!(vec!["static", "pair-a", "pair-c"], names(&hosts));
+    }
+
+    lazy_static! {
+        static ref SUBGRAPH_ID: DeploymentHash = DeploymentHash::new("parallelHandlers").unwrap();
+        static ref SCHEMA: Arc<Schema> = Arc::new(
+            Schema::parse(
+                "
+                type Token @entity { id: ID!, count: Int! }
+                type Pair @entity { id: ID!, count: Int! }
+                ",
+                SUBGRAPH_ID.clone(),
+            )
+            .unwrap()
+        );
+    }
+
+    /// A store without any entities
+    struct EmptyStore;
+
+    #[async_trait]
+    impl WritableStore for EmptyStore {
+        async fn block_ptr(&self) -> Option<BlockPtr> {
+            unimplemented!()
+        }
+
+        async fn block_cursor(&self) -> Option<String> {
+            unimplemented!()
+        }
+
+        async fn delete_block_cursor(&self) -> Result<(), StoreError> {
+            unimplemented!()
+        }
+
+        async fn start_subgraph_deployment(&self, _: &Logger) -> Result<(), StoreError> {
+            unimplemented!()
+        }
+
+        async fn revert_block_operations(
+            &self,
+            _: BlockPtr,
+            _: Option<&str>,
+        ) -> Result<(), StoreError> {
+            unimplemented!()
+        }
+
+        fn unfail_deterministic_error(
+            &self,
+            _: &BlockPtr,
+            _: &BlockPtr,
+        ) -> Result<UnfailOutcome, StoreError> {
+            unimplemented!()
+        }
+
+        fn unfail_non_deterministic_error(
+            &self,
+            _: &BlockPtr,
+        ) -> Result<UnfailOutcome, StoreError> {
+            unimplemented!()
+        }
+
+        async fn fail_subgraph(&self, _: SubgraphError) -> Result<(), StoreError> {
+            unimplemented!()
+        }
+
+        async fn supports_proof_of_indexing(&self) -> Result<bool, StoreError> {
+            unimplemented!()
+        }
+
+        fn get(&self, _: &EntityKey) -> Result<Option<Entity>, StoreError> {
+            Ok(None)
+        }
+
+        async fn transact_block_operations(
+            &self,
+            _: BlockPtr,
+            _: Option<String>,
+            _: Vec<EntityModification>,
+            _: &StopwatchMetrics,
+            _: Vec<StoredDynamicDataSource>,
+            _: Vec<SubgraphError>,
+        ) -> Result<(), StoreError> {
+            unimplemented!()
+        }
+
+        fn get_many(
+            &self,
+            _: BTreeMap<&EntityType, Vec<&str>>,
+        ) -> Result<BTreeMap<EntityType, Vec<Entity>>, StoreError> {
+            Ok(BTreeMap::new())
+        }
+
+        fn deployment_synced(&self) -> Result<(), StoreError> {
+            unimplemented!()
+        }
+
+        async fn is_deployment_synced(&self) -> Result<bool, StoreError> {
+            unimplemented!()
+        }
+
+        fn unassign_subgraph(&self) -> Result<(), StoreError> {
+            unimplemented!()
+        }
+
+        async fn load_dynamic_data_sources(
+            &self,
+        ) -> Result<Vec<StoredDynamicDataSource>, StoreError> {
+            unimplemented!()
+        }
+
+        fn shard(&self) -> &str {
+            unimplemented!()
+        }
+
+        async fn health(&self, _: &DeploymentHash) -> Result<SubgraphHealth, StoreError> {
+            unimplemented!()
+        }
+
+        fn input_schema(&self) -> Arc<Schema> {
+            SCHEMA.clone()
+        }
+
+        async fn flush(&self) -> Result<(), StoreError> {
+            unimplemented!()
+        }
+    }
+
+    /// A host whose handler counts how often it ran in an entity named
+    /// after the host, and in a `total` entity that all hosts for the same
+    /// entity type share. The handler fails when its count reaches
+    /// `fails_at`
+    #[derive(PartialEq)]
+    struct CountingHost {
+        name: &'static str,
+        entities: Vec<String>,
+        fails_at: Option<i32>,
+    }
+
+    impl CountingHost {
+        fn new(name: &'static str, entity_type: &str, fails_at: Option<i32>) -> Self {
+            CountingHost {
+                name,
+                entities: vec![entity_type.to_owned()],
+                fails_at,
+            }
+        }
+
+        fn increment(
+            &self,
+            logger: &Logger,
+            state: &mut BlockState<MockBlockchain>,
+            proof_of_indexing: &SharedProofOfIndexing,
+            id: &str,
+        ) -> Result<i32, Error> {
+            let entity_type = self.entities[0].as_str();
+            let key = EntityKey::data(SUBGRAPH_ID.clone(), entity_type.to_owned(), id.to_owned());
+            let count = match state.entity_cache.get(&key)? {
+                Some(entity) => entity.get("count").unwrap().as_int().unwrap() + 1,
+                None => 1,
+            };
+            let data: HashMap<String, Value> = HashMap::from([
+                ("id".to_owned(), Value::from(id)),
+                ("count".to_owned(), Value::from(count)),
+            ]);
+            if let Some(proof_of_indexing) = proof_of_indexing {
+                proof_of_indexing.borrow_mut().write(
+                    logger,
+                    "mock",
+                    &ProofOfIndexingEvent::SetEntity {
+                        entity_type,
+                        id,
+                        data: &data,
+                    },
+                );
+            }
+            state.entity_cache.set(key, Entity::from(data))?;
+            Ok(count)
+        }
+    }
+
+    #[async_trait]
+    impl RuntimeHost<MockBlockchain> for CountingHost {
+        fn match_and_decode(
+            &self,
+            _: &MockTriggerData,
+            _: &Arc<MockBlock>,
+            _: &Logger,
+        ) -> Result<Option<TriggerWithHandler<MockBlockchain>>, Error> {
+            Ok(Some(TriggerWithHandler::new(
+                MockMappingTrigger {},
+                self.name.to_owned(),
+            )))
+        }
+
+        async fn process_mapping_trigger(
+            &self,
+            logger: &Logger,
+            _: BlockPtr,
+            trigger: TriggerWithHandler<MockBlockchain>,
+            mut state: BlockState<MockBlockchain>,
+            proof_of_indexing: SharedProofOfIndexing,
+            _: &Option<Arc<dyn SubgraphFork>>,
+        ) -> Result<BlockState<MockBlockchain>, MappingError> {
+            state.enter_handler(trigger.handler_name());
+            let count = self.increment(logger, &mut state, &proof_of_indexing, self.name)?;
+            if Some(count) == self.fails_at {
+                return Err(MappingError::Unknown(anyhow!(
+                    "{} failed at {}",
+                    self.name,
+                    count
+                )));
+            }
+            self.increment(logger, &mut state, &proof_of_indexing, "total")?;
+            state.exit_handler();
+            Ok(state)
+        }
+
+        fn creation_block_number(&self) -> Option<BlockNumber> {
+            None
+        }
+
+        fn entities(&self) -> &[String] {
+            &self.entities
+        }
+
+        fn data_source(&self) -> &MockDataSource {
+            unimplemented!()
+        }
+    }
+
+    #[derive(Clone)]
+    struct CountingHostBuilder;
+
+    impl RuntimeHostBuilder<MockBlockchain> for CountingHostBuilder {
+        type Host = CountingHost;
+        type Req = ();
+
+        fn build(
+            &self,
+            _: String,
+            _: DeploymentHash,
+            _: MockDataSource,
+            _: Arc<Vec<MockDataSourceTemplate>>,
+            _: Sender<()>,
+            _: Arc<HostMetrics>,
+        ) -> Result<CountingHost, Error> {
+            unimplemented!()
+        }
+
+        fn spawn_mapping(
+            _: Vec<u8>,
+            _: Logger,
+            _: DeploymentHash,
+            _: Arc<HostMetrics>,
+        ) -> Result<Sender<()>, Error> {
+            unimplemented!()
+        }
+    }
+
+    /// The entity changes and proof of indexing digests from processing
+    /// `trigger_count` triggers with `hosts`, or the error it ran into
+    async fn process(
+        hosts: Vec<CountingHost>,
+        trigger_count: usize,
+        parallel: bool,
+    ) -> Result<(Vec<EntityModification>, BTreeMap<String, Vec<u8>>), String> {
+        let logger = Logger::root(graph::slog::Discard, graph::slog::o!());
+        let instance = SubgraphInstance {
+            subgraph_id: SUBGRAPH_ID.clone(),
+            network: "mock".to_owned(),
+            host_builder: CountingHostBuilder,
+            hosts: hosts.into_iter().map(Arc::new).collect(),
+            removed_hosts: Vec::new(),
+            expiring: BTreeMap::new(),
+            module_cache: HashMap::new(),
+        };
+        let metrics = Arc::new(SubgraphInstanceMetrics::new(
+            Arc::new(MockMetricsRegistry::new()),
+            SUBGRAPH_ID.as_str(),
+        ));
+        let store: Arc<dyn WritableStore> = Arc::new(EmptyStore);
+        let block = Arc::new(MockBlock { number: 1 });
+        let triggers = vec![MockTriggerData; trigger_count];
+        let proof_of_indexing = Arc::new(AtomicRefCell::new(ProofOfIndexing::new(1)));
+        let shared: SharedProofOfIndexing = Some(proof_of_indexing.clone());
+
+        let state = if parallel {
+            instance
+                .process_triggers_in_parallel(
+                    &logger,
+                    &block,
+                    triggers,
+                    store,
+                    LfuCache::new(),
+                    &shared,
+                    "mock",
+                    &None,
+                    &metrics,
+                )
+                .await
+        } else {
+            let mut state = Ok(BlockState::new(store, LfuCache::new()));
+            for trigger in &triggers {
+                state = match state {
+                    Ok(state) => instance
+                        .process_trigger(
+                            &logger, &block, trigger, state, &shared, "mock", &None, &metrics,
+                        )
+                        .await
+                        .map_err(|e| trigger_error(e, trigger)),
+                    Err(e) => Err(e),
+                };
+            }
+            state
+        };
+        let state = state.map_err(|e| match e {
+            MappingError::Unknown(e) => format!("{:#}", e),
+            e => format!("{:?}", e),
+        })?;
+
+        let mut modifications = state.entity_cache.as_modifications().unwrap().modifications;
+        modifications.sort_by(|a, b| a.entity_key().cmp(b.entity_key()));
+        drop(shared);
+        let digests = Arc::try_unwrap(proof_of_indexing)
+            .unwrap()
+            .into_inner()
+            .take()
+            .into_iter()
+            .map(|(region, stream)| (region, stream.pause(None)))
+            .collect();
+        Ok((modifications, digests))
+    }
+
+    fn hosts(token_fails_at: Option<i32>, pair_fails_at: Option<i32>) -> Vec<CountingHost> {
+        vec![
+            CountingHost::new("token-a", "Token", None),
+            CountingHost::new("pair-a", "Pair", pair_fails_at),
+            CountingHost::new("token-b", "Token", token_fails_at),
+            CountingHost::new("pair-b", "Pair", None),
+        ]
+    }
+
+    /// Running the handlers of hosts that do not share entities in
+    /// parallel changes the same entities, produces the same proof of
+    /// indexing, and fails with the same error as running them one after
+    /// the other
+    #[tokio::test]
+    async fn parallel_handlers_match_sequential_processing() {
+        let sequential = process(hosts(None, None), 3, false).await;
+        let parallel = process(hosts(None, None), 3, true).await;
+        let (modifications, digests) = sequential.clone().unwrap();
+        assert_eq!(6, modifications.len());
+        assert!(modifications
+            .iter()
+            .all(|modification| matches!(modification, EntityModification::Insert { .. })));
+        let total = modifications
+            .iter()
+            .find(|modification| {
+                modification.entity_key().entity_type.as_str() == "Token"
+                    && modification.entity_key().entity_id == "total"
+            })
+            .and_then(|modification| modification.entity())
+            .and_then(|entity| entity.get("count").cloned());
+        assert_eq!(Some(Value::Int(6)), total);
+        assert_eq!(1, digests.len());
+        assert_eq!(sequential, parallel);
+
+        // When both lanes fail, the error is the one of the earliest
+        // failing handler, no matter which lane fails first
+        for (token_fails_at, pair_fails_at) in [(Some(3), Some(2)), (Some(2), Some(2))] {
+            let sequential = process(hosts(token_fails_at, pair_fails_at), 3, false).await;
+            let parallel = process(hosts(token_fails_at, pair_fails_at), 3, true).await;
+            assert_eq!(
+                Err("failed to process trigger: pair-a failed at 2".to_owned()),
+                sequential
+            );
+            assert_eq!(sequential, parallel);
+        }
+    }
+}
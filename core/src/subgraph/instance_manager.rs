@@ -5,15 +5,30 @@ use crate::subgraph::metrics::{
     RunnerMetrics, SubgraphInstanceManagerMetrics, SubgraphInstanceMetrics,
 };
 use crate::subgraph::runner::SubgraphRunner;
+use crate::subgraph::shutdown::Shutdown;
 use crate::subgraph::SubgraphInstance;
+use crate::StoredLinkResolver;
 use graph::blockchain::block_stream::BlockStreamMetrics;
 use graph::blockchain::Blockchain;
 use graph::blockchain::NodeCapabilities;
 use graph::blockchain::{BlockchainKind, TriggerFilter};
+use graph::data::subgraph::SubgraphFeature;
 use graph::prelude::{SubgraphInstanceManager as SubgraphInstanceManagerTrait, *};
+use graph::util::backoff::ExponentialBackoff;
 use graph::{blockchain::BlockchainMap, components::store::DeploymentLocator};
+use std::time::Duration;
+use tokio::sync::oneshot;
 use tokio::task;
 
+/// How long to wait before restarting a subgraph that failed the first
+/// time; the wait doubles with every restart up to
+/// `GRAPH_SUBGRAPH_ERROR_RETRY_CEIL_SECS`
+const MINIMUM_RESTART_DELAY: Duration = Duration::from_secs(30);
+
+/// By how much restart delays vary randomly so that subgraphs that failed
+/// because of the same provider outage do not all restart at once
+const RESTART_JITTER: f64 = 0.25;
+
 pub struct SubgraphInstanceManager<S: SubgraphStore> {
     logger_factory: LoggerFactory,
     subgraph_store: Arc<S>,
@@ -21,8 +36,10 @@ pub struct SubgraphInstanceManager<S: SubgraphStore> {
     metrics_registry: Arc<dyn MetricsRegistry>,
     manager_metrics: SubgraphInstanceManagerMetrics,
     instances: SharedInstanceKeepAliveMap,
+    supervisors: SharedInstanceKeepAliveMap,
     link_resolver: Arc<dyn LinkResolver>,
     static_filters: bool,
+    shutdown: Shutdown,
 }
 
 #[async_trait]
@@ -34,48 +51,70 @@ impl<S: SubgraphStore> SubgraphInstanceManagerTrait for SubgraphInstanceManager<
         stop_block: Option<BlockNumber>,
     ) {
         let logger = self.logger_factory.subgraph_logger(&loc);
-        let err_logger = logger.clone();
-        let instance_manager = self.cheap_clone();
-
-        let subgraph_start_future = async move {
-            match BlockchainKind::from_manifest(&manifest)? {
-                BlockchainKind::Ethereum => {
-                    instance_manager
-                        .start_subgraph_inner::<graph_chain_ethereum::Chain>(
-                            logger, loc, manifest, stop_block,
-                        )
-                        .await
-                }
-                BlockchainKind::Near => {
-                    instance_manager
-                        .start_subgraph_inner::<graph_chain_near::Chain>(
-                            logger, loc, manifest, stop_block,
-                        )
-                        .await
-                }
-                BlockchainKind::Tendermint => {
-                    instance_manager
-                        .start_subgraph_inner::<graph_chain_tendermint::Chain>(
-                            logger, loc, manifest, stop_block,
-                        )
-                        .await
-                }
-            }
-        };
+
+        // Replacing the guard from an earlier start of the same deployment
+        // keeps its supervisor from restarting it
+        let supervisor = CancelGuard::new();
+        let unassigned = supervisor.handle();
+        self.supervisors.write().unwrap().insert(loc.id, supervisor);
+
         // Perform the actual work of starting the subgraph in a separate
         // task. If the subgraph is a graft or a copy, starting it will
         // perform the actual work of grafting/copying, which can take
         // hours. Running it in the background makes sure the instance
         // manager does not hang because of that work.
+        //
+        // The task also supervises the subgraph: if it can not be started,
+        // or stops with an error other than a deterministic failure, it is
+        // started again with backoff for as long as it is assigned to us
         graph::spawn(async move {
-            match subgraph_start_future.await {
-                Ok(()) => self.manager_metrics.subgraph_count.inc(),
-                Err(err) => error!(
-                    err_logger,
-                    "Failed to start subgraph";
-                    "error" => format!("{:#}", err),
-                    "code" => LogCode::SubgraphStartFailure
-                ),
+            let mut backoff =
+                ExponentialBackoff::new(MINIMUM_RESTART_DELAY, ENV_VARS.subgraph_error_retry_ceil)
+                    .with_jitter(RESTART_JITTER);
+            let mut counted = false;
+            loop {
+                let started = self
+                    .cheap_clone()
+                    .start_subgraph_once(logger.clone(), loc.clone(), manifest.clone(), stop_block)
+                    .await;
+                let failed = match started {
+                    Ok(finished) => {
+                        if !counted {
+                            self.manager_metrics.subgraph_count.inc();
+                            counted = true;
+                        }
+                        // The runner thread dropping the sender means it
+                        // panicked
+                        !matches!(finished.await, Ok(Ok(())))
+                    }
+                    Err(err) => {
+                        error!(
+                            logger,
+                            "Failed to start subgraph";
+                            "error" => format!("{:#}", err),
+                            "code" => LogCode::SubgraphStartFailure
+                        );
+                        true
+                    }
+                };
+
+                if !failed || unassigned.is_canceled() || self.shutdown.is_requested() {
+                    return;
+                }
+
+                warn!(
+                    logger,
+                    "Restarting subgraph after it failed with a non-deterministic error";
+                    "attempt" => backoff.attempt,
+                    "retry_delay_s" => backoff.delay().as_secs()
+                );
+                backoff.sleep_async().await;
+                if unassigned.is_canceled() || self.shutdown.is_requested() {
+                    return;
+                }
+                if let Err(e) = self.subgraph_store.record_restart(&loc) {
+                    warn!(logger, "Failed to record subgraph restart"; "error" => e.to_string());
+                }
             }
         });
     }
@@ -84,7 +123,9 @@ impl<S: SubgraphStore> SubgraphInstanceManagerTrait for SubgraphInstanceManager<
         let logger = self.logger_factory.subgraph_logger(&loc);
         info!(logger, "Stop subgraph");
 
-        // Drop the cancel guard to shut down the subgraph now
+        // Drop the cancel guards to shut down the subgraph now and to keep
+        // it from being restarted
+        self.supervisors.write().unwrap().remove(&loc.id);
         let mut instances = self.instances.write().unwrap();
         instances.remove(&loc.id);
 
@@ -111,8 +152,49 @@ impl<S: SubgraphStore> SubgraphInstanceManager<S> {
             manager_metrics: SubgraphInstanceManagerMetrics::new(metrics_registry.cheap_clone()),
             metrics_registry,
             instances: SharedInstanceKeepAliveMap::default(),
+            supervisors: SharedInstanceKeepAliveMap::default(),
             link_resolver,
             static_filters,
+            shutdown: Shutdown::new(),
+        }
+    }
+
+    /// Stop all subgraphs once they have finished processing the block
+    /// they are working on and wait until their writes have been flushed
+    /// to the store. Subgraphs that are assigned to this node afterwards
+    /// are not started anymore
+    pub async fn shutdown(&self) {
+        self.shutdown.shutdown().await;
+    }
+
+    /// Start the subgraph; the receiver resolves once the subgraph has
+    /// stopped running, with an error if it stopped because of one
+    async fn start_subgraph_once(
+        self: Arc<Self>,
+        logger: Logger,
+        loc: DeploymentLocator,
+        manifest: serde_yaml::Mapping,
+        stop_block: Option<BlockNumber>,
+    ) -> Result<oneshot::Receiver<Result<(), Error>>, Error> {
+        match BlockchainKind::from_manifest(&manifest)? {
+            BlockchainKind::Ethereum => {
+                self.start_subgraph_inner::<graph_chain_ethereum::Chain>(
+                    logger, loc, manifest, stop_block,
+                )
+                .await
+            }
+            BlockchainKind::Near => {
+                self.start_subgraph_inner::<graph_chain_near::Chain>(
+                    logger, loc, manifest, stop_block,
+                )
+                .await
+            }
+            BlockchainKind::Tendermint => {
+                self.start_subgraph_inner::<graph_chain_tendermint::Chain>(
+                    logger, loc, manifest, stop_block,
+                )
+                .await
+            }
         }
     }
 
@@ -122,7 +204,7 @@ impl<S: SubgraphStore> SubgraphInstanceManager<S> {
         deployment: DeploymentLocator,
         manifest: serde_yaml::Mapping,
         stop_block: Option<BlockNumber>,
-    ) -> Result<(), Error> {
+    ) -> Result<oneshot::Receiver<Result<(), Error>>, Error> {
         let subgraph_store = self.subgraph_store.cheap_clone();
         let registry = self.metrics_registry.cheap_clone();
         let store = self
@@ -140,11 +222,16 @@ impl<S: SubgraphStore> SubgraphInstanceManager<S> {
         let manifest: SubgraphManifest<C> = {
             info!(logger, "Resolve subgraph files using IPFS");
 
+            // Allow for infinite retries for subgraph definition files, and
+            // keep them in the store so we do not depend on IPFS next time
+            let link_resolver: Arc<dyn LinkResolver> = Arc::new(StoredLinkResolver::new(
+                self.link_resolver.with_retries().into(),
+                self.subgraph_store.file_registry(),
+            ));
             let mut manifest = SubgraphManifest::resolve_from_raw(
                 deployment.hash.cheap_clone(),
                 manifest,
-                // Allow for infinite retries for subgraph definition files.
-                &Arc::from(self.link_resolver.with_retries()),
+                &link_resolver,
                 &logger,
                 ENV_VARS.max_spec_version.clone(),
             )
@@ -190,6 +277,14 @@ impl<S: SubgraphStore> SubgraphInstanceManager<S> {
         }
 
         let start_blocks = manifest.start_blocks();
+        let indexing_delay = manifest.indexing_delay.unwrap_or(0);
+        if indexing_delay > 0 && chain.is_firehose_supported() {
+            warn!(
+                logger,
+                "The indexing delay of the subgraph is ignored since its blocks come from Firehose";
+                "indexing_delay" => indexing_delay
+            );
+        }
 
         let templates = Arc::new(manifest.templates.clone());
 
@@ -241,6 +336,12 @@ impl<S: SubgraphStore> SubgraphInstanceManager<S> {
             chain.runtime_adapter(),
             self.link_resolver.cheap_clone(),
             subgraph_store.ens_lookup(),
+        )
+        .with_uncrashable(manifest.features.contains(&SubgraphFeature::Uncrashable))
+        .with_strict_references(
+            manifest
+                .features
+                .contains(&SubgraphFeature::StrictReferences),
         );
 
         let features = manifest.features.clone();
@@ -253,6 +354,7 @@ impl<S: SubgraphStore> SubgraphInstanceManager<S> {
             features,
             start_blocks,
             stop_block,
+            indexing_delay,
             store,
             debug_fork,
             triggers_adapter,
@@ -262,11 +364,17 @@ impl<S: SubgraphStore> SubgraphInstanceManager<S> {
             static_filters: self.static_filters,
         };
 
+        let shutdown = self
+            .shutdown
+            .listen()
+            .ok_or_else(|| anyhow!("the node is shutting down"))?;
+
         // The subgraph state tracks the state of the subgraph instance over time
         let ctx = IndexingContext {
             instance,
             instances: self.instances.cheap_clone(),
             filter,
+            shutdown,
         };
 
         let metrics = RunnerMetrics {
@@ -288,9 +396,11 @@ impl<S: SubgraphStore> SubgraphInstanceManager<S> {
         // scheduling. It is also logical in terms of performance to run this with `unconstrained`,
         // it has a dedicated OS thread so the OS will handle the preemption. See
         // https://github.com/tokio-rs/tokio/issues/3493.
+        let (finished, finished_receiver) = oneshot::channel();
         graph::spawn_thread(deployment.to_string(), move || {
             let runner = SubgraphRunner::new(inputs, ctx, logger.cheap_clone(), metrics);
-            if let Err(e) = graph::block_on(task::unconstrained(runner.run())) {
+            let result = graph::block_on(task::unconstrained(runner.run()));
+            if let Err(e) = &result {
                 error!(
                     &logger,
                     "Subgraph instance failed to run: {}",
@@ -298,8 +408,9 @@ impl<S: SubgraphStore> SubgraphInstanceManager<S> {
                 );
             }
             subgraph_metrics_unregister.unregister(registry);
+            let _ = finished.send(result);
         });
 
-        Ok(())
+        Ok(finished_receiver)
     }
 }
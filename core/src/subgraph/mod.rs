@@ -8,6 +8,7 @@ mod metrics;
 mod provider;
 mod registrar;
 mod runner;
+mod shutdown;
 mod state;
 mod stream;
 
@@ -19,7 +19,7 @@ impl<I: SubgraphInstanceManager> SubgraphAssignmentProvider<I> {
     pub fn new(
         logger_factory: &LoggerFactory,
         link_resolver: Arc<dyn LinkResolver>,
-        instance_manager: I,
+        instance_manager: Arc<I>,
     ) -> Self {
         let logger = logger_factory.component_logger("SubgraphAssignmentProvider", None);
         let logger_factory = logger_factory.with_parent(logger.clone());
@@ -29,7 +29,7 @@ impl<I: SubgraphInstanceManager> SubgraphAssignmentProvider<I> {
             logger_factory,
             subgraphs_running: Arc::new(Mutex::new(HashSet::new())),
             link_resolver: link_resolver.with_retries().into(),
-            instance_manager: Arc::new(instance_manager),
+            instance_manager,
         }
     }
 }
@@ -1,12 +1,19 @@
-use std::collections::HashSet;
+use std::collections::{BTreeMap, HashSet};
+use std::path::Path;
 use std::time::Instant;
 
 use async_trait::async_trait;
 use graph::blockchain::Blockchain;
 use graph::blockchain::BlockchainKind;
 use graph::blockchain::BlockchainMap;
-use graph::components::store::{DeploymentId, DeploymentLocator, SubscriptionManager};
+use graph::blockchain::DataSource as _;
+use graph::components::store::{
+    AdminCall, DeploymentId, DeploymentLocator, ExportRequest, ExportedFile, SubscriptionManager,
+};
+use graph::components::subgraph::{ManifestSource, SubgraphDiagnostic, SubgraphValidation};
+use graph::data::subgraph::features::detect_features;
 use graph::data::subgraph::schema::DeploymentCreate;
+use graph::data::subgraph::{local, networks, parameters};
 use graph::prelude::{
     CreateSubgraphResult, SubgraphAssignmentProvider as SubgraphAssignmentProviderTrait,
     SubgraphRegistrar as SubgraphRegistrarTrait, *,
@@ -394,6 +401,230 @@ where
 
         Ok(())
     }
+
+    async fn export_subgraph(
+        &self,
+        hash: &DeploymentHash,
+        request: ExportRequest,
+    ) -> Result<Vec<ExportedFile>, SubgraphRegistrarError> {
+        let locations = self.store.locators(hash)?;
+        let deployment = match locations.len() {
+            0 => return Err(SubgraphRegistrarError::DeploymentNotFound(hash.to_string())),
+            1 => locations[0].clone(),
+            _ => {
+                return Err(SubgraphRegistrarError::StoreError(
+                    anyhow!(
+                        "there are {} different deployments with id {}",
+                        locations.len(),
+                        hash.as_str()
+                    )
+                    .into(),
+                ))
+            }
+        };
+
+        // Exports can take a long time, don't tie up the executor with them
+        let store = self.store.clone();
+        graph::spawn_blocking_allow_panic(move || store.export(&deployment, &request))
+            .await
+            .map_err(|e| SubgraphRegistrarError::Unknown(e.into()))?
+            .map_err(SubgraphRegistrarError::from)
+    }
+
+    async fn import_files(&self, path: &str) -> Result<DeploymentHash, SubgraphRegistrarError> {
+        let root = ENV_VARS.local_subgraph_root.clone().ok_or_else(|| {
+            SubgraphRegistrarError::ResolveError(SubgraphManifestResolveError::ResolveError(
+                anyhow!("deploying subgraphs from local files is turned off"),
+            ))
+        })?;
+        let registry = self.store.file_registry();
+        let path = path.to_string();
+        let hash = graph::spawn_blocking_allow_panic(move || {
+            local::import(&path, Path::new(&root), &*registry)
+        })
+        .await
+        .map_err(|e| SubgraphRegistrarError::Unknown(e.into()))?
+        .map_err(|e| {
+            SubgraphRegistrarError::ResolveError(SubgraphManifestResolveError::ResolveError(e))
+        })?;
+        info!(self.logger, "Imported subgraph files"; "subgraph_id" => hash.to_string());
+        Ok(hash)
+    }
+
+    async fn instantiate_manifest(
+        &self,
+        hash: DeploymentHash,
+        values: &BTreeMap<String, serde_json::Value>,
+    ) -> Result<DeploymentHash, SubgraphRegistrarError> {
+        let resolve_error =
+            |e| SubgraphRegistrarError::ResolveError(SubgraphManifestResolveError::ResolveError(e));
+
+        let manifest = self
+            .resolver
+            .cat(&self.logger, &hash.to_ipfs_link())
+            .await
+            .map_err(resolve_error)?;
+        let instance = match parameters::instantiate(&manifest, values).map_err(resolve_error)? {
+            Some(instance) => instance,
+            None => return Ok(hash),
+        };
+
+        let instance_hash = DeploymentHash::new(local::content_hash(&instance))
+            .expect("content hashes are valid deployment hashes");
+        let registry = self.store.file_registry();
+        let link = instance_hash.to_string();
+        graph::spawn_blocking_allow_panic(move || registry.put_file(&link, &instance))
+            .await
+            .map_err(|e| SubgraphRegistrarError::Unknown(e.into()))??;
+        info!(self.logger, "Set the parameters of the manifest";
+              "manifest" => hash.to_string(),
+              "subgraph_id" => instance_hash.to_string());
+        Ok(instance_hash)
+    }
+
+    async fn instantiate_networks(
+        &self,
+        hash: &DeploymentHash,
+    ) -> Result<Option<Vec<(String, DeploymentHash)>>, SubgraphRegistrarError> {
+        let resolve_error =
+            |e| SubgraphRegistrarError::ResolveError(SubgraphManifestResolveError::ResolveError(e));
+
+        let manifest = self
+            .resolver
+            .cat(&self.logger, &hash.to_ipfs_link())
+            .await
+            .map_err(resolve_error)?;
+        let raw: serde_yaml::Mapping =
+            serde_yaml::from_slice(&manifest).map_err(|e| resolve_error(Error::from(e)))?;
+        if !raw.contains_key(&serde_yaml::Value::from("networks")) {
+            return Ok(None);
+        }
+        let kind = BlockchainKind::from_manifest(&raw).map_err(resolve_error)?;
+        let instances =
+            networks::instantiate(&manifest, |network| self.chains.contains(kind, network))
+                .map_err(resolve_error)?
+                .unwrap_or_default();
+        if instances.is_empty() {
+            return Err(SubgraphRegistrarError::ResolveError(
+                SubgraphManifestResolveError::ResolveError(anyhow!(
+                    "this node has no chains for any of the networks of the manifest"
+                )),
+            ));
+        }
+
+        let mut family = Vec::new();
+        for (network, instance) in instances {
+            let instance_hash = DeploymentHash::new(local::content_hash(&instance))
+                .expect("content hashes are valid deployment hashes");
+            let store = self.store.clone();
+            let registry = store.file_registry();
+            let (parent, member, net) = (hash.clone(), instance_hash.clone(), network.clone());
+            graph::spawn_blocking_allow_panic(move || {
+                registry.put_file(member.as_str(), &instance)?;
+                store.add_to_family(&parent, &member, &net)
+            })
+            .await
+            .map_err(|e| SubgraphRegistrarError::Unknown(e.into()))??;
+            info!(self.logger, "Instantiated the manifest for a network";
+                  "manifest" => hash.to_string(),
+                  "network" => &network,
+                  "subgraph_id" => instance_hash.to_string());
+            family.push((network, instance_hash));
+        }
+        Ok(Some(family))
+    }
+
+    async fn validate_subgraph(
+        &self,
+        manifest: ManifestSource,
+    ) -> Result<SubgraphValidation, SubgraphRegistrarError> {
+        let (hash, text) = match manifest {
+            ManifestSource::Hash(hash) => (hash, None),
+            ManifestSource::Inline(text) => {
+                let hash = DeploymentHash::new(local::content_hash(text.as_bytes()))
+                    .expect("content hashes are valid deployment hashes");
+                (hash, Some(text.into_bytes()))
+            }
+        };
+        let logger = self
+            .logger_factory
+            .subgraph_logger(&DeploymentLocator::new(DeploymentId(0), hash.clone()));
+        let mut validation = SubgraphValidation::new(hash.clone());
+
+        let resolved = async {
+            let file_bytes = match text {
+                Some(text) => text,
+                None => self.resolver.cat(&logger, &hash.to_ipfs_link()).await?,
+            };
+            let raw: serde_yaml::Mapping = serde_yaml::from_slice(&file_bytes)?;
+            let kind = BlockchainKind::from_manifest(&raw)?;
+            Ok::<_, Error>((raw, kind))
+        };
+        let (raw, kind) = match resolved.await {
+            Ok(resolved) => resolved,
+            Err(e) => {
+                validation.push(SubgraphDiagnostic::new("resolve", None, format!("{:#}", e)));
+                return Ok(validation);
+            }
+        };
+
+        match kind {
+            BlockchainKind::Ethereum => {
+                validate_subgraph_version::<graph_chain_ethereum::Chain, _>(
+                    &logger,
+                    self.store.clone(),
+                    &self.chains,
+                    raw,
+                    &self.resolver,
+                    &mut validation,
+                )
+                .await
+            }
+
+            BlockchainKind::Near => {
+                validate_subgraph_version::<graph_chain_near::Chain, _>(
+                    &logger,
+                    self.store.clone(),
+                    &self.chains,
+                    raw,
+                    &self.resolver,
+                    &mut validation,
+                )
+                .await
+            }
+
+            BlockchainKind::Tendermint => {
+                validate_subgraph_version::<graph_chain_tendermint::Chain, _>(
+                    &logger,
+                    self.store.clone(),
+                    &self.chains,
+                    raw,
+                    &self.resolver,
+                    &mut validation,
+                )
+                .await
+            }
+        };
+
+        debug!(
+            &logger,
+            "Validated subgraph manifest";
+            "subgraph_hash" => hash.to_string(),
+            "diagnostics" => validation.diagnostics.len(),
+        );
+
+        Ok(validation)
+    }
+
+    async fn record_admin_call(&self, call: AdminCall) -> Result<(), SubgraphRegistrarError> {
+        // Writing to the audit log needs a database connection, don't
+        // block the executor while we wait for one
+        let store = self.store.clone();
+        graph::spawn_blocking_allow_panic(move || store.record_admin_call(&call))
+            .await
+            .map_err(|e| SubgraphRegistrarError::Unknown(e.into()))?
+            .map_err(SubgraphRegistrarError::from)
+    }
 }
 
 async fn handle_assignment_event(
@@ -454,10 +685,44 @@ async fn start_subgraph(
     }
 }
 
+/// The start blocks of the manifest's data sources. Data sources that do
+/// not set one start where the chain says they need to, if it can tell
+async fn start_blocks<C: Blockchain>(
+    manifest: &SubgraphManifest<C>,
+    chain: &C,
+    logger: &Logger,
+) -> Vec<BlockNumber> {
+    let mut start_blocks = Vec::with_capacity(manifest.data_sources.len());
+    for data_source in &manifest.data_sources {
+        let start_block = match data_source.start_block() {
+            0 if ENV_VARS.detect_start_blocks => {
+                match chain.detect_start_block(logger, data_source).await {
+                    Ok(Some(start_block)) => {
+                        info!(logger, "Detected start block of data source";
+                            "data_source" => data_source.name(),
+                            "block" => start_block);
+                        start_block
+                    }
+                    Ok(None) => 0,
+                    Err(e) => {
+                        warn!(logger, "Could not detect start block of data source, starting it at the genesis block";
+                            "data_source" => data_source.name(),
+                            "error" => format!("{:#}", e));
+                        0
+                    }
+                }
+            }
+            start_block => start_block,
+        };
+        start_blocks.push(start_block);
+    }
+    start_blocks
+}
+
 /// Resolves the subgraph's earliest block and the manifest's graft base block
-async fn resolve_subgraph_chain_blocks(
-    manifest: &SubgraphManifest<impl Blockchain>,
-    chain: Arc<impl Blockchain>,
+async fn resolve_subgraph_chain_blocks<C: Blockchain>(
+    manifest: &SubgraphManifest<C>,
+    chain: Arc<C>,
     logger: &Logger,
 ) -> Result<(Option<BlockPtr>, Option<(DeploymentHash, BlockPtr)>), SubgraphRegistrarError> {
     let logger1 = logger.clone();
@@ -466,8 +731,8 @@ async fn resolve_subgraph_chain_blocks(
     // If the minimum start block is 0 (i.e. the genesis block),
     // return `None` to start indexing from the genesis block. Otherwise
     // return a block pointer for the block with number `min_start_block - 1`.
-    let start_block_ptr = match manifest
-        .start_blocks()
+    let start_block_ptr = match start_blocks(manifest, chain.as_ref(), logger)
+        .await
         .into_iter()
         .min()
         .expect("cannot identify minimum start block because there are no data sources")
@@ -594,3 +859,78 @@ async fn create_subgraph_version<C: Blockchain, S: SubgraphStore>(
         .map_err(|e| SubgraphRegistrarError::SubgraphDeploymentError(e))
         .map(|_| ())
 }
+
+/// Run the same checks as `create_subgraph_version` against the chain and
+/// the store, without writing anything, and add what they find to
+/// `validation`
+async fn validate_subgraph_version<C: Blockchain, S: SubgraphStore>(
+    logger: &Logger,
+    store: Arc<S>,
+    chains: &BlockchainMap,
+    raw: serde_yaml::Mapping,
+    resolver: &Arc<dyn LinkResolver>,
+    validation: &mut SubgraphValidation,
+) {
+    let unvalidated = match UnvalidatedSubgraphManifest::<C>::resolve(
+        validation.deployment.clone(),
+        raw,
+        resolver,
+        logger,
+        ENV_VARS.max_spec_version.clone(),
+    )
+    .await
+    {
+        Ok(unvalidated) => unvalidated,
+        Err(e) => {
+            validation.push(SubgraphDiagnostic::new("resolve", None, e));
+            return;
+        }
+    };
+
+    let manifest = unvalidated.manifest();
+    validation.spec_version = Some(manifest.spec_version.to_string());
+    validation.network = manifest
+        .data_sources
+        .iter()
+        .find_map(|ds| ds.network().map(|n| n.to_string()));
+    validation.start_block = manifest.start_blocks().into_iter().min();
+    validation.graft_base = manifest.graft.as_ref().map(|graft| graft.base.clone());
+    validation.graft_block = manifest.graft.as_ref().map(|graft| graft.block);
+    // Mappings that can't be read are reported by `validate`
+    if let Ok(features) = detect_features(manifest) {
+        validation.features = features.iter().map(|f| f.to_string()).collect();
+    }
+
+    let manifest = match unvalidated.validate(store, true).await {
+        Ok(manifest) => manifest,
+        Err(errors) => {
+            for error in &errors {
+                for diagnostic in SubgraphDiagnostic::from_validation_error(error) {
+                    validation.push(diagnostic);
+                }
+            }
+            return;
+        }
+    };
+
+    let chain = match chains.get::<C>(manifest.network_name()) {
+        Ok(chain) => chain,
+        Err(e) => {
+            validation.push(SubgraphDiagnostic::new("network", None, e));
+            return;
+        }
+    };
+
+    // Makes sure that the start block and the graft block exist on the chain
+    match resolve_subgraph_chain_blocks(&manifest, chain, logger).await {
+        Ok(_) => {}
+        Err(SubgraphRegistrarError::ManifestValidationError(errors)) => {
+            for error in &errors {
+                for diagnostic in SubgraphDiagnostic::from_validation_error(error) {
+                    validation.push(diagnostic);
+                }
+            }
+        }
+        Err(e) => validation.push(SubgraphDiagnostic::new("block", None, e)),
+    }
+}
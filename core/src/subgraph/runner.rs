@@ -1,6 +1,7 @@
 use crate::subgraph::context::IndexingContext;
-use crate::subgraph::error::BlockProcessingError;
+use crate::subgraph::error::{BlockProcessingError, ErrorKind};
 use crate::subgraph::inputs::IndexingInputs;
+use crate::subgraph::instance::trigger_error;
 use crate::subgraph::metrics::RunnerMetrics;
 use crate::subgraph::state::IndexingState;
 use crate::subgraph::stream::new_block_stream;
@@ -101,10 +102,19 @@ where
 
             // Process events from the stream as long as no restart is needed
             loop {
+                // Only stop between blocks so that a block is either
+                // processed and written completely or not at all
+                if self.ctx.shutdown.is_requested() {
+                    return self.shut_down().await;
+                }
+
                 let event = {
                     let _section = self.metrics.stream.stopwatch.start_section("scan_blocks");
 
-                    block_stream.next().await
+                    tokio::select! {
+                        event = block_stream.next() => event,
+                        _ = self.ctx.shutdown.requested() => continue,
+                    }
                 };
 
                 // TODO: move cancel handle to the Context
@@ -121,6 +131,21 @@ where
         }
     }
 
+    /// Stop because the node is shutting down and wait until the blocks
+    /// that were processed have been written
+    async fn shut_down(&self) -> Result<(), Error> {
+        info!(
+            self.logger,
+            "Stopping subgraph because the node is shutting down"
+        );
+        self.inputs
+            .store
+            .flush()
+            .await
+            .context("Failed to flush pending writes")?;
+        Ok(())
+    }
+
     /// Processes a block and returns the updated context and a boolean flag indicating
     /// whether new dynamic data sources have been added to the subgraph.
     async fn process_block(
@@ -129,6 +154,83 @@ where
         block: BlockWithTriggers<C>,
         firehose_cursor: Option<String>,
     ) -> Result<Action, BlockProcessingError> {
+        let block_number = block.ptr().number;
+        let written = self
+            .process_and_write_block(block_stream_cancel_handle, block, firehose_cursor)
+            .await;
+        let written = match written {
+            Ok(Some(written)) => written,
+            // The block was not written. Hosts that were removed or created
+            // while processing it have to be put back or dropped, so that
+            // processing the block again starts from the same hosts
+            Ok(None) => {
+                self.revert_data_sources(block_number);
+                return Ok(Action::Restart);
+            }
+            Err(e) => {
+                self.revert_data_sources(block_number);
+                return Err(e);
+            }
+        };
+        let WrittenBlock {
+            has_errors,
+            first_error,
+            needs_restart,
+        } = written;
+        let is_non_fatal_errors_active = self
+            .inputs
+            .features
+            .contains(&SubgraphFeature::NonFatalErrors);
+
+        // For subgraphs with `nonFatalErrors` feature disabled, we consider
+        // any error as fatal.
+        //
+        // So we do an early return to make the subgraph stop processing blocks.
+        //
+        // In this scenario the only entity that is stored/transacted is the PoI,
+        // all of the others are discarded.
+        if has_errors && !is_non_fatal_errors_active {
+            // Only the first error is reported.
+            return Err(BlockProcessingError::Deterministic(first_error.unwrap()));
+        }
+
+        // To prevent a buggy pending version from replacing a current version, if errors are
+        // present the subgraph will be unassigned.
+        let store = &self.inputs.store;
+        if has_errors && !ENV_VARS.disable_fail_fast && !store.is_deployment_synced().await? {
+            store
+                .unassign_subgraph()
+                .map_err(|e| BlockProcessingError::Unknown(e.into()))?;
+
+            // Use `Canceled` to avoiding setting the subgraph health to failed, an error was
+            // just transacted so it will be already be set to unhealthy.
+            return Err(BlockProcessingError::Canceled);
+        }
+
+        match needs_restart {
+            true => Ok(Action::Restart),
+            false => Ok(Action::Continue),
+        }
+    }
+
+    /// Undo the changes to the hosts that processing `block_number` made
+    fn revert_data_sources(&mut self, block_number: BlockNumber) {
+        let restored = self.ctx.instance.revert_data_sources(block_number);
+        if !restored.is_empty() && !self.inputs.static_filters {
+            self.ctx.filter = C::TriggerFilter::from_data_sources(self.ctx.instance.data_sources());
+        }
+    }
+
+    /// Process the triggers of `block` and write the changes they produce
+    /// to the store. Return `None` if the block can not be processed
+    /// because of a possible reorg. Hosts are only removed for good once
+    /// this returns `Some`; the caller reverts them otherwise
+    async fn process_and_write_block(
+        &mut self,
+        block_stream_cancel_handle: &CancelHandle,
+        block: BlockWithTriggers<C>,
+        firehose_cursor: Option<String>,
+    ) -> Result<Option<WrittenBlock>, BlockProcessingError> {
         let triggers = block.trigger_data;
         let block = Arc::new(block.block);
         let block_ptr = block.ptr();
@@ -159,6 +261,16 @@ where
         // There are currently no other causality regions since offchain data is not supported.
         let causality_region = CausalityRegion::from_network(self.ctx.instance.network());
 
+        // Data sources that expired with an earlier block must not see the
+        // triggers of this one. The block stream does not send blocks
+        // without triggers, and the blocks in which they expired might
+        // never have been processed
+        let expired = self.ctx.instance.expired_data_sources(block_ptr.number - 1);
+        let expired = self
+            .ctx
+            .instance
+            .remove_data_sources(&expired, block_ptr.number);
+
         // Process events one after the other, passing in entity operations
         // collected previously to every new event being processed
         let mut block_state = match self
@@ -170,6 +282,9 @@ where
 
             // Some form of unknown or non-deterministic error ocurred.
             Err(MappingError::Unknown(e)) => return Err(BlockProcessingError::Unknown(e)),
+            Err(MappingError::QuotaExceeded(e)) => {
+                return Err(BlockProcessingError::QuotaExceeded(e))
+            }
             Err(MappingError::PossibleReorg(e)) => {
                 info!(logger,
                     "Possible reorg detected, retrying";
@@ -179,17 +294,19 @@ where
                 // In case of a possible reorg, we want this function to do nothing and restart the
                 // block stream so it has a chance to detect the reorg.
                 //
-                // The `state` is unchanged at this point, except for having cleared the entity cache.
+                // The `state` is unchanged at this point, except for having cleared the entity cache,
+                // and the caller puts the data sources that expired back.
                 // Losing the cache is a bit annoying but not an issue for correctness.
                 //
                 // See also b21fa73b-6453-4340-99fb-1a78ec62efb1.
-                return Ok(Action::Restart);
+                return Ok(None);
             }
         };
 
         // If new data sources have been created, and static filters are not in use, it is necessary
         // to restart the block stream with the new filters.
-        let needs_restart = block_state.has_created_data_sources() && !self.inputs.static_filters;
+        let mut needs_restart =
+            block_state.has_created_data_sources() && !self.inputs.static_filters;
 
         // This loop will:
         // 1. Instantiate created data sources.
@@ -256,11 +373,41 @@ where
                         MappingError::PossibleReorg(e) | MappingError::Unknown(e) => {
                             BlockProcessingError::Unknown(e)
                         }
+                        MappingError::QuotaExceeded(e) => BlockProcessingError::QuotaExceeded(e),
                     }
                 })?;
             }
         }
 
+        // Remove the data sources that handlers removed, after all their
+        // triggers from this block have been processed, together with the
+        // ones that expired
+        let removed = block_state.drain_removed_data_sources();
+        let mut data_sources = expired;
+        data_sources.extend(
+            self.ctx
+                .instance
+                .remove_data_sources(&removed, block_ptr.number),
+        );
+        if !data_sources.is_empty() {
+            self.remove_dynamic_data_sources(
+                &mut block_state.entity_cache,
+                data_sources,
+                block_ptr.number,
+            );
+            needs_restart = needs_restart || !self.inputs.static_filters;
+        }
+
+        if self
+            .inputs
+            .features
+            .contains(&SubgraphFeature::StrictReferences)
+        {
+            block_state
+                .check_references(&block_ptr)
+                .map_err(|e| BlockProcessingError::Unknown(e.into()))?;
+        }
+
         let has_errors = block_state.has_errors();
         let is_non_fatal_errors_active = self
             .inputs
@@ -316,6 +463,13 @@ where
                 "code" => LogCode::SubgraphSyncingFailure
             );
         }
+        let warnings = block_state.drain_warnings();
+        for warning in &warnings {
+            warn!(&logger, "Subgraph warning";
+                "warning" => &warning.message,
+                "handler" => warning.handler.as_deref().unwrap_or_default()
+            );
+        }
 
         // Transact entity operations into the store and update the
         // subgraph's block stream pointer
@@ -337,12 +491,18 @@ where
         }
 
         let BlockState {
-            deterministic_errors,
+            mut deterministic_errors,
             ..
         } = block_state;
 
         let first_error = deterministic_errors.first().cloned();
 
+        // Warnings are stored like non-fatal errors, but only if the
+        // changes whose problems they describe are kept
+        if !has_errors || is_non_fatal_errors_active {
+            deterministic_errors.extend(warnings);
+        }
+
         store
             .transact_block_operations(
                 block_ptr,
@@ -355,40 +515,17 @@ where
             .await
             .context("Failed to transact block operations")?;
 
-        // For subgraphs with `nonFatalErrors` feature disabled, we consider
-        // any error as fatal.
-        //
-        // So we do an early return to make the subgraph stop processing blocks.
-        //
-        // In this scenario the only entity that is stored/transacted is the PoI,
-        // all of the others are discarded.
-        if has_errors && !is_non_fatal_errors_active {
-            // Only the first error is reported.
-            return Err(BlockProcessingError::Deterministic(first_error.unwrap()));
-        }
-
         let elapsed = start.elapsed().as_secs_f64();
         self.metrics
             .subgraph
             .block_ops_transaction_duration
             .observe(elapsed);
 
-        // To prevent a buggy pending version from replacing a current version, if errors are
-        // present the subgraph will be unassigned.
-        if has_errors && !ENV_VARS.disable_fail_fast && !store.is_deployment_synced().await? {
-            store
-                .unassign_subgraph()
-                .map_err(|e| BlockProcessingError::Unknown(e.into()))?;
-
-            // Use `Canceled` to avoiding setting the subgraph health to failed, an error was
-            // just transacted so it will be already be set to unhealthy.
-            return Err(BlockProcessingError::Canceled);
-        }
-
-        match needs_restart {
-            true => Ok(Action::Restart),
-            false => Ok(Action::Continue),
-        }
+        Ok(Some(WrittenBlock {
+            has_errors,
+            first_error,
+            needs_restart,
+        }))
     }
 
     async fn process_triggers(
@@ -398,12 +535,31 @@ where
         triggers: Vec<C::TriggerData>,
         causality_region: &str,
     ) -> Result<BlockState<C>, MappingError> {
-        let mut block_state = BlockState::new(
-            self.inputs.store.clone(),
-            std::mem::take(&mut self.state.entity_lfu_cache),
-        );
+        let entity_lfu_cache = std::mem::take(&mut self.state.entity_lfu_cache);
+
+        if self
+            .inputs
+            .features
+            .contains(&SubgraphFeature::ParallelHandlers)
+        {
+            return self
+                .ctx
+                .instance
+                .process_triggers_in_parallel(
+                    &self.logger,
+                    block,
+                    triggers,
+                    self.inputs.store.clone(),
+                    entity_lfu_cache,
+                    proof_of_indexing,
+                    causality_region,
+                    &self.inputs.debug_fork,
+                    &self.metrics.subgraph,
+                )
+                .await;
+        }
 
-        use graph::blockchain::TriggerData;
+        let mut block_state = BlockState::new(self.inputs.store.clone(), entity_lfu_cache);
 
         for trigger in triggers {
             block_state = self
@@ -420,13 +576,7 @@ where
                     &self.metrics.subgraph,
                 )
                 .await
-                .map_err(move |mut e| {
-                    let error_context = trigger.error_context();
-                    if !error_context.is_empty() {
-                        e = e.context(error_context);
-                    }
-                    e.context("failed to process trigger".to_string())
-                })?;
+                .map_err(|e| trigger_error(e, &trigger))?;
         }
         Ok(block_state)
     }
@@ -501,6 +651,36 @@ where
         // Merge filters from data sources into the block stream builder
         self.ctx.filter.extend(data_sources.iter());
     }
+
+    fn remove_dynamic_data_sources(
+        &mut self,
+        entity_cache: &mut EntityCache,
+        data_sources: Vec<C::DataSource>,
+        block: BlockNumber,
+    ) {
+        debug!(
+            self.logger,
+            "Removing {} dynamic data source(s)",
+            data_sources.len()
+        );
+
+        for data_source in data_sources.iter() {
+            debug!(
+                self.logger,
+                "Removing data_source";
+                "name" => &data_source.name(),
+                "address" => &data_source.address().map(|address| hex::encode(address)).unwrap_or("none".to_string()),
+            );
+            entity_cache.remove_data_source(data_source, block);
+        }
+
+        // Stop asking the block stream for the triggers of the removed data
+        // sources. With static filters, the filters cover all templates
+        // anyway
+        if !self.inputs.static_filters {
+            self.ctx.filter = C::TriggerFilter::from_data_sources(self.ctx.instance.data_sources());
+        }
+    }
 }
 
 impl<C, T> SubgraphRunner<C, T>
@@ -538,6 +718,13 @@ enum Action {
     Restart,
 }
 
+/// What is left to do once a block has been written
+struct WrittenBlock {
+    has_errors: bool,
+    first_error: Option<SubgraphError>,
+    needs_restart: bool,
+}
+
 #[async_trait]
 trait StreamEventHandler<C: Blockchain> {
     async fn handle_process_block(
@@ -674,12 +861,9 @@ where
 
                 return Ok(Action::Continue);
             }
-            Err(BlockProcessingError::Canceled) => {
-                debug!(self.logger, "Subgraph block stream shut down cleanly");
-                return Ok(Action::Stop);
-            }
-
-            // Handle unexpected stream errors by marking the subgraph as failed.
+            // Handle errors according to their kind: deterministic errors fail
+            // the subgraph, non-deterministic ones mark it as failed and retry
+            // the block with backoff.
             Err(e) => {
                 // Clear entity cache when a subgraph fails.
                 //
@@ -690,22 +874,64 @@ where
                 // and be transacted incorrectly in the next run.
                 self.state.entity_lfu_cache = LfuCache::new();
 
-                self.metrics.stream.deployment_failed.set(1.0);
-
+                let kind = e.kind();
                 let message = format!("{:#}", e).replace("\n", "\t");
                 let err = anyhow!("{}, code: {}", message, LogCode::SubgraphSyncingFailure);
-                let deterministic = e.is_deterministic();
-
                 let error = SubgraphError {
                     subgraph_id: self.inputs.deployment.hash.clone(),
                     message,
                     block_ptr: Some(block_ptr),
                     handler: None,
-                    deterministic,
+                    deterministic: kind == ErrorKind::Deterministic,
                 };
 
-                match deterministic {
-                    true => {
+                match kind {
+                    ErrorKind::Canceled => {
+                        debug!(self.logger, "Subgraph block stream shut down cleanly");
+                        return Ok(Action::Stop);
+                    }
+                    ErrorKind::ConcurrentWriter => {
+                        // The deployment was reassigned and the node it is
+                        // now assigned to claimed it; that node is the one
+                        // that gets to write to it from now on
+                        crit!(
+                            self.logger,
+                            "Stopping subgraph because another node took it over: {:#}", e;
+                            "code" => LogCode::SubgraphSyncingFailure
+                        );
+                        self.ctx
+                            .instances
+                            .write()
+                            .unwrap()
+                            .remove(&self.inputs.deployment.id);
+                        return Ok(Action::Stop);
+                    }
+                    ErrorKind::QuotaExceeded => {
+                        // Retrying would only use up the resources of the node again. The
+                        // deployment stays failed until it is restarted, e.g. after the quota
+                        // was raised, and the error is cleared once it makes progress again
+                        self.metrics.stream.deployment_failed.set(1.0);
+                        self.inputs
+                            .store
+                            .fail_subgraph(error)
+                            .await
+                            .context("Failed to set subgraph status to `failed`")?;
+                        self.ctx
+                            .instances
+                            .write()
+                            .unwrap()
+                            .remove(&self.inputs.deployment.id);
+
+                        crit!(
+                            self.logger,
+                            "Stopping subgraph because it exceeded its quota: {:#}", err;
+                            "code" => LogCode::SubgraphSyncingFailure
+                        );
+                        return Ok(Action::Stop);
+                    }
+                    ErrorKind::Deterministic => {
+                        self.metrics.stream.deployment_failed.set(1.0);
+
                         // Fail subgraph:
                         // - Change status/health.
                         // - Save the error to the database.
@@ -715,9 +941,25 @@ where
                             .await
                             .context("Failed to set subgraph status to `failed`")?;
 
-                        return Err(err);
+                        // Processing the block again would fail the same
+                        // way, so the subgraph stops instead of returning
+                        // an error that would get it restarted
+                        error!(
+                            self.logger,
+                            "Subgraph failed with a deterministic error: {:#}", err
+                        );
+                        return Ok(Action::Stop);
                     }
-                    false => {
+                    ErrorKind::NonDeterministic => {
+                        self.metrics.stream.deployment_failed.set(1.0);
+
+                        // Errors such as handler timeouts, IPFS timeouts or
+                        // failing providers may well go away when the block
+                        // is processed again. The subgraph is marked as failed
+                        // so that its status shows the error, and the block
+                        // is retried with backoff; the failure is cleared
+                        // once a retry makes progress.
+                        //
                         // Shouldn't fail subgraph if it's already failed for non-deterministic
                         // reasons.
                         //
@@ -750,8 +992,7 @@ where
                             .unwrap()
                             .remove(&self.inputs.deployment.id);
 
-                        let message = format!("{:#}", e).replace("\n", "\t");
-                        error!(self.logger, "Subgraph failed with non-deterministic error: {}", message;
+                        error!(self.logger, "Subgraph failed with non-deterministic error, retrying the block: {:#}", err;
                             "attempt" => self.state.backoff.attempt,
                             "retry_delay_s" => self.state.backoff.delay().as_secs());
 
@@ -807,16 +1048,24 @@ where
             .set(subgraph_ptr.number as f64);
 
         // Revert the in-memory state:
-        // - Remove hosts for reverted dynamic data sources.
+        // - Remove hosts for reverted dynamic data sources, and restore the
+        //   hosts of data sources whose removal was reverted.
         // - Clear the entity cache.
         //
-        // Note that we do not currently revert the filters, which means the filters
-        // will be broader than necessary. This is not ideal for performance, but is not
-        // incorrect since we will discard triggers that match the filters but do not
-        // match any data sources.
-        self.ctx.instance.revert_data_sources(subgraph_ptr.number);
+        // Note that we do not currently remove reverted data sources from the
+        // filters, which means the filters will be broader than necessary. This
+        // is not ideal for performance, but is not incorrect since we will
+        // discard triggers that match the filters but do not match any data
+        // sources. Restored data sources have to be added to the filters
+        // again though, and the block stream restarted with them.
+        let restored = self.ctx.instance.revert_data_sources(subgraph_ptr.number);
         self.state.entity_lfu_cache = LfuCache::new();
 
+        if !restored.is_empty() && !self.inputs.static_filters {
+            self.ctx.filter.extend(restored.iter());
+            return Ok(Action::Restart);
+        }
+
         Ok(Action::Continue)
     }
 
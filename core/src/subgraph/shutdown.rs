@@ -0,0 +1,112 @@
+use graph::prelude::futures03::future;
+use graph::prelude::tokio::sync::{mpsc, watch, Mutex as AsyncMutex};
+use std::sync::Mutex;
+
+/// Coordinates stopping all subgraph runners when the node shuts down.
+/// Runners are asked to stop at the next block boundary, i.e., after the
+/// block they are currently processing has been handed to the store, and
+/// the instance manager waits until all of them have flushed their writes
+pub struct Shutdown {
+    requested: watch::Sender<bool>,
+    listener: watch::Receiver<bool>,
+    // Every runner holds a clone of this sender; once they are all gone,
+    // `stopped` is closed. Taken when shutdown is requested so that no new
+    // runners can start
+    running: Mutex<Option<mpsc::Sender<()>>>,
+    stopped: AsyncMutex<mpsc::Receiver<()>>,
+}
+
+impl Shutdown {
+    pub fn new() -> Self {
+        let (requested, listener) = watch::channel(false);
+        let (running, stopped) = mpsc::channel(1);
+        Shutdown {
+            requested,
+            listener,
+            running: Mutex::new(Some(running)),
+            stopped: AsyncMutex::new(stopped),
+        }
+    }
+
+    /// A listener for a runner that is about to start, or `None` if the
+    /// node is already shutting down
+    pub fn listen(&self) -> Option<ShutdownListener> {
+        self.running
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map(|running| ShutdownListener {
+                requested: self.listener.clone(),
+                _running: running.clone(),
+            })
+    }
+
+    /// Whether the node is shutting down
+    pub fn is_requested(&self) -> bool {
+        *self.listener.borrow()
+    }
+
+    /// Ask all runners to stop and wait until they have
+    pub async fn shutdown(&self) {
+        self.running.lock().unwrap().take();
+        // We hold on to `listener`, so sending can not fail
+        let _ = self.requested.send(true);
+
+        // `recv` only returns `None` once every runner has dropped its
+        // listener; nobody ever sends on the channel
+        let mut stopped = self.stopped.lock().await;
+        while stopped.recv().await.is_some() {}
+    }
+}
+
+/// What a runner holds on to while it runs. Dropping it tells the instance
+/// manager that the runner has stopped
+pub struct ShutdownListener {
+    requested: watch::Receiver<bool>,
+    _running: mpsc::Sender<()>,
+}
+
+impl ShutdownListener {
+    pub fn is_requested(&self) -> bool {
+        *self.requested.borrow()
+    }
+
+    /// Resolves once shutdown has been requested
+    pub async fn requested(&mut self) {
+        while !self.is_requested() {
+            if self.requested.changed().await.is_err() {
+                // The instance manager is gone and can't ask us to stop
+                future::pending::<()>().await;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use graph::prelude::tokio;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn waits_for_runners_to_stop() {
+        let shutdown = Shutdown::new();
+        let mut listener = shutdown.listen().unwrap();
+        assert!(!listener.is_requested());
+
+        let flushed = Arc::new(AtomicBool::new(false));
+        let flushed2 = flushed.clone();
+        tokio::spawn(async move {
+            listener.requested().await;
+            // Pretend to flush pending writes
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            flushed2.store(true, Ordering::SeqCst);
+        });
+
+        shutdown.shutdown().await;
+        assert!(flushed.load(Ordering::SeqCst));
+        assert!(shutdown.listen().is_none());
+    }
+}
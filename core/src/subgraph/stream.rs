@@ -35,6 +35,7 @@ pub async fn new_block_stream<C: Blockchain>(
             current_ptr,
             Arc::new(filter.clone()),
             inputs.unified_api_version.clone(),
+            inputs.indexing_delay,
         ),
     }
     .await?;
@@ -0,0 +1,397 @@
+use std::sync::Arc;
+
+use graph::blockchain::block_stream::{
+    BlockStream, BlockStreamBuilder, BlockStreamEvent, BufferedBlockStream,
+};
+use graph::blockchain::{Block, TriggersAdapter as _};
+use graph::prelude::ethabi::{self, Token};
+use graph::prelude::web3::types::{
+    Address, Bytes, Log, Transaction, TransactionReceipt, H2048, H256, U256, U64,
+};
+use graph::prelude::{
+    anyhow, async_trait, tokio, BlockNumber, BlockPtr, Error, EthereumBlock,
+    EthereumBlockWithCalls, LightEthereumBlock, Logger,
+};
+use graph_chain_ethereum::chain::BlockFinality;
+use graph_chain_ethereum::{Chain, TriggerFilter};
+
+#[derive(Clone)]
+enum Event {
+    Block(BlockFinality),
+    Revert(BlockPtr),
+}
+
+#[derive(Clone)]
+pub(crate) struct MockCall {
+    pub address: Address,
+    pub encoded_call: Vec<u8>,
+    pub block: BlockPtr,
+    pub return_value: Vec<u8>,
+}
+
+/// An Ethereum chain whose blocks are written by a test. Each block gets
+/// its logs from the test, and every log is put into its own transaction.
+/// Block hashes are made up from the block number and the branch of the
+/// chain that the block is on, so that blocks that replace reverted
+/// blocks have different hashes
+#[derive(Clone, Default)]
+pub struct MockChain {
+    events: Vec<Event>,
+    calls: Vec<MockCall>,
+    /// The blocks from genesis to the current head
+    branch: Vec<BlockFinality>,
+    /// How often the chain was reverted so far
+    reverts: u32,
+}
+
+impl MockChain {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a block with `logs` on top of the current head; the first block
+    /// is block 0. Returns the pointer to the new block
+    pub fn block(&mut self, logs: Vec<Log>) -> BlockPtr {
+        let number = self.branch.len() as BlockNumber;
+        let block_hash = self.hash(number, 0);
+        let parent_hash = self
+            .branch
+            .last()
+            .map(|parent| parent.ptr().hash_as_h256())
+            .unwrap_or_default();
+
+        let mut transactions = Vec::new();
+        let mut receipts = Vec::new();
+        for (index, mut log) in logs.into_iter().enumerate() {
+            let transaction_hash = self.hash(number, index as u32 + 1);
+            log.block_hash = Some(block_hash);
+            log.block_number = Some(U64::from(number));
+            log.transaction_hash = Some(transaction_hash);
+            log.transaction_index = Some(U64::from(index));
+            log.log_index = Some(U256::from(index));
+            log.transaction_log_index = Some(U256::zero());
+            log.removed = Some(false);
+
+            transactions.push(Transaction {
+                hash: transaction_hash,
+                block_hash: Some(block_hash),
+                block_number: Some(U64::from(number)),
+                transaction_index: Some(U64::from(index)),
+                from: Some(Address::zero()),
+                to: Some(log.address),
+                ..Transaction::default()
+            });
+            receipts.push(Arc::new(TransactionReceipt {
+                transaction_hash,
+                transaction_index: U64::from(index),
+                block_hash: Some(block_hash),
+                block_number: Some(U64::from(number)),
+                from: Address::zero(),
+                to: Some(log.address),
+                cumulative_gas_used: U256::zero(),
+                gas_used: Some(U256::zero()),
+                contract_address: None,
+                logs: vec![log],
+                status: Some(U64::from(1)),
+                root: None,
+                logs_bloom: H2048::zero(),
+                transaction_type: None,
+                effective_gas_price: None,
+            }));
+        }
+
+        let block = BlockFinality::NonFinal(EthereumBlockWithCalls {
+            ethereum_block: EthereumBlock {
+                block: Arc::new(LightEthereumBlock {
+                    hash: Some(block_hash),
+                    parent_hash,
+                    number: Some(U64::from(number)),
+                    timestamp: U256::from(number),
+                    transactions,
+                    ..LightEthereumBlock::default()
+                }),
+                transaction_receipts: receipts,
+            },
+            calls: Some(vec![]),
+        });
+        let ptr = block.ptr();
+        self.branch.push(block.clone());
+        self.events.push(Event::Block(block));
+        ptr
+    }
+
+    /// Make block `number` the head of the chain again. Blocks that are
+    /// added afterwards start a new branch from there
+    pub fn revert_to(&mut self, number: BlockNumber) -> Result<BlockPtr, Error> {
+        let ptr = self
+            .branch
+            .get(number as usize)
+            .map(|block| block.ptr())
+            .ok_or_else(|| anyhow!("can not revert to block {} which does not exist", number))?;
+        self.branch.truncate(number as usize + 1);
+        self.reverts += 1;
+        self.events.push(Event::Revert(ptr.clone()));
+        Ok(ptr)
+    }
+
+    /// Answer the contract call `encoded_call` to `address` at `block`
+    /// with `return_value`. Both are ABI encoded, the call including the
+    /// function selector
+    pub fn mock_call(
+        &mut self,
+        address: Address,
+        encoded_call: Vec<u8>,
+        block: BlockPtr,
+        return_value: Vec<u8>,
+    ) {
+        self.calls.push(MockCall {
+            address,
+            encoded_call,
+            block,
+            return_value,
+        });
+    }
+
+    /// The current head of the chain
+    pub fn head(&self) -> Option<BlockPtr> {
+        self.branch.last().map(|block| block.ptr())
+    }
+
+    /// Pointers to all blocks that were ever added, including the ones
+    /// that were reverted later
+    pub fn block_ptrs(&self) -> Vec<BlockPtr> {
+        self.blocks().map(|block| block.ptr()).collect()
+    }
+
+    pub(crate) fn head_block(&self) -> Option<&BlockFinality> {
+        self.branch.last()
+    }
+
+    /// All blocks that were ever added, including the ones that were
+    /// reverted later
+    pub(crate) fn blocks(&self) -> impl Iterator<Item = &BlockFinality> {
+        self.events.iter().filter_map(|event| match event {
+            Event::Block(block) => Some(block),
+            Event::Revert(_) => None,
+        })
+    }
+
+    pub(crate) fn calls(&self) -> &[MockCall] {
+        &self.calls
+    }
+
+    pub(crate) fn stream_builder(&self) -> MockStreamBuilder {
+        MockStreamBuilder {
+            events: Arc::new(self.events.clone()),
+        }
+    }
+
+    fn hash(&self, number: BlockNumber, transaction: u32) -> H256 {
+        let mut hash = [0u8; 32];
+        hash[0..4].copy_from_slice(&self.reverts.to_be_bytes());
+        hash[4..8].copy_from_slice(&transaction.to_be_bytes());
+        hash[28..32].copy_from_slice(&number.to_be_bytes());
+        H256::from(hash)
+    }
+}
+
+/// The log that a contract at `address` writes when it emits `event` with
+/// `params`. Indexed parameters must have a type whose encoding fits into a
+/// topic; logs for events with indexed strings, bytes or arrays, which
+/// are stored as their hash, have to be built by hand
+pub fn event_log(address: Address, event: &ethabi::Event, params: &[Token]) -> Result<Log, Error> {
+    if event.inputs.len() != params.len() {
+        return Err(anyhow!(
+            "event {} has {} parameters but {} were given",
+            event.name,
+            event.inputs.len(),
+            params.len()
+        ));
+    }
+
+    let mut topics = Vec::new();
+    if !event.anonymous {
+        topics.push(event.signature());
+    }
+    let mut data = Vec::new();
+    for (input, param) in event.inputs.iter().zip(params) {
+        if input.indexed {
+            let encoded = ethabi::encode(&[param.clone()]);
+            if encoded.len() != 32 {
+                return Err(anyhow!(
+                    "indexed parameter `{}` of event {} does not fit into a topic",
+                    input.name,
+                    event.name
+                ));
+            }
+            topics.push(H256::from_slice(&encoded));
+        } else {
+            data.push(param.clone());
+        }
+    }
+
+    Ok(Log {
+        address,
+        topics,
+        data: Bytes::from(ethabi::encode(&data)),
+        block_hash: None,
+        block_number: None,
+        transaction_hash: None,
+        transaction_index: None,
+        log_index: None,
+        transaction_log_index: None,
+        log_type: None,
+        removed: None,
+    })
+}
+
+/// Streams the blocks and reverts of a `MockChain` to a subgraph. A
+/// subgraph that already indexed part of the chain continues after the
+/// block it is at. Once all blocks have been sent, the stream waits like
+/// it would for a real chain to produce new blocks
+pub(crate) struct MockStreamBuilder {
+    events: Arc<Vec<Event>>,
+}
+
+#[async_trait]
+impl BlockStreamBuilder<Chain> for MockStreamBuilder {
+    async fn build(
+        &self,
+        logger: Logger,
+        adapter: Arc<graph_chain_ethereum::chain::TriggersAdapter>,
+        filter: Arc<TriggerFilter>,
+        start_blocks: Vec<BlockNumber>,
+        subgraph_current_block: Option<BlockPtr>,
+    ) -> Result<Box<dyn BlockStream<Chain>>, Error> {
+        let events = self.events.clone();
+        let start_block = start_blocks.into_iter().min().unwrap_or(0);
+        let skip = subgraph_current_block
+            .as_ref()
+            .and_then(|current| {
+                events.iter().rposition(|event| match event {
+                    Event::Block(block) => &block.ptr() == current,
+                    Event::Revert(_) => false,
+                })
+            })
+            .map_or(0, |pos| pos + 1);
+
+        let (sender, receiver) = tokio::sync::mpsc::channel(1);
+        graph::spawn(async move {
+            let mut current = subgraph_current_block;
+            for event in events.iter().skip(skip) {
+                let event = match event {
+                    Event::Block(block) => {
+                        let ptr = block.ptr();
+                        let behind = current
+                            .as_ref()
+                            .map_or(false, |current| ptr.number <= current.number);
+                        if ptr.number < start_block || behind {
+                            continue;
+                        }
+                        current = Some(ptr);
+                        adapter
+                            .triggers_in_block(&logger, block.clone(), &filter)
+                            .await
+                            .map(|block| BlockStreamEvent::ProcessBlock(block, None))
+                    }
+                    Event::Revert(ptr) => {
+                        let ahead = current
+                            .as_ref()
+                            .map_or(false, |current| current.number > ptr.number);
+                        if !ahead {
+                            continue;
+                        }
+                        current = Some(ptr.clone());
+                        Ok(BlockStreamEvent::Revert(ptr.clone(), None))
+                    }
+                };
+                if sender.send(event).await.is_err() {
+                    return;
+                }
+            }
+            sender.closed().await;
+        });
+
+        Ok(Box::new(BufferedBlockStream::new(receiver)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reverted_blocks_get_new_hashes() {
+        let mut chain = MockChain::new();
+        let b0 = chain.block(vec![]);
+        let b1 = chain.block(vec![]);
+        assert_eq!(0, b0.number);
+        assert_eq!(1, b1.number);
+        assert_eq!(
+            Some(b0.clone()),
+            chain.head_block().and_then(|block| block.parent_ptr())
+        );
+
+        assert_eq!(b0, chain.revert_to(0).unwrap());
+        assert_eq!(Some(b0.clone()), chain.head());
+        let b1_new = chain.block(vec![]);
+        assert_eq!(1, b1_new.number);
+        assert_ne!(b1.hash, b1_new.hash);
+        assert_eq!(3, chain.blocks().count());
+
+        assert!(chain.revert_to(5).is_err());
+    }
+
+    #[test]
+    fn logs_get_transactions() {
+        let event = ethabi::Event {
+            name: "Transfer".to_string(),
+            inputs: vec![
+                ethabi::EventParam {
+                    name: "from".to_string(),
+                    kind: ethabi::ParamType::Address,
+                    indexed: true,
+                },
+                ethabi::EventParam {
+                    name: "value".to_string(),
+                    kind: ethabi::ParamType::Uint(256),
+                    indexed: false,
+                },
+            ],
+            anonymous: false,
+        };
+        let address = Address::from_low_u64_be(7);
+        let log = event_log(
+            address,
+            &event,
+            &[
+                Token::Address(Address::from_low_u64_be(1)),
+                Token::Uint(U256::from(10)),
+            ],
+        )
+        .unwrap();
+        assert_eq!(2, log.topics.len());
+        assert_eq!(32, log.data.0.len());
+
+        let mut chain = MockChain::new();
+        chain.block(vec![log.clone(), log]);
+        let block = match chain.head_block().unwrap() {
+            BlockFinality::NonFinal(block) => block.ethereum_block.clone(),
+            BlockFinality::Final(_) => unreachable!("mock blocks are never final"),
+        };
+        assert_eq!(2, block.block.transactions.len());
+        for (tx, receipt) in block
+            .block
+            .transactions
+            .iter()
+            .zip(&block.transaction_receipts)
+        {
+            assert_eq!(tx.hash, receipt.transaction_hash);
+            let log = &receipt.logs[0];
+            assert_eq!(Some(tx.hash), log.transaction_hash);
+            assert!(block.block.transaction_for_log(log).is_some());
+        }
+
+        assert!(event_log(address, &event, &[]).is_err());
+    }
+}
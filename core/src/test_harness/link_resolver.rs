@@ -0,0 +1,144 @@
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use anyhow::Context;
+use async_trait::async_trait;
+use graph::components::link_resolver::{JsonStreamValue, JsonValueStream};
+use graph::prelude::{
+    serde_json, serde_yaml, Error, Link, LinkResolver as LinkResolverTrait, Logger,
+};
+
+/// Resolves links to files on the local filesystem; relative links are
+/// taken to be relative to the directory of the manifest
+#[derive(Clone, Debug)]
+pub struct FileLinkResolver {
+    base: PathBuf,
+}
+
+impl FileLinkResolver {
+    pub fn new(base: impl Into<PathBuf>) -> Self {
+        FileLinkResolver { base: base.into() }
+    }
+
+    fn path(&self, link: &Link) -> PathBuf {
+        let path = Path::new(&link.link);
+        if path.is_absolute() {
+            path.to_path_buf()
+        } else {
+            self.base.join(path)
+        }
+    }
+}
+
+#[async_trait]
+impl LinkResolverTrait for FileLinkResolver {
+    fn with_timeout(&self, _timeout: Duration) -> Box<dyn LinkResolverTrait> {
+        Box::new(self.clone())
+    }
+
+    fn with_retries(&self) -> Box<dyn LinkResolverTrait> {
+        Box::new(self.clone())
+    }
+
+    async fn cat(&self, _logger: &Logger, link: &Link) -> Result<Vec<u8>, Error> {
+        let path = self.path(link);
+        std::fs::read(&path).with_context(|| format!("failed to read `{}`", path.display()))
+    }
+
+    async fn json_stream(&self, logger: &Logger, link: &Link) -> Result<JsonValueStream, Error> {
+        let text = String::from_utf8(self.cat(logger, link).await?)?;
+        let values: Vec<_> = text
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty())
+            .map(|(line, text)| {
+                serde_json::from_str(text)
+                    .map(|value| JsonStreamValue { value, line })
+                    .map_err(Error::from)
+            })
+            .collect();
+        Ok(Box::pin(futures::stream::iter(values)))
+    }
+}
+
+/// Read the manifest that `graph build` wrote to `path`. The manifest
+/// refers to its files as `file: <path>`; turn them into the links that
+/// manifests uploaded to IPFS have so that the manifest resolves through
+/// a `FileLinkResolver`
+pub fn load_manifest(path: &Path) -> Result<serde_yaml::Mapping, Error> {
+    let text = std::fs::read_to_string(path)
+        .with_context(|| format!("failed to read manifest `{}`", path.display()))?;
+    let mut manifest: serde_yaml::Value = serde_yaml::from_str(&text)
+        .with_context(|| format!("invalid manifest `{}`", path.display()))?;
+    rewrite_file_links(&mut manifest);
+    match manifest {
+        serde_yaml::Value::Mapping(manifest) => Ok(manifest),
+        _ => Err(anyhow::anyhow!(
+            "manifest `{}` is not a YAML mapping",
+            path.display()
+        )),
+    }
+}
+
+fn rewrite_file_links(value: &mut serde_yaml::Value) {
+    use serde_yaml::Value;
+
+    match value {
+        Value::Mapping(map) => {
+            for (key, value) in map.iter_mut() {
+                let file = match (key.as_str(), &*value) {
+                    (Some("file"), Value::String(file)) => Some(file.clone()),
+                    _ => None,
+                };
+                match file {
+                    Some(file) => {
+                        let mut link = serde_yaml::Mapping::new();
+                        link.insert(Value::from("/"), Value::from(file));
+                        *value = Value::Mapping(link);
+                    }
+                    None => rewrite_file_links(value),
+                }
+            }
+        }
+        Value::Sequence(values) => values.iter_mut().for_each(rewrite_file_links),
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rewrites_file_links() {
+        let mut manifest: serde_yaml::Value = serde_yaml::from_str(
+            "
+schema:
+  file: ./schema.graphql
+dataSources:
+  - mapping:
+      file: ./mapping.wasm
+      abis:
+        - name: Token
+          file: ./abis/Token.json
+",
+        )
+        .unwrap();
+        rewrite_file_links(&mut manifest);
+
+        let expected: serde_yaml::Value = serde_yaml::from_str(
+            "
+schema:
+  file: { '/': ./schema.graphql }
+dataSources:
+  - mapping:
+      file: { '/': ./mapping.wasm }
+      abis:
+        - name: Token
+          file: { '/': ./abis/Token.json }
+",
+        )
+        .unwrap();
+        assert_eq!(expected, manifest);
+    }
+}
@@ -0,0 +1,368 @@
+//! Run a subgraph's handlers end-to-end without Postgres, IPFS or an
+//! Ethereum node. The subgraph is loaded from the files that `graph build`
+//! produces, indexes the blocks of a `MockChain`, and writes to an
+//! in-memory SQLite store that tests can inspect afterwards:
+//!
+//! ```ignore
+//! let mut chain = MockChain::new();
+//! chain.block(vec![event_log(token, &transfer, &[from, to, value])?]);
+//! let harness = TestHarness::start("build/subgraph.yaml", chain).await?;
+//! harness.sync().await?;
+//! assert!(harness.get("Transfer", &id)?.is_some());
+//! ```
+//!
+//! Contract calls from mappings are answered from the chain's call cache
+//! only; see `MockChain::mock_call`. Chains that go through reorgs can be
+//! built from a script with `reorged_chain`
+mod chain;
+mod link_resolver;
+mod reorg;
+
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use graph::blockchain::{BlockHash, BlockchainMap, ChainIdentifier};
+use graph::cheap_clone::CheapClone;
+use graph::components::store::{
+    AttributeNames, ChainStore as _, DeploymentLocator, EntityCollection, EntityKey, EntityQuery,
+    EntityRange, EntityType, QueryStoreManager as _, WritableStore,
+};
+use graph::data::graphql::DocumentExt as _;
+use graph::data::query::QueryTarget;
+use graph::data::subgraph::schema::{DeploymentCreate, SubgraphHealth, POI_OBJECT};
+use graph::data::subgraph::status;
+use graph::firehose::FirehoseEndpoints;
+use graph::prelude::{
+    anyhow, r, BlockNumber, BlockPtr, DeploymentHash, Entity, Error, EthereumCallCache as _,
+    LinkResolver, LoggerFactory, NodeId, Schema, StatusStore, StoreError,
+    SubgraphInstanceManager as _, SubgraphManifest, SubgraphName, SubgraphStore as _,
+    SubgraphVersionSwitchingMode, ENV_VARS,
+};
+use graph::prometheus::Registry;
+use graph::slog::Logger;
+use graph::url::Url;
+use graph_chain_ethereum::{self as ethereum, EthereumNetworks, NodeCapabilities};
+use graph_store_sqlite::{
+    BlockStore, ChainHeadUpdateListener, Database, Store, SubgraphStore, SubscriptionManager,
+};
+
+use crate::{MetricsRegistry, SubgraphInstanceManager};
+
+pub use self::chain::{event_log, MockChain};
+pub use self::link_resolver::{load_manifest, FileLinkResolver};
+pub use self::reorg::{parse_script, reorged_chain, ReorgStep};
+
+const DEPLOYMENT_HASH: &str = "QmTestHarness";
+const SUBGRAPH_NAME: &str = "test-harness";
+const SYNC_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// One subgraph indexing a `MockChain`. Dropping the harness stops the
+/// subgraph
+pub struct TestHarness {
+    logger: Logger,
+    store: Arc<Store>,
+    writable: Arc<dyn WritableStore>,
+    instance_manager: Arc<SubgraphInstanceManager<SubgraphStore>>,
+    deployment: DeploymentLocator,
+    schema: Schema,
+    head: Option<BlockPtr>,
+}
+
+impl TestHarness {
+    /// Deploy the subgraph whose built manifest is at `manifest` and start
+    /// indexing `chain` with it
+    pub async fn start(manifest: impl AsRef<Path>, chain: MockChain) -> Result<Self, Error> {
+        let manifest = manifest.as_ref();
+        let logger = graph::log::logger(false);
+        let logger_factory = LoggerFactory::new(logger.clone(), None);
+        let registry = Arc::new(MetricsRegistry::new(
+            logger.clone(),
+            Arc::new(Registry::new()),
+        ));
+        let node_id = NodeId::new("test_harness").unwrap();
+        let hash = DeploymentHash::new(DEPLOYMENT_HASH).unwrap();
+
+        let link_resolver: Arc<dyn LinkResolver> = Arc::new(FileLinkResolver::new(
+            manifest.parent().unwrap_or_else(|| Path::new(".")),
+        ));
+        let raw = load_manifest(manifest)?;
+        let resolved = SubgraphManifest::<ethereum::Chain>::resolve_from_raw(
+            hash.clone(),
+            raw.clone(),
+            &link_resolver,
+            &logger,
+            ENV_VARS.max_spec_version.clone(),
+        )
+        .await?;
+        let network = resolved.network_name();
+
+        let db = Arc::new(Database::in_memory()?);
+        let subscription_manager = Arc::new(SubscriptionManager::new());
+        let listener = Arc::new(ChainHeadUpdateListener::new());
+        let subgraph_store = Arc::new(SubgraphStore::new(db.cheap_clone(), subscription_manager));
+        let block_store = Arc::new(BlockStore::new(db, listener.cheap_clone()));
+        let store = Arc::new(Store::new(subgraph_store.clone(), block_store.clone()));
+
+        let genesis_block_hash = chain
+            .blocks()
+            .next()
+            .map(|block| graph::blockchain::Block::hash(block))
+            .unwrap_or_else(BlockHash::zero);
+        let chain_store = block_store.add_chain(
+            &network,
+            &ChainIdentifier {
+                net_version: "1".to_string(),
+                genesis_block_hash,
+            },
+        )?;
+        for block in chain.blocks() {
+            chain_store.upsert_block(Arc::new(block.clone())).await?;
+        }
+        for call in chain.calls() {
+            chain_store.set_call(
+                call.address,
+                &call.encoded_call,
+                call.block.clone(),
+                &call.return_value,
+            )?;
+        }
+        if let Some(head) = chain.head_block() {
+            chain_store
+                .cheap_clone()
+                .set_chain_head(Arc::new(head.clone()), String::new())
+                .await?;
+        }
+
+        // Mappings get an adapter that is not connected to anything so that
+        // contract calls that are not in the call cache fail
+        let transport = ethereum::Transport::new_rpc(
+            Url::parse("http://127.0.0.1:1").unwrap(),
+            Default::default(),
+        );
+        let adapter = ethereum::EthereumAdapter::new(
+            logger.clone(),
+            "test-harness".to_string(),
+            "http://127.0.0.1:1",
+            transport,
+            Arc::new(ethereum::ProviderEthRpcMetrics::new(registry.clone())),
+            true,
+            Default::default(),
+        )
+        .await;
+        let mut networks = EthereumNetworks::new();
+        networks.insert(
+            network.clone(),
+            NodeCapabilities {
+                archive: true,
+                traces: true,
+            },
+            Arc::new(adapter),
+        );
+        let eth_adapters = networks.networks.remove(&network).unwrap();
+
+        let eth_chain = ethereum::Chain::new(
+            logger_factory.clone(),
+            network.clone(),
+            node_id.clone(),
+            registry.clone(),
+            chain_store.cheap_clone(),
+            chain_store.cheap_clone(),
+            FirehoseEndpoints::new(),
+            eth_adapters,
+            listener,
+            ENV_VARS.reorg_threshold,
+            false,
+        )
+        .with_block_stream_builder(Arc::new(chain.stream_builder()));
+        let mut chains = BlockchainMap::new();
+        chains.insert::<ethereum::Chain>(network.clone(), Arc::new(eth_chain));
+
+        let name = SubgraphName::new(SUBGRAPH_NAME).unwrap();
+        subgraph_store.create_subgraph(name.clone())?;
+        let deployment = subgraph_store.create_subgraph_deployment(
+            name,
+            &resolved.schema,
+            DeploymentCreate::new(&resolved, None),
+            node_id,
+            network,
+            SubgraphVersionSwitchingMode::Instant,
+        )?;
+        let writable = subgraph_store
+            .cheap_clone()
+            .writable(logger.clone(), deployment.id)
+            .await?;
+
+        let instance_manager = Arc::new(SubgraphInstanceManager::new(
+            &logger_factory,
+            subgraph_store,
+            Arc::new(chains),
+            registry,
+            link_resolver,
+            false,
+        ));
+        instance_manager
+            .cheap_clone()
+            .start_subgraph(deployment.clone(), raw, None)
+            .await;
+
+        Ok(TestHarness {
+            logger,
+            store,
+            writable,
+            instance_manager,
+            deployment,
+            schema: resolved.schema,
+            head: chain.head(),
+        })
+    }
+
+    /// Wait until the subgraph has indexed the head of the chain. Fails if
+    /// the subgraph fails, or if it takes longer than a minute
+    pub async fn sync(&self) -> Result<(), Error> {
+        match self.head.clone() {
+            Some(head) => self.wait_for(head).await,
+            None => Ok(()),
+        }
+    }
+
+    /// Wait until the subgraph has indexed `block`
+    pub async fn wait_for(&self, block: BlockPtr) -> Result<(), Error> {
+        let started = Instant::now();
+        loop {
+            if self.writable.block_ptr().await.as_ref() == Some(&block) {
+                return Ok(());
+            }
+            if self.writable.health(&self.deployment.hash).await? == SubgraphHealth::Failed {
+                let message = self
+                    .status()?
+                    .fatal_error
+                    .map(|error| error.message)
+                    .unwrap_or_default();
+                return Err(anyhow!("subgraph failed: {}", message));
+            }
+            if started.elapsed() > SYNC_TIMEOUT {
+                return Err(anyhow!(
+                    "subgraph did not reach block {} within {}s",
+                    block,
+                    SYNC_TIMEOUT.as_secs()
+                ));
+            }
+            graph::prelude::tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+    }
+
+    /// Look up the entity `entity_type` with `id` as of the block the
+    /// subgraph is at
+    pub fn get(&self, entity_type: &str, id: &str) -> Result<Option<Entity>, StoreError> {
+        self.writable.get(&EntityKey::data(
+            self.deployment.hash.clone(),
+            entity_type.to_string(),
+            id.to_string(),
+        ))
+    }
+
+    /// All entities as of `block`, keyed by their type and id. Comparing
+    /// these for every block between two runs of a subgraph shows whether
+    /// they produced the same block ranges
+    pub async fn entities_at(
+        &self,
+        block: BlockNumber,
+    ) -> Result<BTreeMap<(String, String), BTreeMap<String, r::Value>>, Error> {
+        let mut entities = BTreeMap::new();
+        for object_type in self.schema.document.get_object_type_definitions() {
+            if !object_type
+                .directives
+                .iter()
+                .any(|dir| dir.name == "entity")
+            {
+                continue;
+            }
+            let entity_type = EntityType::new(object_type.name.clone());
+            for (id, entity) in self.values_at(entity_type, block).await? {
+                entities.insert((object_type.name.clone(), id), entity);
+            }
+        }
+        Ok(entities)
+    }
+
+    /// The digest of the proof of indexing of each causality region as of
+    /// `block`. Unlike the proof of indexing itself, they do not depend on
+    /// the hash of `block`, and two runs that handled the same events have
+    /// the same digests even if their blocks are on different branches of
+    /// the chain
+    pub async fn poi_digests_at(
+        &self,
+        block: BlockNumber,
+    ) -> Result<BTreeMap<String, r::Value>, Error> {
+        Ok(self
+            .values_at(POI_OBJECT.clone(), block)
+            .await?
+            .into_iter()
+            .map(|(region, mut poi)| (region, poi.remove("digest").unwrap_or(r::Value::Null)))
+            .collect())
+    }
+
+    /// All entities of type `entity_type` as of `block`, keyed by their id
+    async fn values_at(
+        &self,
+        entity_type: EntityType,
+        block: BlockNumber,
+    ) -> Result<Vec<(String, BTreeMap<String, r::Value>)>, Error> {
+        let store = self
+            .store
+            .query_store(QueryTarget::Deployment(self.deployment.hash.clone()), false)
+            .await?;
+        let collection = EntityCollection::All(vec![(entity_type.clone(), AttributeNames::All)]);
+        let query =
+            EntityQuery::new(self.deployment.hash.clone(), block, collection).range(EntityRange {
+                first: None,
+                skip: 0,
+            });
+        store
+            .find_query_values(query)?
+            .into_iter()
+            .map(|entity| {
+                // Ids that are not strings, like `Bytes` ids, are compared
+                // by their text form
+                let id = match entity.get("id") {
+                    Some(r::Value::String(id)) => id.clone(),
+                    Some(id) => id.to_string(),
+                    None => return Err(anyhow!("{} entity without an id", entity_type)),
+                };
+                Ok((id, entity))
+            })
+            .collect()
+    }
+
+    /// The indexing status of the subgraph
+    pub fn status(&self) -> Result<status::Info, StoreError> {
+        let filter = status::Filter::Deployments(vec![self.deployment.hash.to_string()]);
+        self.store
+            .status(filter)?
+            .into_iter()
+            .next()
+            .ok_or_else(|| StoreError::Unknown(anyhow!("no status for {}", self.deployment.hash)))
+    }
+
+    /// The store that the subgraph writes to, for running GraphQL queries
+    /// against it
+    pub fn store(&self) -> Arc<Store> {
+        self.store.clone()
+    }
+
+    pub fn deployment(&self) -> &DeploymentLocator {
+        &self.deployment
+    }
+
+    pub fn logger(&self) -> &Logger {
+        &self.logger
+    }
+}
+
+impl Drop for TestHarness {
+    fn drop(&mut self) {
+        self.instance_manager.stop_subgraph(self.deployment.clone());
+    }
+}
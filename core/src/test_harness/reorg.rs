@@ -0,0 +1,142 @@
+//! Scripted reorgs. A script like `advance 5, revert 2, advance 4` walks
+//! through the blocks of a chain: `advance` adds the next blocks, `revert`
+//! removes the last ones again, and the blocks that are added after a
+//! revert are the same blocks as before but on a new branch of the chain.
+//! Whatever blocks the script does not reach are added at the end, so
+//! that a subgraph that indexes the resulting chain has to end up with
+//! the same entities as one that indexes the blocks without any reorgs
+use std::fmt;
+use std::str::FromStr;
+
+use graph::prelude::web3::types::Log;
+use graph::prelude::{anyhow, BlockNumber, Error};
+
+use super::MockChain;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ReorgStep {
+    /// Add this many blocks
+    Advance(usize),
+    /// Revert this many blocks
+    Revert(usize),
+}
+
+impl FromStr for ReorgStep {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut words = s.split_whitespace();
+        let (step, count) = match (words.next(), words.next(), words.next()) {
+            (Some(step), Some(count), None) => (step, count),
+            _ => return Err(anyhow!("invalid reorg step `{}`", s.trim())),
+        };
+        let count = count
+            .parse()
+            .map_err(|_| anyhow!("invalid number of blocks in reorg step `{}`", s.trim()))?;
+        match step {
+            "advance" => Ok(ReorgStep::Advance(count)),
+            "revert" => Ok(ReorgStep::Revert(count)),
+            _ => Err(anyhow!(
+                "reorg steps must be `advance <n>` or `revert <n>`, not `{}`",
+                s.trim()
+            )),
+        }
+    }
+}
+
+impl fmt::Display for ReorgStep {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReorgStep::Advance(count) => write!(f, "advance {}", count),
+            ReorgStep::Revert(count) => write!(f, "revert {}", count),
+        }
+    }
+}
+
+/// Parse a comma-separated list of reorg steps
+pub fn parse_script(script: &str) -> Result<Vec<ReorgStep>, Error> {
+    script
+        .split(',')
+        .filter(|step| !step.trim().is_empty())
+        .map(ReorgStep::from_str)
+        .collect()
+}
+
+/// Build a chain that ends with one block for each entry of `blocks`, with
+/// the logs of that entry, and that gets there by following `script`
+pub fn reorged_chain(blocks: &[Vec<Log>], script: &[ReorgStep]) -> Result<MockChain, Error> {
+    let mut chain = MockChain::new();
+    let mut next = 0;
+    for step in script {
+        match *step {
+            ReorgStep::Advance(count) => {
+                if next + count > blocks.len() {
+                    return Err(anyhow!(
+                        "`{}` goes past the last of the {} blocks",
+                        step,
+                        blocks.len()
+                    ));
+                }
+                for logs in &blocks[next..next + count] {
+                    chain.block(logs.clone());
+                }
+                next += count;
+            }
+            ReorgStep::Revert(count) => {
+                if count >= next {
+                    return Err(anyhow!(
+                        "`{}` goes back before the first block, only {} blocks were added",
+                        step,
+                        next
+                    ));
+                }
+                next -= count;
+                chain.revert_to((next - 1) as BlockNumber)?;
+            }
+        }
+    }
+    for logs in &blocks[next..] {
+        chain.block(logs.clone());
+    }
+    Ok(chain)
+}
+
+#[cfg(test)]
+mod tests {
+    use graph::prelude::BlockNumber;
+
+    use super::{parse_script, reorged_chain, ReorgStep};
+
+    #[test]
+    fn parses_scripts() {
+        assert_eq!(
+            vec![
+                ReorgStep::Advance(5),
+                ReorgStep::Revert(2),
+                ReorgStep::Advance(1)
+            ],
+            parse_script("advance 5, revert 2,advance 1").unwrap()
+        );
+        assert!(parse_script("advance").is_err());
+        assert!(parse_script("skip 3").is_err());
+        assert!(parse_script("revert -1").is_err());
+    }
+
+    #[test]
+    fn builds_reorged_chains() {
+        let blocks = vec![vec![]; 4];
+        let script = parse_script("advance 3, revert 2, advance 1").unwrap();
+        let chain = reorged_chain(&blocks, &script).unwrap();
+        let head = chain.head().unwrap();
+        assert_eq!(3 as BlockNumber, head.number);
+        // Blocks 1 and 2 were added twice
+        assert_eq!(6, chain.blocks().count());
+
+        let straight = reorged_chain(&blocks, &[]).unwrap();
+        assert_eq!(head.number, straight.head().unwrap().number);
+        assert_ne!(head.hash, straight.head().unwrap().hash);
+
+        assert!(reorged_chain(&blocks, &parse_script("advance 5").unwrap()).is_err());
+        assert!(reorged_chain(&blocks, &parse_script("advance 2, revert 2").unwrap()).is_err());
+    }
+}
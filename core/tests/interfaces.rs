@@ -267,7 +267,12 @@ async fn follow_interface_reference_invalid() {
                 "Cannot query field \"parent\" on type \"Legged\"."
             );
         }
-        QueryError::ExecutionError(QueryExecutionError::UnknownField(_, type_name, field_name)) => {
+        QueryError::ExecutionError(QueryExecutionError::UnknownField(
+            _,
+            type_name,
+            field_name,
+            _,
+        )) => {
             assert_eq!(type_name, "Legged");
             assert_eq!(field_name, "parent");
         }
@@ -578,7 +583,12 @@ async fn invalid_fragment() {
                 "Cannot query field \"name\" on type \"Legged\"."
             );
         }
-        QueryError::ExecutionError(QueryExecutionError::UnknownField(_, type_name, field_name)) => {
+        QueryError::ExecutionError(QueryExecutionError::UnknownField(
+            _,
+            type_name,
+            field_name,
+            _,
+        )) => {
             assert_eq!(type_name, "Legged");
             assert_eq!(field_name, "name");
         }
@@ -0,0 +1,135 @@
+//! Tests for setting up deployments through the registrar the way the
+//! JSON-RPC server does
+
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use graph::blockchain::BlockchainMap;
+use graph::components::link_resolver::{JsonValueStream, LinkResolver as LinkResolverTrait};
+use graph::components::store::DeploymentLocator;
+use graph::data::subgraph::local::content_hash;
+use graph::prelude::{
+    anyhow, async_trait, serde_json, serde_yaml, BlockNumber, DeploymentHash, Error, Link, Logger,
+    LoggerFactory, NodeId, SubgraphAssignmentProvider, SubgraphAssignmentProviderError,
+    SubgraphRegistrar as _, SubgraphStore as _, SubgraphVersionSwitchingMode,
+};
+use graph_core::{StoredLinkResolver, SubgraphRegistrar};
+use test_store::*;
+
+const MANIFEST: &str = "
+specVersion: 0.0.4
+parameters:
+  factory:
+  startBlock:
+    default: 10
+dataSources:
+  - source:
+      address: \"{{ factory }}\"
+      startBlock: {{startBlock}}
+";
+
+/// Files can only come from the store
+#[derive(Debug)]
+struct NoIpfs;
+
+#[async_trait]
+impl LinkResolverTrait for NoIpfs {
+    fn with_timeout(&self, _timeout: Duration) -> Box<dyn LinkResolverTrait> {
+        Box::new(NoIpfs)
+    }
+
+    fn with_retries(&self) -> Box<dyn LinkResolverTrait> {
+        Box::new(NoIpfs)
+    }
+
+    async fn cat(&self, _logger: &Logger, link: &Link) -> Result<Vec<u8>, Error> {
+        Err(anyhow!("IPFS is not available for {}", link.link))
+    }
+
+    async fn json_stream(&self, _logger: &Logger, _link: &Link) -> Result<JsonValueStream, Error> {
+        unimplemented!()
+    }
+}
+
+struct NoProvider;
+
+#[async_trait]
+impl SubgraphAssignmentProvider for NoProvider {
+    async fn start(
+        &self,
+        _deployment: DeploymentLocator,
+        _stop_block: Option<BlockNumber>,
+    ) -> Result<(), SubgraphAssignmentProviderError> {
+        unimplemented!()
+    }
+
+    async fn stop(
+        &self,
+        _deployment: DeploymentLocator,
+    ) -> Result<(), SubgraphAssignmentProviderError> {
+        unimplemented!()
+    }
+}
+
+#[test]
+fn instantiate_manifest() {
+    run_test_sequentially(|store| async move {
+        let subgraph_store = store.subgraph_store();
+        let registry = subgraph_store.file_registry();
+        let registrar = SubgraphRegistrar::new(
+            &LoggerFactory::new(LOGGER.clone(), None),
+            Arc::new(StoredLinkResolver::new(Arc::new(NoIpfs), registry.clone())),
+            Arc::new(NoProvider),
+            subgraph_store,
+            SUBSCRIPTION_MANAGER.clone(),
+            Arc::new(BlockchainMap::new()),
+            NodeId::new("test").unwrap(),
+            SubgraphVersionSwitchingMode::Instant,
+        );
+
+        let hash = DeploymentHash::new(content_hash(MANIFEST.as_bytes())).unwrap();
+        registry
+            .put_file(hash.as_str(), MANIFEST.as_bytes())
+            .unwrap();
+
+        // The parameters as the `subgraph_deploy` method receives them; the
+        // quote and newline in the address must not end up as YAML
+        let values: BTreeMap<String, serde_json::Value> = serde_json::from_str(
+            r#"{ "factory": "0xabc\"\n      startBlock: 99", "startBlock": 42 }"#,
+        )
+        .unwrap();
+        let instance = registrar
+            .instantiate_manifest(hash.clone(), &values)
+            .await
+            .unwrap();
+        assert_ne!(hash, instance);
+
+        let manifest = registry.get_file(instance.as_str()).unwrap().unwrap();
+        let manifest: serde_yaml::Value = serde_yaml::from_slice(&manifest).unwrap();
+        assert!(manifest.get("parameters").is_none());
+        let source = &manifest["dataSources"][0]["source"];
+        assert_eq!(
+            Some("0xabc\"\n      startBlock: 99"),
+            source["address"].as_str()
+        );
+        assert_eq!(Some(42), source["startBlock"].as_u64());
+
+        // The same values lead to the same deployment
+        assert_eq!(
+            instance,
+            registrar
+                .instantiate_manifest(hash.clone(), &values)
+                .await
+                .unwrap()
+        );
+
+        let err = registrar
+            .instantiate_manifest(hash, &BTreeMap::new())
+            .await
+            .unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("no value for the parameter `factory`"));
+    })
+}
@@ -80,8 +80,18 @@ pub trait BlockStream<C: Blockchain>:
 {
 }
 
-pub trait BlockStreamBuilder<C: Blockchain> {
-    fn build() -> Box<dyn BlockStream<C>>;
+/// Builds the block streams of a chain in place of the ones that read from
+/// the chain's providers. Tests use this to feed subgraphs scripted blocks
+#[async_trait]
+pub trait BlockStreamBuilder<C: Blockchain>: Send + Sync {
+    async fn build(
+        &self,
+        logger: Logger,
+        adapter: Arc<C::TriggersAdapter>,
+        filter: Arc<C::TriggerFilter>,
+        start_blocks: Vec<BlockNumber>,
+        subgraph_current_block: Option<BlockPtr>,
+    ) -> Result<Box<dyn BlockStream<C>>, Error>;
 }
 
 pub type FirehoseCursor = Option<String>;
@@ -8,6 +8,7 @@ use async_trait::async_trait;
 use core::fmt;
 use serde::Deserialize;
 use std::{convert::TryFrom, sync::Arc};
+use web3::types::H256;
 
 use super::{block_stream, HostFn, IngestorError, TriggerWithHandler};
 
@@ -27,7 +28,7 @@ pub struct MockBlock {
 
 impl Block for MockBlock {
     fn ptr(&self) -> BlockPtr {
-        todo!()
+        BlockPtr::from((H256::from_low_u64_be(self.number), self.number))
     }
 
     fn parent_ptr(&self) -> Option<BlockPtr> {
@@ -88,6 +89,10 @@ impl<C: Blockchain> DataSource<C> for MockDataSource {
         todo!()
     }
 
+    fn entities(&self) -> &[String] {
+        todo!()
+    }
+
     fn match_and_decode(
         &self,
         _trigger: &C::TriggerData,
@@ -135,6 +140,10 @@ impl<C: Blockchain> UnresolvedDataSource<C> for MockUnresolvedDataSource {
 pub struct MockDataSourceTemplate;
 
 impl<C: Blockchain> DataSourceTemplate<C> for MockDataSourceTemplate {
+    fn validate(&self) -> Vec<anyhow::Error> {
+        todo!()
+    }
+
     fn api_version(&self) -> semver::Version {
         todo!()
     }
@@ -206,7 +215,7 @@ pub struct MockTriggerData;
 
 impl TriggerData for MockTriggerData {
     fn error_context(&self) -> String {
-        todo!()
+        String::new()
     }
 }
 
@@ -313,6 +322,7 @@ impl Blockchain for MockBlockchain {
         _subgraph_current_block: Option<BlockPtr>,
         _filter: std::sync::Arc<Self::TriggerFilter>,
         _unified_api_version: crate::data::subgraph::UnifiedMappingApiVersion,
+        _indexing_delay: crate::components::store::BlockNumber,
     ) -> Result<Box<dyn block_stream::BlockStream<Self>>, anyhow::Error> {
         todo!()
     }
@@ -340,6 +350,14 @@ impl Blockchain for MockBlockchain {
         todo!()
     }
 
+    async fn detect_start_block(
+        &self,
+        _logger: &slog::Logger,
+        _data_source: &Self::DataSource,
+    ) -> Result<Option<crate::components::store::BlockNumber>, anyhow::Error> {
+        todo!()
+    }
+
     fn runtime_adapter(&self) -> std::sync::Arc<Self::RuntimeAdapter> {
         todo!()
     }
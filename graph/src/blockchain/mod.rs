@@ -61,6 +61,12 @@ pub trait Block: Send + Sync {
         self.parent_ptr().map(|ptr| ptr.hash)
     }
 
+    /// When the block was produced, in seconds since the epoch, for chains
+    /// whose blocks have a timestamp
+    fn timestamp(&self) -> Option<u64> {
+        None
+    }
+
     /// The data that should be stored for this block in the `ChainStore`
     fn data(&self) -> Result<serde_json::Value, serde_json::Error> {
         Ok(serde_json::Value::Null)
@@ -114,6 +120,8 @@ pub trait Blockchain: Debug + Sized + Send + Sync + Unpin + 'static {
         unified_api_version: UnifiedMappingApiVersion,
     ) -> Result<Box<dyn BlockStream<Self>>, Error>;
 
+    /// Create a block stream that polls the chain store for new blocks.
+    /// The stream stays `indexing_delay` blocks behind the chain head
     async fn new_polling_block_stream(
         &self,
         deployment: DeploymentLocator,
@@ -121,6 +129,7 @@ pub trait Blockchain: Debug + Sized + Send + Sync + Unpin + 'static {
         subgraph_current_block: Option<BlockPtr>,
         filter: Arc<Self::TriggerFilter>,
         unified_api_version: UnifiedMappingApiVersion,
+        indexing_delay: BlockNumber,
     ) -> Result<Box<dyn BlockStream<Self>>, Error>;
 
     fn chain_store(&self) -> Arc<dyn ChainStore>;
@@ -131,6 +140,15 @@ pub trait Blockchain: Debug + Sized + Send + Sync + Unpin + 'static {
         number: BlockNumber,
     ) -> Result<BlockPtr, IngestorError>;
 
+    /// The block from which `data_source`, which does not set a start
+    /// block, needs to be indexed, if the chain can tell. For data sources
+    /// of a contract, that is the block in which the contract was created
+    async fn detect_start_block(
+        &self,
+        logger: &Logger,
+        data_source: &Self::DataSource,
+    ) -> Result<Option<BlockNumber>, Error>;
+
     fn runtime_adapter(&self) -> Arc<Self::RuntimeAdapter>;
 
     fn is_firehose_supported(&self) -> bool;
@@ -196,6 +214,15 @@ pub trait DataSource<C: Blockchain>:
     fn api_version(&self) -> semver::Version;
     fn runtime(&self) -> &[u8];
 
+    /// The entity types that the mapping of this data source declares
+    fn entities(&self) -> &[String];
+
+    /// The handlers of the mapping that declare which entity types they
+    /// may access themselves, together with those entity types
+    fn handler_entities(&self) -> Vec<(&str, &[String])> {
+        vec![]
+    }
+
     /// Checks if `trigger` matches this data source, and if so decodes it into a `MappingTrigger`.
     /// A return of `Ok(None)` mean the trigger does not match.
     ///
@@ -242,6 +269,11 @@ pub trait DataSourceTemplate<C: Blockchain>: Send + Sync + Debug {
     fn api_version(&self) -> semver::Version;
     fn runtime(&self) -> &[u8];
     fn name(&self) -> &str;
+
+    /// Perform the checks on the template that `DataSource::validate`
+    /// performs on data sources that can be done before the template is
+    /// instantiated
+    fn validate(&self) -> Vec<Error>;
 }
 
 #[async_trait]
@@ -382,6 +414,11 @@ impl BlockchainMap {
             .downcast()
             .map_err(|_| anyhow!("unable to downcast, wrong type for blockchain {}", C::KIND))
     }
+
+    /// Whether there is a chain of `kind` for `network`
+    pub fn contains(&self, kind: BlockchainKind, network: &str) -> bool {
+        self.0.contains_key(&(kind, network.to_string()))
+    }
 }
 
 pub struct TriggerWithHandler<C: Blockchain> {
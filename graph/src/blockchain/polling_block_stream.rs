@@ -87,6 +87,8 @@ where
     // This is not really a block number, but the (unsigned) difference
     // between two block numbers
     reorg_threshold: BlockNumber,
+    // Not a BlockNumber, but the number of blocks to stay behind the head
+    indexing_delay: BlockNumber,
     filter: Arc<C::TriggerFilter>,
     start_blocks: Vec<BlockNumber>,
     logger: Logger,
@@ -108,6 +110,7 @@ impl<C: Blockchain> Clone for PollingBlockStreamContext<C> {
             node_id: self.node_id.clone(),
             subgraph_id: self.subgraph_id.clone(),
             reorg_threshold: self.reorg_threshold,
+            indexing_delay: self.indexing_delay,
             filter: self.filter.clone(),
             start_blocks: self.start_blocks.clone(),
             logger: self.logger.clone(),
@@ -154,6 +157,7 @@ where
         filter: Arc<C::TriggerFilter>,
         start_blocks: Vec<BlockNumber>,
         reorg_threshold: BlockNumber,
+        indexing_delay: BlockNumber,
         logger: Logger,
         max_block_range_size: BlockNumber,
         target_triggers_per_block_range: u64,
@@ -171,6 +175,7 @@ where
                 node_id,
                 subgraph_id,
                 reorg_threshold,
+                indexing_delay,
                 logger,
                 filter,
                 start_blocks,
@@ -247,12 +252,19 @@ where
         // Make sure not to include genesis in the reorg threshold.
         let reorg_threshold = ctx.reorg_threshold.min(head_ptr.number);
 
+        // The subgraph does not go past this block; with an indexing delay
+        // at least as large as the reorg threshold, it never gets close
+        // enough to the head to see a reorg
+        let head_limit = head_ptr.number - ctx.indexing_delay;
+
         // Only continue if the subgraph block ptr is behind the head block ptr.
         // subgraph_ptr > head_ptr shouldn't happen, but if it does, it's safest to just stop.
         if let Some(ptr) = &subgraph_ptr {
-            if ptr.number >= head_ptr.number {
+            if ptr.number >= head_limit {
                 return Ok(ReconciliationStep::Done);
             }
+        } else if head_limit < 0 {
+            return Ok(ReconciliationStep::Done);
         }
 
         // Subgraph ptr is behind head ptr.
@@ -347,7 +359,10 @@ where
             // End either just before the the next data source start_block or just
             // prior to the reorg threshold. It isn't safe to go farther than the
             // reorg threshold due to race conditions.
-            let to_limit = cmp::min(head_ptr.number - reorg_threshold, next_start_block - 1);
+            let to_limit = cmp::min(
+                head_ptr.number - cmp::max(reorg_threshold, ctx.indexing_delay),
+                next_start_block - 1,
+            );
 
             // Calculate the range size according to the target number of triggers,
             // respecting the global maximum and also not increasing too
@@ -206,6 +206,24 @@ impl TryFrom<(&str, i64)> for BlockPtr {
     }
 }
 
+/// Parse a block pointer that is written as `BLOCK_HASH:BLOCK_NUMBER`
+impl FromStr for BlockPtr {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (hash, number) = s
+            .split_once(':')
+            .ok_or_else(|| anyhow!("block `{}` is not of the form BLOCK_HASH:BLOCK_NUMBER", s))?;
+        let number = number
+            .parse::<BlockNumber>()
+            .with_context(|| format!("invalid block number in `{}`", s))?;
+        if number < 0 {
+            return Err(anyhow!("block number in `{}` is negative", s));
+        }
+        BlockPtr::try_from((hash, number as i64))
+    }
+}
+
 impl TryFrom<(&[u8], i64)> for BlockPtr {
     type Error = anyhow::Error;
 
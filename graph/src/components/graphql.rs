@@ -3,7 +3,7 @@ use futures::prelude::*;
 use crate::data::query::{CacheStatus, Query, QueryTarget};
 use crate::data::subscription::{Subscription, SubscriptionError, SubscriptionResult};
 use crate::data::{graphql::effort::LoadManager, query::QueryResults};
-use crate::prelude::DeploymentHash;
+use crate::prelude::{serde_json, BlockNumber, DeploymentHash, QueryExecutionError, Schema};
 
 use async_trait::async_trait;
 use std::sync::Arc;
@@ -42,6 +42,22 @@ pub trait GraphQlRunner: Send + Sync + 'static {
         target: QueryTarget,
     ) -> Result<SubscriptionResult, SubscriptionError>;
 
+    /// Runs a SQL query against the entities of a deployment as of
+    /// `block`, or as of the latest block the deployment has processed if
+    /// `block` is `None`, and returns the resulting rows.
+    async fn run_sql(
+        self: Arc<Self>,
+        sql: String,
+        block: Option<BlockNumber>,
+        target: QueryTarget,
+    ) -> Result<Vec<serde_json::Value>, QueryExecutionError>;
+
+    /// The schema of the deployment for `target` as its author wrote it
+    async fn input_schema(
+        self: Arc<Self>,
+        target: QueryTarget,
+    ) -> Result<Arc<Schema>, QueryExecutionError>;
+
     fn load_manager(&self) -> Arc<LoadManager>;
 }
 
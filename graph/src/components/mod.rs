@@ -55,6 +55,9 @@ pub mod link_resolver;
 /// Components dealing with collecting metrics
 pub mod metrics;
 
+/// Notifications about the lifecycle of deployments
+pub mod webhooks;
+
 /// A component that receives events of type `T`.
 pub trait EventConsumer<E> {
     /// Get the event sink.
@@ -0,0 +1,149 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+
+use async_trait::async_trait;
+use serde::Serialize;
+
+use crate::prelude::{BlockNumber, DeploymentHash, StoreError};
+
+/// How a deployment is doing, as reported by `/health/<deployment>`
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct DeploymentHealth {
+    pub deployment: String,
+    /// One of `healthy`, `unhealthy`, or `failed`, or `unavailable` if the
+    /// database that stores the deployment can not be reached
+    pub health: String,
+    pub synced: bool,
+    pub latest_block: Option<BlockNumber>,
+    pub chain_head_block: Option<BlockNumber>,
+    /// How far the deployment is behind the head of its chain; `None` if
+    /// either block is not known yet
+    pub blocks_behind: Option<BlockNumber>,
+}
+
+/// Whether the node can do its work, as reported by `/ready`
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct NodeReadiness {
+    pub ready: bool,
+    /// Whether we could connect to the database of each shard
+    pub stores: BTreeMap<String, bool>,
+    /// The chains for which a block ingestor is running
+    pub ingestors: Vec<String>,
+    /// The chains for which this node is the one in the cluster that
+    /// ingests blocks; on the other nodes, the ingestors for these chains
+    /// stand by to take over
+    pub leading: Vec<String>,
+}
+
+/// Answers the health and readiness checks of the HTTP server
+#[async_trait]
+pub trait HealthCheck: Send + Sync + 'static {
+    /// The health of `deployment`, or `None` if there is no such deployment
+    async fn deployment_health(
+        &self,
+        deployment: DeploymentHash,
+    ) -> Result<Option<DeploymentHealth>, StoreError>;
+
+    /// Whether this node is ready. That requires that all stores can be
+    /// reached, and, on nodes that ingest blocks, that at least one block
+    /// ingestor is running
+    async fn readiness(&self) -> NodeReadiness;
+}
+
+/// Keeps track of the block ingestors that are running in this process.
+/// Ingestors hold on to the guard that `start` returns for as long as they
+/// run, and to the guard that `lead` returns for as long as they are the
+/// ones that ingest blocks for their chain in the cluster
+#[derive(Debug, Default)]
+pub struct RunningIngestors {
+    chains: Mutex<HashMap<String, usize>>,
+    leading: Mutex<HashMap<String, usize>>,
+}
+
+impl RunningIngestors {
+    pub fn start(self: &Arc<Self>, chain: &str) -> IngestorGuard {
+        self.guard(chain, false)
+    }
+
+    pub fn lead(self: &Arc<Self>, chain: &str) -> IngestorGuard {
+        self.guard(chain, true)
+    }
+
+    fn guard(self: &Arc<Self>, chain: &str, leading: bool) -> IngestorGuard {
+        *self
+            .counts(leading)
+            .lock()
+            .unwrap()
+            .entry(chain.to_string())
+            .or_default() += 1;
+        IngestorGuard {
+            ingestors: self.clone(),
+            chain: chain.to_string(),
+            leading,
+        }
+    }
+
+    fn counts(&self, leading: bool) -> &Mutex<HashMap<String, usize>> {
+        if leading {
+            &self.leading
+        } else {
+            &self.chains
+        }
+    }
+
+    /// The chains for which at least one ingestor is running
+    pub fn chains(&self) -> Vec<String> {
+        Self::sorted(&self.chains)
+    }
+
+    /// The chains for which this process ingests blocks for the cluster
+    pub fn leading(&self) -> Vec<String> {
+        Self::sorted(&self.leading)
+    }
+
+    fn sorted(counts: &Mutex<HashMap<String, usize>>) -> Vec<String> {
+        let mut chains: Vec<_> = counts.lock().unwrap().keys().cloned().collect();
+        chains.sort();
+        chains
+    }
+}
+
+pub struct IngestorGuard {
+    ingestors: Arc<RunningIngestors>,
+    chain: String,
+    leading: bool,
+}
+
+impl Drop for IngestorGuard {
+    fn drop(&mut self) {
+        let mut chains = self.ingestors.counts(self.leading).lock().unwrap();
+        if let Some(count) = chains.get_mut(&self.chain) {
+            *count -= 1;
+            if *count == 0 {
+                chains.remove(&self.chain);
+            }
+        }
+    }
+}
+
+#[test]
+fn tracks_running_ingestors() {
+    let ingestors = Arc::new(RunningIngestors::default());
+    let mainnet = ingestors.start("mainnet");
+    let mainnet2 = ingestors.start("mainnet");
+    let near = ingestors.start("near");
+    assert_eq!(vec!["mainnet", "near"], ingestors.chains());
+
+    drop(near);
+    drop(mainnet);
+    assert_eq!(vec!["mainnet"], ingestors.chains());
+    drop(mainnet2);
+    assert!(ingestors.chains().is_empty());
+
+    let leading = ingestors.lead("mainnet");
+    let _standby = ingestors.start("near");
+    assert_eq!(vec!["mainnet"], ingestors.leading());
+    assert_eq!(vec!["near"], ingestors.chains());
+    drop(leading);
+    assert!(ingestors.leading().is_empty());
+}
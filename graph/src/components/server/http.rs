@@ -0,0 +1,295 @@
+//! Connection settings and draining that all HTTP servers share. Servers
+//! are bound with [`bind`], so that they are configured from the
+//! `GRAPH_HTTP_*` environment variables, and run with
+//! [`shutdown_signal`] as their graceful shutdown signal. When the node
+//! shuts down, [`drain`] makes all servers stop accepting connections and
+//! waits until the requests they are serving have been answered. Servers
+//! that are not built on [`bind`] hold an [`OpenConnection`] for each
+//! connection or request they serve to make [`drain`] wait for them
+use std::io;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+
+use futures03::future::BoxFuture;
+use futures03::FutureExt;
+use hyper::server::accept::Accept;
+use hyper::server::conn::{AddrIncoming, AddrStream};
+use hyper::server::Builder;
+use hyper::Server;
+use lazy_static::lazy_static;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::sync::{mpsc, watch, OwnedSemaphorePermit, Semaphore};
+
+use super::tls::{MaybeTlsStream, Protocol};
+use crate::env::ENV_VARS;
+
+lazy_static! {
+    static ref DRAIN: Drain = Drain::new();
+}
+
+/// Whether the servers are draining, and the connections that are still
+/// open
+struct Drain {
+    draining: (watch::Sender<bool>, watch::Receiver<bool>),
+    /// Every open connection holds a clone of this sender; once the
+    /// original has been dropped, the receiver sees the channel closing
+    /// when the last connection is closed
+    open: (
+        Mutex<Option<mpsc::Sender<()>>>,
+        Mutex<Option<mpsc::Receiver<()>>>,
+    ),
+}
+
+impl Drain {
+    fn new() -> Self {
+        let (sender, receiver) = mpsc::channel(1);
+        Drain {
+            draining: watch::channel(false),
+            open: (Mutex::new(Some(sender)), Mutex::new(Some(receiver))),
+        }
+    }
+
+    fn open_connection(&self) -> OpenConnection {
+        OpenConnection(self.open.0.lock().unwrap().clone())
+    }
+
+    fn is_draining(&self) -> bool {
+        *self.draining.1.borrow()
+    }
+
+    fn shutdown_signal(&self) -> impl std::future::Future<Output = ()> + Send + 'static {
+        let mut draining = self.draining.1.clone();
+        async move {
+            while !*draining.borrow() {
+                if draining.changed().await.is_err() {
+                    return;
+                }
+            }
+        }
+    }
+
+    async fn drain(&self) {
+        // The receiver is always there since we never drop it
+        let _ = self.draining.0.send(true);
+        self.open.0.lock().unwrap().take();
+        let receiver = self.open.1.lock().unwrap().take();
+        if let Some(mut receiver) = receiver {
+            while receiver.recv().await.is_some() {}
+        }
+    }
+}
+
+/// Makes [`drain`] wait until it is dropped. Connections that are opened
+/// once the servers are draining do not hold up draining
+pub struct OpenConnection(Option<mpsc::Sender<()>>);
+
+/// Register a connection, or a request, that [`drain`] has to wait for
+pub fn open_connection() -> OpenConnection {
+    DRAIN.open_connection()
+}
+
+/// Whether the node has started shutting down
+pub fn is_draining() -> bool {
+    DRAIN.is_draining()
+}
+
+/// Bind a server to `addr` with the settings from the environment
+pub fn bind(addr: &SocketAddr) -> Result<Builder<Incoming>, hyper::Error> {
+    bind_with(addr, false)
+}
+
+/// Like [`bind`], but the server terminates TLS if that is enabled; see
+/// the [`tls`](super::tls) module
+pub fn bind_tls(addr: &SocketAddr) -> Result<Builder<Incoming>, hyper::Error> {
+    bind_with(addr, true)
+}
+
+fn bind_with(addr: &SocketAddr, tls: bool) -> Result<Builder<Incoming>, hyper::Error> {
+    let mut incoming = AddrIncoming::bind(addr)?;
+    incoming.set_keepalive(ENV_VARS.http_keep_alive_interval);
+    let incoming = Incoming {
+        inner: incoming,
+        limit: ENV_VARS
+            .http_max_connections
+            .map(|max| Arc::new(Semaphore::new(max))),
+        acquiring: None,
+        permit: None,
+        tls,
+    };
+
+    Ok(Server::builder(incoming)
+        .http1_keepalive(ENV_VARS.http_keep_alive)
+        .http2_keep_alive_interval(ENV_VARS.http_keep_alive_interval)
+        .http2_keep_alive_timeout(ENV_VARS.http_keep_alive_timeout)
+        .http2_max_concurrent_streams(ENV_VARS.http2_max_concurrent_streams))
+}
+
+/// A future that resolves once the node starts shutting down
+pub fn shutdown_signal() -> impl std::future::Future<Output = ()> + Send + 'static {
+    DRAIN.shutdown_signal()
+}
+
+/// Make all servers stop accepting connections and wait until all open
+/// connections have been closed. Calling this more than once does not
+/// wait again
+pub async fn drain() {
+    DRAIN.drain().await
+}
+
+/// The connections that a server accepts. There are at most
+/// `GRAPH_HTTP_MAX_CONNECTIONS` of them open at the same time
+pub struct Incoming {
+    inner: AddrIncoming,
+    limit: Option<Arc<Semaphore>>,
+    acquiring: Option<BoxFuture<'static, OwnedSemaphorePermit>>,
+    permit: Option<OwnedSemaphorePermit>,
+    tls: bool,
+}
+
+impl Accept for Incoming {
+    type Conn = Connection;
+    type Error = io::Error;
+
+    fn poll_accept(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Self::Conn, Self::Error>>> {
+        let this = self.get_mut();
+
+        // Wait for a free slot before accepting the next connection
+        if let (Some(limit), None) = (&this.limit, &this.permit) {
+            let limit = limit.clone();
+            let acquiring = this.acquiring.get_or_insert_with(|| {
+                async move {
+                    limit
+                        .acquire_owned()
+                        .await
+                        .expect("the connection semaphore is never closed")
+                }
+                .boxed()
+            });
+            match acquiring.poll_unpin(cx) {
+                Poll::Ready(permit) => {
+                    this.acquiring = None;
+                    this.permit = Some(permit);
+                }
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+
+        match Pin::new(&mut this.inner).poll_accept(cx) {
+            Poll::Ready(Some(Ok(stream))) => {
+                let open = open_connection();
+                let remote_addr = stream.remote_addr();
+                let stream = if this.tls {
+                    MaybeTlsStream::new(stream, Protocol::Http)
+                } else {
+                    MaybeTlsStream::Plain(stream)
+                };
+                Poll::Ready(Some(Ok(Connection {
+                    stream,
+                    remote_addr,
+                    _permit: this.permit.take(),
+                    _open: open,
+                })))
+            }
+            Poll::Ready(Some(Err(e))) => Poll::Ready(Some(Err(e))),
+            Poll::Ready(None) => Poll::Ready(None),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+/// A connection accepted by a server. Dropping it frees its slot
+pub struct Connection {
+    stream: MaybeTlsStream<AddrStream>,
+    remote_addr: SocketAddr,
+    _permit: Option<OwnedSemaphorePermit>,
+    _open: OpenConnection,
+}
+
+impl Connection {
+    pub fn remote_addr(&self) -> SocketAddr {
+        self.remote_addr
+    }
+}
+
+impl AsyncRead for Connection {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().stream).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for Connection {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.get_mut().stream).poll_write(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().stream).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().stream).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::Drain;
+
+    #[tokio::test]
+    async fn drain_signals_shutdown() {
+        let drain = Drain::new();
+        let signal = tokio::spawn(drain.shutdown_signal());
+        tokio::time::timeout(Duration::from_secs(5), drain.drain())
+            .await
+            .expect("draining without connections finishes right away");
+        tokio::time::timeout(Duration::from_secs(5), signal)
+            .await
+            .expect("servers are told to shut down")
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn drain_waits_for_open_connections() {
+        let drain = Drain::new();
+        let first = drain.open_connection();
+        let second = drain.open_connection();
+
+        let draining = drain.drain();
+        tokio::pin!(draining);
+        assert!(
+            tokio::time::timeout(Duration::from_millis(100), &mut draining)
+                .await
+                .is_err(),
+            "draining waits while connections are open"
+        );
+        assert!(drain.is_draining());
+
+        // Connections that are opened while draining do not count
+        let _late = drain.open_connection();
+        drop(first);
+        assert!(
+            tokio::time::timeout(Duration::from_millis(100), &mut draining)
+                .await
+                .is_err()
+        );
+        drop(second);
+        tokio::time::timeout(Duration::from_secs(5), draining)
+            .await
+            .expect("draining finishes once all connections are closed");
+    }
+}
@@ -12,3 +12,12 @@ pub mod index_node;
 
 /// Components for the Prometheus metrics server.
 pub mod metrics;
+
+/// Health and readiness checks for the HTTP server.
+pub mod health;
+
+/// Settings and connection draining shared by the HTTP servers.
+pub mod http;
+
+/// TLS termination for the query and subscription servers.
+pub mod tls;
@@ -0,0 +1,451 @@
+//! TLS termination for the GraphQL and WebSocket servers. The certificate
+//! and key are read from the files named by `GRAPH_TLS_CERT` and
+//! `GRAPH_TLS_KEY` when the node starts, and again whenever it receives
+//! `SIGHUP`; connections that were accepted before a reload keep using the
+//! old certificate. Clients have `GRAPH_TLS_HANDSHAKE_TIMEOUT` to finish the
+//! handshake, so that connections that never do can not use up the
+//! connection limit of a server
+use std::fs::File;
+use std::future::Future;
+use std::io::{self, BufReader};
+use std::pin::Pin;
+use std::sync::{Arc, RwLock};
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use anyhow::{anyhow, Error};
+use futures03::ready;
+use lazy_static::lazy_static;
+use slog::{error, info, Logger};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::time::Sleep;
+use tokio_rustls::rustls::internal::pemfile;
+use tokio_rustls::rustls::{NoClientAuth, ServerConfig};
+use tokio_rustls::server::TlsStream;
+use tokio_rustls::{Accept, TlsAcceptor};
+
+use crate::env::ENV_VARS;
+
+lazy_static! {
+    static ref ACCEPTORS: RwLock<Option<Acceptors>> = RwLock::new(None);
+}
+
+/// The protocol a server speaks, which determines what we offer to
+/// clients during ALPN
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Protocol {
+    /// HTTP/2 or HTTP/1.1
+    Http,
+    /// WebSockets, which need HTTP/1.1
+    WebSocket,
+}
+
+#[derive(Clone)]
+struct Acceptors {
+    http: TlsAcceptor,
+    ws: TlsAcceptor,
+}
+
+impl Acceptors {
+    fn load(cert_path: &str, key_path: &str) -> Result<Self, Error> {
+        let certs = pemfile::certs(&mut BufReader::new(File::open(cert_path)?))
+            .map_err(|()| anyhow!("invalid certificate in {}", cert_path))?;
+        if certs.is_empty() {
+            return Err(anyhow!("no certificate in {}", cert_path));
+        }
+        let invalid_key = |()| anyhow!("invalid private key in {}", key_path);
+        let mut keys = pemfile::pkcs8_private_keys(&mut BufReader::new(File::open(key_path)?))
+            .map_err(invalid_key)?;
+        if keys.is_empty() {
+            keys = pemfile::rsa_private_keys(&mut BufReader::new(File::open(key_path)?))
+                .map_err(invalid_key)?;
+        }
+        let key = keys
+            .into_iter()
+            .next()
+            .ok_or_else(|| anyhow!("no private key in {}", key_path))?;
+
+        let config = |protocols: &[&[u8]]| -> Result<TlsAcceptor, Error> {
+            let mut config = ServerConfig::new(NoClientAuth::new());
+            config.set_single_cert(certs.clone(), key.clone())?;
+            config.set_protocols(
+                &protocols
+                    .iter()
+                    .map(|protocol| protocol.to_vec())
+                    .collect::<Vec<_>>(),
+            );
+            Ok(TlsAcceptor::from(Arc::new(config)))
+        };
+        Ok(Acceptors {
+            http: config(&[b"h2", b"http/1.1"])?,
+            ws: config(&[b"http/1.1"])?,
+        })
+    }
+
+    fn acceptor(&self, protocol: Protocol) -> TlsAcceptor {
+        match protocol {
+            Protocol::Http => self.http.clone(),
+            Protocol::WebSocket => self.ws.clone(),
+        }
+    }
+}
+
+/// Load the certificate and key if `GRAPH_TLS_CERT` and `GRAPH_TLS_KEY`
+/// are set, and reload them on `SIGHUP`. Servers only terminate TLS after
+/// this has been called
+pub fn init(logger: &Logger) -> Result<(), Error> {
+    let (cert_path, key_path) = match (&ENV_VARS.tls_cert, &ENV_VARS.tls_key) {
+        (Some(cert_path), Some(key_path)) => (cert_path.clone(), key_path.clone()),
+        (None, None) => return Ok(()),
+        _ => {
+            return Err(anyhow!(
+                "GRAPH_TLS_CERT and GRAPH_TLS_KEY must either both be set or both be unset"
+            ))
+        }
+    };
+    watch(logger, cert_path, key_path)
+}
+
+/// Load the certificate and key from `cert_path` and `key_path`, and load
+/// them again whenever the node receives `SIGHUP`
+fn watch(logger: &Logger, cert_path: String, key_path: String) -> Result<(), Error> {
+    *ACCEPTORS.write().unwrap() = Some(Acceptors::load(&cert_path, &key_path)?);
+
+    let logger = logger.clone();
+    let mut sighup = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup())?;
+    crate::spawn(async move {
+        while sighup.recv().await.is_some() {
+            match Acceptors::load(&cert_path, &key_path) {
+                Ok(acceptors) => {
+                    *ACCEPTORS.write().unwrap() = Some(acceptors);
+                    info!(logger, "Reloaded TLS certificate"; "cert" => &cert_path);
+                }
+                Err(e) => error!(logger, "Failed to reload TLS certificate, keeping the old one";
+                                 "cert" => &cert_path, "error" => e.to_string()),
+            }
+        }
+    });
+    Ok(())
+}
+
+/// Whether servers terminate TLS
+pub fn is_enabled() -> bool {
+    ACCEPTORS.read().unwrap().is_some()
+}
+
+/// The scheme for URLs of servers that speak `scheme` in plain text, e.g.
+/// `https` for `http` if TLS is enabled
+pub fn scheme(scheme: &str) -> String {
+    if is_enabled() {
+        format!("{}s", scheme)
+    } else {
+        scheme.to_string()
+    }
+}
+
+/// A connection that is encrypted if TLS is enabled. The TLS handshake
+/// happens when the connection is first read from or written to, and
+/// fails if it does not finish before the deadline
+pub enum MaybeTlsStream<S> {
+    Plain(S),
+    Handshaking(Pin<Box<Accept<S>>>, Pin<Box<Sleep>>),
+    Tls(Box<TlsStream<S>>),
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> MaybeTlsStream<S> {
+    pub fn new(stream: S, protocol: Protocol) -> Self {
+        let acceptor = ACCEPTORS
+            .read()
+            .unwrap()
+            .as_ref()
+            .map(|acceptors| acceptors.acceptor(protocol));
+        Self::with_acceptor(stream, acceptor, ENV_VARS.tls_handshake_timeout)
+    }
+
+    fn with_acceptor(stream: S, acceptor: Option<TlsAcceptor>, timeout: Duration) -> Self {
+        match acceptor {
+            None => MaybeTlsStream::Plain(stream),
+            Some(acceptor) => MaybeTlsStream::Handshaking(
+                Box::pin(acceptor.accept(stream)),
+                Box::pin(tokio::time::sleep(timeout)),
+            ),
+        }
+    }
+
+    fn poll_handshake(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        if let MaybeTlsStream::Handshaking(accept, deadline) = self {
+            let stream = match accept.as_mut().poll(cx) {
+                Poll::Ready(stream) => stream?,
+                Poll::Pending => {
+                    ready!(deadline.as_mut().poll(cx));
+                    return Poll::Ready(Err(io::Error::new(
+                        io::ErrorKind::TimedOut,
+                        "the TLS handshake did not finish in time",
+                    )));
+                }
+            };
+            *self = MaybeTlsStream::Tls(Box::new(stream));
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> AsyncRead for MaybeTlsStream<S> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        ready!(this.poll_handshake(cx))?;
+        match this {
+            MaybeTlsStream::Plain(stream) => Pin::new(stream).poll_read(cx, buf),
+            MaybeTlsStream::Tls(stream) => Pin::new(stream.as_mut()).poll_read(cx, buf),
+            MaybeTlsStream::Handshaking(..) => unreachable!("the handshake has finished"),
+        }
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> AsyncWrite for MaybeTlsStream<S> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        ready!(this.poll_handshake(cx))?;
+        match this {
+            MaybeTlsStream::Plain(stream) => Pin::new(stream).poll_write(cx, buf),
+            MaybeTlsStream::Tls(stream) => Pin::new(stream.as_mut()).poll_write(cx, buf),
+            MaybeTlsStream::Handshaking(..) => unreachable!("the handshake has finished"),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        ready!(this.poll_handshake(cx))?;
+        match this {
+            MaybeTlsStream::Plain(stream) => Pin::new(stream).poll_flush(cx),
+            MaybeTlsStream::Tls(stream) => Pin::new(stream.as_mut()).poll_flush(cx),
+            MaybeTlsStream::Handshaking(..) => unreachable!("the handshake has finished"),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        ready!(this.poll_handshake(cx))?;
+        match this {
+            MaybeTlsStream::Plain(stream) => Pin::new(stream).poll_shutdown(cx),
+            MaybeTlsStream::Tls(stream) => Pin::new(stream.as_mut()).poll_shutdown(cx),
+            MaybeTlsStream::Handshaking(..) => unreachable!("the handshake has finished"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    //! The certificates in `test-certs` are signed by the CA in `ca.pem`
+    //! and valid for `localhost` until 2126. `one.key` is a PKCS8 key and
+    //! `two.key` an RSA key
+    use std::process::Command;
+
+    use slog::{o, Discard};
+    use tokio::io::{duplex, AsyncReadExt, AsyncWriteExt, DuplexStream};
+    use tokio_rustls::client::TlsStream as ClientTlsStream;
+    use tokio_rustls::rustls::{ClientConfig, Session};
+    use tokio_rustls::webpki::DNSNameRef;
+    use tokio_rustls::TlsConnector;
+
+    use super::*;
+
+    const CA: &str = include_str!("test-certs/ca.pem");
+    const ONE: (&str, &str) = (
+        include_str!("test-certs/one.pem"),
+        include_str!("test-certs/one.key"),
+    );
+    const TWO: (&str, &str) = (
+        include_str!("test-certs/two.pem"),
+        include_str!("test-certs/two.key"),
+    );
+    const TIMEOUT: Duration = Duration::from_secs(10);
+    const H2_OR_HTTP1: &[&[u8]] = &[b"h2", b"http/1.1"];
+    const HTTP1: &[&[u8]] = &[b"http/1.1"];
+
+    fn fixture(name: &str) -> String {
+        format!(
+            "{}/src/components/server/test-certs/{}",
+            env!("CARGO_MANIFEST_DIR"),
+            name
+        )
+    }
+
+    fn der(cert: &str) -> Vec<u8> {
+        pemfile::certs(&mut cert.as_bytes()).unwrap()[0].0.clone()
+    }
+
+    async fn connect(
+        stream: DuplexStream,
+        protocols: &[&[u8]],
+    ) -> io::Result<ClientTlsStream<DuplexStream>> {
+        let mut config = ClientConfig::new();
+        config.root_store.add_pem_file(&mut CA.as_bytes()).unwrap();
+        config.set_protocols(
+            &protocols
+                .iter()
+                .map(|protocol| protocol.to_vec())
+                .collect::<Vec<_>>(),
+        );
+        let localhost = DNSNameRef::try_from_ascii_str("localhost").unwrap();
+        TlsConnector::from(Arc::new(config))
+            .connect(localhost, stream)
+            .await
+    }
+
+    /// Send the first four bytes the client sends back to it
+    async fn echo(mut server: MaybeTlsStream<DuplexStream>) -> io::Result<()> {
+        let mut buf = [0u8; 4];
+        server.read_exact(&mut buf).await?;
+        server.write_all(&buf).await?;
+        server.flush().await
+    }
+
+    async fn ping<S: AsyncRead + AsyncWrite + Unpin>(client: &mut S) {
+        client.write_all(b"ping").await.unwrap();
+        client.flush().await.unwrap();
+        let mut buf = [0u8; 4];
+        client.read_exact(&mut buf).await.unwrap();
+        assert_eq!(b"ping", &buf);
+    }
+
+    /// Connect to the server end that `accept` sets up, exchange some data,
+    /// and return the protocol that was negotiated and the certificate the
+    /// server presented
+    async fn handshake<F>(accept: F, protocols: &[&[u8]]) -> (Option<Vec<u8>>, Vec<u8>)
+    where
+        F: FnOnce(DuplexStream) -> MaybeTlsStream<DuplexStream>,
+    {
+        let (client, server) = duplex(16 * 1024);
+        let server = tokio::spawn(echo(accept(server)));
+        let mut client = connect(client, protocols).await.unwrap();
+        ping(&mut client).await;
+        server.await.unwrap().unwrap();
+
+        let (_, session) = client.get_ref();
+        (
+            session
+                .get_alpn_protocol()
+                .map(|protocol| protocol.to_vec()),
+            session.get_peer_certificates().unwrap()[0].0.clone(),
+        )
+    }
+
+    #[test]
+    fn load_certificates() {
+        assert!(Acceptors::load(&fixture("one.pem"), &fixture("one.key")).is_ok());
+        assert!(Acceptors::load(&fixture("two.pem"), &fixture("two.key")).is_ok());
+
+        let err = |cert: &str, key: &str| {
+            Acceptors::load(&fixture(cert), &fixture(key))
+                .err()
+                .unwrap()
+                .to_string()
+        };
+        assert!(err("one.key", "one.key").starts_with("no certificate in"));
+        assert!(err("one.pem", "one.pem").starts_with("no private key in"));
+        assert!(Acceptors::load(&fixture("missing.pem"), &fixture("one.key")).is_err());
+    }
+
+    #[tokio::test]
+    async fn plain_streams() {
+        let (mut client, server) = duplex(1024);
+        let server = MaybeTlsStream::with_acceptor(server, None, TIMEOUT);
+        assert!(matches!(server, MaybeTlsStream::Plain(_)));
+        let server = tokio::spawn(echo(server));
+        ping(&mut client).await;
+        server.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn tls_streams() {
+        let acceptors = Acceptors::load(&fixture("one.pem"), &fixture("one.key")).unwrap();
+
+        // The GraphQL server prefers HTTP/2, the WebSocket server only
+        // speaks HTTP/1.1
+        let http = acceptors.acceptor(Protocol::Http);
+        let (protocol, cert) = handshake(
+            |stream| MaybeTlsStream::with_acceptor(stream, Some(http), TIMEOUT),
+            H2_OR_HTTP1,
+        )
+        .await;
+        assert_eq!(Some(b"h2".to_vec()), protocol);
+        assert_eq!(der(ONE.0), cert);
+
+        let ws = acceptors.acceptor(Protocol::WebSocket);
+        let (protocol, _) = handshake(
+            |stream| MaybeTlsStream::with_acceptor(stream, Some(ws), TIMEOUT),
+            H2_OR_HTTP1,
+        )
+        .await;
+        assert_eq!(Some(b"http/1.1".to_vec()), protocol);
+    }
+
+    #[tokio::test]
+    async fn handshake_timeout() {
+        let acceptors = Acceptors::load(&fixture("one.pem"), &fixture("one.key")).unwrap();
+
+        // A client that connects but never starts the handshake
+        let (_client, server) = duplex(1024);
+        let mut server = MaybeTlsStream::with_acceptor(
+            server,
+            Some(acceptors.acceptor(Protocol::Http)),
+            Duration::from_millis(100),
+        );
+        let err = tokio::time::timeout(TIMEOUT, server.read(&mut [0u8; 4]))
+            .await
+            .expect("the handshake times out")
+            .unwrap_err();
+        assert_eq!(io::ErrorKind::TimedOut, err.kind());
+    }
+
+    #[tokio::test]
+    async fn reload_on_sighup() {
+        let dir = std::env::temp_dir().join(format!("tls-reload-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let (cert_path, key_path) = (dir.join("cert.pem"), dir.join("key.pem"));
+        let install = |(cert, key): (&str, &str)| {
+            std::fs::write(&cert_path, cert).unwrap();
+            std::fs::write(&key_path, key).unwrap();
+        };
+        let served = || handshake(|stream| MaybeTlsStream::new(stream, Protocol::Http), HTTP1);
+
+        install(ONE);
+        watch(
+            &Logger::root(Discard, o!()),
+            cert_path.to_str().unwrap().to_string(),
+            key_path.to_str().unwrap().to_string(),
+        )
+        .unwrap();
+        assert!(is_enabled());
+        assert_eq!("https", scheme("http"));
+        assert_eq!(der(ONE.0), served().await.1);
+
+        install(TWO);
+        let status = Command::new("kill")
+            .arg("-HUP")
+            .arg(std::process::id().to_string())
+            .status()
+            .unwrap();
+        assert!(status.success());
+        let mut cert = served().await.1;
+        for _ in 0..50 {
+            if cert == der(TWO.0) {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            cert = served().await.1;
+        }
+        assert_eq!(der(TWO.0), cert);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,7 @@
+/// The version of the set of traits that a storage backend implements:
+/// `SubgraphStore`, `WritableStore`, `QueryStoreManager`, `BlockStore` and
+/// `StatusStore`. It must be increased whenever one of these traits changes
+/// in a way that requires backends to change, so that a backend that was
+/// written against an older version is rejected when the node starts
+/// rather than misbehaving later
+pub const STORE_INTERFACE_VERSION: u32 = 1;
@@ -5,7 +5,7 @@ use std::sync::Arc;
 
 use crate::blockchain::BlockPtr;
 use crate::components::store::{
-    self as s, Entity, EntityKey, EntityOp, EntityOperation, EntityType,
+    self as s, BlockNumber, Entity, EntityKey, EntityOp, EntityOperation, EntityType,
 };
 use crate::prelude::ENV_VARS;
 use crate::util::lfu_cache::LfuCache;
@@ -113,6 +113,20 @@ impl EntityCache {
         Ok(entity)
     }
 
+    /// The ids of the entities of type `entity_type` that the handlers that
+    /// ran before the current one for this block changed or removed, in
+    /// order. Changes that the current handler made are not included
+    pub fn changed_ids(&self, entity_type: &EntityType) -> Vec<String> {
+        let mut ids: Vec<_> = self
+            .updates
+            .keys()
+            .filter(|key| &key.entity_type == entity_type)
+            .map(|key| key.entity_id.clone())
+            .collect();
+        ids.sort();
+        ids
+    }
+
     pub fn remove(&mut self, key: EntityKey) {
         self.entity_op(key, EntityOp::Remove);
     }
@@ -193,6 +207,17 @@ impl EntityCache {
             .push(data_source.as_stored_dynamic_data_source());
     }
 
+    /// Mark the dynamic data source `data_source` as removed in `block`
+    pub fn remove_data_source<C: s::Blockchain>(
+        &mut self,
+        data_source: &impl s::DataSource<C>,
+        block: BlockNumber,
+    ) {
+        let mut stored = data_source.as_stored_dynamic_data_source();
+        stored.removed_block = Some(block);
+        self.data_sources.push(stored);
+    }
+
     fn entity_op(&mut self, key: EntityKey, op: EntityOp) {
         use std::collections::hash_map::Entry;
         let updates = match self.in_handler {
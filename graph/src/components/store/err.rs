@@ -3,7 +3,7 @@ use thiserror::Error;
 use tokio::task::JoinError;
 
 use super::{BlockNumber, DeploymentHash};
-use crate::prelude::QueryExecutionError;
+use crate::prelude::{NodeId, QueryExecutionError};
 
 #[derive(Error, Debug)]
 pub enum StoreError {
@@ -44,6 +44,19 @@ pub enum StoreError {
     Canceled,
     #[error("database unavailable")]
     DatabaseUnavailable,
+    /// The database aborted the transaction because it conflicted with a
+    /// concurrent transaction, either because of a serialization failure
+    /// or a deadlock. Running the transaction again usually succeeds
+    #[error("transaction conflict: {0}")]
+    TransactionConflict(String),
+    /// Another node is writing to the deployment, either because it
+    /// claimed the deployment first or because the deployment was
+    /// reassigned to it. Contains that node if it is known
+    #[error(
+        "another node is writing subgraph `{0}`; \
+         there are most likely two (or more) nodes indexing this subgraph"
+    )]
+    ConcurrentWriter(DeploymentHash, Option<NodeId>),
     #[error("subgraph forking failed: {0}")]
     ForkFailure(String),
     #[error("subgraph writer poisoned by previous error")]
@@ -65,7 +78,21 @@ macro_rules! constraint_violation {
 
 impl From<::diesel::result::Error> for StoreError {
     fn from(e: ::diesel::result::Error) -> Self {
-        StoreError::Unknown(e.into())
+        use ::diesel::result::{DatabaseErrorKind, Error as DieselError};
+
+        match &e {
+            DieselError::DatabaseError(DatabaseErrorKind::SerializationFailure, info) => {
+                StoreError::TransactionConflict(info.message().to_string())
+            }
+            // Diesel does not have an error kind for deadlocks, but
+            // Postgres always reports them with this message
+            DieselError::DatabaseError(_, info)
+                if info.message().starts_with("deadlock detected") =>
+            {
+                StoreError::TransactionConflict(info.message().to_string())
+            }
+            _ => StoreError::Unknown(e.into()),
+        }
     }
 }
 
@@ -98,3 +125,31 @@ impl From<std::fmt::Error> for StoreError {
         StoreError::Unknown(anyhow!("{}", e.to_string()))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use diesel::result::{DatabaseErrorKind, Error as DieselError};
+
+    fn db_error(kind: DatabaseErrorKind, message: &str) -> StoreError {
+        StoreError::from(DieselError::DatabaseError(
+            kind,
+            Box::new(message.to_string()),
+        ))
+    }
+
+    #[test]
+    fn conflicts_are_recognized() {
+        let err = db_error(
+            DatabaseErrorKind::SerializationFailure,
+            "could not serialize access due to concurrent update",
+        );
+        assert!(matches!(err, StoreError::TransactionConflict(_)));
+
+        let err = db_error(DatabaseErrorKind::__Unknown, "deadlock detected");
+        assert!(matches!(err, StoreError::TransactionConflict(_)));
+
+        let err = db_error(DatabaseErrorKind::UniqueViolation, "duplicate key value");
+        assert!(matches!(err, StoreError::Unknown(_)));
+    }
+}
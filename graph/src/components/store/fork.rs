@@ -4,7 +4,7 @@ use std::{
     sync::{Arc, Mutex},
 };
 
-use graph::{
+use crate::{
     block_on,
     components::store::SubgraphFork as SubgraphForkTrait,
     data::graphql::ext::DirectiveFinder,
@@ -38,7 +38,7 @@ struct Variables {
 /// Since this mechanism is used for debug forks, entities are
 /// fetched only once per id in order to avoid fetching an entity
 /// that was deleted from the local store and thus causing inconsistencies.
-pub(crate) struct SubgraphFork {
+pub struct SubgraphFork {
     client: reqwest::Client,
     endpoint: Url,
     schema: Arc<Schema>,
@@ -88,7 +88,7 @@ impl SubgraphForkTrait for SubgraphFork {
 }
 
 impl SubgraphFork {
-    pub(crate) fn new(
+    pub fn new(
         base: Url,
         id: DeploymentHash,
         schema: Arc<Schema>,
@@ -240,7 +240,7 @@ mod tests {
 
     use super::*;
 
-    use graph::{
+    use crate::{
         data::store::scalar,
         prelude::{s::Type, DeploymentHash},
         slog::{self, o},
@@ -1,7 +1,10 @@
+mod backend;
 mod cache;
 mod err;
+pub mod fork;
 mod traits;
 
+pub use backend::STORE_INTERFACE_VERSION;
 pub use cache::{CachedEthereumCall, EntityCache, ModificationsAndCache};
 pub use err::StoreError;
 pub use traits::*;
@@ -15,6 +18,7 @@ use std::collections::btree_map::Entry;
 use std::collections::{BTreeMap, BTreeSet, HashSet};
 use std::fmt;
 use std::fmt::Display;
+use std::path::{Component, Path};
 use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::{Arc, RwLock};
 use std::time::Duration;
@@ -23,6 +27,7 @@ use crate::blockchain::DataSource;
 use crate::blockchain::{Block, Blockchain};
 use crate::data::{store::*, subgraph::Source};
 use crate::prelude::*;
+use crate::util::fair_scheduler::FairPermit;
 
 /// The type name of an entity. This is the string that is used in the
 /// subgraph's GraphQL schema as `type NAME @entity { .. }`
@@ -141,13 +146,17 @@ pub enum EntityFilter {
     And(Vec<EntityFilter>),
     Or(Vec<EntityFilter>),
     Equal(Attribute, Value),
+    EqualNoCase(Attribute, Value),
     Not(Attribute, Value),
+    NotNoCase(Attribute, Value),
     GreaterThan(Attribute, Value),
     LessThan(Attribute, Value),
     GreaterOrEqual(Attribute, Value),
     LessOrEqual(Attribute, Value),
     In(Attribute, Vec<Value>),
+    InNoCase(Attribute, Vec<Value>),
     NotIn(Attribute, Vec<Value>),
+    NotInNoCase(Attribute, Vec<Value>),
     Contains(Attribute, Value),
     ContainsNoCase(Attribute, Value),
     NotContains(Attribute, Value),
@@ -160,6 +169,14 @@ pub enum EntityFilter {
     EndsWithNoCase(Attribute, Value),
     NotEndsWith(Attribute, Value),
     NotEndsWithNoCase(Attribute, Value),
+    /// The list attribute has at least one element in common with the list
+    /// value
+    Overlaps(Attribute, Value),
+    /// The list attribute is empty (`true`) or not empty (`false`)
+    Empty(Attribute, bool),
+    /// The String attribute matches the POSIX regular expression given as
+    /// the value
+    Matches(Attribute, Value),
     ChangeBlockGte(BlockNumber),
 }
 
@@ -759,6 +776,30 @@ pub struct StoredDynamicDataSource {
     pub source: Source,
     pub context: Option<String>,
     pub creation_block: Option<BlockNumber>,
+    /// The block in which the data source was removed. When a data source
+    /// with this set is passed to `transact_block_operations`, the store
+    /// marks the data source that `is_same_as` it as removed instead of
+    /// adding it
+    pub removed_block: Option<BlockNumber>,
+}
+
+impl StoredDynamicDataSource {
+    /// Whether `self` and `other` are the same data source, no matter
+    /// whether and when either of them was removed
+    pub fn is_same_as(&self, other: &StoredDynamicDataSource) -> bool {
+        // The context is serialized from a hash map, and the order of its
+        // entries is arbitrary
+        fn context(ds: &StoredDynamicDataSource) -> Option<serde_json::Value> {
+            ds.context
+                .as_deref()
+                .and_then(|ctx| serde_json::from_str(ctx).ok())
+        }
+
+        self.name == other.name
+            && self.source == other.source
+            && self.creation_block == other.creation_block
+            && context(self) == context(other)
+    }
 }
 
 /// An internal identifer for the specific instance of a deployment. The
@@ -964,3 +1005,256 @@ impl From<BlockNumber> for PartialBlockPtr {
         Self { number, hash: None }
     }
 }
+
+/// A block from the block time index of a chain store, which keeps a few
+/// details of each block after the block has been removed from the block
+/// cache
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct IndexedBlock {
+    pub hash: web3::types::H256,
+    pub number: BlockNumber,
+    /// The hash of the parent block; like in the block cache, this is all
+    /// zeroes for the genesis block
+    pub parent_hash: web3::types::H256,
+    /// When the block was produced, in seconds since the epoch
+    pub timestamp: u64,
+}
+
+/// The file format for exporting entities with `SubgraphStore::export`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase", try_from = "String")]
+pub enum ExportFormat {
+    Csv,
+}
+
+impl TryFrom<String> for ExportFormat {
+    type Error = anyhow::Error;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl std::str::FromStr for ExportFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "csv" => Ok(ExportFormat::Csv),
+            _ => Err(anyhow!("unknown export format `{}`", s)),
+        }
+    }
+}
+
+/// What to export from a deployment with `SubgraphStore::export`
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ExportRequest {
+    /// The entity types to export; all of them if this is empty
+    #[serde(default)]
+    pub entity_types: Vec<String>,
+    /// The block as of which to export entities; if it is not set, the
+    /// latest block the deployment has processed
+    pub block: Option<BlockNumber>,
+    pub format: ExportFormat,
+    /// The local directory into which the export is written, one file per
+    /// entity type
+    pub destination: String,
+}
+
+impl ExportRequest {
+    /// Check that `destination` is a local directory and make it relative
+    /// to `root`. The destination must be a relative path that stays
+    /// underneath `root`, which is created if it does not exist yet
+    pub fn confine_to(&mut self, root: &Path) -> Result<(), anyhow::Error> {
+        if self.destination.contains("://") {
+            return Err(anyhow!(
+                "exports can only be written to a local directory, not to `{}`",
+                self.destination
+            ));
+        }
+        let destination = Path::new(&self.destination);
+        if destination.as_os_str().is_empty()
+            || !destination
+                .components()
+                .all(|component| matches!(component, Component::Normal(_)))
+        {
+            return Err(anyhow!(
+                "the destination `{}` must be a relative path without `..`",
+                self.destination
+            ));
+        }
+
+        // Symlinks in the export directory might still lead out of it
+        let root = root.canonicalize()?;
+        let path = root.join(destination);
+        std::fs::create_dir_all(&path)?;
+        let path = path.canonicalize()?;
+        if !path.starts_with(&root) {
+            return Err(anyhow!(
+                "the destination `{}` is not underneath the export directory",
+                self.destination
+            ));
+        }
+        self.destination = path.display().to_string();
+        Ok(())
+    }
+}
+
+/// A file that was written by `SubgraphStore::export`
+#[derive(Clone, Debug, Serialize)]
+pub struct ExportedFile {
+    pub entity_type: String,
+    pub path: String,
+    pub rows: usize,
+}
+
+/// A long-running operation on a deployment that an index node runs as a
+/// job in the background. The job records its progress in the store so
+/// that it can be inspected and continues where it left off when the node
+/// that runs it restarts
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum JobRequest {
+    /// Copy the deployment into `shard` as of the given block and assign
+    /// the copy to `node`. The job finishes once the copy has caught up
+    /// with that block
+    Copy {
+        shard: String,
+        node: String,
+        block_hash: String,
+        block_number: BlockNumber,
+    },
+    /// Drop the history partitions of the deployment that only hold
+    /// entity versions from before block `before`
+    Prune { before: BlockNumber },
+    /// Pause the deployment, rewind it to the given block, and resume it
+    Rewind {
+        block_hash: String,
+        block_number: BlockNumber,
+    },
+    /// Export the entities of the deployment one entity type at a time
+    Export(ExportRequest),
+}
+
+impl JobRequest {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            JobRequest::Copy { .. } => "copy",
+            JobRequest::Prune { .. } => "prune",
+            JobRequest::Rewind { .. } => "rewind",
+            JobRequest::Export(_) => "export",
+        }
+    }
+}
+
+/// How a call to the admin server turned out
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AdminCallOutcome {
+    /// The caller was not allowed to make the call
+    Denied,
+    Succeeded,
+    Failed,
+}
+
+impl AdminCallOutcome {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AdminCallOutcome::Denied => "denied",
+            AdminCallOutcome::Succeeded => "succeeded",
+            AdminCallOutcome::Failed => "failed",
+        }
+    }
+}
+
+/// A call to the admin server as it is recorded in the audit log
+#[derive(Clone, Debug)]
+pub struct AdminCall {
+    pub method: String,
+    /// The name of the token the call was made with, if the caller sent a
+    /// known token
+    pub token: Option<String>,
+    /// The role of that token
+    pub role: Option<String>,
+    pub params: serde_json::Value,
+    pub outcome: AdminCallOutcome,
+    pub error: Option<String>,
+}
+
+/// A change to an entity that was recorded in the outbox of a shard in the
+/// same transaction that made the change, and that still needs to be
+/// delivered to the systems that mirror entities
+#[derive(Clone, Debug, Serialize)]
+pub struct OutboxEntry {
+    /// The position of the entry in the outbox of its shard
+    #[serde(skip)]
+    pub id: i64,
+    #[serde(skip)]
+    pub shard: String,
+    pub deployment: DeploymentHash,
+    pub block: BlockNumber,
+    pub entity_type: String,
+    pub entity_id: String,
+    /// Either `set` or `remove`
+    pub operation: String,
+    /// The entity after the change; `None` if it was removed
+    pub data: Option<serde_json::Value>,
+}
+
+/// Permission to run a query. The query may use the database for as long
+/// as the permit is held
+pub struct QueryPermit {
+    pub permit: tokio::sync::OwnedSemaphorePermit,
+    /// For queries against a deployment, the share of the deployment in
+    /// the query permits of the connection pool
+    pub share: Option<FairPermit>,
+}
+
+impl From<tokio::sync::OwnedSemaphorePermit> for QueryPermit {
+    fn from(permit: tokio::sync::OwnedSemaphorePermit) -> Self {
+        QueryPermit {
+            permit,
+            share: None,
+        }
+    }
+}
+
+#[test]
+fn confines_export_destinations() {
+    let root = std::env::temp_dir().join(format!("export-confine-{}", std::process::id()));
+    std::fs::create_dir_all(&root).unwrap();
+    let request = |destination: &str| ExportRequest {
+        entity_types: vec![],
+        block: None,
+        format: ExportFormat::Csv,
+        destination: destination.to_string(),
+    };
+
+    let mut ok = request("nightly/tokens");
+    ok.confine_to(&root).unwrap();
+    assert_eq!(
+        root.canonicalize().unwrap().join("nightly/tokens"),
+        Path::new(&ok.destination)
+    );
+
+    for destination in [
+        "",
+        "/tmp/elsewhere",
+        "../elsewhere",
+        "a/../../b",
+        "s3://bucket/x",
+    ] {
+        assert!(
+            request(destination).confine_to(&root).is_err(),
+            "{}",
+            destination
+        );
+    }
+    std::fs::remove_dir_all(&root).unwrap();
+
+    assert!("parquet".parse::<ExportFormat>().is_err());
+    assert!(serde_json::from_str::<ExportFormat>("\"parquet\"").is_err());
+    assert_eq!(
+        ExportFormat::Csv,
+        serde_json::from_str::<ExportFormat>("\"csv\"").unwrap()
+    );
+}
@@ -3,7 +3,7 @@ use web3::types::{Address, H256};
 use super::*;
 use crate::components::server::index_node::VersionInfo;
 use crate::components::transaction_receipt;
-use crate::data::subgraph::status;
+use crate::data::subgraph::{status, SubgraphDeprecation};
 use crate::data::{query::QueryTarget, subgraph::schema::*};
 
 pub trait SubscriptionManager: Send + Sync + 'static {
@@ -30,6 +30,19 @@ pub trait EnsLookup: Send + Sync + 'static {
     fn find_name(&self, hash: &str) -> Result<Option<String>, StoreError>;
 }
 
+/// Content-addressed storage for the files that make up deployments, i.e.,
+/// their manifests, schemas, ABIs and WASM modules, keyed by the IPFS hash
+/// they are linked with
+pub trait FileRegistry: Send + Sync + 'static {
+    /// Return the contents of the file for `link`, or `None` if it was never
+    /// stored. Fails if the contents do not match the digest that was
+    /// recorded when the file was stored
+    fn get_file(&self, link: &str) -> Result<Option<Vec<u8>>, StoreError>;
+
+    /// Store `content` as the file for `link`
+    fn put_file(&self, link: &str, content: &[u8]) -> Result<(), StoreError>;
+}
+
 /// An entry point for all operations that require access to the node's storage
 /// layer. It provides access to a [`BlockStore`] and a [`SubgraphStore`].
 pub trait Store: Clone + StatusStore + Send + Sync + 'static {
@@ -49,6 +62,8 @@ pub trait Store: Clone + StatusStore + Send + Sync + 'static {
 pub trait SubgraphStore: Send + Sync + 'static {
     fn ens_lookup(&self) -> Arc<dyn EnsLookup>;
 
+    fn file_registry(&self) -> Arc<dyn FileRegistry>;
+
     /// Check if the store is accepting queries for the specified subgraph.
     /// May return true even if the specified subgraph is not currently assigned to an indexing
     /// node, as the store will still accept queries.
@@ -90,10 +105,38 @@ pub trait SubgraphStore: Send + Sync + 'static {
 
     fn assignments(&self, node: &NodeId) -> Result<Vec<DeploymentLocator>, StoreError>;
 
+    /// Count that the deployment was restarted automatically because it
+    /// failed with a non-deterministic error
+    fn record_restart(&self, deployment: &DeploymentLocator) -> Result<(), StoreError>;
+
     /// Return `true` if a subgraph `name` exists, regardless of whether the
     /// subgraph has any deployments attached to it
     fn subgraph_exists(&self, name: &SubgraphName) -> Result<bool, StoreError>;
 
+    /// Record that `deployment` for `network` was instantiated from the
+    /// multi-network manifest `family`
+    fn add_to_family(
+        &self,
+        family: &DeploymentHash,
+        deployment: &DeploymentHash,
+        network: &str,
+    ) -> Result<(), StoreError>;
+
+    /// The deployments that were instantiated from the multi-network
+    /// manifest `family`, together with their networks
+    fn family(&self, family: &DeploymentHash) -> Result<Vec<(DeploymentHash, String)>, StoreError>;
+
+    /// Attach the deprecation notice `deprecation` to the subgraph `name`,
+    /// or remove the notice it has if `deprecation` is `None`
+    fn set_deprecation(
+        &self,
+        name: &SubgraphName,
+        deprecation: Option<SubgraphDeprecation>,
+    ) -> Result<(), StoreError>;
+
+    /// The deprecation notice of the subgraph `name`, if it has one
+    fn deprecation(&self, name: &SubgraphName) -> Result<Option<SubgraphDeprecation>, StoreError>;
+
     /// Returns a collection of all [`EntityModification`] items in relation to
     /// the given [`BlockNumber`]. No distinction is made between inserts and
     /// updates, which may be returned as either [`EntityModification::Insert`]
@@ -137,6 +180,31 @@ pub trait SubgraphStore: Send + Sync + 'static {
 
     /// Find the deployment locators for the subgraph with the given hash
     fn locators(&self, hash: &str) -> Result<Vec<DeploymentLocator>, StoreError>;
+
+    /// Write the entities of `deployment` that `request` asks for to
+    /// files. Entities are read in batches so that exporting large tables
+    /// does not need much memory
+    fn export(
+        &self,
+        deployment: &DeploymentLocator,
+        request: &ExportRequest,
+    ) -> Result<Vec<ExportedFile>, StoreError>;
+
+    /// Add `call` to the audit log of the admin server
+    fn record_admin_call(&self, call: &AdminCall) -> Result<(), StoreError>;
+
+    /// Pass entity changes from the outbox of each shard to `deliver`, at
+    /// most `limit` at a time, and remove them from the outbox once
+    /// `deliver` succeeds. Changes are therefore delivered at least once,
+    /// and in the order in which they were made for each deployment.
+    /// While one caller delivers changes from a shard, other callers get
+    /// no changes from it. Returns the number of changes that were
+    /// delivered
+    fn deliver_entity_changes(
+        &self,
+        limit: usize,
+        deliver: &mut dyn FnMut(&[OutboxEntry]) -> Result<(), Error>,
+    ) -> Result<usize, StoreError>;
 }
 
 /// A view of the store for indexing. All indexing-related operations need
@@ -254,6 +322,13 @@ pub trait QueryStoreManager: Send + Sync + 'static {
         target: QueryTarget,
         for_subscription: bool,
     ) -> Result<Arc<dyn QueryStore + Send + Sync>, QueryExecutionError>;
+
+    /// The deprecation notice of the subgraph `name`, which is reported
+    /// with every response to a query through the name
+    async fn deprecation(
+        &self,
+        name: &SubgraphName,
+    ) -> Result<Option<SubgraphDeprecation>, QueryExecutionError>;
 }
 
 pub trait BlockStore: Send + Sync + 'static {
@@ -350,6 +425,28 @@ pub trait ChainStore: Send + Sync + 'static {
     /// may purge any other blocks with that number
     fn confirm_block_hash(&self, number: BlockNumber, hash: &H256) -> Result<usize, Error>;
 
+    /// Find the block with the highest number whose timestamp is at most
+    /// `timestamp`, in seconds since the epoch. This uses an index that
+    /// keeps the timestamps of blocks after they have been removed from
+    /// the block cache
+    fn block_at_time(&self, timestamp: u64) -> Result<Option<BlockPtr>, StoreError>;
+
+    /// The block with `hash` from the same index as `block_at_time`
+    fn indexed_block(&self, hash: &H256) -> Result<Option<IndexedBlock>, StoreError>;
+
+    /// All blocks with `number` from the same index as `block_at_time`.
+    /// Close to the chain head, that can be blocks from more than one fork
+    fn indexed_blocks_by_number(
+        &self,
+        number: BlockNumber,
+    ) -> Result<Vec<IndexedBlock>, StoreError>;
+
+    /// The timestamp of the block with `hash` in seconds since the epoch,
+    /// from the same index as `block_at_time`
+    fn block_time(&self, hash: &H256) -> Result<Option<u64>, StoreError> {
+        Ok(self.indexed_block(hash)?.map(|block| block.timestamp))
+    }
+
     /// Find the block with `block_hash` and return the network name and number
     fn block_number(&self, block_hash: H256) -> Result<Option<(String, BlockNumber)>, StoreError>;
 
@@ -358,6 +455,14 @@ pub trait ChainStore: Send + Sync + 'static {
         &self,
         block_ptr: &H256,
     ) -> Result<Vec<transaction_receipt::LightTransactionReceipt>, StoreError>;
+
+    /// The number of the block in which the contract at `address` was
+    /// created, if it has been looked up before
+    fn contract_creation_block(&self, address: &[u8]) -> Result<Option<BlockNumber>, Error>;
+
+    /// Remember that the contract at `address` was created in block `number`
+    fn set_contract_creation_block(&self, address: &[u8], number: BlockNumber)
+        -> Result<(), Error>;
 }
 
 pub trait EthereumCallCache: Send + Sync + 'static {
@@ -392,12 +497,52 @@ pub trait QueryStore: Send + Sync {
         query: EntityQuery,
     ) -> Result<Vec<BTreeMap<String, r::Value>>, QueryExecutionError>;
 
+    /// Run `query` under `explain analyze` and return the SQL that the
+    /// store used for it together with its plan
+    fn explain_query(
+        &self,
+        query: EntityQuery,
+    ) -> Result<crate::data::query::QueryExplanation, QueryExecutionError>;
+
+    /// Run the SQL query `sql` against the entities of the deployment as
+    /// they were at `block` and return the resulting rows
+    fn execute_sql(
+        &self,
+        sql: &str,
+        block: BlockNumber,
+    ) -> Result<Vec<serde_json::Value>, QueryExecutionError>;
+
+    /// Return the numbers of the blocks in the range `(after, until]` in
+    /// which entities of the deployment changed
+    fn changed_blocks(
+        &self,
+        after: BlockNumber,
+        until: BlockNumber,
+    ) -> Result<Vec<BlockNumber>, StoreError>;
+
+    /// Return the changes that were made to entities in block `block`
+    fn entity_changes(&self, block: BlockNumber) -> Result<Vec<EntityOperation>, StoreError>;
+
     async fn is_deployment_synced(&self) -> Result<bool, Error>;
 
     async fn block_ptr(&self) -> Result<Option<BlockPtr>, StoreError>;
 
     fn block_number(&self, block_hash: H256) -> Result<Option<BlockNumber>, StoreError>;
 
+    /// Find the hash of the parent of the block with `block_hash`
+    fn block_parent_hash(&self, block_hash: H256) -> Result<Option<H256>, StoreError>;
+
+    /// The number of the head block of the deployment's chain
+    fn chain_head_number(&self) -> Result<Option<BlockNumber>, StoreError>;
+
+    /// The timestamp of the block with `hash` on the deployment's chain in
+    /// seconds since the epoch, if the chain store knows it
+    fn block_time(&self, hash: &H256) -> Result<Option<u64>, StoreError>;
+
+    /// The block with the highest number on the deployment's chain that
+    /// was produced at or before `timestamp`, in seconds since the epoch
+    fn block_at_time(&self, timestamp: u64) -> Result<Option<BlockPtr>, StoreError>;
+
     fn wait_stats(&self) -> PoolWaitStats;
 
     /// If `block` is `None`, assumes the latest block.
@@ -409,10 +554,13 @@ pub trait QueryStore: Send + Sync {
 
     fn api_schema(&self) -> Result<Arc<ApiSchema>, QueryExecutionError>;
 
+    fn input_schema(&self) -> Result<Arc<Schema>, QueryExecutionError>;
+
     fn network_name(&self) -> &str;
 
-    /// A permit should be acquired before starting query execution.
-    async fn query_permit(&self) -> tokio::sync::OwnedSemaphorePermit;
+    /// A permit should be acquired before starting query execution. Fails
+    /// if too many queries against the deployment are already waiting
+    async fn query_permit(&self) -> Result<QueryPermit, QueryExecutionError>;
 }
 
 /// A view of the store that can provide information about the indexing status
@@ -424,6 +572,13 @@ pub trait StatusStore: Send + Sync + 'static {
 
     fn status(&self, filter: status::Filter) -> Result<Vec<status::Info>, StoreError>;
 
+    /// The head and earliest cached block of every chain the store knows
+    /// about. The providers of the chains are left empty
+    fn chains(&self) -> Result<Vec<status::ChainStatus>, StoreError>;
+
+    /// The jobs that match `filter`, newest first
+    fn jobs(&self, filter: status::JobFilter) -> Result<Vec<status::JobInfo>, StoreError>;
+
     /// Support for the explorer-specific API
     fn version_info(&self, version_id: &str) -> Result<VersionInfo, StoreError>;
 
@@ -1,6 +1,8 @@
 use std::cmp::PartialEq;
-use std::sync::Arc;
-use std::time::Instant;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
 use anyhow::Error;
 use async_trait::async_trait;
@@ -16,6 +18,9 @@ use crate::{components::metrics::HistogramVec, runtime::DeterministicHostError};
 pub enum MappingError {
     /// A possible reorg was detected while running the mapping.
     PossibleReorg(anyhow::Error),
+    /// The deployment used more memory or handler time than its quota
+    /// allows. Retrying will not help, and the deployment must be stopped.
+    QuotaExceeded(anyhow::Error),
     Unknown(anyhow::Error),
 }
 
@@ -36,6 +41,7 @@ impl MappingError {
         use MappingError::*;
         match self {
             PossibleReorg(e) => PossibleReorg(e.context(s)),
+            QuotaExceeded(e) => QuotaExceeded(e.context(s)),
             Unknown(e) => Unknown(e.context(s)),
         }
     }
@@ -64,12 +70,24 @@ pub trait RuntimeHost<C: Blockchain>: Send + Sync + 'static {
     /// Block number in which this host was created.
     /// Returns `None` for static data sources.
     fn creation_block_number(&self) -> Option<BlockNumber>;
+
+    /// The entity types that the mapping of the data source declares.
+    fn entities(&self) -> &[String];
+
+    /// The data source this host runs the mapping of.
+    fn data_source(&self) -> &C::DataSource;
 }
 
 pub struct HostMetrics {
     handler_execution_time: Box<HistogramVec>,
     host_fn_execution_time: Box<HistogramVec>,
+    handler_memory: Box<Histogram>,
+    limit_exceeded: Box<CounterVec>,
+    mapping_queue_wait_time: Box<Histogram>,
+    mapping_pool_busy: Gauge,
+    mapping_pool_queued: Gauge,
     pub stopwatch: StopwatchMetrics,
+    pub usage: ResourceUsage,
 }
 
 impl HostMetrics {
@@ -96,10 +114,57 @@ impl HostMetrics {
                 vec![0.025, 0.05, 0.2, 2.0, 8.0, 20.0],
             )
             .expect("failed to create `deployment_host_fn_execution_time` histogram");
+        let handler_memory = registry
+            .new_deployment_histogram(
+                "deployment_handler_memory",
+                "Measures the size of the WASM memory after handlers ran, in bytes",
+                subgraph,
+                vec![1e6, 1e7, 1e8, 1e9],
+            )
+            .expect("failed to create `deployment_handler_memory` histogram");
+        let limit_exceeded = registry
+            .new_deployment_counter_vec(
+                "deployment_handler_limit_exceeded",
+                "Counts the handlers that failed because they exceeded a resource limit",
+                subgraph,
+                vec![String::from("limit")],
+            )
+            .expect("failed to create `deployment_handler_limit_exceeded` counter");
+        let mapping_queue_wait_time = registry
+            .new_deployment_histogram(
+                "deployment_mapping_queue_wait_time",
+                "Measures how long handlers wait for a mapping thread, in seconds",
+                subgraph,
+                vec![0.001, 0.01, 0.1, 1.0, 10.0],
+            )
+            .expect("failed to create `deployment_mapping_queue_wait_time` histogram");
+        let mapping_pool_busy = registry
+            .global_gauge(
+                "mapping_pool_busy_threads",
+                "The number of mapping threads that are running a handler",
+                HashMap::new(),
+            )
+            .expect("failed to create `mapping_pool_busy_threads` gauge");
+        let mapping_pool_queued = registry
+            .global_gauge(
+                "mapping_pool_queued_handlers",
+                "The number of handlers that wait for a mapping thread",
+                HashMap::new(),
+            )
+            .expect("failed to create `mapping_pool_queued_handlers` gauge");
         Self {
             handler_execution_time,
             host_fn_execution_time,
+            handler_memory,
+            limit_exceeded,
+            mapping_queue_wait_time,
+            mapping_pool_busy,
+            mapping_pool_queued,
             stopwatch,
+            usage: ResourceUsage::new(
+                ENV_VARS.mappings.deployment_max_memory,
+                ENV_VARS.mappings.deployment_cpu_quota,
+            ),
         }
     }
 
@@ -115,6 +180,27 @@ impl HostMetrics {
             .observe(duration);
     }
 
+    pub fn observe_handler_memory(&self, bytes: usize) {
+        self.handler_memory.observe(bytes as f64);
+    }
+
+    /// Record that a handler exceeded `limit`, either `memory`, `timeout`,
+    /// `deployment_memory` or `deployment_cpu`
+    pub fn limit_exceeded(&self, limit: &str) {
+        self.limit_exceeded.with_label_values(&[limit][..]).inc();
+    }
+
+    pub fn observe_mapping_queue_wait_time(&self, duration: f64) {
+        self.mapping_queue_wait_time.observe(duration);
+    }
+
+    /// Record how many mapping threads are `busy` and how many handlers are
+    /// `queued` waiting for one
+    pub fn set_mapping_pool_usage(&self, busy: usize, queued: usize) {
+        self.mapping_pool_busy.set(busy as f64);
+        self.mapping_pool_queued.set(queued as f64);
+    }
+
     pub fn time_host_fn_execution_region(
         self: Arc<HostMetrics>,
         fn_name: &'static str,
@@ -127,6 +213,103 @@ impl HostMetrics {
     }
 }
 
+/// The window over which the handler time of a deployment is checked
+/// against `GRAPH_DEPLOYMENT_CPU_QUOTA`
+pub const CPU_QUOTA_WINDOW: Duration = Duration::from_secs(60);
+
+/// The memory and handler time that the handlers of one deployment use,
+/// checked against the quotas for a deployment
+pub struct ResourceUsage {
+    max_memory: Option<usize>,
+    cpu_quota: Option<Duration>,
+    /// The memory of the WASM instances that are alive right now
+    memory: Arc<AtomicUsize>,
+    /// The start of the current window and the handler time used in it
+    cpu: Mutex<(Instant, Duration)>,
+}
+
+impl ResourceUsage {
+    pub fn new(max_memory: Option<usize>, cpu_quota: Option<Duration>) -> Self {
+        ResourceUsage {
+            max_memory,
+            cpu_quota,
+            memory: Arc::new(AtomicUsize::new(0)),
+            cpu: Mutex::new((Instant::now(), Duration::ZERO)),
+        }
+    }
+
+    /// Account for the memory of a WASM instance until the returned hold is
+    /// dropped. The hold starts out empty
+    pub fn hold_memory(&self) -> MemoryHold {
+        MemoryHold {
+            memory: self.memory.cheap_clone(),
+            bytes: 0,
+        }
+    }
+
+    /// Check that the WASM instances of the deployment do not hold more
+    /// memory than the quota allows
+    pub fn check_memory(&self) -> Result<(), MappingError> {
+        let used = self.memory.load(Ordering::SeqCst);
+        match self.max_memory {
+            Some(max) if used > max => Err(MappingError::QuotaExceeded(anyhow!(
+                "the handlers of the deployment use {} bytes of memory, but \
+                 GRAPH_DEPLOYMENT_MAX_MEMORY only allows {} bytes",
+                used,
+                max
+            ))),
+            _ => Ok(()),
+        }
+    }
+
+    /// Add `elapsed` handler time that ended at `now` and check that the
+    /// handlers of the deployment did not run for longer than the quota
+    /// allows in the current window
+    pub fn add_cpu_time(&self, elapsed: Duration, now: Instant) -> Result<(), MappingError> {
+        let mut cpu = self.cpu.lock().unwrap();
+        let (start, used) = &mut *cpu;
+        if now.saturating_duration_since(*start) >= CPU_QUOTA_WINDOW {
+            *start = now;
+            *used = Duration::ZERO;
+        }
+        *used += elapsed;
+        match self.cpu_quota {
+            Some(quota) if *used > quota => Err(MappingError::QuotaExceeded(anyhow!(
+                "the handlers of the deployment ran for {}s within {}s, but \
+                 GRAPH_DEPLOYMENT_CPU_QUOTA only allows {}s",
+                used.as_secs(),
+                CPU_QUOTA_WINDOW.as_secs(),
+                quota.as_secs()
+            ))),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// The memory that one WASM instance holds, released when it is dropped
+pub struct MemoryHold {
+    memory: Arc<AtomicUsize>,
+    bytes: usize,
+}
+
+impl MemoryHold {
+    /// Change the memory that is held to `bytes`
+    pub fn resize(&mut self, bytes: usize) {
+        if bytes >= self.bytes {
+            self.memory.fetch_add(bytes - self.bytes, Ordering::SeqCst);
+        } else {
+            self.memory.fetch_sub(self.bytes - bytes, Ordering::SeqCst);
+        }
+        self.bytes = bytes;
+    }
+}
+
+impl Drop for MemoryHold {
+    fn drop(&mut self) {
+        self.memory.fetch_sub(self.bytes, Ordering::SeqCst);
+    }
+}
+
 #[must_use]
 pub struct HostFnExecutionTimer {
     start: Instant,
@@ -166,3 +349,54 @@ pub trait RuntimeHostBuilder<C: Blockchain>: Clone + Send + Sync + 'static {
         metrics: Arc<HostMetrics>,
     ) -> Result<mpsc::Sender<Self::Req>, anyhow::Error>;
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn memory_quota() {
+        let usage = ResourceUsage::new(Some(100), None);
+
+        let mut first = usage.hold_memory();
+        first.resize(60);
+        assert!(usage.check_memory().is_ok());
+
+        let mut second = usage.hold_memory();
+        second.resize(60);
+        assert!(matches!(
+            usage.check_memory(),
+            Err(MappingError::QuotaExceeded(_))
+        ));
+
+        // Instances release their memory when they are dropped
+        drop(second);
+        assert!(usage.check_memory().is_ok());
+        first.resize(40);
+        drop(first);
+        assert_eq!(0, usage.memory.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn cpu_quota() {
+        let usage = ResourceUsage::new(None, Some(Duration::from_secs(10)));
+        let start = usage.cpu.lock().unwrap().0;
+        let secs = Duration::from_secs;
+
+        assert!(usage.add_cpu_time(secs(6), start + secs(6)).is_ok());
+        assert!(matches!(
+            usage.add_cpu_time(secs(6), start + secs(12)),
+            Err(MappingError::QuotaExceeded(_))
+        ));
+
+        // The handler time is counted afresh in the next window
+        let next = start + CPU_QUOTA_WINDOW;
+        assert!(usage.add_cpu_time(secs(6), next).is_ok());
+        assert!(usage.add_cpu_time(secs(4), next + secs(4)).is_ok());
+        assert!(usage.add_cpu_time(secs(1), next + secs(5)).is_err());
+
+        // Without a quota, handlers can run as long as they like
+        let usage = ResourceUsage::new(None, None);
+        assert!(usage.add_cpu_time(CPU_QUOTA_WINDOW, start).is_ok());
+    }
+}
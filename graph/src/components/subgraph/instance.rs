@@ -1,4 +1,8 @@
-use crate::blockchain::Blockchain;
+use itertools::Itertools;
+
+use crate::blockchain::{Blockchain, DataSource};
+use crate::components::store::EntityType;
+use crate::data::subgraph::API_VERSION_0_0_8;
 use crate::prelude::*;
 use crate::util::lfu_cache::LfuCache;
 use crate::{components::store::WritableStore, data::subgraph::schema::SubgraphError};
@@ -11,17 +15,119 @@ pub struct DataSourceTemplateInfo<C: Blockchain> {
     pub creation_block: BlockNumber,
 }
 
+/// A dynamic data source that a handler removed with `dataSource.remove()`
+#[derive(Clone, Debug)]
+pub struct RemovedDataSource {
+    pub name: String,
+    pub address: Vec<u8>,
+    pub context: Arc<Option<DataSourceContext>>,
+    pub creation_block: BlockNumber,
+}
+
+/// The entry in the context of a dynamic data source that makes it expire.
+/// A data source whose context sets it to the number of a block, as an
+/// `Int`, is removed once that block has been processed, as if a handler
+/// had removed it in that block. Mappings with an `apiVersion` before
+/// 0.0.8 can not use it
+pub const EXPIRES_AFTER_BLOCK: &str = "graph:expiresAfterBlock";
+
+impl RemovedDataSource {
+    /// The removal of `data_source`, which must be a dynamic data source
+    pub fn of<C: Blockchain>(data_source: &C::DataSource) -> Option<Self> {
+        Some(RemovedDataSource {
+            name: data_source.name().to_string(),
+            address: data_source.address().unwrap_or_default().to_vec(),
+            context: data_source.context(),
+            creation_block: data_source.creation_block()?,
+        })
+    }
+
+    /// The block after which `data_source` expires according to the
+    /// `EXPIRES_AFTER_BLOCK` entry in its context
+    pub fn expiry<C: Blockchain>(data_source: &C::DataSource) -> Option<BlockNumber> {
+        if data_source.creation_block().is_none() || data_source.api_version() < API_VERSION_0_0_8 {
+            return None;
+        }
+        match data_source
+            .context()
+            .as_ref()
+            .as_ref()?
+            .get(EXPIRES_AFTER_BLOCK)?
+        {
+            Value::Int(block) => Some(*block),
+            _ => None,
+        }
+    }
+
+    /// Whether `data_source` is the data source that was removed
+    pub fn matches<C: Blockchain>(&self, data_source: &C::DataSource) -> bool {
+        data_source.creation_block() == Some(self.creation_block)
+            && data_source.name() == self.name
+            && data_source.address().unwrap_or_default() == self.address.as_slice()
+            && data_source.context() == self.context
+    }
+}
+
+/// A reference from field `field` of `source` to the entity with id
+/// `target_id`, which must be of one of the types in `target_types`.
+/// Handlers of subgraphs with the `strictReferences` feature record them so
+/// that they can be checked once the block has been processed
+#[derive(Clone, Debug)]
+pub struct EntityReference {
+    pub source: EntityKey,
+    pub field: String,
+    pub target_types: Vec<EntityType>,
+    pub target_id: String,
+    pub handler: String,
+}
+
+impl EntityReference {
+    /// The id of the entity that the `value` of a reference field points
+    /// to. Ids of type `Bytes` are turned into hex strings
+    pub fn id_of(value: &Value) -> Option<String> {
+        match value {
+            Value::String(id) => Some(id.clone()),
+            Value::Bytes(id) => Some(id.to_string()),
+            _ => None,
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct BlockState<C: Blockchain> {
     pub entity_cache: EntityCache,
     pub deterministic_errors: Vec<SubgraphError>,
+
+    // Problems that handlers worked around, like required fields that
+    // `uncrashable` subgraphs left unset. They are stored like
+    // deterministic errors, but do not fail the block.
+    warnings: Vec<SubgraphError>,
+
+    // Warnings of the current handler.
+    handler_warnings: Vec<SubgraphError>,
+
     created_data_sources: Vec<DataSourceTemplateInfo<C>>,
 
     // Data sources created in the current handler.
     handler_created_data_sources: Vec<DataSourceTemplateInfo<C>>,
 
-    // Marks whether a handler is currently executing.
-    in_handler: bool,
+    removed_data_sources: Vec<RemovedDataSource>,
+
+    // Data sources removed in the current handler.
+    handler_removed_data_sources: Vec<RemovedDataSource>,
+
+    // References to other entities that handlers set.
+    references: Vec<EntityReference>,
+
+    // References set by the current handler.
+    handler_references: Vec<EntityReference>,
+
+    // The name of the handler that is currently executing, if any.
+    handler: Option<String>,
+
+    // Marks whether handlers may only access the entity types that the
+    // mapping of their data source declares.
+    declared_entities_only: bool,
 }
 
 impl<C: Blockchain> BlockState<C> {
@@ -32,26 +138,61 @@ impl<C: Blockchain> BlockState<C> {
         BlockState {
             entity_cache: EntityCache::with_current(store, lfu_cache),
             deterministic_errors: Vec::new(),
+            warnings: Vec::new(),
+            handler_warnings: Vec::new(),
             created_data_sources: Vec::new(),
             handler_created_data_sources: Vec::new(),
-            in_handler: false,
+            removed_data_sources: Vec::new(),
+            handler_removed_data_sources: Vec::new(),
+            references: Vec::new(),
+            handler_references: Vec::new(),
+            handler: None,
+            declared_entities_only: false,
         }
     }
 
+    /// Restrict handlers to the entity types that the mapping of their data
+    /// source declares if `declared_entities_only` is set
+    pub fn with_declared_entities_only(mut self, declared_entities_only: bool) -> Self {
+        self.declared_entities_only = declared_entities_only;
+        self
+    }
+
+    pub fn declared_entities_only(&self) -> bool {
+        self.declared_entities_only
+    }
+
     pub fn extend(&mut self, other: BlockState<C>) {
-        assert!(!other.in_handler);
+        assert!(other.handler.is_none());
 
         let BlockState {
             entity_cache,
             deterministic_errors,
+            warnings,
+            handler_warnings,
             created_data_sources,
             handler_created_data_sources,
-            in_handler,
+            removed_data_sources,
+            handler_removed_data_sources,
+            references,
+            handler_references,
+            handler,
+            declared_entities_only: _,
         } = self;
 
-        match in_handler {
-            true => handler_created_data_sources.extend(other.created_data_sources),
-            false => created_data_sources.extend(other.created_data_sources),
+        match handler {
+            Some(_) => {
+                handler_created_data_sources.extend(other.created_data_sources);
+                handler_removed_data_sources.extend(other.removed_data_sources);
+                handler_references.extend(other.references);
+                handler_warnings.extend(other.warnings);
+            }
+            None => {
+                created_data_sources.extend(other.created_data_sources);
+                removed_data_sources.extend(other.removed_data_sources);
+                references.extend(other.references);
+                warnings.extend(other.warnings);
+            }
         }
         deterministic_errors.extend(other.deterministic_errors);
         entity_cache.extend(other.entity_cache);
@@ -61,40 +202,156 @@ impl<C: Blockchain> BlockState<C> {
         !self.deterministic_errors.is_empty()
     }
 
+    pub fn drain_warnings(&mut self) -> Vec<SubgraphError> {
+        assert!(self.handler.is_none());
+        std::mem::take(&mut self.warnings)
+    }
+
+    pub fn append_warnings(&mut self, mut warnings: Vec<SubgraphError>) {
+        assert!(self.handler.is_none());
+        self.warnings.append(&mut warnings);
+    }
+
     pub fn has_created_data_sources(&self) -> bool {
-        assert!(!self.in_handler);
+        assert!(self.handler.is_none());
         !self.created_data_sources.is_empty()
     }
 
     pub fn drain_created_data_sources(&mut self) -> Vec<DataSourceTemplateInfo<C>> {
-        assert!(!self.in_handler);
+        assert!(self.handler.is_none());
         std::mem::take(&mut self.created_data_sources)
     }
 
-    pub fn enter_handler(&mut self) {
-        assert!(!self.in_handler);
-        self.in_handler = true;
+    pub fn append_created_data_sources(
+        &mut self,
+        mut data_sources: Vec<DataSourceTemplateInfo<C>>,
+    ) {
+        assert!(self.handler.is_none());
+        self.created_data_sources.append(&mut data_sources);
+    }
+
+    pub fn drain_removed_data_sources(&mut self) -> Vec<RemovedDataSource> {
+        assert!(self.handler.is_none());
+        std::mem::take(&mut self.removed_data_sources)
+    }
+
+    pub fn append_removed_data_sources(&mut self, mut data_sources: Vec<RemovedDataSource>) {
+        assert!(self.handler.is_none());
+        self.removed_data_sources.append(&mut data_sources);
+    }
+
+    pub fn enter_handler(&mut self, handler: &str) {
+        assert!(self.handler.is_none());
+        self.handler = Some(handler.to_string());
         self.entity_cache.enter_handler()
     }
 
     pub fn exit_handler(&mut self) {
-        assert!(self.in_handler);
-        self.in_handler = false;
+        assert!(self.handler.is_some());
+        self.handler = None;
         self.created_data_sources
             .append(&mut self.handler_created_data_sources);
+        self.removed_data_sources
+            .append(&mut self.handler_removed_data_sources);
+        self.references.append(&mut self.handler_references);
+        self.warnings.append(&mut self.handler_warnings);
         self.entity_cache.exit_handler()
     }
 
     pub fn exit_handler_and_discard_changes_due_to_error(&mut self, e: SubgraphError) {
-        assert!(self.in_handler);
-        self.in_handler = false;
+        assert!(self.handler.is_some());
+        self.handler = None;
         self.handler_created_data_sources.clear();
+        self.handler_removed_data_sources.clear();
+        self.handler_references.clear();
+        self.handler_warnings.clear();
         self.entity_cache.exit_handler_and_discard_changes();
         self.deterministic_errors.push(e);
     }
 
+    /// The name of the handler that is currently executing
+    pub fn handler(&self) -> Option<&str> {
+        self.handler.as_deref()
+    }
+
+    pub fn push_warning(&mut self, warning: SubgraphError) {
+        assert!(self.handler.is_some());
+        self.handler_warnings.push(warning);
+    }
+
+    pub fn push_reference(&mut self, reference: EntityReference) {
+        assert!(self.handler.is_some());
+        self.handler_references.push(reference);
+    }
+
+    /// Check that the references that handlers set point to entities that
+    /// exist now that all handlers for `block_ptr` have run, and add a
+    /// deterministic error for each one that does not. References whose
+    /// source was changed to point elsewhere or removed again are ignored
+    pub fn check_references(&mut self, block_ptr: &BlockPtr) -> Result<(), QueryExecutionError> {
+        assert!(self.handler.is_none());
+        for reference in std::mem::take(&mut self.references) {
+            let still_referenced = match self.entity_cache.get(&reference.source)? {
+                Some(source) => match source.get(&reference.field) {
+                    Some(Value::List(values)) => values.iter().any(|value| {
+                        EntityReference::id_of(value).as_ref() == Some(&reference.target_id)
+                    }),
+                    Some(value) => {
+                        EntityReference::id_of(value).as_ref() == Some(&reference.target_id)
+                    }
+                    None => false,
+                },
+                None => false,
+            };
+            if !still_referenced {
+                continue;
+            }
+
+            let mut exists = false;
+            for target_type in &reference.target_types {
+                let key = EntityKey {
+                    subgraph_id: reference.source.subgraph_id.clone(),
+                    entity_type: target_type.clone(),
+                    entity_id: reference.target_id.clone(),
+                };
+                if self.entity_cache.get(&key)?.is_some() {
+                    exists = true;
+                    break;
+                }
+            }
+            if exists {
+                continue;
+            }
+
+            let message = format!(
+                "Entity {}[{}]: field `{}` references the {} `{}`, which does not exist \
+                 at the end of block {}; the reference was set by handler `{}`",
+                reference.source.entity_type,
+                reference.source.entity_id,
+                reference.field,
+                reference.target_types.iter().join(" or "),
+                reference.target_id,
+                block_ptr,
+                reference.handler
+            );
+            self.deterministic_errors.push(SubgraphError {
+                subgraph_id: reference.source.subgraph_id.clone(),
+                message,
+                block_ptr: Some(block_ptr.clone()),
+                handler: Some(reference.handler),
+                deterministic: true,
+            });
+        }
+        Ok(())
+    }
+
     pub fn push_created_data_source(&mut self, ds: DataSourceTemplateInfo<C>) {
-        assert!(self.in_handler);
+        assert!(self.handler.is_some());
         self.handler_created_data_sources.push(ds);
     }
+
+    pub fn push_removed_data_source(&mut self, ds: RemovedDataSource) {
+        assert!(self.handler.is_some());
+        self.handler_removed_data_sources.push(ds);
+    }
 }
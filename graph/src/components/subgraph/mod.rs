@@ -7,12 +7,19 @@ mod registrar;
 
 pub use crate::prelude::Entity;
 
-pub use self::host::{HostMetrics, MappingError, RuntimeHost, RuntimeHostBuilder};
-pub use self::instance::{BlockState, DataSourceTemplateInfo};
+pub use self::host::{
+    HostMetrics, MappingError, MemoryHold, ResourceUsage, RuntimeHost, RuntimeHostBuilder,
+};
+pub use self::instance::{
+    BlockState, DataSourceTemplateInfo, EntityReference, RemovedDataSource, EXPIRES_AFTER_BLOCK,
+};
 pub use self::instance_manager::SubgraphInstanceManager;
 pub use self::proof_of_indexing::{
     BlockEventStream, CausalityRegion, ProofOfIndexing, ProofOfIndexingEvent,
     ProofOfIndexingFinisher, SharedProofOfIndexing,
 };
 pub use self::provider::SubgraphAssignmentProvider;
-pub use self::registrar::{SubgraphRegistrar, SubgraphVersionSwitchingMode};
+pub use self::registrar::{
+    ManifestSource, SubgraphDiagnostic, SubgraphRegistrar, SubgraphValidation,
+    SubgraphVersionSwitchingMode,
+};
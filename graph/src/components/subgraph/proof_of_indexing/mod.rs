@@ -262,4 +262,42 @@ mod tests {
             }
         }
     }
+
+    #[test]
+    fn replayed_events_match_written_events() {
+        let logger = Logger::root(Discard, o!());
+        let data = hashmap! {
+            "val".to_owned() => Value::Int(1)
+        };
+        let events = [
+            ProofOfIndexingEvent::SetEntity {
+                entity_type: "type",
+                id: "id",
+                data: &data,
+            },
+            ProofOfIndexingEvent::RemoveEntity {
+                entity_type: "type",
+                id: "other",
+            },
+        ];
+
+        let mut written = ProofOfIndexing::new(3);
+        let mut recording = ProofOfIndexing::recording(3);
+        for event in events.iter() {
+            written.write(&logger, "region", event);
+            recording.write(&logger, "region", event);
+        }
+        let mut replayed = ProofOfIndexing::new(3);
+        replayed.replay(&logger, recording);
+
+        let digests = |poi: ProofOfIndexing| {
+            poi.take()
+                .into_iter()
+                .map(|(name, region)| (name, region.pause(None)))
+                .collect::<HashMap<_, _>>()
+        };
+        let written = digests(written);
+        assert_eq!(1, written.len());
+        assert_eq!(written, digests(replayed));
+    }
 }
@@ -5,7 +5,7 @@
 use super::ProofOfIndexingEvent;
 use crate::{
     blockchain::BlockPtr,
-    prelude::{debug, BlockNumber, DeploymentHash, Logger, ENV_VARS},
+    prelude::{debug, BlockNumber, DeploymentHash, Logger, Value, ENV_VARS},
 };
 use stable_hash::crypto::{Blake3SeqNo, SetHasher};
 use stable_hash::prelude::*;
@@ -100,6 +100,90 @@ impl BlockEventStream {
     }
 }
 
+/// An event written to a recording `ProofOfIndexing`, together with the
+/// causality region it was written to
+enum RecordedEvent {
+    RemoveEntity {
+        causality_region: String,
+        entity_type: String,
+        id: String,
+    },
+    SetEntity {
+        causality_region: String,
+        entity_type: String,
+        id: String,
+        data: HashMap<String, Value>,
+    },
+    DeterministicError {
+        causality_region: String,
+        redacted_events: u64,
+    },
+}
+
+impl RecordedEvent {
+    fn new(causality_region: &str, event: &ProofOfIndexingEvent<'_>) -> Self {
+        let causality_region = causality_region.to_owned();
+        match event {
+            ProofOfIndexingEvent::RemoveEntity { entity_type, id } => RecordedEvent::RemoveEntity {
+                causality_region,
+                entity_type: entity_type.to_string(),
+                id: id.to_string(),
+            },
+            ProofOfIndexingEvent::SetEntity {
+                entity_type,
+                id,
+                data,
+            } => RecordedEvent::SetEntity {
+                causality_region,
+                entity_type: entity_type.to_string(),
+                id: id.to_string(),
+                data: (*data).clone(),
+            },
+            ProofOfIndexingEvent::DeterministicError { redacted_events } => {
+                RecordedEvent::DeterministicError {
+                    causality_region,
+                    redacted_events: *redacted_events,
+                }
+            }
+        }
+    }
+
+    fn event(&self) -> (&str, ProofOfIndexingEvent<'_>) {
+        match self {
+            RecordedEvent::RemoveEntity {
+                causality_region,
+                entity_type,
+                id,
+            } => (
+                causality_region,
+                ProofOfIndexingEvent::RemoveEntity { entity_type, id },
+            ),
+            RecordedEvent::SetEntity {
+                causality_region,
+                entity_type,
+                id,
+                data,
+            } => (
+                causality_region,
+                ProofOfIndexingEvent::SetEntity {
+                    entity_type,
+                    id,
+                    data,
+                },
+            ),
+            RecordedEvent::DeterministicError {
+                causality_region,
+                redacted_events,
+            } => (
+                causality_region,
+                ProofOfIndexingEvent::DeterministicError {
+                    redacted_events: *redacted_events,
+                },
+            ),
+        }
+    }
+}
+
 #[derive(Default)]
 pub struct ProofOfIndexing {
     block_number: BlockNumber,
@@ -108,6 +192,10 @@ pub struct ProofOfIndexing {
     /// state with other data sources. This may also give us some freedom to change
     /// the order of triggers in the future.
     per_causality_region: HashMap<String, BlockEventStream>,
+    /// For a recording `ProofOfIndexing`, the events written to it so far.
+    /// They are not hashed until they are replayed into another
+    /// `ProofOfIndexing`
+    recorded: Option<Vec<RecordedEvent>>,
 }
 
 impl fmt::Debug for ProofOfIndexing {
@@ -121,6 +209,26 @@ impl ProofOfIndexing {
         Self {
             block_number,
             per_causality_region: HashMap::new(),
+            recorded: None,
+        }
+    }
+
+    /// A `ProofOfIndexing` that only records the events written to it so
+    /// that handlers can run concurrently and their events can be written to
+    /// the real `ProofOfIndexing` with `replay` in the order in which the
+    /// handlers would have run one after the other
+    pub fn recording(block_number: BlockNumber) -> Self {
+        Self {
+            recorded: Some(Vec::new()),
+            ..Self::new(block_number)
+        }
+    }
+
+    /// Write the events recorded by `recording` to this `ProofOfIndexing`
+    pub fn replay(&mut self, logger: &Logger, recording: ProofOfIndexing) {
+        for recorded in recording.recorded.into_iter().flatten() {
+            let (causality_region, event) = recorded.event();
+            self.write(logger, causality_region, &event);
         }
     }
 
@@ -142,6 +250,11 @@ impl ProofOfIndexing {
         causality_region: &str,
         event: &ProofOfIndexingEvent<'_>,
     ) {
+        if let Some(recorded) = &mut self.recorded {
+            recorded.push(RecordedEvent::new(causality_region, event));
+            return;
+        }
+
         if ENV_VARS.log_poi_events {
             debug!(
                 logger,
@@ -1,7 +1,9 @@
+use std::collections::BTreeMap;
 use std::str::FromStr;
 
 use async_trait::async_trait;
 
+use crate::components::store::{AdminCall, ExportRequest, ExportedFile};
 use crate::prelude::*;
 
 #[derive(Clone, Copy, Debug)]
@@ -28,6 +30,114 @@ impl FromStr for SubgraphVersionSwitchingMode {
     }
 }
 
+/// The manifest that `SubgraphRegistrar::validate_subgraph` checks
+#[derive(Clone, Debug)]
+pub enum ManifestSource {
+    /// The manifest with this deployment hash
+    Hash(DeploymentHash),
+    /// The text of a manifest. It is not stored anywhere; the files it
+    /// links to are resolved like those of any other manifest
+    Inline(String),
+}
+
+/// A problem that validating a manifest found
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SubgraphDiagnostic {
+    /// The kind of check that failed: `resolve`, `manifest`,
+    /// `dataSource`, `schema`, `graft`, `features`, `network` or `block`
+    pub check: &'static str,
+    /// The data source that has the problem, if it is in one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub data_source: Option<String>,
+    pub message: String,
+}
+
+impl SubgraphDiagnostic {
+    pub fn new(check: &'static str, data_source: Option<String>, message: impl ToString) -> Self {
+        SubgraphDiagnostic {
+            check,
+            data_source,
+            message: message.to_string(),
+        }
+    }
+
+    /// The diagnostics for `error`. Schema errors turn into one
+    /// diagnostic for each problem with the schema
+    pub fn from_validation_error(error: &SubgraphManifestValidationError) -> Vec<Self> {
+        use SubgraphManifestValidationError::*;
+
+        let check = match error {
+            NoDataSources
+            | SourceAddressRequired
+            | MultipleEthereumNetworks
+            | EthereumNetworkRequired
+            | DifferentApiVersions(_) => "manifest",
+            BlockNotFound(_) => "block",
+            SchemaImportError(errors) => {
+                return errors
+                    .iter()
+                    .map(|e| Self::new("schema", None, e))
+                    .collect()
+            }
+            SchemaValidationError(errors) => {
+                return errors
+                    .iter()
+                    .map(|e| Self::new("schema", None, e))
+                    .collect()
+            }
+            GraftBaseInvalid(_) => "graft",
+            FeatureValidationError(_) => "features",
+            VersionGate(_) => "version",
+            DataSourceValidation(name, e) => {
+                return vec![Self::new("dataSource", Some(name.clone()), e)]
+            }
+        };
+        vec![Self::new(check, None, error)]
+    }
+}
+
+/// What validating a manifest found out about it. The fields other than
+/// `deployment`, `valid` and `diagnostics` are only set if the manifest
+/// could be resolved
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SubgraphValidation {
+    pub deployment: DeploymentHash,
+    /// Whether deploying the manifest would pass validation
+    pub valid: bool,
+    pub spec_version: Option<String>,
+    pub network: Option<String>,
+    /// The features the manifest uses, whether it declares them or not
+    pub features: Vec<String>,
+    /// The smallest start block of the data sources
+    pub start_block: Option<BlockNumber>,
+    pub graft_base: Option<DeploymentHash>,
+    pub graft_block: Option<BlockNumber>,
+    pub diagnostics: Vec<SubgraphDiagnostic>,
+}
+
+impl SubgraphValidation {
+    pub fn new(deployment: DeploymentHash) -> Self {
+        SubgraphValidation {
+            deployment,
+            valid: true,
+            spec_version: None,
+            network: None,
+            features: vec![],
+            start_block: None,
+            graft_base: None,
+            graft_block: None,
+            diagnostics: vec![],
+        }
+    }
+
+    pub fn push(&mut self, diagnostic: SubgraphDiagnostic) {
+        self.valid = false;
+        self.diagnostics.push(diagnostic);
+    }
+}
+
 /// Common trait for subgraph registrars.
 #[async_trait]
 pub trait SubgraphRegistrar: Send + Sync + 'static {
@@ -52,4 +162,86 @@ pub trait SubgraphRegistrar: Send + Sync + 'static {
         hash: &DeploymentHash,
         node_id: &NodeId,
     ) -> Result<(), SubgraphRegistrarError>;
+
+    /// Export the entities of the deployment `hash` as `request` describes
+    async fn export_subgraph(
+        &self,
+        hash: &DeploymentHash,
+        request: ExportRequest,
+    ) -> Result<Vec<ExportedFile>, SubgraphRegistrarError>;
+
+    /// Add the manifest at the local `path` and the files it refers to to
+    /// the store so that it can be deployed without IPFS, and return its
+    /// deployment hash
+    async fn import_files(&self, path: &str) -> Result<DeploymentHash, SubgraphRegistrarError>;
+
+    /// Set the parameters of the manifest `hash` to `values` and the
+    /// defaults in the manifest, and add the resulting manifest to the
+    /// store. Return the deployment hash of that manifest, or `hash` if the
+    /// manifest has no parameters
+    async fn instantiate_manifest(
+        &self,
+        hash: DeploymentHash,
+        values: &BTreeMap<String, serde_json::Value>,
+    ) -> Result<DeploymentHash, SubgraphRegistrarError>;
+
+    /// Split the multi-network manifest `hash` into one manifest for each
+    /// of its networks that this node has a chain for, add them to the
+    /// store and record them as the family of `hash`. Return the networks
+    /// and the deployment hashes of their manifests, or `None` if the
+    /// manifest does not list networks
+    async fn instantiate_networks(
+        &self,
+        hash: &DeploymentHash,
+    ) -> Result<Option<Vec<(String, DeploymentHash)>>, SubgraphRegistrarError>;
+
+    /// Run the checks that deploying `manifest` would run without
+    /// deploying it. Problems with the manifest are reported in the
+    /// diagnostics of the result, not as an error
+    async fn validate_subgraph(
+        &self,
+        manifest: ManifestSource,
+    ) -> Result<SubgraphValidation, SubgraphRegistrarError>;
+
+    /// Add `call` to the audit log of the admin server
+    async fn record_admin_call(&self, call: AdminCall) -> Result<(), SubgraphRegistrarError>;
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::data::schema::SchemaValidationError;
+
+    use super::*;
+
+    #[test]
+    fn diagnostics_from_validation_errors() {
+        let error = SubgraphManifestValidationError::SchemaValidationError(vec![
+            SchemaValidationError::InterfaceUndefined("Thing".to_string()),
+            SchemaValidationError::InterfaceUndefined("Other".to_string()),
+        ]);
+        let diagnostics = SubgraphDiagnostic::from_validation_error(&error);
+        assert_eq!(2, diagnostics.len());
+        assert_eq!(
+            SubgraphDiagnostic::new("schema", None, "Interface `Thing` not defined"),
+            diagnostics[0]
+        );
+
+        let error = SubgraphManifestValidationError::DataSourceValidation(
+            "Token".to_string(),
+            anyhow!("no handlers"),
+        );
+        assert_eq!(
+            vec![SubgraphDiagnostic::new(
+                "dataSource",
+                Some("Token".to_string()),
+                "no handlers"
+            )],
+            SubgraphDiagnostic::from_validation_error(&error)
+        );
+
+        let mut validation = SubgraphValidation::new(DeploymentHash::new("QmTest").unwrap());
+        assert!(validation.valid);
+        validation.push(SubgraphDiagnostic::new("graft", None, "no base"));
+        assert!(!validation.valid);
+    }
 }
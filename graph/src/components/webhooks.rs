@@ -0,0 +1,238 @@
+//! Post notifications about deployments being created, synced, failed, or
+//! reassigned to the URLs in `GRAPH_WEBHOOK_URLS`. Each notification is a
+//! JSON object that is posted to every URL independently, and retried with
+//! an exponential backoff until the receiver responds with a success
+//! status or we run out of attempts. If `GRAPH_WEBHOOK_SECRET` is set, the
+//! body is signed with HMAC-SHA256 and the hex-encoded signature is sent in
+//! the `X-Graph-Signature` header as `sha256=<signature>`
+use std::time::Duration;
+
+use hmac::{Hmac, Mac, NewMac};
+use serde::Serialize;
+use sha2::Sha256;
+use slog::{debug, o, warn, Logger};
+
+use crate::data::subgraph::DeploymentHash;
+use crate::env::ENV_VARS;
+use crate::prelude::BlockNumber;
+use crate::util::backoff::ExponentialBackoff;
+
+const BACKOFF_BASE: Duration = Duration::from_secs(1);
+const BACKOFF_CEIL: Duration = Duration::from_secs(300);
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Something that happened to a deployment
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum LifecycleEvent {
+    /// A version of `subgraph` with the deployment was deployed to `node`
+    DeploymentCreated {
+        subgraph: String,
+        node: String,
+        network: String,
+    },
+    /// The deployment caught up with the chain head for the first time
+    DeploymentSynced,
+    /// The deployment stopped because of a fatal error
+    DeploymentFailed {
+        error: String,
+        block: Option<BlockNumber>,
+        deterministic: bool,
+    },
+    /// The deployment was moved to the index node `to`
+    DeploymentReassigned { from: Option<String>, to: String },
+}
+
+impl LifecycleEvent {
+    fn name(&self) -> &'static str {
+        match self {
+            LifecycleEvent::DeploymentCreated { .. } => "deployment_created",
+            LifecycleEvent::DeploymentSynced => "deployment_synced",
+            LifecycleEvent::DeploymentFailed { .. } => "deployment_failed",
+            LifecycleEvent::DeploymentReassigned { .. } => "deployment_reassigned",
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct Payload<'a> {
+    deployment: &'a str,
+    timestamp: String,
+    #[serde(flatten)]
+    event: &'a LifecycleEvent,
+}
+
+pub struct Webhooks {
+    logger: Logger,
+    client: reqwest::Client,
+    urls: Vec<String>,
+    secret: Option<String>,
+    max_attempts: u32,
+}
+
+impl Webhooks {
+    pub fn new(
+        logger: &Logger,
+        urls: Vec<String>,
+        secret: Option<String>,
+        max_attempts: u32,
+    ) -> Self {
+        Webhooks {
+            logger: logger.new(o!("component" => "Webhooks")),
+            client: reqwest::Client::new(),
+            urls,
+            secret,
+            max_attempts,
+        }
+    }
+
+    /// Create webhooks from the `GRAPH_WEBHOOK_*` environment variables
+    pub fn from_env(logger: &Logger) -> Self {
+        Self::new(
+            logger,
+            ENV_VARS.webhook_urls.clone(),
+            ENV_VARS.webhook_secret.clone(),
+            ENV_VARS.webhook_max_attempts,
+        )
+    }
+
+    /// Send a notification about `event` to all URLs in the background.
+    /// This never fails; notifications that can not be delivered are
+    /// logged and dropped
+    pub fn notify(&self, deployment: &DeploymentHash, event: LifecycleEvent) {
+        if self.urls.is_empty() {
+            return;
+        }
+
+        let handle = match tokio::runtime::Handle::try_current() {
+            Ok(handle) => handle,
+            Err(_) => {
+                warn!(self.logger, "Can not send webhook notification outside of a runtime";
+                      "deployment" => deployment.as_str(),
+                      "event" => event.name());
+                return;
+            }
+        };
+
+        let payload = Payload {
+            deployment: deployment.as_str(),
+            timestamp: chrono::Utc::now().to_rfc3339(),
+            event: &event,
+        };
+        let body = serde_json::to_vec(&payload).expect("webhook payloads can be serialized");
+        let signature = self.secret.as_ref().map(|secret| sign(secret, &body));
+
+        for url in &self.urls {
+            let delivery = Delivery {
+                logger: self.logger.new(o!("url" => url.clone(),
+                                           "deployment" => deployment.to_string(),
+                                           "event" => event.name())),
+                client: self.client.clone(),
+                url: url.clone(),
+                event: event.name(),
+                body: body.clone(),
+                signature: signature.clone(),
+                max_attempts: self.max_attempts,
+            };
+            handle.spawn(delivery.run());
+        }
+    }
+}
+
+struct Delivery {
+    logger: Logger,
+    client: reqwest::Client,
+    url: String,
+    event: &'static str,
+    body: Vec<u8>,
+    signature: Option<String>,
+    max_attempts: u32,
+}
+
+impl Delivery {
+    async fn run(self) {
+        let mut backoff = ExponentialBackoff::new(BACKOFF_BASE, BACKOFF_CEIL);
+        loop {
+            let mut request = self
+                .client
+                .post(&self.url)
+                .timeout(REQUEST_TIMEOUT)
+                .header(reqwest::header::CONTENT_TYPE, "application/json")
+                .header("X-Graph-Event", self.event)
+                .body(self.body.clone());
+            if let Some(signature) = &self.signature {
+                request = request.header("X-Graph-Signature", format!("sha256={}", signature));
+            }
+
+            let error = match request.send().await {
+                Ok(response) if response.status().is_success() => {
+                    debug!(self.logger, "Delivered webhook notification");
+                    return;
+                }
+                Ok(response) => format!("receiver responded with {}", response.status()),
+                Err(e) => e.to_string(),
+            };
+
+            if backoff.attempt + 1 >= self.max_attempts as u64 {
+                warn!(self.logger, "Giving up on webhook notification";
+                      "error" => error,
+                      "attempts" => backoff.attempt + 1);
+                return;
+            }
+            warn!(self.logger, "Failed to deliver webhook notification, will retry";
+                  "error" => error,
+                  "attempt" => backoff.attempt + 1,
+                  "retry_delay_s" => backoff.delay().as_secs());
+            backoff.sleep_async().await;
+        }
+    }
+}
+
+/// The hex-encoded HMAC-SHA256 of `body` with key `secret`
+fn sign(secret: &str, body: &[u8]) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_varkey(secret.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(body);
+    hex::encode(mac.finalize().into_bytes())
+}
+
+#[test]
+fn signs_payloads() {
+    assert_eq!(
+        "f7bc83f430538424b13298e6aa6fb143ef4d59a14946175997479dbc2d1a3cd8",
+        sign("key", b"The quick brown fox jumps over the lazy dog")
+    );
+}
+
+#[test]
+fn serializes_payloads() {
+    let event = LifecycleEvent::DeploymentReassigned {
+        from: Some("index_node_0".to_string()),
+        to: "index_node_1".to_string(),
+    };
+    let payload = Payload {
+        deployment: "QmTest",
+        timestamp: "2022-04-20T10:00:00+00:00".to_string(),
+        event: &event,
+    };
+    assert_eq!(
+        serde_json::json!({
+            "deployment": "QmTest",
+            "timestamp": "2022-04-20T10:00:00+00:00",
+            "event": "deployment_reassigned",
+            "from": "index_node_0",
+            "to": "index_node_1",
+        }),
+        serde_json::to_value(&payload).unwrap()
+    );
+
+    let payload = Payload {
+        deployment: "QmTest",
+        timestamp: "2022-04-20T10:00:00+00:00".to_string(),
+        event: &LifecycleEvent::DeploymentSynced,
+    };
+    assert_eq!(
+        "deployment_synced",
+        serde_json::to_value(&payload).unwrap()["event"]
+    );
+}
@@ -11,6 +11,9 @@ pub trait ObjectTypeExt {
     fn field(&self, name: &str) -> Option<&Field>;
     fn is_meta(&self) -> bool;
     fn is_immutable(&self) -> bool;
+    /// The fields that form the composite key of the type, from a
+    /// `@compositeId(fields: [..])` directive
+    fn composite_id(&self) -> Option<Vec<&str>>;
 }
 
 impl ObjectTypeExt for ObjectType {
@@ -31,6 +34,25 @@ impl ObjectTypeExt for ObjectType {
             })
             .unwrap_or(false)
     }
+
+    fn composite_id(&self) -> Option<Vec<&str>> {
+        match self
+            .find_directive("compositeId")?
+            .argument("fields")
+            .unwrap_or(&Value::Null)
+        {
+            Value::List(fields) => Some(
+                fields
+                    .iter()
+                    .filter_map(|field| match field {
+                        Value::String(field) => Some(field.as_str()),
+                        _ => None,
+                    })
+                    .collect(),
+            ),
+            _ => Some(vec![]),
+        }
+    }
 }
 
 impl ObjectTypeExt for InterfaceType {
@@ -45,6 +67,10 @@ impl ObjectTypeExt for InterfaceType {
     fn is_immutable(&self) -> bool {
         false
     }
+
+    fn composite_id(&self) -> Option<Vec<&str>> {
+        None
+    }
 }
 
 pub trait DocumentExt {
@@ -310,6 +336,18 @@ impl ValueExt for Value {
 pub trait DirectiveFinder {
     fn find_directive(&self, name: &str) -> Option<&Directive>;
     fn is_derived(&self) -> bool;
+
+    /// The reason from the `@deprecated` directive, or `None` if there is
+    /// no such directive
+    fn deprecation_reason(&self) -> Option<String> {
+        self.find_directive("deprecated").map(|directive| {
+            directive
+                .argument("reason")
+                .and_then(Value::as_str)
+                .unwrap_or("No longer supported")
+                .to_string()
+        })
+    }
 }
 
 impl DirectiveFinder for ObjectType {
@@ -422,6 +460,28 @@ mod directive_finder_tests {
         assert!(fields[1].find_directive("derivedFrom").is_some());
     }
 
+    #[test]
+    fn deprecation_reason() {
+        const SCHEMA: &str = "
+        type Token @entity {
+            id: ID!
+            symbol: String! @deprecated(reason: \"Use `ticker`\")
+            decimals: Int! @deprecated
+        }";
+        let ast = parse_schema::<String>(SCHEMA).unwrap();
+        let fields = &ast.get_object_type_definitions()[0].fields;
+
+        assert_eq!(None, fields[0].deprecation_reason());
+        assert_eq!(
+            Some("Use `ticker`".to_string()),
+            fields[1].deprecation_reason()
+        );
+        assert_eq!(
+            Some("No longer supported".to_string()),
+            fields[2].deprecation_reason()
+        );
+    }
+
     /// Makes sure that the DirectiveFinder::is_derived implementation for ObjectiveType and Field works
     #[test]
     fn is_derived_impls() {
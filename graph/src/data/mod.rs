@@ -7,6 +7,9 @@ pub mod query;
 /// Data types for dealing with GraphQL schemas.
 pub mod schema;
 
+/// Comparing the schemas of different versions of a subgraph.
+pub mod schema_diff;
+
 /// Data types for dealing with storing entities.
 pub mod store;
 
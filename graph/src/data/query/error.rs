@@ -48,7 +48,8 @@ pub enum QueryExecutionError {
     OrderByNotSupportedError(String, String),
     OrderByNotSupportedForType(String),
     FilterNotSupportedError(String, String),
-    UnknownField(Pos, String, String),
+    UnknownField(Pos, String, String, Option<String>),
+    UnknownArgument(Pos, String, String, Option<String>),
     EmptyQuery,
     MultipleSubscriptionFields,
     SubgraphDeploymentIdError(String),
@@ -83,6 +84,12 @@ pub enum QueryExecutionError {
     SubgraphManifestResolveError(Arc<SubgraphManifestResolveError>),
     InvalidSubgraphManifest,
     ResultTooBig(usize, usize),
+    InvalidSql(String),
+    SqlTooExpensive(u64, u64),         // (cost, max_cost)
+    TooManyRows(usize),                // max_rows
+    RegexFilterTooExpensive(u64, u64), // (cost, max_cost)
+    RegexFilterTimeout(u64),           // timeout in seconds
+    DatabaseUnavailable,
 }
 
 impl QueryExecutionError {
@@ -102,7 +109,8 @@ impl QueryExecutionError {
             | OrderByNotSupportedError(_, _)
             | OrderByNotSupportedForType(_)
             | FilterNotSupportedError(_, _)
-            | UnknownField(_, _, _)
+            | UnknownField(_, _, _, _)
+            | UnknownArgument(_, _, _, _)
             | EmptyQuery
             | MultipleSubscriptionFields
             | SubgraphDeploymentIdError(_)
@@ -116,7 +124,8 @@ impl QueryExecutionError {
             | CyclicalFragment(_)
             | UndefinedFragment(_)
             | FulltextQueryInvalidSyntax(_)
-            | FulltextQueryRequiresFilter => true,
+            | FulltextQueryRequiresFilter
+            | InvalidSql(_) => true,
             NonNullError(_, _)
             | ListValueError(_, _)
             | ResolveEntitiesError(_)
@@ -139,7 +148,12 @@ impl QueryExecutionError {
             | SubgraphManifestResolveError(_)
             | InvalidSubgraphManifest
             | ValidationError(_, _)
-            | ResultTooBig(_, _) => false,
+            | ResultTooBig(_, _)
+            | SqlTooExpensive(_, _)
+            | TooManyRows(_)
+            | RegexFilterTooExpensive(_, _)
+            | RegexFilterTimeout(_)
+            | DatabaseUnavailable => false,
         }
     }
 }
@@ -206,8 +220,19 @@ impl fmt::Display for QueryExecutionError {
             FilterNotSupportedError(value, filter) => {
                 write!(f, "Filter not supported by value `{}`: `{}`", value, filter)
             }
-            UnknownField(_, t, s) => {
-                write!(f, "Type `{}` has no field `{}`", t, s)
+            UnknownField(_, t, s, suggestion) => {
+                write!(f, "Type `{}` has no field `{}`", t, s)?;
+                if let Some(suggestion) = suggestion {
+                    write!(f, "; did you mean `{}`?", suggestion)?;
+                }
+                Ok(())
+            }
+            UnknownArgument(_, field, arg, suggestion) => {
+                write!(f, "Field `{}` has no argument `{}`", field, arg)?;
+                if let Some(suggestion) = suggestion {
+                    write!(f, "; did you mean `{}`?", suggestion)?;
+                }
+                Ok(())
             }
             EmptyQuery => write!(f, "The query is empty"),
             MultipleSubscriptionFields => write!(
@@ -287,6 +312,12 @@ impl fmt::Display for QueryExecutionError {
             SubgraphManifestResolveError(e) => write!(f, "failed to resolve subgraph manifest: {}", e),
             InvalidSubgraphManifest => write!(f, "invalid subgraph manifest file"),
             ResultTooBig(actual, limit) => write!(f, "the result size of {} is larger than the allowed limit of {}", actual, limit),
+            InvalidSql(msg) => write!(f, "invalid SQL query: {}", msg),
+            SqlTooExpensive(cost, max_cost) => write!(f, "the estimated cost {} of the SQL query is larger than the allowed limit of {}", cost, max_cost),
+            TooManyRows(max_rows) => write!(f, "the SQL query returned more than {} rows", max_rows),
+            RegexFilterTooExpensive(cost, max_cost) => write!(f, "the estimated cost {} of the query with a `_matches` filter is larger than the allowed limit of {}", cost, max_cost),
+            RegexFilterTimeout(secs) => write!(f, "the query with a `_matches` filter was canceled because it took longer than {} seconds", secs),
+            DatabaseUnavailable => write!(f, "the database that stores this subgraph is currently unavailable"),
         }
     }
 }
@@ -317,7 +348,10 @@ impl From<bigdecimal::ParseBigDecimalError> for QueryExecutionError {
 
 impl From<StoreError> for QueryExecutionError {
     fn from(e: StoreError) -> Self {
-        QueryExecutionError::StoreError(CloneableAnyhowError(Arc::new(e.into())))
+        match e {
+            StoreError::DatabaseUnavailable => QueryExecutionError::DatabaseUnavailable,
+            e => QueryExecutionError::StoreError(CloneableAnyhowError(Arc::new(e.into()))),
+        }
     }
 }
 
@@ -334,6 +368,9 @@ pub enum QueryError {
     ParseError(Arc<anyhow::Error>),
     ExecutionError(QueryExecutionError),
     IndexingError,
+    /// The client sent only the hash of a persisted query, and we do not
+    /// know the query for it; the client should resend it with the query
+    PersistedQueryNotFound,
 }
 
 impl QueryError {
@@ -341,7 +378,7 @@ impl QueryError {
         match self {
             QueryError::EncodingError(_) | QueryError::ParseError(_) => true,
             QueryError::ExecutionError(err) => err.is_attestable(),
-            QueryError::IndexingError => false,
+            QueryError::IndexingError | QueryError::PersistedQueryNotFound => false,
         }
     }
 }
@@ -381,6 +418,10 @@ impl fmt::Display for QueryError {
 
             // This error message is part of attestable responses.
             QueryError::IndexingError => write!(f, "indexing_error"),
+
+            // Clients that implement automatic persisted queries look for
+            // exactly this message
+            QueryError::PersistedQueryNotFound => write!(f, "PersistedQueryNotFound"),
         }
     }
 }
@@ -392,15 +433,12 @@ impl Serialize for QueryError {
     {
         use self::QueryExecutionError::*;
 
-        let entry_count =
-            if let QueryError::ExecutionError(QueryExecutionError::IncorrectPrefetchResult {
-                ..
-            }) = self
-            {
-                3
-            } else {
-                1
-            };
+        let entry_count = match self {
+            QueryError::ExecutionError(QueryExecutionError::IncorrectPrefetchResult { .. }) => 3,
+            QueryError::ExecutionError(QueryExecutionError::DatabaseUnavailable)
+            | QueryError::PersistedQueryNotFound => 2,
+            _ => 1,
+        };
         let mut map = serializer.serialize_map(Some(entry_count))?;
 
         let msg = match self {
@@ -450,7 +488,8 @@ impl Serialize for QueryError {
             | QueryError::ExecutionError(AmbiguousDerivedFromResult(pos, _, _, _))
             | QueryError::ExecutionError(EnumCoercionError(pos, _, _, _, _))
             | QueryError::ExecutionError(ScalarCoercionError(pos, _, _, _))
-            | QueryError::ExecutionError(UnknownField(pos, _, _)) => {
+            | QueryError::ExecutionError(UnknownField(pos, _, _, _))
+            | QueryError::ExecutionError(UnknownArgument(pos, _, _, _)) => {
                 let mut location = HashMap::new();
                 location.insert("line", pos.line);
                 location.insert("column", pos.column);
@@ -463,6 +502,20 @@ impl Serialize for QueryError {
                 map.serialize_entry("prefetch", &SerializableValue(prefetch))?;
                 format!("{}", self)
             }
+            // Give clients a way to tell that they should retry later,
+            // possibly against a different node
+            QueryError::ExecutionError(DatabaseUnavailable) => {
+                let mut extensions = HashMap::new();
+                extensions.insert("code", "DATABASE_UNAVAILABLE");
+                map.serialize_entry("extensions", &extensions)?;
+                format!("{}", self)
+            }
+            QueryError::PersistedQueryNotFound => {
+                let mut extensions = HashMap::new();
+                extensions.insert("code", "PERSISTED_QUERY_NOT_FOUND");
+                map.serialize_entry("extensions", &extensions)?;
+                format!("{}", self)
+            }
             _ => format!("{}", self),
         };
 
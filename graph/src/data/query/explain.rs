@@ -0,0 +1,46 @@
+use serde::Serialize;
+
+/// How the store answered the query for the entities of one GraphQL
+/// field. Reported in the `extensions` of a response when the query was
+/// run with `explain` turned on
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct QueryExplanation {
+    /// The response key of the field
+    pub field: String,
+    /// The entity types that the query selected from
+    pub entity_types: Vec<String>,
+    /// The SQL query, including the values of bind variables
+    pub sql: String,
+    /// The number of rows the planner expected the query to return
+    pub estimated_rows: Option<f64>,
+    /// The number of rows the query actually returned
+    pub actual_rows: Option<f64>,
+    /// The plan for the query as produced by
+    /// `explain (analyze, buffers, format json)`
+    pub plan: serde_json::Value,
+}
+
+impl QueryExplanation {
+    pub fn new(entity_types: Vec<String>, sql: String, plan: serde_json::Value) -> Self {
+        let estimated_rows = plan[0]["Plan"]["Plan Rows"].as_f64();
+        let actual_rows = plan[0]["Plan"]["Actual Rows"].as_f64();
+        QueryExplanation {
+            field: String::new(),
+            entity_types,
+            sql,
+            estimated_rows,
+            actual_rows,
+            plan,
+        }
+    }
+}
+
+#[test]
+fn row_counts_from_plan() {
+    let plan = serde_json::json!([{ "Plan": { "Plan Rows": 10.0, "Actual Rows": 3.0 } }]);
+    let explanation =
+        QueryExplanation::new(vec!["Thing".to_string()], "select 1".to_string(), plan);
+    assert_eq!(Some(10.0), explanation.estimated_rows);
+    assert_eq!(Some(3.0), explanation.actual_rows);
+}
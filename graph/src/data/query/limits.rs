@@ -0,0 +1,147 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::data::subgraph::DeploymentHash;
+use crate::env::ENV_VARS;
+
+/// The limits that are checked when a query is validated, before any of it
+/// gets executed. Queries that violate any of these limits are rejected with
+/// an error that states the limit that was exceeded
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct QueryLimits {
+    /// The maximum complexity of a query; `None` means no limit
+    pub max_complexity: Option<u64>,
+    /// The maximum depth of nested selections
+    pub max_depth: u8,
+    /// The maximum value for the `first` argument of collection fields
+    pub max_first: u32,
+    /// The maximum value for the `skip` argument of collection fields
+    pub max_skip: u32,
+}
+
+impl QueryLimits {
+    /// The limits set through `GRAPH_GRAPHQL_MAX_COMPLEXITY`,
+    /// `GRAPH_GRAPHQL_MAX_DEPTH`, `GRAPH_GRAPHQL_MAX_FIRST`, and
+    /// `GRAPH_GRAPHQL_MAX_SKIP`
+    pub fn from_env() -> Self {
+        QueryLimits {
+            max_complexity: ENV_VARS.graphql.max_complexity,
+            max_depth: ENV_VARS.graphql.max_depth,
+            max_first: ENV_VARS.graphql.max_first,
+            max_skip: ENV_VARS.graphql.max_skip,
+        }
+    }
+
+    /// Limits that never reject a query. Only meant for internal queries,
+    /// like the ones the index node server runs, and for tests
+    pub fn unlimited() -> Self {
+        QueryLimits {
+            max_complexity: None,
+            max_depth: u8::MAX,
+            max_first: u32::MAX,
+            max_skip: u32::MAX,
+        }
+    }
+
+    /// Return these limits with any of the values in `overrides` that are
+    /// set replacing the corresponding value
+    pub fn with_overrides(mut self, overrides: &QueryLimitOverrides) -> Self {
+        if let Some(max_complexity) = overrides.max_complexity {
+            self.max_complexity = Some(max_complexity);
+        }
+        if let Some(max_depth) = overrides.max_depth {
+            self.max_depth = max_depth;
+        }
+        if let Some(max_first) = overrides.max_first {
+            self.max_first = max_first;
+        }
+        if let Some(max_skip) = overrides.max_skip {
+            self.max_skip = max_skip;
+        }
+        self
+    }
+}
+
+impl Default for QueryLimits {
+    fn default() -> Self {
+        Self::from_env()
+    }
+}
+
+/// A partial set of limits; values that are not set leave the limit they
+/// are applied to unchanged
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct QueryLimitOverrides {
+    pub max_complexity: Option<u64>,
+    pub max_depth: Option<u8>,
+    pub max_first: Option<u32>,
+    pub max_skip: Option<u32>,
+}
+
+/// The query limits for a node, together with limits for specific
+/// deployments that differ from the node's limits
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct DeploymentQueryLimits {
+    node: QueryLimits,
+    deployments: HashMap<DeploymentHash, QueryLimits>,
+}
+
+impl DeploymentQueryLimits {
+    pub fn new(node: QueryLimits) -> Self {
+        DeploymentQueryLimits {
+            node,
+            deployments: HashMap::new(),
+        }
+    }
+
+    /// Use limits that are derived from the node's limits by applying
+    /// `overrides` for queries against `deployment`
+    pub fn add_deployment(&mut self, deployment: DeploymentHash, overrides: &QueryLimitOverrides) {
+        self.deployments
+            .insert(deployment, self.node.with_overrides(overrides));
+    }
+
+    /// The limits that apply to queries against `deployment`
+    pub fn for_deployment(&self, deployment: &DeploymentHash) -> QueryLimits {
+        self.deployments
+            .get(deployment)
+            .copied()
+            .unwrap_or(self.node)
+    }
+
+    pub fn node(&self) -> QueryLimits {
+        self.node
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn deployment_limits_override_node_limits() {
+        let node = QueryLimits {
+            max_complexity: None,
+            max_depth: 20,
+            max_first: 1000,
+            max_skip: 5000,
+        };
+        let mut limits = DeploymentQueryLimits::new(node);
+        let big = DeploymentHash::new("QmBig").unwrap();
+        let other = DeploymentHash::new("QmOther").unwrap();
+        limits.add_deployment(
+            big.clone(),
+            &QueryLimitOverrides {
+                max_first: Some(5000),
+                ..Default::default()
+            },
+        );
+
+        let big_limits = limits.for_deployment(&big);
+        assert_eq!(5000, big_limits.max_first);
+        assert_eq!(20, big_limits.max_depth);
+        assert_eq!(5000, big_limits.max_skip);
+        assert_eq!(node, limits.for_deployment(&other));
+    }
+}
@@ -1,9 +1,13 @@
 mod cache_status;
 mod error;
+mod explain;
+mod limits;
 mod query;
 mod result;
 
 pub use self::cache_status::CacheStatus;
 pub use self::error::{QueryError, QueryExecutionError};
+pub use self::explain::QueryExplanation;
+pub use self::limits::{DeploymentQueryLimits, QueryLimitOverrides, QueryLimits};
 pub use self::query::{Query, QueryTarget, QueryVariables};
-pub use self::result::{QueryResult, QueryResults};
+pub use self::result::{Freshness, QueryResult, QueryResults, QueryWarning};
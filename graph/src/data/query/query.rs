@@ -110,7 +110,7 @@ impl serde::ser::Serialize for QueryVariables {
     }
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub enum QueryTarget {
     Name(SubgraphName),
     Deployment(DeploymentHash),
@@ -136,6 +136,10 @@ pub struct Query {
     pub shape_hash: u64,
     pub query_text: Arc<String>,
     pub variables_text: Arc<String>,
+    /// Whether to report the SQL queries and Postgres plans used to
+    /// answer this query in the `extensions` of the response. Such queries
+    /// bypass the query cache
+    pub explain: bool,
     _force_use_of_new: (),
 }
 
@@ -160,6 +164,7 @@ impl Query {
             shape_hash,
             query_text: Arc::new(query_text),
             variables_text: Arc::new(variables_text),
+            explain: false,
             _force_use_of_new: (),
         }
     }
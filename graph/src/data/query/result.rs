@@ -1,14 +1,16 @@
 use super::error::{QueryError, QueryExecutionError};
+use super::explain::QueryExplanation;
 use crate::data::value::Object;
-use crate::prelude::{r, CacheWeight, DeploymentHash};
+use crate::prelude::{r, BlockNumber, BlockPtr, CacheWeight, DeploymentHash};
 use http::header::{
     ACCESS_CONTROL_ALLOW_HEADERS, ACCESS_CONTROL_ALLOW_METHODS, ACCESS_CONTROL_ALLOW_ORIGIN,
-    CONTENT_TYPE,
+    CACHE_CONTROL, CONTENT_TYPE,
 };
 use serde::ser::*;
 use serde::Serialize;
 use std::convert::TryFrom;
 use std::sync::Arc;
+use std::time::Duration;
 
 fn serialize_data<S>(data: &Option<Data>, serializer: S) -> Result<S::Ok, S::Error>
 where
@@ -23,6 +25,37 @@ where
     ser.end()
 }
 
+/// Serialize the block of a subscription result as `{ "_meta": { "block":
+/// { "number": .., "hash": ".." } } }`
+fn serialize_block_meta<S>(block: &Option<BlockPtr>, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    #[derive(Serialize)]
+    struct Block {
+        number: BlockNumber,
+        hash: String,
+    }
+    #[derive(Serialize)]
+    struct Meta {
+        block: Block,
+    }
+
+    // Unwrap: the block is only serialized if it is `Some`.
+    let block = block.as_ref().unwrap();
+    let mut ser = serializer.serialize_map(None)?;
+    ser.serialize_entry(
+        "_meta",
+        &Meta {
+            block: Block {
+                number: block.number,
+                hash: format!("0x{}", block.hash_hex()),
+            },
+        },
+    )?;
+    ser.end()
+}
+
 fn serialize_value_map<'a, S>(
     data: impl Iterator<Item = &'a Data>,
     serializer: S,
@@ -45,12 +78,93 @@ pub type Data = Object;
 /// A collection of query results that is serialized as a single result.
 pub struct QueryResults {
     results: Vec<Arc<QueryResult>>,
+    /// How long HTTP caches may keep the response. A zero duration means
+    /// that it may not be cached at all; `None` that we do not say
+    max_age: Option<Duration>,
+    /// Reported in the `warnings` extension of the response
+    warnings: Vec<QueryWarning>,
+    /// Reported in the `freshness` extension of the response
+    freshness: Option<Freshness>,
+}
+
+/// Something about the subgraph that was queried that clients should know
+/// about, even though the query succeeded
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct QueryWarning {
+    pub message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub subgraph: Option<String>,
+    /// When the subgraph will stop being served, as `YYYY-MM-DD`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub removal_date: Option<String>,
+}
+
+/// How far the deployment that answered a query is behind its chain
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Freshness {
+    /// The block the deployment has processed
+    pub block: BlockNumber,
+    pub chain_head: Option<BlockNumber>,
+    pub blocks_behind: Option<BlockNumber>,
+    /// The timestamp of `block`, in seconds since the epoch
+    pub last_indexed_at: Option<u64>,
+    /// How many seconds ago `block` was produced
+    pub seconds_behind: Option<u64>,
+}
+
+impl Freshness {
+    /// The freshness of a deployment at `block` on a chain whose head is
+    /// `chain_head`, when `block` has timestamp `timestamp` and it is `now`
+    /// in seconds since the epoch
+    pub fn new(
+        block: BlockNumber,
+        chain_head: Option<BlockNumber>,
+        timestamp: Option<u64>,
+        now: u64,
+    ) -> Self {
+        Freshness {
+            block,
+            chain_head,
+            blocks_behind: chain_head.map(|head| (head - block).max(0)),
+            last_indexed_at: timestamp,
+            seconds_behind: timestamp.map(|timestamp| now.saturating_sub(timestamp)),
+        }
+    }
 }
 
 impl QueryResults {
     pub fn empty() -> Self {
         QueryResults {
             results: Vec::new(),
+            max_age: None,
+            warnings: Vec::new(),
+            freshness: None,
+        }
+    }
+
+    pub fn add_warning(&mut self, warning: QueryWarning) {
+        self.warnings.push(warning);
+    }
+
+    pub fn set_freshness(&mut self, freshness: Freshness) {
+        self.freshness = Some(freshness);
+    }
+
+    pub fn set_max_age(&mut self, max_age: Duration) {
+        self.max_age = Some(max_age);
+    }
+
+    /// The value for the `Cache-Control` header of the response, if any.
+    /// Responses with errors are never cached since the errors might be
+    /// transient
+    pub fn cache_control(&self) -> Option<String> {
+        let max_age = self.max_age?;
+        if max_age.as_secs() == 0 || self.results.iter().any(|r| r.has_errors()) {
+            Some("no-store".to_string())
+        } else {
+            Some(format!("public, max-age={}", max_age.as_secs()))
         }
     }
 
@@ -70,6 +184,12 @@ impl Serialize for QueryResults {
         if has_errors {
             len += 1;
         }
+        let has_explanations = self.results.iter().any(|r| !r.explanations.is_empty());
+        let has_extensions =
+            has_explanations || !self.warnings.is_empty() || self.freshness.is_some();
+        if has_extensions {
+            len += 1;
+        }
 
         let mut state = serializer.serialize_struct("QueryResults", len)?;
 
@@ -106,6 +226,43 @@ impl Serialize for QueryResults {
             state.serialize_field("errors", &SerError(self))?;
         }
 
+        // Serialize explanations, warnings and freshness as
+        // `extensions: { explain: [..], warnings: [..], freshness: {..} }`
+        if has_extensions {
+            struct SerExplanations<'a>(&'a QueryResults);
+
+            impl Serialize for SerExplanations<'_> {
+                fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                    let mut seq = serializer.serialize_seq(None)?;
+                    for explanation in self.0.results.iter().map(|r| &r.explanations).flatten() {
+                        seq.serialize_element(explanation)?;
+                    }
+                    seq.end()
+                }
+            }
+
+            struct SerExtensions<'a>(&'a QueryResults, bool);
+
+            impl Serialize for SerExtensions<'_> {
+                fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                    let has_explanations = self.1;
+                    let mut map = serializer.serialize_map(None)?;
+                    if has_explanations {
+                        map.serialize_entry("explain", &SerExplanations(self.0))?;
+                    }
+                    if !self.0.warnings.is_empty() {
+                        map.serialize_entry("warnings", &self.0.warnings)?;
+                    }
+                    if let Some(freshness) = &self.0.freshness {
+                        map.serialize_entry("freshness", freshness)?;
+                    }
+                    map.end()
+                }
+            }
+
+            state.serialize_field("extensions", &SerExtensions(self, has_explanations))?;
+        }
+
         state.end()
     }
 }
@@ -114,6 +271,9 @@ impl From<Data> for QueryResults {
     fn from(x: Data) -> Self {
         QueryResults {
             results: vec![Arc::new(x.into())],
+            max_age: None,
+            warnings: Vec::new(),
+            freshness: None,
         }
     }
 }
@@ -122,13 +282,21 @@ impl From<QueryResult> for QueryResults {
     fn from(x: QueryResult) -> Self {
         QueryResults {
             results: vec![Arc::new(x)],
+            max_age: None,
+            warnings: Vec::new(),
+            freshness: None,
         }
     }
 }
 
 impl From<Arc<QueryResult>> for QueryResults {
     fn from(x: Arc<QueryResult>) -> Self {
-        QueryResults { results: vec![x] }
+        QueryResults {
+            results: vec![x],
+            max_age: None,
+            warnings: Vec::new(),
+            freshness: None,
+        }
     }
 }
 
@@ -136,6 +304,9 @@ impl From<QueryExecutionError> for QueryResults {
     fn from(x: QueryExecutionError) -> Self {
         QueryResults {
             results: vec![Arc::new(x.into())],
+            max_age: None,
+            warnings: Vec::new(),
+            freshness: None,
         }
     }
 }
@@ -144,6 +315,9 @@ impl From<Vec<QueryExecutionError>> for QueryResults {
     fn from(x: Vec<QueryExecutionError>) -> Self {
         QueryResults {
             results: vec![Arc::new(x.into())],
+            max_age: None,
+            warnings: Vec::new(),
+            freshness: None,
         }
     }
 }
@@ -154,10 +328,40 @@ impl QueryResults {
     }
 
     pub fn as_http_response<T: From<String>>(&self) -> http::Response<T> {
-        let status_code = http::StatusCode::OK;
         let json =
             serde_json::to_string(self).expect("Failed to serialize GraphQL response to JSON");
-        http::Response::builder()
+        self.http_response(json)
+    }
+
+    /// The response for a batch of queries; its body is the array of their
+    /// results. It may only be cached if all of the results may be, and
+    /// only for as long as the one that may be cached the shortest
+    pub fn batch_http_response<T: From<String>>(batch: &[QueryResults]) -> http::Response<T> {
+        let json =
+            serde_json::to_string(batch).expect("Failed to serialize GraphQL response to JSON");
+        let combined = QueryResults {
+            results: batch
+                .iter()
+                .flat_map(|results| results.results.iter().cloned())
+                .collect(),
+            max_age: batch
+                .iter()
+                .map(|results| results.max_age)
+                .collect::<Option<Vec<_>>>()
+                .and_then(|max_ages| max_ages.into_iter().min()),
+            warnings: Vec::new(),
+            freshness: None,
+        };
+        combined.http_response(json)
+    }
+
+    fn http_response<T: From<String>>(&self, json: String) -> http::Response<T> {
+        let status_code = http::StatusCode::OK;
+        let mut builder = http::Response::builder();
+        if let Some(cache_control) = self.cache_control() {
+            builder = builder.header(CACHE_CONTROL, cache_control);
+        }
+        builder
             .status(status_code)
             .header(ACCESS_CONTROL_ALLOW_ORIGIN, "*")
             .header(ACCESS_CONTROL_ALLOW_HEADERS, "Content-Type, User-Agent")
@@ -184,6 +388,18 @@ pub struct QueryResult {
     errors: Vec<QueryError>,
     #[serde(skip_serializing)]
     pub deployment: Option<DeploymentHash>,
+    /// How the store answered the query; only filled in for queries that
+    /// were run with `explain`
+    #[serde(skip_serializing)]
+    pub explanations: Vec<QueryExplanation>,
+    /// The block at which the result was computed; only filled in for the
+    /// results of subscriptions so that clients can order and dedupe them
+    #[serde(
+        rename = "extensions",
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "serialize_block_meta"
+    )]
+    pub block: Option<BlockPtr>,
 }
 
 impl QueryResult {
@@ -192,6 +408,8 @@ impl QueryResult {
             data: Some(data),
             errors: Vec::new(),
             deployment: None,
+            explanations: Vec::new(),
+            block: None,
         }
     }
 
@@ -204,6 +422,8 @@ impl QueryResult {
             data: self.data.clone(),
             errors: self.errors.clone(),
             deployment: self.deployment.clone(),
+            explanations: self.explanations.clone(),
+            block: self.block.clone(),
         }
     }
 
@@ -246,6 +466,8 @@ impl From<QueryExecutionError> for QueryResult {
             data: None,
             errors: vec![e.into()],
             deployment: None,
+            explanations: Vec::new(),
+            block: None,
         }
     }
 }
@@ -256,6 +478,8 @@ impl From<QueryError> for QueryResult {
             data: None,
             errors: vec![e],
             deployment: None,
+            explanations: Vec::new(),
+            block: None,
         }
     }
 }
@@ -266,6 +490,8 @@ impl From<Vec<QueryExecutionError>> for QueryResult {
             data: None,
             errors: e.into_iter().map(QueryError::from).collect(),
             deployment: None,
+            explanations: Vec::new(),
+            block: None,
         }
     }
 }
@@ -326,3 +552,173 @@ fn multiple_data_items() {
     let actual = serde_json::to_string(&res).unwrap();
     assert_eq!(expected, actual)
 }
+
+#[test]
+fn explanations_are_extensions() {
+    use serde_json::json;
+
+    let mut map = Object::new();
+    map.insert("key".to_owned(), r::Value::String("value".to_owned()));
+    let mut result = QueryResult::from(map);
+    result.explanations.push(QueryExplanation::new(
+        vec!["Thing".to_string()],
+        "select 1".to_string(),
+        json!([{ "Plan": { "Plan Rows": 1.0 } }]),
+    ));
+    let res = QueryResults::from(result);
+
+    let actual = serde_json::to_value(&res).unwrap();
+    assert_eq!(json!({"key": "value"}), actual["data"]);
+    assert_eq!(json!("select 1"), actual["extensions"]["explain"][0]["sql"]);
+    assert_eq!(
+        json!(1.0),
+        actual["extensions"]["explain"][0]["estimatedRows"]
+    );
+}
+
+#[test]
+fn warnings_are_extensions() {
+    use serde_json::json;
+
+    let mut map = Object::new();
+    map.insert("key".to_owned(), r::Value::String("value".to_owned()));
+    let mut res = QueryResults::from(map);
+    assert!(serde_json::to_value(&res)
+        .unwrap()
+        .get("extensions")
+        .is_none());
+
+    res.add_warning(QueryWarning {
+        message: "going away".to_string(),
+        subgraph: Some("a/b".to_string()),
+        removal_date: Some("2022-10-01".to_string()),
+    });
+    let actual = serde_json::to_value(&res).unwrap();
+    assert_eq!(json!({"key": "value"}), actual["data"]);
+    assert_eq!(
+        json!({"warnings": [{
+            "message": "going away",
+            "subgraph": "a/b",
+            "removalDate": "2022-10-01"
+        }]}),
+        actual["extensions"]
+    );
+}
+
+#[test]
+fn freshness_is_an_extension() {
+    use serde_json::json;
+
+    let mut map = Object::new();
+    map.insert("key".to_owned(), r::Value::String("value".to_owned()));
+    let mut res = QueryResults::from(map);
+    res.set_freshness(Freshness::new(100, Some(107), Some(1_000), 1_084));
+    assert_eq!(
+        json!({"freshness": {
+            "block": 100,
+            "chainHead": 107,
+            "blocksBehind": 7,
+            "lastIndexedAt": 1000,
+            "secondsBehind": 84
+        }}),
+        serde_json::to_value(&res).unwrap()["extensions"]
+    );
+
+    // A deployment can briefly be ahead of what the store thinks the
+    // chain head is, and a block can have a timestamp in the future
+    let freshness = Freshness::new(110, Some(107), None, 1_084);
+    assert_eq!(Some(0), freshness.blocks_behind);
+    assert_eq!(None, freshness.seconds_behind);
+    assert_eq!(
+        Some(0),
+        Freshness::new(110, None, Some(2_000), 1_084).seconds_behind
+    );
+}
+
+#[test]
+fn cache_control() {
+    let mut map = Object::new();
+    map.insert("key".to_owned(), r::Value::String("value".to_owned()));
+    let mut res = QueryResults::from(map);
+    assert_eq!(None, res.cache_control());
+
+    res.set_max_age(Duration::from_secs(60));
+    assert_eq!(Some("public, max-age=60".to_string()), res.cache_control());
+
+    res.set_max_age(Duration::from_secs(0));
+    assert_eq!(Some("no-store".to_string()), res.cache_control());
+
+    let mut res = QueryResults::from(QueryExecutionError::Timeout);
+    res.set_max_age(Duration::from_secs(60));
+    assert_eq!(Some("no-store".to_string()), res.cache_control());
+}
+
+#[test]
+fn batch_responses_are_cached_as_briefly_as_their_parts() {
+    let mut map = Object::new();
+    map.insert("key".to_owned(), r::Value::String("value".to_owned()));
+    let mut long = QueryResults::from(map.clone());
+    long.set_max_age(Duration::from_secs(600));
+    let mut short = QueryResults::from(map.clone());
+    short.set_max_age(Duration::from_secs(60));
+
+    let response: http::Response<String> = QueryResults::batch_http_response(&[long, short]);
+    assert_eq!("public, max-age=60", response.headers()[CACHE_CONTROL]);
+    let body: serde_json::Value = serde_json::from_str(response.body()).unwrap();
+    assert_eq!(2, body.as_array().unwrap().len());
+
+    let mut cached = QueryResults::from(map.clone());
+    cached.set_max_age(Duration::from_secs(60));
+    let response: http::Response<String> =
+        QueryResults::batch_http_response(&[cached, QueryResults::from(map)]);
+    assert!(response.headers().get(CACHE_CONTROL).is_none());
+}
+
+#[test]
+fn subscription_block_is_an_extension() {
+    use serde_json::json;
+
+    let mut map = Object::new();
+    map.insert("key".to_owned(), r::Value::String("value".to_owned()));
+    let mut result = QueryResult::from(map);
+    assert_eq!(
+        json!({"data": {"key": "value"}}),
+        serde_json::to_value(&result).unwrap()
+    );
+
+    result.block = Some(BlockPtr::from((
+        crate::prelude::web3::types::H256::from_low_u64_be(7),
+        7u64,
+    )));
+    let expected = json!({
+        "data": {"key": "value"},
+        "extensions": {
+            "_meta": {
+                "block": {
+                    "number": 7,
+                    "hash": "0x0000000000000000000000000000000000000000000000000000000000000007"
+                }
+            }
+        }
+    });
+    assert_eq!(expected, serde_json::to_value(&result).unwrap());
+}
+
+#[test]
+fn unavailable_database_has_error_code() {
+    use crate::components::store::StoreError;
+    use serde_json::json;
+
+    // Clients can tell that they should retry, possibly elsewhere, and the
+    // error does not end up in attestations
+    let err = QueryExecutionError::from(StoreError::DatabaseUnavailable);
+    assert!(!err.is_attestable());
+    let res = QueryResults::from(err);
+    assert_eq!(
+        json!([{
+            "message": "the database that stores this subgraph is currently unavailable",
+            "extensions": { "code": "DATABASE_UNAVAILABLE" }
+        }]),
+        serde_json::to_value(&res).unwrap()["errors"]
+    );
+}
@@ -15,6 +15,7 @@ use graphql_parser::{self, Pos};
 use inflector::Inflector;
 use itertools::Itertools;
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 use thiserror::Error;
 
 use std::collections::{BTreeMap, HashMap, HashSet};
@@ -112,6 +113,10 @@ pub enum SchemaValidationError {
     FulltextIncludedFieldMissingRequiredProperty,
     #[error("Fulltext entity field, {0}, not found or not a string")]
     FulltextIncludedFieldInvalid(String),
+    #[error("Type `{0}` has an invalid @compositeId: {1}")]
+    InvalidCompositeId(String, String), // (type, reason)
+    #[error("Field `{1}` in type `{0}` has an invalid @constraint: {2}")]
+    InvalidConstraint(String, String, String), // (type, field, reason)
 }
 
 #[derive(Clone, Debug, PartialEq)]
@@ -568,6 +573,145 @@ fn add_introspection_schema(schema: &mut Document) {
     query_type.fields.append(&mut introspection_fields());
 }
 
+/// The constraints that a directive like `@constraint(min: 0, max: 100)`,
+/// `@constraint(pattern: "^0x")` or `@constraint(maxLength: 64)` on a field
+/// puts on its values. They are checked whenever an entity is saved, and for
+/// list fields apply to each element of the list
+#[derive(Clone, Debug, PartialEq)]
+pub struct FieldConstraint {
+    pub min: Option<scalar::BigDecimal>,
+    pub max: Option<scalar::BigDecimal>,
+    pub max_length: Option<usize>,
+    pub pattern: Option<ConstraintPattern>,
+}
+
+/// A regular expression that string values must match somewhere
+#[derive(Clone, Debug)]
+pub struct ConstraintPattern(regex::Regex);
+
+impl PartialEq for ConstraintPattern {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.as_str() == other.0.as_str()
+    }
+}
+
+impl FieldConstraint {
+    /// Parse the `@constraint` directive of `field` if it has one. `min`
+    /// and `max` can only be used on fields of type `Int`, `BigInt` and
+    /// `BigDecimal`, `pattern` only on `String` fields, and `maxLength` on
+    /// `String` and `Bytes` fields
+    fn from_field(field: &s::Field) -> Result<Option<Self>, String> {
+        let directive = match field.find_directive("constraint") {
+            Some(directive) => directive,
+            None => return Ok(None),
+        };
+        let base_type = field.field_type.get_base_type();
+        let numeric = matches!(base_type, "Int" | "BigInt" | "BigDecimal");
+
+        let number = |name: &str, value: &Value| {
+            if !numeric {
+                return Err(format!("`{}` can only be used on numeric fields", name));
+            }
+            match value {
+                Value::Int(n) => n.as_i64().map(scalar::BigDecimal::from),
+                Value::Float(f) => Some(scalar::BigDecimal::from(*f)),
+                // Allows bounds that do not fit into an `i64`
+                Value::String(s) => scalar::BigDecimal::from_str(s).ok(),
+                _ => None,
+            }
+            .ok_or_else(|| format!("`{}` must be a number", name))
+        };
+
+        let mut constraint = FieldConstraint {
+            min: None,
+            max: None,
+            max_length: None,
+            pattern: None,
+        };
+        for (name, value) in &directive.arguments {
+            match name.as_str() {
+                "min" => constraint.min = Some(number(name, value)?),
+                "max" => constraint.max = Some(number(name, value)?),
+                "maxLength" => {
+                    if !matches!(base_type, "String" | "Bytes") {
+                        return Err(
+                            "`maxLength` can only be used on String and Bytes fields".to_string()
+                        );
+                    }
+                    let max_length = match value {
+                        Value::Int(n) => n.as_i64().and_then(|n| usize::try_from(n).ok()),
+                        _ => None,
+                    };
+                    constraint.max_length =
+                        Some(max_length.ok_or_else(|| {
+                            "`maxLength` must be a non-negative integer".to_string()
+                        })?);
+                }
+                "pattern" => {
+                    if base_type != "String" {
+                        return Err("`pattern` can only be used on String fields".to_string());
+                    }
+                    let pattern = match value {
+                        Value::String(pattern) => regex::Regex::new(pattern)
+                            .map_err(|e| format!("`pattern` is not a valid regex: {}", e))?,
+                        _ => return Err("`pattern` must be a string".to_string()),
+                    };
+                    constraint.pattern = Some(ConstraintPattern(pattern));
+                }
+                _ => return Err(format!("unknown argument `{}`", name)),
+            }
+        }
+        if let (Some(min), Some(max)) = (&constraint.min, &constraint.max) {
+            if min > max {
+                return Err(format!("`min` {} is larger than `max` {}", min, max));
+            }
+        }
+        Ok(Some(constraint))
+    }
+
+    /// Check that `value` satisfies the constraint. The error says how it
+    /// does not
+    pub fn check(&self, value: &store::Value) -> Result<(), String> {
+        use store::Value as V;
+
+        let number = match value {
+            V::Int(n) => Some(scalar::BigDecimal::from(*n)),
+            V::BigInt(n) => Some(scalar::BigDecimal::new(n.clone(), 0)),
+            V::BigDecimal(d) => Some(d.clone()),
+            _ => None,
+        };
+        if let Some(number) = number {
+            if let Some(min) = self.min.as_ref().filter(|min| &number < *min) {
+                return Err(format!("it is less than the minimum {}", min));
+            }
+            if let Some(max) = self.max.as_ref().filter(|max| &number > *max) {
+                return Err(format!("it is larger than the maximum {}", max));
+            }
+        }
+
+        let length = match value {
+            V::String(s) => Some(s.chars().count()),
+            V::Bytes(b) => Some(b.as_slice().len()),
+            _ => None,
+        };
+        if let (Some(length), Some(max_length)) = (length, self.max_length) {
+            if length > max_length {
+                return Err(format!(
+                    "it is {} long, but at most {} is allowed",
+                    length, max_length
+                ));
+            }
+        }
+
+        if let (V::String(s), Some(pattern)) = (value, &self.pattern) {
+            if !pattern.0.is_match(s) {
+                return Err(format!("it does not match the pattern `{}`", pattern.0));
+            }
+        }
+        Ok(())
+    }
+}
+
 /// A validated and preprocessed GraphQL schema for a subgraph.
 #[derive(Clone, Debug, PartialEq)]
 pub struct Schema {
@@ -581,6 +725,13 @@ pub struct Schema {
     pub types_for_interface: BTreeMap<EntityType, Vec<ObjectType>>,
 
     immutable_types: HashSet<EntityType>,
+
+    // The fields that form the composite id of a type, and whether their
+    // values are stored as bytes, by entity type
+    composite_ids: HashMap<EntityType, Vec<(String, bool)>>,
+
+    // The constraints on fields, by entity type and field name.
+    constraints: HashMap<EntityType, HashMap<String, FieldConstraint>>,
 }
 
 impl Schema {
@@ -590,9 +741,15 @@ impl Schema {
     // some is done here makes it incredibly murky whether a `Schema` is
     // fully validated. The code should be changed to make sure that a
     // `Schema` is always fully valid
-    pub fn new(id: DeploymentHash, document: s::Document) -> Result<Self, SchemaValidationError> {
+    pub fn new(
+        id: DeploymentHash,
+        mut document: s::Document,
+    ) -> Result<Self, SchemaValidationError> {
+        Self::add_composite_id_fields(&mut document);
         let (interfaces_for_type, types_for_interface) = Self::collect_interfaces(&document)?;
         let immutable_types = Self::collect_immutable_types(&document);
+        let composite_ids = Self::collect_composite_ids(&document);
+        let constraints = Self::collect_constraints(&document);
 
         let mut schema = Schema {
             id: id.clone(),
@@ -600,6 +757,8 @@ impl Schema {
             interfaces_for_type,
             types_for_interface,
             immutable_types,
+            composite_ids,
+            constraints,
         };
 
         schema.add_subgraph_id_directives(id);
@@ -644,6 +803,62 @@ impl Schema {
         self.immutable_types.contains(entity_type)
     }
 
+    /// The id of the entity of type `entity_type` with attributes `data`
+    /// if the type has a composite id, and `None` otherwise. The id is
+    /// formed from the values of the key fields, joined with `-`, which is
+    /// also how the database forms it when entities are read. Since key
+    /// fields can only be `Bytes` or integers, a `-` in the id is either a
+    /// separator or the sign of the integer that follows it, and the id
+    /// can be split back into the key values. Mappings therefore do not
+    /// need to set the id of such entities
+    pub fn composite_id(
+        &self,
+        entity_type: &EntityType,
+        data: &HashMap<store::Attribute, store::Value>,
+    ) -> Result<Option<String>, Error> {
+        let fields = match self.composite_ids.get(entity_type) {
+            Some(fields) => fields,
+            None => return Ok(None),
+        };
+
+        let mut parts = Vec::with_capacity(fields.len());
+        for (field, is_bytes) in fields {
+            let part = match (data.get(field), *is_bytes) {
+                (Some(store::Value::String(s)), true) => scalar::Bytes::from_str(s)
+                    .map_err(|e| {
+                        anyhow!(
+                            "Entity {}: the value `{}` for field `{}` is not a valid hex string: {}",
+                            entity_type,
+                            s,
+                            field,
+                            e
+                        )
+                    })?
+                    .to_string(),
+                (Some(store::Value::Bytes(b)), _) => b.to_string(),
+                (Some(store::Value::Int(i)), _) => i.to_string(),
+                (Some(store::Value::BigInt(i)), _) => i.to_string(),
+                (None, _) | (Some(store::Value::Null), _) => {
+                    return Err(anyhow!(
+                        "Entity {}: the field `{}` is part of its composite id and must be set",
+                        entity_type,
+                        field
+                    ))
+                }
+                (Some(value), _) => {
+                    return Err(anyhow!(
+                        "Entity {}: the value `{}` for field `{}` can not be part of a composite id",
+                        entity_type,
+                        value,
+                        field
+                    ))
+                }
+            };
+            parts.push(part);
+        }
+        Ok(Some(parts.join("-")))
+    }
+
     pub fn resolve_schema_references<S: SubgraphStore>(
         &self,
         store: Arc<S>,
@@ -751,12 +966,178 @@ impl Schema {
         )
     }
 
+    /// Types with a composite id get their `id` from their key fields.
+    /// Give those that do not declare an `id` field one so that they can
+    /// be handled like all other entity types
+    fn add_composite_id_fields(document: &mut s::Document) {
+        for definition in document.definitions.iter_mut() {
+            if let Definition::TypeDefinition(TypeDefinition::Object(object_type)) = definition {
+                if object_type.find_directive("compositeId").is_some()
+                    && object_type.field("id").is_none()
+                {
+                    object_type.fields.insert(
+                        0,
+                        s::Field {
+                            position: Pos::default(),
+                            description: None,
+                            name: "id".to_owned(),
+                            arguments: vec![],
+                            field_type: s::Type::NonNullType(Box::new(s::Type::NamedType(
+                                "ID".to_owned(),
+                            ))),
+                            directives: vec![],
+                        },
+                    );
+                }
+            }
+        }
+    }
+
+    fn collect_composite_ids(document: &s::Document) -> HashMap<EntityType, Vec<(String, bool)>> {
+        document
+            .get_object_type_definitions()
+            .into_iter()
+            .filter(|obj_type| obj_type.is_immutable())
+            .filter_map(|obj_type| {
+                let fields = obj_type
+                    .composite_id()?
+                    .into_iter()
+                    .map(|name| {
+                        // References are stored like the id of the
+                        // referenced type
+                        let base_type = obj_type
+                            .field(name)
+                            .map(|field| field.field_type.get_base_type())
+                            .unwrap_or_default();
+                        let id_type = match document.get_named_type(base_type) {
+                            Some(TypeDefinition::Object(t)) => t.field("id"),
+                            Some(TypeDefinition::Interface(t)) => t.field("id"),
+                            _ => None,
+                        }
+                        .map(|id| id.field_type.get_base_type())
+                        .unwrap_or(base_type);
+                        (name.to_owned(), id_type == "Bytes")
+                    })
+                    .collect();
+                Some((EntityType::from(obj_type), fields))
+            })
+            .collect()
+    }
+
+    /// The constraints on fields that are valid; `validate` reports the
+    /// invalid ones
+    fn collect_constraints(
+        document: &s::Document,
+    ) -> HashMap<EntityType, HashMap<String, FieldConstraint>> {
+        document
+            .get_object_type_definitions()
+            .into_iter()
+            .map(|obj_type| {
+                let constraints: HashMap<_, _> = obj_type
+                    .fields
+                    .iter()
+                    .filter_map(|field| {
+                        FieldConstraint::from_field(field)
+                            .ok()
+                            .flatten()
+                            .map(|constraint| (field.name.clone(), constraint))
+                    })
+                    .collect();
+                (EntityType::from(obj_type), constraints)
+            })
+            .filter(|(_, constraints)| !constraints.is_empty())
+            .collect()
+    }
+
+    /// The constraint that `@constraint` puts on `field` of `entity_type`
+    pub fn constraint(&self, entity_type: &EntityType, field: &str) -> Option<&FieldConstraint> {
+        self.constraints.get(entity_type)?.get(field)
+    }
+
     pub fn parse(raw: &str, id: DeploymentHash) -> Result<Self, Error> {
         let document = graphql_parser::parse_schema(raw)?.into_static();
 
         Schema::new(id, document).map_err(Into::into)
     }
 
+    /// A hash of the schema that only changes when the types in it change:
+    /// the order of definitions, fields and directives, descriptions, and
+    /// the deployment the schema belongs to make no difference
+    pub fn canonical_hash(&self) -> String {
+        fn directives(directives: &mut Vec<s::Directive>) {
+            directives.retain(|directive| directive.name != "subgraphId");
+            for directive in directives.iter_mut() {
+                directive.arguments.sort_by(|a, b| a.0.cmp(&b.0));
+            }
+            directives.sort_by(|a, b| a.name.cmp(&b.name));
+        }
+        fn input_values(values: &mut Vec<s::InputValue>) {
+            for value in values.iter_mut() {
+                value.description = None;
+                directives(&mut value.directives);
+            }
+            values.sort_by(|a, b| a.name.cmp(&b.name));
+        }
+        fn fields(fields: &mut Vec<s::Field>) {
+            for field in fields.iter_mut() {
+                field.description = None;
+                directives(&mut field.directives);
+                input_values(&mut field.arguments);
+            }
+            fields.sort_by(|a, b| a.name.cmp(&b.name));
+        }
+
+        let mut definitions: Vec<_> = self
+            .document
+            .definitions
+            .iter()
+            .cloned()
+            .map(|mut definition| {
+                if let Definition::TypeDefinition(def) = &mut definition {
+                    match def {
+                        TypeDefinition::Object(t) => {
+                            t.description = None;
+                            t.implements_interfaces.sort();
+                            directives(&mut t.directives);
+                            fields(&mut t.fields);
+                        }
+                        TypeDefinition::Interface(t) => {
+                            t.description = None;
+                            directives(&mut t.directives);
+                            fields(&mut t.fields);
+                        }
+                        TypeDefinition::Enum(t) => {
+                            t.description = None;
+                            directives(&mut t.directives);
+                            for value in t.values.iter_mut() {
+                                value.description = None;
+                                directives(&mut value.directives);
+                            }
+                            t.values.sort_by(|a, b| a.name.cmp(&b.name));
+                        }
+                        TypeDefinition::InputObject(t) => {
+                            t.description = None;
+                            directives(&mut t.directives);
+                            input_values(&mut t.fields);
+                        }
+                        TypeDefinition::Scalar(t) => {
+                            t.description = None;
+                            directives(&mut t.directives);
+                        }
+                        TypeDefinition::Union(t) => {
+                            t.description = None;
+                            directives(&mut t.directives);
+                            t.types.sort();
+                        }
+                    }
+                }
+                definition.to_string()
+            })
+            .collect();
+        definitions.sort();
+        hex::encode(Sha256::digest(definitions.join("\n").as_bytes()))
+    }
+
     fn imported_types(&self) -> HashMap<ImportedType, SchemaReference> {
         fn parse_types(import: &Directive) -> Vec<ImportedType> {
             import
@@ -880,6 +1261,8 @@ impl Schema {
         errors.append(&mut self.validate_fields());
         errors.append(&mut self.validate_import_directives());
         errors.append(&mut self.validate_fulltext_directives());
+        errors.append(&mut self.validate_composite_ids());
+        errors.append(&mut self.validate_constraints());
         errors.append(&mut self.validate_imported_types(schemas));
 
         if errors.is_empty() {
@@ -889,6 +1272,123 @@ impl Schema {
         }
     }
 
+    fn validate_constraints(&self) -> Vec<SchemaValidationError> {
+        let mut errors = Vec::new();
+        for object_type in self.document.get_object_type_definitions() {
+            for field in &object_type.fields {
+                if let Err(reason) = FieldConstraint::from_field(field) {
+                    errors.push(SchemaValidationError::InvalidConstraint(
+                        object_type.name.clone(),
+                        field.name.clone(),
+                        reason,
+                    ));
+                }
+            }
+        }
+        errors
+    }
+
+    /// A `@compositeId(fields: ["a", "b"])` on an immutable entity type
+    /// makes the fields `a` and `b` the key for its entities. The fields
+    /// must be non-nullable scalars, enums or references to other entities
+    /// that are stored with the entity. The `id` of such entities is formed
+    /// from the key fields and is not stored; types that declare an `id`
+    /// must make it an `ID!` or `String!`
+    fn validate_composite_ids(&self) -> Vec<SchemaValidationError> {
+        let mut errors = Vec::new();
+        for object_type in self.document.get_object_type_definitions() {
+            let directive = match object_type.find_directive("compositeId") {
+                Some(directive) => directive,
+                None => continue,
+            };
+            let invalid = |reason: String| {
+                SchemaValidationError::InvalidCompositeId(object_type.name.clone(), reason)
+            };
+
+            if !object_type.is_immutable() {
+                errors.push(invalid(
+                    "only immutable entity types can have a composite id".to_string(),
+                ));
+            }
+            if let Some(id) = object_type.field("id") {
+                if !id.field_type.is_non_null()
+                    || !matches!(id.field_type.get_base_type(), "ID" | "String")
+                {
+                    errors.push(invalid(
+                        "the `id` is formed from the key fields and must have type ID! or String!"
+                            .to_string(),
+                    ));
+                }
+            }
+            let fields = match directive.argument("fields") {
+                Some(Value::List(fields)) => fields,
+                _ => {
+                    errors.push(invalid(
+                        "the `fields` argument must be a list of field names".to_string(),
+                    ));
+                    continue;
+                }
+            };
+            if fields.len() < 2 {
+                errors.push(invalid(
+                    "it must consist of at least two fields".to_string(),
+                ));
+            }
+            let mut seen = HashSet::new();
+            for field in fields {
+                let name = match field {
+                    Value::String(name) => name,
+                    _ => {
+                        errors.push(invalid(format!("`{}` is not a field name", field)));
+                        continue;
+                    }
+                };
+                if !seen.insert(name) {
+                    errors.push(invalid(format!("the field `{}` is listed twice", name)));
+                    continue;
+                }
+                match object_type.field(name) {
+                    None => errors.push(invalid(format!("there is no field `{}`", name))),
+                    Some(field) if name == "id" || field.is_derived() => errors.push(invalid(
+                        format!("the field `{}` can not be part of a composite id", name),
+                    )),
+                    Some(field)
+                        if field.field_type.is_list() || !field.field_type.is_non_null() =>
+                    {
+                        errors.push(invalid(format!(
+                            "the field `{}` must be non-nullable and can not be a list",
+                            name
+                        )))
+                    }
+                    Some(field) => {
+                        // The text of none of these types contains a `-`
+                        // except as the sign of a number, so that the id
+                        // can be split back into the key values
+                        let base_type = field.field_type.get_base_type();
+                        let id_type = match self.document.get_named_type(base_type) {
+                            Some(TypeDefinition::Object(t)) => t.field("id"),
+                            Some(TypeDefinition::Interface(t)) => t.field("id"),
+                            _ => None,
+                        }
+                        .map(|id| id.field_type.get_base_type());
+                        let valid = match id_type {
+                            Some(id_type) => id_type == "Bytes",
+                            None => matches!(base_type, "Bytes" | "Int" | "BigInt"),
+                        };
+                        if !valid {
+                            errors.push(invalid(format!(
+                                "the field `{}` has type {}, which can not be part of a composite id; \
+                                 only Bytes, Int, BigInt and references to entities with Bytes ids can",
+                                name, base_type
+                            )))
+                        }
+                    }
+                }
+            }
+        }
+        errors
+    }
+
     fn validate_schema_type_has_no_fields(&self) -> Result<(), SchemaValidationError> {
         match self
             .subgraph_schema_object_type()
@@ -1952,3 +2452,263 @@ type Gravatar @entity {
 
     assert_eq!(schema.validate_fulltext_directives(), vec![]);
 }
+
+#[test]
+fn test_composite_id_validation() {
+    fn validate(directives: &str, fields: &str) -> Vec<String> {
+        let schema = format!(
+            "type Pair @entity {{ id: Bytes! }}
+             type Token @entity {{ id: ID! }}
+             type Swap @entity{} {{ id: ID!, {} }}",
+            directives, fields
+        );
+        let schema = Schema::parse(&schema, DeploymentHash::new("id1").unwrap()).unwrap();
+        schema
+            .validate_composite_ids()
+            .into_iter()
+            .map(|e| e.to_string())
+            .collect()
+    }
+
+    const FIELDS: &str = "pair: Pair!, blockNumber: BigInt!, amount: BigInt, pairs: [Pair!]!";
+    const IMMUTABLE: &str = "(immutable: true)";
+
+    let ok = format!(
+        r#"{} @compositeId(fields: ["pair", "blockNumber"])"#,
+        IMMUTABLE
+    );
+    assert_eq!(Vec::<String>::new(), validate(&ok, FIELDS));
+
+    let err = |reason: &str| {
+        vec![format!(
+            "Type `Swap` has an invalid @compositeId: {}",
+            reason
+        )]
+    };
+    assert_eq!(
+        err("only immutable entity types can have a composite id"),
+        validate(r#" @compositeId(fields: ["pair", "blockNumber"])"#, FIELDS)
+    );
+    assert_eq!(
+        err("it must consist of at least two fields"),
+        validate(
+            &format!(r#"{} @compositeId(fields: ["pair"])"#, IMMUTABLE),
+            FIELDS
+        )
+    );
+    assert_eq!(
+        err("there is no field `block`"),
+        validate(
+            &format!(r#"{} @compositeId(fields: ["pair", "block"])"#, IMMUTABLE),
+            FIELDS
+        )
+    );
+    assert_eq!(
+        err("the field `amount` must be non-nullable and can not be a list"),
+        validate(
+            &format!(r#"{} @compositeId(fields: ["pair", "amount"])"#, IMMUTABLE),
+            FIELDS
+        )
+    );
+    assert_eq!(
+        err("the field `pairs` must be non-nullable and can not be a list"),
+        validate(
+            &format!(
+                r#"{} @compositeId(fields: ["pairs", "blockNumber"])"#,
+                IMMUTABLE
+            ),
+            FIELDS
+        )
+    );
+    assert_eq!(
+        err("the field `pair` is listed twice"),
+        validate(
+            &format!(r#"{} @compositeId(fields: ["pair", "pair"])"#, IMMUTABLE),
+            FIELDS
+        )
+    );
+    let invalid_type = |field: &str, typ: &str| {
+        err(&format!(
+            "the field `{}` has type {}, which can not be part of a composite id; \
+             only Bytes, Int, BigInt and references to entities with Bytes ids can",
+            field, typ
+        ))
+    };
+    assert_eq!(
+        invalid_type("price", "BigDecimal"),
+        validate(
+            &format!(r#"{} @compositeId(fields: ["pair", "price"])"#, IMMUTABLE),
+            "pair: Pair!, price: BigDecimal!"
+        )
+    );
+    // Strings can contain the `-` that separates the key values
+    assert_eq!(
+        invalid_type("name", "String"),
+        validate(
+            &format!(r#"{} @compositeId(fields: ["pair", "name"])"#, IMMUTABLE),
+            "pair: Pair!, name: String!"
+        )
+    );
+    assert_eq!(
+        invalid_type("token", "Token"),
+        validate(
+            &format!(r#"{} @compositeId(fields: ["pair", "token"])"#, IMMUTABLE),
+            "pair: Pair!, token: Token!"
+        )
+    );
+
+    // Types with a composite id do not need to declare an `id`, and the
+    // one they declare must be a string
+    let schema = Schema::parse(
+        r#"type Swap @entity(immutable: true) @compositeId(fields: ["pair", "block"]) {
+               pair: Bytes!, block: Int!
+           }"#,
+        DeploymentHash::new("id1").unwrap(),
+    )
+    .unwrap();
+    assert_eq!(
+        Vec::<SchemaValidationError>::new(),
+        schema.validate_composite_ids()
+    );
+    let swap = schema.document.get_object_type_definition("Swap").unwrap();
+    assert_eq!("ID!", swap.field("id").unwrap().field_type.to_string());
+
+    let schema = Schema::parse(
+        r#"type Swap @entity(immutable: true) @compositeId(fields: ["pair", "block"]) {
+               id: Bytes!, pair: Bytes!, block: Int!
+           }"#,
+        DeploymentHash::new("id1").unwrap(),
+    )
+    .unwrap();
+    assert_eq!(
+        vec![
+            "Type `Swap` has an invalid @compositeId: the `id` is formed from the key fields \
+             and must have type ID! or String!"
+        ],
+        schema
+            .validate_composite_ids()
+            .into_iter()
+            .map(|e| e.to_string())
+            .collect::<Vec<_>>()
+    );
+}
+
+#[test]
+fn test_composite_id() {
+    const SCHEMA: &str = r#"
+        type Pair @entity { id: Bytes! }
+        type Swap @entity(immutable: true) @compositeId(fields: ["pair", "hash", "block", "amount"]) {
+            pair: Pair!, hash: Bytes!, block: Int!, amount: BigInt!, memo: String
+        }"#;
+
+    let schema = Schema::parse(SCHEMA, DeploymentHash::new("id1").unwrap()).unwrap();
+    let swap = EntityType::from("Swap");
+    let mut data: HashMap<String, store::Value> = HashMap::from_iter(vec![
+        ("pair".to_owned(), store::Value::from("0xAB01")),
+        (
+            "hash".to_owned(),
+            store::Value::Bytes(scalar::Bytes::from(&[0xcd, 0x02][..])),
+        ),
+        ("block".to_owned(), store::Value::from(12)),
+        (
+            "amount".to_owned(),
+            store::Value::BigInt(scalar::BigInt::from(-5)),
+        ),
+        ("memo".to_owned(), store::Value::from("hi")),
+    ]);
+
+    // References to types with `Bytes` ids are formatted the way the
+    // database formats them
+    assert_eq!(
+        Some("0xab01-0xcd02-12--5".to_owned()),
+        schema.composite_id(&swap, &data).unwrap()
+    );
+    assert_eq!(
+        None,
+        schema
+            .composite_id(&EntityType::from("Pair"), &data)
+            .unwrap()
+    );
+
+    data.remove("block");
+    assert!(schema
+        .composite_id(&swap, &data)
+        .unwrap_err()
+        .to_string()
+        .contains("the field `block` is part of its composite id"));
+}
+
+#[test]
+fn test_constraint_validation() {
+    fn validate(field: &str) -> Vec<String> {
+        let schema = format!("type Thing @entity {{ id: ID!, {} }}", field);
+        let schema = Schema::parse(&schema, DeploymentHash::new("id1").unwrap()).unwrap();
+        schema
+            .validate_constraints()
+            .into_iter()
+            .map(|e| e.to_string())
+            .collect()
+    }
+
+    let err = |field: &str, reason: &str| {
+        vec![format!(
+            "Field `{}` in type `Thing` has an invalid @constraint: {}",
+            field, reason
+        )]
+    };
+
+    assert!(validate("amount: BigInt! @constraint(min: 0, max: 100)").is_empty());
+    assert!(validate("ratio: BigDecimal @constraint(min: 0.5)").is_empty());
+    assert!(validate(r#"name: String @constraint(pattern: "^[a-z]+$", maxLength: 8)"#).is_empty());
+    assert!(validate("data: Bytes @constraint(maxLength: 32)").is_empty());
+    assert_eq!(
+        err("name", "`min` can only be used on numeric fields"),
+        validate("name: String @constraint(min: 1)")
+    );
+    assert_eq!(
+        err("count", "`pattern` can only be used on String fields"),
+        validate(r#"count: Int @constraint(pattern: "^1")"#)
+    );
+    assert_eq!(
+        err("count", "`min` 10 is larger than `max` 1"),
+        validate("count: Int @constraint(min: 10, max: 1)")
+    );
+    assert_eq!(
+        err("name", "`maxLength` must be a non-negative integer"),
+        validate("name: String @constraint(maxLength: -1)")
+    );
+    assert_eq!(
+        err("name", "unknown argument `minLength`"),
+        validate("name: String @constraint(minLength: 1)")
+    );
+    assert_eq!(
+        1,
+        validate(r#"name: String @constraint(pattern: "[")"#).len()
+    );
+}
+
+#[test]
+fn test_canonical_hash() {
+    let hash = |schema: &str, id: &str| {
+        Schema::parse(schema, DeploymentHash::new(id).unwrap())
+            .unwrap()
+            .canonical_hash()
+    };
+
+    let schema = r#"
+        """A token"""
+        type Token @entity { id: ID!, symbol: String!, pairs: [Pair!]! @derivedFrom(field: "token") }
+        type Pair @entity { id: ID!, token: Token! }
+        enum Kind { Swap, Mint }"#;
+    let reordered = r#"
+        enum Kind { Mint, Swap }
+        type Pair @entity { token: Token!, id: ID! }
+        type Token @entity { pairs: [Pair!]! @derivedFrom(field: "token"), id: ID!, symbol: String! }"#;
+    let changed = r#"
+        type Token @entity { id: ID!, symbol: String, pairs: [Pair!]! @derivedFrom(field: "token") }
+        type Pair @entity { id: ID!, token: Token! }
+        enum Kind { Swap, Mint }"#;
+
+    assert_eq!(hash(schema, "id1"), hash(reordered, "id2"));
+    assert_ne!(hash(schema, "id1"), hash(changed, "id1"));
+}
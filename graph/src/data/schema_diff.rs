@@ -0,0 +1,334 @@
+//! Comparing the schemas of two versions of a subgraph, so that operators
+//! and consumers can see what promoting the pending version would change
+//! before it becomes the current version
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+
+use crate::data::graphql::{object, IntoValue};
+use crate::prelude::{r, s};
+
+/// Whether queries against one version of a subgraph keep working
+/// against another version
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SchemaCompatibility {
+    /// The other version only adds to the schema
+    Compatible,
+    /// The other version removes or changes types or fields
+    Breaking,
+}
+
+impl SchemaCompatibility {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SchemaCompatibility::Compatible => "compatible",
+            SchemaCompatibility::Breaking => "breaking",
+        }
+    }
+}
+
+impl fmt::Display for SchemaCompatibility {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl FromStr for SchemaCompatibility {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "compatible" => Ok(SchemaCompatibility::Compatible),
+            "breaking" => Ok(SchemaCompatibility::Breaking),
+            _ => Err(format!("invalid schema compatibility: {:?}", s)),
+        }
+    }
+}
+
+impl IntoValue for SchemaCompatibility {
+    fn into_value(self) -> r::Value {
+        r::Value::Enum(self.as_str().to_string())
+    }
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct SchemaDiff {
+    pub added_types: Vec<String>,
+    pub removed_types: Vec<String>,
+    pub changed_types: Vec<TypeChange>,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct TypeChange {
+    pub name: String,
+    pub current_kind: &'static str,
+    pub pending_kind: &'static str,
+    /// For enums, these are the enum values
+    pub added_fields: Vec<String>,
+    pub removed_fields: Vec<String>,
+    pub changed_fields: Vec<FieldChange>,
+}
+
+/// A field whose type is different in the two versions
+#[derive(Debug, PartialEq)]
+pub struct FieldChange {
+    pub name: String,
+    pub current_type: String,
+    pub pending_type: String,
+}
+
+impl SchemaDiff {
+    /// Whether queries that work against the current version might fail
+    /// against the pending version. Removing or changing anything can
+    /// break queries, adding something can not
+    pub fn is_breaking(&self) -> bool {
+        !self.removed_types.is_empty()
+            || self.changed_types.iter().any(|change| {
+                change.current_kind != change.pending_kind
+                    || !change.removed_fields.is_empty()
+                    || !change.changed_fields.is_empty()
+            })
+    }
+
+    pub fn compatibility(&self) -> SchemaCompatibility {
+        if self.is_breaking() {
+            SchemaCompatibility::Breaking
+        } else {
+            SchemaCompatibility::Compatible
+        }
+    }
+}
+
+impl IntoValue for SchemaDiff {
+    fn into_value(self) -> r::Value {
+        let breaking = self.is_breaking();
+        object! {
+            __typename: "SchemaDiff",
+            addedTypes: self.added_types,
+            removedTypes: self.removed_types,
+            changedTypes: self.changed_types,
+            breaking: breaking,
+        }
+    }
+}
+
+impl IntoValue for TypeChange {
+    fn into_value(self) -> r::Value {
+        object! {
+            __typename: "TypeChange",
+            name: self.name,
+            currentKind: self.current_kind,
+            pendingKind: self.pending_kind,
+            addedFields: self.added_fields,
+            removedFields: self.removed_fields,
+            changedFields: self.changed_fields,
+        }
+    }
+}
+
+impl IntoValue for FieldChange {
+    fn into_value(self) -> r::Value {
+        object! {
+            __typename: "FieldChange",
+            name: self.name,
+            currentType: self.current_type,
+            pendingType: self.pending_type,
+        }
+    }
+}
+
+/// The kind of a type definition and the types of its fields by name.
+/// Enum values have an empty type since only their presence matters
+fn members(def: &s::TypeDefinition) -> (&'static str, BTreeMap<&str, String>) {
+    fn fields(fields: &[s::Field]) -> BTreeMap<&str, String> {
+        fields
+            .iter()
+            .map(|field| (field.name.as_str(), field.field_type.to_string()))
+            .collect()
+    }
+
+    match def {
+        s::TypeDefinition::Object(t) => ("OBJECT", fields(&t.fields)),
+        s::TypeDefinition::Interface(t) => ("INTERFACE", fields(&t.fields)),
+        s::TypeDefinition::Enum(t) => (
+            "ENUM",
+            t.values
+                .iter()
+                .map(|value| (value.name.as_str(), String::new()))
+                .collect(),
+        ),
+        s::TypeDefinition::InputObject(t) => (
+            "INPUT_OBJECT",
+            t.fields
+                .iter()
+                .map(|field| (field.name.as_str(), field.value_type.to_string()))
+                .collect(),
+        ),
+        s::TypeDefinition::Scalar(_) => ("SCALAR", BTreeMap::new()),
+        s::TypeDefinition::Union(t) => (
+            "UNION",
+            t.types
+                .iter()
+                .map(|name| (name.as_str(), String::new()))
+                .collect(),
+        ),
+    }
+}
+
+fn type_definitions(document: &s::Document) -> BTreeMap<&str, &s::TypeDefinition> {
+    document
+        .definitions
+        .iter()
+        .filter_map(|def| match def {
+            s::Definition::TypeDefinition(def) => Some(def),
+            _ => None,
+        })
+        .map(|def| (type_name(def), def))
+        .collect()
+}
+
+fn type_name(def: &s::TypeDefinition) -> &str {
+    match def {
+        s::TypeDefinition::Object(t) => &t.name,
+        s::TypeDefinition::Interface(t) => &t.name,
+        s::TypeDefinition::Enum(t) => &t.name,
+        s::TypeDefinition::InputObject(t) => &t.name,
+        s::TypeDefinition::Scalar(t) => &t.name,
+        s::TypeDefinition::Union(t) => &t.name,
+    }
+}
+
+/// The differences between the types of the schemas `current` and
+/// `pending`. Types and fields are listed in alphabetical order
+pub fn diff(current: &s::Document, pending: &s::Document) -> SchemaDiff {
+    let current = type_definitions(current);
+    let pending = type_definitions(pending);
+    let mut diff = SchemaDiff::default();
+
+    for (name, current_def) in &current {
+        let pending_def = match pending.get(name) {
+            Some(pending_def) => pending_def,
+            None => {
+                diff.removed_types.push(name.to_string());
+                continue;
+            }
+        };
+        let (current_kind, current_fields) = members(current_def);
+        let (pending_kind, pending_fields) = members(pending_def);
+
+        let mut change = TypeChange {
+            name: name.to_string(),
+            current_kind,
+            pending_kind,
+            added_fields: Vec::new(),
+            removed_fields: Vec::new(),
+            changed_fields: Vec::new(),
+        };
+        for (field, current_type) in &current_fields {
+            match pending_fields.get(field) {
+                None => change.removed_fields.push(field.to_string()),
+                Some(pending_type) if pending_type != current_type => {
+                    change.changed_fields.push(FieldChange {
+                        name: field.to_string(),
+                        current_type: current_type.clone(),
+                        pending_type: pending_type.clone(),
+                    })
+                }
+                Some(_) => {}
+            }
+        }
+        change.added_fields = pending_fields
+            .keys()
+            .filter(|field| !current_fields.contains_key(*field))
+            .map(|field| field.to_string())
+            .collect();
+
+        if current_kind != pending_kind
+            || !change.added_fields.is_empty()
+            || !change.removed_fields.is_empty()
+            || !change.changed_fields.is_empty()
+        {
+            diff.changed_types.push(change);
+        }
+    }
+    diff.added_types = pending
+        .keys()
+        .filter(|name| !current.contains_key(*name))
+        .map(|name| name.to_string())
+        .collect();
+    diff
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn schema(text: &str) -> s::Document {
+        graphql_parser::parse_schema::<String>(text)
+            .unwrap()
+            .into_static()
+    }
+
+    #[test]
+    fn diffs_types_and_fields() {
+        let current = schema(
+            "type Token @entity { id: ID!, symbol: String!, decimals: Int! }
+             type Pair @entity { id: ID!, token0: Token! }
+             enum Kind { Swap, Mint }",
+        );
+        let pending = schema(
+            "type Token @entity { id: ID!, symbol: String, name: String! }
+             type Pair @entity { id: ID!, token0: Token! }
+             enum Kind { Swap, Mint, Burn }
+             type Swap @entity { id: ID! }",
+        );
+
+        let diff = diff(&current, &pending);
+        assert_eq!(vec!["Swap".to_string()], diff.added_types);
+        assert!(diff.removed_types.is_empty());
+        assert_eq!(
+            vec![
+                TypeChange {
+                    name: "Kind".to_string(),
+                    current_kind: "ENUM",
+                    pending_kind: "ENUM",
+                    added_fields: vec!["Burn".to_string()],
+                    removed_fields: vec![],
+                    changed_fields: vec![],
+                },
+                TypeChange {
+                    name: "Token".to_string(),
+                    current_kind: "OBJECT",
+                    pending_kind: "OBJECT",
+                    added_fields: vec!["name".to_string()],
+                    removed_fields: vec!["decimals".to_string()],
+                    changed_fields: vec![FieldChange {
+                        name: "symbol".to_string(),
+                        current_type: "String!".to_string(),
+                        pending_type: "String".to_string(),
+                    }],
+                }
+            ],
+            diff.changed_types
+        );
+        assert_eq!(SchemaCompatibility::Breaking, diff.compatibility());
+    }
+
+    #[test]
+    fn additions_are_not_breaking() {
+        let current = schema("type Token @entity { id: ID! }");
+        let pending = schema(
+            "type Token @entity { id: ID!, symbol: String! }
+             type Pair @entity { id: ID! }",
+        );
+        assert_eq!(
+            SchemaCompatibility::Compatible,
+            diff(&current, &pending).compatibility()
+        );
+        assert_eq!(
+            SchemaCompatibility::Breaking,
+            diff(&pending, &current).compatibility()
+        );
+        assert_eq!(SchemaDiff::default(), diff(&current, &current));
+    }
+}
@@ -621,54 +621,38 @@ impl Entity {
         }
     }
 
+    /// Check that the exponents of all `BigDecimal` values of this entity
+    /// are within the range that the store can hold reliably. This is not
+    /// part of `validate` since mappings only get this check from
+    /// `apiVersion` 0.0.8 on
+    pub fn check_big_decimals(&self, key: &EntityKey) -> Result<(), anyhow::Error> {
+        for (field, value) in &self.0 {
+            let elts = match value {
+                Value::List(elts) => elts.as_slice(),
+                value => std::slice::from_ref(value),
+            };
+            for elt in elts {
+                if let Value::BigDecimal(d) = elt {
+                    d.check_exponent().map_err(|e| {
+                        anyhow!(
+                            "Entity {}[{}]: the value `{}` for field `{}` can not be stored: {}",
+                            key.entity_type,
+                            key.entity_id,
+                            value,
+                            field,
+                            e
+                        )
+                    })?;
+                }
+            }
+        }
+        Ok(())
+    }
+
     /// Validate that this entity matches the object type definition in the
     /// schema. An entity that passes these checks can be stored
     /// successfully in the subgraph's database schema
     pub fn validate(&self, schema: &Schema, key: &EntityKey) -> Result<(), anyhow::Error> {
-        fn scalar_value_type(schema: &Schema, field_type: &s::Type) -> ValueType {
-            use s::TypeDefinition as t;
-            match field_type {
-                s::Type::NamedType(name) => ValueType::from_str(name).unwrap_or_else(|_| {
-                    match schema.document.get_named_type(name) {
-                        Some(t::Object(obj_type)) => {
-                            let id = obj_type.field("id").expect("all object types have an id");
-                            scalar_value_type(schema, &id.field_type)
-                        }
-                        Some(t::Interface(intf)) => {
-                            // Validation checks that all implementors of an
-                            // interface use the same type for `id`. It is
-                            // therefore enough to use the id type of one of
-                            // the implementors
-                            match schema
-                                .types_for_interface()
-                                .get(&EntityType::new(intf.name.clone()))
-                                .expect("interface type names are known")
-                                .first()
-                            {
-                                None => {
-                                    // Nothing is implementing this interface; we assume it's of type string
-                                    // see also: id-type-for-unimplemented-interfaces
-                                    ValueType::String
-                                }
-                                Some(obj_type) => {
-                                    let id =
-                                        obj_type.field("id").expect("all object types have an id");
-                                    scalar_value_type(schema, &id.field_type)
-                                }
-                            }
-                        }
-                        Some(t::Enum(_)) => ValueType::String,
-                        Some(t::Scalar(_)) => unreachable!("user-defined scalars are not used"),
-                        Some(t::Union(_)) => unreachable!("unions are not used"),
-                        Some(t::InputObject(_)) => unreachable!("inputObjects are not used"),
-                        None => unreachable!("names of field types have been validated"),
-                    }
-                }),
-                s::Type::NonNullType(inner) => scalar_value_type(schema, inner),
-                s::Type::ListType(inner) => scalar_value_type(schema, inner),
-            }
-        }
-
         if key.entity_type.is_poi() {
             // Users can't modify Poi entities, and therefore they do not
             // need to be validated. In addition, the schema has no object
@@ -686,6 +670,23 @@ impl Entity {
                 )
             })?;
 
+        // Ids of type `Bytes` are stored as binary in the database, and
+        // must be hex strings
+        if let Some(id) = object_type.field("id") {
+            if id.field_type.get_base_type() == "Bytes" {
+                scalar::Bytes::from_str(&key.entity_id).map_err(|e| {
+                    anyhow!(
+                        "Entity {}[{}]: the id `{}` is not a valid hex string for an id \
+                         of type Bytes: {}",
+                        key.entity_type,
+                        key.entity_id,
+                        key.entity_id,
+                        e
+                    )
+                })?;
+            }
+        }
+
         for field in &object_type.fields {
             let is_derived = field.is_derived();
             match (self.get(&field.name), is_derived) {
@@ -724,6 +725,47 @@ impl Entity {
                             value.type_name()
                         );
                     }
+                    let elts = match value {
+                        Value::List(elts) => elts.as_slice(),
+                        value => std::slice::from_ref(value),
+                    };
+                    // Postgres would reject values that are not part of
+                    // the enum, too, but only with an obscure error
+                    if let Some(s::TypeDefinition::Enum(enum_type)) = schema
+                        .document
+                        .get_named_type(field.field_type.get_base_type())
+                    {
+                        for elt in elts {
+                            if let Value::String(elt) = elt {
+                                if !enum_type.values.iter().any(|v| &v.name == elt) {
+                                    anyhow::bail!(
+                                        "Entity {}[{}]: the value `{}` for field `{}` is not \
+                                         one of the values of the enum {}",
+                                        key.entity_type,
+                                        key.entity_id,
+                                        elt,
+                                        field.name,
+                                        enum_type.name
+                                    );
+                                }
+                            }
+                        }
+                    }
+                    if let Some(constraint) = schema.constraint(&key.entity_type, &field.name) {
+                        for elt in elts {
+                            constraint.check(elt).map_err(|reason| {
+                                anyhow!(
+                                    "Entity {}[{}]: the value `{}` for field `{}` violates \
+                                     its @constraint: {}",
+                                    key.entity_type,
+                                    key.entity_id,
+                                    elt,
+                                    field.name,
+                                    reason
+                                )
+                            })?;
+                        }
+                    }
                 }
                 (None, false) => {
                     if field.field_type.is_non_null() {
@@ -750,6 +792,108 @@ impl Entity {
         }
         Ok(())
     }
+
+    /// The fields of the object type of `key` that are required but that
+    /// this entity has no value for, together with the default values that
+    /// they can be filled with: `false`, zero, empty strings, bytes and
+    /// lists, and the first value of enums. Fields that reference a single
+    /// other entity are left out since there is no entity that a default
+    /// could point to
+    pub fn required_defaults(&self, schema: &Schema, key: &EntityKey) -> Vec<(String, Value)> {
+        let object_type = match schema
+            .document
+            .get_object_type_definitions()
+            .into_iter()
+            .find(|object_type| key.entity_type.as_str() == object_type.name)
+        {
+            Some(object_type) => object_type,
+            None => return vec![],
+        };
+        object_type
+            .fields
+            .iter()
+            .filter(|field| field.field_type.is_non_null() && !field.is_derived())
+            .filter(|field| matches!(self.get(&field.name), None | Some(Value::Null)))
+            .filter(|field| {
+                field.field_type.is_list()
+                    || !matches!(
+                        schema
+                            .document
+                            .get_named_type(field.field_type.get_base_type()),
+                        Some(s::TypeDefinition::Object(_)) | Some(s::TypeDefinition::Interface(_))
+                    )
+            })
+            .map(|field| {
+                let default = if field.field_type.is_list() {
+                    Value::List(vec![])
+                } else if let Some(s::TypeDefinition::Enum(enum_type)) = schema
+                    .document
+                    .get_named_type(field.field_type.get_base_type())
+                {
+                    enum_type
+                        .values
+                        .first()
+                        .map(|value| Value::String(value.name.clone()))
+                        .unwrap_or(Value::Null)
+                } else {
+                    match scalar_value_type(schema, &field.field_type) {
+                        ValueType::Boolean => Value::Bool(false),
+                        ValueType::BigInt => Value::BigInt(scalar::BigInt::from(0)),
+                        ValueType::Bytes => Value::Bytes(scalar::Bytes::from(&[][..])),
+                        ValueType::BigDecimal => Value::BigDecimal(scalar::BigDecimal::from(0)),
+                        ValueType::Int => Value::Int(0),
+                        ValueType::String => Value::String(String::new()),
+                    }
+                };
+                (field.name.clone(), default)
+            })
+            .collect()
+    }
+}
+
+/// The type of the values of a field of type `field_type`; references to
+/// other entities have the type of their `id`
+fn scalar_value_type(schema: &Schema, field_type: &s::Type) -> ValueType {
+    use s::TypeDefinition as t;
+    match field_type {
+        s::Type::NamedType(name) => ValueType::from_str(name).unwrap_or_else(|_| {
+            match schema.document.get_named_type(name) {
+                Some(t::Object(obj_type)) => {
+                    let id = obj_type.field("id").expect("all object types have an id");
+                    scalar_value_type(schema, &id.field_type)
+                }
+                Some(t::Interface(intf)) => {
+                    // Validation checks that all implementors of an
+                    // interface use the same type for `id`. It is
+                    // therefore enough to use the id type of one of
+                    // the implementors
+                    match schema
+                        .types_for_interface()
+                        .get(&EntityType::new(intf.name.clone()))
+                        .expect("interface type names are known")
+                        .first()
+                    {
+                        None => {
+                            // Nothing is implementing this interface; we assume it's of type string
+                            // see also: id-type-for-unimplemented-interfaces
+                            ValueType::String
+                        }
+                        Some(obj_type) => {
+                            let id = obj_type.field("id").expect("all object types have an id");
+                            scalar_value_type(schema, &id.field_type)
+                        }
+                    }
+                }
+                Some(t::Enum(_)) => ValueType::String,
+                Some(t::Scalar(_)) => unreachable!("user-defined scalars are not used"),
+                Some(t::Union(_)) => unreachable!("unions are not used"),
+                Some(t::InputObject(_)) => unreachable!("inputObjects are not used"),
+                None => unreachable!("names of field types have been validated"),
+            }
+        }),
+        s::Type::NonNullType(inner) => scalar_value_type(schema, inner),
+        s::Type::ListType(inner) => scalar_value_type(schema, inner),
+    }
 }
 
 impl From<Entity> for BTreeMap<String, q::Value> {
@@ -946,6 +1090,142 @@ fn entity_validation() {
         thing,
         "Entity Thing[t8]: field `cruft` is derived and can not be set",
     );
+
+    let mut thing = make_thing("t9");
+    thing.set("favorite_color", "purple");
+    check(
+        thing,
+        "Entity Thing[t9]: the value `purple` for field `favorite_color` \
+         is not one of the values of the enum Color",
+    );
+}
+
+#[test]
+fn bytes_id_validation() {
+    const DOCUMENT: &str = "type Thing @entity { id: Bytes!, name: String! }";
+    let hash = DeploymentHash::new("doesntmatter").unwrap();
+    let schema = Schema::parse(DOCUMENT, hash.clone()).expect("Failed to parse test schema");
+
+    let check = |id: &str| {
+        let mut thing = Entity::new();
+        thing.set("id", scalar::Bytes::from_str("0xbadd").unwrap());
+        thing.set("name", "thing");
+        let key = EntityKey::data(hash.clone(), "Thing".to_owned(), id.to_owned());
+        thing.validate(&schema, &key).map_err(|e| e.to_string())
+    };
+
+    assert_eq!(Ok(()), check("0xbadd"));
+    assert_eq!(Ok(()), check("badd"));
+    assert_eq!(
+        Err(
+            "Entity Thing[0xnope]: the id `0xnope` is not a valid hex string for an id \
+             of type Bytes: Invalid character 'n' at position 0"
+                .to_string()
+        ),
+        check("0xnope")
+    );
+}
+
+#[test]
+fn constraint_validation() {
+    const DOCUMENT: &str = "
+      type Thing @entity {
+          id: ID!,
+          amount: BigInt @constraint(min: 0, max: \"1000000000000000000000\"),
+          symbol: String @constraint(pattern: \"^[A-Z]+$\", maxLength: 5),
+          scores: [Int!] @constraint(max: 10)
+      }";
+    let hash = DeploymentHash::new("doesntmatter").unwrap();
+    let schema = Schema::parse(DOCUMENT, hash.clone()).expect("Failed to parse test schema");
+    let key = EntityKey::data(hash, "Thing".to_owned(), "t1".to_owned());
+
+    let check = |field: &str, value: Value| {
+        let mut thing = Entity::new();
+        thing.set("id", "t1");
+        thing.set(field, value);
+        thing.validate(&schema, &key).map_err(|e| e.to_string())
+    };
+
+    assert_eq!(Ok(()), check("amount", Value::BigInt(0.into())));
+    assert_eq!(Ok(()), check("symbol", Value::from("GRT")));
+    assert_eq!(Ok(()), check("scores", Value::from(vec![1, 10])));
+    assert_eq!(
+        Err(
+            "Entity Thing[t1]: the value `-1` for field `amount` violates its \
+             @constraint: it is less than the minimum 0"
+                .to_string()
+        ),
+        check("amount", Value::BigInt((-1).into()))
+    );
+    assert_eq!(
+        Err(
+            "Entity Thing[t1]: the value `grt` for field `symbol` violates its \
+             @constraint: it does not match the pattern `^[A-Z]+$`"
+                .to_string()
+        ),
+        check("symbol", Value::from("grt"))
+    );
+    assert_eq!(
+        Err(
+            "Entity Thing[t1]: the value `TOOLONG` for field `symbol` violates its \
+             @constraint: it is 7 long, but at most 5 is allowed"
+                .to_string()
+        ),
+        check("symbol", Value::from("TOOLONG"))
+    );
+    assert_eq!(
+        Err(
+            "Entity Thing[t1]: the value `11` for field `scores` violates its \
+             @constraint: it is larger than the maximum 10"
+                .to_string()
+        ),
+        check("scores", Value::from(vec![1, 11]))
+    );
+}
+
+#[test]
+fn required_defaults() {
+    const DOCUMENT: &str = "
+      enum Color { red, yellow, blue }
+      type Owner @entity { id: Bytes! }
+      type Thing @entity {
+          id: ID!,
+          name: String!,
+          count: Int!,
+          color: Color!,
+          owner: Owner!,
+          tags: [String!]!,
+          note: String,
+          owners: [Owner!]! @derivedFrom(field: \"thing\")
+      }";
+    let hash = DeploymentHash::new("doesntmatter").unwrap();
+    let schema = Schema::parse(DOCUMENT, hash.clone()).expect("Failed to parse test schema");
+    let key = EntityKey::data(hash, "Thing".to_owned(), "t1".to_owned());
+
+    let mut thing = Entity::new();
+    thing.set("id", "t1");
+    thing.set("name", "thing");
+    thing.set("count", Value::Null);
+
+    let mut defaults = thing.required_defaults(&schema, &key);
+    defaults.sort_by(|(a, _), (b, _)| a.cmp(b));
+    assert_eq!(
+        vec![
+            ("color".to_string(), Value::String("red".to_string())),
+            ("count".to_string(), Value::Int(0)),
+            ("tags".to_string(), Value::List(vec![])),
+        ],
+        defaults
+    );
+
+    // References to other entities are not filled in, and leave the
+    // entity invalid
+    for (field, default) in defaults {
+        thing.insert(field, default);
+    }
+    assert!(thing.validate(&schema, &key).is_err());
+    thing.set("owner", scalar::Bytes::from(&[1u8][..]));
+    assert!(thing.validate(&schema, &key).is_ok());
 }
 
 #[test]
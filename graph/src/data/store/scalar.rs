@@ -64,6 +64,31 @@ impl BigDecimal {
         self.0.digits()
     }
 
+    /// The exponent of the value when it is written as `digits * 10^exp`
+    /// with `digits` an integer
+    pub fn exponent(&self) -> i64 {
+        -self.0.as_bigint_and_exponent().1
+    }
+
+    /// Check that the exponent of this value is between `MIN_EXP` and
+    /// `MAX_EXP`. Values outside of that range can not be stored reliably,
+    /// and mappings that produce them must fail deterministically
+    pub fn check_exponent(&self) -> Result<(), anyhow::Error> {
+        let exp = self.exponent();
+        let min_exp: i64 = Self::MIN_EXP.into();
+        let max_exp: i64 = Self::MAX_EXP.into();
+        if exp < min_exp || max_exp < exp {
+            Err(anyhow::anyhow!(
+                "big decimal exponent `{}` is outside the `{}` to `{}` range",
+                exp,
+                min_exp,
+                max_exp
+            ))
+        } else {
+            Ok(())
+        }
+    }
+
     // Copy-pasted from `bigdecimal::BigDecimal::normalize`. We can use the upstream version once it
     // is included in a released version supported by Diesel.
     #[must_use]
@@ -677,6 +702,24 @@ mod test {
         }
     }
 
+    #[test]
+    fn check_exponent() {
+        let at_limit = BigDecimal::new(BigInt::from(7), BigDecimal::MAX_EXP.into());
+        assert!(at_limit.check_exponent().is_ok());
+        let at_limit = BigDecimal::new(BigInt::from(7), BigDecimal::MIN_EXP.into());
+        assert!(at_limit.check_exponent().is_ok());
+
+        let too_big = BigDecimal::new(BigInt::from(7), i64::from(BigDecimal::MAX_EXP) + 1);
+        assert!(too_big.check_exponent().is_err());
+        let too_small = BigDecimal::new(BigInt::from(7), i64::from(BigDecimal::MIN_EXP) - 1);
+        assert!(too_small.check_exponent().is_err());
+
+        // Trailing zeros do not count against the exponent
+        let trimmed = BigDecimal::new(BigInt::from(700), i64::from(BigDecimal::MIN_EXP) - 2);
+        assert_eq!(i64::from(BigDecimal::MIN_EXP), trimmed.exponent());
+        assert!(trimmed.check_exponent().is_ok());
+    }
+
     #[test]
     fn fmt_debug() {
         let bi = BigInt::from(-17);
@@ -17,6 +17,11 @@ pub const API_VERSION_0_0_6: Version = Version::new(0, 0, 6);
 /// Enables event handlers to require transaction receipts in the runtime.
 pub const API_VERSION_0_0_7: Version = Version::new(0, 0, 7);
 
+/// Allocates the objects that the host passes to mappings through the AssemblyScript runtime and
+/// pins them while host functions run, so that mappings can use the garbage collector of modern
+/// AssemblyScript versions.
+pub const API_VERSION_0_0_8: Version = Version::new(0, 0, 8);
+
 /// Before this check was introduced, there were already subgraphs in the wild with spec version
 /// 0.0.3, due to confusion with the api version. To avoid breaking those, we accept 0.0.3 though it
 /// doesn't exist.
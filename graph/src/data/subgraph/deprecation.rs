@@ -0,0 +1,66 @@
+//! Deprecation notices for subgraph names. Operators attach a notice to a
+//! subgraph name before they stop serving it, and every response to a query
+//! through that name carries the notice in its `warnings` extension, so that
+//! consumers learn about the removal while the endpoint still works
+use chrono::NaiveDate;
+
+use crate::data::query::QueryWarning;
+
+use super::SubgraphName;
+
+/// The format of removal dates, in notices and in warnings
+const DATE_FORMAT: &str = "%Y-%m-%d";
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SubgraphDeprecation {
+    /// Why the subgraph is deprecated and what to use instead
+    pub message: String,
+    /// The day after which the subgraph name may stop working
+    pub removal_date: NaiveDate,
+}
+
+impl SubgraphDeprecation {
+    /// Parse a removal date in the `YYYY-MM-DD` format
+    pub fn parse_date(date: &str) -> Result<NaiveDate, chrono::ParseError> {
+        NaiveDate::parse_from_str(date, DATE_FORMAT)
+    }
+
+    pub fn removal_date_string(&self) -> String {
+        self.removal_date.format(DATE_FORMAT).to_string()
+    }
+
+    /// The warning for responses to queries through `name`
+    pub fn warning(&self, name: &SubgraphName) -> QueryWarning {
+        let removal_date = self.removal_date_string();
+        QueryWarning {
+            message: format!(
+                "subgraph `{}` is deprecated and will be removed after {}: {}",
+                name, removal_date, self.message
+            ),
+            subgraph: Some(name.to_string()),
+            removal_date: Some(removal_date),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn deprecation_warning() {
+        let deprecation = SubgraphDeprecation {
+            message: "use `uniswap/v3` instead".to_string(),
+            removal_date: SubgraphDeprecation::parse_date("2022-10-01").unwrap(),
+        };
+        let warning = deprecation.warning(&SubgraphName::new("uniswap/v2").unwrap());
+        assert_eq!(
+            "subgraph `uniswap/v2` is deprecated and will be removed after 2022-10-01: \
+             use `uniswap/v3` instead",
+            warning.message
+        );
+        assert_eq!(Some("2022-10-01".to_string()), warning.removal_date);
+
+        assert!(SubgraphDeprecation::parse_date("10/01/2022").is_err());
+    }
+}
@@ -33,6 +33,9 @@ pub enum SubgraphFeature {
     Grafting,
     FullTextSearch,
     IpfsOnEthereumContracts,
+    ParallelHandlers,
+    Uncrashable,
+    StrictReferences,
 }
 
 impl fmt::Display for SubgraphFeature {
@@ -88,6 +91,8 @@ pub fn detect_features<C: Blockchain>(
         detect_grafting(manifest),
         detect_full_text_search(&manifest.schema),
         detect_ipfs_on_ethereum_contracts(manifest)?,
+        detect_uncrashable(manifest),
+        detect_strict_references(manifest),
     ]
     .into_iter()
     .flatten()
@@ -105,6 +110,22 @@ fn detect_non_fatal_errors<C: Blockchain>(
     }
 }
 
+fn detect_uncrashable<C: Blockchain>(manifest: &SubgraphManifest<C>) -> Option<SubgraphFeature> {
+    manifest
+        .features
+        .contains(&SubgraphFeature::Uncrashable)
+        .then(|| SubgraphFeature::Uncrashable)
+}
+
+fn detect_strict_references<C: Blockchain>(
+    manifest: &SubgraphManifest<C>,
+) -> Option<SubgraphFeature> {
+    manifest
+        .features
+        .contains(&SubgraphFeature::StrictReferences)
+        .then(|| SubgraphFeature::StrictReferences)
+}
+
 fn detect_grafting<C: Blockchain>(manifest: &SubgraphManifest<C>) -> Option<SubgraphFeature> {
     manifest.graft.as_ref().map(|_| SubgraphFeature::Grafting)
 }
@@ -146,17 +167,23 @@ fn detect_ipfs_on_ethereum_contracts<C: Blockchain>(
 mod tests {
     use super::*;
     use SubgraphFeature::*;
-    const VARIANTS: [SubgraphFeature; 4] = [
+    const VARIANTS: [SubgraphFeature; 7] = [
         NonFatalErrors,
         Grafting,
         FullTextSearch,
         IpfsOnEthereumContracts,
+        ParallelHandlers,
+        Uncrashable,
+        StrictReferences,
     ];
-    const STRING: [&str; 4] = [
+    const STRING: [&str; 7] = [
         "nonFatalErrors",
         "grafting",
         "fullTextSearch",
         "ipfsOnEthereumContracts",
+        "parallelHandlers",
+        "uncrashable",
+        "strictReferences",
     ];
 
     #[test]
@@ -0,0 +1,238 @@
+//! Deploying subgraphs from the files that `graph build` writes without
+//! uploading them to IPFS first. All files that the manifest refers to
+//! with `file: <path>` are added to a `FileRegistry` under the hash of
+//! their contents, and the manifest links to them by that hash as if they
+//! had been uploaded to IPFS. The hash of the rewritten manifest is the
+//! deployment hash
+//!
+//! Only files underneath a root directory can be imported; neither the
+//! manifest nor the files it refers to can escape from it with absolute
+//! paths, `..` or symlinks
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Context};
+use serde_yaml::{Mapping, Value};
+use sha2::{Digest, Sha256};
+
+use crate::components::store::FileRegistry;
+use crate::prelude::{DeploymentHash, Error};
+
+/// The prefix of deployment paths that refer to local files
+pub const FILE_SCHEME: &str = "file://";
+
+/// The prefix of the hashes of local files. IPFS hashes never contain an
+/// underscore, so that local files can not take the place of files from
+/// IPFS in the registry, and vice versa
+pub const HASH_PREFIX: &str = "L_";
+
+/// The name of the manifest in a directory that `graph build` wrote
+const MANIFEST: &str = "subgraph.yaml";
+
+/// The hash under which `content` is stored: `HASH_PREFIX` followed by the
+/// base58-encoded sha256 digest of it. The hashes are valid deployment
+/// hashes, but they can never be mistaken for IPFS hashes
+pub fn content_hash(content: &[u8]) -> String {
+    format!(
+        "{}{}",
+        HASH_PREFIX,
+        bs58::encode(Sha256::digest(content)).into_string()
+    )
+}
+
+/// Add the manifest at `path`, which may have a `file://` prefix, and all
+/// the files it refers to to `registry`. If `path` is a directory, the
+/// manifest is the `subgraph.yaml` in it. A relative `path` is resolved
+/// against `root`, and all files must be underneath `root`. Return the
+/// deployment hash of the manifest
+pub fn import(
+    path: &str,
+    root: &Path,
+    registry: &dyn FileRegistry,
+) -> Result<DeploymentHash, Error> {
+    let root = root
+        .canonicalize()
+        .with_context(|| format!("invalid root directory `{}`", root.display()))?;
+    let path = path.strip_prefix(FILE_SCHEME).unwrap_or(path);
+    let path = confine(&root, &root.join(path), path)?;
+    let manifest_path = if path.is_dir() {
+        confine(&root, &path.join(MANIFEST), MANIFEST)?
+    } else {
+        path
+    };
+    let base = manifest_path.parent().unwrap_or(&root).to_path_buf();
+
+    // Neither the manifest nor errors from parsing it can be returned to
+    // the caller since they might reveal the contents of files that the
+    // caller could not read otherwise
+    let text = std::fs::read(&manifest_path).map_err(|_| anyhow!("failed to read manifest"))?;
+    let mut manifest: Value =
+        serde_yaml::from_slice(&text).map_err(|_| anyhow!("the manifest is not valid YAML"))?;
+    import_links(&mut manifest, &root, &base, registry)?;
+
+    let hash = store(registry, &serde_yaml::to_vec(&manifest)?)?;
+    DeploymentHash::new(hash).map_err(|hash| anyhow!("invalid deployment hash `{}`", hash))
+}
+
+/// Resolve `path`, which the caller called `name`, and make sure that it
+/// is underneath `root`, which must already be canonical
+fn confine(root: &Path, path: &Path, name: &str) -> Result<PathBuf, Error> {
+    let path = path
+        .canonicalize()
+        .map_err(|_| anyhow!("failed to read `{}`", name))?;
+    if !path.starts_with(root) {
+        return Err(anyhow!(
+            "`{}` is not underneath the directory from which subgraphs can be deployed",
+            name
+        ));
+    }
+    Ok(path)
+}
+
+fn store(registry: &dyn FileRegistry, content: &[u8]) -> Result<String, Error> {
+    let hash = content_hash(content);
+    registry.put_file(&hash, content)?;
+    Ok(hash)
+}
+
+/// Replace every `file: <path>` in `value` with a link to the contents of
+/// the file at `path`, relative to `base`, after adding them to `registry`
+fn import_links(
+    value: &mut Value,
+    root: &Path,
+    base: &Path,
+    registry: &dyn FileRegistry,
+) -> Result<(), Error> {
+    match value {
+        Value::Mapping(map) => {
+            for (key, value) in map.iter_mut() {
+                let (name, file) = match (key.as_str(), &*value) {
+                    (Some("file"), Value::String(name)) => {
+                        (name.clone(), confine(root, &base.join(name), name)?)
+                    }
+                    _ => {
+                        import_links(value, root, base, registry)?;
+                        continue;
+                    }
+                };
+                let content =
+                    std::fs::read(&file).map_err(|_| anyhow!("failed to read `{}`", name))?;
+                let hash = store(registry, &content)?;
+                let mut link = Mapping::new();
+                link.insert(Value::from("/"), Value::from(format!("/ipfs/{}", hash)));
+                *value = Value::Mapping(link);
+            }
+        }
+        Value::Sequence(values) => {
+            for value in values {
+                import_links(value, root, base, registry)?;
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::sync::Mutex;
+
+    use crate::prelude::StoreError;
+
+    use super::*;
+
+    #[derive(Default)]
+    struct MemoryRegistry(Mutex<HashMap<String, Vec<u8>>>);
+
+    impl FileRegistry for MemoryRegistry {
+        fn get_file(&self, link: &str) -> Result<Option<Vec<u8>>, StoreError> {
+            Ok(self.0.lock().unwrap().get(link).cloned())
+        }
+
+        fn put_file(&self, link: &str, content: &[u8]) -> Result<(), StoreError> {
+            self.0
+                .lock()
+                .unwrap()
+                .insert(link.to_string(), content.to_vec());
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn content_hashes() {
+        assert_eq!(
+            "L_GKot5hBsd81kMupNCXHaqbhv3huEbxAFMLnpcX2hniwn",
+            content_hash(b"")
+        );
+        assert!(DeploymentHash::new(content_hash(b"type Token @entity")).is_ok());
+        // The longest possible hash still fits into a deployment hash
+        assert!(DeploymentHash::new(content_hash(&[0xff; 1024])).is_ok());
+    }
+
+    #[test]
+    fn imports_manifest_and_files() {
+        let dir = std::env::temp_dir().join(format!("local-import-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("abis")).unwrap();
+        std::fs::write(dir.join("schema.graphql"), "type Token @entity { id: ID! }").unwrap();
+        std::fs::write(dir.join("abis/Token.json"), "[]").unwrap();
+        std::fs::write(
+            dir.join(MANIFEST),
+            "
+schema:
+  file: schema.graphql
+dataSources:
+  - mapping:
+      abis:
+        - name: Token
+          file: abis/Token.json
+",
+        )
+        .unwrap();
+
+        let registry = MemoryRegistry::default();
+        let hash = import(&format!("file://{}", dir.display()), &dir, &registry).unwrap();
+        let relative = import("file://.", &dir, &registry).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(hash, relative);
+
+        let manifest = registry.get_file(hash.as_str()).unwrap().unwrap();
+        let manifest: Value = serde_yaml::from_slice(&manifest).unwrap();
+        let schema = manifest["schema"]["file"]["/"].as_str().unwrap();
+        assert_eq!(
+            format!("/ipfs/{}", content_hash(b"type Token @entity { id: ID! }")),
+            schema
+        );
+        assert_eq!(3, registry.0.lock().unwrap().len());
+        assert!(import("file:///does/not/exist", &std::env::temp_dir(), &registry).is_err());
+    }
+
+    #[test]
+    fn confines_files_to_root() {
+        let base = std::env::temp_dir().join(format!("local-confine-{}", std::process::id()));
+        let root = base.join("root");
+        std::fs::create_dir_all(&root).unwrap();
+        std::fs::write(base.join("secret"), "password: hunter2").unwrap();
+        std::fs::write(root.join("escape.yaml"), "schema:\n  file: ../secret\n").unwrap();
+        std::fs::write(
+            root.join("absolute.yaml"),
+            format!("schema:\n  file: {}\n", base.join("secret").display()),
+        )
+        .unwrap();
+
+        let registry = MemoryRegistry::default();
+        let secret = base.join("secret").display().to_string();
+        let attempts = [
+            secret.clone(),
+            format!("file://{}", secret),
+            "file://../secret".to_string(),
+            "file://escape.yaml".to_string(),
+            "file://absolute.yaml".to_string(),
+        ];
+        for attempt in attempts {
+            let err = import(&attempt, &root, &registry).unwrap_err();
+            assert!(!format!("{:#}", err).contains("hunter2"), "{}", attempt);
+        }
+        std::fs::remove_dir_all(&base).unwrap();
+        assert!(registry.0.lock().unwrap().is_empty());
+    }
+}
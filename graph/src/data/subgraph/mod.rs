@@ -5,10 +5,17 @@ pub mod schema;
 pub mod api_version;
 pub use api_version::*;
 
+pub mod deprecation;
 pub mod features;
+pub mod local;
+pub mod networks;
+pub mod parameters;
 pub mod status;
+pub mod version_gate;
 
+pub use deprecation::SubgraphDeprecation;
 pub use features::{SubgraphFeature, SubgraphFeatureValidationError};
+pub use version_gate::{VersionGate, VersionGateError};
 
 use anyhow::ensure;
 use anyhow::{anyhow, Error};
@@ -347,6 +354,8 @@ pub enum SubgraphManifestValidationError {
     DifferentApiVersions(BTreeSet<Version>),
     #[error(transparent)]
     FeatureValidationError(#[from] SubgraphFeatureValidationError),
+    #[error(transparent)]
+    VersionGate(#[from] VersionGateError),
     #[error("data source {0} is invalid: {1}")]
     DataSourceValidation(String, Error),
 }
@@ -487,6 +496,9 @@ pub struct BaseSubgraphManifest<C, S, D, T> {
     pub schema: S,
     pub data_sources: Vec<D>,
     pub graft: Option<Graft>,
+    /// How many blocks behind the chain head the subgraph stays on purpose
+    /// so that it rarely, or never, has to revert blocks
+    pub indexing_delay: Option<BlockNumber>,
     #[serde(default)]
     pub templates: Vec<T>,
     #[serde(skip_serializing, default)]
@@ -551,6 +563,40 @@ impl<C: Blockchain> UnvalidatedSubgraphManifest<C> {
             }));
         }
 
+        for template in &self.0.templates {
+            errors.extend(template.validate().into_iter().map(|e| {
+                SubgraphManifestValidationError::DataSourceValidation(template.name().to_owned(), e)
+            }));
+        }
+
+        // Validate that the manifest and the mappings only use what their
+        // versions allow
+        if self.0.indexing_delay.is_some() {
+            if let Err(e) = version_gate::INDEXING_DELAY.check(&self.0.spec_version) {
+                errors.push(e.into());
+            }
+        }
+        let mappings = self
+            .0
+            .data_sources
+            .iter()
+            .map(|ds| (ds.name(), ds.runtime(), ds.api_version()))
+            .chain(
+                self.0
+                    .templates
+                    .iter()
+                    .map(|t| (t.name(), t.runtime(), t.api_version())),
+            );
+        for (name, runtime, api_version) in mappings {
+            let gate_errors = match version_gate::check_host_functions(runtime, &api_version) {
+                Ok(gate_errors) => gate_errors.into_iter().map(Error::from).collect(),
+                Err(e) => vec![e],
+            };
+            errors.extend(gate_errors.into_iter().map(|e| {
+                SubgraphManifestValidationError::DataSourceValidation(name.to_owned(), e)
+            }));
+        }
+
         // For API versions newer than 0.0.5, validate that all mappings uses the same api_version
         if let Err(different_api_versions) = self.0.unified_mapping_api_version() {
             errors.push(different_api_versions.into());
@@ -608,6 +654,10 @@ impl<C: Blockchain> UnvalidatedSubgraphManifest<C> {
     pub fn spec_version(&self) -> &Version {
         &self.0.spec_version
     }
+
+    pub fn manifest(&self) -> &SubgraphManifest<C> {
+        &self.0
+    }
 }
 
 impl<C: Blockchain> SubgraphManifest<C> {
@@ -689,10 +739,17 @@ impl<C: Blockchain> UnresolvedSubgraphManifest<C> {
             schema,
             data_sources,
             graft,
+            indexing_delay,
             templates,
             chain,
         } = self;
 
+        ensure!(
+            indexing_delay.map_or(true, |delay| delay >= 0),
+            "The indexing delay of subgraph `{}` must not be negative",
+            id
+        );
+
         if !(MIN_SPEC_VERSION..=max_spec_version.clone()).contains(&spec_version) {
             return Err(anyhow!(
                 "This Graph Node only supports manifest spec versions between {} and {}, but subgraph `{}` uses `{}`",
@@ -738,6 +795,7 @@ impl<C: Blockchain> UnresolvedSubgraphManifest<C> {
             schema,
             data_sources,
             graft,
+            indexing_delay,
             templates,
             chain,
         })
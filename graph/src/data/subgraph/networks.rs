@@ -0,0 +1,217 @@
+//! Manifests for more than one network. Such a manifest lists the networks
+//! in a toplevel `networks` section, and, for each network, the settings of
+//! every data source that differ between networks, like its address and
+//! start block:
+//!
+//! ```yaml
+//! networks:
+//!   mainnet:
+//!     Factory:
+//!       address: "0x1f98431c8ad98523631ae4a59f267346ea31f984"
+//!       startBlock: 12369621
+//!   matic:
+//!     Factory:
+//!       address: "0x1f98431c8ad98523631ae4a59f267346ea31f984"
+//!       startBlock: 22757547
+//! dataSources:
+//!   - name: Factory
+//!     network: mainnet
+//!     source:
+//!       abi: Factory
+//! ```
+//!
+//! Deploying the manifest turns it into one manifest per network, in which
+//! all data sources and templates use that network and the settings for
+//! that network are merged into the `source` of each data source. The
+//! manifests for all networks form a family of deployments that comes from
+//! the same manifest
+use anyhow::anyhow;
+use serde_yaml::{Mapping, Value};
+
+use crate::prelude::Error;
+
+/// The toplevel key of the manifest that lists the networks
+const NETWORKS: &str = "networks";
+
+/// Split `manifest` into one manifest for each of its networks for which
+/// `supported` is true, and return the network names together with the
+/// manifests. Return `None` if the manifest does not list networks
+pub fn instantiate(
+    manifest: &[u8],
+    supported: impl Fn(&str) -> bool,
+) -> Result<Option<Vec<(String, Vec<u8>)>>, Error> {
+    let mut raw: Mapping = serde_yaml::from_slice(manifest)?;
+    let networks = match raw.remove(&Value::from(NETWORKS)) {
+        Some(Value::Mapping(networks)) => networks,
+        Some(_) => return Err(anyhow!("`networks` must be a map of network names")),
+        None => return Ok(None),
+    };
+
+    let mut instances = Vec::new();
+    for (network, overrides) in &networks {
+        let network = network
+            .as_str()
+            .ok_or_else(|| anyhow!("network names must be strings"))?;
+        let overrides = match overrides {
+            Value::Mapping(overrides) => overrides.clone(),
+            Value::Null => Mapping::new(),
+            _ => {
+                return Err(anyhow!(
+                    "the settings for network `{}` must be a map of data source names",
+                    network
+                ))
+            }
+        };
+        // Check every network, not just the supported ones, so that a
+        // manifest does not deploy on one node and fail on another
+        let instance = instance(&raw, network, &overrides)?;
+        if supported(network) {
+            instances.push((network.to_string(), serde_yaml::to_vec(&instance)?));
+        }
+    }
+    Ok(Some(instances))
+}
+
+/// The manifest `raw` for `network`, with the settings in `overrides`
+fn instance(raw: &Mapping, network: &str, overrides: &Mapping) -> Result<Mapping, Error> {
+    let mut instance = raw.clone();
+    let mut names = Vec::new();
+    if let Some(Value::Sequence(data_sources)) = instance.get_mut(&Value::from("dataSources")) {
+        for data_source in data_sources.iter_mut().filter_map(Value::as_mapping_mut) {
+            let name = data_source
+                .get(&Value::from("name"))
+                .and_then(Value::as_str)
+                .ok_or_else(|| anyhow!("data sources in multi-network manifests need a name"))?
+                .to_string();
+            let settings = match overrides.get(&Value::from(name.as_str())) {
+                Some(Value::Mapping(settings)) => settings.clone(),
+                Some(Value::Null) => Mapping::new(),
+                Some(_) => {
+                    return Err(anyhow!(
+                        "the settings for data source `{}` on network `{}` must be a map",
+                        name,
+                        network
+                    ))
+                }
+                None => {
+                    return Err(anyhow!(
+                        "the manifest has no settings for data source `{}` on network `{}`",
+                        name,
+                        network
+                    ))
+                }
+            };
+            data_source.insert(Value::from("network"), Value::from(network));
+            if !settings.is_empty() {
+                let source_key = Value::from("source");
+                if data_source.get(&source_key).is_none() {
+                    data_source.insert(source_key.clone(), Value::Mapping(Mapping::new()));
+                }
+                let source = data_source
+                    .get_mut(&source_key)
+                    .and_then(Value::as_mapping_mut)
+                    .ok_or_else(|| anyhow!("the source of data source `{}` must be a map", name))?;
+                for (key, value) in settings {
+                    source.insert(key, value);
+                }
+            }
+            names.push(name);
+        }
+    }
+    if let Some(name) = overrides
+        .iter()
+        .filter_map(|(name, _)| name.as_str())
+        .find(|name| !names.iter().any(|known| known == name))
+    {
+        return Err(anyhow!(
+            "the settings for network `{}` refer to an unknown data source `{}`",
+            network,
+            name
+        ));
+    }
+
+    if let Some(Value::Sequence(templates)) = instance.get_mut(&Value::from("templates")) {
+        for template in templates.iter_mut().filter_map(Value::as_mapping_mut) {
+            template.insert(Value::from("network"), Value::from(network));
+        }
+    }
+    Ok(instance)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MANIFEST: &str = "
+specVersion: 0.0.4
+networks:
+  mainnet:
+    Factory:
+      address: \"0x01\"
+      startBlock: 100
+  matic:
+    Factory:
+      address: \"0x02\"
+  goerli:
+    Factory:
+dataSources:
+  - name: Factory
+    network: mainnet
+    source:
+      abi: Factory
+templates:
+  - name: Pair
+    network: mainnet
+";
+
+    #[test]
+    fn instantiates_supported_networks() {
+        let instances = instantiate(MANIFEST.as_bytes(), |network| network != "goerli")
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            vec!["mainnet", "matic"],
+            instances
+                .iter()
+                .map(|(network, _)| network.as_str())
+                .collect::<Vec<_>>()
+        );
+
+        let (_, matic) = &instances[1];
+        let matic: Value = serde_yaml::from_slice(matic).unwrap();
+        assert!(matic.get(NETWORKS).is_none());
+        let data_source = &matic["dataSources"][0];
+        assert_eq!("matic", data_source["network"].as_str().unwrap());
+        assert_eq!("0x02", data_source["source"]["address"].as_str().unwrap());
+        assert_eq!("Factory", data_source["source"]["abi"].as_str().unwrap());
+        assert!(data_source["source"].get("startBlock").is_none());
+        assert_eq!("matic", matic["templates"][0]["network"].as_str().unwrap());
+    }
+
+    #[test]
+    fn checks_settings() {
+        assert_eq!(
+            None,
+            instantiate(b"specVersion: 0.0.4\n", |_| true).unwrap()
+        );
+
+        let missing = MANIFEST.replace("  goerli:\n    Factory:\n", "  goerli:\n");
+        assert_eq!(
+            "the manifest has no settings for data source `Factory` on network `goerli`",
+            instantiate(missing.as_bytes(), |network| network == "mainnet")
+                .unwrap_err()
+                .to_string()
+        );
+
+        let unknown = MANIFEST.replace(
+            "  goerli:\n    Factory:\n",
+            "  goerli:\n    Factory:\n    Router:\n",
+        );
+        assert_eq!(
+            "the settings for network `goerli` refer to an unknown data source `Router`",
+            instantiate(unknown.as_bytes(), |_| true)
+                .unwrap_err()
+                .to_string()
+        );
+    }
+}
@@ -0,0 +1,399 @@
+//! Manifests with parameters, so that one manifest can be deployed many
+//! times, for example, for the same protocol on different chains. The
+//! manifest declares its parameters in a toplevel `parameters` section and
+//! refers to them anywhere in the manifest as `{{name}}`:
+//!
+//! ```yaml
+//! parameters:
+//!   factory:
+//!     description: The address of the factory contract
+//!   startBlock:
+//!     default: 0
+//! dataSources:
+//!   - source:
+//!       address: "{{factory}}"
+//!       startBlock: {{startBlock}}
+//! ```
+//!
+//! Deploying such a manifest with values for its parameters deploys the
+//! manifest with the placeholders replaced by the values and without the
+//! `parameters` section. Different values therefore lead to different
+//! deployments
+use std::collections::{BTreeMap, BTreeSet};
+
+use anyhow::anyhow;
+use serde::Deserialize;
+use serde_json::Value as JsonValue;
+use serde_yaml::Value;
+
+use crate::prelude::Error;
+
+/// The toplevel key of the manifest that declares the parameters
+const PARAMETERS: &str = "parameters";
+
+/// What placeholders are replaced with before parsing the manifest; the
+/// number is the position of the parameter in the list of used parameters
+const MARKER_PREFIX: &str = "__parameter_";
+const MARKER_SUFFIX: &str = "__";
+
+#[derive(Debug, Default, Deserialize)]
+struct Parameter {
+    /// The value to use when the deployment does not set the parameter
+    default: Option<Value>,
+}
+
+/// Replace the parameters in `manifest` with `values` and the defaults in
+/// the manifest. Return `None` if the manifest does not declare parameters
+/// and no values were given, since the manifest can then be deployed as it
+/// is
+///
+/// The manifest is parsed before any values are set, and the values are
+/// put into the parsed manifest; they can therefore never change its
+/// structure, no matter what characters they contain
+pub fn instantiate(
+    manifest: &[u8],
+    values: &BTreeMap<String, JsonValue>,
+) -> Result<Option<Vec<u8>>, Error> {
+    let text = std::str::from_utf8(manifest)?;
+    let used: Vec<String> = placeholders(text).into_iter().collect();
+
+    // A placeholder that is a whole value, like `startBlock: {{startBlock}}`,
+    // is not valid YAML; replace each placeholder with a plain string that
+    // is put back after parsing
+    let mut masked = text.to_string();
+    for (index, name) in used.iter().enumerate() {
+        masked = replace(&masked, name, &marker(index));
+    }
+    let mut instance: Value = serde_yaml::from_str(&masked)?;
+    let declared = match instance.get(PARAMETERS) {
+        Some(declared) => parameters(declared)?,
+        // Manifests without parameters are left alone, even if they
+        // happen to contain something that looks like a placeholder
+        None if values.is_empty() => return Ok(None),
+        None => return Err(anyhow!("the manifest does not declare any parameters")),
+    };
+
+    if let Some(name) = values.keys().find(|name| !declared.contains_key(*name)) {
+        return Err(anyhow!("the manifest has no parameter `{}`", name));
+    }
+    if let Some(name) = used.iter().find(|name| !declared.contains_key(*name)) {
+        return Err(anyhow!(
+            "the manifest uses the parameter `{}` without declaring it",
+            name
+        ));
+    }
+    let mut settings = BTreeMap::new();
+    for (name, parameter) in &declared {
+        let value = match (values.get(name), &parameter.default) {
+            (Some(value), _) => json_value(name, value)?,
+            (None, Some(default)) => default_value(name, default)?,
+            (None, None) => return Err(anyhow!("no value for the parameter `{}`", name)),
+        };
+        settings.insert(name.as_str(), value);
+    }
+    let settings: Vec<Value> = used
+        .iter()
+        .map(|name| settings[name.as_str()].clone())
+        .collect();
+
+    if let Value::Mapping(map) = &mut instance {
+        map.remove(&Value::from(PARAMETERS));
+    }
+    substitute(&mut instance, &settings);
+    Ok(Some(serde_yaml::to_vec(&instance)?))
+}
+
+fn parameters(declared: &Value) -> Result<BTreeMap<String, Parameter>, Error> {
+    let map = match declared {
+        Value::Mapping(map) => map,
+        Value::Null => return Ok(BTreeMap::new()),
+        _ => return Err(anyhow!("`parameters` must be a map of parameter names")),
+    };
+    map.iter()
+        .map(|(name, parameter)| {
+            let name = name
+                .as_str()
+                .ok_or_else(|| anyhow!("parameter names must be strings"))?;
+            let parameter = match parameter {
+                Value::Null => Parameter::default(),
+                parameter => serde_yaml::from_value(parameter.clone())
+                    .map_err(|e| anyhow!("invalid parameter `{}`: {}", name, e))?,
+            };
+            Ok((name.to_string(), parameter))
+        })
+        .collect()
+}
+
+/// The names of all `{{name}}` placeholders in `text`
+fn placeholders(text: &str) -> BTreeSet<String> {
+    let mut names = BTreeSet::new();
+    let mut rest = text;
+    while let Some(start) = rest.find("{{") {
+        rest = &rest[start + 2..];
+        match rest.find("}}") {
+            Some(end) => {
+                names.insert(rest[..end].trim().to_string());
+                rest = &rest[end + 2..];
+            }
+            None => break,
+        }
+    }
+    names
+}
+
+/// Replace all placeholders for `name` in `text` with `value`, allowing
+/// for spaces inside the braces
+fn replace(text: &str, name: &str, value: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find("{{") {
+        let after = &rest[start + 2..];
+        match after.find("}}") {
+            Some(end) if after[..end].trim() == name => {
+                result.push_str(&rest[..start]);
+                result.push_str(value);
+                rest = &after[end + 2..];
+            }
+            _ => {
+                result.push_str(&rest[..start + 2]);
+                rest = after;
+            }
+        }
+    }
+    result.push_str(rest);
+    result
+}
+
+fn marker(index: usize) -> String {
+    format!("{}{}{}", MARKER_PREFIX, index, MARKER_SUFFIX)
+}
+
+/// Put the `settings` where the manifest has markers. A string that is
+/// just a marker becomes the value itself, so that numbers stay numbers;
+/// markers inside longer strings are replaced by the text of the value
+fn substitute(value: &mut Value, settings: &[Value]) {
+    match value {
+        Value::String(s) => match setting(s, settings) {
+            Some((setting, rest)) if rest.is_empty() => *value = setting.clone(),
+            _ if s.contains(MARKER_PREFIX) => *s = fill(s, settings),
+            _ => {}
+        },
+        Value::Sequence(values) => {
+            for value in values {
+                substitute(value, settings);
+            }
+        }
+        Value::Mapping(map) => {
+            *map = std::mem::take(map)
+                .into_iter()
+                .map(|(mut key, mut value)| {
+                    substitute(&mut key, settings);
+                    substitute(&mut value, settings);
+                    (key, value)
+                })
+                .collect();
+        }
+        Value::Null | Value::Bool(_) | Value::Number(_) => {}
+    }
+}
+
+/// The setting for the marker at the start of `text` and the text after
+/// the marker
+fn setting<'a, 'b>(text: &'a str, settings: &'b [Value]) -> Option<(&'b Value, &'a str)> {
+    let rest = text.strip_prefix(MARKER_PREFIX)?;
+    let digits = rest.find(|c: char| !c.is_ascii_digit())?;
+    let setting = settings.get(rest[..digits].parse::<usize>().ok()?)?;
+    let rest = rest[digits..].strip_prefix(MARKER_SUFFIX)?;
+    Some((setting, rest))
+}
+
+/// Replace the markers in `s` with the text of their settings. The
+/// text is scanned once so that markers in settings are left alone
+fn fill(s: &str, settings: &[Value]) -> String {
+    let mut result = String::with_capacity(s.len());
+    let mut rest = s;
+    while let Some(start) = rest.find(MARKER_PREFIX) {
+        result.push_str(&rest[..start]);
+        rest = &rest[start..];
+        match setting(rest, settings) {
+            Some((setting, after)) => {
+                result.push_str(&text(setting));
+                rest = after;
+            }
+            None => {
+                result.push_str(MARKER_PREFIX);
+                rest = &rest[MARKER_PREFIX.len()..];
+            }
+        }
+    }
+    result.push_str(rest);
+    result
+}
+
+fn json_value(name: &str, value: &JsonValue) -> Result<Value, Error> {
+    match value {
+        JsonValue::String(_) | JsonValue::Number(_) | JsonValue::Bool(_) => {
+            Ok(serde_yaml::to_value(value)?)
+        }
+        _ => Err(anyhow!(
+            "the value for the parameter `{}` must be a string, number or boolean",
+            name
+        )),
+    }
+}
+
+fn default_value(name: &str, value: &Value) -> Result<Value, Error> {
+    match value {
+        Value::String(_) | Value::Number(_) | Value::Bool(_) => Ok(value.clone()),
+        _ => Err(anyhow!(
+            "the default for the parameter `{}` must be a string, number or boolean",
+            name
+        )),
+    }
+}
+
+/// The text of a string, number or boolean
+fn text(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        Value::Number(n) => n.to_string(),
+        Value::Bool(b) => b.to_string(),
+        _ => unreachable!("parameter values are strings, numbers or booleans"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MANIFEST: &str = "
+specVersion: 0.0.4
+parameters:
+  factory:
+  startBlock:
+    default: 10
+dataSources:
+  - source:
+      address: \"{{ factory }}\"
+      startBlock: {{startBlock}}
+";
+
+    fn values(values: &[(&str, JsonValue)]) -> BTreeMap<String, JsonValue> {
+        values
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.clone()))
+            .collect()
+    }
+
+    fn source(manifest: &[u8]) -> (String, u64) {
+        let manifest: Value = serde_yaml::from_slice(manifest).unwrap();
+        assert!(manifest.get(PARAMETERS).is_none());
+        let source = &manifest["dataSources"][0]["source"];
+        (
+            source["address"].as_str().unwrap().to_string(),
+            source["startBlock"].as_u64().unwrap(),
+        )
+    }
+
+    #[test]
+    fn uses_values_and_defaults() {
+        let instance = instantiate(
+            MANIFEST.as_bytes(),
+            &values(&[("factory", JsonValue::from("0xabc"))]),
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(("0xabc".to_string(), 10), source(&instance));
+
+        let instance = instantiate(
+            MANIFEST.as_bytes(),
+            &values(&[
+                ("factory", JsonValue::from("0xdef")),
+                ("startBlock", JsonValue::from(42)),
+            ]),
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(("0xdef".to_string(), 42), source(&instance));
+    }
+
+    #[test]
+    fn rejects_bad_values() {
+        let err = |values: BTreeMap<String, JsonValue>| {
+            instantiate(MANIFEST.as_bytes(), &values)
+                .unwrap_err()
+                .to_string()
+        };
+
+        assert_eq!("no value for the parameter `factory`", err(values(&[])));
+        assert_eq!(
+            "the manifest has no parameter `token`",
+            err(values(&[
+                ("factory", JsonValue::from("0xabc")),
+                ("token", JsonValue::from("0xdef"))
+            ]))
+        );
+        assert!(err(values(&[("factory", JsonValue::Null)])).contains("must be a string"));
+
+        let undeclared = "parameters:\nsource: \"{{factory}}\"\n";
+        assert_eq!(
+            "the manifest uses the parameter `factory` without declaring it",
+            instantiate(undeclared.as_bytes(), &BTreeMap::new())
+                .unwrap_err()
+                .to_string()
+        );
+    }
+
+    #[test]
+    fn values_cannot_change_the_manifest() {
+        // Values that would end the string or start a new key if they
+        // were pasted into the manifest text stay inside their string
+        for evil in [
+            "0xabc\"\n      startBlock: 99",
+            "0xabc\n  - source:\n      address: other",
+            "{{startBlock}}",
+            "__parameter_1__",
+        ] {
+            let instance = instantiate(
+                MANIFEST.as_bytes(),
+                &values(&[("factory", JsonValue::from(evil))]),
+            )
+            .unwrap()
+            .unwrap();
+            assert_eq!((evil.to_string(), 10), source(&instance));
+            let manifest: Value = serde_yaml::from_slice(&instance).unwrap();
+            assert_eq!(1, manifest["dataSources"].as_sequence().unwrap().len());
+        }
+    }
+
+    #[test]
+    fn placeholders_inside_strings() {
+        let manifest = "
+parameters:
+  network:
+  version:
+    default: 2
+description: \"{{network}} exchange, v{{ version }}\"
+\"{{network}}\": {{version}}
+";
+        let instance = instantiate(
+            manifest.as_bytes(),
+            &values(&[("network", JsonValue::from("x: \"y\""))]),
+        )
+        .unwrap()
+        .unwrap();
+        let instance: Value = serde_yaml::from_slice(&instance).unwrap();
+        assert_eq!(
+            Some("x: \"y\" exchange, v2"),
+            instance["description"].as_str()
+        );
+        assert_eq!(Some(2), instance["x: \"y\""].as_u64());
+    }
+
+    #[test]
+    fn manifests_without_parameters() {
+        let manifest = b"specVersion: 0.0.4\ndescription: \"{{ not a parameter }}\"\n";
+        assert_eq!(None, instantiate(manifest, &BTreeMap::new()).unwrap());
+        assert!(instantiate(manifest, &values(&[("factory", JsonValue::from("0xabc"))])).is_err());
+    }
+}
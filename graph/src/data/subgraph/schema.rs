@@ -12,6 +12,7 @@ use std::{fmt, fmt::Display};
 use super::DeploymentHash;
 use crate::data::graphql::TryFromValue;
 use crate::data::store::Value;
+use crate::data::subgraph::features::detect_features;
 use crate::data::subgraph::SubgraphManifest;
 use crate::prelude::*;
 use crate::{blockchain::Blockchain, components::store::EntityType};
@@ -166,12 +167,20 @@ pub struct SubgraphManifestEntity {
 }
 
 impl<'a, C: Blockchain> From<&'a super::SubgraphManifest<C>> for SubgraphManifestEntity {
+    /// The `features` are the features that the manifest declares together
+    /// with the ones that were detected in the subgraph. Validation ensures
+    /// that the latter are declared, but manifests with a spec version
+    /// before 0.0.4 can not declare features and are not validated
     fn from(manifest: &'a super::SubgraphManifest<C>) -> Self {
+        let mut features = manifest.features.clone();
+        if let Ok(detected) = detect_features(manifest) {
+            features.extend(detected);
+        }
         Self {
             spec_version: manifest.spec_version.to_string(),
             description: manifest.description.clone(),
             repository: manifest.repository.clone(),
-            features: manifest.features.iter().map(|f| f.to_string()).collect(),
+            features: features.iter().map(|f| f.to_string()).collect(),
             schema: manifest.schema.document.clone().to_string(),
         }
     }
@@ -1,9 +1,21 @@
 //! Support for the indexing status API
 
+use std::collections::BTreeSet;
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+
 use super::schema::{SubgraphError, SubgraphHealth};
-use crate::components::store::DeploymentId;
+use super::SubgraphFeature;
+use crate::components::store::{DeploymentId, JobRequest};
 use crate::data::graphql::{object, IntoValue};
-use crate::prelude::{r, web3::types::H256, BlockPtr, Value};
+use crate::data::schema_diff::SchemaCompatibility;
+use crate::prelude::{
+    chrono::{DateTime, Utc},
+    r,
+    web3::types::H256,
+    BlockNumber, BlockPtr, Value,
+};
 
 pub enum Filter {
     /// Get all versions for the named subgraph
@@ -86,6 +98,126 @@ impl IntoValue for ChainInfo {
     }
 }
 
+/// How a provider of a chain answered when this node last asked it for its
+/// latest block
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ProviderState {
+    Ok,
+    Failed,
+    Timeout,
+}
+
+impl ProviderState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ProviderState::Ok => "ok",
+            ProviderState::Failed => "failed",
+            ProviderState::Timeout => "timeout",
+        }
+    }
+}
+
+/// The health of one provider of a chain
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ProviderInfo {
+    /// The label of the provider from the configuration
+    pub name: String,
+    pub status: ProviderState,
+    /// The latest block the provider knows about; `None` unless
+    /// `status` is `Ok`
+    pub latest_block: Option<BlockNumber>,
+    /// How long the provider took to answer; `None` if it did not answer
+    /// in time
+    pub latency: Option<Duration>,
+}
+
+impl IntoValue for ProviderInfo {
+    fn into_value(self) -> r::Value {
+        let ProviderInfo {
+            name,
+            status,
+            latest_block,
+            latency,
+        } = self;
+        object! {
+            __typename: "ProviderStatus",
+            name: name,
+            status: r::Value::Enum(status.as_str().to_string()),
+            latestBlock: latest_block.map(|number| format!("{}", number)),
+            latencyMs: latency.map(|d| d.as_millis() as i32),
+        }
+    }
+}
+
+/// The state of a chain that this node knows about, independent of any
+/// deployment
+#[derive(Debug)]
+pub struct ChainStatus {
+    pub network: String,
+    /// The latest block that the block ingestor wrote to the chain store
+    pub chain_head_block: Option<EthereumBlock>,
+    /// The oldest block that is still in the block cache of the chain
+    pub earliest_block: Option<EthereumBlock>,
+    /// Only filled in for chains whose providers can be checked; this node
+    /// can only do that for Ethereum chains
+    pub providers: Vec<ProviderInfo>,
+}
+
+impl IntoValue for ChainStatus {
+    fn into_value(self) -> r::Value {
+        let ChainStatus {
+            network,
+            chain_head_block,
+            earliest_block,
+            providers,
+        } = self;
+        object! {
+            __typename: "ChainStatus",
+            network: network,
+            chainHeadBlock: chain_head_block,
+            earliestBlock: earliest_block,
+            providers: providers,
+        }
+    }
+}
+
+/// The changes of a deployment that were processed but not written to the
+/// store yet. Only the node that indexes the deployment knows this
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct WriteQueueInfo {
+    /// The number of write and revert requests in the queue
+    pub depth: usize,
+    /// The number of blocks whose changes are in the queue
+    pub blocks: usize,
+    /// The number of entity changes in the queue
+    pub entities: usize,
+    /// How long writing the last request to the store took
+    pub last_write: Option<Duration>,
+    /// Whether processing blocks is currently held up because the queue
+    /// is full
+    pub throttled: bool,
+}
+
+impl IntoValue for WriteQueueInfo {
+    fn into_value(self) -> r::Value {
+        let WriteQueueInfo {
+            depth,
+            blocks,
+            entities,
+            last_write,
+            throttled,
+        } = self;
+        object! {
+            __typename: "WriteQueueStatus",
+            depth: depth as i32,
+            blocks: blocks as i32,
+            entities: entities as i32,
+            lastWriteMs: last_write.map(|d| d.as_millis() as i32),
+            throttled: throttled,
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct Info {
     pub id: DeploymentId,
@@ -104,8 +236,66 @@ pub struct Info {
 
     pub entity_count: u64,
 
+    /// An estimate of the number of bytes the deployment's entities take
+    /// up on disk, or `None` if the store has not determined it yet
+    pub entity_size: Option<u64>,
+
+    /// How often the deployment was restarted automatically after it
+    /// failed with a non-deterministic error
+    pub restart_count: u64,
+
     /// ID of the Graph Node that the subgraph is indexed by.
     pub node: Option<String>,
+
+    /// The features the subgraph declares or was found to use when it was
+    /// deployed
+    pub features: BTreeSet<SubgraphFeature>,
+
+    /// Whether the database shard that stores the deployment could be
+    /// reached. If it could not, only `id`, `subgraph`, `node`, and the
+    /// network of the chain are known, and all other fields have
+    /// placeholder values
+    pub available: bool,
+
+    /// The write queue of the deployment if this node indexes it
+    pub write_queue: Option<WriteQueueInfo>,
+
+    /// The canonical hash of the deployment's schema; deployments created
+    /// before schemas were hashed do not have one
+    pub schema_hash: Option<String>,
+
+    /// How the deployment's schema compares to the schema of the version
+    /// it replaced, if it replaced one
+    pub schema_compatibility: Option<SchemaCompatibility>,
+}
+
+impl Info {
+    /// The status of a deployment whose shard can not be reached
+    pub fn unavailable(id: DeploymentId, subgraph: String, network: String) -> Self {
+        Info {
+            id,
+            subgraph,
+            synced: false,
+            health: SubgraphHealth::Unhealthy,
+            fatal_error: None,
+            non_fatal_errors: vec![],
+            chains: vec![ChainInfo {
+                network,
+                chain_head_block: None,
+                earliest_block: None,
+                latest_block: None,
+            }],
+            entity_count: 0,
+            entity_size: None,
+            restart_count: 0,
+            node: None,
+            features: BTreeSet::new(),
+            available: false,
+            write_queue: None,
+            schema_hash: None,
+            schema_compatibility: None,
+        }
+    }
 }
 
 impl IntoValue for Info {
@@ -115,11 +305,18 @@ impl IntoValue for Info {
             subgraph,
             chains,
             entity_count,
+            entity_size,
+            restart_count,
             fatal_error,
             health,
             node,
             non_fatal_errors,
             synced,
+            features,
+            available,
+            write_queue,
+            schema_hash,
+            schema_compatibility,
         } = self;
 
         fn subgraph_error_to_value(subgraph_error: SubgraphError) -> r::Value {
@@ -160,7 +357,188 @@ impl IntoValue for Info {
             nonFatalErrors: non_fatal_errors,
             chains: chains.into_iter().map(|chain| chain.into_value()).collect::<Vec<_>>(),
             entityCount: format!("{}", entity_count),
+            entitySize: entity_size.map(|size| format!("{}", size)),
+            restartCount: restart_count as i32,
+            node: node,
+            features: features
+                .iter()
+                .map(|feature| r::Value::String(feature.to_string()))
+                .collect::<Vec<_>>(),
+            available: available,
+            writeQueue: write_queue,
+            schemaHash: schema_hash,
+            schemaCompatibility: schema_compatibility,
+        }
+    }
+}
+
+/// Where a job is in its life. Jobs start out `Queued`, are `Running`
+/// once an index node picked them up, and end up in one of the other
+/// states
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum JobState {
+    Queued,
+    Running,
+    Succeeded,
+    Failed,
+    Cancelled,
+}
+
+impl JobState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            JobState::Queued => "queued",
+            JobState::Running => "running",
+            JobState::Succeeded => "succeeded",
+            JobState::Failed => "failed",
+            JobState::Cancelled => "cancelled",
+        }
+    }
+
+    /// Whether the job will not do any more work
+    pub fn is_finished(&self) -> bool {
+        matches!(
+            self,
+            JobState::Succeeded | JobState::Failed | JobState::Cancelled
+        )
+    }
+}
+
+impl fmt::Display for JobState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl FromStr for JobState {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "queued" => Ok(JobState::Queued),
+            "running" => Ok(JobState::Running),
+            "succeeded" => Ok(JobState::Succeeded),
+            "failed" => Ok(JobState::Failed),
+            "cancelled" => Ok(JobState::Cancelled),
+            _ => Err(format!("invalid job state: {:?}", s)),
+        }
+    }
+}
+
+impl IntoValue for JobState {
+    fn into_value(self) -> r::Value {
+        r::Value::Enum(self.as_str().to_string())
+    }
+}
+
+/// Which jobs to list; jobs have to match all the conditions that are set
+#[derive(Clone, Debug, Default)]
+pub struct JobFilter {
+    pub id: Option<i32>,
+    /// The hash of the deployment the jobs work on
+    pub deployment: Option<String>,
+    /// Only jobs in one of these states; all jobs if this is empty
+    pub states: Vec<JobState>,
+}
+
+/// A job and how far it got
+#[derive(Clone, Debug)]
+pub struct JobInfo {
+    pub id: i32,
+    /// The hash of the deployment the job works on
+    pub deployment: String,
+    pub request: JobRequest,
+    pub state: JobState,
+    /// The index node that runs or ran the job
+    pub node: Option<String>,
+    /// How many units of work the job has done, out of `total`. What a
+    /// unit is depends on the kind of job, for example, rows for copies
+    /// and entity types for exports
+    pub done: i64,
+    /// The units of work the job has to do, if it knows that yet
+    pub total: Option<i64>,
+    /// A summary of what the job did once it succeeded
+    pub outcome: Option<String>,
+    pub error: Option<String>,
+    pub cancel_requested: bool,
+    pub created_at: DateTime<Utc>,
+    pub started_at: Option<DateTime<Utc>>,
+    pub finished_at: Option<DateTime<Utc>>,
+}
+
+impl IntoValue for JobInfo {
+    fn into_value(self) -> r::Value {
+        let JobInfo {
+            id,
+            deployment,
+            request,
+            state,
+            node,
+            done,
+            total,
+            outcome,
+            error,
+            cancel_requested,
+            created_at,
+            started_at,
+            finished_at,
+        } = self;
+        object! {
+            __typename: "Job",
+            id: id,
+            deployment: deployment,
+            kind: request.kind(),
+            state: state,
             node: node,
+            done: format!("{}", done),
+            total: total.map(|total| format!("{}", total)),
+            outcome: outcome,
+            error: error,
+            cancelRequested: cancel_requested,
+            createdAt: created_at.to_rfc3339(),
+            startedAt: started_at.map(|at| at.to_rfc3339()),
+            finishedAt: finished_at.map(|at| at.to_rfc3339()),
+        }
+    }
+}
+
+#[test]
+fn unavailable_info() {
+    let info = Info::unavailable(
+        DeploymentId(7),
+        "QmUnavailable".to_string(),
+        "mainnet".to_string(),
+    );
+    let value = match info.into_value() {
+        r::Value::Object(value) => value,
+        value => panic!("status is not an object: {:?}", value),
+    };
+
+    // What we know about the deployment without its shard is there, the
+    // rest says that nothing is known
+    assert_eq!(
+        Some(&r::Value::String("QmUnavailable".to_string())),
+        value.get("subgraph")
+    );
+    assert_eq!(Some(&r::Value::Boolean(false)), value.get("available"));
+    assert_eq!(Some(&r::Value::Boolean(false)), value.get("synced"));
+    assert_eq!(
+        Some(&r::Value::Enum("unhealthy".to_string())),
+        value.get("health")
+    );
+    assert_eq!(Some(&r::Value::Null), value.get("node"));
+    let chain = match value.get("chains") {
+        Some(r::Value::List(chains)) if chains.len() == 1 => &chains[0],
+        chains => panic!("expected one chain but got {:?}", chains),
+    };
+    match chain {
+        r::Value::Object(chain) => {
+            assert_eq!(
+                Some(&r::Value::String("mainnet".to_string())),
+                chain.get("network")
+            );
+            assert_eq!(Some(&r::Value::Null), chain.get("latestBlock"));
         }
+        chain => panic!("chain is not an object: {:?}", chain),
     }
 }
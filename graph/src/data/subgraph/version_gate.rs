@@ -0,0 +1,152 @@
+//! Parts of subgraphs that are only available from some manifest
+//! `specVersion` or mapping `apiVersion` on. Everything that is added to
+//! manifests, trigger types or host functions and that older `graph-cli`
+//! and `graph-ts` versions do not know about gets a `VersionGate` here,
+//! and deploying a subgraph that uses it with an older version fails with
+//! an error that says which version it needs
+use std::fmt;
+
+use semver::Version;
+use thiserror::Error;
+
+use super::{calls_host_fn, API_VERSION_0_0_7, API_VERSION_0_0_8, SPEC_VERSION_0_0_5};
+
+/// Which of the versions in a subgraph a gate checks
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum VersionKind {
+    /// The `specVersion` of the manifest
+    Spec,
+    /// The `apiVersion` of a mapping
+    Api,
+}
+
+impl VersionKind {
+    fn subject(&self) -> &'static str {
+        match self {
+            VersionKind::Spec => "the manifest",
+            VersionKind::Api => "the mapping",
+        }
+    }
+}
+
+impl fmt::Display for VersionKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            VersionKind::Spec => write!(f, "specVersion"),
+            VersionKind::Api => write!(f, "apiVersion"),
+        }
+    }
+}
+
+/// Something that needs at least version `since` of the `specVersion` or
+/// `apiVersion`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct VersionGate {
+    /// What the gate is for, as it appears in manifests or mappings
+    pub name: &'static str,
+    pub kind: VersionKind,
+    pub since: Version,
+}
+
+impl VersionGate {
+    pub const fn spec(name: &'static str, since: Version) -> Self {
+        VersionGate {
+            name,
+            kind: VersionKind::Spec,
+            since,
+        }
+    }
+
+    pub const fn api(name: &'static str, since: Version) -> Self {
+        VersionGate {
+            name,
+            kind: VersionKind::Api,
+            since,
+        }
+    }
+
+    /// Check that `version`, which must be of the kind of the gate, is
+    /// recent enough for what the gate is for
+    pub fn check(&self, version: &Version) -> Result<(), VersionGateError> {
+        if version >= &self.since {
+            return Ok(());
+        }
+        Err(VersionGateError {
+            gate: self.clone(),
+            version: version.clone(),
+        })
+    }
+}
+
+#[derive(Clone, Debug, Error, PartialEq, Eq)]
+#[error(
+    "{} requires {} {} or later, but {} uses {} {}",
+    .gate.name, .gate.kind, .gate.since, .gate.kind.subject(), .gate.kind, .version
+)]
+pub struct VersionGateError {
+    pub gate: VersionGate,
+    pub version: Version,
+}
+
+/// The `indexingDelay` field of the manifest
+pub const INDEXING_DELAY: VersionGate = VersionGate::spec("`indexingDelay`", SPEC_VERSION_0_0_5);
+
+/// Event handlers with `receipt: true`
+pub const EVENT_HANDLER_RECEIPTS: VersionGate =
+    VersionGate::api("`receipt: true` on event handlers", API_VERSION_0_0_7);
+
+/// Host functions that mappings can only import from some `apiVersion` on
+pub const HOST_FUNCTIONS: &[VersionGate] = &[
+    VersionGate::api("ethereum.tryCall", API_VERSION_0_0_7),
+    VersionGate::api("ethereum.encodeCall", API_VERSION_0_0_7),
+    VersionGate::api("ethereum.decodeCall", API_VERSION_0_0_7),
+    VersionGate::api("store.getChangesInBlock", API_VERSION_0_0_8),
+    VersionGate::api("dataSource.remove", API_VERSION_0_0_8),
+    VersionGate::api("dataSource.contextValue", API_VERSION_0_0_8),
+    VersionGate::api("ethereum.getBlockByHash", API_VERSION_0_0_8),
+    VersionGate::api("ethereum.getBlockByNumber", API_VERSION_0_0_8),
+];
+
+/// Check that the mapping `runtime` with `api_version` only imports the
+/// host functions in `HOST_FUNCTIONS` that its `api_version` allows
+pub fn check_host_functions(
+    runtime: &[u8],
+    api_version: &Version,
+) -> Result<Vec<VersionGateError>, anyhow::Error> {
+    let mut errors = vec![];
+    for gate in HOST_FUNCTIONS {
+        if let Err(e) = gate.check(api_version) {
+            if calls_host_fn(runtime, gate.name)? {
+                errors.push(e);
+            }
+        }
+    }
+    Ok(errors)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gate_errors() {
+        assert_eq!(Ok(()), INDEXING_DELAY.check(&Version::new(0, 0, 5)));
+        assert_eq!(Ok(()), INDEXING_DELAY.check(&Version::new(0, 1, 0)));
+        assert_eq!(
+            "`indexingDelay` requires specVersion 0.0.5 or later, \
+             but the manifest uses specVersion 0.0.4",
+            INDEXING_DELAY
+                .check(&Version::new(0, 0, 4))
+                .unwrap_err()
+                .to_string()
+        );
+        assert_eq!(
+            "`receipt: true` on event handlers requires apiVersion 0.0.7 or later, \
+             but the mapping uses apiVersion 0.0.6",
+            EVENT_HANDLER_RECEIPTS
+                .check(&Version::new(0, 0, 6))
+                .unwrap_err()
+                .to_string()
+        );
+    }
+}
@@ -82,6 +82,92 @@ pub struct EnvVarsGraphQl {
     /// Set by the flag `GRAPH_GRAPHQL_MAX_OPERATIONS_PER_CONNECTION`. No
     /// default is provided.
     pub max_operations_per_connection: Option<usize>,
+    /// Serve a read-only REST API for each deployment under
+    /// `/subgraphs/.../rest/<Entity>`.
+    ///
+    /// Set by the flag `GRAPH_ENABLE_REST_API`. Off by default.
+    pub enable_rest_api: bool,
+    /// Accept SQL queries against the entities of each deployment under
+    /// `/subgraphs/.../sql`.
+    ///
+    /// Set by the flag `GRAPH_ENABLE_SQL_API`. Off by default.
+    pub enable_sql_api: bool,
+    /// The largest cost that Postgres may estimate for a SQL query before
+    /// we refuse to run it. Set by the environment variable
+    /// `GRAPH_SQL_MAX_COST`. The default value is 1000000.
+    pub sql_max_cost: u64,
+    /// The maximum number of rows a SQL query may return. Set by the
+    /// environment variable `GRAPH_SQL_MAX_ROWS`. The default value is
+    /// 10000.
+    pub sql_max_rows: usize,
+    /// Stream the changes to the entities of each deployment over
+    /// WebSockets at `/subgraphs/id/<id>/changes`.
+    ///
+    /// Set by the flag `GRAPH_ENABLE_CHANGE_STREAM`. Off by default.
+    pub enable_change_stream: bool,
+    /// Add `_matches` filters, which match String attributes against a
+    /// POSIX regular expression, to the GraphQL API.
+    ///
+    /// Set by the flag `GRAPH_GRAPHQL_ENABLE_REGEX_FILTER`. Off by default.
+    pub enable_regex_filter: bool,
+    /// The largest cost that Postgres may estimate for a query with a
+    /// `_matches` filter before we refuse to run it. Set by the
+    /// environment variable `GRAPH_GRAPHQL_REGEX_FILTER_MAX_COST`. The
+    /// default value is 1000000.
+    pub regex_filter_max_cost: u64,
+    /// How long a query with a `_matches` filter may run before it is
+    /// canceled, since its estimated cost can be far off. Set by the
+    /// environment variable `GRAPH_GRAPHQL_REGEX_FILTER_TIMEOUT` (expressed
+    /// in seconds). The default value is 10.
+    pub regex_filter_timeout: Duration,
+    /// The operator token that requests have to present as a bearer token
+    /// to run queries with `?explain=true`. Set by the environment variable
+    /// `GRAPH_GRAPHQL_EXPLAIN_TOKEN`. When it is not set, queries can not
+    /// be explained.
+    pub explain_token: Option<String>,
+    /// How long HTTP caches may keep responses to queries that only ask
+    /// for blocks that can not change anymore, i.e., blocks given by hash
+    /// or by a number that is further behind the subgraph head than the
+    /// reorg threshold. Set by the environment variable
+    /// `GRAPH_GRAPHQL_PINNED_QUERY_MAX_AGE` (expressed in seconds). The
+    /// default value is 31536000, one year.
+    pub pinned_query_max_age: Duration,
+    /// How long HTTP caches may keep responses to queries at the subgraph
+    /// head. This should be about the block time of the chain. Set by the
+    /// environment variable `GRAPH_GRAPHQL_HEAD_QUERY_MAX_AGE` (expressed
+    /// in seconds). The default value is 1.
+    pub head_query_max_age: Duration,
+    /// The longest query string that GraphQL queries sent with `GET` may
+    /// have. Set by the environment variable
+    /// `GRAPH_GRAPHQL_MAX_GET_QUERY_LENGTH` (expressed in bytes). The
+    /// default value is 8192.
+    pub max_get_query_length: usize,
+    /// How much memory the queries that clients registered with automatic
+    /// persisted queries may use. Set by the environment variable
+    /// `GRAPH_GRAPHQL_PERSISTED_QUERY_CACHE_MB` (expressed in MB). The
+    /// default value is 16.
+    pub persisted_query_cache_max_mem: usize,
+    /// Responses that are smaller than this are sent uncompressed even if
+    /// the client accepts compressed responses. Set by the environment
+    /// variable `GRAPH_GRAPHQL_COMPRESSION_MIN_SIZE` (expressed in bytes).
+    /// The default value is 8192.
+    pub compression_min_size: usize,
+    /// The level for compressing responses, from 0 (fastest) to 11 (best).
+    /// Gzip uses at most level 9. Set by the environment variable
+    /// `GRAPH_GRAPHQL_COMPRESSION_LEVEL`. The default value is 5.
+    pub compression_level: u32,
+    /// The maximum number of queries in one batch sent to the `/graphql`
+    /// endpoint. Set by the environment variable
+    /// `GRAPH_GRAPHQL_MAX_BATCH_SIZE`. The default value is 10.
+    pub max_batch_size: usize,
+    /// Whether query responses report how far the deployment is behind
+    /// its chain in the `freshness` extension. Set by the environment
+    /// variable `GRAPH_GRAPHQL_FRESHNESS_EXTENSION`. Off by default.
+    pub freshness_extension: bool,
+    /// Whether queries that pass arguments a field does not have fail
+    /// instead of having those arguments ignored. Set by the environment
+    /// variable `GRAPH_GRAPHQL_REJECT_UNKNOWN_ARGUMENTS`. Off by default.
+    pub reject_unknown_arguments: bool,
 }
 
 // This does not print any values avoid accidentally leaking any sensitive env vars
@@ -125,6 +211,24 @@ impl From<InnerGraphQl> for EnvVarsGraphQl {
             warn_result_size: x.warn_result_size.0 .0,
             error_result_size: x.error_result_size.0 .0,
             max_operations_per_connection: x.max_operations_per_connection,
+            enable_rest_api: x.enable_rest_api.0,
+            enable_sql_api: x.enable_sql_api.0,
+            sql_max_cost: x.sql_max_cost.0,
+            sql_max_rows: x.sql_max_rows.0,
+            enable_change_stream: x.enable_change_stream.0,
+            enable_regex_filter: x.enable_regex_filter.0,
+            regex_filter_max_cost: x.regex_filter_max_cost.0,
+            regex_filter_timeout: Duration::from_secs(x.regex_filter_timeout_in_secs),
+            explain_token: x.explain_token,
+            pinned_query_max_age: Duration::from_secs(x.pinned_query_max_age_in_secs),
+            head_query_max_age: Duration::from_secs(x.head_query_max_age_in_secs),
+            max_get_query_length: x.max_get_query_length.0,
+            persisted_query_cache_max_mem: x.persisted_query_cache_max_mem_in_mb.0 * 1000 * 1000,
+            compression_min_size: x.compression_min_size.0,
+            compression_level: x.compression_level.min(11),
+            max_batch_size: x.max_batch_size,
+            freshness_extension: x.freshness_extension.0,
+            reject_unknown_arguments: x.reject_unknown_arguments.0,
         }
     }
 }
@@ -168,4 +272,40 @@ pub struct InnerGraphQl {
     error_result_size: WithDefaultUsize<NoUnderscores<usize>, { usize::MAX }>,
     #[envconfig(from = "GRAPH_GRAPHQL_MAX_OPERATIONS_PER_CONNECTION")]
     max_operations_per_connection: Option<usize>,
+    #[envconfig(from = "GRAPH_ENABLE_REST_API", default = "false")]
+    enable_rest_api: EnvVarBoolean,
+    #[envconfig(from = "GRAPH_ENABLE_SQL_API", default = "false")]
+    enable_sql_api: EnvVarBoolean,
+    #[envconfig(from = "GRAPH_SQL_MAX_COST", default = "1000000")]
+    sql_max_cost: NoUnderscores<u64>,
+    #[envconfig(from = "GRAPH_SQL_MAX_ROWS", default = "10000")]
+    sql_max_rows: NoUnderscores<usize>,
+    #[envconfig(from = "GRAPH_ENABLE_CHANGE_STREAM", default = "false")]
+    enable_change_stream: EnvVarBoolean,
+    #[envconfig(from = "GRAPH_GRAPHQL_ENABLE_REGEX_FILTER", default = "false")]
+    enable_regex_filter: EnvVarBoolean,
+    #[envconfig(from = "GRAPH_GRAPHQL_REGEX_FILTER_MAX_COST", default = "1000000")]
+    regex_filter_max_cost: NoUnderscores<u64>,
+    #[envconfig(from = "GRAPH_GRAPHQL_REGEX_FILTER_TIMEOUT", default = "10")]
+    regex_filter_timeout_in_secs: u64,
+    #[envconfig(from = "GRAPH_GRAPHQL_EXPLAIN_TOKEN")]
+    explain_token: Option<String>,
+    #[envconfig(from = "GRAPH_GRAPHQL_PINNED_QUERY_MAX_AGE", default = "31536000")]
+    pinned_query_max_age_in_secs: u64,
+    #[envconfig(from = "GRAPH_GRAPHQL_HEAD_QUERY_MAX_AGE", default = "1")]
+    head_query_max_age_in_secs: u64,
+    #[envconfig(from = "GRAPH_GRAPHQL_MAX_GET_QUERY_LENGTH", default = "8192")]
+    max_get_query_length: NoUnderscores<usize>,
+    #[envconfig(from = "GRAPH_GRAPHQL_PERSISTED_QUERY_CACHE_MB", default = "16")]
+    persisted_query_cache_max_mem_in_mb: NoUnderscores<usize>,
+    #[envconfig(from = "GRAPH_GRAPHQL_COMPRESSION_MIN_SIZE", default = "8192")]
+    compression_min_size: NoUnderscores<usize>,
+    #[envconfig(from = "GRAPH_GRAPHQL_COMPRESSION_LEVEL", default = "5")]
+    compression_level: u32,
+    #[envconfig(from = "GRAPH_GRAPHQL_MAX_BATCH_SIZE", default = "10")]
+    max_batch_size: usize,
+    #[envconfig(from = "GRAPH_GRAPHQL_FRESHNESS_EXTENSION", default = "false")]
+    freshness_extension: EnvVarBoolean,
+    #[envconfig(from = "GRAPH_GRAPHQL_REJECT_UNKNOWN_ARGUMENTS", default = "false")]
+    reject_unknown_arguments: EnvVarBoolean,
 }
@@ -10,7 +10,7 @@ pub struct EnvVarsMapping {
     /// kilobytes). The default value is 10 megabytes.
     pub entity_cache_size: usize,
     /// Set by the environment variable `GRAPH_MAX_API_VERSION`. The default
-    /// value is `0.0.6`.
+    /// value is `0.0.7`; `0.0.8` has to be enabled explicitly.
     pub max_api_version: Version,
     /// Set by the environment variable `GRAPH_MAPPING_HANDLER_TIMEOUT`
     /// (expressed in seconds). No default is provided.
@@ -20,6 +20,37 @@ pub struct EnvVarsMapping {
     /// Set by the environment variable `GRAPH_RUNTIME_MAX_STACK_SIZE`
     /// (expressed in bytes). The default value is 512KiB.
     pub max_stack_size: usize,
+    /// Maximum size of the linear memory of a WASM instance. Handlers that
+    /// need more memory fail.
+    ///
+    /// Set by the environment variable `GRAPH_RUNTIME_MAX_MEMORY` (expressed
+    /// in bytes). No default value is provided.
+    pub max_memory: Option<usize>,
+    /// Maximum amount of memory that the WASM instances of one deployment
+    /// hold at the same time. Deployments that use more are stopped and
+    /// marked as failed.
+    ///
+    /// Set by the environment variable `GRAPH_DEPLOYMENT_MAX_MEMORY`
+    /// (expressed in bytes). No default value is provided.
+    pub deployment_max_memory: Option<usize>,
+    /// Maximum time that the handlers of one deployment may run per minute.
+    /// Deployments that use more are stopped and marked as failed.
+    ///
+    /// Set by the environment variable `GRAPH_DEPLOYMENT_CPU_QUOTA`
+    /// (expressed in seconds per minute). No default value is provided.
+    pub deployment_cpu_quota: Option<Duration>,
+    /// Number of threads that run mapping handlers for all deployments.
+    ///
+    /// Set by the environment variable `GRAPH_MAPPING_THREADS`. The default
+    /// value is four times the number of CPUs, since handlers spend much of
+    /// their time waiting for the store.
+    pub threads: usize,
+    /// Number of mapping threads that the handlers of one deployment can use
+    /// at the same time.
+    ///
+    /// Set by the environment variable `GRAPH_MAPPING_THREADS_PER_DEPLOYMENT`.
+    /// The default value is 1.
+    pub threads_per_deployment: usize,
 
     /// Set by the environment variable `GRAPH_MAX_IPFS_CACHE_FILE_SIZE`
     /// (expressed in bytes). The default value is 1MiB.
@@ -65,6 +96,14 @@ impl From<InnerMappingHandlers> for EnvVarsMapping {
             max_api_version: x.max_api_version,
             timeout: x.mapping_handler_timeout_in_secs.map(Duration::from_secs),
             max_stack_size: x.runtime_max_stack_size.0 .0,
+            max_memory: x.runtime_max_memory,
+            deployment_max_memory: x.deployment_max_memory,
+            deployment_cpu_quota: x.deployment_cpu_quota_in_secs.map(Duration::from_secs),
+            threads: x
+                .mapping_threads
+                .unwrap_or_else(|| 4 * num_cpus::get())
+                .max(1),
+            threads_per_deployment: x.mapping_threads_per_deployment.max(1),
 
             max_ipfs_cache_file_size: x.max_ipfs_cache_file_size.0,
             max_ipfs_cache_size: x.max_ipfs_cache_size,
@@ -86,6 +125,16 @@ pub struct InnerMappingHandlers {
     mapping_handler_timeout_in_secs: Option<u64>,
     #[envconfig(from = "GRAPH_RUNTIME_MAX_STACK_SIZE", default = "")]
     runtime_max_stack_size: WithDefaultUsize<NoUnderscores<usize>, { 512 * 1024 }>,
+    #[envconfig(from = "GRAPH_RUNTIME_MAX_MEMORY")]
+    runtime_max_memory: Option<usize>,
+    #[envconfig(from = "GRAPH_DEPLOYMENT_MAX_MEMORY")]
+    deployment_max_memory: Option<usize>,
+    #[envconfig(from = "GRAPH_DEPLOYMENT_CPU_QUOTA")]
+    deployment_cpu_quota_in_secs: Option<u64>,
+    #[envconfig(from = "GRAPH_MAPPING_THREADS")]
+    mapping_threads: Option<usize>,
+    #[envconfig(from = "GRAPH_MAPPING_THREADS_PER_DEPLOYMENT", default = "1")]
+    mapping_threads_per_deployment: usize,
 
     // IPFS.
     #[envconfig(from = "GRAPH_MAX_IPFS_CACHE_FILE_SIZE", default = "")]
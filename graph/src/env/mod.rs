@@ -6,7 +6,7 @@ use envconfig::Envconfig;
 use lazy_static::lazy_static;
 use semver::Version;
 use std::{
-    collections::HashSet,
+    collections::{HashMap, HashSet},
     env::VarError,
     fmt,
     str::FromStr,
@@ -18,7 +18,8 @@ use self::graphql::*;
 use self::mappings::*;
 use self::store::*;
 use crate::{
-    components::subgraph::SubgraphVersionSwitchingMode, runtime::gas::CONST_MAX_GAS_PER_HANDLER,
+    components::subgraph::SubgraphVersionSwitchingMode, prelude::BlockNumber,
+    runtime::gas::CONST_MAX_GAS_PER_HANDLER,
 };
 
 pub static UNSAFE_CONFIG: AtomicBool = AtomicBool::new(false);
@@ -114,6 +115,10 @@ pub struct EnvVars {
     pub max_spec_version: Version,
     /// Set by the flag `GRAPH_DISABLE_GRAFTS`.
     pub disable_grafts: bool,
+    /// Whether to start data sources without a `startBlock` at the block
+    /// in which their contract was created. Set by the flag
+    /// `GRAPH_DISABLE_START_BLOCK_DETECTION`.
+    pub detect_start_blocks: bool,
     /// Set by the environment variable `GRAPH_LOAD_WINDOW_SIZE` (expressed in
     /// seconds). The default value is 300 seconds.
     pub load_window_size: Duration,
@@ -168,6 +173,12 @@ pub struct EnvVars {
     /// Set by the environment variable `GRAPH_SUBGRAPH_MAX_DATA_SOURCES`. No
     /// default value is provided.
     pub subgraph_max_data_sources: Option<usize>,
+    /// The largest reorg we expect; blocks that are further behind the
+    /// chain head than this are considered final.
+    ///
+    /// Set by the environment variable `ETHEREUM_REORG_THRESHOLD`. The default
+    /// value is 250 blocks.
+    pub reorg_threshold: BlockNumber,
     /// Keep deterministic errors non-fatal even if the subgraph is pending.
     /// Used for testing Graph Node itself.
     ///
@@ -203,6 +214,123 @@ pub struct EnvVars {
     /// Set by the environment variable `EXTERNAL_WS_BASE_URL`. No default
     /// value is provided.
     pub external_ws_base_url: Option<String>,
+    /// How often to check the configuration file for changes to the
+    /// providers and deployment rules. Set by the environment variable
+    /// `GRAPH_CONFIG_RELOAD_INTERVAL` (expressed in seconds). The default
+    /// value of 0 turns reloading off.
+    pub config_reload_interval: Option<Duration>,
+    /// How often each node records a heartbeat in the primary. Set by the
+    /// environment variable `GRAPH_NODE_HEARTBEAT_INTERVAL` (expressed in
+    /// seconds). The default value is 30s.
+    pub node_heartbeat_interval: Duration,
+    /// Index nodes that have not recorded a heartbeat for this long have
+    /// their deployments moved to other index nodes. Set by the environment
+    /// variable `GRAPH_NODE_HEARTBEAT_TIMEOUT` (expressed in seconds). The
+    /// default value of 0 turns automatic reassignment off.
+    pub node_heartbeat_timeout: Option<Duration>,
+    /// How often index nodes try to even out the load of deployments across
+    /// index nodes. Set by the environment variable
+    /// `GRAPH_REBALANCE_INTERVAL` (expressed in seconds). The default value
+    /// of 0 turns automatic rebalancing off.
+    pub rebalance_interval: Option<Duration>,
+    /// Nodes whose load is more than this fraction above the average load
+    /// have deployments moved away from them. Set by the environment
+    /// variable `GRAPH_REBALANCE_THRESHOLD`. The default value is 0.25.
+    pub rebalance_threshold: f64,
+    /// The maximum number of deployments moved in one rebalancing run. Set
+    /// by the environment variable `GRAPH_REBALANCE_MAX_MOVES`. The default
+    /// value is 5.
+    pub rebalance_max_moves: usize,
+    /// How often index nodes work on the jobs they run and look for new
+    /// jobs in the queue. Set by the environment variable
+    /// `GRAPH_JOB_QUEUE_INTERVAL` (expressed in seconds). The default value
+    /// is 10s; 0 keeps the node from running jobs.
+    pub job_queue_interval: Option<Duration>,
+    /// How often a node that ingests blocks for a chain checks that it
+    /// still holds the lease for that in the primary, and how often the
+    /// other nodes try to take the lease over. Set by the environment
+    /// variable `GRAPH_INGESTOR_LEASE_INTERVAL` (expressed in seconds). The
+    /// default value is 10s.
+    pub ingestor_lease_interval: Duration,
+    /// How long the node waits for subgraphs to finish the block they are
+    /// processing and for their writes to be flushed when it is asked to
+    /// shut down before it exits anyway. Set by the environment variable
+    /// `GRAPH_SHUTDOWN_TIMEOUT` (expressed in seconds). The default value is
+    /// 60s.
+    pub shutdown_timeout: Duration,
+    /// The Kafka brokers to which entity changes are published, as a
+    /// comma-separated list of `host:port`. Set by the environment variable
+    /// `GRAPH_KAFKA_BROKERS`. No default value is provided, and changes are
+    /// not published if it is not set.
+    pub kafka_brokers: Option<String>,
+    /// The topic to which the entity changes of each deployment are
+    /// published; `{deployment}` is replaced with the deployment's IPFS
+    /// hash. Set by the environment variable `GRAPH_KAFKA_TOPIC`. The
+    /// default value is `subgraph-{deployment}`.
+    pub kafka_topic: String,
+    /// The URLs to which notifications about deployments being created,
+    /// synced, failed, or reassigned are posted. Set by the environment
+    /// variable `GRAPH_WEBHOOK_URLS` as a comma-separated list. No
+    /// notifications are sent if it is not set.
+    pub webhook_urls: Vec<String>,
+    /// The key with which the body of each webhook notification is signed.
+    /// Set by the environment variable `GRAPH_WEBHOOK_SECRET`. Notifications
+    /// are not signed if it is not set.
+    pub webhook_secret: Option<String>,
+    /// How often we try to deliver a webhook notification before giving
+    /// up. Set by the environment variable `GRAPH_WEBHOOK_MAX_ATTEMPTS`. The
+    /// default value is 10.
+    pub webhook_max_attempts: u32,
+    /// Whether the HTTP servers keep HTTP/1 connections open between
+    /// requests. Set by the environment variable `GRAPH_HTTP_KEEP_ALIVE`.
+    /// On by default.
+    pub http_keep_alive: bool,
+    /// How often idle connections to the HTTP servers are probed with TCP
+    /// keepalives and HTTP/2 pings. Set by the environment variable
+    /// `GRAPH_HTTP_KEEP_ALIVE_INTERVAL` (expressed in seconds). No probes
+    /// are sent if it is not set or set to 0.
+    pub http_keep_alive_interval: Option<Duration>,
+    /// How long the HTTP servers wait for the answer to an HTTP/2 ping
+    /// before they close the connection. Set by the environment variable
+    /// `GRAPH_HTTP_KEEP_ALIVE_TIMEOUT` (expressed in seconds). The default
+    /// value is 20s.
+    pub http_keep_alive_timeout: Duration,
+    /// The maximum number of connections each HTTP server accepts at the
+    /// same time; further clients wait until a connection is closed. Set
+    /// by the environment variable `GRAPH_HTTP_MAX_CONNECTIONS`. There is
+    /// no limit if it is not set or set to 0.
+    pub http_max_connections: Option<usize>,
+    /// The maximum number of concurrent HTTP/2 streams on one connection.
+    /// Set by the environment variable `GRAPH_HTTP2_MAX_CONCURRENT_STREAMS`.
+    /// Hyper's default is used if it is not set or set to 0.
+    pub http2_max_concurrent_streams: Option<u32>,
+    /// The PEM file with the certificate chain with which the GraphQL and
+    /// WebSocket servers terminate TLS. Set by the environment variable
+    /// `GRAPH_TLS_CERT`. The servers speak plain text if it is not set.
+    pub tls_cert: Option<String>,
+    /// The PEM file with the private key for `tls_cert`. Set by the
+    /// environment variable `GRAPH_TLS_KEY`.
+    pub tls_key: Option<String>,
+    /// How long a client has to finish the TLS handshake before its
+    /// connection is closed. Set by the environment variable
+    /// `GRAPH_TLS_HANDSHAKE_TIMEOUT` (expressed in seconds). The default
+    /// value is 10s.
+    pub tls_handshake_timeout: Duration,
+    /// The tokens that callers of the admin JSON-RPC server must present,
+    /// as a comma-separated list of `name:role:token`. Set by the
+    /// environment variable `GRAPH_ADMIN_TOKENS`. Callers do not need a
+    /// token if it is not set.
+    pub admin_tokens: Option<String>,
+    /// The directory underneath which `subgraph_deploy` and
+    /// `subgraph_validate` read subgraphs from `file://` paths. Set by the
+    /// environment variable `GRAPH_LOCAL_SUBGRAPH_ROOT`. Deploying from
+    /// local files through the admin server is not possible if it is not
+    /// set.
+    pub local_subgraph_root: Option<String>,
+    /// The directory underneath which `subgraph_export` writes exports.
+    /// Set by the environment variable `GRAPH_EXPORT_DIR`. Exporting
+    /// through the admin server is not possible if it is not set.
+    pub export_dir: Option<String>,
 }
 
 impl EnvVars {
@@ -226,6 +354,7 @@ impl EnvVars {
                 || cfg!(debug_assertions),
             max_spec_version: inner.max_spec_version,
             disable_grafts: inner.disable_grafts.0,
+            detect_start_blocks: !inner.disable_start_block_detection.0,
             load_window_size: Duration::from_secs(inner.load_window_size_in_secs),
             load_bin_size: Duration::from_secs(inner.load_bin_size_in_secs),
             elastic_search_flush_interval: Duration::from_secs(
@@ -249,6 +378,7 @@ impl EnvVars {
             kill_if_unresponsive: inner.kill_if_unresponsive.0,
             poi_access_token: inner.poi_access_token,
             subgraph_max_data_sources: inner.subgraph_max_data_sources,
+            reorg_threshold: inner.reorg_threshold,
             disable_fail_fast: inner.disable_fail_fast.0,
             subgraph_error_retry_ceil: Duration::from_secs(inner.subgraph_error_retry_ceil_in_secs),
             enable_select_by_specific_attributes: inner.enable_select_by_specific_attributes.0,
@@ -258,6 +388,61 @@ impl EnvVars {
             explorer_query_threshold: Duration::from_millis(inner.explorer_query_threshold_in_msec),
             external_http_base_url: inner.external_http_base_url,
             external_ws_base_url: inner.external_ws_base_url,
+            config_reload_interval: match inner.config_reload_interval_in_secs {
+                0 => None,
+                secs => Some(Duration::from_secs(secs)),
+            },
+            node_heartbeat_interval: Duration::from_secs(inner.node_heartbeat_interval_in_secs),
+            node_heartbeat_timeout: match inner.node_heartbeat_timeout_in_secs {
+                0 => None,
+                secs => Some(Duration::from_secs(secs)),
+            },
+            rebalance_interval: match inner.rebalance_interval_in_secs {
+                0 => None,
+                secs => Some(Duration::from_secs(secs)),
+            },
+            rebalance_threshold: inner.rebalance_threshold,
+            rebalance_max_moves: inner.rebalance_max_moves,
+            job_queue_interval: match inner.job_queue_interval_in_secs {
+                0 => None,
+                secs => Some(Duration::from_secs(secs)),
+            },
+            ingestor_lease_interval: Duration::from_secs(inner.ingestor_lease_interval_in_secs),
+            shutdown_timeout: Duration::from_secs(inner.shutdown_timeout_in_secs),
+            kafka_brokers: inner.kafka_brokers,
+            kafka_topic: inner.kafka_topic,
+            webhook_urls: inner
+                .webhook_urls
+                .map(|urls| {
+                    urls.split(',')
+                        .map(str::trim)
+                        .filter(|url| !url.is_empty())
+                        .map(str::to_string)
+                        .collect()
+                })
+                .unwrap_or_default(),
+            webhook_secret: inner.webhook_secret,
+            webhook_max_attempts: inner.webhook_max_attempts,
+            http_keep_alive: inner.http_keep_alive.0,
+            http_keep_alive_interval: match inner.http_keep_alive_interval_in_secs {
+                0 => None,
+                secs => Some(Duration::from_secs(secs)),
+            },
+            http_keep_alive_timeout: Duration::from_secs(inner.http_keep_alive_timeout_in_secs),
+            http_max_connections: match inner.http_max_connections {
+                0 => None,
+                max => Some(max),
+            },
+            http2_max_concurrent_streams: match inner.http2_max_concurrent_streams {
+                0 => None,
+                max => Some(max),
+            },
+            tls_cert: inner.tls_cert,
+            tls_key: inner.tls_key,
+            tls_handshake_timeout: Duration::from_secs(inner.tls_handshake_timeout_in_secs),
+            admin_tokens: inner.admin_tokens,
+            local_subgraph_root: inner.local_subgraph_root,
+            export_dir: inner.export_dir,
         })
     }
 
@@ -267,6 +452,12 @@ impl EnvVars {
         self.load_threshold.is_zero()
     }
 
+    /// Whether entity changes need to be recorded in the outbox because
+    /// some system wants them delivered
+    pub fn entity_change_outbox_enabled(&self) -> bool {
+        self.kafka_brokers.is_some()
+    }
+
     fn log_query_timing_contains(&self, kind: &str) -> bool {
         self.log_query_timing.iter().any(|s| s == kind)
     }
@@ -282,6 +473,18 @@ impl EnvVars {
     pub fn log_gql_cache_timing(&self) -> bool {
         self.log_query_timing_contains("cache") && self.log_gql_timing()
     }
+
+    /// The number of the latest block that is final when the chain head
+    /// is at `chain_head`. It can be negative if no block is final yet
+    pub fn final_block(&self, chain_head: BlockNumber) -> BlockNumber {
+        chain_head - self.reorg_threshold
+    }
+
+    /// Whether block `number` is final when the chain head is at
+    /// `chain_head`, i.e., can not be reverted anymore
+    pub fn is_final(&self, number: BlockNumber, chain_head: BlockNumber) -> bool {
+        number <= self.final_block(chain_head)
+    }
 }
 
 impl Default for EnvVars {
@@ -314,6 +517,8 @@ struct Inner {
     max_spec_version: Version,
     #[envconfig(from = "GRAPH_DISABLE_GRAFTS", default = "false")]
     disable_grafts: EnvVarBoolean,
+    #[envconfig(from = "GRAPH_DISABLE_START_BLOCK_DETECTION", default = "false")]
+    disable_start_block_detection: EnvVarBoolean,
     #[envconfig(from = "GRAPH_LOAD_WINDOW_SIZE", default = "300")]
     load_window_size_in_secs: u64,
     #[envconfig(from = "GRAPH_LOAD_BIN_SIZE", default = "1")]
@@ -348,6 +553,8 @@ struct Inner {
     poi_access_token: Option<String>,
     #[envconfig(from = "GRAPH_SUBGRAPH_MAX_DATA_SOURCES")]
     subgraph_max_data_sources: Option<usize>,
+    #[envconfig(from = "ETHEREUM_REORG_THRESHOLD", default = "250")]
+    reorg_threshold: BlockNumber,
     #[envconfig(from = "GRAPH_DISABLE_FAIL_FAST", default = "false")]
     disable_fail_fast: EnvVarBoolean,
     #[envconfig(from = "GRAPH_SUBGRAPH_ERROR_RETRY_CEIL_SECS", default = "1800")]
@@ -366,6 +573,56 @@ struct Inner {
     external_http_base_url: Option<String>,
     #[envconfig(from = "EXTERNAL_WS_BASE_URL")]
     external_ws_base_url: Option<String>,
+    #[envconfig(from = "GRAPH_CONFIG_RELOAD_INTERVAL", default = "0")]
+    config_reload_interval_in_secs: u64,
+    #[envconfig(from = "GRAPH_NODE_HEARTBEAT_INTERVAL", default = "30")]
+    node_heartbeat_interval_in_secs: u64,
+    #[envconfig(from = "GRAPH_NODE_HEARTBEAT_TIMEOUT", default = "0")]
+    node_heartbeat_timeout_in_secs: u64,
+    #[envconfig(from = "GRAPH_REBALANCE_INTERVAL", default = "0")]
+    rebalance_interval_in_secs: u64,
+    #[envconfig(from = "GRAPH_REBALANCE_THRESHOLD", default = "0.25")]
+    rebalance_threshold: f64,
+    #[envconfig(from = "GRAPH_REBALANCE_MAX_MOVES", default = "5")]
+    rebalance_max_moves: usize,
+    #[envconfig(from = "GRAPH_JOB_QUEUE_INTERVAL", default = "10")]
+    job_queue_interval_in_secs: u64,
+    #[envconfig(from = "GRAPH_INGESTOR_LEASE_INTERVAL", default = "10")]
+    ingestor_lease_interval_in_secs: u64,
+    #[envconfig(from = "GRAPH_SHUTDOWN_TIMEOUT", default = "60")]
+    shutdown_timeout_in_secs: u64,
+    #[envconfig(from = "GRAPH_KAFKA_BROKERS")]
+    kafka_brokers: Option<String>,
+    #[envconfig(from = "GRAPH_KAFKA_TOPIC", default = "subgraph-{deployment}")]
+    kafka_topic: String,
+    #[envconfig(from = "GRAPH_WEBHOOK_URLS")]
+    webhook_urls: Option<String>,
+    #[envconfig(from = "GRAPH_WEBHOOK_SECRET")]
+    webhook_secret: Option<String>,
+    #[envconfig(from = "GRAPH_WEBHOOK_MAX_ATTEMPTS", default = "10")]
+    webhook_max_attempts: u32,
+    #[envconfig(from = "GRAPH_HTTP_KEEP_ALIVE", default = "true")]
+    http_keep_alive: EnvVarBoolean,
+    #[envconfig(from = "GRAPH_HTTP_KEEP_ALIVE_INTERVAL", default = "0")]
+    http_keep_alive_interval_in_secs: u64,
+    #[envconfig(from = "GRAPH_HTTP_KEEP_ALIVE_TIMEOUT", default = "20")]
+    http_keep_alive_timeout_in_secs: u64,
+    #[envconfig(from = "GRAPH_HTTP_MAX_CONNECTIONS", default = "0")]
+    http_max_connections: usize,
+    #[envconfig(from = "GRAPH_HTTP2_MAX_CONCURRENT_STREAMS", default = "0")]
+    http2_max_concurrent_streams: u32,
+    #[envconfig(from = "GRAPH_TLS_CERT")]
+    tls_cert: Option<String>,
+    #[envconfig(from = "GRAPH_TLS_KEY")]
+    tls_key: Option<String>,
+    #[envconfig(from = "GRAPH_TLS_HANDSHAKE_TIMEOUT", default = "10")]
+    tls_handshake_timeout_in_secs: u64,
+    #[envconfig(from = "GRAPH_ADMIN_TOKENS")]
+    admin_tokens: Option<String>,
+    #[envconfig(from = "GRAPH_LOCAL_SUBGRAPH_ROOT")]
+    local_subgraph_root: Option<String>,
+    #[envconfig(from = "GRAPH_EXPORT_DIR")]
+    export_dir: Option<String>,
 }
 
 #[derive(Clone, Debug)]
@@ -438,3 +695,19 @@ where
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::EnvVars;
+
+    #[test]
+    fn finality() {
+        let mut env = EnvVars::default();
+        env.reorg_threshold = 10;
+
+        assert_eq!(90, env.final_block(100));
+        assert!(env.is_final(90, 100));
+        assert!(!env.is_final(91, 100));
+        assert!(!env.is_final(0, 5));
+    }
+}
@@ -78,6 +78,9 @@ pub struct EnvVarsStore {
     /// Set by the environment variable `GRAPH_REMOVE_UNUSED_INTERVAL`
     /// (expressed in minutes). The default value is 360 minutes.
     pub remove_unused_interval: chrono::Duration,
+    /// Set by the environment variable `GRAPH_UNUSED_QUERY_INTERVAL`
+    /// (expressed in minutes). The default value is 1440 minutes.
+    pub unused_query_interval: chrono::Duration,
 
     // These should really be set through the configuration file, especially for
     // `GRAPH_STORE_CONNECTION_MIN_IDLE` and
@@ -98,6 +101,78 @@ pub struct EnvVarsStore {
     /// Setting this to `0` disables pipelined writes, and writes will be
     /// done synchronously.
     pub write_queue_size: usize,
+    /// How many entity changes the write queue can hold before calls to
+    /// transact block operations will block, regardless of how many
+    /// blocks they are spread over. A block with more changes than that
+    /// is still queued when the queue is empty. Set by the environment
+    /// variable `GRAPH_STORE_WRITE_QUEUE_MAX_ENTITIES`. `0` means there is
+    /// no limit. The default is 500000
+    pub write_queue_max_entities: usize,
+    /// How often to run a write transaction again that the database
+    /// aborted because of a serialization failure or a deadlock before
+    /// giving up.
+    ///
+    /// Set by the environment variable `GRAPH_STORE_CONFLICT_RETRIES`. The
+    /// default value is 5.
+    pub conflict_retries: usize,
+    /// The directory into which index nodes write the change journals of
+    /// the deployments they index that have journaling turned on. Set by
+    /// the environment variable `GRAPH_CHANGE_JOURNAL_DIR`. No journals are
+    /// written if it is not set.
+    pub change_journal_dir: Option<String>,
+    /// How often index nodes write change journals. Set by the environment
+    /// variable `GRAPH_CHANGE_JOURNAL_INTERVAL` (expressed in seconds). The
+    /// default value is 60s.
+    pub change_journal_interval: Duration,
+    /// The maximum number of blocks in one batch of a change journal. Set
+    /// by the environment variable `GRAPH_CHANGE_JOURNAL_MAX_BLOCKS`. The
+    /// default value is 10000.
+    pub change_journal_max_blocks: i32,
+    /// How often to run routine database maintenance: analyzing entity
+    /// tables that changed a lot, reindexing bloated indexes and vacuuming
+    /// the metadata tables. Set by the environment variable
+    /// `GRAPH_MAINTENANCE_INTERVAL` (expressed in seconds). Maintenance is
+    /// off if it is not set.
+    pub maintenance_interval: Option<Duration>,
+    /// How many maintenance operations to run at the same time in each
+    /// shard. Set by the environment variable
+    /// `GRAPH_MAINTENANCE_CONCURRENCY`. The default value is 1.
+    pub maintenance_concurrency: usize,
+    /// Entity tables are analyzed when the number of rows that changed
+    /// since they were last analyzed is more than this fraction of their
+    /// rows. Set by the environment variable
+    /// `GRAPH_MAINTENANCE_ANALYZE_RATIO`. The default value is 0.1.
+    pub maintenance_analyze_ratio: f64,
+    /// Indexes of entity tables are rebuilt when they are more than this
+    /// many times as large as their estimated size without bloat. Set by
+    /// the environment variable `GRAPH_MAINTENANCE_REINDEX_BLOAT`. The
+    /// default value is 2.
+    pub maintenance_reindex_bloat: f64,
+    /// The weights with which deployments share the query permits of a
+    /// connection pool, as a comma-separated list of `<deployment>=<weight>`
+    /// entries. Deployments that are not listed have weight 1. When
+    /// queries have to wait for a permit, the next permit goes to the
+    /// deployment that holds the fewest permits relative to its weight.
+    ///
+    /// Set by the environment variable `GRAPH_QUERY_DEPLOYMENT_WEIGHTS`.
+    /// Empty by default. E.g. `GRAPH_QUERY_DEPLOYMENT_WEIGHTS=QmXyz=4,QmAbc=2`
+    pub query_deployment_weights: HashMap<String, u32>,
+    /// How many queries against one deployment may wait for a query permit
+    /// before further queries against it are rejected.
+    ///
+    /// Set by the environment variable
+    /// `GRAPH_QUERY_MAX_WAITING_PER_DEPLOYMENT`. Not set by default, which
+    /// means that there is no limit.
+    pub query_max_waiting_per_deployment: Option<usize>,
+    /// Deployments record a checkpoint of all their current entities when
+    /// they write a block that reaches or passes a multiple of this
+    /// number, so that they can be
+    /// rewound to blocks whose history has been pruned. Older checkpoints
+    /// are thinned out so that the distance between them doubles with
+    /// their age. Set by the environment variable
+    /// `GRAPH_ENTITY_CHECKPOINT_INTERVAL`. No checkpoints are recorded if
+    /// it is not set.
+    pub entity_checkpoint_interval: Option<BlockNumber>,
 }
 
 // This does not print any values avoid accidentally leaking any sensitive env vars
@@ -135,10 +210,46 @@ impl From<InnerStore> for EnvVarsStore {
             remove_unused_interval: chrono::Duration::minutes(
                 x.remove_unused_interval_in_minutes as i64,
             ),
+            unused_query_interval: chrono::Duration::minutes(
+                x.unused_query_interval_in_minutes as i64,
+            ),
             connection_timeout: Duration::from_millis(x.connection_timeout_in_millis),
             connection_min_idle: x.connection_min_idle,
             connection_idle_timeout: Duration::from_secs(x.connection_idle_timeout_in_secs),
             write_queue_size: x.write_queue_size,
+            write_queue_max_entities: x.write_queue_max_entities,
+            conflict_retries: x.conflict_retries,
+            change_journal_dir: x.change_journal_dir,
+            change_journal_interval: Duration::from_secs(x.change_journal_interval_in_secs),
+            change_journal_max_blocks: x.change_journal_max_blocks,
+            maintenance_interval: x.maintenance_interval_in_secs.map(Duration::from_secs),
+            maintenance_concurrency: x.maintenance_concurrency,
+            maintenance_analyze_ratio: x.maintenance_analyze_ratio,
+            maintenance_reindex_bloat: x.maintenance_reindex_bloat,
+            query_deployment_weights: x
+                .query_deployment_weights
+                .split(',')
+                .map(str::trim)
+                .filter(|entry| !entry.is_empty())
+                .map(|entry| {
+                    entry
+                        .split_once('=')
+                        .and_then(|(deployment, weight)| {
+                            Some((deployment.trim().to_string(), weight.trim().parse().ok()?))
+                        })
+                        .unwrap_or_else(|| {
+                            panic!(
+                                "invalid entry `{}` in GRAPH_QUERY_DEPLOYMENT_WEIGHTS; \
+                                 entries must have the form `<deployment>=<weight>`",
+                                entry
+                            )
+                        })
+                })
+                .collect(),
+            query_max_waiting_per_deployment: x.query_max_waiting_per_deployment,
+            entity_checkpoint_interval: x
+                .entity_checkpoint_interval
+                .filter(|interval| *interval > 0),
         }
     }
 }
@@ -171,6 +282,8 @@ pub struct InnerStore {
     connection_try_always: EnvVarBoolean,
     #[envconfig(from = "GRAPH_REMOVE_UNUSED_INTERVAL", default = "360")]
     remove_unused_interval_in_minutes: u64,
+    #[envconfig(from = "GRAPH_UNUSED_QUERY_INTERVAL", default = "1440")]
+    unused_query_interval_in_minutes: u64,
 
     // These should really be set through the configuration file, especially for
     // `GRAPH_STORE_CONNECTION_MIN_IDLE` and
@@ -184,4 +297,28 @@ pub struct InnerStore {
     connection_idle_timeout_in_secs: u64,
     #[envconfig(from = "GRAPH_STORE_WRITE_QUEUE", default = "5")]
     write_queue_size: usize,
+    #[envconfig(from = "GRAPH_STORE_WRITE_QUEUE_MAX_ENTITIES", default = "500000")]
+    write_queue_max_entities: usize,
+    #[envconfig(from = "GRAPH_STORE_CONFLICT_RETRIES", default = "5")]
+    conflict_retries: usize,
+    #[envconfig(from = "GRAPH_CHANGE_JOURNAL_DIR")]
+    change_journal_dir: Option<String>,
+    #[envconfig(from = "GRAPH_CHANGE_JOURNAL_INTERVAL", default = "60")]
+    change_journal_interval_in_secs: u64,
+    #[envconfig(from = "GRAPH_CHANGE_JOURNAL_MAX_BLOCKS", default = "10000")]
+    change_journal_max_blocks: i32,
+    #[envconfig(from = "GRAPH_MAINTENANCE_INTERVAL")]
+    maintenance_interval_in_secs: Option<u64>,
+    #[envconfig(from = "GRAPH_MAINTENANCE_CONCURRENCY", default = "1")]
+    maintenance_concurrency: usize,
+    #[envconfig(from = "GRAPH_MAINTENANCE_ANALYZE_RATIO", default = "0.1")]
+    maintenance_analyze_ratio: f64,
+    #[envconfig(from = "GRAPH_MAINTENANCE_REINDEX_BLOAT", default = "2")]
+    maintenance_reindex_bloat: f64,
+    #[envconfig(from = "GRAPH_QUERY_DEPLOYMENT_WEIGHTS", default = "")]
+    query_deployment_weights: String,
+    #[envconfig(from = "GRAPH_QUERY_MAX_WAITING_PER_DEPLOYMENT")]
+    query_max_waiting_per_deployment: Option<usize>,
+    #[envconfig(from = "GRAPH_ENTITY_CHECKPOINT_INTERVAL")]
+    entity_checkpoint_interval: Option<BlockNumber>,
 }
@@ -118,7 +118,7 @@ pub mod prelude {
         EntityCache, EntityChange, EntityChangeOperation, EntityCollection, EntityFilter,
         EntityKey, EntityLink, EntityModification, EntityOperation, EntityOrder, EntityQuery,
         EntityRange, EntityWindow, EthereumCallCache, ParentLink, PartialBlockPtr, PoolWaitStats,
-        QueryStore, QueryStoreManager, StoreError, StoreEvent, StoreEventStream,
+        QueryPermit, QueryStore, QueryStoreManager, StoreError, StoreEvent, StoreEventStream,
         StoreEventStreamBox, SubgraphStore, UnfailOutcome, WindowAttribute, BLOCK_NUMBER_MAX,
     };
     pub use crate::components::subgraph::{
@@ -24,6 +24,18 @@ pub trait AscHeap {
         &mut self,
         type_id_index: IndexForAscTypeId,
     ) -> Result<u32, DeterministicHostError>;
+
+    /// Allocate an object of the class with `type_id_index` through the
+    /// AssemblyScript runtime, write `content` to it and return the address
+    /// of the content. The runtime writes the header and keeps the object
+    /// alive until the host function that allocated it returns.
+    /// Only used for version >= 0.0.8.
+    fn managed_new(
+        &mut self,
+        type_id_index: IndexForAscTypeId,
+        content: &[u8],
+        gas: &GasCounter,
+    ) -> Result<u32, DeterministicHostError>;
 }
 
 /// Instantiate `rust_obj` as an Asc object of class `C`.
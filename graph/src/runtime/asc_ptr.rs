@@ -2,6 +2,7 @@ use super::gas::GasCounter;
 use super::{padding_to_16, DeterministicHostError};
 
 use super::{AscHeap, AscIndexId, AscType, IndexForAscTypeId};
+use crate::data::subgraph::API_VERSION_0_0_8;
 use semver::Version;
 use std::fmt;
 use std::marker::PhantomData;
@@ -80,6 +81,13 @@ impl<C: AscType> AscPtr<C> {
                 let heap_ptr = heap.raw_new(&asc_obj.to_asc_bytes()?, gas)?;
                 Ok(AscPtr::new(heap_ptr))
             }
+            version if version >= API_VERSION_0_0_8 => {
+                let bytes = asc_obj.to_asc_bytes()?;
+                let content_len = asc_obj.content_len(&bytes);
+                let heap_ptr =
+                    heap.managed_new(C::INDEX_ASC_TYPE_ID, &bytes[..content_len], gas)?;
+                Ok(AscPtr::new(heap_ptr))
+            }
             _ => {
                 let mut bytes = asc_obj.to_asc_bytes()?;
 
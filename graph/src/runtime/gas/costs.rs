@@ -53,6 +53,10 @@ pub const BIG_MATH_GAS_OP: GasOp = GasOp {
 // Allow up to 100,000 data sources to be created
 pub const CREATE_DATA_SOURCE: Gas = Gas(CONST_MAX_GAS_PER_HANDLER / 100_000);
 
+// Removing a data source is as expensive as creating one since both change
+// the filters of the block stream
+pub const REMOVE_DATA_SOURCE: Gas = CREATE_DATA_SOURCE;
+
 pub const LOG_OP: GasOp = GasOp {
     // Allow up to 100,000 logs
     base_cost: CONST_MAX_GAS_PER_HANDLER / 100_000,
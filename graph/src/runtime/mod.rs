@@ -258,10 +258,12 @@ pub enum IndexForAscTypeId {
     Log = 1001,
     ArrayH256 = 1002,
     ArrayLog = 1003,
+    EthereumCallResult = 1004,
+    EthereumBlockInfo = 1005,
     // Continue to add more Ethereum type IDs here.
     // e.g.:
-    // NextEthereumType = 1004,
-    // AnotherEthereumType = 1005,
+    // NextEthereumType = 1006,
+    // AnotherEthereumType = 1007,
     // ...
     // LastEthereumType = 1499,
 
@@ -400,9 +402,11 @@ impl From<anyhow::Error> for HostExportError {
 impl From<DeterministicHostError> for HostExportError {
     fn from(value: DeterministicHostError) -> Self {
         match value {
-            // Until we are confident on the gas numbers, gas errors are not deterministic
-            DeterministicHostError::Gas(e) => HostExportError::Unknown(e),
-            DeterministicHostError::Other(e) => HostExportError::Deterministic(e),
+            // Running out of gas depends only on what the handler does, the
+            // same as a trap
+            DeterministicHostError::Gas(e) | DeterministicHostError::Other(e) => {
+                HostExportError::Deterministic(e)
+            }
         }
     }
 }
@@ -412,3 +416,27 @@ pub const HEADER_SIZE: usize = 20;
 pub fn padding_to_16(content_length: usize) -> usize {
     (16 - (HEADER_SIZE + content_length) % 16) % 16
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn running_out_of_gas_is_deterministic() {
+        let gas = DeterministicHostError::gas(anyhow::anyhow!("out of gas"));
+        assert!(matches!(
+            HostExportError::from(gas),
+            HostExportError::Deterministic(_)
+        ));
+
+        let other = DeterministicHostError::from(anyhow::anyhow!("invalid UTF-8"));
+        assert!(matches!(
+            HostExportError::from(other),
+            HostExportError::Deterministic(_)
+        ));
+        assert!(matches!(
+            HostExportError::from(anyhow::anyhow!("provider failed")),
+            HostExportError::Unknown(_)
+        ));
+    }
+}
@@ -1,5 +1,7 @@
 use std::time::Duration;
 
+use rand::Rng;
+
 /// Facilitate sleeping with an exponential backoff. Sleep durations will
 /// increase by a factor of 2 from `base` until they reach `ceiling`, at
 /// which point any call to `sleep` or `sleep_async` will sleep for
@@ -8,6 +10,7 @@ pub struct ExponentialBackoff {
     pub attempt: u64,
     base: Duration,
     ceiling: Duration,
+    jitter: f64,
 }
 
 impl ExponentialBackoff {
@@ -16,9 +19,19 @@ impl ExponentialBackoff {
             attempt: 0,
             base,
             ceiling,
+            jitter: 0.0,
         }
     }
 
+    /// Make every sleep randomly up to `jitter` times the delay shorter or
+    /// longer, so that many callers that started backing off at the same
+    /// time do not all wake up at the same time. `jitter` must be between
+    /// 0 and 1
+    pub fn with_jitter(mut self, jitter: f64) -> Self {
+        self.jitter = jitter.clamp(0.0, 1.0);
+        self
+    }
+
     /// Record that we made an attempt and sleep for the appropriate amount
     /// of time. Do not use this from async contexts since it uses
     /// `thread::sleep`
@@ -41,7 +54,11 @@ impl ExponentialBackoff {
     }
 
     fn next_attempt(&mut self) -> Duration {
-        let delay = self.delay();
+        let mut delay = self.delay();
+        if self.jitter > 0.0 {
+            let factor = rand::thread_rng().gen_range(1.0 - self.jitter..=1.0 + self.jitter);
+            delay = delay.mul_f64(factor);
+        }
         self.attempt += 1;
         delay
     }
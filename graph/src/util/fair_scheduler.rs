@@ -0,0 +1,290 @@
+//! Weighted fair admission of work from many sources to a limited number
+//! of slots. Whenever a slot becomes free, it goes to the source with
+//! waiting work that holds the fewest slots relative to its weight, so
+//! that a busy source can not starve the others; among waiters from the
+//! same source, the oldest goes first
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+
+use crate::prelude::tokio::sync::oneshot;
+
+struct Source {
+    weight: u32,
+    /// The number of slots the source holds
+    running: usize,
+    /// The waiters of the source in the order in which they arrived,
+    /// together with their arrival number
+    waiting: VecDeque<(u64, oneshot::Sender<()>)>,
+}
+
+impl Source {
+    fn new(weight: u32) -> Self {
+        Source {
+            weight: weight.max(1),
+            running: 0,
+            waiting: VecDeque::new(),
+        }
+    }
+}
+
+struct State {
+    capacity: usize,
+    in_use: usize,
+    arrivals: u64,
+    sources: HashMap<String, Source>,
+}
+
+impl State {
+    /// Hand out free slots to waiters
+    fn dispatch(&mut self) {
+        while self.in_use < self.capacity {
+            // The source with the lowest share of slots per weight; compare
+            // `running / weight` without dividing
+            let next = self
+                .sources
+                .iter()
+                .filter_map(|(key, source)| {
+                    source
+                        .waiting
+                        .front()
+                        .map(|(arrival, _)| (key, source, *arrival))
+                })
+                .min_by(|(_, a, a_arrival), (_, b, b_arrival)| {
+                    let a_share = a.running as u64 * b.weight as u64;
+                    let b_share = b.running as u64 * a.weight as u64;
+                    a_share.cmp(&b_share).then(a_arrival.cmp(b_arrival))
+                })
+                .map(|(key, _, _)| key.clone());
+            let key = match next {
+                Some(key) => key,
+                None => return,
+            };
+            let source = self.sources.get_mut(&key).unwrap();
+            let (_, waiter) = source.waiting.pop_front().unwrap();
+            // The waiter is gone if it stopped waiting; give the slot to
+            // someone else
+            if waiter.send(()).is_ok() {
+                source.running += 1;
+                self.in_use += 1;
+            }
+            self.forget_idle(&key);
+        }
+    }
+
+    fn forget_idle(&mut self, key: &str) {
+        if let Some(source) = self.sources.get(key) {
+            if source.running == 0 && source.waiting.is_empty() {
+                self.sources.remove(key);
+            }
+        }
+    }
+
+    fn release(&mut self, key: &str) {
+        if let Some(source) = self.sources.get_mut(key) {
+            source.running -= 1;
+            self.in_use -= 1;
+        }
+        self.forget_idle(key);
+        self.dispatch();
+    }
+}
+
+/// The error for work that was turned away because its source already had
+/// too much waiting work
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Rejected;
+
+/// Admits work from many sources to `capacity` slots. See the module
+/// documentation
+#[derive(Clone)]
+pub struct FairScheduler {
+    state: Arc<Mutex<State>>,
+}
+
+impl FairScheduler {
+    pub fn new(capacity: usize) -> Self {
+        FairScheduler {
+            state: Arc::new(Mutex::new(State {
+                capacity,
+                in_use: 0,
+                arrivals: 0,
+                sources: HashMap::new(),
+            })),
+        }
+    }
+
+    /// Wait for a slot for work from `source`, whose share of the slots is
+    /// proportional to `weight`. If the source already has `max_waiting`
+    /// waiters, fail right away. The slot is held until the returned
+    /// permit is dropped
+    pub async fn acquire(
+        &self,
+        source: &str,
+        weight: u32,
+        max_waiting: Option<usize>,
+    ) -> Result<FairPermit, Rejected> {
+        let receiver = {
+            let mut state = self.state.lock().unwrap();
+            let idle = state.in_use < state.capacity
+                && state
+                    .sources
+                    .values()
+                    .all(|source| source.waiting.is_empty());
+            if idle {
+                state.in_use += 1;
+                state
+                    .sources
+                    .entry(source.to_string())
+                    .or_insert_with(|| Source::new(weight))
+                    .running += 1;
+                None
+            } else {
+                let arrival = state.arrivals;
+                state.arrivals += 1;
+                let entry = state
+                    .sources
+                    .entry(source.to_string())
+                    .or_insert_with(|| Source::new(weight));
+                if max_waiting.map_or(false, |max| entry.waiting.len() >= max) {
+                    state.forget_idle(source);
+                    return Err(Rejected);
+                }
+                let (sender, receiver) = oneshot::channel();
+                entry.waiting.push_back((arrival, sender));
+                Some(receiver)
+            }
+        };
+
+        if let Some(receiver) = receiver {
+            let mut waiter = Waiter {
+                receiver: Some(receiver),
+                state: self.state.clone(),
+                source: source.to_string(),
+            };
+            // The sender is only dropped without sending if the scheduler
+            // is gone, and then the slot does not matter anymore
+            let _ = waiter.receiver.as_mut().unwrap().await;
+            waiter.receiver = None;
+        }
+        Ok(FairPermit {
+            state: self.state.clone(),
+            source: source.to_string(),
+        })
+    }
+
+    /// The number of slots that are in use
+    pub fn in_use(&self) -> usize {
+        self.state.lock().unwrap().in_use
+    }
+}
+
+/// Cleans up after a caller of `FairScheduler::acquire` that stopped
+/// waiting, for example, because its query was canceled
+struct Waiter {
+    /// `None` once the slot was handed to the caller
+    receiver: Option<oneshot::Receiver<()>>,
+    state: Arc<Mutex<State>>,
+    source: String,
+}
+
+impl Drop for Waiter {
+    fn drop(&mut self) {
+        if let Some(mut receiver) = self.receiver.take() {
+            let mut state = self.state.lock().unwrap();
+            receiver.close();
+            if receiver.try_recv().is_ok() {
+                // We got a slot, but nobody is going to use it
+                state.release(&self.source);
+            } else if let Some(source) = state.sources.get_mut(&self.source) {
+                source.waiting.retain(|(_, sender)| !sender.is_closed());
+                state.forget_idle(&self.source);
+            }
+        }
+    }
+}
+
+/// A slot from a `FairScheduler`, which is released when the permit is
+/// dropped
+pub struct FairPermit {
+    state: Arc<Mutex<State>>,
+    source: String,
+}
+
+impl Drop for FairPermit {
+    fn drop(&mut self) {
+        self.state.lock().unwrap().release(&self.source);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures03::{pin_mut, FutureExt};
+
+    use crate::prelude::tokio;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn busy_source_does_not_starve_others() {
+        let scheduler = FairScheduler::new(2);
+        let a1 = scheduler.acquire("a", 1, None).await.unwrap();
+        let _a2 = scheduler.acquire("a", 1, None).await.unwrap();
+
+        // `a` queues up more work before `b` arrives
+        let a3 = scheduler.acquire("a", 1, None);
+        pin_mut!(a3);
+        assert!(a3.as_mut().now_or_never().is_none());
+        let b1 = scheduler.acquire("b", 1, None);
+        pin_mut!(b1);
+        assert!(b1.as_mut().now_or_never().is_none());
+
+        // `b` holds no slots and gets the next one even though it arrived
+        // later
+        drop(a1);
+        let b1 = b1.now_or_never().unwrap().unwrap();
+        assert!(a3.as_mut().now_or_never().is_none());
+        drop(b1);
+        let _a3 = a3.now_or_never().unwrap().unwrap();
+        assert_eq!(2, scheduler.in_use());
+    }
+
+    #[tokio::test]
+    async fn weights_and_canceled_waiters() {
+        let scheduler = FairScheduler::new(3);
+        let heavy1 = scheduler.acquire("heavy", 2, None).await.unwrap();
+        let _heavy2 = scheduler.acquire("heavy", 2, None).await.unwrap();
+        let _light1 = scheduler.acquire("light", 1, None).await.unwrap();
+
+        let light2 = scheduler.acquire("light", 1, None);
+        pin_mut!(light2);
+        assert!(light2.as_mut().now_or_never().is_none());
+        {
+            let heavy3 = scheduler.acquire("heavy", 2, None);
+            pin_mut!(heavy3);
+            assert!(heavy3.as_mut().now_or_never().is_none());
+        }
+        // Once `heavy` holds fewer slots than its weight, it would get the
+        // next slot, but its only waiter is gone
+        drop(heavy1);
+        let _light2 = light2.now_or_never().unwrap().unwrap();
+        assert_eq!(3, scheduler.in_use());
+    }
+
+    #[tokio::test]
+    async fn rejects_when_too_many_waiting() {
+        let scheduler = FairScheduler::new(1);
+        let _a1 = scheduler.acquire("a", 1, Some(1)).await.unwrap();
+        let waiting = scheduler.acquire("a", 1, Some(1));
+        pin_mut!(waiting);
+        assert!(waiting.as_mut().now_or_never().is_none());
+        assert!(matches!(
+            scheduler.acquire("a", 1, Some(1)).await,
+            Err(Rejected)
+        ));
+
+        // Other sources can still wait
+        let other = scheduler.acquire("b", 1, Some(1));
+        pin_mut!(other);
+        assert!(other.as_mut().now_or_never().is_none());
+    }
+}
@@ -163,6 +163,25 @@ impl<K: Clone + Ord + Eq + Hash + Debug + CacheWeight, V: CacheWeight + Default>
         self.queue.len()
     }
 
+    /// Split the cache into the entries whose key satisfies `pred` and the
+    /// remaining entries. Both keep the frequencies of their entries
+    pub fn partition(self, pred: impl Fn(&K) -> bool) -> (Self, Self) {
+        let mut matching = LfuCache::new();
+        let mut rest = LfuCache::new();
+        matching.stale_counter = self.stale_counter;
+        rest.stale_counter = self.stale_counter;
+        for (entry, priority) in self.queue {
+            let cache = if pred(&entry.key) {
+                &mut matching
+            } else {
+                &mut rest
+            };
+            cache.total_weight += entry.weight;
+            cache.queue.push(entry, priority);
+        }
+        (matching, rest)
+    }
+
     /// Same as `evict_with_period(max_weight, STALE_PERIOD)`
     pub fn evict(&mut self, max_weight: usize) -> Option<(usize, usize, usize)> {
         self.evict_with_period(max_weight, STALE_PERIOD)
@@ -225,7 +244,13 @@ impl<K: Ord + Eq + Hash + 'static, V: 'static> IntoIterator for LfuCache<K, V> {
 
 impl<K: Ord + Eq + Hash, V> Extend<(CacheEntry<K, V>, Priority)> for LfuCache<K, V> {
     fn extend<T: IntoIterator<Item = (CacheEntry<K, V>, Priority)>>(&mut self, iter: T) {
-        self.queue.extend(iter);
+        for (entry, priority) in iter {
+            let weight = entry.weight;
+            // Entries that are already in the cache are kept
+            if self.queue.push(entry, priority).is_none() {
+                self.total_weight += weight;
+            }
+        }
     }
 }
 
@@ -294,3 +319,32 @@ fn entity_lru_cache() {
     assert!(cache.get(&"alligator").is_none());
     assert_eq!(cache.get(&"lion"), Some(&Weight(lion_inner_weight)));
 }
+
+#[test]
+fn partition() {
+    #[derive(Default, Debug, PartialEq, Eq)]
+    struct Weight(usize);
+
+    impl CacheWeight for Weight {
+        fn indirect_weight(&self) -> usize {
+            self.0
+        }
+    }
+
+    let mut cache: LfuCache<&'static str, Weight> = LfuCache::new();
+    cache.insert("panda", Weight(2));
+    cache.insert("cow", Weight(1));
+    cache.insert("crow", Weight(3));
+    let weight = cache.total_weight;
+
+    let (mut birds, mut rest) = cache.partition(|key| *key == "crow");
+    assert_eq!(birds.len(), 1);
+    assert_eq!(rest.len(), 2);
+    assert_eq!(birds.total_weight + rest.total_weight, weight);
+    assert_eq!(birds.get(&"crow"), Some(&Weight(3)));
+    assert!(birds.get(&"cow").is_none());
+
+    rest.extend(birds);
+    assert_eq!(rest.len(), 3);
+    assert_eq!(rest.total_weight, weight);
+}
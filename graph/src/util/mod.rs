@@ -22,3 +22,6 @@ pub mod jobs;
 pub mod backoff;
 
 pub mod bounded_queue;
+
+/// Weighted fair admission of work to a limited number of slots
+pub mod fair_scheduler;
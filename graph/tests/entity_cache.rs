@@ -1,7 +1,10 @@
 use async_trait::async_trait;
+use graph::blockchain::mock::MockBlockchain;
 use graph::blockchain::BlockPtr;
+use graph::components::subgraph::{BlockState, EntityReference};
 use graph::data::subgraph::schema::{SubgraphError, SubgraphHealth};
 use graph::prelude::{Schema, StopwatchMetrics, StoreError, UnfailOutcome};
+use graph::util::lfu_cache::LfuCache;
 use lazy_static::lazy_static;
 use slog::Logger;
 use std::collections::BTreeMap;
@@ -26,6 +29,11 @@ lazy_static! {
                 founded: Int
                 label: String
             }
+
+            type Member @entity {
+                id: ID!
+                band: Band!
+            }
             ",
             SUBGRAPH_ID.clone(),
         )
@@ -340,3 +348,122 @@ fn consecutive_modifications() {
         },])
     );
 }
+
+#[test]
+fn changed_ids() {
+    let store = Arc::new(MockStore::new(BTreeMap::new()));
+    let mut cache = EntityCache::new(store.clone());
+
+    let (sigurros_key, sigurros_data) = make_band(
+        "sigurros",
+        vec![("id", "sigurros".into()), ("name", "Sigur Ros".into())],
+    );
+    cache.set(sigurros_key, sigurros_data).unwrap();
+    let (mogwai_key, mogwai_data) = make_band(
+        "mogwai",
+        vec![("id", "mogwai".into()), ("name", "Mogwai".into())],
+    );
+    cache.set(mogwai_key, mogwai_data).unwrap();
+    let (radiohead_key, _) = make_band("radiohead", vec![]);
+    cache.remove(radiohead_key);
+
+    assert_eq!(
+        vec!["mogwai", "radiohead", "sigurros"],
+        cache.changed_ids(&EntityType::new("Band".to_string()))
+    );
+    assert!(cache
+        .changed_ids(&EntityType::new("Song".to_string()))
+        .is_empty());
+}
+
+fn make_member(id: &str, band: &str) -> (EntityKey, Entity) {
+    (
+        EntityKey::data(SUBGRAPH_ID.clone(), "Member".to_string(), id.to_string()),
+        Entity::from(vec![("id", id.into()), ("band", band.into())]),
+    )
+}
+
+fn member_error(id: &str, message: &str) -> SubgraphError {
+    SubgraphError {
+        subgraph_id: SUBGRAPH_ID.clone(),
+        message: format!("{}: {}", id, message),
+        block_ptr: Some(BlockPtr::from((vec![1u8; 32], 1u64))),
+        handler: Some("handleMember".to_string()),
+        deterministic: true,
+    }
+}
+
+fn block_state() -> BlockState<MockBlockchain> {
+    let (_, sigurros) = make_band(
+        "sigurros",
+        vec![("id", "sigurros".into()), ("name", "Sigur Ros".into())],
+    );
+    let mut entities = BTreeMap::new();
+    entities.insert(EntityType::new("Band".to_string()), vec![sigurros]);
+    entities.insert(EntityType::new("Member".to_string()), vec![]);
+    BlockState::new(Arc::new(MockStore::new(entities)), LfuCache::new())
+}
+
+#[test]
+fn check_references() {
+    let mut state = block_state();
+    let block_ptr = BlockPtr::from((vec![1u8; 32], 1u64));
+
+    state.enter_handler("handleMember");
+    for (member, band) in [
+        ("jonsi", "sigurros"),
+        ("thom", "radiohead"),
+        ("colin", "radiohead"),
+        ("colin", "sigurros"),
+    ] {
+        let (key, entity) = make_member(member, band);
+        state.push_reference(EntityReference {
+            source: key.clone(),
+            field: "band".to_string(),
+            target_types: vec![EntityType::new("Band".to_string())],
+            target_id: band.to_string(),
+            handler: "handleMember".to_string(),
+        });
+        state.entity_cache.set(key, entity).unwrap();
+    }
+    state.exit_handler();
+
+    // Only `thom` still points to a band that does not exist; the
+    // reference from `colin` was replaced in the same block
+    state.check_references(&block_ptr).unwrap();
+    assert_eq!(1, state.deterministic_errors.len());
+    let error = &state.deterministic_errors[0];
+    assert!(error.message.starts_with("Entity Member[thom]"));
+    assert!(error.message.contains("`radiohead`"));
+    assert_eq!(Some(block_ptr), error.block_ptr);
+    assert_eq!(Some("handleMember"), error.handler.as_deref());
+    assert!(error.deterministic);
+    assert!(state.has_errors());
+
+    // References are only checked once
+    state.deterministic_errors.clear();
+    state.check_references(&block_ptr).unwrap();
+    assert!(!state.has_errors());
+}
+
+#[test]
+fn warnings_follow_handler_changes() {
+    let mut state = block_state();
+
+    // Warnings of a handler that fails are dropped with its changes
+    state.enter_handler("handleMember");
+    state.push_warning(member_error("thom", "filled in a default"));
+    state.exit_handler_and_discard_changes_due_to_error(member_error("thom", "failed"));
+    assert!(state.drain_warnings().is_empty());
+    assert!(state.has_errors());
+
+    // Warnings do not count as errors
+    let mut state = block_state();
+    state.enter_handler("handleMember");
+    state.push_warning(member_error("jonsi", "filled in a default"));
+    state.exit_handler();
+    assert!(!state.has_errors());
+    let warnings = state.drain_warnings();
+    assert_eq!(1, warnings.len());
+    assert_eq!("jonsi: filled in a default", warnings[0].message);
+}
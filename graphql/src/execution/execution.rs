@@ -9,11 +9,12 @@ use lazy_static::lazy_static;
 use stable_hash::crypto::SetHasher;
 use stable_hash::prelude::*;
 use stable_hash::utils::stable_hash;
+use std::sync::Mutex;
 use std::time::Instant;
 use std::{borrow::ToOwned, collections::HashSet};
 
 use graph::data::graphql::*;
-use graph::data::query::CacheStatus;
+use graph::data::query::{CacheStatus, QueryExplanation};
 use graph::env::CachedSubgraphIds;
 use graph::prelude::*;
 use graph::util::lfu_cache::LfuCache;
@@ -143,6 +144,10 @@ where
 
     /// Records whether this was a cache hit, used for logging.
     pub(crate) cache_status: AtomicCell<CacheStatus>,
+
+    /// How the store answered the queries for this execution; only
+    /// collected if the query asked for `explain`
+    pub(crate) explanations: Mutex<Vec<QueryExplanation>>,
 }
 
 pub(crate) fn get_field<'a>(
@@ -175,6 +180,7 @@ where
 
             // `cache_status` is a dead value for the introspection context.
             cache_status: AtomicCell::new(CacheStatus::Miss),
+            explanations: Default::default(),
         }
     }
 }
@@ -239,6 +245,7 @@ pub(crate) async fn execute_root_selection_set<R: Resolver>(
     let mut key: Option<QueryHash> = None;
 
     let should_check_cache = R::CACHEABLE
+        && !ctx.query.explain
         && match ENV_VARS.graphql.cached_subgraph_ids {
             CachedSubgraphIds::All => true,
             CachedSubgraphIds::Only(ref subgraph_ids) => {
@@ -282,7 +289,10 @@ pub(crate) async fn execute_root_selection_set<R: Resolver>(
     let execute_selection_set = selection_set.cheap_clone();
     let execute_root_type = root_type.cheap_clone();
     let run_query = async move {
-        let _permit = execute_ctx.resolver.query_permit().await;
+        let _permit = match execute_ctx.resolver.query_permit().await {
+            Ok(permit) => permit,
+            Err(e) => return Arc::new(QueryResult::from(e)),
+        };
 
         let logger = execute_ctx.logger.clone();
         let query_text = execute_ctx.query.query_text.cheap_clone();
@@ -297,6 +307,7 @@ pub(crate) async fn execute_root_selection_set<R: Resolver>(
             // Unwrap: In practice should never fail, but if it does we will catch the panic.
             execute_ctx.resolver.post_process(&mut query_res).unwrap();
             query_res.deployment = Some(execute_ctx.query.schema.id().clone());
+            query_res.explanations = std::mem::take(&mut *execute_ctx.explanations.lock().unwrap());
             Arc::new(query_res)
         })
         .await
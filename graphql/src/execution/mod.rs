@@ -4,6 +4,7 @@ mod execution;
 mod query;
 /// Common trait for field resolvers used in the execution.
 mod resolver;
+mod suggestion;
 
 /// Our representation of a query AST
 pub mod ast;
@@ -1,9 +1,11 @@
+use graph::data::graphql::ext::DirectiveFinder as _;
 use graph::data::graphql::DocumentExt as _;
 use graph::data::value::Object;
 use graphql_parser::Pos;
 use graphql_tools::validation::rules::*;
 use graphql_tools::validation::validate::{validate, ValidationPlan};
 use lazy_static::lazy_static;
+use std::cell::RefCell;
 use std::collections::{BTreeMap, HashMap, HashSet};
 use std::hash::{Hash, Hasher};
 use std::iter::FromIterator;
@@ -12,12 +14,13 @@ use std::time::Instant;
 use std::{collections::hash_map::DefaultHasher, convert::TryFrom};
 
 use graph::data::graphql::{ext::TypeExt, ObjectOrInterface};
-use graph::data::query::QueryExecutionError;
 use graph::data::query::{Query as GraphDataQuery, QueryVariables};
+use graph::data::query::{QueryExecutionError, QueryLimits, QueryWarning};
 use graph::data::schema::ApiSchema;
 use graph::prelude::{info, o, q, r, s, BlockNumber, CheapClone, Logger, TryFromValue, ENV_VARS};
 
 use crate::execution::ast as a;
+use crate::execution::suggestion::suggestion;
 use crate::query::{ast as qast, ext::BlockConstraint};
 use crate::schema::ast::{self as sast};
 use crate::values::coercion;
@@ -133,20 +136,27 @@ pub struct Query {
     pub query_text: Arc<String>,
     pub variables_text: Arc<String>,
     pub query_id: String,
+
+    /// Whether to collect the SQL and plans for the store queries used to
+    /// answer this query
+    pub explain: bool,
+
+    /// Warnings about the query, like the use of deprecated fields, that
+    /// are reported in the extensions of the response
+    pub warnings: Vec<QueryWarning>,
 }
 
 impl Query {
     /// Process the raw GraphQL query `query` and prepare for executing it.
-    /// The returned `Query` has already been validated and, if `max_complexity`
-    /// is given, also checked whether it is too complex. If validation fails,
-    /// or the query is too complex, errors are returned
+    /// The returned `Query` has already been validated and checked against
+    /// `limits`. If validation fails, or the query exceeds one of the
+    /// limits, errors are returned
     pub fn new(
         logger: &Logger,
         schema: Arc<ApiSchema>,
         network: Option<String>,
         query: GraphDataQuery,
-        max_complexity: Option<u64>,
-        max_depth: u8,
+        limits: QueryLimits,
     ) -> Result<Arc<Self>, Vec<QueryExecutionError>> {
         let validation_errors =
             validate(schema.document(), &query.document, &GRAPHQL_VALIDATION_PLAN);
@@ -223,9 +233,9 @@ impl Query {
         // doesn't risk a stack overflow from invalid queries. We don't
         // really care about the resulting complexity, only that all the
         // checks that `check_complexity` performs pass successfully
-        let _ = raw_query.check_complexity(max_complexity, max_depth)?;
+        let _ = raw_query.check_complexity(limits.max_complexity, limits.max_depth)?;
         raw_query.validate_fields()?;
-        let selection_set = raw_query.convert()?;
+        let (selection_set, warnings) = raw_query.convert(&limits)?;
 
         let query = Self {
             schema,
@@ -238,6 +248,8 @@ impl Query {
             query_text: query.query_text.cheap_clone(),
             variables_text: query.variables_text.cheap_clone(),
             query_id,
+            explain: query.explain,
+            warnings,
         };
 
         Ok(Arc::new(query))
@@ -634,6 +646,7 @@ impl<'s> RawQuery<'s> {
                             field.position,
                             type_name.into(),
                             field.name.clone(),
+                            suggestion(&field.name, ty.fields().iter().map(|f| f.name.as_str())),
                         )),
                     },
                     q::Selection::FragmentSpread(fragment) => {
@@ -679,7 +692,10 @@ impl<'s> RawQuery<'s> {
             })
     }
 
-    fn convert(self) -> Result<a::SelectionSet, Vec<QueryExecutionError>> {
+    fn convert(
+        self,
+        limits: &QueryLimits,
+    ) -> Result<(a::SelectionSet, Vec<QueryWarning>), Vec<QueryExecutionError>> {
         let RawQuery {
             schema,
             variables,
@@ -692,8 +708,16 @@ impl<'s> RawQuery<'s> {
             schema,
             variables,
             fragments,
+            max_first: limits.max_first,
+            max_skip: limits.max_skip,
+            warnings: RefCell::new(Vec::new()),
         };
-        transform.expand_selection_set(selection_set, &a::ObjectTypeSet::Any, root_type.into())
+        let selection_set = transform.expand_selection_set(
+            selection_set,
+            &a::ObjectTypeSet::Any,
+            root_type.into(),
+        )?;
+        Ok((selection_set, transform.warnings.into_inner()))
     }
 }
 
@@ -701,6 +725,9 @@ struct Transform {
     schema: Arc<ApiSchema>,
     variables: HashMap<String, r::Value>,
     fragments: HashMap<String, q::FragmentDefinition>,
+    max_first: u32,
+    max_skip: u32,
+    warnings: RefCell<Vec<QueryWarning>>,
 }
 
 impl Transform {
@@ -789,6 +816,7 @@ impl Transform {
         arguments: &mut Vec<(String, r::Value)>,
         ty: ObjectOrInterface<'a>,
         field_name: &str,
+        pos: &Pos,
     ) -> Result<(), Vec<QueryExecutionError>> {
         let mut errors = vec![];
 
@@ -830,15 +858,56 @@ impl Transform {
         // see: graphql-bug-compat
         // avoids error 'unknown argument on field'
         if defined_args < arguments.len() {
-            // `arguments` contains undefined arguments, remove them
-            match sast::get_argument_definitions(ty, field_name) {
-                None => arguments.clear(),
-                Some(arg_defs) => {
-                    arguments.retain(|(name, _)| arg_defs.iter().any(|def| &def.name == name))
+            let arg_defs = sast::get_argument_definitions(ty, field_name);
+            let is_defined = |name: &str| {
+                arg_defs.map_or(false, |arg_defs| {
+                    arg_defs.iter().any(|def| def.name == name)
+                })
+            };
+            if ENV_VARS.graphql.reject_unknown_arguments {
+                for (name, _) in arguments.iter().filter(|(name, _)| !is_defined(name)) {
+                    let known = arg_defs.into_iter().flatten().map(|def| def.name.as_str());
+                    errors.push(QueryExecutionError::UnknownArgument(
+                        *pos,
+                        format!("{}.{}", ty.name(), field_name),
+                        name.clone(),
+                        suggestion(name, known),
+                    ));
                 }
             }
+            // `arguments` contains undefined arguments, remove them
+            arguments.retain(|(name, _)| is_defined(name));
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
         }
+    }
 
+    /// Check that the `first` and `skip` arguments, after they have been
+    /// coerced and had their defaults filled in, are within the limits
+    /// for this query
+    fn check_range_arguments(
+        &self,
+        arguments: &[(String, r::Value)],
+    ) -> Result<(), Vec<QueryExecutionError>> {
+        let mut errors = vec![];
+        for (name, value) in arguments {
+            match (name.as_str(), value) {
+                ("first", r::Value::Int(n)) if *n <= 0 || *n > self.max_first as i64 => errors
+                    .push(QueryExecutionError::RangeArgumentsError(
+                        "first",
+                        self.max_first,
+                        *n,
+                    )),
+                ("skip", r::Value::Int(n)) if *n < 0 || *n > self.max_skip as i64 => errors.push(
+                    QueryExecutionError::RangeArgumentsError("skip", self.max_skip, *n),
+                ),
+                _ => {}
+            }
+        }
         if errors.is_empty() {
             Ok(())
         } else {
@@ -879,6 +948,7 @@ impl Transform {
                 position,
                 parent_type.name().to_string(),
                 name.clone(),
+                suggestion(&name, parent_type.fields().iter().map(|f| f.name.as_str())),
             )]
         })?;
 
@@ -887,8 +957,26 @@ impl Transform {
             return Ok(None);
         }
 
+        if let Some(reason) = field_type.deprecation_reason() {
+            let warning = QueryWarning {
+                message: format!(
+                    "The field `{}.{}` is deprecated: {}",
+                    parent_type.name(),
+                    name,
+                    reason
+                ),
+                subgraph: None,
+                removal_date: None,
+            };
+            let mut warnings = self.warnings.borrow_mut();
+            if !warnings.contains(&warning) {
+                warnings.push(warning);
+            }
+        }
+
         let mut arguments = self.interpolate_arguments(arguments, &position);
-        self.coerce_argument_values(&mut arguments, parent_type, &name)?;
+        self.coerce_argument_values(&mut arguments, parent_type, &name, &position)?;
+        self.check_range_arguments(&arguments)?;
 
         let is_leaf_type = self.schema.document().is_leaf_type(&field_type.field_type);
         let selection_set = if selection_set.items.is_empty() {
@@ -1,5 +1,5 @@
 use graph::components::store::UnitStream;
-use graph::prelude::{async_trait, s, tokio, ApiSchema, Error, QueryExecutionError};
+use graph::prelude::{async_trait, s, ApiSchema, Error, QueryExecutionError, QueryPermit};
 use graph::{
     data::graphql::ObjectOrInterface,
     prelude::{r, QueryResult},
@@ -12,7 +12,7 @@ use crate::execution::{ast as a, ExecutionContext};
 pub trait Resolver: Sized + Send + Sync + 'static {
     const CACHEABLE: bool;
 
-    async fn query_permit(&self) -> tokio::sync::OwnedSemaphorePermit;
+    async fn query_permit(&self) -> Result<QueryPermit, QueryExecutionError>;
 
     /// Prepare for executing a query by prefetching as much data as possible
     fn prefetch(
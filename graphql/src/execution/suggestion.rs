@@ -0,0 +1,62 @@
+//! Suggestions for names in a query that the schema does not know, so that
+//! errors can point out the most likely typo
+
+/// The candidate closest to `name` if it is close enough to be what the
+/// query meant. Like `graphql-js`, we accept candidates whose edit distance
+/// from `name`, ignoring case, is at most 40% of the length of `name`
+pub(crate) fn suggestion<'a>(
+    name: &str,
+    candidates: impl IntoIterator<Item = &'a str>,
+) -> Option<String> {
+    let threshold = name.chars().count() * 2 / 5 + 1;
+    let lower = name.to_lowercase();
+    candidates
+        .into_iter()
+        .map(|candidate| (distance(&lower, &candidate.to_lowercase()), candidate))
+        .filter(|(distance, _)| *distance <= threshold)
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, candidate)| candidate.to_string())
+}
+
+/// The Levenshtein distance between `a` and `b`
+fn distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let above = row[j + 1];
+            row[j + 1] = if ca == *cb {
+                diagonal
+            } else {
+                1 + diagonal.min(row[j]).min(above)
+            };
+            diagonal = above;
+        }
+    }
+    row[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{distance, suggestion};
+
+    #[test]
+    fn edit_distance() {
+        assert_eq!(0, distance("name", "name"));
+        assert_eq!(1, distance("nme", "name"));
+        assert_eq!(2, distance("nmae", "name"));
+        assert_eq!(3, distance("kitten", "sitting"));
+        assert_eq!(4, distance("", "name"));
+    }
+
+    #[test]
+    fn suggests_close_names() {
+        let fields = ["id", "name", "mainBand", "bands"];
+        assert_eq!(Some("name".to_string()), suggestion("nmae", fields));
+        assert_eq!(Some("mainBand".to_string()), suggestion("mainband", fields));
+        assert_eq!(Some("bands".to_string()), suggestion("band", fields));
+        assert_eq!(None, suggestion("writtenSongs", fields));
+    }
+}
@@ -1,4 +1,4 @@
-use graph::data::graphql::ext::{FieldExt, TypeDefinitionExt};
+use graph::data::graphql::ext::{DirectiveFinder, FieldExt, TypeDefinitionExt};
 use graphql_parser::Pos;
 use std::collections::BTreeMap;
 
@@ -101,11 +101,12 @@ fn enum_values(enum_type: &s::EnumType) -> r::Value {
 }
 
 fn enum_value(enum_value: &s::EnumValue) -> r::Value {
+    let deprecation_reason = enum_value.directives.deprecation_reason();
     object! {
         name: enum_value.name.to_owned(),
         description: enum_value.description.clone(),
-        isDeprecated: false,
-        deprecationReason: r::Value::Null,
+        isDeprecated: deprecation_reason.is_some(),
+        deprecationReason: deprecation_reason,
     }
 }
 
@@ -178,13 +179,14 @@ fn field_objects(
 }
 
 fn field_object(schema: &Schema, type_objects: &mut TypeObjectsMap, field: &s::Field) -> r::Value {
+    let deprecation_reason = field.deprecation_reason();
     object! {
         name: field.name.to_owned(),
         description: field.description.clone(),
         args: input_values(schema, type_objects, &field.arguments),
         type: type_object(schema, type_objects, &field.field_type),
-        isDeprecated: false,
-        deprecationReason: r::Value::Null,
+        isDeprecated: deprecation_reason.is_some(),
+        deprecationReason: deprecation_reason,
     }
 }
 
@@ -358,7 +360,7 @@ impl Resolver for IntrospectionResolver {
     // see `fn as_introspection_context`, so this value is irrelevant.
     const CACHEABLE: bool = false;
 
-    async fn query_permit(&self) -> tokio::sync::OwnedSemaphorePermit {
+    async fn query_permit(&self) -> Result<QueryPermit, QueryExecutionError> {
         unreachable!()
     }
 
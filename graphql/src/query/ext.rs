@@ -61,6 +61,13 @@ pub enum BlockConstraint {
     /// Execute the query on the latest block only if the the subgraph has progressed to or past the
     /// given block number.
     Min(BlockNumber),
+    /// Execute the query on the block with the highest number that was
+    /// produced at or before the given time, in seconds since the epoch
+    Timestamp(u64),
+    /// Execute the query on the latest block of the subgraph that is
+    /// further behind the chain head than the reorg threshold, so that the
+    /// data it returns will not be reverted
+    Final,
     Latest,
 }
 
@@ -89,6 +96,15 @@ impl TryFromValue for BlockConstraint {
             Ok(BlockConstraint::Min(BlockNumber::try_from_value(
                 number_value,
             )?))
+        } else if let Some(timestamp_value) = map.get("timestamp_lte") {
+            Ok(BlockConstraint::Timestamp(u64::try_from_value(
+                timestamp_value,
+            )?))
+        } else if let Some(final_value) = map.get("final") {
+            match bool::try_from_value(final_value)? {
+                true => Ok(BlockConstraint::Final),
+                false => Ok(BlockConstraint::Latest),
+            }
         } else {
             Err(anyhow!("invalid `BlockConstraint`"))
         }
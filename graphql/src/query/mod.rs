@@ -49,6 +49,7 @@ where
         max_first: options.max_first,
         max_skip: options.max_skip,
         cache_status: Default::default(),
+        explanations: Default::default(),
     });
 
     if !query.is_query() {
@@ -1,22 +1,27 @@
 use std::collections::HashMap;
 use std::sync::Arc;
-use std::time::Instant;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 use crate::prelude::{QueryExecutionOptions, StoreResolver, SubscriptionExecutionOptions};
 use crate::query::execute_query;
+use crate::query::ext::BlockConstraint;
 use crate::subscription::execute_prepared_subscription;
 use graph::prelude::MetricsRegistry;
 use graph::prometheus::{Gauge, Histogram};
 use graph::{
     components::store::SubscriptionManager,
     prelude::{
-        async_trait, o, CheapClone, DeploymentState, GraphQlRunner as GraphQlRunnerTrait, Logger,
-        Query, QueryExecutionError, Subscription, SubscriptionError, SubscriptionResult, ENV_VARS,
+        async_trait, o, serde_json, warn, BlockNumber, CheapClone, DeploymentState,
+        GraphQlRunner as GraphQlRunnerTrait, Logger, Query, QueryExecutionError, Schema,
+        StoreError, Subscription, SubscriptionError, SubscriptionResult, ENV_VARS,
     },
 };
 use graph::{data::graphql::effort::LoadManager, prelude::QueryStoreManager};
 use graph::{
-    data::query::{QueryResults, QueryTarget},
+    data::query::{
+        DeploymentQueryLimits, Freshness, QueryLimitOverrides, QueryLimits, QueryResults,
+        QueryTarget,
+    },
     prelude::QueryStore,
 };
 
@@ -73,6 +78,7 @@ pub struct GraphQlRunner<S, SM> {
     subscription_manager: Arc<SM>,
     load_manager: Arc<LoadManager>,
     result_size: Arc<ResultSizeMetrics>,
+    limits: DeploymentQueryLimits,
 }
 
 #[cfg(debug_assertions)]
@@ -102,9 +108,16 @@ where
             subscription_manager,
             load_manager,
             result_size,
+            limits: DeploymentQueryLimits::new(QueryLimits::from_env()),
         }
     }
 
+    /// Use `limits` instead of the limits from the environment when
+    /// validating queries
+    pub fn with_query_limits(self, limits: DeploymentQueryLimits) -> Self {
+        GraphQlRunner { limits, ..self }
+    }
+
     /// Check if the subgraph state differs from `state` now in a way that
     /// would affect a query that looked at data as fresh as `latest_block`.
     /// If the subgraph did change, return the `Err` that should be sent back
@@ -154,6 +167,11 @@ where
         // while the query is running. `self.store` can not be used after this
         // point, and everything needs to go through the `store` we are
         // setting up here
+        let name = match &target {
+            QueryTarget::Name(name) => Some(name.clone()),
+            QueryTarget::Deployment(_) => None,
+        };
+        let by_deployment = name.is_none();
         let store = self.store.query_store(target, false).await?;
         let state = store.deployment_state().await?;
         let network = Some(store.network_name().to_string());
@@ -167,15 +185,18 @@ where
             .clone()
             .unwrap_or(state);
 
-        let max_depth = max_depth.unwrap_or(ENV_VARS.graphql.max_depth);
-        let query = crate::execution::Query::new(
-            &self.logger,
-            schema,
-            network,
-            query,
-            max_complexity,
-            max_depth,
-        )?;
+        // Explicitly passed limits take precedence over the limits for
+        // the deployment
+        let limits = self
+            .limits
+            .for_deployment(schema.id())
+            .with_overrides(&QueryLimitOverrides {
+                max_complexity,
+                max_depth,
+                max_first,
+                max_skip,
+            });
+        let query = crate::execution::Query::new(&self.logger, schema, network, query, limits)?;
         self.load_manager
             .decide(
                 &store.wait_stats(),
@@ -185,10 +206,16 @@ where
             .to_result()?;
         let by_block_constraint = query.block_constraint()?;
         let mut max_block = 0;
+        let mut max_age = ENV_VARS.graphql.pinned_query_max_age;
         let mut result: QueryResults = QueryResults::empty();
 
         // Note: This will always iterate at least once.
         for (bc, (selection_set, error_policy)) in by_block_constraint {
+            max_age = max_age.min(cache_max_age(
+                &bc,
+                state.latest_ethereum_block_number,
+                by_deployment,
+            ));
             let resolver = StoreResolver::at_block(
                 &self.logger,
                 store.cheap_clone(),
@@ -207,14 +234,39 @@ where
                 QueryExecutionOptions {
                     resolver,
                     deadline: ENV_VARS.graphql.query_timeout.map(|t| Instant::now() + t),
-                    max_first: max_first.unwrap_or(ENV_VARS.graphql.max_first),
-                    max_skip: max_skip.unwrap_or(ENV_VARS.graphql.max_skip),
+                    max_first: limits.max_first,
+                    max_skip: limits.max_skip,
                     load_manager: self.load_manager.clone(),
                 },
             )
             .await;
             result.append(query_res);
         }
+        if query.explain {
+            // Explanations are only meant for the operator who asked
+            max_age = Duration::ZERO;
+        }
+        result.set_max_age(max_age);
+        for warning in &query.warnings {
+            result.add_warning(warning.clone());
+        }
+        if ENV_VARS.graphql.freshness_extension {
+            match freshness(store.as_ref()).await {
+                Ok(Some(freshness)) => result.set_freshness(freshness),
+                Ok(None) => {}
+                Err(e) => warn!(self.logger, "Failed to determine the freshness of a deployment";
+                                "deployment" => query.schema.id().as_str(), "error" => e.to_string()),
+            }
+        }
+        if let Some(name) = name {
+            // Failing to look up the notice should not fail the query
+            match self.store.deprecation(&name).await {
+                Ok(Some(deprecation)) => result.add_warning(deprecation.warning(&name)),
+                Ok(None) => {}
+                Err(e) => warn!(self.logger, "Failed to look up the deprecation notice";
+                                "subgraph" => name.as_str(), "error" => e.to_string()),
+            }
+        }
 
         query.log_execution(max_block);
         self.deployment_changed(store.as_ref(), state, max_block as u64)
@@ -224,6 +276,46 @@ where
     }
 }
 
+/// How far the deployment behind `store` is behind its chain, or `None` if
+/// it has not processed any blocks yet
+async fn freshness(
+    store: &(dyn QueryStore + Send + Sync),
+) -> Result<Option<Freshness>, StoreError> {
+    let ptr = match store.block_ptr().await? {
+        Some(ptr) => ptr,
+        None => return Ok(None),
+    };
+    let chain_head = store.chain_head_number()?;
+    let timestamp = store.block_time(&ptr.hash_as_h256())?;
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|now| now.as_secs())
+        .unwrap_or(0);
+    Ok(Some(Freshness::new(ptr.number, chain_head, timestamp, now)))
+}
+
+/// How long HTTP caches may keep the response for the part of a query
+/// with block constraint `bc` when the subgraph is at block `head`. Data
+/// for blocks that can not be reverted anymore will never change, but only
+/// for queries `by_deployment`: a subgraph name refers to a different
+/// deployment as soon as a new version is deployed
+fn cache_max_age(bc: &BlockConstraint, head: BlockNumber, by_deployment: bool) -> Duration {
+    if !by_deployment {
+        return ENV_VARS.graphql.head_query_max_age;
+    }
+    match bc {
+        BlockConstraint::Hash(_) => ENV_VARS.graphql.pinned_query_max_age,
+        BlockConstraint::Number(number) if ENV_VARS.is_final(*number, head) => {
+            ENV_VARS.graphql.pinned_query_max_age
+        }
+        BlockConstraint::Number(_)
+        | BlockConstraint::Min(_)
+        | BlockConstraint::Timestamp(_)
+        | BlockConstraint::Final
+        | BlockConstraint::Latest => ENV_VARS.graphql.head_query_max_age,
+    }
+}
+
 #[async_trait]
 impl<S, SM> GraphQlRunnerTrait for GraphQlRunner<S, SM>
 where
@@ -231,15 +323,8 @@ where
     SM: SubscriptionManager,
 {
     async fn run_query(self: Arc<Self>, query: Query, target: QueryTarget) -> QueryResults {
-        self.run_query_with_complexity(
-            query,
-            target,
-            ENV_VARS.graphql.max_complexity,
-            Some(ENV_VARS.graphql.max_depth),
-            Some(ENV_VARS.graphql.max_first),
-            Some(ENV_VARS.graphql.max_skip),
-        )
-        .await
+        self.run_query_with_complexity(query, target, None, None, None, None)
+            .await
     }
 
     async fn run_query_with_complexity(
@@ -272,14 +357,14 @@ where
         let store = self.store.query_store(target, true).await?;
         let schema = store.api_schema()?;
         let network = store.network_name().to_string();
+        let limits = self.limits.for_deployment(schema.id());
 
         let query = crate::execution::Query::new(
             &self.logger,
             schema,
             Some(network),
             subscription.query,
-            ENV_VARS.graphql.max_complexity,
-            ENV_VARS.graphql.max_depth,
+            limits,
         )?;
 
         if let Err(err) = self
@@ -301,15 +386,52 @@ where
                 store,
                 subscription_manager: self.subscription_manager.cheap_clone(),
                 timeout: ENV_VARS.graphql.query_timeout,
-                max_complexity: ENV_VARS.graphql.max_complexity,
-                max_depth: ENV_VARS.graphql.max_depth,
-                max_first: ENV_VARS.graphql.max_first,
-                max_skip: ENV_VARS.graphql.max_skip,
+                max_complexity: limits.max_complexity,
+                max_depth: limits.max_depth,
+                max_first: limits.max_first,
+                max_skip: limits.max_skip,
                 result_size: self.result_size.clone(),
             },
         )
     }
 
+    async fn run_sql(
+        self: Arc<Self>,
+        sql: String,
+        block: Option<BlockNumber>,
+        target: QueryTarget,
+    ) -> Result<Vec<serde_json::Value>, QueryExecutionError> {
+        let store = self.store.query_store(target, false).await?;
+        let latest = store
+            .block_ptr()
+            .await?
+            .map(|ptr| ptr.number)
+            .ok_or_else(|| {
+                QueryExecutionError::InvalidSql("the subgraph has not started syncing".to_string())
+            })?;
+        let block = match block {
+            Some(block) if block > latest => {
+                return Err(QueryExecutionError::InvalidSql(format!(
+                    "the subgraph has only indexed up to block number {} \
+                     and data for block number {} is therefore not yet available",
+                    latest, block
+                )))
+            }
+            Some(block) => block,
+            None => latest,
+        };
+
+        let _permit = store.query_permit().await?;
+        store.execute_sql(&sql, block)
+    }
+
+    async fn input_schema(
+        self: Arc<Self>,
+        target: QueryTarget,
+    ) -> Result<Arc<Schema>, QueryExecutionError> {
+        self.store.query_store(target, false).await?.input_schema()
+    }
+
     fn load_manager(&self) -> Arc<LoadManager> {
         self.load_manager.clone()
     }
@@ -7,7 +7,8 @@ use lazy_static::lazy_static;
 use crate::schema::ast;
 
 use graph::data::{
-    graphql::ext::{DirectiveExt, DocumentExt, ValueExt},
+    graphql::ext::{DirectiveExt, DirectiveFinder, DocumentExt, TypeExt, ValueExt},
+    graphql::ObjectTypeExt,
     schema::{META_FIELD_NAME, META_FIELD_TYPE, SCHEMA_TYPE_NAME},
 };
 use graph::prelude::s::{Value, *};
@@ -266,19 +267,34 @@ fn field_scalar_filter_input_values(
     match field_type.name.as_ref() {
         "BigInt" => vec!["", "not", "gt", "lt", "gte", "lte", "in", "not_in"],
         "Boolean" => vec!["", "not", "in", "not_in"],
-        "Bytes" => vec!["", "not", "in", "not_in", "contains", "not_contains"],
+        "Bytes" => vec![
+            "",
+            "not",
+            "gt",
+            "lt",
+            "gte",
+            "lte",
+            "in",
+            "not_in",
+            "contains",
+            "not_contains",
+        ],
         "BigDecimal" => vec!["", "not", "gt", "lt", "gte", "lte", "in", "not_in"],
         "ID" => vec!["", "not", "gt", "lt", "gte", "lte", "in", "not_in"],
         "Int" => vec!["", "not", "gt", "lt", "gte", "lte", "in", "not_in"],
         "String" => vec![
             "",
+            "nocase",
             "not",
+            "not_nocase",
             "gt",
             "lt",
             "gte",
             "lte",
             "in",
+            "in_nocase",
             "not_in",
+            "not_in_nocase",
             "contains",
             "contains_nocase",
             "not_contains",
@@ -295,10 +311,16 @@ fn field_scalar_filter_input_values(
         _ => vec!["", "not"],
     }
     .into_iter()
+    .chain(match field_type.name.as_ref() {
+        "String" if ENV_VARS.graphql.enable_regex_filter => Some("matches"),
+        _ => None,
+    })
     .map(|filter_type| {
         let field_type = Type::NamedType(field_type.name.to_owned());
         let value_type = match filter_type {
-            "in" | "not_in" => Type::ListType(Box::new(Type::NonNullType(Box::new(field_type)))),
+            "in" | "not_in" | "in_nocase" | "not_in_nocase" => {
+                Type::ListType(Box::new(Type::NonNullType(Box::new(field_type))))
+            }
             _ => field_type,
         };
         input_value(&field.name, filter_type, value_type)
@@ -351,27 +373,32 @@ fn field_list_filter_input_values(
             TypeDefinition::InputObject(_) | TypeDefinition::Union(_) => return None,
         };
 
-        Some(
-            vec![
-                "",
-                "not",
-                "contains",
-                "contains_nocase",
-                "not_contains",
-                "not_contains_nocase",
-            ]
-            .into_iter()
-            .map(|filter_type| {
-                input_value(
-                    &field.name,
-                    filter_type,
-                    Type::ListType(Box::new(Type::NonNullType(Box::new(
-                        input_field_type.clone(),
-                    )))),
-                )
-            })
-            .collect(),
-        )
+        let mut values: Vec<_> = vec![
+            "",
+            "not",
+            "contains",
+            "contains_nocase",
+            "not_contains",
+            "not_contains_nocase",
+            "overlaps",
+        ]
+        .into_iter()
+        .map(|filter_type| {
+            input_value(
+                &field.name,
+                filter_type,
+                Type::ListType(Box::new(Type::NonNullType(Box::new(
+                    input_field_type.clone(),
+                )))),
+            )
+        })
+        .collect();
+        values.push(input_value(
+            &field.name,
+            "empty",
+            Type::NamedType("Boolean".to_owned()),
+        ));
+        Some(values)
     })
 }
 
@@ -417,6 +444,11 @@ fn add_query_type(
         .filter_map(|fulltext| query_field_for_fulltext(fulltext))
         .collect();
     fields.append(&mut fulltext_fields);
+    let mut composite_id_fields = object_types
+        .iter()
+        .filter_map(|object_type| query_field_for_composite_id(schema, object_type))
+        .collect();
+    fields.append(&mut composite_id_fields);
     fields.push(meta_field());
 
     let typedef = TypeDefinition::Object(ObjectType {
@@ -432,6 +464,46 @@ fn add_query_type(
     Ok(())
 }
 
+/// Generates a `Query` field that looks up an entity by the fields of its
+/// `@compositeId`, e.g., `swapByKey(pair: ID!, blockNumber: BigInt!)`. The
+/// field carries the `@compositeId` directive so that the resolver can tell
+/// it apart from other fields
+fn query_field_for_composite_id(schema: &Document, object_type: &ObjectType) -> Option<Field> {
+    let directive = object_type.find_directive("compositeId")?.clone();
+    let mut arguments = object_type
+        .composite_id()?
+        .into_iter()
+        .filter_map(|name| object_type.field(name))
+        .map(|field| {
+            // References are looked up by the id of the referenced entity
+            let base_type = field.field_type.get_base_type();
+            let id_type = match schema.get_named_type(base_type) {
+                Some(TypeDefinition::Object(object_type)) => object_type.field("id"),
+                Some(TypeDefinition::Interface(interface_type)) => interface_type.field("id"),
+                _ => None,
+            }
+            .map(|id| id.field_type.get_base_type())
+            .unwrap_or(base_type);
+            input_value(
+                &field.name,
+                "",
+                Type::NonNullType(Box::new(Type::NamedType(id_type.to_owned()))),
+            )
+        })
+        .collect::<Vec<_>>();
+    arguments.push(block_argument());
+    arguments.push(subgraph_error_argument());
+
+    Some(Field {
+        position: Pos::default(),
+        description: None,
+        name: format!("{}ByKey", object_type.name.to_camel_case()),
+        arguments,
+        field_type: Type::NamedType(object_type.name.to_owned()),
+        directives: vec![directive],
+    })
+}
+
 fn query_field_for_fulltext(fulltext: &Directive) -> Option<Field> {
     let name = fulltext.argument("name").unwrap().as_str().unwrap().into();
 
@@ -528,7 +600,9 @@ fn block_argument() -> InputValue {
             "The block at which the query should be executed. \
              Can either be a `{ hash: Bytes }` value containing a block hash, \
              a `{ number: Int }` containing the block number, \
-             or a `{ number_gte: Int }` containing the minimum block number. \
+             a `{ number_gte: Int }` containing the minimum block number, \
+             a `{ timestamp_lte: Int }` for the last block produced at or before a time in seconds since the epoch, \
+             or `{ final: true }` for the latest block that can not be reverted anymore. \
              In the case of `number_gte`, the query will be executed on the latest block only if \
              the subgraph has progressed to or past the minimum block number. \
              Defaults to the latest block when omitted."
@@ -827,6 +901,40 @@ mod tests {
         assert_eq!(values, ["id", "name"]);
     }
 
+    #[test]
+    fn api_schema_contains_bytes_filters() {
+        let input_schema = parse_schema("type Token @entity { id: ID!, owner: Bytes! }")
+            .expect("Failed to parse input schema");
+        let schema = api_schema(&input_schema).expect("Failed to derived API schema");
+
+        let filter_type = match schema.get_named_type("Token_filter") {
+            Some(TypeDefinition::InputObject(t)) => t,
+            _ => panic!("Token_filter type is missing in derived API schema"),
+        };
+
+        let owner_filters = filter_type
+            .fields
+            .iter()
+            .map(|field| field.name.as_str())
+            .filter(|name| name.starts_with("owner"))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                "owner",
+                "owner_not",
+                "owner_gt",
+                "owner_lt",
+                "owner_gte",
+                "owner_lte",
+                "owner_in",
+                "owner_not_in",
+                "owner_contains",
+                "owner_not_contains",
+            ],
+            owner_filters
+        );
+    }
+
     #[test]
     fn api_schema_contains_object_type_filter_enum() {
         let input_schema = parse_schema(
@@ -885,13 +993,17 @@ mod tests {
                 "id_in",
                 "id_not_in",
                 "name",
+                "name_nocase",
                 "name_not",
+                "name_not_nocase",
                 "name_gt",
                 "name_lt",
                 "name_gte",
                 "name_lte",
                 "name_in",
+                "name_in_nocase",
                 "name_not_in",
+                "name_not_in_nocase",
                 "name_contains",
                 "name_contains_nocase",
                 "name_not_contains",
@@ -910,24 +1022,32 @@ mod tests {
                 "favoritePetNames_contains_nocase",
                 "favoritePetNames_not_contains",
                 "favoritePetNames_not_contains_nocase",
+                "favoritePetNames_overlaps",
+                "favoritePetNames_empty",
                 "pets",
                 "pets_not",
                 "pets_contains",
                 "pets_contains_nocase",
                 "pets_not_contains",
                 "pets_not_contains_nocase",
+                "pets_overlaps",
+                "pets_empty",
                 "favoriteFurType",
                 "favoriteFurType_not",
                 "favoriteFurType_in",
                 "favoriteFurType_not_in",
                 "favoritePet",
+                "favoritePet_nocase",
                 "favoritePet_not",
+                "favoritePet_not_nocase",
                 "favoritePet_gt",
                 "favoritePet_lt",
                 "favoritePet_gte",
                 "favoritePet_lte",
                 "favoritePet_in",
+                "favoritePet_in_nocase",
                 "favoritePet_not_in",
+                "favoritePet_not_in_nocase",
                 "favoritePet_contains",
                 "favoritePet_contains_nocase",
                 "favoritePet_not_contains",
@@ -1170,4 +1290,48 @@ type Gravatar @entity {
         }
         .expect("\"metadata\" field is missing on Query type");
     }
+
+    #[test]
+    fn api_schema_contains_by_key_query_field_for_composite_id() {
+        const SCHEMA: &str = r#"
+type Pair @entity {
+  id: Bytes!
+}
+type Swap @entity(immutable: true) @compositeId(fields: ["pair", "blockNumber"]) {
+  id: ID!
+  pair: Pair!
+  blockNumber: BigInt!
+}
+"#;
+        let input_schema = parse_schema(SCHEMA).expect("Failed to parse input schema");
+        let schema = api_schema(&input_schema).expect("Failed to derive API schema");
+
+        let query_type = schema
+            .get_named_type("Query")
+            .expect("Query type is missing in derived API schema");
+
+        let by_key_field = match query_type {
+            TypeDefinition::Object(t) => ast::get_field(t, &String::from("swapByKey")),
+            _ => None,
+        }
+        .expect("\"swapByKey\" field is missing on Query type");
+
+        assert_eq!(
+            by_key_field
+                .arguments
+                .iter()
+                .map(|input_value| input_value.value_type.to_string())
+                .collect::<Vec<String>>(),
+            [
+                "Bytes!",
+                "BigInt!",
+                "Block_height",
+                "_SubgraphErrorPolicy_!"
+            ]
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<String>>()
+        );
+        assert_eq!(by_key_field.field_type, Type::NamedType("Swap".to_owned()));
+    }
 }
@@ -14,12 +14,15 @@ use crate::query::ast as qast;
 
 pub(crate) enum FilterOp {
     Not,
+    NotNoCase,
     GreaterThan,
     LessThan,
     GreaterOrEqual,
     LessOrEqual,
     In,
+    InNoCase,
     NotIn,
+    NotInNoCase,
     Contains,
     ContainsNoCase,
     NotContains,
@@ -32,7 +35,11 @@ pub(crate) enum FilterOp {
     EndsWithNoCase,
     NotEndsWith,
     NotEndsWithNoCase,
+    Overlaps,
+    Empty,
+    Matches,
     Equal,
+    EqualNoCase,
 }
 
 /// Split a "name_eq" style name into an attribute ("name") and a filter op (`Equal`).
@@ -65,6 +72,13 @@ pub(crate) fn parse_field_as_filter(key: &str) -> (String, FilterOp) {
         }
         k if k.ends_with("_ends_with") => ("_ends_with", FilterOp::EndsWith),
         k if k.ends_with("_ends_with_nocase") => ("_ends_with_nocase", FilterOp::EndsWithNoCase),
+        k if k.ends_with("_overlaps") => ("_overlaps", FilterOp::Overlaps),
+        k if k.ends_with("_empty") => ("_empty", FilterOp::Empty),
+        k if k.ends_with("_matches") => ("_matches", FilterOp::Matches),
+        k if k.ends_with("_not_in_nocase") => ("_not_in_nocase", FilterOp::NotInNoCase),
+        k if k.ends_with("_in_nocase") => ("_in_nocase", FilterOp::InNoCase),
+        k if k.ends_with("_not_nocase") => ("_not_nocase", FilterOp::NotNoCase),
+        k if k.ends_with("_nocase") => ("_nocase", FilterOp::EqualNoCase),
         _ => ("", FilterOp::Equal),
     };
 
@@ -3,6 +3,7 @@
 
 use anyhow::{anyhow, Error};
 use graph::constraint_violation;
+use graph::data::query::QueryExplanation;
 use graph::data::value::Object;
 use graph::prelude::{r, CacheWeight};
 use graph::slog::warn;
@@ -10,6 +11,7 @@ use graph::util::cache_weight;
 use lazy_static::lazy_static;
 use std::collections::BTreeMap;
 use std::rc::Rc;
+use std::sync::Mutex;
 use std::time::Instant;
 
 use graph::{components::store::EntityType, data::graphql::*};
@@ -631,13 +633,16 @@ fn execute_field(
         ctx.max_skip,
         ctx.query.query_id.clone(),
         selected_attrs,
+        ctx.query.explain.then(|| &ctx.explanations),
     )
     .map_err(|e| vec![e])
 }
 
 /// Query child entities for `parents` from the store. The `join` indicates
 /// in which child field to look for the parent's id/join field. When
-/// `is_single` is `true`, there is at most one child per parent.
+/// `is_single` is `true`, there is at most one child per parent. If
+/// `explanations` is given, the query is also explained and the result
+/// added to it
 fn fetch(
     logger: Logger,
     store: &(impl QueryStore + ?Sized),
@@ -651,6 +656,7 @@ fn fetch(
     max_skip: u32,
     query_id: String,
     selected_attrs: SelectedAttributes,
+    explanations: Option<&Mutex<Vec<QueryExplanation>>>,
 ) -> Result<Vec<Node>, QueryExecutionError> {
     let mut query = build_query(
         join.child_type,
@@ -686,6 +692,11 @@ fn fetch(
         }
         query.collection = EntityCollection::Window(windows);
     }
+    if let Some(explanations) = explanations {
+        let mut explanation = store.explain_query(query.clone())?;
+        explanation.field = field.response_key().to_owned();
+        explanations.lock().unwrap().push(explanation);
+    }
     store
         .find_query_values(query)
         .map(|entities| entities.into_iter().map(|entity| entity.into()).collect())
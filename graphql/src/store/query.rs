@@ -1,6 +1,7 @@
 use std::collections::{BTreeMap, BTreeSet, HashSet, VecDeque};
 use std::mem::discriminant;
 
+use graph::data::graphql::{ObjectTypeExt as _, TypeExt as _};
 use graph::data::value::Object;
 use graph::prelude::*;
 use graph::{components::store::EntityType, data::graphql::ObjectOrInterface};
@@ -116,7 +117,7 @@ fn build_filter(
         Some(r::Value::Null) => Ok(None),
         None => match field.argument_value("text") {
             Some(r::Value::Object(filter)) => build_fulltext_filter_from_object(filter),
-            None => Ok(None),
+            None => build_composite_id_filter(entity, field),
             _ => Err(QueryExecutionError::InvalidFilterError),
         },
         _ => Err(QueryExecutionError::InvalidFilterError),
@@ -141,6 +142,40 @@ fn build_fulltext_filter_from_object(
     )
 }
 
+/// Turns the arguments of a `<type>ByKey` field into a filter on the
+/// fields of the type's composite id. Returns `None` for fields that do not
+/// have an argument for each of these fields
+fn build_composite_id_filter(
+    entity: ObjectOrInterface,
+    field: &a::Field,
+) -> Result<Option<EntityFilter>, QueryExecutionError> {
+    let key = match entity {
+        ObjectOrInterface::Object(object) => match object.composite_id() {
+            Some(key) if !key.is_empty() => key,
+            _ => return Ok(None),
+        },
+        ObjectOrInterface::Interface(_) => return Ok(None),
+    };
+
+    let mut filters = Vec::with_capacity(key.len());
+    for name in key {
+        let value = match field.argument_value(name) {
+            Some(value) => value,
+            None => return Ok(None),
+        };
+        let ty = &sast::get_field(entity, name)
+            .ok_or_else(|| {
+                QueryExecutionError::EntityFieldError(entity.name().to_owned(), name.to_owned())
+            })?
+            .field_type;
+        filters.push(EntityFilter::Equal(
+            name.to_owned(),
+            Value::from_query_value(value, ty)?,
+        ));
+    }
+    Ok(Some(EntityFilter::And(filters)))
+}
+
 fn parse_change_block_filter(value: &r::Value) -> Result<BlockNumber, QueryExecutionError> {
     match value {
         r::Value::Object(object) => i32::try_from_value(
@@ -173,7 +208,17 @@ fn build_filter_from_object(
 
                 use self::sast::FilterOp::*;
 
-                let (field_name, op) = sast::parse_field_as_filter(key);
+                let (field_name, op) = match sast::parse_field_as_filter(key) {
+                    // An attribute whose name ends in something that looks
+                    // like a filter suffix, like `is_empty`
+                    (field_name, _)
+                        if sast::get_field(entity, &field_name).is_none()
+                            && sast::get_field(entity, key).is_some() =>
+                    {
+                        (key.clone(), Equal)
+                    }
+                    (field_name, op) => (field_name, op),
+                };
 
                 let field = sast::get_field(entity, &field_name).ok_or_else(|| {
                     QueryExecutionError::EntityFieldError(
@@ -183,16 +228,46 @@ fn build_filter_from_object(
                 })?;
 
                 let ty = &field.field_type;
+                if matches!(op, Overlaps | Empty) && !ty.is_list() {
+                    return Err(QueryExecutionError::InvalidFilterError);
+                }
+                if let Matches = op {
+                    if !ENV_VARS.graphql.enable_regex_filter {
+                        return Err(QueryExecutionError::NotSupported(format!(
+                            "the filter `{}` is not supported since `_matches` filters are disabled",
+                            key
+                        )));
+                    }
+                    if !matches!(sast::get_field_value_type(ty), Ok(ValueType::String))
+                        || ty.is_list()
+                    {
+                        return Err(QueryExecutionError::InvalidFilterError);
+                    }
+                }
+                if let Empty = op {
+                    return match value {
+                        r::Value::Boolean(empty) => Ok(EntityFilter::Empty(field_name, *empty)),
+                        _ => Err(QueryExecutionError::InvalidFilterError),
+                    };
+                }
                 let store_value = Value::from_query_value(value, ty)?;
 
                 Ok(match op {
                     Not => EntityFilter::Not(field_name, store_value),
+                    NotNoCase => EntityFilter::NotNoCase(field_name, store_value),
                     GreaterThan => EntityFilter::GreaterThan(field_name, store_value),
                     LessThan => EntityFilter::LessThan(field_name, store_value),
                     GreaterOrEqual => EntityFilter::GreaterOrEqual(field_name, store_value),
                     LessOrEqual => EntityFilter::LessOrEqual(field_name, store_value),
                     In => EntityFilter::In(field_name, list_values(store_value, "_in")?),
+                    InNoCase => {
+                        EntityFilter::InNoCase(field_name, list_values(store_value, "_in_nocase")?)
+                    }
                     NotIn => EntityFilter::NotIn(field_name, list_values(store_value, "_not_in")?),
+                    NotInNoCase => EntityFilter::NotInNoCase(
+                        field_name,
+                        list_values(store_value, "_not_in_nocase")?,
+                    ),
                     Contains => EntityFilter::Contains(field_name, store_value),
                     ContainsNoCase => EntityFilter::ContainsNoCase(field_name, store_value),
                     NotContains => EntityFilter::NotContains(field_name, store_value),
@@ -207,7 +282,11 @@ fn build_filter_from_object(
                     EndsWithNoCase => EntityFilter::EndsWithNoCase(field_name, store_value),
                     NotEndsWith => EntityFilter::NotEndsWith(field_name, store_value),
                     NotEndsWithNoCase => EntityFilter::NotEndsWithNoCase(field_name, store_value),
+                    Overlaps => EntityFilter::Overlaps(field_name, store_value),
+                    Empty => unreachable!("`_empty` filters were handled above"),
+                    Matches => EntityFilter::Matches(field_name, store_value),
                     Equal => EntityFilter::Equal(field_name, store_value),
+                    EqualNoCase => EntityFilter::EqualNoCase(field_name, store_value),
                 })
             })
             .collect::<Result<Vec<EntityFilter>, QueryExecutionError>>()?
@@ -371,8 +450,8 @@ mod tests {
         components::store::EntityType,
         data::value::Object,
         prelude::{
-            r, AttributeNames, EntityCollection, EntityFilter, EntityRange, Value, ValueType,
-            BLOCK_NUMBER_MAX,
+            r, AttributeNames, BigInt, EntityCollection, EntityFilter, EntityRange, Value,
+            ValueType, BLOCK_NUMBER_MAX,
         },
         prelude::{
             s::{self, Directive, Field, InputValue, ObjectType, Type, Value as SchemaValue},
@@ -753,6 +832,73 @@ mod tests {
         )
     }
 
+    #[test]
+    fn build_query_rejects_disabled_matches_filter() {
+        let query_field = default_field_with(
+            "where",
+            r::Value::Object(Object::from_iter(vec![(
+                "name_matches".to_string(),
+                r::Value::String("^h.*o$".to_string()),
+            )])),
+        );
+        let res = build_query(
+            &ObjectType {
+                fields: vec![field("name", Type::NamedType("String".to_owned()))],
+                ..default_object()
+            },
+            BLOCK_NUMBER_MAX,
+            &query_field,
+            &BTreeMap::new(),
+            std::u32::MAX,
+            std::u32::MAX,
+            Default::default(),
+        );
+        assert!(matches!(res, Err(QueryExecutionError::NotSupported(_))));
+    }
+
+    #[test]
+    fn build_query_yields_composite_id_filter() {
+        let mut object = ObjectType {
+            fields: vec![
+                field("pair", Type::NamedType("String".to_owned())),
+                field("blockNumber", Type::NamedType("BigInt".to_owned())),
+            ],
+            ..default_object()
+        };
+        object.directives.push(Directive {
+            name: "compositeId".to_string(),
+            position: Pos::default(),
+            arguments: vec![(
+                "fields".to_string(),
+                SchemaValue::List(vec![
+                    SchemaValue::String("pair".to_string()),
+                    SchemaValue::String("blockNumber".to_string()),
+                ]),
+            )],
+        });
+        let query_field = default_field_with_vec(vec![
+            ("pair", r::Value::String("0xab".to_string())),
+            ("blockNumber", r::Value::String("17".to_string())),
+        ]);
+        assert_eq!(
+            build_query(
+                &object,
+                BLOCK_NUMBER_MAX,
+                &query_field,
+                &BTreeMap::new(),
+                std::u32::MAX,
+                std::u32::MAX,
+                Default::default()
+            )
+            .unwrap()
+            .filter,
+            Some(EntityFilter::And(vec![
+                EntityFilter::Equal("pair".to_string(), Value::String("0xab".to_string())),
+                EntityFilter::Equal("blockNumber".to_string(), Value::BigInt(BigInt::from(17)),),
+            ]))
+        )
+    }
+
     #[test]
     fn build_query_yields_block_change_gte_filter() {
         let query_field = default_field_with(
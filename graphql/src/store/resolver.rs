@@ -158,6 +158,58 @@ impl StoreResolver {
                 .await
                 .map_err(Into::into)
                 .and_then(|ptr| check_ptr(subgraph, ptr, number)),
+            BlockConstraint::Timestamp(timestamp) => {
+                let block = store.block_at_time(timestamp)?.ok_or_else(|| {
+                    QueryExecutionError::ValueParseError(
+                        "block.timestamp_lte".to_owned(),
+                        "no block at or before that time found".to_owned(),
+                    )
+                })?;
+                let ptr = store.block_ptr().await?;
+                check_ptr(subgraph, ptr, block.number)?;
+                // Close to the chain head, the block might be on a fork
+                // that is not the main chain; like for
+                // `BlockConstraint::Number`, we therefore only use its
+                // number
+                // See 7a7b9708-adb7-4fc2-acec-88680cb07ec1
+                Ok(BlockPtr::from((
+                    web3::types::H256::zero(),
+                    block.number as u64,
+                )))
+            }
+            BlockConstraint::Final => {
+                let ptr = store
+                    .block_ptr()
+                    .await?
+                    .expect("we should have already checked that the subgraph exists");
+                let head = store.chain_head_number()?.ok_or_else(|| {
+                    QueryExecutionError::ValueParseError(
+                        "block.final".to_owned(),
+                        format!("the chain of subgraph {} has no head block yet", subgraph),
+                    )
+                })?;
+                let final_number = ENV_VARS.final_block(head);
+                if final_number < 0 {
+                    return Err(QueryExecutionError::ValueParseError(
+                        "block.final".to_owned(),
+                        format!(
+                            "no block of the chain of subgraph {} is final yet",
+                            subgraph
+                        ),
+                    ));
+                }
+                if ptr.number <= final_number {
+                    Ok(ptr)
+                } else {
+                    // Like for `BlockConstraint::Number`, we do not know the
+                    // hash of the final block
+                    // See 7a7b9708-adb7-4fc2-acec-88680cb07ec1
+                    Ok(BlockPtr::from((
+                        web3::types::H256::zero(),
+                        final_number as u64,
+                    )))
+                }
+            }
             BlockConstraint::Latest => {
                 store.block_ptr().await.map_err(Into::into).map(|ptr| {
                     ptr.expect("we should have already checked that the subgraph exists")
@@ -171,10 +223,10 @@ impl StoreResolver {
         prefetched_object: Option<r::Value>,
         object_type: &ObjectOrInterface<'_>,
     ) -> Result<(Option<r::Value>, Option<r::Value>), QueryExecutionError> {
-        // Pretend that the whole `_meta` field was loaded by prefetch. Eager
-        // loading this is ok until we add more information to this field
-        // that would force us to query the database; when that happens, we
-        // need to switch to loading on demand
+        // Pretend that the whole `_meta` field was loaded by prefetch. The
+        // parent hash and the chain head need a trip to the database each,
+        // but they are cheap lookups by primary key; if `_meta` ever needs
+        // anything more expensive, we need to switch to loading on demand
         if object_type.is_meta() {
             let hash = self
                 .block_ptr
@@ -196,10 +248,27 @@ impl StoreResolver {
                 .as_ref()
                 .map(|ptr| r::Value::Int((ptr.number as i32).into()))
                 .unwrap_or(r::Value::Null);
+            let parent_hash = match &hash {
+                r::Value::Null => None,
+                _ => self
+                    .block_ptr
+                    .as_ref()
+                    .map(|ptr| self.store.block_parent_hash(ptr.hash_as_h256()))
+                    .transpose()?
+                    .flatten(),
+            }
+            .map(|parent| r::Value::String(format!("0x{:x}", parent)))
+            .unwrap_or(r::Value::Null);
+            let is_final = match (&self.block_ptr, self.store.chain_head_number()?) {
+                (Some(ptr), Some(head)) => ENV_VARS.is_final(ptr.number, head),
+                _ => false,
+            };
             let mut map = BTreeMap::new();
             let block = object! {
                 hash: hash,
                 number: number,
+                parentHash: parent_hash,
+                isFinal: is_final,
                 __typename: BLOCK_FIELD_TYPE
             };
             map.insert("prefetch:block".to_string(), r::Value::List(vec![block]));
@@ -225,7 +294,7 @@ impl StoreResolver {
 impl Resolver for StoreResolver {
     const CACHEABLE: bool = true;
 
-    async fn query_permit(&self) -> tokio::sync::OwnedSemaphorePermit {
+    async fn query_permit(&self) -> Result<QueryPermit, QueryExecutionError> {
         self.store.query_permit().await
     }
 
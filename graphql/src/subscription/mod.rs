@@ -2,6 +2,7 @@ use std::result::Result;
 use std::time::{Duration, Instant};
 
 use graph::components::store::UnitStream;
+use graph::data::query::QueryLimits;
 use graph::{components::store::SubscriptionManager, prelude::*};
 
 use crate::runner::ResultSizeMetrics;
@@ -50,8 +51,12 @@ pub fn execute_subscription(
         schema,
         None,
         subscription.query,
-        options.max_complexity,
-        options.max_depth,
+        QueryLimits {
+            max_complexity: options.max_complexity,
+            max_depth: options.max_depth,
+            max_first: options.max_first,
+            max_skip: options.max_skip,
+        },
     )?;
     execute_prepared_subscription(query, options)
 }
@@ -96,6 +101,7 @@ fn create_source_event_stream(
         max_first: options.max_first,
         max_skip: options.max_skip,
         cache_status: Default::default(),
+        explanations: Default::default(),
     };
 
     let subscription_type = ctx
@@ -209,6 +215,7 @@ async fn execute_subscription_event(
         max_first,
         max_skip,
         cache_status: Default::default(),
+        explanations: Default::default(),
     });
 
     let subscription_type = match ctx.query.schema.subscription_type.as_ref() {
@@ -216,11 +223,17 @@ async fn execute_subscription_event(
         None => return Arc::new(QueryExecutionError::NoRootSubscriptionObjectType.into()),
     };
 
-    execute_root_selection_set(
+    let mut result = execute_root_selection_set(
         ctx.cheap_clone(),
         ctx.query.selection_set.cheap_clone(),
         subscription_type.into(),
-        block_ptr,
+        block_ptr.clone(),
     )
-    .await
+    .await;
+
+    // Subscriptions are never cached, so nothing else holds on to the result
+    if let Some(result) = Arc::get_mut(&mut result) {
+        result.block = block_ptr;
+    }
+    result
 }
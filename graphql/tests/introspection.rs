@@ -4,9 +4,10 @@ extern crate pretty_assertions;
 use std::sync::Arc;
 
 use graph::data::graphql::{object, object_value, ObjectOrInterface};
+use graph::data::query::QueryLimits;
 use graph::prelude::{
     async_trait, o, r, s, slog, tokio, ApiSchema, DeploymentHash, Logger, Query,
-    QueryExecutionError, QueryResult, Schema,
+    QueryExecutionError, QueryPermit, QueryResult, Schema,
 };
 use graph_graphql::prelude::{
     a, api_schema, execute_query, ExecutionContext, Query as PreparedQuery, QueryExecutionOptions,
@@ -50,11 +51,12 @@ impl Resolver for MockResolver {
         Ok(r::Value::Null)
     }
 
-    async fn query_permit(&self) -> tokio::sync::OwnedSemaphorePermit {
-        Arc::new(tokio::sync::Semaphore::new(1))
+    async fn query_permit(&self) -> Result<QueryPermit, QueryExecutionError> {
+        Ok(Arc::new(tokio::sync::Semaphore::new(1))
             .acquire_owned()
             .await
             .unwrap()
+            .into())
     }
 }
 
@@ -571,7 +573,16 @@ async fn introspection_query(schema: Schema, query: &str) -> QueryResult {
     };
 
     let schema = Arc::new(ApiSchema::from_api_schema(schema).unwrap());
-    let result = match PreparedQuery::new(&logger, schema, None, query, None, 100) {
+    let result = match PreparedQuery::new(
+        &logger,
+        schema,
+        None,
+        query,
+        QueryLimits {
+            max_depth: 100,
+            ..QueryLimits::unlimited()
+        },
+    ) {
         Ok(query) => Ok(Arc::try_unwrap(execute_query(query, None, None, options).await).unwrap()),
         Err(e) => Err(e),
     };
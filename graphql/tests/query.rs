@@ -25,7 +25,7 @@ use graph::{
         EntityKey, EntityOperation, FutureExtension, GraphQlRunner as _, Logger, NodeId, Query,
         QueryError, QueryExecutionError, QueryResult, QueryStoreManager, QueryVariables, Schema,
         SubgraphManifest, SubgraphName, SubgraphStore, SubgraphVersionSwitchingMode, Subscription,
-        SubscriptionError, Value,
+        SubscriptionError, Value, ENV_VARS,
     },
     semver::Version,
 };
@@ -65,6 +65,7 @@ async fn setup_with_features(
         schema: schema.clone(),
         data_sources: vec![],
         graft: None,
+        indexing_delay: None,
         templates: vec![],
         chain: PhantomData,
     };
@@ -1090,6 +1091,53 @@ fn skip_is_nullable() {
     })
 }
 
+#[test]
+fn first_and_skip_are_checked_against_limits() {
+    run_test_sequentially(|store| async move {
+        let deployment = setup(store.as_ref()).await;
+        let runner = Arc::new(GraphQlRunner::new(
+            &*LOGGER,
+            STORE.clone(),
+            SUBSCRIPTION_MANAGER.clone(),
+            LOAD_MANAGER.clone(),
+            METRICS_REGISTRY.clone(),
+        ));
+        let query = Query::new(
+            graphql_parser::parse_query(
+                "query { musicians(first: 20, skip: 30, orderBy: id) { name } }",
+            )
+            .unwrap()
+            .into_static(),
+            None,
+        );
+
+        let result = runner
+            .run_query_with_complexity(
+                query,
+                QueryTarget::Deployment(deployment.hash.clone()),
+                None,
+                None,
+                Some(10),
+                Some(10),
+            )
+            .await
+            .first()
+            .unwrap()
+            .duplicate();
+
+        let errors = result.to_result().unwrap_err();
+        assert_eq!(2, errors.len());
+        assert_eq!(
+            "The `first` argument must be between 0 and 10, but is 20",
+            errors[0].to_string()
+        );
+        assert_eq!(
+            "The `skip` argument must be between 0 and 10, but is 30",
+            errors[1].to_string()
+        );
+    })
+}
+
 #[test]
 fn first_is_nullable() {
     run_test_sequentially(|store| async move {
@@ -1719,9 +1767,26 @@ fn query_at_block() {
             format!("hash : \"0x{}\"", block.hash)
         }
 
+        async fn musicians_at_time(
+            deployment: &DeploymentLocator,
+            timestamp: u64,
+            expected: Result<Vec<&str>, &str>,
+            qid: &str,
+        ) {
+            let query = "query by_time($time: Int!) { \
+                           musicians(block: { timestamp_lte: $time }) { id } \
+                         }";
+            let var = Some(("time", r::Value::Int(timestamp as i64)));
+
+            check_musicians_at(&deployment.hash, query, var, expected, qid).await;
+        }
+
         const BLOCK_NOT_INDEXED: &str = "subgraph graphqlTestsQuery has only indexed \
          up to block number 1 and data for block number 7000 is therefore not yet available";
+        const BLOCK_TWO_NOT_INDEXED: &str = "subgraph graphqlTestsQuery has only indexed \
+         up to block number 1 and data for block number 2 is therefore not yet available";
         const BLOCK_HASH_NOT_FOUND: &str = "no block with that hash found";
+        const BLOCK_TIME_NOT_FOUND: &str = "no block at or before that time found";
 
         let deployment = setup(store.as_ref()).await;
         musicians_at(&deployment, "number: 7000", Err(BLOCK_NOT_INDEXED), "n7000").await;
@@ -1819,9 +1884,26 @@ fn query_at_block_with_vars() {
             check_musicians_at(&deployment.hash, query, var, expected, qid).await;
         }
 
+        async fn musicians_at_time(
+            deployment: &DeploymentLocator,
+            timestamp: u64,
+            expected: Result<Vec<&str>, &str>,
+            qid: &str,
+        ) {
+            let query = "query by_time($time: Int!) { \
+                           musicians(block: { timestamp_lte: $time }) { id } \
+                         }";
+            let var = Some(("time", r::Value::Int(timestamp as i64)));
+
+            check_musicians_at(&deployment.hash, query, var, expected, qid).await;
+        }
+
         const BLOCK_NOT_INDEXED: &str = "subgraph graphqlTestsQuery has only indexed \
          up to block number 1 and data for block number 7000 is therefore not yet available";
+        const BLOCK_TWO_NOT_INDEXED: &str = "subgraph graphqlTestsQuery has only indexed \
+         up to block number 1 and data for block number 2 is therefore not yet available";
         const BLOCK_HASH_NOT_FOUND: &str = "no block with that hash found";
+        const BLOCK_TIME_NOT_FOUND: &str = "no block at or before that time found";
 
         let deployment = setup(store.as_ref()).await;
         musicians_at_nr(&deployment, 7000, Err(BLOCK_NOT_INDEXED), "n7000").await;
@@ -1848,6 +1930,36 @@ fn query_at_block_with_vars() {
         )
         .await;
         musicians_at_hash(&deployment, &BLOCK_THREE, Err(BLOCK_HASH_NOT_FOUND), "h3").await;
+
+        let time = |block: &FakeBlock| block.block_timestamp();
+        musicians_at_time(
+            &deployment,
+            time(&GENESIS_BLOCK) - 1,
+            Err(BLOCK_TIME_NOT_FOUND),
+            "t-1",
+        )
+        .await;
+        musicians_at_time(
+            &deployment,
+            time(&GENESIS_BLOCK),
+            Ok(vec!["m1", "m2"]),
+            "t0",
+        )
+        .await;
+        musicians_at_time(
+            &deployment,
+            time(&BLOCK_ONE) + 1,
+            Ok(vec!["m1", "m2", "m3", "m4"]),
+            "t1",
+        )
+        .await;
+        musicians_at_time(
+            &deployment,
+            time(&BLOCK_TWO),
+            Err(BLOCK_TWO_NOT_INDEXED),
+            "t2",
+        )
+        .await;
     })
 }
 
@@ -1973,6 +2085,25 @@ fn can_query_meta() {
         };
         assert_eq!(extract_data!(result), Some(exp));
 
+        // the parent of block 1 is block 0, and block 1 is not final since
+        // it is much closer to the chain head than the reorg threshold
+        let query = "query { _meta { block { number parentHash isFinal } } }";
+        let query = graphql_parser::parse_query(query)
+            .expect("invalid test query")
+            .into_static();
+
+        let result = execute_query_document(&deployment.hash, query).await;
+        let exp = object! {
+            _meta: object! {
+                block: object! {
+                    number: 1,
+                    parentHash: "0xbd34884280958002c51d3f7b5f853e6febeba33de0f40d15b0363006533c924f",
+                    isFinal: false
+                },
+            },
+        };
+        assert_eq!(extract_data!(result), Some(exp));
+
         // metadata for block 2, which is beyond what the subgraph has indexed
         let query = "query { _meta(block: { number: 2 }) { deployment block { hash number } } }";
         let query = graphql_parser::parse_query(query)
@@ -1984,6 +2115,38 @@ fn can_query_meta() {
     })
 }
 
+#[test]
+fn query_final_block() {
+    run_test_sequentially(|store| async move {
+        // The test chain is much shorter than the reorg threshold, and none
+        // of its blocks are final
+        let query = "query { _meta(block: { final: true }) { block { number } } }";
+        let query = graphql_parser::parse_query(query)
+            .expect("invalid test query")
+            .into_static();
+
+        let deployment = setup(store.as_ref()).await;
+        let result = execute_query_document(&deployment.hash, query).await;
+        assert!(result.has_errors());
+
+        // `final: false` is the same as asking for the latest block
+        let query = "query { _meta(block: { final: false }) { block { number } } }";
+        let query = graphql_parser::parse_query(query)
+            .expect("invalid test query")
+            .into_static();
+
+        let result = execute_query_document(&deployment.hash, query).await;
+        let exp = object! {
+            _meta: object! {
+                block: object! {
+                    number: 1
+                },
+            },
+        };
+        assert_eq!(extract_data!(result), Some(exp));
+    })
+}
+
 #[test]
 fn non_fatal_errors() {
     use serde_json::json;
@@ -2098,3 +2261,165 @@ fn can_query_root_typename() {
         assert_eq!(extract_data!(result), Some(exp));
     })
 }
+
+/// Responses for a block given by hash can be cached for a long time, but
+/// only when the query names the deployment; the deployment a subgraph
+/// name refers to changes whenever a new version is deployed
+#[test]
+fn cache_control_depends_on_query_target() {
+    run_test_sequentially(|store| async move {
+        let deployment = setup(store.as_ref()).await;
+        let runner = Arc::new(GraphQlRunner::new(
+            &*LOGGER,
+            STORE.clone(),
+            SUBSCRIPTION_MANAGER.clone(),
+            LOAD_MANAGER.clone(),
+            METRICS_REGISTRY.clone(),
+        ));
+        let query = format!(
+            "query {{ musicians(block: {{ hash: \"{}\" }}) {{ id }} }}",
+            GENESIS_PTR.hash_hex()
+        );
+        let cache_control = |target: QueryTarget| {
+            let runner = runner.clone();
+            let query = graphql_parser::parse_query(&query).unwrap().into_static();
+            async move {
+                runner
+                    .run_query(Query::new(query, None), target)
+                    .await
+                    .cache_control()
+            }
+        };
+
+        let by_deployment = cache_control(QueryTarget::Deployment(deployment.hash.clone())).await;
+        let by_name =
+            cache_control(QueryTarget::Name(SubgraphName::new("test/query").unwrap())).await;
+        assert_eq!(
+            Some(format!(
+                "public, max-age={}",
+                ENV_VARS.graphql.pinned_query_max_age.as_secs()
+            )),
+            by_deployment
+        );
+        assert_eq!(
+            Some(format!(
+                "public, max-age={}",
+                ENV_VARS.graphql.head_query_max_age.as_secs()
+            )),
+            by_name
+        );
+    })
+}
+
+/// Entities with a composite id can be looked up by their key, and get
+/// an `id` formed from it even though it is not stored. Lookups by that
+/// `id`, also through references, find them by their key
+#[test]
+fn can_query_by_composite_id() {
+    const SCHEMA: &str = "
+        type Pair @entity {
+            id: Bytes!
+            name: String!
+            lastSwap: Swap
+            topSwaps: [Swap!]!
+            swaps: [Swap!]! @derivedFrom(field: \"pair\")
+        }
+
+        type Swap @entity(immutable: true) @compositeId(fields: [\"pair\", \"block\"]) {
+            pair: Pair!
+            block: Int!
+            amount: Int!
+        }";
+
+    fn swap(pair: &str, block: i32, amount: i32) -> EntityOperation {
+        let id = format!("{}-{}", pair, block);
+        EntityOperation::Set {
+            key: EntityKey::data(
+                DeploymentHash::new("graphqlTestsCompositeId").unwrap(),
+                "Swap".to_owned(),
+                id.clone(),
+            ),
+            data: Entity::from(vec![
+                ("id", Value::from(id)),
+                ("pair", Value::from(pair)),
+                ("block", Value::from(block)),
+                ("amount", Value::from(amount)),
+            ]),
+        }
+    }
+
+    run_test_sequentially(|store| async move {
+        use test_store::block_store::{self, BLOCK_ONE, BLOCK_TWO, GENESIS_BLOCK};
+
+        let chain = vec![&*GENESIS_BLOCK, &*BLOCK_ONE, &*BLOCK_TWO];
+        block_store::set_chain(chain, NETWORK_NAME);
+        test_store::remove_subgraphs();
+
+        let id = DeploymentHash::new("graphqlTestsCompositeId").unwrap();
+        let deployment = test_store::create_test_subgraph(&id, SCHEMA).await;
+        let pair = EntityOperation::Set {
+            key: EntityKey::data(id.clone(), "Pair".to_owned(), "0x01".to_owned()),
+            data: Entity::from(vec![
+                ("id", Value::from("0x01")),
+                ("name", Value::from("First")),
+                ("lastSwap", Value::from("0x01-2")),
+                (
+                    "topSwaps",
+                    Value::List(vec![
+                        Value::from("0x01-2"),
+                        Value::from("bogus"),
+                        Value::from("0x01-1"),
+                    ]),
+                ),
+            ]),
+        };
+        test_store::transact_and_wait(
+            &store.subgraph_store(),
+            &deployment,
+            GENESIS_PTR.clone(),
+            vec![pair, swap("0x01", 1, 10), swap("0x02", 1, 30)],
+        )
+        .await
+        .unwrap();
+        test_store::transact_and_wait(
+            &store.subgraph_store(),
+            &deployment,
+            test_store::BLOCK_ONE.clone(),
+            vec![swap("0x01", 2, 20)],
+        )
+        .await
+        .unwrap();
+
+        let query = graphql_parser::parse_query(
+            "
+            query {
+                first: swapByKey(pair: \"0x01\", block: 2) { id block amount pair { name } }
+                missing: swapByKey(pair: \"0x02\", block: 2) { id }
+                byId: swap(id: \"0x02-1\") { block amount }
+                pair(id: \"0x01\") {
+                    swaps(orderBy: block) { id amount }
+                    lastSwap { amount }
+                    topSwaps(orderBy: amount, orderDirection: desc) { id }
+                }
+            }",
+        )
+        .expect("invalid test query")
+        .into_static();
+        let result = execute_query_document(&deployment.hash, query).await;
+
+        let exp = object! {
+            first: object! { id: "0x01-2", block: 2, amount: 20, pair: object! { name: "First" } },
+            missing: r::Value::Null,
+            byId: object! { block: 1, amount: 30 },
+            pair: object! {
+                swaps: vec![
+                    object! { id: "0x01-1", amount: 10 },
+                    object! { id: "0x01-2", amount: 20 },
+                ],
+                lastSwap: object! { amount: 20 },
+                topSwaps: vec![object! { id: "0x01-2" }, object! { id: "0x01-1" }],
+            },
+        };
+        assert_eq!(extract_data!(result), Some(exp));
+    })
+}
@@ -1,4 +1,6 @@
-use std::{collections::HashMap, env, num::ParseIntError, sync::Arc, time::Duration};
+use std::{
+    collections::HashMap, env, num::ParseIntError, path::PathBuf, sync::Arc, time::Duration,
+};
 
 use config::PoolSize;
 use git_testament::{git_testament, render_testament};
@@ -9,8 +11,9 @@ use lazy_static::lazy_static;
 use structopt::StructOpt;
 
 use graph::{
+    components::store::ExportFormat,
     log::logger,
-    prelude::{info, o, slog, tokio, Logger, NodeId, ENV_VARS},
+    prelude::{anyhow, info, o, slog, tokio, BlockNumber, Logger, NodeId, ENV_VARS},
     url::Url,
 };
 use graph_node::{
@@ -54,9 +57,9 @@ pub struct Opt {
         long,
         short,
         env = "GRAPH_NODE_CONFIG",
-        help = "the name of the configuration file\n"
+        help = "the name of the configuration file. Required by all commands but `test`\n"
     )]
-    pub config: String,
+    pub config: Option<String>,
     #[structopt(
         long,
         default_value = "default",
@@ -131,6 +134,24 @@ pub enum Command {
         /// The name of the subgraph to create
         name: String,
     },
+    /// Attach a deprecation notice to a subgraph name, or remove it
+    ///
+    /// Responses to queries through the name carry the notice in their
+    /// `warnings` extension. Other index nodes report a new notice within
+    /// a minute
+    Deprecate {
+        /// Remove the notice instead of setting it
+        #[structopt(long, conflicts_with_all = &["removal-date", "message"])]
+        clear: bool,
+        /// The day after which the name may stop working, as YYYY-MM-DD
+        #[structopt(long, short, required_unless = "clear")]
+        removal_date: Option<String>,
+        /// The name of the subgraph
+        name: String,
+        /// Why the subgraph is deprecated and what to use instead
+        #[structopt(required_unless = "clear")]
+        message: Option<String>,
+    },
     /// Assign or reassign a deployment
     Reassign {
         /// The deployment (see `help info`)
@@ -143,6 +164,24 @@ pub enum Command {
         /// The deployment (see `help info`)
         deployment: DeploymentSearch,
     },
+    /// Even out the load of deployments across index nodes
+    ///
+    /// Deployments are moved away from index nodes whose load, as reported
+    /// by the index nodes themselves, is more than `threshold` above the
+    /// average, but only to nodes that the deployment rules allow
+    Rebalance {
+        /// Only print which deployments would be moved
+        #[structopt(long, short)]
+        dry_run: bool,
+        /// How far above the average load a node can be, as a fraction of
+        /// the average. Defaults to `GRAPH_REBALANCE_THRESHOLD`
+        #[structopt(long, short)]
+        threshold: Option<f64>,
+        /// The maximum number of deployments to move. Defaults to
+        /// `GRAPH_REBALANCE_MAX_MOVES`
+        #[structopt(long, short)]
+        max_moves: Option<usize>,
+    },
     /// Rewind a subgraph to a specific block
     Rewind {
         /// Force rewinding even if the block hash is not found in the local
@@ -157,6 +196,10 @@ pub enum Command {
             parse(try_from_str = parse_duration_in_secs)
         )]
         sleep: Duration,
+        /// Queue the rewind as a job that an index node runs in the
+        /// background instead of running it here (see `help job`)
+        #[structopt(long, short = "b")]
+        background: bool,
         /// The block hash of the target block
         block_hash: String,
         /// The block number of the target block
@@ -164,6 +207,25 @@ pub enum Command {
         /// The deployments to rewind (see `help info`)
         deployments: Vec<DeploymentSearch>,
     },
+    /// Change the tables of a deployment in place to match a new schema
+    ///
+    /// The new schema may only add entity types, enums, and nullable
+    /// attributes to the deployment's current schema. The deployment is
+    /// paused while its tables are changed
+    MigrateSchema {
+        /// Sleep for this many seconds after pausing the subgraph
+        #[structopt(
+            long,
+            short,
+            default_value = "10",
+            parse(try_from_str = parse_duration_in_secs)
+        )]
+        sleep: Duration,
+        /// The GraphQL schema file with the new schema
+        schema: String,
+        /// The deployment to migrate (see `help info`)
+        deployment: DeploymentSearch,
+    },
     /// Deploy and run an arbitrary subgraph up to a certain block, although it can surpass it by a few blocks, it's not exact (use for dev and testing purposes) -- WARNING: WILL RUN MIGRATIONS ON THE DB, DO NOT USE IN PRODUCTION
     ///
     /// Also worth noting that the deployed subgraph will be removed at the end.
@@ -180,6 +242,40 @@ pub enum Command {
         /// Prometheus push gateway endpoint.
         prometheus_host: Option<String>,
     },
+    /// Index a block range of a deployment again and compare the entity
+    /// changes with the ones in the store
+    ///
+    /// The blocks are replayed by a copy of the deployment in `shard` that
+    /// never becomes active, so that the deployment itself is not changed.
+    /// Use this to find nondeterminism in mappings, or to see what a fixed
+    /// mapping would change before rewinding the deployment
+    Replay {
+        /// The deployment to replay (see `help info`)
+        deployment: DeploymentSearch,
+        /// The first block to replay
+        start_block: BlockNumber,
+        /// The last block to replay (inclusive)
+        end_block: BlockNumber,
+        /// The shard for the copy that replays the blocks. It must be a
+        /// different shard than the one the deployment is in
+        #[structopt(long, short)]
+        shard: String,
+        /// The node that the copy is assigned to. No index node should use
+        /// this name
+        #[structopt(long, default_value = "graphman_replay")]
+        node: String,
+        /// Keep the copy after replaying instead of removing it
+        #[structopt(long)]
+        keep: bool,
+        /// Give up if the copy does not index any blocks for this many
+        /// seconds
+        #[structopt(
+            long,
+            default_value = "600",
+            parse(try_from_str = parse_duration_in_secs)
+        )]
+        timeout: Duration,
+    },
     /// Check and interrogate the configuration
     ///
     /// Print information about a configuration file without
@@ -189,6 +285,19 @@ pub enum Command {
     Listen(ListenCommand),
     /// Manage deployment copies and grafts
     Copy(CopyCommand),
+    /// Take snapshots of deployments and restore them
+    Snapshot(SnapshotCommand),
+    /// Manage the change journals of deployments
+    Journal(JournalCommand),
+    /// Partition the tables of deployments by block range
+    Partition(PartitionCommand),
+    /// Follow and cancel the jobs that index nodes run in the background
+    ///
+    /// Rewinding, pruning, exporting and copying deployments can be queued
+    /// as jobs with `--background`. Index nodes take jobs from the queue
+    /// every `GRAPH_JOB_QUEUE_INTERVAL` seconds and work on them in steps,
+    /// and continue where they left off if they are restarted
+    Job(JobCommand),
     /// Run a GraphQL query
     Query {
         /// The subgraph to query
@@ -200,6 +309,35 @@ pub enum Command {
         /// The variables in the form `key=value`
         vars: Vec<String>,
     },
+    /// Export the entities of a deployment to files
+    ///
+    /// Writes one file per entity type into the `destination` directory,
+    /// with the entities as they were at `block`
+    Export {
+        /// The deployment (see `help info`)
+        deployment: DeploymentSearch,
+        /// The directory into which to write the files
+        destination: String,
+        /// The block as of which to export; defaults to the subgraph head
+        #[structopt(long, short)]
+        block: Option<BlockNumber>,
+        /// The file format; only `csv` is supported
+        #[structopt(long, short, default_value = "csv")]
+        format: ExportFormat,
+        /// The entity types to export; all of them if none are given
+        #[structopt(long = "entity", short = "e")]
+        entity_types: Vec<String>,
+        /// Queue the export as a job that an index node runs in the
+        /// background instead of running it here (see `help job`)
+        #[structopt(long, short = "b")]
+        background: bool,
+    },
+    /// Manage the files of deployments that the store keeps
+    ///
+    /// Nodes keep the manifests, schemas, ABIs and WASM modules of the
+    /// deployments they start in the store, by their IPFS hash, and use
+    /// them when IPFS is not available
+    File(FileCommand),
     /// Get information about chains and manipulate them
     Chain(ChainCommand),
     /// Manipulate internal subgraph statistics
@@ -207,6 +345,27 @@ pub enum Command {
 
     /// Manage database indexes
     Index(IndexCommand),
+
+    /// Run the tests of a subgraph
+    ///
+    /// Each test is a JSON file with the blocks and events that the
+    /// subgraph's handlers run against, and the entities they should
+    /// produce. The subgraph is indexed with an in-memory store and does
+    /// not need a configuration, a database or an Ethereum node. Only
+    /// available when `graphman` is built with `--features test-harness`
+    Test {
+        /// The manifest that `graph build` produced, usually
+        /// `build/subgraph.yaml`
+        manifest: PathBuf,
+        /// The test files to run
+        #[structopt(required = true)]
+        tests: Vec<PathBuf>,
+        /// Also index the blocks of each test through these reorgs, e.g.
+        /// `advance 5, revert 2, advance 4`, and check that the subgraph
+        /// writes the same entities at every block as without them
+        #[structopt(long, value_name = "SCRIPT")]
+        reorgs: Option<String>,
+    },
 }
 
 impl Command {
@@ -302,6 +461,10 @@ pub enum CopyCommand {
         shard: String,
         /// The name of the node that should index the copy
         node: String,
+        /// Queue the copy as a job that an index node runs in the
+        /// background instead of running it here (see `help job`)
+        #[structopt(long, short = "b")]
+        background: bool,
     },
     /// Activate the copy of a deployment.
     ///
@@ -323,6 +486,174 @@ pub enum CopyCommand {
     },
 }
 
+#[derive(Clone, Debug, StructOpt)]
+pub enum FileCommand {
+    /// Print a file that the store keeps
+    Get {
+        /// The IPFS hash of the file
+        link: String,
+        /// Write the file here instead of to stdout
+        #[structopt(long, short)]
+        output: Option<String>,
+    },
+    /// Add a subgraph that `graph build` wrote to the store
+    ///
+    /// Prints the deployment hash of the subgraph, which can then be
+    /// deployed with `subgraph_deploy` even when no IPFS node is available
+    Import {
+        /// The manifest, or the directory with a `subgraph.yaml` in it
+        path: String,
+    },
+}
+
+#[derive(Clone, Debug, StructOpt)]
+pub enum PartitionCommand {
+    /// Partition the large tables of a deployment by block range
+    ///
+    /// Current entity versions are kept in one partition, and versions
+    /// that have been replaced or removed in partitions that each cover
+    /// the given number of blocks. Tables that are already partitioned
+    /// get new partitions up to the block the deployment is at. Writes to
+    /// the deployment are blocked while a table is rewritten
+    Create {
+        /// The number of blocks each partition covers
+        #[structopt(long, short, default_value = "1000000")]
+        blocks: i32,
+        /// Only partition tables with at least this many rows
+        #[structopt(long, short, default_value = "10000000")]
+        min_rows: i64,
+        /// The deployment (see `help info`)
+        deployment: DeploymentSearch,
+    },
+    /// Drop the partitions that only contain history before a block
+    ///
+    /// Queries for blocks whose history was dropped fail afterwards
+    Prune {
+        /// Drop the history from before this block
+        #[structopt(long)]
+        before: i32,
+        /// Queue the pruning as a job that an index node runs in the
+        /// background instead of running it here (see `help job`)
+        #[structopt(long, short = "b")]
+        background: bool,
+        /// The deployment (see `help info`)
+        deployment: DeploymentSearch,
+    },
+}
+
+#[derive(Clone, Debug, StructOpt)]
+pub enum JobCommand {
+    /// List the jobs that are queued or running
+    List {
+        /// List all jobs, including the ones that are finished
+        #[structopt(long, short)]
+        all: bool,
+        /// Only list the jobs of the deployment with this IPFS hash
+        #[structopt(long, short)]
+        deployment: Option<String>,
+    },
+    /// Show the state and progress of a job
+    Info {
+        /// The id of the job
+        id: i32,
+    },
+    /// Cancel a job
+    ///
+    /// Queued jobs are cancelled right away, running jobs before their
+    /// next step. Cancelling a rewind resumes the deployment without
+    /// rewinding it if it has not been rewound yet; cancelling a copy
+    /// stops copying but keeps the copy
+    Cancel {
+        /// The id of the job
+        id: i32,
+    },
+}
+
+#[derive(Clone, Debug, StructOpt)]
+pub enum JournalCommand {
+    /// Turn on the change journal of a deployment
+    ///
+    /// Index nodes with `GRAPH_CHANGE_JOURNAL_DIR` set then periodically
+    /// write the changes the deployment made in blocks that are final
+    /// into that directory, starting after the block the deployment is at
+    /// now. Take a snapshot after starting the journal so that the
+    /// journal can be applied to it
+    ///
+    /// Only one node writes the journal, and it keeps doing that when the
+    /// deployment is reassigned so that all of the journal ends up in the
+    /// same directory
+    Start {
+        /// The node that writes the journal; defaults to the node the
+        /// deployment is assigned to
+        #[structopt(long)]
+        node: Option<String>,
+        /// The deployment (see `help info`)
+        deployment: DeploymentSearch,
+    },
+    /// Turn off the change journal of a deployment
+    Stop {
+        /// The deployment (see `help info`)
+        deployment: DeploymentSearch,
+    },
+    /// Move a deployment forward to a block with its change journal
+    ///
+    /// This is usually done right after restoring the deployment from a
+    /// snapshot. The deployment is paused while the journal is applied
+    Apply {
+        /// Apply the journal even if the target block is not in the
+        /// block cache
+        #[structopt(long, short)]
+        force: bool,
+        /// Sleep for this many seconds after pausing the deployment
+        #[structopt(
+            long,
+            short,
+            default_value = "10",
+            parse(try_from_str = parse_duration_in_secs)
+        )]
+        sleep: Duration,
+        /// The deployment (see `help info`)
+        deployment: DeploymentSearch,
+        /// The directory with the change journals, the same as
+        /// `GRAPH_CHANGE_JOURNAL_DIR` of the index node that wrote them
+        dir: String,
+        /// The block hash of the target block
+        block_hash: String,
+        /// The block number of the target block
+        block_number: i32,
+    },
+}
+
+#[derive(Clone, Debug, StructOpt)]
+pub enum SnapshotCommand {
+    /// Write a snapshot of a deployment into a directory
+    ///
+    /// The snapshot contains all versions of all entities of the
+    /// deployment, its dynamic data sources, errors, and block pointer as
+    /// of the time the snapshot is taken. The deployment keeps indexing
+    /// while the snapshot is written
+    Create {
+        /// The deployment to snapshot (see `help info`)
+        deployment: DeploymentSearch,
+        /// The directory into which to write the snapshot
+        destination: String,
+    },
+    /// Recreate a deployment from a snapshot
+    ///
+    /// The deployment is created in `shard` and assigned to `node`, and
+    /// continues indexing from the block of the snapshot. It must not
+    /// exist in this installation yet. Deploying a subgraph with the same
+    /// IPFS hash gives the deployment a name
+    Restore {
+        /// The directory with the snapshot
+        source: String,
+        /// The name of the database shard into which to restore
+        shard: String,
+        /// The name of the node that should index the deployment
+        node: String,
+    },
+}
+
 #[derive(Clone, Debug, StructOpt)]
 pub enum ChainCommand {
     /// List all chains that are in the database
@@ -443,12 +774,25 @@ pub enum IndexCommand {
         #[structopt(empty_values = false)]
         index_name: String,
     },
+
+    /// Brings the indexes of a deployment up to the current index strategy.
+    ///
+    /// Creates the indexes that deployments get now but that the deployment is missing, and
+    /// then drops the indexes that were generated for it with an earlier strategy. Indexes are
+    /// built concurrently, and indexes created with `index create` are left alone.
+    ///
+    /// This command may be time-consuming.
+    Upgrade {
+        /// The deployment (see `help info`).
+        #[structopt(empty_values = false)]
+        deployment: DeploymentSearch,
+    },
 }
 
 impl From<Opt> for config::Opt {
     fn from(opt: Opt) -> Self {
         let mut config_opt = config::Opt::default();
-        config_opt.config = Some(opt.config);
+        config_opt.config = opt.config;
         config_opt.store_connection_pool_size = 5;
         config_opt
     }
@@ -575,6 +919,7 @@ impl Context {
             &self.logger,
             &self.node_id,
             &self.config,
+            Arc::new(self.config.deployment.clone()),
             self.fork_base,
             self.registry,
         );
@@ -610,19 +955,17 @@ impl Context {
     fn graphql_runner(self) -> Arc<GraphQlRunner<Store, PanicSubscriptionManager>> {
         let logger = self.logger.clone();
         let registry = self.registry.clone();
+        let query_limits = self.config.query_limits();
 
         let store = self.store();
 
         let subscription_manager = Arc::new(PanicSubscriptionManager);
         let load_manager = Arc::new(LoadManager::new(&logger, vec![], registry.clone()));
 
-        Arc::new(GraphQlRunner::new(
-            &logger,
-            store,
-            subscription_manager,
-            load_manager,
-            registry,
-        ))
+        Arc::new(
+            GraphQlRunner::new(&logger, store, subscription_manager, load_manager, registry)
+                .with_query_limits(query_limits),
+        )
     }
 }
 
@@ -644,6 +987,24 @@ async fn main() {
         render_testament!(TESTAMENT)
     );
 
+    // Tests run without any of the infrastructure that the other commands
+    // manage
+    if let Command::Test {
+        manifest,
+        tests,
+        reorgs,
+    } = &opt.cmd
+    {
+        if let Err(e) = run_tests(manifest.clone(), tests.clone(), reorgs.clone()).await {
+            die!("error: {}", e);
+        }
+        return;
+    }
+
+    if opt.config.is_none() {
+        eprintln!("the --config argument or GRAPH_NODE_CONFIG is required");
+        std::process::exit(1);
+    }
     let mut config = match Cfg::load(&logger, &opt.clone().into()) {
         Err(e) => {
             eprintln!("configuration error: {}", e);
@@ -749,15 +1110,34 @@ async fn main() {
         }
         Remove { name } => commands::remove::run(ctx.subgraph_store(), name),
         Create { name } => commands::create::run(ctx.subgraph_store(), name),
+        Deprecate {
+            clear,
+            removal_date,
+            name,
+            message,
+        } => match (clear, removal_date, message) {
+            (true, _, _) => commands::deprecate::clear(ctx.subgraph_store(), name),
+            (false, Some(removal_date), Some(message)) => {
+                commands::deprecate::set(ctx.subgraph_store(), name, removal_date, message)
+            }
+            // structopt makes sure that both are given without `--clear`
+            (false, _, _) => unreachable!(),
+        },
         Unassign { deployment } => {
             commands::assign::unassign(ctx.primary_pool(), &deployment).await
         }
         Reassign { deployment, node } => {
             commands::assign::reassign(ctx.primary_pool(), &deployment, node)
         }
+        Rebalance {
+            dry_run,
+            threshold,
+            max_moves,
+        } => commands::rebalance::run(ctx.subgraph_store(), threshold, max_moves, dry_run),
         Rewind {
             force,
             sleep,
+            background,
             block_hash,
             block_number,
             deployments,
@@ -771,6 +1151,21 @@ async fn main() {
                 block_number,
                 force,
                 sleep,
+                background,
+            )
+        }
+        MigrateSchema {
+            sleep,
+            schema,
+            deployment,
+        } => {
+            let (store, primary) = ctx.store_and_primary();
+            commands::migrate_schema::run(
+                primary,
+                store.subgraph_store(),
+                deployment,
+                schema,
+                sleep,
             )
         }
         Run {
@@ -806,6 +1201,37 @@ async fn main() {
             )
             .await
         }
+        Replay {
+            deployment,
+            start_block,
+            end_block,
+            shard,
+            node,
+            keep,
+            timeout,
+        } => {
+            let logger = ctx.logger.clone();
+            let config = ctx.config();
+            let registry = ctx.metrics_registry().clone();
+            let store_builder = ctx.store_builder().await;
+            let ipfs_url = ctx.ipfs_url.clone();
+
+            commands::replay::run(
+                logger,
+                store_builder,
+                ipfs_url,
+                config,
+                registry,
+                deployment,
+                shard,
+                node,
+                start_block,
+                end_block,
+                keep,
+                timeout,
+            )
+            .await
+        }
         Listen(cmd) => {
             use ListenCommand::*;
             match cmd {
@@ -827,10 +1253,14 @@ async fn main() {
                     shard,
                     node,
                     offset,
+                    background,
                 } => {
                     let shards: Vec<_> = ctx.config.stores.keys().cloned().collect();
                     let (store, primary) = ctx.store_and_primary();
-                    commands::copy::create(store, primary, src, shard, shards, node, offset).await
+                    commands::copy::create(
+                        store, primary, src, shard, shards, node, offset, background,
+                    )
+                    .await
                 }
                 Activate { deployment, shard } => {
                     commands::copy::activate(ctx.subgraph_store(), deployment, shard)
@@ -839,11 +1269,134 @@ async fn main() {
                 Status { dst } => commands::copy::status(ctx.pools(), &dst),
             }
         }
+        Snapshot(cmd) => {
+            use SnapshotCommand::*;
+            match cmd {
+                Create {
+                    deployment,
+                    destination,
+                } => {
+                    let (store, primary) = ctx.store_and_primary();
+                    commands::snapshot::create(
+                        store.subgraph_store(),
+                        primary,
+                        deployment,
+                        destination,
+                    )
+                }
+                Restore {
+                    source,
+                    shard,
+                    node,
+                } => {
+                    let shards: Vec<_> = ctx.config.stores.keys().cloned().collect();
+                    commands::snapshot::restore(ctx.subgraph_store(), source, shard, shards, node)
+                }
+            }
+        }
+        Partition(cmd) => {
+            use PartitionCommand::*;
+            let (store, primary) = ctx.store_and_primary();
+            match cmd {
+                Create {
+                    blocks,
+                    min_rows,
+                    deployment,
+                } => commands::partition::create(
+                    store.subgraph_store(),
+                    primary,
+                    deployment,
+                    blocks,
+                    min_rows,
+                ),
+                Prune {
+                    before,
+                    deployment,
+                    background,
+                } => commands::partition::prune(
+                    store.subgraph_store(),
+                    primary,
+                    deployment,
+                    before,
+                    background,
+                ),
+            }
+        }
+        Job(cmd) => {
+            use JobCommand::*;
+            match cmd {
+                List { all, deployment } => {
+                    commands::job::list(ctx.subgraph_store(), deployment, all)
+                }
+                Info { id } => commands::job::info(ctx.subgraph_store(), id),
+                Cancel { id } => commands::job::cancel(ctx.subgraph_store(), id),
+            }
+        }
+        Journal(cmd) => {
+            use JournalCommand::*;
+            match cmd {
+                Start { node, deployment } => {
+                    let (store, primary) = ctx.store_and_primary();
+                    commands::journal::start(store.subgraph_store(), primary, deployment, node)
+                }
+                Stop { deployment } => {
+                    let (store, primary) = ctx.store_and_primary();
+                    commands::journal::stop(store.subgraph_store(), primary, deployment)
+                }
+                Apply {
+                    force,
+                    sleep,
+                    deployment,
+                    dir,
+                    block_hash,
+                    block_number,
+                } => {
+                    let (store, primary) = ctx.store_and_primary();
+                    commands::journal::apply(
+                        primary,
+                        store,
+                        deployment,
+                        dir,
+                        block_hash,
+                        block_number,
+                        force,
+                        sleep,
+                    )
+                }
+            }
+        }
         Query {
             target,
             query,
             vars,
         } => commands::query::run(ctx.graphql_runner(), target, query, vars).await,
+        Export {
+            deployment,
+            destination,
+            block,
+            format,
+            entity_types,
+            background,
+        } => {
+            let (store, primary) = ctx.store_and_primary();
+            commands::export::run(
+                store.subgraph_store(),
+                primary,
+                deployment,
+                entity_types,
+                block,
+                format,
+                destination,
+                background,
+            )
+        }
+        File(cmd) => {
+            use FileCommand::*;
+            match cmd {
+                Get { link, output } => commands::file::get(ctx.subgraph_store(), link, output),
+                Import { path } => commands::file::import(ctx.subgraph_store(), path),
+            }
+        }
         Chain(cmd) => {
             use ChainCommand::*;
             match cmd {
@@ -915,8 +1468,12 @@ async fn main() {
                     commands::index::drop(subgraph_store, primary_pool, deployment, &index_name)
                         .await
                 }
+                Upgrade { deployment } => {
+                    commands::index::upgrade(subgraph_store, primary_pool, deployment).await
+                }
             }
         }
+        Test { .. } => unreachable!("tests run before the configuration is loaded"),
     };
     if let Err(e) = result {
         die!("error: {}", e)
@@ -926,3 +1483,23 @@ async fn main() {
 fn parse_duration_in_secs(s: &str) -> Result<Duration, ParseIntError> {
     Ok(Duration::from_secs(s.parse()?))
 }
+
+#[cfg(feature = "test-harness")]
+async fn run_tests(
+    manifest: PathBuf,
+    tests: Vec<PathBuf>,
+    reorgs: Option<String>,
+) -> Result<(), anyhow::Error> {
+    commands::test::run(manifest, tests, reorgs).await
+}
+
+#[cfg(not(feature = "test-harness"))]
+async fn run_tests(
+    _manifest: PathBuf,
+    _tests: Vec<PathBuf>,
+    _reorgs: Option<String>,
+) -> Result<(), anyhow::Error> {
+    Err(anyhow!(
+        "graphman was built without test support; rebuild it with `--features test-harness`"
+    ))
+}
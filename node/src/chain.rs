@@ -149,6 +149,7 @@ pub async fn create_ethereum_networks(
                             transport,
                             eth_rpc_metrics.clone(),
                             supports_eip_1898,
+                            web3.get_logs_limits(),
                         )
                         .await,
                     ),
@@ -413,6 +414,7 @@ mod test {
             ethereum_ws: vec![],
             ethereum_ipc: vec![],
             unsafe_config: false,
+            dev_db: None,
         };
 
         let config = Config::load(&logger, &opt).expect("can create config");
@@ -458,7 +460,7 @@ mod test {
             .networks
             .get("goerli")
             .unwrap()
-            .adapters
+            .adapters()
             .iter()
             .next()
             .unwrap()
@@ -467,7 +469,7 @@ mod test {
             .networks
             .get("mainnet")
             .unwrap()
-            .adapters
+            .adapters()
             .iter()
             .next()
             .unwrap()
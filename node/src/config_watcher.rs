@@ -0,0 +1,201 @@
+//! Apply changes to the configuration file while the node is running.
+//! Only the deployment rules and the Ethereum JSON-RPC providers of chains
+//! that the node already knows about can be changed that way; all other
+//! changes are reported, but only take effect after a restart
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use ethereum::EthereumNetworks;
+use graph::blockchain::BlockchainKind;
+use graph::prelude::{anyhow, serde_json, tokio};
+use graph::slog::{error, info, o, warn, Logger};
+use graph_chain_ethereum as ethereum;
+use graph_core::MetricsRegistry;
+
+use crate::chain::{connect_ethereum_networks, create_ethereum_networks};
+use crate::config::{Chain, Config, Provider, ProviderDetails, ReloadablePlacer};
+
+pub struct ConfigWatcher {
+    logger: Logger,
+    path: PathBuf,
+    modified: Option<SystemTime>,
+    config: Config,
+    placer: Arc<ReloadablePlacer>,
+    eth_networks: EthereumNetworks,
+    registry: Arc<MetricsRegistry>,
+}
+
+impl ConfigWatcher {
+    /// Create a watcher for the configuration file at `path`. The node
+    /// was started with `config`; `placer` and `eth_networks` are the
+    /// deployment rules and the Ethereum adapters that are in use and
+    /// that will be updated when the file changes
+    pub fn new(
+        logger: &Logger,
+        path: impl Into<PathBuf>,
+        config: Config,
+        placer: Arc<ReloadablePlacer>,
+        eth_networks: EthereumNetworks,
+        registry: Arc<MetricsRegistry>,
+    ) -> Self {
+        let path = path.into();
+        let modified = fs::metadata(&path).and_then(|md| md.modified()).ok();
+        ConfigWatcher {
+            logger: logger.new(o!("component" => "ConfigWatcher")),
+            path,
+            modified,
+            config,
+            placer,
+            eth_networks,
+            registry,
+        }
+    }
+
+    /// Check the configuration file for changes every `interval`
+    pub async fn run(mut self, interval: Duration) {
+        info!(self.logger, "Watching configuration file for changes";
+              "path" => self.path.display().to_string(),
+              "interval_s" => interval.as_secs());
+        let mut ticker = tokio::time::interval(interval);
+        loop {
+            ticker.tick().await;
+            if let Err(e) = self.check().await {
+                error!(self.logger, "Failed to reload configuration; keeping the current one";
+                       "error" => format!("{:#}", e));
+            }
+        }
+    }
+
+    async fn check(&mut self) -> Result<(), anyhow::Error> {
+        let modified = fs::metadata(&self.path)?.modified()?;
+        if self.modified == Some(modified) {
+            return Ok(());
+        }
+        // Remember the modification time even if the file turns out to
+        // be invalid so that we don't complain about it over and over
+        self.modified = Some(modified);
+
+        info!(self.logger, "Configuration file changed, reloading it");
+        let config = Config::from_str(&fs::read_to_string(&self.path)?)?;
+        self.apply(config).await
+    }
+
+    async fn apply(&mut self, config: Config) -> Result<(), anyhow::Error> {
+        if serde_json::to_value(&config.deployment)?
+            != serde_json::to_value(&self.config.deployment)?
+        {
+            self.placer.reload(config.deployment.clone());
+            info!(self.logger, "Reloaded deployment rules");
+        }
+
+        for (name, chain) in &config.chains.chains {
+            self.apply_chain(&config, name, chain).await;
+        }
+        for name in self.config.chains.chains.keys() {
+            if !config.chains.chains.contains_key(name) {
+                warn!(self.logger, "Chain was removed from the configuration; \
+                                    the node needs to be restarted for that to take effect";
+                      "chain" => name);
+            }
+        }
+
+        if serde_json::to_value(&config.stores)? != serde_json::to_value(&self.config.stores)?
+            || config.chains.ingestor != self.config.chains.ingestor
+            || serde_json::to_value(&config.general)? != serde_json::to_value(&self.config.general)?
+            || serde_json::to_value(&config.query)? != serde_json::to_value(&self.config.query)?
+        {
+            warn!(
+                self.logger,
+                "The configuration of stores, the block ingestor, the general section or \
+                 query limits changed; the node needs to be restarted for that to take effect"
+            );
+        }
+
+        self.config = config;
+        Ok(())
+    }
+
+    /// Replace the Ethereum adapters for the chain `name` if its JSON-RPC
+    /// providers changed
+    async fn apply_chain(&self, config: &Config, name: &str, chain: &Chain) {
+        let logger = self.logger.new(o!("chain" => name.to_string()));
+
+        let old = match self.config.chains.chains.get(name) {
+            Some(old) => old,
+            None => {
+                warn!(
+                    logger,
+                    "Chain was added to the configuration; \
+                               the node needs to be restarted to index it"
+                );
+                return;
+            }
+        };
+
+        let (old_web3, old_firehose) = split_providers(old);
+        let (web3, firehose) = split_providers(chain);
+        if old_firehose != firehose || old.shard != chain.shard {
+            warn!(
+                logger,
+                "Firehose providers or the shard of the chain changed; \
+                           the node needs to be restarted for that to take effect"
+            );
+        }
+        if chain.protocol != BlockchainKind::Ethereum || old_web3 == web3 {
+            return;
+        }
+
+        let adapters = match self.eth_networks.networks.get(name) {
+            Some(adapters) => adapters,
+            None => {
+                warn!(
+                    logger,
+                    "The node does not use JSON-RPC providers for this chain; \
+                               ignoring changes to them"
+                );
+                return;
+            }
+        };
+
+        // Only create adapters for the chain that changed so that we do not
+        // open new connections to the providers of all the other chains
+        let mut single = config.clone();
+        single.chains.chains = BTreeMap::from([(name.to_string(), chain.clone())]);
+        let networks =
+            match create_ethereum_networks(logger.clone(), self.registry.clone(), &single).await {
+                Ok(networks) => networks,
+                Err(e) => {
+                    error!(logger, "Failed to create adapters for the new providers; \
+                                    keeping the current ones";
+                           "error" => format!("{:#}", e));
+                    return;
+                }
+            };
+        let (networks, _) = connect_ethereum_networks(&logger, networks).await;
+
+        match networks.networks.get(name) {
+            Some(new_adapters) if !new_adapters.is_empty() => {
+                adapters.replace(new_adapters);
+                info!(logger, "Reloaded providers";
+                      "providers" => adapters.providers().join(", "));
+            }
+            _ => {
+                error!(
+                    logger,
+                    "None of the new providers can be used; keeping the current ones"
+                );
+            }
+        }
+    }
+}
+
+/// Split the providers of `chain` into JSON-RPC and Firehose providers
+fn split_providers(chain: &Chain) -> (Vec<&Provider>, Vec<&Provider>) {
+    chain
+        .providers
+        .iter()
+        .partition(|provider| matches!(provider.details, ProviderDetails::Web3(_)))
+}
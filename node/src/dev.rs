@@ -0,0 +1,350 @@
+//! Run `graph-node --dev`: a single node that keeps everything in one
+//! SQLite file instead of Postgres. It only indexes Ethereum chains, runs
+//! no store maintenance jobs, and does not take part in assigning
+//! deployments across nodes
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+use std::time::Duration;
+
+use ethereum::{BlockIngestor, EthereumAdapterTrait, EthereumNetworks};
+use futures::TryFutureExt;
+use graph::blockchain::{Blockchain, BlockchainMap};
+use graph::cheap_clone::CheapClone;
+use graph::components::server::health::{
+    DeploymentHealth, HealthCheck, NodeReadiness, RunningIngestors,
+};
+use graph::components::server::tls;
+use graph::data::graphql::effort::LoadManager;
+use graph::data::query::DeploymentQueryLimits;
+use graph::data::subgraph::status;
+use graph::firehose::FirehoseEndpoints;
+use graph::prelude::{
+    anyhow, async_trait, error, info, q, DeploymentHash, IndexNodeServer as _, JsonRpcServer as _,
+    LoggerFactory, NodeId, StatusStore, StoreError, SubgraphRegistrar as _, ENV_VARS,
+};
+use graph::slog::Logger;
+use graph::url::Url;
+use graph_chain_ethereum as ethereum;
+use graph_core::{
+    LinkResolver, MetricsRegistry, SubgraphAssignmentProvider as IpfsSubgraphAssignmentProvider,
+    SubgraphInstanceManager, SubgraphRegistrar as IpfsSubgraphRegistrar,
+};
+use graph_graphql::prelude::GraphQlRunner;
+use graph_server_http::GraphQLServer as GraphQLQueryServer;
+use graph_server_index_node::IndexNodeServer;
+use graph_server_json_rpc::JsonRpcServer;
+use graph_server_metrics::PrometheusMetricsServer;
+use graph_server_websocket::SubscriptionServer as GraphQLSubscriptionServer;
+use graph_store_sqlite::{
+    BlockStore, ChainHeadUpdateListener, Database, Store, SubgraphStore, SubscriptionManager,
+};
+
+use crate::chain::connect_ethereum_networks;
+use crate::launch::{deploy_subgraph_from_flag, shutdown_on_signal};
+use crate::opt::Opt;
+use crate::store_factory::SqliteStoreFactory;
+
+/// Everything `main` sets up before it decides which store to use
+pub struct DevNode {
+    /// The `sqlite://` connection string of the primary store
+    pub database: String,
+    pub opt: Opt,
+    pub node_id: NodeId,
+    pub logger_factory: LoggerFactory,
+    pub link_resolver: Arc<LinkResolver>,
+    pub metrics_registry: Arc<MetricsRegistry>,
+    pub metrics_server: PrometheusMetricsServer,
+    pub eth_networks: EthereumNetworks,
+    pub expensive_queries: Vec<Arc<q::Document>>,
+    pub query_limits: DeploymentQueryLimits,
+    pub fork_base: Option<Url>,
+}
+
+impl DevNode {
+    pub async fn launch(self, logger: Logger) {
+        let DevNode {
+            database,
+            opt,
+            node_id,
+            logger_factory,
+            link_resolver,
+            metrics_registry,
+            mut metrics_server,
+            eth_networks,
+            expensive_queries,
+            query_limits,
+            fork_base,
+        } = self;
+
+        let path = SqliteStoreFactory::path(&database)
+            .expect("the store factory checked the connection string");
+        info!(logger, "Using SQLite store for development"; "path" => path);
+        let db = Arc::new(
+            Database::open(path).unwrap_or_else(|e| panic!("Failed to open `{}`: {}", path, e)),
+        );
+        let subscription_manager = Arc::new(SubscriptionManager::new());
+        let chain_head_update_listener = Arc::new(ChainHeadUpdateListener::new());
+        let mut subgraph_store =
+            SubgraphStore::new(db.cheap_clone(), subscription_manager.cheap_clone());
+        if let Some(fork_base) = fork_base {
+            subgraph_store = subgraph_store.with_fork_base(fork_base);
+        }
+        let subgraph_store = Arc::new(subgraph_store);
+        let block_store = Arc::new(BlockStore::new(
+            db,
+            chain_head_update_listener.cheap_clone(),
+        ));
+        let store = Arc::new(Store::new(subgraph_store.clone(), block_store.clone()));
+
+        let (eth_networks, ethereum_idents) =
+            connect_ethereum_networks(&logger, eth_networks).await;
+
+        let mut blockchain_map = BlockchainMap::new();
+        let mut chains = HashMap::new();
+        for (network_name, idents) in ethereum_idents {
+            let ident = match idents.first() {
+                Some(ident) => ident,
+                None => {
+                    error!(
+                        logger,
+                        "No provider for Ethereum chain {} could be reached; ignoring this chain",
+                        network_name
+                    );
+                    continue;
+                }
+            };
+            let eth_adapters = match eth_networks.networks.get(&network_name) {
+                Some(eth_adapters) => eth_adapters.clone(),
+                None => continue,
+            };
+            let chain_store = block_store
+                .add_chain(&network_name, ident)
+                .unwrap_or_else(|e| panic!("Failed to set up chain {}: {}", network_name, e));
+            let chain = Arc::new(ethereum::Chain::new(
+                logger_factory.clone(),
+                network_name.clone(),
+                node_id.clone(),
+                metrics_registry.clone(),
+                chain_store.cheap_clone(),
+                chain_store,
+                FirehoseEndpoints::new(),
+                eth_adapters,
+                chain_head_update_listener.cheap_clone(),
+                ENV_VARS.reorg_threshold,
+                true,
+            ));
+            blockchain_map.insert::<ethereum::Chain>(network_name.clone(), chain.cheap_clone());
+            chains.insert(network_name, chain);
+        }
+        let blockchain_map = Arc::new(blockchain_map);
+
+        let load_manager = Arc::new(LoadManager::new(
+            &logger,
+            expensive_queries,
+            metrics_registry.clone(),
+        ));
+        let graphql_runner = Arc::new(
+            GraphQlRunner::new(
+                &logger,
+                store.clone(),
+                subscription_manager.clone(),
+                load_manager,
+                metrics_registry.clone(),
+            )
+            .with_query_limits(query_limits),
+        );
+        let ingestors = Arc::new(RunningIngestors::default());
+        let health = Arc::new(DevHealthCheck {
+            store: store.clone(),
+            ingestors: ingestors.clone(),
+            ingests_blocks: !opt.disable_block_ingestor,
+        });
+        let mut graphql_server = GraphQLQueryServer::new(
+            &logger_factory,
+            metrics_registry.clone(),
+            graphql_runner.clone(),
+            health,
+            node_id.clone(),
+        );
+        let subscription_server =
+            GraphQLSubscriptionServer::new(&logger, graphql_runner.clone(), store.clone());
+        let mut index_node_server = IndexNodeServer::new(
+            &logger_factory,
+            blockchain_map.clone(),
+            graphql_runner.clone(),
+            store.clone(),
+            link_resolver.clone(),
+        );
+
+        if !opt.disable_block_ingestor {
+            let polling_interval = Duration::from_millis(opt.ethereum_polling_interval);
+            for (network_name, chain) in &chains {
+                start_block_ingestor(&logger, network_name, chain, polling_interval, &ingestors);
+            }
+        }
+
+        let subgraph_instance_manager = Arc::new(SubgraphInstanceManager::new(
+            &logger_factory,
+            subgraph_store.clone(),
+            blockchain_map.cheap_clone(),
+            metrics_registry.clone(),
+            link_resolver.clone(),
+            ENV_VARS.experimental_static_filters,
+        ));
+        // All writes go straight to the database file, and there are no
+        // connection pools to wait for
+        graph::spawn(shutdown_on_signal(
+            logger.clone(),
+            subgraph_instance_manager.cheap_clone(),
+            vec![],
+        ));
+
+        let subgraph_provider = IpfsSubgraphAssignmentProvider::new(
+            &logger_factory,
+            link_resolver.clone(),
+            subgraph_instance_manager,
+        );
+        let subgraph_registrar = Arc::new(IpfsSubgraphRegistrar::new(
+            &logger_factory,
+            link_resolver,
+            Arc::new(subgraph_provider),
+            subgraph_store,
+            subscription_manager,
+            blockchain_map,
+            node_id.clone(),
+            ENV_VARS.subgraph_version_switching_mode,
+        ));
+        graph::spawn(
+            subgraph_registrar
+                .start()
+                .map_err(|e| panic!("failed to initialize subgraph provider {}", e))
+                .compat(),
+        );
+
+        let json_rpc_server = JsonRpcServer::serve(
+            opt.admin_port,
+            opt.http_port,
+            opt.ws_port,
+            subgraph_registrar.clone(),
+            node_id.clone(),
+            logger.clone(),
+        )
+        .expect("failed to start JSON-RPC admin server");
+        std::mem::forget(json_rpc_server);
+
+        if let Some(subgraph) = opt.subgraph {
+            deploy_subgraph_from_flag(
+                subgraph_registrar,
+                subgraph,
+                opt.debug_fork,
+                opt.start_block,
+                node_id,
+            );
+        }
+
+        tls::init(&logger).expect("Failed to load TLS certificate");
+        graph::spawn(
+            graphql_server
+                .serve(opt.http_port, opt.ws_port)
+                .expect("Failed to start GraphQL query server")
+                .compat(),
+        );
+        graph::spawn(subscription_server.serve(opt.ws_port));
+        graph::spawn(
+            index_node_server
+                .serve(opt.index_node_port)
+                .expect("Failed to start index node server")
+                .compat(),
+        );
+        graph::spawn(
+            metrics_server
+                .serve(opt.metrics_port)
+                .expect("Failed to start metrics server")
+                .compat(),
+        );
+    }
+}
+
+fn start_block_ingestor(
+    logger: &Logger,
+    network_name: &str,
+    chain: &Arc<ethereum::Chain>,
+    polling_interval: Duration,
+    ingestors: &Arc<RunningIngestors>,
+) {
+    let eth_adapter = chain.cheapest_adapter();
+    let logger = logger.new(graph::slog::o!(
+        "component" => "BlockIngestor",
+        "provider" => eth_adapter.provider().to_string()
+    ));
+    let block_ingestor = BlockIngestor::new(
+        logger.clone(),
+        ENV_VARS.reorg_threshold,
+        eth_adapter,
+        chain.chain_store(),
+        polling_interval,
+    )
+    .expect("failed to create Ethereum block ingestor");
+
+    info!(logger, "Starting block ingestor for network"; "network_name" => network_name);
+    // A dev node is the only node there is, and does not need to compete
+    // for ingesting blocks
+    let running = (ingestors.start(network_name), ingestors.lead(network_name));
+    graph::spawn(async move {
+        let _running = running;
+        block_ingestor.into_polling_stream().await
+    });
+}
+
+/// Health checks against the SQLite store; the single database file is the
+/// only store, and it is reachable as long as the node runs
+struct DevHealthCheck {
+    store: Arc<Store>,
+    ingestors: Arc<RunningIngestors>,
+    ingests_blocks: bool,
+}
+
+#[async_trait]
+impl HealthCheck for DevHealthCheck {
+    async fn deployment_health(
+        &self,
+        deployment: DeploymentHash,
+    ) -> Result<Option<DeploymentHealth>, StoreError> {
+        let store = self.store.clone();
+        let filter = status::Filter::Deployments(vec![deployment.to_string()]);
+        let infos = graph::spawn_blocking_allow_panic(move || store.status(filter))
+            .await
+            .map_err(|e| StoreError::Unknown(anyhow::anyhow!("health check panicked: {}", e)))??;
+
+        Ok(infos.into_iter().next().map(|info| {
+            let chain = info.chains.first();
+            let latest_block = chain
+                .and_then(|chain| chain.latest_block.as_ref())
+                .map(|block| block.number());
+            let chain_head_block = chain
+                .and_then(|chain| chain.chain_head_block.as_ref())
+                .map(|block| block.number());
+            let blocks_behind = latest_block
+                .zip(chain_head_block)
+                .map(|(latest, head)| (head - latest).max(0));
+            DeploymentHealth {
+                deployment: info.subgraph,
+                health: info.health.as_str().to_string(),
+                synced: info.synced,
+                latest_block,
+                chain_head_block,
+                blocks_behind,
+            }
+        }))
+    }
+
+    async fn readiness(&self) -> NodeReadiness {
+        let ingestors = self.ingestors.chains();
+        NodeReadiness {
+            ready: !self.ingests_blocks || !ingestors.is_empty(),
+            stores: BTreeMap::from([("sqlite".to_string(), true)]),
+            ingestors,
+            leading: self.ingestors.leading(),
+        }
+    }
+}
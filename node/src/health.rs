@@ -0,0 +1,86 @@
+use std::sync::Arc;
+
+use graph::components::server::health::{
+    DeploymentHealth, HealthCheck, NodeReadiness, RunningIngestors,
+};
+use graph::data::subgraph::status;
+use graph::prelude::{anyhow, async_trait, DeploymentHash, StatusStore, StoreError};
+use graph_store_postgres::Store;
+
+/// Answers health checks from the status of deployments in the store, and
+/// readiness checks by trying to connect to every shard and looking at the
+/// block ingestors that run in this process
+pub struct NodeHealthCheck {
+    store: Arc<Store>,
+    ingestors: Arc<RunningIngestors>,
+    /// Whether this node is supposed to run block ingestors
+    ingests_blocks: bool,
+}
+
+impl NodeHealthCheck {
+    pub fn new(store: Arc<Store>, ingestors: Arc<RunningIngestors>, ingests_blocks: bool) -> Self {
+        NodeHealthCheck {
+            store,
+            ingestors,
+            ingests_blocks,
+        }
+    }
+}
+
+#[async_trait]
+impl HealthCheck for NodeHealthCheck {
+    async fn deployment_health(
+        &self,
+        deployment: DeploymentHash,
+    ) -> Result<Option<DeploymentHealth>, StoreError> {
+        let store = self.store.clone();
+        let filter = status::Filter::Deployments(vec![deployment.to_string()]);
+        let infos = graph::spawn_blocking_allow_panic(move || store.status(filter))
+            .await
+            .map_err(|e| StoreError::Unknown(anyhow!("health check panicked: {}", e)))??;
+
+        Ok(infos.into_iter().next().map(|info| {
+            let chain = info.chains.first();
+            let latest_block = chain
+                .and_then(|chain| chain.latest_block.as_ref())
+                .map(|block| block.number());
+            let chain_head_block = chain
+                .and_then(|chain| chain.chain_head_block.as_ref())
+                .map(|block| block.number());
+            let blocks_behind = match (latest_block, chain_head_block) {
+                (Some(latest), Some(head)) => Some((head - latest).max(0)),
+                _ => None,
+            };
+            let health = if info.available {
+                info.health.as_str()
+            } else {
+                "unavailable"
+            };
+            DeploymentHealth {
+                deployment: info.subgraph,
+                health: health.to_string(),
+                synced: info.synced,
+                latest_block,
+                chain_head_block,
+                blocks_behind,
+            }
+        }))
+    }
+
+    async fn readiness(&self) -> NodeReadiness {
+        let subgraph_store = self.store.subgraph_store();
+        let stores = graph::spawn_blocking_allow_panic(move || subgraph_store.check_shards())
+            .await
+            .unwrap_or_default();
+        let ingestors = self.ingestors.chains();
+
+        let stores_reachable = !stores.is_empty() && stores.values().all(|reachable| *reachable);
+        let ingesting = !self.ingests_blocks || !ingestors.is_empty();
+        NodeReadiness {
+            ready: stores_reachable && ingesting,
+            stores,
+            ingestors,
+            leading: self.ingestors.leading(),
+        }
+    }
+}
@@ -0,0 +1,79 @@
+//! Making sure that only one node in a cluster ingests blocks for a chain.
+//! Every node that is configured to ingest blocks competes for a lease in
+//! the primary for each of its chains; the node that holds the lease runs
+//! the ingestor, and the others check periodically whether they can take
+//! over. Deployments on all nodes follow the chain head that the ingestor
+//! writes to the chain store
+use std::future::Future;
+use std::sync::Arc;
+
+use graph::components::server::health::RunningIngestors;
+use graph::prelude::{info, tokio, warn, CheapClone, Logger, ENV_VARS};
+use graph_store_postgres::{BlockStore, IngestorLease};
+
+/// Run the ingestor that `ingest` creates for `chain` whenever this node
+/// holds the lease for the chain, and stop it when the lease is lost
+pub async fn lead_ingestion<F, Fut>(
+    logger: Logger,
+    store: Arc<BlockStore>,
+    ingestors: Arc<RunningIngestors>,
+    chain: String,
+    ingest: F,
+) where
+    F: Fn() -> Fut + Send + 'static,
+    Fut: Future<Output = ()> + Send + 'static,
+{
+    let interval = ENV_VARS.ingestor_lease_interval;
+    let _running = ingestors.start(&chain);
+    loop {
+        let lease = {
+            let store = store.cheap_clone();
+            let chain = chain.clone();
+            graph::spawn_blocking_allow_panic(move || store.try_lease_ingestor(&chain)).await
+        };
+        match lease {
+            Ok(Ok(Some(mut lease))) => {
+                info!(logger, "Took over block ingestion"; "network_name" => &chain);
+                let _leading = ingestors.lead(&chain);
+                let ingestor = graph::spawn(ingest());
+                loop {
+                    tokio::time::sleep(interval).await;
+                    lease = match check(&logger, lease).await {
+                        Some(lease) => lease,
+                        None => break,
+                    };
+                }
+                ingestor.abort();
+                warn!(logger, "Lost the lease for block ingestion, stopped ingesting blocks";
+                      "network_name" => &chain);
+            }
+            Ok(Ok(None)) => {}
+            Ok(Err(e)) => {
+                warn!(logger, "Failed to get the lease for block ingestion";
+                      "network_name" => &chain, "error" => e.to_string());
+            }
+            Err(e) => {
+                warn!(logger, "Failed to get the lease for block ingestion";
+                      "network_name" => &chain, "error" => e.to_string());
+            }
+        }
+        tokio::time::sleep(interval).await;
+    }
+}
+
+/// Return `lease` if it is still held, and `None` otherwise
+async fn check(logger: &Logger, lease: IngestorLease) -> Option<IngestorLease> {
+    let checked = graph::spawn_blocking_allow_panic(move || {
+        let held = lease.check();
+        (lease, held)
+    })
+    .await;
+    let error = match checked {
+        Ok((lease, Ok(true))) => return Some(lease),
+        Ok((_, Ok(false))) => "the lock is not held anymore".to_string(),
+        Ok((_, Err(e))) => e.to_string(),
+        Err(e) => e.to_string(),
+    };
+    warn!(logger, "Could not confirm the lease for block ingestion"; "error" => error);
+    None
+}
@@ -0,0 +1,88 @@
+//! The parts of starting and stopping a node that `graph-node` and
+//! `graph-node --dev` share, whichever store they run on
+use std::sync::Arc;
+
+use futures::TryFutureExt;
+use graph::components::server::http;
+use graph::prelude::{
+    info, tokio, warn, BlockPtr, DeploymentHash, Logger, NodeId, SubgraphName, SubgraphRegistrar,
+    SubgraphStore, ENV_VARS,
+};
+use graph_core::SubgraphInstanceManager;
+use graph_store_postgres::connection_pool::ConnectionPool;
+
+/// Deploy the subgraph given with `--subgraph`, which is either an IPFS
+/// hash or `name:hash`, to `node_id`
+pub fn deploy_subgraph_from_flag<R: SubgraphRegistrar>(
+    registrar: Arc<R>,
+    subgraph: String,
+    debug_fork: Option<String>,
+    start_block: Option<BlockPtr>,
+    node_id: NodeId,
+) {
+    let (name, hash) = match subgraph.split_once(':') {
+        Some((name, hash)) => (name.to_owned(), hash.to_owned()),
+        None => ("cli".to_owned(), subgraph),
+    };
+
+    let name = SubgraphName::new(name)
+        .expect("Subgraph name must contain only a-z, A-Z, 0-9, '-' and '_'");
+    let subgraph_id = DeploymentHash::new(hash).expect("Subgraph hash must be a valid IPFS hash");
+    let debug_fork = debug_fork
+        .map(DeploymentHash::new)
+        .map(|h| h.expect("Debug fork hash must be a valid IPFS hash"));
+
+    graph::spawn(
+        async move {
+            registrar.create_subgraph(name.clone()).await?;
+            registrar
+                .create_subgraph_version(name, subgraph_id, node_id, debug_fork, start_block)
+                .await
+        }
+        .map_err(|e| panic!("Failed to deploy subgraph from `--subgraph` flag: {}", e)),
+    );
+}
+
+/// Wait for SIGTERM or SIGINT and then shut down: stop all subgraphs once
+/// they have finished the block they are processing, wait for their writes
+/// to be flushed, for the HTTP servers to answer the requests they are
+/// serving and for the connection `pools` to become idle, and exit. If
+/// that takes longer than `GRAPH_SHUTDOWN_TIMEOUT`, exit anyway; subgraphs
+/// will then redo the blocks whose writes did not make it on restart
+pub async fn shutdown_on_signal<S: SubgraphStore>(
+    logger: Logger,
+    instance_manager: Arc<SubgraphInstanceManager<S>>,
+    pools: Vec<ConnectionPool>,
+) {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut sigterm = signal(SignalKind::terminate()).expect("failed to listen for SIGTERM");
+    let mut sigint = signal(SignalKind::interrupt()).expect("failed to listen for SIGINT");
+    tokio::select! {
+        _ = sigterm.recv() => info!(logger, "Received SIGTERM, shutting down"),
+        _ = sigint.recv() => info!(logger, "Received SIGINT, shutting down"),
+    }
+
+    let timeout = ENV_VARS.shutdown_timeout;
+    let shutdown = async {
+        // Stop taking queries while subgraphs finish their blocks, but
+        // answer the ones that are already running
+        futures::future::join(instance_manager.shutdown(), http::drain()).await;
+        info!(
+            logger,
+            "All subgraphs stopped, waiting for database connections"
+        );
+        futures::future::join_all(pools.iter().map(|pool| pool.close())).await;
+    };
+    match tokio::time::timeout(timeout, shutdown).await {
+        Ok(()) => {
+            info!(logger, "Shutdown complete");
+            std::process::exit(0);
+        }
+        Err(_) => {
+            warn!(logger, "Shutdown did not complete in time, exiting anyway";
+                  "timeout_s" => timeout.as_secs());
+            std::process::exit(1);
+        }
+    }
+}
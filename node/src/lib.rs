@@ -8,8 +8,14 @@ extern crate diesel;
 
 pub mod chain;
 pub mod config;
+pub mod config_watcher;
+pub mod dev;
+pub mod health;
+pub mod ingestor;
+pub mod launch;
 pub mod opt;
 pub mod store_builder;
+pub mod store_factory;
 
 pub mod manager;
 
@@ -2,6 +2,8 @@ use ethereum::{BlockIngestor as EthereumBlockIngestor, EthereumAdapterTrait, Eth
 use git_testament::{git_testament, render_testament};
 use graph::blockchain::firehose_block_ingestor::FirehoseBlockIngestor;
 use graph::blockchain::{Block as BlockchainBlock, Blockchain, BlockchainKind, BlockchainMap};
+use graph::components::server::health::RunningIngestors;
+use graph::components::server::tls;
 use graph::components::store::BlockStore;
 use graph::data::graphql::effort::LoadManager;
 use graph::env::EnvVars;
@@ -14,8 +16,9 @@ use graph_chain_ethereum as ethereum;
 use graph_chain_near::{self as near, HeaderOnlyBlock as NearFirehoseHeaderOnlyBlock};
 use graph_chain_tendermint::{self as tendermint, EventList as TendermintFirehoseEventList};
 use graph_core::{
-    LinkResolver, MetricsRegistry, SubgraphAssignmentProvider as IpfsSubgraphAssignmentProvider,
-    SubgraphInstanceManager, SubgraphRegistrar as IpfsSubgraphRegistrar,
+    LinkResolver, MetricsRegistry, StoredLinkResolver,
+    SubgraphAssignmentProvider as IpfsSubgraphAssignmentProvider, SubgraphInstanceManager,
+    SubgraphRegistrar as IpfsSubgraphRegistrar,
 };
 use graph_graphql::prelude::GraphQlRunner;
 use graph_node::chain::{
@@ -23,14 +26,24 @@ use graph_node::chain::{
     create_firehose_networks, create_ipfs_clients,
 };
 use graph_node::config::Config;
+use graph_node::config_watcher::ConfigWatcher;
+use graph_node::dev::DevNode;
+use graph_node::health::NodeHealthCheck;
+use graph_node::ingestor::lead_ingestion;
+use graph_node::launch::{deploy_subgraph_from_flag, shutdown_on_signal};
 use graph_node::opt;
 use graph_node::store_builder::StoreBuilder;
+use graph_node::store_factory::{SqliteStoreFactory, StoreFactories, StoreFactory as _};
 use graph_server_http::GraphQLServer as GraphQLQueryServer;
 use graph_server_index_node::IndexNodeServer;
 use graph_server_json_rpc::JsonRpcServer;
 use graph_server_metrics::PrometheusMetricsServer;
 use graph_server_websocket::SubscriptionServer as GraphQLSubscriptionServer;
-use graph_store_postgres::{register_jobs as register_store_jobs, ChainHeadUpdateListener, Store};
+use graph_store_postgres::{
+    register_change_journal_job, register_heartbeat_jobs, register_job_queue_job,
+    register_jobs as register_store_jobs, register_load_jobs, register_query_activity_job,
+    ChainHeadUpdateListener, NodeRole, Store,
+};
 use std::collections::BTreeMap;
 use std::io::{BufRead, BufReader};
 use std::path::Path;
@@ -121,7 +134,8 @@ async fn main() {
 
     let node_id =
         NodeId::new(opt.node_id.clone()).expect("Node ID must contain only a-z, A-Z, 0-9, and '_'");
-    let query_only = config.query_only(&node_id);
+    let node_role = config.role(&node_id);
+    let query_only = node_role == NodeRole::Query;
 
     // Obtain subgraph related command-line arguments
     let subgraph = opt.subgraph.clone();
@@ -219,6 +233,34 @@ async fn main() {
     let contention_logger = logger.clone();
 
     let expensive_queries = read_expensive_queries().unwrap();
+    let query_limits = config.query_limits();
+
+    // `--dev` keeps everything in SQLite; any other node needs Postgres
+    let store_factory = StoreFactories::with_defaults()
+        .for_url(&config.primary_store().connection)
+        .unwrap_or_else(|e| panic!("Unsupported primary store: {}", e));
+    store_factory
+        .check(&config)
+        .unwrap_or_else(|e| panic!("Invalid store configuration: {}", e));
+    if store_factory.name() == SqliteStoreFactory.name() {
+        let dev_node = DevNode {
+            database: config.primary_store().connection.clone(),
+            opt,
+            node_id,
+            logger_factory,
+            link_resolver,
+            metrics_registry,
+            metrics_server,
+            eth_networks,
+            expensive_queries,
+            query_limits,
+            fork_base,
+        };
+        graph::spawn(dev_node.launch(logger.clone()));
+        spawn_contention_checker(contention_logger);
+        futures::future::pending::<()>().await;
+        return;
+    }
 
     let store_builder = StoreBuilder::new(
         &logger,
@@ -233,6 +275,7 @@ async fn main() {
         let subscription_manager = store_builder.subscription_manager();
         let chain_head_update_listener = store_builder.chain_head_update_listener();
         let primary_pool = store_builder.primary_pool();
+        let pools = store_builder.pools();
 
         // To support the ethereum block ingestor, ethereum networks are referenced both by the
         // `blockchain_map` and `ethereum_chains`. Future chains should be referred to only in
@@ -242,6 +285,20 @@ async fn main() {
         let (eth_networks, ethereum_idents) =
             connect_ethereum_networks(&logger, eth_networks).await;
 
+        // Pick up changes to the deployment rules and providers without a
+        // restart if that was asked for
+        if let (Some(path), Some(interval)) = (&opt.config, ENV_VARS.config_reload_interval) {
+            let watcher = ConfigWatcher::new(
+                &logger,
+                path,
+                config,
+                store_builder.placer(),
+                eth_networks.clone(),
+                metrics_registry.clone(),
+            );
+            graph::spawn(watcher.run(interval));
+        }
+
         let (near_networks, near_idents) =
             connect_firehose_networks::<NearFirehoseHeaderOnlyBlock>(
                 &logger,
@@ -305,17 +362,27 @@ async fn main() {
             expensive_queries,
             metrics_registry.clone(),
         ));
-        let graphql_runner = Arc::new(GraphQlRunner::new(
-            &logger,
+        let graphql_runner = Arc::new(
+            GraphQlRunner::new(
+                &logger,
+                network_store.clone(),
+                subscription_manager.clone(),
+                load_manager,
+                metrics_registry.clone(),
+            )
+            .with_query_limits(query_limits),
+        );
+        let ingestors = Arc::new(RunningIngestors::default());
+        let health = Arc::new(NodeHealthCheck::new(
             network_store.clone(),
-            subscription_manager.clone(),
-            load_manager,
-            metrics_registry.clone(),
+            ingestors.clone(),
+            !opt.disable_block_ingestor,
         ));
         let mut graphql_server = GraphQLQueryServer::new(
             &logger_factory,
             graphql_metrics_registry,
             graphql_runner.clone(),
+            health,
             node_id.clone(),
         );
         let subscription_server =
@@ -336,8 +403,10 @@ async fn main() {
                 start_block_ingestor(
                     &logger,
                     &logger_factory,
+                    &network_store,
                     block_polling_interval,
                     ethereum_chains,
+                    &ingestors,
                 );
             }
 
@@ -345,11 +414,13 @@ async fn main() {
                 &logger,
                 &network_store,
                 near_chains,
+                &ingestors,
             );
             start_firehose_block_ingestor::<_, TendermintFirehoseEventList>(
                 &logger,
                 &network_store,
                 tendermint_chains,
+                &ingestors,
             );
 
             // Start a task runner
@@ -362,21 +433,87 @@ async fn main() {
             );
             graph::spawn_blocking(job_runner.start());
         }
+
+        // Let other nodes know that this node is alive and which
+        // deployments it answered queries for; index nodes also take over
+        // deployments from index nodes that stopped doing that, report
+        // their load so assignments can be evened out, and write the change
+        // journals of their deployments
+        let mut assignment_runner = graph::util::jobs::Runner::new(&logger);
+        register_heartbeat_jobs(
+            &mut assignment_runner,
+            network_store.subgraph_store(),
+            node_id.clone(),
+            node_role,
+        );
+        register_query_activity_job(&mut assignment_runner, network_store.subgraph_store());
+        if node_role == NodeRole::Index {
+            register_load_jobs(
+                &mut assignment_runner,
+                network_store.subgraph_store(),
+                prometheus_registry.clone(),
+                node_id.clone(),
+            );
+            register_change_journal_job(
+                &mut assignment_runner,
+                network_store.subgraph_store(),
+                node_id.clone(),
+            );
+        }
+        graph::spawn_blocking(assignment_runner.start());
+
+        // Index nodes work on the queued jobs of long-running operations;
+        // they get a runner of their own so that they do not hold up
+        // heartbeats
+        if node_role == NodeRole::Index {
+            let mut job_queue_runner = graph::util::jobs::Runner::new(&logger);
+            register_job_queue_job(
+                &mut job_queue_runner,
+                network_store.subgraph_store(),
+                node_id.clone(),
+            );
+            graph::spawn_blocking(job_queue_runner.start());
+        }
+
+        // Index nodes are the ones that fill the outbox of entity changes,
+        // so they also empty it
+        match &ENV_VARS.kafka_brokers {
+            Some(brokers) if node_role == NodeRole::Index => {
+                start_kafka_publisher(&logger, network_store.subgraph_store(), brokers)
+            }
+            _ => {}
+        }
+
         let static_filters = ENV_VARS.experimental_static_filters;
 
-        let subgraph_instance_manager = SubgraphInstanceManager::new(
+        let subgraph_instance_manager = Arc::new(SubgraphInstanceManager::new(
             &logger_factory,
             network_store.subgraph_store(),
             blockchain_map.cheap_clone(),
             metrics_registry.clone(),
             link_resolver.clone(),
             static_filters,
-        );
+        ));
+
+        // Stop subgraphs at a block boundary and flush their writes when
+        // the node is asked to terminate
+        graph::spawn(shutdown_on_signal(
+            logger.clone(),
+            subgraph_instance_manager.cheap_clone(),
+            pools,
+        ));
+
+        // The files of deployments are also kept in the store so that
+        // starting and deploying subgraphs does not always need IPFS
+        let deployment_link_resolver = Arc::new(StoredLinkResolver::new(
+            link_resolver.clone(),
+            network_store.subgraph_store().file_registry(),
+        ));
 
         // Create IPFS-based subgraph provider
         let subgraph_provider = IpfsSubgraphAssignmentProvider::new(
             &logger_factory,
-            link_resolver.clone(),
+            deployment_link_resolver.clone(),
             subgraph_instance_manager,
         );
 
@@ -386,7 +523,7 @@ async fn main() {
         // Create named subgraph provider for resolving subgraph name->ID mappings
         let subgraph_registrar = Arc::new(IpfsSubgraphRegistrar::new(
             &logger_factory,
-            link_resolver,
+            deployment_link_resolver,
             Arc::new(subgraph_provider),
             network_store.subgraph_store(),
             subscription_manager,
@@ -417,52 +554,17 @@ async fn main() {
 
         // Add the CLI subgraph with a REST request to the admin server.
         if let Some(subgraph) = subgraph {
-            let (name, hash) = if subgraph.contains(':') {
-                let mut split = subgraph.split(':');
-                (split.next().unwrap(), split.next().unwrap().to_owned())
-            } else {
-                ("cli", subgraph)
-            };
-
-            let name = SubgraphName::new(name)
-                .expect("Subgraph name must contain only a-z, A-Z, 0-9, '-' and '_'");
-            let subgraph_id =
-                DeploymentHash::new(hash).expect("Subgraph hash must be a valid IPFS hash");
-            let debug_fork = opt
-                .debug_fork
-                .map(DeploymentHash::new)
-                .map(|h| h.expect("Debug fork hash must be a valid IPFS hash"));
-            let start_block = opt
-                .start_block
-                .map(|block| {
-                    let mut split = block.split(":");
-                    (
-                        // BlockHash
-                        split.next().unwrap().to_owned(),
-                        // BlockNumber
-                        split.next().unwrap().parse::<i64>().unwrap(),
-                    )
-                })
-                .map(|(hash, number)| BlockPtr::try_from((hash.as_str(), number)))
-                .map(Result::unwrap);
-
-            graph::spawn(
-                async move {
-                    subgraph_registrar.create_subgraph(name.clone()).await?;
-                    subgraph_registrar
-                        .create_subgraph_version(
-                            name,
-                            subgraph_id,
-                            node_id,
-                            debug_fork,
-                            start_block,
-                        )
-                        .await
-                }
-                .map_err(|e| panic!("Failed to deploy subgraph from `--subgraph` flag: {}", e)),
+            deploy_subgraph_from_flag(
+                subgraph_registrar,
+                subgraph,
+                opt.debug_fork,
+                opt.start_block,
+                node_id,
             );
         }
 
+        tls::init(&logger).expect("Failed to load TLS certificate");
+
         // Serve GraphQL queries over HTTP
         graph::spawn(
             graphql_server
@@ -492,9 +594,15 @@ async fn main() {
 
     graph::spawn(launch_services(logger.clone()));
 
-    // Periodically check for contention in the tokio threadpool. First spawn a
-    // task that simply responds to "ping" requests. Then spawn a separate
-    // thread to periodically ping it and check responsiveness.
+    spawn_contention_checker(contention_logger);
+
+    futures::future::pending::<()>().await;
+}
+
+/// Periodically check for contention in the tokio threadpool. First spawn a
+/// task that simply responds to "ping" requests. Then spawn a separate
+/// thread to periodically ping it and check responsiveness.
+fn spawn_contention_checker(contention_logger: Logger) {
     let (ping_send, mut ping_receive) = mpsc::channel::<crossbeam_channel::Sender<()>>(1);
     graph::spawn(async move {
         while let Some(pong_send) = ping_receive.recv().await {
@@ -525,8 +633,6 @@ async fn main() {
             }
         }
     });
-
-    futures::future::pending::<()>().await;
 }
 
 /// Return the hashmap of ethereum chains and also add them to `blockchain_map`.
@@ -574,7 +680,7 @@ fn ethereum_networks_as_chains(
                 firehose_endpoints.map_or_else(|| FirehoseEndpoints::new(), |v| v.clone()),
                 eth_adapters.clone(),
                 chain_head_update_listener.clone(),
-                ethereum::ENV_VARS.reorg_threshold,
+                ENV_VARS.reorg_threshold,
                 is_ingestible,
             );
             (network_name.clone(), Arc::new(chain))
@@ -691,8 +797,10 @@ fn near_networks_as_chains(
 fn start_block_ingestor(
     logger: &Logger,
     logger_factory: &LoggerFactory,
+    store: &Store,
     block_polling_interval: Duration,
     chains: HashMap<String, Arc<ethereum::Chain>>,
+    ingestors: &Arc<RunningIngestors>,
 ) {
     info!(
         logger,
@@ -736,17 +844,31 @@ fn start_block_ingestor(
             // The block ingestor must be configured to keep at least REORG_THRESHOLD ancestors,
             // because the json-rpc BlockStream expects blocks after the reorg threshold to be
             // present in the DB.
-            let block_ingestor = EthereumBlockIngestor::new(
-                logger,
-                ethereum::ENV_VARS.reorg_threshold,
-                eth_adapter,
-                chain.chain_store(),
-                block_polling_interval,
-            )
-            .expect("failed to create Ethereum block ingestor");
+            let chain_store = chain.chain_store();
+            let ingest = {
+                let logger = logger.clone();
+                move || {
+                    let block_ingestor = EthereumBlockIngestor::new(
+                        logger.clone(),
+                        ENV_VARS.reorg_threshold,
+                        eth_adapter.clone(),
+                        chain_store.clone(),
+                        block_polling_interval,
+                    )
+                    .expect("failed to create Ethereum block ingestor");
+                    block_ingestor.into_polling_stream()
+                }
+            };
 
-            // Run the Ethereum block ingestor in the background
-            graph::spawn(block_ingestor.into_polling_stream());
+            // Run the Ethereum block ingestor in the background whenever
+            // this node is the one that ingests blocks for the chain
+            graph::spawn(lead_ingestion(
+                logger,
+                store.block_store(),
+                ingestors.clone(),
+                network_name.clone(),
+                ingest,
+            ));
         });
 }
 
@@ -760,6 +882,7 @@ fn start_firehose_block_ingestor<C, M>(
     logger: &Logger,
     store: &Store,
     chains: HashMap<String, FirehoseChain<C>>,
+    ingestors: &Arc<RunningIngestors>,
 ) where
     C: Blockchain,
     M: prost::Message + BlockchainBlock + Default + 'static,
@@ -792,14 +915,25 @@ fn start_firehose_block_ingestor<C, M>(
 
             match store.block_store().chain_store(network_name.as_ref()) {
                 Some(s) => {
-                    let block_ingestor = FirehoseBlockIngestor::<M>::new(
-                        s,
-                        endpoint.clone(),
-                        logger.new(o!("component" => "FirehoseBlockIngestor", "provider" => endpoint.provider.clone())),
-                    );
+                    let logger = logger.new(o!("component" => "FirehoseBlockIngestor", "provider" => endpoint.provider.clone()));
+                    let ingest = {
+                        let logger = logger.clone();
+                        let endpoint = endpoint.clone();
+                        move || {
+                            FirehoseBlockIngestor::<M>::new(s.clone(), endpoint.clone(), logger.clone()).run()
+                        }
+                    };
 
                     // Run the Firehose block ingestor in the background
-                    graph::spawn(block_ingestor.run());
+                    // whenever this node is the one that ingests blocks for
+                    // the chain
+                    graph::spawn(lead_ingestion(
+                        logger,
+                        store.block_store(),
+                        ingestors.clone(),
+                        network_name.clone(),
+                        ingest,
+                    ));
                 },
                 None => {
                     error!(logger, "Not starting firehose block ingestor (no chain store available)"; "network_name" => &network_name);
@@ -807,3 +941,28 @@ fn start_firehose_block_ingestor<C, M>(
             }
         });
 }
+
+/// Publish the entity changes that the store records in its outbox to the
+/// Kafka brokers `brokers`
+#[cfg(feature = "kafka")]
+fn start_kafka_publisher<S: SubgraphStore>(logger: &Logger, store: Arc<S>, brokers: &str) {
+    let publisher = graph_core::kafka::KafkaPublisher::new(
+        logger,
+        store,
+        brokers,
+        ENV_VARS.kafka_topic.clone(),
+    )
+    .expect("failed to create Kafka producer");
+    graph::spawn(publisher.run());
+}
+
+#[cfg(not(feature = "kafka"))]
+fn start_kafka_publisher<S: SubgraphStore>(logger: &Logger, _store: Arc<S>, _brokers: &str) {
+    // The changes pile up in the outbox until a node that can publish them
+    // comes along
+    error!(
+        logger,
+        "GRAPH_KAFKA_BROKERS is set, but graph-node was built without Kafka support; \
+         build it with `--features kafka` to publish entity changes"
+    );
+}
@@ -2,7 +2,7 @@ use diesel::{ExpressionMethods, JoinOnDsl, OptionalExtension, QueryDsl, RunQuery
 use std::{collections::HashMap, sync::Arc, time::SystemTime};
 
 use graph::{
-    components::store::BlockStore as _,
+    components::store::{BlockStore as _, JobRequest},
     prelude::{
         anyhow::{anyhow, bail, Error},
         chrono::{DateTime, Duration, SecondsFormat, Utc},
@@ -15,6 +15,7 @@ use graph_store_postgres::{
 };
 use graph_store_postgres::{connection_pool::ConnectionPool, Shard, Store, SubgraphStore};
 
+use crate::manager::commands;
 use crate::manager::deployment::DeploymentSearch;
 use crate::manager::display::List;
 
@@ -86,6 +87,7 @@ pub async fn create(
     shards: Vec<String>,
     node: String,
     block_offset: u32,
+    background: bool,
 ) -> Result<(), Error> {
     let block_offset = block_offset as i32;
     let subgraph_store = store.subgraph_store();
@@ -128,6 +130,16 @@ pub async fn create(
     let shard = Shard::new(shard)?;
     let node = NodeId::new(node.clone()).map_err(|()| anyhow!("invalid node id `{}`", node))?;
 
+    if background {
+        let request = JobRequest::Copy {
+            shard: shard.to_string(),
+            node: node.to_string(),
+            block_hash: base_ptr.hash_hex(),
+            block_number: base_ptr.number,
+        };
+        return commands::job::enqueue(&subgraph_store, &src, request);
+    }
+
     let dst = subgraph_store.copy_deployment(&src, shard, node, base_ptr)?;
 
     println!("created deployment {} as copy of {}", dst, src);
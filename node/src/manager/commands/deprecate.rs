@@ -0,0 +1,42 @@
+use std::sync::Arc;
+
+use graph::data::subgraph::SubgraphDeprecation;
+use graph::prelude::{anyhow, Error, SubgraphName, SubgraphStore as _};
+use graph_store_postgres::SubgraphStore;
+
+fn subgraph_name(store: &SubgraphStore, name: String) -> Result<SubgraphName, Error> {
+    let name = SubgraphName::new(name.clone())
+        .map_err(|()| anyhow!("illegal subgraph name `{}`", name))?;
+    if !store.subgraph_exists(&name)? {
+        return Err(anyhow!("there is no subgraph `{}`", name));
+    }
+    Ok(name)
+}
+
+pub fn set(
+    store: Arc<SubgraphStore>,
+    name: String,
+    removal_date: String,
+    message: String,
+) -> Result<(), Error> {
+    let name = subgraph_name(&store, name)?;
+    let removal_date = SubgraphDeprecation::parse_date(&removal_date)
+        .map_err(|e| anyhow!("invalid removal date `{}`: {}", removal_date, e))?;
+
+    store.set_deprecation(
+        &name,
+        Some(SubgraphDeprecation {
+            message,
+            removal_date,
+        }),
+    )?;
+    println!("deprecated subgraph {}", name);
+    Ok(())
+}
+
+pub fn clear(store: Arc<SubgraphStore>, name: String) -> Result<(), Error> {
+    let name = subgraph_name(&store, name)?;
+    store.set_deprecation(&name, None)?;
+    println!("removed the deprecation notice of subgraph {}", name);
+    Ok(())
+}
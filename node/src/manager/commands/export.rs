@@ -0,0 +1,39 @@
+use std::sync::Arc;
+
+use graph::components::store::{ExportFormat, ExportRequest, JobRequest};
+use graph::prelude::{anyhow, BlockNumber, SubgraphStore as _};
+use graph_store_postgres::{connection_pool::ConnectionPool, SubgraphStore};
+
+use crate::manager::commands;
+use crate::manager::deployment::DeploymentSearch;
+use crate::manager::display::List;
+
+pub fn run(
+    store: Arc<SubgraphStore>,
+    primary: ConnectionPool,
+    search: DeploymentSearch,
+    entity_types: Vec<String>,
+    block: Option<BlockNumber>,
+    format: ExportFormat,
+    destination: String,
+    background: bool,
+) -> Result<(), anyhow::Error> {
+    let locator = search.locate_unique(&primary)?;
+    let request = ExportRequest {
+        entity_types,
+        block,
+        format,
+        destination,
+    };
+    if background {
+        return commands::job::enqueue(&store, &locator, JobRequest::Export(request));
+    }
+    let files = store.export(&locator, &request)?;
+
+    let mut list = List::new(vec!["entity type", "rows", "file"]);
+    for file in files {
+        list.append(vec![file.entity_type, file.rows.to_string(), file.path]);
+    }
+    list.render();
+    Ok(())
+}
@@ -0,0 +1,43 @@
+use std::io::Write;
+use std::path::Path;
+use std::sync::Arc;
+
+use graph::anyhow::{anyhow, Error};
+use graph::data::subgraph::local;
+use graph::prelude::SubgraphStore as _;
+use graph_store_postgres::SubgraphStore;
+
+/// Write the deployment file for `link` that the store keeps to `output`,
+/// or to stdout. Reading the file checks it against its digest
+pub fn get(store: Arc<SubgraphStore>, link: String, output: Option<String>) -> Result<(), Error> {
+    let hash = link.trim_start_matches("/ipfs/");
+    let content = store
+        .file_registry()
+        .get_file(hash)?
+        .ok_or_else(|| anyhow!("the store does not have a deployment file for {}", link))?;
+    match output {
+        Some(output) => {
+            std::fs::write(&output, &content)?;
+            eprintln!("wrote {} bytes for {} to {}", content.len(), link, output);
+        }
+        None => std::io::stdout().write_all(&content)?,
+    }
+    Ok(())
+}
+
+/// Add the subgraph at the local `path` to the store and print its
+/// deployment hash. The manifest can only refer to files in its own
+/// directory and underneath it
+pub fn import(store: Arc<SubgraphStore>, path: String) -> Result<(), Error> {
+    let file = Path::new(path.strip_prefix(local::FILE_SCHEME).unwrap_or(&path))
+        .canonicalize()
+        .map_err(|e| anyhow!("can not read {}: {}", path, e))?;
+    let root = if file.is_dir() {
+        file.as_path()
+    } else {
+        file.parent().unwrap_or(&file)
+    };
+    let hash = local::import(&file.to_string_lossy(), root, &*store.file_registry())?;
+    println!("{}", hash);
+    Ok(())
+}
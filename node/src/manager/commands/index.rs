@@ -68,3 +68,23 @@ pub async fn drop(
     println!("Dropped index {index_name}");
     Ok(())
 }
+
+pub async fn upgrade(
+    store: Arc<SubgraphStore>,
+    pool: ConnectionPool,
+    search: DeploymentSearch,
+) -> Result<(), anyhow::Error> {
+    let deployment_locator = search.locate_unique(&pool)?;
+    println!("Index upgrade started. Please wait.");
+    let (created, dropped) = store.upgrade_indexes(&deployment_locator).await?;
+    for index in &created {
+        println!("Created index {index}");
+    }
+    for index in &dropped {
+        println!("Dropped index {index}");
+    }
+    if created.is_empty() && dropped.is_empty() {
+        println!("The indexes of {deployment_locator} are up to date");
+    }
+    Ok(())
+}
@@ -0,0 +1,119 @@
+use std::sync::Arc;
+
+use graph::components::store::{DeploymentLocator, JobRequest};
+use graph::data::subgraph::status::{JobFilter, JobInfo, JobState};
+use graph::prelude::anyhow::{self, bail};
+use graph_store_postgres::SubgraphStore;
+
+use crate::manager::display::List;
+
+/// Queue a job that runs `request` against `deployment`
+pub fn enqueue(
+    store: &SubgraphStore,
+    deployment: &DeploymentLocator,
+    request: JobRequest,
+) -> Result<(), anyhow::Error> {
+    let id = store.enqueue_job(deployment, &request)?;
+    println!(
+        "queued {} job {} for {}; use `graphman job info {}` to follow it",
+        request.kind(),
+        id,
+        deployment,
+        id
+    );
+    Ok(())
+}
+
+fn progress(job: &JobInfo) -> String {
+    match job.total {
+        Some(total) if total > 0 => format!(
+            "{} of {} ({:.1}%)",
+            job.done,
+            total,
+            job.done as f64 * 100.0 / total as f64
+        ),
+        Some(total) => format!("{} of {}", job.done, total),
+        None => job.done.to_string(),
+    }
+}
+
+fn render(jobs: Vec<JobInfo>) {
+    let mut list = List::new(vec![
+        "id",
+        "deployment",
+        "kind",
+        "state",
+        "node",
+        "progress",
+        "created",
+        "finished",
+        "result",
+    ]);
+    for job in jobs {
+        let state = if job.cancel_requested && !job.state.is_finished() {
+            format!("{} (cancelling)", job.state)
+        } else {
+            job.state.to_string()
+        };
+        list.append(vec![
+            job.id.to_string(),
+            job.deployment.clone(),
+            job.request.kind().to_string(),
+            state,
+            job.node.clone().unwrap_or_default(),
+            progress(&job),
+            job.created_at.to_rfc3339(),
+            job.finished_at
+                .map(|at| at.to_rfc3339())
+                .unwrap_or_default(),
+            job.error.or(job.outcome).unwrap_or_default(),
+        ]);
+    }
+    list.render();
+}
+
+pub fn list(
+    store: Arc<SubgraphStore>,
+    deployment: Option<String>,
+    all: bool,
+) -> Result<(), anyhow::Error> {
+    let states = if all {
+        vec![]
+    } else {
+        vec![JobState::Queued, JobState::Running]
+    };
+    let jobs = store.jobs(JobFilter {
+        id: None,
+        deployment,
+        states,
+    })?;
+    if jobs.is_empty() {
+        println!("no jobs");
+    }
+    render(jobs);
+    Ok(())
+}
+
+pub fn info(store: Arc<SubgraphStore>, id: i32) -> Result<(), anyhow::Error> {
+    let jobs = store.jobs(JobFilter {
+        id: Some(id),
+        ..JobFilter::default()
+    })?;
+    if jobs.is_empty() {
+        bail!("there is no job with id {}", id);
+    }
+    render(jobs);
+    Ok(())
+}
+
+pub fn cancel(store: Arc<SubgraphStore>, id: i32) -> Result<(), anyhow::Error> {
+    match store.cancel_job(id)? {
+        JobState::Running => println!(
+            "job {} will be cancelled once the node running it notices",
+            id
+        ),
+        JobState::Cancelled => println!("job {} is cancelled", id),
+        state => println!("job {} is already {}", id, state),
+    }
+    Ok(())
+}
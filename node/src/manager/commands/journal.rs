@@ -0,0 +1,116 @@
+use std::slice;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use graph::anyhow::bail;
+use graph::prelude::{anyhow, BlockNumber, NodeId};
+use graph_store_postgres::{connection_pool::ConnectionPool, Store, SubgraphStore};
+
+use crate::manager::commands::rewind::block_ptr;
+use crate::manager::deployment::DeploymentSearch;
+
+const PAUSED: &str = "paused_";
+
+pub fn start(
+    store: Arc<SubgraphStore>,
+    primary: ConnectionPool,
+    search: DeploymentSearch,
+    node: Option<String>,
+) -> Result<(), anyhow::Error> {
+    let mut deployments = search.lookup(&primary)?;
+    let deployment = match deployments.len() {
+        0 => bail!("Found no deployment for `{}`", search),
+        1 => deployments.pop().unwrap(),
+        n => bail!("Found {} deployments for `{}`", n, search),
+    };
+    let locator = deployment.locator();
+
+    // The journal stays with the node that writes it, even when the
+    // deployment is moved to another node later
+    let node = match node.or(deployment.node_id) {
+        Some(node) => node.strip_prefix(PAUSED).unwrap_or(&node).to_string(),
+        None => bail!(
+            "{} is not assigned to a node, use `--node` to say which node writes its journal",
+            locator
+        ),
+    };
+    let node = NodeId::new(node.clone()).map_err(|()| anyhow!("invalid node id `{}`", node))?;
+    if store.start_change_journal(&locator, &node)? {
+        println!(
+            "started the change journal for {}, written by {}",
+            locator, node
+        );
+    } else {
+        println!("the change journal for {} was already on", locator);
+    }
+    Ok(())
+}
+
+pub fn stop(
+    store: Arc<SubgraphStore>,
+    primary: ConnectionPool,
+    search: DeploymentSearch,
+) -> Result<(), anyhow::Error> {
+    let locator = search.locate_unique(&primary)?;
+    if store.stop_change_journal(&locator)? {
+        println!("stopped the change journal for {}", locator);
+    } else {
+        println!("the change journal for {} was not on", locator);
+    }
+    Ok(())
+}
+
+pub fn apply(
+    primary: ConnectionPool,
+    store: Arc<Store>,
+    search: DeploymentSearch,
+    dir: String,
+    block_hash: String,
+    block_number: BlockNumber,
+    force: bool,
+    sleep: Duration,
+) -> Result<(), anyhow::Error> {
+    let subgraph_store = store.subgraph_store();
+    let mut deployments = search.lookup(&primary)?;
+    let deployment = match deployments.len() {
+        0 => bail!("Found no deployment for `{}`", search),
+        1 => deployments.pop().unwrap(),
+        n => bail!("Found {} deployments for `{}`", n, search),
+    };
+    let locator = deployment.locator();
+    let ptr = block_ptr(
+        store.block_store(),
+        slice::from_ref(&search),
+        slice::from_ref(&deployment),
+        &block_hash,
+        block_number,
+        force,
+    )?;
+
+    // The deployment must not index while we change its data
+    let node = deployment
+        .node_id
+        .as_ref()
+        .filter(|node| !node.starts_with(PAUSED));
+    if let Some(node) = node {
+        let paused = NodeId::new(format!("{}{}", PAUSED, node)).expect("paused_ node id is valid");
+        subgraph_store.reassign_subgraph(&locator, &paused)?;
+        println!("paused {}, waiting for it to stop", locator);
+        thread::sleep(sleep);
+    }
+
+    let applied = subgraph_store.apply_change_journal(&locator, &dir, &ptr);
+
+    if let Some(node) = node {
+        let node = NodeId::new(node.clone()).expect("node id is valid");
+        subgraph_store.reassign_subgraph(&locator, &node)?;
+        println!("resumed {}", locator);
+    }
+
+    println!(
+        "applied {} batches of the change journal in {} to {}, which is now at block {}",
+        applied?, dir, locator, ptr.number
+    );
+    Ok(())
+}
@@ -0,0 +1,69 @@
+use std::collections::HashMap;
+use std::fs;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use graph::anyhow::bail;
+use graph::prelude::{anyhow, NodeId, Schema, SubgraphStore as _};
+use graph_store_postgres::{connection_pool::ConnectionPool, SubgraphStore};
+
+use crate::manager::deployment::DeploymentSearch;
+
+pub fn run(
+    primary: ConnectionPool,
+    store: Arc<SubgraphStore>,
+    search: DeploymentSearch,
+    schema_file: String,
+    sleep: Duration,
+) -> Result<(), anyhow::Error> {
+    const PAUSED: &str = "paused_";
+
+    let loc = search.locate_unique(&primary)?;
+
+    let raw = fs::read_to_string(&schema_file)
+        .map_err(|e| anyhow!("can not read schema file {}: {}", schema_file, e))?;
+    let schema = Schema::parse(&raw, loc.hash.clone())?;
+    if let Err(errors) = schema.validate(&HashMap::new()) {
+        let errors = errors
+            .iter()
+            .map(|e| e.to_string())
+            .collect::<Vec<_>>()
+            .join("\n    - ");
+        bail!(
+            "the schema in {} is not valid:\n    - {}",
+            schema_file,
+            errors
+        );
+    }
+
+    let node = store.assigned_node(&loc)?;
+    let paused = match &node {
+        Some(node) if !node.as_str().starts_with(PAUSED) => {
+            println!("Pausing {}", loc);
+            let paused = NodeId::new(format!("{}{}", PAUSED, node.as_str()))
+                .expect("paused_ node id is valid");
+            store.reassign_subgraph(&loc, &paused)?;
+            // There's no good way to tell that a subgraph has in fact
+            // stopped indexing. We sleep and hope for the best.
+            println!(
+                "Waiting {}s to make sure pausing was processed",
+                sleep.as_secs()
+            );
+            thread::sleep(sleep);
+            true
+        }
+        _ => false,
+    };
+
+    println!("Migrating the schema of {}", loc);
+    let res = store.migrate_schema(&loc.hash, &schema);
+
+    if paused {
+        println!("Resuming {}", loc);
+        store.reassign_subgraph(&loc, node.as_ref().unwrap())?;
+    }
+    res?;
+    println!("Migrated {} to the schema in {}", loc, schema_file);
+    Ok(())
+}
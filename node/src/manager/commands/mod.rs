@@ -3,13 +3,25 @@ pub mod chain;
 pub mod config;
 pub mod copy;
 pub mod create;
+pub mod deprecate;
+pub mod export;
+pub mod file;
 pub mod index;
 pub mod info;
+pub mod job;
+pub mod journal;
 pub mod listen;
+pub mod migrate_schema;
+pub mod partition;
 pub mod query;
+pub mod rebalance;
 pub mod remove;
+pub mod replay;
 pub mod rewind;
 pub mod run;
+pub mod snapshot;
 pub mod stats;
+#[cfg(feature = "test-harness")]
+pub mod test;
 pub mod txn_speed;
 pub mod unused_deployments;
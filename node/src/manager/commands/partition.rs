@@ -0,0 +1,50 @@
+use std::sync::Arc;
+
+use graph::components::store::JobRequest;
+use graph::prelude::{anyhow, BlockNumber};
+use graph_store_postgres::{connection_pool::ConnectionPool, SubgraphStore};
+
+use crate::manager::commands;
+use crate::manager::deployment::DeploymentSearch;
+
+pub fn create(
+    store: Arc<SubgraphStore>,
+    primary: ConnectionPool,
+    search: DeploymentSearch,
+    blocks: BlockNumber,
+    min_rows: i64,
+) -> Result<(), anyhow::Error> {
+    let locator = search.locate_unique(&primary)?;
+    let tables = store.partition(&locator, blocks, min_rows)?;
+    if tables.is_empty() {
+        println!("no tables of {} needed partitioning", locator);
+    }
+    for table in tables {
+        println!("partitioned {}", table);
+    }
+    Ok(())
+}
+
+pub fn prune(
+    store: Arc<SubgraphStore>,
+    primary: ConnectionPool,
+    search: DeploymentSearch,
+    before: BlockNumber,
+    background: bool,
+) -> Result<(), anyhow::Error> {
+    let locator = search.locate_unique(&primary)?;
+    if background {
+        return commands::job::enqueue(&store, &locator, JobRequest::Prune { before });
+    }
+    let partitions = store.prune_partitions(&locator, before)?;
+    if partitions.is_empty() {
+        println!(
+            "no partitions of {} only hold history before block {}",
+            locator, before
+        );
+    }
+    for partition in partitions {
+        println!("dropped {}", partition);
+    }
+    Ok(())
+}
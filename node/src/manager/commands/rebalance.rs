@@ -0,0 +1,38 @@
+use std::sync::Arc;
+
+use graph::env::ENV_VARS;
+use graph::prelude::anyhow::Error;
+use graph_store_postgres::SubgraphStore;
+
+use crate::manager::display::List;
+
+pub fn run(
+    store: Arc<SubgraphStore>,
+    threshold: Option<f64>,
+    max_moves: Option<usize>,
+    dry_run: bool,
+) -> Result<(), Error> {
+    let threshold = threshold.unwrap_or(ENV_VARS.rebalance_threshold);
+    let max_moves = max_moves.unwrap_or(ENV_VARS.rebalance_max_moves);
+    let moves = store.rebalance(threshold, max_moves, dry_run)?;
+
+    if moves.is_empty() {
+        println!("assignments are balanced; nothing to move");
+        return Ok(());
+    }
+
+    let mut list = List::new(vec!["deployment", "from", "to", "handler s/min"]);
+    for mv in moves {
+        list.append(vec![
+            mv.deployment.to_string(),
+            mv.from.to_string(),
+            mv.to.to_string(),
+            format!("{:.2}", mv.load),
+        ]);
+    }
+    list.render();
+    if dry_run {
+        println!("dry run: no deployments were moved");
+    }
+    Ok(())
+}
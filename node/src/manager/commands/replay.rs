@@ -0,0 +1,378 @@
+//! Replay a block range of a deployment to check that its mappings are
+//! deterministic, or to see what a fixed mapping would change before
+//! rewinding. The blocks are indexed again by a copy of the deployment in
+//! another shard that is grafted onto the deployment right before the
+//! first block of the range; the copy is never activated, and assigned to
+//! a node that no index node uses, so that it does not affect queries or
+//! the live deployment. Afterwards, the entity changes of both are
+//! compared block by block and the copy is removed again
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use graph::anyhow::{anyhow, bail, Error};
+use graph::components::store::{BlockStore as _, DeploymentLocator};
+use graph::data::subgraph::schema::SubgraphHealth;
+use graph::prelude::{
+    tokio, BlockNumber, BlockPtr, ChainStore as _, Entity, EntityOperation, NodeId,
+    QueryStoreManager, SubgraphAssignmentProvider, SubgraphStore as _,
+};
+use graph::slog::{info, Logger};
+use graph_core::MetricsRegistry;
+use graph_store_postgres::command_support::catalog;
+use graph_store_postgres::connection_pool::ConnectionPool;
+use graph_store_postgres::{Shard, SubgraphStore};
+
+use crate::config::Config;
+use crate::manager::commands::run::{indexer, Indexer};
+use crate::manager::deployment::DeploymentSearch;
+use crate::store_builder::StoreBuilder;
+
+/// The entities that a deployment changed in a block, by entity type and
+/// id; `None` means that the entity was removed
+type Changes = BTreeMap<(String, String), Option<Entity>>;
+
+pub async fn run(
+    logger: Logger,
+    store_builder: StoreBuilder,
+    ipfs_url: Vec<String>,
+    config: Config,
+    metrics_registry: Arc<MetricsRegistry>,
+    search: DeploymentSearch,
+    shard: String,
+    node: String,
+    start: BlockNumber,
+    end: BlockNumber,
+    keep: bool,
+    timeout: Duration,
+) -> Result<(), Error> {
+    if start < 1 || end < start {
+        bail!("the block range {start}..={end} must start after block 0 and can not be empty");
+    }
+    let shards: Vec<_> = config.stores.keys().cloned().collect();
+    if !shards.contains(&shard) {
+        bail!(
+            "unknown shard {shard}, only shards {} are configured",
+            shards.join(", ")
+        )
+    }
+    let shard = Shard::new(shard)?;
+    let node = NodeId::new(node.clone()).map_err(|()| anyhow!("invalid node id `{}`", node))?;
+
+    let primary = store_builder.primary_pool();
+    let mut deployments = search.lookup(&primary)?;
+    let deployment = match deployments.len() {
+        0 => bail!("Found no deployment for `{}`", search),
+        1 => deployments.pop().unwrap(),
+        n => bail!("Found {} deployments for `{}`", n, search),
+    };
+    let src = deployment.locator();
+    if deployment.shard == shard.as_str() {
+        bail!(
+            "{} is in shard {}, but the copy that replays it must be in a different shard",
+            src,
+            shard
+        );
+    }
+
+    let Indexer {
+        network_store,
+        provider,
+        ..
+    } = indexer(
+        &logger,
+        store_builder,
+        &deployment.chain,
+        &ipfs_url,
+        &config,
+        metrics_registry,
+        &node,
+    )
+    .await?;
+    let subgraph_store = network_store.subgraph_store();
+
+    let query_store = network_store
+        .query_store(src.hash.clone().into(), true)
+        .await?;
+    let head = query_store
+        .block_ptr()
+        .await?
+        .ok_or_else(|| anyhow!("{} has not indexed any blocks yet", src))?;
+    if head.number < end {
+        bail!(
+            "{} has only indexed up to block {}, and can not be replayed up to block {}",
+            src,
+            head.number,
+            end
+        );
+    }
+
+    let chain_store = network_store
+        .block_store()
+        .chain_store(&deployment.chain)
+        .ok_or_else(|| {
+            anyhow!(
+                "could not find chain store for network {}",
+                deployment.chain
+            )
+        })?;
+    let base = start - 1;
+    let base_ptr = match chain_store.block_hashes_by_block_number(base)?.as_slice() {
+        [hash] => BlockPtr::from((*hash, base)),
+        [] => bail!("could not find a block with number {} in our cache", base),
+        hashes => bail!(
+            "the cache contains {} hashes for block number {}",
+            hashes.len(),
+            base
+        ),
+    };
+
+    let dst = subgraph_store.copy_deployment(&src, shard, node, base_ptr)?;
+    info!(
+        logger,
+        "Replaying blocks {} to {} of {} in {}", start, end, src, dst
+    );
+
+    // Whatever happens while replaying, don't leave the copy behind
+    let replayed = replay(
+        &logger,
+        provider.as_ref(),
+        &subgraph_store,
+        &src,
+        &dst,
+        start,
+        end,
+        timeout,
+    )
+    .await;
+    if keep {
+        println!("kept the copy {} that replayed the blocks", dst);
+    } else {
+        // Give the subgraph time to stop before removing its data; see the
+        // same wait in `graphman run`
+        tokio::time::sleep(Duration::from_millis(4000)).await;
+        remove(&primary, &subgraph_store, &dst)?;
+    }
+
+    let differences = replayed?;
+    if differences > 0 {
+        bail!(
+            "replaying blocks {} to {} produced {} entity changes that differ from the ones in the store",
+            start,
+            end,
+            differences
+        );
+    }
+    println!(
+        "replaying blocks {} to {} produced the same entity changes as the ones in the store",
+        start, end
+    );
+    Ok(())
+}
+
+/// Index blocks `start` to `end` with the copy `dst` and print how its
+/// entity changes differ from the ones of `src`. Return the number of
+/// differences
+async fn replay<P: SubgraphAssignmentProvider>(
+    logger: &Logger,
+    provider: &P,
+    subgraph_store: &Arc<SubgraphStore>,
+    src: &DeploymentLocator,
+    dst: &DeploymentLocator,
+    start: BlockNumber,
+    end: BlockNumber,
+    timeout: Duration,
+) -> Result<usize, Error> {
+    provider.start(dst.clone(), Some(end)).await?;
+    let waited = wait_for_copy(logger, subgraph_store, dst, end, timeout).await;
+    provider.stop(dst.clone()).await?;
+    waited?;
+
+    let mut differences = 0;
+    for block in start..=end {
+        let live = changes(subgraph_store.deployment_changes_in_block(src, block)?);
+        let replayed = changes(subgraph_store.deployment_changes_in_block(dst, block)?);
+        for difference in diff(&live, &replayed) {
+            differences += 1;
+            println!("block {:>10}  {}", block, difference);
+        }
+    }
+    Ok(differences)
+}
+
+/// Wait until the copy `dst` has indexed block `end`. Fail if it fails or
+/// if it does not make any progress for `timeout`
+async fn wait_for_copy(
+    logger: &Logger,
+    subgraph_store: &Arc<SubgraphStore>,
+    dst: &DeploymentLocator,
+    end: BlockNumber,
+    timeout: Duration,
+) -> Result<(), Error> {
+    let writable = subgraph_store
+        .clone()
+        .writable(logger.clone(), dst.id)
+        .await?;
+    let mut last = None;
+    let mut since = Instant::now();
+    loop {
+        tokio::time::sleep(Duration::from_millis(1000)).await;
+        let block = writable.block_ptr().await.map(|ptr| ptr.number);
+        if block != last {
+            last = block;
+            since = Instant::now();
+        }
+        let health = writable.health(&dst.hash).await?;
+        if copy_done(dst, last, health, end, since.elapsed(), timeout)? {
+            return Ok(());
+        }
+    }
+}
+
+/// Whether the copy `dst`, which is at `block` and has been there for
+/// `idle`, is done replaying up to `end`; an error if it never will be
+fn copy_done(
+    dst: &DeploymentLocator,
+    block: Option<BlockNumber>,
+    health: SubgraphHealth,
+    end: BlockNumber,
+    idle: Duration,
+    timeout: Duration,
+) -> Result<bool, Error> {
+    if block.map_or(false, |block| block >= end) {
+        return Ok(true);
+    }
+    let at = block.map_or("the start".to_string(), |block| format!("block {}", block));
+    if health == SubgraphHealth::Failed {
+        bail!(
+            "the copy {} failed at {}, see its status for the error",
+            dst,
+            at
+        );
+    }
+    if idle >= timeout {
+        bail!(
+            "the copy {} has been stuck at {} for {}s",
+            dst,
+            at,
+            idle.as_secs()
+        );
+    }
+    Ok(false)
+}
+
+fn changes(operations: Vec<EntityOperation>) -> Changes {
+    operations
+        .into_iter()
+        .map(|operation| match operation {
+            EntityOperation::Set { key, data } => {
+                ((key.entity_type.to_string(), key.entity_id), Some(data))
+            }
+            EntityOperation::Remove { key } => ((key.entity_type.to_string(), key.entity_id), None),
+        })
+        .collect()
+}
+
+/// Describe how the entities in `replayed` differ from the ones in `live`
+fn diff(live: &Changes, replayed: &Changes) -> Vec<String> {
+    let describe = |entity: Option<&Option<Entity>>| match entity {
+        None => "unchanged".to_string(),
+        Some(None) => "removed".to_string(),
+        Some(Some(entity)) => format!("{:?}", entity),
+    };
+
+    let mut keys: Vec<_> = live.keys().chain(replayed.keys()).collect();
+    keys.sort();
+    keys.dedup();
+    keys.into_iter()
+        .filter(|key| live.get(*key) != replayed.get(*key))
+        .map(|key| {
+            let (entity_type, id) = key;
+            format!(
+                "{}[{}]\n{:18}store:    {}\n{:18}replayed: {}",
+                entity_type,
+                id,
+                "",
+                describe(live.get(key)),
+                "",
+                describe(replayed.get(key))
+            )
+        })
+        .collect()
+}
+
+fn remove(
+    primary: &ConnectionPool,
+    subgraph_store: &SubgraphStore,
+    dst: &DeploymentLocator,
+) -> Result<(), Error> {
+    let conn = catalog::Connection::new(primary.get()?);
+    let site = conn
+        .locate_site(dst.clone())?
+        .ok_or_else(|| anyhow!("failed to locate site for {dst}"))?;
+    conn.unassign_subgraph(&site)?;
+    subgraph_store.remove_deployment(dst.id.into())?;
+    println!("removed the copy {} that replayed the blocks", dst);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use graph::components::store::{DeploymentId, DeploymentLocator};
+    use graph::data::subgraph::schema::SubgraphHealth;
+    use graph::entity;
+    use graph::prelude::{DeploymentHash, Entity};
+
+    use super::{copy_done, diff, Changes};
+
+    fn changes(entries: Vec<(&str, Option<Entity>)>) -> Changes {
+        entries
+            .into_iter()
+            .map(|(id, entity)| (("Token".to_string(), id.to_string()), entity))
+            .collect()
+    }
+
+    #[test]
+    fn diffs_changes() {
+        let live = changes(vec![
+            ("1", Some(entity! { id: "1", supply: 10 })),
+            ("2", None),
+            ("3", Some(entity! { id: "3", supply: 1 })),
+        ]);
+        let replayed = changes(vec![
+            ("1", Some(entity! { id: "1", supply: 10 })),
+            ("2", Some(entity! { id: "2", supply: 5 })),
+            ("4", None),
+        ]);
+
+        assert!(diff(&live, &live).is_empty());
+        let differences = diff(&live, &replayed);
+        assert_eq!(3, differences.len());
+        assert!(differences[0].starts_with("Token[2]"));
+        assert!(differences[0].contains("store:    removed"));
+        assert!(differences[1].contains("replayed: unchanged"));
+        assert!(differences[2].contains("store:    unchanged"));
+    }
+
+    #[test]
+    fn waits_for_copy() {
+        let dst =
+            DeploymentLocator::new(DeploymentId::new(1), DeploymentHash::new("Qmdst").unwrap());
+        let timeout = Duration::from_secs(60);
+        let done = |block, health, idle| copy_done(&dst, block, health, 10, idle, timeout);
+        let secs = Duration::from_secs;
+
+        assert!(!done(None, SubgraphHealth::Healthy, secs(1)).unwrap());
+        assert!(!done(Some(9), SubgraphHealth::Unhealthy, secs(59)).unwrap());
+        assert!(done(Some(10), SubgraphHealth::Healthy, secs(0)).unwrap());
+        // A copy that got to the end is done, even if it stopped there
+        assert!(done(Some(10), SubgraphHealth::Failed, secs(600)).unwrap());
+
+        let err = done(Some(5), SubgraphHealth::Failed, secs(1)).unwrap_err();
+        assert!(err.to_string().contains("failed at block 5"));
+        let err = done(None, SubgraphHealth::Healthy, secs(60)).unwrap_err();
+        assert!(err.to_string().contains("stuck at the start for 60s"));
+    }
+}
@@ -4,14 +4,15 @@ use std::time::Duration;
 use std::{collections::HashSet, convert::TryFrom};
 
 use graph::anyhow::bail;
-use graph::components::store::{BlockStore as _, ChainStore as _};
+use graph::components::store::{BlockStore as _, ChainStore as _, JobRequest};
 use graph::prelude::{anyhow, BlockNumber, BlockPtr, NodeId, SubgraphStore};
 use graph_store_postgres::BlockStore;
 use graph_store_postgres::{connection_pool::ConnectionPool, Store};
 
+use crate::manager::commands;
 use crate::manager::deployment::{Deployment, DeploymentSearch};
 
-fn block_ptr(
+pub(super) fn block_ptr(
     store: Arc<BlockStore>,
     searches: &[DeploymentSearch],
     deployments: &[Deployment],
@@ -65,6 +66,7 @@ pub fn run(
     block_number: BlockNumber,
     force: bool,
     sleep: Duration,
+    background: bool,
 ) -> Result<(), anyhow::Error> {
     const PAUSED: &str = "paused_";
 
@@ -92,6 +94,17 @@ pub fn run(
         force,
     )?;
 
+    if background {
+        for deployment in &deployments {
+            let request = JobRequest::Rewind {
+                block_hash: block_ptr_to.hash_hex(),
+                block_number: block_ptr_to.number,
+            };
+            commands::job::enqueue(&subgraph_store, &deployment.locator(), request)?;
+        }
+        return Ok(());
+    }
+
     println!("Pausing deployments");
     let mut paused = false;
     for deployment in &deployments {
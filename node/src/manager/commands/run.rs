@@ -28,6 +28,7 @@ use graph_core::{
     LinkResolver, MetricsRegistry, SubgraphAssignmentProvider as IpfsSubgraphAssignmentProvider,
     SubgraphInstanceManager, SubgraphRegistrar as IpfsSubgraphRegistrar,
 };
+use graph_store_postgres::{Store, SubgraphStore as DieselSubgraphStore};
 use url::Url;
 
 fn locate(store: &dyn SubgraphStore, hash: &str) -> Result<DeploymentLocator, anyhow::Error> {
@@ -39,27 +40,30 @@ fn locate(store: &dyn SubgraphStore, hash: &str) -> Result<DeploymentLocator, an
     }
 }
 
-pub async fn run(
-    logger: Logger,
-    store_builder: StoreBuilder,
-    network_name: String,
-    ipfs_url: Vec<String>,
-    config: Config,
-    metrics_ctx: MetricsContext,
-    node_id: NodeId,
-    subgraph: String,
-    stop_block: BlockNumber,
-) -> Result<(), anyhow::Error> {
-    println!(
-        "Run command: starting subgraph => {}, stop_block = {}",
-        subgraph, stop_block
-    );
+/// What a command needs to index subgraphs in-process: the stores, the
+/// chains that are configured for `network_name`, and a provider that
+/// starts subgraphs on them
+pub(super) struct Indexer {
+    pub network_store: Arc<Store>,
+    pub blockchain_map: Arc<BlockchainMap>,
+    pub link_resolver: Arc<LinkResolver>,
+    pub provider: Arc<IpfsSubgraphAssignmentProvider<SubgraphInstanceManager<DieselSubgraphStore>>>,
+}
 
-    let metrics_registry = metrics_ctx.registry.clone();
+pub(super) async fn indexer(
+    logger: &Logger,
+    store_builder: StoreBuilder,
+    network_name: &str,
+    ipfs_url: &Vec<String>,
+    config: &Config,
+    metrics_registry: Arc<MetricsRegistry>,
+    node_id: &NodeId,
+) -> Result<Indexer, anyhow::Error> {
+    let logger = logger.clone();
+    let network_name = network_name.to_string();
     let logger_factory = LoggerFactory::new(logger.clone(), None);
-
     // FIXME: Hard-coded IPFS config, take it from config file instead?
-    let ipfs_clients: Vec<_> = create_ipfs_clients(&logger, &ipfs_url);
+    let ipfs_clients: Vec<_> = create_ipfs_clients(&logger, ipfs_url);
 
     // Convert the clients into a link resolver. Since we want to get past
     // possible temporary DNS failures, make the resolver retry
@@ -68,11 +72,11 @@ pub async fn run(
         Arc::new(EnvVars::default()),
     ));
 
-    let eth_networks = create_ethereum_networks(logger.clone(), metrics_registry.clone(), &config)
+    let eth_networks = create_ethereum_networks(logger.clone(), metrics_registry.clone(), config)
         .await
         .expect("Failed to parse Ethereum networks");
     let firehose_networks_by_kind =
-        create_firehose_networks(logger.clone(), metrics_registry.clone(), &config)
+        create_firehose_networks(logger.clone(), metrics_registry.clone(), config)
             .await
             .expect("Failed to parse Firehose endpoints");
     let firehose_networks = firehose_networks_by_kind.get(&BlockchainKind::Ethereum);
@@ -82,8 +86,9 @@ pub async fn run(
         Some(adapters) => adapters.clone(),
         None => {
             return Err(format_err!(
-                "No ethereum adapters found, but required in this state of graphman run command"
-            ))
+            "No ethereum adapters found for network {}, but they are required to index subgraphs",
+            network_name
+        ))
         }
     };
 
@@ -116,7 +121,7 @@ pub async fn run(
         firehose_endpoints.map_or_else(|| FirehoseEndpoints::new(), |v| v.clone()),
         eth_adapters,
         chain_head_update_listener,
-        ethereum::ENV_VARS.reorg_threshold,
+        ENV_VARS.reorg_threshold,
         // We assume the tested chain is always ingestible for now
         true,
     );
@@ -127,14 +132,14 @@ pub async fn run(
     let static_filters = ENV_VARS.experimental_static_filters;
 
     let blockchain_map = Arc::new(blockchain_map);
-    let subgraph_instance_manager = SubgraphInstanceManager::new(
+    let subgraph_instance_manager = Arc::new(SubgraphInstanceManager::new(
         &logger_factory,
         subgraph_store.clone(),
         blockchain_map.clone(),
         metrics_registry.clone(),
         link_resolver.cheap_clone(),
         static_filters,
-    );
+    ));
 
     // Create IPFS-based subgraph provider
     let subgraph_provider = Arc::new(IpfsSubgraphAssignmentProvider::new(
@@ -143,6 +148,48 @@ pub async fn run(
         subgraph_instance_manager,
     ));
 
+    Ok(Indexer {
+        network_store,
+        blockchain_map,
+        link_resolver,
+        provider: subgraph_provider,
+    })
+}
+
+pub async fn run(
+    logger: Logger,
+    store_builder: StoreBuilder,
+    network_name: String,
+    ipfs_url: Vec<String>,
+    config: Config,
+    metrics_ctx: MetricsContext,
+    node_id: NodeId,
+    subgraph: String,
+    stop_block: BlockNumber,
+) -> Result<(), anyhow::Error> {
+    println!(
+        "Run command: starting subgraph => {}, stop_block = {}",
+        subgraph, stop_block
+    );
+
+    let logger_factory = LoggerFactory::new(logger.clone(), None);
+    let Indexer {
+        network_store,
+        blockchain_map,
+        link_resolver,
+        provider: subgraph_provider,
+    } = indexer(
+        &logger,
+        store_builder,
+        &network_name,
+        &ipfs_url,
+        &config,
+        metrics_ctx.registry.clone(),
+        &node_id,
+    )
+    .await?;
+    let subgraph_store = network_store.subgraph_store();
+
     let panicking_subscription_manager = Arc::new(PanicSubscriptionManager {});
 
     let subgraph_registrar = Arc::new(IpfsSubgraphRegistrar::new(
@@ -375,6 +422,7 @@ async fn create_ethereum_networks(
                             transport,
                             eth_rpc_metrics.clone(),
                             supports_eip_1898,
+                            web3.get_logs_limits(),
                         )
                         .await,
                     ),
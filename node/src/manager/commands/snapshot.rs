@@ -0,0 +1,52 @@
+use std::sync::Arc;
+
+use graph::prelude::{anyhow::anyhow, Error, NodeId};
+use graph_store_postgres::{connection_pool::ConnectionPool, Shard, SubgraphStore};
+
+use crate::manager::deployment::DeploymentSearch;
+use crate::manager::display::List;
+
+pub fn create(
+    store: Arc<SubgraphStore>,
+    primary: ConnectionPool,
+    search: DeploymentSearch,
+    destination: String,
+) -> Result<(), Error> {
+    let locator = search.locate_unique(&primary)?;
+    let snapshot = store.snapshot(&locator, &destination)?;
+
+    let mut list = List::new(vec!["entity type", "rows", "file"]);
+    for table in snapshot.tables {
+        list.append(vec![table.entity_type, table.rows.to_string(), table.file]);
+    }
+    list.render();
+    println!(
+        "wrote snapshot of {} at block {} to {}",
+        locator, snapshot.block.number, destination
+    );
+    Ok(())
+}
+
+pub fn restore(
+    store: Arc<SubgraphStore>,
+    source: String,
+    shard: String,
+    shards: Vec<String>,
+    node: String,
+) -> Result<(), Error> {
+    if !shards.contains(&shard) {
+        return Err(anyhow!(
+            "unknown shard {shard}, only shards {} are configured",
+            shards.join(", ")
+        ));
+    }
+    let shard = Shard::new(shard)?;
+    let node = NodeId::new(node.clone()).map_err(|()| anyhow!("invalid node id `{}`", node))?;
+
+    let (snapshot, locator) = store.restore(&source, shard, node.clone())?;
+    println!(
+        "restored {} at block {} from {} and assigned it to {}",
+        locator, snapshot.block.number, source, node
+    );
+    Ok(())
+}
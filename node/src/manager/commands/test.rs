@@ -0,0 +1,482 @@
+//! Run the tests of a subgraph. Every test is a JSON file that describes
+//! the blocks the subgraph should index and the entities it should have
+//! written afterwards:
+//!
+//! ```json
+//! {
+//!   "calls": [
+//!     { "address": "0x..", "abi": "Token", "function": "symbol",
+//!       "params": [], "block": 0, "returns": ["TKN"] }
+//!   ],
+//!   "blocks": [
+//!     { "events": [
+//!         { "address": "0x..", "abi": "Token", "event": "Transfer",
+//!           "params": ["0x..", "0x..", "100"] }
+//!     ] },
+//!     { "revertTo": 0 }
+//!   ],
+//!   "expect": {
+//!     "Account": { "0x..": { "balance": "100" }, "0x..": null }
+//!   }
+//! }
+//! ```
+//!
+//! Events and calls name the ABIs of the manifest. Expected entities list
+//! all their attributes except for the `id`; `null` means that the entity
+//! must not exist
+//!
+//! With a reorg script like `advance 5, revert 2, advance 4`, every test
+//! also checks that reorgs do not change what the subgraph writes: the
+//! blocks of the test are indexed once straight and once through the
+//! reorgs of the script, and all entities as of every block have to be the
+//! same for both. Proofs of indexing are not compared since the in-memory
+//! store does not keep them
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fs::File;
+use std::path::{Path, PathBuf};
+
+use graph::prelude::ethabi::{Contract, ParamType, Token};
+use graph::prelude::serde_json::{self, Map, Value as JsonValue};
+use graph::prelude::web3::types::{Address, Log, U256};
+use graph::prelude::{anyhow, hex, r, serde_yaml, BigInt, BlockNumber, Value};
+use graph_core::test_harness::{
+    event_log, parse_script, reorged_chain, MockChain, ReorgStep, TestHarness,
+};
+use serde::Deserialize;
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+struct TestCase {
+    #[serde(default)]
+    calls: Vec<CallFixture>,
+    blocks: Vec<BlockFixture>,
+    #[serde(default)]
+    expect: BTreeMap<String, BTreeMap<String, Option<Map<String, JsonValue>>>>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum BlockFixture {
+    #[serde(rename_all = "camelCase")]
+    Revert { revert_to: BlockNumber },
+    Block {
+        #[serde(default)]
+        events: Vec<EventFixture>,
+    },
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct EventFixture {
+    address: Address,
+    abi: String,
+    event: String,
+    #[serde(default)]
+    params: Vec<JsonValue>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CallFixture {
+    address: Address,
+    abi: String,
+    function: String,
+    #[serde(default)]
+    params: Vec<JsonValue>,
+    block: BlockNumber,
+    returns: Vec<JsonValue>,
+}
+
+pub async fn run(
+    manifest: PathBuf,
+    tests: Vec<PathBuf>,
+    reorgs: Option<String>,
+) -> Result<(), anyhow::Error> {
+    let abis = load_abis(&manifest)?;
+    let script = reorgs.as_deref().map(parse_script).transpose()?;
+
+    let mut failed = 0;
+    for test in &tests {
+        match run_test(&manifest, &abis, test, script.as_deref()).await {
+            Ok(mismatches) if mismatches.is_empty() => println!("ok      {}", test.display()),
+            Ok(mismatches) => {
+                failed += 1;
+                println!("FAILED  {}", test.display());
+                for mismatch in mismatches {
+                    println!("        {}", mismatch);
+                }
+            }
+            Err(e) => {
+                failed += 1;
+                println!("FAILED  {}\n        {:#}", test.display(), e);
+            }
+        }
+    }
+
+    println!("\n{} passed, {} failed", tests.len() - failed, failed);
+    if failed > 0 {
+        Err(anyhow!("{} of {} tests failed", failed, tests.len()))
+    } else {
+        Ok(())
+    }
+}
+
+/// Run the test in `path` and return how the entities differ from the
+/// ones the test expects. With a reorg `script`, also index the blocks
+/// that remain after the test's own reverts once straight and once
+/// through the reorgs of the script, and report every block at which the
+/// two runs disagree about an entity
+async fn run_test(
+    manifest: &Path,
+    abis: &HashMap<String, Contract>,
+    path: &Path,
+    script: Option<&[ReorgStep]>,
+) -> Result<Vec<String>, anyhow::Error> {
+    let file = File::open(path)?;
+    let test: TestCase = serde_json::from_reader(file)?;
+
+    let mut chain = MockChain::new();
+    // The logs of the blocks that are left after the test's reverts
+    let mut canonical: Vec<Vec<Log>> = Vec::new();
+    for block in test.blocks {
+        match block {
+            BlockFixture::Revert { revert_to } => {
+                chain.revert_to(revert_to)?;
+                canonical.truncate(revert_to as usize + 1);
+            }
+            BlockFixture::Block { events } => {
+                let logs: Vec<Log> = events
+                    .iter()
+                    .map(|event| {
+                        let abi = abi(abis, &event.abi)?;
+                        let abi_event = abi.event(&event.event)?;
+                        let kinds = abi_event.inputs.iter().map(|input| &input.kind);
+                        let params = tokens(kinds, &event.params)?;
+                        event_log(event.address, abi_event, &params)
+                    })
+                    .collect::<Result<_, _>>()?;
+                chain.block(logs.clone());
+                canonical.push(logs);
+            }
+        }
+    }
+
+    let mut calls = Vec::new();
+    for call in test.calls {
+        let function = abi(abis, &call.abi)?.function(&call.function)?;
+        let params = tokens(function.inputs.iter().map(|p| &p.kind), &call.params)?;
+        let returns = tokens(function.outputs.iter().map(|p| &p.kind), &call.returns)?;
+        let encoded_call = function.encode_input(&params)?;
+        let return_value = graph::prelude::ethabi::encode(&returns);
+        if !chain
+            .block_ptrs()
+            .iter()
+            .any(|ptr| ptr.number == call.block)
+        {
+            return Err(anyhow!(
+                "the call to {} is mocked at block {} which the test does not have",
+                call.function,
+                call.block
+            ));
+        }
+        calls.push((call.address, encoded_call, call.block, return_value));
+    }
+
+    let harness = TestHarness::start(manifest, mock_calls(chain, &calls)).await?;
+    harness.sync().await?;
+
+    let mut mismatches = Vec::new();
+    for (entity_type, entities) in test.expect {
+        for (id, expected) in entities {
+            let actual = harness.get(&entity_type, &id)?.map(|entity| {
+                entity
+                    .sorted()
+                    .into_iter()
+                    .filter(|(attr, value)| attr != "id" && value != &Value::Null)
+                    .map(|(attr, value)| (attr, json_value(value)))
+                    .collect::<Map<_, _>>()
+            });
+            let expected = expected.map(|attrs| {
+                attrs
+                    .into_iter()
+                    .filter(|(attr, value)| attr != "id" && !value.is_null())
+                    .collect::<Map<_, _>>()
+            });
+            if actual != expected {
+                mismatches.push(format!(
+                    "{}[{}]: expected {} but got {}",
+                    entity_type,
+                    id,
+                    display(expected),
+                    display(actual)
+                ));
+            }
+        }
+    }
+    drop(harness);
+
+    if let Some(script) = script {
+        let straight = entity_history(manifest, reorged_chain(&canonical, &[])?, &calls).await?;
+        let reorged = entity_history(manifest, reorged_chain(&canonical, script)?, &calls).await?;
+        for (number, (straight, reorged)) in straight.iter().zip(reorged.iter()).enumerate() {
+            if straight.poi != reorged.poi {
+                mismatches.push(format!(
+                    "block {}: the proof of indexing is {} after the reorgs but {} without",
+                    number,
+                    display_digests(&reorged.poi),
+                    display_digests(&straight.poi)
+                ));
+            }
+            let (straight, reorged) = (&straight.entities, &reorged.entities);
+            let keys: BTreeSet<_> = straight.keys().chain(reorged.keys()).collect();
+            for key @ (entity_type, id) in keys {
+                let (straight, reorged) = (straight.get(key), reorged.get(key));
+                if straight != reorged {
+                    mismatches.push(format!(
+                        "block {}: {}[{}] is {} after the reorgs but {} without",
+                        number,
+                        entity_type,
+                        id,
+                        display_value(reorged),
+                        display_value(straight)
+                    ));
+                }
+            }
+        }
+    }
+    Ok(mismatches)
+}
+
+/// Answer the mocked `calls` on every branch of `chain`, since a block
+/// number may refer to blocks on several branches
+fn mock_calls(
+    mut chain: MockChain,
+    calls: &[(Address, Vec<u8>, BlockNumber, Vec<u8>)],
+) -> MockChain {
+    let ptrs = chain.block_ptrs();
+    for (address, encoded_call, block, return_value) in calls {
+        for ptr in ptrs.iter().filter(|ptr| ptr.number == *block) {
+            chain.mock_call(
+                *address,
+                encoded_call.clone(),
+                ptr.clone(),
+                return_value.clone(),
+            );
+        }
+    }
+    chain
+}
+
+/// What a subgraph had stored as of one block
+struct BlockState {
+    entities: BTreeMap<(String, String), BTreeMap<String, r::Value>>,
+    /// The digests of the proof of indexing by causality region
+    poi: BTreeMap<String, r::Value>,
+}
+
+/// Index `chain` and return what the subgraph had stored as of each of
+/// its blocks
+async fn entity_history(
+    manifest: &Path,
+    chain: MockChain,
+    calls: &[(Address, Vec<u8>, BlockNumber, Vec<u8>)],
+) -> Result<Vec<BlockState>, anyhow::Error> {
+    let head = chain.head().map_or(-1, |head| head.number);
+    let harness = TestHarness::start(manifest, mock_calls(chain, calls)).await?;
+    harness.sync().await?;
+    let mut history = Vec::new();
+    for number in 0..=head {
+        history.push(BlockState {
+            entities: harness.entities_at(number).await?,
+            poi: harness.poi_digests_at(number).await?,
+        });
+    }
+    Ok(history)
+}
+
+/// Load the ABIs of all data sources and templates in the manifest,
+/// keyed by the name the manifest gives them
+fn load_abis(manifest: &Path) -> Result<HashMap<String, Contract>, anyhow::Error> {
+    let base = manifest.parent().unwrap_or_else(|| Path::new("."));
+    let text = std::fs::read_to_string(manifest)?;
+    let raw: serde_yaml::Value = serde_yaml::from_str(&text)?;
+
+    let mut abis = HashMap::new();
+    let sources = ["dataSources", "templates"]
+        .iter()
+        .filter_map(|key| raw.get(*key)?.as_sequence())
+        .flatten();
+    for source in sources {
+        let entries = source
+            .get("mapping")
+            .and_then(|mapping| mapping.get("abis"))
+            .and_then(|abis| abis.as_sequence())
+            .into_iter()
+            .flatten();
+        for entry in entries {
+            let name = entry.get("name").and_then(|name| name.as_str());
+            let file = entry.get("file").and_then(|file| file.as_str());
+            if let (Some(name), Some(file)) = (name, file) {
+                if abis.contains_key(name) {
+                    continue;
+                }
+                let contract = Contract::load(File::open(base.join(file))?)
+                    .map_err(|e| anyhow!("invalid ABI `{}` in {}: {}", name, file, e))?;
+                abis.insert(name.to_string(), contract);
+            }
+        }
+    }
+    Ok(abis)
+}
+
+fn abi<'a>(abis: &'a HashMap<String, Contract>, name: &str) -> Result<&'a Contract, anyhow::Error> {
+    abis.get(name)
+        .ok_or_else(|| anyhow!("the manifest has no ABI named `{}`", name))
+}
+
+fn tokens<'a>(
+    kinds: impl ExactSizeIterator<Item = &'a ParamType>,
+    values: &[JsonValue],
+) -> Result<Vec<Token>, anyhow::Error> {
+    if kinds.len() != values.len() {
+        return Err(anyhow!(
+            "expected {} values but got {}",
+            kinds.len(),
+            values.len()
+        ));
+    }
+    kinds
+        .zip(values)
+        .map(|(kind, value)| token(kind, value))
+        .collect()
+}
+
+/// Convert a JSON value from a test into a token of type `kind`. Integers
+/// can be given as numbers or as strings, addresses and bytes as hex
+/// strings, and arrays and tuples as JSON arrays
+fn token(kind: &ParamType, value: &JsonValue) -> Result<Token, anyhow::Error> {
+    let token = match (kind, value) {
+        (ParamType::Address, JsonValue::String(s)) => {
+            Token::Address(Address::from_slice(&bytes(s, Some(20))?))
+        }
+        (ParamType::Uint(_), JsonValue::Number(n)) => {
+            Token::Uint(U256::from_dec_str(&n.to_string())?)
+        }
+        (ParamType::Uint(_), JsonValue::String(s)) => Token::Uint(match s.strip_prefix("0x") {
+            Some(hex) => U256::from_str_radix(hex, 16)?,
+            None => U256::from_dec_str(s)?,
+        }),
+        (ParamType::Int(_), JsonValue::Number(n)) => {
+            Token::Int(n.to_string().parse::<BigInt>()?.to_signed_u256())
+        }
+        (ParamType::Int(_), JsonValue::String(s)) => {
+            Token::Int(s.parse::<BigInt>()?.to_signed_u256())
+        }
+        (ParamType::Bool, JsonValue::Bool(b)) => Token::Bool(*b),
+        (ParamType::String, JsonValue::String(s)) => Token::String(s.clone()),
+        (ParamType::Bytes, JsonValue::String(s)) => Token::Bytes(bytes(s, None)?),
+        (ParamType::FixedBytes(len), JsonValue::String(s)) => {
+            Token::FixedBytes(bytes(s, Some(*len))?)
+        }
+        (ParamType::Array(kind), JsonValue::Array(values)) => Token::Array(
+            values
+                .iter()
+                .map(|value| token(kind, value))
+                .collect::<Result<_, _>>()?,
+        ),
+        (ParamType::FixedArray(kind, len), JsonValue::Array(values)) if values.len() == *len => {
+            Token::FixedArray(
+                values
+                    .iter()
+                    .map(|value| token(kind, value))
+                    .collect::<Result<_, _>>()?,
+            )
+        }
+        (ParamType::Tuple(kinds), JsonValue::Array(values)) => {
+            Token::Tuple(tokens(kinds.iter(), values)?)
+        }
+        _ => return Err(anyhow!("`{}` is not a valid {}", value, kind)),
+    };
+    Ok(token)
+}
+
+fn bytes(s: &str, len: Option<usize>) -> Result<Vec<u8>, anyhow::Error> {
+    let bytes = hex::decode(s.trim_start_matches("0x"))?;
+    match len {
+        Some(len) if bytes.len() != len => Err(anyhow!(
+            "`{}` has {} bytes but must have {}",
+            s,
+            bytes.len(),
+            len
+        )),
+        _ => Ok(bytes),
+    }
+}
+
+/// The JSON form of an attribute in expected entities: `BigInt`,
+/// `BigDecimal` and `Bytes` are strings, the latter in hex
+fn json_value(value: Value) -> JsonValue {
+    match value {
+        Value::String(s) => JsonValue::String(s),
+        Value::Int(i) => JsonValue::from(i),
+        Value::BigDecimal(d) => JsonValue::String(d.to_string()),
+        Value::Bool(b) => JsonValue::Bool(b),
+        Value::List(values) => JsonValue::Array(values.into_iter().map(json_value).collect()),
+        Value::Null => JsonValue::Null,
+        Value::Bytes(b) => JsonValue::String(b.to_string()),
+        Value::BigInt(i) => JsonValue::String(i.to_string()),
+    }
+}
+
+fn display_value(entity: Option<&BTreeMap<String, r::Value>>) -> String {
+    match entity {
+        Some(entity) => serde_json::to_string(entity).unwrap_or_else(|e| e.to_string()),
+        None => "no entity".to_string(),
+    }
+}
+
+fn display_digests(digests: &BTreeMap<String, r::Value>) -> String {
+    serde_json::to_string(digests).unwrap_or_else(|e| e.to_string())
+}
+
+fn display(entity: Option<Map<String, JsonValue>>) -> String {
+    match entity {
+        Some(entity) => JsonValue::Object(entity).to_string(),
+        None => "no entity".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn converts_json_to_tokens() {
+        let address = "0x0000000000000000000000000000000000000007";
+        assert_eq!(
+            Token::Address(Address::from_low_u64_be(7)),
+            token(&ParamType::Address, &JsonValue::from(address)).unwrap()
+        );
+        assert_eq!(
+            Token::Uint(U256::from(255)),
+            token(&ParamType::Uint(256), &JsonValue::from("0xff")).unwrap()
+        );
+        assert_eq!(
+            Token::Uint(U256::from(10)),
+            token(&ParamType::Uint(8), &JsonValue::from(10)).unwrap()
+        );
+        assert_eq!(
+            Token::Int(U256::max_value()),
+            token(&ParamType::Int(256), &JsonValue::from("-1")).unwrap()
+        );
+        let tuple = ParamType::Tuple(vec![ParamType::Bool, ParamType::FixedBytes(2)]);
+        assert_eq!(
+            Token::Tuple(vec![Token::Bool(true), Token::FixedBytes(vec![1, 2])]),
+            token(&tuple, &serde_json::json!([true, "0x0102"])).unwrap()
+        );
+
+        assert!(token(&ParamType::Address, &JsonValue::from("0x07")).is_err());
+        assert!(token(&ParamType::Bool, &JsonValue::from("true")).is_err());
+        assert!(token(&tuple, &serde_json::json!([true])).is_err());
+    }
+}
@@ -93,6 +93,7 @@ impl DeploymentSearch {
                     "(case
                     when subgraphs.subgraph.pending_version = subgraphs.subgraph_version.id then 'pending'
                     when subgraphs.subgraph.current_version = subgraphs.subgraph_version.id then 'current'
+                    when subgraphs.subgraph.previous_version = subgraphs.subgraph_version.id then 'previous'
                     else 'unused' end) status",
                 ),
                 v::deployment,
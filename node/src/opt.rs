@@ -1,4 +1,5 @@
 use git_testament::{git_testament, render_testament};
+use graph::prelude::BlockPtr;
 use lazy_static::lazy_static;
 use structopt::StructOpt;
 
@@ -20,8 +21,8 @@ pub struct Opt {
     #[structopt(
         long,
         env = "GRAPH_NODE_CONFIG",
-        conflicts_with_all = &["postgres-url", "postgres-secondary-hosts", "postgres-host-weights"],
-        required_unless = "postgres-url",
+        conflicts_with_all = &["postgres-url", "postgres-secondary-hosts", "postgres-host-weights", "dev"],
+        required_unless_one = &["postgres-url", "dev"],
         help = "the name of the configuration file",
     )]
     pub config: Option<String>,
@@ -38,19 +39,32 @@ pub struct Opt {
     #[structopt(
         long,
         value_name = "BLOCK_HASH:BLOCK_NUMBER",
-        help = "block hash and number that the subgraph passed will start indexing at"
+        help = "the block after which the subgraph passed with --subgraph starts indexing, instead of the manifest's start block. Earlier blocks are not indexed at all"
     )]
-    pub start_block: Option<String>,
+    pub start_block: Option<BlockPtr>,
 
     #[structopt(
         long,
         value_name = "URL",
         env = "POSTGRES_URL",
-        conflicts_with = "config",
-        required_unless = "config",
+        conflicts_with_all = &["config", "dev"],
+        required_unless_one = &["config", "dev"],
         help = "Location of the Postgres database used for storing entities"
     )]
     pub postgres_url: Option<String>,
+    #[structopt(
+        long,
+        help = "Run a single node for local development that keeps all data in a SQLite file"
+    )]
+    pub dev: bool,
+    #[structopt(
+        long,
+        value_name = "PATH",
+        default_value = "graph-node.db",
+        env = "GRAPH_NODE_DEV_DB",
+        help = "The SQLite file used with --dev"
+    )]
+    pub dev_db: String,
     #[structopt(
         long,
         value_name = "URL,",
@@ -229,6 +243,8 @@ impl From<Opt> for config::Opt {
             ethereum_ws,
             ethereum_ipc,
             unsafe_config,
+            dev,
+            dev_db,
             ..
         } = opt;
 
@@ -244,6 +260,7 @@ impl From<Opt> for config::Opt {
             ethereum_ws,
             ethereum_ipc,
             unsafe_config,
+            dev_db: if dev { Some(dev_db) } else { None },
         }
     }
 }
@@ -12,11 +12,11 @@ use graph::{
 use graph_store_postgres::connection_pool::{ConnectionPool, ForeignServer, PoolName};
 use graph_store_postgres::{
     BlockStore as DieselBlockStore, ChainHeadUpdateListener as PostgresChainHeadUpdateListener,
-    NotificationSender, Shard as ShardName, Store as DieselStore, SubgraphStore,
+    DeploymentPlacer, NotificationSender, Shard as ShardName, Store as DieselStore, SubgraphStore,
     SubscriptionManager, PRIMARY_SHARD,
 };
 
-use crate::config::{Config, Shard};
+use crate::config::{Config, ReloadablePlacer, Shard};
 
 pub struct StoreBuilder {
     logger: Logger,
@@ -26,6 +26,7 @@ pub struct StoreBuilder {
     chain_head_update_listener: Arc<PostgresChainHeadUpdateListener>,
     /// Map network names to the shards where they are/should be stored
     chains: HashMap<String, ShardName>,
+    placer: Arc<ReloadablePlacer>,
 }
 
 impl StoreBuilder {
@@ -47,10 +48,12 @@ impl StoreBuilder {
             registry.clone(),
         ));
 
+        let placer = Arc::new(ReloadablePlacer::new(config.deployment.clone()));
         let (store, pools) = Self::make_subgraph_store_and_pools(
             logger,
             node,
             config,
+            placer.cheap_clone(),
             fork_base,
             registry.cheap_clone(),
         );
@@ -80,16 +83,18 @@ impl StoreBuilder {
             subscription_manager,
             chain_head_update_listener,
             chains,
+            placer,
         }
     }
 
     /// Make a `ShardedStore` across all configured shards, and also return
     /// the main connection pools for each shard, but not any pools for
-    /// replicas
+    /// replicas. New deployments are placed according to `placer`
     pub fn make_subgraph_store_and_pools(
         logger: &Logger,
         node: &NodeId,
         config: &Config,
+        placer: Arc<dyn DeploymentPlacer + Send + Sync>,
         fork_base: Option<Url>,
         registry: Arc<impl MetricsRegistry>,
     ) -> (Arc<SubgraphStore>, HashMap<ShardName, ConnectionPool>) {
@@ -140,8 +145,9 @@ impl StoreBuilder {
 
         let store = Arc::new(SubgraphStore::new(
             logger,
+            node.clone(),
             shards,
-            Arc::new(config.deployment.clone()),
+            placer,
             notification_sender,
             fork_base,
             registry,
@@ -286,7 +292,18 @@ impl StoreBuilder {
         self.chain_head_update_listener.clone()
     }
 
+    /// The placer that decides where new deployments go; its rules can be
+    /// replaced when the configuration is reloaded
+    pub fn placer(&self) -> Arc<ReloadablePlacer> {
+        self.placer.cheap_clone()
+    }
+
     pub fn primary_pool(&self) -> ConnectionPool {
         self.pools.get(&*PRIMARY_SHARD).unwrap().clone()
     }
+
+    /// The main connection pools of all shards
+    pub fn pools(&self) -> Vec<ConnectionPool> {
+        self.pools.values().cloned().collect()
+    }
 }
@@ -0,0 +1,292 @@
+//! Choose the storage backend that graph-node uses from the configuration.
+//! Each backend provides a `StoreFactory` that is responsible for the URL
+//! schemes that it understands; the factory for the connection string of
+//! the primary shard decides which stores the node sets up.
+
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use graph::components::store::STORE_INTERFACE_VERSION;
+use graph::prelude::{anyhow, Error};
+use graph::url::Url;
+
+use crate::config::Config;
+
+/// Describes one storage backend
+pub trait StoreFactory: Send + Sync + 'static {
+    /// The version of the store interface that this factory was written
+    /// against; factories are only accepted if this is
+    /// `STORE_INTERFACE_VERSION`
+    fn interface_version(&self) -> u32;
+
+    /// A human-readable name for the backend
+    fn name(&self) -> &str;
+
+    /// The URL schemes of the connection strings this factory handles
+    fn schemes(&self) -> &[&'static str];
+
+    /// Check that `config` only asks for things this backend can do
+    fn check(&self, config: &Config) -> Result<(), Error>;
+}
+
+/// The store factories that are known to the node, by URL scheme
+#[derive(Default)]
+pub struct StoreFactories {
+    factories: BTreeMap<String, Arc<dyn StoreFactory>>,
+}
+
+impl StoreFactories {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The factories for all backends that come with graph-node
+    pub fn with_defaults() -> Self {
+        let mut factories = Self::new();
+        factories
+            .register(Arc::new(PostgresStoreFactory))
+            .expect("the builtin store factories are valid");
+        factories
+            .register(Arc::new(SqliteStoreFactory))
+            .expect("the builtin store factories are valid");
+        factories
+    }
+
+    /// Make `factory` available for its URL schemes. Fails if the factory
+    /// implements a different version of the store interface, or if
+    /// another factory already handles one of its schemes
+    pub fn register(&mut self, factory: Arc<dyn StoreFactory>) -> Result<(), Error> {
+        if factory.interface_version() != STORE_INTERFACE_VERSION {
+            return Err(anyhow!(
+                "the `{}` store implements version {} of the store interface but this node \
+                 requires version {}",
+                factory.name(),
+                factory.interface_version(),
+                STORE_INTERFACE_VERSION
+            ));
+        }
+        for scheme in factory.schemes() {
+            if let Some(other) = self.factories.get(*scheme) {
+                return Err(anyhow!(
+                    "the `{}` store and the `{}` store both handle URLs with scheme `{}`",
+                    other.name(),
+                    factory.name(),
+                    scheme
+                ));
+            }
+        }
+        for scheme in factory.schemes() {
+            self.factories.insert(scheme.to_string(), factory.clone());
+        }
+        Ok(())
+    }
+
+    /// The factory that handles the connection string `url`
+    pub fn for_url(&self, url: &str) -> Result<Arc<dyn StoreFactory>, Error> {
+        let scheme = scheme(url)?;
+        self.factories.get(&scheme).cloned().ok_or_else(|| {
+            let supported: Vec<_> = self.factories.keys().map(String::as_str).collect();
+            anyhow!(
+                "there is no store for URLs with scheme `{}`; supported schemes are {}",
+                scheme,
+                supported.join(", ")
+            )
+        })
+    }
+}
+
+/// The stores that keep everything in Postgres
+pub struct PostgresStoreFactory;
+
+impl StoreFactory for PostgresStoreFactory {
+    fn interface_version(&self) -> u32 {
+        STORE_INTERFACE_VERSION
+    }
+
+    fn name(&self) -> &str {
+        "postgres"
+    }
+
+    fn schemes(&self) -> &[&'static str] {
+        &["postgres", "postgresql"]
+    }
+
+    fn check(&self, config: &Config) -> Result<(), Error> {
+        // All shards and their replicas have to live in Postgres since
+        // they are queried together through foreign data wrappers
+        for (name, shard) in &config.stores {
+            let connections = std::iter::once(&shard.connection)
+                .chain(shard.replicas.values().map(|replica| &replica.connection));
+            for connection in connections {
+                let scheme = scheme(connection)?;
+                if !self.schemes().contains(&scheme.as_str()) {
+                    return Err(anyhow!(
+                        "shard `{}` uses a `{}` connection but the primary is in Postgres",
+                        name,
+                        scheme
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The store that `graph-node --dev` keeps in a single SQLite file
+pub struct SqliteStoreFactory;
+
+impl SqliteStoreFactory {
+    /// The path of the database file in the connection string `url`
+    pub fn path(url: &str) -> Result<&str, Error> {
+        url.strip_prefix("sqlite://")
+            .filter(|path| !path.is_empty())
+            .ok_or_else(|| anyhow!("`{}` does not name a SQLite database file", url))
+    }
+}
+
+impl StoreFactory for SqliteStoreFactory {
+    fn interface_version(&self) -> u32 {
+        STORE_INTERFACE_VERSION
+    }
+
+    fn name(&self) -> &str {
+        "sqlite"
+    }
+
+    fn schemes(&self) -> &[&'static str] {
+        &["sqlite"]
+    }
+
+    fn check(&self, config: &Config) -> Result<(), Error> {
+        if config.stores.len() > 1 {
+            return Err(anyhow!("the SQLite store does not support shards"));
+        }
+        let primary = config.primary_store();
+        if !primary.replicas.is_empty() {
+            return Err(anyhow!("the SQLite store does not support replicas"));
+        }
+        Self::path(&primary.connection).map(|_| ())
+    }
+}
+
+fn scheme(url: &str) -> Result<String, Error> {
+    Url::parse(url)
+        .map(|url| url.scheme().to_string())
+        .map_err(|e| anyhow!("invalid store connection string: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct OtherFactory(u32);
+
+    impl StoreFactory for OtherFactory {
+        fn interface_version(&self) -> u32 {
+            self.0
+        }
+
+        fn name(&self) -> &str {
+            "other"
+        }
+
+        fn schemes(&self) -> &[&'static str] {
+            &["postgres", "other"]
+        }
+
+        fn check(&self, _: &Config) -> Result<(), Error> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn finds_factory_by_scheme() {
+        let factories = StoreFactories::with_defaults();
+        for url in &[
+            "postgres://graph@localhost/graph",
+            "postgresql://localhost/graph",
+        ] {
+            assert_eq!("postgres", factories.for_url(url).unwrap().name());
+        }
+
+        assert_eq!(
+            "sqlite",
+            factories.for_url("sqlite:///tmp/graph.db").unwrap().name()
+        );
+
+        let err = factories
+            .for_url("mysql://localhost/graph")
+            .err()
+            .unwrap()
+            .to_string();
+        assert!(err.contains("scheme `mysql`"));
+        assert!(err.contains("postgres, postgresql, sqlite"));
+    }
+
+    #[test]
+    fn finds_sqlite_path() {
+        assert_eq!(
+            "/tmp/graph.db",
+            SqliteStoreFactory::path("sqlite:///tmp/graph.db").unwrap()
+        );
+        assert_eq!(
+            "graph-node.db",
+            SqliteStoreFactory::path("sqlite://graph-node.db").unwrap()
+        );
+        assert!(SqliteStoreFactory::path("sqlite://").is_err());
+        assert!(SqliteStoreFactory::path("postgres://localhost/graph").is_err());
+    }
+
+    #[test]
+    fn checks_shards_against_backend() {
+        let config = |shard: &str| -> Config {
+            toml::from_str(&format!(
+                r#"
+                [store.primary]
+                connection = "postgresql://localhost/graph"
+                pool_size = 10
+                [store.primary.replicas.repl1]
+                connection = "postgresql://replica/graph"
+                [store.other]
+                connection = "{}"
+                pool_size = 10
+                [chains]
+                ingestor = "default"
+                [deployment]
+                rule = []
+                "#,
+                shard
+            ))
+            .unwrap()
+        };
+
+        PostgresStoreFactory
+            .check(&config("postgres://other/graph"))
+            .unwrap();
+        let err = PostgresStoreFactory
+            .check(&config("sqlite:///tmp/graph.db"))
+            .unwrap_err();
+        assert!(err.to_string().contains("shard `other`"));
+
+        // SQLite only ever has a single database file
+        let err = SqliteStoreFactory
+            .check(&config("sqlite:///tmp/graph.db"))
+            .unwrap_err();
+        assert!(err.to_string().contains("shards"));
+    }
+
+    #[test]
+    fn rejects_bad_factories() {
+        let mut factories = StoreFactories::with_defaults();
+        let err = factories
+            .register(Arc::new(OtherFactory(STORE_INTERFACE_VERSION + 1)))
+            .unwrap_err();
+        assert!(err.to_string().contains("version"));
+
+        let err = factories
+            .register(Arc::new(OtherFactory(STORE_INTERFACE_VERSION)))
+            .unwrap_err();
+        assert!(err.to_string().contains("scheme `postgres`"));
+        assert!(factories.for_url("other://somewhere").is_err());
+    }
+}
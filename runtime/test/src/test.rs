@@ -849,7 +849,11 @@ async fn run_data_source_create(
     )
     .await;
 
-    module.instance_ctx_mut().ctx.state.enter_handler();
+    module
+        .instance_ctx_mut()
+        .ctx
+        .state
+        .enter_handler("dataSourceCreate");
     module.invoke_export2_void("dataSourceCreate", &name, &params)?;
     module.instance_ctx_mut().ctx.state.exit_handler();
 
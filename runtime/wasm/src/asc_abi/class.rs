@@ -522,7 +522,7 @@ impl Default for EthereumValueKind {
 impl AscValue for EthereumValueKind {}
 
 #[repr(u32)]
-#[derive(AscType, Copy, Clone)]
+#[derive(AscType, Copy, Clone, Debug, PartialEq, Eq)]
 pub enum StoreValueKind {
     String,
     Int,
@@ -549,6 +549,29 @@ impl StoreValueKind {
             Value::BigInt(_) => StoreValueKind::BigInt,
         }
     }
+
+    /// The kind that mappings pass as the number `kind`
+    pub(crate) fn from_discriminant(kind: u32) -> Option<StoreValueKind> {
+        use StoreValueKind::*;
+
+        [String, Int, BigDecimal, Bool, Array, Null, Bytes, BigInt]
+            .get(kind as usize)
+            .copied()
+    }
+
+    /// The name of the kind in `graph-ts`
+    pub(crate) fn name(&self) -> &'static str {
+        match self {
+            StoreValueKind::String => "String",
+            StoreValueKind::Int => "Int",
+            StoreValueKind::BigDecimal => "BigDecimal",
+            StoreValueKind::Bool => "Bool",
+            StoreValueKind::Array => "Array",
+            StoreValueKind::Null => "Null",
+            StoreValueKind::Bytes => "Bytes",
+            StoreValueKind::BigInt => "BigInt",
+        }
+    }
 }
 
 impl Default for StoreValueKind {
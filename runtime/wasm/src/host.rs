@@ -22,6 +22,8 @@ pub struct RuntimeHostBuilder<C: Blockchain> {
     runtime_adapter: Arc<C::RuntimeAdapter>,
     link_resolver: Arc<dyn LinkResolver>,
     ens_lookup: Arc<dyn EnsLookup>,
+    uncrashable: bool,
+    strict_references: bool,
 }
 
 impl<C: Blockchain> Clone for RuntimeHostBuilder<C> {
@@ -30,6 +32,8 @@ impl<C: Blockchain> Clone for RuntimeHostBuilder<C> {
             runtime_adapter: self.runtime_adapter.cheap_clone(),
             link_resolver: self.link_resolver.cheap_clone(),
             ens_lookup: self.ens_lookup.cheap_clone(),
+            uncrashable: self.uncrashable,
+            strict_references: self.strict_references,
         }
     }
 }
@@ -44,8 +48,24 @@ impl<C: Blockchain> RuntimeHostBuilder<C> {
             runtime_adapter,
             link_resolver,
             ens_lookup,
+            uncrashable: false,
+            strict_references: false,
         }
     }
+
+    /// Fill in defaults for required fields that handlers leave unset
+    /// instead of failing them if `uncrashable` is set
+    pub fn with_uncrashable(mut self, uncrashable: bool) -> Self {
+        self.uncrashable = uncrashable;
+        self
+    }
+
+    /// Record the references that handlers set so that they can be checked
+    /// at the end of each block if `strict_references` is set
+    pub fn with_strict_references(mut self, strict_references: bool) -> Self {
+        self.strict_references = strict_references;
+        self
+    }
 }
 
 impl<C: Blockchain> RuntimeHostBuilderTrait<C> for RuntimeHostBuilder<C> {
@@ -91,6 +111,8 @@ impl<C: Blockchain> RuntimeHostBuilderTrait<C> for RuntimeHostBuilder<C> {
             mapping_request_sender,
             metrics,
             self.ens_lookup.cheap_clone(),
+            self.uncrashable,
+            self.strict_references,
         )
     }
 }
@@ -117,17 +139,23 @@ where
         mapping_request_sender: Sender<MappingRequest<C>>,
         metrics: Arc<HostMetrics>,
         ens_lookup: Arc<dyn EnsLookup>,
+        uncrashable: bool,
+        strict_references: bool,
     ) -> Result<Self, Error> {
         // Create new instance of externally hosted functions invoker. The `Arc` is simply to avoid
         // implementing `Clone` for `HostExports`.
-        let host_exports = Arc::new(HostExports::new(
-            subgraph_id,
-            &data_source,
-            network_name,
-            templates,
-            link_resolver,
-            ens_lookup,
-        ));
+        let host_exports = Arc::new(
+            HostExports::new(
+                subgraph_id,
+                &data_source,
+                network_name,
+                templates,
+                link_resolver,
+                ens_lookup,
+            )
+            .with_uncrashable(uncrashable)
+            .with_strict_references(strict_references),
+        );
 
         let host_fns = Arc::new(runtime_adapter.host_fns(&data_source)?);
 
@@ -241,6 +269,14 @@ impl<C: Blockchain> RuntimeHostTrait<C> for RuntimeHost<C> {
     fn creation_block_number(&self) -> Option<BlockNumber> {
         self.data_source.creation_block()
     }
+
+    fn entities(&self) -> &[String] {
+        self.data_source.entities()
+    }
+
+    fn data_source(&self) -> &C::DataSource {
+        &self.data_source
+    }
 }
 
 impl<C: Blockchain> PartialEq for RuntimeHost<C> {
@@ -12,11 +12,17 @@ use graph::blockchain::DataSource;
 use graph::blockchain::{Blockchain, DataSourceTemplate as _};
 use graph::components::store::EntityType;
 use graph::components::store::{EnsLookup, EntityKey};
-use graph::components::subgraph::{CausalityRegion, ProofOfIndexingEvent, SharedProofOfIndexing};
+use graph::components::subgraph::{
+    CausalityRegion, EntityReference, ProofOfIndexingEvent, RemovedDataSource,
+    SharedProofOfIndexing,
+};
+use graph::data::graphql::ext::{DirectiveFinder, DocumentExt, TypeExt};
 use graph::data::store;
+use graph::data::subgraph::schema::SubgraphError;
+use graph::data::subgraph::API_VERSION_0_0_8;
 use graph::ensure;
 use graph::prelude::ethabi::param_type::Reader;
-use graph::prelude::ethabi::{decode, encode, Token};
+use graph::prelude::ethabi::{self, decode, encode, Token};
 use graph::prelude::serde_json;
 use graph::prelude::{slog::b, slog::record_static, *};
 use graph::runtime::gas::{self, complexity, Gas, GasCounter};
@@ -61,6 +67,9 @@ pub struct HostExports<C: Blockchain> {
     data_source_address: Vec<u8>,
     data_source_network: String,
     data_source_context: Arc<Option<DataSourceContext>>,
+    data_source_creation_block: Option<BlockNumber>,
+    data_source_entities: Vec<String>,
+    handler_entities: HashMap<String, Vec<String>>,
     /// Some data sources have indeterminism or different notions of time. These
     /// need to be each be stored separately to separate causality between them,
     /// and merge the results later. Right now, this is just the ethereum
@@ -69,6 +78,8 @@ pub struct HostExports<C: Blockchain> {
     templates: Arc<Vec<C::DataSourceTemplate>>,
     pub(crate) link_resolver: Arc<dyn LinkResolver>,
     ens_lookup: Arc<dyn EnsLookup>,
+    uncrashable: bool,
+    strict_references: bool,
 }
 
 impl<C: Blockchain> HostExports<C> {
@@ -86,14 +97,37 @@ impl<C: Blockchain> HostExports<C> {
             data_source_name: data_source.name().to_owned(),
             data_source_address: data_source.address().unwrap_or_default().to_owned(),
             data_source_context: data_source.context().cheap_clone(),
+            data_source_creation_block: data_source.creation_block(),
+            data_source_entities: data_source.entities().to_vec(),
+            handler_entities: data_source
+                .handler_entities()
+                .into_iter()
+                .map(|(handler, entities)| (handler.to_string(), entities.to_vec()))
+                .collect(),
             causality_region: CausalityRegion::from_network(&data_source_network),
             data_source_network,
             templates,
             link_resolver,
             ens_lookup,
+            uncrashable: false,
+            strict_references: false,
         }
     }
 
+    /// Fill in defaults for required fields that handlers leave unset if
+    /// the subgraph declares the `uncrashable` feature
+    pub fn with_uncrashable(mut self, uncrashable: bool) -> Self {
+        self.uncrashable = uncrashable;
+        self
+    }
+
+    /// Record the references that handlers set if the subgraph declares
+    /// the `strictReferences` feature
+    pub fn with_strict_references(mut self, strict_references: bool) -> Self {
+        self.strict_references = strict_references;
+        self
+    }
+
     pub(crate) fn abort(
         &self,
         message: Option<String>,
@@ -126,17 +160,84 @@ impl<C: Blockchain> HostExports<C> {
         )))
     }
 
+    /// Fail if the handler that is running declares the entities it may
+    /// access, or may only access the entities that the mapping declares,
+    /// and `entity_type` is not one of them
+    fn check_entity_access(
+        &self,
+        state: &BlockState<C>,
+        entity_type: &str,
+    ) -> Result<(), HostExportError> {
+        if let Some((handler, entities)) = state
+            .handler()
+            .and_then(|handler| self.handler_entities.get_key_value(handler))
+        {
+            if !entities.iter().any(|entity| entity == entity_type) {
+                return Err(HostExportError::Deterministic(anyhow::anyhow!(
+                    "Handler `{}` of data source `{}` can not access entity type `{}` since it is not listed in the `entities` of the handler",
+                    handler,
+                    self.data_source_name,
+                    entity_type
+                )));
+            }
+        }
+        if state.declared_entities_only()
+            && !self
+                .data_source_entities
+                .iter()
+                .any(|entity| entity == entity_type)
+        {
+            return Err(HostExportError::Deterministic(anyhow::anyhow!(
+                "Data source `{}` can not access entity type `{}` since it is not listed in the `entities` of its mapping",
+                self.data_source_name,
+                entity_type
+            )));
+        }
+        Ok(())
+    }
+
     pub(crate) fn store_set(
         &self,
         logger: &Logger,
         state: &mut BlockState<C>,
+        block_ptr: &BlockPtr,
         proof_of_indexing: &SharedProofOfIndexing,
         entity_type: String,
         entity_id: String,
-        data: HashMap<String, Value>,
+        mut data: HashMap<String, Value>,
         stopwatch: &StopwatchMetrics,
         gas: &GasCounter,
-    ) -> Result<(), anyhow::Error> {
+    ) -> Result<(), HostExportError> {
+        self.check_entity_access(state, &entity_type)?;
+
+        // Entities of types with a composite id get their id from their key
+        // fields. Mappings do not need to set it, i.e., can pass an empty
+        // id, but if they set it, it has to be that id
+        let entity_id = match state
+            .entity_cache
+            .store
+            .input_schema()
+            .composite_id(&EntityType::new(entity_type.clone()), &data)
+            .map_err(HostExportError::Deterministic)?
+        {
+            Some(id) => {
+                let data_id = data.get("id").and_then(|id| id.as_str());
+                for given in [Some(entity_id.as_str()), data_id].into_iter().flatten() {
+                    if !given.is_empty() && given != id {
+                        return Err(HostExportError::Deterministic(anyhow::anyhow!(
+                            "Entity {}[{}]: the id is formed from the key fields and must be `{}`",
+                            entity_type,
+                            given,
+                            id
+                        )));
+                    }
+                }
+                data.insert("id".to_owned(), Value::String(id.clone()));
+                id
+            }
+            None => entity_id,
+        };
+
         let poi_section = stopwatch.start_section("host_export_store_set__proof_of_indexing");
         write_poi_event(
             proof_of_indexing,
@@ -158,12 +259,109 @@ impl<C: Blockchain> HostExports<C> {
 
         gas.consume_host_fn(gas::STORE_SET.with_args(complexity::Linear, (&key, &data)))?;
 
-        let entity = Entity::from(data);
+        let mut entity = Entity::from(data);
+        if self.uncrashable {
+            Self::fill_required_defaults(state, block_ptr, &key, &mut entity)?;
+        }
+        if self.strict_references {
+            Self::record_references(state, &key, &entity);
+        }
+        if self.api_version >= API_VERSION_0_0_8 {
+            entity
+                .check_big_decimals(&key)
+                .map_err(HostExportError::Deterministic)?;
+        }
         state.entity_cache.set(key.clone(), entity)?;
 
         Ok(())
     }
 
+    /// Give the fields of `entity` that are required, but that neither
+    /// `entity` nor the existing version of it sets, default values, and
+    /// record a warning for each of them. Only used for subgraphs that
+    /// declare the `uncrashable` feature
+    fn fill_required_defaults(
+        state: &mut BlockState<C>,
+        block_ptr: &BlockPtr,
+        key: &EntityKey,
+        entity: &mut Entity,
+    ) -> Result<(), HostExportError> {
+        let schema = state.entity_cache.store.input_schema();
+        if entity.validate(&schema, key).is_ok() {
+            return Ok(());
+        }
+        let mut merged = state
+            .entity_cache
+            .get(key)
+            .map_err(|e| HostExportError::Unknown(e.into()))?
+            .unwrap_or_default();
+        merged.merge(entity.clone());
+        for (field, default) in merged.required_defaults(&schema, key) {
+            let message = format!(
+                "Entity {}[{}]: required field `{}` was not set and was filled in with `{}`",
+                key.entity_type, key.entity_id, field, default
+            );
+            let handler = state.handler().map(str::to_string);
+            state.push_warning(SubgraphError {
+                subgraph_id: key.subgraph_id.clone(),
+                message,
+                block_ptr: Some(block_ptr.clone()),
+                handler,
+                deterministic: true,
+            });
+            entity.insert(field, default);
+        }
+        Ok(())
+    }
+
+    /// Remember the references to other entities that `entity` sets so
+    /// that they can be checked at the end of the block
+    fn record_references(state: &mut BlockState<C>, key: &EntityKey, entity: &Entity) {
+        let schema = state.entity_cache.store.input_schema();
+        let object_type = match schema
+            .document
+            .get_object_type_definitions()
+            .into_iter()
+            .find(|object_type| object_type.name == key.entity_type.as_str())
+        {
+            Some(object_type) => object_type,
+            None => return,
+        };
+        let handler = state.handler().unwrap_or_default().to_string();
+        for field in object_type
+            .fields
+            .iter()
+            .filter(|field| !field.is_derived())
+        {
+            let target_types = match schema
+                .document
+                .get_named_type(field.field_type.get_base_type())
+            {
+                Some(s::TypeDefinition::Object(target)) => vec![EntityType::from(target)],
+                Some(s::TypeDefinition::Interface(target)) => schema
+                    .types_for_interface
+                    .get(&EntityType::from(target))
+                    .map(|types| types.iter().map(EntityType::from).collect())
+                    .unwrap_or_default(),
+                _ => continue,
+            };
+            let values = match entity.get(&field.name) {
+                Some(Value::List(values)) => values.as_slice(),
+                Some(value) => std::slice::from_ref(value),
+                None => continue,
+            };
+            for target_id in values.iter().filter_map(EntityReference::id_of) {
+                state.push_reference(EntityReference {
+                    source: key.clone(),
+                    field: field.name.clone(),
+                    target_types: target_types.clone(),
+                    target_id,
+                    handler: handler.clone(),
+                });
+            }
+        }
+    }
+
     pub(crate) fn store_remove(
         &self,
         logger: &Logger,
@@ -173,6 +371,8 @@ impl<C: Blockchain> HostExports<C> {
         entity_id: String,
         gas: &GasCounter,
     ) -> Result<(), HostExportError> {
+        self.check_entity_access(state, &entity_type)?;
+
         write_poi_event(
             proof_of_indexing,
             &ProofOfIndexingEvent::RemoveEntity {
@@ -201,19 +401,40 @@ impl<C: Blockchain> HostExports<C> {
         entity_type: String,
         entity_id: String,
         gas: &GasCounter,
-    ) -> Result<Option<Entity>, anyhow::Error> {
+    ) -> Result<Option<Entity>, HostExportError> {
+        self.check_entity_access(state, &entity_type)?;
+
         let store_key = EntityKey {
             subgraph_id: self.subgraph_id.clone(),
             entity_type: EntityType::new(entity_type),
             entity_id,
         };
 
-        let result = state.entity_cache.get(&store_key)?;
+        let result = state
+            .entity_cache
+            .get(&store_key)
+            .map_err(anyhow::Error::from)?;
         gas.consume_host_fn(gas::STORE_GET.with_args(complexity::Linear, (&store_key, &result)))?;
 
         Ok(result)
     }
 
+    pub(crate) fn store_changes_in_block(
+        &self,
+        state: &mut BlockState<C>,
+        entity_type: String,
+        gas: &GasCounter,
+    ) -> Result<Vec<String>, HostExportError> {
+        self.check_entity_access(state, &entity_type)?;
+
+        let ids = state
+            .entity_cache
+            .changed_ids(&EntityType::new(entity_type));
+        gas.consume_host_fn(gas::STORE_GET.with_args(complexity::Size, &ids))?;
+
+        Ok(ids)
+    }
+
     /// Prints the module of `n` in hex.
     /// Integers are encoded using the least amount of digits (no leading zero digits).
     /// Their encoding may be of uneven length. The number zero encodes as "0x0".
@@ -517,7 +738,7 @@ impl<C: Blockchain> HostExports<C> {
         gas: &GasCounter,
     ) -> Result<BigDecimal, DeterministicHostError> {
         gas.consume_host_fn(gas::BIG_MATH_GAS_OP.with_args(complexity::Linear, (&x, &y)))?;
-        Ok(x + y)
+        checked_big_decimal(&self.api_version, x + y)
     }
 
     pub(crate) fn big_decimal_minus(
@@ -527,7 +748,7 @@ impl<C: Blockchain> HostExports<C> {
         gas: &GasCounter,
     ) -> Result<BigDecimal, DeterministicHostError> {
         gas.consume_host_fn(gas::BIG_MATH_GAS_OP.with_args(complexity::Linear, (&x, &y)))?;
-        Ok(x - y)
+        checked_big_decimal(&self.api_version, x - y)
     }
 
     pub(crate) fn big_decimal_times(
@@ -537,7 +758,7 @@ impl<C: Blockchain> HostExports<C> {
         gas: &GasCounter,
     ) -> Result<BigDecimal, DeterministicHostError> {
         gas.consume_host_fn(gas::BIG_MATH_GAS_OP.with_args(complexity::Mul, (&x, &y)))?;
-        Ok(x * y)
+        checked_big_decimal(&self.api_version, x * y)
     }
 
     /// Maximum precision of 100 decimal digits.
@@ -554,7 +775,7 @@ impl<C: Blockchain> HostExports<C> {
                 x
             )));
         }
-        Ok(x / y)
+        checked_big_decimal(&self.api_version, x / y)
     }
 
     pub(crate) fn big_decimal_equals(
@@ -582,9 +803,10 @@ impl<C: Blockchain> HostExports<C> {
         gas: &GasCounter,
     ) -> Result<BigDecimal, DeterministicHostError> {
         gas.consume_host_fn(gas::DEFAULT_GAS_OP.with_args(complexity::Size, &s))?;
-        BigDecimal::from_str(&s)
+        let big_decimal = BigDecimal::from_str(&s)
             .with_context(|| format!("string  is not a BigDecimal: '{}'", s))
-            .map_err(DeterministicHostError::from)
+            .map_err(DeterministicHostError::from)?;
+        checked_big_decimal(&self.api_version, big_decimal)
     }
 
     pub(crate) fn data_source_create(
@@ -638,6 +860,41 @@ impl<C: Blockchain> HostExports<C> {
         Ok(())
     }
 
+    pub(crate) fn data_source_remove(
+        &self,
+        logger: &Logger,
+        state: &mut BlockState<C>,
+        gas: &GasCounter,
+    ) -> Result<(), HostExportError> {
+        gas.consume_host_fn(gas::REMOVE_DATA_SOURCE)?;
+        let creation_block = self
+            .data_source_creation_block
+            .with_context(|| {
+                format!(
+                    "Failed to remove data source `{}`: only data sources \
+                     created from templates can be removed",
+                    self.data_source_name
+                )
+            })
+            .map_err(DeterministicHostError::from)?;
+        info!(
+            logger,
+            "Remove data source";
+            "name" => &self.data_source_name,
+            "address" => hex::encode(&self.data_source_address)
+        );
+
+        // Remember that we need to remove this data source
+        state.push_removed_data_source(RemovedDataSource {
+            name: self.data_source_name.clone(),
+            address: self.data_source_address.clone(),
+            context: self.data_source_context.cheap_clone(),
+            creation_block,
+        });
+
+        Ok(())
+    }
+
     pub(crate) fn ens_name_by_hash(&self, hash: &str) -> Result<Option<String>, anyhow::Error> {
         Ok(self.ens_lookup.find_name(hash)?)
     }
@@ -695,6 +952,21 @@ impl<C: Blockchain> HostExports<C> {
             .unwrap_or_default())
     }
 
+    /// The value of `key` in the context of the data source, if it has one
+    pub(crate) fn data_source_context_value(
+        &self,
+        key: &str,
+        gas: &GasCounter,
+    ) -> Result<Option<Value>, DeterministicHostError> {
+        gas.consume_host_fn(Gas::new(gas::DEFAULT_BASE_COST))?;
+        Ok(self
+            .data_source_context
+            .as_ref()
+            .as_ref()
+            .and_then(|context| context.get(key))
+            .cloned())
+    }
+
     pub(crate) fn json_from_bytes(
         &self,
         bytes: &Vec<u8>,
@@ -742,21 +1014,121 @@ impl<C: Blockchain> HostExports<C> {
         types: String,
         data: Vec<u8>,
         gas: &GasCounter,
-    ) -> Result<Token, anyhow::Error> {
+    ) -> Result<Option<Token>, DeterministicHostError> {
         gas.consume_host_fn(gas::DEFAULT_GAS_OP.with_args(complexity::Size, &data))?;
 
-        let param_types =
-            Reader::read(&types).map_err(|e| anyhow::anyhow!("Failed to read types: {}", e))?;
+        let param_types = match Reader::read(&types) {
+            Ok(param_types) => param_types,
+            Err(_) => return Ok(None),
+        };
 
-        decode(&[param_types], &data)
+        Ok(decode(&[param_types], &data)
             // The `.pop().unwrap()` here is ok because we're always only passing one
             // `param_types` to `decode`, so the returned `Vec` has always size of one.
             // We can't do `tokens[0]` because the value can't be moved out of the `Vec`.
             .map(|mut tokens| tokens.pop().unwrap())
-            .context("Failed to decode")
+            .ok())
+    }
+
+    pub(crate) fn ethereum_encode_call(
+        &self,
+        signature: String,
+        args: Vec<Token>,
+        gas: &GasCounter,
+    ) -> Result<Option<Vec<u8>>, DeterministicHostError> {
+        let encoded = match encode_call(&signature, &args) {
+            Ok(encoded) => encoded,
+            Err(_) => return Ok(None),
+        };
+
+        gas.consume_host_fn(gas::DEFAULT_GAS_OP.with_args(complexity::Size, &encoded))?;
+
+        Ok(Some(encoded))
+    }
+
+    pub(crate) fn ethereum_decode_call(
+        &self,
+        signature: String,
+        data: Vec<u8>,
+        gas: &GasCounter,
+    ) -> Result<Option<Vec<Token>>, DeterministicHostError> {
+        gas.consume_host_fn(gas::DEFAULT_GAS_OP.with_args(complexity::Size, &data))?;
+
+        Ok(decode_call(&signature, &data).ok())
+    }
+}
+
+/// Encode a call of the function with `signature`, for example
+/// `transfer(address,uint256)`, with `args`. The encoding starts with the
+/// function selector, like the input of a transaction
+fn encode_call(signature: &str, args: &[Token]) -> Result<Vec<u8>, anyhow::Error> {
+    let (name, types) = parse_function_signature(signature)?;
+    if !Token::types_check(args, &types) {
+        return Err(anyhow::anyhow!(
+            "arguments do not match the parameters of `{}`",
+            signature
+        ));
+    }
+
+    let mut encoded = ethabi::short_signature(name, &types).to_vec();
+    encoded.extend(encode(args));
+    Ok(encoded)
+}
+
+/// Decode the arguments of a call of the function with `signature` from
+/// `data`, which must start with the selector of that function
+fn decode_call(signature: &str, data: &[u8]) -> Result<Vec<Token>, anyhow::Error> {
+    let (name, types) = parse_function_signature(signature)?;
+    let selector = ethabi::short_signature(name, &types);
+    if data.len() < 4 || data[..4] != selector {
+        return Err(anyhow::anyhow!(
+            "data is not a call of `{}`, which has selector 0x{}",
+            signature,
+            hex::encode(selector)
+        ));
+    }
+
+    decode(&types, &data[4..]).context("Failed to decode")
+}
+
+/// Split a function signature like `transfer(address,uint256)` into the
+/// function name and its parameter types
+fn parse_function_signature(
+    signature: &str,
+) -> Result<(&str, Vec<ethabi::ParamType>), anyhow::Error> {
+    let signature = signature.trim();
+    let (name, params) = signature
+        .find('(')
+        .map(|pos| signature.split_at(pos))
+        .filter(|(name, _)| !name.is_empty())
+        .ok_or_else(|| anyhow::anyhow!("`{}` is not a function signature", signature))?;
+    match Reader::read(params) {
+        Ok(ethabi::ParamType::Tuple(types)) => Ok((name, types)),
+        Ok(_) => Err(anyhow::anyhow!(
+            "`{}` is not a function signature",
+            signature
+        )),
+        Err(e) => Err(anyhow::anyhow!(
+            "Failed to read the parameters of `{}`: {}",
+            signature,
+            e
+        )),
     }
 }
 
+/// Fail deterministically for `BigDecimal` results that we could not store.
+/// Mappings before `apiVersion` 0.0.8 get their results unchecked, as they
+/// always have, so that their subgraphs keep indexing the same way
+fn checked_big_decimal(
+    api_version: &Version,
+    x: BigDecimal,
+) -> Result<BigDecimal, DeterministicHostError> {
+    if api_version >= &API_VERSION_0_0_8 {
+        x.check_exponent().map_err(DeterministicHostError::from)?;
+    }
+    Ok(x)
+}
+
 fn string_to_h160(string: &str) -> Result<H160, DeterministicHostError> {
     // `H160::from_str` takes a hex string with no leading `0x`.
     let s = string.trim_start_matches("0x");
@@ -813,3 +1185,54 @@ fn bytes_to_string_is_lossy() {
         )
     )
 }
+
+#[test]
+fn checks_big_decimal_exponents_from_api_version_0_0_8() {
+    let too_big = BigDecimal::new(BigInt::from(7), i64::from(BigDecimal::MAX_EXP) + 1);
+    let fine = BigDecimal::new(BigInt::from(7), i64::from(BigDecimal::MAX_EXP));
+
+    // Older mappings keep getting the results they always got
+    let old = Version::new(0, 0, 7);
+    assert_eq!(too_big, checked_big_decimal(&old, too_big.clone()).unwrap());
+    assert!(checked_big_decimal(&API_VERSION_0_0_8, too_big).is_err());
+    assert_eq!(
+        fine,
+        checked_big_decimal(&API_VERSION_0_0_8, fine.clone()).unwrap()
+    );
+}
+
+#[test]
+fn encodes_and_decodes_calls() {
+    let signature = "swap(address,(uint256,bytes[]))";
+    let args = vec![
+        Token::Address(H160::from_low_u64_be(7)),
+        Token::Tuple(vec![
+            Token::Uint(1.into()),
+            Token::Array(vec![Token::Bytes(vec![1, 2, 3])]),
+        ]),
+    ];
+    let data = encode_call(signature, &args).unwrap();
+    assert_eq!(
+        ethabi::short_signature(
+            "swap",
+            &[
+                ethabi::ParamType::Address,
+                ethabi::ParamType::Tuple(vec![
+                    ethabi::ParamType::Uint(256),
+                    ethabi::ParamType::Array(Box::new(ethabi::ParamType::Bytes)),
+                ]),
+            ],
+        ),
+        data[..4]
+    );
+    assert_eq!(args, decode_call(signature, &data).unwrap());
+
+    // Calls of other functions and arguments of the wrong type are rejected
+    assert!(decode_call("swap(address,uint256)", &data).is_err());
+    assert!(decode_call(signature, &data[..3]).is_err());
+    assert!(encode_call(signature, &args[..1]).is_err());
+
+    assert!(parse_function_signature("(address,uint256)").is_err());
+    assert!(parse_function_signature("transfer").is_err());
+    assert!(parse_function_signature("transfer(address,integer)").is_err());
+}
@@ -1,17 +1,100 @@
 use crate::gas_rules::GasRules;
 use crate::module::{ExperimentalFeatures, WasmInstance};
 use futures::sync::mpsc;
-use futures03::channel::oneshot::Sender;
+use futures03::channel::oneshot::{self, Sender};
 use graph::blockchain::{Blockchain, HostFn, TriggerWithHandler};
 use graph::components::store::SubgraphFork;
 use graph::components::subgraph::{MappingError, SharedProofOfIndexing};
+use graph::prelude::tokio::sync::Semaphore;
 use graph::prelude::*;
 use graph::runtime::gas::Gas;
-use std::collections::BTreeMap;
-use std::sync::Arc;
-use std::thread;
+use lazy_static::lazy_static;
+use std::collections::{BTreeMap, HashMap};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc as std_mpsc;
+use std::sync::{Arc, Mutex, Weak};
+use std::time::Instant;
 
-/// Spawn a wasm module in its own thread.
+lazy_static! {
+    static ref MAPPING_POOL: MappingPool = MappingPool::new(ENV_VARS.mappings.threads);
+}
+
+type Job = Box<dyn FnOnce() + Send>;
+
+/// The threads that run the handlers of all mappings. They are separate
+/// from the threads of the tokio runtime so that long-running handlers can
+/// not keep it from serving queries. The handlers of one deployment only
+/// ever occupy `threads_per_deployment` of them at the same time.
+struct MappingPool {
+    jobs: Mutex<std_mpsc::Sender<Job>>,
+    busy: Arc<AtomicUsize>,
+    queued: Arc<AtomicUsize>,
+    deployments: Mutex<HashMap<DeploymentHash, Weak<Semaphore>>>,
+}
+
+impl MappingPool {
+    fn new(threads: usize) -> Self {
+        let (jobs, receiver) = std_mpsc::channel::<Job>();
+        let receiver = Arc::new(Mutex::new(receiver));
+        for i in 0..threads {
+            let receiver = receiver.cheap_clone();
+            graph::spawn_thread(format!("mapping-{}", i), move || loop {
+                let job = match receiver.lock().unwrap().recv() {
+                    Ok(job) => job,
+                    Err(_) => break,
+                };
+                // A panicking handler drops its result sender, which fails
+                // the trigger, but must not take the thread down with it.
+                let _ = panic::catch_unwind(AssertUnwindSafe(job));
+            });
+        }
+        MappingPool {
+            jobs: Mutex::new(jobs),
+            busy: Arc::new(AtomicUsize::new(0)),
+            queued: Arc::new(AtomicUsize::new(0)),
+            deployments: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// The semaphore that limits how many threads the handlers of
+    /// `deployment` use. All modules of the deployment share it
+    fn permits(&self, deployment: &DeploymentHash) -> Arc<Semaphore> {
+        let mut deployments = self.deployments.lock().unwrap();
+        deployments.retain(|_, permits| permits.strong_count() > 0);
+        if let Some(permits) = deployments.get(deployment).and_then(Weak::upgrade) {
+            return permits;
+        }
+        let permits = Arc::new(Semaphore::new(ENV_VARS.mappings.threads_per_deployment));
+        deployments.insert(deployment.clone(), Arc::downgrade(&permits));
+        permits
+    }
+
+    /// Run `job` on one of the threads once one is free
+    fn run(&self, metrics: Arc<HostMetrics>, job: impl FnOnce() + Send + 'static) {
+        let busy = self.busy.cheap_clone();
+        let queued = self.queued.cheap_clone();
+        let job = move || {
+            let running = busy.fetch_add(1, Ordering::SeqCst) + 1;
+            let waiting = queued.fetch_sub(1, Ordering::SeqCst) - 1;
+            metrics.set_mapping_pool_usage(running, waiting);
+            // The thread is free again even if the job panics
+            let _running = defer::defer(|| {
+                let running = busy.fetch_sub(1, Ordering::SeqCst) - 1;
+                metrics.set_mapping_pool_usage(running, queued.load(Ordering::SeqCst));
+            });
+            job();
+        };
+
+        let waiting = self.queued.fetch_add(1, Ordering::SeqCst) + 1;
+        metrics.set_mapping_pool_usage(self.busy.load(Ordering::SeqCst), waiting);
+        // Unwrap: the threads never stop receiving jobs
+        self.jobs.lock().unwrap().send(Box::new(job)).unwrap();
+    }
+}
+
+/// Spawn a task that passes the triggers for a wasm module to the mapping
+/// threads, one at a time.
 pub fn spawn_module<C: Blockchain>(
     raw_module: Vec<u8>,
     logger: Logger,
@@ -26,50 +109,52 @@ pub fn spawn_module<C: Blockchain>(
     // Create channel for event handling requests
     let (mapping_request_sender, mapping_request_receiver) = mpsc::channel(100);
 
-    // wasmtime instances are not `Send` therefore they cannot be scheduled by
-    // the regular tokio executor, so we create a dedicated thread.
+    // wasmtime instances are not `Send`, but every trigger is handled by a
+    // new instance on one of the mapping threads.
     //
-    // In case of failure, this thread may panic or simply terminate,
-    // dropping the `mapping_request_receiver` which ultimately causes the
-    // subgraph to fail the next time it tries to handle an event.
-    let conf =
-        thread::Builder::new().name(format!("mapping-{}-{}", &subgraph_id, uuid::Uuid::new_v4()));
-    conf.spawn(move || {
-        let _runtime_guard = runtime.enter();
+    // In case of failure, the job for a trigger drops its `result_sender`,
+    // which ultimately causes the subgraph to fail.
+    let _runtime_guard = runtime.enter();
+    let permits = MAPPING_POOL.permits(&subgraph_id);
+    graph::spawn(async move {
+        let mut requests = mapping_request_receiver.compat();
 
         // Pass incoming triggers to the WASM module and return entity changes;
         // Stop when canceled because all RuntimeHosts and their senders were dropped.
-        match mapping_request_receiver
-            .map_err(|()| unreachable!())
-            .for_each(move |request| {
-                let MappingRequest {
-                    ctx,
-                    trigger,
-                    result_sender,
-                } = request;
+        while let Some(Ok(request)) = requests.next().await {
+            let MappingRequest {
+                ctx,
+                trigger,
+                result_sender,
+            } = request;
 
+            let queued_at = Instant::now();
+            // Unwrap: the semaphore is never closed
+            let permit = permits.cheap_clone().acquire_owned().await.unwrap();
+            let (done_sender, done) = oneshot::channel();
+            let valid_module = valid_module.cheap_clone();
+            let metrics = host_metrics.cheap_clone();
+            MAPPING_POOL.run(host_metrics.cheap_clone(), move || {
+                metrics.observe_mapping_queue_wait_time(queued_at.elapsed().as_secs_f64());
                 let result = instantiate_module_and_handle_trigger(
-                    valid_module.cheap_clone(),
+                    valid_module,
                     ctx,
                     trigger,
-                    host_metrics.cheap_clone(),
+                    metrics,
                     timeout,
                     experimental_features,
                 );
+                // The receiver is gone if the subgraph was stopped
+                let _ = result_sender.send(result);
+                drop(permit);
+                let _ = done_sender.send(());
+            });
 
-                result_sender
-                    .send(result)
-                    .map_err(|_| anyhow::anyhow!("WASM module result receiver dropped."))
-            })
-            .wait()
-        {
-            Ok(()) => debug!(logger, "Subgraph stopped, WASM runtime thread terminated"),
-            Err(e) => debug!(logger, "WASM runtime thread terminated abnormally";
-                                    "error" => e.to_string()),
+            // Handle the triggers of a module in order
+            let _ = done.await;
         }
-    })
-    .map(|_| ())
-    .context("Spawning WASM runtime thread failed")?;
+        debug!(logger, "Subgraph stopped, WASM runtime task terminated");
+    });
 
     Ok(mapping_request_sender)
 }
@@ -124,7 +209,8 @@ impl<C: Blockchain> MappingContext<C> {
             logger: self.logger.cheap_clone(),
             host_exports: self.host_exports.cheap_clone(),
             block_ptr: self.block_ptr.cheap_clone(),
-            state: BlockState::new(self.state.entity_cache.store.clone(), Default::default()),
+            state: BlockState::new(self.state.entity_cache.store.clone(), Default::default())
+                .with_declared_entities_only(self.state.declared_entities_only()),
             proof_of_indexing: self.proof_of_indexing.cheap_clone(),
             host_fns: self.host_fns.cheap_clone(),
             debug_fork: self.debug_fork.cheap_clone(),
@@ -154,8 +240,12 @@ impl ValidModule {
         // e3f03e62-40e4-4f8c-b4a1-d0375cca0b76. We do this by round-tripping the module through
         // parity - injecting gas then serializing again.
         let parity_module = parity_wasm::elements::Module::from_bytes(raw_module)?;
-        let parity_module = pwasm_utils::inject_gas_counter(parity_module, &GasRules, "gas")
+        let mut parity_module = pwasm_utils::inject_gas_counter(parity_module, &GasRules, "gas")
             .map_err(|_| anyhow!("Failed to inject gas counter"))?;
+        if let Some(max_memory) = ENV_VARS.mappings.max_memory {
+            limit_memory(&mut parity_module, max_memory)?;
+            flag_failed_memory_grow(&mut parity_module)?;
+        }
         let raw_module = parity_module.to_bytes()?;
 
         // We currently use Cranelift as a compilation engine. Cranelift is an optimizing compiler,
@@ -192,3 +282,241 @@ impl ValidModule {
         })
     }
 }
+
+/// The size of a WASM memory page in bytes
+const WASM_PAGE_SIZE: usize = 64 * 1024;
+
+/// The global that `flag_failed_memory_grow` exports. It is set to 1 once
+/// a `memory.grow` in the instance failed
+pub(crate) const MEMORY_GROW_FAILED: &str = "__graph_memory_grow_failed";
+
+/// Lower the maximum size of the memories that `module` defines to
+/// `max_memory` bytes, so that `memory.grow` fails when a handler needs more
+fn limit_memory(
+    module: &mut parity_wasm::elements::Module,
+    max_memory: usize,
+) -> Result<(), anyhow::Error> {
+    use parity_wasm::elements::MemoryType;
+
+    let max_pages = u32::try_from(max_memory / WASM_PAGE_SIZE).unwrap_or(u32::MAX);
+    if let Some(section) = module.memory_section_mut() {
+        for memory in section.entries_mut() {
+            let limits = memory.limits();
+            if limits.initial() > max_pages {
+                return Err(anyhow!(
+                    "the module needs {} bytes of memory initially, but GRAPH_RUNTIME_MAX_MEMORY \
+                     only allows {} bytes",
+                    limits.initial() as usize * WASM_PAGE_SIZE,
+                    max_memory
+                ));
+            }
+            let maximum = limits.maximum().map_or(max_pages, |max| max.min(max_pages));
+            *memory = MemoryType::new(limits.initial(), Some(maximum));
+        }
+    }
+    Ok(())
+}
+
+/// Add a mutable global to `module`, exported as `MEMORY_GROW_FAILED`, and
+/// set it whenever a `memory.grow` returns -1. That way, a trap can be
+/// attributed to the memory limit only if the handler actually ran into it
+fn flag_failed_memory_grow(
+    module: &mut parity_wasm::elements::Module,
+) -> Result<(), anyhow::Error> {
+    use parity_wasm::elements::{
+        BlockType, ExportEntry, GlobalEntry, GlobalSection, GlobalType, ImportCountType, InitExpr,
+        Instruction::*, Internal, Local, Section, Type, ValueType,
+    };
+
+    let flag = module.import_count(ImportCountType::Global) as u32
+        + module
+            .global_section()
+            .map_or(0, |section| section.entries().len() as u32);
+    let entry = GlobalEntry::new(
+        GlobalType::new(ValueType::I32, true),
+        InitExpr::new(vec![I32Const(0), End]),
+    );
+    match module.global_section_mut() {
+        Some(section) => section.entries_mut().push(entry),
+        None => module
+            .insert_section(Section::Global(GlobalSection::with_entries(vec![entry])))
+            .map_err(|e| anyhow!("failed to add a global to the module: {}", e))?,
+    }
+    module
+        .export_section_mut()
+        .ok_or_else(|| anyhow!("the module does not export anything"))?
+        .entries_mut()
+        .push(ExportEntry::new(
+            MEMORY_GROW_FAILED.to_string(),
+            Internal::Global(flag),
+        ));
+
+    // The number of parameters of each function that the module defines,
+    // which come before its locals
+    let types: Vec<u32> = module
+        .type_section()
+        .map(|section| {
+            section
+                .types()
+                .iter()
+                .map(|Type::Function(ty)| ty.params().len() as u32)
+                .collect()
+        })
+        .unwrap_or_default();
+    let params: Vec<u32> = module
+        .function_section()
+        .map(|section| {
+            section
+                .entries()
+                .iter()
+                // A module with a dangling type reference is rejected when
+                // it is compiled
+                .map(|func| types.get(func.type_ref() as usize).copied().unwrap_or(0))
+                .collect()
+        })
+        .unwrap_or_default();
+
+    let bodies = match module.code_section_mut() {
+        Some(section) => section.bodies_mut(),
+        None => return Ok(()),
+    };
+    for (body, params) in bodies.iter_mut().zip(params) {
+        if !body
+            .code()
+            .elements()
+            .iter()
+            .any(|instr| matches!(instr, GrowMemory(_)))
+        {
+            continue;
+        }
+
+        // A new local holds the result of `memory.grow` while it is checked
+        let result = params + body.locals().iter().map(Local::count).sum::<u32>();
+        body.locals_mut().push(Local::new(1, ValueType::I32));
+
+        let code = body.code_mut().elements_mut();
+        let mut instrumented = Vec::with_capacity(code.len());
+        for instr in code.drain(..) {
+            match instr {
+                GrowMemory(mem) => instrumented.extend([
+                    GrowMemory(mem),
+                    TeeLocal(result),
+                    I32Const(-1),
+                    I32Eq,
+                    If(BlockType::NoResult),
+                    I32Const(1),
+                    SetGlobal(flag),
+                    End,
+                    GetLocal(result),
+                ]),
+                instr => instrumented.push(instr),
+            }
+        }
+        *code = instrumented;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use graph_mock::MockMetricsRegistry;
+
+    use super::*;
+
+    /// A module with one page of memory that exports `grow`, which grows
+    /// the memory by the given number of pages
+    const GROW: &[u8] = &[
+        0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00, // header
+        0x01, 0x06, 0x01, 0x60, 0x01, 0x7f, 0x01, 0x7f, // type (i32) -> i32
+        0x03, 0x02, 0x01, 0x00, // one function of that type
+        0x05, 0x03, 0x01, 0x00, 0x01, // one memory of one page
+        0x07, 0x08, 0x01, 0x04, b'g', b'r', b'o', b'w', 0x00, 0x00, // export `grow`
+        0x0a, 0x08, 0x01, 0x06, 0x00, // code of `grow`, without locals
+        0x20, 0x00, 0x40, 0x00, 0x0b, // local.get 0; memory.grow; end
+    ];
+
+    #[test]
+    fn flags_failed_memory_grow() {
+        let mut module = parity_wasm::elements::Module::from_bytes(GROW).unwrap();
+        limit_memory(&mut module, 2 * WASM_PAGE_SIZE).unwrap();
+        flag_failed_memory_grow(&mut module).unwrap();
+
+        let store = wasmtime::Store::default();
+        let module =
+            wasmtime::Module::from_binary(store.engine(), &module.to_bytes().unwrap()).unwrap();
+        let instance = wasmtime::Instance::new(&store, &module, &[]).unwrap();
+        let grow = instance
+            .get_func("grow")
+            .unwrap()
+            .typed::<i32, i32>()
+            .unwrap();
+        let failed = || {
+            instance
+                .get_global(MEMORY_GROW_FAILED)
+                .unwrap()
+                .get()
+                .i32()
+                .unwrap()
+        };
+
+        // Growing within the limit returns the old number of pages
+        assert_eq!(1, grow.call(1).unwrap());
+        assert_eq!(0, failed());
+
+        // Growing beyond it fails and sets the flag
+        assert_eq!(-1, grow.call(1).unwrap());
+        assert_eq!(1, failed());
+    }
+
+    #[test]
+    fn rejects_modules_that_need_too_much_memory() {
+        let mut module = parity_wasm::elements::Module::from_bytes(GROW).unwrap();
+        assert!(limit_memory(&mut module, WASM_PAGE_SIZE / 2).is_err());
+    }
+
+    fn host_metrics() -> Arc<HostMetrics> {
+        let registry = Arc::new(MockMetricsRegistry::new());
+        let deployment = DeploymentHash::new("mappingPool").unwrap();
+        let stopwatch = StopwatchMetrics::new(
+            graph::log::logger(false),
+            deployment.clone(),
+            "test",
+            registry.clone(),
+        );
+        Arc::new(HostMetrics::new(registry, deployment.as_str(), stopwatch))
+    }
+
+    #[test]
+    fn panicking_jobs_free_their_thread() {
+        let pool = MappingPool::new(1);
+        let busy = pool.busy.cheap_clone();
+        let (sender, receiver) = std_mpsc::channel();
+
+        pool.run(host_metrics(), || panic!("the handler panicked"));
+        pool.run(host_metrics(), move || {
+            sender.send(busy.load(Ordering::SeqCst)).unwrap();
+        });
+
+        // The only thread survived the panic and runs the second job,
+        // which is the only one that counts as running
+        let running = receiver.recv_timeout(Duration::from_secs(10)).unwrap();
+        assert_eq!(1, running);
+    }
+
+    #[test]
+    fn jobs_of_a_deployment_share_permits() {
+        let pool = MappingPool::new(1);
+        let deployment = DeploymentHash::new("mappingPool").unwrap();
+        let permits = pool.permits(&deployment);
+        assert!(Arc::ptr_eq(&permits, &pool.permits(&deployment)));
+
+        // The pool does not keep the permits of deployments alive that
+        // no module uses any more
+        let weak = Arc::downgrade(&permits);
+        drop(permits);
+        assert!(weak.upgrade().is_none());
+        assert!(pool.deployments.lock().unwrap()[&deployment]
+            .upgrade()
+            .is_none());
+    }
+}
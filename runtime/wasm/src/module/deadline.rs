@@ -0,0 +1,69 @@
+//! Handler timeouts. Instead of every instance watching its own deadline,
+//! a single thread checks the deadlines of all running instances every
+//! `TICK` and interrupts the ones whose handlers ran longer than their
+//! timeout. Hitting the timeout depends on how fast the node is, so it is
+//! never treated as a deterministic failure; only running out of gas is.
+
+use std::sync::{Arc, Mutex, Weak};
+use std::thread;
+use std::time::Duration;
+
+use lazy_static::lazy_static;
+use wasmtime::InterruptHandle;
+
+use super::TimeoutStopwatch;
+
+/// How often deadlines are checked
+const TICK: Duration = Duration::from_millis(100);
+
+struct Deadline {
+    stopwatch: Weak<Mutex<TimeoutStopwatch>>,
+    timeout: Duration,
+    interrupt: InterruptHandle,
+}
+
+lazy_static! {
+    static ref DEADLINES: Arc<Mutex<Vec<Deadline>>> = {
+        let deadlines = Arc::new(Mutex::new(Vec::new()));
+        let ticker = deadlines.clone();
+        thread::Builder::new()
+            .name("handler-deadlines".to_string())
+            .spawn(move || loop {
+                thread::sleep(TICK);
+                check(&ticker);
+            })
+            .expect("failed to spawn the handler deadline thread");
+        deadlines
+    };
+}
+
+/// Interrupt the instance behind `interrupt` once `stopwatch` shows that
+/// it has run for `timeout`. The deadline is forgotten once nothing else
+/// refers to `stopwatch` anymore, which happens when the instance is
+/// dropped.
+pub(crate) fn register(
+    stopwatch: &Arc<Mutex<TimeoutStopwatch>>,
+    timeout: Duration,
+    interrupt: InterruptHandle,
+) {
+    DEADLINES.lock().unwrap().push(Deadline {
+        stopwatch: Arc::downgrade(stopwatch),
+        timeout,
+        interrupt,
+    });
+}
+
+fn check(deadlines: &Mutex<Vec<Deadline>>) {
+    deadlines.lock().unwrap().retain(|deadline| {
+        let stopwatch = match deadline.stopwatch.upgrade() {
+            Some(stopwatch) => stopwatch,
+            None => return false,
+        };
+        let elapsed = stopwatch.lock().unwrap().elapsed();
+        if elapsed >= deadline.timeout {
+            deadline.interrupt.interrupt();
+            return false;
+        }
+        true
+    });
+}
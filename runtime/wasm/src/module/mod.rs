@@ -13,11 +13,15 @@ use wasmtime::{Memory, Trap};
 
 use graph::blockchain::{Blockchain, HostFnCtx, TriggerWithHandler};
 use graph::data::store;
+use graph::data::subgraph::API_VERSION_0_0_8;
 use graph::prelude::*;
 use graph::runtime::gas::{self, Gas, GasCounter, SaturatingInto};
 use graph::runtime::HostExportError;
 use graph::runtime::{AscHeap, IndexForAscTypeId};
-use graph::{components::subgraph::MappingError, runtime::AscPtr};
+use graph::{
+    components::subgraph::{MappingError, MemoryHold},
+    runtime::AscPtr,
+};
 use graph::{
     data::subgraph::schema::SubgraphError,
     runtime::{asc_get, asc_new, try_asc_get, DeterministicHostError},
@@ -31,8 +35,9 @@ use crate::gas_rules::{GAS_COST_LOAD, GAS_COST_STORE};
 pub use crate::host_exports;
 use crate::host_exports::HostExports;
 use crate::mapping::MappingContext;
-use crate::mapping::ValidModule;
+use crate::mapping::{ValidModule, MEMORY_GROW_FAILED};
 
+mod deadline;
 mod into_wasm_ret;
 pub mod stopwatch;
 
@@ -56,6 +61,10 @@ pub struct WasmInstance<C: Blockchain> {
 
     // A reference to the gas counter used for reporting the gas used.
     pub gas: GasCounter,
+
+    // The memory of the instance, counted against the quota of the deployment until the instance
+    // is dropped.
+    memory_hold: MemoryHold,
 }
 
 impl<C: Blockchain> Drop for WasmInstance<C> {
@@ -91,6 +100,16 @@ impl<C: Blockchain> AscHeap for WasmInstance<C> {
     ) -> Result<u32, DeterministicHostError> {
         self.instance_ctx_mut().asc_type_id(type_id_index)
     }
+
+    fn managed_new(
+        &mut self,
+        type_id_index: IndexForAscTypeId,
+        content: &[u8],
+        gas: &GasCounter,
+    ) -> Result<u32, DeterministicHostError> {
+        self.instance_ctx_mut()
+            .managed_new(type_id_index, content, gas)
+    }
 }
 
 impl<C: Blockchain> WasmInstance<C> {
@@ -104,7 +123,10 @@ impl<C: Blockchain> WasmInstance<C> {
         let value = asc_new(&mut self, value, &gas)?;
         let user_data = asc_new(&mut self, user_data, &gas)?;
 
-        self.instance_ctx_mut().ctx.state.enter_handler();
+        self.instance_ctx_mut()
+            .ctx
+            .state
+            .enter_handler(handler_name);
 
         // Invoke the callback
         self.instance
@@ -151,6 +173,15 @@ impl<C: Blockchain> WasmInstance<C> {
         self.gas.get().value()
     }
 
+    /// Whether a `memory.grow` failed in this instance. Only modules that are limited by
+    /// `GRAPH_RUNTIME_MAX_MEMORY` export the flag
+    fn memory_grow_failed(&self) -> bool {
+        self.instance
+            .get_global(MEMORY_GROW_FAILED)
+            .and_then(|flag| flag.get().i32())
+            .map_or(false, |flag| flag == 1)
+    }
+
     fn invoke_handler<T>(
         &mut self,
         handler: &str,
@@ -162,16 +193,44 @@ impl<C: Blockchain> WasmInstance<C> {
             .with_context(|| format!("function {} not found", handler))?;
 
         // Caution: Make sure all exit paths from this function call `exit_handler`.
-        self.instance_ctx_mut().ctx.state.enter_handler();
+        self.instance_ctx_mut().ctx.state.enter_handler(handler);
+
+        let start = Instant::now();
+        let result = func.typed()?.call(arg.wasm_ptr());
+        let elapsed = start.elapsed();
+
+        // The memory of an instance never shrinks, so this is what it holds until it is dropped.
+        let memory_size = self.instance_ctx().memory.data_size();
+        let host_metrics = self.instance_ctx().host_metrics.cheap_clone();
+        host_metrics.observe_handler_memory(memory_size);
+        self.memory_hold.resize(memory_size);
+
+        // A deployment that exceeds its quota is stopped, whether the handler succeeded or not.
+        let quota = match host_metrics.usage.add_cpu_time(elapsed, Instant::now()) {
+            Ok(()) => host_metrics
+                .usage
+                .check_memory()
+                .map_err(|e| ("deployment_memory", e)),
+            Err(e) => Err(("deployment_cpu", e)),
+        };
+        if let Err((limit, e)) = quota {
+            host_metrics.limit_exceeded(limit);
+            self.instance_ctx_mut().ctx.state.exit_handler();
+            return Err(e.context(format!(
+                "Handler '{}' exceeded a quota of the deployment",
+                handler
+            )));
+        }
 
         // This `match` will return early if there was a non-deterministic trap.
-        let deterministic_error: Option<Error> = match func.typed()?.call(arg.wasm_ptr()) {
+        let deterministic_error: Option<Error> = match result {
             Ok(()) => None,
             Err(trap) if self.instance_ctx().possible_reorg => {
                 self.instance_ctx_mut().ctx.state.exit_handler();
                 return Err(MappingError::PossibleReorg(trap.into()));
             }
             Err(trap) if trap.to_string().contains(TRAP_TIMEOUT) => {
+                host_metrics.limit_exceeded("timeout");
                 self.instance_ctx_mut().ctx.state.exit_handler();
                 return Err(MappingError::Unknown(Error::from(trap).context(format!(
                     "Handler '{}' hit the timeout of '{}' seconds",
@@ -179,6 +238,17 @@ impl<C: Blockchain> WasmInstance<C> {
                     self.instance_ctx().timeout.unwrap().as_secs()
                 ))));
             }
+            // A `memory.grow` failed because of `GRAPH_RUNTIME_MAX_MEMORY`. Whether that happens
+            // depends on the configuration of the node, so the error is not deterministic.
+            Err(trap) if self.memory_grow_failed() => {
+                host_metrics.limit_exceeded("memory");
+                self.instance_ctx_mut().ctx.state.exit_handler();
+                return Err(MappingError::Unknown(Error::from(trap).context(format!(
+                    "Handler '{}' hit the memory limit of {} bytes",
+                    handler,
+                    ENV_VARS.mappings.max_memory.unwrap_or_default()
+                ))));
+            }
             Err(trap) => {
                 use wasmtime::TrapCode::*;
                 let trap_code = trap.trap_code();
@@ -236,6 +306,93 @@ pub struct ExperimentalFeatures {
     pub allow_non_deterministic_ipfs: bool,
 }
 
+/// The functions of the AssemblyScript runtime that `--exportRuntime` exports. The host allocates
+/// objects with them so that the garbage collector knows about them.
+struct ManagedRuntime {
+    /// `__new(size, id)`, allocates an object of class `id` with `size` bytes of content
+    new: wasmtime::TypedFunc<(u32, u32), u32>,
+    /// `__pin(ptr)`, keeps the object at `ptr` alive until it is unpinned
+    pin: wasmtime::TypedFunc<u32, u32>,
+    /// `__unpin(ptr)`
+    unpin: wasmtime::TypedFunc<u32, ()>,
+    /// The objects allocated through `new` that are pinned, by generation. The first generation
+    /// holds the objects passed to handlers, which stay pinned until the instance is dropped, and
+    /// every host function call adds a generation for the objects it allocates that is unpinned
+    /// when the call returns.
+    pinned: Vec<Vec<u32>>,
+}
+
+impl ManagedRuntime {
+    /// Look up the runtime functions with `get_func`
+    fn new(get_func: impl Fn(&str) -> Option<wasmtime::Func>) -> Result<Self, anyhow::Error> {
+        let func = |name: &str| {
+            get_func(name).with_context(|| {
+                format!(
+                    "`{}` function not found, the mapping must be compiled with `--exportRuntime`",
+                    name
+                )
+            })
+        };
+        Ok(ManagedRuntime {
+            new: func("__new")?.typed()?.clone(),
+            pin: func("__pin")?.typed()?.clone(),
+            unpin: func("__unpin")?.typed()?.clone(),
+            pinned: vec![vec![]],
+        })
+    }
+
+    /// Allocate an object of class `type_id`, pin it in the current generation and write
+    /// `content` to it
+    fn alloc(
+        &mut self,
+        memory: &Memory,
+        type_id: u32,
+        content: &[u8],
+    ) -> Result<u32, DeterministicHostError> {
+        let size = u32::try_from(content.len()).map_err(|_| {
+            DeterministicHostError::from(anyhow!("object cannot fit in WASM memory"))
+        })?;
+        let ptr = self
+            .new
+            .call((size, type_id))
+            .with_context(|| format!("Failed to allocate object of class {}", type_id))
+            .map_err(DeterministicHostError::from)?;
+
+        // `__new` may run the garbage collector, which could free objects that we allocated
+        // earlier and that nothing in wasm refers to yet, unless they are pinned.
+        self.pin
+            .call(ptr)
+            .context("Failed to pin object")
+            .map_err(DeterministicHostError::from)?;
+        self.pinned.last_mut().unwrap().push(ptr);
+
+        memory.write(ptr as usize, content).map_err(|_| {
+            DeterministicHostError::from(anyhow!(
+                "Heap access out of bounds. Offset: {} Size: {}",
+                ptr,
+                size
+            ))
+        })?;
+        Ok(ptr)
+    }
+
+    /// Start a new generation of pinned objects for a host function call.
+    fn enter(&mut self) {
+        self.pinned.push(vec![]);
+    }
+
+    /// Unpin the objects that the current host function call allocated. The object it returns is
+    /// safe to unpin since wasm refers to it as soon as the call returns, before it can allocate.
+    fn exit(&mut self) -> Result<(), Trap> {
+        if self.pinned.len() > 1 {
+            for ptr in self.pinned.pop().unwrap() {
+                self.unpin.call(ptr)?;
+            }
+        }
+        Ok(())
+    }
+}
+
 pub struct WasmInstanceContext<C: Blockchain> {
     // In the future there may be multiple memories, but currently there is only one memory per
     // module. And at least AS calls it "memory". There is no uninitialized memory in Wasm, memory
@@ -249,6 +406,9 @@ pub struct WasmInstanceContext<C: Blockchain> {
     // Function wrapper for `idof<T>` from AssemblyScript
     id_of_type: Option<wasmtime::TypedFunc<u32, u32>>,
 
+    // The AssemblyScript runtime exports, for apiVersion >= 0.0.8.
+    managed_runtime: Option<ManagedRuntime>,
+
     pub ctx: MappingContext<C>,
     pub valid_module: Arc<ValidModule>,
     pub host_metrics: Arc<HostMetrics>,
@@ -295,25 +455,11 @@ impl<C: Blockchain> WasmInstance<C> {
         // it will be moved so we need this ugly thing.
         let ctx: Rc<RefCell<Option<MappingContext<C>>>> = Rc::new(RefCell::new(Some(ctx)));
 
-        // Start the timeout watchdog task.
+        // Interrupt the handler once it hits the timeout.
         let timeout_stopwatch = Arc::new(std::sync::Mutex::new(TimeoutStopwatch::start_new()));
         if let Some(timeout) = timeout {
-            // This task is likely to outlive the instance, which is fine.
             let interrupt_handle = linker.store().interrupt_handle().unwrap();
-            let timeout_stopwatch = timeout_stopwatch.clone();
-            graph::spawn_allow_panic(async move {
-                let minimum_wait = Duration::from_secs(1);
-                loop {
-                    let time_left =
-                        timeout.checked_sub(timeout_stopwatch.lock().unwrap().elapsed());
-                    match time_left {
-                        None => break interrupt_handle.interrupt(), // Timed out.
-
-                        Some(time) if time < minimum_wait => break interrupt_handle.interrupt(),
-                        Some(time) => tokio::time::sleep(time).await,
-                    }
-                }
-            });
+            deadline::register(&timeout_stopwatch, timeout, interrupt_handle);
         }
 
         // Because `gas` and `deterministic_host_trap` need to be accessed from the gas
@@ -364,10 +510,12 @@ impl<C: Blockchain> WasmInstance<C> {
                             let instance = instance.as_mut().unwrap();
                             let _section = instance.host_metrics.stopwatch.start_section($section);
 
+                            instance.enter_host_fn();
                             let result = instance.$rust_name(
                                 &gas,
                                 $($param.into()),*
                             );
+                            instance.exit_host_fn()?;
                             match result {
                                 Ok(result) => Ok(result.into_wasm_ret()),
                                 Err(e) => {
@@ -425,13 +573,18 @@ impl<C: Blockchain> WasmInstance<C> {
                     let _section =
                         stopwatch.start_section(&format!("host_export_{}", name_for_metrics));
 
+                    instance.enter_host_fn();
                     let ctx = HostFnCtx {
                         logger: instance.ctx.logger.cheap_clone(),
                         block_ptr: instance.ctx.block_ptr.cheap_clone(),
                         heap: instance,
                         gas: gas.cheap_clone(),
                     };
-                    let ret = (host_fn.func)(ctx, call_ptr).map_err(|e| match e {
+                    let ret = (host_fn.func)(ctx, call_ptr);
+                    // Unpin what the host fn allocated no matter how it returns, so that every
+                    // call leaves the generations of pinned objects as it found them
+                    instance.exit_host_fn()?;
+                    let ret = ret.map_err(|e| match e {
                         HostExportError::Deterministic(e) => {
                             instance.deterministic_host_trap = true;
                             e
@@ -453,6 +606,18 @@ impl<C: Blockchain> WasmInstance<C> {
 
         link!("ethereum.encode", ethereum_encode, params_ptr);
         link!("ethereum.decode", ethereum_decode, params_ptr, data_ptr);
+        link!(
+            "ethereum.encodeCall",
+            ethereum_encode_call,
+            signature_ptr,
+            args_ptr
+        );
+        link!(
+            "ethereum.decodeCall",
+            ethereum_decode_call,
+            signature_ptr,
+            data_ptr
+        );
 
         link!("abort", abort, message_ptr, file_name_ptr, line, column);
 
@@ -483,6 +648,11 @@ impl<C: Blockchain> WasmInstance<C> {
         );
 
         link!("store.remove", store_remove, entity_ptr, id_ptr);
+        link!(
+            "store.getChangesInBlock",
+            store_get_changes_in_block,
+            entity_ptr
+        );
 
         link!("typeConversion.bytesToString", bytes_to_string, ptr);
         link!("typeConversion.bytesToHex", bytes_to_hex, ptr);
@@ -532,6 +702,13 @@ impl<C: Blockchain> WasmInstance<C> {
         link!("dataSource.address", data_source_address,);
         link!("dataSource.network", data_source_network,);
         link!("dataSource.context", data_source_context,);
+        link!("dataSource.remove", data_source_remove,);
+        link!(
+            "dataSource.contextValue",
+            data_source_context_value,
+            key,
+            kind
+        );
 
         link!("ens.nameByHash", ens_name_by_hash, ptr);
 
@@ -587,10 +764,19 @@ impl<C: Blockchain> WasmInstance<C> {
             }
         }
 
+        let memory_hold = {
+            let ctx = shared_ctx.borrow();
+            let ctx = ctx.as_ref().unwrap();
+            let mut memory_hold = ctx.host_metrics.usage.hold_memory();
+            memory_hold.resize(ctx.memory.data_size());
+            memory_hold
+        };
+
         Ok(WasmInstance {
             instance,
             instance_ctx: shared_ctx,
             gas,
+            memory_hold,
         })
     }
 }
@@ -686,6 +872,21 @@ impl<C: Blockchain> AscHeap for WasmInstanceContext<C> {
             .map_err(DeterministicHostError::from)?;
         Ok(type_id)
     }
+
+    fn managed_new(
+        &mut self,
+        type_id_index: IndexForAscTypeId,
+        content: &[u8],
+        gas: &GasCounter,
+    ) -> Result<u32, DeterministicHostError> {
+        gas.consume_host_fn(Gas::new(GAS_COST_STORE as u64 * content.len() as u64))?;
+
+        let type_id = self.asc_type_id(type_id_index)?;
+        self.managed_runtime
+            .as_mut()
+            .unwrap() // Unwrap ok because it's only called on apiVersion >= 0.0.8
+            .alloc(&self.memory, type_id, content)
+    }
 }
 
 impl<C: Blockchain> WasmInstanceContext<C> {
@@ -725,9 +926,15 @@ impl<C: Blockchain> WasmInstanceContext<C> {
             ),
         };
 
+        let managed_runtime = match &ctx.host_exports.api_version {
+            version if *version < API_VERSION_0_0_8 => None,
+            _ => Some(ManagedRuntime::new(|name| instance.get_func(name))?),
+        };
+
         Ok(WasmInstanceContext {
             memory_allocate,
             id_of_type,
+            managed_runtime,
             memory,
             ctx,
             valid_module,
@@ -781,9 +988,17 @@ impl<C: Blockchain> WasmInstanceContext<C> {
             ),
         };
 
+        let managed_runtime = match &ctx.host_exports.api_version {
+            version if *version < API_VERSION_0_0_8 => None,
+            _ => Some(ManagedRuntime::new(|name| {
+                caller.get_export(name).and_then(|e| e.into_func())
+            })?),
+        };
+
         Ok(WasmInstanceContext {
             id_of_type,
             memory_allocate,
+            managed_runtime,
             memory,
             ctx,
             valid_module,
@@ -797,6 +1012,21 @@ impl<C: Blockchain> WasmInstanceContext<C> {
             experimental_features,
         })
     }
+
+    /// Start a new generation of pinned objects for a host function call.
+    fn enter_host_fn(&mut self) {
+        if let Some(runtime) = &mut self.managed_runtime {
+            runtime.enter();
+        }
+    }
+
+    /// Unpin the objects that the current host function call allocated.
+    fn exit_host_fn(&mut self) -> Result<(), Trap> {
+        match &mut self.managed_runtime {
+            Some(runtime) => runtime.exit(),
+            None => Ok(()),
+        }
+    }
 }
 
 // Implementation of externals.
@@ -851,6 +1081,7 @@ impl<C: Blockchain> WasmInstanceContext<C> {
         self.ctx.host_exports.store_set(
             &self.ctx.logger,
             &mut self.ctx.state,
+            &self.ctx.block_ptr,
             &self.ctx.proof_of_indexing,
             entity,
             id,
@@ -881,6 +1112,20 @@ impl<C: Blockchain> WasmInstanceContext<C> {
         )
     }
 
+    /// function store.getChangesInBlock(entity: string): Array<string>
+    pub fn store_get_changes_in_block(
+        &mut self,
+        gas: &GasCounter,
+        entity_ptr: AscPtr<AscString>,
+    ) -> Result<AscPtr<Array<AscPtr<AscString>>>, HostExportError> {
+        let entity_type: String = asc_get(self, entity_ptr, gas)?;
+        let ids =
+            self.ctx
+                .host_exports
+                .store_changes_in_block(&mut self.ctx.state, entity_type, gas)?;
+        Ok(asc_new(self, &ids, gas)?)
+    }
+
     /// function store.get(entity: string, id: string): Entity | null
     pub fn store_get(
         &mut self,
@@ -1533,6 +1778,13 @@ impl<C: Blockchain> WasmInstanceContext<C> {
         )
     }
 
+    /// function dataSource.remove(): void
+    pub fn data_source_remove(&mut self, gas: &GasCounter) -> Result<(), HostExportError> {
+        self.ctx
+            .host_exports
+            .data_source_remove(&self.ctx.logger, &mut self.ctx.state, gas)
+    }
+
     /// function dataSource.address(): Bytes
     pub fn data_source_address(
         &mut self,
@@ -1565,6 +1817,28 @@ impl<C: Blockchain> WasmInstanceContext<C> {
         )
     }
 
+    /// function dataSource.contextValue(key: string, kind: ValueKind): Value | null
+    pub fn data_source_context_value(
+        &mut self,
+        gas: &GasCounter,
+        key_ptr: AscPtr<AscString>,
+        kind: u32,
+    ) -> Result<AscPtr<AscEnum<StoreValueKind>>, DeterministicHostError> {
+        let key: String = asc_get(self, key_ptr, gas)?;
+        let kind = StoreValueKind::from_discriminant(kind)
+            .ok_or_else(|| anyhow!("`{}` is not a valid value kind", kind))?;
+        match self.ctx.host_exports.data_source_context_value(&key, gas)? {
+            None | Some(store::Value::Null) => Ok(AscPtr::null()),
+            Some(value) if StoreValueKind::get_kind(&value) == kind => asc_new(self, &value, gas),
+            Some(value) => Err(DeterministicHostError::from(anyhow!(
+                "the data source context entry `{}` is a {}, not a {}",
+                key,
+                StoreValueKind::get_kind(&value).name(),
+                kind.name()
+            ))),
+        }
+    }
+
     pub fn ens_name_by_hash(
         &mut self,
         gas: &GasCounter,
@@ -1610,11 +1884,9 @@ impl<C: Blockchain> WasmInstanceContext<C> {
         let data = self
             .ctx
             .host_exports
-            .ethereum_encode(asc_get(self, token_ptr, gas)?, gas);
+            .ethereum_encode(asc_get(self, token_ptr, gas)?, gas)?;
 
-        // return `null` if it fails
-        data.map(|bytes| asc_new(self, &*bytes, gas))
-            .unwrap_or(Ok(AscPtr::null()))
+        asc_new(self, &*data, gas)
     }
 
     /// function decode(types: String, data: Bytes): ethereum.Value | null
@@ -1628,12 +1900,53 @@ impl<C: Blockchain> WasmInstanceContext<C> {
             asc_get(self, types_ptr, gas)?,
             asc_get(self, data_ptr, gas)?,
             gas,
-        );
+        )?;
 
         // return `null` if it fails
-        result
-            .map(|param| asc_new(self, &param, gas))
-            .unwrap_or(Ok(AscPtr::null()))
+        match result {
+            Some(param) => asc_new(self, &param, gas),
+            None => Ok(AscPtr::null()),
+        }
+    }
+
+    /// function encodeCall(signature: string, args: Array<ethereum.Value>): Bytes | null
+    pub fn ethereum_encode_call(
+        &mut self,
+        gas: &GasCounter,
+        signature_ptr: AscPtr<AscString>,
+        args_ptr: AscPtr<Array<AscPtr<AscEnum<EthereumValueKind>>>>,
+    ) -> Result<AscPtr<Uint8Array>, DeterministicHostError> {
+        let data = self.ctx.host_exports.ethereum_encode_call(
+            asc_get(self, signature_ptr, gas)?,
+            asc_get(self, args_ptr, gas)?,
+            gas,
+        )?;
+
+        // return `null` if it fails
+        match data {
+            Some(bytes) => asc_new(self, &*bytes, gas),
+            None => Ok(AscPtr::null()),
+        }
+    }
+
+    /// function decodeCall(signature: string, data: Bytes): Array<ethereum.Value> | null
+    pub fn ethereum_decode_call(
+        &mut self,
+        gas: &GasCounter,
+        signature_ptr: AscPtr<AscString>,
+        data_ptr: AscPtr<Uint8Array>,
+    ) -> Result<AscEnumArray<EthereumValueKind>, DeterministicHostError> {
+        let result = self.ctx.host_exports.ethereum_decode_call(
+            asc_get(self, signature_ptr, gas)?,
+            asc_get(self, data_ptr, gas)?,
+            gas,
+        )?;
+
+        // return `null` if it fails
+        match result {
+            Some(args) => asc_new(self, args.as_slice(), gas),
+            None => Ok(AscPtr::null()),
+        }
     }
 
     /// function arweave.transactionData(txId: string): Bytes | null
@@ -1658,3 +1971,70 @@ impl<C: Blockchain> WasmInstanceContext<C> {
         )))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A stand-in for the AssemblyScript runtime: `__new` hands out memory with a bump
+    /// allocator and `__pin`/`__unpin` count the pinned objects in `pins`
+    const RUNTIME: &str = r#"
+        (module
+          (memory (export "memory") 1)
+          (global $next (mut i32) (i32.const 16))
+          (global $pins (export "pins") (mut i32) (i32.const 0))
+          (func (export "__new") (param $size i32) (param $id i32) (result i32)
+            (global.get $next)
+            (global.set $next (i32.add (global.get $next) (local.get $size))))
+          (func (export "__pin") (param $ptr i32) (result i32)
+            (global.set $pins (i32.add (global.get $pins) (i32.const 1)))
+            (local.get $ptr))
+          (func (export "__unpin") (param $ptr i32)
+            (global.set $pins (i32.sub (global.get $pins) (i32.const 1)))))
+    "#;
+
+    #[test]
+    fn pins_managed_objects_by_generation() {
+        let store = wasmtime::Store::default();
+        let module = wasmtime::Module::new(store.engine(), RUNTIME).unwrap();
+        let instance = wasmtime::Instance::new(&store, &module, &[]).unwrap();
+        let memory = instance.get_memory("memory").unwrap();
+        let pins = || instance.get_global("pins").unwrap().get().i32().unwrap();
+        let mut runtime = ManagedRuntime::new(|name| instance.get_func(name)).unwrap();
+
+        // Objects for the handler stay pinned
+        let ptr = runtime.alloc(&memory, 7, b"handler").unwrap();
+        assert_eq!(16, ptr);
+        let mut content = [0u8; 7];
+        memory.read(ptr as usize, &mut content).unwrap();
+        assert_eq!(b"handler", &content);
+        assert_eq!(1, pins());
+
+        // Objects a host fn allocates are unpinned when it returns, also when host fns nest
+        runtime.enter();
+        runtime.alloc(&memory, 7, b"outer").unwrap();
+        runtime.enter();
+        runtime.alloc(&memory, 7, b"inner").unwrap();
+        runtime.alloc(&memory, 7, b"inner").unwrap();
+        assert_eq!(4, pins());
+        runtime.exit().unwrap();
+        assert_eq!(2, pins());
+        runtime.exit().unwrap();
+        assert_eq!(1, pins());
+
+        // Exiting more often than entering never unpins the handler's objects
+        runtime.exit().unwrap();
+        assert_eq!(1, pins());
+    }
+
+    #[test]
+    fn requires_exported_runtime() {
+        let store = wasmtime::Store::default();
+        let module = wasmtime::Module::new(store.engine(), "(module)").unwrap();
+        let instance = wasmtime::Instance::new(&store, &module, &[]).unwrap();
+        let err = ManagedRuntime::new(|name| instance.get_func(name))
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("--exportRuntime"));
+    }
+}
@@ -120,20 +120,10 @@ impl TryFromAscObj<AscBigDecimal> for BigDecimal {
         byte_array[..bytes.len()].copy_from_slice(&bytes);
         let big_decimal = BigDecimal::new(digits, i64::from_le_bytes(byte_array));
 
-        // Validate the exponent.
-        let exp = -big_decimal.as_bigint_and_exponent().1;
-        let min_exp: i64 = BigDecimal::MIN_EXP.into();
-        let max_exp: i64 = BigDecimal::MAX_EXP.into();
-        if exp < min_exp || max_exp < exp {
-            Err(DeterministicHostError::from(anyhow::anyhow!(
-                "big decimal exponent `{}` is outside the `{}` to `{}` range",
-                exp,
-                min_exp,
-                max_exp
-            )))
-        } else {
-            Ok(big_decimal)
-        }
+        big_decimal
+            .check_exponent()
+            .map_err(DeterministicHostError::from)?;
+        Ok(big_decimal)
     }
 }
 
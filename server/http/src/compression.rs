@@ -0,0 +1,266 @@
+//! Compression of responses for clients that send an `Accept-Encoding`
+//! header that allows it
+use std::io::{self, Read, Write};
+
+use graph::prelude::{futures03, tokio, ENV_VARS};
+use http::header::{
+    HeaderMap, HeaderValue, ACCEPT_ENCODING, CONTENT_ENCODING, CONTENT_LENGTH, VARY,
+};
+use hyper::body::{Bytes, HttpBody, Sender};
+use hyper::{Body, Response};
+
+/// The size of the chunks in which compressed responses are sent
+const CHUNK_SIZE: usize = 64 * 1024;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub(crate) enum Encoding {
+    Brotli,
+    Gzip,
+}
+
+impl Encoding {
+    /// The encoding that the client prefers according to the
+    /// `Accept-Encoding` header in `headers`, if it accepts any that we
+    /// support. When the client likes both equally, we use brotli
+    pub(crate) fn negotiate(headers: &HeaderMap) -> Option<Encoding> {
+        let mut brotli = None;
+        let mut gzip = None;
+        let mut any = None;
+        for value in headers.get_all(ACCEPT_ENCODING) {
+            let value = match value.to_str() {
+                Ok(value) => value,
+                Err(_) => continue,
+            };
+            for item in value.split(',') {
+                let mut parts = item.split(';');
+                let coding = parts.next().unwrap_or("").trim().to_lowercase();
+                let quality = parts
+                    .filter_map(|param| param.trim().strip_prefix("q="))
+                    .filter_map(|q| q.trim().parse::<f32>().ok())
+                    .next()
+                    .unwrap_or(1.0);
+                match coding.as_str() {
+                    "br" => brotli = Some(quality),
+                    "gzip" | "x-gzip" => gzip = Some(quality),
+                    "*" => any = Some(quality),
+                    _ => {}
+                }
+            }
+        }
+        let brotli = brotli.or(any).unwrap_or(0.0);
+        let gzip = gzip.or(any).unwrap_or(0.0);
+        if brotli <= 0.0 && gzip <= 0.0 {
+            None
+        } else if brotli >= gzip {
+            Some(Encoding::Brotli)
+        } else {
+            Some(Encoding::Gzip)
+        }
+    }
+
+    fn as_str(&self) -> &'static str {
+        match self {
+            Encoding::Brotli => "br",
+            Encoding::Gzip => "gzip",
+        }
+    }
+
+    /// Compress everything that can be read from `data` with this
+    /// encoding, writing the result to `out` as it is produced
+    fn compress(&self, mut data: impl Read, out: impl Write) -> io::Result<()> {
+        let level = ENV_VARS.graphql.compression_level;
+        match self {
+            Encoding::Brotli => {
+                let mut encoder = brotli::CompressorWriter::new(out, CHUNK_SIZE, level, 22);
+                io::copy(&mut data, &mut encoder)?;
+                encoder.into_inner().flush()
+            }
+            Encoding::Gzip => {
+                let mut encoder =
+                    flate2::write::GzEncoder::new(out, flate2::Compression::new(level.min(9)));
+                io::copy(&mut data, &mut encoder)?;
+                encoder.finish()?.flush()
+            }
+        }
+    }
+}
+
+/// Reads the chunks of a response body as they become available
+struct BodyReader {
+    body: Body,
+    chunk: Bytes,
+}
+
+impl Read for BodyReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.chunk.is_empty() {
+            match futures03::executor::block_on(self.body.data()) {
+                Some(Ok(chunk)) => self.chunk = chunk,
+                Some(Err(e)) => return Err(io::Error::new(io::ErrorKind::Other, e)),
+                None => return Ok(0),
+            }
+        }
+        let len = buf.len().min(self.chunk.len());
+        buf[..len].copy_from_slice(&self.chunk.split_to(len));
+        Ok(len)
+    }
+}
+
+/// Sends everything written to it as chunks of a response body
+struct BodyWriter(Sender);
+
+impl Write for BodyWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        futures03::executor::block_on(self.0.send_data(Bytes::copy_from_slice(buf)))
+            .map_err(|e| io::Error::new(io::ErrorKind::BrokenPipe, e))?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Compress the body of `response` with `encoding` unless it is already
+/// encoded or too small to be worth it. The body is compressed as its
+/// chunks become available, and the compressed body is streamed to the
+/// client while it is being produced
+pub(crate) async fn compress(
+    response: Response<Body>,
+    encoding: Option<Encoding>,
+) -> Response<Body> {
+    let encoding = match encoding {
+        Some(encoding) if !response.headers().contains_key(CONTENT_ENCODING) => encoding,
+        _ => return response,
+    };
+    if let Some(size) = response.body().size_hint().exact() {
+        if size < ENV_VARS.graphql.compression_min_size as u64 {
+            return response;
+        }
+    }
+
+    let (mut parts, body) = response.into_parts();
+    let data = BodyReader {
+        body,
+        chunk: Bytes::new(),
+    };
+
+    let (sender, body) = Body::channel();
+    tokio::task::spawn_blocking(move || {
+        let mut out = io::BufWriter::with_capacity(CHUNK_SIZE, BodyWriter(sender));
+        // If this fails, either the client went away and there is nobody
+        // to report the error to, or producing the body failed. In the
+        // latter case, make sure the client does not take what it got so
+        // far for the whole body
+        if encoding.compress(data, &mut out).is_err() {
+            let (BodyWriter(sender), _) = out.into_parts();
+            sender.abort();
+        }
+    });
+
+    parts.headers.remove(CONTENT_LENGTH);
+    parts.headers.insert(
+        CONTENT_ENCODING,
+        HeaderValue::from_static(encoding.as_str()),
+    );
+    parts
+        .headers
+        .append(VARY, HeaderValue::from_static("Accept-Encoding"));
+    Response::from_parts(parts, body)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use graph::prelude::tokio;
+    use http::header::{HeaderMap, HeaderValue, ACCEPT_ENCODING};
+    use hyper::body::Bytes;
+    use hyper::{Body, Response};
+
+    use super::{compress, Encoding};
+
+    fn negotiate(accept: &str) -> Option<Encoding> {
+        let mut headers = HeaderMap::new();
+        headers.insert(ACCEPT_ENCODING, HeaderValue::from_str(accept).unwrap());
+        Encoding::negotiate(&headers)
+    }
+
+    #[test]
+    fn negotiates_encoding() {
+        assert_eq!(None, Encoding::negotiate(&HeaderMap::new()));
+        assert_eq!(None, negotiate("identity"));
+        assert_eq!(Some(Encoding::Gzip), negotiate("gzip, deflate"));
+        assert_eq!(Some(Encoding::Brotli), negotiate("gzip, deflate, br"));
+        assert_eq!(Some(Encoding::Gzip), negotiate("br;q=0.5, gzip"));
+        assert_eq!(None, negotiate("br;q=0, gzip;q=0"));
+        assert_eq!(Some(Encoding::Brotli), negotiate("*"));
+        assert_eq!(Some(Encoding::Gzip), negotiate("*, br;q=0"));
+    }
+
+    #[test]
+    fn compresses_with_gzip() {
+        let data = "hello ".repeat(1000);
+        let mut compressed = Vec::new();
+        Encoding::Gzip
+            .compress(data.as_bytes(), &mut compressed)
+            .unwrap();
+        assert!(compressed.len() < data.len());
+
+        let mut decompressed = String::new();
+        flate2::read::GzDecoder::new(compressed.as_slice())
+            .read_to_string(&mut decompressed)
+            .unwrap();
+        assert_eq!(data, decompressed);
+    }
+
+    #[test]
+    fn compresses_with_brotli() {
+        let data = "hello ".repeat(1000);
+        let mut compressed = Vec::new();
+        Encoding::Brotli
+            .compress(data.as_bytes(), &mut compressed)
+            .unwrap();
+        assert!(compressed.len() < data.len());
+
+        let mut decompressed = String::new();
+        brotli::Decompressor::new(compressed.as_slice(), 4096)
+            .read_to_string(&mut decompressed)
+            .unwrap();
+        assert_eq!(data, decompressed);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn compresses_body_as_it_arrives() {
+        let (mut sender, body) = Body::channel();
+        let response = compress(Response::new(body), Some(Encoding::Gzip)).await;
+        assert_eq!("gzip", response.headers()["content-encoding"]);
+
+        let data = "hello ".repeat(1000);
+        for chunk in data.as_bytes().chunks(100) {
+            sender
+                .send_data(Bytes::copy_from_slice(chunk))
+                .await
+                .unwrap();
+        }
+        drop(sender);
+
+        let compressed = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let mut decompressed = String::new();
+        flate2::read::GzDecoder::new(compressed.as_ref())
+            .read_to_string(&mut decompressed)
+            .unwrap();
+        assert_eq!(data, decompressed);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn aborts_compressed_body_when_body_fails() {
+        let (mut sender, body) = Body::channel();
+        let response = compress(Response::new(body), Some(Encoding::Brotli)).await;
+
+        sender.send_data(Bytes::from("hello")).await.unwrap();
+        sender.abort();
+
+        assert!(hyper::body::to_bytes(response.into_body()).await.is_err());
+    }
+}
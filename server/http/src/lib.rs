@@ -8,7 +8,9 @@ extern crate http;
 extern crate hyper;
 extern crate serde;
 
+mod compression;
 mod request;
+mod rest;
 mod server;
 mod service;
 
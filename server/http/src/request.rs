@@ -1,9 +1,20 @@
+use std::sync::Mutex;
+
 use graph::prelude::serde_json;
+use graph::util::lfu_cache::LfuCache;
 use hyper::body::Bytes;
+use sha2::{Digest, Sha256};
 
 use graph::components::server::query::GraphQLServerError;
+use graph::data::query::QueryTarget;
 use graph::prelude::*;
 
+lazy_static! {
+    /// The queries that clients registered with automatic persisted
+    /// queries, keyed by the hex SHA-256 hash of the query text
+    static ref PERSISTED_QUERIES: Mutex<LfuCache<String, String>> = Mutex::new(LfuCache::new());
+}
+
 /// Future for a query parsed from an HTTP request.
 pub struct GraphQLRequest {
     body: Bytes,
@@ -14,6 +25,139 @@ impl GraphQLRequest {
     pub fn new(body: Bytes) -> Self {
         GraphQLRequest { body }
     }
+
+    /// Creates a GraphQLRequest from the query string of a `GET` request.
+    /// The `query` parameter holds the query text, and `variables` and
+    /// `extensions` hold JSON, just like the fields of the same name in
+    /// the body of a `POST` request
+    pub fn from_query_string(query_string: &str) -> Result<Self, GraphQLServerError> {
+        let max_len = ENV_VARS.graphql.max_get_query_length;
+        if query_string.len() > max_len {
+            return Err(GraphQLServerError::ClientError(format!(
+                "the query string is longer than the maximum of {} bytes",
+                max_len
+            )));
+        }
+
+        let mut obj = serde_json::Map::new();
+        for (key, value) in graph::url::form_urlencoded::parse(query_string.as_bytes()) {
+            // Invalid percent-encoded UTF-8 gets replaced with U+FFFD
+            if value.contains(char::REPLACEMENT_CHARACTER) {
+                return Err(GraphQLServerError::ClientError(format!(
+                    "the query parameter `{}` is not valid UTF-8",
+                    key
+                )));
+            }
+            let value = match key.as_ref() {
+                "query" => serde_json::Value::String(value.into_owned()),
+                "variables" | "extensions" => serde_json::from_str(&value).map_err(|e| {
+                    GraphQLServerError::ClientError(format!(
+                        "the query parameter `{}` is not valid JSON: {}",
+                        key, e
+                    ))
+                })?,
+                _ => continue,
+            };
+            obj.insert(key.into_owned(), value);
+        }
+        let body = serde_json::to_vec(&obj)
+            .map_err(|e| GraphQLServerError::InternalError(e.to_string()))?;
+        Ok(GraphQLRequest::new(Bytes::from(body)))
+    }
+}
+
+/// Find the query text for a request that uses automatic persisted
+/// queries, i.e., that has `extensions.persistedQuery.sha256Hash`. A
+/// request with a query registers it under the hash; a request without one
+/// fails with `PersistedQueryNotFound` if we do not know the hash, so that
+/// clients resend it together with the query
+fn persisted_query(
+    obj: &serde_json::Map<String, serde_json::Value>,
+    hash: &str,
+) -> Result<String, GraphQLServerError> {
+    match obj.get("query") {
+        Some(serde_json::Value::String(query)) => {
+            if hex::encode(Sha256::digest(query.as_bytes())) != hash.to_lowercase() {
+                return Err(GraphQLServerError::ClientError(
+                    "provided sha256Hash does not match query".to_string(),
+                ));
+            }
+            let mut cache = PERSISTED_QUERIES.lock().unwrap();
+            cache.insert(hash.to_lowercase(), query.clone());
+            cache.evict(ENV_VARS.graphql.persisted_query_cache_max_mem);
+            Ok(query.clone())
+        }
+        Some(_) => Err(GraphQLServerError::ClientError(String::from(
+            "The \"query\" field is not a string",
+        ))),
+        None => PERSISTED_QUERIES
+            .lock()
+            .unwrap()
+            .get(&hash.to_lowercase())
+            .cloned()
+            .ok_or_else(|| GraphQLServerError::QueryError(QueryError::PersistedQueryNotFound)),
+    }
+}
+
+/// Remove the `@deployment(id: "Qm...")` or `@deployment(name: "...")`
+/// directive from the operations in `document` and return the deployment
+/// it names. The directive is not part of any subgraph's schema, and
+/// would fail validation if we left it in place
+pub(crate) fn take_deployment_directive(
+    document: &mut q::Document,
+) -> Result<Option<QueryTarget>, GraphQLServerError> {
+    let mut target = None;
+    for definition in document.definitions.iter_mut() {
+        let directives = match definition {
+            q::Definition::Operation(q::OperationDefinition::Query(op)) => &mut op.directives,
+            q::Definition::Operation(q::OperationDefinition::Mutation(op)) => &mut op.directives,
+            q::Definition::Operation(q::OperationDefinition::Subscription(op)) => {
+                &mut op.directives
+            }
+            q::Definition::Operation(q::OperationDefinition::SelectionSet(_))
+            | q::Definition::Fragment(_) => continue,
+        };
+        let (deployment, others): (Vec<_>, Vec<_>) = directives
+            .drain(..)
+            .partition(|directive| directive.name == "deployment");
+        *directives = others;
+
+        for directive in deployment {
+            let this = match directive.arguments.as_slice() {
+                [(name, q::Value::String(id))] if name == "id" => DeploymentHash::new(id.as_str())
+                    .map(QueryTarget::from)
+                    .map_err(|id| {
+                        GraphQLServerError::ClientError(format!("Invalid subgraph id `{}`", id))
+                    })?,
+                [(name, q::Value::String(subgraph))] if name == "name" => {
+                    SubgraphName::new(subgraph.as_str())
+                        .map(QueryTarget::from)
+                        .map_err(|()| {
+                            GraphQLServerError::ClientError(format!(
+                                "Invalid subgraph name {:?}",
+                                subgraph
+                            ))
+                        })?
+                }
+                _ => {
+                    return Err(GraphQLServerError::ClientError(
+                        "the @deployment directive needs exactly one of the string \
+                         arguments `id` or `name`"
+                            .to_string(),
+                    ))
+                }
+            };
+            match &target {
+                Some(target) if target != &this => {
+                    return Err(GraphQLServerError::ClientError(
+                        "all operations in a query must use the same @deployment".to_string(),
+                    ))
+                }
+                _ => target = Some(this),
+            }
+        }
+    }
+    Ok(target)
 }
 
 impl Future for GraphQLRequest {
@@ -30,20 +174,36 @@ impl Future for GraphQLRequest {
             GraphQLServerError::ClientError(String::from("Request data is not an object"))
         })?;
 
-        // Ensure the JSON data has a "query" field
-        let query_value = obj.get("query").ok_or_else(|| {
-            GraphQLServerError::ClientError(String::from(
-                "The \"query\" field is missing in request data",
-            ))
-        })?;
+        let persisted_hash = obj
+            .get("extensions")
+            .and_then(|extensions| extensions.get("persistedQuery"))
+            .and_then(|persisted| persisted.get("sha256Hash"))
+            .and_then(|hash| hash.as_str());
 
-        // Ensure the "query" field is a string
-        let query_string = query_value.as_str().ok_or_else(|| {
-            GraphQLServerError::ClientError(String::from("The \"query\" field is not a string"))
-        })?;
+        let query_string = match persisted_hash {
+            Some(hash) => persisted_query(obj, hash)?,
+            None => {
+                // Ensure the JSON data has a "query" field
+                let query_value = obj.get("query").ok_or_else(|| {
+                    GraphQLServerError::ClientError(String::from(
+                        "The \"query\" field is missing in request data",
+                    ))
+                })?;
+
+                // Ensure the "query" field is a string
+                query_value
+                    .as_str()
+                    .ok_or_else(|| {
+                        GraphQLServerError::ClientError(String::from(
+                            "The \"query\" field is not a string",
+                        ))
+                    })?
+                    .to_owned()
+            }
+        };
 
         // Parse the "query" field of the JSON body
-        let document = graphql_parser::parse_query(query_string)
+        let document = graphql_parser::parse_query(&query_string)
             .map_err(|e| GraphQLServerError::from(QueryError::ParseError(Arc::new(e.into()))))?
             .into_static();
 
@@ -71,9 +231,11 @@ mod tests {
     use std::collections::HashMap;
 
     use graph::{
+        components::server::query::GraphQLServerError,
         data::{query::QueryTarget, value::Object},
         prelude::*,
     };
+    use sha2::Digest;
 
     use super::GraphQLRequest;
 
@@ -187,4 +349,124 @@ mod tests {
         assert_eq!(query.document, expected_query);
         assert_eq!(query.variables, Some(expected_variables));
     }
+
+    #[test]
+    fn accepts_get_queries() {
+        let request = GraphQLRequest::from_query_string(
+            "query=%7B%20user%20%7B%20name%20%7D%20%7D&variables=%7B%22int%22%3A%205%7D",
+        )
+        .expect("Should accept the query string");
+        let query = request.wait().expect("Should accept valid queries");
+
+        let expected_query = graphql_parser::parse_query("{ user { name } }")
+            .unwrap()
+            .into_static();
+        let expected_variables = QueryVariables::new(HashMap::from_iter(
+            vec![(String::from("int"), r::Value::Int(5))].into_iter(),
+        ));
+        assert_eq!(query.document, expected_query);
+        assert_eq!(query.variables, Some(expected_variables));
+    }
+
+    #[test]
+    fn rejects_bad_get_queries() {
+        GraphQLRequest::from_query_string("query=%FF")
+            .err()
+            .expect("Should reject invalid UTF-8");
+        GraphQLRequest::from_query_string("query=%7B%20user%20%7D&variables=%7B")
+            .err()
+            .expect("Should reject invalid JSON in variables");
+        let long = format!(
+            "query={}",
+            "x".repeat(ENV_VARS.graphql.max_get_query_length)
+        );
+        GraphQLRequest::from_query_string(&long)
+            .err()
+            .expect("Should reject overly long query strings");
+    }
+
+    #[test]
+    fn registers_and_uses_persisted_queries() {
+        let query = "{ user { id } }";
+        let hash = hex::encode(sha2::Sha256::digest(query.as_bytes()));
+        let only_hash = format!(
+            "{{\"extensions\": {{\"persistedQuery\": {{\"version\": 1, \"sha256Hash\": \"{}\"}}}}}}",
+            hash
+        );
+        let with_query = format!(
+            "{{\"query\": \"{}\", \"extensions\": {{\"persistedQuery\": {{\"version\": 1, \"sha256Hash\": \"{}\"}}}}}}",
+            query, hash
+        );
+
+        let err = GraphQLRequest::new(hyper::body::Bytes::from(only_hash.clone()))
+            .wait()
+            .expect_err("Should not know the query yet");
+        assert!(
+            matches!(
+                err,
+                GraphQLServerError::QueryError(QueryError::PersistedQueryNotFound)
+            ),
+            "unexpected error {}",
+            err
+        );
+
+        GraphQLRequest::new(hyper::body::Bytes::from(with_query))
+            .wait()
+            .expect("Should register the query");
+        let query = GraphQLRequest::new(hyper::body::Bytes::from(only_hash))
+            .wait()
+            .expect("Should find the registered query");
+        assert_eq!(
+            query.document,
+            graphql_parser::parse_query("{ user { id } }")
+                .unwrap()
+                .into_static()
+        );
+
+        let wrong_hash = format!(
+            "{{\"query\": \"{{ user {{ name }} }}\", \"extensions\": {{\"persistedQuery\": {{\"version\": 1, \"sha256Hash\": \"{}\"}}}}}}",
+            hash
+        );
+        GraphQLRequest::new(hyper::body::Bytes::from(wrong_hash))
+            .wait()
+            .expect_err("Should reject a hash that does not match the query");
+    }
+
+    #[test]
+    fn takes_deployment_directive() {
+        let mut document = graphql_parser::parse_query(
+            "query q @deployment(id: \"users\") @live { user { name } }",
+        )
+        .unwrap()
+        .into_static();
+        let target = super::take_deployment_directive(&mut document).unwrap();
+        assert_eq!(
+            Some(QueryTarget::Deployment(
+                DeploymentHash::new("users").unwrap()
+            )),
+            target
+        );
+        assert_eq!(
+            graphql_parser::parse_query("query q @live { user { name } }")
+                .unwrap()
+                .into_static(),
+            document
+        );
+
+        let mut document = graphql_parser::parse_query("{ user { name } }")
+            .unwrap()
+            .into_static();
+        assert_eq!(
+            None,
+            super::take_deployment_directive(&mut document).unwrap()
+        );
+
+        let mut document = graphql_parser::parse_query(
+            "query a @deployment(name: \"a/b\") { user } query b @deployment(name: \"c/d\") { user }",
+        )
+        .unwrap()
+        .into_static();
+        super::take_deployment_directive(&mut document)
+            .expect_err("operations must agree on the deployment");
+    }
 }
@@ -0,0 +1,418 @@
+//! A read-only REST facade over the GraphQL API of a deployment. Requests
+//! for `/rest/<name>/<Entity>/<id>` and `/rest/<name>/<Entity>?<filters>`
+//! are turned into GraphQL queries against the deployment and run like any
+//! other query, so that they go through the same resolvers and the same
+//! query cache.
+//!
+//! The fields that are returned and the types of the filters are taken
+//! from the input schema of the deployment
+use std::collections::HashMap;
+
+use graph::components::server::query::GraphQLServerError;
+use graph::data::graphql::ext::DirectiveFinder;
+use graph::data::graphql::{DocumentExt, ObjectOrInterface, TypeExt};
+use graph::data::schema::SCHEMA_TYPE_NAME;
+use graph::prelude::{q, r, s, serde_json, Query, QueryResults, QueryVariables, Schema};
+use graph::url::form_urlencoded;
+use http::header::{ACCESS_CONTROL_ALLOW_ORIGIN, CONTENT_TYPE};
+use hyper::{Body, Response, StatusCode};
+use inflector::Inflector;
+use percent_encoding::percent_decode_str;
+use serde_json::Value as JsonValue;
+
+/// Query parameters that are passed as arguments of the collection field
+/// instead of being turned into filters
+const FIRST: &str = "first";
+const SKIP: &str = "skip";
+const ORDER_BY: &str = "orderBy";
+const ORDER_DIRECTION: &str = "orderDirection";
+const BLOCK: &str = "block";
+
+pub(crate) struct RestRequest {
+    entity: String,
+    id: Option<String>,
+    params: Vec<(String, String)>,
+}
+
+impl RestRequest {
+    /// A request for the entity of type `entity` with the given `id`, or
+    /// for a list of such entities if `id` is `None`. The `id` is the
+    /// percent-encoded path segment. The `query` string holds the filters
+    /// for lists, and the block for both
+    pub fn new(
+        entity: &str,
+        id: Option<&str>,
+        query: Option<&str>,
+    ) -> Result<Self, GraphQLServerError> {
+        let valid = entity
+            .chars()
+            .enumerate()
+            .all(|(i, c)| c == '_' || c.is_ascii_alphabetic() || (i > 0 && c.is_ascii_digit()));
+        if entity.is_empty() || !valid {
+            return Err(GraphQLServerError::ClientError(format!(
+                "invalid entity type `{}`",
+                entity
+            )));
+        }
+        let params = query
+            .map(|query| {
+                form_urlencoded::parse(query.as_bytes())
+                    .into_owned()
+                    .collect()
+            })
+            .unwrap_or_default();
+        Ok(RestRequest {
+            entity: entity.to_string(),
+            id: id.map(decode_segment).transpose()?,
+            params,
+        })
+    }
+
+    /// The GraphQL query for this request against a deployment with the
+    /// given input `schema`. Returns `None` if the schema does not have the
+    /// entity type we are asked for
+    pub fn query(&self, schema: &Schema) -> Result<Option<Query>, GraphQLServerError> {
+        let entity_type = match schema.document.object_or_interface(&self.entity) {
+            Some(entity_type) if self.entity != SCHEMA_TYPE_NAME => entity_type,
+            _ => return Ok(None),
+        };
+        let selection = entity_type
+            .fields()
+            .iter()
+            .map(|field| {
+                if is_reference(schema, field) {
+                    format!("{} {{ id }}", field.name)
+                } else {
+                    field.name.clone()
+                }
+            })
+            .collect::<Vec<_>>()
+            .join(" ");
+
+        let mut variables = HashMap::new();
+        let mut args = Vec::new();
+        let mut arg = |name: &str, ty: String, value: r::Value| {
+            args.push((name.to_string(), ty));
+            variables.insert(name.to_string(), value);
+        };
+
+        let mut filter = Vec::new();
+        for (key, value) in &self.params {
+            match key.as_str() {
+                BLOCK => arg(
+                    BLOCK,
+                    "Block_height".to_string(),
+                    r::Value::object(
+                        vec![("number".to_string(), int_param(key, value)?)]
+                            .into_iter()
+                            .collect(),
+                    ),
+                ),
+                _ if self.id.is_some() => {
+                    return Err(GraphQLServerError::ClientError(format!(
+                        "unknown query parameter `{}`; only `block` can be used when \
+                         requesting a single entity",
+                        key
+                    )))
+                }
+                FIRST | SKIP => arg(key.as_str(), "Int".to_string(), int_param(key, value)?),
+                ORDER_BY => arg(
+                    ORDER_BY,
+                    format!("{}_orderBy", self.entity),
+                    r::Value::Enum(value.clone()),
+                ),
+                ORDER_DIRECTION => arg(
+                    ORDER_DIRECTION,
+                    "OrderDirection".to_string(),
+                    r::Value::Enum(value.clone()),
+                ),
+                _ => filter.push((
+                    key.clone(),
+                    self.filter_value(schema, entity_type, key, value)?,
+                )),
+            }
+        }
+
+        let field = match &self.id {
+            Some(id) => {
+                arg("id", "ID!".to_string(), r::Value::String(id.clone()));
+                self.entity.to_camel_case()
+            }
+            None => {
+                if !filter.is_empty() {
+                    arg(
+                        "where",
+                        format!("{}_filter", self.entity),
+                        r::Value::object(filter.into_iter().collect()),
+                    );
+                }
+                self.entity.to_plural().to_camel_case()
+            }
+        };
+
+        let text = if args.is_empty() {
+            format!("{{ {} {{ {} }} }}", field, selection)
+        } else {
+            let decls = args
+                .iter()
+                .map(|(name, ty)| format!("${}: {}", name, ty))
+                .collect::<Vec<_>>()
+                .join(", ");
+            let uses = args
+                .iter()
+                .map(|(name, _)| format!("{}: ${}", name, name))
+                .collect::<Vec<_>>()
+                .join(", ");
+            format!(
+                "query({}) {{ {}({}) {{ {} }} }}",
+                decls, field, uses, selection
+            )
+        };
+        parse(&text, variables).map(Some)
+    }
+
+    /// Convert the value of the filter `key` to the type the filter has in
+    /// the API schema, which follows from the field the filter is for and
+    /// its operator. Lists are given as comma-separated values
+    fn filter_value(
+        &self,
+        schema: &Schema,
+        entity_type: ObjectOrInterface<'_>,
+        key: &str,
+        value: &str,
+    ) -> Result<r::Value, GraphQLServerError> {
+        // The filter is `<field>` or `<field>_<op>`; derived fields can not
+        // be filtered on
+        let (field, op) = entity_type
+            .fields()
+            .iter()
+            .filter(|field| !field.is_derived())
+            .filter_map(|field| {
+                let op = if key == field.name {
+                    Some("")
+                } else {
+                    key.strip_prefix(field.name.as_str())
+                        .and_then(|op| op.strip_prefix('_'))
+                        .filter(|op| !op.is_empty())
+                };
+                op.map(|op| (field, op))
+            })
+            .max_by_key(|(field, _)| field.name.len())
+            .ok_or_else(|| {
+                GraphQLServerError::ClientError(format!(
+                    "unknown filter `{}` for entity type `{}`",
+                    key, self.entity
+                ))
+            })?;
+
+        // References to other entities are filtered by their ids
+        let base_type = field.field_type.get_base_type();
+        let value_type = match schema.document.get_named_type(base_type) {
+            Some(s::TypeDefinition::Enum(_)) => "enum",
+            _ if is_reference(schema, field) => "ID",
+            _ => base_type,
+        };
+        let scalar = |value: &str| -> Result<r::Value, GraphQLServerError> {
+            match value_type {
+                "enum" => Ok(r::Value::Enum(value.to_string())),
+                "Int" => int_param(key, value),
+                "Boolean" => bool_param(key, value),
+                _ => Ok(r::Value::String(value.to_string())),
+            }
+        };
+        match op {
+            "empty" => bool_param(key, value),
+            "in" | "not_in" | "in_nocase" | "not_in_nocase" => list(value, scalar),
+            _ if field.field_type.is_list() => list(value, scalar),
+            _ => scalar(value),
+        }
+    }
+
+    /// The response for the `results` of running the query for this
+    /// request: the entity or the list of entities, or the errors the
+    /// query caused
+    pub fn response(&self, results: &QueryResults) -> Response<Body> {
+        let json = serde_json::to_value(results).unwrap_or(JsonValue::Null);
+        if json.get("errors").is_some() {
+            return json_response(StatusCode::BAD_REQUEST, &json);
+        }
+        match json["data"]
+            .as_object()
+            .and_then(|data| data.values().next())
+        {
+            None | Some(JsonValue::Null) => not_found(),
+            Some(value) => json_response(StatusCode::OK, value),
+        }
+    }
+}
+
+/// Whether `field` refers to other entities
+fn is_reference(schema: &Schema, field: &s::Field) -> bool {
+    schema
+        .document
+        .object_or_interface(field.field_type.get_base_type())
+        .is_some()
+}
+
+/// Split the comma-separated `value` into a list of values
+fn list<F>(value: &str, scalar: F) -> Result<r::Value, GraphQLServerError>
+where
+    F: Fn(&str) -> Result<r::Value, GraphQLServerError>,
+{
+    value
+        .split(',')
+        .filter(|elem| !elem.is_empty())
+        .map(scalar)
+        .collect::<Result<_, _>>()
+        .map(r::Value::List)
+}
+
+fn int_param(key: &str, value: &str) -> Result<r::Value, GraphQLServerError> {
+    value.parse().map(r::Value::Int).map_err(|_| {
+        GraphQLServerError::ClientError(format!("the parameter `{}` must be an integer", key))
+    })
+}
+
+fn bool_param(key: &str, value: &str) -> Result<r::Value, GraphQLServerError> {
+    value.parse().map(r::Value::Boolean).map_err(|_| {
+        GraphQLServerError::ClientError(format!(
+            "the parameter `{}` must be `true` or `false`",
+            key
+        ))
+    })
+}
+
+/// Decode the percent-encoded path segment `segment`
+pub(crate) fn decode_segment(segment: &str) -> Result<String, GraphQLServerError> {
+    percent_decode_str(segment)
+        .decode_utf8()
+        .map(|segment| segment.into_owned())
+        .map_err(|_| {
+            GraphQLServerError::ClientError(format!(
+                "the path segment `{}` is not valid UTF-8",
+                segment
+            ))
+        })
+}
+
+fn parse(text: &str, variables: HashMap<String, r::Value>) -> Result<Query, GraphQLServerError> {
+    let document: q::Document = graphql_parser::parse_query(text)
+        .map_err(|e| GraphQLServerError::ClientError(format!("invalid request: {}", e)))?
+        .into_static();
+    Ok(Query::new(document, Some(QueryVariables::new(variables))))
+}
+
+fn json_response(status: StatusCode, value: &JsonValue) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .header(ACCESS_CONTROL_ALLOW_ORIGIN, "*")
+        .header(CONTENT_TYPE, "application/json")
+        .body(Body::from(value.to_string()))
+        .unwrap()
+}
+
+pub(crate) fn not_found() -> Response<Body> {
+    Response::builder()
+        .status(StatusCode::NOT_FOUND)
+        .header(CONTENT_TYPE, "text/plain")
+        .header(ACCESS_CONTROL_ALLOW_ORIGIN, "*")
+        .body(Body::from("Not found"))
+        .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use graph::prelude::DeploymentHash;
+
+    use super::*;
+
+    const SCHEMA: &str = "
+        enum Kind { Fungible, Unique }
+        type Token @entity {
+            id: ID!
+            count: Int
+            kind: Kind
+            owner: User
+            holders: [User!]! @derivedFrom(field: \"token\")
+        }
+        type User @entity {
+            id: ID!
+            token: Token
+        }
+    ";
+
+    fn schema() -> Schema {
+        Schema::parse(SCHEMA, DeploymentHash::new("rest").unwrap()).unwrap()
+    }
+
+    #[test]
+    fn builds_queries() {
+        let request = RestRequest::new("Token", Some("0x1"), Some("block=7")).unwrap();
+        let query = request.query(&schema()).unwrap().unwrap();
+        let expected = graphql_parser::parse_query(
+            "query($block: Block_height, $id: ID!) { \
+               token(block: $block, id: $id) { id count kind owner { id } holders { id } } }",
+        )
+        .unwrap()
+        .into_static();
+        assert_eq!(expected, query.document);
+
+        let request = RestRequest::new(
+            "Token",
+            None,
+            Some("first=2&count_gt=3&id_in=a,b&kind=Unique&owner_not=c"),
+        )
+        .unwrap();
+        let query = request.query(&schema()).unwrap().unwrap();
+        let variables = query.variables.unwrap();
+        assert_eq!(Some(&r::Value::Int(2)), variables.get("first"));
+        assert_eq!(
+            Some(&r::Value::object(
+                vec![
+                    ("count_gt".to_string(), r::Value::Int(3)),
+                    (
+                        "id_in".to_string(),
+                        r::Value::List(vec![
+                            r::Value::String("a".to_string()),
+                            r::Value::String("b".to_string())
+                        ])
+                    ),
+                    ("kind".to_string(), r::Value::Enum("Unique".to_string())),
+                    ("owner_not".to_string(), r::Value::String("c".to_string())),
+                ]
+                .into_iter()
+                .collect()
+            )),
+            variables.get("where")
+        );
+    }
+
+    #[test]
+    fn decodes_ids() {
+        let request = RestRequest::new("Token", Some("a%2Fb%20c"), None).unwrap();
+        let query = request.query(&schema()).unwrap().unwrap();
+        assert_eq!(
+            Some(&r::Value::String("a/b c".to_string())),
+            query.variables.unwrap().get("id")
+        );
+
+        assert!(RestRequest::new("Token", Some("%ff"), None).is_err());
+    }
+
+    #[test]
+    fn rejects_bad_requests() {
+        assert!(RestRequest::new("Token\") { x", None, None).is_err());
+
+        let request = RestRequest::new("Token", None, Some("color=red")).unwrap();
+        assert!(request.query(&schema()).is_err());
+        let request = RestRequest::new("Token", None, Some("count_gt=many")).unwrap();
+        assert!(request.query(&schema()).is_err());
+        let request = RestRequest::new("Token", None, Some("holders=a")).unwrap();
+        assert!(request.query(&schema()).is_err());
+        let request = RestRequest::new("Token", Some("0x1"), Some("first=2")).unwrap();
+        assert!(request.query(&schema()).is_err());
+
+        let request = RestRequest::new("Unknown", None, None).unwrap();
+        assert!(request.query(&schema()).unwrap().is_none());
+    }
+}
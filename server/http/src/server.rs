@@ -1,9 +1,10 @@
 use std::net::{Ipv4Addr, SocketAddrV4};
 
 use hyper::service::make_service_fn;
-use hyper::Server;
 
 use crate::service::{GraphQLService, GraphQLServiceMetrics};
+use graph::components::server::health::HealthCheck;
+use graph::components::server::{http, tls};
 use graph::prelude::{GraphQLServer as GraphQLServerTrait, *};
 use thiserror::Error;
 
@@ -25,6 +26,7 @@ pub struct GraphQLServer<Q> {
     logger: Logger,
     metrics: Arc<GraphQLServiceMetrics>,
     graphql_runner: Arc<Q>,
+    health: Arc<dyn HealthCheck>,
     node_id: NodeId,
 }
 
@@ -34,6 +36,7 @@ impl<Q> GraphQLServer<Q> {
         logger_factory: &LoggerFactory,
         metrics_registry: Arc<impl MetricsRegistry>,
         graphql_runner: Arc<Q>,
+        health: Arc<dyn HealthCheck>,
         node_id: NodeId,
     ) -> Self {
         let logger = logger_factory.component_logger(
@@ -49,6 +52,7 @@ impl<Q> GraphQLServer<Q> {
             logger,
             metrics,
             graphql_runner,
+            health,
             node_id,
         }
     }
@@ -69,7 +73,9 @@ where
 
         info!(
             logger,
-            "Starting GraphQL HTTP server at: http://localhost:{}", port
+            "Starting GraphQL HTTP server at: {}://localhost:{}",
+            tls::scheme("http"),
+            port
         );
 
         let addr = SocketAddrV4::new(Ipv4Addr::new(0, 0, 0, 0), port);
@@ -79,20 +85,23 @@ where
         let logger_for_service = self.logger.clone();
         let graphql_runner = self.graphql_runner.clone();
         let metrics = self.metrics.clone();
+        let health = self.health.clone();
         let node_id = self.node_id.clone();
         let new_service = make_service_fn(move |_| {
             futures03::future::ok::<_, Error>(GraphQLService::new(
                 logger_for_service.clone(),
                 metrics.clone(),
                 graphql_runner.clone(),
+                health.clone(),
                 ws_port,
                 node_id.clone(),
             ))
         });
 
         // Create a task to run the server and handle HTTP requests
-        let task = Server::try_bind(&addr.into())?
+        let task = http::bind_tls(&addr.into())?
             .serve(new_service)
+            .with_graceful_shutdown(http::shutdown_signal())
             .map_err(move |e| error!(logger, "Server error"; "error" => format!("{}", e)));
 
         Ok(Box::new(task.compat()))
@@ -5,6 +5,7 @@ use std::task::Context;
 use std::task::Poll;
 use std::time::Instant;
 
+use graph::components::server::health::HealthCheck;
 use graph::prelude::*;
 use graph::{components::server::query::GraphQLServerError, data::query::QueryTarget};
 use http::header;
@@ -14,8 +15,11 @@ use http::header::{
 };
 use hyper::service::Service;
 use hyper::{Body, Method, Request, Response, StatusCode};
+use serde::Serialize;
 
-use crate::request::GraphQLRequest;
+use crate::compression::{self, Encoding};
+use crate::request::{take_deployment_directive, GraphQLRequest};
+use crate::rest::{self, RestRequest};
 
 pub struct GraphQLServiceMetrics {
     query_execution_time: Box<HistogramVec>,
@@ -67,27 +71,39 @@ impl GraphQLServiceMetrics {
     }
 }
 
+/// The content type of requests that contain a SQL query
+const SQL_CONTENT_TYPE: &str = "application/sql";
+const DEPLOYMENT_HEADER: &str = "Graph-Deployment";
+const SUBGRAPH_HEADER: &str = "Graph-Subgraph";
+
 pub type GraphQLServiceResult = Result<Response<Body>, GraphQLServerError>;
 /// An asynchronous response to a GraphQL request.
 pub type GraphQLServiceResponse =
     Pin<Box<dyn std::future::Future<Output = GraphQLServiceResult> + Send>>;
 
 /// A Hyper Service that serves GraphQL over a POST / endpoint.
-#[derive(Debug)]
 pub struct GraphQLService<Q> {
     logger: Logger,
     metrics: Arc<GraphQLServiceMetrics>,
     graphql_runner: Arc<Q>,
+    health: Arc<dyn HealthCheck>,
     ws_port: u16,
     node_id: NodeId,
 }
 
+impl<Q> fmt::Debug for GraphQLService<Q> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "GraphQLService {{ node_id: {} }}", self.node_id)
+    }
+}
+
 impl<Q> Clone for GraphQLService<Q> {
     fn clone(&self) -> Self {
         Self {
             logger: self.logger.clone(),
             metrics: self.metrics.clone(),
             graphql_runner: self.graphql_runner.clone(),
+            health: self.health.clone(),
             ws_port: self.ws_port,
             node_id: self.node_id.clone(),
         }
@@ -103,6 +119,7 @@ where
         logger: Logger,
         metrics: Arc<GraphQLServiceMetrics>,
         graphql_runner: Arc<Q>,
+        health: Arc<dyn HealthCheck>,
         ws_port: u16,
         node_id: NodeId,
     ) -> Self {
@@ -110,6 +127,7 @@ where
             logger,
             metrics,
             graphql_runner,
+            health,
             ws_port,
             node_id,
         }
@@ -158,8 +176,7 @@ where
             GraphQLServerError::ClientError(format!("Invalid subgraph name {:?}", subgraph_name))
         })?;
 
-        self.handle_graphql_query(subgraph_name.into(), request.into_body())
-            .await
+        self.handle_query(subgraph_name.into(), request).await
     }
 
     fn handle_graphql_query_by_id(
@@ -171,28 +188,136 @@ where
             .map_err(|id| GraphQLServerError::ClientError(format!("Invalid subgraph id `{}`", id)));
         match res {
             Err(_) => self.handle_not_found(),
-            Ok(id) => self
-                .handle_graphql_query(id.into(), request.into_body())
-                .boxed(),
+            Ok(id) => self.handle_query(id.into(), request).boxed(),
         }
     }
 
+    /// Run the query in `request` against `target`. Requests with content
+    /// type `application/sql` contain a SQL query, all others a GraphQL query
+    async fn handle_query(
+        self,
+        target: QueryTarget,
+        request: Request<Body>,
+    ) -> GraphQLServiceResult {
+        let is_sql = request
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .map_or(false, |value| value.starts_with(SQL_CONTENT_TYPE));
+        if is_sql {
+            self.handle_sql_query(target, request).await
+        } else {
+            self.handle_graphql_query(target, request).await
+        }
+    }
+
+    /// Run the SQL query in the body of `request`; the query string may
+    /// set the `block` to query at
+    async fn handle_sql_query(
+        self,
+        target: QueryTarget,
+        request: Request<Body>,
+    ) -> GraphQLServiceResult {
+        if !ENV_VARS.graphql.enable_sql_api {
+            return Err(GraphQLServerError::ClientError(
+                "SQL queries are not enabled on this node".to_string(),
+            ));
+        }
+
+        let block =
+            graph::url::form_urlencoded::parse(request.uri().query().unwrap_or("").as_bytes())
+                .find(|(key, _)| key == "block")
+                .map(|(_, value)| {
+                    value.parse::<BlockNumber>().map_err(|_| {
+                        GraphQLServerError::ClientError(format!("invalid block number `{}`", value))
+                    })
+                })
+                .transpose()?;
+        let body = hyper::body::to_bytes(request.into_body())
+            .map_err(|_| GraphQLServerError::InternalError("Failed to read request body".into()))
+            .await?;
+        let sql = String::from_utf8(body.to_vec())
+            .map_err(|_| GraphQLServerError::ClientError("the query is not valid UTF-8".into()))?;
+
+        let (status, json) = match self
+            .graphql_runner
+            .cheap_clone()
+            .run_sql(sql, block, target)
+            .await
+        {
+            Ok(rows) => (StatusCode::OK, serde_json::json!({ "data": rows })),
+            Err(e) => (
+                StatusCode::BAD_REQUEST,
+                serde_json::json!({ "errors": [{ "message": e.to_string() }] }),
+            ),
+        };
+        Ok(Response::builder()
+            .status(status)
+            .header(ACCESS_CONTROL_ALLOW_ORIGIN, "*")
+            .header(CONTENT_TYPE, "application/json")
+            .body(Body::from(json.to_string()))
+            .unwrap())
+    }
+
+    /// Answer a request to the REST API for the subgraph `name`, which is
+    /// a single percent-encoded path segment
+    async fn handle_rest_request_by_name(
+        self,
+        name: String,
+        entity: String,
+        id: Option<String>,
+        request: Request<Body>,
+    ) -> GraphQLServiceResult {
+        let name = rest::decode_segment(&name)?;
+        let name = SubgraphName::new(name.as_str()).map_err(|()| {
+            GraphQLServerError::ClientError(format!("Invalid subgraph name {:?}", name))
+        })?;
+        self.handle_rest_request(name.into(), entity, id, request)
+            .await
+    }
+
+    /// Run the GraphQL query in `request`, which is either in the body of a
+    /// `POST` request or in the query string of a `GET` request. When the
+    /// query string contains
+    /// `explain=true` and the request carries the operator token, the
+    /// response reports how the store answered the query
     async fn handle_graphql_query(
         self,
         target: QueryTarget,
-        request_body: Body,
+        request: Request<Body>,
     ) -> GraphQLServiceResult {
         let service = self.clone();
         let service_metrics = self.metrics.clone();
 
+        let explain =
+            graph::url::form_urlencoded::parse(request.uri().query().unwrap_or("").as_bytes())
+                .any(|(key, value)| key == "explain" && value == "true");
+        if explain && !is_operator(request.headers()) {
+            return Err(GraphQLServerError::ClientError(
+                "explaining queries requires a valid operator token".to_string(),
+            ));
+        }
+
         let start = Instant::now();
-        let body = hyper::body::to_bytes(request_body)
-            .map_err(|_| GraphQLServerError::InternalError("Failed to read request body".into()))
-            .await?;
-        let query = GraphQLRequest::new(body).compat().await;
+        let query = if request.method() == Method::GET {
+            match GraphQLRequest::from_query_string(request.uri().query().unwrap_or("")) {
+                Ok(request) => request.compat().await,
+                Err(e) => Err(e),
+            }
+        } else {
+            let body = hyper::body::to_bytes(request.into_body())
+                .map_err(|_| {
+                    GraphQLServerError::InternalError("Failed to read request body".into())
+                })
+                .await?;
+            GraphQLRequest::new(body).compat().await
+        };
 
         let result = match query {
-            Ok(query) => service.graphql_runner.run_query(query, target).await,
+            Ok(mut query) => {
+                query.explain = explain;
+                service.graphql_runner.run_query(query, target).await
+            }
             Err(GraphQLServerError::QueryError(e)) => QueryResult::from(e).into(),
             Err(e) => return Err(e),
         };
@@ -205,13 +330,224 @@ where
         Ok(result.as_http_response())
     }
 
+    /// Run the GraphQL queries sent to `/graphql`. A query names the
+    /// deployment it runs against with a `@deployment` directive on its
+    /// operation; queries without one use the deployment from the
+    /// `Graph-Deployment` or `Graph-Subgraph` header. The body of a `POST`
+    /// may also be an array of queries, possibly for different
+    /// deployments, which are run concurrently and answered with the array
+    /// of their results
+    async fn handle_unified_query(self, request: Request<Body>) -> GraphQLServiceResult {
+        let default_target = target_from_headers(request.headers())?;
+        let explain =
+            graph::url::form_urlencoded::parse(request.uri().query().unwrap_or("").as_bytes())
+                .any(|(key, value)| key == "explain" && value == "true");
+        if explain && !is_operator(request.headers()) {
+            return Err(GraphQLServerError::ClientError(
+                "explaining queries requires a valid operator token".to_string(),
+            ));
+        }
+
+        if request.method() == Method::GET {
+            let query = GraphQLRequest::from_query_string(request.uri().query().unwrap_or(""))?
+                .compat()
+                .await;
+            let results = self
+                .run_unified_query(query, default_target, explain)
+                .await?;
+            return Ok(results.as_http_response());
+        }
+
+        let body = hyper::body::to_bytes(request.into_body())
+            .map_err(|_| GraphQLServerError::InternalError("Failed to read request body".into()))
+            .await?;
+        let batch = match serde_json::from_slice(&body) {
+            Ok(serde_json::Value::Array(batch)) => batch,
+            _ => {
+                let query = GraphQLRequest::new(body).compat().await;
+                let results = self
+                    .run_unified_query(query, default_target, explain)
+                    .await?;
+                return Ok(results.as_http_response());
+            }
+        };
+        if batch.len() > ENV_VARS.graphql.max_batch_size {
+            return Err(GraphQLServerError::ClientError(format!(
+                "a batch can contain at most {} queries",
+                ENV_VARS.graphql.max_batch_size
+            )));
+        }
+
+        let results = futures03::future::join_all(batch.into_iter().map(|request| {
+            let service = self.clone();
+            let default_target = default_target.clone();
+            async move {
+                let body = hyper::body::Bytes::from(request.to_string());
+                let query = GraphQLRequest::new(body).compat().await;
+                match service
+                    .run_unified_query(query, default_target, explain)
+                    .await
+                {
+                    // Problems with one query in a batch should not keep
+                    // the others from running
+                    Err(GraphQLServerError::ClientError(e)) => {
+                        Ok(QueryExecutionError::ValidationError(None, e).into())
+                    }
+                    res => res,
+                }
+            }
+        }))
+        .await
+        .into_iter()
+        .collect::<Result<Vec<_>, _>>()?;
+        Ok(QueryResults::batch_http_response(&results))
+    }
+
+    /// Run one query sent to `/graphql` against the deployment from its
+    /// `@deployment` directive, or against `default_target`
+    async fn run_unified_query(
+        &self,
+        query: Result<Query, GraphQLServerError>,
+        default_target: Option<QueryTarget>,
+        explain: bool,
+    ) -> Result<QueryResults, GraphQLServerError> {
+        let start = Instant::now();
+        let query = match query {
+            Ok(query) => query,
+            Err(GraphQLServerError::QueryError(e)) => return Ok(QueryResult::from(e).into()),
+            Err(e) => return Err(e),
+        };
+
+        let mut document = query.document;
+        let target = take_deployment_directive(&mut document)?
+            .or(default_target)
+            .ok_or_else(|| {
+                GraphQLServerError::ClientError(format!(
+                    "the query must name its deployment with a @deployment directive \
+                     or the {} or {} header",
+                    DEPLOYMENT_HEADER, SUBGRAPH_HEADER
+                ))
+            })?;
+        let mut query = Query::new(document, query.variables);
+        query.explain = explain;
+
+        let result = self.graphql_runner.clone().run_query(query, target).await;
+        if let Some(id) = result.first().and_then(|res| res.deployment.clone()) {
+            self.metrics
+                .observe_query_execution_time(start.elapsed().as_secs_f64(), id.to_string());
+        }
+        Ok(result)
+    }
+
+    /// Answer a request to the REST API by turning it into a GraphQL query
+    /// against `target`
+    async fn handle_rest_request(
+        self,
+        target: QueryTarget,
+        entity: String,
+        id: Option<String>,
+        request: Request<Body>,
+    ) -> GraphQLServiceResult {
+        if !ENV_VARS.graphql.enable_rest_api {
+            return Ok(rest::not_found());
+        }
+
+        let rest_request = RestRequest::new(&entity, id.as_deref(), request.uri().query())?;
+        let schema = match self
+            .graphql_runner
+            .cheap_clone()
+            .input_schema(target.clone())
+            .await
+        {
+            Ok(schema) => schema,
+            // The subgraph does not exist or can not be queried right now
+            Err(e) => return Ok(QueryResults::from(e).as_http_response()),
+        };
+
+        let query = match rest_request.query(&schema)? {
+            Some(query) => query,
+            None => return Ok(rest::not_found()),
+        };
+        let results = self
+            .graphql_runner
+            .cheap_clone()
+            .run_query(query, target)
+            .await;
+        Ok(rest_request.response(&results))
+    }
+
+    /// Report the health of a deployment. The response has status 503 if
+    /// the deployment has failed or its shard is unavailable, or, when the query string contains
+    /// `max_blocks_behind`, if it is further behind the chain head than
+    /// that, so that it can be used as a readiness probe for the deployment
+    async fn handle_deployment_health(
+        self,
+        id: String,
+        request: Request<Body>,
+    ) -> GraphQLServiceResult {
+        let id = match DeploymentHash::new(id) {
+            Ok(id) => id,
+            Err(_) => return self.handle_not_found().await,
+        };
+        let max_blocks_behind =
+            graph::url::form_urlencoded::parse(request.uri().query().unwrap_or("").as_bytes())
+                .find(|(key, _)| key == "max_blocks_behind")
+                .map(|(_, value)| {
+                    value.parse::<BlockNumber>().map_err(|_| {
+                        GraphQLServerError::ClientError(format!(
+                            "invalid value `{}` for max_blocks_behind",
+                            value
+                        ))
+                    })
+                })
+                .transpose()?;
+
+        let health = match self
+            .health
+            .deployment_health(id)
+            .await
+            .map_err(|e| GraphQLServerError::InternalError(e.to_string()))?
+        {
+            Some(health) => health,
+            None => return self.handle_not_found().await,
+        };
+
+        let too_far_behind = match (max_blocks_behind, health.blocks_behind) {
+            (Some(max), Some(behind)) => behind > max,
+            (Some(_), None) => true,
+            (None, _) => false,
+        };
+        let status =
+            if health.health == "failed" || health.health == "unavailable" || too_far_behind {
+                StatusCode::SERVICE_UNAVAILABLE
+            } else {
+                StatusCode::OK
+            };
+        Ok(json_response(status, &health))
+    }
+
+    /// Report whether the node is ready; the response has status 503 if
+    /// it is not
+    async fn handle_readiness(self) -> GraphQLServiceResult {
+        let readiness = self.health.readiness().await;
+        let status = if readiness.ready {
+            StatusCode::OK
+        } else {
+            StatusCode::SERVICE_UNAVAILABLE
+        };
+        Ok(json_response(status, &readiness))
+    }
+
     // Handles OPTIONS requests
     fn handle_graphql_options(&self, _request: Request<Body>) -> GraphQLServiceResponse {
         async {
             Ok(Response::builder()
                 .status(200)
                 .header(ACCESS_CONTROL_ALLOW_ORIGIN, "*")
-                .header(ACCESS_CONTROL_ALLOW_HEADERS, "Content-Type, User-Agent")
+                .header(
+                    ACCESS_CONTROL_ALLOW_HEADERS,
+                    "Content-Type, User-Agent, Graph-Deployment, Graph-Subgraph",
+                )
                 .header(ACCESS_CONTROL_ALLOW_METHODS, "GET, OPTIONS, POST")
                 .header(CONTENT_TYPE, "text/html")
                 .body(Body::from(""))
@@ -265,12 +601,42 @@ where
 
         match (method, path_segments.as_slice()) {
             (Method::GET, [""]) => self.index().boxed(),
+            (Method::GET, &["ready"]) => self.handle_readiness().boxed(),
+            (Method::POST, &["graphql"]) => self.handle_unified_query(req).boxed(),
+            (Method::GET, &["graphql"]) if has_graphql_query(&req) => {
+                self.handle_unified_query(req).boxed()
+            }
+            (Method::OPTIONS, &["graphql"]) => self.handle_graphql_options(req),
+            (Method::GET, &["health", id]) => {
+                self.handle_deployment_health(id.to_owned(), req).boxed()
+            }
             (Method::GET, &["subgraphs", "id", _, "graphql"])
             | (Method::GET, &["subgraphs", "name", _, "graphql"])
             | (Method::GET, &["subgraphs", "name", _, _, "graphql"])
             | (Method::GET, &["subgraphs", "network", _, _, "graphql"])
             | (Method::GET, &["subgraphs", "graphql"]) => self.handle_graphiql(),
 
+            (Method::GET, &["subgraphs", "id", subgraph_id]) if has_graphql_query(&req) => {
+                self.handle_graphql_query_by_id(subgraph_id.to_owned(), req)
+            }
+            (Method::GET, &["subgraphs", "name", subgraph_name]) if has_graphql_query(&req) => self
+                .handle_graphql_query_by_name(subgraph_name.to_owned(), req)
+                .boxed(),
+            (Method::GET, ["subgraphs", "name", subgraph_name_part1, subgraph_name_part2])
+                if has_graphql_query(&req) =>
+            {
+                let subgraph_name = format!("{}/{}", subgraph_name_part1, subgraph_name_part2);
+                self.handle_graphql_query_by_name(subgraph_name, req)
+                    .boxed()
+            }
+            (Method::GET, ["subgraphs", "network", subgraph_name_part1, subgraph_name_part2])
+                if has_graphql_query(&req) =>
+            {
+                let subgraph_name =
+                    format!("network/{}/{}", subgraph_name_part1, subgraph_name_part2);
+                self.handle_graphql_query_by_name(subgraph_name, req)
+                    .boxed()
+            }
             (Method::GET, path @ ["subgraphs", "id", _])
             | (Method::GET, path @ ["subgraphs", "name", _])
             | (Method::GET, path @ ["subgraphs", "name", _, _])
@@ -299,6 +665,18 @@ where
                     .boxed()
             }
 
+            (Method::GET, ["rest", name, entity]) => self
+                .handle_rest_request_by_name(name.to_string(), entity.to_string(), None, req)
+                .boxed(),
+            (Method::GET, ["rest", name, entity, id]) => self
+                .handle_rest_request_by_name(
+                    name.to_string(),
+                    entity.to_string(),
+                    Some(id.to_string()),
+                    req,
+                )
+                .boxed(),
+
             (Method::OPTIONS, ["subgraphs", "name", _])
             | (Method::OPTIONS, ["subgraphs", "name", _, _])
             | (Method::OPTIONS, ["subgraphs", "network", _, _]) => self.handle_graphql_options(req),
@@ -308,6 +686,73 @@ where
     }
 }
 
+/// Whether `req` is a `GET` request for a GraphQL query, as opposed to a
+/// request for GraphiQL
+fn has_graphql_query(req: &Request<Body>) -> bool {
+    graph::url::form_urlencoded::parse(req.uri().query().unwrap_or("").as_bytes())
+        .any(|(key, _)| key == "query" || key == "extensions")
+}
+
+/// Whether the request carries the operator token from
+/// `GRAPH_GRAPHQL_EXPLAIN_TOKEN` as its bearer token
+fn is_operator(headers: &hyper::HeaderMap) -> bool {
+    let token = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.as_bytes().strip_prefix(b"Bearer "));
+    match (ENV_VARS.graphql.explain_token.as_ref(), token) {
+        (Some(expected), Some(token)) => {
+            // Compare hashes so that the comparison does not leak how much
+            // of the token was right through its timing
+            blake3::hash(expected.as_bytes()) == blake3::hash(token)
+        }
+        _ => false,
+    }
+}
+
+/// The deployment that the `Graph-Deployment` header (with a deployment
+/// id) or the `Graph-Subgraph` header (with a subgraph name) of a request
+/// to `/graphql` names
+fn target_from_headers(
+    headers: &hyper::HeaderMap,
+) -> Result<Option<QueryTarget>, GraphQLServerError> {
+    let header = |name: &str| -> Result<Option<&str>, GraphQLServerError> {
+        headers
+            .get(name)
+            .map(|value| {
+                value.to_str().map_err(|_| {
+                    GraphQLServerError::ClientError(format!("the {} header is not valid", name))
+                })
+            })
+            .transpose()
+    };
+    match (header(DEPLOYMENT_HEADER)?, header(SUBGRAPH_HEADER)?) {
+        (Some(_), Some(_)) => Err(GraphQLServerError::ClientError(format!(
+            "only one of the {} and {} headers can be set",
+            DEPLOYMENT_HEADER, SUBGRAPH_HEADER
+        ))),
+        (Some(id), None) => DeploymentHash::new(id)
+            .map(|id| Some(id.into()))
+            .map_err(|id| GraphQLServerError::ClientError(format!("Invalid subgraph id `{}`", id))),
+        (None, Some(name)) => SubgraphName::new(name)
+            .map(|name| Some(name.into()))
+            .map_err(|()| {
+                GraphQLServerError::ClientError(format!("Invalid subgraph name {:?}", name))
+            }),
+        (None, None) => Ok(None),
+    }
+}
+
+fn json_response<T: Serialize>(status: StatusCode, value: &T) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .header(ACCESS_CONTROL_ALLOW_ORIGIN, "*")
+        .header(CONTENT_TYPE, "application/json")
+        .body(Body::from(
+            serde_json::to_string(value).expect("health reports can be serialized"),
+        ))
+        .unwrap()
+}
+
 impl<Q> Service<Request<Body>> for GraphQLService<Q>
 where
     Q: GraphQlRunner,
@@ -327,9 +772,10 @@ where
         // Returning Err here will prevent the client from receiving any response.
         // Instead, we generate a Response with an error code and return Ok
         Box::pin(async move {
+            let encoding = Encoding::negotiate(req.headers());
             let result = service.handle_call(req).await;
             match result {
-                Ok(response) => Ok(response),
+                Ok(response) => Ok(compression::compress(response, encoding).await),
                 Err(err @ GraphQLServerError::ClientError(_)) => Ok(Response::builder()
                     .status(400)
                     .header(CONTENT_TYPE, "text/plain")
@@ -419,6 +865,22 @@ mod tests {
             unreachable!();
         }
 
+        async fn run_sql(
+            self: Arc<Self>,
+            _sql: String,
+            _block: Option<BlockNumber>,
+            _target: QueryTarget,
+        ) -> Result<Vec<serde_json::Value>, QueryExecutionError> {
+            unimplemented!()
+        }
+
+        async fn input_schema(
+            self: Arc<Self>,
+            _target: QueryTarget,
+        ) -> Result<Arc<Schema>, QueryExecutionError> {
+            unimplemented!()
+        }
+
         fn load_manager(&self) -> Arc<LoadManager> {
             unimplemented!()
         }
@@ -433,7 +895,9 @@ mod tests {
         let graphql_runner = Arc::new(TestGraphQlRunner);
 
         let node_id = NodeId::new("test").unwrap();
-        let mut service = GraphQLService::new(logger, metrics, graphql_runner, 8001, node_id);
+        let health = Arc::new(test_utils::TestHealthCheck);
+        let mut service =
+            GraphQLService::new(logger, metrics, graphql_runner, health, 8001, node_id);
 
         let request = Request::builder()
             .method(Method::POST)
@@ -456,6 +920,40 @@ mod tests {
         );
     }
 
+    #[test]
+    fn explaining_queries_requires_operator_token() {
+        let logger = Logger::root(slog::Discard, o!());
+        let metrics_registry = Arc::new(MockMetricsRegistry::new());
+        let metrics = Arc::new(GraphQLServiceMetrics::new(metrics_registry));
+        let subgraph_id = USERS.clone();
+        let graphql_runner = Arc::new(TestGraphQlRunner);
+
+        let node_id = NodeId::new("test").unwrap();
+        let health = Arc::new(test_utils::TestHealthCheck);
+        let mut service =
+            GraphQLService::new(logger, metrics, graphql_runner, health, 8001, node_id);
+
+        let request = Request::builder()
+            .method(Method::POST)
+            .uri(format!(
+                "http://localhost:8000/subgraphs/id/{}?explain=true",
+                subgraph_id
+            ))
+            .header(hyper::header::AUTHORIZATION, "Bearer not-the-token")
+            .body(Body::from("{\"query\": \"{ name }\"}"))
+            .unwrap();
+
+        let response =
+            futures03::executor::block_on(service.call(request)).expect("Should return a response");
+        let errors = test_utils::assert_error_response(response, StatusCode::BAD_REQUEST, false);
+
+        let message = errors[0].as_str().expect("Error message is not a string");
+        assert_eq!(
+            message,
+            "GraphQL server error (client error): explaining queries requires a valid operator token"
+        );
+    }
+
     #[tokio::test(flavor = "multi_thread")]
     async fn posting_valid_queries_yields_result_response() {
         let logger = Logger::root(slog::Discard, o!());
@@ -465,7 +963,9 @@ mod tests {
         let graphql_runner = Arc::new(TestGraphQlRunner);
 
         let node_id = NodeId::new("test").unwrap();
-        let mut service = GraphQLService::new(logger, metrics, graphql_runner, 8001, node_id);
+        let health = Arc::new(test_utils::TestHealthCheck);
+        let mut service =
+            GraphQLService::new(logger, metrics, graphql_runner, health, 8001, node_id);
 
         let request = Request::builder()
             .method(Method::POST)
@@ -491,4 +991,111 @@ mod tests {
             .expect("Query result field \"name\" is not a string");
         assert_eq!(name, "Jordi".to_string());
     }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn routes_queries_sent_to_graphql_endpoint() {
+        let logger = Logger::root(slog::Discard, o!());
+        let metrics_registry = Arc::new(MockMetricsRegistry::new());
+        let metrics = Arc::new(GraphQLServiceMetrics::new(metrics_registry));
+        let graphql_runner = Arc::new(TestGraphQlRunner);
+
+        let node_id = NodeId::new("test").unwrap();
+        let health = Arc::new(test_utils::TestHealthCheck);
+        let service = GraphQLService::new(logger, metrics, graphql_runner, health, 8001, node_id);
+
+        let post = |header: Option<(&str, &str)>, body: &str| {
+            let mut request = Request::builder()
+                .method(Method::POST)
+                .uri("http://localhost:8000/graphql");
+            if let Some((name, value)) = header {
+                request = request.header(name, value);
+            }
+            let request = request.body(Body::from(body.to_string())).unwrap();
+            let mut service = service.clone();
+            async move {
+                tokio::spawn(service.call(request))
+                    .await
+                    .unwrap()
+                    .expect("Should return a response")
+            }
+        };
+
+        let response = post(
+            Some(("Graph-Deployment", "users")),
+            "{\"query\": \"{ name }\"}",
+        )
+        .await;
+        let data = test_utils::assert_successful_response(response);
+        assert_eq!(
+            Some("Jordi"),
+            data.get("name").and_then(|name| name.as_str())
+        );
+
+        let response = post(
+            None,
+            "{\"query\": \"query @deployment(name: \\\"users/name\\\") { name }\"}",
+        )
+        .await;
+        test_utils::assert_successful_response(response);
+
+        let response = post(None, "{\"query\": \"{ name }\"}").await;
+        test_utils::assert_error_response(response, StatusCode::BAD_REQUEST, false);
+
+        let response = post(
+            Some(("Graph-Subgraph", "users/name")),
+            "[{\"query\": \"{ name }\"}, \
+              {\"query\": \"query @deployment(id: \\\"users\\\") { name }\"}, \
+              {\"query\": \"query @deployment { name }\"}]",
+        )
+        .await;
+        assert_eq!(StatusCode::OK, response.status());
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let results: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        let results = results
+            .as_array()
+            .expect("batches are answered with an array");
+        assert_eq!(3, results.len());
+        assert_eq!("Jordi", results[0]["data"]["name"]);
+        assert_eq!("Jordi", results[1]["data"]["name"]);
+        assert!(results[2].get("errors").is_some());
+    }
+
+    #[test]
+    fn reports_health() {
+        let logger = Logger::root(slog::Discard, o!());
+        let metrics_registry = Arc::new(MockMetricsRegistry::new());
+        let metrics = Arc::new(GraphQLServiceMetrics::new(metrics_registry));
+        let graphql_runner = Arc::new(TestGraphQlRunner);
+        let health = Arc::new(test_utils::TestHealthCheck);
+
+        let node_id = NodeId::new("test").unwrap();
+        let mut service =
+            GraphQLService::new(logger, metrics, graphql_runner, health, 8001, node_id);
+
+        let status = |service: &mut GraphQLService<TestGraphQlRunner>, uri: &str| {
+            let request = Request::builder()
+                .method(Method::GET)
+                .uri(format!("http://localhost:8000{}", uri))
+                .body(Body::empty())
+                .unwrap();
+            futures03::executor::block_on(service.call(request))
+                .expect("Should return a response")
+                .status()
+        };
+
+        assert_eq!(StatusCode::OK, status(&mut service, "/ready"));
+        assert_eq!(StatusCode::OK, status(&mut service, "/health/users"));
+        assert_eq!(
+            StatusCode::OK,
+            status(&mut service, "/health/users?max_blocks_behind=10")
+        );
+        assert_eq!(
+            StatusCode::SERVICE_UNAVAILABLE,
+            status(&mut service, "/health/users?max_blocks_behind=1")
+        );
+        assert_eq!(
+            StatusCode::NOT_FOUND,
+            status(&mut service, "/health/unknown")
+        );
+    }
 }
@@ -1,3 +1,4 @@
+use graph::components::server::health::{DeploymentHealth, HealthCheck, NodeReadiness};
 use graph::prelude::serde_json;
 use graph::prelude::*;
 use http::StatusCode;
@@ -70,3 +71,36 @@ pub fn assert_expected_headers(response: &Response<Body>) {
         &"*"
     );
 }
+
+/// A health check that knows a single deployment `users`, which is healthy
+/// and 5 blocks behind the chain head
+pub struct TestHealthCheck;
+
+#[async_trait]
+impl HealthCheck for TestHealthCheck {
+    async fn deployment_health(
+        &self,
+        deployment: DeploymentHash,
+    ) -> Result<Option<DeploymentHealth>, StoreError> {
+        if deployment.as_str() != "users" {
+            return Ok(None);
+        }
+        Ok(Some(DeploymentHealth {
+            deployment: deployment.to_string(),
+            health: "healthy".to_string(),
+            synced: true,
+            latest_block: Some(95),
+            chain_head_block: Some(100),
+            blocks_behind: Some(5),
+        }))
+    }
+
+    async fn readiness(&self) -> NodeReadiness {
+        NodeReadiness {
+            ready: true,
+            stores: vec![("primary".to_string(), true)].into_iter().collect(),
+            ingestors: vec!["mainnet".to_string()],
+            leading: vec!["mainnet".to_string()],
+        }
+    }
+}
@@ -69,6 +69,22 @@ impl GraphQlRunner for TestGraphQlRunner {
         unreachable!();
     }
 
+    async fn run_sql(
+        self: Arc<Self>,
+        _sql: String,
+        _block: Option<BlockNumber>,
+        _target: QueryTarget,
+    ) -> Result<Vec<serde_json::Value>, QueryExecutionError> {
+        unimplemented!()
+    }
+
+    async fn input_schema(
+        self: Arc<Self>,
+        _target: QueryTarget,
+    ) -> Result<Arc<Schema>, QueryExecutionError> {
+        unimplemented!()
+    }
+
     fn load_manager(&self) -> Arc<LoadManager> {
         unimplemented!()
     }
@@ -94,7 +110,13 @@ mod test {
                 let id = USERS.clone();
                 let query_runner = Arc::new(TestGraphQlRunner);
                 let node_id = NodeId::new("test").unwrap();
-                let mut server = HyperGraphQLServer::new(&logger_factory, metrics_registry, query_runner, node_id);
+                let mut server = HyperGraphQLServer::new(
+                    &logger_factory,
+                    metrics_registry,
+                    query_runner,
+                    Arc::new(test_utils::TestHealthCheck),
+                    node_id,
+                );
                 let http_server = server
                     .serve(8007, 8008)
                     .expect("Failed to start GraphQL server");
@@ -136,8 +158,13 @@ mod test {
             let id = USERS.clone();
             let query_runner = Arc::new(TestGraphQlRunner);
             let node_id = NodeId::new("test").unwrap();
-            let mut server =
-                HyperGraphQLServer::new(&logger_factory, metrics_registry, query_runner, node_id);
+            let mut server = HyperGraphQLServer::new(
+                &logger_factory,
+                metrics_registry,
+                query_runner,
+                Arc::new(test_utils::TestHealthCheck),
+                node_id,
+            );
             let http_server = server
                 .serve(8002, 8003)
                 .expect("Failed to start GraphQL server");
@@ -218,8 +245,13 @@ mod test {
             let id = USERS.clone();
             let query_runner = Arc::new(TestGraphQlRunner);
             let node_id = NodeId::new("test").unwrap();
-            let mut server =
-                HyperGraphQLServer::new(&logger_factory, metrics_registry, query_runner, node_id);
+            let mut server = HyperGraphQLServer::new(
+                &logger_factory,
+                metrics_registry,
+                query_runner,
+                Arc::new(test_utils::TestHealthCheck),
+                node_id,
+            );
             let http_server = server
                 .serve(8003, 8004)
                 .expect("Failed to start GraphQL server");
@@ -265,8 +297,13 @@ mod test {
             let id = USERS.clone();
             let query_runner = Arc::new(TestGraphQlRunner);
             let node_id = NodeId::new("test").unwrap();
-            let mut server =
-                HyperGraphQLServer::new(&logger_factory, metrics_registry, query_runner, node_id);
+            let mut server = HyperGraphQLServer::new(
+                &logger_factory,
+                metrics_registry,
+                query_runner,
+                Arc::new(test_utils::TestHealthCheck),
+                node_id,
+            );
             let http_server = server
                 .serve(8005, 8006)
                 .expect("Failed to start GraphQL server");
@@ -1,5 +1,6 @@
 mod auth;
 mod explorer;
+mod metadata;
 mod resolver;
 mod schema;
 mod server;
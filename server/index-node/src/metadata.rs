@@ -0,0 +1,186 @@
+//! The `metadata` subgraph: the node's own metadata about deployments,
+//! their assignments, versions, errors and head pointers, served at
+//! `/subgraphs/id/metadata` so that it can be queried with plain GraphQL.
+//! It is always available since it is computed from the metadata in the
+//! store and does not need to be deployed or indexed
+use graph::components::store::Store;
+use graph::data::graphql::{object, IntoValue, ObjectOrInterface, ValueMap};
+use graph::data::subgraph::status;
+use graph::prelude::*;
+use graph_graphql::prelude::{a, ExecutionContext, Resolver};
+
+lazy_static! {
+    pub static ref METADATA_SCHEMA: Arc<ApiSchema> = {
+        let raw_schema = include_str!("./metadata.graphql");
+        let document = graphql_parser::parse_schema(&raw_schema).unwrap();
+        Arc::new(
+            ApiSchema::from_api_schema(
+                Schema::new(DeploymentHash::new("metadata").unwrap(), document).unwrap(),
+            )
+            .unwrap(),
+        )
+    };
+}
+
+/// Resolver for the `metadata` subgraph
+pub struct MetadataResolver<S: Store> {
+    store: Arc<S>,
+}
+
+impl<S: Store> MetadataResolver<S> {
+    pub fn new(store: Arc<S>) -> Self {
+        Self { store }
+    }
+
+    /// Turn the status of a deployment into a `Deployment`, adding the
+    /// subgraph versions that use it
+    fn deployment(&self, info: status::Info) -> Result<r::Value, QueryExecutionError> {
+        let versions = self
+            .store
+            .subgraphs_for_deployment_hash(&info.subgraph)?
+            .into_iter()
+            .map(|(subgraph, status)| {
+                object! {
+                    __typename: "Version",
+                    subgraph: subgraph,
+                    status: r::Value::Enum(status),
+                }
+            })
+            .collect::<Vec<_>>();
+
+        let mut value = match info.into_value() {
+            r::Value::Object(value) => value,
+            _ => unreachable!("the status of a deployment is an object"),
+        };
+        if let Some(id) = value.remove("subgraph") {
+            value.insert("id".to_string(), id);
+        }
+        value.insert(
+            "__typename".to_string(),
+            r::Value::String("Deployment".to_string()),
+        );
+        value.insert("versions".to_string(), r::Value::List(versions));
+        Ok(r::Value::Object(value))
+    }
+
+    fn resolve_deployments(&self, field: &a::Field) -> Result<r::Value, QueryExecutionError> {
+        let node = field
+            .get_optional::<String>("node")
+            .expect("node must be a string");
+
+        // An empty list of deployments means all deployments
+        let deployments = self
+            .store
+            .status(status::Filter::Deployments(vec![]))?
+            .into_iter()
+            .filter(|info| node.is_none() || info.node == node)
+            .map(|info| self.deployment(info))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(r::Value::List(deployments))
+    }
+
+    fn resolve_deployment(&self, field: &a::Field) -> Result<r::Value, QueryExecutionError> {
+        // We can safely unwrap because the argument is non-nullable and has been validated.
+        let id = field.get_required::<String>("id").unwrap();
+
+        match self
+            .store
+            .status(status::Filter::Deployments(vec![id]))?
+            .into_iter()
+            .next()
+        {
+            Some(info) => self.deployment(info),
+            None => Ok(r::Value::Null),
+        }
+    }
+
+    fn resolve_subgraph(&self, field: &a::Field) -> Result<r::Value, QueryExecutionError> {
+        // We can safely unwrap because the argument is non-nullable and has been validated.
+        let name = field.get_required::<String>("name").unwrap();
+
+        let version = |current| -> Result<r::Value, QueryExecutionError> {
+            match self
+                .store
+                .status(status::Filter::SubgraphVersion(name.clone(), current))?
+                .into_iter()
+                .next()
+            {
+                Some(info) => self.deployment(info),
+                None => Ok(r::Value::Null),
+            }
+        };
+        let current = version(true)?;
+        let pending = version(false)?;
+        if current.is_null() && pending.is_null() {
+            return Ok(r::Value::Null);
+        }
+
+        Ok(object! {
+            __typename: "Subgraph",
+            name: name.clone(),
+            currentVersion: current,
+            pendingVersion: pending,
+        })
+    }
+}
+
+impl<S: Store> Clone for MetadataResolver<S> {
+    fn clone(&self) -> Self {
+        Self {
+            store: self.store.clone(),
+        }
+    }
+}
+
+#[async_trait]
+impl<S: Store> Resolver for MetadataResolver<S> {
+    const CACHEABLE: bool = false;
+
+    async fn query_permit(&self) -> Result<QueryPermit, QueryExecutionError> {
+        Ok(self.store.query_permit().await.into())
+    }
+
+    fn prefetch(
+        &self,
+        _: &ExecutionContext<Self>,
+        _: &a::SelectionSet,
+    ) -> Result<Option<r::Value>, Vec<QueryExecutionError>> {
+        Ok(None)
+    }
+
+    fn resolve_objects(
+        &self,
+        prefetched_objects: Option<r::Value>,
+        field: &a::Field,
+        _field_definition: &s::Field,
+        object_type: ObjectOrInterface<'_>,
+    ) -> Result<r::Value, QueryExecutionError> {
+        match (prefetched_objects, object_type.name(), field.name.as_str()) {
+            (None, "Deployment", "deployments") => self.resolve_deployments(field),
+
+            // Resolve lists nested in objects we already resolved
+            (value, _, _) => Ok(value.unwrap_or(r::Value::Null)),
+        }
+    }
+
+    fn resolve_object(
+        &self,
+        prefetched_object: Option<r::Value>,
+        field: &a::Field,
+        _field_definition: &s::Field,
+        _object_type: ObjectOrInterface<'_>,
+    ) -> Result<r::Value, QueryExecutionError> {
+        match (prefetched_object, field.name.as_str()) {
+            (None, "deployment") => self.resolve_deployment(field),
+            (None, "subgraph") => self.resolve_subgraph(field),
+
+            // Resolve objects nested in objects we already resolved
+            (value, _) => Ok(value.unwrap_or(r::Value::Null)),
+        }
+    }
+}
+
+#[test]
+fn metadata_schema_parses() {
+    let _ = &*METADATA_SCHEMA;
+}
@@ -1,5 +1,6 @@
 use std::collections::BTreeMap;
 use std::convert::TryInto;
+use std::time::Duration;
 
 use either::Either;
 use web3::types::{Address, H256};
@@ -7,6 +8,7 @@ use web3::types::{Address, H256};
 use graph::blockchain::{Blockchain, BlockchainKind, BlockchainMap};
 use graph::components::store::{BlockStore, EntityType, Store};
 use graph::data::graphql::{object, IntoValue, ObjectOrInterface, ValueMap};
+use graph::data::schema_diff;
 use graph::data::subgraph::features::detect_features;
 use graph::data::subgraph::status;
 use graph::data::value::Object;
@@ -15,6 +17,10 @@ use graph_graphql::prelude::{a, ExecutionContext, Resolver};
 
 use crate::auth::PoiProtection;
 
+/// How long the `chains` query waits for a provider to tell it its latest
+/// block
+const PROVIDER_PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+
 #[derive(Clone, Debug)]
 struct PublicProofOfIndexingRequest {
     pub deployment: DeploymentHash,
@@ -284,6 +290,161 @@ impl<S: Store> IndexNodeResolver<S> {
         ))
     }
 
+    fn resolve_subgraph_deprecation(
+        &self,
+        field: &a::Field,
+    ) -> Result<r::Value, QueryExecutionError> {
+        let name = field
+            .get_required::<String>("subgraphName")
+            .expect("subgraphName not provided");
+        // Invalid names can not have a notice
+        let name = match SubgraphName::new(name) {
+            Ok(name) => name,
+            Err(()) => return Ok(r::Value::Null),
+        };
+
+        match self.store.subgraph_store().deprecation(&name)? {
+            Some(deprecation) => Ok(object! {
+                __typename: "SubgraphDeprecation",
+                subgraph: name.to_string(),
+                message: deprecation.message.clone(),
+                removalDate: deprecation.removal_date_string(),
+            }),
+            None => Ok(r::Value::Null),
+        }
+    }
+
+    fn resolve_schema_diff(&self, field: &a::Field) -> Result<r::Value, QueryExecutionError> {
+        // We can safely unwrap because the arguments are non-nullable and
+        // have been validated.
+        let deployment = |name: &str| {
+            DeploymentHash::new(field.get_required::<String>(name).unwrap())
+                .map_err(QueryExecutionError::SubgraphDeploymentIdError)
+        };
+        let current = deployment("currentVersion")?;
+        let pending = deployment("pendingVersion")?;
+
+        let subgraph_store = self.store.subgraph_store();
+        let current = subgraph_store.input_schema(&current)?;
+        let pending = subgraph_store.input_schema(&pending)?;
+        Ok(schema_diff::diff(&current.document, &pending.document).into_value())
+    }
+
+    fn resolve_jobs(&self, field: &a::Field) -> Result<r::Value, QueryExecutionError> {
+        // The arguments have been validated, so they have the right types
+        // and `states` only holds values of the `JobState` enum
+        let id = field.get_optional::<i32>("id").expect("Invalid id");
+        let deployment = field
+            .get_optional::<String>("deployment")
+            .expect("Invalid deployment");
+        let states = match field.argument_value("states") {
+            Some(r::Value::List(states)) => states
+                .iter()
+                .map(|state| match state {
+                    r::Value::Enum(state) => state.parse::<status::JobState>().unwrap(),
+                    _ => unreachable!(),
+                })
+                .collect(),
+            _ => vec![],
+        };
+
+        let jobs = self.store.jobs(status::JobFilter {
+            id,
+            deployment,
+            states,
+        })?;
+        Ok(jobs.into_value())
+    }
+
+    fn resolve_block_info(
+        &self,
+        field: &a::Field,
+        by_hash: bool,
+    ) -> Result<r::Value, QueryExecutionError> {
+        let network = field
+            .get_required::<String>("network")
+            .expect("Valid network required");
+
+        let chain = match self
+            .blockchain_map
+            .get::<graph_chain_ethereum::Chain>(network.clone())
+        {
+            Ok(chain) => chain,
+            Err(_) => {
+                error!(
+                    self.logger,
+                    "Failed to look up block; nonexistent network";
+                    "network" => network,
+                );
+                return Ok(r::Value::Null);
+            }
+        };
+        let block = if by_hash {
+            let block_hash = field
+                .get_required::<H256>("blockHash")
+                .expect("Valid blockHash required");
+            graph::block_on(chain.block_by_hash(&self.logger, block_hash))
+        } else {
+            let block_number = field
+                .get_required::<BlockNumber>("blockNumber")
+                .expect("Valid blockNumber required");
+            graph::block_on(chain.block_by_number(&self.logger, block_number))
+        };
+
+        match block {
+            Ok(Some(block)) => Ok(object! {
+                __typename: "BlockInfo",
+                hash: block.hash.as_bytes(),
+                number: block.number,
+                timestamp: block.timestamp.to_string(),
+                parentHash: block.parent_hash.as_bytes(),
+            }),
+            Ok(None) => Ok(r::Value::Null),
+            Err(e) => {
+                error!(
+                    self.logger,
+                    "Failed to look up block";
+                    "network" => network.as_str(),
+                    "error" => format!("{:#}", e),
+                );
+                Err(QueryExecutionError::StoreError(e.into()))
+            }
+        }
+    }
+
+    fn resolve_chains(&self, field: &a::Field) -> Result<r::Value, QueryExecutionError> {
+        let mut chains = self.store.chains()?;
+
+        // Asking the providers takes a while; only do that if the query
+        // wants to know about them
+        let wants_providers = field
+            .selection_set
+            .fields()
+            .any(|(_, mut fields)| fields.any(|field| field.name == "providers"));
+        if wants_providers {
+            let probes = chains.iter().map(|chain| {
+                let eth_chain = self
+                    .blockchain_map
+                    .get::<graph_chain_ethereum::Chain>(chain.network.clone())
+                    .ok();
+                async move {
+                    match eth_chain {
+                        Some(eth_chain) => {
+                            eth_chain.provider_statuses(PROVIDER_PROBE_TIMEOUT).await
+                        }
+                        None => vec![],
+                    }
+                }
+            });
+            let providers = graph::block_on(futures03::future::join_all(probes));
+            for (chain, providers) in chains.iter_mut().zip(providers) {
+                chain.providers = providers;
+            }
+        }
+
+        Ok(chains.into_value())
+    }
+
     fn resolve_proof_of_indexing(&self, field: &a::Field) -> Result<r::Value, QueryExecutionError> {
         let deployment_id = field
             .get_required::<DeploymentHash>("subgraph")
@@ -693,8 +854,8 @@ impl<S: Store> Clone for IndexNodeResolver<S> {
 impl<S: Store> Resolver for IndexNodeResolver<S> {
     const CACHEABLE: bool = false;
 
-    async fn query_permit(&self) -> tokio::sync::OwnedSemaphorePermit {
-        self.store.query_permit().await
+    async fn query_permit(&self) -> Result<QueryPermit, QueryExecutionError> {
+        Ok(self.store.query_permit().await.into())
     }
 
     fn prefetch(
@@ -744,6 +905,7 @@ impl<S: Store> Resolver for IndexNodeResolver<S> {
             (None, "SubgraphIndexingStatus", "indexingStatusesForSubgraphName") => {
                 self.resolve_indexing_statuses_for_subgraph_name(field)
             }
+            (None, "ChainStatus", "chains") => self.resolve_chains(field),
             (None, "CachedEthereumCall", "cachedEthereumCalls") => {
                 self.resolve_cached_ethereum_calls(field)
             }
@@ -775,6 +937,11 @@ impl<S: Store> Resolver for IndexNodeResolver<S> {
             }
             (None, "subgraphFeatures") => graph::block_on(self.resolve_subgraph_features(field)),
             (None, "entityChangesInBlock") => self.resolve_entity_changes_in_block(field),
+            (None, "blockByHash") => self.resolve_block_info(field, true),
+            (None, "blockByNumber") => self.resolve_block_info(field, false),
+            (None, "subgraphDeprecation") => self.resolve_subgraph_deprecation(field),
+            (None, "schemaDiff") => self.resolve_schema_diff(field),
+            (None, "jobs") => self.resolve_jobs(field),
 
             // Resolve fields of `Object` values (e.g. the `latestBlock` field of `EthereumBlock`)
             (value, _) => Ok(value.unwrap_or(r::Value::Null)),
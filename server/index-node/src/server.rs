@@ -1,11 +1,10 @@
 use hyper;
 use hyper::service::make_service_fn;
-use hyper::Server;
 use std::net::{Ipv4Addr, SocketAddrV4};
 
 use graph::{
     blockchain::BlockchainMap,
-    components::store::Store,
+    components::{server::http, store::Store},
     prelude::{IndexNodeServer as IndexNodeServerTrait, *},
 };
 
@@ -98,8 +97,9 @@ where
             make_service_fn(move |_| futures03::future::ok::<_, Error>(service.clone()));
 
         // Create a task to run the server and handle HTTP requests
-        let task = Server::try_bind(&addr.into())?
+        let task = http::bind(&addr.into())?
             .serve(new_service)
+            .with_graceful_shutdown(http::shutdown_signal())
             .map_err(move |e| error!(logger, "Server error"; "error" => format!("{}", e)));
 
         Ok(Box::new(task.compat()))
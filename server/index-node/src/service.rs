@@ -11,7 +11,7 @@ use std::task::Context;
 use std::task::Poll;
 
 use graph::components::{server::query::GraphQLServerError, store::Store};
-use graph::data::query::QueryResults;
+use graph::data::query::{QueryLimits, QueryResults};
 use graph::prelude::*;
 use graph_graphql::prelude::{execute_query, Query as PreparedQuery, QueryExecutionOptions};
 use graphql_parser;
@@ -19,6 +19,7 @@ use graphql_parser;
 use crate::auth::bearer_token;
 
 use crate::explorer::Explorer;
+use crate::metadata::{MetadataResolver, METADATA_SCHEMA};
 use crate::resolver::IndexNodeResolver;
 use crate::schema::SCHEMA;
 
@@ -120,7 +121,11 @@ where
         let validated = ValidatedRequest::new(body, &req_parts.headers)?;
         let query = validated.query;
 
-        let query = match PreparedQuery::new(&self.logger, schema, None, query, None, 100) {
+        let limits = QueryLimits {
+            max_depth: 100,
+            ..QueryLimits::unlimited()
+        };
+        let query = match PreparedQuery::new(&self.logger, schema, None, query, limits) {
             Ok(query) => query,
             Err(e) => return Ok(QueryResults::from(QueryResult::from(e)).as_http_response()),
         };
@@ -141,8 +146,8 @@ where
             let options = QueryExecutionOptions {
                 resolver,
                 deadline: None,
-                max_first: std::u32::MAX,
-                max_skip: std::u32::MAX,
+                max_first: limits.max_first,
+                max_skip: limits.max_skip,
                 load_manager,
             };
             let result = execute_query(query_clone.cheap_clone(), None, None, options).await;
@@ -156,6 +161,45 @@ where
         Ok(QueryResults::from(result).as_http_response())
     }
 
+    /// Run a query against the built-in `metadata` subgraph
+    async fn handle_metadata_query(
+        &self,
+        request: Request<Body>,
+    ) -> Result<Response<Body>, GraphQLServerError> {
+        let (req_parts, req_body) = request.into_parts();
+
+        let body = hyper::body::to_bytes(req_body)
+            .map_err(|_| GraphQLServerError::InternalError("Failed to read request body".into()))
+            .await?;
+        let validated = ValidatedRequest::new(body, &req_parts.headers)?;
+
+        let limits = QueryLimits::from_env();
+        let query = match PreparedQuery::new(
+            &self.logger,
+            METADATA_SCHEMA.clone(),
+            None,
+            validated.query,
+            limits,
+        ) {
+            Ok(query) => query,
+            Err(e) => return Ok(QueryResults::from(QueryResult::from(e)).as_http_response()),
+        };
+
+        let options = QueryExecutionOptions {
+            resolver: MetadataResolver::new(self.store.clone()),
+            deadline: None,
+            max_first: limits.max_first,
+            max_skip: limits.max_skip,
+            load_manager: self.graphql_runner.load_manager(),
+        };
+        let result = execute_query(query.cheap_clone(), None, None, options).await;
+        query.log_execution(0);
+        // Metadata queries are not cacheable, so we may unwrap this.
+        let result = QueryResult::from(Arc::try_unwrap(result).unwrap());
+
+        Ok(QueryResults::from(result).as_http_response())
+    }
+
     // Handles OPTIONS requests
     fn handle_graphql_options(_request: Request<Body>) -> Response<Body> {
         Response::builder()
@@ -228,6 +272,13 @@ where
             (Method::POST, ["graphql"]) => self.handle_graphql_query(req).await,
             (Method::OPTIONS, ["graphql"]) => Ok(Self::handle_graphql_options(req)),
 
+            (Method::POST, ["subgraphs", "id", "metadata"]) => {
+                self.handle_metadata_query(req).await
+            }
+            (Method::OPTIONS, ["subgraphs", "id", "metadata"]) => {
+                Ok(Self::handle_graphql_options(req))
+            }
+
             (Method::GET, ["explorer", rest @ ..]) => self.explorer.handle(&self.logger, rest),
 
             _ => Ok(Self::handle_not_found()),
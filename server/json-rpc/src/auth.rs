@@ -0,0 +1,236 @@
+//! Tokens for the admin server and the roles they grant. The tokens are
+//! listed in `GRAPH_ADMIN_TOKENS` as `name:role:token`, separated by
+//! commas, and callers send them as bearer tokens. Without any tokens,
+//! callers can use every method without sending one
+use std::fmt;
+use std::str::FromStr;
+
+use graph::env::EnvVars;
+use jsonrpc_http_server::hyper::{header::AUTHORIZATION, Body, Request};
+use jsonrpc_http_server::jsonrpc_core::Metadata;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Role {
+    /// Can create and deploy subgraphs and validate manifests
+    Deployer,
+    /// Can also remove, reassign and export subgraphs
+    Operator,
+}
+
+impl Role {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::Deployer => "deployer",
+            Role::Operator => "operator",
+        }
+    }
+
+    /// The role a caller needs to call `method`
+    pub fn required_for(method: &str) -> Role {
+        match method {
+            "subgraph_create" | "subgraph_deploy" | "subgraph_validate" => Role::Deployer,
+            _ => Role::Operator,
+        }
+    }
+}
+
+impl FromStr for Role {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "deployer" => Ok(Role::Deployer),
+            "operator" => Ok(Role::Operator),
+            _ => Err(format!(
+                "unknown role `{}`, roles must be `deployer` or `operator`",
+                s
+            )),
+        }
+    }
+}
+
+/// The bearer token that came with a request, if any
+#[derive(Clone, Debug, Default)]
+pub struct Caller {
+    token: Option<String>,
+}
+
+impl Metadata for Caller {}
+
+impl Caller {
+    pub fn from_request(request: &Request<Body>) -> Self {
+        let token = request
+            .headers()
+            .get(AUTHORIZATION)
+            .and_then(|header| header.to_str().ok())
+            .and_then(|header| header.strip_prefix("Bearer "))
+            .map(|token| token.trim().to_string());
+        Caller { token }
+    }
+}
+
+pub struct Token {
+    pub name: String,
+    pub role: Role,
+    // We only keep the hash of the token and compare hashes so that the
+    // comparison does not leak how much of a token a caller got right
+    hash: blake3::Hash,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum AuthError {
+    /// The caller sent no token or a token we do not know
+    Unauthenticated,
+    /// The caller's token does not have the role the method needs
+    Forbidden { token: String, required: Role },
+}
+
+impl fmt::Display for AuthError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AuthError::Unauthenticated => write!(f, "a valid admin token is required"),
+            AuthError::Forbidden { token, required } => write!(
+                f,
+                "the token `{}` does not have the `{}` role",
+                token,
+                required.as_str()
+            ),
+        }
+    }
+}
+
+pub struct AdminAuth {
+    tokens: Vec<Token>,
+}
+
+impl AdminAuth {
+    /// The tokens from `GRAPH_ADMIN_TOKENS`
+    pub fn from_env(env: &EnvVars) -> Result<Self, String> {
+        match &env.admin_tokens {
+            Some(tokens) => Self::parse(tokens),
+            None => Ok(AdminAuth { tokens: vec![] }),
+        }
+    }
+
+    fn parse(s: &str) -> Result<Self, String> {
+        let mut tokens: Vec<Token> = Vec::new();
+        for entry in s
+            .split(',')
+            .map(str::trim)
+            .filter(|entry| !entry.is_empty())
+        {
+            let mut parts = entry.splitn(3, ':');
+            let (name, role, token) = match (parts.next(), parts.next(), parts.next()) {
+                (Some(name), Some(role), Some(token)) if !name.is_empty() && !token.is_empty() => {
+                    (name, role, token)
+                }
+                _ => {
+                    return Err(format!(
+                        "admin tokens must have the form `name:role:token`, but `{}...` does not",
+                        entry.split(':').next().unwrap_or_default()
+                    ))
+                }
+            };
+            if tokens.iter().any(|token| token.name == name) {
+                return Err(format!("the admin token `{}` is listed twice", name));
+            }
+            tokens.push(Token {
+                name: name.to_string(),
+                role: role.parse()?,
+                hash: blake3::hash(token.as_bytes()),
+            });
+        }
+        Ok(AdminAuth { tokens })
+    }
+
+    /// Whether callers need a token
+    pub fn is_active(&self) -> bool {
+        !self.tokens.is_empty()
+    }
+
+    /// Check that `caller` may call `method`. Return the token the caller
+    /// sent, or `None` if callers do not need a token
+    pub fn authorize(&self, caller: &Caller, method: &str) -> Result<Option<&Token>, AuthError> {
+        if !self.is_active() {
+            return Ok(None);
+        }
+        let hash = match &caller.token {
+            Some(token) => blake3::hash(token.as_bytes()),
+            None => return Err(AuthError::Unauthenticated),
+        };
+        let token = self
+            .tokens
+            .iter()
+            .find(|token| token.hash == hash)
+            .ok_or(AuthError::Unauthenticated)?;
+        let required = Role::required_for(method);
+        if token.role < required {
+            return Err(AuthError::Forbidden {
+                token: token.name.clone(),
+                required,
+            });
+        }
+        Ok(Some(token))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn caller(token: Option<&str>) -> Caller {
+        Caller {
+            token: token.map(str::to_string),
+        }
+    }
+
+    #[test]
+    fn roles_scope_methods() {
+        let auth = AdminAuth::parse("ci:deployer:t0ken, alice:operator:s3cret").unwrap();
+
+        let ci = caller(Some("t0ken"));
+        let token = auth.authorize(&ci, "subgraph_deploy").unwrap().unwrap();
+        assert_eq!("ci", token.name);
+        assert_eq!(
+            Err(AuthError::Forbidden {
+                token: "ci".to_string(),
+                required: Role::Operator
+            }),
+            auth.authorize(&ci, "subgraph_remove").map(|_| ())
+        );
+
+        let alice = caller(Some("s3cret"));
+        for method in ["subgraph_deploy", "subgraph_remove", "subgraph_reassign"] {
+            assert!(auth.authorize(&alice, method).is_ok());
+        }
+
+        for unknown in [caller(None), caller(Some("guess"))] {
+            assert_eq!(
+                Err(AuthError::Unauthenticated),
+                auth.authorize(&unknown, "subgraph_create").map(|_| ())
+            );
+        }
+    }
+
+    #[test]
+    fn no_tokens_allow_everything() {
+        let auth = AdminAuth::parse("").unwrap();
+        assert!(!auth.is_active());
+        assert!(auth
+            .authorize(&caller(None), "subgraph_remove")
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn invalid_tokens() {
+        assert!(AdminAuth::parse("ci:deployer").is_err());
+        assert!(AdminAuth::parse("ci:admin:t0ken").is_err());
+        assert!(AdminAuth::parse("ci:deployer:a,ci:operator:b").is_err());
+        // Tokens may contain colons
+        let auth = AdminAuth::parse("ci:operator:a:b").unwrap();
+        assert!(auth
+            .authorize(&caller(Some("a:b")), "subgraph_remove")
+            .is_ok());
+    }
+}
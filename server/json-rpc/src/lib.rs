@@ -3,21 +3,37 @@ extern crate jsonrpc_http_server;
 extern crate lazy_static;
 extern crate serde;
 
+mod auth;
+
+use graph::components::server::http;
+use graph::components::store::{AdminCall, AdminCallOutcome, ExportRequest};
+use graph::components::subgraph::ManifestSource;
+use graph::data::subgraph::local;
 use graph::prelude::serde_json;
 use graph::prelude::{JsonRpcServer as JsonRpcServerTrait, *};
 use jsonrpc_http_server::{
-    jsonrpc_core::{self, Compatibility, IoHandler, Params, Value},
+    hyper,
+    jsonrpc_core::{self, Compatibility, MetaIoHandler, Params, Value},
     RestApi, Server, ServerBuilder,
 };
+use serde::de::DeserializeOwned;
 
 use std::collections::BTreeMap;
+use std::future::Future;
 use std::io;
 use std::net::{Ipv4Addr, SocketAddrV4};
+use std::path::Path;
+
+use crate::auth::{AdminAuth, Caller};
 
 const JSON_RPC_DEPLOY_ERROR: i64 = 0;
 const JSON_RPC_REMOVE_ERROR: i64 = 1;
 const JSON_RPC_CREATE_ERROR: i64 = 2;
 const JSON_RPC_REASSIGN_ERROR: i64 = 3;
+const JSON_RPC_EXPORT_ERROR: i64 = 4;
+const JSON_RPC_VALIDATE_ERROR: i64 = 5;
+const JSON_RPC_AUTH_ERROR: i64 = 6;
+const JSON_RPC_SHUTDOWN_ERROR: i64 = 7;
 
 #[derive(Debug, Deserialize)]
 struct SubgraphCreateParams {
@@ -27,9 +43,17 @@ struct SubgraphCreateParams {
 #[derive(Debug, Deserialize)]
 struct SubgraphDeployParams {
     name: SubgraphName,
-    ipfs_hash: DeploymentHash,
+    /// The IPFS hash of the manifest, or a `file://` path to a manifest
+    /// on the local filesystem
+    ipfs_hash: String,
     node_id: Option<NodeId>,
     debug_fork: Option<DeploymentHash>,
+    /// Start indexing after this block instead of the manifest's start
+    /// block, given as `BLOCK_HASH:BLOCK_NUMBER`
+    start_block: Option<String>,
+    /// Values for the parameters that the manifest declares
+    #[serde(default)]
+    parameters: BTreeMap<String, serde_json::Value>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -43,15 +67,101 @@ struct SubgraphReassignParams {
     node_id: NodeId,
 }
 
+#[derive(Debug, Deserialize)]
+struct SubgraphExportParams {
+    ipfs_hash: DeploymentHash,
+    #[serde(flatten)]
+    request: ExportRequest,
+}
+
+/// Exactly one of `ipfs_hash` and `manifest` must be given
+#[derive(Debug, Deserialize)]
+struct SubgraphValidateParams {
+    /// The IPFS hash of the manifest, or a `file://` path to a manifest
+    /// on the local filesystem
+    ipfs_hash: Option<String>,
+    /// The text of the manifest
+    manifest: Option<String>,
+}
+
 pub struct JsonRpcServer<R> {
     registrar: Arc<R>,
     http_port: u16,
     ws_port: u16,
     node_id: NodeId,
     logger: Logger,
+    auth: AdminAuth,
 }
 
 impl<R: SubgraphRegistrar> JsonRpcServer<R> {
+    /// Check that `caller` may call `method` and run `handler` with the
+    /// parsed `params` if so. The call is added to the audit log whether
+    /// it was allowed or not. Once the node starts shutting down, calls are
+    /// turned away, and it waits for the ones that are running to finish
+    async fn call<P, F, Fut>(
+        self: Arc<Self>,
+        method: &'static str,
+        params: Params,
+        caller: Caller,
+        handler: F,
+    ) -> Result<Value, jsonrpc_core::Error>
+    where
+        P: DeserializeOwned,
+        F: FnOnce(Arc<Self>, P) -> Fut,
+        Fut: Future<Output = Result<Value, jsonrpc_core::Error>>,
+    {
+        // Draining starts before connections stop being counted, so this
+        // call is either counted or turned away
+        let _open = http::open_connection();
+        if http::is_draining() {
+            return Err(jsonrpc_core::Error {
+                code: jsonrpc_core::ErrorCode::ServerError(JSON_RPC_SHUTDOWN_ERROR),
+                message: "the node is shutting down".to_string(),
+                data: None,
+            });
+        }
+
+        let mut call = AdminCall {
+            method: method.to_string(),
+            token: None,
+            role: None,
+            params: serde_json::to_value(&params).unwrap_or(Value::Null),
+            outcome: AdminCallOutcome::Denied,
+            error: None,
+        };
+
+        let result = match self.auth.authorize(&caller, method) {
+            Ok(token) => {
+                call.token = token.map(|token| token.name.clone());
+                call.role = token.map(|token| token.role.as_str().to_string());
+                let result = match params.parse() {
+                    Ok(params) => handler(self.clone(), params).await,
+                    Err(e) => Err(e),
+                };
+                call.outcome = match result {
+                    Ok(_) => AdminCallOutcome::Succeeded,
+                    Err(_) => AdminCallOutcome::Failed,
+                };
+                result
+            }
+            Err(e) => {
+                warn!(self.logger, "Denied admin call"; "method" => method, "error" => e.to_string());
+                Err(jsonrpc_core::Error {
+                    code: jsonrpc_core::ErrorCode::ServerError(JSON_RPC_AUTH_ERROR),
+                    message: e.to_string(),
+                    data: None,
+                })
+            }
+        };
+        call.error = result.as_ref().err().map(|e| e.message.clone());
+
+        if let Err(e) = self.registrar.record_admin_call(call).await {
+            error!(self.logger, "Failed to add admin call to the audit log";
+                   "method" => method, "error" => e.to_string());
+        }
+        result
+    }
+
     /// Handler for the `subgraph_create` endpoint.
     async fn create_handler(
         &self,
@@ -81,29 +191,129 @@ impl<R: SubgraphRegistrar> JsonRpcServer<R> {
         info!(&self.logger, "Received subgraph_deploy request"; "params" => format!("{:?}", params));
 
         let node_id = params.node_id.clone().unwrap_or(self.node_id.clone());
-        let routes = subgraph_routes(&params.name, self.http_port, self.ws_port);
-        match self
+        let start_block = params
+            .start_block
+            .as_deref()
+            .map(|block| block.parse::<BlockPtr>())
+            .transpose()
+            .map_err(|e| jsonrpc_core::Error::invalid_params(e.to_string()))?;
+        let hash = if params.ipfs_hash.starts_with(local::FILE_SCHEME) {
+            match self.registrar.import_files(&params.ipfs_hash).await {
+                Ok(hash) => hash,
+                Err(e) => {
+                    return Err(json_rpc_error(
+                        &self.logger,
+                        "subgraph_deploy",
+                        e,
+                        JSON_RPC_DEPLOY_ERROR,
+                        params,
+                    ))
+                }
+            }
+        } else {
+            DeploymentHash::new(params.ipfs_hash.clone()).map_err(|hash| {
+                jsonrpc_core::Error::invalid_params(format!("invalid IPFS hash `{}`", hash))
+            })?
+        };
+        let hash = match self
             .registrar
-            .create_subgraph_version(
-                params.name.clone(),
-                params.ipfs_hash.clone(),
-                node_id,
-                params.debug_fork.clone(),
-                // Here it doesn't make sense to receive another
-                // startBlock, we'll use the one from the manifest.
-                None,
-            )
+            .instantiate_manifest(hash, &params.parameters)
             .await
         {
-            Ok(_) => Ok(routes),
-            Err(e) => Err(json_rpc_error(
-                &self.logger,
-                "subgraph_deploy",
-                e,
-                JSON_RPC_DEPLOY_ERROR,
-                params,
-            )),
+            Ok(hash) => hash,
+            Err(e) => {
+                return Err(json_rpc_error(
+                    &self.logger,
+                    "subgraph_deploy",
+                    e,
+                    JSON_RPC_DEPLOY_ERROR,
+                    params,
+                ))
+            }
+        };
+        let family = match self.registrar.instantiate_networks(&hash).await {
+            Ok(family) => family,
+            Err(e) => {
+                return Err(json_rpc_error(
+                    &self.logger,
+                    "subgraph_deploy",
+                    e,
+                    JSON_RPC_DEPLOY_ERROR,
+                    params,
+                ))
+            }
+        };
+        let family = match family {
+            Some(family) => family,
+            None => {
+                let routes = subgraph_routes(&params.name, self.http_port, self.ws_port);
+                return match self
+                    .registrar
+                    .create_subgraph_version(
+                        params.name.clone(),
+                        hash,
+                        node_id,
+                        params.debug_fork.clone(),
+                        start_block,
+                    )
+                    .await
+                {
+                    Ok(_) => Ok(routes),
+                    Err(e) => Err(json_rpc_error(
+                        &self.logger,
+                        "subgraph_deploy",
+                        e,
+                        JSON_RPC_DEPLOY_ERROR,
+                        params,
+                    )),
+                };
+            }
+        };
+
+        // A block only makes sense for one network
+        if start_block.is_some() {
+            return Err(jsonrpc_core::Error::invalid_params(
+                "`start_block` can not be used with manifests for several networks",
+            ));
+        }
+
+        // Each network of the family is deployed as its own subgraph,
+        // named after the network
+        let mut routes = BTreeMap::new();
+        for (network, hash) in family {
+            let name = SubgraphName::new(format!("{}/{}", params.name, network)).map_err(|_| {
+                jsonrpc_core::Error::invalid_params(format!(
+                    "the network `{}` can not be used in a subgraph name",
+                    network
+                ))
+            })?;
+            let deployed = async {
+                self.registrar.create_subgraph(name.clone()).await?;
+                self.registrar
+                    .create_subgraph_version(
+                        name.clone(),
+                        hash,
+                        node_id.clone(),
+                        params.debug_fork.clone(),
+                        None,
+                    )
+                    .await
+            };
+            if let Err(e) = deployed.await {
+                return Err(json_rpc_error(
+                    &self.logger,
+                    "subgraph_deploy",
+                    e,
+                    JSON_RPC_DEPLOY_ERROR,
+                    params,
+                ));
+            }
+            routes.insert(
+                network,
+                subgraph_routes(&name, self.http_port, self.ws_port),
+            );
         }
+        Ok(jsonrpc_core::to_value(routes).unwrap())
     }
 
     /// Handler for the `subgraph_remove` endpoint.
@@ -149,6 +359,96 @@ impl<R: SubgraphRegistrar> JsonRpcServer<R> {
             )),
         }
     }
+
+    /// Handler for the `subgraph_export` endpoint.
+    async fn export_handler(
+        &self,
+        params: SubgraphExportParams,
+    ) -> Result<Value, jsonrpc_core::Error> {
+        info!(&self.logger, "Received subgraph_export request"; "params" => format!("{:?}", params));
+
+        // Callers can only write into the export directory
+        let mut request = params.request.clone();
+        let confined = match &ENV_VARS.export_dir {
+            Some(dir) => request.confine_to(Path::new(dir)),
+            None => Err(anyhow!(
+                "exporting through the admin server is turned off; set GRAPH_EXPORT_DIR"
+            )),
+        };
+        if let Err(e) = confined {
+            return Err(json_rpc_error(
+                &self.logger,
+                "subgraph_export",
+                SubgraphRegistrarError::StoreError(StoreError::Unknown(e)),
+                JSON_RPC_EXPORT_ERROR,
+                params,
+            ));
+        }
+
+        match self
+            .registrar
+            .export_subgraph(&params.ipfs_hash, request)
+            .await
+        {
+            Ok(files) => Ok(serde_json::to_value(files).expect("invalid export result")),
+            Err(e) => Err(json_rpc_error(
+                &self.logger,
+                "subgraph_export",
+                e,
+                JSON_RPC_EXPORT_ERROR,
+                params,
+            )),
+        }
+    }
+
+    /// Handler for the `subgraph_validate` endpoint.
+    async fn validate_handler(
+        &self,
+        params: SubgraphValidateParams,
+    ) -> Result<Value, jsonrpc_core::Error> {
+        info!(&self.logger, "Received subgraph_validate request"; "params" => format!("{:?}", params));
+
+        let manifest = match (&params.ipfs_hash, &params.manifest) {
+            (Some(path), None) if path.starts_with(local::FILE_SCHEME) => {
+                match self.registrar.import_files(path).await {
+                    Ok(hash) => ManifestSource::Hash(hash),
+                    Err(e) => {
+                        return Err(json_rpc_error(
+                            &self.logger,
+                            "subgraph_validate",
+                            e,
+                            JSON_RPC_VALIDATE_ERROR,
+                            params,
+                        ))
+                    }
+                }
+            }
+            (Some(hash), None) => {
+                ManifestSource::Hash(DeploymentHash::new(hash.clone()).map_err(|hash| {
+                    jsonrpc_core::Error::invalid_params(format!("invalid IPFS hash `{}`", hash))
+                })?)
+            }
+            (None, Some(manifest)) => ManifestSource::Inline(manifest.clone()),
+            _ => {
+                return Err(jsonrpc_core::Error::invalid_params(
+                    "exactly one of `ipfs_hash` and `manifest` must be given",
+                ))
+            }
+        };
+
+        match self.registrar.validate_subgraph(manifest).await {
+            Ok(validation) => {
+                Ok(serde_json::to_value(validation).expect("invalid validation result"))
+            }
+            Err(e) => Err(json_rpc_error(
+                &self.logger,
+                "subgraph_validate",
+                e,
+                JSON_RPC_VALIDATE_ERROR,
+                params,
+            )),
+        }
+    }
 }
 
 impl<R> JsonRpcServerTrait<R> for JsonRpcServer<R>
@@ -174,7 +474,16 @@ where
 
         let addr = SocketAddrV4::new(Ipv4Addr::new(0, 0, 0, 0), port);
 
-        let mut handler = IoHandler::with_compatibility(Compatibility::Both);
+        let auth = AdminAuth::from_env(&ENV_VARS)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        if !auth.is_active() {
+            info!(
+                logger,
+                "Admin server does not require tokens since GRAPH_ADMIN_TOKENS is not set"
+            );
+        }
+
+        let mut handler = MetaIoHandler::with_compatibility(Compatibility::Both);
 
         let arc_self = Arc::new(JsonRpcServer {
             registrar,
@@ -182,49 +491,86 @@ where
             ws_port,
             node_id,
             logger,
+            auth,
         });
 
         let me = arc_self.clone();
-        handler.add_method("subgraph_create", move |params: Params| {
-            let me = me.clone();
-            async move {
-                let params = params.parse()?;
-                me.create_handler(params).await
-            }
+        handler.add_method_with_meta("subgraph_create", move |params: Params, caller: Caller| {
+            me.clone().call(
+                "subgraph_create",
+                params,
+                caller,
+                |me, params: SubgraphCreateParams| async move { me.create_handler(params).await },
+            )
         });
 
         let me = arc_self.clone();
-        handler.add_method("subgraph_deploy", move |params: Params| {
-            let me = me.clone();
-            async move {
-                let params = params.parse()?;
-                me.deploy_handler(params).await
-            }
+        handler.add_method_with_meta("subgraph_deploy", move |params: Params, caller: Caller| {
+            me.clone().call(
+                "subgraph_deploy",
+                params,
+                caller,
+                |me, params: SubgraphDeployParams| async move { me.deploy_handler(params).await },
+            )
         });
 
         let me = arc_self.clone();
-        handler.add_method("subgraph_remove", move |params: Params| {
-            let me = me.clone();
-            async move {
-                let params = params.parse()?;
-                me.remove_handler(params).await
-            }
+        handler.add_method_with_meta("subgraph_remove", move |params: Params, caller: Caller| {
+            me.clone().call(
+                "subgraph_remove",
+                params,
+                caller,
+                |me, params: SubgraphRemoveParams| async move { me.remove_handler(params).await },
+            )
         });
 
-        let me = arc_self;
-        handler.add_method("subgraph_reassign", move |params: Params| {
-            let me = me.clone();
-            async move {
-                let params = params.parse()?;
-                me.reassign_handler(params).await
-            }
+        let me = arc_self.clone();
+        handler.add_method_with_meta(
+            "subgraph_reassign",
+            move |params: Params, caller: Caller| {
+                me.clone().call(
+                    "subgraph_reassign",
+                    params,
+                    caller,
+                    |me, params: SubgraphReassignParams| async move {
+                        me.reassign_handler(params).await
+                    },
+                )
+            },
+        );
+
+        let me = arc_self.clone();
+        handler.add_method_with_meta("subgraph_export", move |params: Params, caller: Caller| {
+            me.clone().call(
+                "subgraph_export",
+                params,
+                caller,
+                |me, params: SubgraphExportParams| async move { me.export_handler(params).await },
+            )
         });
 
-        ServerBuilder::new(handler)
-            // Enable REST API:
-            // POST /<method>/<param1>/<param2>
-            .rest_api(RestApi::Secure)
-            .start_http(&addr.into())
+        let me = arc_self;
+        handler.add_method_with_meta(
+            "subgraph_validate",
+            move |params: Params, caller: Caller| {
+                me.clone().call(
+                    "subgraph_validate",
+                    params,
+                    caller,
+                    |me, params: SubgraphValidateParams| async move {
+                        me.validate_handler(params).await
+                    },
+                )
+            },
+        );
+
+        ServerBuilder::with_meta_extractor(handler, |request: &hyper::Request<hyper::Body>| {
+            Caller::from_request(request)
+        })
+        // Enable REST API:
+        // POST /<method>/<param1>/<param2>
+        .rest_api(RestApi::Secure)
+        .start_http(&addr.into())
     }
 }
 
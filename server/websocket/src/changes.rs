@@ -0,0 +1,766 @@
+use std::cmp;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio_tungstenite::tungstenite::{Error as WsError, Message as WsMessage};
+use tokio_tungstenite::WebSocketStream;
+
+use graph::prelude::futures03::Sink;
+use graph::prelude::web3::types::H256;
+use graph::prelude::*;
+
+/// How often we check whether the deployment has processed new blocks
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How many blocks we look for changes in with one query
+const BLOCK_BATCH_SIZE: BlockNumber = 10_000;
+
+/// Where a client is in the stream of changes: the last block it has
+/// received, the hash of that block if it could still be reverted when it
+/// was sent, and how many blocks the deployment had reverted at that
+/// point. Clients pass the cursor of the last message they processed back
+/// to us when they reconnect so that we can resume the stream
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Cursor {
+    block: BlockNumber,
+    hash: Option<H256>,
+    reorg_count: u32,
+}
+
+impl Cursor {
+    fn new(block: BlockNumber, hashes: &BTreeMap<BlockNumber, H256>, reorg_count: u32) -> Self {
+        Cursor {
+            block,
+            hash: hashes.get(&block).copied(),
+            reorg_count,
+        }
+    }
+}
+
+impl fmt::Display for Cursor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}:", self.block, self.reorg_count)?;
+        if let Some(hash) = &self.hash {
+            write!(f, "{:x}", hash)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Cursor {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || anyhow!("invalid cursor `{}`", s);
+        let mut parts = s.splitn(3, ':');
+        let block = parts.next().ok_or_else(invalid)?.parse()?;
+        let reorg_count = parts.next().ok_or_else(invalid)?.parse()?;
+        let hash = match parts.next().ok_or_else(invalid)? {
+            "" => None,
+            hash => {
+                let bytes = hex::decode(hash)?;
+                if bytes.len() != H256::len_bytes() {
+                    return Err(invalid());
+                }
+                Some(H256::from_slice(&bytes))
+            }
+        };
+        Ok(Cursor {
+            block,
+            hash,
+            reorg_count,
+        })
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "operation", rename_all = "lowercase")]
+enum Change {
+    Set {
+        entity: String,
+        id: String,
+        data: BTreeMap<String, r::Value>,
+    },
+    Remove {
+        entity: String,
+        id: String,
+    },
+}
+
+impl From<EntityOperation> for Change {
+    fn from(op: EntityOperation) -> Self {
+        match op {
+            EntityOperation::Set { key, data } => Change::Set {
+                entity: key.entity_type.to_string(),
+                id: key.entity_id,
+                data: data
+                    .sorted()
+                    .into_iter()
+                    .map(|(name, value)| (name, r::Value::from(value)))
+                    .collect(),
+            },
+            EntityOperation::Remove { key } => Change::Remove {
+                entity: key.entity_type.to_string(),
+                id: key.entity_id,
+            },
+        }
+    }
+}
+
+/// Message sent to clients of the change stream
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum OutgoingMessage {
+    /// The changes that were made to entities in `block`
+    Block {
+        block: BlockNumber,
+        cursor: String,
+        changes: Vec<Change>,
+    },
+    /// All changes up to and including `block` have been sent
+    Progress {
+        block: BlockNumber,
+        cursor: String,
+    },
+    /// The changes from all blocks after `block` were reverted and must be
+    /// discarded. Changes from the blocks that replace them will be sent
+    /// next
+    Revert {
+        block: BlockNumber,
+        cursor: String,
+    },
+    Error {
+        message: String,
+    },
+}
+
+impl OutgoingMessage {
+    fn into_ws_message(self) -> WsMessage {
+        WsMessage::Text(serde_json::to_string(&self).expect("invalid change stream message"))
+    }
+}
+
+/// Streams the changes that were made to the entities of a deployment to a
+/// WebSocket client, block by block, starting after the client's cursor or
+/// at the beginning of the deployment. The stream is built from the
+/// versions of entities in the store. Reverts are detected by comparing
+/// the hash of the block in the cursor with the deployment's chain, and by
+/// watching the deployment's `reorg_count` for blocks whose hash we don't
+/// know.
+///
+/// The store must read from the primary since the deployment's state is
+/// always read from there, and the changes must not lag behind it
+pub struct EntityChangeStream<S> {
+    logger: Logger,
+    store: Arc<dyn QueryStore + Send + Sync>,
+    stream: WebSocketStream<S>,
+    cursor: Option<Cursor>,
+}
+
+impl<S> EntityChangeStream<S>
+where
+    S: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+{
+    pub fn new(
+        logger: &Logger,
+        store: Arc<dyn QueryStore + Send + Sync>,
+        stream: WebSocketStream<S>,
+        cursor: Option<Cursor>,
+    ) -> Self {
+        EntityChangeStream {
+            logger: logger.new(o!("component" => "EntityChangeStream")),
+            store,
+            stream,
+            cursor,
+        }
+    }
+
+    pub async fn run(self) {
+        let EntityChangeStream {
+            logger,
+            store,
+            stream,
+            mut cursor,
+        } = self;
+        debug!(logger, "Entity change stream opened"; "cursor" => format!("{:?}", cursor));
+
+        let (mut sink, mut incoming) = stream.split();
+
+        // Clients do not send anything; we only listen so that we notice
+        // when they close the connection
+        let closed = async move {
+            while let Some(Ok(msg)) = incoming.next().await {
+                if msg.is_close() {
+                    break;
+                }
+            }
+        };
+        futures03::pin_mut!(closed);
+
+        loop {
+            match send_changes(&store, &mut sink, &mut cursor).await {
+                Ok(()) => {}
+                Err(SendError::Closed) => break,
+                Err(SendError::Store(e)) => {
+                    warn!(logger, "Failed to stream entity changes"; "error" => e.to_string());
+                    let message = OutgoingMessage::Error {
+                        message: e.to_string(),
+                    };
+                    let _ = sink.send(message.into_ws_message()).await;
+                    break;
+                }
+            }
+
+            tokio::select! {
+                _ = &mut closed => break,
+                _ = tokio::time::sleep(POLL_INTERVAL) => {}
+            }
+        }
+        debug!(logger, "Entity change stream closed"; "cursor" => format!("{:?}", cursor));
+    }
+}
+
+/// Send everything that changed since `cursor` and advance it
+async fn send_changes<Si>(
+    store: &Arc<dyn QueryStore + Send + Sync>,
+    sink: &mut Si,
+    cursor: &mut Option<Cursor>,
+) -> Result<(), SendError>
+where
+    Si: Sink<WsMessage, Error = WsError> + Unpin,
+{
+    // We must read the state before any changes so that a revert that
+    // happens while we read them is noticed the next time around
+    let state = store.deployment_state().await?;
+    let head = match store.block_ptr().await? {
+        Some(head) => head,
+        None => return Ok(()),
+    };
+    let mut current = cursor.unwrap_or(Cursor {
+        block: -1,
+        hash: None,
+        reorg_count: state.reorg_count,
+    });
+    if current.reorg_count > state.reorg_count {
+        return Err(anyhow!("cursor {} is not valid for this deployment", current).into());
+    }
+
+    // The hashes of the blocks on the deployment's chain that can still be
+    // reverted and that we need to check the cursor or to hand out new
+    // cursors
+    let reversible = head.number - ENV_VARS.reorg_threshold;
+    let down_to = cmp::max(cmp::min(current.block, head.number), reversible);
+    let ours = {
+        let head = head.clone();
+        blocking(store, move |store| chain_hashes(store, &head, down_to)).await?
+    };
+
+    if let Some(block) =
+        revert_point(store, &current, head.number, &ours, state.reorg_count).await?
+    {
+        current = Cursor::new(block, &ours, state.reorg_count);
+        *cursor = Some(current);
+        send(
+            sink,
+            OutgoingMessage::Revert {
+                block,
+                cursor: current.to_string(),
+            },
+        )
+        .await?;
+    }
+    current.reorg_count = state.reorg_count;
+
+    while current.block < head.number {
+        let until = cmp::min(head.number, current.block + BLOCK_BATCH_SIZE);
+        let after = current.block;
+        let blocks = blocking(store, move |store| store.changed_blocks(after, until)).await?;
+        for block in blocks {
+            let changes = blocking(store, move |store| store.entity_changes(block)).await?;
+            current = Cursor::new(block, &ours, state.reorg_count);
+            send(
+                sink,
+                OutgoingMessage::Block {
+                    block,
+                    cursor: current.to_string(),
+                    changes: changes.into_iter().map(Change::from).collect(),
+                },
+            )
+            .await?;
+            *cursor = Some(current);
+        }
+        current = Cursor::new(until, &ours, state.reorg_count);
+        send(
+            sink,
+            OutgoingMessage::Progress {
+                block: until,
+                cursor: current.to_string(),
+            },
+        )
+        .await?;
+        *cursor = Some(current);
+    }
+    Ok(())
+}
+
+/// The hashes of `head` and its ancestors down to block `down_to` by
+/// following parent hashes. Stops early if the chain store does not have
+/// a block
+fn chain_hashes(
+    store: &dyn QueryStore,
+    head: &BlockPtr,
+    down_to: BlockNumber,
+) -> Result<BTreeMap<BlockNumber, H256>, StoreError> {
+    let mut hashes = BTreeMap::new();
+    let mut number = head.number;
+    let mut hash = head.hash_as_h256();
+    loop {
+        hashes.insert(number, hash);
+        if number <= down_to || number <= 0 {
+            break;
+        }
+        match store.block_parent_hash(hash)? {
+            Some(parent) => {
+                hash = parent;
+                number -= 1;
+            }
+            None => break,
+        }
+    }
+    Ok(hashes)
+}
+
+/// The block that the client with `cursor` needs to revert to, or `None`
+/// if everything it received is still on the deployment's chain. `ours`
+/// has the hashes of the deployment's chain from `head` back
+async fn revert_point(
+    store: &Arc<dyn QueryStore + Send + Sync>,
+    cursor: &Cursor,
+    head: BlockNumber,
+    ours: &BTreeMap<BlockNumber, H256>,
+    reorg_count: u32,
+) -> Result<Option<BlockNumber>, SendError> {
+    // We can't always tell from hashes where the chains split. Every
+    // reverted block increments `reorg_count`, so the deployment can not
+    // have gone back further than that many blocks since the cursor was
+    // handed out. We might make the client throw away a few blocks that
+    // were not actually reverted; those simply get sent again
+    let reverted = (reorg_count - cursor.reorg_count) as BlockNumber;
+    let estimate = cmp::min(cursor.block - reverted, head);
+
+    match (cursor.hash, ours.get(&cursor.block)) {
+        (Some(theirs), Some(our_hash)) => {
+            if theirs == *our_hash {
+                return Ok(None);
+            }
+            // Follow both chains back until they meet, but not further
+            // than blocks can be reverted
+            let (number, our_hash) = (cursor.block, *our_hash);
+            let lowest = head - ENV_VARS.reorg_threshold;
+            let (met, number) = blocking(store, move |store| {
+                let (mut number, mut theirs, mut ours) = (number, theirs, our_hash);
+                while number > cmp::max(lowest, 0) {
+                    match (
+                        store.block_parent_hash(theirs)?,
+                        store.block_parent_hash(ours)?,
+                    ) {
+                        (Some(their_parent), Some(our_parent)) => {
+                            number -= 1;
+                            if their_parent == our_parent {
+                                return Ok((true, number));
+                            }
+                            theirs = their_parent;
+                            ours = our_parent;
+                        }
+                        _ => break,
+                    }
+                }
+                Ok((false, number))
+            })
+            .await?;
+            if met {
+                Ok(Some(number))
+            } else {
+                // The chains split somewhere before `number`
+                Ok(Some(cmp::max(cmp::min(estimate, number - 1), -1)))
+            }
+        }
+        _ if cursor.block <= head && reverted == 0 => Ok(None),
+        _ => Ok(Some(cmp::max(estimate, -1))),
+    }
+}
+
+enum SendError {
+    /// The client went away
+    Closed,
+    Store(anyhow::Error),
+}
+
+impl From<anyhow::Error> for SendError {
+    fn from(e: anyhow::Error) -> Self {
+        SendError::Store(e)
+    }
+}
+
+impl From<StoreError> for SendError {
+    fn from(e: StoreError) -> Self {
+        SendError::Store(e.into())
+    }
+}
+
+impl From<QueryExecutionError> for SendError {
+    fn from(e: QueryExecutionError) -> Self {
+        SendError::Store(e.into())
+    }
+}
+
+impl From<WsError> for SendError {
+    fn from(_: WsError) -> Self {
+        SendError::Closed
+    }
+}
+
+async fn send<Si>(sink: &mut Si, message: OutgoingMessage) -> Result<(), SendError>
+where
+    Si: Sink<WsMessage, Error = WsError> + Unpin,
+{
+    Ok(sink.send(message.into_ws_message()).await?)
+}
+
+/// Run a store operation on a blocking thread while holding a query permit
+async fn blocking<T, F>(store: &Arc<dyn QueryStore + Send + Sync>, f: F) -> Result<T, SendError>
+where
+    T: Send + 'static,
+    F: FnOnce(&dyn QueryStore) -> Result<T, StoreError> + Send + 'static,
+{
+    let _permit = store.query_permit().await.map_err(|e| anyhow!("{}", e))?;
+    let store = store.cheap_clone();
+    graph::spawn_blocking_allow_panic(move || f(store.as_ref()))
+        .await
+        .map_err(|e| anyhow!("entity change stream task failed: {}", e))?
+        .map_err(SendError::from)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::sync::Mutex;
+
+    use graph::components::store::{EntityKey, EntityType};
+    use graph::data::query::QueryExplanation;
+    use graph::prelude::futures03::channel::mpsc;
+    use graph::prelude::tokio::sync::Semaphore;
+
+    use super::*;
+
+    fn hash(number: BlockNumber, fork: u8) -> H256 {
+        let mut bytes = [fork; 32];
+        bytes[..4].copy_from_slice(&number.to_be_bytes());
+        H256::from(bytes)
+    }
+
+    struct Chain {
+        /// The hashes of the blocks the deployment has processed, and
+        /// whether entities changed in them
+        blocks: Vec<(H256, bool)>,
+        /// The parents of all blocks we have ever seen
+        parents: HashMap<H256, H256>,
+        reorg_count: u32,
+    }
+
+    /// A deployment whose chain we can extend and revert
+    struct FakeStore {
+        chain: Mutex<Chain>,
+        permits: Arc<Semaphore>,
+    }
+
+    impl FakeStore {
+        fn new() -> Arc<Self> {
+            Arc::new(FakeStore {
+                chain: Mutex::new(Chain {
+                    blocks: Vec::new(),
+                    parents: HashMap::new(),
+                    reorg_count: 0,
+                }),
+                permits: Arc::new(Semaphore::new(1)),
+            })
+        }
+
+        /// Process blocks from `fork` up to block `to`; entities change in
+        /// the blocks in `changed`
+        fn extend(&self, fork: u8, to: BlockNumber, changed: &[BlockNumber]) {
+            let mut chain = self.chain.lock().unwrap();
+            for number in chain.blocks.len() as BlockNumber..=to {
+                let parent = chain
+                    .blocks
+                    .last()
+                    .map(|(hash, _)| *hash)
+                    .unwrap_or_else(H256::zero);
+                let hash = hash(number, fork);
+                chain.parents.insert(hash, parent);
+                chain.blocks.push((hash, changed.contains(&number)));
+            }
+        }
+
+        fn revert_to(&self, number: BlockNumber) {
+            let mut chain = self.chain.lock().unwrap();
+            while chain.blocks.len() as BlockNumber > number + 1 {
+                chain.blocks.pop();
+                chain.reorg_count += 1;
+            }
+        }
+    }
+
+    #[async_trait]
+    impl QueryStore for FakeStore {
+        fn find_query_values(
+            &self,
+            _query: EntityQuery,
+        ) -> Result<Vec<BTreeMap<String, r::Value>>, QueryExecutionError> {
+            unimplemented!()
+        }
+
+        fn explain_query(
+            &self,
+            _query: EntityQuery,
+        ) -> Result<QueryExplanation, QueryExecutionError> {
+            unimplemented!()
+        }
+
+        fn execute_sql(
+            &self,
+            _sql: &str,
+            _block: BlockNumber,
+        ) -> Result<Vec<serde_json::Value>, QueryExecutionError> {
+            unimplemented!()
+        }
+
+        fn changed_blocks(
+            &self,
+            after: BlockNumber,
+            until: BlockNumber,
+        ) -> Result<Vec<BlockNumber>, StoreError> {
+            let chain = self.chain.lock().unwrap();
+            Ok((after + 1..=until)
+                .filter(|number| chain.blocks[*number as usize].1)
+                .collect())
+        }
+
+        fn entity_changes(&self, block: BlockNumber) -> Result<Vec<EntityOperation>, StoreError> {
+            let hash = self.chain.lock().unwrap().blocks[block as usize].0;
+            Ok(vec![EntityOperation::Set {
+                key: EntityKey {
+                    subgraph_id: DeploymentHash::new("fake").unwrap(),
+                    entity_type: EntityType::new("Thing".to_owned()),
+                    entity_id: block.to_string(),
+                },
+                data: Entity::from(vec![("hash", Value::from(format!("{:x}", hash)))]),
+            }])
+        }
+
+        async fn is_deployment_synced(&self) -> Result<bool, Error> {
+            Ok(true)
+        }
+
+        async fn block_ptr(&self) -> Result<Option<BlockPtr>, StoreError> {
+            let chain = self.chain.lock().unwrap();
+            Ok(chain
+                .blocks
+                .last()
+                .map(|(hash, _)| BlockPtr::from((*hash, chain.blocks.len() as BlockNumber - 1))))
+        }
+
+        fn block_number(&self, _block_hash: H256) -> Result<Option<BlockNumber>, StoreError> {
+            unimplemented!()
+        }
+
+        fn block_parent_hash(&self, block_hash: H256) -> Result<Option<H256>, StoreError> {
+            Ok(self.chain.lock().unwrap().parents.get(&block_hash).copied())
+        }
+
+        fn chain_head_number(&self) -> Result<Option<BlockNumber>, StoreError> {
+            unimplemented!()
+        }
+
+        fn block_time(&self, _hash: &H256) -> Result<Option<u64>, StoreError> {
+            unimplemented!()
+        }
+
+        fn block_at_time(&self, _timestamp: u64) -> Result<Option<BlockPtr>, StoreError> {
+            unimplemented!()
+        }
+
+        fn wait_stats(&self) -> PoolWaitStats {
+            unimplemented!()
+        }
+
+        async fn has_non_fatal_errors(
+            &self,
+            _block: Option<BlockNumber>,
+        ) -> Result<bool, StoreError> {
+            unimplemented!()
+        }
+
+        async fn deployment_state(&self) -> Result<DeploymentState, QueryExecutionError> {
+            let chain = self.chain.lock().unwrap();
+            Ok(DeploymentState {
+                id: DeploymentHash::new("fake").unwrap(),
+                reorg_count: chain.reorg_count,
+                max_reorg_depth: 0,
+                latest_ethereum_block_number: chain.blocks.len() as BlockNumber - 1,
+            })
+        }
+
+        fn api_schema(&self) -> Result<Arc<ApiSchema>, QueryExecutionError> {
+            unimplemented!()
+        }
+
+        fn input_schema(&self) -> Result<Arc<Schema>, QueryExecutionError> {
+            unimplemented!()
+        }
+
+        fn network_name(&self) -> &str {
+            "fake"
+        }
+
+        async fn query_permit(&self) -> Result<QueryPermit, QueryExecutionError> {
+            Ok(self
+                .permits
+                .cheap_clone()
+                .acquire_owned()
+                .await
+                .unwrap()
+                .into())
+        }
+    }
+
+    /// Run `send_changes` once and return the type and block of each
+    /// message it sent
+    fn poll(store: &Arc<FakeStore>, cursor: &mut Option<Cursor>) -> Vec<(String, BlockNumber)> {
+        let store: Arc<dyn QueryStore + Send + Sync> = store.clone();
+        let (sender, mut receiver) = mpsc::unbounded();
+        let mut sink = sender.sink_map_err(|_| WsError::ConnectionClosed);
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        if runtime
+            .block_on(send_changes(&store, &mut sink, cursor))
+            .is_err()
+        {
+            panic!("sending changes failed");
+        }
+        drop(sink);
+
+        let mut messages = Vec::new();
+        while let Ok(Some(WsMessage::Text(text))) = receiver.try_next() {
+            let message: serde_json::Value = serde_json::from_str(&text).unwrap();
+            let cursor: Cursor = message["cursor"].as_str().unwrap().parse().unwrap();
+            let block = message["block"].as_i64().unwrap() as BlockNumber;
+            assert_eq!(block, cursor.block);
+            messages.push((message["type"].as_str().unwrap().to_owned(), block));
+        }
+        messages
+    }
+
+    fn msg(kind: &str, block: BlockNumber) -> (String, BlockNumber) {
+        (kind.to_owned(), block)
+    }
+
+    #[test]
+    fn parses_cursors() {
+        let cursor = Cursor {
+            block: -1,
+            hash: None,
+            reorg_count: 3,
+        };
+        assert_eq!("-1:3:", cursor.to_string());
+        assert_eq!(cursor, "-1:3:".parse().unwrap());
+
+        let cursor = Cursor {
+            block: 1234,
+            hash: Some(hash(1234, 7)),
+            reorg_count: 0,
+        };
+        assert_eq!(cursor, cursor.to_string().parse().unwrap());
+        assert!("1234-0".parse::<Cursor>().is_err());
+        assert!("1234:0:abcd".parse::<Cursor>().is_err());
+    }
+
+    #[test]
+    fn streams_and_resumes() {
+        let store = FakeStore::new();
+        store.extend(0, 3, &[1, 3]);
+
+        let mut cursor = None;
+        assert_eq!(
+            vec![msg("block", 1), msg("block", 3), msg("progress", 3)],
+            poll(&store, &mut cursor)
+        );
+        assert_eq!(Some(hash(3, 0)), cursor.unwrap().hash);
+        assert!(poll(&store, &mut cursor).is_empty());
+
+        store.extend(0, 5, &[4]);
+        assert_eq!(
+            vec![msg("block", 4), msg("progress", 5)],
+            poll(&store, &mut cursor)
+        );
+
+        // A client that reconnects with the cursor of block 1 gets the
+        // rest of the changes
+        let mut resumed = Some(Cursor {
+            block: 1,
+            hash: Some(hash(1, 0)),
+            reorg_count: 0,
+        });
+        assert_eq!(
+            vec![msg("block", 3), msg("block", 4), msg("progress", 5)],
+            poll(&store, &mut resumed)
+        );
+    }
+
+    #[test]
+    fn reverts_to_common_ancestor() {
+        let store = FakeStore::new();
+        store.extend(0, 4, &[1, 3, 4]);
+        let mut cursor = None;
+        poll(&store, &mut cursor);
+        let before_revert = Cursor {
+            block: 1,
+            hash: Some(hash(1, 0)),
+            reorg_count: 0,
+        };
+
+        // Blocks 3 and 4 are replaced with blocks from another fork
+        store.revert_to(2);
+        store.extend(1, 4, &[4]);
+        assert_eq!(
+            vec![msg("revert", 2), msg("block", 4), msg("progress", 4)],
+            poll(&store, &mut cursor)
+        );
+        assert_eq!(Some(hash(4, 1)), cursor.unwrap().hash);
+
+        // Blocks before the fork are still good even though the
+        // deployment reverted blocks since the cursor was handed out
+        let mut resumed = Some(before_revert);
+        assert_eq!(
+            vec![msg("block", 4), msg("progress", 4)],
+            poll(&store, &mut resumed)
+        );
+
+        // A cursor from a fork that is gone is reverted to where the forks
+        // meet, even when the deployment's reorg count does not tell
+        let mut stale = Some(Cursor {
+            block: 3,
+            hash: Some(hash(3, 0)),
+            reorg_count: 2,
+        });
+        assert_eq!(
+            vec![msg("revert", 2), msg("block", 4), msg("progress", 4)],
+            poll(&store, &mut stale)
+        );
+    }
+}
@@ -1,17 +1,50 @@
 use graph::{
+    components::server::{
+        http,
+        tls::{self, MaybeTlsStream, Protocol},
+    },
     data::query::QueryTarget,
     prelude::{SubscriptionServer as SubscriptionServerTrait, *},
 };
 use http::header::{ACCESS_CONTROL_ALLOW_ORIGIN, CONTENT_TYPE};
 use http::{HeaderValue, Response, StatusCode};
 use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::str::FromStr;
 use std::sync::Mutex;
 use tokio::net::TcpListener;
 use tokio_tungstenite::accept_hdr_async;
 use tokio_tungstenite::tungstenite::handshake::server::Request;
 
+use crate::changes::{Cursor, EntityChangeStream};
 use crate::connection::GraphQlConnection;
 
+/// What a client asked for when it connected
+enum Endpoint {
+    /// GraphQL subscriptions over the `graphql-ws` protocol
+    GraphQl(DeploymentHash),
+    /// The stream of entity changes, resuming after the cursor if there is
+    /// one
+    Changes(DeploymentHash, Option<Cursor>),
+}
+
+/// Whether `path` asks for the stream of entity changes of a deployment
+fn is_change_stream(path: &str) -> bool {
+    matches!(
+        path.split('/').collect::<Vec<_>>().as_slice(),
+        &["", "subgraphs", "id", _, "changes"]
+    )
+}
+
+/// The `cursor` parameter from the query string of a request
+fn cursor_param(query: Option<&str>) -> Result<Option<Cursor>, anyhow::Error> {
+    query
+        .into_iter()
+        .flat_map(|query| query.split('&'))
+        .find_map(|param| param.strip_prefix("cursor="))
+        .map(Cursor::from_str)
+        .transpose()
+}
+
 /// A GraphQL subscription server based on Hyper / Websockets.
 pub struct SubscriptionServer<Q, S> {
     logger: Logger,
@@ -74,6 +107,11 @@ where
             &["subgraphs", "id", subgraph_id] => {
                 Ok(state(store, target_from_id(subgraph_id)).await)
             }
+            &["subgraphs", "id", subgraph_id, "changes"]
+                if ENV_VARS.graphql.enable_change_stream =>
+            {
+                Ok(state(store, target_from_id(subgraph_id)).await)
+            }
             &["subgraphs", "name", _] | &["subgraphs", "name", _, _] => {
                 Ok(state(store, target_from_name(path_segments[2..].join("/"))).await)
             }
@@ -94,7 +132,9 @@ where
     async fn serve(self, port: u16) {
         info!(
             self.logger,
-            "Starting GraphQL WebSocket server at: ws://localhost:{}", port
+            "Starting GraphQL WebSocket server at: {}://localhost:{}",
+            tls::scheme("ws"),
+            port
         );
 
         let addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0)), port);
@@ -102,22 +142,33 @@ where
             .await
             .expect("Failed to bind WebSocket port");
 
+        // Once the node shuts down, we stop accepting connections and close
+        // the open ones; subscriptions never end by themselves, and clients
+        // reconnect to another node
+        let shutdown = http::shutdown_signal();
+        tokio::pin!(shutdown);
         loop {
-            let stream = match socket.accept().await {
-                Ok((stream, _)) => stream,
+            let accepted = tokio::select! {
+                _ = &mut shutdown => break,
+                accepted = socket.accept() => accepted,
+            };
+            let stream = match accepted {
+                Ok((stream, _)) => MaybeTlsStream::new(stream, Protocol::WebSocket),
                 Err(e) => {
                     trace!(self.logger, "Connection error: {}", e);
                     continue;
                 }
             };
+            let open = http::open_connection();
             let logger = self.logger.clone();
             let logger2 = self.logger.clone();
             let graphql_runner = self.graphql_runner.clone();
             let store = self.store.clone();
 
             // Subgraph that the request is resolved to (if any)
-            let subgraph_id = Arc::new(Mutex::new(None));
-            let accept_subgraph_id = subgraph_id.clone();
+            let endpoint = Arc::new(Mutex::new(None));
+            let accept_endpoint = endpoint.clone();
+            let change_store = self.store.clone();
 
             accept_hdr_async(stream, move |request: &Request, mut response: Response<()>| {
                 // Try to obtain the subgraph ID or name from the URL path.
@@ -171,7 +222,20 @@ where
                             .unwrap());
                     }
 
-                *accept_subgraph_id.lock().unwrap() = Some(state.id);
+                if is_change_stream(path) {
+                    let cursor = cursor_param(request.uri().query()).map_err(|e| {
+                        Response::builder()
+                            .status(StatusCode::BAD_REQUEST)
+                            .header(ACCESS_CONTROL_ALLOW_ORIGIN, "*")
+                            .header(CONTENT_TYPE, "text/plain")
+                            .body(Some(e.to_string()))
+                            .unwrap()
+                    })?;
+                    *accept_endpoint.lock().unwrap() = Some(Endpoint::Changes(state.id, cursor));
+                    return Ok(response);
+                }
+
+                *accept_endpoint.lock().unwrap() = Some(Endpoint::GraphQl(state.id));
                 response.headers_mut().insert(
                     "Sec-WebSocket-Protocol",
                     HeaderValue::from_static("graphql-ws"),
@@ -182,17 +246,47 @@ where
                 match result {
                     Ok(ws_stream) => {
                         // Obtain the subgraph ID or name that we resolved the request to
-                        let subgraph_id = subgraph_id.lock().unwrap().clone().unwrap();
-
-                        // Spawn a GraphQL over WebSocket connection
-                        let service = GraphQlConnection::new(
-                            &logger2,
-                            subgraph_id,
-                            ws_stream,
-                            graphql_runner.clone(),
-                        );
+                        match endpoint.lock().unwrap().take().unwrap() {
+                            Endpoint::GraphQl(subgraph_id) => {
+                                // Spawn a GraphQL over WebSocket connection
+                                let service = GraphQlConnection::new(
+                                    &logger2,
+                                    subgraph_id,
+                                    ws_stream,
+                                    graphql_runner.clone(),
+                                );
 
-                        graph::spawn_allow_panic(service.into_future().compat());
+                                graph::spawn_allow_panic(async move {
+                                    let _open = open;
+                                    tokio::select! {
+                                        _ = service.into_future().compat() => {}
+                                        _ = http::shutdown_signal() => {}
+                                    }
+                                });
+                            }
+                            Endpoint::Changes(subgraph_id, cursor) => {
+                                graph::spawn_allow_panic(async move {
+                                    let _open = open;
+                                    // The stream reads the deployment's state from
+                                    // the primary, and the changes have to come
+                                    // from there, too, so they don't lag behind it
+                                    let target = QueryTarget::Deployment(subgraph_id);
+                                    match change_store.query_store(target, true).await {
+                                        Ok(store) => {
+                                            let stream = EntityChangeStream::new(&logger2, store, ws_stream, cursor);
+                                            tokio::select! {
+                                                _ = stream.run() => {}
+                                                _ = http::shutdown_signal() => {}
+                                            }
+                                        }
+                                        Err(e) => {
+                                            error!(logger2, "Failed to start entity change stream";
+                                                "error" => e.to_string())
+                                        }
+                                    }
+                                });
+                            }
+                        }
                     }
                     Err(e) => {
                         // We gracefully skip over failed connection attempts rather
@@ -202,5 +296,9 @@ where
                 }
             }).await
         }
+        info!(
+            self.logger,
+            "GraphQL WebSocket server stopped accepting connections"
+        );
     }
 }
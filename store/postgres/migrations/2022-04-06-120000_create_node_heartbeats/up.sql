@@ -0,0 +1,9 @@
+-- Every node regularly records that it is still alive here. Index nodes
+-- whose heartbeat is too old can have their deployments reassigned to
+-- other index nodes
+create table public.node_heartbeats (
+	node_id      text primary key,
+	role         text not null,
+	started_at   timestamptz not null default now(),
+	heartbeat_at timestamptz not null default now()
+);
@@ -0,0 +1,9 @@
+-- Recent load of each deployment as measured by the index node that
+-- indexes it. Used to even out assignments across index nodes
+create table public.deployment_load (
+	deployment           text primary key,
+	node_id              text not null,
+	blocks_per_minute    float8 not null,
+	handler_secs_per_min float8 not null,
+	updated_at           timestamptz not null default now()
+);
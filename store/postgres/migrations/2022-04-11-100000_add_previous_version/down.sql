@@ -0,0 +1,3 @@
+alter table subgraphs.subgraph
+  drop column retain_previous_version,
+  drop column previous_version;
@@ -0,0 +1,5 @@
+-- Subgraph names can be configured to keep indexing the version that was
+-- current before the latest version replaced it
+alter table subgraphs.subgraph
+  add column retain_previous_version bool not null default false,
+  add column previous_version text;
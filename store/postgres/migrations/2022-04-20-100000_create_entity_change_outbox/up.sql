@@ -0,0 +1,17 @@
+-- Entity changes that still need to be delivered to external systems
+-- like Kafka. Rows are written in the same transaction as the changes
+-- themselves and removed once they have been delivered. While a node
+-- delivers entries, they are claimed until `claimed_until`
+create table subgraphs.entity_change_outbox (
+	id            bigserial primary key,
+	deployment    int4 not null,
+	block_number  int4 not null,
+	entity_type   text not null,
+	entity_id     text not null,
+	operation     text not null,
+	data          jsonb,
+	claimed_until timestamptz
+);
+create index entity_change_outbox_claimed
+    on subgraphs.entity_change_outbox(claimed_until)
+ where claimed_until is not null;
@@ -0,0 +1,8 @@
+-- When each deployment was last queried, as reported by the nodes that
+-- answered queries for it. Deployments that were queried recently are
+-- not considered unused
+create table public.deployment_query_activity (
+	id              integer primary key
+	                references public.deployment_schemas(id) on delete cascade,
+	last_queried_at timestamptz not null
+);
@@ -0,0 +1 @@
+alter table subgraphs.subgraph_deployment drop column entity_size;
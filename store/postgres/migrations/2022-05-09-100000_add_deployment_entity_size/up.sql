@@ -0,0 +1,6 @@
+-- An estimate of how many bytes the deployment's tables take up on disk.
+-- The value -1 means that the size has not been determined yet; it is
+-- computed from the size of the tables the next time the deployment
+-- writes a block
+alter table subgraphs.subgraph_deployment
+  add column entity_size numeric not null default -1;
@@ -0,0 +1,4 @@
+-- How often the deployment was restarted automatically after it failed
+-- with a non-deterministic error
+alter table subgraphs.subgraph_deployment
+  add column restart_count int4 not null default 0;
@@ -0,0 +1,3 @@
+alter table subgraphs.subgraph_deployment
+  drop column writer,
+  drop column writer_node;
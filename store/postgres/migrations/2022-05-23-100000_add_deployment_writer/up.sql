@@ -0,0 +1,9 @@
+-- A token that identifies the writable store that may write to the
+-- deployment, and the node it runs on. Every time a node starts indexing
+-- the deployment, it claims it with a new token unless a writer on the
+-- node the deployment is assigned to holds it already; a node that finds a
+-- token that is not its own when it writes knows that another node took
+-- the deployment over and stops
+alter table subgraphs.subgraph_deployment
+  add column writer text,
+  add column writer_node text;
@@ -0,0 +1 @@
+drop table subgraphs.change_journal;
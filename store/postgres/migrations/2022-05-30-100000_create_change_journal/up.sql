@@ -0,0 +1,10 @@
+-- The deployments for which index nodes write change journals, the last
+-- block that the journal of each of them has been written up to, and the
+-- node that writes it. That node does not change when the deployment is
+-- reassigned so that the journal stays in one place
+create table subgraphs.change_journal (
+  id           int4 primary key
+               references subgraphs.subgraph_deployment(id) on delete cascade,
+  journaled_to int4 not null,
+  node_id      text not null
+);
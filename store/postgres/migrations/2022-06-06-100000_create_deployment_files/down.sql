@@ -0,0 +1 @@
+drop table public.deployment_files;
@@ -0,0 +1,11 @@
+-- The files that make up deployments, i.e., their manifests, schemas, ABIs
+-- and WASM modules, by the IPFS hash they were linked with, so that nodes
+-- can start deployments without fetching them from IPFS. The `digest` is
+-- the blake3 hash of the `content` and is checked whenever a file is read
+create table public.deployment_files (
+  link       text primary key,
+  digest     bytea not null,
+  size       int4 not null,
+  content    bytea not null,
+  stored_at  timestamptz not null default now()
+);
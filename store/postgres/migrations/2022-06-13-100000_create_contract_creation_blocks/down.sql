@@ -0,0 +1 @@
+drop table public.contract_creation_blocks;
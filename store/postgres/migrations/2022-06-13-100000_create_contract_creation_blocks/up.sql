@@ -0,0 +1,10 @@
+-- The block in which the contract at `address` on `network` was created.
+-- It is found by searching the chain when a data source does not set a
+-- `startBlock`, and remembered here so that the search only happens once
+-- for each contract
+create table public.contract_creation_blocks (
+  network       text not null,
+  address       bytea not null,
+  block_number  int4 not null,
+  primary key (network, address)
+);
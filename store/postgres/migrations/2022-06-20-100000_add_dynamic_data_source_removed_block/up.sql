@@ -0,0 +1,4 @@
+-- The block in which a mapping removed the data source or in which it
+-- expired. The data source is not used for blocks after that
+alter table subgraphs.dynamic_ethereum_contract_data_source
+  add column removed_block int4;
@@ -0,0 +1,13 @@
+do $$
+declare
+  nsp text;
+begin
+  for nsp in
+    select namespace from public.ethereum_networks where namespace != 'public'
+  loop
+    execute format('drop table if exists %I.block_times', nsp);
+  end loop;
+end;
+$$;
+
+drop table public.ethereum_block_times;
@@ -0,0 +1,41 @@
+-- An index from block numbers and hashes to block timestamps for each
+-- chain. Unlike the block cache, it is not purged when old blocks are
+-- removed from the cache. The ingestor adds new blocks as it stores them;
+-- blocks that are already cached are added by a background job so that
+-- this migration does not have to unpack the JSON of every cached block
+create table public.ethereum_block_times (
+  network_name varchar not null,
+  hash         bytea   not null,
+  number       int8    not null,
+  parent_hash  bytea   not null,
+  timestamp    int8    not null,
+  primary key (network_name, hash)
+);
+create index ethereum_block_times_number
+    on public.ethereum_block_times
+ using btree(network_name, number);
+create index ethereum_block_times_timestamp
+    on public.ethereum_block_times
+ using btree(network_name, timestamp, number);
+
+do $$
+declare
+  nsp text;
+begin
+  for nsp in
+    select namespace from public.ethereum_networks where namespace != 'public'
+  loop
+    execute format('
+      create table %1$I.block_times (
+        hash         bytea not null primary key,
+        number       int8  not null,
+        parent_hash  bytea not null,
+        timestamp    int8  not null
+      );
+      create index block_times_number
+          on %1$I.block_times using btree(number);
+      create index block_times_timestamp
+          on %1$I.block_times using btree(timestamp, number)', nsp);
+  end loop;
+end;
+$$;
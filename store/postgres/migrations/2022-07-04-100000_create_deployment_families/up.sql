@@ -0,0 +1,12 @@
+-- Deployments that come from the same multi-network manifest, keyed by
+-- the deployment hash of each network's manifest. The `family` is the
+-- deployment hash of the manifest that lists all networks
+create table public.deployment_families (
+  deployment  text primary key,
+  family      text not null,
+  network     text not null,
+  created_at  timestamptz not null default now()
+);
+
+create index deployment_families_family
+    on public.deployment_families(family);
@@ -0,0 +1,3 @@
+drop table subgraphs.pruned_history;
+drop table subgraphs.entity_checkpoint_data;
+drop table subgraphs.entity_checkpoint;
@@ -0,0 +1,32 @@
+-- Checkpoints of the entities of deployments: at the `block_number` of a
+-- checkpoint, `entity_checkpoint_data` has one row for each entity version
+-- that was current then, with the name of its table and the row as JSONB
+create table subgraphs.entity_checkpoint (
+  deployment   int4 not null
+               references subgraphs.subgraph_deployment(id) on delete cascade,
+  block_number int4 not null,
+  block_hash   bytea not null,
+  created_at   timestamptz not null default now(),
+  primary key (deployment, block_number)
+);
+
+create table subgraphs.entity_checkpoint_data (
+  deployment   int4 not null,
+  block_number int4 not null,
+  entity_table text not null,
+  data         jsonb not null,
+  foreign key (deployment, block_number)
+    references subgraphs.entity_checkpoint(deployment, block_number)
+    on delete cascade
+);
+
+create index entity_checkpoint_data_checkpoint
+    on subgraphs.entity_checkpoint_data(deployment, block_number, entity_table);
+
+-- The block before which the history of a deployment's entities has been
+-- pruned; rewinding to an earlier block needs a checkpoint
+create table subgraphs.pruned_history (
+  id            int4 primary key
+                references subgraphs.subgraph_deployment(id) on delete cascade,
+  pruned_before int4 not null
+);
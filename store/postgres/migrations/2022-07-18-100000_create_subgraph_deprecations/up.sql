@@ -0,0 +1,8 @@
+-- Deprecation notices for subgraph names, which are reported in the
+-- responses to queries through the name
+create table public.subgraph_deprecations (
+  name          text primary key,
+  message       text not null,
+  removal_date  date not null,
+  created_at    timestamptz not null default now()
+);
@@ -0,0 +1,3 @@
+alter table subgraphs.subgraph_manifest
+  drop column schema_hash,
+  drop column schema_compatibility;
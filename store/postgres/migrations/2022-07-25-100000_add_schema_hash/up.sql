@@ -0,0 +1,6 @@
+-- The canonical hash of the schema of a deployment, and how the schema
+-- compares to the schema of the current version of the subgraph the
+-- deployment was created for
+alter table subgraphs.subgraph_manifest
+  add column schema_hash text,
+  add column schema_compatibility text;
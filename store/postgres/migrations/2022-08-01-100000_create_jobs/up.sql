@@ -0,0 +1,25 @@
+-- Long-running operations on deployments that index nodes run in the
+-- background. `progress` is whatever the job needs to pick up where it
+-- left off when the node running it restarts
+create table public.jobs (
+  id                   serial primary key,
+  deployment           text not null,
+  deployment_id        int not null,
+  kind                 text not null,
+  request              jsonb not null,
+  state                text not null default 'queued',
+  node_id              text,
+  progress             jsonb,
+  done                 bigint not null default 0,
+  total                bigint,
+  outcome              text,
+  error                text,
+  cancel_requested_at  timestamptz,
+  created_at           timestamptz not null default now(),
+  started_at           timestamptz,
+  finished_at          timestamptz
+);
+
+create index jobs_deployment on public.jobs(deployment);
+create index jobs_unfinished on public.jobs(state)
+  where state in ('queued', 'running');
@@ -0,0 +1 @@
+drop table public.admin_audit_log;
@@ -0,0 +1,15 @@
+-- Every call to the admin JSON-RPC server, with the name and role of the
+-- token it was made with, if any
+create table public.admin_audit_log (
+  id          serial primary key,
+  method      text not null,
+  token_name  text,
+  role        text,
+  params      jsonb not null,
+  -- One of 'denied', 'succeeded' or 'failed'
+  outcome     text not null,
+  error       text,
+  created_at  timestamptz not null default now()
+);
+
+create index admin_audit_log_created_at on public.admin_audit_log(created_at);
@@ -0,0 +1,2 @@
+delete from subgraphs.entity_checkpoint where not complete;
+alter table subgraphs.entity_checkpoint drop column complete;
@@ -0,0 +1,6 @@
+-- Checkpoints are recorded when a block is written, but their entities are
+-- copied afterwards; only complete checkpoints can be restored
+alter table subgraphs.entity_checkpoint
+  add column complete bool not null default true;
+alter table subgraphs.entity_checkpoint
+  alter column complete set default false;
@@ -11,8 +11,21 @@
 //! We use the following 2x 32-bit locks
 //!   * 1, n: to lock copying of the deployment with id n in the destination
 //!           shard
+//!   * 2, 0: to make sure only one node at a time reassigns deployments
+//!           away from unresponsive index nodes
+//!   * 2, 1: to make sure only one rebalancing of assignments runs at a
+//!           time
+//!   * 2, 2: to make sure only one node at a time delivers the entity
+//!           changes from the outbox of a shard, so that they are
+//!           delivered in order
+//!   * 2, 3: held by the node that runs routine maintenance in a shard
+//!           while it does that, so that nodes do not rebuild the same
+//!           indexes at the same time
+//!   * 4, n: held for the lifetime of a connection by the node that
+//!           ingests blocks for the chain with id n, so that only one node
+//!           in the cluster does that
 
-use diesel::{sql_query, PgConnection, RunQueryDsl};
+use diesel::{dsl::sql, select, sql_query, sql_types::Bool, PgConnection, RunQueryDsl};
 use graph::prelude::StoreError;
 
 use crate::command_support::catalog::Site;
@@ -44,3 +57,75 @@ pub(crate) fn unlock_copying(conn: &PgConnection, dst: &Site) -> Result<(), Stor
         .map(|_| ())
         .map_err(StoreError::from)
 }
+
+/// Try to get the lock for reassigning deployments; the lock is held until
+/// the end of the current transaction. Return `false` if another
+/// connection holds the lock already
+pub(crate) fn try_lock_reassignment(conn: &PgConnection) -> Result<bool, StoreError> {
+    select(sql::<Bool>("pg_try_advisory_xact_lock(2, 0)"))
+        .get_result::<bool>(conn)
+        .map_err(StoreError::from)
+}
+
+/// Try to get the lock for rebalancing assignments; the lock is held until
+/// the end of the current transaction
+pub(crate) fn try_lock_rebalance(conn: &PgConnection) -> Result<bool, StoreError> {
+    select(sql::<Bool>("pg_try_advisory_xact_lock(2, 1)"))
+        .get_result::<bool>(conn)
+        .map_err(StoreError::from)
+}
+
+/// Try to get the lock for delivering entity changes from the outbox of
+/// the shard that `conn` is connected to; the lock is held until the end
+/// of the current transaction
+pub(crate) fn try_lock_outbox(conn: &PgConnection) -> Result<bool, StoreError> {
+    select(sql::<Bool>("pg_try_advisory_xact_lock(2, 2)"))
+        .get_result::<bool>(conn)
+        .map_err(StoreError::from)
+}
+
+/// Try to get the lock for running maintenance in the shard that `conn` is
+/// connected to. The lock is held until it is released with
+/// `unlock_maintenance` or until `conn` is closed. Return `false` if
+/// another connection holds the lock already
+pub(crate) fn try_lock_maintenance(conn: &PgConnection) -> Result<bool, StoreError> {
+    select(sql::<Bool>("pg_try_advisory_lock(2, 3)"))
+        .get_result::<bool>(conn)
+        .map_err(StoreError::from)
+}
+
+pub(crate) fn unlock_maintenance(conn: &PgConnection) -> Result<(), StoreError> {
+    sql_query("select pg_advisory_unlock(2, 3)")
+        .execute(conn)
+        .map(|_| ())
+        .map_err(StoreError::from)
+}
+
+/// Try to become the block ingestor for the chain with id `chain`. The lock
+/// is held until it is released with `unlock_ingestor` or until `conn` is
+/// closed. Return `false` if another connection holds the lock already
+pub(crate) fn try_lock_ingestor(conn: &PgConnection, chain: i32) -> Result<bool, StoreError> {
+    select(sql::<Bool>(&format!("pg_try_advisory_lock(4, {})", chain)))
+        .get_result::<bool>(conn)
+        .map_err(StoreError::from)
+}
+
+/// Whether `conn` still holds the ingestor lock for the chain with id
+/// `chain`
+pub(crate) fn holds_ingestor_lock(conn: &PgConnection, chain: i32) -> Result<bool, StoreError> {
+    select(sql::<Bool>(&format!(
+        "exists (select 1 from pg_locks \
+                  where locktype = 'advisory' and classid = 4 and objid = {} \
+                    and objsubid = 2 and pid = pg_backend_pid() and granted)",
+        chain
+    )))
+    .get_result::<bool>(conn)
+    .map_err(StoreError::from)
+}
+
+pub(crate) fn unlock_ingestor(conn: &PgConnection, chain: i32) -> Result<(), StoreError> {
+    sql_query(&format!("select pg_advisory_unlock(4, {})", chain))
+        .execute(conn)
+        .map(|_| ())
+        .map_err(StoreError::from)
+}
@@ -174,11 +174,15 @@ impl<'a> BlockRangeColumn<'a> {
                 self.name(out);
                 out.push_sql(" @> ");
                 out.push_bind_param::<Integer, _>(block)?;
-                if table.is_account_like && *block < BLOCK_NUMBER_MAX {
+                if (table.is_account_like || table.partitioned) && *block < BLOCK_NUMBER_MAX {
                     // When block is BLOCK_NUMBER_MAX, these checks would be wrong; we
                     // don't worry about adding the equivalent in that case since
                     // we generally only see BLOCK_NUMBER_MAX here for metadata
-                    // queries where block ranges don't matter anyway
+                    // queries where block ranges don't matter anyway.
+                    //
+                    // For partitioned tables, the check on the upper bound is
+                    // what lets Postgres skip the partitions of versions that
+                    // were closed before `block`
                     out.push_sql(" and coalesce(upper(");
                     out.push_identifier(BLOCK_RANGE_COLUMN)?;
                     out.push_sql("), 2147483647) > ");
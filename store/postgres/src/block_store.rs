@@ -1,13 +1,14 @@
 use std::{
     collections::{HashMap, HashSet},
     iter::FromIterator,
-    sync::{Arc, RwLock},
+    sync::{Arc, Mutex, RwLock},
     time::Duration,
 };
 
 use graph::{
     blockchain::ChainIdentifier,
     components::store::BlockStore as BlockStoreTrait,
+    data::subgraph::status,
     prelude::{error, warn, BlockNumber, BlockPtr, Logger},
 };
 use graph::{
@@ -19,14 +20,90 @@ use graph::{
     util::timed_cache::TimedCache,
 };
 
+use diesel::PgConnection;
+
 use crate::{
-    chain_head_listener::ChainHeadUpdateSender, connection_pool::ConnectionPool,
+    advisory_lock, chain_head_listener::ChainHeadUpdateSender, connection_pool::ConnectionPool,
     primary::Mirror as PrimaryMirror, ChainStore, NotificationSender, Shard, PRIMARY_SHARD,
 };
 
 #[cfg(debug_assertions)]
 pub const FAKE_NETWORK_SHARED: &str = "fake_network_shared";
 
+/// The connection on which a node holds the locks for the chains it
+/// ingests blocks for. It is shared by all leases and not part of any
+/// connection pool, so that leading ingestion for many chains does not
+/// take connections away from the pool
+#[derive(Default)]
+struct IngestorLocks {
+    conn: Option<PgConnection>,
+    /// The ids of the chains whose lock `conn` holds, and the lease that
+    /// holds it. A connection can take an advisory lock that it already
+    /// holds, so we need to make sure there is only one lease per chain
+    held: HashMap<i32, u64>,
+    last_lease: u64,
+}
+
+impl IngestorLocks {
+    /// Forget the connection after an error; closing it releases all
+    /// locks, and the leases that held them notice on their next check
+    fn reset(&mut self) {
+        self.conn = None;
+        self.held.clear();
+    }
+}
+
+/// The right to ingest blocks for a chain, which only one node in the
+/// cluster holds at a time. See `BlockStore::try_lease_ingestor`
+pub struct IngestorLease {
+    chain: String,
+    id: i32,
+    lease: u64,
+    locks: Arc<Mutex<IngestorLocks>>,
+}
+
+impl IngestorLease {
+    pub fn chain(&self) -> &str {
+        &self.chain
+    }
+
+    /// Check that the lease is still held. Once this returns `false` or an
+    /// error, other nodes may have started ingesting blocks for the chain,
+    /// and the holder needs to stop doing that
+    pub fn check(&self) -> Result<bool, StoreError> {
+        let mut locks = self.locks.lock().unwrap();
+        if locks.held.get(&self.id) != Some(&self.lease) {
+            return Ok(false);
+        }
+        let held = match &locks.conn {
+            Some(conn) => advisory_lock::holds_ingestor_lock(conn, self.id),
+            None => return Ok(false),
+        };
+        if held.is_err() {
+            locks.reset();
+        }
+        held
+    }
+}
+
+impl Drop for IngestorLease {
+    fn drop(&mut self) {
+        let mut locks = self.locks.lock().unwrap();
+        if locks.held.get(&self.id) != Some(&self.lease) {
+            return;
+        }
+        locks.held.remove(&self.id);
+        let unlocked = match &locks.conn {
+            Some(conn) => advisory_lock::unlock_ingestor(conn, self.id),
+            None => Ok(()),
+        };
+        if unlocked.is_err() {
+            // The connection is broken, and the locks are gone with it
+            locks.reset();
+        }
+    }
+}
+
 /// The status of a chain: whether we can only read from the chain, or
 /// whether it is ok to ingest from it, too
 #[derive(Copy, Clone)]
@@ -183,6 +260,7 @@ pub struct BlockStore {
     sender: Arc<NotificationSender>,
     mirror: PrimaryMirror,
     chain_head_cache: TimedCache<String, HashMap<String, BlockPtr>>,
+    ingestor_locks: Arc<Mutex<IngestorLocks>>,
 }
 
 impl BlockStore {
@@ -220,6 +298,7 @@ impl BlockStore {
             sender,
             mirror,
             chain_head_cache,
+            ingestor_locks: Arc::new(Mutex::new(IngestorLocks::default())),
         };
 
         fn reduce_idents(
@@ -408,6 +487,33 @@ impl BlockStore {
         Ok(map)
     }
 
+    /// The stores of all chains that this store knows about
+    pub(crate) fn chain_stores(&self) -> Vec<Arc<ChainStore>> {
+        self.stores.read().unwrap().values().cloned().collect()
+    }
+
+    /// The head and earliest cached block of every chain. Chains whose
+    /// shard can not be reached are left out
+    pub fn chain_statuses(&self) -> Result<Vec<status::ChainStatus>, StoreError> {
+        let heads = self.chain_head_pointers()?;
+        let mut statuses = Vec::new();
+        for store in self.chain_stores() {
+            let earliest_block = match store.earliest_block() {
+                Ok(block) => block,
+                Err(StoreError::DatabaseUnavailable) => continue,
+                Err(e) => return Err(e),
+            };
+            statuses.push(status::ChainStatus {
+                network: store.chain.clone(),
+                chain_head_block: heads.get(&store.chain).cloned().map(Into::into),
+                earliest_block: earliest_block.map(Into::into),
+                providers: vec![],
+            });
+        }
+        statuses.sort_by(|a, b| a.network.cmp(&b.network));
+        Ok(statuses)
+    }
+
     pub fn chain_head_block(&self, chain: &str) -> Result<Option<BlockNumber>, StoreError> {
         let store = self
             .store(chain)
@@ -447,6 +553,48 @@ impl BlockStore {
             })
     }
 
+    /// Try to become the one node in the cluster that ingests blocks for
+    /// `chain`. Return `None` if another node, or this node, does that
+    /// already. The node stays the ingestor until the lease is dropped or
+    /// until its connection to the primary breaks, at which point another
+    /// node can take over
+    pub fn try_lease_ingestor(&self, chain: &str) -> Result<Option<IngestorLease>, StoreError> {
+        let id = self
+            .mirror
+            .read(|conn| primary::find_chain(conn, chain))?
+            .ok_or_else(|| constraint_violation!("unknown chain {}", chain))?
+            .id;
+
+        let mut locks = self.ingestor_locks.lock().unwrap();
+        if locks.held.contains_key(&id) {
+            return Ok(None);
+        }
+        let conn = match locks.conn.take() {
+            Some(conn) => conn,
+            None => self.mirror.primary().dedicated_connection()?,
+        };
+        let locked = match advisory_lock::try_lock_ingestor(&conn, id) {
+            Ok(locked) => locked,
+            Err(e) => {
+                locks.reset();
+                return Err(e);
+            }
+        };
+        locks.conn = Some(conn);
+        if !locked {
+            return Ok(None);
+        }
+        locks.last_lease += 1;
+        let lease = locks.last_lease;
+        locks.held.insert(id, lease);
+        Ok(Some(IngestorLease {
+            chain: chain.to_string(),
+            id,
+            lease,
+            locks: self.ingestor_locks.clone(),
+        }))
+    }
+
     pub fn drop_chain(&self, chain: &str) -> Result<(), StoreError> {
         let chain_store = self
             .store(chain)
@@ -1,4 +1,4 @@
-use diesel::sql_types::{Bool, Integer};
+use diesel::sql_types::{BigInt, Bool, Double, Integer};
 use diesel::{connection::SimpleConnection, prelude::RunQueryDsl, select};
 use diesel::{insert_into, OptionalExtension};
 use diesel::{pg::PgConnection, sql_query};
@@ -12,7 +12,10 @@ use std::iter::FromIterator;
 use std::sync::Arc;
 
 use graph::prelude::anyhow::anyhow;
-use graph::{data::subgraph::schema::POI_TABLE, prelude::StoreError};
+use graph::{
+    data::subgraph::schema::POI_TABLE,
+    prelude::{BlockNumber, StoreError},
+};
 
 use crate::connection_pool::ForeignServer;
 use crate::{
@@ -57,6 +60,12 @@ pub struct Catalog {
     /// in their entirety. This influences both DDL generation and how
     /// queries are generated
     pub use_bytea_prefix: bool,
+    /// The tables that are partitioned by the block at which their entity
+    /// versions were closed
+    partitioned: HashSet<String>,
+    /// The block before which the history of the deployment has been
+    /// pruned. Queries for earlier blocks can not be answered
+    pub pruned_before: Option<BlockNumber>,
 }
 
 impl Catalog {
@@ -68,11 +77,15 @@ impl Catalog {
     ) -> Result<Self, StoreError> {
         let text_columns = get_text_columns(conn, &site.namespace)?;
         let use_poi = supports_proof_of_indexing(conn, &site.namespace)?;
+        let partitioned = partitioned_tables(conn, &site.namespace)?;
+        let pruned_before = crate::checkpoint::pruned_before(conn, &site)?;
         Ok(Catalog {
             site,
             text_columns,
             use_poi,
             use_bytea_prefix,
+            partitioned,
+            pruned_before,
         })
     }
 
@@ -85,6 +98,8 @@ impl Catalog {
             use_poi: true,
             // DDL generation creates indexes for prefixes of bytes columns
             use_bytea_prefix: true,
+            partitioned: HashSet::default(),
+            pruned_before: None,
         }
     }
 
@@ -97,6 +112,8 @@ impl Catalog {
             text_columns: HashMap::default(),
             use_poi: false,
             use_bytea_prefix: true,
+            partitioned: HashSet::default(),
+            pruned_before: None,
         })
     }
 
@@ -108,6 +125,36 @@ impl Catalog {
             .map(|cols| cols.contains(column.as_str()))
             .unwrap_or(false)
     }
+
+    /// Return `true` if `table` is partitioned
+    pub fn is_partitioned(&self, table: &SqlName) -> bool {
+        self.partitioned.contains(table.as_str())
+    }
+}
+
+fn partitioned_tables(
+    conn: &PgConnection,
+    namespace: &Namespace,
+) -> Result<HashSet<String>, StoreError> {
+    const QUERY: &str = "
+        select c.relname as name
+          from pg_partitioned_table p
+               join pg_class c on c.oid = p.partrelid
+               join pg_namespace n on n.oid = c.relnamespace
+         where n.nspname = $1";
+
+    #[derive(QueryableByName)]
+    struct Table {
+        #[sql_type = "Text"]
+        name: String,
+    }
+
+    Ok(diesel::sql_query(QUERY)
+        .bind::<Text, _>(namespace.as_str())
+        .load::<Table>(conn)?
+        .into_iter()
+        .map(|table| table.name)
+        .collect())
 }
 
 fn get_text_columns(
@@ -474,6 +521,33 @@ pub(crate) fn indexes_for_table(
 
     Ok(results.into_iter().map(|i| i.def).collect())
 }
+/// The names of the indexes on the table `table_name` in `namespace`
+pub(crate) fn index_names(
+    conn: &PgConnection,
+    namespace: &Namespace,
+    table_name: &SqlName,
+) -> Result<Vec<String>, StoreError> {
+    #[derive(QueryableByName)]
+    struct Index {
+        #[sql_type = "Text"]
+        name: String,
+    }
+
+    let query = "
+        select indexname as name
+          from pg_indexes
+         where schemaname = $1
+           and tablename = $2
+         order by indexname";
+    Ok(sql_query(query)
+        .bind::<Text, _>(namespace.as_str())
+        .bind::<Text, _>(table_name.as_str())
+        .load::<Index>(conn)?
+        .into_iter()
+        .map(|index| index.name)
+        .collect())
+}
+
 pub(crate) fn drop_index(
     conn: &PgConnection,
     schema_name: &str,
@@ -487,3 +561,228 @@ pub(crate) fn drop_index(
         .map_err::<StoreError, _>(Into::into)?;
     Ok(())
 }
+
+/// The entity tables in which more rows changed since they were last
+/// analyzed than `ratio` times the number of their rows, most changed
+/// first. Tables are given as qualified and quoted names
+pub(crate) fn tables_to_analyze(
+    conn: &PgConnection,
+    ratio: f64,
+) -> Result<Vec<String>, StoreError> {
+    #[derive(QueryableByName)]
+    struct Table {
+        #[sql_type = "Text"]
+        name: String,
+    }
+
+    // Ignore small tables, which the autovacuum daemon keeps up with
+    let query = "
+        select format('%I.%I', schemaname, relname) as name
+          from pg_stat_user_tables
+         where schemaname like 'sgd%'
+           and n_mod_since_analyze > $1 * greatest(n_live_tup, 10000)
+         order by n_mod_since_analyze desc";
+    Ok(sql_query(query)
+        .bind::<Double, _>(ratio)
+        .load::<Table>(conn)?
+        .into_iter()
+        .map(|table| table.name)
+        .collect())
+}
+
+/// The btree indexes on entity tables with more than `min_pages` pages
+/// that are more than `bloat` times as large as their estimated size,
+/// largest first. The estimate assumes that each index entry takes the
+/// average width of the indexed columns and expressions plus 16 bytes of
+/// overhead, and that pages are 90% full. Postgres keeps the statistics
+/// for expressions with the index, and those for plain columns with the
+/// table. Indexes that have not been analyzed are left out since we can
+/// not estimate their size. Indexes are given as qualified and quoted
+/// names
+pub(crate) fn bloated_indexes(
+    conn: &PgConnection,
+    bloat: f64,
+    min_pages: i32,
+) -> Result<Vec<String>, StoreError> {
+    #[derive(QueryableByName)]
+    struct Index {
+        #[sql_type = "Text"]
+        name: String,
+    }
+
+    let query = "
+        select format('%I.%I', n.nspname, c.relname) as name
+          from pg_index i
+               join pg_class c on c.oid = i.indexrelid
+               join pg_class t on t.oid = i.indrelid
+               join pg_namespace n on n.oid = c.relnamespace
+               join pg_am am on am.oid = c.relam
+               cross join lateral (
+                 select sum(s.avg_width) + 16 as width
+                   from pg_attribute a
+                        join pg_stats s on s.schemaname = n.nspname
+                                       and s.attname = a.attname
+                                       and not s.inherited
+                                       and s.tablename =
+                                             case when i.indkey[a.attnum - 1] = 0
+                                                  then c.relname
+                                                  else t.relname end
+                  where a.attrelid = c.oid
+                    and a.attnum > 0
+                 having count(*) = i.indnatts) w
+         where n.nspname like 'sgd%'
+           and am.amname = 'btree'
+           and i.indisvalid
+           and c.relpages > $2
+           and c.relpages > $1 * greatest(1, t.reltuples * w.width / (8192 * 0.9))
+         order by c.relpages desc";
+    Ok(sql_query(query)
+        .bind::<Double, _>(bloat)
+        .bind::<Integer, _>(min_pages)
+        .load::<Index>(conn)?
+        .into_iter()
+        .map(|index| index.name)
+        .collect())
+}
+
+/// The invalid indexes on entity tables that a `reindex concurrently`
+/// that failed left behind, and that no other connection is building
+/// right now. Indexes are given as qualified and quoted names
+pub(crate) fn reindex_leftovers(conn: &PgConnection) -> Result<Vec<String>, StoreError> {
+    #[derive(QueryableByName)]
+    struct Index {
+        #[sql_type = "Text"]
+        name: String,
+    }
+
+    let query = "
+        select format('%I.%I', n.nspname, c.relname) as name
+          from pg_index i
+               join pg_class c on c.oid = i.indexrelid
+               join pg_namespace n on n.oid = c.relnamespace
+         where n.nspname like 'sgd%'
+           and not i.indisvalid
+           and c.relname ~ '_cc(new|old)[0-9]*$'
+           and not exists (select 1
+                             from pg_stat_progress_create_index p
+                            where p.index_relid = c.oid)
+         order by c.relname";
+    Ok(sql_query(query)
+        .load::<Index>(conn)?
+        .into_iter()
+        .map(|index| index.name)
+        .collect())
+}
+
+/// The version of the database server that `conn` is connected to, as a
+/// number like `120005` for version 12.5
+pub(crate) fn server_version(conn: &PgConnection) -> Result<i32, StoreError> {
+    #[derive(QueryableByName)]
+    struct Version {
+        #[sql_type = "Integer"]
+        version: i32,
+    }
+
+    Ok(
+        sql_query("select current_setting('server_version_num')::int as version")
+            .get_result::<Version>(conn)?
+            .version,
+    )
+}
+
+/// The tables in the `subgraphs` schema that hold the metadata of the
+/// deployments in a shard, as qualified and quoted names
+pub(crate) fn metadata_tables(conn: &PgConnection) -> Result<Vec<String>, StoreError> {
+    #[derive(QueryableByName)]
+    struct Table {
+        #[sql_type = "Text"]
+        name: String,
+    }
+
+    let query = "
+        select format('%I.%I', schemaname, tablename) as name
+          from pg_tables
+         where schemaname = 'subgraphs'
+         order by tablename";
+    Ok(sql_query(query)
+        .load::<Table>(conn)?
+        .into_iter()
+        .map(|table| table.name)
+        .collect())
+}
+
+/// The partitions of the table `table_name` in `namespace`
+pub(crate) fn partitions(
+    conn: &PgConnection,
+    namespace: &Namespace,
+    table_name: &SqlName,
+) -> Result<Vec<String>, StoreError> {
+    #[derive(QueryableByName)]
+    struct Partition {
+        #[sql_type = "Text"]
+        name: String,
+    }
+
+    let query = "
+        select c.relname as name
+          from pg_inherits i
+               join pg_class c on c.oid = i.inhrelid
+               join pg_class p on p.oid = i.inhparent
+               join pg_namespace n on n.oid = p.relnamespace
+         where n.nspname = $1
+           and p.relname = $2
+         order by c.relname";
+    Ok(sql_query(query)
+        .bind::<Text, _>(namespace.as_str())
+        .bind::<Text, _>(table_name.as_str())
+        .load::<Partition>(conn)?
+        .into_iter()
+        .map(|partition| partition.name)
+        .collect())
+}
+
+/// The estimated number of rows in each table in `namespace` that is not
+/// partitioned
+pub(crate) fn row_estimates(
+    conn: &PgConnection,
+    namespace: &Namespace,
+) -> Result<HashMap<String, i64>, StoreError> {
+    #[derive(QueryableByName)]
+    struct Estimate {
+        #[sql_type = "Text"]
+        name: String,
+        #[sql_type = "BigInt"]
+        rows: i64,
+    }
+
+    // Partitioned tables and their partitions are left out
+    let query = "
+        select c.relname as name, greatest(c.reltuples, 0)::int8 as rows
+          from pg_class c
+               join pg_namespace n on n.oid = c.relnamespace
+         where n.nspname = $1
+           and c.relkind = 'r'
+           and not c.relispartition";
+    Ok(sql_query(query)
+        .bind::<Text, _>(namespace.as_str())
+        .load::<Estimate>(conn)?
+        .into_iter()
+        .map(|estimate| (estimate.name, estimate.rows))
+        .collect())
+}
+
+/// The name of the sequence that generates the `vid` of the rows of
+/// `table`, which must be qualified and quoted
+pub(crate) fn vid_sequence(conn: &PgConnection, table: &str) -> Result<String, StoreError> {
+    #[derive(QueryableByName)]
+    struct Sequence {
+        #[sql_type = "Text"]
+        name: String,
+    }
+
+    sql_query("select pg_get_serial_sequence($1, 'vid') as name")
+        .bind::<Text, _>(table)
+        .get_result::<Sequence>(conn)
+        .map(|seq| seq.name)
+        .map_err(StoreError::from)
+}
@@ -14,6 +14,7 @@ use std::{
 
 use graph::blockchain::{Block, ChainIdentifier};
 use graph::cheap_clone::CheapClone;
+use graph::components::store::IndexedBlock;
 use graph::prelude::web3::types::H256;
 use graph::prelude::{
     async_trait, ethabi, serde_json as json, transaction_receipt::LightTransactionReceipt,
@@ -41,6 +42,14 @@ mod public {
             head_block_cursor -> Nullable<Varchar>,
         }
     }
+
+    table! {
+        contract_creation_blocks (network, address) {
+            network -> Text,
+            address -> Binary,
+            block_number -> Integer,
+        }
+    }
 }
 
 pub use data::Storage;
@@ -51,11 +60,12 @@ mod data {
     use diesel::dsl::sql;
     use diesel::pg::{Pg, PgConnection};
     use diesel::serialize::Output;
-    use diesel::sql_types::{BigInt, Binary, Bytea, Integer, Jsonb, Text};
+    use diesel::sql_types::{BigInt, Binary, Bytea, Integer, Jsonb, Nullable, Text};
     use diesel::types::{FromSql, ToSql};
     use diesel::{delete, insert_into, prelude::*, sql_query, update};
     use diesel_dynamic_schema as dds;
     use graph::blockchain::{Block, BlockHash};
+    use graph::components::store::IndexedBlock;
     use graph::constraint_violation;
     use graph::prelude::ethabi::ethereum_types::H160;
     use graph::prelude::transaction_receipt::LightTransactionReceipt;
@@ -66,12 +76,15 @@ mod data {
 
     use std::fmt;
     use std::iter::FromIterator;
-    use std::{convert::TryFrom, io::Write};
+    use std::{convert::TryFrom, io::Write, str::FromStr};
 
     use crate::transaction_receipt::RawTransactionReceipt;
 
     pub(crate) const ETHEREUM_BLOCKS_TABLE_NAME: &'static str = "public.ethereum_blocks";
 
+    /// The block time index for chains that use `Storage::Shared`
+    const ETHEREUM_BLOCK_TIMES_TABLE_NAME: &'static str = "public.ethereum_block_times";
+
     mod public {
         pub(super) use super::super::public::ethereum_networks;
 
@@ -122,6 +135,45 @@ mod data {
         hash: Vec<u8>,
     }
 
+    #[derive(QueryableByName)]
+    struct BlockTime {
+        #[sql_type = "Bytea"]
+        hash: Vec<u8>,
+        #[sql_type = "BigInt"]
+        number: i64,
+        #[sql_type = "Bytea"]
+        parent_hash: Vec<u8>,
+        #[sql_type = "BigInt"]
+        timestamp: i64,
+    }
+
+    impl TryFrom<BlockTime> for IndexedBlock {
+        type Error = StoreError;
+
+        fn try_from(block: BlockTime) -> Result<Self, Self::Error> {
+            Ok(IndexedBlock {
+                hash: h256_from_bytes(&block.hash)?,
+                number: BlockNumber::try_from(block.number)
+                    .map_err(|e| StoreError::QueryExecutionError(e.to_string()))?,
+                parent_hash: h256_from_bytes(&block.parent_hash)?,
+                timestamp: block.timestamp as u64,
+            })
+        }
+    }
+
+    #[derive(QueryableByName)]
+    struct LowestNumber {
+        #[sql_type = "Nullable<BigInt>"]
+        number: Option<i64>,
+    }
+
+    /// Turn the hex string that Ethereum blocks have for their timestamp
+    /// into a number in SQL. `ts` must be a string like `0x5f5e1000`; other
+    /// strings, or numbers that are too big, need to be filtered out with
+    /// `BLOCK_TIMESTAMP_FILTER`
+    const BLOCK_TIMESTAMP_SQL: &str = "('x' || lpad(substr(ts, 3), 16, '0'))::bit(64)::int8";
+    const BLOCK_TIMESTAMP_FILTER: &str = "ts like '0x%' and length(ts) <= 17";
+
     // Like H256::from_slice, but returns an error instead of panicking
     // when `bytes` does not have the right length
     fn h256_from_bytes(bytes: &[u8]) -> Result<H256, StoreError> {
@@ -172,6 +224,10 @@ mod data {
             self.table.column::<BigInt, _>("number")
         }
 
+        fn parent_hash(&self) -> DynColumn<Bytea> {
+            self.table.column::<Bytea, _>("parent_hash")
+        }
+
         fn data(&self) -> DynColumn<Jsonb> {
             self.table.column::<Jsonb, _>("data")
         }
@@ -246,6 +302,10 @@ mod data {
         blocks: BlocksTable,
         call_meta: CallMetaTable,
         call_cache: CallCacheTable,
+        /// The fully qualified name of the block time index, which maps
+        /// block numbers and hashes to timestamps and, unlike `blocks`, is
+        /// not purged when old blocks are removed from the block cache
+        block_times: String,
     }
 
     impl Schema {
@@ -253,11 +313,13 @@ mod data {
             let blocks = BlocksTable::new(&name);
             let call_meta = CallMetaTable::new(&name);
             let call_cache = CallCacheTable::new(&name);
+            let block_times = format!("{}.block_times", name);
             Self {
                 name,
                 blocks,
                 call_meta,
                 call_cache,
+                block_times,
             }
         }
     }
@@ -346,6 +408,17 @@ mod data {
                     contract_address bytea not null primary key,
                     accessed_at      date  not null
                 );
+
+                create table {nsp}.block_times (
+                  hash         bytea not null primary key,
+                  number       int8  not null,
+                  parent_hash  bytea not null,
+                  timestamp    int8  not null
+                );
+                create index block_times_number
+                    on {nsp}.block_times using btree(number);
+                create index block_times_timestamp
+                    on {nsp}.block_times using btree(timestamp, number);
             ",
                     nsp = nsp
                 )
@@ -378,6 +451,12 @@ mod data {
                 Storage::Shared => {
                     use public::ethereum_blocks as b;
                     delete(b::table.filter(b::network_name.eq(name))).execute(conn)?;
+                    sql_query(format!(
+                        "delete from {} where network_name = $1",
+                        ETHEREUM_BLOCK_TIMES_TABLE_NAME
+                    ))
+                    .bind::<Text, _>(name)
+                    .execute(conn)?;
                     Ok(())
                 }
                 Storage::Private(Schema { name, .. }) => {
@@ -388,11 +467,18 @@ mod data {
         }
 
         pub(super) fn truncate_block_cache(&self, conn: &PgConnection) -> Result<(), StoreError> {
-            let table_name = match &self {
-                Storage::Shared => ETHEREUM_BLOCKS_TABLE_NAME,
-                Storage::Private(Schema { blocks, .. }) => &blocks.qname,
+            let (table_name, times_name) = match &self {
+                Storage::Shared => (ETHEREUM_BLOCKS_TABLE_NAME, ETHEREUM_BLOCK_TIMES_TABLE_NAME),
+                Storage::Private(Schema {
+                    blocks,
+                    block_times,
+                    ..
+                }) => (blocks.qname.as_str(), block_times.as_str()),
             };
-            conn.batch_execute(&format!("truncate table {} restart identity", table_name))?;
+            conn.batch_execute(&format!(
+                "truncate table {} restart identity; truncate table {}",
+                table_name, times_name
+            ))?;
             Ok(())
         }
 
@@ -472,9 +558,270 @@ mod data {
                         .execute(conn)?;
                 }
             };
+            if let Some(timestamp) = block.timestamp() {
+                self.index_block_time(
+                    conn,
+                    chain,
+                    hash.as_slice(),
+                    number,
+                    parent_hash.as_slice(),
+                    timestamp,
+                )?;
+            }
             Ok(())
         }
 
+        /// Add a block to the block time index. The timestamp of a block
+        /// never changes, and there is nothing to do if the block is
+        /// already in the index
+        fn index_block_time(
+            &self,
+            conn: &PgConnection,
+            chain: &str,
+            hash: &[u8],
+            number: i64,
+            parent_hash: &[u8],
+            timestamp: u64,
+        ) -> Result<(), StoreError> {
+            let timestamp = i64::try_from(timestamp).map_err(|_| {
+                constraint_violation!("the timestamp {} of block {} is too big", timestamp, number)
+            })?;
+            match self {
+                Storage::Shared => {
+                    let query = format!(
+                        "insert into {}(network_name, hash, number, parent_hash, timestamp) \
+                         values ($1, $2, $3, $4, $5) on conflict do nothing",
+                        ETHEREUM_BLOCK_TIMES_TABLE_NAME
+                    );
+                    sql_query(query)
+                        .bind::<Text, _>(chain)
+                        .bind::<Bytea, _>(hash)
+                        .bind::<BigInt, _>(number)
+                        .bind::<Bytea, _>(parent_hash)
+                        .bind::<BigInt, _>(timestamp)
+                        .execute(conn)?;
+                }
+                Storage::Private(Schema { block_times, .. }) => {
+                    let query = format!(
+                        "insert into {}(hash, number, parent_hash, timestamp) \
+                         values ($1, $2, $3, $4) on conflict do nothing",
+                        block_times
+                    );
+                    sql_query(query)
+                        .bind::<Bytea, _>(hash)
+                        .bind::<BigInt, _>(number)
+                        .bind::<Bytea, _>(parent_hash)
+                        .bind::<BigInt, _>(timestamp)
+                        .execute(conn)?;
+                }
+            }
+            Ok(())
+        }
+
+        /// Add the blocks in the block cache with numbers in `[from, to)`
+        /// to the block time index, taking their timestamps from the block
+        /// JSON. Blocks without a timestamp are left out. Return how many
+        /// blocks were added
+        pub(super) fn index_cached_block_times(
+            &self,
+            conn: &PgConnection,
+            chain: &str,
+            from: BlockNumber,
+            to: BlockNumber,
+        ) -> Result<usize, StoreError> {
+            let count = match self {
+                Storage::Shared => {
+                    let query = format!(
+                        "insert into {times}(network_name, hash, number, parent_hash, timestamp) \
+                         select network_name, decode(hash, 'hex'), number, \
+                                decode(parent_hash, 'hex'), {timestamp} \
+                           from (select network_name, hash, number, parent_hash, \
+                                        coalesce(data -> 'block', data) ->> 'timestamp' as ts \
+                                   from {blocks} \
+                                  where network_name = $1 \
+                                    and number >= $2 and number < $3) b \
+                          where {filter} \
+                             on conflict do nothing",
+                        times = ETHEREUM_BLOCK_TIMES_TABLE_NAME,
+                        blocks = ETHEREUM_BLOCKS_TABLE_NAME,
+                        timestamp = BLOCK_TIMESTAMP_SQL,
+                        filter = BLOCK_TIMESTAMP_FILTER
+                    );
+                    sql_query(query)
+                        .bind::<Text, _>(chain)
+                        .bind::<BigInt, _>(from as i64)
+                        .bind::<BigInt, _>(to as i64)
+                        .execute(conn)?
+                }
+                Storage::Private(Schema {
+                    blocks,
+                    block_times,
+                    ..
+                }) => {
+                    let query = format!(
+                        "insert into {times}(hash, number, parent_hash, timestamp) \
+                         select hash, number, parent_hash, {timestamp} \
+                           from (select hash, number, parent_hash, \
+                                        coalesce(data -> 'block', data) ->> 'timestamp' as ts \
+                                   from {blocks} \
+                                  where number >= $1 and number < $2) b \
+                          where {filter} \
+                             on conflict do nothing",
+                        times = block_times,
+                        blocks = blocks.qname,
+                        timestamp = BLOCK_TIMESTAMP_SQL,
+                        filter = BLOCK_TIMESTAMP_FILTER
+                    );
+                    sql_query(query)
+                        .bind::<BigInt, _>(from as i64)
+                        .bind::<BigInt, _>(to as i64)
+                        .execute(conn)?
+                }
+            };
+            Ok(count)
+        }
+
+        /// The lowest block number in the block time index
+        pub(super) fn lowest_indexed_block(
+            &self,
+            conn: &PgConnection,
+            chain: &str,
+        ) -> Result<Option<BlockNumber>, StoreError> {
+            let lowest = match self {
+                Storage::Shared => {
+                    let query = format!(
+                        "select min(number) as number from {} where network_name = $1",
+                        ETHEREUM_BLOCK_TIMES_TABLE_NAME
+                    );
+                    sql_query(query)
+                        .bind::<Text, _>(chain)
+                        .get_result::<LowestNumber>(conn)?
+                }
+                Storage::Private(Schema { block_times, .. }) => {
+                    let query = format!("select min(number) as number from {}", block_times);
+                    sql_query(query).get_result::<LowestNumber>(conn)?
+                }
+            };
+            lowest
+                .number
+                .map(|number| {
+                    BlockNumber::try_from(number)
+                        .map_err(|e| StoreError::QueryExecutionError(e.to_string()))
+                })
+                .transpose()
+        }
+
+        /// The block with the highest number among the blocks in the block
+        /// time index whose timestamp is at most `timestamp`. Close to the
+        /// chain head, the index can contain blocks from more than one
+        /// fork, and the block might not be on the main chain
+        pub(super) fn block_at_time(
+            &self,
+            conn: &PgConnection,
+            chain: &str,
+            timestamp: i64,
+        ) -> Result<Option<BlockPtr>, StoreError> {
+            let block = match self {
+                Storage::Shared => {
+                    let query = format!(
+                        "select hash, number, parent_hash, timestamp from {} \
+                          where network_name = $1 and timestamp <= $2 \
+                          order by timestamp desc, number desc limit 1",
+                        ETHEREUM_BLOCK_TIMES_TABLE_NAME
+                    );
+                    sql_query(query)
+                        .bind::<Text, _>(chain)
+                        .bind::<BigInt, _>(timestamp)
+                        .get_result::<BlockTime>(conn)
+                        .optional()?
+                }
+                Storage::Private(Schema { block_times, .. }) => {
+                    let query = format!(
+                        "select hash, number, parent_hash, timestamp from {} \
+                          where timestamp <= $1 \
+                          order by timestamp desc, number desc limit 1",
+                        block_times
+                    );
+                    sql_query(query)
+                        .bind::<BigInt, _>(timestamp)
+                        .get_result::<BlockTime>(conn)
+                        .optional()?
+                }
+            };
+            block
+                .map(|block| {
+                    IndexedBlock::try_from(block)
+                        .map(|block| BlockPtr::from((block.hash, block.number)))
+                })
+                .transpose()
+        }
+
+        /// The block with `hash` from the block time index
+        pub(super) fn indexed_block(
+            &self,
+            conn: &PgConnection,
+            chain: &str,
+            hash: &H256,
+        ) -> Result<Option<IndexedBlock>, StoreError> {
+            let block = match self {
+                Storage::Shared => {
+                    let query = format!(
+                        "select hash, number, parent_hash, timestamp from {} \
+                          where network_name = $1 and hash = $2",
+                        ETHEREUM_BLOCK_TIMES_TABLE_NAME
+                    );
+                    sql_query(query)
+                        .bind::<Text, _>(chain)
+                        .bind::<Bytea, _>(hash.as_bytes())
+                        .get_result::<BlockTime>(conn)
+                        .optional()?
+                }
+                Storage::Private(Schema { block_times, .. }) => {
+                    let query = format!(
+                        "select hash, number, parent_hash, timestamp from {} where hash = $1",
+                        block_times
+                    );
+                    sql_query(query)
+                        .bind::<Bytea, _>(hash.as_bytes())
+                        .get_result::<BlockTime>(conn)
+                        .optional()?
+                }
+            };
+            block.map(IndexedBlock::try_from).transpose()
+        }
+
+        /// The blocks with `number` from the block time index
+        pub(super) fn indexed_blocks_by_number(
+            &self,
+            conn: &PgConnection,
+            chain: &str,
+            number: BlockNumber,
+        ) -> Result<Vec<IndexedBlock>, StoreError> {
+            let blocks = match self {
+                Storage::Shared => {
+                    let query = format!(
+                        "select hash, number, parent_hash, timestamp from {} \
+                          where network_name = $1 and number = $2",
+                        ETHEREUM_BLOCK_TIMES_TABLE_NAME
+                    );
+                    sql_query(query)
+                        .bind::<Text, _>(chain)
+                        .bind::<BigInt, _>(number as i64)
+                        .load::<BlockTime>(conn)?
+                }
+                Storage::Private(Schema { block_times, .. }) => {
+                    let query = format!(
+                        "select hash, number, parent_hash, timestamp from {} where number = $1",
+                        block_times
+                    );
+                    sql_query(query)
+                        .bind::<BigInt, _>(number as i64)
+                        .load::<BlockTime>(conn)?
+                }
+            };
+            blocks.into_iter().map(IndexedBlock::try_from).collect()
+        }
+
         pub(super) fn blocks(
             &self,
             conn: &PgConnection,
@@ -560,6 +907,16 @@ mod data {
                 Storage::Shared => {
                     use public::ethereum_blocks as b;
 
+                    let query = format!(
+                        "delete from {} where network_name = $1 and number = $2 and hash != $3",
+                        ETHEREUM_BLOCK_TIMES_TABLE_NAME
+                    );
+                    sql_query(query)
+                        .bind::<Text, _>(chain)
+                        .bind::<BigInt, _>(number)
+                        .bind::<Bytea, _>(hash.as_bytes())
+                        .execute(conn)?;
+
                     let hash = format!("{:x}", hash);
                     diesel::delete(b::table)
                         .filter(b::network_name.eq(chain))
@@ -568,7 +925,20 @@ mod data {
                         .execute(conn)
                         .map_err(Error::from)
                 }
-                Storage::Private(Schema { blocks, .. }) => {
+                Storage::Private(Schema {
+                    blocks,
+                    block_times,
+                    ..
+                }) => {
+                    let query = format!(
+                        "delete from {} where number = $1 and hash != $2",
+                        block_times
+                    );
+                    sql_query(query)
+                        .bind::<BigInt, _>(number)
+                        .bind::<Bytea, _>(hash.as_bytes())
+                        .execute(conn)?;
+
                     let query = format!(
                         "delete from {} where number = $1 and hash != $2",
                         blocks.qname
@@ -612,6 +982,41 @@ mod data {
                 .transpose()
         }
 
+        pub(super) fn block_parent_hash(
+            &self,
+            conn: &PgConnection,
+            hash: H256,
+        ) -> Result<Option<H256>, StoreError> {
+            match self {
+                Storage::Shared => {
+                    use public::ethereum_blocks as b;
+
+                    b::table
+                        .select(b::parent_hash)
+                        .filter(b::hash.eq(format!("{:x}", hash)))
+                        .first::<Option<String>>(conn)
+                        .optional()?
+                        .flatten()
+                        .map(|parent| {
+                            H256::from_str(&parent).map_err(|e| {
+                                StoreError::QueryExecutionError(format!(
+                                    "invalid parent hash `{}` for block {:x}: {}",
+                                    parent, hash, e
+                                ))
+                            })
+                        })
+                        .transpose()
+                }
+                Storage::Private(Schema { blocks, .. }) => Ok(blocks
+                    .table()
+                    .select(blocks.parent_hash())
+                    .filter(blocks.hash().eq(hash.as_bytes()))
+                    .first::<Vec<u8>>(conn)
+                    .optional()?
+                    .map(|parent| H256::from_slice(&parent))),
+            }
+        }
+
         /// Find the first block that is missing from the database needed to
         /// complete the chain from block `hash` to the block with number
         /// `first_block`.
@@ -764,6 +1169,35 @@ mod data {
             }
         }
 
+        /// The block with the lowest number in the block cache
+        pub(super) fn earliest_block(
+            &self,
+            conn: &PgConnection,
+            chain: &str,
+        ) -> Result<Option<BlockPtr>, Error> {
+            match self {
+                Storage::Shared => {
+                    use public::ethereum_blocks as b;
+                    b::table
+                        .filter(b::network_name.eq(chain))
+                        .order_by((b::number.asc(), b::hash))
+                        .select((b::hash, b::number))
+                        .first::<(String, i64)>(conn)
+                        .optional()?
+                        .map(|(hash, number)| BlockPtr::try_from((hash.as_str(), number)))
+                        .transpose()
+                }
+                Storage::Private(Schema { blocks, .. }) => blocks
+                    .table()
+                    .order_by((blocks.number().asc(), blocks.hash()))
+                    .select((blocks.hash(), blocks.number()))
+                    .first::<(Vec<u8>, i64)>(conn)
+                    .optional()?
+                    .map(|(hash, number)| BlockPtr::try_from((hash.as_slice(), number)))
+                    .transpose(),
+            }
+        }
+
         pub(super) fn ancestor_block(
             &self,
             conn: &PgConnection,
@@ -856,6 +1290,32 @@ mod data {
             Ok(data)
         }
 
+        /// Remove the blocks with numbers below `block` from the block
+        /// time index
+        #[cfg(debug_assertions)]
+        pub(super) fn delete_block_times_before(
+            &self,
+            conn: &PgConnection,
+            chain: &str,
+            block: i64,
+        ) -> Result<usize, StoreError> {
+            let count = match self {
+                Storage::Shared => sql_query(format!(
+                    "delete from {} where network_name = $1 and number < $2",
+                    ETHEREUM_BLOCK_TIMES_TABLE_NAME
+                ))
+                .bind::<Text, _>(chain)
+                .bind::<BigInt, _>(block)
+                .execute(conn)?,
+                Storage::Private(Schema { block_times, .. }) => {
+                    sql_query(format!("delete from {} where number < $1", block_times))
+                        .bind::<BigInt, _>(block)
+                        .execute(conn)?
+                }
+            };
+            Ok(count)
+        }
+
         pub(super) fn delete_blocks_before(
             &self,
             conn: &PgConnection,
@@ -1119,6 +1579,13 @@ mod data {
                     diesel::delete(b::table.filter(b::network_name.eq(chain_name)))
                         .execute(conn)
                         .expect("Failed to delete ethereum_blocks");
+                    sql_query(format!(
+                        "delete from {} where network_name = $1",
+                        ETHEREUM_BLOCK_TIMES_TABLE_NAME
+                    ))
+                    .bind::<Text, _>(chain_name)
+                    .execute(conn)
+                    .expect("Failed to delete ethereum_block_times");
                     // We don't have a good way to clean out the call cache
                     // per chain; just nuke everything
                     diesel::delete(c::table).execute(conn).unwrap();
@@ -1128,9 +1595,15 @@ mod data {
                     blocks,
                     call_meta,
                     call_cache,
+                    block_times,
                     ..
                 }) => {
-                    for qname in &[&blocks.qname, &call_meta.qname, &call_cache.qname] {
+                    for qname in &[
+                        &blocks.qname,
+                        &call_meta.qname,
+                        &call_cache.qname,
+                        block_times,
+                    ] {
                         let query = format!("delete from {}", qname);
                         sql_query(query)
                             .execute(conn)
@@ -1270,10 +1743,13 @@ impl ChainStore {
         use diesel::dsl::delete;
         use public::ethereum_networks as n;
 
+        use public::contract_creation_blocks as c;
+
         let conn = self.get_conn()?;
         conn.transaction(|| {
             self.storage.drop_storage(&conn, &self.chain)?;
 
+            delete(c::table.filter(c::network.eq(&self.chain))).execute(&conn)?;
             delete(n::table.filter(n::name.eq(&self.chain))).execute(&conn)?;
             Ok(())
         })
@@ -1332,6 +1808,70 @@ impl ChainStore {
             .set_chain(&conn, &self.chain, genesis_hash, chain);
     }
 
+    /// Remove the blocks with numbers below `block` from the block time
+    /// index so that tests can pretend that these blocks were cached before
+    /// the index existed
+    #[cfg(debug_assertions)]
+    pub fn delete_block_times_before(&self, block: BlockNumber) -> Result<usize, StoreError> {
+        let conn = self.get_conn()?;
+        self.storage
+            .delete_block_times_before(&conn, &self.chain, block as i64)
+    }
+
+    /// Find the hash of the parent of the block with `hash`
+    pub fn block_parent_hash(&self, hash: H256) -> Result<Option<H256>, StoreError> {
+        let conn = self.get_conn()?;
+        self.storage.block_parent_hash(&conn, hash)
+    }
+
+    /// The block with the lowest number that is still in the block cache
+    pub fn earliest_block(&self) -> Result<Option<BlockPtr>, StoreError> {
+        let conn = self.pool.get()?;
+        Ok(self.storage.earliest_block(&conn, &self.chain)?)
+    }
+
+    /// Add blocks that were cached before the block time index existed to
+    /// the index, working down from the block `below` to the earliest
+    /// cached block in batches of at most `batch_size` blocks; if `below`
+    /// is `None`, start at the lowest block in the index, or at the chain
+    /// head if the index is empty. Return the block below which blocks
+    /// still need to be added, or `None` once all cached blocks are in the
+    /// index
+    pub fn index_cached_block_times(
+        &self,
+        below: Option<BlockNumber>,
+        batch_size: BlockNumber,
+    ) -> Result<Option<BlockNumber>, StoreError> {
+        use public::ethereum_networks as n;
+
+        let conn = self.get_conn()?;
+        let earliest = match self.storage.earliest_block(&conn, &self.chain)? {
+            Some(earliest) => earliest.number,
+            None => return Ok(None),
+        };
+        let below = match below {
+            Some(below) => Some(below),
+            None => match self.storage.lowest_indexed_block(&conn, &self.chain)? {
+                Some(lowest) => Some(lowest),
+                None => n::table
+                    .filter(n::name.eq(&self.chain))
+                    .select(n::head_block_number)
+                    .first::<Option<i64>>(&conn)
+                    .optional()?
+                    .flatten()
+                    .map(|head| head as BlockNumber + 1),
+            },
+        };
+        let below = match below {
+            Some(below) if below > earliest => below,
+            _ => return Ok(None),
+        };
+        let from = (below - batch_size).max(earliest);
+        self.storage
+            .index_cached_block_times(&conn, &self.chain, from, below)?;
+        Ok(Some(from).filter(|from| *from > earliest))
+    }
+
     pub fn truncate_block_cache(&self) -> Result<(), StoreError> {
         let conn = self.get_conn()?;
         self.storage.truncate_block_cache(&conn)?;
@@ -1627,6 +2167,27 @@ impl ChainStoreTrait for ChainStore {
             .confirm_block_hash(&conn, &self.chain, number, hash)
     }
 
+    fn block_at_time(&self, timestamp: u64) -> Result<Option<BlockPtr>, StoreError> {
+        // Timestamps beyond what the index can hold are later than any block
+        let timestamp = i64::try_from(timestamp).unwrap_or(i64::MAX);
+        let conn = self.pool.get()?;
+        self.storage.block_at_time(&conn, &self.chain, timestamp)
+    }
+
+    fn indexed_block(&self, hash: &H256) -> Result<Option<IndexedBlock>, StoreError> {
+        let conn = self.pool.get()?;
+        self.storage.indexed_block(&conn, &self.chain, hash)
+    }
+
+    fn indexed_blocks_by_number(
+        &self,
+        number: BlockNumber,
+    ) -> Result<Vec<IndexedBlock>, StoreError> {
+        let conn = self.pool.get()?;
+        self.storage
+            .indexed_blocks_by_number(&conn, &self.chain, number)
+    }
+
     fn block_number(&self, hash: H256) -> Result<Option<(String, BlockNumber)>, StoreError> {
         let conn = self.get_conn()?;
         Ok(self
@@ -1649,6 +2210,40 @@ impl ChainStoreTrait for ChainStore {
         })
         .await
     }
+
+    fn contract_creation_block(&self, address: &[u8]) -> Result<Option<BlockNumber>, Error> {
+        use public::contract_creation_blocks as c;
+
+        let conn = self.get_conn()?;
+        c::table
+            .filter(c::network.eq(&self.chain))
+            .filter(c::address.eq(address))
+            .select(c::block_number)
+            .first::<BlockNumber>(&conn)
+            .optional()
+            .map_err(Error::from)
+    }
+
+    fn set_contract_creation_block(
+        &self,
+        address: &[u8],
+        number: BlockNumber,
+    ) -> Result<(), Error> {
+        use public::contract_creation_blocks as c;
+
+        let conn = self.get_conn()?;
+        insert_into(c::table)
+            .values((
+                c::network.eq(&self.chain),
+                c::address.eq(address),
+                c::block_number.eq(number),
+            ))
+            .on_conflict((c::network, c::address))
+            .do_update()
+            .set(c::block_number.eq(number))
+            .execute(&conn)?;
+        Ok(())
+    }
 }
 
 impl EthereumCallCache for ChainStore {
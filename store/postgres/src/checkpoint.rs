@@ -0,0 +1,341 @@
+//! Checkpoints of the entities of a deployment. When history has been
+//! pruned, a deployment can not be reverted to a block before the pruned
+//! history with the block ranges of its entities alone. With
+//! `GRAPH_ENTITY_CHECKPOINT_INTERVAL` set, writing a block that crosses a
+//! multiple of the interval records a checkpoint at that block. The entity
+//! versions that are current at the checkpoint are copied into
+//! `subgraphs.entity_checkpoint_data` afterwards, outside of the
+//! transaction that writes the block. Rewinding to a block before the
+//! pruned history replaces the entities of the deployment with those of
+//! the latest complete checkpoint at or before that block.
+//!
+//! Old checkpoints are thinned out so that the number of checkpoints only
+//! grows with the logarithm of the number of blocks: of all the checkpoints
+//! that are between `2^k - 1` and `2^(k+1) - 2` intervals behind the latest
+//! one, we only keep the oldest
+use diesel::pg::PgConnection;
+use diesel::sql_types::{Integer, Text};
+use diesel::{delete, insert_into, sql_query, update, Connection};
+use diesel::{ExpressionMethods, OptionalExtension, QueryDsl, RunQueryDsl};
+
+use graph::prelude::{BlockNumber, BlockPtr, EntityChange, StoreError, StoreEvent};
+
+use crate::block_range::{BLOCK_COLUMN, BLOCK_RANGE_COLUMN};
+use crate::primary::Site;
+use crate::relational::{Layout, Table};
+
+table! {
+    subgraphs.entity_checkpoint (deployment, block_number) {
+        deployment -> Integer,
+        block_number -> Integer,
+        block_hash -> Binary,
+        complete -> Bool,
+    }
+}
+
+table! {
+    subgraphs.pruned_history (id) {
+        id -> Integer,
+        pruned_before -> Integer,
+    }
+}
+
+/// The condition for the versions in `table` that are current at the
+/// block that is bound to `$2`
+fn current_at(table: &Table) -> String {
+    if table.immutable {
+        format!("\"{}\" <= $2", BLOCK_COLUMN)
+    } else {
+        "block_range @> $2".to_string()
+    }
+}
+
+/// Whether writing block `to` after block `prev` crosses a multiple of
+/// `interval`. Blocks without triggers are not written, and checking for
+/// exact multiples would miss most of them
+pub(crate) fn is_due(prev: Option<BlockNumber>, to: BlockNumber, interval: BlockNumber) -> bool {
+    let prev = prev.unwrap_or(to - 1);
+    to.div_euclid(interval) > prev.div_euclid(interval)
+}
+
+/// Record a checkpoint at `ptr`, which must be the block that is being
+/// written. The checkpoint is incomplete until `fill` copies its entities
+pub(crate) fn add(conn: &PgConnection, site: &Site, ptr: &BlockPtr) -> Result<(), StoreError> {
+    use entity_checkpoint as c;
+
+    insert_into(c::table)
+        .values((
+            c::deployment.eq(site.id),
+            c::block_number.eq(ptr.number),
+            c::block_hash.eq(ptr.hash_slice()),
+        ))
+        .on_conflict_do_nothing()
+        .execute(conn)?;
+    Ok(())
+}
+
+/// Copy the entities of all incomplete checkpoints of the deployment, one
+/// checkpoint per transaction, and thin out the older checkpoints if
+/// `interval` is given. A checkpoint that is being filled is locked, and
+/// concurrent calls skip it. A checkpoint whose history was pruned before
+/// all its entities were copied can not be used and is dropped
+pub(crate) fn fill(
+    conn: &PgConnection,
+    site: &Site,
+    layout: &Layout,
+    interval: Option<BlockNumber>,
+) -> Result<(), StoreError> {
+    use entity_checkpoint as c;
+
+    loop {
+        let filled = conn.transaction(|| -> Result<bool, StoreError> {
+            let block = match c::table
+                .filter(c::deployment.eq(site.id))
+                .filter(c::complete.eq(false))
+                .order(c::block_number)
+                .select(c::block_number)
+                .for_update()
+                .skip_locked()
+                .first::<BlockNumber>(conn)
+                .optional()?
+            {
+                Some(block) => block,
+                None => return Ok(false),
+            };
+
+            for table in layout.tables.values() {
+                let query = format!(
+                    "insert into subgraphs.entity_checkpoint_data\
+                            (deployment, block_number, entity_table, data)
+                     select $1, $2, $3, to_jsonb(t.*)
+                       from {} t
+                      where {}",
+                    table.qualified_name,
+                    current_at(table)
+                );
+                sql_query(query)
+                    .bind::<Integer, _>(site.id)
+                    .bind::<Integer, _>(block)
+                    .bind::<Text, _>(table.name.as_str())
+                    .execute(conn)?;
+            }
+
+            let checkpoint = c::table
+                .filter(c::deployment.eq(site.id))
+                .filter(c::block_number.eq(block));
+            if pruned_before(conn, site)?.map_or(false, |before| before > block) {
+                delete(checkpoint).execute(conn)?;
+                return Ok(true);
+            }
+            update(checkpoint).set(c::complete.eq(true)).execute(conn)?;
+
+            if let Some(interval) = interval {
+                let blocks = c::table
+                    .filter(c::deployment.eq(site.id))
+                    .filter(c::complete.eq(true))
+                    .select(c::block_number)
+                    .load::<BlockNumber>(conn)?;
+                let latest = blocks.iter().copied().max().unwrap_or(block);
+                let dropped = checkpoints_to_drop(latest, interval, &blocks);
+                if !dropped.is_empty() {
+                    delete(
+                        c::table
+                            .filter(c::deployment.eq(site.id))
+                            .filter(c::block_number.eq_any(dropped)),
+                    )
+                    .execute(conn)?;
+                }
+            }
+            Ok(true)
+        })?;
+        if !filled {
+            return Ok(());
+        }
+    }
+}
+
+/// The checkpoints among `blocks` that thinning drops when `latest` is the
+/// latest checkpoint: we keep the oldest checkpoint of each bucket, where
+/// the checkpoint `k` intervals behind `latest` is in bucket
+/// `floor(log2(k + 1))`
+fn checkpoints_to_drop(
+    latest: BlockNumber,
+    interval: BlockNumber,
+    blocks: &[BlockNumber],
+) -> Vec<BlockNumber> {
+    fn bucket(latest: BlockNumber, interval: BlockNumber, block: BlockNumber) -> u32 {
+        let behind = ((latest - block).max(0) / interval.max(1)) as u32;
+        31 - (behind + 1).leading_zeros()
+    }
+
+    let mut blocks = blocks.to_vec();
+    blocks.sort_unstable();
+    let mut dropped = Vec::new();
+    let mut last_bucket = None;
+    for block in blocks {
+        let bucket = bucket(latest, interval, block);
+        if last_bucket == Some(bucket) {
+            dropped.push(block);
+        }
+        last_bucket = Some(bucket);
+    }
+    dropped
+}
+
+/// Remember that the history of the deployment before block `before` has
+/// been pruned
+pub(crate) fn record_pruned(
+    conn: &PgConnection,
+    site: &Site,
+    before: BlockNumber,
+) -> Result<(), StoreError> {
+    use pruned_history as p;
+
+    insert_into(p::table)
+        .values((p::id.eq(site.id), p::pruned_before.eq(before)))
+        .on_conflict(p::id)
+        .do_update()
+        .set(p::pruned_before.eq(diesel::dsl::sql::<Integer>(
+            "greatest(subgraphs.pruned_history.pruned_before, excluded.pruned_before)",
+        )))
+        .execute(conn)?;
+    Ok(())
+}
+
+/// The block before which the history of the deployment has been pruned,
+/// or `None` if it has never been pruned
+pub(crate) fn pruned_before(
+    conn: &PgConnection,
+    site: &Site,
+) -> Result<Option<BlockNumber>, StoreError> {
+    use pruned_history as p;
+
+    Ok(p::table
+        .filter(p::id.eq(site.id))
+        .select(p::pruned_before)
+        .first::<BlockNumber>(conn)
+        .optional()?)
+}
+
+/// The latest complete checkpoint of the deployment at or before `block`
+pub(crate) fn latest_at_or_before(
+    conn: &PgConnection,
+    site: &Site,
+    block: BlockNumber,
+) -> Result<Option<BlockPtr>, StoreError> {
+    use entity_checkpoint as c;
+
+    Ok(c::table
+        .filter(c::deployment.eq(site.id))
+        .filter(c::block_number.le(block))
+        .filter(c::complete.eq(true))
+        .order(c::block_number.desc())
+        .select((c::block_hash, c::block_number))
+        .first::<(Vec<u8>, BlockNumber)>(conn)
+        .optional()?
+        .map(BlockPtr::from))
+}
+
+/// Forget the checkpoints of the deployment after `block`, which is the
+/// block the deployment is reverted to
+pub(crate) fn forget_after(
+    conn: &PgConnection,
+    site: &Site,
+    block: BlockNumber,
+) -> Result<(), StoreError> {
+    use entity_checkpoint as c;
+
+    delete(
+        c::table
+            .filter(c::deployment.eq(site.id))
+            .filter(c::block_number.gt(block)),
+    )
+    .execute(conn)?;
+    Ok(())
+}
+
+/// Replace all entities of the deployment with the ones in the checkpoint
+/// at `checkpoint`, and forget the checkpoints after it. The versions that
+/// were current at the checkpoint are current again, and their block
+/// ranges are reopened. Since the entities no longer have any history
+/// before the checkpoint, the history counts as pruned up to it
+pub(crate) fn restore(
+    conn: &PgConnection,
+    site: &Site,
+    layout: &Layout,
+    checkpoint: &BlockPtr,
+) -> Result<StoreEvent, StoreError> {
+    use pruned_history as p;
+
+    let mut changes = Vec::new();
+    for table in layout.tables.values() {
+        sql_query(format!("delete from {}", table.qualified_name)).execute(conn)?;
+        let query = format!(
+            "insert into {0}
+             select (jsonb_populate_record(null::{0}, data)).*
+               from subgraphs.entity_checkpoint_data
+              where deployment = $1
+                and block_number = $2
+                and entity_table = $3",
+            table.qualified_name
+        );
+        sql_query(query)
+            .bind::<Integer, _>(site.id)
+            .bind::<Integer, _>(checkpoint.number)
+            .bind::<Text, _>(table.name.as_str())
+            .execute(conn)?;
+        if !table.immutable {
+            let query = format!(
+                "update {0}
+                    set {1} = int4range(lower({1}), null)
+                  where not upper_inf({1})",
+                table.qualified_name, BLOCK_RANGE_COLUMN
+            );
+            sql_query(query).execute(conn)?;
+        }
+        changes.push(EntityChange::Data {
+            subgraph_id: site.deployment.clone(),
+            entity_type: table.object.clone(),
+        });
+    }
+
+    forget_after(conn, site, checkpoint.number)?;
+    update(p::table.filter(p::id.eq(site.id)))
+        .set(p::pruned_before.eq(checkpoint.number))
+        .execute(conn)?;
+
+    Ok(StoreEvent::new(changes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{checkpoints_to_drop, is_due};
+
+    #[test]
+    fn checkpoints_when_crossing_an_interval() {
+        assert!(is_due(Some(99), 100, 100));
+        assert!(is_due(Some(95), 130, 100));
+        assert!(is_due(Some(50), 250, 100));
+        assert!(!is_due(Some(100), 101, 100));
+        assert!(!is_due(Some(101), 199, 100));
+
+        // The first block written is only a checkpoint if it is a multiple
+        assert!(is_due(None, 200, 100));
+        assert!(!is_due(None, 201, 100));
+        assert!(is_due(None, 0, 100));
+    }
+
+    #[test]
+    fn thins_checkpoints_exponentially() {
+        let blocks: Vec<_> = (0..=10).map(|n| n * 100).collect();
+        assert_eq!(
+            vec![100, 200, 300, 500, 600, 700, 900],
+            checkpoints_to_drop(1000, 100, &blocks)
+        );
+
+        // Thinning what is left after the next checkpoint keeps the gaps
+        // between checkpoints growing with their age
+        let blocks = vec![0, 400, 800, 1000, 1100];
+        assert_eq!(vec![400], checkpoints_to_drop(1100, 100, &blocks));
+    }
+}
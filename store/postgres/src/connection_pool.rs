@@ -16,15 +16,17 @@ use graph::{
         anyhow::{self, anyhow, bail},
         crit, debug, error, info, o,
         tokio::sync::Semaphore,
-        CancelGuard, CancelHandle, CancelToken as _, CancelableError, Counter, Gauge, Logger,
-        MetricsRegistry, MovingStats, PoolWaitStats, StoreError, ENV_VARS,
+        CancelGuard, CancelHandle, CancelToken as _, CancelableError, Counter, CounterVec,
+        DeploymentHash, Gauge, HistogramVec, Logger, MetricsRegistry, MovingStats, PoolWaitStats,
+        QueryExecutionError, QueryPermit, StoreError, ENV_VARS,
     },
+    util::fair_scheduler::{FairScheduler, Rejected},
     util::security::SafeDisplay,
 };
 
 use std::fmt::{self, Write};
 use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
 use std::{collections::HashMap, sync::RwLock};
 
@@ -212,6 +214,10 @@ impl ForeignServer {
 /// them on idle. This is much shorter than the default of 10 minutes.
 const FDW_IDLE_TIMEOUT: Duration = Duration::from_secs(60);
 
+/// While a database is unavailable, let one attempt to get a connection
+/// through this often to find out whether it has come back
+const AVAILABILITY_PROBE_INTERVAL: Duration = Duration::from_secs(10);
+
 /// A pool goes through several states, and this enum tracks what state we
 /// are in, together with the `state_tracker` field on `ConnectionPool`.
 /// When first created, the pool is in state `Created`; once we successfully
@@ -274,26 +280,55 @@ impl PoolName {
 #[derive(Clone)]
 struct PoolStateTracker {
     available: Arc<AtomicBool>,
+    closed: Arc<AtomicBool>,
+    /// When we last tried to use the database while it was unavailable
+    last_probe: Arc<Mutex<Instant>>,
 }
 
 impl PoolStateTracker {
     fn new() -> Self {
         Self {
             available: Arc::new(AtomicBool::new(true)),
+            closed: Arc::new(AtomicBool::new(false)),
+            last_probe: Arc::new(Mutex::new(Instant::now())),
         }
     }
 
+    fn mark_closed(&self) {
+        self.closed.store(true, Ordering::Relaxed);
+    }
+
+    fn is_closed(&self) -> bool {
+        self.closed.load(Ordering::Relaxed)
+    }
+
     fn mark_available(&self) {
         self.available.store(true, Ordering::Relaxed);
     }
 
     fn mark_unavailable(&self) {
-        self.available.store(false, Ordering::Relaxed);
+        if self.available.swap(false, Ordering::Relaxed) {
+            *self.last_probe.lock().unwrap() = Instant::now();
+        }
     }
 
     fn is_available(&self) -> bool {
         self.available.load(Ordering::Relaxed)
     }
+
+    /// Whether an unavailable database should be tried again. Returns
+    /// `true` at most once every `AVAILABILITY_PROBE_INTERVAL` so that
+    /// only few operations wait for a connection timeout while the
+    /// database is down, but we notice soon when it comes back
+    fn should_probe(&self) -> bool {
+        let mut last_probe = self.last_probe.lock().unwrap();
+        if last_probe.elapsed() >= AVAILABILITY_PROBE_INTERVAL {
+            *last_probe = Instant::now();
+            true
+        } else {
+            false
+        }
+    }
 }
 
 impl ConnectionPool {
@@ -348,12 +383,19 @@ impl ConnectionPool {
     /// `StoreError::DatabaseUnavailable`
     fn get_ready(&self) -> Result<Arc<PoolInner>, StoreError> {
         let mut guard = self.inner.lock(&self.logger);
-        if !self.state_tracker.is_available() && !ENV_VARS.store.connection_try_always {
+        if self.state_tracker.is_closed() {
+            return Err(StoreError::DatabaseUnavailable);
+        }
+        if !self.state_tracker.is_available()
+            && !ENV_VARS.store.connection_try_always
+            && !self.state_tracker.should_probe()
+        {
             // We know that trying to use this pool is pointless since the
             // database is not available, and will only lead to other
             // operations having to wait until the connection timeout is
             // reached. `TRY_ALWAYS` allows users to force us to try
-            // regardless.
+            // regardless. Every so often, we let an operation through so
+            // that we notice when the database is back
             return Err(StoreError::DatabaseUnavailable);
         }
 
@@ -449,9 +491,19 @@ impl ConnectionPool {
         ForeignServer::new(pool.shard.clone(), &pool.postgres_url).map_err(|e| e.into())
     }
 
+    /// Open a connection to the database that is not part of the pool,
+    /// for holding session state like advisory locks for a long time
+    /// without keeping a pooled connection busy
+    pub(crate) fn dedicated_connection(&self) -> Result<PgConnection, StoreError> {
+        let pool = self.get_ready()?;
+        PgConnection::establish(&pool.postgres_url).map_err(|e| {
+            StoreError::Unknown(anyhow!("failed to connect to shard {}: {}", pool.shard, e))
+        })
+    }
+
     /// Check that we can connect to the database
     pub fn check(&self) -> bool {
-        true
+        self.get_ready().map(|pool| pool.check()).unwrap_or(false)
     }
 
     /// Setup the database for this pool. This includes configuring foreign
@@ -471,6 +523,20 @@ impl ConnectionPool {
         .unwrap();
     }
 
+    /// Stop handing out connections and wait until all connections that
+    /// are currently in use have been returned to the pool. This is used
+    /// when the node shuts down so that work that is in flight, like
+    /// writing a block, can finish before the process exits
+    pub async fn close(&self) {
+        self.state_tracker.mark_closed();
+        let pool = match &*self.inner.lock(&self.logger) {
+            PoolState::Created(pool, _) | PoolState::Ready(pool) => pool.clone(),
+        };
+        while pool.connections_in_use() > 0 {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+    }
+
     pub(crate) async fn query_permit(&self) -> tokio::sync::OwnedSemaphorePermit {
         let pool = match &*self.inner.lock(&self.logger) {
             PoolState::Created(pool, _) | PoolState::Ready(pool) => pool.clone(),
@@ -478,6 +544,16 @@ impl ConnectionPool {
         pool.query_permit().await
     }
 
+    pub(crate) async fn deployment_query_permit(
+        &self,
+        deployment: &DeploymentHash,
+    ) -> Result<QueryPermit, QueryExecutionError> {
+        let pool = match &*self.inner.lock(&self.logger) {
+            PoolState::Created(pool, _) | PoolState::Ready(pool) => pool.clone(),
+        };
+        pool.deployment_query_permit(deployment).await
+    }
+
     pub(crate) fn wait_stats(&self) -> PoolWaitStats {
         match &*self.inner.lock(&self.logger) {
             PoolState::Created(pool, _) | PoolState::Ready(pool) => pool.wait_stats.clone(),
@@ -683,6 +759,14 @@ pub struct PoolInner {
     query_semaphore: Arc<tokio::sync::Semaphore>,
     semaphore_wait_stats: Arc<RwLock<MovingStats>>,
     semaphore_wait_gauge: Box<Gauge>,
+
+    // Decides which deployment gets the next query permit when queries
+    // have to wait, so that queries against one busy deployment can not
+    // use up all permits. It has as many slots as `query_semaphore` has
+    // permits
+    query_scheduler: FairScheduler,
+    admission_wait: HistogramVec,
+    admission_rejected: CounterVec,
 }
 
 impl PoolInner {
@@ -759,6 +843,22 @@ impl PoolInner {
             .expect("failed to create `query_effort_ms` counter");
         let max_concurrent_queries = pool_size as usize + ENV_VARS.store.extra_query_permits;
         let query_semaphore = Arc::new(tokio::sync::Semaphore::new(max_concurrent_queries));
+        let query_scheduler = FairScheduler::new(max_concurrent_queries);
+        let admission_wait = registry
+            .global_histogram_vec(
+                "query_admission_wait_seconds",
+                "The time queries against a deployment waited for a query permit",
+                &["deployment", "shard"],
+            )
+            .expect("failed to create `query_admission_wait_seconds` histogram");
+        let admission_rejected = registry
+            .global_counter_vec(
+                "query_admission_rejected",
+                "The number of queries against a deployment that were rejected \
+                 because too many queries against it were waiting for a permit",
+                &["deployment", "shard"],
+            )
+            .expect("failed to create `query_admission_rejected` counter");
         PoolInner {
             logger: logger_pool,
             shard: Shard::new(shard_name.to_string())
@@ -771,6 +871,9 @@ impl PoolInner {
             semaphore_wait_stats: Arc::new(RwLock::new(MovingStats::default())),
             query_semaphore,
             semaphore_wait_gauge,
+            query_scheduler,
+            admission_wait,
+            admission_rejected,
         }
     }
 
@@ -866,6 +969,16 @@ impl PoolInner {
         self.pool.get().map_err(|_| StoreError::DatabaseUnavailable)
     }
 
+    /// The number of connections from the main and the fdw pool that are
+    /// checked out right now
+    fn connections_in_use(&self) -> u32 {
+        let in_use = |pool: &Pool<ConnectionManager<PgConnection>>| {
+            let state = pool.state();
+            state.connections - state.idle_connections
+        };
+        in_use(&self.pool) + self.fdw_pool.as_ref().map(in_use).unwrap_or(0)
+    }
+
     pub fn get_with_timeout_warning(
         &self,
         logger: &Logger,
@@ -972,6 +1085,47 @@ impl PoolInner {
         permit.unwrap()
     }
 
+    /// Wait for a permit for a query against `deployment`. When queries
+    /// have to wait, deployments get permits in proportion to their weight
+    /// in `GRAPH_QUERY_DEPLOYMENT_WEIGHTS` rather than in the order in
+    /// which queries arrive. Fails if `GRAPH_QUERY_MAX_WAITING_PER_DEPLOYMENT`
+    /// queries against the deployment are already waiting
+    pub(crate) async fn deployment_query_permit(
+        &self,
+        deployment: &DeploymentHash,
+    ) -> Result<QueryPermit, QueryExecutionError> {
+        let labels = [deployment.as_str(), self.shard.as_str()];
+        let start = Instant::now();
+        let weight = ENV_VARS
+            .store
+            .query_deployment_weights
+            .get(deployment.as_str())
+            .copied()
+            .unwrap_or(1);
+        let share = self
+            .query_scheduler
+            .acquire(
+                deployment.as_str(),
+                weight,
+                ENV_VARS.store.query_max_waiting_per_deployment,
+            )
+            .await
+            .map_err(|Rejected| {
+                self.admission_rejected.with_label_values(&labels).inc();
+                QueryExecutionError::Throttled
+            })?;
+        // Queries that are not against a deployment, like the ones for the
+        // status API, only use the semaphore
+        let permit = self.query_permit().await;
+        self.admission_wait
+            .with_label_values(&labels)
+            .observe(start.elapsed().as_secs_f64());
+        Ok(QueryPermit {
+            permit,
+            share: Some(share),
+        })
+    }
+
     fn configure_fdw(&self, servers: &[ForeignServer]) -> Result<(), StoreError> {
         info!(&self.logger, "Setting up fdw");
         let conn = self.get()?;
@@ -1067,3 +1221,39 @@ fn migrate_schema(logger: &Logger, conn: &PgConnection) -> Result<(), StoreError
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use super::{PoolStateTracker, AVAILABILITY_PROBE_INTERVAL};
+
+    #[test]
+    fn probe_unavailable_database() {
+        let tracker = PoolStateTracker::new();
+        assert!(tracker.is_available());
+
+        // Right after the database became unavailable, nobody tries it
+        tracker.mark_unavailable();
+        assert!(!tracker.is_available());
+        assert!(!tracker.should_probe());
+
+        // Once the probe interval has passed, exactly one operation gets
+        // to try the database
+        *tracker.last_probe.lock().unwrap() = Instant::now() - AVAILABILITY_PROBE_INTERVAL;
+        assert!(tracker.should_probe());
+        assert!(!tracker.should_probe());
+
+        // Failing again while the database is known to be unavailable does
+        // not postpone the next probe
+        *tracker.last_probe.lock().unwrap() = Instant::now() - AVAILABILITY_PROBE_INTERVAL;
+        tracker.mark_unavailable();
+        assert!(tracker.should_probe());
+
+        tracker.mark_available();
+        assert!(tracker.is_available());
+        assert!(!tracker.is_closed());
+        tracker.mark_closed();
+        assert!(tracker.is_closed());
+    }
+}
@@ -89,6 +89,60 @@ pub enum Status {
     Cancelled,
 }
 
+/// How far the copy into a deployment got. Since `vid`s have gaps, the
+/// counts are only estimates of the number of entity versions
+pub(crate) struct Progress {
+    /// The entity versions that have been copied
+    pub copied: i64,
+    /// The entity versions that need to be copied
+    pub target: i64,
+    pub finished: bool,
+    pub cancelled: bool,
+}
+
+/// How far the copy into `dst` got, or `None` if the node that the copy is
+/// assigned to has not started it yet
+pub(crate) fn progress(conn: &PgConnection, dst: &Site) -> Result<Option<Progress>, StoreError> {
+    use copy_state as cs;
+    use copy_table_state as cts;
+
+    let (finished, cancelled) = match cs::table
+        .filter(cs::dst.eq(dst.id))
+        .select((
+            cs::finished_at.is_not_null(),
+            cs::cancelled_at.is_not_null(),
+        ))
+        .first::<(bool, bool)>(conn)
+        .optional()?
+    {
+        Some(state) => state,
+        None => return Ok(None),
+    };
+    let tables = cts::table
+        .filter(cts::dst.eq(dst.id))
+        .select((cts::next_vid, cts::target_vid))
+        .load::<(i64, i64)>(conn)?;
+    let (copied, target) = tally(&tables);
+    Ok(Some(Progress {
+        copied,
+        target,
+        finished,
+        cancelled,
+    }))
+}
+
+/// The entity versions that have been copied and that need to be copied
+/// for tables whose copy got to `(next_vid, target_vid)`. A table is done
+/// once `next_vid` is past `target_vid`
+fn tally(tables: &[(i64, i64)]) -> (i64, i64) {
+    let copied = tables
+        .iter()
+        .map(|(next_vid, target_vid)| (*next_vid).min(target_vid + 1))
+        .sum();
+    let target = tables.iter().map(|(_, target_vid)| target_vid + 1).sum();
+    (copied, target)
+}
+
 #[allow(dead_code)]
 struct CopyState {
     src: Arc<Layout>,
@@ -684,3 +738,16 @@ impl Connection {
         res
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::tally;
+
+    #[test]
+    fn tallies_progress() {
+        assert_eq!((0, 0), tally(&[]));
+        // Not started, halfway, and done with a `next_vid` past the target
+        assert_eq!((50, 200), tally(&[(0, 99), (50, 99)]));
+        assert_eq!((150, 300), tally(&[(0, 99), (50, 99), (250, 99)]));
+    }
+}
@@ -16,6 +16,7 @@ use diesel::{
     sql_query,
     sql_types::{Nullable, Text},
 };
+use graph::data::schema_diff::SchemaCompatibility;
 use graph::data::subgraph::schema::SubgraphError;
 use graph::data::subgraph::{
     schema::{DeploymentCreate, SubgraphManifestEntity},
@@ -23,7 +24,7 @@ use graph::data::subgraph::{
 };
 use graph::prelude::{
     anyhow, bigdecimal::ToPrimitive, hex, web3::types::H256, BigDecimal, BlockNumber, BlockPtr,
-    DeploymentHash, DeploymentState, Schema, StoreError,
+    DeploymentHash, DeploymentState, NodeId, Schema, StoreError,
 };
 use stable_hash::crypto::SetHasher;
 use std::{collections::BTreeSet, convert::TryFrom, ops::Bound};
@@ -77,6 +78,7 @@ table! {
         last_healthy_ethereum_block_hash -> Nullable<Binary>,
         last_healthy_ethereum_block_number -> Nullable<Numeric>,
         entity_count -> Numeric,
+        entity_size -> Numeric,
         graft_base -> Nullable<Text>,
         graft_block_hash -> Nullable<Binary>,
         graft_block_number -> Nullable<Numeric>,
@@ -85,6 +87,9 @@ table! {
         current_reorg_depth -> Integer,
         max_reorg_depth -> Integer,
         firehose_cursor -> Nullable<Text>,
+        restart_count -> Integer,
+        writer -> Nullable<Text>,
+        writer_node -> Nullable<Text>,
     }
 }
 
@@ -111,6 +116,8 @@ table! {
         schema -> Text,
         graph_node_version_id -> Nullable<Integer>,
         use_bytea_prefix -> Bool,
+        schema_hash -> Nullable<Text>,
+        schema_compatibility -> Nullable<Text>,
     }
 }
 
@@ -231,6 +238,54 @@ pub fn schema(conn: &PgConnection, site: &Site) -> Result<(Schema, bool), StoreE
         .map(|schema| (schema, use_bytea_prefix))
 }
 
+/// Replace the GraphQL schema stored for the deployment with `schema`
+pub fn set_schema(conn: &PgConnection, site: &Site, schema: &Schema) -> Result<(), StoreError> {
+    use subgraph_manifest as sm;
+    update(sm::table.filter(sm::id.eq(site.id)))
+        .set((
+            sm::schema.eq(schema.document.to_string()),
+            sm::schema_hash.eq(schema.canonical_hash()),
+        ))
+        .execute(conn)?;
+    Ok(())
+}
+
+/// How the deployment's schema compares to the schema of the version it
+/// replaced, if that is known
+pub fn schema_compatibility(
+    conn: &PgConnection,
+    site: &Site,
+) -> Result<Option<SchemaCompatibility>, StoreError> {
+    use subgraph_manifest as sm;
+    sm::table
+        .select(sm::schema_compatibility)
+        .filter(sm::id.eq(site.id))
+        .first::<Option<String>>(conn)?
+        .map(|compatibility| {
+            SchemaCompatibility::from_str(&compatibility)
+                .map_err(|e| constraint_violation!("{}", e))
+        })
+        .transpose()
+}
+
+/// Record the canonical hash of the deployment's schema and how it
+/// compares to the schema of the version it replaces
+pub fn set_schema_hash(
+    conn: &PgConnection,
+    site: &Site,
+    hash: &str,
+    compatibility: Option<SchemaCompatibility>,
+) -> Result<(), StoreError> {
+    use subgraph_manifest as sm;
+    update(sm::table.filter(sm::id.eq(site.id)))
+        .set((
+            sm::schema_hash.eq(hash),
+            sm::schema_compatibility.eq(compatibility.as_ref().map(SchemaCompatibility::as_str)),
+        ))
+        .execute(conn)?;
+    Ok(())
+}
+
 pub fn manifest_info(
     conn: &PgConnection,
     site: &Site,
@@ -260,13 +315,18 @@ pub fn features(conn: &PgConnection, site: &Site) -> Result<BTreeSet<SubgraphFea
         .collect()
 }
 
+/// Move the block pointer of the deployment forward to `ptr` on behalf of
+/// `writer`. This must be the first change a block write makes: the update
+/// locks the deployment's row until the end of the transaction, which
+/// keeps claims and maintenance out until the block has been written, and
+/// it fails without writing anything if another writer claimed the
+/// deployment or the block has been processed already
 pub fn transact_block(
     conn: &PgConnection,
     site: &Site,
+    writer: &str,
     ptr: &BlockPtr,
     firehose_cursor: Option<&str>,
-    full_count_query: &str,
-    count: i32,
 ) -> Result<(), StoreError> {
     use crate::diesel::BoolExpressionMethods;
     use subgraph_deployment as d;
@@ -274,13 +334,6 @@ pub fn transact_block(
     // Work around a Diesel issue with serializing BigDecimals to numeric
     let number = format!("{}::numeric", ptr.number);
 
-    let count_sql = if count == 0 {
-        // This amounts to a noop - the entity count does not change
-        "entity_count".to_string()
-    } else {
-        entity_count_sql(full_count_query, count)
-    };
-
     // Treat a cursor of "" as null; not absolutely necessary for
     // correctness since the firehose treats both as the same, but makes it
     // a little clearer.
@@ -291,18 +344,20 @@ pub fn transact_block(
     };
 
     let row_count = update(
-        d::table.filter(d::id.eq(site.id)).filter(
-            // Asserts that the processing direction is forward.
-            d::latest_ethereum_block_number
-                .lt(sql(&number))
-                .or(d::latest_ethereum_block_number.is_null()),
-        ),
+        d::table
+            .filter(d::id.eq(site.id))
+            .filter(
+                // Asserts that the processing direction is forward.
+                d::latest_ethereum_block_number
+                    .lt(sql(&number))
+                    .or(d::latest_ethereum_block_number.is_null()),
+            )
+            .filter(d::writer.is_null().or(d::writer.eq(writer))),
     )
     .set((
         d::latest_ethereum_block_number.eq(sql(&number)),
         d::latest_ethereum_block_hash.eq(ptr.hash_slice()),
         d::firehose_cursor.eq(firehose_cursor),
-        d::entity_count.eq(sql(&count_sql)),
         d::current_reorg_depth.eq(0),
     ))
     .execute(conn)
@@ -312,16 +367,28 @@ pub fn transact_block(
         // Common case: A single row was updated.
         1 => Ok(()),
 
-        // No matching rows were found. This is an error. By the filter conditions, this can only be
-        // due to a missing deployment (which `block_ptr` catches) or duplicate block processing.
-        0 => match block_ptr(&conn, &site.deployment)? {
-            Some(block_ptr_from) if block_ptr_from.number >= ptr.number => Err(
-                StoreError::DuplicateBlockProcessing(site.deployment.clone(), ptr.number),
-            ),
-            None | Some(_) => Err(StoreError::Unknown(anyhow!(
-                "unknown error forwarding block ptr"
-            ))),
-        },
+        // No matching rows were found. This is an error. By the filter
+        // conditions, this can only be due to another writer, a missing
+        // deployment (which `block_ptr` catches) or duplicate block
+        // processing.
+        0 => {
+            let claim = d::table
+                .filter(d::id.eq(site.id))
+                .select((d::writer, d::writer_node))
+                .get_result::<(Option<String>, Option<String>)>(conn)
+                .optional()?;
+            if let Some((claimed, node)) = claim {
+                check_claim(site, writer, claimed, node)?;
+            }
+            match block_ptr(&conn, &site.deployment)? {
+                Some(block_ptr_from) if block_ptr_from.number >= ptr.number => Err(
+                    StoreError::DuplicateBlockProcessing(site.deployment.clone(), ptr.number),
+                ),
+                None | Some(_) => Err(StoreError::Unknown(anyhow!(
+                    "unknown error forwarding block ptr"
+                ))),
+            }
+        }
 
         // More than one matching row was found.
         _ => Err(StoreError::ConstraintViolation(
@@ -580,6 +647,94 @@ pub fn set_synced(conn: &PgConnection, id: &DeploymentHash) -> Result<(), StoreE
     Ok(())
 }
 
+pub fn record_restart(conn: &PgConnection, site: &Site) -> Result<(), StoreError> {
+    use subgraph_deployment as d;
+
+    update(d::table.filter(d::id.eq(site.id)))
+        .set(d::restart_count.eq(d::restart_count + 1))
+        .execute(conn)?;
+    Ok(())
+}
+
+/// Make `writer`, which runs on `node`, the only writable store that may
+/// write to the deployment. The claim is refused if a writer on another
+/// node holds it and the deployment is still assigned to that node
+/// (`assigned`), so that the first writer keeps the deployment until it is
+/// reassigned
+pub fn claim_writer(
+    conn: &PgConnection,
+    site: &Site,
+    writer: &str,
+    node: &NodeId,
+    assigned: Option<&NodeId>,
+) -> Result<(), StoreError> {
+    use subgraph_deployment as d;
+
+    let (claimed, claimed_node) = d::table
+        .filter(d::id.eq(site.id))
+        .select((d::writer, d::writer_node))
+        .for_update()
+        .get_result::<(Option<String>, Option<String>)>(conn)?;
+    if let (Some(claimed), Some(claimed_node)) = (claimed, claimed_node) {
+        let live = claimed != writer
+            && claimed_node != node.as_str()
+            && assigned.map(|assigned| assigned.as_str()) == Some(claimed_node.as_str());
+        if live {
+            return Err(StoreError::ConcurrentWriter(
+                site.deployment.clone(),
+                NodeId::new(claimed_node).ok(),
+            ));
+        }
+    }
+
+    update(d::table.filter(d::id.eq(site.id)))
+        .set((d::writer.eq(writer), d::writer_node.eq(node.as_str())))
+        .execute(conn)?;
+    Ok(())
+}
+
+/// Lock the deployment's row until the end of the current transaction.
+/// Block writes and claims need to update that row, and have to wait until
+/// then
+pub fn lock(conn: &PgConnection, site: &Site) -> Result<(), StoreError> {
+    use subgraph_deployment as d;
+
+    d::table
+        .filter(d::id.eq(site.id))
+        .select(d::id)
+        .for_update()
+        .get_result::<i32>(conn)?;
+    Ok(())
+}
+
+/// Lock the deployment's row like `lock` and check that `writer` is the
+/// writable store that last claimed the deployment
+pub fn lock_for_writer(conn: &PgConnection, site: &Site, writer: &str) -> Result<(), StoreError> {
+    use subgraph_deployment as d;
+
+    let (claimed, node) = d::table
+        .filter(d::id.eq(site.id))
+        .select((d::writer, d::writer_node))
+        .for_update()
+        .get_result::<(Option<String>, Option<String>)>(conn)?;
+    check_claim(site, writer, claimed, node)
+}
+
+fn check_claim(
+    site: &Site,
+    writer: &str,
+    claimed: Option<String>,
+    node: Option<String>,
+) -> Result<(), StoreError> {
+    match claimed {
+        Some(claimed) if claimed != writer => Err(StoreError::ConcurrentWriter(
+            site.deployment.clone(),
+            node.and_then(|node| NodeId::new(node).ok()),
+        )),
+        _ => Ok(()),
+    }
+}
+
 /// Returns `true` if the deployment (as identified by `site.id`)
 pub fn exists(conn: &PgConnection, site: &Site) -> Result<bool, StoreError> {
     use subgraph_deployment as d;
@@ -996,6 +1151,51 @@ fn entity_count_sql(full_count_query: &str, count: i32) -> String {
     )
 }
 
+/// Like `entity_count_sql`, but for the estimate of the deployment's size
+/// on disk. The estimate is seeded from the size Postgres reports for the
+/// deployment's tables, which does not require scanning them, and then
+/// grows by the estimated size of the rows that get written. Since rows
+/// that are reverted or removed only free up space once they are
+/// vacuumed, the estimate never shrinks; setting it to `-1` forces it to
+/// be recomputed
+fn entity_size_sql(full_size_query: &str, size: u64) -> String {
+    format!(
+        "coalesce((nullif(entity_size, -1)) + ({size}),
+                  ({full_size_query}))",
+        full_size_query = full_size_query,
+        size = size
+    )
+}
+
+/// Add `count` and `size` to the number of entities and the size of the
+/// deployment after writing a block
+pub fn update_entity_count_and_size(
+    conn: &PgConnection,
+    site: &Site,
+    full_count_query: &str,
+    count: i32,
+    full_size_query: &str,
+    size: u64,
+) -> Result<(), StoreError> {
+    use subgraph_deployment as d;
+
+    let count_sql = if count == 0 {
+        // This amounts to a noop - the entity count does not change
+        "entity_count".to_string()
+    } else {
+        entity_count_sql(full_count_query, count)
+    };
+    let size_sql = entity_size_sql(full_size_query, size);
+
+    update(d::table.filter(d::id.eq(site.id)))
+        .set((
+            d::entity_count.eq(sql(&count_sql)),
+            d::entity_size.eq(sql(&size_sql)),
+        ))
+        .execute(conn)?;
+    Ok(())
+}
+
 pub fn update_entity_count(
     conn: &PgConnection,
     site: &Site,
@@ -1015,6 +1215,20 @@ pub fn update_entity_count(
     Ok(())
 }
 
+/// The entity count and the estimated size in bytes of every deployment in
+/// the shard. The size is `None` for deployments that have not determined
+/// it yet
+pub fn entity_sizes(conn: &PgConnection) -> Result<Vec<(String, u64, Option<u64>)>, StoreError> {
+    use subgraph_deployment as d;
+
+    Ok(d::table
+        .select((d::deployment, d::entity_count, d::entity_size))
+        .load::<(String, BigDecimal, BigDecimal)>(conn)?
+        .into_iter()
+        .map(|(deployment, count, size)| (deployment, count.to_u64().unwrap_or(0), size.to_u64()))
+        .collect())
+}
+
 /// Set the deployment's entity count to whatever `full_count_query` produces
 pub fn set_entity_count(
     conn: &PgConnection,
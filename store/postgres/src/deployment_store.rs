@@ -4,7 +4,11 @@ use diesel::connection::SimpleConnection;
 use diesel::pg::PgConnection;
 use diesel::prelude::*;
 use diesel::r2d2::{ConnectionManager, PooledConnection};
-use graph::components::store::{EntityType, StoredDynamicDataSource};
+use graph::components::store::{
+    EntityType, ExportRequest, ExportedFile, OutboxEntry, StoredDynamicDataSource,
+};
+use graph::data::query::QueryExplanation;
+use graph::data::schema_diff::SchemaCompatibility;
 use graph::data::subgraph::status;
 use graph::prelude::{
     tokio, CancelHandle, CancelToken, CancelableError, EntityOperation, PoolWaitStats,
@@ -18,6 +22,7 @@ use std::convert::Into;
 use std::iter::FromIterator;
 use std::ops::Bound;
 use std::ops::Deref;
+use std::path::PathBuf;
 use std::sync::{atomic::AtomicUsize, Arc, Mutex};
 use std::time::Instant;
 
@@ -25,11 +30,12 @@ use graph::components::store::EntityCollection;
 use graph::components::subgraph::ProofOfIndexingFinisher;
 use graph::constraint_violation;
 use graph::data::subgraph::schema::{DeploymentCreate, SubgraphError, POI_OBJECT};
+use graph::prelude::serde_json;
 use graph::prelude::{
     anyhow, debug, info, o, warn, web3, ApiSchema, AttributeNames, BlockNumber, BlockPtr,
-    CheapClone, DeploymentHash, DeploymentState, Entity, EntityKey, EntityModification,
-    EntityQuery, Error, Logger, QueryExecutionError, Schema, StopwatchMetrics, StoreError,
-    StoreEvent, UnfailOutcome, Value, ENV_VARS,
+    CacheWeight, CheapClone, DeploymentHash, DeploymentState, Entity, EntityKey,
+    EntityModification, EntityQuery, Error, Logger, NodeId, QueryExecutionError, QueryPermit,
+    Schema, StopwatchMetrics, StoreError, StoreEvent, UnfailOutcome, Value, ENV_VARS,
 };
 use graph_graphql::prelude::api_schema;
 use web3::types::Address;
@@ -38,10 +44,17 @@ use crate::block_range::block_number;
 use crate::catalog;
 use crate::deployment;
 use crate::detail::ErrorDetail;
+use crate::primary::{DeploymentId, Site};
 use crate::relational::{Layout, LayoutCache, SqlName, Table};
 use crate::relational_queries::FromEntityData;
+use crate::snapshot::Snapshot;
+use crate::{checkpoint, dynds, journal, outbox};
 use crate::{connection_pool::ConnectionPool, detail};
-use crate::{dynds, primary::Site};
+
+/// The estimated number of bytes that a row takes up on disk in addition
+/// to the entity data in it: the tuple header, the `vid`, the block range
+/// or block number, and the entries for the row in the table's indexes
+const ROW_OVERHEAD_BYTES: u64 = 100;
 
 /// When connected to read replicas, this allows choosing which DB server to use for an operation.
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
@@ -161,6 +174,7 @@ impl DeploymentStore {
         site: Arc<Site>,
         graft_base: Option<Arc<Layout>>,
         replace: bool,
+        compatibility: Option<SchemaCompatibility>,
     ) -> Result<(), StoreError> {
         let conn = self.get_conn()?;
         conn.transaction(|| -> Result<_, StoreError> {
@@ -175,6 +189,12 @@ impl DeploymentStore {
                     exists,
                     replace,
                 )?;
+                deployment::set_schema_hash(
+                    &conn,
+                    &site,
+                    &schema.canonical_hash(),
+                    compatibility,
+                )?;
             };
 
             // Create the schema for the subgraph data
@@ -241,6 +261,174 @@ impl DeploymentStore {
         )
     }
 
+    pub(crate) fn explain_query(
+        &self,
+        conn: &PgConnection,
+        site: Arc<Site>,
+        query: EntityQuery,
+    ) -> Result<QueryExplanation, QueryExecutionError> {
+        let layout = self.layout(conn, site)?;
+        layout.explain(
+            conn,
+            query.collection,
+            query.filter,
+            query.order,
+            query.range,
+            query.block,
+            query.query_id,
+        )
+    }
+
+    pub(crate) fn execute_sql(
+        &self,
+        conn: &PgConnection,
+        site: Arc<Site>,
+        sql: &str,
+        block: BlockNumber,
+    ) -> Result<Vec<serde_json::Value>, QueryExecutionError> {
+        let layout = self.layout(conn, site)?;
+        crate::sql::execute(conn, &layout, sql, block)
+    }
+
+    /// Export the entities of the deployment as of the block in `request`,
+    /// or as of the subgraph head if it does not specify one. All tables
+    /// are read from the same snapshot of the database
+    pub(crate) fn export(
+        &self,
+        site: Arc<Site>,
+        request: &ExportRequest,
+    ) -> Result<Vec<ExportedFile>, StoreError> {
+        let conn = self.get_conn()?;
+        let layout = self.layout(&conn, site.clone())?;
+        let block = match request.block {
+            Some(block) => block,
+            None => Self::block_ptr_with_conn(&conn, site.clone())?
+                .map(|ptr| ptr.number)
+                .ok_or_else(|| {
+                    constraint_violation!("deployment {} has not started syncing", site.deployment)
+                })?,
+        };
+        conn.build_transaction()
+            .read_only()
+            .repeatable_read()
+            .run(|| crate::export::export(&conn, &layout, request, block))
+    }
+
+    /// The block as of which `request` exports the entities of the
+    /// deployment, and the entity types it exports in the order in which
+    /// `export` writes them
+    pub(crate) fn export_plan(
+        &self,
+        site: Arc<Site>,
+        request: &ExportRequest,
+    ) -> Result<(BlockNumber, Vec<String>), StoreError> {
+        let conn = self.get_conn()?;
+        let layout = self.layout(&conn, site.clone())?;
+        let block = match request.block {
+            Some(block) => block,
+            None => Self::block_ptr_with_conn(&conn, site.clone())?
+                .map(|ptr| ptr.number)
+                .ok_or_else(|| {
+                    constraint_violation!("deployment {} has not started syncing", site.deployment)
+                })?,
+        };
+        let entity_types = crate::export::tables(&layout, request)?
+            .into_iter()
+            .map(|table| table.object.to_string())
+            .collect();
+        Ok((block, entity_types))
+    }
+
+    /// How far copying into `site` got, or `None` if copying has not
+    /// started
+    pub(crate) fn copy_progress(
+        &self,
+        site: &Site,
+    ) -> Result<Option<crate::copy::Progress>, StoreError> {
+        let conn = self.get_conn()?;
+        crate::copy::progress(&conn, site)
+    }
+
+    /// Write a snapshot of the deployment into the directory `destination`.
+    /// Like exports, snapshots are read from one snapshot of the database
+    pub(crate) fn snapshot(
+        &self,
+        site: Arc<Site>,
+        destination: &str,
+    ) -> Result<Snapshot, StoreError> {
+        let conn = self.get_conn()?;
+        let layout = self.layout(&conn, site)?;
+        conn.build_transaction()
+            .read_only()
+            .repeatable_read()
+            .run(|| crate::snapshot::create(&conn, &layout, destination))
+    }
+
+    /// Fill the deployment, which must have just been created, with the
+    /// data and metadata from the snapshot in `source`
+    pub(crate) fn restore(
+        &self,
+        site: Arc<Site>,
+        source: &str,
+        snapshot: &Snapshot,
+    ) -> Result<(), StoreError> {
+        let conn = self.get_conn()?;
+        let layout = self.layout(&conn, site)?;
+        crate::snapshot::restore(&conn, &layout, source, snapshot)
+    }
+
+    /// Turn the change journal for the deployment on, starting after the
+    /// block it is at now; `node` writes the journal
+    pub(crate) fn start_change_journal(
+        &self,
+        site: &Site,
+        node: &NodeId,
+    ) -> Result<bool, StoreError> {
+        let conn = self.get_conn()?;
+        let head = deployment::block_ptr(&conn, &site.deployment)?
+            .map(|ptr| ptr.number)
+            .unwrap_or(-1);
+        journal::start(&conn, site, head, node)
+    }
+
+    /// The deployments in this shard whose change journal `node` writes
+    pub(crate) fn change_journals(&self, node: &NodeId) -> Result<Vec<DeploymentId>, StoreError> {
+        let conn = self.get_conn()?;
+        journal::written_by(&conn, node)
+    }
+
+    pub(crate) fn stop_change_journal(&self, site: &Site) -> Result<bool, StoreError> {
+        let conn = self.get_conn()?;
+        journal::stop(&conn, site)
+    }
+
+    /// Write the next batch of the change journal of the deployment into
+    /// `dir`. The rows of the batch all come from the same snapshot of the
+    /// database, even though the deployment keeps indexing
+    pub(crate) fn write_change_journal(
+        &self,
+        site: Arc<Site>,
+        dir: &str,
+        reorg_threshold: BlockNumber,
+    ) -> Result<Option<PathBuf>, StoreError> {
+        let conn = self.get_conn()?;
+        let layout = self.layout(&conn, site)?;
+        conn.build_transaction()
+            .repeatable_read()
+            .run(|| journal::write_next(&conn, &layout, dir, reorg_threshold))
+    }
+
+    pub(crate) fn apply_change_journal(
+        &self,
+        site: Arc<Site>,
+        dir: &str,
+        ptr: &BlockPtr,
+    ) -> Result<usize, StoreError> {
+        let conn = self.get_conn()?;
+        let layout = self.layout(&conn, site)?;
+        conn.transaction(|| journal::restore(&conn, &layout, dir, ptr))
+    }
+
     fn check_interface_entity_uniqueness(
         &self,
         conn: &PgConnection,
@@ -294,9 +482,21 @@ impl DeploymentStore {
         mods: &[EntityModification],
         ptr: &BlockPtr,
         stopwatch: &StopwatchMetrics,
-    ) -> Result<i32, StoreError> {
+    ) -> Result<(i32, u64), StoreError> {
         use EntityModification::*;
         let mut count = 0;
+        // Every insert and overwrite adds a row version, and removals only
+        // close the block range of existing rows, so only they change how
+        // much space the subgraph takes up
+        let size = mods
+            .iter()
+            .map(|modification| match modification {
+                Insert { data, .. } | Overwrite { data, .. } => {
+                    ROW_OVERHEAD_BYTES + data.weight() as u64
+                }
+                Remove { .. } => 0,
+            })
+            .sum();
 
         // Group `Insert`s and `Overwrite`s by key, and accumulate `Remove`s.
         let mut inserts = HashMap::new();
@@ -350,7 +550,7 @@ impl DeploymentStore {
                 stopwatch,
             )? as i32;
         }
-        Ok(count)
+        Ok((count, size))
     }
 
     fn insert_entities<'a>(
@@ -487,12 +687,13 @@ impl DeploymentStore {
     pub(crate) async fn query_permit(
         &self,
         replica: ReplicaId,
-    ) -> tokio::sync::OwnedSemaphorePermit {
+        deployment: &DeploymentHash,
+    ) -> Result<QueryPermit, QueryExecutionError> {
         let pool = match replica {
             ReplicaId::Main => &self.pool,
             ReplicaId::ReadOnly(idx) => &self.read_only_pools[idx],
         };
-        pool.query_permit().await
+        pool.deployment_query_permit(deployment).await
     }
 
     pub(crate) fn wait_stats(&self, replica: ReplicaId) -> PoolWaitStats {
@@ -614,6 +815,227 @@ impl DeploymentStore {
         conn.transaction(|| deployment::set_synced(&conn, id))
     }
 
+    pub(crate) fn record_restart(&self, site: &Site) -> Result<(), StoreError> {
+        let conn = self.get_conn()?;
+        deployment::record_restart(&conn, site)
+    }
+
+    pub(crate) fn schema_compatibility(
+        &self,
+        site: &Site,
+    ) -> Result<Option<SchemaCompatibility>, StoreError> {
+        let conn = self.get_conn()?;
+        deployment::schema_compatibility(&conn, site)
+    }
+
+    /// Change the tables of the deployment in place so that they match
+    /// `schema`. This is only possible if `schema` merely adds entity
+    /// types, enums, or nullable attributes to the deployment's current
+    /// schema; for anything else, the deployment has to be synced again
+    /// The current layout of the deployment and its layout for `schema`,
+    /// provided the deployment can be migrated to `schema`
+    fn migration_layouts(
+        &self,
+        conn: &PgConnection,
+        site: Arc<Site>,
+        schema: &Schema,
+    ) -> Result<(Arc<Layout>, Layout), StoreError> {
+        let base = self.layout(conn, site.cheap_clone())?;
+        let layout = Layout::new(site.cheap_clone(), schema, base.catalog.clone())?;
+
+        let errors = layout.can_migrate_from(&base);
+        if !errors.is_empty() {
+            return Err(StoreError::Unknown(anyhow!(
+                "The deployment `{}` cannot be migrated to the new schema \
+                 because the schemas are incompatible:\n    - {}",
+                site.deployment,
+                errors.join("\n    - ")
+            )));
+        }
+        Ok((base, layout))
+    }
+
+    pub(crate) fn migrate_schema(
+        &self,
+        site: Arc<Site>,
+        schema: &Schema,
+    ) -> Result<(), StoreError> {
+        let ddl_error =
+            |_| StoreError::Unknown(anyhow!("failed to generate DDL for schema migration"));
+
+        let conn = self.get_conn()?;
+
+        // New values for existing enums have to be added outside of a
+        // transaction, one statement at a time. Adding them again when
+        // the migration is retried does nothing
+        let (base, layout) = self.migration_layouts(&conn, site.cheap_clone(), schema)?;
+        for stmt in layout.enum_values_ddl(&base).map_err(ddl_error)? {
+            conn.batch_execute(&stmt)?;
+        }
+
+        conn.transaction(|| -> Result<_, StoreError> {
+            deployment::lock(&conn, &site)?;
+            let (base, layout) = self.migration_layouts(&conn, site.cheap_clone(), schema)?;
+            let sql = layout.migration_ddl(&base).map_err(ddl_error)?;
+            if !sql.is_empty() {
+                conn.batch_execute(&sql)?;
+            }
+            deployment::set_schema(&conn, &site, schema)
+        })?;
+
+        self.layout_cache.remove(&site);
+        self.subgraph_cache.lock().unwrap().remove(&site.deployment);
+        Ok(())
+    }
+
+    /// Partition the mutable tables of the deployment that have at least
+    /// `min_rows` rows by the block at which entity versions were closed,
+    /// with history partitions of `blocks` blocks each. Tables that are
+    /// already partitioned get new history partitions up to the current
+    /// head of the deployment. Return the names of the tables that were
+    /// changed
+    ///
+    /// Each table is rewritten in its own transaction, and writes to the
+    /// deployment are only blocked while that one table is rewritten. If
+    /// rewriting a table fails, the tables before it stay partitioned
+    pub(crate) fn partition(
+        &self,
+        site: Arc<Site>,
+        blocks: BlockNumber,
+        min_rows: i64,
+    ) -> Result<Vec<String>, StoreError> {
+        if blocks <= 0 {
+            return Err(StoreError::Unknown(anyhow!(
+                "partitions must span at least one block"
+            )));
+        }
+
+        let conn = self.get_conn()?;
+        let layout = self.layout(&conn, site.cheap_clone())?;
+        let estimates = catalog::row_estimates(&conn, &site.namespace)?;
+        let ddl_error = |_: std::fmt::Error| {
+            StoreError::Unknown(anyhow!("failed to generate DDL for partitions"))
+        };
+
+        let mut tables = layout.tables.values().collect::<Vec<_>>();
+        tables.sort_by_key(|table| table.position);
+        let tables = tables.into_iter().filter(|table| {
+            !table.immutable
+                && (table.partitioned
+                    || estimates.get(table.name.as_str()).copied().unwrap_or(0) >= min_rows)
+        });
+
+        let mut changed = Vec::new();
+        let mut result = Ok(());
+        for table in tables {
+            let partitioned = conn.transaction(|| -> Result<_, StoreError> {
+                deployment::lock(&conn, &site)?;
+                let head = deployment::block_ptr(&conn, &site.deployment)?
+                    .map(|ptr| ptr.number)
+                    .unwrap_or(0);
+                let sql = if table.partitioned {
+                    let partitions = catalog::partitions(&conn, &site.namespace, &table.name)?;
+                    let from = partitions
+                        .iter()
+                        .filter_map(|partition| table.history_partition_range(partition))
+                        .map(|(_, to)| to)
+                        .max()
+                        .unwrap_or(0);
+                    if from > head {
+                        return Ok(false);
+                    }
+                    table
+                        .extend_partitions_ddl(&layout, from, head, blocks)
+                        .map_err(ddl_error)?
+                } else {
+                    let qualified = format!("{}.{}", site.namespace, table.name.quoted());
+                    let sequence = catalog::vid_sequence(&conn, &qualified)?;
+                    table
+                        .partition_ddl(&layout, &sequence, head, blocks)
+                        .map_err(ddl_error)?
+                };
+                conn.batch_execute(&sql)?;
+                Ok(true)
+            });
+            match partitioned {
+                Ok(true) => changed.push(table.name.to_string()),
+                Ok(false) => { /* nothing to do for this table */ }
+                Err(e) => {
+                    result = Err(e);
+                    break;
+                }
+            }
+        }
+
+        // Tables before a failed one were changed, too
+        self.layout_cache.remove(&site);
+        result.map(|()| changed)
+    }
+
+    /// Drop the history partitions of the partitioned tables of the
+    /// deployment that only contain entity versions that were closed
+    /// before block `before`. Queries for blocks before the end of the
+    /// last dropped partition fail afterwards since their results would
+    /// be wrong. Return the names of the partitions that were dropped
+    pub(crate) fn prune_partitions(
+        &self,
+        site: Arc<Site>,
+        before: BlockNumber,
+    ) -> Result<Vec<String>, StoreError> {
+        let conn = self.get_conn()?;
+        let dropped = conn.transaction(|| -> Result<_, StoreError> {
+            deployment::lock(&conn, &site)?;
+            let head = deployment::block_ptr(&conn, &site.deployment)?
+                .map(|ptr| ptr.number)
+                .unwrap_or(0);
+            // Reverts need the history of the blocks that can still be
+            // reverted
+            if before > head - ENV_VARS.reorg_threshold {
+                return Err(StoreError::Unknown(anyhow!(
+                    "can not prune history after block {} since the deployment is at block {} \
+                     and blocks up to {} blocks back can be reverted",
+                    head - ENV_VARS.reorg_threshold,
+                    head,
+                    ENV_VARS.reorg_threshold
+                )));
+            }
+
+            let layout = self.layout(&conn, site.cheap_clone())?;
+            let mut tables = layout.tables.values().collect::<Vec<_>>();
+            tables.sort_by_key(|table| table.position);
+            let mut dropped = Vec::new();
+            // Versions that were closed before the end of a dropped
+            // partition are gone, and queries for blocks before that
+            // would not see them
+            let mut pruned_before = None;
+            for table in tables.into_iter().filter(|table| table.partitioned) {
+                let partitions = catalog::partitions(&conn, &site.namespace, &table.name)?;
+                let sql = table
+                    .prune_partitions_ddl(&layout, &partitions, before)
+                    .map_err(|_| {
+                        StoreError::Unknown(anyhow!("failed to generate DDL for pruning"))
+                    })?;
+                conn.batch_execute(&sql)?;
+                for partition in partitions {
+                    match table.history_partition_range(&partition) {
+                        Some((_, to)) if to <= before => {
+                            pruned_before = pruned_before.max(Some(to));
+                            dropped.push(partition);
+                        }
+                        _ => { /* partition is kept */ }
+                    }
+                }
+            }
+            if let Some(pruned_before) = pruned_before {
+                checkpoint::record_pruned(&conn, &site, pruned_before)?;
+            }
+            Ok(dropped)
+        })?;
+
+        self.layout_cache.remove(&site);
+        Ok(dropped)
+    }
+
     // Only used for tests
     #[cfg(debug_assertions)]
     pub(crate) fn drop_deployment_schema(
@@ -640,6 +1062,8 @@ impl DeploymentStore {
         delete from subgraphs.copy_table_state;
         delete from subgraphs.copy_state;
         delete from active_copies;
+        delete from jobs;
+        delete from subgraphs.entity_change_outbox;
     ";
 
         let conn = self.get_conn()?;
@@ -648,6 +1072,11 @@ impl DeploymentStore {
         Ok(())
     }
 
+    pub(crate) async fn entity_sizes(&self) -> Result<Vec<(String, u64, Option<u64>)>, StoreError> {
+        self.with_conn(|conn, _| deployment::entity_sizes(conn).map_err(|e| e.into()))
+            .await
+    }
+
     pub(crate) async fn vacuum(&self) -> Result<(), StoreError> {
         self.with_conn(|conn, _| {
             conn.batch_execute("vacuum (analyze) subgraphs.subgraph_deployment")?;
@@ -752,6 +1181,89 @@ impl DeploymentStore {
         })
         .await
     }
+
+    /// Create the indexes that the current index strategy calls for on the
+    /// tables of the deployment that are missing or invalid, and then drop
+    /// the generated indexes that it does not use anymore. Indexes are built
+    /// concurrently where possible so that the deployment can keep
+    /// indexing. Return the names of the indexes that were created and of
+    /// the ones that were dropped
+    pub(crate) async fn upgrade_indexes(
+        &self,
+        site: Arc<Site>,
+    ) -> Result<(Vec<String>, Vec<String>), StoreError> {
+        let store = self.clone();
+        self.with_conn(move |conn, _| {
+            let layout = store.layout(conn, site.cheap_clone())?;
+            let nsp = site.namespace.as_str();
+            let mut tables = layout.tables.values().collect::<Vec<_>>();
+            tables.sort_by_key(|table| table.position);
+
+            let mut created = Vec::new();
+            let mut dropped = Vec::new();
+            for table in tables {
+                let existing = catalog::index_names(conn, &site.namespace, &table.name)?;
+                let wanted = table.index_ddl(&layout).map_err(|_| {
+                    StoreError::Unknown(anyhow!("failed to generate DDL for indexes"))
+                })?;
+                // Indexes on partitioned tables can not be built or dropped
+                // concurrently
+                let concurrently = if table.partitioned {
+                    ""
+                } else {
+                    "concurrently "
+                };
+
+                for (name, sql) in &wanted {
+                    if existing.contains(name) {
+                        if catalog::check_index_is_valid(conn, nsp, name)? {
+                            continue;
+                        }
+                        // An earlier concurrent build that failed left an
+                        // invalid index behind that Postgres does not use;
+                        // build it again
+                        conn.batch_execute(&format!(
+                            "drop index {}if exists {}.\"{}\"",
+                            concurrently, nsp, name
+                        ))?;
+                    }
+                    let sql = sql.replacen(
+                        "create index ",
+                        &format!("create index {}if not exists ", concurrently),
+                        1,
+                    );
+                    // This might take a long time.
+                    conn.batch_execute(&sql)?;
+                    if !catalog::check_index_is_valid(conn, nsp, name)? {
+                        conn.batch_execute(&format!(
+                            "drop index {}if exists {}.\"{}\"",
+                            concurrently, nsp, name
+                        ))?;
+                        return Err(StoreError::Unknown(anyhow!(
+                            "building the index {}.{} failed",
+                            nsp,
+                            name
+                        ))
+                        .into());
+                    }
+                    created.push(name.clone());
+                }
+
+                let obsolete = existing.into_iter().filter(|name| {
+                    table.is_generated_index(name) && !wanted.iter().any(|(w, _)| w == name)
+                });
+                for name in obsolete {
+                    conn.batch_execute(&format!(
+                        "drop index {}{}.\"{}\"",
+                        concurrently, nsp, name
+                    ))?;
+                    dropped.push(name);
+                }
+            }
+            Ok((created, dropped))
+        })
+        .await
+    }
 }
 
 /// Methods that back the trait `graph::components::Store`, but have small
@@ -936,6 +1448,53 @@ impl DeploymentStore {
         Ok(changes)
     }
 
+    pub(crate) fn deliver_entity_changes(
+        &self,
+        shard: &str,
+        limit: usize,
+        deliver: &mut dyn FnMut(&[OutboxEntry]) -> Result<(), Error>,
+    ) -> Result<usize, StoreError> {
+        // Delivering can take a while; we do not hold on to a connection
+        // while that happens
+        let entries = outbox::claim(&*self.get_conn()?, shard, limit)?;
+        if entries.is_empty() {
+            return Ok(0);
+        }
+
+        let ids: Vec<_> = entries.iter().map(|entry| entry.id).collect();
+        match deliver(&entries) {
+            Ok(()) => {
+                outbox::remove(&*self.get_conn()?, ids)?;
+                Ok(entries.len())
+            }
+            Err(e) => {
+                outbox::release(&*self.get_conn()?, ids)?;
+                Err(StoreError::Unknown(e))
+            }
+        }
+    }
+
+    pub(crate) fn changed_blocks(
+        &self,
+        conn: &PgConnection,
+        site: Arc<Site>,
+        after: BlockNumber,
+        until: BlockNumber,
+    ) -> Result<Vec<BlockNumber>, StoreError> {
+        let layout = self.layout(conn, site)?;
+        layout.changed_blocks(conn, after, until)
+    }
+
+    pub(crate) fn entity_changes(
+        &self,
+        conn: &PgConnection,
+        site: Arc<Site>,
+        block: BlockNumber,
+    ) -> Result<Vec<EntityOperation>, StoreError> {
+        let layout = self.layout(conn, site)?;
+        layout.entity_changes(conn, block)
+    }
+
     // Only used by tests
     #[cfg(debug_assertions)]
     pub(crate) fn find(
@@ -950,6 +1509,7 @@ impl DeploymentStore {
     pub(crate) fn transact_block_operations(
         &self,
         site: Arc<Site>,
+        writer: &str,
         block_ptr_to: &BlockPtr,
         firehose_cursor: Option<&str>,
         mods: &[EntityModification],
@@ -974,7 +1534,21 @@ impl DeploymentStore {
             self.get_conn()?
         };
 
-        let event = conn.transaction(|| -> Result<_, StoreError> {
+        let (event, checkpoint_due) = conn.transaction(|| -> Result<_, StoreError> {
+            // Whether the block needs a checkpoint has to be decided before
+            // the block pointer moves. We only record the checkpoint in this
+            // transaction; its entities are copied once the block has been
+            // written
+            let checkpoint_due = match ENV_VARS.store.entity_checkpoint_interval {
+                Some(interval) => {
+                    let prev = deployment::block_ptr(&conn, &site.deployment)?;
+                    checkpoint::is_due(prev.map(|ptr| ptr.number), block_ptr_to.number, interval)
+                }
+                None => false,
+            };
+
+            deployment::transact_block(&conn, &site, writer, block_ptr_to, firehose_cursor)?;
+
             // Emit a store event for the changes we are about to make. We
             // wait with sending it until we have done all our other work
             // so that we do not hold a lock on the notification queue
@@ -984,7 +1558,7 @@ impl DeploymentStore {
             // Make the changes
             let layout = self.layout(&conn, site.clone())?;
             let section = stopwatch.start_section("apply_entity_modifications");
-            let count = self.apply_entity_modifications(
+            let (count, size) = self.apply_entity_modifications(
                 &conn,
                 layout.as_ref(),
                 mods,
@@ -993,8 +1567,16 @@ impl DeploymentStore {
             )?;
             section.end();
 
+            if ENV_VARS.entity_change_outbox_enabled() {
+                outbox::insert(&conn, &site, block_ptr_to.number, mods)?;
+            }
+
             dynds::insert(&conn, &site.deployment, data_sources, block_ptr_to)?;
 
+            if checkpoint_due {
+                checkpoint::add(&conn, &site, block_ptr_to)?;
+            }
+
             if !deterministic_errors.is_empty() {
                 deployment::insert_subgraph_errors(
                     &conn,
@@ -1004,21 +1586,63 @@ impl DeploymentStore {
                 )?;
             }
 
-            deployment::transact_block(
+            deployment::update_entity_count_and_size(
                 &conn,
                 &site,
-                block_ptr_to,
-                firehose_cursor,
                 layout.count_query.as_str(),
                 count,
+                layout.size_query.as_str(),
+                size,
             )?;
 
-            Ok(event)
+            Ok((event, checkpoint_due))
         })?;
 
+        if checkpoint_due {
+            let store = self.clone();
+            graph::spawn_thread("entity-checkpoint", move || {
+                if let Err(e) = store.fill_checkpoints(site.cheap_clone()) {
+                    error!(store.logger, "Failed to fill entity checkpoint";
+                           "sgd" => site.id, "error" => e.to_string());
+                }
+            });
+        }
+
         Ok(event)
     }
 
+    /// Copy the entities of the checkpoints of the deployment that have
+    /// been recorded but not filled yet
+    fn fill_checkpoints(&self, site: Arc<Site>) -> Result<(), StoreError> {
+        let conn = self.get_conn()?;
+        let layout = self.layout(&conn, site.cheap_clone())?;
+        checkpoint::fill(
+            &conn,
+            &site,
+            &layout,
+            ENV_VARS.store.entity_checkpoint_interval,
+        )
+    }
+
+    /// Record a checkpoint of the entities of the deployment at its
+    /// current block and copy its entities
+    pub(crate) fn create_checkpoint(&self, site: Arc<Site>) -> Result<BlockPtr, StoreError> {
+        let conn = self.get_conn()?;
+        let ptr = conn.transaction(|| -> Result<_, StoreError> {
+            deployment::lock(&conn, &site)?;
+            let ptr = deployment::block_ptr(&conn, &site.deployment)?.ok_or_else(|| {
+                StoreError::Unknown(anyhow!(
+                    "deployment `{}` has not written any blocks yet",
+                    site.deployment
+                ))
+            })?;
+            checkpoint::add(&conn, &site, &ptr)?;
+            Ok(ptr)
+        })?;
+        self.fill_checkpoints(site)?;
+        Ok(ptr)
+    }
+
     fn rewind_with_conn(
         &self,
         conn: &PgConnection,
@@ -1044,6 +1668,7 @@ impl DeploymentStore {
             }
 
             deployment::revert_block_ptr(conn, &site.deployment, block_ptr_to.clone())?;
+            checkpoint::forget_after(conn, &site, block_ptr_to.number)?;
 
             if let Some(cursor) = firehose_cursor {
                 deployment::update_firehose_cursor(conn, &site.deployment, cursor)
@@ -1059,7 +1684,11 @@ impl DeploymentStore {
             // The revert functions want the number of the first block that we need to get rid of
             let block = block + 1;
 
-            let (event, count) = layout.revert_block(conn, block)?;
+            let (event, count, reverted) = layout.revert_block(conn, block)?;
+
+            if ENV_VARS.entity_change_outbox_enabled() {
+                outbox::insert_reverted(conn, &site, &layout, block_ptr_to.number, reverted)?;
+            }
 
             // Revert the meta data changes that correspond to this subgraph.
             // Only certain meta data changes need to be reverted, most
@@ -1099,15 +1728,75 @@ impl DeploymentStore {
             );
         }
 
+        // The history before a pruned block is gone, and we can only get
+        // there by restoring a checkpoint
+        if let Some(pruned_before) = checkpoint::pruned_before(&conn, &site)? {
+            if block_ptr_to.number < pruned_before {
+                return self.rewind_to_checkpoint(&conn, site, block_ptr_to);
+            }
+        }
+
         // When rewinding, we reset the firehose cursor to the empty string. That way, on resume,
         // Firehose will start from the block_ptr instead (with sanity check to ensure it's resume
         // at the exact block).
         self.rewind_with_conn(&conn, site, block_ptr_to, Some(""))
     }
 
+    /// Rewind to the latest checkpoint at or before `block_ptr_to`, from
+    /// which the deployment then indexes forward again
+    fn rewind_to_checkpoint(
+        &self,
+        conn: &PgConnection,
+        site: Arc<Site>,
+        block_ptr_to: BlockPtr,
+    ) -> Result<StoreEvent, StoreError> {
+        conn.transaction(|| -> Result<_, StoreError> {
+            deployment::lock(conn, &site)?;
+            let ptr = checkpoint::latest_at_or_before(conn, &site, block_ptr_to.number)?
+                .ok_or_else(|| {
+                    StoreError::Unknown(anyhow!(
+                        "can not rewind deployment `{}` to block {} since its history \
+                         has been pruned and there is no checkpoint at or before that \
+                         block; the deployment needs to be resynced",
+                        site.deployment,
+                        block_ptr_to.number
+                    ))
+                })?;
+
+            let info = self.subgraph_info_with_conn(conn, site.as_ref())?;
+            if let Some(graft_block) = info.graft_block {
+                if graft_block > ptr.number {
+                    return Err(anyhow!(
+                        "Can not revert subgraph `{}` to the checkpoint at block {} as it \
+                        was grafted at block {} and reverting past a graft point \
+                        is not possible",
+                        site.deployment.clone(),
+                        ptr.number,
+                        graft_block
+                    )
+                    .into());
+                }
+            }
+
+            deployment::revert_block_ptr(conn, &site.deployment, ptr.clone())?;
+            deployment::update_firehose_cursor(conn, &site.deployment, "")
+                .context("updating firehose cursor")?;
+
+            let layout = self.layout(conn, site.clone())?;
+            let event = checkpoint::restore(conn, &site, &layout, &ptr)?;
+            Layout::revert_metadata(conn, &site.deployment, ptr.number + 1)?;
+            deployment::set_entity_count(conn, site.as_ref(), layout.count_query.as_str())?;
+
+            info!(self.logger, "Rewound deployment to a checkpoint";
+                  "sgd" => site.id, "block" => ptr.number, "requested_block" => block_ptr_to.number);
+            Ok(event)
+        })
+    }
+
     pub(crate) fn revert_block_operations(
         &self,
         site: Arc<Site>,
+        writer: &str,
         block_ptr_to: BlockPtr,
         firehose_cursor: Option<&str>,
     ) -> Result<StoreEvent, StoreError> {
@@ -1120,7 +1809,25 @@ impl DeploymentStore {
             panic!("revert_block_operations must revert only backward, you are trying to revert forward going from subgraph block {} to new block {}", deployment_head, block_ptr_to);
         }
 
-        self.rewind_with_conn(&conn, site, block_ptr_to, firehose_cursor)
+        conn.transaction(|| {
+            deployment::lock_for_writer(&conn, &site, writer)?;
+            self.rewind_with_conn(&conn, site, block_ptr_to, firehose_cursor)
+        })
+    }
+
+    /// Make `writer`, which runs on `node`, the only writable store that
+    /// may write to `site`. Fails if a writer on the node `assigned`, the
+    /// node the deployment is assigned to, claimed it already. A writer
+    /// that claimed the deployment earlier fails its next write
+    pub(crate) fn claim_writer(
+        &self,
+        site: &Site,
+        writer: &str,
+        node: &NodeId,
+        assigned: Option<&NodeId>,
+    ) -> Result<(), StoreError> {
+        let conn = self.get_conn()?;
+        conn.transaction(|| deployment::claim_writer(&conn, site, writer, node, assigned))
     }
 
     pub(crate) async fn deployment_state_from_id(
@@ -1144,6 +1851,11 @@ impl DeploymentStore {
         Ok(())
     }
 
+    /// Check that we can connect to the main database of this shard
+    pub(crate) fn check_connection(&self) -> bool {
+        self.pool.check()
+    }
+
     pub(crate) fn replica_for_query(
         &self,
         for_subscription: bool,
@@ -1302,6 +2014,7 @@ impl DeploymentStore {
     pub(crate) fn unfail_deterministic_error(
         &self,
         site: Arc<Site>,
+        writer: &str,
         current_ptr: &BlockPtr,
         parent_ptr: &BlockPtr,
     ) -> Result<UnfailOutcome, StoreError> {
@@ -1350,7 +2063,12 @@ impl DeploymentStore {
                     // We reset the firehose cursor to the empty string. That way, on resume,
                     // Firehose will start from the block_ptr instead (with sanity checks to ensure it's resuming
                     // at the correct block).
-                    let _ = self.revert_block_operations(site.clone(), parent_ptr.clone(), Some(""))?;
+                    let _ = self.revert_block_operations(
+                        site.clone(),
+                        writer,
+                        parent_ptr.clone(),
+                        Some(""),
+                    )?;
 
                     // Unfail the deployment.
                     deployment::update_deployment_status(conn, deployment_id, prev_health, None)?;
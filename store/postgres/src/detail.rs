@@ -9,14 +9,18 @@ use diesel::prelude::{
 };
 use diesel_derives::Associations;
 use git_testament::{git_testament, git_testament_macros};
+use graph::data::schema_diff::SchemaCompatibility;
 use graph::data::subgraph::schema::{SubgraphError, SubgraphManifestEntity};
+use graph::data::subgraph::SubgraphFeature;
 use graph::prelude::{
     bigdecimal::ToPrimitive, BigDecimal, BlockPtr, DeploymentHash, StoreError,
     SubgraphDeploymentEntity,
 };
 use graph::{constraint_violation, data::subgraph::status, prelude::web3::types::H256};
 use itertools::Itertools;
+use std::collections::HashMap;
 use std::convert::TryFrom;
+use std::str::FromStr;
 use std::{ops::Bound, sync::Arc};
 
 use crate::deployment::{
@@ -55,6 +59,7 @@ pub struct DeploymentDetail {
     last_healthy_ethereum_block_hash: Option<Bytes>,
     last_healthy_ethereum_block_number: Option<BigDecimal>,
     pub entity_count: BigDecimal,
+    pub entity_size: BigDecimal,
     graft_base: Option<String>,
     graft_block_hash: Option<Bytes>,
     graft_block_number: Option<BigDecimal>,
@@ -63,6 +68,9 @@ pub struct DeploymentDetail {
     current_reorg_depth: i32,
     max_reorg_depth: i32,
     firehose_cursor: Option<String>,
+    restart_count: i32,
+    writer: Option<String>,
+    writer_node: Option<String>,
 }
 
 #[derive(Queryable, QueryableByName)]
@@ -170,12 +178,17 @@ pub(crate) fn block(
     }
 }
 
+/// The features, schema hash and schema compatibility of a deployment
+type ManifestDetail = (Vec<String>, Option<String>, Option<String>);
+
 pub(crate) fn info_from_details(
     detail: DeploymentDetail,
     fatal: Option<ErrorDetail>,
     non_fatal: Vec<ErrorDetail>,
+    manifest: ManifestDetail,
     sites: &[Arc<Site>],
 ) -> Result<status::Info, StoreError> {
+    let (features, schema_hash, schema_compatibility) = manifest;
     let DeploymentDetail {
         id,
         deployment,
@@ -189,6 +202,8 @@ pub(crate) fn info_from_details(
         latest_ethereum_block_hash,
         latest_ethereum_block_number,
         entity_count,
+        entity_size,
+        restart_count,
         graft_base: _,
         graft_block_hash: _,
         graft_block_number: _,
@@ -228,11 +243,23 @@ pub(crate) fn info_from_details(
             deployment
         )
     })?;
+    // A size of -1 means that it has not been determined yet
+    let entity_size = entity_size.to_u64();
     let fatal_error = fatal.map(SubgraphError::try_from).transpose()?;
     let non_fatal_errors = non_fatal
         .into_iter()
         .map(SubgraphError::try_from)
         .collect::<Result<Vec<SubgraphError>, StoreError>>()?;
+    let features = features
+        .iter()
+        .map(|feature| SubgraphFeature::from_str(feature).map_err(StoreError::from))
+        .collect::<Result<_, _>>()?;
+    let schema_compatibility = schema_compatibility
+        .map(|compatibility| {
+            SchemaCompatibility::from_str(&compatibility)
+                .map_err(|e| constraint_violation!("{}", e))
+        })
+        .transpose()?;
 
     // 'node' needs to be filled in later from a different shard
     Ok(status::Info {
@@ -244,7 +271,14 @@ pub(crate) fn info_from_details(
         non_fatal_errors,
         chains: vec![chain],
         entity_count,
+        entity_size,
+        restart_count: restart_count as u64,
         node: None,
+        features,
+        available: true,
+        write_queue: None,
+        schema_hash,
+        schema_compatibility,
     })
 }
 
@@ -272,6 +306,7 @@ pub(crate) fn deployment_statuses(
 ) -> Result<Vec<status::Info>, StoreError> {
     use subgraph_deployment as d;
     use subgraph_error as e;
+    use subgraph_manifest as sm;
 
     // First, we fetch all deployment information along with any fatal errors.
     // Subsequently, we fetch non-fatal errors and we group them by deployment
@@ -312,11 +347,28 @@ pub(crate) fn deployment_statuses(
         .into_group_map()
     };
 
+    let mut manifests: HashMap<DeploymentId, ManifestDetail> = {
+        let query = sm::table.select((
+            sm::id,
+            (sm::features, sm::schema_hash, sm::schema_compatibility),
+        ));
+        if sites.is_empty() {
+            query.load(conn)?
+        } else {
+            query
+                .filter(sm::id.eq_any(sites.iter().map(|site| site.id)))
+                .load(conn)?
+        }
+        .into_iter()
+        .collect()
+    };
+
     details_with_fatal_error
         .into_iter()
         .map(|(detail, fatal)| {
             let non_fatal = non_fatal_errors.remove(&detail.id).unwrap_or(vec![]);
-            info_from_details(detail, fatal, non_fatal, sites)
+            let manifest = manifests.remove(&detail.id).unwrap_or_default();
+            info_from_details(detail, fatal, non_fatal, manifest, sites)
         })
         .collect()
 }
@@ -336,6 +388,8 @@ struct StoredSubgraphManifest {
     schema: String,
     graph_node_version_id: Option<i32>,
     use_bytea_prefix: bool,
+    schema_hash: Option<String>,
+    schema_compatibility: Option<String>,
 }
 
 impl From<StoredSubgraphManifest> for SubgraphManifestEntity {
@@ -3,9 +3,10 @@
 use diesel::{
     delete,
     dsl::{count, sql},
-    prelude::{ExpressionMethods, QueryDsl, RunQueryDsl},
+    prelude::{BoolExpressionMethods, ExpressionMethods, QueryDsl, RunQueryDsl},
     sql_query,
-    sql_types::{Integer, Text},
+    sql_types::{Binary, Integer, Nullable, Text},
+    update,
 };
 use diesel::{insert_into, pg::PgConnection};
 
@@ -34,6 +35,7 @@ table! {
         ethereum_block_number -> Numeric,
         deployment -> Text,
         context -> Nullable<Text>,
+        removed_block -> Nullable<Integer>,
     }
 }
 
@@ -82,6 +84,11 @@ pub fn load(
             decds::ethereum_block_number,
         ))
         .filter(decds::ethereum_block_number.le(sql(&format!("{}::numeric", block))))
+        .filter(
+            decds::removed_block
+                .is_null()
+                .or(decds::removed_block.gt(block)),
+        )
         .order_by((decds::ethereum_block_number, decds::vid))
         .load::<(
             i64,
@@ -102,6 +109,7 @@ pub fn load(
             source,
             context,
             creation_block,
+            removed_block: None,
         };
 
         if data_sources.last().and_then(|d| d.creation_block) > data_source.creation_block {
@@ -115,6 +123,8 @@ pub fn load(
     Ok(data_sources)
 }
 
+/// Add the data sources in `data_sources` that were created and mark the
+/// ones that were removed as removed
 pub(crate) fn insert(
     conn: &PgConnection,
     deployment: &DeploymentHash,
@@ -123,12 +133,19 @@ pub(crate) fn insert(
 ) -> Result<usize, StoreError> {
     use dynamic_ethereum_contract_data_source as decds;
 
-    if data_sources.is_empty() {
+    // Data sources that were created and removed in the same block have to
+    // be inserted before they can be removed
+    let (removed, created): (Vec<_>, Vec<_>) = data_sources
+        .iter()
+        .partition(|ds| ds.removed_block.is_some());
+
+    if created.is_empty() {
         // Avoids a roundtrip to the DB.
+        remove(conn, deployment, &removed)?;
         return Ok(0);
     }
 
-    let dds: Vec<_> = data_sources
+    let dds: Vec<_> = created
         .into_iter()
         .map(|ds| {
             let StoredDynamicDataSource {
@@ -141,6 +158,7 @@ pub(crate) fn insert(
                     },
                 context,
                 creation_block: _,
+                removed_block: _,
             } = ds;
             let address = match address {
                 Some(address) => address.as_bytes().to_vec(),
@@ -164,10 +182,59 @@ pub(crate) fn insert(
         })
         .collect::<Result<_, _>>()?;
 
-    insert_into(decds::table)
-        .values(dds)
-        .execute(conn)
-        .map_err(|e| e.into())
+    let count = insert_into(decds::table).values(dds).execute(conn)?;
+    remove(conn, deployment, &removed)?;
+    Ok(count)
+}
+
+/// Mark the stored data sources that are the same as the ones in
+/// `data_sources` as removed in the block in their `removed_block`
+fn remove(
+    conn: &PgConnection,
+    deployment: &DeploymentHash,
+    data_sources: &[&StoredDynamicDataSource],
+) -> Result<(), StoreError> {
+    // The context is compared as `jsonb` since the order of its entries is
+    // arbitrary
+    const QUERY: &str = "\
+      update subgraphs.dynamic_ethereum_contract_data_source
+         set removed_block = $1
+       where deployment = $2
+         and name = $3
+         and address is not distinct from $4
+         and ethereum_block_number = $5::numeric
+         and context::jsonb is not distinct from $6::jsonb
+         and removed_block is null";
+
+    for ds in data_sources {
+        let address = ds.source.address.map(|address| address.as_bytes().to_vec());
+        let creation_block = ds.creation_block.ok_or_else(|| {
+            constraint_violation!(
+                "only dynamic data sources can be removed, but `{}` is not one",
+                ds.name
+            )
+        })?;
+        let count = sql_query(QUERY)
+            .bind::<Nullable<Integer>, _>(ds.removed_block)
+            .bind::<Text, _>(deployment.as_str())
+            .bind::<Text, _>(ds.name.as_str())
+            .bind::<Nullable<Binary>, _>(address)
+            .bind::<Integer, _>(creation_block)
+            .bind::<Nullable<Text>, _>(ds.context.as_deref())
+            .execute(conn)?;
+        // A data source that stays active in the store would be started
+        // again when the deployment restarts
+        if count != 1 {
+            return Err(constraint_violation!(
+                "removing data source `{}` created at block {} of {} changed {} rows instead of one",
+                ds.name,
+                creation_block,
+                deployment,
+                count
+            ));
+        }
+    }
+    Ok(())
 }
 
 /// Copy the dynamic data sources for `src` to `dst`. All data sources that
@@ -200,10 +267,11 @@ pub(crate) fn copy(
         "\
       insert into subgraphs.dynamic_ethereum_contract_data_source(name,
              address, abi, start_block, ethereum_block_hash,
-             ethereum_block_number, deployment, context)
+             ethereum_block_number, deployment, context, removed_block)
       select e.name, e.address, e.abi, e.start_block,
              e.ethereum_block_hash, e.ethereum_block_number, $2 as deployment,
-             e.context
+             e.context,
+             case when e.removed_block <= $3 then e.removed_block end
         from {src_nsp}.dynamic_ethereum_contract_data_source e
        where e.deployment = $1
          and e.ethereum_block_number <= $3",
@@ -226,6 +294,9 @@ pub(crate) fn revert(
 
     let dds = decds::table.filter(decds::deployment.eq(id.as_str()));
     delete(dds.filter(decds::ethereum_block_number.ge(sql(&block.to_string())))).execute(conn)?;
+    update(dds.filter(decds::removed_block.ge(block)))
+        .set(decds::removed_block.eq(None::<BlockNumber>))
+        .execute(conn)?;
     Ok(())
 }
 
@@ -0,0 +1,190 @@
+//! Export the entities of a deployment as of a block to CSV files, one
+//! file per entity type. Tables are read in batches ordered by `vid` so
+//! that the memory we need does not depend on the size of the table, and
+//! each file only gets its final name once it is complete
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use diesel::pg::PgConnection;
+use diesel::sql_types::{Array, BigInt, Nullable, Text};
+use diesel::{sql_query, RunQueryDsl};
+use graph::components::store::{ExportFormat, ExportRequest, ExportedFile};
+use graph::prelude::{anyhow, BlockNumber, StoreError};
+
+use crate::block_range::{BLOCK_COLUMN, BLOCK_RANGE_COLUMN};
+use crate::relational::{ColumnType, Layout, Table};
+
+/// The number of rows we read from the database at once
+const BATCH_SIZE: i64 = 10_000;
+
+/// Export the tables from `layout` that `request` asks for as of `block`
+pub fn export(
+    conn: &PgConnection,
+    layout: &Layout,
+    request: &ExportRequest,
+    block: BlockNumber,
+) -> Result<Vec<ExportedFile>, StoreError> {
+    let ExportFormat::Csv = request.format;
+
+    if request.destination.contains("://") {
+        return Err(StoreError::Unknown(anyhow!(
+            "exports can only be written to a local directory, not to `{}`",
+            request.destination
+        )));
+    }
+
+    let tables = tables(layout, request)?;
+    let dir = Path::new(&request.destination);
+    fs::create_dir_all(dir).map_err(|e| StoreError::Unknown(e.into()))?;
+    tables
+        .into_iter()
+        .map(|table| {
+            // Write to a temporary file first so that a file with the
+            // final name is always a complete export
+            let path = dir.join(format!("{}.csv", table.object));
+            let tmp = dir.join(format!("{}.csv.tmp", table.object));
+            let rows = export_table(conn, table, block, &tmp)
+                .and_then(|rows| {
+                    fs::rename(&tmp, &path)?;
+                    Ok(rows)
+                })
+                .map_err(|e| {
+                    fs::remove_file(&tmp).ok();
+                    StoreError::Unknown(e.context(path.display().to_string()))
+                })?;
+            Ok(ExportedFile {
+                entity_type: table.object.to_string(),
+                path: path.display().to_string(),
+                rows,
+            })
+        })
+        .collect()
+}
+
+/// The tables from `layout` that `request` asks for, ordered by entity
+/// type
+pub(crate) fn tables<'a>(
+    layout: &'a Layout,
+    request: &ExportRequest,
+) -> Result<Vec<&'a Table>, StoreError> {
+    let mut tables: Vec<&Table> = layout
+        .tables
+        .values()
+        .filter(|table| !table.object.is_poi())
+        .map(|table| table.as_ref())
+        .collect();
+    if !request.entity_types.is_empty() {
+        for entity_type in &request.entity_types {
+            if !tables
+                .iter()
+                .any(|table| table.object.as_str() == entity_type)
+            {
+                return Err(StoreError::UnknownTable(entity_type.clone()));
+            }
+        }
+        tables.retain(|table| request.entity_types.contains(&table.object.to_string()));
+    }
+    tables.sort_by(|a, b| a.object.as_str().cmp(b.object.as_str()));
+    Ok(tables)
+}
+
+fn export_table(
+    conn: &PgConnection,
+    table: &Table,
+    block: BlockNumber,
+    path: &Path,
+) -> Result<usize, anyhow::Error> {
+    #[derive(QueryableByName)]
+    struct Row {
+        #[sql_type = "BigInt"]
+        vid: i64,
+        #[sql_type = "Array<Nullable<Text>>"]
+        data: Vec<Option<String>>,
+    }
+
+    let columns: Vec<_> = table
+        .columns
+        .iter()
+        .filter(|column| !column.is_fulltext())
+        .collect();
+    let values = columns
+        .iter()
+        .map(|column| match column.column_type {
+            ColumnType::Bytes if !column.is_list() => {
+                format!("'0x' || encode(\"{}\", 'hex')", column.name)
+            }
+            ColumnType::Bytes => format!(
+                "case when \"{0}\" is null then null \
+                      else array(select '0x' || encode(b, 'hex') \
+                                   from unnest(\"{0}\") with ordinality as u(b, i) \
+                                  order by i)::text end",
+                column.name
+            ),
+            _ => format!("\"{}\"::text", column.name),
+        })
+        .collect::<Vec<_>>()
+        .join(", ");
+    let visible = if table.immutable {
+        format!("\"{}\" <= {}", BLOCK_COLUMN, block)
+    } else {
+        format!("{} @> {}", BLOCK_RANGE_COLUMN, block)
+    };
+    let query = format!(
+        "select vid, array[{}] as data from {} \
+          where {} and vid > $1 order by vid limit {}",
+        values,
+        table.source(),
+        visible,
+        BATCH_SIZE
+    );
+
+    let mut out = BufWriter::new(File::create(path)?);
+    let header = columns
+        .iter()
+        .map(|column| csv_field(&column.field))
+        .collect::<Vec<_>>()
+        .join(",");
+    writeln!(out, "{}", header)?;
+
+    let mut count = 0;
+    let mut last_vid = -1;
+    loop {
+        let rows = sql_query(&query)
+            .bind::<BigInt, _>(last_vid)
+            .load::<Row>(conn)?;
+        for row in &rows {
+            let line = row
+                .data
+                .iter()
+                .map(|value| value.as_deref().map(csv_field).unwrap_or_default())
+                .collect::<Vec<_>>()
+                .join(",");
+            writeln!(out, "{}", line)?;
+        }
+        count += rows.len();
+        match rows.last() {
+            Some(row) if rows.len() as i64 == BATCH_SIZE => last_vid = row.vid,
+            _ => break,
+        }
+    }
+    out.flush()?;
+    Ok(count)
+}
+
+/// Quote `value` for use in a CSV file if it needs quoting
+fn csv_field(value: &str) -> String {
+    if value.contains(|c| matches!(c, ',' | '"' | '\n' | '\r')) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+#[test]
+fn quotes_csv_fields() {
+    assert_eq!("plain", csv_field("plain"));
+    assert_eq!("\"a,b\"", csv_field("a,b"));
+    assert_eq!("\"say \"\"hi\"\"\"", csv_field("say \"hi\""));
+    assert_eq!("\"two\nlines\"", csv_field("two\nlines"));
+}
@@ -0,0 +1,384 @@
+//! Long-running operations on deployments that run as jobs. Jobs are kept
+//! in `public.jobs` in the primary. Index nodes claim queued jobs and work
+//! on them in steps, and record after each step how far they got. A job
+//! stays with the node that claimed it, and continues from its last step
+//! when that node restarts; jobs of index nodes that stopped sending
+//! heartbeats go back into the queue if `GRAPH_NODE_HEARTBEAT_TIMEOUT` is
+//! set. Cancelling a running job takes effect before its next step
+use std::convert::TryFrom;
+
+use graph::components::store::{DeploymentId as GraphDeploymentId, DeploymentLocator};
+use graph::components::store::{ExportRequest, JobRequest};
+use graph::constraint_violation;
+use graph::data::subgraph::status::JobState;
+use graph::prelude::serde::{de::DeserializeOwned, Deserialize, Serialize};
+use graph::prelude::{
+    anyhow, chrono, error, info, serde_json, BlockNumber, BlockPtr, Logger, NodeId, StoreError,
+    SubgraphStore as _, ENV_VARS,
+};
+
+use crate::primary::DeploymentId;
+use crate::{Shard, SubgraphStore};
+
+/// Paused deployments are assigned to the node they were assigned to
+/// with this prefix
+const PAUSED: &str = "paused_";
+
+/// How long to wait after pausing a deployment before rewinding it so
+/// that the node that indexes it has stopped
+const PAUSE_WAIT_SECS: i64 = 10;
+
+/// A job that a node has claimed
+pub struct QueuedJob {
+    pub id: i32,
+    pub site: DeploymentId,
+    pub request: JobRequest,
+    pub progress: Option<serde_json::Value>,
+    pub cancel_requested: bool,
+}
+
+/// How far a job got: `state` is whatever the job needs to take its next
+/// step, and `done` and `total` are what is reported as its progress
+struct Progress {
+    state: serde_json::Value,
+    done: i64,
+    total: Option<i64>,
+}
+
+impl Progress {
+    fn new<T: Serialize>(state: &T, done: i64, total: Option<i64>) -> Result<Self, StoreError> {
+        let state = serde_json::to_value(state)
+            .map_err(|e| constraint_violation!("can not serialize job progress: {}", e))?;
+        Ok(Progress { state, done, total })
+    }
+}
+
+enum Step {
+    /// The job can take its next step right away
+    Continue(Progress),
+    /// The job has to wait before it can take its next step
+    Wait(Progress),
+    /// The job is finished; the string summarizes what it did
+    Done(String),
+}
+
+/// The stages of a copy job. The job records that it is creating the
+/// copy before it does, so that it looks for the copy instead of creating
+/// another one if its node goes away in the middle
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "stage", rename_all = "lowercase")]
+enum CopyState {
+    Creating,
+    /// The job created the copy `dst`
+    Copying {
+        dst: GraphDeploymentId,
+    },
+}
+
+/// The stages of a rewind job. `node` is the node the deployment was
+/// assigned to before it was paused, if it was assigned to one. The node
+/// is recorded before the deployment is paused so that the job can
+/// always assign the deployment back to it
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "stage", rename_all = "lowercase")]
+enum RewindState {
+    Pausing {
+        node: Option<String>,
+    },
+    Paused {
+        node: Option<String>,
+        /// When the deployment was paused, in seconds since the epoch
+        /// according to the database
+        paused_at: i64,
+    },
+    Rewound {
+        node: Option<String>,
+    },
+}
+
+/// An export job exports the entity types in `remaining` one at a time,
+/// all as of `block`
+#[derive(Serialize, Deserialize)]
+struct ExportState {
+    block: BlockNumber,
+    remaining: Vec<String>,
+    entity_types: usize,
+    rows: usize,
+}
+
+/// Check for jobs of unresponsive nodes, then take the next steps of the
+/// jobs that are running on `node` and of the oldest job from the queue
+pub fn run(store: &SubgraphStore, logger: &Logger, node: &NodeId) -> Result<(), StoreError> {
+    let pconn = store.primary_conn()?;
+    if let Some(timeout) = ENV_VARS.node_heartbeat_timeout {
+        let timeout = chrono::Duration::from_std(timeout)
+            .map_err(|e| constraint_violation!("invalid heartbeat timeout: {}", e))?;
+        let (_, unresponsive) = pconn.index_nodes_by_heartbeat(timeout)?;
+        for id in pconn.requeue_jobs(&unresponsive)? {
+            info!(logger, "Put job of unresponsive node back into the queue"; "job" => id);
+        }
+    }
+
+    let mut jobs = pconn.running_jobs(node)?;
+    if let Some(job) = pconn.claim_job(node)? {
+        info!(logger, "Starting job"; "job" => job.id, "kind" => job.request.kind());
+        jobs.push(job);
+    }
+    for job in jobs {
+        let (id, kind) = (job.id, job.request.kind());
+        if let Err(e) = work(store, logger, job) {
+            error!(logger, "Job failed"; "job" => id, "kind" => kind, "error" => e.to_string());
+            pconn.finish_job(id, JobState::Failed, None, Some(&e.to_string()))?;
+        }
+    }
+    Ok(())
+}
+
+/// Take steps of `job` until it is finished or has to wait
+fn work(store: &SubgraphStore, logger: &Logger, mut job: QueuedJob) -> Result<(), StoreError> {
+    loop {
+        if job.cancel_requested {
+            stop(store, &job)?;
+            info!(logger, "Cancelled job"; "job" => job.id);
+            return store
+                .primary_conn()?
+                .finish_job(job.id, JobState::Cancelled, None, None);
+        }
+        let (progress, wait) = match step(store, &job)? {
+            Step::Continue(progress) => (progress, false),
+            Step::Wait(progress) => (progress, true),
+            Step::Done(outcome) => {
+                info!(logger, "Finished job"; "job" => job.id, "outcome" => &outcome);
+                return store.primary_conn()?.finish_job(
+                    job.id,
+                    JobState::Succeeded,
+                    Some(&outcome),
+                    None,
+                );
+            }
+        };
+        job.cancel_requested = store.primary_conn()?.record_job_progress(
+            job.id,
+            &progress.state,
+            progress.done,
+            progress.total,
+        )?;
+        job.progress = Some(progress.state);
+        if wait && !job.cancel_requested {
+            return Ok(());
+        }
+    }
+}
+
+/// The state that the last step of `job` recorded, or `None` if the job
+/// has not taken a step yet
+fn state<T: DeserializeOwned>(job: &QueuedJob) -> Result<Option<T>, StoreError> {
+    job.progress
+        .clone()
+        .map(serde_json::from_value)
+        .transpose()
+        .map_err(|e| constraint_violation!("invalid progress for job {}: {}", job.id, e))
+}
+
+fn block_ptr(hash: &str, number: BlockNumber) -> Result<BlockPtr, StoreError> {
+    BlockPtr::try_from((hash, number as i64))
+        .map_err(|e| constraint_violation!("invalid block {}:{}: {}", hash, number, e))
+}
+
+fn node_id(node: &str) -> Result<NodeId, StoreError> {
+    NodeId::new(node).map_err(|()| constraint_violation!("invalid node id `{}`", node))
+}
+
+/// The current time in seconds since the epoch according to the
+/// database, so that the clocks of different nodes do not matter
+fn now(store: &SubgraphStore) -> Result<i64, StoreError> {
+    store.primary_conn()?.now()
+}
+
+fn step(store: &SubgraphStore, job: &QueuedJob) -> Result<Step, StoreError> {
+    let site = store.find_site(job.site)?;
+    let loc = DeploymentLocator::from(site.as_ref());
+    match &job.request {
+        JobRequest::Copy {
+            shard,
+            node,
+            block_hash,
+            block_number,
+        } => match state::<CopyState>(job)? {
+            None => Ok(Step::Continue(Progress::new(
+                &CopyState::Creating,
+                0,
+                None,
+            )?)),
+            Some(CopyState::Creating) => {
+                // If the node that ran the job went away after it set up
+                // the copy, the copy is already there and assigned
+                let shard = Shard::new(shard.clone())?;
+                let dst = match store.locate_in_shard(&loc.hash, shard.clone())? {
+                    Some(dst) if dst.id != loc.id && store.assigned_node(&dst)?.is_some() => dst,
+                    _ => store.copy_deployment(
+                        &loc,
+                        shard,
+                        node_id(node)?,
+                        block_ptr(block_hash, *block_number)?,
+                    )?,
+                };
+                Ok(Step::Wait(Progress::new(
+                    &CopyState::Copying { dst: dst.id },
+                    0,
+                    None,
+                )?))
+            }
+            Some(CopyState::Copying { dst }) => {
+                let state = CopyState::Copying { dst };
+                let dst = store.find_site(dst.into())?;
+                let progress = store.for_site(dst.as_ref())?.copy_progress(dst.as_ref())?;
+                match progress {
+                    None => Ok(Step::Wait(Progress::new(&state, 0, None)?)),
+                    Some(progress) if progress.cancelled => Err(StoreError::Unknown(anyhow!(
+                        "copying into {} was cancelled",
+                        dst.namespace
+                    ))),
+                    Some(progress) if progress.finished => {
+                        Ok(Step::Done(format!("copied {} to {}", loc, dst.namespace)))
+                    }
+                    Some(progress) => Ok(Step::Wait(Progress::new(
+                        &state,
+                        progress.copied,
+                        Some(progress.target),
+                    )?)),
+                }
+            }
+        },
+        JobRequest::Prune { before } => {
+            let dropped = store.prune_partitions(&loc, *before)?;
+            Ok(Step::Done(format!("dropped {} partitions", dropped.len())))
+        }
+        JobRequest::Rewind {
+            block_hash,
+            block_number,
+        } => match state::<RewindState>(job)? {
+            None => {
+                let node = store
+                    .assigned_node(&loc)?
+                    .filter(|node| !node.as_str().starts_with(PAUSED));
+                let state = RewindState::Pausing {
+                    node: node.map(|node| node.to_string()),
+                };
+                Ok(Step::Continue(Progress::new(&state, 0, Some(3))?))
+            }
+            Some(RewindState::Pausing { node }) => {
+                if let Some(node) = &node {
+                    store.reassign_subgraph(&loc, &node_id(&format!("{}{}", PAUSED, node))?)?;
+                }
+                let state = RewindState::Paused {
+                    node,
+                    paused_at: now(store)?,
+                };
+                Ok(Step::Wait(Progress::new(&state, 1, Some(3))?))
+            }
+            Some(RewindState::Paused { node, paused_at }) => {
+                if node.is_some() && now(store)? - paused_at < PAUSE_WAIT_SECS {
+                    let state = RewindState::Paused { node, paused_at };
+                    return Ok(Step::Wait(Progress::new(&state, 1, Some(3))?));
+                }
+                store.rewind(
+                    site.deployment.clone(),
+                    block_ptr(block_hash, *block_number)?,
+                )?;
+                Ok(Step::Continue(Progress::new(
+                    &RewindState::Rewound { node },
+                    2,
+                    Some(3),
+                )?))
+            }
+            Some(RewindState::Rewound { node }) => {
+                if let Some(node) = node {
+                    store.reassign_subgraph(&loc, &node_id(&node)?)?;
+                }
+                Ok(Step::Done(format!(
+                    "rewound {} to block {}",
+                    loc, block_number
+                )))
+            }
+        },
+        JobRequest::Export(request) => {
+            let deployment_store = store.for_site(site.as_ref())?;
+            match state::<ExportState>(job)? {
+                None => {
+                    let (block, remaining) = deployment_store.export_plan(site.clone(), request)?;
+                    let state = ExportState {
+                        block,
+                        entity_types: remaining.len(),
+                        remaining,
+                        rows: 0,
+                    };
+                    Ok(Step::Continue(Progress::new(
+                        &state,
+                        0,
+                        Some(state.entity_types as i64),
+                    )?))
+                }
+                Some(mut state) if !state.remaining.is_empty() => {
+                    let request = ExportRequest {
+                        entity_types: vec![state.remaining.remove(0)],
+                        block: Some(state.block),
+                        ..request.clone()
+                    };
+                    for file in deployment_store.export(site.clone(), &request)? {
+                        state.rows += file.rows;
+                    }
+                    let done = state.entity_types - state.remaining.len();
+                    Ok(Step::Continue(Progress::new(
+                        &state,
+                        done as i64,
+                        Some(state.entity_types as i64),
+                    )?))
+                }
+                Some(state) => Ok(Step::Done(format!(
+                    "exported {} rows of {} entity types as of block {} to {}",
+                    state.rows, state.entity_types, state.block, request.destination
+                ))),
+            }
+        }
+    }
+}
+
+/// Undo what is needed to leave the deployment of the cancelled `job` in
+/// a usable state. Copies that were created stay around but stop copying
+fn stop(store: &SubgraphStore, job: &QueuedJob) -> Result<(), StoreError> {
+    match &job.request {
+        JobRequest::Copy { shard, .. } => {
+            let dst = match state::<CopyState>(job)? {
+                None => None,
+                Some(CopyState::Creating) => {
+                    let site = store.find_site(job.site)?;
+                    store
+                        .locate_in_shard(&site.deployment, Shard::new(shard.clone())?)?
+                        .filter(|dst| dst.id != DeploymentLocator::from(site.as_ref()).id)
+                        .map(|dst| store.find_site(dst.id.into()))
+                        .transpose()?
+                }
+                Some(CopyState::Copying { dst }) => Some(store.find_site(dst.into())?),
+            };
+            if let Some(dst) = dst {
+                store.primary_conn()?.cancel_copy(dst.as_ref())?;
+            }
+        }
+        JobRequest::Rewind { .. } => {
+            let node = match state::<RewindState>(job)? {
+                Some(RewindState::Pausing { node })
+                | Some(RewindState::Paused { node, .. })
+                | Some(RewindState::Rewound { node }) => node,
+                None => None,
+            };
+            if let Some(node) = node {
+                let site = store.find_site(job.site)?;
+                store
+                    .reassign_subgraph(&DeploymentLocator::from(site.as_ref()), &node_id(&node)?)?;
+            }
+        }
+        JobRequest::Prune { .. } | JobRequest::Export(_) => {}
+    }
+    Ok(())
+}
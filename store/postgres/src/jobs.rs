@@ -1,17 +1,20 @@
 //! Jobs for database maintenance
 use std::collections::HashMap;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 use std::time::{Duration, Instant};
 
 use async_trait::async_trait;
 use diesel::{prelude::RunQueryDsl, sql_query, sql_types::Double};
 
-use graph::prelude::{error, Logger, MetricsRegistry, StoreError, ENV_VARS};
-use graph::prometheus::Gauge;
+use graph::prelude::{
+    error, info, BlockNumber, CheapClone, DeploymentHash, Logger, MetricsRegistry, NodeId,
+    StoreError, ENV_VARS,
+};
+use graph::prometheus::{CounterVec, Gauge, GaugeVec, Registry};
 use graph::util::jobs::{Job, Runner};
 
 use crate::connection_pool::ConnectionPool;
-use crate::{unused, Store, SubgraphStore};
+use crate::{job_queue, unused, BlockStore, DeploymentLoad, NodeRole, Store, SubgraphStore};
 
 pub fn register(
     runner: &mut Runner,
@@ -25,20 +28,118 @@ pub fn register(
     );
 
     runner.register(
-        Arc::new(NotificationQueueUsage::new(primary_pool, registry)),
+        Arc::new(NotificationQueueUsage::new(
+            primary_pool,
+            registry.cheap_clone(),
+        )),
         Duration::from_secs(60),
     );
 
+    runner.register(
+        Arc::new(EntitySizeUsage::new(
+            store.subgraph_store(),
+            registry.cheap_clone(),
+        )),
+        Duration::from_secs(5 * 60),
+    );
+
     runner.register(
         Arc::new(MirrorPrimary::new(store.subgraph_store())),
         Duration::from_secs(15 * 60),
     );
 
+    runner.register(
+        Arc::new(BlockTimesJob::new(store.block_store())),
+        Duration::from_secs(60),
+    );
+
     // Remove unused deployments every 2 hours
     runner.register(
         Arc::new(UnusedJob::new(store.subgraph_store())),
         Duration::from_secs(2 * 60 * 60),
-    )
+    );
+
+    if let Some(interval) = ENV_VARS.store.maintenance_interval {
+        runner.register(
+            Arc::new(MaintenanceJob::new(store.subgraph_store(), registry)),
+            interval,
+        );
+    }
+}
+
+/// Register the jobs that let other nodes know that `node` is alive, and,
+/// for index nodes, take over the deployments of index nodes that are not
+/// alive anymore if `GRAPH_NODE_HEARTBEAT_TIMEOUT` is set
+pub fn register_heartbeat(
+    runner: &mut Runner,
+    store: Arc<SubgraphStore>,
+    node: NodeId,
+    role: NodeRole,
+) {
+    runner.register(
+        Arc::new(HeartbeatJob::new(store.cheap_clone(), node, role)),
+        ENV_VARS.node_heartbeat_interval,
+    );
+
+    if let (NodeRole::Index, Some(timeout)) = (role, ENV_VARS.node_heartbeat_timeout) {
+        runner.register(
+            Arc::new(ReassignJob::new(store, timeout)),
+            ENV_VARS.node_heartbeat_interval,
+        );
+    }
+}
+
+/// Register the job that records which deployments this node answered
+/// queries for, so that they are not removed as unused. Meant for all nodes
+pub fn register_query_activity(runner: &mut Runner, store: Arc<SubgraphStore>) {
+    runner.register(
+        Arc::new(QueryActivityJob::new(store)),
+        Duration::from_secs(5 * 60),
+    );
+}
+
+/// Register the job that records how busy the deployments that `node`
+/// indexes keep it, and, if `GRAPH_REBALANCE_INTERVAL` is set, the job
+/// that evens out the load across index nodes. Only meant for index nodes
+pub fn register_load(
+    runner: &mut Runner,
+    store: Arc<SubgraphStore>,
+    prometheus: Arc<Registry>,
+    node: NodeId,
+) {
+    runner.register(
+        Arc::new(DeploymentLoadJob::new(
+            store.cheap_clone(),
+            prometheus,
+            node,
+        )),
+        Duration::from_secs(60),
+    );
+
+    if let Some(interval) = ENV_VARS.rebalance_interval {
+        runner.register(Arc::new(RebalanceJob::new(store)), interval);
+    }
+}
+
+/// Register the job that works on the jobs in the queue of long-running
+/// operations, if `GRAPH_JOB_QUEUE_INTERVAL` is not 0. Only meant for
+/// index nodes, and for a runner of its own since jobs can take a while
+pub fn register_job_queue(runner: &mut Runner, store: Arc<SubgraphStore>, node: NodeId) {
+    if let Some(interval) = ENV_VARS.job_queue_interval {
+        runner.register(Arc::new(JobQueueJob::new(store, node)), interval);
+    }
+}
+
+/// Register the job that writes the change journals of the deployments
+/// that `node` indexes into `GRAPH_CHANGE_JOURNAL_DIR`, if that is set.
+/// Only meant for index nodes
+pub fn register_change_journal(runner: &mut Runner, store: Arc<SubgraphStore>, node: NodeId) {
+    if let Some(dir) = &ENV_VARS.store.change_journal_dir {
+        runner.register(
+            Arc::new(ChangeJournalJob::new(store, node, dir.clone())),
+            ENV_VARS.store.change_journal_interval,
+        );
+    }
 }
 
 /// A job that vacuums `subgraphs.subgraph_deployment`. With a large number
@@ -127,6 +228,156 @@ impl Job for NotificationQueueUsage {
     }
 }
 
+/// Report the entity count and estimated size of each deployment as
+/// metrics. Both are maintained by the writers of the deployments, so
+/// reading them is cheap
+struct EntitySizeUsage {
+    store: Arc<SubgraphStore>,
+    count_gauges: Box<GaugeVec>,
+    size_gauges: Box<GaugeVec>,
+}
+
+impl EntitySizeUsage {
+    fn new(store: Arc<SubgraphStore>, registry: Arc<impl MetricsRegistry>) -> Self {
+        let count_gauges = registry
+            .new_gauge_vec(
+                "deployment_entity_count",
+                "The number of entities in a deployment",
+                vec!["deployment".to_string()],
+            )
+            .expect("Can register the deployment_entity_count gauge");
+        let size_gauges = registry
+            .new_gauge_vec(
+                "deployment_entity_size_bytes",
+                "The estimated number of bytes that the entities of a deployment take up on disk",
+                vec!["deployment".to_string()],
+            )
+            .expect("Can register the deployment_entity_size_bytes gauge");
+        EntitySizeUsage {
+            store,
+            count_gauges,
+            size_gauges,
+        }
+    }
+}
+
+#[async_trait]
+impl Job for EntitySizeUsage {
+    fn name(&self) -> &str {
+        "Report deployment entity counts and sizes"
+    }
+
+    async fn run(&self, logger: &Logger) {
+        let mut sizes = Vec::new();
+        for res in self.store.entity_sizes().await {
+            match res {
+                Ok(shard_sizes) => sizes.extend(shard_sizes),
+                Err(e) => {
+                    error!(logger, "Reading deployment entity sizes failed: {}", e);
+                    return;
+                }
+            }
+        }
+
+        // Start over so that deployments that were removed disappear
+        self.count_gauges.reset();
+        self.size_gauges.reset();
+        for (deployment, count, size) in sizes {
+            self.count_gauges
+                .with_label_values(&[&deployment])
+                .set(count as f64);
+            if let Some(size) = size {
+                self.size_gauges
+                    .with_label_values(&[&deployment])
+                    .set(size as f64);
+            }
+        }
+    }
+}
+
+/// Analyze, reindex and vacuum the tables and indexes in each shard that
+/// need it, and report how many operations succeeded and failed and how
+/// long they took as metrics. See `maintenance::run`
+struct MaintenanceJob {
+    store: Arc<SubgraphStore>,
+    operations: Box<CounterVec>,
+    seconds: Box<CounterVec>,
+}
+
+impl MaintenanceJob {
+    fn new(store: Arc<SubgraphStore>, registry: Arc<impl MetricsRegistry>) -> Self {
+        let labels = vec![
+            "shard".to_string(),
+            "operation".to_string(),
+            "result".to_string(),
+        ];
+        let operations = registry
+            .new_counter_vec(
+                "store_maintenance_operations",
+                "The number of maintenance operations that were run",
+                labels.clone(),
+            )
+            .expect("Can register the store_maintenance_operations counter");
+        let seconds = registry
+            .new_counter_vec(
+                "store_maintenance_seconds",
+                "The time spent running maintenance operations",
+                labels,
+            )
+            .expect("Can register the store_maintenance_seconds counter");
+        MaintenanceJob {
+            store,
+            operations,
+            seconds,
+        }
+    }
+}
+
+#[async_trait]
+impl Job for MaintenanceJob {
+    fn name(&self) -> &str {
+        "Analyze, reindex and vacuum tables"
+    }
+
+    async fn run(&self, logger: &Logger) {
+        for (shard, outcomes) in self.store.maintain().await {
+            let outcomes = match outcomes {
+                Ok(outcomes) => outcomes,
+                Err(e) => {
+                    error!(logger, "Finding tables that need maintenance failed";
+                        "shard" => shard.as_str(), "error" => e.to_string());
+                    continue;
+                }
+            };
+            for outcome in outcomes {
+                let result = match &outcome.result {
+                    Ok(()) => {
+                        info!(logger, "Ran maintenance operation";
+                            "shard" => shard.as_str(),
+                            "operation" => outcome.operation.as_str(),
+                            "target" => &outcome.target,
+                            "time_ms" => outcome.duration.as_millis());
+                        "ok"
+                    }
+                    Err(e) => {
+                        error!(logger, "Maintenance operation failed";
+                            "shard" => shard.as_str(),
+                            "operation" => outcome.operation.as_str(),
+                            "target" => &outcome.target,
+                            "error" => e.to_string());
+                        "error"
+                    }
+                };
+                let labels = [shard.as_str(), outcome.operation.as_str(), result];
+                self.operations.with_label_values(&labels).inc();
+                self.seconds
+                    .with_label_values(&labels)
+                    .inc_by(outcome.duration.as_secs_f64());
+            }
+        }
+    }
+}
+
 struct MirrorPrimary {
     store: Arc<SubgraphStore>,
 }
@@ -207,3 +458,371 @@ impl Job for UnusedJob {
         }
     }
 }
+
+/// A job that adds the blocks that were cached before the block time index
+/// existed to the index, working its way down from the lowest indexed block
+/// of each chain. The ingestor adds new blocks to the index itself, so
+/// that, once a chain is done, there is nothing left to do for it
+struct BlockTimesJob {
+    store: Arc<BlockStore>,
+    /// The block of each chain below which blocks still need to be added,
+    /// or `None` for chains that are done. Chains that are not in the map
+    /// have not been looked at yet
+    progress: Mutex<HashMap<String, Option<BlockNumber>>>,
+}
+
+impl BlockTimesJob {
+    fn new(store: Arc<BlockStore>) -> BlockTimesJob {
+        BlockTimesJob {
+            store,
+            progress: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+#[async_trait]
+impl Job for BlockTimesJob {
+    fn name(&self) -> &str {
+        "Add cached blocks to the block time index"
+    }
+
+    async fn run(&self, logger: &Logger) {
+        // Unpacking block JSON is expensive; work in small batches and
+        // stop after a while to not block other jobs for too long
+        const BATCH_SIZE: BlockNumber = 10_000;
+        const DEADLINE: Duration = Duration::from_secs(30);
+
+        let start = Instant::now();
+        for chain_store in self.store.chain_stores() {
+            let chain = chain_store.chain.clone();
+            loop {
+                let below = match self.progress.lock().unwrap().get(&chain) {
+                    Some(None) => break,
+                    Some(Some(below)) => Some(*below),
+                    None => None,
+                };
+                if start.elapsed() > DEADLINE {
+                    return;
+                }
+                match chain_store.index_cached_block_times(below, BATCH_SIZE) {
+                    Ok(next) => {
+                        if next.is_none() && below.is_some() {
+                            info!(logger, "added all cached blocks to the block time index";
+                                          "chain" => &chain);
+                        }
+                        self.progress.lock().unwrap().insert(chain.clone(), next);
+                    }
+                    Err(e) => {
+                        error!(logger, "failed to add cached blocks to the block time index";
+                                       "chain" => &chain,
+                                       "error" => e.to_string());
+                        break;
+                    }
+                }
+            }
+        }
+    }
+}
+
+struct QueryActivityJob {
+    store: Arc<SubgraphStore>,
+}
+
+impl QueryActivityJob {
+    fn new(store: Arc<SubgraphStore>) -> QueryActivityJob {
+        QueryActivityJob { store }
+    }
+}
+
+#[async_trait]
+impl Job for QueryActivityJob {
+    fn name(&self) -> &str {
+        "Record which deployments were queried"
+    }
+
+    async fn run(&self, logger: &Logger) {
+        if let Err(e) = self.store.record_query_activity() {
+            error!(logger, "failed to record query activity"; "error" => e.to_string());
+        }
+    }
+}
+
+struct HeartbeatJob {
+    store: Arc<SubgraphStore>,
+    node: NodeId,
+    role: NodeRole,
+}
+
+impl HeartbeatJob {
+    fn new(store: Arc<SubgraphStore>, node: NodeId, role: NodeRole) -> HeartbeatJob {
+        HeartbeatJob { store, node, role }
+    }
+}
+
+#[async_trait]
+impl Job for HeartbeatJob {
+    fn name(&self) -> &str {
+        "Record node heartbeat"
+    }
+
+    async fn run(&self, logger: &Logger) {
+        if let Err(e) = self.store.record_heartbeat(&self.node, self.role) {
+            error!(logger, "failed to record heartbeat"; "error" => e.to_string());
+        }
+    }
+}
+
+struct ReassignJob {
+    store: Arc<SubgraphStore>,
+    timeout: Duration,
+}
+
+impl ReassignJob {
+    fn new(store: Arc<SubgraphStore>, timeout: Duration) -> ReassignJob {
+        ReassignJob { store, timeout }
+    }
+}
+
+#[async_trait]
+impl Job for ReassignJob {
+    fn name(&self) -> &str {
+        "Reassign deployments from unresponsive index nodes"
+    }
+
+    async fn run(&self, logger: &Logger) {
+        match self.store.reassign_from_unresponsive_nodes(self.timeout) {
+            Ok(moves) => {
+                for (deployment, from, to) in moves {
+                    info!(logger, "reassigned deployment from unresponsive node";
+                                  "deployment" => deployment.to_string(),
+                                  "from" => from.as_str(),
+                                  "to" => to.as_str());
+                }
+            }
+            Err(e) => {
+                error!(logger, "failed to reassign deployments from unresponsive nodes";
+                               "error" => e.to_string());
+            }
+        }
+    }
+}
+
+struct ChangeJournalJob {
+    store: Arc<SubgraphStore>,
+    node: NodeId,
+    dir: String,
+}
+
+impl ChangeJournalJob {
+    fn new(store: Arc<SubgraphStore>, node: NodeId, dir: String) -> ChangeJournalJob {
+        ChangeJournalJob { store, node, dir }
+    }
+}
+
+#[async_trait]
+impl Job for ChangeJournalJob {
+    fn name(&self) -> &str {
+        "Write change journals"
+    }
+
+    async fn run(&self, logger: &Logger) {
+        match self
+            .store
+            .write_change_journals(&self.node, &self.dir, ENV_VARS.reorg_threshold)
+        {
+            Ok(outcomes) => {
+                for (deployment, written) in outcomes {
+                    match written {
+                        Ok(Some(path)) => {
+                            info!(logger, "wrote change journal batch";
+                                          "deployment" => deployment.to_string(),
+                                          "file" => path.display().to_string());
+                        }
+                        Ok(None) => {}
+                        Err(e) => {
+                            error!(logger, "failed to write change journal";
+                                           "deployment" => deployment.to_string(),
+                                           "error" => e.to_string());
+                        }
+                    }
+                }
+            }
+            Err(e) => {
+                error!(logger, "failed to write change journals"; "error" => e.to_string());
+            }
+        }
+    }
+}
+
+struct JobQueueJob {
+    store: Arc<SubgraphStore>,
+    node: NodeId,
+}
+
+impl JobQueueJob {
+    fn new(store: Arc<SubgraphStore>, node: NodeId) -> JobQueueJob {
+        JobQueueJob { store, node }
+    }
+}
+
+#[async_trait]
+impl Job for JobQueueJob {
+    fn name(&self) -> &str {
+        "Run queued jobs"
+    }
+
+    async fn run(&self, logger: &Logger) {
+        if let Err(e) = job_queue::run(&self.store, logger, &self.node) {
+            error!(logger, "failed to run queued jobs"; "error" => e.to_string());
+        }
+    }
+}
+
+/// Running totals of the work done for a deployment since it started
+#[derive(Clone, Copy, Default)]
+struct LoadTotals {
+    blocks: u64,
+    handler_secs: f64,
+}
+
+/// A job that turns the block processing and handler metrics for the
+/// deployments this node indexes into rates and records them in the
+/// primary so that the rebalancer can use them
+struct DeploymentLoadJob {
+    store: Arc<SubgraphStore>,
+    prometheus: Arc<Registry>,
+    node: NodeId,
+    last: Mutex<Option<(Instant, HashMap<String, LoadTotals>)>>,
+}
+
+impl DeploymentLoadJob {
+    fn new(store: Arc<SubgraphStore>, prometheus: Arc<Registry>, node: NodeId) -> Self {
+        DeploymentLoadJob {
+            store,
+            prometheus,
+            node,
+            last: Mutex::new(None),
+        }
+    }
+
+    fn totals(&self) -> HashMap<String, LoadTotals> {
+        let mut totals: HashMap<String, LoadTotals> = HashMap::new();
+        for family in self.prometheus.gather() {
+            let blocks = match family.get_name() {
+                "deployment_block_processing_duration" => true,
+                "deployment_handler_execution_time" => false,
+                _ => continue,
+            };
+            for metric in family.get_metric() {
+                let deployment = match metric
+                    .get_label()
+                    .iter()
+                    .find(|label| label.get_name() == "deployment")
+                {
+                    Some(label) => label.get_value().to_string(),
+                    None => continue,
+                };
+                let entry = totals.entry(deployment).or_default();
+                let histogram = metric.get_histogram();
+                if blocks {
+                    entry.blocks += histogram.get_sample_count();
+                } else {
+                    entry.handler_secs += histogram.get_sample_sum();
+                }
+            }
+        }
+        totals
+    }
+
+    /// The load of each deployment since the last time this was called.
+    /// Deployments that were restarted in the meantime have counters that
+    /// went backwards and are skipped until the next time
+    fn loads(&self) -> Vec<DeploymentLoad> {
+        let now = Instant::now();
+        let totals = self.totals();
+        let mut last = self.last.lock().unwrap();
+
+        let loads = match last.as_ref() {
+            None => vec![],
+            Some((then, previous)) => {
+                let minutes = now.duration_since(*then).as_secs_f64() / 60.0;
+                totals
+                    .iter()
+                    .filter_map(|(deployment, current)| {
+                        let previous = previous.get(deployment).copied().unwrap_or_default();
+                        if current.blocks < previous.blocks
+                            || current.handler_secs < previous.handler_secs
+                            || minutes <= 0.0
+                        {
+                            return None;
+                        }
+                        let deployment = DeploymentHash::new(deployment.as_str()).ok()?;
+                        Some(DeploymentLoad {
+                            deployment,
+                            blocks_per_minute: (current.blocks - previous.blocks) as f64 / minutes,
+                            handler_secs_per_minute: (current.handler_secs - previous.handler_secs)
+                                / minutes,
+                        })
+                    })
+                    .collect()
+            }
+        };
+        *last = Some((now, totals));
+        loads
+    }
+}
+
+#[async_trait]
+impl Job for DeploymentLoadJob {
+    fn name(&self) -> &str {
+        "Record deployment load"
+    }
+
+    async fn run(&self, logger: &Logger) {
+        let loads = self.loads();
+        if loads.is_empty() {
+            return;
+        }
+        if let Err(e) = self.store.record_deployment_load(&self.node, &loads) {
+            error!(logger, "failed to record deployment load"; "error" => e.to_string());
+        }
+    }
+}
+
+struct RebalanceJob {
+    store: Arc<SubgraphStore>,
+}
+
+impl RebalanceJob {
+    fn new(store: Arc<SubgraphStore>) -> RebalanceJob {
+        RebalanceJob { store }
+    }
+}
+
+#[async_trait]
+impl Job for RebalanceJob {
+    fn name(&self) -> &str {
+        "Rebalance deployment assignments"
+    }
+
+    async fn run(&self, logger: &Logger) {
+        match self.store.rebalance(
+            ENV_VARS.rebalance_threshold,
+            ENV_VARS.rebalance_max_moves,
+            false,
+        ) {
+            Ok(moves) => {
+                for mv in moves {
+                    info!(logger, "moved deployment to even out load";
+                                  "deployment" => mv.deployment.to_string(),
+                                  "from" => mv.from.as_str(),
+                                  "to" => mv.to.as_str(),
+                                  "handler_secs_per_minute" => mv.load);
+                }
+            }
+            Err(e) => {
+                error!(logger, "failed to rebalance deployments"; "error" => e.to_string());
+            }
+        }
+    }
+}
@@ -0,0 +1,555 @@
+//! Change journals. For deployments that have journaling turned on, index
+//! nodes periodically write the rows that changed in final blocks into
+//! zstd-compressed batches, one file per range of blocks. A batch has the
+//! rows that were added in its blocks, the `vid` and new upper bound of
+//! rows that were closed in its blocks, and the dynamic data sources that
+//! were added in them. Since `vid`s survive snapshots, applying the
+//! batches in order to a deployment that was restored from a snapshot
+//! brings it to any block that the journal covers without going through
+//! the WAL of the database the deployment came from
+//!
+//! The journal of a deployment is written by the node that was named when
+//! journaling was turned on, not by the node that indexes the deployment,
+//! so that all its batches end up in the same directory even when the
+//! deployment is reassigned
+use std::fs::{self, File};
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+
+use diesel::pg::PgConnection;
+use diesel::sql_types::{Array, BigInt, Integer, Text};
+use diesel::{delete, insert_into, sql_query, update};
+use diesel::{ExpressionMethods, OptionalExtension, QueryDsl, RunQueryDsl};
+use graph::constraint_violation;
+use graph::prelude::serde::{Deserialize, Serialize};
+use graph::prelude::{
+    anyhow, serde_json, BlockNumber, BlockPtr, DeploymentHash, NodeId, StoreError, ENV_VARS,
+};
+
+use crate::block_range::{BLOCK_COLUMN, BLOCK_RANGE_COLUMN};
+use crate::primary::{DeploymentId, Site};
+use crate::relational::{Layout, Table};
+use crate::snapshot::reset_vid_sequence;
+
+table! {
+    subgraphs.change_journal (id) {
+        id -> Integer,
+        journaled_to -> Integer,
+        node_id -> Text,
+    }
+}
+
+/// The zstd compression level for batches
+const COMPRESSION_LEVEL: i32 = 3;
+
+/// The changes to one table in a batch
+#[derive(Debug, Serialize, Deserialize)]
+struct BatchTable {
+    table: String,
+    /// The rows that were added, as JSON
+    rows: Vec<serde_json::Value>,
+    /// The `vid` of rows that were closed together with the block at which
+    /// they were closed
+    clamps: Vec<(i64, BlockNumber)>,
+}
+
+/// The changes that a deployment made in the blocks `(after, to]`
+#[derive(Debug, Serialize, Deserialize)]
+struct Batch {
+    deployment: String,
+    after: BlockNumber,
+    to: BlockNumber,
+    tables: Vec<BatchTable>,
+    data_sources: Vec<serde_json::Value>,
+}
+
+impl Batch {
+    fn file_name(&self) -> String {
+        format!("{:010}-{:010}.json.zst", self.after + 1, self.to)
+    }
+
+    /// Write the batch into `dir`. The batch is first written to a
+    /// temporary file and then renamed so that whatever ships the journal
+    /// elsewhere never sees a partial batch
+    fn write(&self, dir: &Path) -> Result<PathBuf, anyhow::Error> {
+        fs::create_dir_all(dir)?;
+        let path = dir.join(self.file_name());
+        let tmp = dir.join(format!(".{}.tmp", self.file_name()));
+        let json = serde_json::to_vec(self)?;
+        fs::write(&tmp, zstd::encode_all(json.as_slice(), COMPRESSION_LEVEL)?)?;
+        fs::rename(&tmp, &path)?;
+        Ok(path)
+    }
+
+    fn read(path: &Path) -> Result<Self, anyhow::Error> {
+        let json = zstd::decode_all(BufReader::new(File::open(path)?))?;
+        Ok(serde_json::from_slice(&json)?)
+    }
+}
+
+/// Turn journaling on for `site`; the journal starts after `block` and is
+/// written by `node`. Return `false` if journaling was already on
+pub(crate) fn start(
+    conn: &PgConnection,
+    site: &Site,
+    block: BlockNumber,
+    node: &NodeId,
+) -> Result<bool, StoreError> {
+    use change_journal as j;
+
+    let rows = insert_into(j::table)
+        .values((
+            j::id.eq(site.id),
+            j::journaled_to.eq(block),
+            j::node_id.eq(node.as_str()),
+        ))
+        .on_conflict_do_nothing()
+        .execute(conn)?;
+    Ok(rows > 0)
+}
+
+/// Turn journaling off for `site`. Return `false` if it was not on
+pub(crate) fn stop(conn: &PgConnection, site: &Site) -> Result<bool, StoreError> {
+    use change_journal as j;
+
+    let rows = delete(j::table.filter(j::id.eq(site.id))).execute(conn)?;
+    Ok(rows > 0)
+}
+
+/// The block up to which the journal for `site` has been written, or
+/// `None` if journaling is not on for it
+pub(crate) fn journaled_to(
+    conn: &PgConnection,
+    site: &Site,
+) -> Result<Option<BlockNumber>, StoreError> {
+    use change_journal as j;
+
+    Ok(j::table
+        .filter(j::id.eq(site.id))
+        .select(j::journaled_to)
+        .get_result::<BlockNumber>(conn)
+        .optional()?)
+}
+
+/// The deployments whose journal `node` writes
+pub(crate) fn written_by(
+    conn: &PgConnection,
+    node: &NodeId,
+) -> Result<Vec<DeploymentId>, StoreError> {
+    use change_journal as j;
+
+    Ok(j::table
+        .filter(j::node_id.eq(node.as_str()))
+        .select(j::id)
+        .order(j::id)
+        .load::<DeploymentId>(conn)?)
+}
+
+fn set_journaled_to(
+    conn: &PgConnection,
+    site: &Site,
+    block: BlockNumber,
+) -> Result<(), StoreError> {
+    use change_journal as j;
+
+    update(j::table.filter(j::id.eq(site.id)))
+        .set(j::journaled_to.eq(block))
+        .execute(conn)?;
+    Ok(())
+}
+
+/// Collect the changes the deployment with `layout` made in the blocks
+/// `(after, to]`. The caller must make sure that all queries run against
+/// the same snapshot of the database
+fn batch(
+    conn: &PgConnection,
+    layout: &Layout,
+    after: BlockNumber,
+    to: BlockNumber,
+) -> Result<Batch, StoreError> {
+    #[derive(QueryableByName)]
+    struct Row {
+        #[sql_type = "Text"]
+        row: String,
+    }
+
+    #[derive(QueryableByName)]
+    struct Clamp {
+        #[sql_type = "BigInt"]
+        vid: i64,
+        #[sql_type = "Integer"]
+        upper: BlockNumber,
+    }
+
+    let to_json = |rows: Vec<Row>| -> Result<Vec<serde_json::Value>, StoreError> {
+        rows.into_iter()
+            .map(|row| serde_json::from_str(&row.row).map_err(|e| StoreError::Unknown(e.into())))
+            .collect()
+    };
+
+    let mut tables: Vec<&Table> = layout.tables.values().map(|table| table.as_ref()).collect();
+    tables.sort_by(|a, b| a.name.as_str().cmp(b.name.as_str()));
+    let mut batch_tables = Vec::new();
+    for table in tables {
+        let added = if table.immutable {
+            format!("\"{}\"", BLOCK_COLUMN)
+        } else {
+            format!("lower({})", BLOCK_RANGE_COLUMN)
+        };
+        let rows = sql_query(&format!(
+            "select row_to_json(t)::text as row from {} t \
+              where {added} > $1 and {added} <= $2 order by vid",
+            table.qualified_name,
+            added = added
+        ))
+        .bind::<Integer, _>(after)
+        .bind::<Integer, _>(to)
+        .load::<Row>(conn)?;
+
+        let clamps = if table.immutable {
+            vec![]
+        } else {
+            // Rows that were added in this batch are closed as part of
+            // their JSON
+            sql_query(&format!(
+                "select vid, upper({range}) as upper from {} \
+                  where lower({range}) <= $1 \
+                    and upper({range}) > $1 and upper({range}) <= $2 \
+                  order by vid",
+                table.qualified_name,
+                range = BLOCK_RANGE_COLUMN
+            ))
+            .bind::<Integer, _>(after)
+            .bind::<Integer, _>(to)
+            .load::<Clamp>(conn)?
+            .into_iter()
+            .map(|clamp| (clamp.vid, clamp.upper))
+            .collect()
+        };
+
+        if !rows.is_empty() || !clamps.is_empty() {
+            batch_tables.push(BatchTable {
+                table: table.name.to_string(),
+                rows: to_json(rows)?,
+                clamps,
+            });
+        }
+    }
+
+    let data_sources = sql_query(
+        "select row_to_json(d)::text as row
+           from (select name, address, abi, start_block, ethereum_block_hash,
+                        ethereum_block_number, context
+                   from subgraphs.dynamic_ethereum_contract_data_source
+                  where deployment = $1
+                    and ethereum_block_number > $2 and ethereum_block_number <= $3
+                  order by vid) d",
+    )
+    .bind::<Text, _>(layout.site.deployment.as_str())
+    .bind::<Integer, _>(after)
+    .bind::<Integer, _>(to)
+    .load::<Row>(conn)?;
+
+    Ok(Batch {
+        deployment: layout.site.deployment.to_string(),
+        after,
+        to,
+        tables: batch_tables,
+        data_sources: to_json(data_sources)?,
+    })
+}
+
+/// The batches in the journal of `deployment` under `dir`, ordered by the
+/// blocks they cover
+fn batches(
+    dir: &str,
+    deployment: &DeploymentHash,
+) -> Result<Vec<(BlockNumber, BlockNumber, PathBuf)>, StoreError> {
+    let dir = Path::new(dir).join(deployment.as_str());
+    let entries = fs::read_dir(&dir).map_err(|e| {
+        StoreError::Unknown(anyhow::Error::from(e).context(dir.display().to_string()))
+    })?;
+    let mut batches = Vec::new();
+    for entry in entries {
+        let path = entry.map_err(|e| StoreError::Unknown(e.into()))?.path();
+        let name = match path.file_name().and_then(|name| name.to_str()) {
+            Some(name) => name,
+            None => continue,
+        };
+        let range = match name.strip_suffix(".json.zst") {
+            Some(range) if !name.starts_with('.') => range,
+            _ => continue,
+        };
+        match range
+            .split_once('-')
+            .map(|(first, last)| (first.parse(), last.parse()))
+        {
+            Some((Ok(first), Ok(last))) => batches.push((first, last, path)),
+            _ => continue,
+        }
+    }
+    batches.sort_by_key(|(first, _, _)| *first);
+    Ok(batches)
+}
+
+/// Write the next batch of the journal of the deployment with `layout`
+/// into `dir`. Only blocks that are more than `reorg_threshold` blocks
+/// behind the deployment's head are journaled, and a batch never covers
+/// more than `GRAPH_CHANGE_JOURNAL_MAX_BLOCKS` blocks. Return the file that
+/// was written, or `None` if there was nothing to journal
+pub(crate) fn write_next(
+    conn: &PgConnection,
+    layout: &Layout,
+    dir: &str,
+    reorg_threshold: BlockNumber,
+) -> Result<Option<PathBuf>, StoreError> {
+    let site = layout.site.as_ref();
+    let journaled_to = match journaled_to(conn, site)? {
+        Some(journaled_to) => journaled_to,
+        None => return Ok(None),
+    };
+    let head = match crate::deployment::block_ptr(conn, &site.deployment)? {
+        Some(head) => head.number,
+        None => return Ok(None),
+    };
+    if head < journaled_to {
+        return Err(constraint_violation!(
+            "{} was rewound to block {} but its journal already goes up to block {}; \
+             stop and start the journal again to continue journaling",
+            site.deployment,
+            head,
+            journaled_to
+        ));
+    }
+    let to = (head - reorg_threshold)
+        .min(journaled_to.saturating_add(ENV_VARS.store.change_journal_max_blocks));
+    if to <= journaled_to {
+        return Ok(None);
+    }
+
+    let batch = batch(conn, layout, journaled_to, to)?;
+    let path = batch
+        .write(&Path::new(dir).join(&batch.deployment))
+        .map_err(StoreError::Unknown)?;
+    set_journaled_to(conn, site, to)?;
+    Ok(Some(path))
+}
+
+/// Apply the journal of the deployment with `layout` under `dir` to it,
+/// up to the block `ptr`. The deployment must be at a block that the
+/// journal covers, usually because it was just restored from a snapshot.
+/// Return the number of batches that were applied
+pub(crate) fn restore(
+    conn: &PgConnection,
+    layout: &Layout,
+    dir: &str,
+    ptr: &BlockPtr,
+) -> Result<usize, StoreError> {
+    let site = layout.site.as_ref();
+    let head = crate::deployment::block_ptr(conn, &site.deployment)?
+        .map(|head| head.number)
+        .unwrap_or(-1);
+    if ptr.number <= head {
+        return Err(constraint_violation!(
+            "{} is already at block {}, and can not be moved back to block {} with its journal",
+            site.deployment,
+            head,
+            ptr.number
+        ));
+    }
+
+    let batches: Vec<_> = batches(dir, &site.deployment)?
+        .into_iter()
+        .filter(|(first, last, _)| *last > head && *first <= ptr.number)
+        .collect();
+    let mut covered = head;
+    for (first, last, path) in &batches {
+        if *first > covered + 1 {
+            return Err(constraint_violation!(
+                "the journal of {} is missing blocks {} to {}",
+                site.deployment,
+                covered + 1,
+                first - 1
+            ));
+        }
+        covered = covered.max(*last);
+        apply(conn, layout, path, head, ptr.number)?;
+    }
+    if covered < ptr.number {
+        return Err(constraint_violation!(
+            "the journal of {} only goes up to block {}, not to block {}",
+            site.deployment,
+            covered,
+            ptr.number
+        ));
+    }
+
+    crate::deployment::forward_block_ptr(conn, &site.deployment, ptr)?;
+    // The cursor belongs to the block the deployment was at before
+    crate::deployment::delete_subgraph_firehose_cursor(conn, layout.site.clone())?;
+    crate::deployment::set_entity_count(conn, site, &layout.count_query)?;
+    Ok(batches.len())
+}
+
+/// Apply the batch in the file `path` to the deployment with `layout`,
+/// which is at block `head`, leaving out everything that happened after
+/// block `until`
+fn apply(
+    conn: &PgConnection,
+    layout: &Layout,
+    path: &Path,
+    head: BlockNumber,
+    until: BlockNumber,
+) -> Result<(), StoreError> {
+    let batch = Batch::read(path)
+        .map_err(|e| StoreError::Unknown(e.context(path.display().to_string())))?;
+    if batch.deployment != layout.site.deployment.as_str() {
+        return Err(StoreError::Unknown(anyhow!(
+            "{} is part of the journal of {}, not of {}",
+            path.display(),
+            batch.deployment,
+            layout.site.deployment
+        )));
+    }
+
+    for changes in &batch.tables {
+        let table = layout
+            .tables
+            .values()
+            .find(|table| table.name.as_str() == changes.table)
+            .ok_or_else(|| StoreError::UnknownTable(changes.table.clone()))?;
+        apply_table(conn, table, changes, until)?;
+    }
+
+    apply_data_sources(conn, &layout.site, &batch.data_sources, head, until)
+}
+
+fn apply_table(
+    conn: &PgConnection,
+    table: &Table,
+    changes: &BatchTable,
+    until: BlockNumber,
+) -> Result<(), StoreError> {
+    if !changes.rows.is_empty() {
+        // Rows can already be there if the snapshot was taken in the
+        // middle of the batch; in that case, they may have been closed
+        // since, and the batch knows better
+        let (added, conflict) = if table.immutable {
+            (format!("r.\"{}\"", BLOCK_COLUMN), "do nothing".to_string())
+        } else {
+            (
+                format!("lower(r.{})", BLOCK_RANGE_COLUMN),
+                format!(
+                    "do update set {range} = excluded.{range}",
+                    range = BLOCK_RANGE_COLUMN
+                ),
+            )
+        };
+        let rows =
+            serde_json::to_string(&changes.rows).map_err(|e| StoreError::Unknown(e.into()))?;
+        sql_query(&format!(
+            "insert into {table} \
+             select * from json_populate_recordset(null::{table}, $1::json) r \
+              where {added} <= $2 \
+             on conflict (vid) {conflict}",
+            table = table.qualified_name,
+            added = added,
+            conflict = conflict
+        ))
+        .bind::<Text, _>(rows)
+        .bind::<Integer, _>(until)
+        .execute(conn)?;
+        reset_vid_sequence(conn, table)?;
+    }
+
+    if table.immutable {
+        return Ok(());
+    }
+
+    let (vids, uppers): (Vec<i64>, Vec<BlockNumber>) = changes
+        .clamps
+        .iter()
+        .filter(|(_, upper)| *upper <= until)
+        .cloned()
+        .unzip();
+    if !vids.is_empty() {
+        sql_query(&format!(
+            "update {table} t set {range} = int4range(lower(t.{range}), c.upper) \
+               from unnest($1::int8[], $2::int4[]) as c(vid, upper) \
+              where t.vid = c.vid",
+            table = table.qualified_name,
+            range = BLOCK_RANGE_COLUMN
+        ))
+        .bind::<Array<BigInt>, _>(vids)
+        .bind::<Array<Integer>, _>(uppers)
+        .execute(conn)?;
+    }
+
+    // Rows that were closed after `until` are still current as of `until`
+    sql_query(&format!(
+        "update {table} set {range} = int4range(lower({range}), null) \
+          where upper({range}) > $1",
+        table = table.qualified_name,
+        range = BLOCK_RANGE_COLUMN
+    ))
+    .bind::<Integer, _>(until)
+    .execute(conn)?;
+    Ok(())
+}
+
+fn apply_data_sources(
+    conn: &PgConnection,
+    site: &Site,
+    data_sources: &[serde_json::Value],
+    head: BlockNumber,
+    until: BlockNumber,
+) -> Result<(), StoreError> {
+    if data_sources.is_empty() {
+        return Ok(());
+    }
+    let data_sources =
+        serde_json::to_string(data_sources).map_err(|e| StoreError::Unknown(e.into()))?;
+    sql_query(
+        "insert into subgraphs.dynamic_ethereum_contract_data_source(name,
+                address, abi, start_block, ethereum_block_hash,
+                ethereum_block_number, deployment, context)
+         select d.name, d.address, d.abi, d.start_block, d.ethereum_block_hash,
+                d.ethereum_block_number, $2, d.context
+           from json_populate_recordset(
+                  null::subgraphs.dynamic_ethereum_contract_data_source, $1::json) d
+          where d.ethereum_block_number > $3 and d.ethereum_block_number <= $4",
+    )
+    .bind::<Text, _>(data_sources)
+    .bind::<Text, _>(site.deployment.as_str())
+    .bind::<Integer, _>(head)
+    .bind::<Integer, _>(until)
+    .execute(conn)?;
+    Ok(())
+}
+
+#[test]
+fn batches_roundtrip() {
+    let deployment = DeploymentHash::new("QmJournalTest").unwrap();
+    let root = std::env::temp_dir().join(format!("journal-{}", std::process::id()));
+    let batch = Batch {
+        deployment: deployment.to_string(),
+        after: 9,
+        to: 20,
+        tables: vec![BatchTable {
+            table: "token".to_string(),
+            rows: vec![serde_json::json!({ "vid": 3, "id": "a" })],
+            clamps: vec![(1, 15)],
+        }],
+        data_sources: vec![],
+    };
+    let path = batch.write(&root.join(deployment.as_str())).unwrap();
+    assert!(path.ends_with("0000000010-0000000020.json.zst"));
+
+    let read = Batch::read(&path).unwrap();
+    assert_eq!((9, 20), (read.after, read.to));
+    assert_eq!(vec![(1, 15)], read.tables[0].clamps);
+
+    let listed = batches(root.to_str().unwrap(), &deployment).unwrap();
+    assert_eq!(vec![(10, 20, path)], listed);
+    fs::remove_dir_all(root).unwrap();
+}
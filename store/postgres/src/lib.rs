@@ -17,21 +17,29 @@ mod block_store;
 mod catalog;
 mod chain_head_listener;
 mod chain_store;
+mod checkpoint;
 pub mod connection_pool;
 mod copy;
 mod deployment;
 mod deployment_store;
 mod detail;
 mod dynds;
-mod fork;
+mod export;
 mod functions;
+mod job_queue;
 mod jobs;
+mod journal;
 mod jsonb;
+mod maintenance;
 mod notification_listener;
+mod outbox;
 mod primary;
 pub mod query_store;
+mod rebalance;
 mod relational;
 mod relational_queries;
+mod snapshot;
+mod sql;
 mod sql_value;
 mod store;
 mod store_events;
@@ -44,25 +52,41 @@ pub mod layout_for_tests {
     pub use crate::block_range::*;
     pub use crate::block_store::FAKE_NETWORK_SHARED;
     pub use crate::catalog::set_account_like;
+    pub use crate::export::export;
     pub use crate::primary::{
         make_dummy_site, Connection, Mirror, Namespace, EVENT_TAP, EVENT_TAP_ENABLED,
     };
     pub use crate::relational::*;
+    pub mod job_queue {
+        pub use crate::job_queue::run;
+    }
+    pub mod maintenance {
+        pub use crate::catalog::{bloated_indexes, reindex_leftovers, server_version};
+    }
     pub mod writable {
         pub use crate::writable::test_support::allow_steps;
     }
 }
 
-pub use self::block_store::BlockStore;
+pub use self::block_store::{BlockStore, IngestorLease};
 pub use self::chain_head_listener::ChainHeadUpdateListener;
 pub use self::chain_store::ChainStore;
 pub use self::detail::DeploymentDetail;
-pub use self::jobs::register as register_jobs;
+pub use self::jobs::{
+    register as register_jobs, register_change_journal as register_change_journal_job,
+    register_heartbeat as register_heartbeat_jobs, register_job_queue as register_job_queue_job,
+    register_load as register_load_jobs, register_query_activity as register_query_activity_job,
+};
 pub use self::notification_listener::NotificationSender;
-pub use self::primary::{db_version, UnusedDeployment};
+pub use self::primary::{db_version, DeploymentLoad, UnusedDeployment};
+pub use self::rebalance::Move;
+pub use self::snapshot::{Snapshot, SnapshotBlock, SnapshotTable};
 pub use self::store::Store;
 pub use self::store_events::SubscriptionManager;
-pub use self::subgraph_store::{unused, DeploymentPlacer, Shard, SubgraphStore, PRIMARY_SHARD};
+pub use self::subgraph_store::{
+    unused, DeploymentPlacer, NodeRole, PreviousVersion, Shard, SubgraphStore, VersionPolicy,
+    PRIMARY_SHARD,
+};
 
 /// This module is only meant to support command line tooling. It must not
 /// be used in 'normal' graph-node code
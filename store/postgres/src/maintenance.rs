@@ -0,0 +1,196 @@
+//! Routine maintenance of the database of a shard: analyze entity tables
+//! that changed a lot since they were last analyzed, rebuild bloated
+//! indexes, and vacuum the metadata tables. The autovacuum daemon does
+//! some of that, too, but its defaults are tuned for much smaller tables
+//! than the ones that deployments produce
+//!
+//! Indexes are rebuilt with `reindex concurrently`, which needs Postgres
+//! 12 or later; on older versions, indexes are not rebuilt. When such a
+//! rebuild fails, it leaves an invalid copy of the index behind, which we
+//! drop right away, and, should that fail, on the next run
+use std::fmt;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use diesel::connection::SimpleConnection;
+use diesel::r2d2::{ConnectionManager, PooledConnection};
+use diesel::PgConnection;
+use graph::prelude::futures03::{stream, StreamExt};
+use graph::prelude::{StoreError, ENV_VARS};
+
+use crate::advisory_lock;
+use crate::catalog;
+use crate::deployment_store::{DeploymentStore, ReplicaId};
+
+/// Indexes with fewer pages than this (8MB) are not rebuilt since that
+/// would not free up much space
+const REINDEX_MIN_PAGES: i32 = 1000;
+
+/// The first version of Postgres that supports `reindex concurrently`
+const REINDEX_CONCURRENTLY_VERSION: i32 = 120000;
+
+/// The kinds of maintenance operations
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Operation {
+    Analyze,
+    Reindex,
+    Vacuum,
+    /// Drop an invalid index that a failed `reindex concurrently` left
+    Drop,
+}
+
+impl Operation {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Operation::Analyze => "analyze",
+            Operation::Reindex => "reindex",
+            Operation::Vacuum => "vacuum",
+            Operation::Drop => "drop",
+        }
+    }
+
+    /// The SQL statement that performs the operation on `target`, a
+    /// qualified and quoted table or index name
+    fn sql(&self, target: &str) -> String {
+        match self {
+            Operation::Analyze => format!("analyze {}", target),
+            // Rebuilding concurrently does not lock out writes to the table
+            Operation::Reindex => format!("reindex index concurrently {}", target),
+            Operation::Vacuum => format!("vacuum (analyze) {}", target),
+            Operation::Drop => format!("drop index concurrently if exists {}", target),
+        }
+    }
+}
+
+impl fmt::Display for Operation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+/// What happened when an operation was performed on `target`
+pub struct Outcome {
+    pub operation: Operation,
+    pub target: String,
+    pub duration: Duration,
+    pub result: Result<(), StoreError>,
+}
+
+/// A connection that holds the maintenance lock of a shard
+struct MaintenanceLock(Option<PooledConnection<ConnectionManager<PgConnection>>>);
+
+impl MaintenanceLock {
+    /// Get the maintenance lock for the shard of `store`, or `None` if
+    /// another node is running maintenance there
+    async fn acquire(store: &Arc<DeploymentStore>) -> Result<Option<Self>, StoreError> {
+        let store = store.clone();
+        graph::spawn_blocking_allow_panic(move || {
+            let conn = store
+                .get_replica_conn(ReplicaId::Main)
+                .map_err(StoreError::Unknown)?;
+            if advisory_lock::try_lock_maintenance(&conn)? {
+                Ok(Some(MaintenanceLock(Some(conn))))
+            } else {
+                Ok(None)
+            }
+        })
+        .await
+        .map_err(|e| StoreError::Unknown(e.into()))?
+    }
+
+    async fn release(mut self) -> Result<(), StoreError> {
+        let conn = self.0.take().expect("the lock is only released once");
+        graph::spawn_blocking_allow_panic(move || advisory_lock::unlock_maintenance(&conn))
+            .await
+            .map_err(|e| StoreError::Unknown(e.into()))?
+    }
+}
+
+impl Drop for MaintenanceLock {
+    fn drop(&mut self) {
+        // Only happens if `run` was cancelled; the connection goes back
+        // into the pool, and must not keep holding the lock
+        if let Some(conn) = self.0.take() {
+            advisory_lock::unlock_maintenance(&conn).ok();
+        }
+    }
+}
+
+/// Find the tables and indexes in the shard of `store` that need
+/// maintenance, and run the operations for them, at most
+/// `GRAPH_MAINTENANCE_CONCURRENCY` at a time. If another node is already
+/// running maintenance in the shard, do nothing
+pub(crate) async fn run(store: Arc<DeploymentStore>) -> Result<Vec<Outcome>, StoreError> {
+    let lock = match MaintenanceLock::acquire(&store).await? {
+        Some(lock) => lock,
+        None => return Ok(vec![]),
+    };
+    let outcomes = run_locked(store).await;
+    lock.release().await?;
+    outcomes
+}
+
+async fn run_locked(store: Arc<DeploymentStore>) -> Result<Vec<Outcome>, StoreError> {
+    let targets = store
+        .with_conn(|conn, _| {
+            let reindex = catalog::server_version(conn)? >= REINDEX_CONCURRENTLY_VERSION;
+
+            let mut targets = Vec::new();
+            if reindex {
+                for index in catalog::reindex_leftovers(conn)? {
+                    targets.push((Operation::Drop, index));
+                }
+            }
+            for table in catalog::tables_to_analyze(conn, ENV_VARS.store.maintenance_analyze_ratio)?
+            {
+                targets.push((Operation::Analyze, table));
+            }
+            if reindex {
+                for index in catalog::bloated_indexes(
+                    conn,
+                    ENV_VARS.store.maintenance_reindex_bloat,
+                    REINDEX_MIN_PAGES,
+                )? {
+                    targets.push((Operation::Reindex, index));
+                }
+            }
+            for table in catalog::metadata_tables(conn)? {
+                targets.push((Operation::Vacuum, table));
+            }
+            Ok(targets)
+        })
+        .await?;
+
+    let outcomes = stream::iter(targets)
+        .map(|(operation, target)| {
+            let store = store.clone();
+            async move {
+                let start = Instant::now();
+                let sql = operation.sql(&target);
+                let result = store
+                    .with_conn(move |conn, _| {
+                        let result = conn.batch_execute(&sql);
+                        if result.is_err() && operation == Operation::Reindex {
+                            // Don't leave the invalid copy of the index
+                            // behind; it slows down writes to the table
+                            for index in catalog::reindex_leftovers(conn)? {
+                                conn.batch_execute(&Operation::Drop.sql(&index))?;
+                            }
+                        }
+                        result?;
+                        Ok(())
+                    })
+                    .await;
+                Outcome {
+                    operation,
+                    target,
+                    duration: start.elapsed(),
+                    result,
+                }
+            }
+        })
+        .buffer_unordered(ENV_VARS.store.maintenance_concurrency.max(1))
+        .collect()
+        .await;
+    Ok(outcomes)
+}
@@ -0,0 +1,230 @@
+//! The outbox of entity changes in each shard. When some system wants the
+//! changes to entities delivered to it, we record every change in
+//! `subgraphs.entity_change_outbox` in the same transaction that makes it.
+//! Changes are removed from the outbox once they have been delivered,
+//! which makes sure that every committed change is delivered at least
+//! once, and that changes that were never committed are never delivered
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use diesel::pg::PgConnection;
+use diesel::sql_types::{Array, BigInt, Integer, Jsonb, Nullable, Text};
+use diesel::{delete, insert_into, sql_query};
+use diesel::{Connection, ExpressionMethods, QueryDsl, RunQueryDsl};
+
+use graph::components::store::{EntityType, OutboxEntry};
+use graph::prelude::{
+    r, serde_json, BlockNumber, DeploymentHash, Entity, EntityModification, Error, StoreError,
+};
+
+use crate::advisory_lock;
+use crate::primary::Site;
+use crate::relational::Layout;
+
+table! {
+    subgraphs.entity_change_outbox (id) {
+        id -> BigInt,
+        deployment -> Integer,
+        block_number -> Integer,
+        entity_type -> Text,
+        entity_id -> Text,
+        operation -> Text,
+        data -> Nullable<Jsonb>,
+        claimed_until -> Nullable<Timestamptz>,
+    }
+}
+
+/// How long entries that were taken from the outbox stay claimed by the
+/// connection that took them; delivering them must take less time than that
+const CLAIM_SECS: i32 = 300;
+
+const SET: &str = "set";
+const REMOVE: &str = "remove";
+
+fn to_json(entity: &Entity) -> serde_json::Value {
+    let data: BTreeMap<_, _> = entity
+        .clone()
+        .sorted()
+        .into_iter()
+        .map(|(name, value)| (name, r::Value::from(value)))
+        .collect();
+    serde_json::to_value(data).expect("entities can be serialized")
+}
+
+/// Record the changes that `mods` make in `block`
+pub(crate) fn insert(
+    conn: &PgConnection,
+    site: &Site,
+    block: BlockNumber,
+    mods: &[EntityModification],
+) -> Result<(), StoreError> {
+    use entity_change_outbox as o;
+
+    let rows: Vec<_> = mods
+        .iter()
+        .map(|modification| {
+            let key = modification.entity_key();
+            let operation = if modification.is_remove() {
+                REMOVE
+            } else {
+                SET
+            };
+            (
+                o::deployment.eq(site.id),
+                o::block_number.eq(block),
+                o::entity_type.eq(key.entity_type.as_str()),
+                o::entity_id.eq(key.entity_id.as_str()),
+                o::operation.eq(operation),
+                o::data.eq(modification.entity().map(to_json)),
+            )
+        })
+        .collect();
+    // Postgres limits the number of bind parameters in one statement
+    for chunk in rows.chunks(5_000) {
+        insert_into(o::table).values(chunk.to_vec()).execute(conn)?;
+    }
+    Ok(())
+}
+
+/// Record the state after a revert to `block` of the entities in
+/// `changed`, the entities that `Layout::revert_block` reports it changed.
+/// Consumers that went through the changes that were reverted end up with
+/// the same entities as if they had never seen them
+pub(crate) fn insert_reverted(
+    conn: &PgConnection,
+    site: &Site,
+    layout: &Layout,
+    block: BlockNumber,
+    changed: HashMap<EntityType, BTreeSet<String>>,
+) -> Result<(), StoreError> {
+    use entity_change_outbox as o;
+
+    let ids_for_type: BTreeMap<&EntityType, Vec<&str>> = changed
+        .iter()
+        .map(|(entity_type, ids)| (entity_type, ids.iter().map(String::as_str).collect()))
+        .collect();
+    let mut current = layout.find_many(conn, &ids_for_type, block)?;
+
+    let mut rows = Vec::new();
+    for (entity_type, ids) in &changed {
+        let entities: HashMap<_, _> = current
+            .remove(entity_type)
+            .unwrap_or_default()
+            .into_iter()
+            .map(|entity| (entity.id().expect("entities have an id"), entity))
+            .collect();
+        for id in ids {
+            let entity = entities.get(id);
+            let operation = if entity.is_some() { SET } else { REMOVE };
+            rows.push((
+                o::deployment.eq(site.id),
+                o::block_number.eq(block),
+                o::entity_type.eq(entity_type.as_str().to_string()),
+                o::entity_id.eq(id.clone()),
+                o::operation.eq(operation),
+                o::data.eq(entity.map(to_json)),
+            ));
+        }
+    }
+    for chunk in rows.chunks(5_000) {
+        insert_into(o::table).values(chunk.to_vec()).execute(conn)?;
+    }
+    Ok(())
+}
+
+/// Claim up to `limit` of the oldest entries in the outbox for
+/// `CLAIM_SECS` and return them. While entries are claimed, no others are
+/// handed out so that entries are delivered in order. A claim that runs
+/// out, e.g., because the node that made it crashed, lets the entries be
+/// delivered again
+pub(crate) fn claim(
+    conn: &PgConnection,
+    shard: &str,
+    limit: usize,
+) -> Result<Vec<OutboxEntry>, StoreError> {
+    #[derive(QueryableByName)]
+    struct Row {
+        #[sql_type = "BigInt"]
+        id: i64,
+        #[sql_type = "Text"]
+        deployment: String,
+        #[sql_type = "Integer"]
+        block_number: BlockNumber,
+        #[sql_type = "Text"]
+        entity_type: String,
+        #[sql_type = "Text"]
+        entity_id: String,
+        #[sql_type = "Text"]
+        operation: String,
+        #[sql_type = "Nullable<Jsonb>"]
+        data: Option<serde_json::Value>,
+    }
+
+    const QUERY: &str = "\
+        with busy as (
+          select 1 from subgraphs.entity_change_outbox
+           where claimed_until > now()
+           limit 1),
+        claimed as (
+          update subgraphs.entity_change_outbox o
+             set claimed_until = now() + $2 * interval '1 second'
+           where o.id in (select o.id
+                            from subgraphs.entity_change_outbox o,
+                                 subgraphs.subgraph_deployment d
+                           where o.deployment = d.id
+                           order by o.id
+                           limit $1)
+             and not exists (select 1 from busy)
+          returning o.*)
+        select c.id, d.deployment, c.block_number, c.entity_type, c.entity_id,
+               c.operation, c.data
+          from claimed c, subgraphs.subgraph_deployment d
+         where c.deployment = d.id
+         order by c.id";
+
+    conn.transaction(|| {
+        // Checking that nothing is claimed and claiming entries has to
+        // happen without interference from other connections
+        if !advisory_lock::try_lock_outbox(conn)? {
+            return Ok(vec![]);
+        }
+
+        sql_query(QUERY)
+            .bind::<BigInt, _>(limit as i64)
+            .bind::<Integer, _>(CLAIM_SECS)
+            .load::<Row>(conn)?
+            .into_iter()
+            .map(|row| {
+                Ok(OutboxEntry {
+                    id: row.id,
+                    shard: shard.to_string(),
+                    deployment: DeploymentHash::new(row.deployment).map_err(|id| {
+                        StoreError::ConstraintViolation(format!("invalid deployment id {}", id))
+                    })?,
+                    block: row.block_number,
+                    entity_type: row.entity_type,
+                    entity_id: row.entity_id,
+                    operation: row.operation,
+                    data: row.data,
+                })
+            })
+            .collect()
+    })
+}
+
+/// Give up the claim on the entries with `ids` without delivering them so
+/// that they can be delivered again right away
+pub(crate) fn release(conn: &PgConnection, ids: Vec<i64>) -> Result<(), StoreError> {
+    sql_query("update subgraphs.entity_change_outbox set claimed_until = null where id = any($1)")
+        .bind::<Array<BigInt>, _>(ids)
+        .execute(conn)?;
+    Ok(())
+}
+
+/// Remove the entries with `ids`, which have been delivered, from the
+/// outbox
+pub(crate) fn remove(conn: &PgConnection, ids: Vec<i64>) -> Result<(), StoreError> {
+    use entity_change_outbox as o;
+
+    delete(o::table.filter(o::id.eq_any(ids))).execute(conn)?;
+    Ok(())
+}
@@ -5,9 +5,10 @@ use diesel::{
     connection::SimpleConnection,
     data_types::PgTimestamp,
     dsl::{any, exists, not, select},
+    expression::SqlLiteral,
     pg::Pg,
     serialize::Output,
-    sql_types::{Array, Integer, Text},
+    sql_types::{Array, Integer, Text, Timestamptz},
     types::{FromSql, ToSql},
 };
 use diesel::{
@@ -27,19 +28,23 @@ use graph::{
     prelude::{chrono, CancelHandle, CancelToken},
 };
 use graph::{
-    components::store::DeploymentLocator,
+    components::store::{AdminCall, DeploymentLocator, JobRequest},
     constraint_violation,
-    data::subgraph::status,
+    data::subgraph::{
+        status::{self, JobFilter, JobState},
+        SubgraphDeprecation,
+    },
     prelude::{
         anyhow, bigdecimal::ToPrimitive, serde_json, DeploymentHash, EntityChange,
         EntityChangeOperation, NodeId, StoreError, SubgraphName, SubgraphVersionSwitchingMode,
+        ENV_VARS,
     },
 };
 use graph::{data::subgraph::schema::generate_entity_id, prelude::StoreEvent};
 use itertools::Itertools;
 use maybe_owned::MaybeOwned;
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet},
     convert::TryFrom,
     convert::TryInto,
     fmt,
@@ -48,10 +53,12 @@ use std::{
 };
 
 use crate::{
+    advisory_lock,
     block_range::UNVERSIONED_RANGE,
     connection_pool::{ConnectionPool, ForeignServer},
     detail::DeploymentDetail,
-    subgraph_store::{unused, Shard, PRIMARY_SHARD},
+    job_queue::QueuedJob,
+    subgraph_store::{unused, NodeRole, PreviousVersion, Shard, PRIMARY_SHARD},
     NotificationSender,
 };
 
@@ -74,6 +81,8 @@ table! {
         pending_version -> Nullable<Text>,
         created_at -> Numeric,
         block_range -> Range<Integer>,
+        retain_previous_version -> Bool,
+        previous_version -> Nullable<Text>,
     }
 }
 
@@ -114,6 +123,181 @@ table! {
     }
 }
 
+table! {
+    public.deployment_files(link) {
+        link -> Text,
+        digest -> Binary,
+        size -> Integer,
+        content -> Binary,
+        stored_at -> Timestamptz,
+    }
+}
+
+table! {
+    /// Deployments that come from the same multi-network manifest
+    public.deployment_families(deployment) {
+        deployment -> Text,
+        family -> Text,
+        network -> Text,
+        created_at -> Timestamptz,
+    }
+}
+
+table! {
+    public.subgraph_deprecations(name) {
+        name -> Text,
+        message -> Text,
+        removal_date -> Date,
+    }
+}
+
+table! {
+    /// Long-running operations on deployments that index nodes run in the
+    /// background
+    public.jobs(id) {
+        id -> Integer,
+        deployment -> Text,
+        deployment_id -> Integer,
+        kind -> Text,
+        request -> Jsonb,
+        /// One of the values of `JobState::as_str`
+        state -> Text,
+        /// The node that runs or ran the job
+        node_id -> Nullable<Text>,
+        /// What the job needs to continue where it left off
+        progress -> Nullable<Jsonb>,
+        done -> BigInt,
+        total -> Nullable<BigInt>,
+        outcome -> Nullable<Text>,
+        error -> Nullable<Text>,
+        cancel_requested_at -> Nullable<Timestamptz>,
+        created_at -> Timestamptz,
+        started_at -> Nullable<Timestamptz>,
+        finished_at -> Nullable<Timestamptz>,
+    }
+}
+
+table! {
+    /// Every call to the admin JSON-RPC server
+    public.admin_audit_log(id) {
+        id -> Integer,
+        method -> Text,
+        token_name -> Nullable<Text>,
+        role -> Nullable<Text>,
+        params -> Jsonb,
+        /// One of the values of `AdminCallOutcome::as_str`
+        outcome -> Text,
+        error -> Nullable<Text>,
+        created_at -> Timestamptz,
+    }
+}
+
+/// The columns of `public.jobs` that make up a `JobRow`; the `kind` is
+/// also part of the `request`
+type JobColumns = (
+    jobs::id,
+    jobs::deployment,
+    jobs::deployment_id,
+    jobs::request,
+    jobs::state,
+    jobs::node_id,
+    jobs::progress,
+    jobs::done,
+    jobs::total,
+    jobs::outcome,
+    jobs::error,
+    jobs::cancel_requested_at,
+    jobs::created_at,
+    jobs::started_at,
+    jobs::finished_at,
+);
+
+const JOB_COLUMNS: JobColumns = (
+    jobs::id,
+    jobs::deployment,
+    jobs::deployment_id,
+    jobs::request,
+    jobs::state,
+    jobs::node_id,
+    jobs::progress,
+    jobs::done,
+    jobs::total,
+    jobs::outcome,
+    jobs::error,
+    jobs::cancel_requested_at,
+    jobs::created_at,
+    jobs::started_at,
+    jobs::finished_at,
+);
+
+/// A row of `public.jobs`, as selected with `JOB_COLUMNS`
+#[derive(Queryable)]
+struct JobRow {
+    id: i32,
+    deployment: String,
+    deployment_id: DeploymentId,
+    request: serde_json::Value,
+    state: String,
+    node_id: Option<String>,
+    progress: Option<serde_json::Value>,
+    done: i64,
+    total: Option<i64>,
+    outcome: Option<String>,
+    error: Option<String>,
+    cancel_requested_at: Option<chrono::DateTime<chrono::Utc>>,
+    created_at: chrono::DateTime<chrono::Utc>,
+    started_at: Option<chrono::DateTime<chrono::Utc>>,
+    finished_at: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+impl JobRow {
+    fn request(&self) -> Result<JobRequest, StoreError> {
+        serde_json::from_value(self.request.clone())
+            .map_err(|e| constraint_violation!("invalid request for job {}: {}", self.id, e))
+    }
+}
+
+impl TryFrom<JobRow> for status::JobInfo {
+    type Error = StoreError;
+
+    fn try_from(row: JobRow) -> Result<Self, Self::Error> {
+        let request = row.request()?;
+        let state = row
+            .state
+            .parse::<JobState>()
+            .map_err(|e| constraint_violation!("job {}: {}", row.id, e))?;
+        Ok(status::JobInfo {
+            id: row.id,
+            deployment: row.deployment,
+            request,
+            state,
+            node: row.node_id,
+            done: row.done,
+            total: row.total,
+            outcome: row.outcome,
+            error: row.error,
+            cancel_requested: row.cancel_requested_at.is_some(),
+            created_at: row.created_at,
+            started_at: row.started_at,
+            finished_at: row.finished_at,
+        })
+    }
+}
+
+impl TryFrom<JobRow> for QueuedJob {
+    type Error = StoreError;
+
+    fn try_from(row: JobRow) -> Result<Self, Self::Error> {
+        Ok(QueuedJob {
+            request: row.request()?,
+            id: row.id,
+            site: row.deployment_id,
+            progress: row.progress,
+            cancel_requested: row.cancel_requested_at.is_some(),
+        })
+    }
+}
+
 /// We used to support different layout schemes. The old 'Split' scheme
 /// which used JSONB layout has been removed, and we will only deal
 /// with relational layout. Trying to do anything with a 'Split' subgraph
@@ -171,6 +355,36 @@ table! {
     }
 }
 
+table! {
+    /// Nodes record that they are still alive here regularly
+    public.node_heartbeats(node_id) {
+        node_id -> Text,
+        /// One of the values of `NodeRole::as_str`
+        role -> Text,
+        heartbeat_at -> Timestamptz,
+    }
+}
+
+table! {
+    /// The recent load of deployments, as reported by the index node that
+    /// indexes them
+    public.deployment_load(deployment) {
+        deployment -> Text,
+        node_id -> Text,
+        blocks_per_minute -> Double,
+        handler_secs_per_min -> Double,
+        updated_at -> Timestamptz,
+    }
+}
+
+table! {
+    /// When a deployment was last queried
+    public.deployment_query_activity(id) {
+        id -> Integer,
+        last_queried_at -> Timestamptz,
+    }
+}
+
 table! {
     public.db_version(version) {
         #[sql_name = "db_version"]
@@ -185,6 +399,7 @@ allow_tables_to_appear_in_same_query!(
     deployment_schemas,
     unused_deployments,
     active_copies,
+    deployment_query_activity,
 );
 
 /// Information about the database schema that stores the entities for a
@@ -205,6 +420,15 @@ struct Schema {
     pub(crate) active: bool,
 }
 
+/// How busy indexing a deployment keeps the index node it is assigned to
+#[derive(Clone, Debug, PartialEq)]
+pub struct DeploymentLoad {
+    pub deployment: DeploymentHash,
+    pub blocks_per_minute: f64,
+    /// The time spent in handlers per minute
+    pub handler_secs_per_minute: f64,
+}
+
 #[derive(Clone, Queryable, QueryableByName, Debug)]
 #[table_name = "unused_deployments"]
 pub struct UnusedDeployment {
@@ -394,6 +618,14 @@ pub fn make_dummy_site(deployment: DeploymentHash, namespace: Namespace, network
     }
 }
 
+/// The point in time `d` before the database's `now()`. Timestamps in the
+/// primary are recorded with `now()`, and comparing them against a cutoff
+/// computed on this node would be off by however much the clocks of the
+/// nodes and the database disagree
+fn ago(d: chrono::Duration) -> SqlLiteral<Timestamptz> {
+    sql(&format!("now() - interval '{} seconds'", d.num_seconds()))
+}
+
 /// Queries that we need for both the `Connection` and the `Mirror`. Since
 /// they will also be used by `Mirror`, they can only use tables that are
 /// mirrored through `Mirror::refresh_tables` and must be queries, i.e.,
@@ -456,24 +688,30 @@ mod queries {
         )
     }
 
-    pub(super) fn current_deployment_for_subgraph(
+    pub(super) fn current_deployment(
         conn: &PgConnection,
         name: &SubgraphName,
-    ) -> Result<DeploymentHash, StoreError> {
-        let id = v::table
+    ) -> Result<Option<DeploymentHash>, StoreError> {
+        v::table
             .inner_join(s::table.on(s::current_version.eq(v::id.nullable())))
             .filter(s::name.eq(name.as_str()))
             .select(v::deployment)
             .first::<String>(conn)
-            .optional()?;
-        match id {
-            Some(id) => DeploymentHash::new(id)
-                .map_err(|id| constraint_violation!("illegal deployment id: {}", id)),
-            None => Err(StoreError::QueryExecutionError(format!(
-                "Subgraph `{}` not found",
-                name.as_str()
-            ))),
-        }
+            .optional()?
+            .map(|id| {
+                DeploymentHash::new(id)
+                    .map_err(|id| constraint_violation!("illegal deployment id: {}", id))
+            })
+            .transpose()
+    }
+
+    pub(super) fn current_deployment_for_subgraph(
+        conn: &PgConnection,
+        name: &SubgraphName,
+    ) -> Result<DeploymentHash, StoreError> {
+        current_deployment(conn, name)?.ok_or_else(|| {
+            StoreError::QueryExecutionError(format!("Subgraph `{}` not found", name.as_str()))
+        })
     }
 
     pub(super) fn deployments_for_subgraph(
@@ -658,7 +896,8 @@ mod queries {
                     s::table.on(v::id
                         .nullable()
                         .eq(s::current_version)
-                        .or(v::id.nullable().eq(s::pending_version))),
+                        .or(v::id.nullable().eq(s::pending_version))
+                        .or(v::id.nullable().eq(s::previous_version))),
                 )
                 .filter(v::deployment.eq(&deployment_hash))
                 .select((
@@ -666,6 +905,7 @@ mod queries {
                     sql::<Text>(
                         "(case when subgraphs.subgraph.pending_version = subgraphs.subgraph_version.id then 'pending'
                                when subgraphs.subgraph.current_version = subgraphs.subgraph_version.id then 'current'
+                               when subgraphs.subgraph.previous_version = subgraphs.subgraph_version.id then 'previous'
                                else 'unused'
                          end) as version",
                     ),
@@ -721,7 +961,8 @@ impl<'a> Connection<'a> {
                 s::table.on(v::id
                     .nullable()
                     .eq(s::current_version)
-                    .or(v::id.nullable().eq(s::pending_version))),
+                    .or(v::id.nullable().eq(s::pending_version))
+                    .or(v::id.nullable().eq(s::previous_version))),
             )
             .inner_join(ds::table.on(v::deployment.eq(ds::subgraph)))
             .filter(a::id.eq(ds::id))
@@ -763,6 +1004,35 @@ impl<'a> Connection<'a> {
         Ok(events)
     }
 
+    /// Make `version` the current version of `subgraph` and clear its
+    /// pending version. If the subgraph is set up to retain its previous
+    /// version, the version that was current so far becomes the previous
+    /// version so that it stays assigned; otherwise, the subgraph has no
+    /// previous version afterwards. All of that happens in one update so
+    /// that queries by name see either the old or the new current version
+    fn make_current(&self, subgraph: &str, version: &str) -> Result<(), StoreError> {
+        use subgraph as s;
+
+        let conn = self.conn.as_ref();
+        let (current, retain) = s::table
+            .filter(s::id.eq(subgraph))
+            .select((s::current_version, s::retain_previous_version))
+            .for_update()
+            .first::<(Option<String>, bool)>(conn)?;
+        let previous = match current {
+            Some(current) if retain && current != version => Some(current),
+            _ => None,
+        };
+        update(s::table.filter(s::id.eq(subgraph)))
+            .set((
+                s::current_version.eq(version),
+                s::pending_version.eq::<Option<&str>>(None),
+                s::previous_version.eq(previous),
+            ))
+            .execute(conn)?;
+        Ok(())
+    }
+
     /// Promote the deployment `id` to the current version everywhere where it was
     /// the pending version so far, and remove any assignments that are not needed
     /// any longer as a result. Return the changes that were made to assignments
@@ -783,12 +1053,7 @@ impl<'a> Connection<'a> {
 
         // Switch the pending version to the current version
         for (subgraph, version) in &pending_subgraph_versions {
-            update(s::table.filter(s::id.eq(subgraph)))
-                .set((
-                    s::current_version.eq(version),
-                    s::pending_version.eq::<Option<&str>>(None),
-                ))
-                .execute(conn)?;
+            self.make_current(subgraph, version)?;
         }
 
         // Clean up assignments if we could possibly have changed any
@@ -841,6 +1106,7 @@ impl<'a> Connection<'a> {
         site: &Site,
         node_id: NodeId,
         mode: SubgraphVersionSwitchingMode,
+        previous: PreviousVersion,
         exists_and_synced: F,
     ) -> Result<Vec<EntityChange>, StoreError>
     where
@@ -929,18 +1195,16 @@ impl<'a> Connection<'a> {
         }
 
         // See if we should make this the current or pending version
+        update(s::table.filter(s::id.eq(&subgraph_id)))
+            .set(s::retain_previous_version.eq(previous == PreviousVersion::Retain))
+            .execute(conn)?;
         let subgraph_row = update(s::table.filter(s::id.eq(&subgraph_id)));
         // When the new deployment is also synced already, we always want to
         // overwrite the current version
         let new_exists_and_synced = exists_and_synced(&site.deployment)?;
         match (mode, current_exists_and_synced, new_exists_and_synced) {
             (Instant, _, _) | (Synced, false, _) | (Synced, true, true) => {
-                subgraph_row
-                    .set((
-                        s::current_version.eq(&version_id),
-                        s::pending_version.eq::<Option<&str>>(None),
-                    ))
-                    .execute(conn)?;
+                self.make_current(&subgraph_id, &version_id)?;
             }
             (Synced, true, false) => {
                 subgraph_row
@@ -974,6 +1238,7 @@ impl<'a> Connection<'a> {
         if let Some(subgraph) = subgraph {
             delete(v::table.filter(v::subgraph.eq(&subgraph))).execute(conn)?;
             delete(s::table.filter(s::id.eq(subgraph))).execute(conn)?;
+            self.set_deprecation(&name, None)?;
             self.remove_unused_assignments()
         } else {
             Ok(vec![])
@@ -1314,6 +1579,7 @@ impl<'a> Connection<'a> {
     /// primary will be filled in `unused_deployments`
     pub fn detect_unused_deployments(&self) -> Result<Vec<Site>, StoreError> {
         use active_copies as cp;
+        use deployment_query_activity as qa;
         use deployment_schemas as ds;
         use subgraph as s;
         use subgraph_deployment_assignment as a;
@@ -1322,17 +1588,22 @@ impl<'a> Connection<'a> {
 
         // Deployment is assigned
         let assigned = a::table.filter(a::id.eq(ds::id));
-        // Deployment is current or pending version
+        // Deployment is current, pending or retained previous version
         let current_or_pending = v::table
             .inner_join(
                 s::table.on(v::id
                     .nullable()
                     .eq(s::current_version)
-                    .or(v::id.nullable().eq(s::pending_version))),
+                    .or(v::id.nullable().eq(s::pending_version))
+                    .or(v::id.nullable().eq(s::previous_version))),
             )
             .filter(v::deployment.eq(ds::subgraph));
         // Deployment is the source of an in-progress copy
         let copy_src = cp::table.filter(cp::src.eq(ds::id));
+        // Deployment was queried recently
+        let queried = qa::table
+            .filter(qa::id.eq(ds::id))
+            .filter(qa::last_queried_at.ge(ago(ENV_VARS.store.unused_query_interval)));
 
         // Subgraphs that used a deployment
         let used_by = s::table
@@ -1347,10 +1618,12 @@ impl<'a> Connection<'a> {
         //    pending version of a subgraph. The rest of the system makes
         //    sure that there is always one active copy of a deployment
         // 3. It is not the source of a currently running copy operation
+        // 4. It was not queried within `GRAPH_UNUSED_QUERY_INTERVAL`
         let unused = ds::table
             .filter(not(exists(assigned)))
             .filter(not(ds::active).or(not(exists(current_or_pending))))
             .filter(not(exists(copy_src)))
+            .filter(not(exists(queried)))
             .select((
                 ds::id,
                 ds::created_at,
@@ -1419,6 +1692,45 @@ impl<'a> Connection<'a> {
         Ok(())
     }
 
+    /// Record that the deployments `ids` were just queried. Deployments
+    /// that were marked as unused but not removed yet are in use again
+    pub fn record_query_activity(&self, ids: &[DeploymentId]) -> Result<(), StoreError> {
+        use deployment_query_activity as qa;
+        use unused_deployments as u;
+
+        if ids.is_empty() {
+            return Ok(());
+        }
+
+        // Postgres refuses to update the same row twice in one statement
+        let ids: Vec<_> = ids
+            .iter()
+            .copied()
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .collect();
+        let values: Vec<_> = ids
+            .iter()
+            .map(|id| (qa::id.eq(*id), qa::last_queried_at.eq(sql("now()"))))
+            .collect();
+
+        self.transaction(|| {
+            insert_into(qa::table)
+                .values(&values)
+                .on_conflict(qa::id)
+                .do_update()
+                .set(qa::last_queried_at.eq(sql("now()")))
+                .execute(self.conn.as_ref())?;
+            delete(
+                u::table
+                    .filter(u::id.eq_any(ids))
+                    .filter(u::removed_at.is_null()),
+            )
+            .execute(self.conn.as_ref())?;
+            Ok(())
+        })
+    }
+
     /// The deployment `site` that we marked as unused previously is in fact
     /// now used again, e.g., because it was redeployed in between recording
     /// it as unused and now. Remove it from the `unused_deployments` table
@@ -1445,22 +1757,129 @@ impl<'a> Connection<'a> {
                 .filter(u::removed_at.is_null())
                 .order_by(u::entity_count)
                 .load(self.conn.as_ref())?),
-            UnusedLongerThan(duration) => {
-                let ts = chrono::offset::Local::now()
-                    .checked_sub_signed(duration)
-                    .ok_or_else(|| {
-                        StoreError::ConstraintViolation(format!(
-                            "duration {} is too large",
-                            duration
-                        ))
-                    })?;
-                Ok(u::table
-                    .filter(u::removed_at.is_null())
-                    .filter(u::unused_at.lt(ts))
-                    .order_by(u::entity_count)
-                    .load(self.conn.as_ref())?)
+            UnusedLongerThan(duration) => Ok(u::table
+                .filter(u::removed_at.is_null())
+                .filter(u::unused_at.lt(ago(duration)))
+                .order_by(u::entity_count)
+                .load(self.conn.as_ref())?),
+        }
+    }
+
+    pub fn assignments(&self, node: &NodeId) -> Result<Vec<Site>, StoreError> {
+        queries::assignments(self.conn.as_ref(), node)
+    }
+
+    pub fn record_heartbeat(&self, node: &NodeId, role: NodeRole) -> Result<(), StoreError> {
+        use node_heartbeats as h;
+
+        insert_into(h::table)
+            .values((
+                h::node_id.eq(node.as_str()),
+                h::role.eq(role.as_str()),
+                h::heartbeat_at.eq(sql("now()")),
+            ))
+            .on_conflict(h::node_id)
+            .do_update()
+            .set((h::role.eq(role.as_str()), h::heartbeat_at.eq(sql("now()"))))
+            .execute(self.conn.as_ref())?;
+        Ok(())
+    }
+
+    /// Return all index nodes that have sent a heartbeat within the last
+    /// `timeout` and all the ones whose last heartbeat is older than that
+    pub fn index_nodes_by_heartbeat(
+        &self,
+        timeout: chrono::Duration,
+    ) -> Result<(Vec<NodeId>, Vec<NodeId>), StoreError> {
+        use node_heartbeats as h;
+
+        let nodes = h::table
+            .filter(h::role.eq(NodeRole::Index.as_str()))
+            .select((h::node_id, h::heartbeat_at.ge(ago(timeout))))
+            .load::<(String, bool)>(self.conn.as_ref())?;
+
+        let mut responsive = Vec::new();
+        let mut unresponsive = Vec::new();
+        for (node, alive) in nodes {
+            let node = NodeId::new(&node).map_err(|()| {
+                constraint_violation!("database has heartbeat for illegal node name {:?}", node)
+            })?;
+            if alive {
+                responsive.push(node);
+            } else {
+                unresponsive.push(node);
             }
         }
+        Ok((responsive, unresponsive))
+    }
+
+    /// Try to get the lock that makes sure that only one node reassigns
+    /// deployments from unresponsive nodes at a time. The lock is released
+    /// when the current transaction ends
+    pub fn try_lock_reassignment(&self) -> Result<bool, StoreError> {
+        advisory_lock::try_lock_reassignment(self.conn.as_ref())
+    }
+
+    /// Record the load that `node` measured for the deployments it indexes
+    pub fn record_deployment_load(
+        &self,
+        node: &NodeId,
+        loads: &[DeploymentLoad],
+    ) -> Result<(), StoreError> {
+        use deployment_load as l;
+
+        for load in loads {
+            insert_into(l::table)
+                .values((
+                    l::deployment.eq(load.deployment.as_str()),
+                    l::node_id.eq(node.as_str()),
+                    l::blocks_per_minute.eq(load.blocks_per_minute),
+                    l::handler_secs_per_min.eq(load.handler_secs_per_minute),
+                    l::updated_at.eq(sql("now()")),
+                ))
+                .on_conflict(l::deployment)
+                .do_update()
+                .set((
+                    l::node_id.eq(node.as_str()),
+                    l::blocks_per_minute.eq(load.blocks_per_minute),
+                    l::handler_secs_per_min.eq(load.handler_secs_per_minute),
+                    l::updated_at.eq(sql("now()")),
+                ))
+                .execute(self.conn.as_ref())?;
+        }
+        Ok(())
+    }
+
+    /// The load of all deployments that was reported within the last
+    /// `max_age`
+    pub fn deployment_loads(
+        &self,
+        max_age: chrono::Duration,
+    ) -> Result<Vec<DeploymentLoad>, StoreError> {
+        use deployment_load as l;
+
+        l::table
+            .filter(l::updated_at.ge(ago(max_age)))
+            .select((l::deployment, l::blocks_per_minute, l::handler_secs_per_min))
+            .load::<(String, f64, f64)>(self.conn.as_ref())?
+            .into_iter()
+            .map(|(deployment, blocks_per_minute, handler_secs_per_minute)| {
+                let deployment = DeploymentHash::new(deployment).map_err(|hash| {
+                    constraint_violation!("invalid deployment hash `{}` in deployment_load", hash)
+                })?;
+                Ok(DeploymentLoad {
+                    deployment,
+                    blocks_per_minute,
+                    handler_secs_per_minute,
+                })
+            })
+            .collect()
+    }
+
+    /// Try to get the lock that makes sure that only one rebalancing runs
+    /// at a time. The lock is released when the current transaction ends
+    pub fn try_lock_rebalance(&self) -> Result<bool, StoreError> {
+        advisory_lock::try_lock_rebalance(self.conn.as_ref())
     }
 
     pub fn subgraphs_using_deployment(&self, site: &Site) -> Result<Vec<String>, StoreError> {
@@ -1491,6 +1910,369 @@ impl<'a> Connection<'a> {
             .map_err(|e| anyhow!("error looking up ens_name for hash {}: {}", hash, e).into())
     }
 
+    /// Return the contents of the deployment file for `link` after checking
+    /// them against their digest
+    pub fn find_deployment_file(&self, link: &str) -> Result<Option<Vec<u8>>, StoreError> {
+        use deployment_files as f;
+
+        let file = f::table
+            .select((f::digest, f::content))
+            .find(link)
+            .get_result::<(Vec<u8>, Vec<u8>)>(self.conn.as_ref())
+            .optional()?;
+        match file {
+            Some((digest, content)) => {
+                if blake3::hash(&content).as_bytes().as_slice() != digest.as_slice() {
+                    return Err(constraint_violation!(
+                        "the stored contents of the deployment file {} do not match their digest",
+                        link
+                    ));
+                }
+                Ok(Some(content))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Store `content` as the deployment file for `link`, replacing
+    /// contents that were stored before
+    pub fn store_deployment_file(&self, link: &str, content: &[u8]) -> Result<(), StoreError> {
+        use deployment_files as f;
+
+        let digest = blake3::hash(content).as_bytes().to_vec();
+        insert_into(f::table)
+            .values((
+                f::link.eq(link),
+                f::digest.eq(&digest),
+                f::size.eq(content.len() as i32),
+                f::content.eq(content),
+            ))
+            .on_conflict(f::link)
+            .do_update()
+            .set((
+                f::digest.eq(&digest),
+                f::size.eq(content.len() as i32),
+                f::content.eq(content),
+                f::stored_at.eq(sql("now()")),
+            ))
+            .execute(self.conn.as_ref())?;
+        Ok(())
+    }
+
+    /// Record that `deployment`, which indexes `network`, belongs to
+    /// `family`. Deployments can only belong to one family; recording them
+    /// again changes nothing
+    pub fn add_to_family(
+        &self,
+        family: &DeploymentHash,
+        deployment: &DeploymentHash,
+        network: &str,
+    ) -> Result<(), StoreError> {
+        use deployment_families as df;
+
+        insert_into(df::table)
+            .values((
+                df::deployment.eq(deployment.as_str()),
+                df::family.eq(family.as_str()),
+                df::network.eq(network),
+            ))
+            .on_conflict_do_nothing()
+            .execute(self.conn.as_ref())?;
+        Ok(())
+    }
+
+    /// The deployments in `family` together with their networks, ordered
+    /// by network
+    pub fn family(&self, family: &DeploymentHash) -> Result<Vec<(String, String)>, StoreError> {
+        use deployment_families as df;
+
+        df::table
+            .filter(df::family.eq(family.as_str()))
+            .select((df::deployment, df::network))
+            .order_by(df::network)
+            .load::<(String, String)>(self.conn.as_ref())
+            .map_err(StoreError::from)
+    }
+
+    /// Attach `deprecation` to the subgraph `name`, replacing any notice
+    /// it had, or remove its notice if `deprecation` is `None`
+    pub fn set_deprecation(
+        &self,
+        name: &SubgraphName,
+        deprecation: Option<&SubgraphDeprecation>,
+    ) -> Result<(), StoreError> {
+        use subgraph_deprecations as sd;
+
+        let conn = self.conn.as_ref();
+        match deprecation {
+            Some(deprecation) => {
+                insert_into(sd::table)
+                    .values((
+                        sd::name.eq(name.as_str()),
+                        sd::message.eq(&deprecation.message),
+                        sd::removal_date.eq(deprecation.removal_date),
+                    ))
+                    .on_conflict(sd::name)
+                    .do_update()
+                    .set((
+                        sd::message.eq(&deprecation.message),
+                        sd::removal_date.eq(deprecation.removal_date),
+                    ))
+                    .execute(conn)?;
+            }
+            None => {
+                delete(sd::table.filter(sd::name.eq(name.as_str()))).execute(conn)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// All deprecation notices, by subgraph name
+    pub fn deprecations(&self) -> Result<HashMap<String, SubgraphDeprecation>, StoreError> {
+        use subgraph_deprecations as sd;
+
+        Ok(sd::table
+            .select((sd::name, sd::message, sd::removal_date))
+            .load::<(String, String, chrono::NaiveDate)>(self.conn.as_ref())?
+            .into_iter()
+            .map(|(name, message, removal_date)| {
+                (
+                    name,
+                    SubgraphDeprecation {
+                        message,
+                        removal_date,
+                    },
+                )
+            })
+            .collect())
+    }
+
+    /// The current time according to the database, in seconds since the
+    /// epoch
+    pub fn now(&self) -> Result<i64, StoreError> {
+        select(sql::<diesel::sql_types::BigInt>(
+            "extract(epoch from now())::int8",
+        ))
+        .get_result::<i64>(self.conn.as_ref())
+        .map_err(StoreError::from)
+    }
+
+    /// Add a job for `site` to the end of the queue and return its id
+    pub fn enqueue_job(&self, site: &Site, request: &JobRequest) -> Result<i32, StoreError> {
+        use jobs as j;
+
+        let json = serde_json::to_value(request)
+            .map_err(|e| constraint_violation!("can not serialize job request: {}", e))?;
+        insert_into(j::table)
+            .values((
+                j::deployment.eq(site.deployment.as_str()),
+                j::deployment_id.eq(site.id),
+                j::kind.eq(request.kind()),
+                j::request.eq(json),
+            ))
+            .returning(j::id)
+            .get_result::<i32>(self.conn.as_ref())
+            .map_err(StoreError::from)
+    }
+
+    /// The jobs that match `filter`, newest first
+    pub fn jobs(&self, filter: &JobFilter) -> Result<Vec<status::JobInfo>, StoreError> {
+        use jobs as j;
+
+        let mut query = j::table.select(JOB_COLUMNS).into_boxed();
+        if let Some(id) = filter.id {
+            query = query.filter(j::id.eq(id));
+        }
+        if let Some(deployment) = &filter.deployment {
+            query = query.filter(j::deployment.eq(deployment));
+        }
+        if !filter.states.is_empty() {
+            let states: Vec<_> = filter.states.iter().map(|state| state.as_str()).collect();
+            query = query.filter(j::state.eq_any(states));
+        }
+        query
+            .order_by(j::id.desc())
+            .load::<JobRow>(self.conn.as_ref())?
+            .into_iter()
+            .map(status::JobInfo::try_from)
+            .collect()
+    }
+
+    /// Mark the oldest queued job as running on `node` and return it.
+    /// Nodes that look for jobs at the same time get different jobs
+    pub fn claim_job(&self, node: &NodeId) -> Result<Option<QueuedJob>, StoreError> {
+        use jobs as j;
+
+        let conn = self.conn.as_ref();
+        conn.transaction(|| {
+            let id = j::table
+                .filter(j::state.eq(JobState::Queued.as_str()))
+                .order_by(j::id)
+                .select(j::id)
+                .for_update()
+                .skip_locked()
+                .first::<i32>(conn)
+                .optional()?;
+            let id = match id {
+                Some(id) => id,
+                None => return Ok(None),
+            };
+            update(j::table.filter(j::id.eq(id)))
+                .set((
+                    j::state.eq(JobState::Running.as_str()),
+                    j::node_id.eq(node.as_str()),
+                    j::started_at.eq(sql("coalesce(started_at, now())")),
+                ))
+                .returning(JOB_COLUMNS)
+                .get_result::<JobRow>(conn)
+                .map_err(StoreError::from)
+                .and_then(QueuedJob::try_from)
+                .map(Some)
+        })
+    }
+
+    /// The jobs that are running on `node`, oldest first
+    pub fn running_jobs(&self, node: &NodeId) -> Result<Vec<QueuedJob>, StoreError> {
+        use jobs as j;
+
+        j::table
+            .filter(j::state.eq(JobState::Running.as_str()))
+            .filter(j::node_id.eq(node.as_str()))
+            .order_by(j::id)
+            .select(JOB_COLUMNS)
+            .load::<JobRow>(self.conn.as_ref())?
+            .into_iter()
+            .map(QueuedJob::try_from)
+            .collect()
+    }
+
+    /// Put the jobs that are running on one of `nodes` back into the queue
+    /// so that whichever node picks them up next continues them. Return
+    /// the ids of the jobs
+    pub fn requeue_jobs(&self, nodes: &[NodeId]) -> Result<Vec<i32>, StoreError> {
+        use jobs as j;
+
+        let nodes: Vec<_> = nodes.iter().map(|node| node.as_str()).collect();
+        update(
+            j::table
+                .filter(j::state.eq(JobState::Running.as_str()))
+                .filter(j::node_id.eq_any(nodes)),
+        )
+        .set(j::state.eq(JobState::Queued.as_str()))
+        .returning(j::id)
+        .get_results::<i32>(self.conn.as_ref())
+        .map_err(StoreError::from)
+    }
+
+    /// Record how far the job `id` got. Return whether cancelling the job
+    /// has been requested
+    pub fn record_job_progress(
+        &self,
+        id: i32,
+        progress: &serde_json::Value,
+        done: i64,
+        total: Option<i64>,
+    ) -> Result<bool, StoreError> {
+        use jobs as j;
+
+        update(j::table.filter(j::id.eq(id)))
+            .set((
+                j::progress.eq(progress),
+                j::done.eq(done),
+                j::total.eq(total),
+            ))
+            .returning(j::cancel_requested_at.is_not_null())
+            .get_result::<bool>(self.conn.as_ref())
+            .map_err(StoreError::from)
+    }
+
+    /// Record that the job `id` ended up in `state`, which must be one of
+    /// the states in which jobs are finished
+    pub fn finish_job(
+        &self,
+        id: i32,
+        state: JobState,
+        outcome: Option<&str>,
+        error: Option<&str>,
+    ) -> Result<(), StoreError> {
+        use jobs as j;
+
+        update(j::table.filter(j::id.eq(id)))
+            .set((
+                j::state.eq(state.as_str()),
+                j::outcome.eq(outcome),
+                j::error.eq(error),
+                j::finished_at.eq(sql("now()")),
+            ))
+            .execute(self.conn.as_ref())?;
+        Ok(())
+    }
+
+    /// Cancel the job `id`. Queued jobs are cancelled right away, running
+    /// jobs stop the next time the node running them checks for that.
+    /// Return the state the job is in afterwards, or `None` if there is no
+    /// such job
+    pub fn cancel_job(&self, id: i32) -> Result<Option<JobState>, StoreError> {
+        use jobs as j;
+
+        let conn = self.conn.as_ref();
+        conn.transaction(|| {
+            let state = match j::table
+                .filter(j::id.eq(id))
+                .select(j::state)
+                .for_update()
+                .first::<String>(conn)
+                .optional()?
+            {
+                Some(state) => state
+                    .parse::<JobState>()
+                    .map_err(|e| constraint_violation!("job {}: {}", id, e))?,
+                None => return Ok(None),
+            };
+            match state {
+                JobState::Queued => {
+                    update(j::table.filter(j::id.eq(id)))
+                        .set((
+                            j::state.eq(JobState::Cancelled.as_str()),
+                            j::cancel_requested_at.eq(sql("now()")),
+                            j::finished_at.eq(sql("now()")),
+                        ))
+                        .execute(conn)?;
+                    Ok(Some(JobState::Cancelled))
+                }
+                JobState::Running => {
+                    update(j::table.filter(j::id.eq(id)))
+                        .set(j::cancel_requested_at.eq(sql("coalesce(cancel_requested_at, now())")))
+                        .execute(conn)?;
+                    Ok(Some(JobState::Running))
+                }
+                JobState::Succeeded | JobState::Failed | JobState::Cancelled => Ok(Some(state)),
+            }
+        })
+    }
+
+    pub fn record_admin_call(&self, call: &AdminCall) -> Result<(), StoreError> {
+        use admin_audit_log as a;
+
+        insert_into(a::table)
+            .values((
+                a::method.eq(&call.method),
+                a::token_name.eq(&call.token),
+                a::role.eq(&call.role),
+                a::params.eq(&call.params),
+                a::outcome.eq(call.outcome.as_str()),
+                a::error.eq(&call.error),
+            ))
+            .execute(self.conn.as_ref())?;
+        Ok(())
+    }
+
+    /// Signal the copy process that copies into `site`, if there is one,
+    /// that it should stop
+    pub fn cancel_copy(&self, site: &Site) -> Result<(), StoreError> {
+        self.cancel_copies(vec![site.id])
+    }
+
     pub fn record_active_copy(&self, src: &Site, dst: &Site) -> Result<(), StoreError> {
         use active_copies as cp;
 
@@ -1674,6 +2456,15 @@ impl Mirror {
         self.read(|conn| queries::current_deployment_for_subgraph(conn, name))
     }
 
+    /// The deployment of the current version of `name`, or `None` if
+    /// `name` does not exist or has no current version
+    pub fn current_deployment(
+        &self,
+        name: &SubgraphName,
+    ) -> Result<Option<DeploymentHash>, StoreError> {
+        self.read(|conn| queries::current_deployment(conn, name))
+    }
+
     pub fn deployments_for_subgraph(&self, name: &str) -> Result<Vec<Site>, StoreError> {
         self.read(|conn| queries::deployments_for_subgraph(conn, name))
     }
@@ -4,6 +4,7 @@ use web3::types::H256;
 
 use crate::deployment_store::{DeploymentStore, ReplicaId};
 use graph::components::store::QueryStore as QueryStoreTrait;
+use graph::data::query::QueryExplanation;
 use graph::prelude::*;
 
 use crate::primary::Site;
@@ -45,6 +46,42 @@ impl QueryStoreTrait for QueryStore {
         self.store.execute_query(&conn, self.site.clone(), query)
     }
 
+    fn explain_query(&self, query: EntityQuery) -> Result<QueryExplanation, QueryExecutionError> {
+        assert_eq!(&self.site.deployment, &query.subgraph_id);
+        let conn = self
+            .store
+            .get_replica_conn(self.replica_id)
+            .map_err(|e| QueryExecutionError::StoreError(e.into()))?;
+        self.store.explain_query(&conn, self.site.clone(), query)
+    }
+
+    fn execute_sql(
+        &self,
+        sql: &str,
+        block: BlockNumber,
+    ) -> Result<Vec<serde_json::Value>, QueryExecutionError> {
+        let conn = self
+            .store
+            .get_replica_conn(self.replica_id)
+            .map_err(|e| QueryExecutionError::StoreError(e.into()))?;
+        self.store.execute_sql(&conn, self.site.clone(), sql, block)
+    }
+
+    fn changed_blocks(
+        &self,
+        after: BlockNumber,
+        until: BlockNumber,
+    ) -> Result<Vec<BlockNumber>, StoreError> {
+        let conn = self.store.get_replica_conn(self.replica_id)?;
+        self.store
+            .changed_blocks(&conn, self.site.clone(), after, until)
+    }
+
+    fn entity_changes(&self, block: BlockNumber) -> Result<Vec<EntityOperation>, StoreError> {
+        let conn = self.store.get_replica_conn(self.replica_id)?;
+        self.store.entity_changes(&conn, self.site.clone(), block)
+    }
+
     /// Return true if the deployment with the given id is fully synced,
     /// and return false otherwise. Errors from the store are passed back up
     async fn is_deployment_synced(&self) -> Result<bool, Error> {
@@ -81,6 +118,22 @@ impl QueryStoreTrait for QueryStore {
             .transpose()
     }
 
+    fn block_parent_hash(&self, block_hash: H256) -> Result<Option<H256>, StoreError> {
+        self.chain_store.block_parent_hash(block_hash)
+    }
+
+    fn chain_head_number(&self) -> Result<Option<BlockNumber>, StoreError> {
+        self.chain_store.chain_head_block(self.network_name())
+    }
+
+    fn block_time(&self, hash: &H256) -> Result<Option<u64>, StoreError> {
+        self.chain_store.block_time(hash)
+    }
+
+    fn block_at_time(&self, timestamp: u64) -> Result<Option<BlockPtr>, StoreError> {
+        self.chain_store.block_at_time(timestamp)
+    }
+
     fn wait_stats(&self) -> PoolWaitStats {
         self.store.wait_stats(self.replica_id)
     }
@@ -106,11 +159,18 @@ impl QueryStoreTrait for QueryStore {
         Ok(info.api)
     }
 
+    fn input_schema(&self) -> Result<Arc<Schema>, QueryExecutionError> {
+        let info = self.store.subgraph_info(&self.site)?;
+        Ok(info.input)
+    }
+
     fn network_name(&self) -> &str {
         &self.site.network
     }
 
-    async fn query_permit(&self) -> tokio::sync::OwnedSemaphorePermit {
-        self.store.query_permit(self.replica_id).await
+    async fn query_permit(&self) -> Result<QueryPermit, QueryExecutionError> {
+        self.store
+            .query_permit(self.replica_id, &self.site.deployment)
+            .await
     }
 }
@@ -0,0 +1,173 @@
+//! Even out the load of deployments across index nodes. The load of a
+//! deployment is the time its handlers spent processing triggers per minute
+//! as reported by the index node that indexes it
+use std::collections::HashMap;
+
+use graph::prelude::{DeploymentHash, NodeId};
+
+/// A deployment, the node it is currently assigned to, and the nodes it
+/// may be moved to according to the deployment rules. `None` means that it
+/// can be moved to any node
+#[derive(Clone, Debug)]
+pub(crate) struct Assignment {
+    pub deployment: DeploymentHash,
+    pub node: NodeId,
+    pub load: f64,
+    pub allowed: Option<Vec<NodeId>>,
+}
+
+/// Moving `deployment` from one node to another, with the load that moves
+/// along with it
+#[derive(Clone, Debug, PartialEq)]
+pub struct Move {
+    pub deployment: DeploymentHash,
+    pub from: NodeId,
+    pub to: NodeId,
+    pub load: f64,
+}
+
+/// Plan at most `max_moves` moves of deployments from nodes whose load is
+/// more than `threshold` above the average load of `nodes` to the least
+/// loaded node that the deployment may go to. A deployment is only moved if
+/// that does not leave the target node busier than the node the deployment
+/// moved away from. Assignments to nodes that are not in `nodes` are ignored
+pub(crate) fn plan(
+    nodes: &[NodeId],
+    mut assignments: Vec<Assignment>,
+    threshold: f64,
+    max_moves: usize,
+) -> Vec<Move> {
+    let mut loads: HashMap<&NodeId, f64> = nodes.iter().map(|node| (node, 0.0)).collect();
+    for assignment in &assignments {
+        if let Some(load) = loads.get_mut(&assignment.node) {
+            *load += assignment.load;
+        }
+    }
+    if loads.len() < 2 {
+        return vec![];
+    }
+    let average = loads.values().sum::<f64>() / loads.len() as f64;
+    if average <= 0.0 {
+        return vec![];
+    }
+
+    // Consider the heaviest deployments first since they even things out
+    // with the fewest moves
+    assignments.sort_by(|a, b| b.load.total_cmp(&a.load));
+
+    let mut moves = Vec::new();
+    while moves.len() < max_moves {
+        let (busiest, max_load) = loads
+            .iter()
+            .map(|(node, load)| ((*node).clone(), *load))
+            .max_by(|(_, a), (_, b)| a.total_cmp(b))
+            .unwrap();
+        if max_load <= average * (1.0 + threshold) {
+            break;
+        }
+
+        let candidate = assignments
+            .iter_mut()
+            .filter(|assignment| assignment.node == busiest && assignment.load > 0.0)
+            .find_map(|assignment| {
+                let target = loads
+                    .iter()
+                    .filter(|(node, _)| ***node != busiest)
+                    .filter(|(node, _)| {
+                        assignment
+                            .allowed
+                            .as_ref()
+                            .map_or(true, |allowed| allowed.contains(**node))
+                    })
+                    .filter(|(_, load)| **load + assignment.load <= max_load - assignment.load)
+                    .min_by(|(_, a), (_, b)| a.total_cmp(b))
+                    .map(|(node, _)| (*node).clone());
+                target.map(|target| (target, assignment))
+            });
+
+        match candidate {
+            Some((target, assignment)) => {
+                *loads.get_mut(&busiest).unwrap() -= assignment.load;
+                *loads.get_mut(&target).unwrap() += assignment.load;
+                moves.push(Move {
+                    deployment: assignment.deployment.clone(),
+                    from: busiest,
+                    to: target.clone(),
+                    load: assignment.load,
+                });
+                assignment.node = target;
+            }
+            None => break,
+        }
+    }
+    moves
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn node(name: &str) -> NodeId {
+        NodeId::new(name).unwrap()
+    }
+
+    fn assignment(hash: &str, on: &str, load: f64, allowed: Option<&[&str]>) -> Assignment {
+        Assignment {
+            deployment: DeploymentHash::new(hash).unwrap(),
+            node: node(on),
+            load,
+            allowed: allowed.map(|allowed| allowed.iter().map(|name| node(name)).collect()),
+        }
+    }
+
+    #[test]
+    fn moves_load_from_busiest_node() {
+        let nodes = vec![node("a"), node("b")];
+        let assignments = vec![
+            assignment("QmOne", "a", 10.0, None),
+            assignment("QmTwo", "a", 5.0, None),
+            assignment("QmThree", "a", 1.0, None),
+            assignment("QmFour", "b", 1.0, None),
+        ];
+
+        // Moving `QmOne` would just make `b` the busiest node
+        let moves = plan(&nodes, assignments, 0.2, 10);
+        assert_eq!(2, moves.len());
+        assert_eq!("QmTwo", moves[0].deployment.as_str());
+        assert_eq!("QmThree", moves[1].deployment.as_str());
+        assert!(moves
+            .iter()
+            .all(|m| m.from == node("a") && m.to == node("b")));
+    }
+
+    #[test]
+    fn respects_rules_and_limits() {
+        let nodes = vec![node("a"), node("b"), node("c")];
+        let pinned = ["a"];
+        let assignments = vec![
+            assignment("QmOne", "a", 10.0, Some(&pinned)),
+            assignment("QmTwo", "a", 4.0, Some(&["a", "c"])),
+            assignment("QmThree", "a", 4.0, None),
+        ];
+
+        let moves = plan(&nodes, assignments.clone(), 0.2, 10);
+        assert!(moves.iter().all(|m| m.deployment.as_str() != "QmOne"));
+        assert!(moves
+            .iter()
+            .all(|m| m.deployment.as_str() != "QmTwo" || m.to == node("c")));
+        assert_eq!(2, moves.len());
+
+        let moves = plan(&nodes, assignments, 0.2, 1);
+        assert_eq!(1, moves.len());
+    }
+
+    #[test]
+    fn leaves_balanced_nodes_alone() {
+        let nodes = vec![node("a"), node("b")];
+        let assignments = vec![
+            assignment("QmOne", "a", 5.0, None),
+            assignment("QmTwo", "b", 4.5, None),
+        ];
+        assert!(plan(&nodes, assignments, 0.2, 10).is_empty());
+    }
+}
@@ -17,6 +17,7 @@ use diesel::{debug_query, OptionalExtension, PgConnection, RunQueryDsl};
 use graph::cheap_clone::CheapClone;
 use graph::constraint_violation;
 use graph::data::graphql::TypeExt as _;
+use graph::data::query::QueryExplanation;
 use graph::prelude::{q, s, StopwatchMetrics, ENV_VARS};
 use graph::slog::warn;
 use inflector::Inflector;
@@ -29,18 +30,22 @@ use std::str::FromStr;
 use std::sync::{Arc, Mutex};
 use std::time::{Duration, Instant};
 
-use crate::relational_queries::{FindChangesQuery, FindPossibleDeletionsQuery};
+use crate::relational_queries::{
+    ChangedBlock, ChangedBlocksQuery, FindChangesQuery, FindImmutableInsertsQuery,
+    FindPossibleDeletionsQuery,
+};
 use crate::{
     primary::{Namespace, Site},
     relational_queries::{
-        ClampRangeQuery, ConflictingEntityQuery, EntityData, EntityDeletion, FilterCollection,
-        FilterQuery, FindManyQuery, FindQuery, InsertQuery, RevertClampQuery, RevertRemoveQuery,
+        ClampRangeQuery, ConflictingEntityQuery, EntityData, EntityDeletion, ExplainQuery,
+        FilterCollection, FilterQuery, FindManyQuery, FindQuery, InsertQuery, RevertClampQuery,
+        RevertRemoveQuery,
     },
 };
 use graph::components::store::EntityType;
 use graph::data::graphql::ext::{DirectiveFinder, DocumentExt, ObjectTypeExt};
 use graph::data::schema::{FulltextConfig, FulltextDefinition, Schema, SCHEMA_TYPE_NAME};
-use graph::data::store::BYTES_SCALAR;
+use graph::data::store::{scalar, Value, BYTES_SCALAR};
 use graph::data::subgraph::schema::{POI_OBJECT, POI_TABLE};
 use graph::prelude::{
     anyhow, info, BlockNumber, DeploymentHash, Entity, EntityChange, EntityCollection,
@@ -210,6 +215,9 @@ pub struct Layout {
     pub enums: EnumMap,
     /// The query to count all entities
     pub count_query: String,
+    /// The query to determine how much space the subgraph's tables,
+    /// including their indexes, take up on disk
+    pub size_query: String,
 }
 
 impl Layout {
@@ -320,6 +328,14 @@ impl Layout {
             .collect::<Vec<_>>()
             .join("\nunion all\n");
         let count_query = format!("select sum(e.count) from ({}) e", count_query);
+        let size_query = format!(
+            "select coalesce(sum(pg_total_relation_size(c.oid)), 0) \
+               from pg_class c, pg_namespace n \
+              where c.relnamespace = n.oid \
+                and c.relkind = 'r' \
+                and n.nspname = '{}'",
+            &catalog.site.namespace
+        );
 
         let tables: HashMap<_, _> = tables
             .into_iter()
@@ -334,6 +350,7 @@ impl Layout {
             tables,
             enums,
             count_query,
+            size_query,
         })
     }
 
@@ -373,6 +390,9 @@ impl Layout {
             position: position as u32,
             is_account_like: false,
             immutable: false,
+            composite_id: vec![],
+            qualified_view: None,
+            partitioned: false,
         }
     }
 
@@ -411,6 +431,43 @@ impl Layout {
             .collect()
     }
 
+    /// Determine if the tables of the existing deployment with layout
+    /// `base` can be changed in place to match `self`. That is only
+    /// possible if `self` keeps every table, attribute, and enum value of
+    /// `base` exactly as it is, and only adds new tables, new enums, new
+    /// enum values, or new nullable attributes. Returns a list of errors if migrating is not
+    /// possible. An empty vector indicates that migrating is possible
+    pub fn can_migrate_from(&self, base: &Layout) -> Vec<String> {
+        let mut errors = Vec::new();
+        for (name, values) in &base.enums {
+            // Values can be added to an enum, but not removed
+            match self.enums.get(name) {
+                Some(new_values) => {
+                    let missing = values
+                        .difference(new_values)
+                        .map(String::as_str)
+                        .collect::<Vec<_>>()
+                        .join(", ");
+                    if !missing.is_empty() {
+                        errors.push(format!(
+                            "The enum {} does not have the values {} anymore",
+                            name, missing
+                        ));
+                    }
+                }
+                None => errors.push(format!("The enum {} was removed", name)),
+            }
+        }
+        for src in base.tables.values() {
+            match self.table(&src.name) {
+                Some(dst) => errors.extend(dst.can_migrate_from(src)),
+                None => errors.push(format!("The entity type {} was removed", src.object)),
+            }
+        }
+        errors.sort();
+        errors
+    }
+
     /// Import the database schema for this layout from its own database
     /// shard (in `self.site.shard`) into the database represented by `conn`
     /// if the schema for this layout does not exist yet
@@ -517,12 +574,61 @@ impl Layout {
                 tables.push(&**table);
             }
         }
+        self.changes_in(conn, &tables, block)
+    }
+
+    /// Return the changes that were made to entities in `block`. Unlike
+    /// `find_changes`, this also works for immutable tables, which can
+    /// only have inserts
+    pub fn entity_changes(
+        &self,
+        conn: &PgConnection,
+        block: BlockNumber,
+    ) -> Result<Vec<EntityOperation>, StoreError> {
+        let (immutable, mutable): (Vec<&Table>, Vec<&Table>) = self
+            .tables
+            .values()
+            .filter(|table| table.name.as_str() != POI_TABLE)
+            .map(|table| table.as_ref())
+            .partition(|table| table.immutable);
+
+        let mut changes = if mutable.is_empty() {
+            vec![]
+        } else {
+            self.changes_in(conn, &mutable, block)?
+        };
+        if !immutable.is_empty() {
+            let inserts =
+                FindImmutableInsertsQuery::new(&immutable, block).load::<EntityData>(conn)?;
+            for entity_data in inserts {
+                let entity_type = entity_data.entity_type();
+                let mut data: Entity = entity_data.deserialize_with_layout(self)?;
+                let entity_id = data.id().expect("Invalid ID for entity.");
+                data.remove("__typename")
+                    .expect("__typename expected; this is a bug");
+                changes.push(EntityOperation::Set {
+                    key: EntityKey {
+                        subgraph_id: self.site.deployment.cheap_clone(),
+                        entity_type,
+                        entity_id,
+                    },
+                    data,
+                });
+            }
+        }
+        Ok(changes)
+    }
 
-        let inserts_or_updates =
-            FindChangesQuery::new(&self.catalog.site.namespace, &tables[..], block)
-                .load::<EntityData>(conn)?;
+    fn changes_in(
+        &self,
+        conn: &PgConnection,
+        tables: &[&Table],
+        block: BlockNumber,
+    ) -> Result<Vec<EntityOperation>, StoreError> {
+        let inserts_or_updates = FindChangesQuery::new(&self.catalog.site.namespace, tables, block)
+            .load::<EntityData>(conn)?;
         let deletions =
-            FindPossibleDeletionsQuery::new(&self.catalog.site.namespace, &tables[..], block)
+            FindPossibleDeletionsQuery::new(&self.catalog.site.namespace, tables, block)
                 .load::<EntityDeletion>(conn)?;
 
         let mut processed_entities = HashSet::new();
@@ -568,6 +674,31 @@ impl Layout {
         Ok(changes)
     }
 
+    /// Return the numbers of the blocks in the range `(after, until]` in
+    /// which any entity changed, in ascending order
+    pub fn changed_blocks(
+        &self,
+        conn: &PgConnection,
+        after: BlockNumber,
+        until: BlockNumber,
+    ) -> Result<Vec<BlockNumber>, StoreError> {
+        let tables: Vec<_> = self
+            .tables
+            .values()
+            .filter(|table| table.name.as_str() != POI_TABLE)
+            .map(|table| table.as_ref())
+            .collect();
+        if tables.is_empty() {
+            return Ok(vec![]);
+        }
+
+        Ok(ChangedBlocksQuery::new(&tables[..], after, until)
+            .load::<ChangedBlock>(conn)?
+            .into_iter()
+            .map(|changed| changed.block)
+            .collect())
+    }
+
     pub fn insert<'a>(
         &'a self,
         conn: &PgConnection,
@@ -604,6 +735,22 @@ impl Layout {
             .map(|data| data.entity))
     }
 
+    /// Fail if the history the deployment needs to answer queries at
+    /// `block` has been pruned
+    fn check_not_pruned(&self, block: BlockNumber) -> Result<(), QueryExecutionError> {
+        match self.catalog.pruned_before {
+            Some(before) if block < before => Err(QueryExecutionError::ValueParseError(
+                "block.number".to_owned(),
+                format!(
+                    "the history of subgraph {} before block {} has been pruned \
+                     and data for block number {} is therefore no longer available",
+                    self.site.deployment, before, block
+                ),
+            )),
+            _ => Ok(()),
+        }
+    }
+
     /// order is a tuple (attribute, value_type, direction)
     pub fn query<T: crate::relational_queries::FromEntityData>(
         &self,
@@ -646,6 +793,7 @@ impl Layout {
             );
         }
 
+        self.check_not_pruned(block)?;
         let filter_collection = FilterCollection::new(self, collection, filter.as_ref())?;
         let query = FilterQuery::new(
             &filter_collection,
@@ -656,6 +804,7 @@ impl Layout {
             query_id,
         )?;
         let query_clone = query.clone();
+        let has_regex = filter.as_ref().map_or(false, has_matches);
 
         let start = Instant::now();
         let values = conn
@@ -663,15 +812,30 @@ impl Layout {
                 if let Some(ref timeout_sql) = *STATEMENT_TIMEOUT {
                     conn.batch_execute(timeout_sql)?;
                 }
-                query.load::<EntityData>(conn)
+                if has_regex {
+                    if let Err(e) = limit_regex_query(conn, &query)? {
+                        return Ok(Err(e));
+                    }
+                }
+                query.load::<EntityData>(conn).map(Ok)
             })
             .map_err(|e| match e {
+                diesel::result::Error::DatabaseError(
+                    diesel::result::DatabaseErrorKind::__Unknown,
+                    ref info,
+                ) if has_regex && is_statement_timeout(info.message()) => regex_filter_timeout(),
                 diesel::result::Error::DatabaseError(
                     diesel::result::DatabaseErrorKind::__Unknown,
                     ref info,
                 ) if info.message().starts_with("syntax error in tsquery") => {
                     QueryExecutionError::FulltextQueryInvalidSyntax(info.message().to_string())
                 }
+                diesel::result::Error::DatabaseError(
+                    diesel::result::DatabaseErrorKind::__Unknown,
+                    ref info,
+                ) if info.message().starts_with("invalid regular expression") => {
+                    QueryExecutionError::ResolveEntitiesError(info.message().to_string())
+                }
                 diesel::result::Error::QueryBuilderError(e) => {
                     QueryExecutionError::ResolveEntitiesError(e.to_string())
                 }
@@ -680,7 +844,7 @@ impl Layout {
                     e,
                     debug_query(&query_clone).to_string()
                 )),
-            })?;
+            })??;
         log_query_timing(logger, &query_clone, start.elapsed(), values.len());
         values
             .into_iter()
@@ -692,6 +856,69 @@ impl Layout {
             .collect()
     }
 
+    /// Run the query that `query` would run under `explain analyze` and
+    /// report the SQL and the plan that Postgres used for it
+    pub fn explain(
+        &self,
+        conn: &PgConnection,
+        collection: EntityCollection,
+        filter: Option<EntityFilter>,
+        order: EntityOrder,
+        range: EntityRange,
+        block: BlockNumber,
+        query_id: Option<String>,
+    ) -> Result<QueryExplanation, QueryExecutionError> {
+        let entity_types: BTreeSet<_> = match &collection {
+            EntityCollection::All(types) => types
+                .iter()
+                .map(|(entity_type, _)| entity_type.to_string())
+                .collect(),
+            EntityCollection::Window(windows) => windows
+                .iter()
+                .map(|window| window.child_type.to_string())
+                .collect(),
+        };
+
+        self.check_not_pruned(block)?;
+        let filter_collection = FilterCollection::new(self, collection, filter.as_ref())?;
+        let query = FilterQuery::new(
+            &filter_collection,
+            filter.as_ref(),
+            order,
+            range,
+            block,
+            query_id,
+        )?;
+
+        // `explain analyze` runs the query, and needs to be held to the
+        // same limits as running it directly
+        let has_regex = filter.as_ref().map_or(false, has_matches);
+        let plan = conn
+            .transaction(|| {
+                if let Some(ref timeout_sql) = *STATEMENT_TIMEOUT {
+                    conn.batch_execute(timeout_sql)?;
+                }
+                if has_regex {
+                    if let Err(e) = limit_regex_query(conn, &query)? {
+                        return Ok(Err(e));
+                    }
+                }
+                ExplainQuery::analyze(&query).plan(conn).map(Ok)
+            })
+            .map_err(|e| match e {
+                diesel::result::Error::DatabaseError(
+                    diesel::result::DatabaseErrorKind::__Unknown,
+                    ref info,
+                ) if has_regex && is_statement_timeout(info.message()) => regex_filter_timeout(),
+                _ => QueryExecutionError::ResolveEntitiesError(e.to_string()),
+            })??;
+        Ok(QueryExplanation::new(
+            entity_types.into_iter().collect(),
+            debug_query(&query).to_string(),
+            plan,
+        ))
+    }
+
     pub fn update<'a>(
         &'a self,
         conn: &PgConnection,
@@ -765,13 +992,17 @@ impl Layout {
     /// numbers. After this operation, only entity versions inserted or
     /// updated at blocks with numbers strictly lower than `block` will
     /// remain
+    ///
+    /// Besides the store event and the change in the entity count, return
+    /// the ids of the entities that the revert changed, by entity type
     pub fn revert_block(
         &self,
         conn: &PgConnection,
         block: BlockNumber,
-    ) -> Result<(StoreEvent, i32), StoreError> {
+    ) -> Result<(StoreEvent, i32, HashMap<EntityType, BTreeSet<String>>), StoreError> {
         let mut changes: Vec<EntityChange> = Vec::new();
         let mut count: i32 = 0;
+        let mut reverted = HashMap::new();
 
         for table in self.tables.values() {
             // Remove all versions whose entire block range lies beyond
@@ -801,6 +1032,17 @@ impl Layout {
             let deleted = removed.difference(&unclamped).count() as i32;
             let inserted = unclamped.difference(&removed).count() as i32;
             count += inserted - deleted;
+            if !table.object.is_poi() && !(removed.is_empty() && unclamped.is_empty()) {
+                let ids = removed
+                    .iter()
+                    .chain(unclamped.iter())
+                    .map(|id| match table.primary_key().column_type.id_type() {
+                        IdType::String => id.clone(),
+                        IdType::Bytes => format!("0x{}", id),
+                    })
+                    .collect::<BTreeSet<_>>();
+                reverted.insert(table.object.clone(), ids);
+            }
             // EntityChange for versions we just deleted
             let deleted = removed
                 .into_iter()
@@ -817,14 +1059,16 @@ impl Layout {
             });
             changes.extend(set);
         }
-        Ok((StoreEvent::new(changes), count))
+        Ok((StoreEvent::new(changes), count, reverted))
     }
 
     /// Revert the metadata (dynamic data sources and related entities) for
     /// the given `subgraph`.
     ///
-    /// For metadata, reversion always means deletion since the metadata that
-    /// is subject to reversion is only ever created but never updated
+    /// For metadata, reversion means deletion since the metadata that is
+    /// subject to reversion is only ever created but never updated. The one
+    /// exception are dynamic data sources that were removed in the reverted
+    /// blocks, which become active again
     pub fn revert_metadata(
         conn: &PgConnection,
         subgraph: &DeploymentHash,
@@ -844,8 +1088,9 @@ impl Layout {
     }
 
     /// Update the layout with the latest information from the database; for
-    /// now, an update only changes the `is_account_like` flag for tables or
-    /// the layout's site. If no update is needed, just return `self`.
+    /// now, an update only changes the `is_account_like` flag for tables,
+    /// the block before which history has been pruned, or the layout's
+    /// site. If no update is needed, just return `self`.
     pub fn refresh(
         self: Arc<Self>,
         conn: &PgConnection,
@@ -867,10 +1112,15 @@ impl Layout {
             .values()
             .filter(|table| table.is_account_like != is_account_like(table.as_ref()))
             .collect();
-        if changed_tables.is_empty() && site == self.site {
+        let pruned_before = crate::checkpoint::pruned_before(conn, &self.site)?;
+        if changed_tables.is_empty()
+            && site == self.site
+            && pruned_before == self.catalog.pruned_before
+        {
             return Ok(self);
         }
         let mut layout = (*self).clone();
+        layout.catalog.pruned_before = pruned_before;
         for table in changed_tables.into_iter() {
             let mut table = (*table.as_ref()).clone();
             table.is_account_like = is_account_like(&table);
@@ -881,6 +1131,57 @@ impl Layout {
     }
 }
 
+fn has_matches(filter: &EntityFilter) -> bool {
+    match filter {
+        EntityFilter::And(filters) | EntityFilter::Or(filters) => filters.iter().any(has_matches),
+        EntityFilter::Matches(_, _) => true,
+        _ => false,
+    }
+}
+
+/// Regular expressions can be arbitrarily expensive to evaluate, and
+/// Postgres does not take that into account when it estimates what a query
+/// costs. We therefore only run queries with a `_matches` filter if Postgres
+/// thinks they are cheap, and cancel them if they take longer than
+/// `GRAPH_GRAPHQL_REGEX_FILTER_TIMEOUT` anyway. This must be called in the
+/// transaction that runs `query`
+fn limit_regex_query(
+    conn: &PgConnection,
+    query: &FilterQuery,
+) -> Result<Result<(), QueryExecutionError>, diesel::result::Error> {
+    conn.batch_execute(&format!(
+        "set local statement_timeout={}",
+        regex_timeout().as_millis()
+    ))?;
+
+    let max_cost = ENV_VARS.graphql.regex_filter_max_cost;
+    let cost = ExplainQuery::new(query).cost(conn)?;
+    if cost > max_cost as f64 {
+        return Ok(Err(QueryExecutionError::RegexFilterTooExpensive(
+            cost as u64,
+            max_cost,
+        )));
+    }
+    Ok(Ok(()))
+}
+
+/// How long queries with a `_matches` filter may run; that is never longer
+/// than what `GRAPH_SQL_STATEMENT_TIMEOUT` allows for all queries
+fn regex_timeout() -> Duration {
+    match ENV_VARS.graphql.sql_statement_timeout {
+        Some(timeout) => timeout.min(ENV_VARS.graphql.regex_filter_timeout),
+        None => ENV_VARS.graphql.regex_filter_timeout,
+    }
+}
+
+fn is_statement_timeout(message: &str) -> bool {
+    message.starts_with("canceling statement due to statement timeout")
+}
+
+fn regex_filter_timeout() -> QueryExecutionError {
+    QueryExecutionError::RegexFilterTimeout(regex_timeout().as_secs())
+}
+
 /// A user-defined enum
 #[derive(Clone, Debug, PartialEq)]
 pub struct EnumType {
@@ -1118,6 +1419,15 @@ impl Column {
         self.name.as_str() == PRIMARY_KEY_COLUMN
     }
 
+    /// Whether we create an index on `lower(column)` to support
+    /// case-insensitive filters on this column
+    pub(crate) fn has_lowercase_index(&self) -> bool {
+        self.column_type == ColumnType::String
+            && !self.is_list()
+            && !self.is_reference()
+            && !self.is_primary_key()
+    }
+
     pub fn is_assignable_from(&self, source: &Self, object: &EntityType) -> Option<String> {
         if !self.is_nullable() && source.is_nullable() {
             Some(format!(
@@ -1181,6 +1491,21 @@ pub struct Table {
     /// Entities in this table are immutable, i.e., will never be updated or
     /// deleted
     pub(crate) immutable: bool,
+
+    /// The columns that form the key for the entities in this table, from
+    /// a `@compositeId` directive. Only immutable tables can have such a
+    /// key. It is the primary key of the table, and the `id` of entities is
+    /// formed from it and not stored
+    pub(crate) composite_id: Vec<SqlName>,
+
+    /// For tables with a composite id, the view that adds the `id` to the
+    /// rows of the table, qualified with the schema. See `Table::source`
+    qualified_view: Option<SqlName>,
+
+    /// The table is partitioned by the block at which entity versions were
+    /// closed, with one partition for all current versions. See
+    /// `Table::partition_ddl`
+    pub(crate) partitioned: bool,
 }
 
 impl Table {
@@ -1209,6 +1534,22 @@ impl Table {
             .contains(qualified_name.as_str());
 
         let immutable = defn.is_immutable();
+        let composite_id = defn
+            .composite_id()
+            .filter(|_| immutable)
+            .unwrap_or_default()
+            .into_iter()
+            .map(SqlName::from)
+            .collect::<Vec<_>>();
+        let qualified_view = if composite_id.is_empty() {
+            None
+        } else {
+            Some(SqlName::qualified_name(
+                &catalog.site.namespace,
+                &Self::view_name(&table_name),
+            ))
+        };
+        let partitioned = catalog.is_partitioned(&table_name);
 
         let table = Table {
             object: EntityType::from(defn),
@@ -1218,10 +1559,89 @@ impl Table {
             columns,
             position,
             immutable,
+            composite_id,
+            qualified_view,
+            partitioned,
         };
         Ok(table)
     }
 
+    /// The name of the view for the table `table_name` if it has a
+    /// composite id
+    pub(crate) fn view_name(table_name: &SqlName) -> SqlName {
+        SqlName::verbatim(format!("{}$view", table_name))
+    }
+
+    /// The relation, qualified with the schema, that queries read entities
+    /// from and that entities are inserted into and deleted from. That is
+    /// the table itself, except for tables with a composite id, where it is
+    /// a view that adds the `id` to the rows of the table
+    pub(crate) fn source(&self) -> &SqlName {
+        self.qualified_view.as_ref().unwrap_or(&self.qualified_name)
+    }
+
+    /// Whether the `id` of entities in this table is formed from the
+    /// columns of a composite id instead of being stored
+    pub(crate) fn has_composite_id(&self) -> bool {
+        !self.composite_id.is_empty()
+    }
+
+    /// The columns that form the composite id of the table, in the order
+    /// in which their values appear in the `id`
+    pub(crate) fn key_columns(&self) -> impl Iterator<Item = &Column> {
+        self.composite_id
+            .iter()
+            .filter_map(move |name| self.column(name))
+    }
+
+    /// Split the `id` of an entity in a table with a composite id back
+    /// into the values of the key columns, in the order of `key_columns`.
+    /// Return `None` if the table could not have formed `id`, which means
+    /// that no entity in it has that id. Key values are `Bytes` or integers,
+    /// and a `-` at the start of a value is the sign of an integer; all
+    /// other `-` separate the values
+    pub(crate) fn composite_key(&self, id: &str) -> Option<Vec<Value>> {
+        let mut rest = id;
+        let mut values = Vec::with_capacity(self.composite_id.len());
+        for (i, column) in self.key_columns().enumerate() {
+            if i > 0 {
+                rest = rest.strip_prefix('-')?;
+            }
+            let len = rest
+                .get(1..)
+                .and_then(|tail| tail.find('-'))
+                .map(|pos| pos + 1)
+                .unwrap_or(rest.len());
+            let (part, tail) = rest.split_at(len);
+            let value = match column.column_type {
+                ColumnType::Bytes => scalar::Bytes::from_str(part).ok().map(Value::Bytes),
+                ColumnType::Int => part.parse::<i32>().ok().map(Value::Int),
+                ColumnType::BigInt => scalar::BigInt::from_str(part).ok().map(Value::BigInt),
+                _ => None,
+            }?;
+            // Only the text that the table forms for the value can be part
+            // of an id, not, e.g., `0xAB` or `01`
+            if value.to_string() != part {
+                return None;
+            }
+            values.push(value);
+            rest = tail;
+        }
+        if rest.is_empty() && values.len() == self.composite_id.len() {
+            Some(values)
+        } else {
+            None
+        }
+    }
+
+    /// The columns that the table stores, which is all of them except for
+    /// the `id` of tables with a composite id
+    pub(crate) fn stored_columns(&self) -> impl Iterator<Item = &Column> {
+        self.columns
+            .iter()
+            .filter(move |column| !(self.has_composite_id() && column.is_primary_key()))
+    }
+
     /// Find the column `name` in this table. The name must be in snake case,
     /// i.e., use SQL conventions
     pub fn column(&self, name: &SqlName) -> Option<&Column> {
@@ -1263,6 +1683,72 @@ impl Table {
             .collect()
     }
 
+    fn can_migrate_from(&self, base: &Self) -> Vec<String> {
+        let mut errors = Vec::new();
+        if self.immutable != base.immutable {
+            errors.push(format!(
+                "The entity type {} changed whether it is immutable",
+                self.object
+            ));
+        }
+        if self.composite_id != base.composite_id {
+            errors.push(format!(
+                "The entity type {} changed its composite id",
+                self.object
+            ));
+        }
+        for bcol in &base.columns {
+            match self.columns.iter().find(|col| col.name == bcol.name) {
+                Some(col) => {
+                    // Changes to the values of enums are checked by the
+                    // layout
+                    let same_type = match (&col.column_type, &bcol.column_type) {
+                        (ColumnType::Enum(enum_type), ColumnType::Enum(base_type)) => {
+                            enum_type.name == base_type.name
+                        }
+                        (column_type, base_type) => column_type == base_type,
+                    };
+                    if col.field_type != bcol.field_type
+                        || !same_type
+                        || col.fulltext_fields != bcol.fulltext_fields
+                    {
+                        errors.push(format!(
+                            "The attribute {}.{} has type {}, \
+                             but its type in the deployment is {}",
+                            self.object, col.field, col.field_type, bcol.field_type
+                        ));
+                    }
+                }
+                None => errors.push(format!(
+                    "The attribute {}.{} was removed",
+                    self.object, bcol.field
+                )),
+            }
+        }
+        for col in self.new_columns(base) {
+            if col.is_fulltext() {
+                errors.push(format!(
+                    "The fulltext field {}.{} can not be added to an existing deployment",
+                    self.object, col.field
+                ));
+            } else if !col.is_nullable() {
+                errors.push(format!(
+                    "The attribute {}.{} is non-nullable, \
+                     but there is no such attribute in the deployment",
+                    self.object, col.field
+                ));
+            }
+        }
+        errors
+    }
+
+    /// The columns of this table that do not exist in `base`
+    pub(crate) fn new_columns<'a>(&'a self, base: &'a Self) -> impl Iterator<Item = &'a Column> {
+        self.columns
+            .iter()
+            .filter(move |col| base.columns.iter().all(|bcol| bcol.name != col.name))
+    }
+
     pub fn primary_key(&self) -> &Column {
         self.columns
             .iter()
@@ -1398,6 +1884,12 @@ impl LayoutCache {
         }
     }
 
+    /// Forget the layout for `site` so that the next lookup loads it from
+    /// the database again
+    pub(crate) fn remove(&self, site: &Site) {
+        self.entries.lock().unwrap().remove(&site.deployment);
+    }
+
     // Only needed for tests
     #[cfg(debug_assertions)]
     pub(crate) fn clear(&self) {
@@ -1,6 +1,9 @@
+use std::collections::BTreeSet;
 use std::fmt::{self, Write};
 
-use graph::prelude::BLOCK_NUMBER_MAX;
+use itertools::Itertools;
+
+use graph::prelude::{BlockNumber, BLOCK_NUMBER_MAX};
 
 use crate::relational::{
     ColumnType, BLOCK_COLUMN, BLOCK_RANGE_COLUMN, BYTE_ARRAY_PREFIX_SIZE, STRING_PREFIX_SIZE,
@@ -9,6 +12,9 @@ use crate::relational::{
 
 use super::{Column, Layout, SqlName, Table};
 
+/// Postgres truncates identifiers that are longer than this
+const MAX_IDENTIFIER_LENGTH: usize = 63;
+
 impl Layout {
     /// Generate the DDL for the entire layout, i.e., all `create table`
     /// and `create index` etc. statements needed in the database schema
@@ -33,24 +39,117 @@ impl Layout {
         Ok(out)
     }
 
-    pub(crate) fn write_enum_ddl(&self, out: &mut dyn Write) -> Result<(), fmt::Error> {
+    /// Generate the statements that add the values that this layout has
+    /// and `base` does not have to existing enums. Postgres before version
+    /// 12 can not add enum values inside a transaction block, and that
+    /// includes a string with several statements. Each statement therefore
+    /// has to be run by itself outside of a transaction. The statements
+    /// can be run again if the rest of the migration fails
+    pub fn enum_values_ddl(&self, base: &Layout) -> Result<Vec<String>, fmt::Error> {
+        let mut stmts = Vec::new();
+
         for (name, values) in &self.enums {
-            let mut sep = "";
-            let name = SqlName::from(name.as_str());
-            write!(
-                out,
-                "create type {}.{}\n    as enum (",
-                self.catalog.site.namespace,
-                name.quoted()
-            )?;
-            for value in values.iter() {
-                write!(out, "{}'{}'", sep, value)?;
-                sep = ", "
+            if let Some(base_values) = base.enums.get(name) {
+                // Enum values are sorted in the database so that sorting by
+                // an enum attribute sorts alphabetically. Keep it that way
+                // by adding values at the right spot
+                let mut current = base_values.as_ref().clone();
+                for value in values.difference(base_values) {
+                    let mut stmt = String::new();
+                    write!(
+                        stmt,
+                        "alter type {}.{}\n    add value if not exists '{}'",
+                        self.catalog.site.namespace,
+                        SqlName::from(name.as_str()).quoted(),
+                        value
+                    )?;
+                    if let Some(next) = current.range(value.clone()..).next() {
+                        write!(stmt, " before '{}'", next)?;
+                    }
+                    stmts.push(stmt);
+                    current.insert(value.clone());
+                }
+            }
+        }
+
+        Ok(stmts)
+    }
+
+    /// Generate the DDL that changes the database schema of the existing
+    /// deployment with layout `base` so that it matches this layout. The
+    /// two layouts must have been checked with `can_migrate_from`; only
+    /// new enums, new tables and new attributes of existing tables are
+    /// created. Values for existing enums come from `enum_values_ddl`
+    pub fn migration_ddl(&self, base: &Layout) -> Result<String, fmt::Error> {
+        let mut out = String::new();
+
+        for (name, values) in &self.enums {
+            if !base.enums.contains_key(name) {
+                self.write_one_enum_ddl(&mut out, name, values)?;
+            }
+        }
+
+        let mut tables = self.tables.values().collect::<Vec<_>>();
+        tables.sort_by_key(|table| table.position);
+        for table in tables {
+            match base.table(&table.name) {
+                None => table.as_ddl(&mut out, self)?,
+                Some(base_table) => {
+                    let new_columns = table.new_columns(base_table).collect::<Vec<_>>();
+                    if new_columns.is_empty() {
+                        continue;
+                    }
+                    for column in &new_columns {
+                        write!(
+                            out,
+                            "alter table {}.{}
+    add column",
+                            self.catalog.site.namespace,
+                            table.name.quoted()
+                        )?;
+                        column.as_ddl(&mut out)?;
+                        writeln!(out, ";")?;
+                    }
+                    // The view only has the columns the table had when
+                    // the view was created
+                    table.write_view(&mut out, self)?;
+                    table.write_attribute_indexes(&mut out, self, |column| {
+                        new_columns.iter().any(|new| new.name == column.name)
+                    })?;
+                }
             }
-            writeln!(out, ");")?;
+        }
+
+        Ok(out)
+    }
+
+    pub(crate) fn write_enum_ddl(&self, out: &mut dyn Write) -> Result<(), fmt::Error> {
+        for (name, values) in &self.enums {
+            self.write_one_enum_ddl(out, name, values)?;
         }
         Ok(())
     }
+
+    fn write_one_enum_ddl(
+        &self,
+        out: &mut dyn Write,
+        name: &str,
+        values: &BTreeSet<String>,
+    ) -> Result<(), fmt::Error> {
+        let mut sep = "";
+        let name = SqlName::from(name);
+        write!(
+            out,
+            "create type {}.{}\n    as enum (",
+            self.catalog.site.namespace,
+            name.quoted()
+        )?;
+        for value in values.iter() {
+            write!(out, "{}'{}'", sep, value)?;
+            sep = ", "
+        }
+        writeln!(out, ");")
+    }
 }
 
 impl Table {
@@ -62,7 +161,7 @@ impl Table {
     fn as_ddl(&self, out: &mut String, layout: &Layout) -> fmt::Result {
         fn columns_ddl(table: &Table) -> Result<String, fmt::Error> {
             let mut cols = String::new();
-            for column in &table.columns {
+            for column in table.stored_columns() {
                 write!(cols, "    ")?;
                 column.as_ddl(&mut cols)?;
                 writeln!(cols, ",")?;
@@ -71,7 +170,28 @@ impl Table {
         }
 
         fn create_table(table: &Table, out: &mut String, layout: &Layout) -> fmt::Result {
-            if table.immutable {
+            if table.has_composite_id() {
+                // The composite id is the primary key, and the `id` is
+                // formed from it by the view
+                writeln!(
+                    out,
+                    r#"
+                create table {nsp}.{name} (
+                    {vid}                  bigserial unique,
+                    {block}                int not null,
+                    {cols}
+                    primary key({key})
+                );
+                "#,
+                    nsp = layout.catalog.site.namespace,
+                    name = table.name.quoted(),
+                    cols = columns_ddl(table)?,
+                    vid = VID_COLUMN,
+                    block = BLOCK_COLUMN,
+                    key = table.composite_id.iter().map(SqlName::quoted).join(", ")
+                )?;
+                table.write_view(out, layout)
+            } else if table.immutable {
                 writeln!(
                     out,
                     r#"
@@ -90,6 +210,10 @@ impl Table {
                     id = table.primary_key().name
                 )
             } else {
+                // The exclusion constraint makes sure that no two versions
+                // of an entity overlap, and its GiST index is what finds
+                // the version of an entity at a block; unlike the GiST
+                // indexes for references, it is therefore kept
                 writeln!(
                     out,
                     r#"
@@ -109,145 +233,503 @@ impl Table {
             }
         }
 
-        fn create_time_travel_indexes(
-            table: &Table,
-            out: &mut String,
-            layout: &Layout,
-        ) -> fmt::Result {
-            if table.immutable {
-                write!(
-                    out,
-                    "create index brin_{table_name}\n    \
-                    on {schema_name}.{table_name}\n \
-                       using brin({block}, vid);\n",
-                    table_name = table.name,
-                    schema_name = layout.catalog.site.namespace,
-                    block = BLOCK_COLUMN
-                )
-            } else {
-                // Add a BRIN index on the block_range bounds to exploit the fact
-                // that block ranges closely correlate with where in a table an
-                // entity appears physically. This index is incredibly efficient for
-                // reverts where we look for very recent blocks, so that this index
-                // is highly selective. See https://github.com/graphprotocol/graph-node/issues/1415#issuecomment-630520713
-                // for details on one experiment.
-                //
-                // We do not index the `block_range` as a whole, but rather the lower
-                // and upper bound separately, since experimentation has shown that
-                // Postgres will not use the index on `block_range` for clauses like
-                // `block_range @> $block` but rather falls back to a full table scan.
-                //
-                // We also make sure that we do not put `NULL` in the index for
-                // the upper bound since nulls can not be compared to anything and
-                // will make the index less effective.
-                //
-                // To make the index usable, queries need to have clauses using
-                // `lower(block_range)` and `coalesce(..)` verbatim.
-                //
-                // We also index `vid` as that correlates with the order in which
-                // entities are stored.
+        create_table(self, out, layout)?;
+        self.write_time_travel_indexes(out, layout)?;
+        self.write_attribute_indexes(out, layout, |_| true)
+    }
+
+    /// Generate the view for a table with a composite id. It adds the `id`
+    /// to the rows of the table by joining the text of the values of the
+    /// key columns with `-`, the same way `Schema::composite_id` does.
+    /// Entities are read through the view, and since it is a simple view,
+    /// they can also be inserted into and deleted from it
+    fn write_view(&self, out: &mut String, layout: &Layout) -> fmt::Result {
+        if !self.has_composite_id() {
+            return Ok(());
+        }
+
+        let id = self
+            .composite_id
+            .iter()
+            .filter_map(|name| self.column(name))
+            .map(|column| match column.column_type {
+                ColumnType::Bytes => format!("'0x' || encode(t.{}, 'hex')", column.name.quoted()),
+                _ => format!("t.{}::text", column.name.quoted()),
+            })
+            .join(" || '-' || ");
+        writeln!(
+            out,
+            "create or replace view {nsp}.{view} as\n    \
+             select {id} as {id_column}, t.*\n      \
+               from {nsp}.{name} t;",
+            nsp = layout.catalog.site.namespace,
+            view = Table::view_name(&self.name).quoted(),
+            id = id,
+            id_column = self.primary_key().name.quoted(),
+            name = self.name.quoted()
+        )
+    }
+
+    /// Generate the indexes that help with queries and reverts that look
+    /// at the block range, or the block, of entity versions
+    fn write_time_travel_indexes(&self, out: &mut String, layout: &Layout) -> fmt::Result {
+        if self.immutable {
+            write!(
+                out,
+                "create index brin_{table_name}\n    \
+                on {schema_name}.{table_name}\n \
+                   using brin({block}, vid);\n",
+                table_name = self.name,
+                schema_name = layout.catalog.site.namespace,
+                block = BLOCK_COLUMN
+            )
+        } else {
+            // Add a BRIN index on the block_range bounds to exploit the fact
+            // that block ranges closely correlate with where in a table an
+            // entity appears physically. This index is incredibly efficient for
+            // reverts where we look for very recent blocks, so that this index
+            // is highly selective. See https://github.com/graphprotocol/graph-node/issues/1415#issuecomment-630520713
+            // for details on one experiment.
+            //
+            // We do not index the `block_range` as a whole, but rather the lower
+            // and upper bound separately, since experimentation has shown that
+            // Postgres will not use the index on `block_range` for clauses like
+            // `block_range @> $block` but rather falls back to a full table scan.
+            //
+            // Only the upper bounds of tables that are account-like correlate
+            // with the physical location of rows, since their versions get
+            // closed soon after they were written. In tables that are mostly
+            // appended to, versions stay open for a long time, and
+            // summarizing their upper bound only makes the index bigger. Those
+            // tables get an index on the lower bound, and the
+            // `block_range_closed` index below helps with finding the closed
+            // versions.
+            //
+            // We also make sure that we do not put `NULL` in the index for
+            // the upper bound since nulls can not be compared to anything and
+            // will make the index less effective.
+            //
+            // To make the index usable, queries need to have clauses using
+            // `lower(block_range)` and `coalesce(..)` verbatim.
+            //
+            // We also index `vid` as that correlates with the order in which
+            // entities are stored.
+            if self.is_account_like {
                 write!(out,"create index brin_{table_name}\n    \
                     on {schema_name}.{table_name}\n \
                        using brin(lower(block_range), coalesce(upper(block_range), {block_max}), vid);\n",
-                    table_name = table.name,
+                    table_name = self.name,
                     schema_name = layout.catalog.site.namespace,
                     block_max = BLOCK_NUMBER_MAX)?;
-
-                // Add a BTree index that helps with the `RevertClampQuery` by making
-                // it faster to find entity versions that have been modified
+            } else {
                 write!(
                     out,
-                    "create index {table_name}_block_range_closed\n    \
-                     on {schema_name}.{table_name}(coalesce(upper(block_range), {block_max}))\n \
-                     where coalesce(upper(block_range), {block_max}) < {block_max};\n",
-                    table_name = table.name,
-                    schema_name = layout.catalog.site.namespace,
-                    block_max = BLOCK_NUMBER_MAX
-                )
+                    "create index brin_lower_{table_name}\n    \
+                     on {schema_name}.{table_name}\n \
+                        using brin(lower(block_range), vid);\n",
+                    table_name = self.name,
+                    schema_name = layout.catalog.site.namespace
+                )?;
             }
+
+            // Add a BTree index that helps with the `RevertClampQuery` by making
+            // it faster to find entity versions that have been modified
+            write!(
+                out,
+                "create index {table_name}_block_range_closed\n    \
+                 on {schema_name}.{table_name}(coalesce(upper(block_range), {block_max}))\n \
+                 where coalesce(upper(block_range), {block_max}) < {block_max};\n",
+                table_name = self.name,
+                schema_name = layout.catalog.site.namespace,
+                block_max = BLOCK_NUMBER_MAX
+            )
         }
+    }
 
-        fn create_attribute_indexes(
-            table: &Table,
-            out: &mut String,
-            layout: &Layout,
-        ) -> fmt::Result {
-            // Create indexes. Skip columns whose type is an array of enum,
-            // since there is no good way to index them with Postgres 9.6.
-            // Once we move to Postgres 11, we can enable that
-            // (tracked in graph-node issue #1330)
-            for (i, column) in table
-                .columns
-                .iter()
-                .filter(|col| !(col.is_list() && col.is_enum()))
-                .enumerate()
-            {
-                if table.immutable && column.is_primary_key() {
-                    // We create a unique index on `id` in `create_table`
-                    // and don't need an explicit attribute index
-                    continue;
-                }
+    /// Generate `create index` statements for those columns of the table
+    /// for which `include` returns `true`. Index names are based on the
+    /// position of the column in the table, regardless of which columns
+    /// are included
+    fn write_attribute_indexes(
+        &self,
+        out: &mut String,
+        layout: &Layout,
+        include: impl Fn(&Column) -> bool,
+    ) -> fmt::Result {
+        // Create indexes. Skip columns whose type is an array of enum,
+        // since there is no good way to index them with Postgres 9.6.
+        // Once we move to Postgres 11, we can enable that
+        // (tracked in graph-node issue #1330)
+        for (i, column) in self
+            .columns
+            .iter()
+            .filter(|col| !(col.is_list() && col.is_enum()))
+            .enumerate()
+        {
+            if !include(column) {
+                continue;
+            }
+            if self.immutable && column.is_primary_key() {
+                // We create a unique index on `id` in `create_table`
+                // and don't need an explicit attribute index
+                continue;
+            }
 
-                let (method, index_expr) = if column.is_reference() && !column.is_list() {
-                    // For foreign keys, index the key together with the block range
-                    // since we almost always also have a block_range clause in
-                    // queries that look for specific foreign keys
-                    if table.immutable {
-                        let index_expr = format!("{}, {}", column.name.quoted(), BLOCK_COLUMN);
-                        ("btree", index_expr)
-                    } else {
-                        let index_expr =
-                            format!("{}, {}", column.name.quoted(), BLOCK_RANGE_COLUMN);
-                        ("gist", index_expr)
+            let (kind, method, index_expr) = if column.is_reference() && !column.is_list() {
+                // For foreign keys, index the key together with the block
+                // since we almost always also have a block clause in queries
+                // that look for specific foreign keys. For mutable tables,
+                // the key is indexed together with the `id`, which is what
+                // children are ordered by by default; GiST indexes on the
+                // block range are much bigger and slower to maintain than
+                // BTrees, and the block range is checked on the few rows
+                // per key that the index finds
+                if self.immutable {
+                    let index_expr = format!("{}, {}", column.name.quoted(), BLOCK_COLUMN);
+                    ("attr", "btree", index_expr)
+                } else {
+                    let index_expr = format!(
+                        "{}, {}",
+                        column.name.quoted(),
+                        self.primary_key().name.quoted()
+                    );
+                    ("cover", "btree", index_expr)
+                }
+            } else if column.is_list() || column.is_fulltext() {
+                ("attr", "gin", column.name.quoted())
+            } else if column.use_prefix_comparison {
+                // Attributes that are plain strings or bytes are
+                // indexed with a BTree; but they can be too large for
+                // Postgres' limit on values that can go into a BTree.
+                // For those attributes, only index the first
+                // STRING_PREFIX_SIZE or BYTE_ARRAY_PREFIX_SIZE characters
+                let index_expr = match column.column_type {
+                    ColumnType::String => {
+                        format!("left({}, {})", column.name.quoted(), STRING_PREFIX_SIZE)
                     }
+                    ColumnType::Bytes => format!(
+                        "substring({}, 1, {})",
+                        column.name.quoted(),
+                        BYTE_ARRAY_PREFIX_SIZE
+                    ),
+                    _ => unreachable!("only String and Bytes can have arbitrary size"),
+                };
+                ("attr", "btree", index_expr)
+            } else if column.is_primary_key() || self.has_composite_id() {
+                // Tables with a composite id do not store an `id` that
+                // could be added to the index
+                ("attr", "btree", column.name.quoted())
+            } else {
+                // Queries that sort by an attribute sort by `id` next;
+                // including it in the index lets Postgres read the rows
+                // in that order from the index
+                let index_expr = format!(
+                    "{}, {}",
+                    column.name.quoted(),
+                    self.primary_key().name.quoted()
+                );
+                ("cover", "btree", index_expr)
+            };
+            write!(
+            out,
+            "create index {kind}_{table_index}_{column_index}_{table_name}_{column_name}\n    on {schema_name}.\"{table_name}\" using {method}({index_expr});\n",
+            kind = kind,
+            table_index = self.position,
+            table_name = self.name,
+            column_index = i,
+            column_name = column.name,
+            schema_name = layout.catalog.site.namespace,
+            method = method,
+            index_expr = index_expr,
+        )?;
+
+            // Case-insensitive filters compare `lower(column)`; the
+            // `text_pattern_ops` also make the index usable for
+            // `starts_with_nocase`
+            if column.has_lowercase_index() {
+                let index_expr = if column.use_prefix_comparison {
+                    format!("left({}, {})", column.name.quoted(), STRING_PREFIX_SIZE)
                 } else {
-                    // Attributes that are plain strings or bytes are
-                    // indexed with a BTree; but they can be too large for
-                    // Postgres' limit on values that can go into a BTree.
-                    // For those attributes, only index the first
-                    // STRING_PREFIX_SIZE or BYTE_ARRAY_PREFIX_SIZE characters
-                    let index_expr = if column.use_prefix_comparison {
-                        match column.column_type {
-                            ColumnType::String => {
-                                format!("left({}, {})", column.name.quoted(), STRING_PREFIX_SIZE)
-                            }
-                            ColumnType::Bytes => format!(
-                                "substring({}, 1, {})",
-                                column.name.quoted(),
-                                BYTE_ARRAY_PREFIX_SIZE
-                            ),
-                            _ => unreachable!("only String and Bytes can have arbitrary size"),
-                        }
-                    } else {
-                        column.name.quoted()
-                    };
-
-                    let method = if column.is_list() || column.is_fulltext() {
-                        "gin"
-                    } else {
-                        "btree"
-                    };
-
-                    (method, index_expr)
+                    column.name.quoted()
                 };
                 write!(
+                    out,
+                    "create index lower_{table_index}_{column_index}_{table_name}_{column_name}\n    on {schema_name}.\"{table_name}\" using btree(lower({index_expr}) text_pattern_ops);\n",
+                    table_index = self.position,
+                    table_name = self.name,
+                    column_index = i,
+                    column_name = column.name,
+                    schema_name = layout.catalog.site.namespace,
+                    index_expr = index_expr,
+                )?;
+            }
+        }
+        writeln!(out)
+    }
+
+    /// The indexes that `as_ddl` creates for this table, other than the
+    /// ones for constraints, as pairs of the name of the index and the
+    /// `create index` statement for it. Names are truncated to the length
+    /// that Postgres stores
+    pub(crate) fn index_ddl(&self, layout: &Layout) -> Result<Vec<(String, String)>, fmt::Error> {
+        let mut out = String::new();
+        self.write_time_travel_indexes(&mut out, layout)?;
+        self.write_attribute_indexes(&mut out, layout, |_| true)?;
+
+        Ok(out
+            .split(';')
+            .map(str::trim)
+            .filter_map(|stmt| {
+                let name = stmt
+                    .strip_prefix("create index ")?
+                    .split_whitespace()
+                    .next()?;
+                let name = name.chars().take(MAX_IDENTIFIER_LENGTH).collect();
+                Some((name, format!("{};", stmt)))
+            })
+            .collect())
+    }
+
+    /// Return `true` if the index `name` on this table is one that
+    /// `index_ddl` might have generated, now or with earlier versions of
+    /// the index strategy. Indexes created through `graphman index create`
+    /// and the indexes for constraints are not
+    pub(crate) fn is_generated_index(&self, name: &str) -> bool {
+        const PREFIXES: &[&str] = &["brin_", "attr_", "cover_", "lower_"];
+
+        let closed: String = format!("{}_block_range_closed", self.name)
+            .chars()
+            .take(MAX_IDENTIFIER_LENGTH)
+            .collect();
+        PREFIXES.iter().any(|prefix| name.starts_with(prefix)) || name == closed
+    }
+
+    /// The name of the partition of this table with the versions that were
+    /// closed at blocks in `[from, to)`
+    pub(crate) fn history_partition(&self, from: BlockNumber, to: BlockNumber) -> SqlName {
+        SqlName::verbatim(format!("{}${}_{}", self.name, from, to))
+    }
+
+    /// The block range `[from, to)` of the history partition `partition` of
+    /// this table, or `None` if `partition` is not a history partition
+    pub(crate) fn history_partition_range(
+        &self,
+        partition: &str,
+    ) -> Option<(BlockNumber, BlockNumber)> {
+        let range = partition
+            .strip_prefix(self.name.as_str())?
+            .strip_prefix('$')?;
+        let (from, to) = range.split_once('_')?;
+        Some((from.parse().ok()?, to.parse().ok()?))
+    }
+
+    fn current_partition(&self) -> SqlName {
+        SqlName::verbatim(format!("{}$current", self.name))
+    }
+
+    fn default_partition(&self) -> SqlName {
+        SqlName::verbatim(format!("{}$default", self.name))
+    }
+
+    /// Generate `create table` statements for the history partitions of
+    /// `blocks` blocks each of the table `parent`, a quoted name, that
+    /// start at `from` and go far enough to contain `head`. Return the end
+    /// of the last partition
+    fn write_history_partitions(
+        &self,
+        out: &mut String,
+        layout: &Layout,
+        parent: &str,
+        from: BlockNumber,
+        head: BlockNumber,
+        blocks: BlockNumber,
+    ) -> Result<BlockNumber, fmt::Error> {
+        let mut start = from;
+        while start <= head {
+            let end = start.saturating_add(blocks).min(BLOCK_NUMBER_MAX);
+            writeln!(
                 out,
-                "create index attr_{table_index}_{column_index}_{table_name}_{column_name}\n    on {schema_name}.\"{table_name}\" using {method}({index_expr});\n",
-                table_index = table.position,
-                table_name = table.name,
-                column_index = i,
-                column_name = column.name,
-                schema_name = layout.catalog.site.namespace,
-                method = method,
-                index_expr = index_expr,
+                "create table {nsp}.{partition}\n    \
+                 partition of {nsp}.{parent} for values from ({start}) to ({end});",
+                nsp = layout.catalog.site.namespace,
+                partition = self.history_partition(start, end).quoted(),
+                parent = parent,
+                start = start,
+                end = end
             )?;
-            }
-            writeln!(out)
+            start = end;
         }
+        Ok(start)
+    }
 
-        create_table(self, out, layout)?;
-        create_time_travel_indexes(self, out, layout)?;
-        create_attribute_indexes(self, out, layout)
+    /// Generate the DDL that replaces this table with a table that is
+    /// partitioned by the block at which entity versions were closed. All
+    /// current versions go into one partition; closed versions go into
+    /// partitions of `blocks` blocks each up to the one that contains
+    /// `head`, and into a default partition after that. The rows are
+    /// copied into the new table, which is why the DDL must run in a
+    /// transaction that also holds the lock on writes to the deployment.
+    ///
+    /// Partitioned tables can not have a primary key or an exclusion
+    /// constraint that does not include the partition key. The `vid` is
+    /// therefore only indexed, and the constraint that the block ranges
+    /// for the same entity do not overlap is no longer checked by the
+    /// database
+    ///
+    /// `sequence` is the name of the sequence for the `vid` of the
+    /// existing table; the new table keeps using it
+    pub(crate) fn partition_ddl(
+        &self,
+        layout: &Layout,
+        sequence: &str,
+        head: BlockNumber,
+        blocks: BlockNumber,
+    ) -> Result<String, fmt::Error> {
+        assert!(!self.immutable, "immutable tables can not be partitioned");
+        assert!(blocks > 0, "partitions must span at least one block");
+
+        let nsp = &layout.catalog.site.namespace;
+        let name = self.name.quoted();
+        let tmp = SqlName::verbatim(format!("{}$p", self.name)).quoted();
+        let mut out = String::new();
+
+        writeln!(out, "alter sequence {} owned by none;", sequence)?;
+        writeln!(
+            out,
+            "create table {nsp}.{tmp} (like {nsp}.{name} including defaults)\n    \
+             partition by range(coalesce(upper({block_range}), {block_max}));",
+            nsp = nsp,
+            tmp = tmp,
+            name = name,
+            block_range = BLOCK_RANGE_COLUMN,
+            block_max = BLOCK_NUMBER_MAX
+        )?;
+        writeln!(
+            out,
+            "create table {nsp}.{partition}\n    \
+             partition of {nsp}.{tmp} for values from ({block_max}) to (maxvalue);",
+            nsp = nsp,
+            partition = self.current_partition().quoted(),
+            tmp = tmp,
+            block_max = BLOCK_NUMBER_MAX
+        )?;
+        self.write_history_partitions(&mut out, layout, &tmp, 0, head, blocks)?;
+        writeln!(
+            out,
+            "create table {nsp}.{partition} partition of {nsp}.{tmp} default;",
+            nsp = nsp,
+            partition = self.default_partition().quoted(),
+            tmp = tmp
+        )?;
+        writeln!(
+            out,
+            "insert into {nsp}.{tmp} select * from {nsp}.{name};",
+            nsp = nsp,
+            tmp = tmp,
+            name = name
+        )?;
+        writeln!(out, "drop table {}.{};", nsp, name)?;
+        writeln!(
+            out,
+            "alter table {}.{} rename to {};",
+            nsp,
+            tmp,
+            self.name.quoted()
+        )?;
+        writeln!(
+            out,
+            "alter sequence {} owned by {}.{}.{};",
+            sequence, nsp, name, VID_COLUMN
+        )?;
+        writeln!(
+            out,
+            "create index {table_name}_vid on {nsp}.{name}({vid});",
+            table_name = self.name,
+            nsp = nsp,
+            name = name,
+            vid = VID_COLUMN
+        )?;
+        self.write_time_travel_indexes(&mut out, layout)?;
+        self.write_attribute_indexes(&mut out, layout, |_| true)?;
+        Ok(out)
+    }
+
+    /// Generate the DDL that adds history partitions to this partitioned
+    /// table, starting at `from`, the end of the last existing history
+    /// partition, until there is one that contains `head`. Rows in the
+    /// default partition are moved into the new partitions
+    pub(crate) fn extend_partitions_ddl(
+        &self,
+        layout: &Layout,
+        from: BlockNumber,
+        head: BlockNumber,
+        blocks: BlockNumber,
+    ) -> Result<String, fmt::Error> {
+        assert!(blocks > 0, "partitions must span at least one block");
+
+        let nsp = &layout.catalog.site.namespace;
+        let default = self.default_partition();
+        let old = SqlName::verbatim(format!("{}_old", default)).quoted();
+        let mut out = String::new();
+
+        // Postgres refuses to create a partition for rows that are in the
+        // default partition; move the default partition out of the way
+        // and copy its rows back once the new partitions exist
+        writeln!(
+            out,
+            "alter table {}.{} detach partition {}.{};",
+            nsp,
+            self.name.quoted(),
+            nsp,
+            default.quoted()
+        )?;
+        writeln!(
+            out,
+            "alter table {}.{} rename to {};",
+            nsp,
+            default.quoted(),
+            old
+        )?;
+        self.write_history_partitions(&mut out, layout, &self.name.quoted(), from, head, blocks)?;
+        writeln!(
+            out,
+            "create table {nsp}.{partition} partition of {nsp}.{name} default;",
+            nsp = nsp,
+            partition = default.quoted(),
+            name = self.name.quoted()
+        )?;
+        writeln!(
+            out,
+            "insert into {nsp}.{name} select * from {nsp}.{old};",
+            nsp = nsp,
+            name = self.name.quoted(),
+            old = old
+        )?;
+        writeln!(out, "drop table {}.{};", nsp, old)?;
+        Ok(out)
+    }
+
+    /// Generate the DDL that drops those of the `partitions` of this table
+    /// that only contain versions that were closed before `before`
+    pub(crate) fn prune_partitions_ddl(
+        &self,
+        layout: &Layout,
+        partitions: &[String],
+        before: BlockNumber,
+    ) -> Result<String, fmt::Error> {
+        let mut out = String::new();
+        for partition in partitions {
+            match self.history_partition_range(partition) {
+                Some((_, to)) if to <= before => writeln!(
+                    out,
+                    "drop table {}.{};",
+                    layout.catalog.site.namespace,
+                    SqlName::verbatim(partition.clone()).quoted()
+                )?,
+                _ => {}
+            }
+        }
+        Ok(out)
     }
 }
 
@@ -151,6 +151,290 @@ fn can_copy_from() {
     );
 }
 
+#[test]
+fn can_migrate_from() {
+    let base = test_layout(THING_GQL);
+    // We can always migrate to an identical layout
+    assert!(base.can_migrate_from(&base).is_empty());
+
+    // We allow adding types, enums, enum values and nullable attributes
+    let layout = test_layout(&format!(
+        "{} type Other @entity {{ id: ID! }} enum Shape {{ round }}",
+        THING_GQL
+            .replace("bigThing: Thing!", "bigThing: Thing!, name: String")
+            .replace("red, BLUE", "red, BLUE, green")
+    ));
+    assert!(layout.can_migrate_from(&base).is_empty());
+
+    // Everything else requires syncing the subgraph again
+    let layout = test_layout(
+        "type Thing @entity { id: ID!, bigThing: Thing, size: Int! }
+         enum Color { yellow, red }",
+    );
+    assert_eq!(
+        vec![
+            "The attribute Thing.bigThing has type Thing, \
+             but its type in the deployment is Thing!",
+            "The attribute Thing.size is non-nullable, \
+             but there is no such attribute in the deployment",
+            "The entity type Scalar was removed",
+            "The enum Color does not have the values BLUE anymore",
+            "The enum Size was removed",
+        ],
+        layout.can_migrate_from(&base)
+    );
+}
+
+#[test]
+fn composite_id() {
+    const SWAP_GQL: &str = "
+        type Pair @entity { id: Bytes! }
+        type Swap @entity(immutable: true) @compositeId(fields: [\"pair\", \"blockNumber\"]) {
+            pair: Pair!,
+            blockNumber: BigInt!
+        }";
+
+    let layout = test_layout(SWAP_GQL);
+    let table = layout
+        .table(&"swap".into())
+        .expect("failed to get 'swap' table");
+    assert_eq!(
+        vec![SqlName::from("pair"), SqlName::from("block_number")],
+        table.composite_id
+    );
+    assert_eq!(r#""sgd0815"."swap$view""#, table.source().as_str());
+
+    // The `id` is not stored; the view forms it from the composite id,
+    // which is the primary key
+    let sql = layout
+        .as_ddl()
+        .expect("Failed to generate DDL")
+        .split_whitespace()
+        .join(" ");
+    assert!(sql.contains(
+        r#"create table sgd0815."swap" ( vid bigserial unique, block$ int not null, "pair" bytea not null, "block_number" numeric not null, primary key("pair", "block_number") );"#
+    ));
+    assert!(sql.contains(
+        r#"create or replace view sgd0815."swap$view" as select '0x' || encode(t."pair", 'hex') || '-' || t."block_number"::text as "id", t.* from sgd0815."swap" t;"#
+    ));
+    assert!(!sql.contains(r#"_swap_id"#));
+
+    // New attributes also have to show up in the view
+    let layout = test_layout(&SWAP_GQL.replace(
+        "blockNumber: BigInt!",
+        "blockNumber: BigInt!, amount: BigInt",
+    ));
+    let base = test_layout(SWAP_GQL);
+    assert_eq!(Vec::<String>::new(), layout.can_migrate_from(&base));
+    let sql = layout
+        .migration_ddl(&base)
+        .expect("Failed to generate DDL")
+        .split_whitespace()
+        .join(" ");
+    assert!(sql.contains(r#"alter table sgd0815."swap" add column "amount" numeric;"#));
+    assert!(sql.contains(r#"create or replace view sgd0815."swap$view""#));
+
+    // The composite id can not be changed in a migration
+    let base = test_layout(
+        &SWAP_GQL
+            .replace(" @compositeId(fields: [\"pair\", \"blockNumber\"])", "")
+            .replace("pair: Pair!", "id: ID!, pair: Pair!"),
+    );
+    assert_eq!(
+        vec!["The entity type Swap changed its composite id"],
+        layout.can_migrate_from(&base)
+    );
+}
+
+#[test]
+fn composite_key() {
+    let layout = test_layout(
+        "type Pair @entity { id: Bytes! }
+         type Swap @entity(immutable: true) @compositeId(fields: [\"pair\", \"block\", \"amount\"]) {
+             pair: Pair!, block: Int!, amount: BigInt!
+         }",
+    );
+    let table = layout
+        .table(&"swap".into())
+        .expect("failed to get 'swap' table");
+
+    let pair = Value::Bytes(scalar::Bytes::from(&[0xab, 0x01][..]));
+    assert_eq!(
+        Some(vec![
+            pair.clone(),
+            Value::Int(-3),
+            Value::BigInt(scalar::BigInt::from(12))
+        ]),
+        table.composite_key("0xab01--3-12")
+    );
+    assert_eq!(
+        Some(vec![
+            pair,
+            Value::Int(3),
+            Value::BigInt(scalar::BigInt::from(-12))
+        ]),
+        table.composite_key("0xab01-3--12")
+    );
+    // Only ids that the view could have formed have a key
+    for id in [
+        "0xAB01-3-12",
+        "ab01-3-12",
+        "0xab01-03-12",
+        "0xab01-3--0",
+        "0xab01-3",
+        "0xab01-3-12-",
+        "0xab01-3-12-4",
+        "0xab01-x-12",
+        "",
+    ] {
+        assert_eq!(None, table.composite_key(id), "{}", id);
+    }
+
+    // Lookups by id compare the key columns, not the `id` of the view
+    let find = |id: &str| {
+        debug_query(&FindQuery::new(table, id, 1))
+            .to_string()
+            .split_whitespace()
+            .join(" ")
+    };
+    assert!(find("0xab01--3-12")
+        .contains(r#"e where ("pair" = $2 and "block" = $3 and "amount" = $4::numeric) and"#));
+    assert!(find("0xab01-3").contains("e where false and"));
+}
+
+#[test]
+fn migration_ddl() {
+    let base = test_layout("type Thing @entity { id: ID!, name: String! }");
+    let layout = test_layout(
+        "type Thing @entity { id: ID!, name: String!, nick: String }
+         type Other @entity { id: ID! }",
+    );
+    let sql = layout
+        .migration_ddl(&base)
+        .expect("Failed to generate DDL")
+        .split_whitespace()
+        .join(" ");
+
+    assert!(sql.contains(r#"alter table sgd0815."thing" add column "nick" text;"#));
+    assert!(sql.contains(r#"create index attr_0_2_thing_nick on sgd0815."thing""#));
+    assert!(sql.contains(r#"create index lower_0_2_thing_nick on sgd0815."thing""#));
+    assert!(sql.contains(r#"create table sgd0815."other""#));
+    assert!(!sql.contains(r#"create table sgd0815."thing""#));
+    assert!(!sql.contains("attr_0_1_thing_name"));
+
+    // New enum values are added so that the values stay sorted
+    let base = test_layout("enum Color { red } type Thing @entity { id: ID!, color: Color }");
+    let layout = test_layout(
+        "enum Color { red, blue, yellow } type Thing @entity { id: ID!, color: Color }",
+    );
+    assert!(layout.can_migrate_from(&base).is_empty());
+    let stmts = layout
+        .enum_values_ddl(&base)
+        .expect("Failed to generate DDL")
+        .into_iter()
+        .map(|stmt| stmt.split_whitespace().join(" "))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        vec![
+            "alter type sgd0815.\"color\" add value if not exists 'blue' before 'red'",
+            "alter type sgd0815.\"color\" add value if not exists 'yellow'",
+        ],
+        stmts
+    );
+    assert_eq!("", layout.migration_ddl(&base).unwrap());
+}
+
+#[test]
+fn index_ddl() {
+    let layout = test_layout(MUSIC_GQL);
+    let musician = layout.table(&"musician".into()).unwrap();
+    let names = musician
+        .index_ddl(&layout)
+        .expect("Failed to generate DDL")
+        .into_iter()
+        .map(|(name, _)| name)
+        .collect::<Vec<_>>();
+    assert_eq!(
+        vec![
+            "brin_lower_musician",
+            "musician_block_range_closed",
+            "attr_0_0_musician_id",
+            "attr_0_1_musician_name",
+            "lower_0_1_musician_name",
+            "cover_0_2_musician_main_band",
+            "attr_0_3_musician_bands"
+        ],
+        names
+    );
+
+    // Account-like tables summarize both bounds of the block range
+    let mut account_like = musician.clone();
+    account_like.is_account_like = true;
+    let (name, sql) = account_like.index_ddl(&layout).unwrap().remove(0);
+    assert_eq!("brin_musician", name);
+    assert!(sql.contains("coalesce(upper(block_range), 2147483647), vid);"));
+
+    assert!(musician.is_generated_index("brin_musician"));
+    assert!(musician.is_generated_index("attr_0_2_musician_main_band"));
+    assert!(!musician.is_generated_index("manual_musician_name"));
+    assert!(!musician.is_generated_index("musician_id_block_range_excl"));
+}
+
+#[test]
+fn partition_ddl() {
+    let layout = test_layout("type Thing @entity { id: ID!, name: String! }");
+    let table = layout.table(&"thing".into()).unwrap();
+    let sql = table
+        .partition_ddl(&layout, "sgd0815.thing_vid_seq", 25, 10)
+        .expect("Failed to generate DDL")
+        .split_whitespace()
+        .join(" ");
+
+    assert!(sql.contains(
+        r#"create table sgd0815."thing$p" (like sgd0815."thing" including defaults) partition by range(coalesce(upper(block_range), 2147483647));"#
+    ));
+    assert!(sql.contains(
+        r#"create table sgd0815."thing$current" partition of sgd0815."thing$p" for values from (2147483647) to (maxvalue);"#
+    ));
+    assert!(sql.contains(
+        r#"create table sgd0815."thing$20_30" partition of sgd0815."thing$p" for values from (20) to (30);"#
+    ));
+    assert!(!sql.contains(r#""thing$30_40""#));
+    assert!(sql.contains(
+        r#"create table sgd0815."thing$default" partition of sgd0815."thing$p" default;"#
+    ));
+    assert!(sql.contains(r#"alter table sgd0815."thing$p" rename to "thing";"#));
+    assert!(sql.contains("alter sequence sgd0815.thing_vid_seq owned by sgd0815.\"thing\".vid;"));
+    assert!(!sql.contains("exclude using gist"));
+    assert!(!sql.contains("primary key"));
+
+    let sql = table
+        .extend_partitions_ddl(&layout, 30, 45, 10)
+        .expect("Failed to generate DDL")
+        .split_whitespace()
+        .join(" ");
+    assert!(sql.contains(r#"for values from (30) to (40);"#));
+    assert!(sql.contains(r#"for values from (40) to (50);"#));
+    assert!(
+        sql.contains(r#"insert into sgd0815."thing" select * from sgd0815."thing$default_old";"#)
+    );
+
+    assert_eq!(Some((20, 30)), table.history_partition_range("thing$20_30"));
+    assert_eq!(None, table.history_partition_range("thing$current"));
+    assert_eq!(None, table.history_partition_range("thing$default_old"));
+    let partitions = ["thing$0_10", "thing$10_20", "thing$20_30", "thing$current"]
+        .iter()
+        .map(|p| p.to_string())
+        .collect::<Vec<_>>();
+    let sql = table
+        .prune_partitions_ddl(&layout, &partitions, 20)
+        .expect("Failed to generate DDL");
+    assert_eq!(
+        "drop table sgd0815.\"thing$0_10\";\ndrop table sgd0815.\"thing$10_20\";\n",
+        sql
+    );
+}
+
 const THING_GQL: &str = "
         type Thing @entity {
             id: ID!
@@ -184,16 +468,16 @@ create table sgd0815.\"thing\" (
 
         exclude using gist   (id with =, block_range with &&)
 );
-create index brin_thing
+create index brin_lower_thing
     on sgd0815.thing
- using brin(lower(block_range), coalesce(upper(block_range), 2147483647), vid);
+ using brin(lower(block_range), vid);
 create index thing_block_range_closed
     on sgd0815.thing(coalesce(upper(block_range), 2147483647))
  where coalesce(upper(block_range), 2147483647) < 2147483647;
 create index attr_0_0_thing_id
     on sgd0815.\"thing\" using btree(\"id\");
-create index attr_0_1_thing_big_thing
-    on sgd0815.\"thing\" using gist(\"big_thing\", block_range);
+create index cover_0_1_thing_big_thing
+    on sgd0815.\"thing\" using btree(\"big_thing\", \"id\");
 
 create table sgd0815.\"scalar\" (
         vid                  bigserial primary key,
@@ -209,28 +493,30 @@ create table sgd0815.\"scalar\" (
 
         exclude using gist   (id with =, block_range with &&)
 );
-create index brin_scalar
+create index brin_lower_scalar
     on sgd0815.scalar
- using brin(lower(block_range), coalesce(upper(block_range), 2147483647), vid);
+ using brin(lower(block_range), vid);
 create index scalar_block_range_closed
     on sgd0815.scalar(coalesce(upper(block_range), 2147483647))
  where coalesce(upper(block_range), 2147483647) < 2147483647;
 create index attr_1_0_scalar_id
     on sgd0815.\"scalar\" using btree(\"id\");
-create index attr_1_1_scalar_bool
-    on sgd0815.\"scalar\" using btree(\"bool\");
-create index attr_1_2_scalar_int
-    on sgd0815.\"scalar\" using btree(\"int\");
-create index attr_1_3_scalar_big_decimal
-    on sgd0815.\"scalar\" using btree(\"big_decimal\");
+create index cover_1_1_scalar_bool
+    on sgd0815.\"scalar\" using btree(\"bool\", \"id\");
+create index cover_1_2_scalar_int
+    on sgd0815.\"scalar\" using btree(\"int\", \"id\");
+create index cover_1_3_scalar_big_decimal
+    on sgd0815.\"scalar\" using btree(\"big_decimal\", \"id\");
 create index attr_1_4_scalar_string
     on sgd0815.\"scalar\" using btree(left(\"string\", 256));
+create index lower_1_4_scalar_string
+    on sgd0815.\"scalar\" using btree(lower(left(\"string\", 256)) text_pattern_ops);
 create index attr_1_5_scalar_bytes
     on sgd0815.\"scalar\" using btree(substring(\"bytes\", 1, 64));
-create index attr_1_6_scalar_big_int
-    on sgd0815.\"scalar\" using btree(\"big_int\");
-create index attr_1_7_scalar_color
-    on sgd0815.\"scalar\" using btree(\"color\");
+create index cover_1_6_scalar_big_int
+    on sgd0815.\"scalar\" using btree(\"big_int\", \"id\");
+create index cover_1_7_scalar_color
+    on sgd0815.\"scalar\" using btree(\"color\", \"id\");
 
 ";
 
@@ -271,9 +557,9 @@ const MUSIC_DDL: &str = "create table sgd0815.\"musician\" (
 
         exclude using gist   (id with =, block_range with &&)
 );
-create index brin_musician
+create index brin_lower_musician
     on sgd0815.musician
- using brin(lower(block_range), coalesce(upper(block_range), 2147483647), vid);
+ using brin(lower(block_range), vid);
 create index musician_block_range_closed
     on sgd0815.musician(coalesce(upper(block_range), 2147483647))
  where coalesce(upper(block_range), 2147483647) < 2147483647;
@@ -281,8 +567,10 @@ create index attr_0_0_musician_id
     on sgd0815.\"musician\" using btree(\"id\");
 create index attr_0_1_musician_name
     on sgd0815.\"musician\" using btree(left(\"name\", 256));
-create index attr_0_2_musician_main_band
-    on sgd0815.\"musician\" using gist(\"main_band\", block_range);
+create index lower_0_1_musician_name
+    on sgd0815.\"musician\" using btree(lower(left(\"name\", 256)) text_pattern_ops);
+create index cover_0_2_musician_main_band
+    on sgd0815.\"musician\" using btree(\"main_band\", \"id\");
 create index attr_0_3_musician_bands
     on sgd0815.\"musician\" using gin(\"bands\");
 
@@ -295,9 +583,9 @@ create table sgd0815.\"band\" (
 
         exclude using gist   (id with =, block_range with &&)
 );
-create index brin_band
+create index brin_lower_band
     on sgd0815.band
- using brin(lower(block_range), coalesce(upper(block_range), 2147483647), vid);
+ using brin(lower(block_range), vid);
 create index band_block_range_closed
     on sgd0815.band(coalesce(upper(block_range), 2147483647))
  where coalesce(upper(block_range), 2147483647) < 2147483647;
@@ -305,6 +593,8 @@ create index attr_1_0_band_id
     on sgd0815.\"band\" using btree(\"id\");
 create index attr_1_1_band_name
     on sgd0815.\"band\" using btree(left(\"name\", 256));
+create index lower_1_1_band_name
+    on sgd0815.\"band\" using btree(lower(left(\"name\", 256)) text_pattern_ops);
 create index attr_1_2_band_original_songs
     on sgd0815.\"band\" using gin(\"original_songs\");
 
@@ -322,6 +612,8 @@ create index brin_song
  using brin(block$, vid);
 create index attr_2_1_song_title
     on sgd0815.\"song\" using btree(left(\"title\", 256));
+create index lower_2_1_song_title
+    on sgd0815.\"song\" using btree(lower(left(\"title\", 256)) text_pattern_ops);
 create index attr_2_2_song_written_by
     on sgd0815.\"song\" using btree(\"written_by\", block$);
 
@@ -333,16 +625,16 @@ create table sgd0815.\"song_stat\" (
 
         exclude using gist   (id with =, block_range with &&)
 );
-create index brin_song_stat
+create index brin_lower_song_stat
     on sgd0815.song_stat
- using brin(lower(block_range), coalesce(upper(block_range), 2147483647), vid);
+ using brin(lower(block_range), vid);
 create index song_stat_block_range_closed
     on sgd0815.song_stat(coalesce(upper(block_range), 2147483647))
  where coalesce(upper(block_range), 2147483647) < 2147483647;
 create index attr_3_0_song_stat_id
     on sgd0815.\"song_stat\" using btree(\"id\");
-create index attr_3_1_song_stat_played
-    on sgd0815.\"song_stat\" using btree(\"played\");
+create index cover_3_1_song_stat_played
+    on sgd0815.\"song_stat\" using btree(\"played\", \"id\");
 
 ";
 
@@ -375,16 +667,16 @@ const FOREST_DDL: &str = "create table sgd0815.\"animal\" (
 
         exclude using gist   (id with =, block_range with &&)
 );
-create index brin_animal
+create index brin_lower_animal
     on sgd0815.animal
- using brin(lower(block_range), coalesce(upper(block_range), 2147483647), vid);
+ using brin(lower(block_range), vid);
 create index animal_block_range_closed
     on sgd0815.animal(coalesce(upper(block_range), 2147483647))
  where coalesce(upper(block_range), 2147483647) < 2147483647;
 create index attr_0_0_animal_id
     on sgd0815.\"animal\" using btree(\"id\");
-create index attr_0_1_animal_forest
-    on sgd0815.\"animal\" using gist(\"forest\", block_range);
+create index cover_0_1_animal_forest
+    on sgd0815.\"animal\" using btree(\"forest\", \"id\");
 
 create table sgd0815.\"forest\" (
         vid                  bigserial primary key,
@@ -393,9 +685,9 @@ create table sgd0815.\"forest\" (
 
         exclude using gist   (id with =, block_range with &&)
 );
-create index brin_forest
+create index brin_lower_forest
     on sgd0815.forest
- using brin(lower(block_range), coalesce(upper(block_range), 2147483647), vid);
+ using brin(lower(block_range), vid);
 create index forest_block_range_closed
     on sgd0815.forest(coalesce(upper(block_range), 2147483647))
  where coalesce(upper(block_range), 2147483647) < 2147483647;
@@ -411,16 +703,16 @@ create table sgd0815.\"habitat\" (
 
         exclude using gist   (id with =, block_range with &&)
 );
-create index brin_habitat
+create index brin_lower_habitat
     on sgd0815.habitat
- using brin(lower(block_range), coalesce(upper(block_range), 2147483647), vid);
+ using brin(lower(block_range), vid);
 create index habitat_block_range_closed
     on sgd0815.habitat(coalesce(upper(block_range), 2147483647))
  where coalesce(upper(block_range), 2147483647) < 2147483647;
 create index attr_2_0_habitat_id
     on sgd0815.\"habitat\" using btree(\"id\");
-create index attr_2_1_habitat_most_common
-    on sgd0815.\"habitat\" using gist(\"most_common\", block_range);
+create index cover_2_1_habitat_most_common
+    on sgd0815.\"habitat\" using btree(\"most_common\", \"id\");
 create index attr_2_2_habitat_dwellers
     on sgd0815.\"habitat\" using gin(\"dwellers\");
 
@@ -467,9 +759,9 @@ const FULLTEXT_DDL: &str = "create table sgd0815.\"animal\" (
 
         exclude using gist   (id with =, block_range with &&)
 );
-create index brin_animal
+create index brin_lower_animal
     on sgd0815.animal
- using brin(lower(block_range), coalesce(upper(block_range), 2147483647), vid);
+ using brin(lower(block_range), vid);
 create index animal_block_range_closed
     on sgd0815.animal(coalesce(upper(block_range), 2147483647))
  where coalesce(upper(block_range), 2147483647) < 2147483647;
@@ -477,10 +769,14 @@ create index attr_0_0_animal_id
     on sgd0815.\"animal\" using btree(\"id\");
 create index attr_0_1_animal_name
     on sgd0815.\"animal\" using btree(left(\"name\", 256));
+create index lower_0_1_animal_name
+    on sgd0815.\"animal\" using btree(lower(left(\"name\", 256)) text_pattern_ops);
 create index attr_0_2_animal_species
     on sgd0815.\"animal\" using btree(left(\"species\", 256));
-create index attr_0_3_animal_forest
-    on sgd0815.\"animal\" using gist(\"forest\", block_range);
+create index lower_0_2_animal_species
+    on sgd0815.\"animal\" using btree(lower(left(\"species\", 256)) text_pattern_ops);
+create index cover_0_3_animal_forest
+    on sgd0815.\"animal\" using btree(\"forest\", \"id\");
 create index attr_0_4_animal_search
     on sgd0815.\"animal\" using gin(\"search\");
 
@@ -491,9 +787,9 @@ create table sgd0815.\"forest\" (
 
         exclude using gist   (id with =, block_range with &&)
 );
-create index brin_forest
+create index brin_lower_forest
     on sgd0815.forest
- using brin(lower(block_range), coalesce(upper(block_range), 2147483647), vid);
+ using brin(lower(block_range), vid);
 create index forest_block_range_closed
     on sgd0815.forest(coalesce(upper(block_range), 2147483647))
  where coalesce(upper(block_range), 2147483647) < 2147483647;
@@ -509,16 +805,16 @@ create table sgd0815.\"habitat\" (
 
         exclude using gist   (id with =, block_range with &&)
 );
-create index brin_habitat
+create index brin_lower_habitat
     on sgd0815.habitat
- using brin(lower(block_range), coalesce(upper(block_range), 2147483647), vid);
+ using brin(lower(block_range), vid);
 create index habitat_block_range_closed
     on sgd0815.habitat(coalesce(upper(block_range), 2147483647))
  where coalesce(upper(block_range), 2147483647) < 2147483647;
 create index attr_2_0_habitat_id
     on sgd0815.\"habitat\" using btree(\"id\");
-create index attr_2_1_habitat_most_common
-    on sgd0815.\"habitat\" using gist(\"most_common\", block_range);
+create index cover_2_1_habitat_most_common
+    on sgd0815.\"habitat\" using btree(\"most_common\", \"id\");
 create index attr_2_2_habitat_dwellers
     on sgd0815.\"habitat\" using gin(\"dwellers\");
 
@@ -545,15 +841,15 @@ create table sgd0815.\"thing\" (
 
         exclude using gist   (id with =, block_range with &&)
 );
-create index brin_thing
+create index brin_lower_thing
     on sgd0815.thing
- using brin(lower(block_range), coalesce(upper(block_range), 2147483647), vid);
+ using brin(lower(block_range), vid);
 create index thing_block_range_closed
     on sgd0815.thing(coalesce(upper(block_range), 2147483647))
  where coalesce(upper(block_range), 2147483647) < 2147483647;
 create index attr_0_0_thing_id
     on sgd0815.\"thing\" using btree(\"id\");
-create index attr_0_1_thing_orientation
-    on sgd0815.\"thing\" using btree(\"orientation\");
+create index cover_0_1_thing_orientation
+    on sgd0815.\"thing\" using btree(\"orientation\", \"id\");
 
 ";
@@ -222,6 +222,185 @@ impl ForeignKeyClauses for Column {
     }
 }
 
+/// Conveniences for looking entities up by their `id`. For tables with a
+/// composite id, the `id` is formed by a view and not indexed, and these
+/// compare the key columns, which form the primary key, with the values
+/// that the `id` was formed from instead
+trait IdClauses {
+    /// Generate a clause that is true for the entity with `id`. For tables
+    /// with a composite id, that is
+    ///    `("key0" = $v0 and "key1" = $v1 ..)`
+    /// or `false` if no entity in the table can have that `id`
+    fn id_eq(&self, id: &str, out: &mut AstPass<Pg>) -> QueryResult<()>;
+
+    /// Generate a clause that is true for the entities with one of `ids`.
+    /// For tables with a composite id, that is
+    ///    `exists (select 1 from rows from (unnest($v0s), unnest($v1s) ..)
+    ///                                as p(g$key0, g$key1 ..)
+    ///             where "key0" = p.g$key0 and "key1" = p.g$key1 ..)`
+    fn id_is_in<S>(&self, ids: &[S], out: &mut AstPass<Pg>) -> QueryResult<()>
+    where
+        S: AsRef<str> + diesel::serialize::ToSql<Text, Pg>;
+
+    /// Generate `unnest($v0s), unnest($v1s) ..` for the values of the key
+    /// columns in `keys`, one array for each key column
+    fn push_key_arrays(&self, keys: &[Vec<Value>], out: &mut AstPass<Pg>) -> QueryResult<()>;
+
+    /// Generate `reduce_dim({matrix0}), reduce_dim({matrix1}) ..` where
+    /// each matrix has the values of one key column for the ids in
+    /// `matrix`. Ids that no entity in the table can have become `null`,
+    /// just like the padding in `matrix`. See
+    /// `ForeignKeyClauses::push_matrix` for why the matrices have to be
+    /// literal SQL
+    fn push_key_matrices(
+        &self,
+        matrix: &[Vec<Option<SafeString>>],
+        out: &mut AstPass<Pg>,
+    ) -> QueryResult<()>;
+
+    /// Generate `g$key0, g$key1 ..`, the names of the columns into which
+    /// the key arrays are unnested
+    fn push_key_names(&self, out: &mut AstPass<Pg>);
+
+    /// Generate `{prefix}"key0" = {alias}.g$key0 and ..`
+    fn key_join(&self, prefix: &str, alias: &str, out: &mut AstPass<Pg>) -> QueryResult<()>;
+}
+
+impl IdClauses for Table {
+    fn id_eq(&self, id: &str, out: &mut AstPass<Pg>) -> QueryResult<()> {
+        if !self.has_composite_id() {
+            return self.primary_key().eq(id, out);
+        }
+        match self.composite_key(id) {
+            None => out.push_sql("false"),
+            Some(values) => {
+                out.push_sql("(");
+                for (i, (column, value)) in self.key_columns().zip(values.iter()).enumerate() {
+                    if i > 0 {
+                        out.push_sql(" and ");
+                    }
+                    out.push_identifier(column.name.as_str())?;
+                    out.push_sql(" = ");
+                    QueryValue(value, &column.column_type).walk_ast(out.reborrow())?;
+                }
+                out.push_sql(")");
+            }
+        }
+        Ok(())
+    }
+
+    fn id_is_in<S>(&self, ids: &[S], out: &mut AstPass<Pg>) -> QueryResult<()>
+    where
+        S: AsRef<str> + diesel::serialize::ToSql<Text, Pg>,
+    {
+        if !self.has_composite_id() {
+            return self.primary_key().is_in(ids, out);
+        }
+        let keys: Vec<_> = ids
+            .iter()
+            .filter_map(|id| self.composite_key(id.as_ref()))
+            .collect();
+        out.push_sql("exists (select 1 from rows from (");
+        self.push_key_arrays(&keys, out)?;
+        out.push_sql(") as p(");
+        self.push_key_names(out);
+        out.push_sql(") where ");
+        self.key_join("", "p", out)?;
+        out.push_sql(")");
+        Ok(())
+    }
+
+    fn push_key_arrays(&self, keys: &[Vec<Value>], out: &mut AstPass<Pg>) -> QueryResult<()> {
+        for (i, column) in self.key_columns().enumerate() {
+            if i > 0 {
+                out.push_sql(", ");
+            }
+            let values = Value::List(keys.iter().map(|key| key[i].clone()).collect());
+            out.push_sql("unnest(");
+            QueryValue(&values, &column.column_type).walk_ast(out.reborrow())?;
+            out.push_sql(")");
+        }
+        Ok(())
+    }
+
+    fn push_key_matrices(
+        &self,
+        matrix: &[Vec<Option<SafeString>>],
+        out: &mut AstPass<Pg>,
+    ) -> QueryResult<()> {
+        let keys: Vec<Vec<Option<Vec<Value>>>> = matrix
+            .iter()
+            .map(|ids| {
+                ids.iter()
+                    .map(|id| id.as_ref().and_then(|id| self.composite_key(&id.0)))
+                    .collect()
+            })
+            .collect();
+        for (i, column) in self.key_columns().enumerate() {
+            if i > 0 {
+                out.push_sql(", ");
+            }
+            out.push_sql("reduce_dim(array[");
+            if keys.is_empty() {
+                out.push_sql("array[null]");
+            }
+            for (j, row) in keys.iter().enumerate() {
+                if j > 0 {
+                    out.push_sql(", ");
+                }
+                out.push_sql("array[");
+                for (k, key) in row.iter().enumerate() {
+                    if k > 0 {
+                        out.push_sql(", ");
+                    }
+                    // The values were parsed from the ids and are safe to
+                    // use as literals
+                    match key.as_ref().map(|key| &key[i]) {
+                        Some(Value::Bytes(b)) => {
+                            out.push_sql("'\\x");
+                            out.push_sql(&hex::encode(b.as_slice()));
+                            out.push_sql("'");
+                        }
+                        Some(Value::Int(n)) => out.push_sql(&n.to_string()),
+                        Some(Value::BigInt(n)) => {
+                            out.push_sql("'");
+                            out.push_sql(&n.to_string());
+                            out.push_sql("'");
+                        }
+                        _ => out.push_sql("null"),
+                    }
+                }
+                out.push_sql("]");
+            }
+            out.push_sql("]::");
+            out.push_sql(column.column_type.sql_type());
+            out.push_sql("[][])");
+        }
+        Ok(())
+    }
+
+    fn push_key_names(&self, out: &mut AstPass<Pg>) {
+        for i in 0..self.composite_id.len() {
+            if i > 0 {
+                out.push_sql(", ");
+            }
+            out.push_sql(&format!("g$key{}", i));
+        }
+    }
+
+    fn key_join(&self, prefix: &str, alias: &str, out: &mut AstPass<Pg>) -> QueryResult<()> {
+        for (i, column) in self.key_columns().enumerate() {
+            if i > 0 {
+                out.push_sql(" and ");
+            }
+            out.push_sql(prefix);
+            out.push_identifier(column.name.as_str())?;
+            out.push_sql(&format!(" = {}.g$key{}", alias, i));
+        }
+        Ok(())
+    }
+}
+
 pub trait FromEntityData {
     type Value: FromColumnValue;
 
@@ -859,13 +1038,17 @@ impl<'a> QueryFilter<'a> {
             | NotContains(attr, _)
             | NotContainsNoCase(attr, _)
             | Equal(attr, _)
+            | EqualNoCase(attr, _)
             | Not(attr, _)
+            | NotNoCase(attr, _)
             | GreaterThan(attr, _)
             | LessThan(attr, _)
             | GreaterOrEqual(attr, _)
             | LessOrEqual(attr, _)
             | In(attr, _)
+            | InNoCase(attr, _)
             | NotIn(attr, _)
+            | NotInNoCase(attr, _)
             | StartsWith(attr, _)
             | StartsWithNoCase(attr, _)
             | NotStartsWith(attr, _)
@@ -876,6 +1059,27 @@ impl<'a> QueryFilter<'a> {
             | NotEndsWithNoCase(attr, _) => {
                 table.column_for_field(attr)?;
             }
+
+            Matches(attr, _) => {
+                let column = table.column_for_field(attr)?;
+                if column.column_type != ColumnType::String || column.is_list() {
+                    return Err(StoreError::Unknown(anyhow!(
+                        "the filter `{attr}_matches` can only be used with String attributes",
+                        attr = attr
+                    )));
+                }
+            }
+
+            Overlaps(attr, _) | Empty(attr, _) => {
+                let column = table.column_for_field(attr)?;
+                if !column.is_list() {
+                    return Err(StoreError::Unknown(anyhow!(
+                        "the filters `{attr}_overlaps` and `{attr}_empty` can only be used \
+                         with list attributes, but `{attr}` is not a list",
+                        attr = attr
+                    )));
+                }
+            }
         }
         Ok(())
     }
@@ -952,6 +1156,38 @@ impl<'a> QueryFilter<'a> {
                     out.push_sql(") > 0");
                 }
             }
+            Value::List(values)
+                if !strict
+                    && matches!(column.column_type, ColumnType::String | ColumnType::Enum(_)) =>
+            {
+                // Compare the lowercased elements of the array with the
+                // lowercased values
+                let values = Value::List(
+                    values
+                        .iter()
+                        .map(|value| match value {
+                            Value::String(s) => Value::String(s.to_lowercase()),
+                            value => value.clone(),
+                        })
+                        .collect(),
+                );
+                out.push_sql("(");
+                out.push_identifier(column.name.as_str())?;
+                out.push_sql(" is not null and ");
+                if negated {
+                    out.push_sql("not ");
+                }
+                out.push_sql("array(select lower(x::text) from unnest(");
+                out.push_identifier(column.name.as_str())?;
+                out.push_sql(") as x)");
+                if negated {
+                    out.push_sql(" && ");
+                } else {
+                    out.push_sql(" @> ");
+                }
+                QueryValue(&values, &ColumnType::String).walk_ast(out.reborrow())?;
+                out.push_sql(")");
+            }
             Value::List(_) => {
                 if negated {
                     out.push_sql(" not ");
@@ -982,6 +1218,77 @@ impl<'a> QueryFilter<'a> {
         Ok(())
     }
 
+    fn overlaps(
+        &self,
+        attribute: &Attribute,
+        value: &Value,
+        mut out: AstPass<Pg>,
+    ) -> QueryResult<()> {
+        let column = self.column(attribute);
+
+        match value {
+            Value::List(_) => {
+                out.push_identifier(column.name.as_str())?;
+                out.push_sql(" && ");
+                QueryValue(value, &column.column_type).walk_ast(out)?;
+            }
+            _ => {
+                return Err(UnsupportedFilter {
+                    filter: "overlaps".to_owned(),
+                    value: value.clone(),
+                }
+                .into());
+            }
+        }
+        Ok(())
+    }
+
+    fn matches(
+        &self,
+        attribute: &Attribute,
+        value: &Value,
+        mut out: AstPass<Pg>,
+    ) -> QueryResult<()> {
+        let column = self.column(attribute);
+
+        match value {
+            Value::String(pattern) => {
+                out.push_identifier(column.name.as_str())?;
+                out.push_sql(" ~ ");
+                out.push_bind_param::<Text, _>(pattern)?;
+            }
+            _ => {
+                return Err(UnsupportedFilter {
+                    filter: "matches".to_owned(),
+                    value: value.clone(),
+                }
+                .into());
+            }
+        }
+        Ok(())
+    }
+
+    fn empty(&self, attribute: &Attribute, empty: bool, mut out: AstPass<Pg>) -> QueryResult<()> {
+        let column = self.column(attribute);
+
+        // Since `cardinality` of `null` is `null`, unset attributes are
+        // neither empty nor non-empty
+        out.push_sql("cardinality(");
+        out.push_identifier(column.name.as_str())?;
+        if empty {
+            out.push_sql(") = 0");
+        } else {
+            out.push_sql(") > 0");
+        }
+        Ok(())
+    }
+
+    /// Whether `column` is the `id` of a table with a composite id, which
+    /// is better compared through the key columns that it is formed from
+    fn is_composite_id(&self, column: &Column) -> bool {
+        column.is_primary_key() && self.table.has_composite_id()
+    }
+
     fn equals(
         &self,
         attribute: &Attribute,
@@ -1000,6 +1307,11 @@ impl<'a> QueryFilter<'a> {
                 Comparison::NotEqual => out.push_sql(" is not null"),
                 _ => unreachable!("we only call equals with '=' or '!='"),
             }
+        } else if let (true, Value::String(id)) = (self.is_composite_id(column), value) {
+            if op == Comparison::NotEqual {
+                out.push_sql("not ");
+            }
+            self.table.id_eq(id, &mut out)?;
         } else if column.use_prefix_comparison {
             PrefixComparison::new(op, column, value)?.walk_ast(out.reborrow())?;
         } else if column.is_fulltext() {
@@ -1014,6 +1326,137 @@ impl<'a> QueryFilter<'a> {
         Ok(())
     }
 
+    /// Push `lower(column)`, or `lower(left(column, STRING_PREFIX_SIZE))`
+    /// for columns that we only index by a prefix of their values. Either
+    /// way, this is the expression that the case-insensitive index for
+    /// the column is on
+    fn push_lower_column(column: &Column, out: &mut AstPass<Pg>) -> QueryResult<()> {
+        out.push_sql("lower(");
+        if column.use_prefix_comparison {
+            PrefixType::new(column)?.push_column_prefix(out)?;
+        } else {
+            out.push_identifier(column.name.as_str())?;
+        }
+        out.push_sql(")");
+        Ok(())
+    }
+
+    fn equals_nocase(
+        &self,
+        attribute: &Attribute,
+        value: &Value,
+        negated: bool,
+        mut out: AstPass<Pg>,
+    ) -> QueryResult<()> {
+        let column = self.column(attribute);
+
+        let text = match (&column.column_type, value) {
+            (ColumnType::String, Value::String(text)) => text,
+            _ => {
+                let filter = if negated { "not_nocase" } else { "nocase" };
+                return Err(UnsupportedFilter {
+                    filter: filter.to_owned(),
+                    value: value.clone(),
+                }
+                .into());
+            }
+        };
+
+        if negated {
+            out.push_sql("lower(");
+            out.push_identifier(column.name.as_str())?;
+            out.push_sql(") != lower(");
+            QueryValue(value, &column.column_type).walk_ast(out.reborrow())?;
+            out.push_sql(")");
+        } else if column.use_prefix_comparison {
+            // Compare prefixes first so that Postgres can use the index on
+            // `lower(left(column, STRING_PREFIX_SIZE))`. If the value is
+            // shorter than the prefix, that comparison is all we need
+            Self::push_lower_column(column, &mut out)?;
+            if text.len() <= STRING_PREFIX_SIZE - 1 {
+                out.push_sql(" = lower(");
+                QueryValue(value, &column.column_type).walk_ast(out.reborrow())?;
+                out.push_sql(")");
+            } else {
+                out.push_sql(" = lower(left(");
+                QueryValue(value, &column.column_type).walk_ast(out.reborrow())?;
+                out.push_sql(", ");
+                out.push_sql(&STRING_PREFIX_SIZE.to_string());
+                out.push_sql(")) and lower(");
+                out.push_identifier(column.name.as_str())?;
+                out.push_sql(") = lower(");
+                QueryValue(value, &column.column_type).walk_ast(out.reborrow())?;
+                out.push_sql(")");
+            }
+        } else {
+            Self::push_lower_column(column, &mut out)?;
+            out.push_sql(" = lower(");
+            QueryValue(value, &column.column_type).walk_ast(out.reborrow())?;
+            out.push_sql(")");
+        }
+        Ok(())
+    }
+
+    fn in_array_nocase(
+        &self,
+        attribute: &Attribute,
+        values: &[Value],
+        negated: bool,
+        mut out: AstPass<Pg>,
+    ) -> QueryResult<()> {
+        let column = self.column(attribute);
+
+        if let Some(value) = values
+            .iter()
+            .find(|value| column.column_type != ColumnType::String || !value.is_string())
+        {
+            let filter = if negated {
+                "not_in_nocase"
+            } else {
+                "in_nocase"
+            };
+            return Err(UnsupportedFilter {
+                filter: filter.to_owned(),
+                value: value.clone(),
+            }
+            .into());
+        }
+
+        if values.is_empty() {
+            out.push_sql("false");
+            return Ok(());
+        }
+
+        // Like `in_array`, only compare prefixes if all values are short
+        // enough for that to be exact
+        let short = values.iter().all(|value| match value {
+            Value::String(s) => s.len() <= STRING_PREFIX_SIZE - 1,
+            _ => false,
+        });
+        if !negated && (short || !column.use_prefix_comparison) {
+            Self::push_lower_column(column, &mut out)?;
+        } else {
+            out.push_sql("lower(");
+            out.push_identifier(column.name.as_str())?;
+            out.push_sql(")");
+        }
+        if negated {
+            out.push_sql(" not in (");
+        } else {
+            out.push_sql(" in (");
+        }
+        for (i, value) in values.iter().enumerate() {
+            if i > 0 {
+                out.push_sql(", ");
+            }
+            out.push_sql("lower(");
+            QueryValue(value, &column.column_type).walk_ast(out.reborrow())?;
+            out.push_sql(")");
+        }
+        out.push_sql(")");
+        Ok(())
+    }
+
     fn compare(
         &self,
         attribute: &Attribute,
@@ -1093,7 +1536,19 @@ impl<'a> QueryFilter<'a> {
             out.push_sql(" or ");
         }
 
-        if have_non_nulls {
+        if have_non_nulls && self.is_composite_id(column) {
+            let ids: Vec<_> = values
+                .iter()
+                .filter_map(|value| match value {
+                    Value::String(id) => Some(id.as_str()),
+                    _ => None,
+                })
+                .collect();
+            if negated {
+                out.push_sql("not ");
+            }
+            self.table.id_is_in(&ids, &mut out)?;
+        } else if have_non_nulls {
             if column.use_prefix_comparison
                 && values.iter().all(|v| match v {
                     Value::String(s) => s.len() <= STRING_PREFIX_SIZE - 1,
@@ -1179,6 +1634,33 @@ impl<'a> QueryFilter<'a> {
         }
         Ok(())
     }
+
+    /// Case-insensitive `starts_with` written so that Postgres can use the
+    /// index on `lower(column)`. For prefix-indexed columns, that only
+    /// works when the value is shorter than the prefix; longer values fall
+    /// back to `ilike`
+    fn starts_with_nocase(
+        &self,
+        attribute: &Attribute,
+        value: &Value,
+        mut out: AstPass<Pg>,
+    ) -> QueryResult<()> {
+        let column = self.column(attribute);
+
+        match value {
+            Value::String(s)
+                if column.column_type == ColumnType::String
+                    && (!column.use_prefix_comparison || s.len() <= STRING_PREFIX_SIZE - 1) =>
+            {
+                Self::push_lower_column(column, &mut out)?;
+                out.push_sql(" like lower(");
+                out.push_bind_param::<Text, _>(&format!("{}%", s))?;
+                out.push_sql(")");
+                Ok(())
+            }
+            _ => self.starts_or_ends_with(attribute, value, " ilike ", true, out),
+        }
+    }
 }
 
 impl<'a> QueryFragment<Pg> for QueryFilter<'a> {
@@ -1198,6 +1680,8 @@ impl<'a> QueryFragment<Pg> for QueryFilter<'a> {
 
             Equal(attr, value) => self.equals(attr, value, c::Equal, out)?,
             Not(attr, value) => self.equals(attr, value, c::NotEqual, out)?,
+            EqualNoCase(attr, value) => self.equals_nocase(attr, value, false, out)?,
+            NotNoCase(attr, value) => self.equals_nocase(attr, value, true, out)?,
 
             GreaterThan(attr, value) => self.compare(attr, value, c::Greater, out)?,
             LessThan(attr, value) => self.compare(attr, value, c::Less, out)?,
@@ -1206,13 +1690,13 @@ impl<'a> QueryFragment<Pg> for QueryFilter<'a> {
 
             In(attr, values) => self.in_array(attr, values, false, out)?,
             NotIn(attr, values) => self.in_array(attr, values, true, out)?,
+            InNoCase(attr, values) => self.in_array_nocase(attr, values, false, out)?,
+            NotInNoCase(attr, values) => self.in_array_nocase(attr, values, true, out)?,
 
             StartsWith(attr, value) => {
                 self.starts_or_ends_with(attr, value, " like ", true, out)?
             }
-            StartsWithNoCase(attr, value) => {
-                self.starts_or_ends_with(attr, value, " ilike ", true, out)?
-            }
+            StartsWithNoCase(attr, value) => self.starts_with_nocase(attr, value, out)?,
             NotStartsWith(attr, value) => {
                 self.starts_or_ends_with(attr, value, " not like ", true, out)?
             }
@@ -1229,6 +1713,10 @@ impl<'a> QueryFragment<Pg> for QueryFilter<'a> {
             NotEndsWithNoCase(attr, value) => {
                 self.starts_or_ends_with(attr, value, " not ilike ", false, out)?
             }
+            Overlaps(attr, value) => self.overlaps(attr, value, out)?,
+            Empty(attr, empty) => self.empty(attr, *empty, out)?,
+            Matches(attr, value) => self.matches(attr, value, out)?,
+
             ChangeBlockGte(block_number) => self.filter_block_gte(block_number, out)?,
         }
         Ok(())
@@ -1253,9 +1741,9 @@ impl<'a> QueryFragment<Pg> for FindQuery<'a> {
         out.push_bind_param::<Text, _>(&self.table.object.as_str())?;
         out.push_sql(" as entity, to_jsonb(e.*) as data\n");
         out.push_sql("  from ");
-        out.push_sql(self.table.qualified_name.as_str());
+        out.push_sql(self.table.source().as_str());
         out.push_sql(" e\n where ");
-        self.table.primary_key().eq(self.id, &mut out)?;
+        self.table.id_eq(self.id, &mut out)?;
         out.push_sql(" and ");
         BlockRangeColumn::new(self.table, "e.", self.block).contains(&mut out)
     }
@@ -1297,7 +1785,7 @@ impl<'a> QueryFragment<Pg> for FindChangesQuery<'a> {
             out.push_bind_param::<Text, _>(&table.object.as_str())?;
             out.push_sql(" as entity, to_jsonb(e.*) as data\n");
             out.push_sql("  from ");
-            out.push_sql(table.qualified_name.as_str());
+            out.push_sql(table.source().as_str());
             out.push_sql(" e\n where ");
             BlockRangeLowerBoundClause::new("e.", self.block).walk_ast(out.reborrow())?;
         }
@@ -1320,6 +1808,51 @@ impl<'a> LoadQuery<PgConnection, EntityData> for FindChangesQuery<'a> {
 
 impl<'a, Conn> RunQueryDsl<Conn> for FindChangesQuery<'a> {}
 
+/// Builds a query over a given set of immutable [`Table`]s that finds the
+/// entities that were inserted at a given block number
+#[derive(Debug, Clone, Constructor)]
+pub struct FindImmutableInsertsQuery<'a> {
+    pub(crate) tables: &'a [&'a Table],
+    pub(crate) block: BlockNumber,
+}
+
+impl<'a> QueryFragment<Pg> for FindImmutableInsertsQuery<'a> {
+    fn walk_ast(&self, mut out: AstPass<Pg>) -> QueryResult<()> {
+        out.unsafe_to_cache_prepared();
+
+        for (i, table) in self.tables.iter().enumerate() {
+            if i > 0 {
+                out.push_sql("\nunion all\n");
+            }
+            out.push_sql("select ");
+            out.push_bind_param::<Text, _>(&table.object.as_str())?;
+            out.push_sql(" as entity, to_jsonb(e.*) as data\n");
+            out.push_sql("  from ");
+            out.push_sql(table.source().as_str());
+            out.push_sql(" e\n where ");
+            out.push_identifier(BLOCK_COLUMN)?;
+            out.push_sql(" = ");
+            out.push_bind_param::<Integer, _>(&self.block)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl<'a> QueryId for FindImmutableInsertsQuery<'a> {
+    type QueryId = ();
+
+    const HAS_STATIC_QUERY_ID: bool = false;
+}
+
+impl<'a> LoadQuery<PgConnection, EntityData> for FindImmutableInsertsQuery<'a> {
+    fn internal_load(self, conn: &PgConnection) -> QueryResult<Vec<EntityData>> {
+        conn.query_by_name(&self)
+    }
+}
+
+impl<'a, Conn> RunQueryDsl<Conn> for FindImmutableInsertsQuery<'a> {}
+
 /// Builds a query over a given set of [`Table`]s in an attempt to find deleted
 /// entities; i.e. such that the block range's lower bound is equal to said
 /// block number.
@@ -1347,7 +1880,7 @@ impl<'a> QueryFragment<Pg> for FindPossibleDeletionsQuery<'a> {
             out.push_bind_param::<Text, _>(&table.object.as_str())?;
             out.push_sql(" as entity, e.id\n");
             out.push_sql("  from ");
-            out.push_sql(table.qualified_name.as_str());
+            out.push_sql(table.source().as_str());
             out.push_sql(" e\n where ");
             BlockRangeUpperBoundClause::new("e.", self.block).walk_ast(out.reborrow())?;
         }
@@ -1370,6 +1903,85 @@ impl<'a> LoadQuery<PgConnection, EntityDeletion> for FindPossibleDeletionsQuery<
 
 impl<'a, Conn> RunQueryDsl<Conn> for FindPossibleDeletionsQuery<'a> {}
 
+#[derive(QueryableByName)]
+pub struct ChangedBlock {
+    #[sql_type = "Integer"]
+    pub block: BlockNumber,
+}
+
+/// Builds a query that finds the numbers of all blocks in the range
+/// `(after, until]` in which any of the given [`Table`]s changed, i.e., in
+/// which some entity version was created or ended
+#[derive(Debug, Clone, Constructor)]
+pub struct ChangedBlocksQuery<'a> {
+    pub(crate) tables: &'a [&'a Table],
+    pub(crate) after: BlockNumber,
+    pub(crate) until: BlockNumber,
+}
+
+impl<'a> ChangedBlocksQuery<'a> {
+    fn bounded(&self, out: &mut AstPass<Pg>, column: &str) -> QueryResult<()> {
+        out.push_sql(column);
+        out.push_sql(" > ");
+        out.push_bind_param::<Integer, _>(&self.after)?;
+        out.push_sql(" and ");
+        out.push_sql(column);
+        out.push_sql(" <= ");
+        out.push_bind_param::<Integer, _>(&self.until)
+    }
+}
+
+impl<'a> QueryFragment<Pg> for ChangedBlocksQuery<'a> {
+    fn walk_ast(&self, mut out: AstPass<Pg>) -> QueryResult<()> {
+        out.unsafe_to_cache_prepared();
+
+        // select distinct block from (
+        //   select lower(block_range) as block from t1 where ..
+        //   union all
+        //   select upper(block_range) as block from t1 where ..
+        //   union all
+        //   select block$ as block from t2 where ..) changes
+        // order by block
+        out.push_sql("select distinct block from (\n");
+        let mut first = true;
+        for table in self.tables {
+            let columns: &[&str] = if table.immutable {
+                &["\"block$\""]
+            } else {
+                &["lower(block_range)", "upper(block_range)"]
+            };
+            for column in columns {
+                if !first {
+                    out.push_sql("\nunion all\n");
+                }
+                first = false;
+                out.push_sql("select ");
+                out.push_sql(column);
+                out.push_sql(" as block from ");
+                out.push_sql(table.source().as_str());
+                out.push_sql(" where ");
+                self.bounded(&mut out, column)?;
+            }
+        }
+        out.push_sql(") changes\norder by block");
+        Ok(())
+    }
+}
+
+impl<'a> QueryId for ChangedBlocksQuery<'a> {
+    type QueryId = ();
+
+    const HAS_STATIC_QUERY_ID: bool = false;
+}
+
+impl<'a> LoadQuery<PgConnection, ChangedBlock> for ChangedBlocksQuery<'a> {
+    fn internal_load(self, conn: &PgConnection) -> QueryResult<Vec<ChangedBlock>> {
+        conn.query_by_name(&self)
+    }
+}
+
+impl<'a, Conn> RunQueryDsl<Conn> for ChangedBlocksQuery<'a> {}
+
 #[derive(Debug, Clone, Constructor)]
 pub struct FindManyQuery<'a> {
     pub(crate) _namespace: &'a Namespace,
@@ -1400,11 +2012,9 @@ impl<'a> QueryFragment<Pg> for FindManyQuery<'a> {
             out.push_bind_param::<Text, _>(&table.object.as_str())?;
             out.push_sql(" as entity, to_jsonb(e.*) as data\n");
             out.push_sql("  from ");
-            out.push_sql(table.qualified_name.as_str());
+            out.push_sql(table.source().as_str());
             out.push_sql(" e\n where ");
-            table
-                .primary_key()
-                .is_in(&self.ids_for_type[&table.object], &mut out)?;
+            table.id_is_in(&self.ids_for_type[&table.object], &mut out)?;
             out.push_sql(" and ");
             BlockRangeColumn::new(table, "e.", self.block).contains(&mut out)?;
         }
@@ -1441,7 +2051,7 @@ impl<'a> InsertQuery<'a> {
         block: BlockNumber,
     ) -> Result<InsertQuery<'a>, StoreError> {
         for (entity_key, entity) in entities.iter_mut() {
-            for column in table.columns.iter() {
+            for column in table.stored_columns() {
                 if let Some(fields) = column.fulltext_fields.as_ref() {
                     let fulltext_field_values = fields
                         .iter()
@@ -1482,7 +2092,7 @@ impl<'a> InsertQuery<'a> {
     ) -> Vec<&'a Column> {
         let mut hashmap = HashMap::new();
         for (_key, entity) in entities.iter() {
-            for column in &table.columns {
+            for column in table.stored_columns() {
                 if entity.get(&column.field).is_some() {
                     hashmap.entry(column.name.as_str()).or_insert(column);
                 }
@@ -1506,7 +2116,7 @@ impl<'a> QueryFragment<Pg> for InsertQuery<'a> {
         //
         // and convert and bind the entity's values into it
         out.push_sql("insert into ");
-        out.push_sql(self.table.qualified_name.as_str());
+        out.push_sql(self.table.source().as_str());
 
         out.push_sql("(");
 
@@ -1604,9 +2214,14 @@ impl<'a> QueryFragment<Pg> for ConflictingEntityQuery<'a> {
             out.push_sql("select ");
             out.push_bind_param::<Text, _>(&table.object.as_str())?;
             out.push_sql(" as entity from ");
-            out.push_sql(table.qualified_name.as_str());
-            out.push_sql(" where id = ");
-            out.push_bind_param::<Text, _>(&self.entity_id)?;
+            out.push_sql(table.source().as_str());
+            if table.has_composite_id() {
+                out.push_sql(" where ");
+                table.id_eq(&self.entity_id, &mut out)?;
+            } else {
+                out.push_sql(" where id = ");
+                out.push_bind_param::<Text, _>(&self.entity_id)?;
+            }
         }
         Ok(())
     }
@@ -1836,7 +2451,7 @@ impl<'a> FilterWindow<'a> {
         out.push_sql(") as p(id) cross join lateral (select ");
         write_column_names(&self.column_names, self.table, out)?;
         out.push_sql(" from ");
-        out.push_sql(self.table.qualified_name.as_str());
+        out.push_sql(self.table.source().as_str());
         out.push_sql(" c where ");
         BlockRangeColumn::new(self.table, "c.", block).contains(out)?;
         limit.filter(out);
@@ -1872,7 +2487,7 @@ impl<'a> FilterWindow<'a> {
         out.push_sql("\n/* child_type_a */ from unnest(");
         column.bind_ids(&self.ids, out)?;
         out.push_sql(") as p(id), ");
-        out.push_sql(self.table.qualified_name.as_str());
+        out.push_sql(self.table.source().as_str());
         out.push_sql(" c where ");
         BlockRangeColumn::new(self.table, "c.", block).contains(out)?;
         limit.filter(out);
@@ -1915,7 +2530,7 @@ impl<'a> FilterWindow<'a> {
         out.push_sql(") as p(id) cross join lateral (select ");
         write_column_names(&self.column_names, self.table, out)?;
         out.push_sql(" from ");
-        out.push_sql(self.table.qualified_name.as_str());
+        out.push_sql(self.table.source().as_str());
         out.push_sql(" c where ");
         BlockRangeColumn::new(self.table, "c.", block).contains(out)?;
         limit.filter(out);
@@ -1945,7 +2560,7 @@ impl<'a> FilterWindow<'a> {
         out.push_sql("\n/* child_type_b */  from unnest(");
         column.bind_ids(&self.ids, out)?;
         out.push_sql(") as p(id), ");
-        out.push_sql(self.table.qualified_name.as_str());
+        out.push_sql(self.table.source().as_str());
         out.push_sql(" c where ");
         BlockRangeColumn::new(self.table, "c.", block).contains(out)?;
         limit.filter(out);
@@ -1975,20 +2590,50 @@ impl<'a> FilterWindow<'a> {
         //             limit {first} offset {skip}) c
         //     order by c.{sort_key}
 
+        // For a child table with a composite id, the matrix is split into
+        // one matrix for each key column, and the children are found by
+        //             where exists (select 1
+        //                             from rows from (unnest(p.g$key0), ..)
+        //                                         as k(g$key0, ..)
+        //                            where c.key0 = k.g$key0 and ..)
+
         out.push_sql("\n/* children_type_c */  from ");
         out.push_sql("rows from (unnest(");
         out.push_bind_param::<Array<Text>, _>(&self.ids)?;
-        out.push_sql("), reduce_dim(");
-        self.table.primary_key().push_matrix(child_ids, out)?;
-        out.push_sql(")) as p(id, child_ids)");
+        if self.table.has_composite_id() {
+            out.push_sql("), ");
+            self.table.push_key_matrices(child_ids, out)?;
+            out.push_sql(") as p(id, ");
+            self.table.push_key_names(out);
+            out.push_sql(")");
+        } else {
+            out.push_sql("), reduce_dim(");
+            self.table.primary_key().push_matrix(child_ids, out)?;
+            out.push_sql(")) as p(id, child_ids)");
+        }
         out.push_sql(" cross join lateral (select ");
         write_column_names(&self.column_names, self.table, out)?;
         out.push_sql(" from ");
-        out.push_sql(self.table.qualified_name.as_str());
+        out.push_sql(self.table.source().as_str());
         out.push_sql(" c where ");
         BlockRangeColumn::new(self.table, "c.", block).contains(out)?;
         limit.filter(out);
-        out.push_sql(" and c.id = any(p.child_ids)");
+        if self.table.has_composite_id() {
+            out.push_sql(" and exists (select 1 from rows from (");
+            for i in 0..self.table.composite_id.len() {
+                if i > 0 {
+                    out.push_sql(", ");
+                }
+                out.push_sql(&format!("unnest(p.g$key{})", i));
+            }
+            out.push_sql(") as k(");
+            self.table.push_key_names(out);
+            out.push_sql(") where ");
+            self.table.key_join("c.", "k", out)?;
+            out.push_sql(")");
+        } else {
+            out.push_sql(" and c.id = any(p.child_ids)");
+        }
         self.and_filter(out.reborrow())?;
         limit.restrict(out)?;
         out.push_sql(") c");
@@ -2008,12 +2653,47 @@ impl<'a> FilterWindow<'a> {
         //     where c.id = p.child_id
         //       and .. other conditions on c ..
 
+        // For a child table with a composite id, the child ids are split
+        // into the values of the key columns, and the children are found by
+        //     where c.key0 = p.g$key0 and ..
+
+        if self.table.has_composite_id() {
+            // Leave out the children that can not exist together with
+            // their parents so that the two stay aligned
+            let (parent_ids, keys): (Vec<&str>, Vec<Vec<Value>>) = self
+                .ids
+                .iter()
+                .zip(child_ids)
+                .filter_map(|(parent_id, child_id)| {
+                    self.table
+                        .composite_key(child_id)
+                        .map(|key| (parent_id.as_str(), key))
+                })
+                .unzip();
+            out.push_sql("\n/* child_type_d */ from rows from (unnest(");
+            out.push_bind_param::<Array<Text>, _>(&parent_ids)?;
+            out.push_sql("), ");
+            self.table.push_key_arrays(&keys, out)?;
+            out.push_sql(") as p(id, ");
+            self.table.push_key_names(out);
+            out.push_sql("), ");
+            out.push_sql(self.table.source().as_str());
+            out.push_sql(" c where ");
+            BlockRangeColumn::new(self.table, "c.", block).contains(out)?;
+            limit.filter(out);
+            out.push_sql(" and ");
+            self.table.key_join("c.", "p", out)?;
+            self.and_filter(out.reborrow())?;
+            limit.single_limit(self.ids.len(), out);
+            return Ok(());
+        }
+
         out.push_sql("\n/* child_type_d */ from rows from (unnest(");
         out.push_bind_param::<Array<Text>, _>(&self.ids)?;
         out.push_sql("), unnest(");
         self.table.primary_key().bind_ids(child_ids, out)?;
         out.push_sql(")) as p(id, child_id), ");
-        out.push_sql(self.table.qualified_name.as_str());
+        out.push_sql(self.table.source().as_str());
         out.push_sql(" c where ");
         BlockRangeColumn::new(self.table, "c.", block).contains(out)?;
         limit.filter(out);
@@ -2410,6 +3090,23 @@ impl QueryFragment<Pg> for FilterRange {
     }
 }
 
+impl FilterRange {
+    /// Generate `limit {first + skip}` for one branch of a `union all`
+    /// whose combined result is limited with this range. No branch can
+    /// contribute more rows than that to the final result, and the limit
+    /// lets Postgres stop scanning each table early
+    fn limit_branch(&self, out: &mut AstPass<Pg>) {
+        let range = &self.0;
+        if let Some(first) = &range.first {
+            out.push_sql(
+                "
+ limit ",
+            );
+            out.push_sql(&(*first as u64 + range.skip as u64).to_string());
+        }
+    }
+}
+
 /// The parallel to `EntityQuery`.
 ///
 /// Details of how query generation for `FilterQuery` works can be found
@@ -2463,7 +3160,7 @@ impl<'a> FilterQuery<'a> {
         mut out: AstPass<Pg>,
     ) -> QueryResult<()> {
         out.push_sql("\n  from ");
-        out.push_sql(table.qualified_name.as_str());
+        out.push_sql(table.source().as_str());
         out.push_sql(" c");
         out.push_sql("\n where ");
         BlockRangeColumn::new(&table, "c.", self.block).contains(&mut out)?;
@@ -2552,14 +3249,21 @@ impl<'a> FilterQuery<'a> {
         // Overall, we generate a query
         //
         // with matches as (
-        //   select '...' as entity, id, vid, {sort_key}
-        //     from {table} c
-        //    where {query_filter}
+        //   (select '...' as entity, id, vid, {sort_key}
+        //      from {table} c
+        //     where {query_filter}
+        //     order by {sort_key}
+        //     limit n + m)
         //    union all
         //    ...
         //    order by {sort_key}
         //    limit n offset m)
         //
+        // Sorting and limiting each implementer's rows before combining
+        // them means that we never have to materialize and sort all the
+        // matching rows of every table, only the ones that can possibly
+        // end up in the result
+        //
         // select m.entity, to_jsonb({column names}) as data, c.id, c.{sort_key}
         //   from {table} c, matches m
         //  where c.vid = m.vid and m.entity = '...'
@@ -2577,11 +3281,15 @@ impl<'a> FilterQuery<'a> {
             //        c.id,
             //        c.vid,
             //        c.${sort_key}
-            out.push_sql("select '");
+            out.push_sql("(select '");
             out.push_sql(table.object.as_str());
             out.push_sql("' as entity, c.id, c.vid");
             self.sort_key.select(&mut out)?;
             self.filtered_rows(table, filter, out.reborrow())?;
+            out.push_sql(" ");
+            self.sort_key.order_by(&mut out)?;
+            self.range.limit_branch(&mut out);
+            out.push_sql(")");
         }
         out.push_sql("\n ");
         self.sort_key.order_by(&mut out)?;
@@ -2599,7 +3307,7 @@ impl<'a> FilterQuery<'a> {
             out.push_sql(" as data, c.id");
             self.sort_key.select(&mut out)?;
             out.push_sql("\n  from ");
-            out.push_sql(table.qualified_name.as_str());
+            out.push_sql(table.source().as_str());
             out.push_sql(" c,");
             out.push_sql(" matches m");
             out.push_sql("\n where c.vid = m.vid and m.entity = ");
@@ -2688,7 +3396,7 @@ impl<'a> FilterQuery<'a> {
             jsonb_build_object(&window.column_names, "c", window.table, &mut out)?;
             out.push_sql("|| jsonb_build_object('g$parent_id', m.g$parent_id) as data");
             out.push_sql("\n  from ");
-            out.push_sql(window.table.qualified_name.as_str());
+            out.push_sql(window.table.source().as_str());
             out.push_sql(" c, matches m\n where c.vid = m.vid and m.entity = '");
             out.push_sql(window.table.object.as_str());
             out.push_sql("'");
@@ -2751,6 +3459,74 @@ impl<'a> LoadQuery<PgConnection, EntityData> for FilterQuery<'a> {
 
 impl<'a, Conn> RunQueryDsl<Conn> for FilterQuery<'a> {}
 
+/// Postgres' plan for a `FilterQuery`, used to estimate how expensive the
+/// query is before running it, or to report how it was run
+#[derive(Debug, Clone)]
+pub struct ExplainQuery<'a> {
+    query: &'a FilterQuery<'a>,
+    analyze: bool,
+}
+
+#[derive(QueryableByName)]
+struct QueryPlan {
+    #[column_name = "QUERY PLAN"]
+    #[sql_type = "Text"]
+    plan: String,
+}
+
+impl<'a> ExplainQuery<'a> {
+    /// Have Postgres plan `query` without running it
+    pub fn new(query: &'a FilterQuery<'a>) -> Self {
+        ExplainQuery {
+            query,
+            analyze: false,
+        }
+    }
+
+    /// Run `query` and report the plan together with actual row counts
+    /// and timings
+    pub fn analyze(query: &'a FilterQuery<'a>) -> Self {
+        ExplainQuery {
+            query,
+            analyze: true,
+        }
+    }
+
+    /// The plan in Postgres' JSON format
+    pub fn plan(self, conn: &PgConnection) -> QueryResult<serde_json::Value> {
+        let plan = conn.query_by_name::<_, QueryPlan>(&self)?;
+        let plan = plan
+            .first()
+            .ok_or_else(|| DieselError::DeserializationError("empty query plan".into()))?;
+        serde_json::from_str(&plan.plan).map_err(|e| DieselError::DeserializationError(Box::new(e)))
+    }
+
+    /// The total cost that Postgres estimates for the query
+    pub fn cost(self, conn: &PgConnection) -> QueryResult<f64> {
+        self.plan(conn)?[0]["Plan"]["Total Cost"]
+            .as_f64()
+            .ok_or_else(|| DieselError::DeserializationError("query plan has no cost".into()))
+    }
+}
+
+impl<'a> QueryFragment<Pg> for ExplainQuery<'a> {
+    fn walk_ast(&self, mut out: AstPass<Pg>) -> QueryResult<()> {
+        out.unsafe_to_cache_prepared();
+        if self.analyze {
+            out.push_sql("explain (analyze, buffers, format json) ");
+        } else {
+            out.push_sql("explain (format json) ");
+        }
+        self.query.walk_ast(out)
+    }
+}
+
+impl<'a> QueryId for ExplainQuery<'a> {
+    type QueryId = ();
+
+    const HAS_STATIC_QUERY_ID: bool = false;
+}
+
 /// Reduce the upper bound of the current entry's block range to `block` as
 /// long as that does not result in an empty block range
 #[derive(Debug)]
@@ -2866,7 +3642,7 @@ impl<'a> QueryFragment<Pg> for RevertRemoveQuery<'a> {
         //    where lower(block_range) >= $block
         //   returning id
         out.push_sql("delete from ");
-        out.push_sql(self.table.qualified_name.as_str());
+        out.push_sql(self.table.source().as_str());
         out.push_sql("\n where ");
         self.br_column.changed_since(&mut out)?;
         out.push_sql("\nreturning ");
@@ -3003,7 +3779,7 @@ impl<'a> CopyEntityBatchQuery<'a> {
         last_vid: i64,
     ) -> Result<Self, StoreError> {
         let mut columns = Vec::new();
-        for dcol in &dst.columns {
+        for dcol in dst.stored_columns() {
             if let Some(scol) = src.column(&dcol.name) {
                 if let Some(msg) = dcol.is_assignable_from(scol, &src.object) {
                     return Err(anyhow!("{}", msg).into());
@@ -3097,7 +3873,7 @@ impl<'a> QueryFragment<Pg> for CopyEntityBatchQuery<'a> {
             (false, false) => out.push_sql(BLOCK_RANGE_COLUMN),
         }
         out.push_sql(" from ");
-        out.push_sql(self.src.qualified_name.as_str());
+        out.push_sql(self.src.source().as_str());
         out.push_sql(" where vid >= ");
         out.push_bind_param::<BigInt, _>(&self.first_vid)?;
         out.push_sql(" and vid <= ");
@@ -0,0 +1,443 @@
+//! Snapshots of deployments. A snapshot is a directory with one file per
+//! table of the deployment that has every version of every entity in it,
+//! one JSON object per line, and a `snapshot.json` file with the
+//! deployment's metadata: its manifest, its block pointer, and its dynamic
+//! data sources and errors. Restoring a snapshot creates the deployment
+//! with exactly the rows it had when the snapshot was taken, so that it
+//! can continue indexing right where the original left off
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+use std::str::FromStr;
+
+use diesel::pg::PgConnection;
+use diesel::sql_types::{BigInt, Text};
+use diesel::{sql_query, RunQueryDsl};
+use graph::data::subgraph::schema::{DeploymentCreate, SubgraphManifestEntity};
+use graph::prelude::serde::{Deserialize, Serialize};
+use graph::prelude::{anyhow, serde_json, BlockNumber, BlockPtr, StoreError};
+
+use crate::primary::Site;
+use crate::relational::{Layout, SqlName, Table};
+
+/// The version of the snapshot format; restoring refuses snapshots of
+/// other versions
+const VERSION: u32 = 1;
+
+/// The name of the file with the metadata of a snapshot
+const METADATA_FILE: &str = "snapshot.json";
+
+/// The number of rows we read from the database or from a file at once
+const BATCH_SIZE: usize = 10_000;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SnapshotBlock {
+    pub hash: String,
+    pub number: BlockNumber,
+}
+
+impl From<&BlockPtr> for SnapshotBlock {
+    fn from(ptr: &BlockPtr) -> Self {
+        SnapshotBlock {
+            hash: ptr.hash_hex(),
+            number: ptr.number,
+        }
+    }
+}
+
+impl SnapshotBlock {
+    fn to_ptr(&self) -> Result<BlockPtr, StoreError> {
+        BlockPtr::from_str(&format!("{}:{}", self.hash, self.number))
+            .map_err(|e| StoreError::Unknown(e.context("invalid block in snapshot")))
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SnapshotTable {
+    pub entity_type: String,
+    pub table: String,
+    pub file: String,
+    pub rows: usize,
+}
+
+/// The metadata of a snapshot
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub version: u32,
+    pub deployment: String,
+    pub network: String,
+    pub block: SnapshotBlock,
+    pub earliest_block: Option<SnapshotBlock>,
+    pub firehose_cursor: Option<String>,
+    pub synced: bool,
+    pub spec_version: String,
+    pub description: Option<String>,
+    pub repository: Option<String>,
+    pub features: Vec<String>,
+    pub schema: String,
+    pub account_like: Vec<String>,
+    pub tables: Vec<SnapshotTable>,
+    /// The rows of `subgraphs.dynamic_ethereum_contract_data_source`
+    pub data_sources: Vec<serde_json::Value>,
+    /// The rows of `subgraphs.subgraph_error`
+    pub errors: Vec<serde_json::Value>,
+}
+
+impl Snapshot {
+    /// Read the metadata of the snapshot in the directory `source`
+    pub fn read(source: &str) -> Result<Self, StoreError> {
+        let path = local_dir(source)?.join(METADATA_FILE);
+        let file = File::open(&path).map_err(|e| io_error(e, &path))?;
+        let snapshot: Snapshot = serde_json::from_reader(BufReader::new(file)).map_err(|e| {
+            StoreError::Unknown(anyhow::Error::from(e).context(path.display().to_string()))
+        })?;
+        if snapshot.version != VERSION {
+            return Err(StoreError::Unknown(anyhow!(
+                "the snapshot in {} has version {}, but only version {} is supported",
+                source,
+                snapshot.version,
+                VERSION
+            )));
+        }
+        Ok(snapshot)
+    }
+
+    pub(crate) fn deployment_create(&self) -> Result<DeploymentCreate, StoreError> {
+        Ok(DeploymentCreate {
+            manifest: SubgraphManifestEntity {
+                spec_version: self.spec_version.clone(),
+                description: self.description.clone(),
+                repository: self.repository.clone(),
+                features: self.features.clone(),
+                schema: self.schema.clone(),
+            },
+            earliest_block: self
+                .earliest_block
+                .as_ref()
+                .map(SnapshotBlock::to_ptr)
+                .transpose()?,
+            graft_base: None,
+            graft_block: None,
+            debug_fork: None,
+        })
+    }
+}
+
+fn local_dir(dir: &str) -> Result<&Path, StoreError> {
+    if dir.contains("://") {
+        return Err(StoreError::Unknown(anyhow!(
+            "snapshots can only be kept in a local directory, not in `{}`",
+            dir
+        )));
+    }
+    Ok(Path::new(dir))
+}
+
+fn io_error(e: std::io::Error, path: &Path) -> StoreError {
+    StoreError::Unknown(anyhow::Error::from(e).context(path.display().to_string()))
+}
+
+/// Load the rows that `query` returns as JSON
+fn load_json(
+    conn: &PgConnection,
+    query: &str,
+    deployment: &str,
+) -> Result<Vec<serde_json::Value>, StoreError> {
+    #[derive(QueryableByName)]
+    struct Row {
+        #[sql_type = "Text"]
+        row: String,
+    }
+
+    sql_query(query)
+        .bind::<Text, _>(deployment)
+        .load::<Row>(conn)?
+        .into_iter()
+        .map(|row| serde_json::from_str(&row.row).map_err(|e| StoreError::Unknown(e.into())))
+        .collect()
+}
+
+/// Write a snapshot of the deployment with `layout` into the directory
+/// `destination`. The caller must make sure that all queries run against
+/// the same snapshot of the database
+pub(crate) fn create(
+    conn: &PgConnection,
+    layout: &Layout,
+    destination: &str,
+) -> Result<Snapshot, StoreError> {
+    let site = &layout.site;
+    let dir = local_dir(destination)?;
+    if dir.join(METADATA_FILE).exists() {
+        return Err(StoreError::Unknown(anyhow!(
+            "there already is a snapshot in {}",
+            destination
+        )));
+    }
+
+    let deployment = crate::detail::deployment_entity(conn, site)?;
+    if deployment.failed {
+        return Err(StoreError::Unknown(anyhow!(
+            "can not take a snapshot of deployment {} because it has failed",
+            site.deployment
+        )));
+    }
+    let block = deployment.latest_block.ok_or_else(|| {
+        StoreError::Unknown(anyhow!(
+            "deployment {} has not started syncing",
+            site.deployment
+        ))
+    })?;
+    let firehose_cursor =
+        crate::deployment::get_subgraph_firehose_cursor(conn, layout.site.clone())?;
+    let mut account_like: Vec<_> = crate::catalog::account_like(conn, site)?
+        .into_iter()
+        .collect();
+    account_like.sort();
+
+    let data_sources = load_json(
+        conn,
+        "select row_to_json(d)::text as row
+           from (select name, address, abi, start_block, ethereum_block_hash,
+                        ethereum_block_number, context
+                   from subgraphs.dynamic_ethereum_contract_data_source
+                  where deployment = $1
+                  order by vid) d",
+        site.deployment.as_str(),
+    )?;
+    let errors = load_json(
+        conn,
+        "select row_to_json(e)::text as row
+           from (select id, message, block_hash, handler, deterministic, block_range
+                   from subgraphs.subgraph_error
+                  where subgraph_id = $1
+                  order by vid) e",
+        site.deployment.as_str(),
+    )?;
+
+    fs::create_dir_all(dir).map_err(|e| io_error(e, dir))?;
+    let mut tables: Vec<&Table> = layout.tables.values().map(|table| table.as_ref()).collect();
+    tables.sort_by(|a, b| a.name.as_str().cmp(b.name.as_str()));
+    let tables = tables
+        .into_iter()
+        .map(|table| {
+            let file = format!("{}.jsonl", table.name);
+            let path = dir.join(&file);
+            let rows = dump_table(conn, table, &path)
+                .map_err(|e| StoreError::Unknown(e.context(path.display().to_string())))?;
+            Ok(SnapshotTable {
+                entity_type: table.object.to_string(),
+                table: table.name.to_string(),
+                file,
+                rows,
+            })
+        })
+        .collect::<Result<_, StoreError>>()?;
+
+    let snapshot = Snapshot {
+        version: VERSION,
+        deployment: site.deployment.to_string(),
+        network: site.network.clone(),
+        block: SnapshotBlock::from(&block),
+        earliest_block: deployment.earliest_block.as_ref().map(SnapshotBlock::from),
+        firehose_cursor,
+        synced: deployment.synced,
+        spec_version: deployment.manifest.spec_version,
+        description: deployment.manifest.description,
+        repository: deployment.manifest.repository,
+        features: deployment.manifest.features,
+        schema: deployment.manifest.schema,
+        account_like,
+        tables,
+        data_sources,
+        errors,
+    };
+
+    // Write the metadata last so that an interrupted snapshot can not be
+    // mistaken for a complete one
+    let path = dir.join(METADATA_FILE);
+    let file = File::create(&path).map_err(|e| io_error(e, &path))?;
+    serde_json::to_writer_pretty(BufWriter::new(file), &snapshot).map_err(|e| {
+        StoreError::Unknown(anyhow::Error::from(e).context(path.display().to_string()))
+    })?;
+    Ok(snapshot)
+}
+
+fn dump_table(conn: &PgConnection, table: &Table, path: &Path) -> Result<usize, anyhow::Error> {
+    #[derive(QueryableByName)]
+    struct Row {
+        #[sql_type = "BigInt"]
+        vid: i64,
+        #[sql_type = "Text"]
+        row: String,
+    }
+
+    let query = format!(
+        "select vid, row_to_json(t)::text as row from {} t \
+          where vid > $1 order by vid limit {}",
+        table.qualified_name, BATCH_SIZE
+    );
+
+    let mut out = BufWriter::new(File::create(path)?);
+    let mut count = 0;
+    let mut last_vid = -1;
+    loop {
+        let rows = sql_query(&query)
+            .bind::<BigInt, _>(last_vid)
+            .load::<Row>(conn)?;
+        for row in &rows {
+            writeln!(out, "{}", row.row)?;
+        }
+        count += rows.len();
+        match rows.last() {
+            Some(row) if rows.len() == BATCH_SIZE => last_vid = row.vid,
+            _ => break,
+        }
+    }
+    out.flush()?;
+    Ok(count)
+}
+
+/// Fill the freshly created deployment with `layout` from the snapshot in
+/// `source`, and set its metadata to the one from the snapshot
+pub(crate) fn restore(
+    conn: &PgConnection,
+    layout: &Layout,
+    source: &str,
+    snapshot: &Snapshot,
+) -> Result<(), StoreError> {
+    let site = &layout.site;
+    let dir = local_dir(source)?;
+
+    for entry in &snapshot.tables {
+        let table = layout
+            .tables
+            .values()
+            .find(|table| table.name.as_str() == entry.table)
+            .ok_or_else(|| StoreError::UnknownTable(entry.entity_type.clone()))?;
+        let path = dir.join(&entry.file);
+        let rows = conn
+            .transaction(|| load_table(conn, table, &path))
+            .map_err(|e| StoreError::Unknown(e.context(path.display().to_string())))?;
+        if rows != entry.rows {
+            return Err(StoreError::Unknown(anyhow!(
+                "{} should have {} rows, but has {}",
+                path.display(),
+                entry.rows,
+                rows
+            )));
+        }
+    }
+
+    let block = snapshot.block.to_ptr()?;
+    conn.transaction(|| -> Result<(), StoreError> {
+        insert_json(
+            conn,
+            "insert into subgraphs.dynamic_ethereum_contract_data_source(name,
+                    address, abi, start_block, ethereum_block_hash,
+                    ethereum_block_number, deployment, context)
+             select d.name, d.address, d.abi, d.start_block, d.ethereum_block_hash,
+                    d.ethereum_block_number, $2, d.context
+               from json_populate_recordset(
+                      null::subgraphs.dynamic_ethereum_contract_data_source, $1::json) d",
+            &snapshot.data_sources,
+            site,
+        )?;
+        insert_json(
+            conn,
+            "insert into subgraphs.subgraph_error(id, subgraph_id, message,
+                    block_hash, handler, deterministic, block_range)
+             select e.id, $2, e.message, e.block_hash, e.handler, e.deterministic,
+                    e.block_range
+               from json_populate_recordset(null::subgraphs.subgraph_error, $1::json) e",
+            &snapshot.errors,
+            site,
+        )?;
+        for table in &snapshot.account_like {
+            crate::catalog::set_account_like(conn, site, &SqlName::verbatim(table.clone()), true)?;
+        }
+
+        crate::deployment::forward_block_ptr(conn, &site.deployment, &block)?;
+        if let Some(cursor) = &snapshot.firehose_cursor {
+            crate::deployment::update_firehose_cursor(conn, &site.deployment, cursor)?;
+        }
+        crate::deployment::set_entity_count(conn, site, &layout.count_query)?;
+        if snapshot.synced {
+            crate::deployment::set_synced(conn, &site.deployment)?;
+        }
+        Ok(())
+    })
+}
+
+fn load_table(conn: &PgConnection, table: &Table, path: &Path) -> Result<usize, anyhow::Error> {
+    let query = format!(
+        "insert into {table} select * from json_populate_recordset(null::{table}, $1::json)",
+        table = table.qualified_name
+    );
+    let insert = |batch: &mut Vec<String>| -> Result<(), anyhow::Error> {
+        if !batch.is_empty() {
+            sql_query(&query)
+                .bind::<Text, _>(format!("[{}]", batch.join(",")))
+                .execute(conn)?;
+            batch.clear();
+        }
+        Ok(())
+    };
+
+    let mut count = 0;
+    let mut batch = Vec::with_capacity(BATCH_SIZE);
+    for line in BufReader::new(File::open(path)?).lines() {
+        let line = line?;
+        if line.is_empty() {
+            continue;
+        }
+        batch.push(line);
+        count += 1;
+        if batch.len() == BATCH_SIZE {
+            insert(&mut batch)?;
+        }
+    }
+    insert(&mut batch)?;
+
+    reset_vid_sequence(conn, table)?;
+    Ok(count)
+}
+
+/// Make sure that rows that are added to `table` later get a `vid` that
+/// is larger than that of all rows that are in it now
+pub(crate) fn reset_vid_sequence(conn: &PgConnection, table: &Table) -> Result<(), StoreError> {
+    sql_query(&format!(
+        "select setval(pg_get_serial_sequence('{table}', 'vid'), coalesce(max(vid), 0) + 1, false) \
+           from {table}",
+        table = table.qualified_name
+    ))
+    .execute(conn)?;
+    Ok(())
+}
+
+fn insert_json(
+    conn: &PgConnection,
+    query: &str,
+    rows: &[serde_json::Value],
+    site: &Site,
+) -> Result<(), StoreError> {
+    if rows.is_empty() {
+        return Ok(());
+    }
+    let rows = serde_json::to_string(rows).map_err(|e| StoreError::Unknown(e.into()))?;
+    sql_query(query)
+        .bind::<Text, _>(rows)
+        .bind::<Text, _>(site.deployment.as_str())
+        .execute(conn)?;
+    Ok(())
+}
+
+#[test]
+fn snapshot_blocks_roundtrip() {
+    let ptr = BlockPtr::from((vec![7u8; 32], 42));
+    let block = SnapshotBlock::from(&ptr);
+    assert_eq!(42, block.number);
+    assert_eq!(ptr, block.to_ptr().unwrap());
+
+    assert!(local_dir("s3://bucket/snapshot").is_err());
+    assert!(local_dir("/var/lib/snapshots/one").is_ok());
+}
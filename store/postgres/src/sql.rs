@@ -0,0 +1,483 @@
+//! Run SQL queries that users write against the entities of a deployment.
+//! Only a small dialect of SQL is accepted: a single `select` statement
+//! that may only read from entity tables, which it refers to by the name
+//! of their entity type, and that may only call the functions in
+//! `FUNCTIONS`. References to entity tables are replaced with a subquery
+//! that only contains the entity versions that are visible at the block we
+//! query at, and that uses the GraphQL names of attributes for the columns.
+//!
+//! Queries run in a read-only transaction, are refused if Postgres
+//! estimates that they are too expensive, and may only return a limited
+//! number of rows
+use diesel::pg::PgConnection;
+use diesel::sql_types::{Double, Text};
+use diesel::{connection::SimpleConnection, sql_query, Connection, RunQueryDsl};
+use graph::data::subgraph::schema::POI_OBJECT;
+use graph::prelude::{
+    lazy_static, serde_json, BlockNumber, QueryExecutionError, StoreError, ENV_VARS,
+};
+
+use crate::block_range::{BLOCK_COLUMN, BLOCK_RANGE_COLUMN};
+use crate::relational::{Layout, Table};
+
+lazy_static! {
+    static ref STATEMENT_TIMEOUT: Option<String> = ENV_VARS
+        .graphql
+        .sql_statement_timeout
+        .map(|duration| format!("set local statement_timeout={}", duration.as_millis()));
+}
+
+/// The functions that queries may call
+const FUNCTIONS: &[&str] = &[
+    "abs",
+    "avg",
+    "bit_length",
+    "ceil",
+    "coalesce",
+    "count",
+    "date_part",
+    "date_trunc",
+    "floor",
+    "greatest",
+    "least",
+    "length",
+    "lower",
+    "max",
+    "min",
+    "nullif",
+    "octet_length",
+    "percentile_cont",
+    "percentile_disc",
+    "round",
+    "stddev",
+    "substr",
+    "sum",
+    "to_timestamp",
+    "trunc",
+    "upper",
+    "variance",
+];
+
+/// Words that may be followed by an opening parenthesis without being a
+/// function call
+const KEYWORDS: &[&str] = &[
+    "all", "and", "any", "as", "between", "by", "case", "cast", "else", "exists", "filter", "from",
+    "having", "in", "is", "join", "like", "ilike", "not", "on", "or", "over", "select", "then",
+    "using", "when", "where", "within",
+];
+
+/// Words that can not appear anywhere in a query
+const FORBIDDEN: &[&str] = &[
+    "alter", "call", "copy", "create", "delete", "do", "drop", "execute", "for", "grant", "insert",
+    "into", "lateral", "lock", "merge", "notify", "prepare", "revoke", "set", "table", "truncate",
+    "update", "values", "with",
+];
+
+/// Words that end the list of tables in a `from` clause
+const END_OF_FROM: &[&str] = &[
+    "where",
+    "group",
+    "having",
+    "order",
+    "limit",
+    "offset",
+    "union",
+    "intersect",
+    "except",
+    "on",
+    "using",
+    "window",
+];
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    /// A keyword or an unquoted identifier, lowercased
+    Word(String),
+    /// A double-quoted identifier, without the quotes
+    Quoted(String),
+    /// A string literal, with its quotes
+    Str(String),
+    Number(String),
+    Punct(String),
+}
+
+impl Token {
+    fn is_word(&self, word: &str) -> bool {
+        matches!(self, Token::Word(w) if w == word)
+    }
+
+    fn is_punct(&self, punct: &str) -> bool {
+        matches!(self, Token::Punct(p) if p == punct)
+    }
+
+    fn to_sql(&self) -> String {
+        match self {
+            Token::Word(s) | Token::Str(s) | Token::Number(s) | Token::Punct(s) => s.clone(),
+            Token::Quoted(s) => quote(s),
+        }
+    }
+}
+
+fn invalid(msg: impl Into<String>) -> QueryExecutionError {
+    QueryExecutionError::InvalidSql(msg.into())
+}
+
+fn quote(ident: &str) -> String {
+    format!("\"{}\"", ident.replace('"', "\"\""))
+}
+
+fn tokenize(sql: &str) -> Result<Vec<Token>, QueryExecutionError> {
+    let chars: Vec<char> = sql.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let start = i;
+        if c.is_whitespace() {
+            i += 1;
+        } else if c.is_alphabetic() || c == '_' {
+            while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_') {
+                i += 1;
+            }
+            let word: String = chars[start..i].iter().collect();
+            tokens.push(Token::Word(word.to_lowercase()));
+        } else if c.is_ascii_digit() {
+            while i < chars.len() && (chars[i].is_ascii_digit() || chars[i] == '.') {
+                i += 1;
+            }
+            tokens.push(Token::Number(chars[start..i].iter().collect()));
+        } else if c == '"' || c == '\'' {
+            // Both kinds of quotes are escaped by doubling them
+            let mut text = String::new();
+            i += 1;
+            loop {
+                match chars.get(i) {
+                    None => return Err(invalid("unterminated quoted string or identifier")),
+                    Some(&q) if q == c && chars.get(i + 1) == Some(&c) => {
+                        text.push(c);
+                        i += 2;
+                    }
+                    Some(&q) if q == c => {
+                        i += 1;
+                        break;
+                    }
+                    Some('\\') => return Err(invalid("backslashes are not allowed in strings")),
+                    Some(&q) => {
+                        text.push(q);
+                        i += 1;
+                    }
+                }
+            }
+            if c == '"' {
+                tokens.push(Token::Quoted(text));
+            } else {
+                tokens.push(Token::Str(format!("'{}'", text.replace('\'', "''"))));
+            }
+        } else {
+            let two: String = chars[i..(i + 2).min(chars.len())].iter().collect();
+            match two.as_str() {
+                "--" | "/*" => return Err(invalid("comments are not allowed")),
+                "<=" | ">=" | "<>" | "!=" | "::" | "||" => {
+                    tokens.push(Token::Punct(two));
+                    i += 2;
+                }
+                _ => match c {
+                    '(' | ')' | ',' | '.' | '*' | '+' | '-' | '/' | '%' | '<' | '>' | '=' => {
+                        tokens.push(Token::Punct(c.to_string()));
+                        i += 1;
+                    }
+                    ';' => return Err(invalid("only a single statement is allowed")),
+                    _ => return Err(invalid(format!("unexpected character `{}`", c))),
+                },
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+/// The subquery that replaces references to `table`
+fn table_subquery(table: &Table, block: BlockNumber) -> String {
+    let columns = table
+        .columns
+        .iter()
+        .filter(|column| !column.is_fulltext())
+        .map(|column| {
+            format!(
+                "{} as {}",
+                quote(column.name.as_str()),
+                quote(&column.field)
+            )
+        })
+        .collect::<Vec<_>>()
+        .join(", ");
+    let visible = if table.immutable {
+        format!("{} <= {}", quote(BLOCK_COLUMN), block)
+    } else {
+        format!("{} @> {}", BLOCK_RANGE_COLUMN, block)
+    };
+    format!(
+        "(select {} from {} where {})",
+        columns,
+        table.source(),
+        visible
+    )
+}
+
+/// Check that `tokens` contain no forbidden words and only call the
+/// functions in `FUNCTIONS`
+fn check(tokens: &[Token]) -> Result<(), QueryExecutionError> {
+    for (i, token) in tokens.iter().enumerate() {
+        let is_call = tokens.get(i + 1).map_or(false, |next| next.is_punct("("));
+        match token {
+            Token::Word(word) => {
+                if FORBIDDEN.contains(&word.as_str()) {
+                    return Err(invalid(format!("`{}` is not allowed", word)));
+                }
+                if is_call
+                    && !KEYWORDS.contains(&word.as_str())
+                    && !FUNCTIONS.contains(&word.as_str())
+                {
+                    return Err(invalid(format!("the function `{}` is not allowed", word)));
+                }
+            }
+            // Quoting a function name would otherwise sneak it past the
+            // check above
+            Token::Quoted(ident) if is_call => {
+                return Err(invalid(format!(
+                    "the function `{}` is not allowed",
+                    quote(ident)
+                )));
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// Check that `sql` only uses what we allow and replace references to
+/// entity tables with subqueries for the entities visible at `block`
+fn rewrite(layout: &Layout, sql: &str, block: BlockNumber) -> Result<String, QueryExecutionError> {
+    let tokens = tokenize(sql)?;
+    check(&tokens)?;
+    if !tokens
+        .first()
+        .map_or(false, |token| token.is_word("select"))
+    {
+        return Err(invalid("queries must start with `select`"));
+    }
+
+    let find_table = |token: &Token| -> Option<&Table> {
+        let matches = |name: &str| match token {
+            Token::Word(word) => name.to_lowercase() == *word,
+            Token::Quoted(ident) => name == ident,
+            _ => false,
+        };
+        layout
+            .tables
+            .values()
+            .filter(|table| table.object != *POI_OBJECT)
+            .find(|table| matches(table.object.as_str()))
+            .map(|table| table.as_ref())
+    };
+
+    let mut out = Vec::with_capacity(tokens.len());
+    // The parenthesis depths at which we are in the list of tables of a
+    // `from` clause
+    let mut from_lists: Vec<usize> = Vec::new();
+    let mut depth = 0;
+    let mut expect_table = false;
+    for (i, token) in tokens.iter().enumerate() {
+        let next = tokens.get(i + 1);
+        if expect_table {
+            expect_table = false;
+            if !token.is_punct("(") {
+                let table = find_table(token)
+                    .ok_or_else(|| invalid(format!("unknown entity type `{}`", token.to_sql())))?;
+                if next.map_or(false, |next| next.is_punct(".")) {
+                    return Err(invalid("tables must be referred to by their entity type"));
+                }
+                out.push(table_subquery(table, block));
+                // Make it possible to refer to the table by its entity type
+                // unless the query gives it an alias
+                let aliased = match next {
+                    Some(Token::Quoted(_)) => true,
+                    Some(Token::Word(word)) => {
+                        word == "as"
+                            || !(END_OF_FROM.contains(&word.as_str())
+                                || ["join", "left", "right", "inner", "outer", "full", "cross"]
+                                    .contains(&word.as_str()))
+                    }
+                    _ => false,
+                };
+                if !aliased {
+                    out.push(format!("as {}", quote(table.object.as_str())));
+                }
+                continue;
+            }
+        }
+
+        match token {
+            Token::Word(word) if word == "from" || word == "join" => {
+                if word == "from" {
+                    from_lists.push(depth);
+                }
+                expect_table = true;
+            }
+            Token::Word(word) if END_OF_FROM.contains(&word.as_str()) => {
+                if from_lists.last() == Some(&depth) {
+                    from_lists.pop();
+                }
+            }
+            Token::Punct(p) if p == "," && from_lists.last() == Some(&depth) => {
+                expect_table = true;
+            }
+            Token::Punct(p) if p == "(" => depth += 1,
+            Token::Punct(p) if p == ")" => {
+                if from_lists.last() == Some(&depth) {
+                    from_lists.pop();
+                }
+                if depth == 0 {
+                    return Err(invalid("unbalanced parentheses"));
+                }
+                depth -= 1;
+            }
+            _ => {}
+        }
+        out.push(token.to_sql());
+    }
+    if expect_table {
+        return Err(invalid("missing table after `from` or `join`"));
+    }
+    if depth != 0 {
+        return Err(invalid("unbalanced parentheses"));
+    }
+    Ok(out.join(" "))
+}
+
+/// Run the query `sql` against the entities in `layout` as of `block` and
+/// return the rows it produces as JSON objects
+pub(crate) fn execute(
+    conn: &PgConnection,
+    layout: &Layout,
+    sql: &str,
+    block: BlockNumber,
+) -> Result<Vec<serde_json::Value>, QueryExecutionError> {
+    #[derive(QueryableByName)]
+    struct Plan {
+        #[sql_type = "Text"]
+        plan: String,
+    }
+
+    #[derive(QueryableByName)]
+    struct Rows {
+        #[sql_type = "Text"]
+        rows: String,
+    }
+
+    #[derive(QueryableByName)]
+    struct Cost {
+        #[sql_type = "Double"]
+        cost: f64,
+    }
+
+    let max_rows = ENV_VARS.graphql.sql_max_rows;
+    let max_cost = ENV_VARS.graphql.sql_max_cost;
+    let query = format!(
+        "select coalesce(json_agg(r), '[]')::text as rows \
+           from (select * from ({}) q limit {}) r",
+        rewrite(layout, sql, block)?,
+        max_rows + 1
+    );
+
+    // The transaction only reads, and there is nothing to commit; the
+    // inner result tells us whether the query was too expensive to run
+    let rows = conn
+        .transaction::<_, StoreError, _>(|| {
+            conn.batch_execute("set transaction read only")?;
+            if let Some(ref timeout_sql) = *STATEMENT_TIMEOUT {
+                conn.batch_execute(timeout_sql)?;
+            }
+
+            let plan = sql_query(format!("explain (format json) {}", query))
+                .get_result::<Plan>(conn)?
+                .plan;
+            let cost =
+                sql_query("select ($1::json -> 0 -> 'Plan' ->> 'Total Cost')::float8 as cost")
+                    .bind::<Text, _>(plan)
+                    .get_result::<Cost>(conn)?
+                    .cost;
+            if cost > max_cost as f64 {
+                return Ok(Err(QueryExecutionError::SqlTooExpensive(
+                    cost as u64,
+                    max_cost,
+                )));
+            }
+
+            Ok(Ok(sql_query(&query).get_result::<Rows>(conn)?.rows))
+        })
+        .map_err(QueryExecutionError::from)??;
+
+    let rows: Vec<serde_json::Value> =
+        serde_json::from_str(&rows).map_err(|e| invalid(e.to_string()))?;
+    if rows.len() > max_rows {
+        return Err(QueryExecutionError::TooManyRows(max_rows));
+    }
+    Ok(rows)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tokenizes() {
+        let tokens = tokenize("select \"eyeColor\", 'it''s' from Person where age>=21").unwrap();
+        assert_eq!(
+            vec![
+                Token::Word("select".to_string()),
+                Token::Quoted("eyeColor".to_string()),
+                Token::Punct(",".to_string()),
+                Token::Str("'it''s'".to_string()),
+                Token::Word("from".to_string()),
+                Token::Word("person".to_string()),
+                Token::Word("where".to_string()),
+                Token::Word("age".to_string()),
+                Token::Punct(">=".to_string()),
+                Token::Number("21".to_string()),
+            ],
+            tokens
+        );
+
+        assert!(tokenize("select 1; drop table person").is_err());
+        assert!(tokenize("select 1 -- comment").is_err());
+        assert!(tokenize("select 'unterminated").is_err());
+        assert!(tokenize("select E'\\''").is_err());
+    }
+
+    fn checks(sql: &str) -> Result<(), QueryExecutionError> {
+        check(&tokenize(sql)?)
+    }
+
+    #[test]
+    fn allows_listed_functions() {
+        assert!(checks("select count(*), \"eyeColor\" from Person where age in (1, 2)").is_ok());
+        assert!(checks("select lower(name) from \"Person\" p").is_ok());
+    }
+
+    #[test]
+    fn rejects_bypasses() {
+        // Unlisted functions, whether quoted or not
+        assert!(checks("select pg_read_file('/etc/passwd')").is_err());
+        assert!(checks("select \"pg_read_file\"('/etc/passwd')").is_err());
+        assert!(checks("select pg_catalog.\"pg_read_file\"('/etc/passwd')").is_err());
+        assert!(checks("select \"lower\"(name) from Person").is_err());
+        // Statements that read from something other than entity tables
+        assert!(checks("select * from (table pg_authid) t").is_err());
+        assert!(checks("select * from (values (1), (2)) v").is_err());
+        assert!(checks("select * from Person where id in (values ('a'))").is_err());
+        assert!(checks("with t as (select * from pg_authid) select * from t").is_err());
+        assert!(
+            checks("select * from Person where exists (with x as (select 1) select 1)").is_err()
+        );
+    }
+}
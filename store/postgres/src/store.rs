@@ -6,13 +6,14 @@ use graph::{
         server::index_node::VersionInfo,
         store::{
             BlockStore as BlockStoreTrait, QueryStoreManager, StatusStore, Store as StoreTrait,
+            SubgraphStore as _,
         },
     },
     constraint_violation,
-    data::subgraph::status,
+    data::subgraph::{status, SubgraphDeprecation},
     prelude::{
         tokio, web3::types::Address, BlockNumber, BlockPtr, CheapClone, DeploymentHash,
-        PartialBlockPtr, QueryExecutionError, StoreError,
+        PartialBlockPtr, QueryExecutionError, StoreError, SubgraphName,
     },
 };
 
@@ -94,6 +95,20 @@ impl QueryStoreManager for Store {
 
         Ok(Arc::new(QueryStore::new(store, chain_store, site, replica)))
     }
+
+    async fn deprecation(
+        &self,
+        name: &SubgraphName,
+    ) -> Result<Option<SubgraphDeprecation>, QueryExecutionError> {
+        let store = self.subgraph_store.cheap_clone();
+        let name = name.clone();
+        graph::spawn_blocking_allow_panic(move || {
+            store.deprecation(&name).map_err(QueryExecutionError::from)
+        })
+        .await
+        .map_err(|e| QueryExecutionError::Panic(e.to_string()))
+        .and_then(|x| x)
+    }
 }
 
 #[async_trait]
@@ -110,6 +125,14 @@ impl StatusStore for Store {
         Ok(infos)
     }
 
+    fn chains(&self) -> Result<Vec<status::ChainStatus>, StoreError> {
+        self.block_store.chain_statuses()
+    }
+
+    fn jobs(&self, filter: status::JobFilter) -> Result<Vec<status::JobInfo>, StoreError> {
+        self.subgraph_store.jobs(filter)
+    }
+
     fn version_info(&self, version_id: &str) -> Result<VersionInfo, StoreError> {
         let mut info = self.subgraph_store.version_info(version_id)?;
 
@@ -5,45 +5,52 @@ use diesel::{
     types::{FromSql, ToSql},
 };
 use std::{
-    collections::HashMap,
+    collections::{BTreeMap, HashMap, HashSet},
     sync::{Arc, Mutex},
 };
-use std::{fmt, io::Write};
+use std::{fmt, io::Write, path::PathBuf, str::FromStr};
 use std::{iter::FromIterator, time::Duration};
 
 use graph::{
     cheap_clone::CheapClone,
     components::{
         server::index_node::VersionInfo,
-        store::{self, BlockStore, DeploymentLocator, EnsLookup as EnsLookupTrait, SubgraphFork},
+        store::{
+            self, fork, BlockStore, DeploymentLocator, EnsLookup as EnsLookupTrait,
+            FileRegistry as FileRegistryTrait, JobRequest, SubgraphFork,
+        },
+        webhooks::{LifecycleEvent, Webhooks},
     },
     constraint_violation,
     data::query::QueryTarget,
-    data::subgraph::{schema::DeploymentCreate, status},
+    data::schema_diff::{self, SchemaCompatibility},
+    data::subgraph::{schema::DeploymentCreate, status, SubgraphDeprecation},
     prelude::StoreEvent,
     prelude::{
-        anyhow, futures03::future::join_all, lazy_static, o, web3::types::Address, ApiSchema,
-        BlockHash, BlockNumber, BlockPtr, ChainStore, DeploymentHash, EntityOperation, Logger,
-        MetricsRegistry, NodeId, PartialBlockPtr, Schema, StoreError, SubgraphName,
-        SubgraphStore as SubgraphStoreTrait, SubgraphVersionSwitchingMode,
+        anyhow, chrono, futures03::future::join_all, lazy_static, o, web3::types::Address,
+        ApiSchema, BlockHash, BlockNumber, BlockPtr, ChainStore, DeploymentHash, EntityOperation,
+        Logger, MetricsRegistry, NodeId, PartialBlockPtr, Schema, StoreError, SubgraphName,
+        SubgraphStore as SubgraphStoreTrait, SubgraphVersionSwitchingMode, ENV_VARS,
     },
     url::Url,
     util::timed_cache::TimedCache,
 };
 
-use crate::fork;
 use crate::{
     connection_pool::ConnectionPool,
     primary,
     primary::{DeploymentId, Mirror as PrimaryMirror, Site},
     relational::Layout,
+    snapshot::Snapshot,
     writable::WritableStore,
     NotificationSender,
 };
 use crate::{
     deployment_store::{DeploymentStore, ReplicaId},
     detail::DeploymentDetail,
-    primary::UnusedDeployment,
+    maintenance,
+    primary::{DeploymentLoad, UnusedDeployment},
+    rebalance::{self, Move},
 };
 
 /// The name of a database shard; valid names must match `[a-z0-9_]+`
@@ -58,6 +65,11 @@ lazy_static! {
 /// How long to cache information about a deployment site
 const SITES_CACHE_TTL: Duration = Duration::from_secs(120);
 
+/// How long to cache the deprecation notices of subgraph names. Queries
+/// through a name look its notice up, and notices that are set on a
+/// different node are only noticed once this expires
+const DEPRECATIONS_CACHE_TTL: Duration = Duration::from_secs(60);
+
 impl Shard {
     pub fn new(name: String) -> Result<Self, StoreError> {
         if name.is_empty() {
@@ -116,6 +128,79 @@ impl ToSql<Text, Pg> for Shard {
 pub trait DeploymentPlacer {
     fn place(&self, name: &str, network: &str)
         -> Result<Option<(Vec<Shard>, Vec<NodeId>)>, String>;
+
+    /// How a new version of the subgraph `name` replaces its current
+    /// version. The default is to use the node's version switching mode
+    /// and to remove the previous version
+    fn version_policy(&self, _name: &str, _network: &str) -> Result<VersionPolicy, String> {
+        Ok(VersionPolicy::default())
+    }
+}
+
+/// How a new version of a subgraph replaces its current version
+#[derive(Copy, Clone, Debug, Default)]
+pub struct VersionPolicy {
+    /// When the new version becomes the current version; if this is not
+    /// set, the node's version switching mode is used
+    pub mode: Option<SubgraphVersionSwitchingMode>,
+    /// When the new version becomes the current version if its schema
+    /// breaks queries against the current version; if this is not set,
+    /// `mode` is used
+    pub breaking_mode: Option<SubgraphVersionSwitchingMode>,
+    pub previous: PreviousVersion,
+}
+
+/// What happens to the current version of a subgraph once a new version
+/// replaces it
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum PreviousVersion {
+    /// Unassign the previous version unless another subgraph uses it
+    Remove,
+    /// Keep the previous version assigned and indexing until yet another
+    /// version replaces the current version
+    Retain,
+}
+
+impl Default for PreviousVersion {
+    fn default() -> Self {
+        PreviousVersion::Remove
+    }
+}
+
+impl FromStr for PreviousVersion {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "remove" => Ok(PreviousVersion::Remove),
+            "retain" => Ok(PreviousVersion::Retain),
+            _ => Err(format!("invalid previous version policy: {:?}", s)),
+        }
+    }
+}
+
+/// What a node is used for. Index nodes run the block streams for the
+/// deployments that are assigned to them; query nodes only serve queries
+/// and never index anything
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum NodeRole {
+    Index,
+    Query,
+}
+
+impl NodeRole {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            NodeRole::Index => "index-node",
+            NodeRole::Query => "query-node",
+        }
+    }
+}
+
+impl fmt::Display for NodeRole {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
 }
 
 /// Tools for managing unused deployments
@@ -208,8 +293,12 @@ impl SubgraphStore {
     /// pool. One of the shards must be named `primary`
     ///
     /// The `placer` determines where `create_subgraph_deployment` puts a new deployment
+    ///
+    /// `node` is the node this store runs on; writable stores claim the
+    /// deployments they write to for it
     pub fn new(
         logger: &Logger,
+        node: NodeId,
         stores: Vec<(Shard, ConnectionPool, Vec<ConnectionPool>, Vec<usize>)>,
         placer: Arc<dyn DeploymentPlacer + Send + Sync + 'static>,
         sender: Arc<NotificationSender>,
@@ -218,7 +307,7 @@ impl SubgraphStore {
     ) -> Self {
         Self {
             inner: Arc::new(SubgraphStoreInner::new(
-                logger, stores, placer, sender, registry,
+                logger, node, stores, placer, sender, registry,
             )),
             fork_base,
         }
@@ -258,6 +347,8 @@ impl std::ops::Deref for SubgraphStore {
 }
 
 pub struct SubgraphStoreInner {
+    /// The node this store runs on
+    node: NodeId,
     mirror: PrimaryMirror,
     stores: HashMap<Shard, Arc<DeploymentStore>>,
     /// Cache for the mapping from deployment id to shard/namespace/id. Only
@@ -267,10 +358,16 @@ pub struct SubgraphStoreInner {
     /// different deployment for the same hash propagate across different
     /// graph-node processes over time.
     sites: TimedCache<DeploymentHash, Site>,
+    /// All deprecation notices, by subgraph name
+    deprecations: TimedCache<(), HashMap<String, SubgraphDeprecation>>,
     placer: Arc<dyn DeploymentPlacer + Send + Sync + 'static>,
     sender: Arc<NotificationSender>,
     writables: Mutex<HashMap<DeploymentId, Arc<WritableStore>>>,
     registry: Arc<dyn MetricsRegistry>,
+    webhooks: Webhooks,
+    /// The deployments that were queried since query activity was last
+    /// recorded in the primary
+    queried: Mutex<HashSet<DeploymentId>>,
 }
 
 impl SubgraphStoreInner {
@@ -290,6 +387,7 @@ impl SubgraphStoreInner {
     /// The `placer` determines where `create_subgraph_deployment` puts a new deployment
     pub fn new(
         logger: &Logger,
+        node: NodeId,
         stores: Vec<(Shard, ConnectionPool, Vec<ConnectionPool>, Vec<usize>)>,
         placer: Arc<dyn DeploymentPlacer + Send + Sync + 'static>,
         sender: Arc<NotificationSender>,
@@ -319,17 +417,41 @@ impl SubgraphStoreInner {
             },
         ));
         let sites = TimedCache::new(SITES_CACHE_TTL);
+        let webhooks = Webhooks::from_env(logger);
         SubgraphStoreInner {
+            node,
             mirror,
             stores,
             sites,
+            deprecations: TimedCache::new(DEPRECATIONS_CACHE_TTL),
             placer,
             sender,
             writables: Mutex::new(HashMap::new()),
             registry,
+            webhooks,
+            queried: Mutex::new(HashSet::new()),
         }
     }
 
+    /// The node this store runs on
+    pub(crate) fn node(&self) -> &NodeId {
+        &self.node
+    }
+
+    /// Whether we can connect to the main database of each shard
+    pub fn check_shards(&self) -> BTreeMap<String, bool> {
+        self.stores
+            .iter()
+            .map(|(shard, store)| (shard.to_string(), store.check_connection()))
+            .collect()
+    }
+
+    /// Tell whoever wants to know about `event`. Must only be called once
+    /// the change that caused the event has been committed
+    pub(crate) fn notify(&self, deployment: &DeploymentHash, event: LifecycleEvent) {
+        self.webhooks.notify(deployment, event)
+    }
+
     // Only needed for tests
     #[cfg(debug_assertions)]
     pub(crate) fn clear_caches(&self) {
@@ -368,7 +490,7 @@ impl SubgraphStoreInner {
         Ok(site)
     }
 
-    fn find_site(&self, id: DeploymentId) -> Result<Arc<Site>, StoreError> {
+    pub(crate) fn find_site(&self, id: DeploymentId) -> Result<Arc<Site>, StoreError> {
         if let Some(site) = self.sites.find(|site| site.id == id) {
             return Ok(site);
         }
@@ -512,6 +634,7 @@ impl SubgraphStoreInner {
             (site, node_id)
         };
         let site = Arc::new(site);
+        let compatibility = self.schema_compatibility(&name, schema)?;
 
         let graft_base = deployment
             .graft_base
@@ -535,6 +658,7 @@ impl SubgraphStoreInner {
             site.clone(),
             graft_base,
             replace,
+            compatibility,
         )?;
 
         let exists_and_synced = |id: &DeploymentHash| {
@@ -544,18 +668,58 @@ impl SubgraphStoreInner {
 
         // FIXME: This simultaneously holds a `primary_conn` and a shard connection, which can
         // potentially deadlock.
+        let policy = self
+            .placer
+            .version_policy(name.as_str(), &site.network)
+            .map_err(|msg| constraint_violation!("illegal version policy: {}", msg))?;
+        let mode = match compatibility {
+            Some(SchemaCompatibility::Breaking) => policy.breaking_mode.or(policy.mode),
+            _ => policy.mode,
+        }
+        .unwrap_or(mode);
+
+        let created = LifecycleEvent::DeploymentCreated {
+            subgraph: name.to_string(),
+            node: node_id.to_string(),
+            network: site.network.clone(),
+        };
         let pconn = self.primary_conn()?;
         pconn.transaction(|| -> Result<_, StoreError> {
             // Create subgraph, subgraph version, and assignment
-            let changes =
-                pconn.create_subgraph_version(name, &site, node_id, mode, exists_and_synced)?;
+            let changes = pconn.create_subgraph_version(
+                name,
+                &site,
+                node_id,
+                mode,
+                policy.previous,
+                exists_and_synced,
+            )?;
             let event = StoreEvent::new(changes);
             pconn.send_store_event(&self.sender, &event)?;
             Ok(())
         })?;
+        self.notify(&site.deployment, created);
         Ok(site.as_ref().into())
     }
 
+    /// How `schema` compares to the schema of the current version of
+    /// `name`, or `None` if `name` has no current version yet
+    fn schema_compatibility(
+        &self,
+        name: &SubgraphName,
+        schema: &Schema,
+    ) -> Result<Option<SchemaCompatibility>, StoreError> {
+        let current = match self.mirror.current_deployment(name)? {
+            Some(current) if current != schema.id => current,
+            _ => return Ok(None),
+        };
+        let (store, site) = self.store(&current)?;
+        let current = store.subgraph_info(&site)?.input;
+        Ok(Some(
+            schema_diff::diff(&current.document, &schema.document).compatibility(),
+        ))
+    }
+
     pub fn copy_deployment(
         &self,
         src: &DeploymentLocator,
@@ -621,6 +785,7 @@ impl SubgraphStoreInner {
             dst.clone(),
             Some(graft_base),
             false,
+            src_store.schema_compatibility(src.as_ref())?,
         )?;
 
         let pconn = self.primary_conn()?;
@@ -647,6 +812,200 @@ impl SubgraphStoreInner {
         Ok(())
     }
 
+    /// Return the changes that `deployment` made in `block`. Unlike
+    /// `entity_changes_in_block`, this also works for copies of a
+    /// deployment that are not active
+    pub fn deployment_changes_in_block(
+        &self,
+        deployment: &DeploymentLocator,
+        block: BlockNumber,
+    ) -> Result<Vec<EntityOperation>, StoreError> {
+        let site = self.find_site(deployment.id.into())?;
+        self.for_site(site.as_ref())?.get_changes(site, block)
+    }
+
+    /// Write a snapshot of `deployment` into the directory `destination`
+    pub fn snapshot(
+        &self,
+        deployment: &DeploymentLocator,
+        destination: &str,
+    ) -> Result<Snapshot, StoreError> {
+        let site = self.find_site(deployment.id.into())?;
+        self.for_site(site.as_ref())?.snapshot(site, destination)
+    }
+
+    /// Recreate the deployment from the snapshot in the directory `source`
+    /// in `shard` and assign it to `node`. The deployment must not exist
+    /// yet in any shard
+    pub fn restore(
+        &self,
+        source: &str,
+        shard: Shard,
+        node: NodeId,
+    ) -> Result<(Snapshot, DeploymentLocator), StoreError> {
+        let snapshot = Snapshot::read(source)?;
+        let hash = DeploymentHash::new(snapshot.deployment.clone()).map_err(|hash| {
+            StoreError::Unknown(anyhow!("invalid deployment hash `{}` in snapshot", hash))
+        })?;
+        if !self.locators(hash.as_str())?.is_empty() {
+            return Err(StoreError::Unknown(anyhow!(
+                "can not restore deployment {} since it already exists",
+                hash
+            )));
+        }
+        let schema = Schema::parse(&snapshot.schema, hash.clone()).map_err(StoreError::Unknown)?;
+
+        let deployment_store = self
+            .stores
+            .get(&shard)
+            .ok_or_else(|| StoreError::UnknownShard(shard.to_string()))?;
+        let site = Arc::new(self.primary_conn()?.allocate_site(
+            shard,
+            &hash,
+            snapshot.network.clone(),
+        )?);
+        let restored = snapshot.deployment_create().and_then(|create| {
+            deployment_store.create_deployment(&schema, create, site.clone(), None, false, None)?;
+            deployment_store.restore(site.clone(), source, &snapshot)
+        });
+        if let Err(e) = restored {
+            // Remove what we restored so far so that the restore can simply
+            // be run again once the problem is fixed
+            deployment_store.drop_deployment(&site)?;
+            self.primary_conn()?.drop_site(site.as_ref())?;
+            return Err(e);
+        }
+
+        // As with copies, assigning the deployment is the very last thing
+        // we do so that a partially restored deployment never gets indexed
+        let pconn = self.primary_conn()?;
+        pconn.transaction(|| -> Result<_, StoreError> {
+            let changes = pconn.assign_subgraph(site.as_ref(), &node)?;
+            let event = StoreEvent::new(changes);
+            pconn.send_store_event(&self.sender, &event)?;
+            Ok(())
+        })?;
+        Ok((snapshot, site.as_ref().into()))
+    }
+
+    /// Turn on the change journal of `deployment` and have `node` write
+    /// it, wherever the deployment is assigned. Return `false` if it was
+    /// already on
+    pub fn start_change_journal(
+        &self,
+        deployment: &DeploymentLocator,
+        node: &NodeId,
+    ) -> Result<bool, StoreError> {
+        let site = self.find_site(deployment.id.into())?;
+        self.for_site(site.as_ref())?
+            .start_change_journal(site.as_ref(), node)
+    }
+
+    /// Turn off the change journal of `deployment`. Return `false` if it
+    /// was not on
+    pub fn stop_change_journal(&self, deployment: &DeploymentLocator) -> Result<bool, StoreError> {
+        let site = self.find_site(deployment.id.into())?;
+        self.for_site(site.as_ref())?
+            .stop_change_journal(site.as_ref())
+    }
+
+    /// Partition the large tables of `deployment` by block range, or add
+    /// partitions to the ones that already are. See
+    /// `DeploymentStore::partition`
+    pub fn partition(
+        &self,
+        deployment: &DeploymentLocator,
+        blocks: BlockNumber,
+        min_rows: i64,
+    ) -> Result<Vec<String>, StoreError> {
+        let site = self.find_site(deployment.id.into())?;
+        self.for_site(site.as_ref())?
+            .partition(site, blocks, min_rows)
+    }
+
+    /// Drop the partitions of `deployment` that only contain history
+    /// from before block `before`
+    pub fn prune_partitions(
+        &self,
+        deployment: &DeploymentLocator,
+        before: BlockNumber,
+    ) -> Result<Vec<String>, StoreError> {
+        let site = self.find_site(deployment.id.into())?;
+        self.for_site(site.as_ref())?.prune_partitions(site, before)
+    }
+
+    /// Record a checkpoint of the entities of `deployment` at its current
+    /// block, which it can be rewound to even after its history before
+    /// that block has been pruned. Return the block of the checkpoint
+    pub fn create_checkpoint(
+        &self,
+        deployment: &DeploymentLocator,
+    ) -> Result<BlockPtr, StoreError> {
+        let site = self.find_site(deployment.id.into())?;
+        self.for_site(site.as_ref())?.create_checkpoint(site)
+    }
+
+    /// Add a job that runs `request` against `deployment` to the queue and
+    /// return the id of the job. Index nodes pick up jobs from the queue
+    pub fn enqueue_job(
+        &self,
+        deployment: &DeploymentLocator,
+        request: &JobRequest,
+    ) -> Result<i32, StoreError> {
+        let site = self.find_site(deployment.id.into())?;
+        self.primary_conn()?.enqueue_job(site.as_ref(), request)
+    }
+
+    /// The jobs that match `filter`, newest first
+    pub fn jobs(&self, filter: status::JobFilter) -> Result<Vec<status::JobInfo>, StoreError> {
+        self.primary_conn()?.jobs(&filter)
+    }
+
+    /// Cancel the job `id` and return the state it is in afterwards. A
+    /// running job is only cancelled once the node that runs it notices,
+    /// until then it stays `Running`
+    pub fn cancel_job(&self, id: i32) -> Result<status::JobState, StoreError> {
+        self.primary_conn()?
+            .cancel_job(id)?
+            .ok_or_else(|| StoreError::Unknown(anyhow!("there is no job with id {}", id)))
+    }
+
+    /// Write the next batch of the change journal of every deployment
+    /// whose journal `node` writes into `dir`, journaling only blocks that
+    /// are more than `reorg_threshold` blocks behind the deployment's head.
+    /// Return the outcome for each deployment, `None` meaning that there
+    /// was nothing to write for it
+    pub fn write_change_journals(
+        &self,
+        node: &NodeId,
+        dir: &str,
+        reorg_threshold: BlockNumber,
+    ) -> Result<Vec<(DeploymentHash, Result<Option<PathBuf>, StoreError>)>, StoreError> {
+        let mut written = Vec::new();
+        for store in self.stores.values() {
+            for id in store.change_journals(node)? {
+                let site = self.find_site(id)?;
+                let outcome = store.write_change_journal(site.cheap_clone(), dir, reorg_threshold);
+                written.push((site.deployment.clone(), outcome));
+            }
+        }
+        Ok(written)
+    }
+
+    /// Move `deployment` forward to the block `ptr` with the change
+    /// journal for it in `dir`. Return the number of batches that were
+    /// applied
+    pub fn apply_change_journal(
+        &self,
+        deployment: &DeploymentLocator,
+        dir: &str,
+        ptr: &BlockPtr,
+    ) -> Result<usize, StoreError> {
+        let site = self.find_site(deployment.id.into())?;
+        self.for_site(site.as_ref())?
+            .apply_change_journal(site, dir, ptr)
+    }
+
     // Only for tests to simplify their handling of test fixtures, so that
     // tests can reset the block pointer of a subgraph by recreating it
     #[cfg(debug_assertions)]
@@ -690,6 +1049,7 @@ impl SubgraphStoreInner {
 
         let (store, site) = self.store(&id)?;
         let replica = store.replica_for_query(for_subscription)?;
+        self.queried.lock().unwrap().insert(site.id);
 
         Ok((store.clone(), site, replica))
     }
@@ -772,6 +1132,187 @@ impl SubgraphStoreInner {
         self.primary_conn()?.list_unused_deployments(filter)
     }
 
+    /// Write down in the primary which deployments were queried since the
+    /// last time this was called
+    pub fn record_query_activity(&self) -> Result<(), StoreError> {
+        let ids: Vec<_> = self.queried.lock().unwrap().drain().collect();
+        let res = self
+            .primary_conn()
+            .and_then(|pconn| pconn.record_query_activity(&ids));
+        if res.is_err() {
+            // Try again next time
+            self.queried.lock().unwrap().extend(ids);
+        }
+        res
+    }
+
+    /// Record that `node` is alive and what it is used for
+    pub fn record_heartbeat(&self, node: &NodeId, role: NodeRole) -> Result<(), StoreError> {
+        self.primary_conn()?.record_heartbeat(node, role)
+    }
+
+    /// The nodes that the deployment rules allow `site` to be assigned to,
+    /// or `None` if it can go to any node. Deployments are placed according
+    /// to the name of the first subgraph that uses them
+    fn allowed_nodes(
+        &self,
+        pconn: &primary::Connection,
+        site: &Site,
+    ) -> Result<Option<Vec<NodeId>>, StoreError> {
+        Ok(pconn
+            .subgraphs_using_deployment(site)?
+            .first()
+            .map(|name| self.placer.place(name, &site.network))
+            .transpose()
+            .map_err(|msg| {
+                constraint_violation!("illegal indexer name in deployment rule: {}", msg)
+            })?
+            .flatten()
+            .map(|(_, nodes)| nodes))
+    }
+
+    /// Record the load that `node` measured for the deployments it indexes
+    pub fn record_deployment_load(
+        &self,
+        node: &NodeId,
+        loads: &[DeploymentLoad],
+    ) -> Result<(), StoreError> {
+        self.primary_conn()?.record_deployment_load(node, loads)
+    }
+
+    /// Even out the load across responsive index nodes by moving at most
+    /// `max_moves` deployments away from nodes whose load is more than
+    /// `threshold` above the average. Deployments are only moved to nodes
+    /// that the deployment rules allow for them; in particular, rules with
+    /// a single indexer pin the deployments they match to that indexer.
+    /// With `dry_run`, only return the moves that would be made
+    pub fn rebalance(
+        &self,
+        threshold: f64,
+        max_moves: usize,
+        dry_run: bool,
+    ) -> Result<Vec<Move>, StoreError> {
+        // Nodes report their load every minute; anything much older than
+        // that is from a node that is not indexing the deployment anymore
+        let max_age = chrono::Duration::minutes(10);
+        let liveness = chrono::Duration::from_std(ENV_VARS.node_heartbeat_interval * 3)
+            .map_err(|e| constraint_violation!("invalid heartbeat interval: {}", e))?;
+
+        let pconn = self.primary_conn()?;
+        pconn
+            .transaction(|| -> Result<_, StoreError> {
+                if !pconn.try_lock_rebalance()? {
+                    return Ok(vec![]);
+                }
+
+                let loads: HashMap<_, _> = pconn
+                    .deployment_loads(max_age)?
+                    .into_iter()
+                    .map(|load| (load.deployment, load.handler_secs_per_minute))
+                    .collect();
+                let (nodes, _) = pconn.index_nodes_by_heartbeat(liveness)?;
+
+                let mut sites = HashMap::new();
+                let mut assignments = Vec::new();
+                for node in &nodes {
+                    for site in pconn.assignments(node)? {
+                        assignments.push(rebalance::Assignment {
+                            deployment: site.deployment.clone(),
+                            node: node.clone(),
+                            load: loads.get(&site.deployment).copied().unwrap_or(0.0),
+                            allowed: self.allowed_nodes(&pconn, &site)?,
+                        });
+                        sites.insert(site.deployment.clone(), site);
+                    }
+                }
+
+                let moves = rebalance::plan(&nodes, assignments, threshold, max_moves);
+                if !dry_run {
+                    let mut changes = Vec::new();
+                    for mv in &moves {
+                        changes.extend(pconn.reassign_subgraph(&sites[&mv.deployment], &mv.to)?);
+                    }
+                    if !changes.is_empty() {
+                        pconn.send_store_event(&self.sender, &StoreEvent::new(changes))?;
+                    }
+                }
+                Ok(moves)
+            })
+            .map(|moves| {
+                if !dry_run {
+                    for mv in &moves {
+                        self.notify_reassigned(&mv.deployment, Some(&mv.from), &mv.to);
+                    }
+                }
+                moves
+            })
+    }
+
+    /// Move the deployments that are assigned to index nodes that have not
+    /// sent a heartbeat for at least `timeout` to index nodes that have.
+    /// The new node is chosen from the indexers that the deployment rules
+    /// allow for the deployment, and if the rules allow for any node, from
+    /// all responsive index nodes. Nodes that have never sent a heartbeat
+    /// are never considered unresponsive. Return the hash of each moved
+    /// deployment together with the old and the new node
+    pub fn reassign_from_unresponsive_nodes(
+        &self,
+        timeout: Duration,
+    ) -> Result<Vec<(DeploymentHash, NodeId, NodeId)>, StoreError> {
+        let timeout = chrono::Duration::from_std(timeout)
+            .map_err(|e| constraint_violation!("invalid heartbeat timeout: {}", e))?;
+        let pconn = self.primary_conn()?;
+        pconn
+            .transaction(|| -> Result<_, StoreError> {
+                // Another node is already doing this
+                if !pconn.try_lock_reassignment()? {
+                    return Ok(vec![]);
+                }
+
+                let (responsive, unresponsive) = pconn.index_nodes_by_heartbeat(timeout)?;
+                let mut moves = Vec::new();
+                let mut changes = Vec::new();
+                for node in unresponsive {
+                    for site in pconn.assignments(&node)? {
+                        let allowed = self.allowed_nodes(&pconn, &site)?;
+                        let candidates: Vec<_> = responsive
+                            .iter()
+                            .filter(|candidate| {
+                                allowed
+                                    .as_ref()
+                                    .map_or(true, |nodes| nodes.contains(*candidate))
+                            })
+                            .cloned()
+                            .collect();
+                        if let Some(new_node) = pconn.least_assigned_node(&candidates)? {
+                            changes.extend(pconn.reassign_subgraph(&site, &new_node)?);
+                            moves.push((site.deployment.clone(), node.clone(), new_node));
+                        }
+                    }
+                }
+                if !changes.is_empty() {
+                    pconn.send_store_event(&self.sender, &StoreEvent::new(changes))?;
+                }
+                Ok(moves)
+            })
+            .map(|moves| {
+                for (deployment, from, to) in &moves {
+                    self.notify_reassigned(deployment, Some(from), to);
+                }
+                moves
+            })
+    }
+
+    fn notify_reassigned(&self, deployment: &DeploymentHash, from: Option<&NodeId>, to: &NodeId) {
+        self.notify(
+            deployment,
+            LifecycleEvent::DeploymentReassigned {
+                from: from.map(NodeId::to_string),
+                to: to.to_string(),
+            },
+        )
+    }
+
     /// Remove a deployment, i.e., all its data and metadata. This is only permissible
     /// if the deployment is unused in the sense that it is neither the current nor
     /// pending version of any subgraph, and is not currently assigned to any node
@@ -835,16 +1376,35 @@ impl SubgraphStoreInner {
 
         let by_shard: HashMap<Shard, Vec<Arc<Site>>> = self.deployments_by_shard(sites)?;
 
-        // Go shard-by-shard to look up deployment statuses
+        // Go shard-by-shard to look up deployment statuses. A shard that
+        // is down only affects the deployments it stores
         let mut infos = Vec::new();
         for (shard, sites) in by_shard.into_iter() {
             let store = self
                 .stores
                 .get(&shard)
                 .ok_or_else(|| StoreError::UnknownShard(shard.to_string()))?;
-            infos.extend(store.deployment_statuses(&sites)?);
+            match store.deployment_statuses(&sites) {
+                Ok(statuses) => infos.extend(statuses),
+                Err(StoreError::DatabaseUnavailable) => infos.extend(sites.iter().map(|site| {
+                    status::Info::unavailable(
+                        site.id.into(),
+                        site.deployment.to_string(),
+                        site.network.clone(),
+                    )
+                })),
+                Err(e) => return Err(e),
+            }
         }
         self.mirror.fill_assignments(&mut infos)?;
+
+        // Only the node that indexes a deployment has a writable for it
+        let writables = self.writables.lock().unwrap();
+        for info in &mut infos {
+            if let Some(writable) = writables.get(&DeploymentId::from(info.id)) {
+                info.write_queue = writable.write_queue();
+            }
+        }
         Ok(infos)
     }
 
@@ -904,11 +1464,36 @@ impl SubgraphStoreInner {
         store.error_count(id)
     }
 
+    /// The entity count and estimated size of all deployments, one
+    /// result per shard
+    pub(crate) async fn entity_sizes(
+        &self,
+    ) -> Vec<Result<Vec<(String, u64, Option<u64>)>, StoreError>> {
+        join_all(self.stores.values().map(|store| store.entity_sizes())).await
+    }
+
     /// Vacuum the `subgraph_deployment` table in each shard
     pub(crate) async fn vacuum(&self) -> Vec<Result<(), StoreError>> {
         join_all(self.stores.values().map(|store| store.vacuum())).await
     }
 
+    /// Run routine maintenance in each shard. See `maintenance::run`
+    pub(crate) async fn maintain(
+        &self,
+    ) -> Vec<(Shard, Result<Vec<maintenance::Outcome>, StoreError>)> {
+        join_all(self.stores.iter().map(|(shard, store)| async move {
+            (shard.clone(), maintenance::run(store.clone()).await)
+        }))
+        .await
+    }
+
+    /// Migrate the tables of the deployment `id` in place to `schema`. See
+    /// `Layout::can_migrate_from` for the schema changes that are allowed
+    pub fn migrate_schema(&self, id: &DeploymentHash, schema: &Schema) -> Result<(), StoreError> {
+        let (store, site) = self.store(id)?;
+        store.migrate_schema(site, schema)
+    }
+
     pub fn rewind(&self, id: DeploymentHash, block_ptr_to: BlockPtr) -> Result<(), StoreError> {
         let (store, site) = self.store(&id)?;
         let event = store.rewind(site, block_ptr_to)?;
@@ -1036,6 +1621,16 @@ impl SubgraphStoreInner {
         let (store, site) = self.store(&deployment.hash)?;
         store.drop_index(site, index_name).await
     }
+
+    /// Bring the indexes of `deployment` up to the current index
+    /// strategy. See `DeploymentStore::upgrade_indexes`
+    pub async fn upgrade_indexes(
+        &self,
+        deployment: &DeploymentLocator,
+    ) -> Result<(Vec<String>, Vec<String>), StoreError> {
+        let (store, site) = self.store(&deployment.hash)?;
+        store.upgrade_indexes(site).await
+    }
 }
 
 struct EnsLookup {
@@ -1049,6 +1644,22 @@ impl EnsLookupTrait for EnsLookup {
     }
 }
 
+struct FileRegistry {
+    primary: ConnectionPool,
+}
+
+impl FileRegistryTrait for FileRegistry {
+    fn get_file(&self, link: &str) -> Result<Option<Vec<u8>>, StoreError> {
+        let conn = self.primary.get()?;
+        primary::Connection::new(conn).find_deployment_file(link)
+    }
+
+    fn put_file(&self, link: &str, content: &[u8]) -> Result<(), StoreError> {
+        let conn = self.primary.get()?;
+        primary::Connection::new(conn).store_deployment_file(link, content)
+    }
+}
+
 #[async_trait::async_trait]
 impl SubgraphStoreTrait for SubgraphStore {
     fn ens_lookup(&self) -> Arc<dyn EnsLookupTrait> {
@@ -1057,6 +1668,12 @@ impl SubgraphStoreTrait for SubgraphStore {
         })
     }
 
+    fn file_registry(&self) -> Arc<dyn FileRegistryTrait> {
+        Arc::new(FileRegistry {
+            primary: self.mirror.primary().clone(),
+        })
+    }
+
     // FIXME: This method should not get a node_id
     fn create_subgraph_deployment(
         &self,
@@ -1098,10 +1715,16 @@ impl SubgraphStoreTrait for SubgraphStore {
     ) -> Result<(), StoreError> {
         let site = self.find_site(deployment.id.into())?;
         let pconn = self.primary_conn()?;
-        pconn.transaction(|| -> Result<_, StoreError> {
+        let from = pconn.transaction(|| -> Result<_, StoreError> {
+            let from = pconn.assigned_node(site.as_ref())?;
             let changes = pconn.reassign_subgraph(site.as_ref(), node_id)?;
-            pconn.send_store_event(&self.sender, &StoreEvent::new(changes))
-        })
+            pconn.send_store_event(&self.sender, &StoreEvent::new(changes))?;
+            Ok(from)
+        })?;
+        if from.as_ref() != Some(node_id) {
+            self.notify_reassigned(&site.deployment, from.as_ref(), node_id);
+        }
+        Ok(())
     }
 
     fn assigned_node(&self, deployment: &DeploymentLocator) -> Result<Option<NodeId>, StoreError> {
@@ -1115,10 +1738,65 @@ impl SubgraphStoreTrait for SubgraphStore {
             .map(|sites| sites.iter().map(|site| site.into()).collect())
     }
 
+    fn record_restart(&self, deployment: &DeploymentLocator) -> Result<(), StoreError> {
+        let site = self.find_site(deployment.id.into())?;
+        self.for_site(&site)?.record_restart(&site)
+    }
+
     fn subgraph_exists(&self, name: &SubgraphName) -> Result<bool, StoreError> {
         self.mirror.subgraph_exists(name)
     }
 
+    fn add_to_family(
+        &self,
+        family: &DeploymentHash,
+        deployment: &DeploymentHash,
+        network: &str,
+    ) -> Result<(), StoreError> {
+        self.primary_conn()?
+            .add_to_family(family, deployment, network)
+    }
+
+    fn family(&self, family: &DeploymentHash) -> Result<Vec<(DeploymentHash, String)>, StoreError> {
+        self.primary_conn()?
+            .family(family)?
+            .into_iter()
+            .map(|(deployment, network)| {
+                DeploymentHash::new(deployment)
+                    .map(|deployment| (deployment, network))
+                    .map_err(|deployment| {
+                        constraint_violation!(
+                            "invalid deployment hash `{}` in a family",
+                            deployment
+                        )
+                    })
+            })
+            .collect()
+    }
+
+    fn set_deprecation(
+        &self,
+        name: &SubgraphName,
+        deprecation: Option<SubgraphDeprecation>,
+    ) -> Result<(), StoreError> {
+        self.primary_conn()?
+            .set_deprecation(name, deprecation.as_ref())?;
+        self.deprecations.clear();
+        Ok(())
+    }
+
+    fn deprecation(&self, name: &SubgraphName) -> Result<Option<SubgraphDeprecation>, StoreError> {
+        let deprecations = match self.deprecations.get(&()) {
+            Some(deprecations) => deprecations,
+            None => {
+                let deprecations = Arc::new(self.primary_conn()?.deprecations()?);
+                self.deprecations.set((), deprecations.clone());
+                deprecations
+            }
+        };
+        Ok(deprecations.get(name.as_str()).cloned())
+    }
+
     fn entity_changes_in_block(
         &self,
         subgraph_id: &DeploymentHash,
@@ -1215,4 +1893,30 @@ impl SubgraphStoreTrait for SubgraphStore {
             .map(|site| site.into())
             .collect())
     }
+
+    fn export(
+        &self,
+        deployment: &DeploymentLocator,
+        request: &store::ExportRequest,
+    ) -> Result<Vec<store::ExportedFile>, StoreError> {
+        let site = self.find_site(deployment.id.into())?;
+        let store = self.for_site(&site)?;
+        store.export(site, request)
+    }
+
+    fn record_admin_call(&self, call: &store::AdminCall) -> Result<(), StoreError> {
+        self.primary_conn()?.record_admin_call(call)
+    }
+
+    fn deliver_entity_changes(
+        &self,
+        limit: usize,
+        deliver: &mut dyn FnMut(&[store::OutboxEntry]) -> Result<(), anyhow::Error>,
+    ) -> Result<usize, StoreError> {
+        let mut count = 0;
+        for (shard, store) in &self.stores {
+            count += store.deliver_entity_changes(shard.as_str(), limit, deliver)?;
+        }
+        Ok(count)
+    }
 }
@@ -1,10 +1,12 @@
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::Mutex;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use std::{collections::BTreeMap, sync::Arc};
 
 use graph::data::subgraph::schema;
+use graph::data::subgraph::status::WriteQueueInfo;
 use graph::env::env_var;
+use graph::prelude::tokio::sync::Semaphore;
 use graph::prelude::{
     BlockNumber, Entity, MetricsRegistry, Schema, SubgraphStore as _, BLOCK_NUMBER_MAX,
 };
@@ -13,15 +15,17 @@ use graph::util::bounded_queue::BoundedQueue;
 use graph::{
     cheap_clone::CheapClone,
     components::store::{self, EntityType, WritableStore as WritableStoreTrait},
+    components::webhooks::LifecycleEvent,
     data::subgraph::schema::SubgraphError,
     prelude::{
-        BlockPtr, DeploymentHash, EntityKey, EntityModification, Error, Logger, StopwatchMetrics,
-        StoreError, StoreEvent, UnfailOutcome, ENV_VARS,
+        BlockPtr, DeploymentHash, EntityKey, EntityModification, Error, Logger, NodeId,
+        StopwatchMetrics, StoreError, StoreEvent, UnfailOutcome, ENV_VARS,
     },
     slog::{error, warn},
     util::backoff::ExponentialBackoff,
 };
 use store::StoredDynamicDataSource;
+use uuid::Uuid;
 
 use crate::deployment_store::DeploymentStore;
 use crate::{primary, primary::Site, relational::Layout, SubgraphStore};
@@ -55,6 +59,10 @@ impl WritableSubgraphStore {
     fn layout(&self, id: &DeploymentHash) -> Result<Arc<Layout>, StoreError> {
         self.0.layout(id)
     }
+
+    fn notify(&self, deployment: &DeploymentHash, event: LifecycleEvent) {
+        self.0.notify(deployment, event)
+    }
 }
 
 /// Write synchronously to the actual store, i.e., once a method returns,
@@ -66,11 +74,16 @@ struct SyncStore {
     writable: Arc<DeploymentStore>,
     site: Arc<Site>,
     input_schema: Arc<Schema>,
+    /// Identifies this store when it claims the deployment for writing
+    writer: String,
+    /// The node this store runs on
+    node: NodeId,
 }
 
 impl SyncStore {
     const BACKOFF_BASE: Duration = Duration::from_millis(100);
     const BACKOFF_CEIL: Duration = Duration::from_secs(10);
+    const BACKOFF_JITTER: f64 = 0.5;
 
     fn new(
         subgraph_store: SubgraphStore,
@@ -80,12 +93,15 @@ impl SyncStore {
         let store = WritableSubgraphStore(subgraph_store.clone());
         let writable = subgraph_store.for_site(site.as_ref())?.clone();
         let input_schema = subgraph_store.input_schema(&site.deployment)?;
+        let node = subgraph_store.node().clone();
         Ok(Self {
             logger,
             store,
             writable,
             site,
             input_schema,
+            writer: Uuid::new_v4().to_string(),
+            node,
         })
     }
 
@@ -97,17 +113,40 @@ impl SyncStore {
             "delay_ms" => backoff.delay().as_millis());
     }
 
+    fn log_conflict_warning(
+        &self,
+        op: &str,
+        msg: &str,
+        conflicts: usize,
+        backoff: &ExponentialBackoff,
+    ) {
+        warn!(self.logger,
+            "transaction conflicted with a concurrent transaction, will retry";
+            "operation" => op,
+            "error" => msg,
+            "conflicts" => conflicts,
+            "delay_ms" => backoff.delay().as_millis());
+    }
+
     fn retry<T, F>(&self, op: &str, f: F) -> Result<T, StoreError>
     where
         F: Fn() -> Result<T, StoreError>,
     {
-        let mut backoff = ExponentialBackoff::new(Self::BACKOFF_BASE, Self::BACKOFF_CEIL);
+        let mut backoff = ExponentialBackoff::new(Self::BACKOFF_BASE, Self::BACKOFF_CEIL)
+            .with_jitter(Self::BACKOFF_JITTER);
+        let mut conflicts = 0;
         loop {
             match f() {
                 Ok(v) => return Ok(v),
                 Err(StoreError::DatabaseUnavailable) => {
                     self.log_backoff_warning(op, &backoff);
                 }
+                Err(StoreError::TransactionConflict(msg))
+                    if conflicts < ENV_VARS.store.conflict_retries =>
+                {
+                    conflicts += 1;
+                    self.log_conflict_warning(op, &msg, conflicts, &backoff);
+                }
                 Err(e) => return Err(e),
             }
             backoff.sleep();
@@ -119,13 +158,21 @@ impl SyncStore {
         F: Fn() -> Fut,
         Fut: std::future::Future<Output = Result<T, StoreError>>,
     {
-        let mut backoff = ExponentialBackoff::new(Self::BACKOFF_BASE, Self::BACKOFF_CEIL);
+        let mut backoff = ExponentialBackoff::new(Self::BACKOFF_BASE, Self::BACKOFF_CEIL)
+            .with_jitter(Self::BACKOFF_JITTER);
+        let mut conflicts = 0;
         loop {
             match f().await {
                 Ok(v) => return Ok(v),
                 Err(StoreError::DatabaseUnavailable) => {
                     self.log_backoff_warning(op, &backoff);
                 }
+                Err(StoreError::TransactionConflict(msg))
+                    if conflicts < ENV_VARS.store.conflict_retries =>
+                {
+                    conflicts += 1;
+                    self.log_conflict_warning(op, &msg, conflicts, &backoff);
+                }
                 Err(e) => return Err(e),
             }
             backoff.sleep_async().await;
@@ -170,6 +217,19 @@ impl SyncStore {
         self.retry("start_subgraph_deployment", || {
             let store = &self.writable;
 
+            // Claim the deployment before touching it so that we leave it
+            // alone when another node is writing to it
+            let assigned = self
+                .store
+                .primary_conn()?
+                .assigned_node(self.site.as_ref())?;
+            store.claim_writer(
+                self.site.as_ref(),
+                &self.writer,
+                &self.node,
+                assigned.as_ref(),
+            )?;
+
             let graft_base = match store.graft_pending(&self.site.deployment)? {
                 Some((base_id, base_ptr)) => {
                     let src = self.store.layout(&base_id)?;
@@ -190,6 +250,7 @@ impl SyncStore {
         self.retry("revert_block_operations", || {
             let event = self.writable.revert_block_operations(
                 self.site.clone(),
+                &self.writer,
                 block_ptr_to.clone(),
                 firehose_cursor,
             )?;
@@ -204,8 +265,12 @@ impl SyncStore {
         parent_ptr: &BlockPtr,
     ) -> Result<UnfailOutcome, StoreError> {
         self.retry("unfail_deterministic_error", || {
-            self.writable
-                .unfail_deterministic_error(self.site.clone(), current_ptr, parent_ptr)
+            self.writable.unfail_deterministic_error(
+                self.site.clone(),
+                &self.writer,
+                current_ptr,
+                parent_ptr,
+            )
         })
     }
 
@@ -220,6 +285,11 @@ impl SyncStore {
     }
 
     async fn fail_subgraph(&self, error: SubgraphError) -> Result<(), StoreError> {
+        let failed = LifecycleEvent::DeploymentFailed {
+            error: error.message.clone(),
+            block: error.block_ptr.as_ref().map(|ptr| ptr.number),
+            deterministic: error.deterministic,
+        };
         self.retry_async("fail_subgraph", || {
             let error = error.clone();
             async {
@@ -229,7 +299,9 @@ impl SyncStore {
                     .await
             }
         })
-        .await
+        .await?;
+        self.store.notify(&self.site.deployment, failed);
+        Ok(())
     }
 
     async fn supports_proof_of_indexing(&self) -> Result<bool, StoreError> {
@@ -267,6 +339,7 @@ impl SyncStore {
         self.retry("transact_block_operations", move || {
             let event = self.writable.transact_block_operations(
                 self.site.clone(),
+                &self.writer,
                 block_ptr_to,
                 firehose_cursor,
                 mods,
@@ -339,7 +412,10 @@ impl SyncStore {
             self.writable.deployment_synced(&self.site.deployment)?;
 
             self.store.send_store_event(&event)
-        })
+        })?;
+        self.store
+            .notify(&self.site.deployment, LifecycleEvent::DeploymentSynced);
+        Ok(())
     }
 
     fn shard(&self) -> &str {
@@ -433,6 +509,14 @@ enum Request {
 }
 
 impl Request {
+    /// The number of entity changes in the request
+    fn entity_count(&self) -> usize {
+        match self {
+            Request::Write { mods, .. } => mods.len(),
+            Request::RevertTo { .. } => 0,
+        }
+    }
+
     fn execute(&self) -> Result<(), StoreError> {
         match self {
             Request::Write {
@@ -472,6 +556,19 @@ struct Queue {
     /// that is not true anymore
     queue: BoundedQueue<Arc<Request>>,
 
+    /// Limits the number of entity changes in the queue. Every request
+    /// holds one permit per entity change, but never more than
+    /// `max_entities`, until it has been written
+    entity_permits: Semaphore,
+    /// The number of permits `entity_permits` starts out with; `0` means
+    /// that the number of entity changes is not limited
+    max_entities: usize,
+    /// The number of calls to `push` that are waiting for room in the
+    /// queue
+    waiting: AtomicUsize,
+    /// How long writing the last request took
+    last_write: Mutex<Option<Duration>>,
+
     /// The write task puts errors from `transact_block_operations` here so
     /// we can report them on the next call to transact block operations.
     write_err: Mutex<Option<StoreError>>,
@@ -535,13 +632,17 @@ impl Queue {
                 // the write transaction commits, causing them to return
                 // incorrect results.
                 let req = queue.queue.peek().await;
+                let permits = queue.entity_permits_for(&req);
+                let start = Instant::now();
                 let res = graph::spawn_blocking_allow_panic(move || req.execute()).await;
 
                 match res {
                     Ok(Ok(())) => {
+                        *queue.last_write.lock().unwrap() = Some(start.elapsed());
                         // The request has been handled. It's now safe to remove it
                         // from the queue
                         queue.queue.pop().await;
+                        queue.entity_permits.add_permits(permits as usize);
                     }
                     Ok(Err(e)) => {
                         error!(logger, "Subgraph writer failed"; "error" => e.to_string());
@@ -558,6 +659,10 @@ impl Queue {
         }
 
         let queue = BoundedQueue::with_capacity(capacity);
+        let max_entities = ENV_VARS
+            .store
+            .write_queue_max_entities
+            .min(u32::MAX as usize);
         let write_err = Mutex::new(None);
 
         // Use a separate instance of the `StopwatchMetrics` for background
@@ -573,6 +678,10 @@ impl Queue {
         let queue = Self {
             store,
             queue,
+            entity_permits: Semaphore::new(max_entities),
+            max_entities,
+            waiting: AtomicUsize::new(0),
+            last_write: Mutex::new(None),
             write_err,
             poisoned: AtomicBool::new(false),
             stopwatch,
@@ -584,13 +693,62 @@ impl Queue {
         queue
     }
 
-    /// Add a write request to the queue
+    /// The number of permits from `entity_permits` that `req` needs
+    fn entity_permits_for(&self, req: &Request) -> u32 {
+        if self.max_entities == 0 {
+            return 0;
+        }
+        req.entity_count().min(self.max_entities) as u32
+    }
+
+    /// Add a write request to the queue. If the queue is full, wait until
+    /// the background writer has made room for it, which holds up
+    /// processing more blocks until the store has caught up
     async fn push(&self, req: Request) -> Result<(), StoreError> {
         self.check_err()?;
+        self.waiting.fetch_add(1, Ordering::SeqCst);
+        let res = self.push_inner(req).await;
+        self.waiting.fetch_sub(1, Ordering::SeqCst);
+        res
+    }
+
+    async fn push_inner(&self, req: Request) -> Result<(), StoreError> {
+        let permits = self.entity_permits_for(&req);
+        if permits > 0 {
+            match self.entity_permits.acquire_many(permits).await {
+                Ok(permit) => permit.forget(),
+                // The semaphore is closed when the queue is poisoned
+                Err(_) => {
+                    self.check_err()?;
+                    return Err(StoreError::Poisoned);
+                }
+            }
+        }
         self.queue.push(Arc::new(req)).await;
         Ok(())
     }
 
+    /// What is currently in the queue
+    fn info(&self) -> WriteQueueInfo {
+        let (depth, blocks, entities) =
+            self.queue
+                .fold((0, 0, 0), |(depth, blocks, entities), req| {
+                    match req.as_ref() {
+                        Request::Write { mods, .. } => {
+                            (depth + 1, blocks + 1, entities + mods.len())
+                        }
+                        Request::RevertTo { .. } => (depth + 1, blocks, entities),
+                    }
+                });
+        WriteQueueInfo {
+            depth,
+            blocks,
+            entities,
+            last_write: *self.last_write.lock().unwrap(),
+            throttled: self.waiting.load(Ordering::SeqCst) > 0,
+        }
+    }
+
     /// Wait for the background writer to finish processing queued entries
     async fn flush(&self) -> Result<(), StoreError> {
         self.queue.wait_empty().await;
@@ -613,6 +771,7 @@ impl Queue {
         *self.write_err.lock().unwrap() = Some(e);
         self.poisoned.store(true, Ordering::SeqCst);
         self.queue.clear();
+        self.entity_permits.close();
     }
 
     /// Get the entity for `key` if it exists by looking at both the queue
@@ -756,6 +915,11 @@ impl Queue {
             }
             dds
         });
+        // Data sources that requests in the queue removed are not loaded,
+        // no matter whether they are in the queue or the store
+        let (removed, mut queue_dds): (Vec<_>, Vec<_>) = queue_dds
+            .into_iter()
+            .partition(|dds| dds.removed_block.is_some());
         // Using a stable sort is important here so that dds created at the
         // same block stay in the order in which they were added (and
         // therefore will be loaded from the store in that order once the
@@ -767,6 +931,9 @@ impl Queue {
             .load_dynamic_data_sources(tracker.query_block())
             .await?;
         dds.append(&mut queue_dds);
+        if !removed.is_empty() {
+            dds.retain(|dds| !removed.iter().any(|removed| removed.is_same_as(dds)));
+        }
 
         Ok(dds)
     }
@@ -875,6 +1042,13 @@ impl Writer {
             Writer::Async(queue) => queue.load_dynamic_data_sources().await,
         }
     }
+
+    fn queue_info(&self) -> Option<WriteQueueInfo> {
+        match self {
+            Writer::Sync(_) => None,
+            Writer::Async(queue) => Some(queue.info()),
+        }
+    }
 }
 
 pub struct WritableStore {
@@ -908,6 +1082,12 @@ impl WritableStore {
             writer,
         })
     }
+
+    /// What is waiting to be written to the store, or `None` if writes are
+    /// not pipelined
+    pub(crate) fn write_queue(&self) -> Option<WriteQueueInfo> {
+        self.writer.queue_info()
+    }
 }
 
 #[async_trait::async_trait]
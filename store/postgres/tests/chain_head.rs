@@ -8,7 +8,7 @@ use std::sync::Arc;
 use graph::prelude::web3::types::H256;
 use graph::prelude::{anyhow::anyhow, anyhow::Error};
 use graph::prelude::{serde_json as json, EthereumBlock};
-use graph::prelude::{BlockNumber, QueryStoreManager};
+use graph::prelude::{BlockNumber, QueryStoreManager, StatusStore};
 use graph::{cheap_clone::CheapClone, prelude::web3::types::H160};
 use graph::{components::store::BlockStore as _, prelude::DeploymentHash};
 use graph::{components::store::ChainStore as _, prelude::EthereumCallCache as _};
@@ -244,6 +244,125 @@ fn block_hashes_by_number() {
     })
 }
 
+#[test]
+fn block_times() {
+    let chain = vec![
+        &*GENESIS_BLOCK,
+        &*BLOCK_ONE,
+        &*BLOCK_TWO,
+        &*BLOCK_TWO_NO_PARENT,
+        &*BLOCK_THREE,
+    ];
+    run_test(chain, move |store, _| {
+        let time = |block: &FakeBlock| block.block_timestamp();
+
+        assert_eq!(
+            Some(time(&BLOCK_ONE)),
+            store.block_time(&BLOCK_ONE.block_hash())?
+        );
+        assert_eq!(
+            Some(BLOCK_ONE.block_ptr()),
+            store.block_at_time(time(&BLOCK_ONE) + 1)?
+        );
+        assert_eq!(
+            Some(BLOCK_THREE.block_ptr()),
+            store.block_at_time(u64::MAX)?
+        );
+        assert_eq!(None, store.block_at_time(time(&GENESIS_BLOCK) - 1)?);
+
+        let block = store.indexed_block(&BLOCK_TWO.block_hash())?.unwrap();
+        assert_eq!(2, block.number);
+        assert_eq!(BLOCK_ONE.block_hash(), block.parent_hash);
+        assert_eq!(2, store.indexed_blocks_by_number(2)?.len());
+
+        // Only the confirmed block remains in the index
+        store.confirm_block_hash(2, &BLOCK_TWO.block_hash())?;
+        assert_eq!(None, store.block_time(&BLOCK_TWO_NO_PARENT.block_hash())?);
+        assert_eq!(
+            Some(BLOCK_TWO.block_ptr()),
+            store.block_at_time(time(&BLOCK_TWO))?
+        );
+        assert_eq!(vec![block], store.indexed_blocks_by_number(2)?);
+        Ok(())
+    })
+}
+
+#[test]
+fn index_cached_block_times() {
+    let chain = vec![
+        &*GENESIS_BLOCK,
+        &*BLOCK_ONE,
+        &*BLOCK_TWO,
+        &*BLOCK_THREE,
+        &*BLOCK_FOUR,
+    ];
+    run_test(chain, move |store, _| {
+        // Pretend that the blocks below 3 were cached before the index
+        // existed
+        assert_eq!(3, store.delete_block_times_before(3)?);
+        assert_eq!(None, store.block_time(&BLOCK_ONE.block_hash())?);
+
+        // Blocks are added from the lowest indexed block downwards
+        assert_eq!(Some(1), store.index_cached_block_times(None, 2)?);
+        for block in [&*BLOCK_ONE, &*BLOCK_TWO] {
+            assert_eq!(
+                Some(block.block_timestamp()),
+                store.block_time(&block.block_hash())?
+            );
+        }
+        assert_eq!(None, store.block_time(&GENESIS_BLOCK.block_hash())?);
+        assert_eq!(None, store.index_cached_block_times(Some(1), 2)?);
+        assert_eq!(
+            Some(GENESIS_BLOCK.block_ptr()),
+            store.block_at_time(GENESIS_BLOCK.block_timestamp())?
+        );
+
+        // Once everything is indexed, there is nothing left to do
+        assert_eq!(None, store.index_cached_block_times(None, 2)?);
+        Ok(())
+    })
+}
+
+#[test]
+fn chain_statuses() {
+    let chain = vec![&*GENESIS_BLOCK, &*BLOCK_ONE, &*BLOCK_TWO];
+    run_test(chain, move |chain_store, store| {
+        assert_eq!(
+            Some(GENESIS_BLOCK.block_ptr()),
+            chain_store.earliest_block()?
+        );
+
+        let status = store
+            .chains()?
+            .into_iter()
+            .find(|status| status.network == chain_store.chain)
+            .expect("the chain has a status");
+        assert_eq!(
+            Some(GENESIS_BLOCK.block_ptr()),
+            status.earliest_block.map(|block| block.to_ptr())
+        );
+        assert!(status.providers.is_empty());
+        Ok(())
+    })
+}
+
+#[test]
+fn contract_creation_blocks() {
+    run_test(vec![&*GENESIS_BLOCK], move |store, _| {
+        let address = H160::from_low_u64_be(0x1234);
+        store.set_contract_creation_block(address.as_bytes(), 17)?;
+        assert_eq!(Some(17), store.contract_creation_block(address.as_bytes())?);
+
+        store.set_contract_creation_block(address.as_bytes(), 23)?;
+        assert_eq!(Some(23), store.contract_creation_block(address.as_bytes())?);
+
+        // Nothing ever sets this address
+        let other = H160::from_low_u64_be(0x5678);
+        assert_eq!(None, store.contract_creation_block(other.as_bytes())?);
+        Ok(())
+    })
+}
+
 #[track_caller]
 fn check_ancestor(
     store: &Arc<DieselChainStore>,
@@ -381,3 +500,44 @@ fn test_transaction_receipts_in_block_function() {
         assert!(receipts.is_empty())
     })
 }
+
+#[test]
+fn ingestor_lease() {
+    run_test_sequentially(|store| async move {
+        let block_store = store.block_store();
+        assert!(ingestor_lease_available(NETWORK_NAME));
+
+        let lease = block_store
+            .try_lease_ingestor(NETWORK_NAME)
+            .unwrap()
+            .unwrap();
+        assert_eq!(NETWORK_NAME, lease.chain());
+        assert!(lease.check().unwrap());
+
+        // Neither this node nor any other can lease the chain again, but
+        // other chains are not affected
+        assert!(block_store
+            .try_lease_ingestor(NETWORK_NAME)
+            .unwrap()
+            .is_none());
+        assert!(!ingestor_lease_available(NETWORK_NAME));
+        let shared = block_store
+            .try_lease_ingestor(FAKE_NETWORK_SHARED)
+            .unwrap()
+            .unwrap();
+        assert!(!ingestor_lease_available(FAKE_NETWORK_SHARED));
+
+        // Dropping the lease lets others take it over
+        drop(lease);
+        assert!(ingestor_lease_available(NETWORK_NAME));
+        assert!(shared.check().unwrap());
+        let lease = block_store
+            .try_lease_ingestor(NETWORK_NAME)
+            .unwrap()
+            .unwrap();
+        assert!(lease.check().unwrap());
+        drop(lease);
+        drop(shared);
+        assert!(ingestor_lease_available(FAKE_NETWORK_SHARED));
+    })
+}
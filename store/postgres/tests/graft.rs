@@ -133,6 +133,7 @@ async fn insert_test_data(store: Arc<DieselSubgraphStore>) -> DeploymentLocator
         schema: TEST_SUBGRAPH_SCHEMA.clone(),
         data_sources: vec![],
         graft: None,
+        indexing_delay: None,
         templates: vec![],
         chain: PhantomData,
     };
@@ -0,0 +1,126 @@
+use graph::{
+    components::store::JobRequest,
+    data::subgraph::status::{JobFilter, JobState},
+    prelude::{DeploymentHash, NodeId, SubgraphStore as _},
+};
+use graph_store_postgres::layout_for_tests::job_queue;
+use graph_store_postgres::SubgraphStore;
+use test_store::*;
+
+const SUBGRAPH_GQL: &str = "
+    type User @entity {
+        id: ID!,
+        name: String
+    }
+";
+
+fn job_state(store: &SubgraphStore, id: i32) -> (JobState, i64, bool) {
+    let filter = JobFilter {
+        id: Some(id),
+        ..JobFilter::default()
+    };
+    let jobs = store.jobs(filter).unwrap();
+    assert_eq!(1, jobs.len());
+    (jobs[0].state, jobs[0].done, jobs[0].cancel_requested)
+}
+
+#[test]
+fn claim_requeue_cancel() {
+    run_test_sequentially(|store| async move {
+        remove_subgraphs();
+        let id = DeploymentHash::new("jobsClaim").unwrap();
+        let deployment = create_test_subgraph(&id, SUBGRAPH_GQL).await;
+        let store = store.subgraph_store();
+
+        let prune = JobRequest::Prune { before: 1 };
+        let first = store.enqueue_job(&deployment, &prune).unwrap();
+        let second = store.enqueue_job(&deployment, &prune).unwrap();
+
+        // Every job is claimed by exactly one node, oldest first
+        let left = NodeId::new("left").unwrap();
+        let right = NodeId::new("right").unwrap();
+        let primary = primary_connection();
+        assert_eq!(first, primary.claim_job(&left).unwrap().unwrap().id);
+        assert_eq!(second, primary.claim_job(&right).unwrap().unwrap().id);
+        assert!(primary.claim_job(&left).unwrap().is_none());
+
+        let running: Vec<_> = primary
+            .running_jobs(&left)
+            .unwrap()
+            .into_iter()
+            .map(|job| job.id)
+            .collect();
+        assert_eq!(vec![first], running);
+
+        // Jobs of unresponsive nodes go back into the queue
+        assert_eq!(vec![first], primary.requeue_jobs(&[left.clone()]).unwrap());
+        assert_eq!((JobState::Queued, 0, false), job_state(&store, first));
+        assert!(primary.running_jobs(&left).unwrap().is_empty());
+
+        // Queued jobs are cancelled right away, running jobs only once
+        // the node that runs them notices
+        assert_eq!(
+            Some(JobState::Cancelled),
+            primary.cancel_job(first).unwrap()
+        );
+        assert_eq!(Some(JobState::Running), primary.cancel_job(second).unwrap());
+        assert_eq!((JobState::Running, 0, true), job_state(&store, second));
+        assert_eq!(None, primary.cancel_job(second + 1000).unwrap());
+    })
+}
+
+#[test]
+fn run_rewind_job() {
+    run_test_sequentially(|store| async move {
+        remove_subgraphs();
+        let id = DeploymentHash::new("jobsRewind").unwrap();
+        let deployment = create_test_subgraph(&id, SUBGRAPH_GQL).await;
+        let subgraph_store = store.subgraph_store();
+        for block in BLOCKS[1..].iter() {
+            transact_and_wait(&subgraph_store, &deployment, block.clone(), vec![])
+                .await
+                .unwrap();
+        }
+        // A deployment that is already paused is rewound without waiting
+        // for the node that indexes it to let go of it
+        subgraph_store
+            .reassign_subgraph(&deployment, &NodeId::new("paused_test").unwrap())
+            .unwrap();
+
+        let target = &BLOCKS[1];
+        let request = JobRequest::Rewind {
+            block_hash: target.hash_hex(),
+            block_number: target.number,
+        };
+        let job = subgraph_store.enqueue_job(&deployment, &request).unwrap();
+
+        // The first run stops after pausing and records that, the next run
+        // picks up from there
+        let node = NodeId::new("jobs").unwrap();
+        job_queue::run(&subgraph_store, &LOGGER, &node).unwrap();
+        assert_eq!(
+            (JobState::Running, 1, false),
+            job_state(&subgraph_store, job)
+        );
+
+        job_queue::run(&subgraph_store, &LOGGER, &node).unwrap();
+        let info = subgraph_store
+            .jobs(JobFilter {
+                id: Some(job),
+                ..JobFilter::default()
+            })
+            .unwrap()
+            .pop()
+            .unwrap();
+        assert_eq!(JobState::Succeeded, info.state);
+        assert_eq!(2, info.done);
+        assert_eq!(
+            Some(format!("rewound {} to block {}", deployment, target.number)),
+            info.outcome
+        );
+        assert_eq!(
+            Some(NodeId::new("paused_test").unwrap()),
+            subgraph_store.assigned_node(&deployment).unwrap()
+        );
+    })
+}
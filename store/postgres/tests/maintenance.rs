@@ -0,0 +1,103 @@
+//! Test how routine maintenance finds the indexes it needs to deal with
+use diesel::connection::SimpleConnection as _;
+use diesel::pg::PgConnection;
+use graph_store_postgres::layout_for_tests::maintenance::{
+    bloated_indexes, reindex_leftovers, server_version,
+};
+use test_store::*;
+
+const SCHEMA: &str = "sgd_maintenance_test";
+
+/// The indexes from `indexes` that are in the test schema, so that
+/// leftovers from other tests don't get in the way
+fn ours(indexes: Vec<String>) -> Vec<String> {
+    indexes
+        .into_iter()
+        .filter(|index| index.starts_with(SCHEMA))
+        .collect()
+}
+
+fn setup(conn: &PgConnection) {
+    conn.batch_execute(&format!(
+        "drop schema if exists {schema} cascade;
+         create schema {schema};
+         create table {schema}.thing(id int primary key, name text not null);
+         insert into {schema}.thing
+           select i, repeat('x', 200) || i from generate_series(1, 5000) i;
+         create index thing_name on {schema}.thing(left(name, 150));
+         analyze {schema}.thing;",
+        schema = SCHEMA
+    ))
+    .unwrap();
+}
+
+fn teardown(conn: &PgConnection) {
+    conn.batch_execute(&format!("drop schema {} cascade", SCHEMA))
+        .unwrap();
+}
+
+#[test]
+fn bloated_expression_indexes() {
+    run_test_with_conn(|conn| {
+        setup(conn);
+
+        // The width of the expression comes from the statistics of the
+        // index; with a guess for it, the index would look bloated
+        assert!(ours(bloated_indexes(conn, 2.0, 0).unwrap()).is_empty());
+
+        conn.batch_execute(&format!(
+            "delete from {schema}.thing where id % 10 <> 0;
+             analyze {schema}.thing;",
+            schema = SCHEMA
+        ))
+        .unwrap();
+        assert_eq!(
+            vec![
+                format!("{}.thing_name", SCHEMA),
+                format!("{}.thing_pkey", SCHEMA)
+            ],
+            ours(bloated_indexes(conn, 2.0, 0).unwrap())
+        );
+
+        // Small indexes are left alone
+        assert!(ours(bloated_indexes(conn, 2.0, 1000).unwrap()).is_empty());
+
+        teardown(conn);
+    })
+}
+
+#[test]
+fn failed_reindex_leftovers() {
+    run_test_with_conn(|conn| {
+        if server_version(conn).unwrap() < 120000 {
+            // There is no `reindex concurrently` that could leave
+            // anything behind
+            return;
+        }
+        setup(conn);
+
+        // Building an index concurrently that fails leaves an invalid
+        // index behind, just like a failed `reindex concurrently`
+        conn.batch_execute(&format!(
+            "insert into {}.thing values (10001, 'dup'), (10002, 'dup')",
+            SCHEMA
+        ))
+        .unwrap();
+        for index in ["thing_name_ccnew", "thing_name_unique"] {
+            let res = conn.batch_execute(&format!(
+                "create unique index concurrently {} on {}.thing(name)",
+                index, SCHEMA
+            ));
+            assert!(res.is_err());
+        }
+
+        // Only the leftovers of `reindex concurrently` are dropped
+        assert_eq!(
+            vec![format!("{}.thing_name_ccnew", SCHEMA)],
+            ours(reindex_leftovers(conn).unwrap())
+        );
+        assert!(ours(bloated_indexes(conn, 2.0, 0).unwrap()).is_empty());
+
+        teardown(conn);
+    })
+}
@@ -0,0 +1,188 @@
+use graph::{
+    components::store::{EntityKey, EntityOperation, OutboxEntry},
+    entity,
+    prelude::{anyhow, BlockNumber, DeploymentHash, SubgraphStore as _},
+};
+use test_store::*;
+
+const SUBGRAPH_GQL: &str = "
+    type User @entity {
+        id: ID!,
+        name: String
+    }
+";
+
+/// The outbox is only filled when changes are published somewhere. This
+/// has to happen before anything reads `ENV_VARS`
+fn enable_outbox() {
+    std::env::set_var("GRAPH_KAFKA_BROKERS", "localhost:9092");
+}
+
+fn set(id: &DeploymentHash, user: &str, name: &str) -> EntityOperation {
+    EntityOperation::Set {
+        key: EntityKey::data(id.clone(), "User".to_owned(), user.to_owned()),
+        data: entity! { id: user, name: name },
+    }
+}
+
+fn remove(id: &DeploymentHash, user: &str) -> EntityOperation {
+    EntityOperation::Remove {
+        key: EntityKey::data(id.clone(), "User".to_owned(), user.to_owned()),
+    }
+}
+
+fn change(
+    block: BlockNumber,
+    user: &str,
+    operation: &str,
+    name: Option<&str>,
+) -> (BlockNumber, String, String, Option<String>) {
+    (
+        block,
+        user.to_string(),
+        operation.to_string(),
+        name.map(str::to_string),
+    )
+}
+
+/// The block, entity id, operation and name of each of `entries`. Entries
+/// must be in the order of the blocks they belong to; within a block, the
+/// order does not matter
+fn changes(entries: &[OutboxEntry]) -> Vec<(BlockNumber, String, String, Option<String>)> {
+    assert!(entries
+        .windows(2)
+        .all(|pair| pair[0].block <= pair[1].block));
+    let mut changes: Vec<_> = entries
+        .iter()
+        .map(|entry| {
+            assert_eq!("User", entry.entity_type);
+            let name = entry
+                .data
+                .as_ref()
+                .and_then(|data| data["name"].as_str())
+                .map(str::to_string);
+            (
+                entry.block,
+                entry.entity_id.clone(),
+                entry.operation.clone(),
+                name,
+            )
+        })
+        .collect();
+    changes.sort();
+    changes
+}
+
+#[test]
+fn deliver_changes_in_order() {
+    enable_outbox();
+    run_test_sequentially(|store| async move {
+        remove_subgraphs();
+        let id = DeploymentHash::new("outboxDeliver").unwrap();
+        let deployment = create_test_subgraph(&id, SUBGRAPH_GQL).await;
+        let subgraph_store = store.subgraph_store();
+
+        let ops = vec![set(&id, "1", "Johnton"), set(&id, "2", "Cindini")];
+        transact_and_wait(&subgraph_store, &deployment, BLOCKS[1].clone(), ops)
+            .await
+            .unwrap();
+        let ops = vec![set(&id, "1", "Jonas"), remove(&id, "2")];
+        transact_and_wait(&subgraph_store, &deployment, BLOCKS[2].clone(), ops)
+            .await
+            .unwrap();
+
+        // Changes that could not be delivered stay in the outbox and can
+        // be delivered again right away
+        assert!(subgraph_store
+            .deliver_entity_changes(10, &mut |_| Err(anyhow!("the brokers are down")))
+            .is_err());
+
+        // While changes are being delivered, nobody else gets any
+        let mut delivered = Vec::new();
+        let count = subgraph_store
+            .deliver_entity_changes(3, &mut |entries| {
+                let others = subgraph_store
+                    .deliver_entity_changes(10, &mut |_| panic!("changes were claimed"))
+                    .unwrap();
+                assert_eq!(0, others);
+                assert!(entries.iter().all(|entry| entry.deployment == id));
+                delivered.extend_from_slice(entries);
+                Ok(())
+            })
+            .unwrap();
+        assert_eq!(3, count);
+        let count = subgraph_store
+            .deliver_entity_changes(10, &mut |entries| {
+                delivered.extend_from_slice(entries);
+                Ok(())
+            })
+            .unwrap();
+        assert_eq!(1, count);
+        assert_eq!(
+            vec![
+                change(1, "1", "set", Some("Johnton")),
+                change(1, "2", "set", Some("Cindini")),
+                change(2, "1", "set", Some("Jonas")),
+                change(2, "2", "remove", None),
+            ],
+            changes(&delivered)
+        );
+
+        // Delivered changes are gone
+        let count = subgraph_store
+            .deliver_entity_changes(10, &mut |_| panic!("the outbox is empty"))
+            .unwrap();
+        assert_eq!(0, count);
+    })
+}
+
+#[test]
+fn revert_restores_entities() {
+    enable_outbox();
+    run_test_sequentially(|store| async move {
+        remove_subgraphs();
+        let id = DeploymentHash::new("outboxRevert").unwrap();
+        let deployment = create_test_subgraph(&id, SUBGRAPH_GQL).await;
+        let subgraph_store = store.subgraph_store();
+
+        let ops = vec![set(&id, "1", "Johnton"), set(&id, "2", "Cindini")];
+        transact_and_wait(&subgraph_store, &deployment, BLOCKS[1].clone(), ops)
+            .await
+            .unwrap();
+        let ops = vec![
+            set(&id, "1", "Jonas"),
+            remove(&id, "2"),
+            set(&id, "3", "Shaqueeena"),
+        ];
+        transact_and_wait(&subgraph_store, &deployment, BLOCKS[2].clone(), ops)
+            .await
+            .unwrap();
+        let mut delivered = Vec::new();
+        subgraph_store
+            .deliver_entity_changes(10, &mut |entries| {
+                delivered.extend_from_slice(entries);
+                Ok(())
+            })
+            .unwrap();
+        assert_eq!(5, delivered.len());
+
+        // After the revert, consumers are told the state of every entity
+        // the reverted block changed as of the block reverted to
+        revert_block(&store, &deployment, &BLOCKS[1]).await;
+        let mut delivered = Vec::new();
+        subgraph_store
+            .deliver_entity_changes(10, &mut |entries| {
+                delivered.extend_from_slice(entries);
+                Ok(())
+            })
+            .unwrap();
+        assert_eq!(
+            vec![
+                change(1, "1", "set", Some("Johnton")),
+                change(1, "2", "set", Some("Cindini")),
+                change(1, "3", "remove", None),
+            ],
+            changes(&delivered)
+        );
+    })
+}
@@ -9,6 +9,7 @@ use graph::prelude::{
     ValueType, BLOCK_NUMBER_MAX,
 };
 use graph_mock::MockMetricsRegistry;
+use graph_store_postgres::layout_for_tests::export;
 use graph_store_postgres::layout_for_tests::set_account_like;
 use graph_store_postgres::layout_for_tests::LayoutCache;
 use graph_store_postgres::layout_for_tests::SqlName;
@@ -22,7 +23,7 @@ use std::thread::sleep;
 use std::time::Duration;
 
 use graph::{
-    components::store::{AttributeNames, EntityType},
+    components::store::{AttributeNames, EntityType, ExportFormat, ExportRequest},
     data::store::scalar::{BigDecimal, BigInt, Bytes},
 };
 use graph_store_postgres::{
@@ -1158,7 +1159,16 @@ fn check_find() {
             .check(
                 vec!["garfield", "pluto"],
                 query(vec!["Cat", "Dog"]).unordered(),
-            );
+            )
+            .check(
+                vec!["pluto"],
+                query(vec!["Cat", "Dog"]).desc("name").first(1),
+            )
+            .check(
+                vec!["garfield"],
+                query(vec!["Cat", "Dog"]).desc("name").first(1).skip(1),
+            )
+            .check(vec![], query(vec!["Cat", "Dog"]).asc("id").first(1).skip(2));
 
         // fulltext
         let checker = checker
@@ -1207,6 +1217,48 @@ fn check_find() {
                 )),
             );
 
+        // list contains, ignoring case
+        let checker = checker
+            .check(
+                vec!["3"],
+                user_query().filter(EntityFilter::ContainsNoCase(
+                    "drinks".into(),
+                    vec!["TEA", "Coffee"].into(),
+                )),
+            )
+            .check(
+                vec!["3"],
+                user_query().filter(EntityFilter::NotContainsNoCase(
+                    "drinks".into(),
+                    vec!["BEER"].into(),
+                )),
+            );
+
+        // list overlaps and emptiness
+        let checker = checker
+            .check(
+                vec!["2", "3"],
+                user_query().filter(EntityFilter::Overlaps(
+                    "drinks".into(),
+                    vec!["beer", "tea"].into(),
+                )),
+            )
+            .check(
+                vec![],
+                user_query().filter(EntityFilter::Overlaps(
+                    "drinks".into(),
+                    vec!["water"].into(),
+                )),
+            )
+            .check(
+                vec!["2", "3"],
+                user_query().filter(EntityFilter::Empty("drinks".into(), false)),
+            )
+            .check(
+                vec![],
+                user_query().filter(EntityFilter::Empty("drinks".into(), true)),
+            );
+
         // string attributes
         let checker = checker
             .check(
@@ -1297,6 +1349,61 @@ fn check_find() {
                     ))
                     .desc("name"),
             );
+
+        // string attributes, ignoring case
+        let checker = checker
+            .check(
+                vec!["2"],
+                user_query().filter(EntityFilter::EqualNoCase(
+                    "name".to_owned(),
+                    "cINDINI".into(),
+                )),
+            )
+            .check(
+                vec!["3", "1"],
+                user_query()
+                    .filter(EntityFilter::NotNoCase("name".to_owned(), "cindini".into()))
+                    .desc("name"),
+            )
+            .check(
+                vec!["3", "2"],
+                user_query()
+                    .filter(EntityFilter::InNoCase(
+                        "name".to_owned(),
+                        vec!["shaqueeena".into(), "CINDINI".into(), "nobody".into()],
+                    ))
+                    .desc("name"),
+            )
+            .check(
+                vec!["1"],
+                user_query().filter(EntityFilter::NotInNoCase(
+                    "name".to_owned(),
+                    vec!["SHAQUEEENA".into(), "cindini".into()],
+                )),
+            )
+            .check(
+                vec!["2"],
+                user_query().filter(EntityFilter::StartsWithNoCase(
+                    "name".to_owned(),
+                    "cIn".into(),
+                )),
+            );
+
+        // string attributes matching a regular expression
+        let checker = checker
+            .check(
+                vec!["3", "2"],
+                user_query()
+                    .filter(EntityFilter::Matches(
+                        "name".to_owned(),
+                        "^[CS].*a?$".into(),
+                    ))
+                    .desc("name"),
+            )
+            .check(
+                vec!["2"],
+                user_query().filter(EntityFilter::Matches("name".to_owned(), "in{1}i$".into())),
+            );
         // float attributes
         let checker = checker
             .check(
@@ -1813,3 +1920,62 @@ fn check_filters() {
             .check(vec![], filter_block_gte(BLOCK_NUMBER_MAX));
     });
 }
+
+#[test]
+fn export_csv() {
+    run_test(|conn, layout| {
+        let dir = std::env::temp_dir().join(format!("relational-export-{}", std::process::id()));
+        let export_at = |block: BlockNumber| {
+            let request = ExportRequest {
+                entity_types: vec!["Scalar".to_string()],
+                block: Some(block),
+                format: ExportFormat::Csv,
+                destination: dir.display().to_string(),
+            };
+            let files = export(conn, layout, &request, block).unwrap();
+            assert_eq!(1, files.len());
+            assert_eq!("Scalar", files[0].entity_type);
+            let text = std::fs::read_to_string(&files[0].path).unwrap();
+            (files[0].rows, text)
+        };
+
+        insert_entity(conn, layout, "Scalar", vec![SCALAR_ENTITY.clone()]);
+        let mut changed = SCALAR_ENTITY.clone();
+        changed.set("string", "changed");
+        update_entity_at(conn, layout, "Scalar", vec![changed], 2);
+
+        let (rows, text) = export_at(1);
+        assert_eq!(1, rows);
+        let mut lines = text.lines();
+        let header = lines.next().unwrap();
+        assert!(header.split(',').any(|field| field == "byteArray"));
+        let line = lines.next().unwrap();
+        assert!(lines.next().is_none());
+        assert!(line.contains(",scalar,"));
+        assert!(line.contains(&format!("0x{}", hex::encode(BYTES_VALUE.as_bytes()))));
+        // Lists of bytes are hex-encoded just like single bytes
+        assert!(line.contains(&format!(
+            "\"{{0x{},0x{},0x{}}}\"",
+            hex::encode(BYTES_VALUE.as_bytes()),
+            hex::encode(BYTES_VALUE2.as_bytes()),
+            hex::encode(BYTES_VALUE3.as_bytes())
+        )));
+
+        let (rows, text) = export_at(2);
+        assert_eq!(1, rows);
+        assert!(text.contains(",changed,"));
+        assert!(!text.contains(",scalar,"));
+        // Files are written under a temporary name and then renamed
+        assert!(!dir.join("Scalar.csv.tmp").exists());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let request = ExportRequest {
+            entity_types: vec!["NoEntity".to_string()],
+            block: None,
+            format: ExportFormat::Csv,
+            destination: dir.display().to_string(),
+        };
+        assert!(export(conn, layout, &request, 1).is_err());
+    });
+}
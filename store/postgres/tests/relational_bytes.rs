@@ -11,8 +11,8 @@ use std::{collections::BTreeMap, sync::Arc};
 
 use graph::prelude::{
     o, slog, web3::types::H256, AttributeNames, ChildMultiplicity, DeploymentHash, Entity,
-    EntityCollection, EntityKey, EntityLink, EntityOrder, EntityRange, EntityWindow, Logger,
-    ParentLink, Schema, StopwatchMetrics, Value, WindowAttribute, BLOCK_NUMBER_MAX,
+    EntityCollection, EntityFilter, EntityKey, EntityLink, EntityOrder, EntityRange, EntityWindow,
+    Logger, ParentLink, Schema, StopwatchMetrics, Value, WindowAttribute, BLOCK_NUMBER_MAX,
 };
 use graph::{
     components::store::EntityType,
@@ -30,6 +30,7 @@ const THINGS_GQL: &str = "
         id: Bytes!
         name: String!
         # We use these only in the EntityQuery tests
+        hash: Bytes
         parent: Thing
         children: [Thing!]
     }
@@ -365,23 +366,27 @@ fn make_thing_tree(conn: &PgConnection, layout: &Layout) -> (Entity, Entity, Ent
     let root = entity! {
         id: ROOT,
         name: "root",
+        hash: scalar::Bytes::from_str("0x01").unwrap(),
         children: vec!["babe01", "babe02"]
     };
     let child1 = entity! {
         id: CHILD1,
         name: "child1",
+        hash: scalar::Bytes::from_str("0x0200").unwrap(),
         parent: "dead00",
         children: vec![GRANDCHILD1]
     };
     let child2 = entity! {
         id: CHILD2,
         name: "child2",
+        hash: scalar::Bytes::from_str("0x02ff").unwrap(),
         parent: "dead00",
         children: vec![GRANDCHILD1]
     };
     let grand_child1 = entity! {
         id: GRANDCHILD1,
         name: "grandchild1",
+        hash: scalar::Bytes::from_str("0x03").unwrap(),
         parent: CHILD1
     };
     let grand_child2 = entity! {
@@ -519,3 +524,108 @@ fn query() {
         assert_eq!(vec![ROOT, ROOT], things);
     });
 }
+
+#[test]
+fn filter_and_paginate() {
+    fn fetch(
+        conn: &PgConnection,
+        layout: &Layout,
+        filter: EntityFilter,
+        first: u32,
+    ) -> Vec<String> {
+        layout
+            .query::<Entity>(
+                &*LOGGER,
+                conn,
+                EntityCollection::All(vec![(THING.clone(), AttributeNames::All)]),
+                Some(filter),
+                EntityOrder::Default,
+                EntityRange::first(first),
+                BLOCK_NUMBER_MAX,
+                None,
+            )
+            .expect("the query succeeds")
+            .into_iter()
+            .map(|e| e.id().expect("entities have an id"))
+            .collect::<Vec<_>>()
+    }
+
+    fn bytes(id: &str) -> Value {
+        Value::Bytes(scalar::Bytes::from_str(id).unwrap())
+    }
+
+    run_test(|conn, layout| {
+        make_thing_tree(conn, layout);
+
+        //   things(where: { id_in: [ROOT, GRANDCHILD2, "0xabcd"] }) { id }
+        let filter = EntityFilter::In(
+            "id".to_string(),
+            vec![bytes(ROOT), bytes(GRANDCHILD2), bytes("0xabcd")],
+        );
+        assert_eq!(vec![ROOT, GRANDCHILD2], fetch(conn, layout, filter, 10));
+
+        //   things(where: { id_not_in: [ROOT] }) { id }
+        let filter = EntityFilter::NotIn("id".to_string(), vec![bytes(ROOT)]);
+        assert_eq!(
+            vec![CHILD1, CHILD2, GRANDCHILD1, GRANDCHILD2],
+            fetch(conn, layout, filter, 10)
+        );
+
+        // Bytes attributes compare byte by byte, whether their values are
+        // compared in full or by their prefix
+        //   things(where: { hash_gt: "0x02" }) { id }
+        let filter = EntityFilter::GreaterThan("hash".to_string(), bytes("0x02"));
+        assert_eq!(
+            vec![CHILD1, CHILD2, GRANDCHILD1],
+            fetch(conn, layout, filter, 10)
+        );
+
+        //   things(where: { hash_lt: "0x02ff" }) { id }
+        let filter = EntityFilter::LessThan("hash".to_string(), bytes("0x02ff"));
+        assert_eq!(vec![CHILD1, ROOT], fetch(conn, layout, filter, 10));
+
+        //   things(where: { parent_lt: ROOT }) { id }
+        let filter = EntityFilter::LessThan("parent".to_string(), bytes(ROOT));
+        assert_eq!(
+            vec![GRANDCHILD1, GRANDCHILD2],
+            fetch(conn, layout, filter, 10)
+        );
+
+        //   things(where: { parent_gt: CHILD1 }) { id }
+        let filter = EntityFilter::GreaterThan("parent".to_string(), bytes(CHILD1));
+        assert_eq!(
+            vec![CHILD1, CHILD2, GRANDCHILD2],
+            fetch(conn, layout, filter, 10)
+        );
+
+        //   things(where: { parent_lte: CHILD1 }) { id }
+        let filter = EntityFilter::LessOrEqual("parent".to_string(), bytes(CHILD1));
+        assert_eq!(vec![GRANDCHILD1], fetch(conn, layout, filter, 10));
+
+        //   things(where: { parent_gte: ROOT }) { id }
+        let filter = EntityFilter::GreaterOrEqual("parent".to_string(), bytes(ROOT));
+        assert_eq!(vec![CHILD1, CHILD2], fetch(conn, layout, filter, 10));
+
+        // Page through all things two at a time by using the last id of
+        // each page as the cursor for the next one
+        let mut pages = Vec::new();
+        let mut last = bytes("0x");
+        loop {
+            let filter = EntityFilter::GreaterThan("id".to_string(), last.clone());
+            let page = fetch(conn, layout, filter, 2);
+            match page.last() {
+                Some(id) => last = bytes(id),
+                None => break,
+            }
+            pages.push(page);
+        }
+        assert_eq!(
+            vec![
+                vec![CHILD1, CHILD2],
+                vec![ROOT, GRANDCHILD1],
+                vec![GRANDCHILD2]
+            ],
+            pages
+        );
+    });
+}
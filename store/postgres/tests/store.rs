@@ -163,6 +163,7 @@ async fn insert_test_data(store: Arc<DieselSubgraphStore>) -> DeploymentLocator
         schema: TEST_SUBGRAPH_SCHEMA.clone(),
         data_sources: vec![],
         graft: None,
+        indexing_delay: None,
         templates: vec![],
         chain: PhantomData,
     };
@@ -309,6 +310,14 @@ fn get_entity_count(store: Arc<DieselStore>, subgraph_id: &DeploymentHash) -> u6
     info.entity_count
 }
 
+fn get_entity_size(store: Arc<DieselStore>, subgraph_id: &DeploymentHash) -> Option<u64> {
+    let info = store
+        .status(status::Filter::Deployments(vec![subgraph_id.to_string()]))
+        .unwrap();
+    let info = info.first().unwrap();
+    info.entity_size
+}
+
 #[test]
 fn delete_entity() {
     run_test(|store, writable, deployment| async move {
@@ -430,6 +439,49 @@ fn insert_entity() {
     })
 }
 
+#[test]
+fn entity_size_grows_with_writes() {
+    run_test(|store, _, deployment| async move {
+        let test_entity = create_test_entity(
+            "7",
+            USER,
+            "Wanjon",
+            "wanawana@email.com",
+            76 as i32,
+            111.7,
+            true,
+            Some("green"),
+        );
+        // Writing the test data determined the initial size
+        let size = get_entity_size(store.clone(), &deployment.hash).unwrap();
+        transact_and_wait(
+            &store.subgraph_store(),
+            &deployment,
+            TEST_BLOCK_3_PTR.clone(),
+            vec![test_entity],
+        )
+        .await
+        .unwrap();
+        let grown = get_entity_size(store.clone(), &deployment.hash).unwrap();
+        assert!(grown > size);
+
+        // Removing an entity does not free up space until it is vacuumed
+        let entity_key = EntityKey::data(deployment.hash.clone(), USER.to_owned(), "7".to_owned());
+        transact_and_wait(
+            &store.subgraph_store(),
+            &deployment,
+            TEST_BLOCK_4_PTR.clone(),
+            vec![EntityOperation::Remove { key: entity_key }],
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            Some(grown),
+            get_entity_size(store.clone(), &deployment.hash)
+        );
+    })
+}
+
 #[test]
 fn update_existing() {
     run_test(|store, writable, deployment| async move {
@@ -1272,6 +1324,89 @@ fn revert_block_with_dynamic_data_source_operations() {
     })
 }
 
+#[test]
+fn revert_block_with_dynamic_data_source_removal() {
+    run_test(|store, writable, deployment| async move {
+        let subgraph_store = store.subgraph_store();
+
+        let mut data_source = mock_data_source();
+        data_source.creation_block = Some(TEST_BLOCK_3_PTR.number);
+        let stored = data_source.as_stored_dynamic_data_source();
+        let mut removed = stored.clone();
+        removed.removed_block = Some(TEST_BLOCK_4_PTR.number);
+
+        // Create the data source in one block and remove it in the next
+        transact_entities_and_dynamic_data_sources(
+            &subgraph_store,
+            deployment.clone(),
+            TEST_BLOCK_3_PTR.clone(),
+            vec![stored],
+            vec![],
+        )
+        .await
+        .unwrap();
+        transact_entities_and_dynamic_data_sources(
+            &subgraph_store,
+            deployment.clone(),
+            TEST_BLOCK_4_PTR.clone(),
+            vec![removed],
+            vec![],
+        )
+        .await
+        .unwrap();
+        assert_eq!(0, writable.load_dynamic_data_sources().await.unwrap().len());
+
+        // Reverting the removal brings the data source back
+        revert_block(&store, &deployment, &*TEST_BLOCK_3_PTR).await;
+        let loaded_dds = writable.load_dynamic_data_sources().await.unwrap();
+        assert_eq!(1, loaded_dds.len());
+        assert_eq!(data_source.source, loaded_dds[0].source);
+
+        // Reverting the creation gets rid of it for good
+        revert_block(&store, &deployment, &*TEST_BLOCK_2_PTR).await;
+        assert_eq!(0, writable.load_dynamic_data_sources().await.unwrap().len());
+    })
+}
+
+#[test]
+fn dynamic_data_source_with_typed_context() {
+    run_test(|store, writable, deployment| async move {
+        let subgraph_store = store.subgraph_store();
+
+        let context = DataSourceContext::from(vec![
+            ("count", Value::from(scalar::BigInt::from(u64::MAX))),
+            (
+                "owner",
+                Value::from(scalar::Bytes::from_str("0x010203").unwrap()),
+            ),
+            ("active", Value::from(true)),
+            (
+                "tags",
+                Value::List(vec![Value::from("a"), Value::from("b")]),
+            ),
+        ]);
+        let mut data_source = mock_data_source();
+        data_source.context = Arc::new(Some(context.clone()));
+
+        transact_entities_and_dynamic_data_sources(
+            &subgraph_store,
+            deployment.clone(),
+            TEST_BLOCK_3_PTR.clone(),
+            vec![data_source.as_stored_dynamic_data_source()],
+            vec![],
+        )
+        .await
+        .unwrap();
+
+        // The context comes back with the types of its values intact
+        let loaded_dds = writable.load_dynamic_data_sources().await.unwrap();
+        assert_eq!(1, loaded_dds.len());
+        let loaded: DataSourceContext =
+            serde_json::from_str(loaded_dds[0].context.as_deref().unwrap()).unwrap();
+        assert_eq!(context, loaded);
+    })
+}
+
 #[test]
 fn entity_changes_are_fired_and_forwarded_to_subscriptions() {
     run_test(|store, _, _| async move {
@@ -1287,6 +1422,7 @@ fn entity_changes_are_fired_and_forwarded_to_subscriptions() {
             schema: schema.clone(),
             data_sources: vec![],
             graft: None,
+            indexing_delay: None,
             templates: vec![],
             chain: PhantomData,
         };
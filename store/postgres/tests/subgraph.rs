@@ -1,10 +1,12 @@
 use graph::{
     components::{
         server::index_node::VersionInfo,
-        store::{DeploymentLocator, StatusStore},
+        store::{DeploymentLocator, EntityKey, EntityOperation, EntityType, StatusStore},
     },
+    data::query::QueryTarget,
     data::subgraph::schema::SubgraphHealth,
     data::subgraph::schema::{DeploymentCreate, SubgraphError},
+    entity,
     prelude::EntityChange,
     prelude::EntityChangeOperation,
     prelude::QueryStoreManager,
@@ -14,13 +16,17 @@ use graph::{
     prelude::SubgraphVersionSwitchingMode,
     prelude::UnfailOutcome,
     prelude::{futures03, StoreEvent},
-    prelude::{CheapClone, DeploymentHash, NodeId, SubgraphStore as _},
+    prelude::{
+        AttributeNames, BlockNumber, EntityCollection, EntityOrder, EntityQuery, ValueType,
+        BLOCK_NUMBER_MAX,
+    },
+    prelude::{CheapClone, DeploymentHash, NodeId, SubgraphStore as _, ENV_VARS},
     semver::Version,
 };
 use graph_store_postgres::layout_for_tests::Connection as Primary;
-use graph_store_postgres::SubgraphStore;
+use graph_store_postgres::{unused, NodeRole, SubgraphStore, PRIMARY_SHARD};
 
-use std::{collections::HashSet, marker::PhantomData, sync::Arc};
+use std::{collections::HashSet, marker::PhantomData, sync::Arc, time::Duration};
 use test_store::*;
 
 const SUBGRAPH_GQL: &str = "
@@ -96,6 +102,100 @@ fn reassign_subgraph() {
     })
 }
 
+#[test]
+fn reassign_from_unresponsive_nodes() {
+    run_test_sequentially(|store| async move {
+        let id = DeploymentHash::new("reassignUnresponsive").unwrap();
+        remove_subgraphs();
+        let deployment = create_test_subgraph(&id, SUBGRAPH_GQL).await;
+        let store = store.subgraph_store();
+
+        let left = NodeId::new("left").unwrap();
+        store.reassign_subgraph(&deployment, &left).unwrap();
+        store.record_heartbeat(&left, NodeRole::Index).unwrap();
+        backdate("node_heartbeats", "heartbeat_at", 60);
+
+        // The deployment can only move to nodes that the deployment rules
+        // allow, and never to query nodes
+        let allowed = match place(id.as_str()).unwrap() {
+            Some((_, nodes)) => nodes,
+            None => vec![NodeId::new("right").unwrap()],
+        };
+        for node in &allowed {
+            store.record_heartbeat(node, NodeRole::Index).unwrap();
+        }
+        store
+            .record_heartbeat(&NodeId::new("query").unwrap(), NodeRole::Query)
+            .unwrap();
+
+        let moves = store
+            .reassign_from_unresponsive_nodes(Duration::from_secs(30))
+            .unwrap();
+        assert_eq!(1, moves.len());
+        let (hash, from, to) = &moves[0];
+        assert_eq!(&id, hash);
+        assert_eq!(&left, from);
+        assert!(allowed.contains(to));
+        assert_eq!(Some(to.clone()), store.assigned_node(&deployment).unwrap());
+
+        // Everything is on responsive nodes now
+        let moves = store
+            .reassign_from_unresponsive_nodes(Duration::from_secs(30))
+            .unwrap();
+        assert!(moves.is_empty());
+    })
+}
+
+#[test]
+fn recently_queried_deployments_are_used() {
+    fn detect_unused(store: &SubgraphStore) -> Vec<String> {
+        store
+            .record_unused_deployments()
+            .unwrap()
+            .into_iter()
+            .map(|detail| detail.deployment)
+            .collect()
+    }
+
+    run_test_sequentially(|store| async move {
+        let id = DeploymentHash::new("queriedUnused").unwrap();
+        remove_subgraphs();
+        create_test_subgraph(&id, SUBGRAPH_GQL).await;
+        let subgraph_store = store.subgraph_store();
+        subgraph_store
+            .remove_subgraph(SubgraphName::new(id.as_str()).unwrap())
+            .unwrap();
+
+        // No subgraph uses the deployment, but it was just queried
+        store
+            .query_store(QueryTarget::Deployment(id.clone()), false)
+            .await
+            .unwrap();
+        subgraph_store.record_query_activity().unwrap();
+        assert!(detect_unused(&subgraph_store).is_empty());
+
+        // Once the last query is long enough ago, it is unused
+        let interval = ENV_VARS.store.unused_query_interval.num_seconds();
+        backdate(
+            "deployment_query_activity",
+            "last_queried_at",
+            interval + 60,
+        );
+        assert_eq!(vec![id.to_string()], detect_unused(&subgraph_store));
+
+        // Querying it again takes it off the list of unused deployments
+        store
+            .query_store(QueryTarget::Deployment(id.clone()), false)
+            .await
+            .unwrap();
+        subgraph_store.record_query_activity().unwrap();
+        let listed = subgraph_store
+            .list_unused_deployments(unused::Filter::New)
+            .unwrap();
+        assert!(listed.iter().all(|unused| unused.deployment != id.as_str()));
+    })
+}
+
 #[test]
 fn create_subgraph() {
     const SUBGRAPH_NAME: &str = "create/subgraph";
@@ -133,6 +233,7 @@ fn create_subgraph() {
             schema: schema.clone(),
             data_sources: vec![],
             graft: None,
+            indexing_delay: None,
             templates: vec![],
             chain: PhantomData,
         };
@@ -1010,3 +1111,393 @@ fn fail_unfail_non_deterministic_error_noop() {
         test_store::remove_subgraphs();
     })
 }
+
+#[test]
+fn deployment_family() {
+    run_test_sequentially(|store| async move {
+        let store = store.subgraph_store();
+        let family = DeploymentHash::new("familyManifest").unwrap();
+        let mainnet = DeploymentHash::new("familyMainnet").unwrap();
+        let matic = DeploymentHash::new("familyMatic").unwrap();
+
+        store.add_to_family(&family, &matic, "matic").unwrap();
+        store.add_to_family(&family, &mainnet, "mainnet").unwrap();
+        // Adding a deployment again is not an error
+        store.add_to_family(&family, &mainnet, "mainnet").unwrap();
+
+        assert_eq!(
+            vec![
+                (mainnet, "mainnet".to_string()),
+                (matic, "matic".to_string())
+            ],
+            store.family(&family).unwrap()
+        );
+        let other = DeploymentHash::new("familyOther").unwrap();
+        assert!(store.family(&other).unwrap().is_empty());
+    })
+}
+
+#[test]
+fn snapshot_restore() {
+    run_test_sequentially(|store| async move {
+        let id = DeploymentHash::new("snapshotRestore").unwrap();
+        remove_subgraphs();
+        let deployment = create_test_subgraph(&id, SUBGRAPH_GQL).await;
+        let subgraph_store = store.subgraph_store();
+        let key = EntityKey::data(id.clone(), "User".to_owned(), "1".to_owned());
+        let user = entity! { id: "1", name: "Johnton" };
+        let op = EntityOperation::Set {
+            key: key.clone(),
+            data: user.clone(),
+        };
+        transact_and_wait(&subgraph_store, &deployment, BLOCKS[1].clone(), vec![op])
+            .await
+            .unwrap();
+
+        let dir = std::env::temp_dir().join(format!("snapshot-restore-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let dir_str = dir.to_str().unwrap();
+        let snapshot = subgraph_store.snapshot(&deployment, dir_str).unwrap();
+        assert_eq!(BLOCKS[1].number, snapshot.block.number);
+        remove_subgraph(&id);
+
+        // A restore that fails part of the way through leaves nothing
+        // behind, so that it can be run again
+        let users = dir.join("user.jsonl");
+        let rows = std::fs::read_to_string(&users).unwrap();
+        std::fs::write(&users, "not json\n").unwrap();
+        let node = NodeId::new("restore").unwrap();
+        assert!(subgraph_store
+            .restore(dir_str, PRIMARY_SHARD.clone(), node.clone())
+            .is_err());
+        assert!(subgraph_store.locators(id.as_str()).unwrap().is_empty());
+
+        std::fs::write(&users, rows).unwrap();
+        let (_, restored) = subgraph_store
+            .restore(dir_str, PRIMARY_SHARD.clone(), node.clone())
+            .unwrap();
+        assert_eq!(Some(node), subgraph_store.assigned_node(&restored).unwrap());
+        let writable = subgraph_store
+            .writable(LOGGER.clone(), restored.id)
+            .await
+            .unwrap();
+        assert_eq!(Some(BLOCKS[1].clone()), writable.block_ptr().await);
+        assert_eq!(Some(user), writable.get(&key).unwrap());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+        remove_subgraphs();
+    })
+}
+
+const JOURNAL_GQL: &str = "
+    type User @entity {
+        id: ID!,
+        name: String
+    }
+
+    type Event @entity(immutable: true) {
+        id: ID!
+    }
+";
+
+/// The ids of the entities of type `entity` as of `block`, together with
+/// their `name`
+fn names(hash: &DeploymentHash, entity: &str, block: BlockNumber) -> Vec<(String, Option<String>)> {
+    let query = EntityQuery::new(
+        hash.clone(),
+        block,
+        EntityCollection::All(vec![(EntityType::from(entity), AttributeNames::All)]),
+    )
+    .order(EntityOrder::Ascending("id".to_string(), ValueType::String));
+    STORE
+        .subgraph_store()
+        .find(query)
+        .unwrap()
+        .into_iter()
+        .map(|entity| {
+            let name = entity.get("name").and_then(|name| name.as_str());
+            (entity.id().unwrap(), name.map(str::to_string))
+        })
+        .collect()
+}
+
+#[test]
+fn journal_restore() {
+    fn set(hash: &DeploymentHash, entity: &str, id: &str, name: Option<&str>) -> EntityOperation {
+        let key = EntityKey::data(hash.clone(), entity.to_owned(), id.to_owned());
+        let data = match name {
+            Some(name) => entity! { id: id, name: name },
+            None => entity! { id: id },
+        };
+        EntityOperation::Set { key, data }
+    }
+
+    fn remove(hash: &DeploymentHash, id: &str) -> EntityOperation {
+        let key = EntityKey::data(hash.clone(), "User".to_owned(), id.to_owned());
+        EntityOperation::Remove { key }
+    }
+
+    fn user(id: &str, name: &str) -> (String, Option<String>) {
+        (id.to_string(), Some(name.to_string()))
+    }
+
+    fn event(id: &str) -> (String, Option<String>) {
+        (id.to_string(), None)
+    }
+
+    run_test_sequentially(|store| async move {
+        let id = DeploymentHash::new("journalRestore").unwrap();
+        remove_subgraphs();
+        let deployment = create_test_subgraph(&id, JOURNAL_GQL).await;
+        let subgraph_store = store.subgraph_store();
+        let root = std::env::temp_dir().join(format!("journal-restore-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        let snapshot_dir = root.join("snapshot");
+        let snapshot_dir = snapshot_dir.to_str().unwrap();
+        let journal_dir = root.join("journal");
+        let journal_dir = journal_dir.to_str().unwrap();
+
+        let ops = vec![
+            set(&id, "User", "1", Some("Johnton")),
+            set(&id, "User", "2", Some("Cindini")),
+            set(&id, "Event", "e0", None),
+        ];
+        transact_and_wait(&subgraph_store, &deployment, BLOCKS[0].clone(), ops)
+            .await
+            .unwrap();
+
+        // The journal starts after block 0, which the snapshot is taken at
+        let writer = NodeId::new("journal").unwrap();
+        assert!(subgraph_store
+            .start_change_journal(&deployment, &writer)
+            .unwrap());
+        assert!(!subgraph_store
+            .start_change_journal(&deployment, &writer)
+            .unwrap());
+        let snapshot = subgraph_store.snapshot(&deployment, snapshot_dir).unwrap();
+        assert_eq!(BLOCKS[0].number, snapshot.block.number);
+
+        let ops = vec![
+            set(&id, "User", "1", Some("Jonas")),
+            remove(&id, "2"),
+            set(&id, "Event", "e1", None),
+        ];
+        transact_and_wait(&subgraph_store, &deployment, BLOCKS[1].clone(), ops)
+            .await
+            .unwrap();
+        let ops = vec![
+            set(&id, "User", "1", Some("Joe")),
+            set(&id, "User", "3", Some("Shaqueeena")),
+        ];
+        transact_and_wait(&subgraph_store, &deployment, BLOCKS[2].clone(), ops)
+            .await
+            .unwrap();
+
+        let write = |node: &NodeId| {
+            subgraph_store
+                .write_change_journals(node, journal_dir, 0)
+                .unwrap()
+                .into_iter()
+                .map(|(hash, written)| {
+                    assert_eq!(id, hash);
+                    written
+                        .unwrap()
+                        .map(|path| path.file_name().unwrap().to_str().unwrap().to_string())
+                })
+                .collect::<Vec<_>>()
+        };
+
+        // Blocks within the reorg threshold are not journaled yet
+        assert_eq!(
+            vec![None],
+            subgraph_store
+                .write_change_journals(&writer, journal_dir, 2)
+                .unwrap()
+                .into_iter()
+                .map(|(_, written)| written.unwrap())
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(
+            vec![Some("0000000001-0000000002.json.zst".to_string())],
+            write(&writer)
+        );
+
+        // The node that indexes the deployment after it was reassigned does
+        // not write its journal, the node that journaled it so far does
+        let other = NodeId::new("other").unwrap();
+        subgraph_store
+            .reassign_subgraph(&deployment, &other)
+            .unwrap();
+        transact_and_wait(
+            &subgraph_store,
+            &deployment,
+            BLOCKS[3].clone(),
+            vec![remove(&id, "3")],
+        )
+        .await
+        .unwrap();
+        assert!(write(&other).is_empty());
+        assert_eq!(
+            vec![Some("0000000003-0000000003.json.zst".to_string())],
+            write(&writer)
+        );
+        assert_eq!(vec![None], write(&writer));
+
+        remove_subgraph(&id);
+        let (_, restored) = subgraph_store
+            .restore(
+                snapshot_dir,
+                PRIMARY_SHARD.clone(),
+                NodeId::new("restore").unwrap(),
+            )
+            .unwrap();
+
+        // Applying part of a batch leaves out the later changes in it and
+        // reopens the rows that were only closed later
+        assert_eq!(
+            1,
+            subgraph_store
+                .apply_change_journal(&restored, journal_dir, &BLOCKS[1])
+                .unwrap()
+        );
+        assert_eq!(
+            vec![user("1", "Jonas")],
+            names(&id, "User", BLOCK_NUMBER_MAX)
+        );
+        assert_eq!(
+            vec![user("1", "Johnton"), user("2", "Cindini")],
+            names(&id, "User", 0)
+        );
+        assert_eq!(
+            vec![event("e0"), event("e1")],
+            names(&id, "Event", BLOCK_NUMBER_MAX)
+        );
+
+        let err = subgraph_store
+            .apply_change_journal(&restored, journal_dir, &BLOCKS[1])
+            .unwrap_err();
+        assert!(err.to_string().contains("is already at block 1"));
+
+        assert_eq!(
+            2,
+            subgraph_store
+                .apply_change_journal(&restored, journal_dir, &BLOCKS[3])
+                .unwrap()
+        );
+        assert_eq!(vec![user("1", "Joe")], names(&id, "User", BLOCK_NUMBER_MAX));
+        assert_eq!(
+            vec![user("1", "Joe"), user("3", "Shaqueeena")],
+            names(&id, "User", 2)
+        );
+        assert_eq!(vec![user("1", "Jonas")], names(&id, "User", 1));
+        assert_eq!(
+            vec![event("e0"), event("e1")],
+            names(&id, "Event", BLOCK_NUMBER_MAX)
+        );
+
+        let writable = subgraph_store
+            .writable(LOGGER.clone(), restored.id)
+            .await
+            .unwrap();
+        assert_eq!(Some(BLOCKS[3].clone()), writable.block_ptr().await);
+
+        std::fs::remove_dir_all(&root).unwrap();
+        remove_subgraphs();
+    })
+}
+
+#[test]
+fn migrate_schema() {
+    const BASE_GQL: &str = "
+        enum Color { red }
+        type Thing @entity { id: ID!, color: Color }
+    ";
+    const NEW_GQL: &str = "
+        enum Color { red, blue }
+        type Thing @entity { id: ID!, color: Color, name: String }
+    ";
+
+    run_test_sequentially(|store| async move {
+        remove_subgraphs();
+        let id = DeploymentHash::new("migrateSchema").unwrap();
+        let deployment = create_test_subgraph(&id, BASE_GQL).await;
+        let subgraph_store = store.subgraph_store();
+
+        // An earlier attempt at the migration added the enum value and
+        // then failed
+        batch_execute(&format!(
+            "alter type sgd{}.\"color\" add value 'blue'",
+            deployment.id
+        ));
+
+        let schema = Schema::parse(NEW_GQL, id.clone()).unwrap();
+        subgraph_store.migrate_schema(&id, &schema).unwrap();
+        // Nothing is left to do the second time around
+        subgraph_store.migrate_schema(&id, &schema).unwrap();
+
+        let key = EntityKey::data(id.clone(), "Thing".to_owned(), "1".to_owned());
+        let data = entity! { id: "1", color: "blue", name: "sky" };
+        transact_and_wait(
+            &subgraph_store,
+            &deployment,
+            BLOCKS[1].clone(),
+            vec![EntityOperation::Set { key, data }],
+        )
+        .await
+        .unwrap();
+
+        let query = EntityQuery::new(
+            id.clone(),
+            BLOCK_NUMBER_MAX,
+            EntityCollection::All(vec![(EntityType::from("Thing"), AttributeNames::All)]),
+        );
+        let things = subgraph_store.find(query).unwrap();
+        assert_eq!(1, things.len());
+        assert_eq!(
+            Some("blue"),
+            things[0].get("color").and_then(|color| color.as_str())
+        );
+        assert_eq!(
+            Some("sky"),
+            things[0].get("name").and_then(|name| name.as_str())
+        );
+        remove_subgraphs();
+    })
+}
+
+#[test]
+fn upgrade_indexes() {
+    run_test_sequentially(|store| async move {
+        remove_subgraphs();
+        let id = DeploymentHash::new("upgradeIndexes").unwrap();
+        let deployment = create_test_subgraph(&id, SUBGRAPH_GQL).await;
+        let store = store.subgraph_store();
+        let nsp = format!("sgd{}", deployment.id);
+
+        // A new deployment already has the current indexes
+        let (created, dropped) = store.upgrade_indexes(&deployment).await.unwrap();
+        assert!(created.is_empty());
+        assert!(dropped.is_empty());
+
+        // Missing and invalid indexes are built, and generated indexes that
+        // are not used anymore are dropped
+        batch_execute(&format!(
+            "drop index {nsp}.attr_0_1_user_name;
+             update pg_index set indisvalid = false
+              where indexrelid = '{nsp}.brin_lower_user'::regclass;
+             create index attr_0_9_user_old on {nsp}.\"user\"(name);",
+            nsp = nsp
+        ));
+        let (created, dropped) = store.upgrade_indexes(&deployment).await.unwrap();
+        assert_eq!(
+            HashSet::from(["attr_0_1_user_name", "brin_lower_user"]),
+            created.iter().map(String::as_str).collect::<HashSet<_>>()
+        );
+        assert_eq!(vec!["attr_0_9_user_old".to_string()], dropped);
+
+        let (created, dropped) = store.upgrade_indexes(&deployment).await.unwrap();
+        assert!(created.is_empty());
+        assert!(dropped.is_empty());
+        remove_subgraphs();
+    })
+}
@@ -3,7 +3,7 @@ use lazy_static::lazy_static;
 use std::marker::PhantomData;
 use test_store::*;
 
-use graph::components::store::{DeploymentLocator, WritableStore};
+use graph::components::store::{DeploymentLocator, StatusStore, WritableStore};
 use graph::components::store::{EntityKey, EntityType};
 use graph::data::subgraph::*;
 use graph::prelude::*;
@@ -42,6 +42,7 @@ async fn insert_test_data(store: Arc<DieselSubgraphStore>) -> DeploymentLocator
         schema: TEST_SUBGRAPH_SCHEMA.clone(),
         data_sources: vec![],
         graft: None,
+        indexing_delay: None,
         templates: vec![],
         chain: PhantomData,
     };
@@ -165,3 +166,192 @@ fn tracker() {
         assert_eq!(2, read_count());
     })
 }
+
+#[test]
+fn write_queue_status() {
+    run_test(|store, _, deployment| async move {
+        let subgraph_store = store.subgraph_store();
+        let write_queue = || {
+            let filter = status::Filter::Deployments(vec![deployment.hash.to_string()]);
+            store.status(filter).unwrap().remove(0).write_queue.unwrap()
+        };
+
+        insert_count(&subgraph_store, &deployment, 1).await;
+        pause_writer(&deployment).await;
+        let queue = write_queue();
+        assert_eq!(0, queue.depth);
+        assert!(queue.last_write.is_some());
+
+        insert_count(&subgraph_store, &deployment, 2).await;
+        insert_count(&subgraph_store, &deployment, 3).await;
+        // The writer might already have been waiting for the first of
+        // these writes when we paused it, but it can't get to the second
+        let queue = write_queue();
+        assert!(queue.depth >= 1);
+        assert_eq!(queue.depth, queue.blocks);
+        assert_eq!(queue.depth, queue.entities);
+        assert!(!queue.throttled);
+
+        resume_writer(&deployment, 2).await;
+        assert_eq!(0, write_queue().depth);
+    })
+}
+
+#[test]
+fn rewind_past_pruned_history() {
+    run_test(|store, writable, deployment| async move {
+        let subgraph_store = store.subgraph_store();
+        let read_count = || {
+            let counter = writable.get(&count_key(&deployment, "1")).unwrap().unwrap();
+            counter.get("count").unwrap().as_int().unwrap()
+        };
+
+        // The counter is `n` at block `n`
+        for count in 1..=2 {
+            insert_count(&subgraph_store, &deployment, count).await;
+        }
+        flush(&deployment).await.unwrap();
+        let checkpoint = subgraph_store.create_checkpoint(&deployment).unwrap();
+        assert_eq!(block_pointer(2), checkpoint);
+        for count in 3..=8 {
+            insert_count(&subgraph_store, &deployment, count).await;
+        }
+        // Move far enough ahead that blocks before 4 can not be reverted
+        insert_count(&subgraph_store, &deployment, 255).await;
+        flush(&deployment).await.unwrap();
+
+        subgraph_store.partition(&deployment, 2, 0).unwrap();
+        let pruned = subgraph_store.prune_partitions(&deployment, 4).unwrap();
+        assert!(!pruned.is_empty());
+
+        // There is no checkpoint at or before block 1
+        assert!(subgraph_store
+            .rewind(deployment.hash.clone(), block_pointer(1))
+            .is_err());
+
+        // Rewinding to block 3 restores the checkpoint at block 2, and the
+        // version of the counter from then is current again
+        subgraph_store
+            .rewind(deployment.hash.clone(), block_pointer(3))
+            .unwrap();
+        let state = deployment_state(store.as_ref(), &deployment.hash).await;
+        assert_eq!(2, state.latest_ethereum_block_number);
+        assert_eq!(2, read_count());
+    })
+}
+
+#[test]
+fn partitioned_history() {
+    run_test(|store, writable, deployment| async move {
+        let subgraph_store = store.subgraph_store();
+        let count_at = |block: BlockNumber| {
+            let query = EntityQuery::new(
+                deployment.hash.clone(),
+                block,
+                EntityCollection::All(vec![(EntityType::from(COUNTER), AttributeNames::All)]),
+            );
+            subgraph_store.find(query).map(|entities| {
+                assert_eq!(1, entities.len());
+                entities[0].get("count").unwrap().as_int().unwrap()
+            })
+        };
+
+        // The counter is `n` at block `n`
+        for count in 1..=10 {
+            insert_count(&subgraph_store, &deployment, count).await;
+        }
+        flush(&deployment).await.unwrap();
+
+        let changed = subgraph_store.partition(&deployment, 2, 0).unwrap();
+        assert_eq!(vec!["counter".to_string()], changed);
+
+        // Time-travel queries find old versions in the history partitions
+        for block in 1..=10 {
+            assert_eq!(block, count_at(block).unwrap());
+        }
+        assert_eq!(10, count_at(BLOCK_NUMBER_MAX).unwrap());
+
+        // Reverting moves the version that becomes current again out of
+        // its history partition
+        writable
+            .revert_block_operations(block_pointer(6), None)
+            .await
+            .unwrap();
+        flush(&deployment).await.unwrap();
+        assert_eq!(6, count_at(BLOCK_NUMBER_MAX).unwrap());
+        assert_eq!(5, count_at(5).unwrap());
+
+        // Queries for blocks whose history has been pruned fail instead of
+        // returning wrong results
+        insert_count(&subgraph_store, &deployment, 255).await;
+        flush(&deployment).await.unwrap();
+        let pruned = subgraph_store.prune_partitions(&deployment, 4).unwrap();
+        assert!(!pruned.is_empty());
+        assert!(count_at(3).is_err());
+        assert_eq!(4, count_at(4).unwrap());
+        assert_eq!(255, count_at(BLOCK_NUMBER_MAX).unwrap());
+    })
+}
+
+#[test]
+fn concurrent_writer() {
+    run_test_sequentially(|store| async move {
+        let subgraph_store = store.subgraph_store();
+        remove_test_data(subgraph_store.clone());
+        let deployment = insert_test_data(subgraph_store.clone()).await;
+        let writable = subgraph_store
+            .cheap_clone()
+            .writable(LOGGER.clone(), deployment.id)
+            .await
+            .unwrap();
+        writable.start_subgraph_deployment(&LOGGER).await.unwrap();
+        insert_count(&subgraph_store, &deployment, 1).await;
+        writable.flush().await.unwrap();
+
+        // Another node can not start the deployment while it is assigned to
+        // us and we hold the claim
+        let other_node = NodeId::new("other").unwrap();
+        let other_store = subgraph_store_for_node(&other_node);
+        let other = other_store
+            .cheap_clone()
+            .writable(LOGGER.clone(), deployment.id)
+            .await
+            .unwrap();
+        match other.start_subgraph_deployment(&LOGGER).await {
+            Err(StoreError::ConcurrentWriter(hash, node)) => {
+                assert_eq!(deployment.hash, hash);
+                assert_eq!(Some(NODE_ID.clone()), node);
+            }
+            res => panic!("expected a concurrent writer error but got {:?}", res),
+        }
+
+        // Our writes are unaffected
+        insert_count(&subgraph_store, &deployment, 2).await;
+        writable.flush().await.unwrap();
+
+        // Once the deployment is reassigned, the other node takes it over
+        // and our next write fails without changing the assignment
+        subgraph_store
+            .reassign_subgraph(&deployment, &other_node)
+            .unwrap();
+        other.start_subgraph_deployment(&LOGGER).await.unwrap();
+        insert_count(&subgraph_store, &deployment, 3).await;
+        match writable.flush().await {
+            Err(StoreError::ConcurrentWriter(hash, node)) => {
+                assert_eq!(deployment.hash, hash);
+                assert_eq!(Some(other_node.clone()), node);
+            }
+            res => panic!("expected a concurrent writer error but got {:?}", res),
+        }
+        assert_eq!(
+            Some(other_node),
+            subgraph_store.assigned_node(&deployment).unwrap()
+        );
+
+        // The other node's writes are unaffected
+        insert_count(&other_store, &deployment, 3).await;
+        other.flush().await.unwrap();
+        let counter = other.get(&count_key(&deployment, "1")).unwrap().unwrap();
+        assert_eq!(3, counter.get("count").unwrap().as_int().unwrap());
+    })
+}
@@ -0,0 +1,100 @@
+//! Test that the write queue holds up writes once it contains as many
+//! entity changes as `GRAPH_STORE_WRITE_QUEUE_MAX_ENTITIES` allows. This
+//! needs its own test binary since the limit is read from the environment
+use std::time::Duration;
+
+use graph::components::store::{DeploymentLocator, EntityKey, EntityOperation, StatusStore as _};
+use graph::data::subgraph::status;
+use graph::entity;
+use graph::prelude::{tokio, DeploymentHash, SubgraphStore as _};
+use graph_store_postgres::layout_for_tests::writable;
+use test_store::*;
+
+const SCHEMA_GQL: &str = "
+    type Counter @entity {
+        id: ID!,
+        count: Int,
+    }
+";
+
+/// The write queue holds at most this many entity changes. This has to be
+/// set before anything reads `ENV_VARS`
+fn limit_entities() {
+    std::env::set_var("GRAPH_STORE_WRITE_QUEUE_MAX_ENTITIES", "2");
+}
+
+/// Two entity changes, which fill the queue
+fn changes(id: &DeploymentHash, count: i32) -> Vec<EntityOperation> {
+    ["1", "2"]
+        .iter()
+        .map(|counter| EntityOperation::Set {
+            key: EntityKey::data(id.clone(), "Counter".to_owned(), counter.to_string()),
+            data: entity! { id: *counter, count: count },
+        })
+        .collect()
+}
+
+fn write_queue(store: &Store, deployment: &DeploymentLocator) -> status::WriteQueueInfo {
+    let filter = status::Filter::Deployments(vec![deployment.hash.to_string()]);
+    store.status(filter).unwrap().remove(0).write_queue.unwrap()
+}
+
+#[test]
+fn push_waits_for_room_in_queue() {
+    limit_entities();
+    run_test_sequentially(|store| async move {
+        // The writer does not write anything until we let it
+        writable::allow_steps(0).await;
+
+        remove_subgraphs();
+        let id = DeploymentHash::new("writeQueueMaxEntities").unwrap();
+        let deployment = create_test_subgraph(&id, SCHEMA_GQL).await;
+        let subgraph_store = store.subgraph_store();
+
+        // The first block fills the queue, and the second one has to wait
+        // until the first one has been written
+        let writes = {
+            let subgraph_store = subgraph_store.clone();
+            let deployment = deployment.clone();
+            let id = id.clone();
+            tokio::spawn(async move {
+                for (block, count) in [(1, 1), (2, 2)] {
+                    transact_entity_operations(
+                        &subgraph_store,
+                        &deployment,
+                        BLOCKS[block].clone(),
+                        changes(&id, count),
+                    )
+                    .await
+                    .unwrap();
+                }
+            })
+        };
+
+        let mut queue = write_queue(&store, &deployment);
+        for _ in 0..50 {
+            if queue.throttled {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            queue = write_queue(&store, &deployment);
+        }
+        assert!(queue.throttled);
+        assert_eq!(1, queue.depth);
+        assert_eq!(2, queue.entities);
+
+        // Once the first block has been written, the second one is queued
+        writable::allow_steps(1).await;
+        tokio::time::timeout(Duration::from_secs(5), writes)
+            .await
+            .expect("the second block is queued once the first is written")
+            .unwrap();
+        let queue = write_queue(&store, &deployment);
+        assert!(!queue.throttled);
+        assert_eq!(1, queue.depth);
+
+        writable::allow_steps(1).await;
+        flush(&deployment).await.unwrap();
+        assert_eq!(0, write_queue(&store, &deployment).depth);
+    })
+}
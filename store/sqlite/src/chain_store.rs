@@ -0,0 +1,714 @@
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+
+use graph::blockchain::{Block, BlockHash, ChainIdentifier};
+use graph::cheap_clone::CheapClone;
+use graph::components::store::{
+    BlockStore as BlockStoreTrait, CachedEthereumCall, ChainStore as ChainStoreTrait,
+    EthereumCallCache, IndexedBlock,
+};
+use graph::components::transaction_receipt::LightTransactionReceipt;
+use graph::data::subgraph::status;
+use graph::prelude::anyhow::ensure;
+use graph::prelude::ethabi::Address;
+use graph::prelude::web3::types::{TransactionReceipt, H256};
+use graph::prelude::{anyhow, async_trait, serde_json, BlockNumber, BlockPtr, Error, StoreError};
+use rusqlite::{params, Connection, OptionalExtension};
+
+use crate::db::Database;
+use crate::events::ChainHeadUpdateListener;
+use crate::store_err;
+
+/// Read a row with `hash, number, parent_hash, timestamp` from `block_times`
+fn indexed_block(row: &rusqlite::Row) -> rusqlite::Result<IndexedBlock> {
+    Ok(IndexedBlock {
+        hash: H256::from_slice(&row.get::<_, Vec<u8>>(0)?),
+        number: row.get(1)?,
+        parent_hash: H256::from_slice(&row.get::<_, Vec<u8>>(2)?),
+        timestamp: row.get::<_, i64>(3)? as u64,
+    })
+}
+
+/// Make sure that the block data we return has a `block` entry, like the
+/// Postgres store does
+fn with_block_entry(data: serde_json::Value) -> serde_json::Value {
+    match data.get("block") {
+        Some(_) => data,
+        None => serde_json::json!({ "block": data, "transaction_receipts": [] }),
+    }
+}
+
+fn block_data(data: String) -> Result<serde_json::Value, StoreError> {
+    Ok(serde_json::from_str(&data)?)
+}
+
+/// The blocks and the call cache of one chain
+#[derive(Clone)]
+pub struct ChainStore {
+    db: Arc<Database>,
+    chain: String,
+    genesis_block_ptr: BlockPtr,
+    listener: Arc<ChainHeadUpdateListener>,
+}
+
+impl ChainStore {
+    fn upsert(&self, conn: &Connection, block: &dyn Block) -> Result<(), StoreError> {
+        let parent_hash = block.parent_hash().unwrap_or_else(BlockHash::zero);
+        let data = block.data()?;
+        conn.execute(
+            "insert or replace into blocks(chain, hash, number, parent_hash, data)
+             values (?1, ?2, ?3, ?4, ?5)",
+            params![
+                self.chain,
+                block.hash().as_slice(),
+                block.number(),
+                parent_hash.as_slice(),
+                data.to_string()
+            ],
+        )
+        .map_err(store_err)?;
+        if let Some(timestamp) = block.timestamp() {
+            conn.execute(
+                "insert or ignore into block_times(chain, hash, number, parent_hash, timestamp)
+                 values (?1, ?2, ?3, ?4, ?5)",
+                params![
+                    self.chain,
+                    block.hash().as_slice(),
+                    block.number(),
+                    parent_hash.as_slice(),
+                    i64::try_from(timestamp).unwrap_or(i64::MAX)
+                ],
+            )
+            .map_err(store_err)?;
+        }
+        Ok(())
+    }
+
+    fn head(&self, conn: &Connection) -> Result<Option<BlockPtr>, StoreError> {
+        let head = conn
+            .query_row(
+                "select head_hash, head_number from chains where name = ?1",
+                params![self.chain],
+                |row| Ok((row.get::<_, Option<Vec<u8>>>(0)?, row.get(1)?)),
+            )
+            .map_err(store_err)?;
+        Ok(match head {
+            (Some(hash), Some(number)) => Some(BlockPtr::new(BlockHash::from(hash), number)),
+            _ => None,
+        })
+    }
+
+    fn set_head(
+        &self,
+        conn: &Connection,
+        ptr: &BlockPtr,
+        cursor: Option<&str>,
+    ) -> Result<(), StoreError> {
+        conn.execute(
+            "update chains set head_hash = ?2, head_number = ?3, head_cursor = ?4
+              where name = ?1",
+            params![self.chain, ptr.hash_slice(), ptr.number, cursor],
+        )
+        .map(|_| ())
+        .map_err(store_err)
+    }
+
+    /// The number and parent hash of the block with `hash`
+    fn parent(
+        &self,
+        conn: &Connection,
+        hash: &[u8],
+    ) -> Result<Option<(BlockNumber, Vec<u8>)>, StoreError> {
+        conn.query_row(
+            "select number, parent_hash from blocks where chain = ?1 and hash = ?2",
+            params![self.chain, hash],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+        .optional()
+        .map_err(store_err)
+    }
+
+    pub(crate) fn block_parent_hash(&self, hash: H256) -> Result<Option<H256>, StoreError> {
+        self.db.with_conn(|conn| {
+            Ok(self
+                .parent(conn, hash.as_bytes())?
+                .map(|(_, parent)| H256::from_slice(&parent)))
+        })
+    }
+
+    pub(crate) fn head_number(&self) -> Result<Option<BlockNumber>, StoreError> {
+        self.db
+            .with_conn(|conn| Ok(self.head(conn)?.map(|head| head.number)))
+    }
+
+    fn data(&self, conn: &Connection, hash: &[u8]) -> Result<Option<String>, StoreError> {
+        conn.query_row(
+            "select data from blocks where chain = ?1 and hash = ?2",
+            params![self.chain, hash],
+            |row| row.get(0),
+        )
+        .optional()
+        .map_err(store_err)
+    }
+}
+
+#[async_trait]
+impl ChainStoreTrait for ChainStore {
+    fn genesis_block_ptr(&self) -> Result<BlockPtr, Error> {
+        Ok(self.genesis_block_ptr.clone())
+    }
+
+    async fn upsert_block(&self, block: Arc<dyn Block>) -> Result<(), Error> {
+        let store = self.clone();
+        Ok(self
+            .db
+            .spawn_with_conn(move |conn| store.upsert(conn, block.as_ref()))
+            .await?)
+    }
+
+    fn upsert_light_blocks(&self, blocks: &[&dyn Block]) -> Result<(), Error> {
+        Ok(self.db.transaction(|conn| {
+            for block in blocks {
+                self.upsert(conn, *block)?;
+            }
+            Ok(())
+        })?)
+    }
+
+    async fn attempt_chain_head_update(
+        self: Arc<Self>,
+        ancestor_count: BlockNumber,
+    ) -> Result<Option<H256>, Error> {
+        let store = self.cheap_clone();
+        let updated = self
+            .db
+            .spawn_transaction(move |conn| {
+                let head = store.head(conn)?.map(|ptr| ptr.number).unwrap_or(-1);
+                let candidate: Option<(Vec<u8>, BlockNumber)> = conn
+                    .query_row(
+                        "select hash, number from blocks
+                      where chain = ?1 and number > ?2
+                      order by number desc limit 1",
+                        params![store.chain, head],
+                        |row| Ok((row.get(0)?, row.get(1)?)),
+                    )
+                    .optional()
+                    .map_err(store_err)?;
+                let (hash, number) = match candidate {
+                    Some(candidate) => candidate,
+                    None => return Ok(Err(None)),
+                };
+
+                // Make sure the candidate's ancestors are all there
+                let mut ancestor = hash.clone();
+                for _ in 0..=ancestor_count {
+                    match store.parent(conn, &ancestor)? {
+                        Some((0, _)) => break,
+                        Some((_, parent_hash)) => ancestor = parent_hash,
+                        None => return Ok(Err(Some(H256::from_slice(&ancestor)))),
+                    }
+                }
+
+                store.set_head(conn, &BlockPtr::new(BlockHash::from(hash), number), None)?;
+                Ok(Ok(()))
+            })
+            .await?;
+
+        match updated {
+            Ok(()) => {
+                self.listener.notify(&self.chain);
+                Ok(None)
+            }
+            Err(missing) => Ok(missing),
+        }
+    }
+
+    async fn chain_head_ptr(self: Arc<Self>) -> Result<Option<BlockPtr>, Error> {
+        let store = self.cheap_clone();
+        Ok(self
+            .db
+            .spawn_with_conn(move |conn| store.head(conn))
+            .await?)
+    }
+
+    async fn cached_head_ptr(self: Arc<Self>) -> Result<Option<BlockPtr>, Error> {
+        self.chain_head_ptr().await
+    }
+
+    fn chain_head_cursor(&self) -> Result<Option<String>, Error> {
+        Ok(self.db.with_conn(|conn| {
+            conn.query_row(
+                "select head_cursor from chains where name = ?1",
+                params![self.chain],
+                |row| row.get(0),
+            )
+            .map_err(store_err)
+        })?)
+    }
+
+    async fn set_chain_head(
+        self: Arc<Self>,
+        block: Arc<dyn Block>,
+        cursor: String,
+    ) -> Result<(), Error> {
+        let store = self.cheap_clone();
+        self.db
+            .spawn_transaction(move |conn| {
+                store.upsert(conn, block.as_ref())?;
+                store.set_head(conn, &block.ptr(), Some(&cursor))
+            })
+            .await?;
+        self.listener.notify(&self.chain);
+        Ok(())
+    }
+
+    fn blocks(&self, hashes: &[H256]) -> Result<Vec<serde_json::Value>, Error> {
+        Ok(self.db.with_conn(|conn| {
+            let mut blocks = Vec::new();
+            for hash in hashes {
+                if let Some(data) = self.data(conn, hash.as_bytes())? {
+                    let data = block_data(data)?;
+                    // Return what is in the `block` entry if there is one
+                    blocks.push(match data.get("block") {
+                        Some(block) => block.clone(),
+                        None => data,
+                    });
+                }
+            }
+            Ok(blocks)
+        })?)
+    }
+
+    async fn ancestor_block(
+        self: Arc<Self>,
+        block_ptr: BlockPtr,
+        offset: BlockNumber,
+    ) -> Result<Option<serde_json::Value>, Error> {
+        ensure!(
+            block_ptr.number >= offset,
+            "block offset {} for block `{}` points to before genesis block",
+            offset,
+            block_ptr.hash_hex()
+        );
+
+        let store = self.cheap_clone();
+        Ok(self
+            .db
+            .spawn_with_conn(move |conn| {
+                let mut hash = block_ptr.hash_slice().to_vec();
+                for _ in 0..offset {
+                    match store.parent(conn, &hash)? {
+                        Some((_, parent_hash)) => hash = parent_hash,
+                        None => return Ok(None),
+                    }
+                }
+                store
+                    .data(conn, &hash)?
+                    .map(|data| block_data(data).map(with_block_entry))
+                    .transpose()
+            })
+            .await?)
+    }
+
+    fn cleanup_cached_blocks(
+        &self,
+        ancestor_count: BlockNumber,
+    ) -> Result<Option<(BlockNumber, usize)>, Error> {
+        // Keep the blocks that the slowest deployment that has not failed
+        // still needs, and `ancestor_count` blocks behind the chain head
+        Ok(self.db.transaction(|conn| {
+            let block: Option<BlockNumber> = conn
+                .query_row(
+                    "select min(min(d.latest_block_number),
+                                (select head_number - ?1 from chains where name = ?2))
+                       from deployments d
+                      where d.network = ?2 and d.node is not null
+                        and d.health != 'failed'",
+                    params![ancestor_count, self.chain],
+                    |row| row.get(0),
+                )
+                .map_err(store_err)?;
+            match block {
+                // Never remove the genesis block
+                Some(block) if block > 0 => {
+                    let deleted = conn
+                        .execute(
+                            "delete from blocks where chain = ?1 and number > 0 and number < ?2",
+                            params![self.chain, block],
+                        )
+                        .map_err(store_err)?;
+                    Ok(Some((block, deleted)))
+                }
+                _ => Ok(None),
+            }
+        })?)
+    }
+
+    fn block_hashes_by_block_number(&self, number: BlockNumber) -> Result<Vec<H256>, Error> {
+        Ok(self.db.with_conn(|conn| {
+            let mut stmt = conn
+                .prepare_cached("select hash from blocks where chain = ?1 and number = ?2")
+                .map_err(store_err)?;
+            let hashes = stmt
+                .query_map(params![self.chain, number], |row| row.get::<_, Vec<u8>>(0))
+                .and_then(|rows| rows.collect::<Result<Vec<_>, _>>())
+                .map_err(store_err)?;
+            Ok(hashes.iter().map(|hash| H256::from_slice(hash)).collect())
+        })?)
+    }
+
+    fn confirm_block_hash(&self, number: BlockNumber, hash: &H256) -> Result<usize, Error> {
+        Ok(self.db.with_conn(|conn| {
+            conn.execute(
+                "delete from block_times where chain = ?1 and number = ?2 and hash != ?3",
+                params![self.chain, number, hash.as_bytes()],
+            )
+            .map_err(store_err)?;
+            conn.execute(
+                "delete from blocks where chain = ?1 and number = ?2 and hash != ?3",
+                params![self.chain, number, hash.as_bytes()],
+            )
+            .map_err(store_err)
+        })?)
+    }
+
+    fn block_at_time(&self, timestamp: u64) -> Result<Option<BlockPtr>, StoreError> {
+        let timestamp = i64::try_from(timestamp).unwrap_or(i64::MAX);
+        self.db.with_conn(|conn| {
+            conn.query_row(
+                "select hash, number from block_times
+                  where chain = ?1 and timestamp <= ?2
+                  order by timestamp desc, number desc limit 1",
+                params![self.chain, timestamp],
+                |row| {
+                    Ok(BlockPtr::new(
+                        BlockHash::from(row.get::<_, Vec<u8>>(0)?),
+                        row.get(1)?,
+                    ))
+                },
+            )
+            .optional()
+            .map_err(store_err)
+        })
+    }
+
+    fn indexed_block(&self, hash: &H256) -> Result<Option<IndexedBlock>, StoreError> {
+        self.db.with_conn(|conn| {
+            conn.query_row(
+                "select hash, number, parent_hash, timestamp from block_times
+                  where chain = ?1 and hash = ?2",
+                params![self.chain, hash.as_bytes()],
+                indexed_block,
+            )
+            .optional()
+            .map_err(store_err)
+        })
+    }
+
+    fn indexed_blocks_by_number(
+        &self,
+        number: BlockNumber,
+    ) -> Result<Vec<IndexedBlock>, StoreError> {
+        self.db.with_conn(|conn| {
+            let mut stmt = conn
+                .prepare(
+                    "select hash, number, parent_hash, timestamp from block_times
+                      where chain = ?1 and number = ?2",
+                )
+                .map_err(store_err)?;
+            let blocks = stmt
+                .query_map(params![self.chain, number], indexed_block)
+                .map_err(store_err)?
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(store_err)?;
+            Ok(blocks)
+        })
+    }
+
+    fn block_number(&self, block_hash: H256) -> Result<Option<(String, BlockNumber)>, StoreError> {
+        self.db.with_conn(|conn| {
+            Ok(self
+                .parent(conn, block_hash.as_bytes())?
+                .map(|(number, _)| (self.chain.clone(), number)))
+        })
+    }
+
+    async fn transaction_receipts_in_block(
+        &self,
+        block_hash: &H256,
+    ) -> Result<Vec<LightTransactionReceipt>, StoreError> {
+        let store = self.clone();
+        let block_hash = *block_hash;
+        let data = match self
+            .db
+            .spawn_with_conn(move |conn| store.data(conn, block_hash.as_bytes()))
+            .await?
+        {
+            Some(data) => block_data(data)?,
+            None => return Ok(vec![]),
+        };
+        match data.get("transaction_receipts") {
+            Some(receipts) => {
+                let receipts: Vec<TransactionReceipt> = serde_json::from_value(receipts.clone())?;
+                Ok(receipts.into_iter().map(Into::into).collect())
+            }
+            None => Ok(vec![]),
+        }
+    }
+
+    fn contract_creation_block(&self, address: &[u8]) -> Result<Option<BlockNumber>, Error> {
+        Ok(self.db.with_conn(|conn| {
+            conn.query_row(
+                "select block_number from contract_creation_blocks
+                  where chain = ?1 and address = ?2",
+                params![self.chain, address],
+                |row| row.get(0),
+            )
+            .optional()
+            .map_err(store_err)
+        })?)
+    }
+
+    fn set_contract_creation_block(
+        &self,
+        address: &[u8],
+        number: BlockNumber,
+    ) -> Result<(), Error> {
+        Ok(self.db.with_conn(|conn| {
+            conn.execute(
+                "insert or replace into contract_creation_blocks(chain, address, block_number)
+                 values (?1, ?2, ?3)",
+                params![self.chain, address, number],
+            )
+            .map(|_| ())
+            .map_err(store_err)
+        })?)
+    }
+}
+
+fn contract_call_id(contract_address: &Address, encoded_call: &[u8], block: &BlockPtr) -> [u8; 32] {
+    let mut hash = blake3::Hasher::new();
+    hash.update(encoded_call);
+    hash.update(contract_address.as_ref());
+    hash.update(block.hash_slice());
+    *hash.finalize().as_bytes()
+}
+
+impl EthereumCallCache for ChainStore {
+    fn get_call(
+        &self,
+        contract_address: Address,
+        encoded_call: &[u8],
+        block: BlockPtr,
+    ) -> Result<Option<Vec<u8>>, Error> {
+        let id = contract_call_id(&contract_address, encoded_call, &block);
+        Ok(self.db.with_conn(|conn| {
+            conn.query_row(
+                "select return_value from call_cache where chain = ?1 and id = ?2",
+                params![self.chain, &id[..]],
+                |row| row.get(0),
+            )
+            .optional()
+            .map_err(store_err)
+        })?)
+    }
+
+    fn get_calls_in_block(&self, block: BlockPtr) -> Result<Vec<CachedEthereumCall>, Error> {
+        Ok(self.db.with_conn(|conn| {
+            let mut stmt = conn
+                .prepare_cached(
+                    "select id, contract_address, return_value from call_cache
+                      where chain = ?1 and block_hash = ?2",
+                )
+                .map_err(store_err)?;
+            let calls = stmt
+                .query_map(params![self.chain, block.hash_slice()], |row| {
+                    Ok((
+                        row.get::<_, Vec<u8>>(0)?,
+                        row.get::<_, Vec<u8>>(1)?,
+                        row.get::<_, Vec<u8>>(2)?,
+                    ))
+                })
+                .and_then(|rows| rows.collect::<Result<Vec<_>, _>>())
+                .map_err(store_err)?;
+            Ok(calls
+                .into_iter()
+                .map(|(id, address, return_value)| CachedEthereumCall {
+                    blake3_id: id,
+                    block_ptr: block.clone(),
+                    contract_address: Address::from_slice(&address),
+                    return_value,
+                })
+                .collect())
+        })?)
+    }
+
+    fn set_call(
+        &self,
+        contract_address: Address,
+        encoded_call: &[u8],
+        block: BlockPtr,
+        return_value: &[u8],
+    ) -> Result<(), Error> {
+        let id = contract_call_id(&contract_address, encoded_call, &block);
+        Ok(self.db.with_conn(|conn| {
+            conn.execute(
+                "insert or replace into
+                 call_cache(chain, id, contract_address, block_hash, block_number, return_value)
+                 values (?1, ?2, ?3, ?4, ?5, ?6)",
+                params![
+                    self.chain,
+                    &id[..],
+                    contract_address.as_bytes(),
+                    block.hash_slice(),
+                    block.number,
+                    return_value
+                ],
+            )
+            .map(|_| ())
+            .map_err(store_err)
+        })?)
+    }
+}
+
+/// The chain stores for all chains in the database
+pub struct BlockStore {
+    db: Arc<Database>,
+    listener: Arc<ChainHeadUpdateListener>,
+    stores: RwLock<HashMap<String, Arc<ChainStore>>>,
+}
+
+impl BlockStore {
+    pub fn new(db: Arc<Database>, listener: Arc<ChainHeadUpdateListener>) -> Self {
+        BlockStore {
+            db,
+            listener,
+            stores: RwLock::new(HashMap::new()),
+        }
+    }
+
+    /// Set up the store for the chain `name` with identifier `ident`.
+    /// Fails if the database already has blocks for a chain with that name
+    /// but a different identifier, since that usually means that the node
+    /// is now connected to a different chain than before
+    pub fn add_chain(
+        &self,
+        name: &str,
+        ident: &ChainIdentifier,
+    ) -> Result<Arc<ChainStore>, StoreError> {
+        let genesis = ident.genesis_block_hash.as_slice().to_vec();
+        self.db.with_conn(|conn| {
+            let existing: Option<(String, Vec<u8>)> = conn
+                .query_row(
+                    "select net_version, genesis_hash from chains where name = ?1",
+                    params![name],
+                    |row| Ok((row.get(0)?, row.get(1)?)),
+                )
+                .optional()
+                .map_err(store_err)?;
+            match existing {
+                Some((net_version, genesis_hash))
+                    if net_version != ident.net_version || genesis_hash != genesis =>
+                {
+                    Err(StoreError::Unknown(anyhow!(
+                        "the chain `{}` in the database has net_version {} and genesis block {} \
+                         but the provider reports net_version {} and genesis block {}; \
+                         remove the database file to index the new chain",
+                        name,
+                        net_version,
+                        BlockHash::from(genesis_hash),
+                        ident.net_version,
+                        ident.genesis_block_hash
+                    )))
+                }
+                Some(_) => Ok(()),
+                None => conn
+                    .execute(
+                        "insert into chains(name, net_version, genesis_hash) values (?1, ?2, ?3)",
+                        params![name, ident.net_version, genesis],
+                    )
+                    .map(|_| ())
+                    .map_err(store_err),
+            }
+        })?;
+
+        let store = Arc::new(ChainStore {
+            db: self.db.clone(),
+            chain: name.to_string(),
+            genesis_block_ptr: BlockPtr::new(ident.genesis_block_hash.clone(), 0),
+            listener: self.listener.clone(),
+        });
+        self.stores
+            .write()
+            .unwrap()
+            .insert(name.to_string(), store.clone());
+        Ok(store)
+    }
+
+    /// The head and earliest cached block of every chain
+    pub(crate) fn chain_statuses(&self) -> Result<Vec<status::ChainStatus>, StoreError> {
+        let heads = self.chain_head_pointers()?;
+        let earliest: Vec<(String, Option<Vec<u8>>, Option<BlockNumber>)> =
+            self.db.with_conn(|conn| {
+                // Sqlite takes `hash` from the row with the minimal number
+                let mut stmt = conn
+                    .prepare_cached(
+                        "select c.name, b.hash, min(b.number)
+                           from chains c left join blocks b on b.chain = c.name
+                          group by c.name
+                          order by c.name",
+                    )
+                    .map_err(store_err)?;
+                let rows = stmt
+                    .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+                    .and_then(|rows| rows.collect::<Result<Vec<_>, _>>())
+                    .map_err(store_err)?;
+                Ok(rows)
+            })?;
+        Ok(earliest
+            .into_iter()
+            .map(|(network, hash, number)| status::ChainStatus {
+                chain_head_block: heads.get(&network).cloned().map(Into::into),
+                earliest_block: hash
+                    .zip(number)
+                    .map(|(hash, number)| BlockPtr::new(BlockHash::from(hash), number).into()),
+                network,
+                providers: vec![],
+            })
+            .collect())
+    }
+
+    /// The head block number of every chain that has one
+    pub(crate) fn chain_head_pointers(&self) -> Result<HashMap<String, BlockPtr>, StoreError> {
+        self.db.with_conn(|conn| {
+            let mut stmt = conn
+                .prepare_cached(
+                    "select name, head_hash, head_number from chains
+                      where head_hash is not null",
+                )
+                .map_err(store_err)?;
+            let heads = stmt
+                .query_map([], |row| {
+                    Ok((
+                        row.get::<_, String>(0)?,
+                        row.get::<_, Vec<u8>>(1)?,
+                        row.get::<_, BlockNumber>(2)?,
+                    ))
+                })
+                .and_then(|rows| rows.collect::<Result<Vec<_>, _>>())
+                .map_err(store_err)?;
+            Ok(heads
+                .into_iter()
+                .map(|(name, hash, number)| (name, BlockPtr::new(BlockHash::from(hash), number)))
+                .collect())
+        })
+    }
+}
+
+impl BlockStoreTrait for BlockStore {
+    type ChainStore = ChainStore;
+
+    fn chain_store(&self, network: &str) -> Option<Arc<Self::ChainStore>> {
+        self.stores.read().unwrap().get(network).cloned()
+    }
+}
@@ -0,0 +1,264 @@
+//! The database file and the tables for metadata about subgraphs,
+//! deployments, and chains. Entity tables are described in `layout`
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use graph::cheap_clone::CheapClone;
+use graph::parking_lot::Mutex;
+use graph::prelude::tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use graph::prelude::StoreError;
+use rusqlite::{Connection, Transaction};
+
+use crate::store_err;
+
+const METADATA: &str = "
+create table if not exists subgraphs (
+    id              integer primary key,
+    name            text not null unique,
+    current_version integer,
+    pending_version integer,
+    created_at      integer not null
+);
+
+create table if not exists subgraph_versions (
+    id         integer primary key,
+    subgraph   integer not null references subgraphs(id) on delete cascade,
+    deployment text not null,
+    created_at integer not null
+);
+
+create table if not exists deployments (
+    id                    integer primary key,
+    hash                  text not null unique,
+    network               text not null,
+    node                  text,
+    schema                text not null,
+    spec_version          text not null,
+    description           text,
+    repository            text,
+    features              text not null,
+    earliest_block_hash   blob,
+    earliest_block_number integer not null default 0,
+    latest_block_hash     blob,
+    latest_block_number   integer,
+    cursor                text,
+    synced                integer not null default 0,
+    health                text not null default 'healthy',
+    fatal_error           integer,
+    reorg_count           integer not null default 0,
+    current_reorg_depth   integer not null default 0,
+    max_reorg_depth       integer not null default 0,
+    debug_fork            text,
+    restart_count         integer not null default 0,
+    created_at            integer not null
+);
+
+create table if not exists subgraph_errors (
+    id            integer primary key,
+    deployment    integer not null references deployments(id) on delete cascade,
+    message       text not null,
+    block_hash    blob,
+    block_number  integer,
+    handler       text,
+    deterministic integer not null
+);
+
+create table if not exists dynamic_data_sources (
+    id             integer primary key,
+    deployment     integer not null references deployments(id) on delete cascade,
+    name           text not null,
+    address        blob,
+    abi            text not null,
+    start_block    integer not null,
+    context        text,
+    creation_block integer,
+    block          integer not null,
+    removed_block  integer
+);
+
+create table if not exists chains (
+    name         text primary key,
+    net_version  text not null,
+    genesis_hash blob not null,
+    head_hash    blob,
+    head_number  integer,
+    head_cursor  text
+);
+
+create table if not exists blocks (
+    chain       text not null,
+    hash        blob not null,
+    number      integer not null,
+    parent_hash blob not null,
+    data        text not null,
+    primary key (chain, hash)
+);
+create index if not exists blocks_number on blocks(chain, number);
+
+create table if not exists block_times (
+    chain       text not null,
+    hash        blob not null,
+    number      integer not null,
+    parent_hash blob not null,
+    timestamp   integer not null,
+    primary key (chain, hash)
+);
+create index if not exists block_times_number on block_times(chain, number);
+create index if not exists block_times_timestamp on block_times(chain, timestamp, number);
+
+create table if not exists call_cache (
+    chain            text not null,
+    id               blob not null,
+    contract_address blob not null,
+    block_hash       blob not null,
+    block_number     integer not null,
+    return_value     blob not null,
+    primary key (chain, id)
+);
+create index if not exists call_cache_block on call_cache(chain, block_hash);
+
+create table if not exists contract_creation_blocks (
+    chain        text not null,
+    address      blob not null,
+    block_number integer not null,
+    primary key (chain, address)
+);
+
+create table if not exists deployment_families (
+    deployment text primary key,
+    family     text not null,
+    network    text not null
+);
+create index if not exists deployment_families_family on deployment_families(family);
+
+create table if not exists subgraph_deprecations (
+    name         text primary key,
+    message      text not null,
+    removal_date text not null
+);
+";
+
+/// The number of queries that may wait for the database at the same time
+const QUERY_PERMITS: usize = 10;
+
+/// The connection to the database file. All stores share it, and use it
+/// one at a time
+pub struct Database {
+    conn: Mutex<Connection>,
+    permits: Arc<Semaphore>,
+}
+
+impl Database {
+    /// Open the database in the file `path`, creating it and the metadata
+    /// tables if needed
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, StoreError> {
+        Self::new(Connection::open(path).map_err(store_err)?)
+    }
+
+    /// A database that only lives in memory and disappears when it is
+    /// dropped
+    pub fn in_memory() -> Result<Self, StoreError> {
+        Self::new(Connection::open_in_memory().map_err(store_err)?)
+    }
+
+    fn new(conn: Connection) -> Result<Self, StoreError> {
+        conn.execute_batch("pragma foreign_keys = on; pragma journal_mode = wal;")
+            .and_then(|()| conn.execute_batch(METADATA))
+            .and_then(|()| upgrade(&conn))
+            .map_err(store_err)?;
+        Ok(Database {
+            conn: Mutex::new(conn),
+            permits: Arc::new(Semaphore::new(QUERY_PERMITS)),
+        })
+    }
+
+    /// Run `f` with the connection
+    pub(crate) fn with_conn<T>(
+        &self,
+        f: impl FnOnce(&Connection) -> Result<T, StoreError>,
+    ) -> Result<T, StoreError> {
+        f(&self.conn.lock())
+    }
+
+    /// Run `f` in a transaction that is committed if `f` succeeds and
+    /// rolled back otherwise
+    pub(crate) fn transaction<T>(
+        &self,
+        f: impl FnOnce(&Transaction) -> Result<T, StoreError>,
+    ) -> Result<T, StoreError> {
+        let mut conn = self.conn.lock();
+        let txn = conn.transaction().map_err(store_err)?;
+        let res = f(&txn)?;
+        txn.commit().map_err(store_err)?;
+        Ok(res)
+    }
+
+    /// Run `f` with the connection on a thread that is allowed to block.
+    /// Async code must use this instead of `with_conn` since waiting for
+    /// the connection would otherwise stall the executor
+    pub(crate) async fn spawn_with_conn<T: Send + 'static>(
+        self: &Arc<Self>,
+        f: impl FnOnce(&Connection) -> Result<T, StoreError> + Send + 'static,
+    ) -> Result<T, StoreError> {
+        let db = self.cheap_clone();
+        graph::spawn_blocking_allow_panic(move || db.with_conn(f))
+            .await
+            .unwrap() // Propagate panics
+    }
+
+    /// Like `transaction`, but on a thread that is allowed to block
+    pub(crate) async fn spawn_transaction<T: Send + 'static>(
+        self: &Arc<Self>,
+        f: impl FnOnce(&Transaction) -> Result<T, StoreError> + Send + 'static,
+    ) -> Result<T, StoreError> {
+        let db = self.cheap_clone();
+        graph::spawn_blocking_allow_panic(move || db.transaction(f))
+            .await
+            .unwrap() // Propagate panics
+    }
+
+    pub(crate) async fn query_permit(&self) -> OwnedSemaphorePermit {
+        self.permits
+            .clone()
+            .acquire_owned()
+            .await
+            .expect("the query semaphore is never closed")
+    }
+}
+
+/// Add the columns that were added to the metadata tables after `conn`
+/// was created
+fn upgrade(conn: &Connection) -> rusqlite::Result<()> {
+    let has_debug_fork = conn
+        .prepare("select 1 from pragma_table_info('deployments') where name = 'debug_fork'")?
+        .exists([])?;
+    if !has_debug_fork {
+        conn.execute_batch("alter table deployments add column debug_fork text")?;
+    }
+    let has_restart_count = conn
+        .prepare("select 1 from pragma_table_info('deployments') where name = 'restart_count'")?
+        .exists([])?;
+    if !has_restart_count {
+        conn.execute_batch(
+            "alter table deployments add column restart_count integer not null default 0",
+        )?;
+    }
+    let has_removed_block = conn
+        .prepare(
+            "select 1 from pragma_table_info('dynamic_data_sources') where name = 'removed_block'",
+        )?
+        .exists([])?;
+    if !has_removed_block {
+        conn.execute_batch("alter table dynamic_data_sources add column removed_block integer")?;
+    }
+    Ok(())
+}
+
+/// The current time in seconds since the epoch
+pub(crate) fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0)
+}
@@ -0,0 +1,147 @@
+//! Notifications about store events and chain head updates. Since only
+//! one process uses the database file, they are passed around in memory
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::sync::Arc;
+
+use graph::blockchain::{
+    ChainHeadUpdateListener as ChainHeadUpdateListenerTrait, ChainHeadUpdateStream,
+};
+use graph::components::store::{
+    StoreEvent, StoreEventStream, StoreEventStreamBox, SubscriptionFilter,
+    SubscriptionManager as SubscriptionManagerTrait, UnitStream,
+};
+use graph::parking_lot::Mutex;
+use graph::prelude::futures03::{self, FutureExt, StreamExt, TryStreamExt};
+use graph::prelude::tokio::sync::{mpsc, watch};
+use graph::prelude::{debug, tokio, Logger, ENV_VARS};
+use graph::tokio_stream::wrappers::{UnboundedReceiverStream, WatchStream};
+
+/// A watch channel together with a receiver that keeps it open
+struct Watcher {
+    sender: watch::Sender<()>,
+    receiver: watch::Receiver<()>,
+}
+
+impl Watcher {
+    fn new() -> Self {
+        let (sender, receiver) = watch::channel(());
+        Watcher { sender, receiver }
+    }
+
+    fn send(&self) {
+        // Unwrap: `self` holds a receiver.
+        self.sender.send(()).unwrap()
+    }
+
+    /// Whether anybody other than `self` is listening
+    fn has_receivers(&self) -> bool {
+        self.sender.receiver_count() > 1
+    }
+}
+
+/// Forwards the events that the store produces to the subscriptions whose
+/// filters they match
+#[derive(Default)]
+pub struct SubscriptionManager {
+    subscriptions: Mutex<
+        Vec<(
+            BTreeSet<SubscriptionFilter>,
+            mpsc::UnboundedSender<Arc<StoreEvent>>,
+        )>,
+    >,
+    subscriptions_no_payload: Mutex<HashMap<BTreeSet<SubscriptionFilter>, Watcher>>,
+}
+
+impl SubscriptionManager {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Send `event` to all matching subscriptions, and forget the ones
+    /// that nobody listens to anymore
+    pub(crate) fn publish(&self, event: StoreEvent) {
+        if event.changes.is_empty() {
+            return;
+        }
+        let event = Arc::new(event);
+
+        self.subscriptions.lock().retain(|(filter, sender)| {
+            !event.matches(filter) || sender.send(event.clone()).is_ok()
+        });
+
+        let mut watchers = self.subscriptions_no_payload.lock();
+        watchers.retain(|_, watcher| watcher.has_receivers());
+        for (_, watcher) in watchers.iter().filter(|(filter, _)| event.matches(filter)) {
+            watcher.send();
+        }
+    }
+}
+
+impl SubscriptionManagerTrait for SubscriptionManager {
+    fn subscribe(&self, entities: BTreeSet<SubscriptionFilter>) -> StoreEventStreamBox {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        self.subscriptions.lock().push((entities.clone(), sender));
+
+        StoreEventStream::new(Box::new(
+            UnboundedReceiverStream::new(receiver).map(Ok).compat(),
+        ))
+        .filter_by_entities(entities)
+    }
+
+    fn subscribe_no_payload(&self, entities: BTreeSet<SubscriptionFilter>) -> UnitStream {
+        let receiver = self
+            .subscriptions_no_payload
+            .lock()
+            .entry(entities)
+            .or_insert_with(Watcher::new)
+            .receiver
+            .clone();
+        Box::new(WatchStream::new(receiver))
+    }
+}
+
+/// Tells block streams when the chain store has a new chain head
+#[derive(Default)]
+pub struct ChainHeadUpdateListener {
+    watchers: Mutex<BTreeMap<String, Watcher>>,
+}
+
+impl ChainHeadUpdateListener {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub(crate) fn notify(&self, network: &str) {
+        if let Some(watcher) = self.watchers.lock().get(network) {
+            watcher.send();
+        }
+    }
+}
+
+impl ChainHeadUpdateListenerTrait for ChainHeadUpdateListener {
+    fn subscribe(&self, network: String, logger: Logger) -> ChainHeadUpdateStream {
+        debug!(logger, "subscribing to chain head updates");
+
+        let receiver = self
+            .watchers
+            .lock()
+            .entry(network)
+            .or_insert_with(Watcher::new)
+            .receiver
+            .clone();
+
+        Box::new(futures03::stream::unfold(receiver, move |mut receiver| {
+            async move {
+                // Wake subscribers up periodically even if there was no
+                // update so that they poll the chain store
+                let _ = tokio::time::timeout(
+                    ENV_VARS.store.chain_head_watcher_timeout,
+                    receiver.changed(),
+                )
+                .await;
+                Some(((), receiver))
+            }
+            .boxed()
+        }))
+    }
+}
@@ -0,0 +1,505 @@
+//! The relational layout of entities. Each entity type of a deployment is
+//! stored in its own table with one column per attribute. A row is one
+//! version of an entity: `block$` is the block at which the version was
+//! written, and `until$` is the block at which it was replaced or removed,
+//! or `NULL` for the current version. Versions are therefore visible for
+//! the blocks in `[block$, until$)`, which is all we need to answer
+//! queries as of a block and to revert blocks.
+use std::collections::{BTreeSet, HashMap};
+use std::fmt::Display;
+use std::str::FromStr;
+
+use graph::components::store::{DeploymentId, EntityType};
+use graph::constraint_violation;
+use graph::data::graphql::ext::{DirectiveFinder, DocumentExt, ObjectTypeExt, TypeExt};
+use graph::data::store::scalar;
+use graph::data::subgraph::schema::POI_OBJECT;
+use graph::prelude::{
+    serde_json, BlockNumber, DeploymentHash, Entity, EntityKey, EntityOperation, Schema,
+    StoreError, Value, ValueType,
+};
+use rusqlite::types::{Value as SqlValue, ValueRef};
+use rusqlite::{params, params_from_iter, Connection, Row};
+
+use crate::store_err;
+
+/// Quote `name` so it can be used as an identifier in SQL
+pub(crate) fn quote(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+pub(crate) struct Column {
+    /// The name of the attribute, which is also the name of the column
+    pub name: String,
+    pub value_type: ValueType,
+    pub is_list: bool,
+}
+
+impl Column {
+    fn sql_type(&self) -> &'static str {
+        if self.is_list {
+            return "text";
+        }
+        match self.value_type {
+            ValueType::Boolean | ValueType::Int => "integer",
+            ValueType::BigInt | ValueType::BigDecimal | ValueType::String => "text",
+            ValueType::Bytes => "blob",
+        }
+    }
+
+    fn invalid(&self, e: impl Display) -> StoreError {
+        constraint_violation!("invalid value in column `{}`: {}", self.name, e)
+    }
+
+    fn decode(&self, value: ValueRef) -> Result<Value, StoreError> {
+        if matches!(value, ValueRef::Null) {
+            return Ok(Value::Null);
+        }
+
+        if self.is_list {
+            let text = value.as_str().map_err(|e| self.invalid(e))?;
+            return match serde_json::from_str(text).map_err(|e| self.invalid(e))? {
+                serde_json::Value::Array(elements) => elements
+                    .into_iter()
+                    .map(|element| self.decode_json(element))
+                    .collect::<Result<_, _>>()
+                    .map(Value::List),
+                other => Err(self.invalid(format!("expected a list but got {}", other))),
+            };
+        }
+
+        let value = match self.value_type {
+            ValueType::Boolean => Value::Bool(value.as_i64().map_err(|e| self.invalid(e))? != 0),
+            ValueType::Int => {
+                let value = value.as_i64().map_err(|e| self.invalid(e))?;
+                Value::Int(i32::try_from(value).map_err(|e| self.invalid(e))?)
+            }
+            ValueType::BigInt => Value::BigInt(
+                scalar::BigInt::from_str(value.as_str().map_err(|e| self.invalid(e))?)
+                    .map_err(|e| self.invalid(e))?,
+            ),
+            ValueType::BigDecimal => Value::BigDecimal(
+                scalar::BigDecimal::from_str(value.as_str().map_err(|e| self.invalid(e))?)
+                    .map_err(|e| self.invalid(e))?,
+            ),
+            ValueType::Bytes => Value::Bytes(scalar::Bytes::from(
+                value.as_blob().map_err(|e| self.invalid(e))?,
+            )),
+            ValueType::String => {
+                Value::String(value.as_str().map_err(|e| self.invalid(e))?.to_string())
+            }
+        };
+        Ok(value)
+    }
+
+    fn decode_json(&self, json: serde_json::Value) -> Result<Value, StoreError> {
+        use serde_json::Value as j;
+
+        let value = match (&self.value_type, json) {
+            (_, j::Null) => Value::Null,
+            (ValueType::Boolean, j::Bool(b)) => Value::Bool(b),
+            (ValueType::Int, j::Number(n)) => match n.as_i64().map(i32::try_from) {
+                Some(Ok(n)) => Value::Int(n),
+                _ => return Err(self.invalid(n)),
+            },
+            (ValueType::BigInt, j::String(s)) => {
+                Value::BigInt(scalar::BigInt::from_str(&s).map_err(|e| self.invalid(e))?)
+            }
+            (ValueType::BigDecimal, j::String(s)) => {
+                Value::BigDecimal(scalar::BigDecimal::from_str(&s).map_err(|e| self.invalid(e))?)
+            }
+            (ValueType::Bytes, j::String(s)) => {
+                Value::Bytes(scalar::Bytes::from_str(&s).map_err(|e| self.invalid(e))?)
+            }
+            (ValueType::String, j::String(s)) => Value::String(s),
+            (_, json) => return Err(self.invalid(json)),
+        };
+        Ok(value)
+    }
+}
+
+/// Convert `value` into what we store in its column. Scalars use the
+/// closest SQLite type, lists are stored as JSON arrays
+fn encode(value: &Value) -> SqlValue {
+    match value {
+        Value::Null => SqlValue::Null,
+        Value::String(s) => SqlValue::Text(s.clone()),
+        Value::Int(i) => SqlValue::Integer(*i as i64),
+        Value::Bool(b) => SqlValue::Integer(*b as i64),
+        Value::BigInt(n) => SqlValue::Text(n.to_string()),
+        Value::BigDecimal(d) => SqlValue::Text(d.to_string()),
+        Value::Bytes(b) => SqlValue::Blob(b.as_slice().to_vec()),
+        Value::List(_) => SqlValue::Text(encode_json(value).to_string()),
+    }
+}
+
+fn encode_json(value: &Value) -> serde_json::Value {
+    use serde_json::Value as j;
+
+    match value {
+        Value::Null => j::Null,
+        Value::String(s) => j::String(s.clone()),
+        Value::Int(i) => j::from(*i),
+        Value::Bool(b) => j::Bool(*b),
+        Value::BigInt(n) => j::String(n.to_string()),
+        Value::BigDecimal(d) => j::String(d.to_string()),
+        Value::Bytes(b) => j::String(b.to_string()),
+        Value::List(values) => j::Array(values.iter().map(encode_json).collect()),
+    }
+}
+
+/// The type of the values of attributes whose type is `name`. References
+/// to other entities have the type of the id of the referenced entity;
+/// enums are strings
+fn value_type(schema: &Schema, name: &str) -> ValueType {
+    if let Ok(value_type) = ValueType::from_str(name) {
+        return value_type;
+    }
+    let doc = &schema.document;
+    let id_field = doc
+        .get_object_type_definition(name)
+        .and_then(|object| object.field("id"))
+        .or_else(|| {
+            doc.find_interface(name)
+                .and_then(|interface| interface.field("id"))
+        });
+    id_field
+        .and_then(|field| ValueType::from_str(field.field_type.get_base_type()).ok())
+        .unwrap_or(ValueType::String)
+}
+
+/// One version of an entity, together with the block at which it was
+/// written
+#[derive(Clone)]
+pub(crate) struct Version {
+    pub block: BlockNumber,
+    pub entity: Entity,
+}
+
+pub(crate) struct Table {
+    pub entity_type: EntityType,
+    name: String,
+    pub columns: Vec<Column>,
+    id_type: ValueType,
+    /// The columns in the order in which `select` lists them, after `block$`
+    select: String,
+}
+
+impl Table {
+    fn new(deployment: DeploymentId, entity_type: EntityType, columns: Vec<Column>) -> Self {
+        let name = quote(&format!("sgd{}${}", deployment, entity_type));
+        let id_type = columns
+            .iter()
+            .find(|column| column.name == "id")
+            .map(|column| column.value_type.clone())
+            .unwrap_or(ValueType::String);
+        let select = columns
+            .iter()
+            .map(|column| quote(&column.name))
+            .collect::<Vec<_>>()
+            .join(", ");
+        Table {
+            entity_type,
+            name,
+            columns,
+            id_type,
+            select,
+        }
+    }
+
+    fn create(&self, conn: &Connection) -> Result<(), StoreError> {
+        let columns: Vec<_> = self
+            .columns
+            .iter()
+            .map(|column| format!("{} {}", quote(&column.name), column.sql_type()))
+            .collect();
+        let index = quote(&format!("{}_id", self.name.trim_matches('"')));
+        let ddl = format!(
+            "create table if not exists {table} (
+                \"vid$\"   integer primary key,
+                \"block$\" integer not null,
+                \"until$\" integer,
+                {columns});
+             create index if not exists {index} on {table}(\"id\", \"until$\");",
+            table = self.name,
+            columns = columns.join(",\n"),
+            index = index,
+        );
+        conn.execute_batch(&ddl).map_err(store_err)
+    }
+
+    fn id_value(&self, id: &str) -> Result<SqlValue, StoreError> {
+        match self.id_type {
+            ValueType::Bytes => scalar::Bytes::from_str(id)
+                .map(|bytes| SqlValue::Blob(bytes.as_slice().to_vec()))
+                .map_err(|e| StoreError::QueryExecutionError(format!("invalid id {}: {}", id, e))),
+            _ => Ok(SqlValue::Text(id.to_string())),
+        }
+    }
+
+    fn entity(&self, row: &Row) -> Result<Version, StoreError> {
+        let block = row.get(0).map_err(store_err)?;
+        let mut entity = Entity::new();
+        for (i, column) in self.columns.iter().enumerate() {
+            let value = column.decode(row.get_ref(i + 1).map_err(store_err)?)?;
+            if value != Value::Null {
+                entity.insert(column.name.clone(), value);
+            }
+        }
+        Ok(Version { block, entity })
+    }
+
+    fn load(
+        &self,
+        conn: &Connection,
+        condition: &str,
+        params: Vec<SqlValue>,
+    ) -> Result<Vec<Version>, StoreError> {
+        let query = format!(
+            "select \"block$\", {} from {} where {}",
+            self.select, self.name, condition
+        );
+        let mut stmt = conn.prepare_cached(&query).map_err(store_err)?;
+        let mut rows = stmt.query(params_from_iter(params)).map_err(store_err)?;
+        let mut versions = Vec::new();
+        while let Some(row) = rows.next().map_err(store_err)? {
+            versions.push(self.entity(row)?);
+        }
+        Ok(versions)
+    }
+
+    /// Write `entity` as the version that is valid from `block` on
+    pub fn insert(
+        &self,
+        conn: &Connection,
+        entity: &Entity,
+        block: BlockNumber,
+    ) -> Result<(), StoreError> {
+        let placeholders = vec!["?"; self.columns.len() + 1].join(", ");
+        let query = format!(
+            "insert into {}(\"block$\", {}) values ({})",
+            self.name, self.select, placeholders
+        );
+        let values = std::iter::once(SqlValue::Integer(block as i64)).chain(
+            self.columns.iter().map(|column| {
+                entity
+                    .get(&column.name)
+                    .map(encode)
+                    .unwrap_or(SqlValue::Null)
+            }),
+        );
+        conn.prepare_cached(&query)
+            .and_then(|mut stmt| stmt.execute(params_from_iter(values)))
+            .map(|_| ())
+            .map_err(store_err)
+    }
+
+    /// End the validity of the current version of the entity `id` at
+    /// `block`
+    pub fn clamp(&self, conn: &Connection, id: &str, block: BlockNumber) -> Result<(), StoreError> {
+        let query = format!(
+            "update {} set \"until$\" = ?1 where \"id\" = ?2 and \"until$\" is null",
+            self.name
+        );
+        let id = self.id_value(id)?;
+        conn.prepare_cached(&query)
+            .and_then(|mut stmt| stmt.execute(params![block, id]))
+            .map(|_| ())
+            .map_err(store_err)
+    }
+
+    /// The version of entity `id` that is visible at `block`
+    pub fn find(
+        &self,
+        conn: &Connection,
+        id: &str,
+        block: BlockNumber,
+    ) -> Result<Option<Entity>, StoreError> {
+        let versions = self.load(
+            conn,
+            "\"id\" = ?1 and \"block$\" <= ?2 and (\"until$\" is null or \"until$\" > ?2)",
+            vec![self.id_value(id)?, SqlValue::Integer(block as i64)],
+        )?;
+        Ok(versions.into_iter().next().map(|version| version.entity))
+    }
+
+    /// All versions that are visible at `block`
+    pub fn scan(&self, conn: &Connection, block: BlockNumber) -> Result<Vec<Version>, StoreError> {
+        self.load(
+            conn,
+            "\"block$\" <= ?1 and (\"until$\" is null or \"until$\" > ?1)",
+            vec![SqlValue::Integer(block as i64)],
+        )
+    }
+
+    /// Undo all changes made after `block`. Returns `true` if anything
+    /// changed
+    pub fn revert(&self, conn: &Connection, block: BlockNumber) -> Result<bool, StoreError> {
+        let deleted = conn
+            .execute(
+                &format!("delete from {} where \"block$\" > ?1", self.name),
+                params![block],
+            )
+            .map_err(store_err)?;
+        let reopened = conn
+            .execute(
+                &format!(
+                    "update {} set \"until$\" = null where \"until$\" > ?1",
+                    self.name
+                ),
+                params![block],
+            )
+            .map_err(store_err)?;
+        Ok(deleted + reopened > 0)
+    }
+
+    /// The changes that `block` made to entities in this table
+    pub fn changes_in_block(
+        &self,
+        conn: &Connection,
+        deployment: &DeploymentHash,
+        block: BlockNumber,
+    ) -> Result<Vec<EntityOperation>, StoreError> {
+        let key = |id: String| EntityKey {
+            subgraph_id: deployment.clone(),
+            entity_type: self.entity_type.clone(),
+            entity_id: id,
+        };
+
+        let mut changes = Vec::new();
+        for version in self.load(
+            conn,
+            "\"block$\" = ?1",
+            vec![SqlValue::Integer(block as i64)],
+        )? {
+            let id = version.entity.id().map_err(StoreError::Unknown)?;
+            changes.push(EntityOperation::Set {
+                key: key(id),
+                data: version.entity,
+            });
+        }
+        let removed = self.load(
+            conn,
+            &format!(
+                "\"until$\" = ?1 and \"id\" not in (select \"id\" from {} where \"block$\" = ?1)",
+                self.name
+            ),
+            vec![SqlValue::Integer(block as i64)],
+        )?;
+        for version in removed {
+            let id = version.entity.id().map_err(StoreError::Unknown)?;
+            changes.push(EntityOperation::Remove { key: key(id) });
+        }
+        Ok(changes)
+    }
+
+    /// The blocks in `(after, until]` in which entities in this table
+    /// changed
+    pub fn changed_blocks(
+        &self,
+        conn: &Connection,
+        after: BlockNumber,
+        until: BlockNumber,
+    ) -> Result<BTreeSet<BlockNumber>, StoreError> {
+        let query = format!(
+            "select \"block$\" from {table} where \"block$\" > ?1 and \"block$\" <= ?2
+             union
+             select \"until$\" from {table} where \"until$\" > ?1 and \"until$\" <= ?2",
+            table = self.name
+        );
+        let mut stmt = conn.prepare_cached(&query).map_err(store_err)?;
+        let blocks = stmt
+            .query_map(params![after, until], |row| row.get(0))
+            .and_then(|rows| rows.collect::<Result<BTreeSet<BlockNumber>, _>>())
+            .map_err(store_err)?;
+        Ok(blocks)
+    }
+
+    /// The number of entities at the latest block
+    pub fn count(&self, conn: &Connection) -> Result<u64, StoreError> {
+        conn.query_row(
+            &format!(
+                "select count(*) from {} where \"until$\" is null",
+                self.name
+            ),
+            [],
+            |row| row.get::<_, i64>(0),
+        )
+        .map(|count| count as u64)
+        .map_err(store_err)
+    }
+}
+
+pub(crate) struct Layout {
+    pub deployment: DeploymentHash,
+    pub tables: HashMap<EntityType, Table>,
+    /// The digests of the proof of indexing. They are kept apart from the
+    /// entities of the subgraph so that they don't show up in entity counts
+    /// and changes
+    pub poi: Table,
+}
+
+impl Layout {
+    /// The layout for the entity types in `schema`. Derived fields are not
+    /// stored, and neither are the internal types whose names start with
+    /// an underscore
+    pub fn new(id: DeploymentId, deployment: DeploymentHash, schema: &Schema) -> Self {
+        let tables = schema
+            .document
+            .get_object_type_definitions()
+            .into_iter()
+            .filter(|object| !object.name.starts_with('_'))
+            .map(|object| {
+                let columns = object
+                    .fields
+                    .iter()
+                    .filter(|field| !field.is_derived())
+                    .map(|field| Column {
+                        name: field.name.clone(),
+                        value_type: value_type(schema, field.field_type.get_base_type()),
+                        is_list: field.field_type.is_list(),
+                    })
+                    .collect();
+                let entity_type = EntityType::new(object.name.clone());
+                (entity_type.clone(), Table::new(id, entity_type, columns))
+            })
+            .collect();
+        let poi = Table::new(
+            id,
+            POI_OBJECT.clone(),
+            vec![
+                Column {
+                    name: "id".to_string(),
+                    value_type: ValueType::String,
+                    is_list: false,
+                },
+                Column {
+                    name: "digest".to_string(),
+                    value_type: ValueType::Bytes,
+                    is_list: false,
+                },
+            ],
+        );
+        Layout {
+            deployment,
+            tables,
+            poi,
+        }
+    }
+
+    pub fn create_tables(&self, conn: &Connection) -> Result<(), StoreError> {
+        for table in self.tables.values().chain(std::iter::once(&self.poi)) {
+            table.create(conn)?;
+        }
+        Ok(())
+    }
+
+    pub fn table(&self, entity_type: &EntityType) -> Result<&Table, StoreError> {
+        if entity_type == &*POI_OBJECT {
+            return Ok(&self.poi);
+        }
+        self.tables
+            .get(entity_type)
+            .ok_or_else(|| StoreError::UnknownTable(entity_type.to_string()))
+    }
+}
@@ -0,0 +1,31 @@
+//! A store that keeps all chain and subgraph data in a single SQLite file.
+//! It is meant for running `graph-node --dev` on a developer's machine
+//! without setting up Postgres, and supports what is needed to index and
+//! query a handful of subgraphs: entities are stored in a relational
+//! layout with one table per entity type, and blocks can be reverted.
+//! Sharding, replicas, grafting, fulltext search, and proofs of indexing
+//! are not supported.
+
+mod chain_store;
+mod db;
+mod events;
+mod layout;
+mod query;
+mod query_store;
+mod store;
+mod subgraph_store;
+mod writable;
+
+pub use self::chain_store::{BlockStore, ChainStore};
+pub use self::db::Database;
+pub use self::events::{ChainHeadUpdateListener, SubscriptionManager};
+pub use self::query_store::QueryStore;
+pub use self::store::Store;
+pub use self::subgraph_store::SubgraphStore;
+pub use self::writable::WritableStore;
+
+use graph::prelude::StoreError;
+
+fn store_err(e: rusqlite::Error) -> StoreError {
+    StoreError::Unknown(e.into())
+}
@@ -0,0 +1,581 @@
+//! Answer an `EntityQuery` by loading the entities that are visible at the
+//! block of the query, and filtering, ordering, and windowing them in
+//! memory. That keeps the SQL simple and is fast enough for the amounts of
+//! data that subgraphs have during local development
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashMap};
+use std::str::FromStr;
+
+use graph::components::store::EntityType;
+use graph::prelude::{
+    r, BigDecimal, BlockNumber, EntityCollection, EntityFilter, EntityLink, EntityOrder,
+    EntityQuery, EntityRange, EntityWindow, ParentLink, StoreError, Value, WindowAttribute,
+};
+use rusqlite::Connection;
+
+use crate::layout::{Layout, Version};
+
+/// The attribute in which queries over windows return the id of the parent
+const PARENT_ID: &str = "g$parent_id";
+
+struct Candidate {
+    entity_type: EntityType,
+    version: Version,
+    parent_id: Option<String>,
+}
+
+impl Candidate {
+    fn get(&self, attr: &str) -> &Value {
+        self.version.entity.get(attr).unwrap_or(&Value::Null)
+    }
+
+    fn into_row(self) -> BTreeMap<String, r::Value> {
+        let mut row = BTreeMap::new();
+        row.insert(
+            "__typename".to_string(),
+            r::Value::String(self.entity_type.into_string()),
+        );
+        for (attr, value) in self.version.entity.sorted() {
+            if value != Value::Null {
+                row.insert(attr, value.into());
+            }
+        }
+        if let Some(parent_id) = self.parent_id {
+            row.insert(PARENT_ID.to_string(), r::Value::String(parent_id));
+        }
+        row
+    }
+}
+
+pub(crate) fn execute(
+    conn: &Connection,
+    layout: &Layout,
+    query: EntityQuery,
+) -> Result<Vec<BTreeMap<String, r::Value>>, StoreError> {
+    let EntityQuery {
+        block,
+        collection,
+        filter,
+        order,
+        range,
+        ..
+    } = query;
+    let mut regexes = Regexes::new();
+    if let Some(filter) = &filter {
+        compile_regexes(filter, &mut regexes)?;
+    }
+    let matches = |candidate: &Candidate| {
+        filter
+            .as_ref()
+            .map_or(true, |filter| matches(filter, candidate, &regexes))
+    };
+
+    let rows = match collection {
+        EntityCollection::All(entity_types) => {
+            let mut candidates = Vec::new();
+            for (entity_type, _) in entity_types {
+                for version in layout.table(&entity_type)?.scan(conn, block)? {
+                    candidates.push(Candidate {
+                        entity_type: entity_type.clone(),
+                        version,
+                        parent_id: None,
+                    });
+                }
+            }
+            candidates.retain(matches);
+            sort(&mut candidates, &order);
+            limit(candidates, &range)
+        }
+        EntityCollection::Window(windows) => {
+            // Windows are grouped by the parent across all windows, and
+            // order and range apply to each group
+            let mut groups: BTreeMap<String, Vec<Candidate>> = BTreeMap::new();
+            for window in windows {
+                for candidate in window_children(conn, layout, &window, block)? {
+                    if matches(&candidate) {
+                        let parent_id = candidate.parent_id.clone().unwrap_or_default();
+                        groups.entry(parent_id).or_default().push(candidate);
+                    }
+                }
+            }
+            groups
+                .into_values()
+                .flat_map(|mut candidates| {
+                    sort(&mut candidates, &order);
+                    limit(candidates, &range)
+                })
+                .collect()
+        }
+    };
+    Ok(rows.into_iter().map(Candidate::into_row).collect())
+}
+
+/// The children of the parents in `window`, each marked with the id of its
+/// parent. A child that belongs to several parents is returned once for
+/// each of them
+fn window_children(
+    conn: &Connection,
+    layout: &Layout,
+    window: &EntityWindow,
+    block: BlockNumber,
+) -> Result<Vec<Candidate>, StoreError> {
+    let versions = layout.table(&window.child_type)?.scan(conn, block)?;
+    let child = |version: &Version, parent_id: &str| Candidate {
+        entity_type: window.child_type.clone(),
+        version: version.clone(),
+        parent_id: Some(parent_id.to_string()),
+    };
+
+    let mut children = Vec::new();
+    match &window.link {
+        EntityLink::Direct(attribute, _) => {
+            for version in &versions {
+                let value = version.entity.get(attribute.name()).unwrap_or(&Value::Null);
+                for parent_id in &window.ids {
+                    let is_child = match (attribute, value) {
+                        (WindowAttribute::Scalar(_), value) => refers_to(value, parent_id),
+                        (WindowAttribute::List(_), Value::List(values)) => {
+                            values.iter().any(|value| refers_to(value, parent_id))
+                        }
+                        (WindowAttribute::List(_), _) => false,
+                    };
+                    if is_child {
+                        children.push(child(version, parent_id));
+                    }
+                }
+            }
+        }
+        EntityLink::Parent(link) => {
+            let by_id: HashMap<_, _> = versions
+                .iter()
+                .filter_map(|version| version.entity.id().ok().map(|id| (id, version)))
+                .collect();
+            let child_ids: Vec<Vec<&String>> = match link {
+                ParentLink::List(ids) => ids.iter().map(|ids| ids.iter().collect()).collect(),
+                ParentLink::Scalar(ids) => ids.iter().map(|id| vec![id]).collect(),
+            };
+            for (parent_id, child_ids) in window.ids.iter().zip(child_ids) {
+                for child_id in child_ids {
+                    if let Some(version) = by_id.get(child_id) {
+                        children.push(child(version, parent_id));
+                    }
+                }
+            }
+        }
+    }
+    Ok(children)
+}
+
+/// Whether `value` is a reference to the entity with id `id`
+fn refers_to(value: &Value, id: &str) -> bool {
+    match value {
+        Value::String(s) => s == id,
+        Value::Bytes(b) => b.to_string() == id,
+        _ => false,
+    }
+}
+
+fn decimal(value: &Value) -> Option<BigDecimal> {
+    match value {
+        Value::Int(i) => Some(BigDecimal::from(*i)),
+        Value::BigInt(n) => BigDecimal::from_str(&n.to_string()).ok(),
+        Value::BigDecimal(d) => Some(d.clone()),
+        _ => None,
+    }
+}
+
+/// Compare two values of the same type; numbers of different types are
+/// compared by their value. Returns `None` for values that can not be
+/// compared, which includes `Null`
+fn compare(a: &Value, b: &Value) -> Option<Ordering> {
+    use Value::*;
+
+    match (a, b) {
+        (String(a), String(b)) => Some(a.cmp(b)),
+        (Int(a), Int(b)) => Some(a.cmp(b)),
+        (Bool(a), Bool(b)) => Some(a.cmp(b)),
+        (BigInt(a), BigInt(b)) => Some(a.cmp(b)),
+        (BigDecimal(a), BigDecimal(b)) => Some(a.cmp(b)),
+        (Bytes(a), Bytes(b)) => Some(a.as_slice().cmp(b.as_slice())),
+        (Int(_) | BigInt(_) | BigDecimal(_), Int(_) | BigInt(_) | BigDecimal(_)) => {
+            Some(decimal(a)?.cmp(&decimal(b)?))
+        }
+        _ => None,
+    }
+}
+
+fn equals(a: &Value, b: &Value) -> bool {
+    match (a, b) {
+        (Value::List(a), Value::List(b)) => {
+            a.len() == b.len() && a.iter().zip(b).all(|(a, b)| equals(a, b))
+        }
+        (a, b) => compare(a, b) == Some(Ordering::Equal),
+    }
+}
+
+fn lowercase(value: &Value) -> Value {
+    match value {
+        Value::String(s) => Value::String(s.to_lowercase()),
+        Value::List(values) => Value::List(values.iter().map(lowercase).collect()),
+        value => value.clone(),
+    }
+}
+
+fn contains(haystack: &Value, needle: &Value) -> bool {
+    match (haystack, needle) {
+        (Value::String(haystack), Value::String(needle)) => haystack.contains(needle.as_str()),
+        (Value::Bytes(haystack), Value::Bytes(needle)) => {
+            let (haystack, needle) = (haystack.as_slice(), needle.as_slice());
+            needle.is_empty() || haystack.windows(needle.len()).any(|w| w == needle)
+        }
+        (Value::List(haystack), Value::List(needles)) => needles
+            .iter()
+            .all(|needle| haystack.iter().any(|value| equals(value, needle))),
+        (Value::List(haystack), needle) => haystack.iter().any(|value| equals(value, needle)),
+        _ => false,
+    }
+}
+
+fn overlaps(value: &Value, other: &Value) -> bool {
+    match (value, other) {
+        (Value::List(values), Value::List(others)) => values
+            .iter()
+            .any(|value| others.iter().any(|other| equals(value, other))),
+        _ => false,
+    }
+}
+
+/// The regular expressions of the `_matches` filters of a query, by their
+/// pattern, so that each of them is only compiled once per query
+type Regexes = HashMap<String, regex::Regex>;
+
+/// Compile the patterns of all `_matches` filters in `filter`. Like
+/// Postgres, we reject queries with an invalid pattern
+fn compile_regexes(filter: &EntityFilter, regexes: &mut Regexes) -> Result<(), StoreError> {
+    match filter {
+        EntityFilter::And(filters) | EntityFilter::Or(filters) => {
+            for filter in filters {
+                compile_regexes(filter, regexes)?;
+            }
+        }
+        EntityFilter::Matches(_, Value::String(pattern)) if !regexes.contains_key(pattern) => {
+            let regex = regex::Regex::new(pattern).map_err(|e| {
+                StoreError::QueryExecutionError(format!(
+                    "invalid regular expression `{}`: {}",
+                    pattern, e
+                ))
+            })?;
+            regexes.insert(pattern.clone(), regex);
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+fn starts_with(value: &Value, prefix: &Value) -> bool {
+    match (value, prefix) {
+        (Value::String(value), Value::String(prefix)) => value.starts_with(prefix.as_str()),
+        (Value::Bytes(value), Value::Bytes(prefix)) => {
+            value.as_slice().starts_with(prefix.as_slice())
+        }
+        _ => false,
+    }
+}
+
+fn ends_with(value: &Value, suffix: &Value) -> bool {
+    match (value, suffix) {
+        (Value::String(value), Value::String(suffix)) => value.ends_with(suffix.as_str()),
+        (Value::Bytes(value), Value::Bytes(suffix)) => {
+            value.as_slice().ends_with(suffix.as_slice())
+        }
+        _ => false,
+    }
+}
+
+/// Whether `candidate` passes `filter`. Like in SQL, a comparison with an
+/// attribute that is not set is false, regardless of whether it is negated.
+/// The patterns of `_matches` filters must have been compiled into `regexes`
+fn matches(filter: &EntityFilter, candidate: &Candidate, regexes: &Regexes) -> bool {
+    use EntityFilter as f;
+
+    // Apply `test` to the attribute, and to its lowercase version together
+    // with a lowercase `value` if `no_case` is set
+    let test = |attr: &str, value: &Value, no_case: bool, test: fn(&Value, &Value) -> bool| {
+        match candidate.get(attr) {
+            Value::Null => None,
+            attr_value if no_case => Some(test(&lowercase(attr_value), &lowercase(value))),
+            attr_value => Some(test(attr_value, value)),
+        }
+    };
+    let cmp = |attr: &str, value: &Value, expected: &[Ordering]| {
+        compare(candidate.get(attr), value).map_or(false, |ord| expected.contains(&ord))
+    };
+
+    match filter {
+        f::And(filters) => filters
+            .iter()
+            .all(|filter| matches(filter, candidate, regexes)),
+        f::Or(filters) => filters
+            .iter()
+            .any(|filter| matches(filter, candidate, regexes)),
+        f::Equal(attr, Value::Null) => candidate.get(attr) == &Value::Null,
+        f::Not(attr, Value::Null) => candidate.get(attr) != &Value::Null,
+        f::Equal(attr, value) => test(attr, value, false, equals).unwrap_or(false),
+        f::Not(attr, value) => test(attr, value, false, equals).map_or(false, |eq| !eq),
+        f::EqualNoCase(attr, value) => test(attr, value, true, equals).unwrap_or(false),
+        f::NotNoCase(attr, value) => test(attr, value, true, equals).map_or(false, |eq| !eq),
+        f::GreaterThan(attr, value) => cmp(attr, value, &[Ordering::Greater]),
+        f::LessThan(attr, value) => cmp(attr, value, &[Ordering::Less]),
+        f::GreaterOrEqual(attr, value) => cmp(attr, value, &[Ordering::Greater, Ordering::Equal]),
+        f::LessOrEqual(attr, value) => cmp(attr, value, &[Ordering::Less, Ordering::Equal]),
+        f::In(attr, values) => values
+            .iter()
+            .any(|value| test(attr, value, false, equals).unwrap_or(false)),
+        f::NotIn(attr, values) => match candidate.get(attr) {
+            Value::Null => false,
+            attr_value => !values.iter().any(|value| equals(attr_value, value)),
+        },
+        f::InNoCase(attr, values) => values
+            .iter()
+            .any(|value| test(attr, value, true, equals).unwrap_or(false)),
+        f::NotInNoCase(attr, values) => match candidate.get(attr) {
+            Value::Null => false,
+            attr_value => !values
+                .iter()
+                .any(|value| equals(&lowercase(attr_value), &lowercase(value))),
+        },
+        f::Contains(attr, value) => test(attr, value, false, contains).unwrap_or(false),
+        f::ContainsNoCase(attr, value) => test(attr, value, true, contains).unwrap_or(false),
+        f::NotContains(attr, value) => test(attr, value, false, contains).map_or(false, |c| !c),
+        f::NotContainsNoCase(attr, value) => {
+            test(attr, value, true, contains).map_or(false, |c| !c)
+        }
+        f::StartsWith(attr, value) => test(attr, value, false, starts_with).unwrap_or(false),
+        f::StartsWithNoCase(attr, value) => test(attr, value, true, starts_with).unwrap_or(false),
+        f::NotStartsWith(attr, value) => {
+            test(attr, value, false, starts_with).map_or(false, |s| !s)
+        }
+        f::NotStartsWithNoCase(attr, value) => {
+            test(attr, value, true, starts_with).map_or(false, |s| !s)
+        }
+        f::EndsWith(attr, value) => test(attr, value, false, ends_with).unwrap_or(false),
+        f::EndsWithNoCase(attr, value) => test(attr, value, true, ends_with).unwrap_or(false),
+        f::NotEndsWith(attr, value) => test(attr, value, false, ends_with).map_or(false, |e| !e),
+        f::NotEndsWithNoCase(attr, value) => {
+            test(attr, value, true, ends_with).map_or(false, |e| !e)
+        }
+        f::Overlaps(attr, value) => test(attr, value, false, overlaps).unwrap_or(false),
+        f::Empty(attr, empty) => match candidate.get(attr) {
+            Value::List(values) => values.is_empty() == *empty,
+            _ => false,
+        },
+        f::Matches(attr, Value::String(pattern)) => {
+            match (candidate.get(attr), regexes.get(pattern)) {
+                (Value::String(value), Some(regex)) => regex.is_match(value),
+                _ => false,
+            }
+        }
+        f::Matches(_, _) => false,
+        f::ChangeBlockGte(block) => candidate.version.block >= *block,
+    }
+}
+
+/// Sort like the Postgres store does: by the attribute with nulls last,
+/// and then by `id` in the same direction
+fn sort(candidates: &mut [Candidate], order: &EntityOrder) {
+    let by_id =
+        |a: &Candidate, b: &Candidate| compare(a.get("id"), b.get("id")).unwrap_or(Ordering::Equal);
+    let by_attr = |a: &Candidate, b: &Candidate, attr: &str| match (a.get(attr), b.get(attr)) {
+        (Value::Null, Value::Null) => Ordering::Equal,
+        (Value::Null, _) => Ordering::Greater,
+        (_, Value::Null) => Ordering::Less,
+        (a, b) => compare(a, b).unwrap_or(Ordering::Equal),
+    };
+
+    match order {
+        EntityOrder::Ascending(attr, _) => {
+            candidates.sort_by(|a, b| by_attr(a, b, attr).then_with(|| by_id(a, b)))
+        }
+        EntityOrder::Descending(attr, _) => candidates.sort_by(|a, b| {
+            // Nulls still go last
+            match (a.get(attr), b.get(attr)) {
+                (Value::Null, _) | (_, Value::Null) => by_attr(a, b, attr),
+                _ => by_attr(b, a, attr),
+            }
+            .then_with(|| by_id(b, a))
+        }),
+        EntityOrder::Default | EntityOrder::Unordered => candidates.sort_by(by_id),
+    }
+}
+
+fn limit(candidates: Vec<Candidate>, range: &EntityRange) -> Vec<Candidate> {
+    let first = range
+        .first
+        .map(|first| first as usize)
+        .unwrap_or(usize::MAX);
+    candidates
+        .into_iter()
+        .skip(range.skip as usize)
+        .take(first)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use graph::entity;
+    use graph::prelude::{Entity, ValueType};
+
+    use super::*;
+
+    fn candidate(block: BlockNumber, entity: Entity) -> Candidate {
+        Candidate {
+            entity_type: EntityType::from("User"),
+            version: Version { block, entity },
+            parent_id: None,
+        }
+    }
+
+    fn users() -> Vec<Candidate> {
+        let mut tagged = entity! { id: "1", name: "Alice", age: 30 };
+        tagged.set(
+            "tags",
+            Value::List(vec![Value::from("admin"), Value::from("ops")]),
+        );
+        vec![
+            candidate(1, tagged),
+            candidate(2, entity! { id: "2", name: "bob", age: 25 }),
+            candidate(3, entity! { id: "3", age: 40 }),
+        ]
+    }
+
+    fn ids(candidates: &[Candidate]) -> Vec<&str> {
+        candidates
+            .iter()
+            .map(|candidate| match candidate.get("id") {
+                Value::String(id) => id.as_str(),
+                _ => panic!("entities have string ids"),
+            })
+            .collect()
+    }
+
+    fn filter(filter: EntityFilter) -> Vec<String> {
+        let users = users();
+        let mut regexes = Regexes::new();
+        compile_regexes(&filter, &mut regexes).unwrap();
+        ids(&users)
+            .into_iter()
+            .zip(&users)
+            .filter(|(_, candidate)| matches(&filter, candidate, &regexes))
+            .map(|(id, _)| id.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn compares_numbers_across_types() {
+        let half = Value::BigDecimal(BigDecimal::from_str("2.5").unwrap());
+        assert_eq!(Some(Ordering::Less), compare(&Value::Int(2), &half));
+        assert_eq!(
+            Some(Ordering::Equal),
+            compare(&Value::BigInt(2.into()), &Value::Int(2))
+        );
+        assert_eq!(None, compare(&Value::Int(2), &Value::from("2")));
+        assert_eq!(None, compare(&Value::Null, &Value::Null));
+    }
+
+    #[test]
+    fn filters_like_postgres() {
+        use EntityFilter as f;
+
+        assert_eq!(vec!["2"], filter(f::Equal("name".into(), "bob".into())));
+        assert_eq!(
+            vec!["2"],
+            filter(f::EqualNoCase("name".into(), "BOB".into()))
+        );
+        // Comparisons with an attribute that is not set are always false
+        assert_eq!(vec!["1"], filter(f::Not("name".into(), "bob".into())));
+        assert_eq!(vec!["3"], filter(f::Equal("name".into(), Value::Null)));
+        assert_eq!(
+            vec!["1"],
+            filter(f::NotIn("name".into(), vec!["bob".into()]))
+        );
+        assert_eq!(
+            vec!["1", "3"],
+            filter(f::GreaterThan("age".into(), Value::Int(25)))
+        );
+        assert_eq!(
+            vec!["1"],
+            filter(f::Contains("tags".into(), Value::List(vec!["ops".into()])))
+        );
+        assert_eq!(
+            vec!["1"],
+            filter(f::StartsWithNoCase("name".into(), "al".into()))
+        );
+        assert_eq!(vec!["2", "3"], filter(f::ChangeBlockGte(2)));
+        assert_eq!(
+            vec!["2", "3"],
+            filter(f::Or(vec![
+                f::Equal("id".into(), "3".into()),
+                f::And(vec![
+                    f::LessThan("age".into(), Value::Int(30)),
+                    f::EndsWith("name".into(), "ob".into()),
+                ]),
+            ]))
+        );
+    }
+
+    #[test]
+    fn matches_regular_expressions() {
+        use EntityFilter as f;
+
+        assert_eq!(
+            vec!["1", "2"],
+            filter(f::Or(vec![
+                f::Matches("name".into(), "^A".into()),
+                f::Matches("name".into(), "b$".into()),
+            ]))
+        );
+
+        let mut regexes = Regexes::new();
+        let err =
+            compile_regexes(&f::Matches("name".into(), "(".into()), &mut regexes).unwrap_err();
+        assert!(err.to_string().contains("invalid regular expression `(`"));
+    }
+
+    #[test]
+    fn sorts_nulls_last() {
+        let mut users = users();
+        sort(
+            &mut users,
+            &EntityOrder::Ascending("name".to_string(), ValueType::String),
+        );
+        // Uppercase sorts before lowercase, like with the `C` collation
+        assert_eq!(vec!["1", "2", "3"], ids(&users));
+
+        sort(
+            &mut users,
+            &EntityOrder::Descending("name".to_string(), ValueType::String),
+        );
+        assert_eq!(vec!["2", "1", "3"], ids(&users));
+
+        sort(
+            &mut users,
+            &EntityOrder::Descending("age".to_string(), ValueType::Int),
+        );
+        assert_eq!(vec!["3", "1", "2"], ids(&users));
+    }
+
+    #[test]
+    fn limits_with_skip_and_first() {
+        let range = EntityRange {
+            first: Some(1),
+            skip: 1,
+        };
+        assert_eq!(vec!["2"], ids(&limit(users(), &range)));
+
+        let range = EntityRange {
+            first: None,
+            skip: 2,
+        };
+        assert_eq!(vec!["3"], ids(&limit(users(), &range)));
+    }
+}
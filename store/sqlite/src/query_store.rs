@@ -0,0 +1,230 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::{Arc, RwLock};
+
+use graph::components::store::{ChainStore as _, PoolWaitStats, QueryStore as QueryStoreTrait};
+use graph::data::query::QueryExplanation;
+use graph::prelude::web3::types::H256;
+use graph::prelude::{
+    async_trait, r, serde_json, ApiSchema, BlockNumber, BlockPtr, DeploymentState, EntityOperation,
+    EntityQuery, Error, MovingStats, QueryExecutionError, QueryPermit, Schema, StoreError,
+};
+use rusqlite::params;
+
+use crate::chain_store::ChainStore;
+use crate::query;
+use crate::store_err;
+use crate::subgraph_store::{Deployment, SubgraphStore};
+
+/// Answers queries for one deployment
+pub struct QueryStore {
+    store: Arc<SubgraphStore>,
+    chain_store: Arc<ChainStore>,
+    deployment: Arc<Deployment>,
+    wait_stats: PoolWaitStats,
+}
+
+impl QueryStore {
+    pub(crate) fn new(
+        store: Arc<SubgraphStore>,
+        chain_store: Arc<ChainStore>,
+        deployment: Arc<Deployment>,
+    ) -> Self {
+        QueryStore {
+            store,
+            chain_store,
+            deployment,
+            wait_stats: Arc::new(RwLock::new(MovingStats::default())),
+        }
+    }
+}
+
+#[async_trait]
+impl QueryStoreTrait for QueryStore {
+    fn find_query_values(
+        &self,
+        query: EntityQuery,
+    ) -> Result<Vec<BTreeMap<String, r::Value>>, QueryExecutionError> {
+        let layout = &self.deployment.layout;
+        Ok(self
+            .store
+            .db
+            .with_conn(|conn| query::execute(conn, layout, query))?)
+    }
+
+    fn explain_query(&self, _query: EntityQuery) -> Result<QueryExplanation, QueryExecutionError> {
+        Err(QueryExecutionError::NotSupported(
+            "explaining queries is not supported by the SQLite store".to_string(),
+        ))
+    }
+
+    fn execute_sql(
+        &self,
+        _sql: &str,
+        _block: BlockNumber,
+    ) -> Result<Vec<serde_json::Value>, QueryExecutionError> {
+        Err(QueryExecutionError::NotSupported(
+            "SQL queries are not supported by the SQLite store".to_string(),
+        ))
+    }
+
+    fn changed_blocks(
+        &self,
+        after: BlockNumber,
+        until: BlockNumber,
+    ) -> Result<Vec<BlockNumber>, StoreError> {
+        self.store.db.with_conn(|conn| {
+            let mut blocks = BTreeSet::new();
+            for table in self.deployment.layout.tables.values() {
+                blocks.extend(table.changed_blocks(conn, after, until)?);
+            }
+            Ok(blocks.into_iter().collect())
+        })
+    }
+
+    fn entity_changes(&self, block: BlockNumber) -> Result<Vec<EntityOperation>, StoreError> {
+        self.store.db.with_conn(|conn| {
+            let mut changes = Vec::new();
+            for table in self.deployment.layout.tables.values() {
+                changes.extend(table.changes_in_block(conn, &self.deployment.hash, block)?);
+            }
+            Ok(changes)
+        })
+    }
+
+    async fn is_deployment_synced(&self) -> Result<bool, Error> {
+        let id = self.deployment.id.0;
+        Ok(self
+            .store
+            .db
+            .spawn_with_conn(move |conn| {
+                conn.query_row(
+                    "select synced from deployments where id = ?1",
+                    params![id],
+                    |row| row.get(0),
+                )
+                .map_err(store_err)
+            })
+            .await?)
+    }
+
+    async fn block_ptr(&self) -> Result<Option<BlockPtr>, StoreError> {
+        let id = self.deployment.id.0;
+        self.store
+            .db
+            .spawn_with_conn(move |conn| {
+                conn.query_row(
+                    "select latest_block_hash, latest_block_number from deployments
+                      where id = ?1",
+                    params![id],
+                    |row| Ok((row.get::<_, Option<Vec<u8>>>(0)?, row.get(1)?)),
+                )
+                .map_err(store_err)
+            })
+            .await
+            .map(|(hash, number)| {
+                hash.zip(number)
+                    .map(|(hash, number)| BlockPtr::from((hash, number)))
+            })
+    }
+
+    fn block_number(&self, block_hash: H256) -> Result<Option<BlockNumber>, StoreError> {
+        let network = self.network_name();
+        self.chain_store
+            .block_number(block_hash)?
+            .map(|(block_network, number)| {
+                if block_network == network {
+                    Ok(number)
+                } else {
+                    Err(StoreError::QueryExecutionError(format!(
+                        "subgraph {} belongs to network {} but block {:x} belongs to network {}",
+                        self.deployment.hash, network, block_hash, block_network
+                    )))
+                }
+            })
+            .transpose()
+    }
+
+    fn block_parent_hash(&self, block_hash: H256) -> Result<Option<H256>, StoreError> {
+        self.chain_store.block_parent_hash(block_hash)
+    }
+
+    fn chain_head_number(&self) -> Result<Option<BlockNumber>, StoreError> {
+        self.chain_store.head_number()
+    }
+
+    fn block_time(&self, hash: &H256) -> Result<Option<u64>, StoreError> {
+        self.chain_store.block_time(hash)
+    }
+
+    fn block_at_time(&self, timestamp: u64) -> Result<Option<BlockPtr>, StoreError> {
+        self.chain_store.block_at_time(timestamp)
+    }
+
+    fn wait_stats(&self) -> PoolWaitStats {
+        self.wait_stats.clone()
+    }
+
+    async fn has_non_fatal_errors(&self, block: Option<BlockNumber>) -> Result<bool, StoreError> {
+        let id = self.deployment.id.0;
+        self.store
+            .db
+            .spawn_with_conn(move |conn| {
+                conn.query_row(
+                    "select exists (select 1 from subgraph_errors e join deployments d
+                                        on e.deployment = d.id
+                                     where d.id = ?1 and e.deterministic
+                                       and e.block_number <= coalesce(?2, d.latest_block_number)
+                                       and e.id is not d.fatal_error)",
+                    params![id, block],
+                    |row| row.get(0),
+                )
+                .map_err(store_err)
+            })
+            .await
+    }
+
+    async fn deployment_state(&self) -> Result<DeploymentState, QueryExecutionError> {
+        let id = self.deployment.id.0;
+        let (reorg_count, max_reorg_depth, latest) = self
+            .store
+            .db
+            .spawn_with_conn(move |conn| {
+                conn.query_row(
+                    "select reorg_count, max_reorg_depth, latest_block_number from deployments
+                      where id = ?1",
+                    params![id],
+                    |row| {
+                        Ok((
+                            row.get::<_, u32>(0)?,
+                            row.get::<_, u32>(1)?,
+                            row.get::<_, Option<BlockNumber>>(2)?,
+                        ))
+                    },
+                )
+                .map_err(store_err)
+            })
+            .await?;
+        Ok(DeploymentState {
+            id: self.deployment.hash.clone(),
+            reorg_count,
+            max_reorg_depth,
+            latest_ethereum_block_number: latest.unwrap_or(0),
+        })
+    }
+
+    fn api_schema(&self) -> Result<Arc<ApiSchema>, QueryExecutionError> {
+        Ok(self.deployment.api.clone())
+    }
+
+    fn input_schema(&self) -> Result<Arc<Schema>, QueryExecutionError> {
+        Ok(self.deployment.input.clone())
+    }
+
+    fn network_name(&self) -> &str {
+        &self.deployment.network
+    }
+
+    async fn query_permit(&self) -> Result<QueryPermit, QueryExecutionError> {
+        Ok(self.store.db.query_permit().await.into())
+    }
+}
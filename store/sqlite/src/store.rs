@@ -0,0 +1,155 @@
+use std::sync::Arc;
+
+use graph::components::server::index_node::VersionInfo;
+use graph::components::store::{
+    BlockStore as _, PartialBlockPtr, QueryStoreManager, StatusStore, Store as StoreTrait,
+    SubgraphStore as _,
+};
+use graph::constraint_violation;
+use graph::data::query::QueryTarget;
+use graph::data::subgraph::{status, SubgraphDeprecation};
+use graph::prelude::tokio::sync::OwnedSemaphorePermit;
+use graph::prelude::web3::types::Address;
+use graph::prelude::{
+    async_trait, BlockNumber, BlockPtr, DeploymentHash, QueryExecutionError, StoreError,
+    SubgraphName,
+};
+
+use crate::chain_store::BlockStore;
+use crate::query_store::QueryStore;
+use crate::subgraph_store::SubgraphStore;
+
+/// The store for everything, kept in one SQLite database
+#[derive(Clone)]
+pub struct Store {
+    subgraph_store: Arc<SubgraphStore>,
+    block_store: Arc<BlockStore>,
+}
+
+impl Store {
+    pub fn new(subgraph_store: Arc<SubgraphStore>, block_store: Arc<BlockStore>) -> Self {
+        Self {
+            subgraph_store,
+            block_store,
+        }
+    }
+}
+
+impl StoreTrait for Store {
+    type BlockStore = BlockStore;
+    type SubgraphStore = SubgraphStore;
+
+    fn subgraph_store(&self) -> Arc<SubgraphStore> {
+        self.subgraph_store.clone()
+    }
+
+    fn block_store(&self) -> Arc<BlockStore> {
+        self.block_store.clone()
+    }
+}
+
+#[async_trait]
+impl QueryStoreManager for Store {
+    async fn query_store(
+        &self,
+        target: QueryTarget,
+        _for_subscription: bool,
+    ) -> Result<Arc<dyn graph::prelude::QueryStore + Send + Sync>, QueryExecutionError> {
+        let deployment = self.subgraph_store.deployment_for_query(target)?;
+        let chain_store = self
+            .block_store
+            .chain_store(&deployment.network)
+            .ok_or_else(|| {
+                constraint_violation!(
+                    "Subgraphs index a known network, but {} indexes `{}` which we do not know about. This is most likely a configuration error.",
+                    deployment.hash,
+                    deployment.network
+                )
+            })?;
+        Ok(Arc::new(QueryStore::new(
+            self.subgraph_store.clone(),
+            chain_store,
+            deployment,
+        )))
+    }
+
+    async fn deprecation(
+        &self,
+        name: &SubgraphName,
+    ) -> Result<Option<SubgraphDeprecation>, QueryExecutionError> {
+        Ok(self.subgraph_store.deprecation(name)?)
+    }
+}
+
+#[async_trait]
+impl StatusStore for Store {
+    fn status(&self, filter: status::Filter) -> Result<Vec<status::Info>, StoreError> {
+        let mut infos = self.subgraph_store.status(filter)?;
+        let ptrs = self.block_store.chain_head_pointers()?;
+
+        for info in &mut infos {
+            for chain in &mut info.chains {
+                chain.chain_head_block = ptrs.get(&chain.network).map(|ptr| ptr.to_owned().into());
+            }
+        }
+        Ok(infos)
+    }
+
+    fn chains(&self) -> Result<Vec<status::ChainStatus>, StoreError> {
+        self.block_store.chain_statuses()
+    }
+
+    fn jobs(&self, _filter: status::JobFilter) -> Result<Vec<status::JobInfo>, StoreError> {
+        // Nothing in this store runs as a job
+        Ok(vec![])
+    }
+
+    fn version_info(&self, version_id: &str) -> Result<VersionInfo, StoreError> {
+        let mut info = self.subgraph_store.version_info(version_id)?;
+
+        info.total_ethereum_blocks_count = self
+            .block_store
+            .chain_head_pointers()?
+            .get(&info.network)
+            .map(|ptr| ptr.number);
+
+        Ok(info)
+    }
+
+    fn versions_for_subgraph_id(
+        &self,
+        subgraph_id: &str,
+    ) -> Result<(Option<String>, Option<String>), StoreError> {
+        self.subgraph_store.versions_for_subgraph_id(subgraph_id)
+    }
+
+    fn subgraphs_for_deployment_hash(
+        &self,
+        deployment_hash: &str,
+    ) -> Result<Vec<(String, String)>, StoreError> {
+        self.subgraph_store
+            .subgraphs_for_deployment_hash(deployment_hash)
+    }
+
+    async fn get_proof_of_indexing(
+        &self,
+        subgraph_id: &DeploymentHash,
+        indexer: &Option<Address>,
+        block: BlockPtr,
+    ) -> Result<Option<[u8; 32]>, StoreError> {
+        self.subgraph_store
+            .proof_of_indexing(subgraph_id, indexer, block)
+    }
+
+    async fn get_public_proof_of_indexing(
+        &self,
+        _subgraph_id: &DeploymentHash,
+        _block_number: BlockNumber,
+    ) -> Result<Option<(PartialBlockPtr, [u8; 32])>, StoreError> {
+        Ok(None)
+    }
+
+    async fn query_permit(&self) -> OwnedSemaphorePermit {
+        self.subgraph_store.db.query_permit().await
+    }
+}
@@ -0,0 +1,1005 @@
+use std::collections::{BTreeSet, HashMap};
+use std::str::FromStr;
+use std::sync::{Arc, RwLock};
+
+use graph::components::server::index_node::VersionInfo;
+use graph::components::store::{
+    fork, AdminCall, DeploymentId, DeploymentLocator, EnsLookup, ExportRequest, ExportedFile,
+    FileRegistry, OutboxEntry, SubgraphFork, SubgraphStore as SubgraphStoreTrait,
+    WritableStore as WritableStoreTrait,
+};
+use graph::components::subgraph::ProofOfIndexingFinisher;
+use graph::constraint_violation;
+use graph::data::query::QueryTarget;
+use graph::data::subgraph::schema::{DeploymentCreate, SubgraphError, SubgraphHealth};
+use graph::data::subgraph::status;
+use graph::data::subgraph::{SubgraphDeprecation, SubgraphFeature};
+use graph::prelude::web3::types::Address;
+use graph::prelude::{
+    anyhow, async_trait, serde_json, ApiSchema, BlockHash, BlockNumber, BlockPtr, DeploymentHash,
+    EntityChange, EntityChangeOperation, EntityOperation, Error, Logger, NodeId, Schema,
+    StoreError, StoreEvent, SubgraphName, SubgraphVersionSwitchingMode, Value,
+};
+use graph::url::Url;
+use graph_graphql::prelude::api_schema;
+use rusqlite::{params, Connection, OptionalExtension, Row};
+
+use crate::db::{now, Database};
+use crate::events::SubscriptionManager;
+use crate::layout::Layout;
+use crate::store_err;
+use crate::writable::WritableStore;
+
+/// What we need to know about a deployment to write and query its
+/// entities. None of it ever changes once the deployment is created
+pub(crate) struct Deployment {
+    pub id: DeploymentId,
+    pub hash: DeploymentHash,
+    pub network: String,
+    pub input: Arc<Schema>,
+    pub api: Arc<ApiSchema>,
+    pub layout: Layout,
+    pub description: Option<String>,
+    pub repository: Option<String>,
+    pub debug_fork: Option<DeploymentHash>,
+}
+
+impl Deployment {
+    pub fn locator(&self) -> DeploymentLocator {
+        DeploymentLocator::new(self.id, self.hash.clone())
+    }
+}
+
+fn deployment_hash(hash: String) -> Result<DeploymentHash, StoreError> {
+    DeploymentHash::new(hash)
+        .map_err(|hash| constraint_violation!("illegal deployment id {}", hash))
+}
+
+fn block_ptr(hash: Option<Vec<u8>>, number: Option<BlockNumber>) -> Option<BlockPtr> {
+    match (hash, number) {
+        (Some(hash), Some(number)) => Some(BlockPtr::new(BlockHash::from(hash), number)),
+        _ => None,
+    }
+}
+
+pub(crate) fn subgraph_error(
+    row: &Row,
+    subgraph_id: &DeploymentHash,
+) -> rusqlite::Result<SubgraphError> {
+    Ok(SubgraphError {
+        subgraph_id: subgraph_id.clone(),
+        message: row.get("message")?,
+        block_ptr: block_ptr(row.get("block_hash")?, row.get("block_number")?),
+        handler: row.get("handler")?,
+        deterministic: row.get("deterministic")?,
+    })
+}
+
+/// There are no rainbow tables for ENS names in this store
+struct NoEnsLookup;
+
+impl EnsLookup for NoEnsLookup {
+    fn find_name(&self, _hash: &str) -> Result<Option<String>, StoreError> {
+        Ok(None)
+    }
+}
+
+/// This store does not keep the files of deployments; they are always
+/// fetched from IPFS
+struct NoFileRegistry;
+
+impl FileRegistry for NoFileRegistry {
+    fn get_file(&self, _link: &str) -> Result<Option<Vec<u8>>, StoreError> {
+        Ok(None)
+    }
+
+    fn put_file(&self, _link: &str, _content: &[u8]) -> Result<(), StoreError> {
+        Ok(())
+    }
+}
+
+/// Subgraphs, their versions, and the deployments they point to
+pub struct SubgraphStore {
+    pub(crate) db: Arc<Database>,
+    subscriptions: Arc<SubscriptionManager>,
+    deployments: RwLock<HashMap<DeploymentHash, Arc<Deployment>>>,
+    /// The GraphQL endpoint from which debug forks fetch entities
+    fork_base: Option<Url>,
+}
+
+impl SubgraphStore {
+    pub fn new(db: Arc<Database>, subscriptions: Arc<SubscriptionManager>) -> Self {
+        SubgraphStore {
+            db,
+            subscriptions,
+            deployments: RwLock::new(HashMap::new()),
+            fork_base: None,
+        }
+    }
+
+    /// Let deployments that were created with a debug fork fetch the
+    /// entities they have not written yet from the subgraph with that id
+    /// under `base`
+    pub fn with_fork_base(mut self, base: Url) -> Self {
+        self.fork_base = Some(base);
+        self
+    }
+
+    pub(crate) fn publish(&self, event: StoreEvent) {
+        self.subscriptions.publish(event);
+    }
+
+    pub(crate) fn deployment(&self, hash: &DeploymentHash) -> Result<Arc<Deployment>, StoreError> {
+        if let Some(deployment) = self.deployments.read().unwrap().get(hash) {
+            return Ok(deployment.clone());
+        }
+
+        let row = self.db.with_conn(|conn| {
+            conn.query_row(
+                "select id, network, schema, description, repository, debug_fork
+                   from deployments where hash = ?1",
+                params![hash.as_str()],
+                |row| {
+                    Ok((
+                        row.get::<_, i32>(0)?,
+                        row.get::<_, String>(1)?,
+                        row.get::<_, String>(2)?,
+                        row.get(3)?,
+                        row.get(4)?,
+                        row.get::<_, Option<String>>(5)?,
+                    ))
+                },
+            )
+            .optional()
+            .map_err(store_err)
+        })?;
+        let (id, network, schema, description, repository, debug_fork) =
+            row.ok_or_else(|| StoreError::DeploymentNotFound(hash.to_string()))?;
+        let debug_fork = debug_fork.map(deployment_hash).transpose()?;
+
+        let input = Schema::parse(&schema, hash.clone())?;
+        let id = DeploymentId::new(id);
+        let layout = Layout::new(id, hash.clone(), &input);
+        // Databases from before the proof of indexing was kept do not have
+        // a table for it yet
+        self.db.with_conn(|conn| layout.create_tables(conn))?;
+
+        // Generate an API schema for the subgraph and make sure all types in
+        // the API schema have a @subgraphId directive as well
+        let mut schema = input.clone();
+        schema.document =
+            api_schema(&schema.document).map_err(|e| StoreError::Unknown(e.into()))?;
+        schema.add_subgraph_id_directives(hash.clone());
+
+        let deployment = Arc::new(Deployment {
+            id,
+            hash: hash.clone(),
+            network,
+            input: Arc::new(input),
+            api: Arc::new(ApiSchema::from_api_schema(schema)?),
+            layout,
+            description,
+            repository,
+            debug_fork,
+        });
+        self.deployments
+            .write()
+            .unwrap()
+            .insert(hash.clone(), deployment.clone());
+        Ok(deployment)
+    }
+
+    pub(crate) fn deployment_by_id(&self, id: DeploymentId) -> Result<Arc<Deployment>, StoreError> {
+        let hash = self.db.with_conn(|conn| {
+            conn.query_row(
+                "select hash from deployments where id = ?1",
+                params![id.0],
+                |row| row.get::<_, String>(0),
+            )
+            .optional()
+            .map_err(store_err)
+        })?;
+        match hash {
+            Some(hash) => self.deployment(&deployment_hash(hash)?),
+            None => Err(StoreError::DeploymentNotFound(id.to_string())),
+        }
+    }
+
+    /// The proof of indexing of deployment `hash` at `block` for `indexer`,
+    /// or `None` if the deployment has not processed that block yet
+    pub(crate) fn proof_of_indexing(
+        &self,
+        hash: &DeploymentHash,
+        indexer: &Option<Address>,
+        block: BlockPtr,
+    ) -> Result<Option<[u8; 32]>, StoreError> {
+        let deployment = self.deployment(hash)?;
+        let regions = self.db.with_conn(|conn| {
+            let latest: Option<BlockNumber> = conn
+                .query_row(
+                    "select latest_block_number from deployments where id = ?1",
+                    params![deployment.id.0],
+                    |row| row.get(0),
+                )
+                .map_err(store_err)?;
+            if latest.map_or(true, |latest| latest < block.number) {
+                return Ok(None);
+            }
+            deployment.layout.poi.scan(conn, block.number).map(Some)
+        })?;
+        let regions = match regions {
+            Some(regions) => regions,
+            None => return Ok(None),
+        };
+
+        let mut finisher = ProofOfIndexingFinisher::new(&block, hash, indexer);
+        for version in regions {
+            let region = version.entity.id().map_err(StoreError::Unknown)?;
+            match version.entity.get("digest") {
+                Some(Value::Bytes(digest)) => finisher.add_causality_region(&region, digest),
+                other => {
+                    return Err(constraint_violation!(
+                        "the proof of indexing for `{}` has digest {:?}",
+                        region,
+                        other
+                    ))
+                }
+            }
+        }
+        Ok(Some(finisher.finish()))
+    }
+
+    /// The deployment that queries for `target` should go to
+    pub(crate) fn deployment_for_query(
+        &self,
+        target: QueryTarget,
+    ) -> Result<Arc<Deployment>, StoreError> {
+        let hash = match target {
+            QueryTarget::Deployment(hash) => hash,
+            QueryTarget::Name(name) => {
+                let hash = self.db.with_conn(|conn| {
+                    conn.query_row(
+                        "select v.deployment
+                           from subgraphs s join subgraph_versions v on v.id = s.current_version
+                          where s.name = ?1",
+                        params![name.as_str()],
+                        |row| row.get::<_, String>(0),
+                    )
+                    .optional()
+                    .map_err(store_err)
+                })?;
+                match hash {
+                    Some(hash) => deployment_hash(hash)?,
+                    None => {
+                        return Err(StoreError::QueryExecutionError(format!(
+                            "Subgraph `{}` not found",
+                            name.as_str()
+                        )))
+                    }
+                }
+            }
+        };
+        self.deployment(&hash)
+    }
+
+    fn subgraph_id(conn: &Connection, name: &SubgraphName) -> Result<Option<i64>, StoreError> {
+        conn.query_row(
+            "select id from subgraphs where name = ?1",
+            params![name.as_str()],
+            |row| row.get(0),
+        )
+        .optional()
+        .map_err(store_err)
+    }
+
+    /// Point the current or pending version of the subgraph `subgraph` at
+    /// `deployment`, unless one of them already does
+    fn create_version(
+        conn: &Connection,
+        subgraph: i64,
+        deployment: &DeploymentHash,
+        mode: SubgraphVersionSwitchingMode,
+    ) -> Result<(), StoreError> {
+        let (current, pending): (Option<String>, Option<String>) = conn
+            .query_row(
+                "select c.deployment, p.deployment
+                   from subgraphs s
+                   left join subgraph_versions c on c.id = s.current_version
+                   left join subgraph_versions p on p.id = s.pending_version
+                  where s.id = ?1",
+                params![subgraph],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .map_err(store_err)?;
+        if current.as_deref() == Some(deployment.as_str())
+            || pending.as_deref() == Some(deployment.as_str())
+        {
+            return Ok(());
+        }
+
+        conn.execute(
+            "insert into subgraph_versions(subgraph, deployment, created_at)
+             values (?1, ?2, ?3)",
+            params![subgraph, deployment.as_str(), now()],
+        )
+        .map_err(store_err)?;
+        let version = conn.last_insert_rowid();
+
+        let synced: bool = conn
+            .query_row(
+                "select synced from deployments where hash = ?1",
+                params![deployment.as_str()],
+                |row| row.get(0),
+            )
+            .map_err(store_err)?;
+        let make_current = match mode {
+            SubgraphVersionSwitchingMode::Instant => true,
+            SubgraphVersionSwitchingMode::Synced => current.is_none() || synced,
+        };
+        let query = if make_current {
+            "update subgraphs set current_version = ?2, pending_version = null where id = ?1"
+        } else {
+            "update subgraphs set pending_version = ?2 where id = ?1"
+        };
+        conn.execute(query, params![subgraph, version])
+            .map(|_| ())
+            .map_err(store_err)
+    }
+
+    pub(crate) fn status(&self, filter: status::Filter) -> Result<Vec<status::Info>, StoreError> {
+        use status::Filter;
+
+        let hashes: Vec<String> = self.db.with_conn(|conn| {
+            let (query, param) = match &filter {
+                Filter::SubgraphName(name) => (
+                    "select distinct v.deployment
+                       from subgraph_versions v join subgraphs s on v.subgraph = s.id
+                      where s.name = ?1",
+                    Some(name.clone()),
+                ),
+                Filter::SubgraphVersion(name, true) => (
+                    "select v.deployment
+                       from subgraphs s join subgraph_versions v on v.id = s.current_version
+                      where s.name = ?1",
+                    Some(name.clone()),
+                ),
+                Filter::SubgraphVersion(name, false) => (
+                    "select v.deployment
+                       from subgraphs s join subgraph_versions v on v.id = s.pending_version
+                      where s.name = ?1",
+                    Some(name.clone()),
+                ),
+                Filter::Deployments(_) | Filter::DeploymentIds(_) => {
+                    ("select hash from deployments order by id", None)
+                }
+            };
+            let mut stmt = conn.prepare_cached(query).map_err(store_err)?;
+            let rows = match param {
+                Some(param) => stmt.query_map(params![param], |row| row.get(0)),
+                None => stmt.query_map([], |row| row.get(0)),
+            };
+            rows.and_then(|rows| rows.collect::<Result<Vec<_>, _>>())
+                .map_err(store_err)
+        })?;
+
+        let mut infos = Vec::new();
+        for hash in hashes {
+            let deployment = self.deployment(&deployment_hash(hash)?)?;
+            let wanted = match &filter {
+                Filter::Deployments(hashes) => {
+                    hashes.is_empty() || hashes.iter().any(|hash| hash == deployment.hash.as_str())
+                }
+                Filter::DeploymentIds(ids) => ids.is_empty() || ids.contains(&deployment.id),
+                _ => true,
+            };
+            if wanted {
+                infos.push(self.deployment_status(&deployment)?);
+            }
+        }
+        Ok(infos)
+    }
+
+    fn deployment_status(&self, deployment: &Deployment) -> Result<status::Info, StoreError> {
+        self.db.with_conn(|conn| {
+            let (synced, health, fatal_error, node, features, earliest, latest, restart_count) =
+                conn.query_row(
+                    "select synced, health, fatal_error, node, features,
+                            earliest_block_hash, earliest_block_number,
+                            latest_block_hash, latest_block_number, restart_count
+                       from deployments where id = ?1",
+                    params![deployment.id.0],
+                    |row| {
+                        Ok((
+                            row.get::<_, bool>(0)?,
+                            row.get::<_, String>(1)?,
+                            row.get::<_, Option<i64>>(2)?,
+                            row.get::<_, Option<String>>(3)?,
+                            row.get::<_, String>(4)?,
+                            block_ptr(row.get(5)?, row.get(6)?),
+                            block_ptr(row.get(7)?, row.get(8)?),
+                            row.get::<_, i64>(9)?,
+                        ))
+                    },
+                )
+                .map_err(store_err)?;
+
+            let mut stmt = conn
+                .prepare_cached("select * from subgraph_errors where deployment = ?1 order by id")
+                .map_err(store_err)?;
+            let errors = stmt
+                .query_map(params![deployment.id.0], |row| {
+                    Ok((
+                        row.get::<_, i64>("id")?,
+                        subgraph_error(row, &deployment.hash)?,
+                    ))
+                })
+                .and_then(|rows| rows.collect::<Result<Vec<_>, _>>())
+                .map_err(store_err)?;
+            let (fatal, non_fatal): (Vec<_>, Vec<_>) = errors
+                .into_iter()
+                .partition(|(id, _)| Some(*id) == fatal_error);
+
+            let features: Vec<String> = serde_json::from_str(&features)?;
+            let mut entity_count = 0;
+            for table in deployment.layout.tables.values() {
+                entity_count += table.count(conn)?;
+            }
+
+            let block =
+                |ptr: BlockPtr| status::EthereumBlock::new(ptr.hash_as_h256(), ptr.number as u64);
+            Ok(status::Info {
+                id: deployment.id,
+                subgraph: deployment.hash.to_string(),
+                synced,
+                health: SubgraphHealth::from_str(&health)?,
+                fatal_error: fatal.into_iter().next().map(|(_, error)| error),
+                non_fatal_errors: non_fatal.into_iter().map(|(_, error)| error).collect(),
+                chains: vec![status::ChainInfo {
+                    network: deployment.network.clone(),
+                    chain_head_block: None,
+                    earliest_block: earliest.map(block),
+                    latest_block: latest.map(block),
+                }],
+                entity_count,
+                entity_size: None,
+                restart_count: restart_count as u64,
+                node,
+                features: features
+                    .iter()
+                    .filter_map(|feature| SubgraphFeature::from_str(feature).ok())
+                    .collect::<BTreeSet<_>>(),
+                available: true,
+                write_queue: None,
+                schema_hash: Some(deployment.input.canonical_hash()),
+                schema_compatibility: None,
+            })
+        })
+    }
+
+    pub(crate) fn version_info(&self, version: &str) -> Result<VersionInfo, StoreError> {
+        let row = self.db.with_conn(|conn| {
+            conn.query_row(
+                "select deployment, created_at from subgraph_versions where id = ?1",
+                params![version],
+                |row| Ok((row.get::<_, String>(0)?, row.get::<_, i64>(1)?)),
+            )
+            .optional()
+            .map_err(store_err)
+        })?;
+        let (hash, created_at) =
+            row.ok_or_else(|| StoreError::DeploymentNotFound(version.to_string()))?;
+        let deployment = self.deployment(&deployment_hash(hash)?)?;
+        let status = self.deployment_status(&deployment)?;
+
+        Ok(VersionInfo {
+            created_at: created_at.to_string(),
+            deployment_id: deployment.hash.to_string(),
+            latest_ethereum_block_number: status
+                .chains
+                .first()
+                .and_then(|chain| chain.latest_block.as_ref())
+                .map(|block| block.number()),
+            total_ethereum_blocks_count: None,
+            synced: status.synced,
+            failed: status.health.is_failed(),
+            description: deployment.description.clone(),
+            repository: deployment.repository.clone(),
+            schema: deployment.input.clone(),
+            network: deployment.network.clone(),
+        })
+    }
+
+    pub(crate) fn versions_for_subgraph_id(
+        &self,
+        subgraph_id: &str,
+    ) -> Result<(Option<String>, Option<String>), StoreError> {
+        let versions = self.db.with_conn(|conn| {
+            conn.query_row(
+                "select current_version, pending_version from subgraphs where id = ?1",
+                params![subgraph_id],
+                |row| Ok((row.get::<_, Option<i64>>(0)?, row.get::<_, Option<i64>>(1)?)),
+            )
+            .optional()
+            .map_err(store_err)
+        })?;
+        let (current, pending) = versions.unwrap_or((None, None));
+        Ok((
+            current.map(|id| id.to_string()),
+            pending.map(|id| id.to_string()),
+        ))
+    }
+
+    pub(crate) fn subgraphs_for_deployment_hash(
+        &self,
+        deployment_hash: &str,
+    ) -> Result<Vec<(String, String)>, StoreError> {
+        self.db.with_conn(|conn| {
+            let mut stmt = conn
+                .prepare_cached(
+                    "select s.name,
+                            case when s.pending_version = v.id then 'pending'
+                                 else 'current' end
+                       from subgraph_versions v
+                       join subgraphs s on v.id = s.current_version or v.id = s.pending_version
+                      where v.deployment = ?1",
+                )
+                .map_err(store_err)?;
+            let subgraphs = stmt
+                .query_map(params![deployment_hash], |row| {
+                    Ok((row.get(0)?, row.get(1)?))
+                })
+                .and_then(|rows| rows.collect::<Result<Vec<_>, _>>())
+                .map_err(store_err)?;
+            Ok(subgraphs)
+        })
+    }
+
+    /// Change the node that `deployment` is assigned to and let the
+    /// registrar know
+    pub(crate) fn set_node(
+        &self,
+        deployment: &DeploymentLocator,
+        node: Option<&NodeId>,
+    ) -> Result<(), StoreError> {
+        self.db.with_conn(|conn| {
+            conn.execute(
+                "update deployments set node = ?2 where id = ?1",
+                params![deployment.id.0, node.map(NodeId::as_str)],
+            )
+            .map_err(store_err)
+        })?;
+        let operation = match node {
+            Some(_) => EntityChangeOperation::Set,
+            None => EntityChangeOperation::Removed,
+        };
+        self.publish(StoreEvent::new(vec![EntityChange::for_assignment(
+            deployment.clone(),
+            operation,
+        )]));
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl SubgraphStoreTrait for SubgraphStore {
+    fn ens_lookup(&self) -> Arc<dyn EnsLookup> {
+        Arc::new(NoEnsLookup)
+    }
+
+    fn file_registry(&self) -> Arc<dyn FileRegistry> {
+        Arc::new(NoFileRegistry)
+    }
+
+    fn is_deployed(&self, id: &DeploymentHash) -> Result<bool, StoreError> {
+        match self.deployment(id) {
+            Ok(_) => Ok(true),
+            Err(StoreError::DeploymentNotFound(_)) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn create_subgraph_deployment(
+        &self,
+        name: SubgraphName,
+        schema: &Schema,
+        deployment: DeploymentCreate,
+        node_id: NodeId,
+        network: String,
+        mode: SubgraphVersionSwitchingMode,
+    ) -> Result<DeploymentLocator, StoreError> {
+        if deployment.graft_base.is_some() {
+            return Err(StoreError::Unknown(anyhow!(
+                "the SQLite store does not support grafting"
+            )));
+        }
+
+        let hash = schema.id.clone();
+        let (id, assigned) = self.db.transaction(|conn| {
+            let existing: Option<(i32, Option<String>)> = conn
+                .query_row(
+                    "select id, node from deployments where hash = ?1",
+                    params![hash.as_str()],
+                    |row| Ok((row.get(0)?, row.get(1)?)),
+                )
+                .optional()
+                .map_err(store_err)?;
+
+            let (id, assigned) = match existing {
+                Some((id, Some(_))) => (id, false),
+                Some((id, None)) => {
+                    conn.execute(
+                        "update deployments set node = ?2 where id = ?1",
+                        params![id, node_id.as_str()],
+                    )
+                    .map_err(store_err)?;
+                    (id, true)
+                }
+                None => {
+                    let manifest = &deployment.manifest;
+                    let earliest = deployment.earliest_block.as_ref();
+                    conn.execute(
+                        "insert into deployments(hash, network, node, schema, spec_version,
+                                                 description, repository, features,
+                                                 earliest_block_hash, earliest_block_number,
+                                                 debug_fork, created_at)
+                         values (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12)",
+                        params![
+                            hash.as_str(),
+                            network,
+                            node_id.as_str(),
+                            manifest.schema,
+                            manifest.spec_version,
+                            manifest.description,
+                            manifest.repository,
+                            serde_json::to_string(&manifest.features)?,
+                            earliest.map(|ptr| ptr.hash_slice().to_vec()),
+                            earliest.map(|ptr| ptr.number).unwrap_or(0),
+                            deployment.debug_fork.as_ref().map(|fork| fork.as_str()),
+                            now()
+                        ],
+                    )
+                    .map_err(store_err)?;
+                    let id = conn.last_insert_rowid() as i32;
+                    Layout::new(DeploymentId::new(id), hash.clone(), schema).create_tables(conn)?;
+                    (id, true)
+                }
+            };
+
+            let subgraph = Self::subgraph_id(conn, &name)?.ok_or_else(|| {
+                StoreError::Unknown(anyhow!(
+                    "Subgraph `{}` does not exist; create it first",
+                    name.as_str()
+                ))
+            })?;
+            Self::create_version(conn, subgraph, &hash, mode)?;
+            Ok((DeploymentId::new(id), assigned))
+        })?;
+
+        let locator = DeploymentLocator::new(id, hash);
+        if assigned {
+            self.publish(StoreEvent::new(vec![EntityChange::for_assignment(
+                locator.clone(),
+                EntityChangeOperation::Set,
+            )]));
+        }
+        Ok(locator)
+    }
+
+    fn create_subgraph(&self, name: SubgraphName) -> Result<String, StoreError> {
+        self.db.with_conn(|conn| {
+            if let Some(id) = Self::subgraph_id(conn, &name)? {
+                return Ok(id.to_string());
+            }
+            conn.execute(
+                "insert into subgraphs(name, created_at) values (?1, ?2)",
+                params![name.as_str(), now()],
+            )
+            .map_err(store_err)?;
+            Ok(conn.last_insert_rowid().to_string())
+        })
+    }
+
+    fn remove_subgraph(&self, name: SubgraphName) -> Result<(), StoreError> {
+        let unused = self.db.transaction(|conn| {
+            let subgraph = Self::subgraph_id(conn, &name)?.ok_or_else(|| {
+                StoreError::Unknown(anyhow!("Subgraph `{}` not found", name.as_str()))
+            })?;
+            conn.execute("delete from subgraphs where id = ?1", params![subgraph])
+                .map_err(store_err)?;
+            conn.execute(
+                "delete from subgraph_deprecations where name = ?1",
+                params![name.as_str()],
+            )
+            .map_err(store_err)?;
+
+            // Deployments that no subgraph uses anymore do not need to be
+            // indexed
+            let mut stmt = conn
+                .prepare_cached(
+                    "select id, hash from deployments
+                      where node is not null
+                        and hash not in (select deployment from subgraph_versions)",
+                )
+                .map_err(store_err)?;
+            let unused = stmt
+                .query_map([], |row| {
+                    Ok((row.get::<_, i32>(0)?, row.get::<_, String>(1)?))
+                })
+                .and_then(|rows| rows.collect::<Result<Vec<_>, _>>())
+                .map_err(store_err)?;
+            Ok(unused)
+        })?;
+
+        for (id, hash) in unused {
+            let locator = DeploymentLocator::new(DeploymentId::new(id), deployment_hash(hash)?);
+            self.set_node(&locator, None)?;
+        }
+        Ok(())
+    }
+
+    fn reassign_subgraph(
+        &self,
+        deployment: &DeploymentLocator,
+        node_id: &NodeId,
+    ) -> Result<(), StoreError> {
+        if self.assigned_node(deployment)?.is_none() {
+            return Err(StoreError::DeploymentNotFound(deployment.hash.to_string()));
+        }
+        self.set_node(deployment, Some(node_id))
+    }
+
+    fn assigned_node(&self, deployment: &DeploymentLocator) -> Result<Option<NodeId>, StoreError> {
+        let node: Option<String> = self.db.with_conn(|conn| {
+            conn.query_row(
+                "select node from deployments where id = ?1",
+                params![deployment.id.0],
+                |row| row.get(0),
+            )
+            .optional()
+            .map(Option::flatten)
+            .map_err(store_err)
+        })?;
+        node.map(|node| {
+            NodeId::new(node.clone())
+                .map_err(|()| constraint_violation!("illegal node id `{}` in the database", node))
+        })
+        .transpose()
+    }
+
+    fn assignments(&self, node: &NodeId) -> Result<Vec<DeploymentLocator>, StoreError> {
+        let rows = self.db.with_conn(|conn| {
+            let mut stmt = conn
+                .prepare_cached("select id, hash from deployments where node = ?1")
+                .map_err(store_err)?;
+            let rows = stmt
+                .query_map(params![node.as_str()], |row| {
+                    Ok((row.get::<_, i32>(0)?, row.get::<_, String>(1)?))
+                })
+                .and_then(|rows| rows.collect::<Result<Vec<_>, _>>())
+                .map_err(store_err)?;
+            Ok(rows)
+        })?;
+        rows.into_iter()
+            .map(|(id, hash)| {
+                Ok(DeploymentLocator::new(
+                    DeploymentId::new(id),
+                    deployment_hash(hash)?,
+                ))
+            })
+            .collect()
+    }
+
+    fn record_restart(&self, deployment: &DeploymentLocator) -> Result<(), StoreError> {
+        self.db.with_conn(|conn| {
+            conn.execute(
+                "update deployments set restart_count = restart_count + 1 where id = ?1",
+                params![deployment.id.0],
+            )
+            .map(|_| ())
+            .map_err(store_err)
+        })
+    }
+
+    fn subgraph_exists(&self, name: &SubgraphName) -> Result<bool, StoreError> {
+        self.db
+            .with_conn(|conn| Self::subgraph_id(conn, name))
+            .map(|id| id.is_some())
+    }
+
+    fn add_to_family(
+        &self,
+        family: &DeploymentHash,
+        deployment: &DeploymentHash,
+        network: &str,
+    ) -> Result<(), StoreError> {
+        self.db.with_conn(|conn| {
+            conn.execute(
+                "insert or ignore into deployment_families(deployment, family, network)
+                 values (?1, ?2, ?3)",
+                params![deployment.as_str(), family.as_str(), network],
+            )
+            .map_err(store_err)?;
+            Ok(())
+        })
+    }
+
+    fn family(&self, family: &DeploymentHash) -> Result<Vec<(DeploymentHash, String)>, StoreError> {
+        let members: Vec<(String, String)> = self.db.with_conn(|conn| {
+            let mut stmt = conn
+                .prepare_cached(
+                    "select deployment, network from deployment_families
+                      where family = ?1 order by network",
+                )
+                .map_err(store_err)?;
+            stmt.query_map(params![family.as_str()], |row| {
+                Ok((row.get(0)?, row.get(1)?))
+            })
+            .and_then(|rows| rows.collect::<Result<Vec<_>, _>>())
+            .map_err(store_err)
+        })?;
+        members
+            .into_iter()
+            .map(|(deployment, network)| {
+                DeploymentHash::new(deployment)
+                    .map(|deployment| (deployment, network))
+                    .map_err(|deployment| {
+                        StoreError::Unknown(anyhow!(
+                            "invalid deployment hash `{}` in a family",
+                            deployment
+                        ))
+                    })
+            })
+            .collect()
+    }
+
+    fn set_deprecation(
+        &self,
+        name: &SubgraphName,
+        deprecation: Option<SubgraphDeprecation>,
+    ) -> Result<(), StoreError> {
+        self.db.with_conn(|conn| {
+            match deprecation {
+                Some(deprecation) => conn.execute(
+                    "insert or replace into subgraph_deprecations(name, message, removal_date)
+                     values (?1, ?2, ?3)",
+                    params![
+                        name.as_str(),
+                        deprecation.message,
+                        deprecation.removal_date_string()
+                    ],
+                ),
+                None => conn.execute(
+                    "delete from subgraph_deprecations where name = ?1",
+                    params![name.as_str()],
+                ),
+            }
+            .map_err(store_err)?;
+            Ok(())
+        })
+    }
+
+    fn deprecation(&self, name: &SubgraphName) -> Result<Option<SubgraphDeprecation>, StoreError> {
+        let deprecation: Option<(String, String)> = self.db.with_conn(|conn| {
+            conn.query_row(
+                "select message, removal_date from subgraph_deprecations where name = ?1",
+                params![name.as_str()],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .optional()
+            .map_err(store_err)
+        })?;
+        deprecation
+            .map(|(message, removal_date)| {
+                let removal_date = SubgraphDeprecation::parse_date(&removal_date).map_err(|e| {
+                    StoreError::Unknown(anyhow!(
+                        "invalid removal date `{}` for subgraph `{}`: {}",
+                        removal_date,
+                        name,
+                        e
+                    ))
+                })?;
+                Ok(SubgraphDeprecation {
+                    message,
+                    removal_date,
+                })
+            })
+            .transpose()
+    }
+
+    fn entity_changes_in_block(
+        &self,
+        subgraph_id: &DeploymentHash,
+        block_number: BlockNumber,
+    ) -> Result<Vec<EntityOperation>, StoreError> {
+        let deployment = self.deployment(subgraph_id)?;
+        self.db.with_conn(|conn| {
+            let mut changes = Vec::new();
+            for table in deployment.layout.tables.values() {
+                changes.extend(table.changes_in_block(conn, &deployment.hash, block_number)?);
+            }
+            Ok(changes)
+        })
+    }
+
+    fn input_schema(&self, subgraph_id: &DeploymentHash) -> Result<Arc<Schema>, StoreError> {
+        Ok(self.deployment(subgraph_id)?.input.clone())
+    }
+
+    fn api_schema(&self, subgraph_id: &DeploymentHash) -> Result<Arc<ApiSchema>, StoreError> {
+        Ok(self.deployment(subgraph_id)?.api.clone())
+    }
+
+    fn debug_fork(
+        &self,
+        subgraph_id: &DeploymentHash,
+        logger: Logger,
+    ) -> Result<Option<Arc<dyn SubgraphFork>>, StoreError> {
+        let deployment = self.deployment(subgraph_id)?;
+        match (self.fork_base.as_ref(), deployment.debug_fork.as_ref()) {
+            (Some(base), Some(fork_id)) => Ok(Some(Arc::new(fork::SubgraphFork::new(
+                base.clone(),
+                fork_id.clone(),
+                deployment.input.clone(),
+                logger,
+            )?))),
+            _ => Ok(None),
+        }
+    }
+
+    async fn writable(
+        self: Arc<Self>,
+        _logger: Logger,
+        deployment: DeploymentId,
+    ) -> Result<Arc<dyn WritableStoreTrait>, StoreError> {
+        let deployment = self.deployment_by_id(deployment)?;
+        Ok(Arc::new(WritableStore::new(self, deployment)))
+    }
+
+    async fn least_block_ptr(&self, id: &DeploymentHash) -> Result<Option<BlockPtr>, StoreError> {
+        let hash = id.to_string();
+        self.db
+            .spawn_with_conn(move |conn| {
+                conn.query_row(
+                    "select latest_block_hash, latest_block_number from deployments where hash = ?1",
+                    params![hash],
+                    |row| Ok(block_ptr(row.get(0)?, row.get(1)?)),
+                )
+                .optional()
+                .map(Option::flatten)
+                .map_err(store_err)
+            })
+            .await
+    }
+
+    fn locators(&self, hash: &str) -> Result<Vec<DeploymentLocator>, StoreError> {
+        match self.deployment(&deployment_hash(hash.to_string())?) {
+            Ok(deployment) => Ok(vec![deployment.locator()]),
+            Err(StoreError::DeploymentNotFound(_)) => Ok(vec![]),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn export(
+        &self,
+        _deployment: &DeploymentLocator,
+        _request: &ExportRequest,
+    ) -> Result<Vec<ExportedFile>, StoreError> {
+        Err(StoreError::Unknown(anyhow!(
+            "the SQLite store does not support exporting entities"
+        )))
+    }
+
+    fn record_admin_call(&self, _call: &AdminCall) -> Result<(), StoreError> {
+        // There is no audit log
+        Ok(())
+    }
+
+    fn deliver_entity_changes(
+        &self,
+        _limit: usize,
+        _deliver: &mut dyn FnMut(&[OutboxEntry]) -> Result<(), Error>,
+    ) -> Result<usize, StoreError> {
+        // There is no outbox
+        Ok(0)
+    }
+}
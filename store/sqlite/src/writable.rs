@@ -0,0 +1,596 @@
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use graph::components::store::{
+    EntityType, StoredDynamicDataSource, UnfailOutcome, WritableStore as WritableStoreTrait,
+};
+use graph::data::subgraph::schema::{SubgraphError, SubgraphHealth};
+use graph::data::subgraph::Source;
+use graph::prelude::ethabi::Address;
+use graph::prelude::{
+    async_trait, BlockNumber, BlockPtr, DeploymentHash, Entity, EntityChange, EntityKey,
+    EntityModification, Logger, Schema, StopwatchMetrics, StoreError, StoreEvent, BLOCK_NUMBER_MAX,
+};
+use rusqlite::{params, Connection, OptionalExtension, Row};
+
+use crate::store_err;
+use crate::subgraph_store::{subgraph_error, Deployment, SubgraphStore};
+
+/// The columns of `dynamic_data_sources` that `data_source` reads
+const DATA_SOURCE_COLUMNS: &str = "name, address, abi, start_block, context, creation_block";
+
+fn data_source(row: &Row) -> rusqlite::Result<StoredDynamicDataSource> {
+    Ok(StoredDynamicDataSource {
+        name: row.get(0)?,
+        source: Source {
+            address: row
+                .get::<_, Option<Vec<u8>>>(1)?
+                .map(|address| Address::from_slice(&address)),
+            abi: row.get(2)?,
+            start_block: row.get(3)?,
+        },
+        context: row.get(4)?,
+        creation_block: row.get(5)?,
+        removed_block: None,
+    })
+}
+
+/// Writes the changes that indexing a deployment produces. Writes happen
+/// right away; there is no queue
+#[derive(Clone)]
+pub struct WritableStore {
+    store: Arc<SubgraphStore>,
+    deployment: Arc<Deployment>,
+}
+
+impl WritableStore {
+    pub(crate) fn new(store: Arc<SubgraphStore>, deployment: Arc<Deployment>) -> Self {
+        WritableStore { store, deployment }
+    }
+
+    fn id(&self) -> i32 {
+        self.deployment.id.0
+    }
+
+    /// Mark the stored data source that is the same as `ds` as removed in
+    /// the block in its `removed_block`
+    fn remove_data_source(
+        &self,
+        conn: &Connection,
+        ds: &StoredDynamicDataSource,
+    ) -> Result<(), StoreError> {
+        let mut stmt = conn
+            .prepare_cached(&format!(
+                "select {}, id from dynamic_data_sources
+                  where deployment = ?1 and name = ?2 and creation_block is ?3
+                    and removed_block is null",
+                DATA_SOURCE_COLUMNS
+            ))
+            .map_err(store_err)?;
+        let candidates = stmt
+            .query_map(params![self.id(), ds.name, ds.creation_block], |row| {
+                Ok((row.get::<_, i64>(6)?, data_source(row)?))
+            })
+            .and_then(|rows| rows.collect::<Result<Vec<_>, _>>())
+            .map_err(store_err)?;
+        for (id, stored) in candidates {
+            if stored.is_same_as(ds) {
+                conn.execute(
+                    "update dynamic_data_sources set removed_block = ?2 where id = ?1",
+                    params![id, ds.removed_block],
+                )
+                .map_err(store_err)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn latest_block(&self, conn: &Connection) -> Result<Option<BlockPtr>, StoreError> {
+        conn.query_row(
+            "select latest_block_hash, latest_block_number from deployments where id = ?1",
+            params![self.id()],
+            |row| Ok((row.get::<_, Option<Vec<u8>>>(0)?, row.get(1)?)),
+        )
+        .map_err(store_err)
+        .map(|(hash, number)| {
+            hash.zip(number)
+                .map(|(hash, number)| BlockPtr::from((hash, number)))
+        })
+    }
+
+    fn insert_error(&self, conn: &Connection, error: &SubgraphError) -> Result<i64, StoreError> {
+        conn.execute(
+            "insert into subgraph_errors(deployment, message, block_hash, block_number,
+                                         handler, deterministic)
+             values (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![
+                self.id(),
+                error.message,
+                error
+                    .block_ptr
+                    .as_ref()
+                    .map(|ptr| ptr.hash_slice().to_vec()),
+                error.block_ptr.as_ref().map(|ptr| ptr.number),
+                error.handler,
+                error.deterministic
+            ],
+        )
+        .map_err(store_err)?;
+        Ok(conn.last_insert_rowid())
+    }
+
+    fn fatal_error(&self, conn: &Connection) -> Result<Option<(i64, SubgraphError)>, StoreError> {
+        conn.query_row(
+            "select e.* from subgraph_errors e join deployments d on e.id = d.fatal_error
+              where d.id = ?1",
+            params![self.id()],
+            |row| Ok((row.get("id")?, subgraph_error(row, &self.deployment.hash)?)),
+        )
+        .optional()
+        .map_err(store_err)
+    }
+
+    /// Set the health to unhealthy if there are non-fatal errors at or
+    /// before `block`, and to healthy otherwise. Failed deployments stay
+    /// failed unless `clear_fatal` is set
+    fn set_health(
+        &self,
+        conn: &Connection,
+        block: BlockNumber,
+        clear_fatal: bool,
+    ) -> Result<(), StoreError> {
+        let has_errors: bool = conn
+            .query_row(
+                "select exists (select 1 from subgraph_errors e join deployments d
+                                    on e.deployment = d.id
+                                 where d.id = ?1 and e.deterministic
+                                   and e.block_number <= ?2
+                                   and e.id is not d.fatal_error)",
+                params![self.id(), block],
+                |row| row.get(0),
+            )
+            .map_err(store_err)?;
+        let health = if has_errors {
+            SubgraphHealth::Unhealthy
+        } else {
+            SubgraphHealth::Healthy
+        };
+        let query = if clear_fatal {
+            "update deployments set health = ?2, fatal_error = null where id = ?1"
+        } else {
+            "update deployments set health = ?2 where id = ?1 and fatal_error is null"
+        };
+        conn.execute(query, params![self.id(), health.as_str()])
+            .map(|_| ())
+            .map_err(store_err)
+    }
+
+    /// Undo everything that happened after `block_ptr_to` and return the
+    /// event for the entity types that changed
+    fn revert(
+        &self,
+        conn: &Connection,
+        block_ptr_to: &BlockPtr,
+        firehose_cursor: Option<&str>,
+    ) -> Result<StoreEvent, StoreError> {
+        let block = block_ptr_to.number;
+        let mut changes = Vec::new();
+        for table in self.deployment.layout.tables.values() {
+            if table.revert(conn, block)? {
+                changes.push(EntityChange::Data {
+                    subgraph_id: self.deployment.hash.clone(),
+                    entity_type: table.entity_type.clone(),
+                });
+            }
+        }
+        self.deployment.layout.poi.revert(conn, block)?;
+
+        conn.execute(
+            "delete from dynamic_data_sources where deployment = ?1 and block > ?2",
+            params![self.id(), block],
+        )
+        .and_then(|_| {
+            conn.execute(
+                "update dynamic_data_sources set removed_block = null
+                  where deployment = ?1 and removed_block > ?2",
+                params![self.id(), block],
+            )
+        })
+        .and_then(|_| {
+            conn.execute(
+                "delete from subgraph_errors where deployment = ?1 and block_number > ?2",
+                params![self.id(), block],
+            )
+        })
+        .and_then(|_| {
+            conn.execute(
+                "update deployments
+                    set latest_block_hash = ?2, latest_block_number = ?3, cursor = ?4,
+                        reorg_count = reorg_count + 1,
+                        current_reorg_depth = current_reorg_depth + 1,
+                        max_reorg_depth = max(max_reorg_depth, current_reorg_depth + 1)
+                  where id = ?1",
+                params![self.id(), block_ptr_to.hash_slice(), block, firehose_cursor],
+            )
+        })
+        .map_err(store_err)?;
+        self.set_health(conn, block, false)?;
+
+        Ok(StoreEvent::new(changes))
+    }
+}
+
+#[async_trait]
+impl WritableStoreTrait for WritableStore {
+    async fn block_ptr(&self) -> Option<BlockPtr> {
+        let store = self.clone();
+        self.store
+            .db
+            .spawn_with_conn(move |conn| store.latest_block(conn))
+            .await
+            .ok()
+            .flatten()
+    }
+
+    async fn block_cursor(&self) -> Option<String> {
+        let id = self.id();
+        self.store
+            .db
+            .spawn_with_conn(move |conn| {
+                conn.query_row(
+                    "select cursor from deployments where id = ?1",
+                    params![id],
+                    |row| row.get(0),
+                )
+                .map_err(store_err)
+            })
+            .await
+            .ok()
+            .flatten()
+    }
+
+    async fn delete_block_cursor(&self) -> Result<(), StoreError> {
+        let id = self.id();
+        self.store
+            .db
+            .spawn_with_conn(move |conn| {
+                conn.execute(
+                    "update deployments set cursor = null where id = ?1",
+                    params![id],
+                )
+                .map(|_| ())
+                .map_err(store_err)
+            })
+            .await
+    }
+
+    async fn start_subgraph_deployment(&self, _logger: &Logger) -> Result<(), StoreError> {
+        // Make sure the block pointer is set so that we respect the
+        // 'startBlock' setting the first time the subgraph is started
+        let id = self.id();
+        self.store
+            .db
+            .spawn_with_conn(move |conn| {
+                conn.execute(
+                    "update deployments
+                        set latest_block_hash = earliest_block_hash,
+                            latest_block_number = earliest_block_number
+                      where id = ?1 and latest_block_hash is null
+                        and earliest_block_hash is not null",
+                    params![id],
+                )
+                .map(|_| ())
+                .map_err(store_err)
+            })
+            .await
+    }
+
+    async fn revert_block_operations(
+        &self,
+        block_ptr_to: BlockPtr,
+        firehose_cursor: Option<&str>,
+    ) -> Result<(), StoreError> {
+        let store = self.clone();
+        let firehose_cursor = firehose_cursor.map(str::to_owned);
+        let event = self
+            .store
+            .db
+            .spawn_transaction(move |conn| {
+                store.revert(conn, &block_ptr_to, firehose_cursor.as_deref())
+            })
+            .await?;
+        self.store.publish(event);
+        Ok(())
+    }
+
+    fn unfail_deterministic_error(
+        &self,
+        current_ptr: &BlockPtr,
+        parent_ptr: &BlockPtr,
+    ) -> Result<UnfailOutcome, StoreError> {
+        let event = self.store.db.transaction(|conn| {
+            let error = match self.fatal_error(conn)? {
+                Some((_, error)) if error.deterministic => error,
+                _ => return Ok(None),
+            };
+
+            // Only revert if the error happened for the current deployment
+            // head
+            match &error.block_ptr {
+                Some(ptr) if ptr.hash == current_ptr.hash => {
+                    let event = self.revert(conn, parent_ptr, Some(""))?;
+                    self.set_health(conn, parent_ptr.number, true)?;
+                    Ok(Some(event))
+                }
+                _ => Ok(None),
+            }
+        })?;
+
+        match event {
+            Some(event) => {
+                self.store.publish(event);
+                Ok(UnfailOutcome::Unfailed)
+            }
+            None => Ok(UnfailOutcome::Noop),
+        }
+    }
+
+    fn unfail_non_deterministic_error(
+        &self,
+        current_ptr: &BlockPtr,
+    ) -> Result<UnfailOutcome, StoreError> {
+        self.store.db.transaction(|conn| {
+            let (id, error) = match self.fatal_error(conn)? {
+                Some((id, error)) if !error.deterministic => (id, error),
+                _ => return Ok(UnfailOutcome::Noop),
+            };
+
+            match error.block_ptr {
+                // The deployment head advanced past the error, which means
+                // that it did not happen again
+                Some(ptr) if current_ptr.number >= ptr.number => {
+                    conn.execute("delete from subgraph_errors where id = ?1", params![id])
+                        .map_err(store_err)?;
+                    conn.execute(
+                        "update deployments set health = 'healthy', fatal_error = null
+                          where id = ?1",
+                        params![self.id()],
+                    )
+                    .map_err(store_err)?;
+                    Ok(UnfailOutcome::Unfailed)
+                }
+                _ => Ok(UnfailOutcome::Noop),
+            }
+        })
+    }
+
+    async fn fail_subgraph(&self, error: SubgraphError) -> Result<(), StoreError> {
+        let store = self.clone();
+        self.store
+            .db
+            .spawn_transaction(move |conn| {
+                let id = store.insert_error(conn, &error)?;
+                conn.execute(
+                    "update deployments set health = 'failed', fatal_error = ?2 where id = ?1",
+                    params![store.id(), id],
+                )
+                .map(|_| ())
+                .map_err(store_err)
+            })
+            .await
+    }
+
+    async fn supports_proof_of_indexing(&self) -> Result<bool, StoreError> {
+        Ok(true)
+    }
+
+    fn get(&self, key: &EntityKey) -> Result<Option<Entity>, StoreError> {
+        let table = self.deployment.layout.table(&key.entity_type)?;
+        self.store
+            .db
+            .with_conn(|conn| table.find(conn, &key.entity_id, BLOCK_NUMBER_MAX))
+    }
+
+    async fn transact_block_operations(
+        &self,
+        block_ptr_to: BlockPtr,
+        firehose_cursor: Option<String>,
+        mods: Vec<EntityModification>,
+        _stopwatch: &StopwatchMetrics,
+        data_sources: Vec<StoredDynamicDataSource>,
+        deterministic_errors: Vec<SubgraphError>,
+    ) -> Result<(), StoreError> {
+        let block = block_ptr_to.number;
+        let event: StoreEvent = mods.iter().collect();
+        let store = self.clone();
+        self.store
+            .db
+            .spawn_transaction(move |conn| {
+                if let Some(latest) = store.latest_block(conn)? {
+                    if latest.number >= block {
+                        return Err(StoreError::DuplicateBlockProcessing(
+                            store.deployment.hash.clone(),
+                            block,
+                        ));
+                    }
+                }
+
+                for modification in &mods {
+                    let key = modification.entity_key();
+                    let table = store.deployment.layout.table(&key.entity_type)?;
+                    match modification {
+                        EntityModification::Insert { data, .. } => {
+                            table.insert(conn, data, block)?
+                        }
+                        EntityModification::Overwrite { data, .. } => {
+                            table.clamp(conn, &key.entity_id, block)?;
+                            table.insert(conn, data, block)?;
+                        }
+                        EntityModification::Remove { .. } => {
+                            table.clamp(conn, &key.entity_id, block)?
+                        }
+                    }
+                }
+
+                for ds in data_sources.iter().filter(|ds| ds.removed_block.is_none()) {
+                    conn.execute(
+                        "insert into dynamic_data_sources(deployment, name, address, abi,
+                                                          start_block, context, creation_block,
+                                                          block)
+                         values (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+                        params![
+                            store.id(),
+                            ds.name,
+                            ds.source.address.map(|address| address.as_bytes().to_vec()),
+                            ds.source.abi,
+                            ds.source.start_block,
+                            ds.context,
+                            ds.creation_block,
+                            block
+                        ],
+                    )
+                    .map_err(store_err)?;
+                }
+                for ds in data_sources.iter().filter(|ds| ds.removed_block.is_some()) {
+                    store.remove_data_source(conn, ds)?;
+                }
+
+                for error in &deterministic_errors {
+                    store.insert_error(conn, error)?;
+                }
+
+                conn.execute(
+                    "update deployments
+                        set latest_block_hash = ?2, latest_block_number = ?3, cursor = ?4,
+                            current_reorg_depth = 0
+                      where id = ?1",
+                    params![
+                        store.id(),
+                        block_ptr_to.hash_slice(),
+                        block,
+                        firehose_cursor
+                    ],
+                )
+                .map_err(store_err)?;
+                store.set_health(conn, block, false)
+            })
+            .await?;
+
+        self.store.publish(event);
+        Ok(())
+    }
+
+    fn get_many(
+        &self,
+        ids_for_type: BTreeMap<&EntityType, Vec<&str>>,
+    ) -> Result<BTreeMap<EntityType, Vec<Entity>>, StoreError> {
+        self.store.db.with_conn(|conn| {
+            let mut entities = BTreeMap::new();
+            for (entity_type, ids) in ids_for_type {
+                let table = self.deployment.layout.table(entity_type)?;
+                let mut found = Vec::new();
+                for id in ids {
+                    if let Some(entity) = table.find(conn, id, BLOCK_NUMBER_MAX)? {
+                        found.push(entity);
+                    }
+                }
+                if !found.is_empty() {
+                    entities.insert(entity_type.clone(), found);
+                }
+            }
+            Ok(entities)
+        })
+    }
+
+    fn deployment_synced(&self) -> Result<(), StoreError> {
+        self.store.db.transaction(|conn| {
+            conn.execute(
+                "update deployments set synced = 1 where id = ?1",
+                params![self.id()],
+            )
+            .map_err(store_err)?;
+
+            // Promote the deployment in the subgraphs where it is the
+            // pending version
+            conn.execute(
+                "update subgraphs
+                    set current_version = pending_version, pending_version = null
+                  where pending_version in
+                        (select id from subgraph_versions where deployment = ?1)",
+                params![self.deployment.hash.as_str()],
+            )
+            .map(|_| ())
+            .map_err(store_err)
+        })
+    }
+
+    async fn is_deployment_synced(&self) -> Result<bool, StoreError> {
+        let id = self.id();
+        self.store
+            .db
+            .spawn_with_conn(move |conn| {
+                conn.query_row(
+                    "select synced from deployments where id = ?1",
+                    params![id],
+                    |row| row.get(0),
+                )
+                .map_err(store_err)
+            })
+            .await
+    }
+
+    fn unassign_subgraph(&self) -> Result<(), StoreError> {
+        self.store.set_node(&self.deployment.locator(), None)
+    }
+
+    async fn load_dynamic_data_sources(&self) -> Result<Vec<StoredDynamicDataSource>, StoreError> {
+        let id = self.id();
+        self.store
+            .db
+            .spawn_with_conn(move |conn| {
+                let mut stmt = conn
+                    .prepare_cached(&format!(
+                        "select {} from dynamic_data_sources
+                          where deployment = ?1 and removed_block is null
+                          order by id",
+                        DATA_SOURCE_COLUMNS
+                    ))
+                    .map_err(store_err)?;
+                let data_sources = stmt
+                    .query_map(params![id], data_source)
+                    .and_then(|rows| rows.collect::<Result<Vec<_>, _>>())
+                    .map_err(store_err)?;
+                Ok(data_sources)
+            })
+            .await
+    }
+
+    fn shard(&self) -> &str {
+        "sqlite"
+    }
+
+    async fn health(&self, _id: &DeploymentHash) -> Result<SubgraphHealth, StoreError> {
+        let id = self.id();
+        let health: String = self
+            .store
+            .db
+            .spawn_with_conn(move |conn| {
+                conn.query_row(
+                    "select health from deployments where id = ?1",
+                    params![id],
+                    |row| row.get(0),
+                )
+                .map_err(store_err)
+            })
+            .await?;
+        health.parse().map_err(StoreError::Unknown)
+    }
+
+    fn input_schema(&self) -> Arc<Schema> {
+        self.deployment.input.clone()
+    }
+
+    async fn flush(&self) -> Result<(), StoreError> {
+        Ok(())
+    }
+}
@@ -0,0 +1,302 @@
+use std::sync::Arc;
+
+use graph::components::store::{DeploymentLocator, EntityKey, EntityModification, WritableStore};
+use graph::components::subgraph::ProofOfIndexingFinisher;
+use graph::data::store::scalar::Bytes;
+use graph::data::subgraph::schema::{DeploymentCreate, SubgraphManifestEntity, POI_OBJECT};
+use graph::data::subgraph::status;
+use graph::entity;
+use graph::log::logger;
+use graph::prelude::{
+    BlockPtr, DeploymentHash, Entity, NodeId, Schema, StatusStore, StopwatchMetrics, SubgraphName,
+    SubgraphStore as SubgraphStoreTrait, SubgraphVersionSwitchingMode,
+};
+use graph::url::Url;
+use graph_mock::MockMetricsRegistry;
+use graph_store_sqlite::{
+    BlockStore, ChainHeadUpdateListener, Database, Store, SubgraphStore, SubscriptionManager,
+};
+
+const SCHEMA: &str = "type User @entity { id: ID!, name: String! }";
+
+fn block(number: i32) -> BlockPtr {
+    BlockPtr::from((vec![number as u8; 32], number))
+}
+
+fn user_key(hash: &DeploymentHash, id: &str) -> EntityKey {
+    EntityKey::data(hash.clone(), "User".to_string(), id.to_string())
+}
+
+fn user(hash: &DeploymentHash, id: &str, name: &str) -> EntityModification {
+    EntityModification::Overwrite {
+        key: user_key(hash, id),
+        data: entity! { id: id, name: name },
+    }
+}
+
+fn deployment(debug_fork: Option<DeploymentHash>) -> DeploymentCreate {
+    DeploymentCreate {
+        manifest: SubgraphManifestEntity {
+            spec_version: "0.0.4".to_string(),
+            description: None,
+            repository: None,
+            features: vec![],
+            schema: SCHEMA.to_string(),
+        },
+        earliest_block: None,
+        graft_base: None,
+        graft_block: None,
+        debug_fork,
+    }
+}
+
+fn create_deployment(
+    store: &SubgraphStore,
+    hash: &DeploymentHash,
+    debug_fork: Option<DeploymentHash>,
+) -> DeploymentLocator {
+    let schema = Schema::parse(SCHEMA, hash.clone()).unwrap();
+    let name = SubgraphName::new("sqlite-test").unwrap();
+    store.create_subgraph(name.clone()).unwrap();
+    store
+        .create_subgraph_deployment(
+            name,
+            &schema,
+            deployment(debug_fork),
+            NodeId::new("test").unwrap(),
+            "mainnet".to_string(),
+            SubgraphVersionSwitchingMode::Instant,
+        )
+        .unwrap()
+}
+
+async fn setup() -> (DeploymentHash, Arc<dyn WritableStore>, StopwatchMetrics) {
+    let db = Arc::new(Database::in_memory().unwrap());
+    let store = Arc::new(SubgraphStore::new(db, Arc::new(SubscriptionManager::new())));
+
+    let hash = DeploymentHash::new("QmSqliteTest").unwrap();
+    let loc = create_deployment(&store, &hash, None);
+    let writable = store.writable(logger(true), loc.id).await.unwrap();
+    let stopwatch = StopwatchMetrics::new(
+        logger(true),
+        hash.clone(),
+        "test",
+        Arc::new(MockMetricsRegistry::new()),
+    );
+    (hash, writable, stopwatch)
+}
+
+fn name_of(entity: Option<Entity>) -> Option<String> {
+    entity.and_then(|entity| entity.get("name").cloned()?.as_string())
+}
+
+#[tokio::test]
+async fn write_and_revert() {
+    let (hash, writable, stopwatch) = setup().await;
+
+    writable
+        .transact_block_operations(
+            block(1),
+            None,
+            vec![user(&hash, "1", "Alice")],
+            &stopwatch,
+            vec![],
+            vec![],
+        )
+        .await
+        .unwrap();
+    writable
+        .transact_block_operations(
+            block(2),
+            None,
+            vec![user(&hash, "1", "Alicia"), user(&hash, "2", "Bob")],
+            &stopwatch,
+            vec![],
+            vec![],
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(Some(block(2)), writable.block_ptr().await);
+    assert_eq!(
+        Some("Alicia".to_string()),
+        name_of(writable.get(&user_key(&hash, "1")).unwrap())
+    );
+    assert!(writable.get(&user_key(&hash, "2")).unwrap().is_some());
+
+    writable
+        .revert_block_operations(block(1), None)
+        .await
+        .unwrap();
+
+    assert_eq!(Some(block(1)), writable.block_ptr().await);
+    assert_eq!(
+        Some("Alice".to_string()),
+        name_of(writable.get(&user_key(&hash, "1")).unwrap())
+    );
+    assert_eq!(None, writable.get(&user_key(&hash, "2")).unwrap());
+}
+
+#[tokio::test]
+async fn rejects_duplicate_blocks() {
+    let (hash, writable, stopwatch) = setup().await;
+
+    writable
+        .transact_block_operations(
+            block(1),
+            None,
+            vec![user(&hash, "1", "Alice")],
+            &stopwatch,
+            vec![],
+            vec![],
+        )
+        .await
+        .unwrap();
+    let res = writable
+        .transact_block_operations(
+            block(1),
+            None,
+            vec![user(&hash, "1", "Alicia")],
+            &stopwatch,
+            vec![],
+            vec![],
+        )
+        .await;
+    assert!(res.is_err());
+}
+
+#[test]
+fn debug_fork_needs_fork_base() {
+    let hash = DeploymentHash::new("QmSqliteTest").unwrap();
+    let fork = DeploymentHash::new("QmSqliteFork").unwrap();
+
+    let db = Arc::new(Database::in_memory().unwrap());
+    let store = SubgraphStore::new(db.clone(), Arc::new(SubscriptionManager::new()));
+    create_deployment(&store, &hash, Some(fork));
+    assert!(store.debug_fork(&hash, logger(true)).unwrap().is_none());
+
+    let base = Url::parse("http://localhost:8000/subgraphs/id/").unwrap();
+    let store = SubgraphStore::new(db, Arc::new(SubscriptionManager::new())).with_fork_base(base);
+    assert!(store.debug_fork(&hash, logger(true)).unwrap().is_some());
+}
+
+#[test]
+fn status_reports_restarts() {
+    let hash = DeploymentHash::new("QmSqliteTest").unwrap();
+
+    let db = Arc::new(Database::in_memory().unwrap());
+    let subgraph_store = Arc::new(SubgraphStore::new(
+        db.clone(),
+        Arc::new(SubscriptionManager::new()),
+    ));
+    let loc = create_deployment(&subgraph_store, &hash, None);
+    subgraph_store.record_restart(&loc).unwrap();
+    subgraph_store.record_restart(&loc).unwrap();
+
+    let block_store = Arc::new(BlockStore::new(
+        db,
+        Arc::new(ChainHeadUpdateListener::new()),
+    ));
+    let store = Store::new(subgraph_store, block_store);
+    let infos = store
+        .status(status::Filter::Deployments(vec![hash.to_string()]))
+        .unwrap();
+    assert_eq!(2, infos[0].restart_count);
+}
+
+#[tokio::test]
+async fn keeps_proof_of_indexing() {
+    let hash = DeploymentHash::new("QmSqliteTest").unwrap();
+    let db = Arc::new(Database::in_memory().unwrap());
+    let subgraph_store = Arc::new(SubgraphStore::new(
+        db.clone(),
+        Arc::new(SubscriptionManager::new()),
+    ));
+    let loc = create_deployment(&subgraph_store, &hash, None);
+    let writable = subgraph_store
+        .clone()
+        .writable(logger(true), loc.id)
+        .await
+        .unwrap();
+    let stopwatch = StopwatchMetrics::new(
+        logger(true),
+        hash.clone(),
+        "test",
+        Arc::new(MockMetricsRegistry::new()),
+    );
+    let store = Store::new(
+        subgraph_store,
+        Arc::new(BlockStore::new(
+            db,
+            Arc::new(ChainHeadUpdateListener::new()),
+        )),
+    );
+
+    let poi = |digest: u8| EntityModification::Overwrite {
+        key: EntityKey {
+            subgraph_id: hash.clone(),
+            entity_type: POI_OBJECT.clone(),
+            entity_id: "ethereum/mainnet".to_string(),
+        },
+        data: entity! { id: "ethereum/mainnet", digest: Bytes::from(vec![digest; 32]) },
+    };
+    let expected = |digest: u8, ptr: BlockPtr| {
+        let mut finisher = ProofOfIndexingFinisher::new(&ptr, &hash, &None);
+        finisher.add_causality_region("ethereum/mainnet", &[digest; 32]);
+        finisher.finish()
+    };
+
+    assert!(writable.supports_proof_of_indexing().await.unwrap());
+    for number in 1..=2 {
+        writable
+            .transact_block_operations(
+                block(number),
+                None,
+                vec![poi(number as u8)],
+                &stopwatch,
+                vec![],
+                vec![],
+            )
+            .await
+            .unwrap();
+    }
+    assert_eq!(
+        Some(expected(1, block(1))),
+        store
+            .get_proof_of_indexing(&hash, &None, block(1))
+            .await
+            .unwrap()
+    );
+    assert_eq!(
+        Some(expected(2, block(2))),
+        store
+            .get_proof_of_indexing(&hash, &None, block(2))
+            .await
+            .unwrap()
+    );
+
+    // Reverting a block also reverts the proof of indexing, and there is
+    // none for blocks the deployment has not processed
+    writable
+        .revert_block_operations(block(1), None)
+        .await
+        .unwrap();
+    assert_eq!(
+        None,
+        store
+            .get_proof_of_indexing(&hash, &None, block(2))
+            .await
+            .unwrap()
+    );
+    writable
+        .transact_block_operations(block(2), None, vec![poi(3)], &stopwatch, vec![], vec![])
+        .await
+        .unwrap();
+    assert_eq!(
+        Some(expected(3, block(2))),
+        store
+            .get_proof_of_indexing(&hash, &None, block(2))
+            .await
+            .unwrap()
+    );
+}
@@ -71,6 +71,11 @@ impl FakeBlock {
         BlockPtr::from((self.block_hash(), self.number))
     }
 
+    /// Fake blocks are produced every 15 seconds, starting at 2020-09-13
+    pub fn block_timestamp(&self) -> u64 {
+        1_600_000_000 + 15 * self.number as u64
+    }
+
     pub fn as_ethereum_block(&self) -> EthereumBlock {
         let parent_hash = H256::from_str(self.parent_hash.as_str()).expect("invalid parent hash");
 
@@ -78,6 +83,7 @@ impl FakeBlock {
         block.number = Some(self.number.into());
         block.parent_hash = parent_hash;
         block.hash = Some(self.block_hash());
+        block.timestamp = self.block_timestamp().into();
 
         EthereumBlock {
             block: Arc::new(block),
@@ -102,6 +108,10 @@ impl Block for FakeBlock {
         }
     }
 
+    fn timestamp(&self) -> Option<u64> {
+        Some(self.block_timestamp())
+    }
+
     fn data(&self) -> Result<serde_json::Value, serde_json::Error> {
         serde_json::to_value(self.as_ethereum_block())
     }
@@ -1,7 +1,7 @@
 use diesel::{self, PgConnection};
 use graph::data::graphql::effort::LoadManager;
-use graph::data::query::QueryResults;
 use graph::data::query::QueryTarget;
+use graph::data::query::{QueryLimits, QueryResults};
 use graph::data::subgraph::schema::{DeploymentCreate, SubgraphError};
 use graph::log;
 use graph::prelude::{QueryStoreManager as _, SubgraphStore as _, *};
@@ -162,6 +162,7 @@ pub async fn create_subgraph(
         schema: schema.clone(),
         data_sources: vec![],
         graft: None,
+        indexing_delay: None,
         templates: vec![],
         chain: PhantomData,
     };
@@ -326,6 +327,49 @@ pub fn insert_ens_name(hash: &str, name: &str) {
         .unwrap();
 }
 
+/// Move every timestamp in `column` of the primary's `table` `secs` seconds
+/// into the past so that tests don't have to sleep to make things look old
+pub fn backdate(table: &str, column: &str, secs: i64) {
+    use diesel::RunQueryDsl;
+
+    let conn = PRIMARY_POOL.get().unwrap();
+    let query = format!(
+        "update {table} set {column} = {column} - interval '{secs} seconds'",
+        table = table,
+        column = column,
+        secs = secs
+    );
+    diesel::sql_query(query).execute(&conn).unwrap();
+}
+
+/// Run the SQL statements in `sql` against the primary, for tests that need
+/// to put the database into a state the store would never produce
+pub fn batch_execute(sql: &str) {
+    use diesel::connection::SimpleConnection;
+
+    let conn = PRIMARY_POOL.get().unwrap();
+    conn.batch_execute(sql).unwrap();
+}
+
+/// Whether another node could take the lease for ingesting blocks for
+/// `chain` right now, checked by taking the lock behind the lease on a
+/// different connection than the one the store uses, and releasing it again
+pub fn ingestor_lease_available(chain: &str) -> bool {
+    use diesel::{dsl::sql, select, sql_types::Bool, RunQueryDsl};
+
+    let conn = PRIMARY_POOL.get().unwrap();
+    let id = format!("(select id from chains where name = '{}')", chain);
+    let locked = select(sql::<Bool>(&format!("pg_try_advisory_lock(4, {})", id)))
+        .get_result::<bool>(&conn)
+        .unwrap();
+    if locked {
+        select(sql::<Bool>(&format!("pg_advisory_unlock(4, {})", id)))
+            .get_result::<bool>(&conn)
+            .unwrap();
+    }
+    locked
+}
+
 /// Insert the given entities and wait until all writes have been processed.
 /// The inserts all happen at `GENESIS_PTR`, i.e., block 0
 pub async fn insert_entities(
@@ -438,14 +482,12 @@ async fn execute_subgraph_query_internal(
     )
     .unwrap();
     let network = Some(status[0].chains[0].network.clone());
-    let query = return_err!(PreparedQuery::new(
-        &logger,
-        schema,
-        network,
-        query,
+    let limits = QueryLimits {
         max_complexity,
-        100
-    ));
+        max_depth: 100,
+        ..QueryLimits::unlimited()
+    };
+    let query = return_err!(PreparedQuery::new(&logger, schema, network, query, limits));
     let mut result = QueryResults::empty();
     let deployment = query.schema.id().clone();
     let store = STORE
@@ -476,8 +518,8 @@ async fn execute_subgraph_query_internal(
                     resolver,
                     deadline,
                     load_manager: LOAD_MANAGER.clone(),
-                    max_first: std::u32::MAX,
-                    max_skip: std::u32::MAX,
+                    max_first: limits.max_first,
+                    max_skip: limits.max_skip,
                 },
             )
             .await,
@@ -509,6 +551,24 @@ pub fn all_shards() -> Vec<Shard> {
         .expect("all configured shard names are valid")
 }
 
+/// A subgraph store with its own connection pools that runs on `node`,
+/// like the store of another `graph-node` process would
+pub fn subgraph_store_for_node(node: &NodeId) -> Arc<DieselSubgraphStore> {
+    let (store, pools) = StoreBuilder::make_subgraph_store_and_pools(
+        &*LOGGER,
+        node,
+        &CONFIG,
+        Arc::new(CONFIG.deployment.clone()),
+        None,
+        METRICS_REGISTRY.clone(),
+    );
+    // The pools of `STORE` have already set up the databases
+    for pool in pools.values() {
+        pool.skip_setup();
+    }
+    store
+}
+
 fn build_store() -> (Arc<Store>, ConnectionPool, Config, Arc<SubscriptionManager>) {
     let mut opt = Opt::default();
     let url = std::env::var_os("THEGRAPH_STORE_POSTGRES_DIESEL_URL").filter(|s| s.len() > 0);